@@ -0,0 +1,119 @@
+//! Embed the metadata of the build in the binary, for support to know exactly what a user runs:
+//! the git commit (when built from a repository), the date, the cargo features and the compiler.
+
+use std::{
+    env, fs,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// The trimmed standard output of this command, if it could be run and succeeded
+fn command_output(program: &str, args: &[&str], dir: &Path) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+// The commit and whether the tracked files were modified, if we are built from our own git
+// repository. Not from a tarball, which may well be extracted in another repository.
+fn git_info(manifest_dir: &Path) -> Option<(String, bool)> {
+    let git_dir = manifest_dir.join(".git");
+    if !git_dir.exists() {
+        return None;
+    }
+
+    // Build again when the commit or the index changes
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    println!("cargo:rerun-if-changed={}", git_dir.join("index").display());
+    if let Ok(head) = fs::read_to_string(git_dir.join("HEAD")) {
+        if head.starts_with("ref: ") {
+            let reference = git_dir.join(head["ref: ".len()..].trim());
+            if reference.exists() {
+                println!("cargo:rerun-if-changed={}", reference.display());
+            }
+        }
+    }
+
+    let commit = command_output("git", &["rev-parse", "HEAD"], manifest_dir)?;
+    let dirty = command_output(
+        "git",
+        &["status", "--porcelain", "--untracked-files=no"],
+        manifest_dir,
+    )
+    .map(|status| !status.is_empty())?;
+
+    Some((commit, dirty))
+}
+
+// The date of this timestamp as YYYY-MM-DD (UTC), from
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn utc_date(timestamp: u64) -> String {
+    let z = (timestamp / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("Set by cargo");
+
+    let (commit, dirty) = match git_info(Path::new(&manifest_dir)) {
+        Some((commit, dirty)) => (commit, dirty.to_string()),
+        None => (String::new(), String::new()),
+    };
+    println!("cargo:rustc-env=REVAULTD_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=REVAULTD_GIT_DIRTY={}", dirty);
+
+    // Reproducible builds set the date
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|t| t.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backward")
+                .as_secs()
+        });
+    println!(
+        "cargo:rustc-env=REVAULTD_BUILD_DATE={}",
+        utc_date(timestamp)
+    );
+
+    // Cargo sets CARGO_FEATURE_<NAME> for each enabled feature, uppercased and with dashes
+    // replaced by underscores (which our feature names already use).
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(var, _)| {
+            if var.starts_with("CARGO_FEATURE_") {
+                Some(var["CARGO_FEATURE_".len()..].to_lowercase())
+            } else {
+                None
+            }
+        })
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=REVAULTD_FEATURES={}", features.join(","));
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"], Path::new(&manifest_dir)).unwrap_or_default();
+    println!("cargo:rustc-env=REVAULTD_RUSTC_VERSION={}", rustc_version);
+}
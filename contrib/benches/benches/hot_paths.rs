@@ -0,0 +1,93 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use revaultd::bench_utils::BenchWallet;
+
+use std::{env, fs};
+
+// A realistic deployment size.
+const STAKEHOLDERS: u8 = 4;
+const MANAGERS: u8 = 2;
+
+const ADDRESSES: u32 = 1_000;
+const UNVAULT_COPIES: usize = 10;
+// A wallet which handed out many addresses, and how much its deposit window moves at once
+const DERIVED_INDEXES: u32 = 10_000;
+const WINDOW_MOVE: u32 = 10;
+
+fn bench_wallet(name: &str) -> BenchWallet {
+    let datadir = env::temp_dir().join(format!("revaultd-benches-{}", name));
+    fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    BenchWallet::new(datadir, STAKEHOLDERS, MANAGERS)
+}
+
+fn address_derivation(c: &mut Criterion) {
+    let wallet = bench_wallet("addresses");
+    assert_eq!(wallet.derive_addresses(ADDRESSES).len(), ADDRESSES as usize);
+
+    c.bench_function("derive 1000 deposit and unvault addresses", |b| {
+        b.iter(|| wallet.derive_addresses(black_box(ADDRESSES)))
+    });
+}
+
+fn derived_scripts_loading(c: &mut Criterion) {
+    let mut wallet = bench_wallet("derived-scripts");
+    wallet.store_derived_scripts(DERIVED_INDEXES);
+    assert_eq!(wallet.load_derived_scripts(), DERIVED_INDEXES as usize);
+
+    c.bench_function(
+        "load the scripts of 10000 derivation indexes at startup",
+        |b| b.iter(|| wallet.load_derived_scripts()),
+    );
+}
+
+fn addresses_import(c: &mut Criterion) {
+    let mut wallet = bench_wallet("import");
+    wallet.store_derived_scripts(DERIVED_INDEXES);
+    wallet.load_derived_scripts();
+    let since = DERIVED_INDEXES - WINDOW_MOVE - 1;
+    assert_eq!(
+        wallet.addresses_to_import(None),
+        2 * DERIVED_INDEXES as usize
+    );
+    assert_eq!(
+        wallet.addresses_to_import(Some(since)),
+        2 * WINDOW_MOVE as usize
+    );
+
+    c.bench_function("all the addresses of a 10000 indexes wallet", |b| {
+        b.iter(|| wallet.addresses_to_import(black_box(None)))
+    });
+    c.bench_function(
+        "the addresses to import as the deposit window moves by 10",
+        |b| b.iter(|| wallet.addresses_to_import(black_box(Some(since)))),
+    );
+}
+
+fn cancel_signatures(c: &mut Criterion) {
+    let wallet = bench_wallet("cancel");
+    let sigs = wallet.cancel_signatures();
+    assert!(sigs.verify());
+
+    c.bench_function("verify the stakeholders' Cancel signatures", |b| {
+        b.iter(|| black_box(&sigs).verify())
+    });
+}
+
+fn unvault_merge(c: &mut Criterion) {
+    let wallet = bench_wallet("merge");
+    let copies = wallet.unvault_copies(UNVAULT_COPIES);
+    assert!(copies.merge());
+
+    c.bench_function("merge 10 partially signed Unvault PSBTs", |b| {
+        b.iter(|| black_box(&copies).merge())
+    });
+}
+
+criterion_group!(
+    hot_paths,
+    address_derivation,
+    derived_scripts_loading,
+    addresses_import,
+    cancel_signatures,
+    unvault_merge
+);
+criterion_main!(hot_paths);
@@ -0,0 +1 @@
+//! Benchmarks of the daemon's hot paths, see `benches/` and the README.
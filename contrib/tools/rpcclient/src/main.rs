@@ -0,0 +1,70 @@
+use std::{convert::TryInto, env, net::SocketAddr, process};
+
+use revault_net::{
+    noise::{PublicKey as NoisePubKey, SecretKey as NoisePrivKey},
+    transport::KKTransport,
+};
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: '{} <client Noise private key> <revaultd Noise public key> <address> <method> [<JSON params>]'",
+        program
+    );
+    process::exit(1);
+}
+
+fn key_from_hex(s: &str) -> [u8; 32] {
+    let bytes: Vec<u8> = (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect::<Option<_>>()
+        .unwrap_or_else(|| {
+            eprintln!("Key '{}' is not valid hex", s);
+            process::exit(1);
+        });
+    bytes.as_slice().try_into().unwrap_or_else(|_| {
+        eprintln!("Key '{}' is not 32 bytes long", s);
+        process::exit(1);
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 5 || args.len() > 6 {
+        usage(&args[0]);
+    }
+    let privkey = NoisePrivKey(key_from_hex(&args[1]));
+    let pubkey = NoisePubKey(key_from_hex(&args[2]));
+    let addr: SocketAddr = args[3].parse().unwrap_or_else(|e| {
+        eprintln!("Invalid address '{}': '{}'", args[3], e);
+        process::exit(1);
+    });
+    let params: serde_json::Value = args
+        .get(5)
+        .map(|p| {
+            serde_json::from_str(p).unwrap_or_else(|e| {
+                eprintln!("Invalid JSON params '{}': '{}'", p, e);
+                process::exit(1);
+            })
+        })
+        .unwrap_or_else(|| serde_json::json!([]));
+
+    let mut transport = KKTransport::connect(addr, &privkey, &pubkey).unwrap_or_else(|e| {
+        eprintln!("Connecting to revaultd: '{}'", e);
+        process::exit(1);
+    });
+    let req = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 0,
+        "method": args[4],
+        "params": params,
+    });
+    let resp = transport
+        .write(req.to_string().as_bytes())
+        .and_then(|_| transport.read())
+        .unwrap_or_else(|e| {
+            eprintln!("Talking to revaultd: '{}'", e);
+            process::exit(1);
+        });
+    println!("{}", String::from_utf8_lossy(&resp));
+}
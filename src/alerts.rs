@@ -0,0 +1,512 @@
+//! Alert about the vaults being unvaulted, canceled or emergency vaulted depending on their
+//! value.
+//!
+//! The `alert_tiers` of the configuration are keyed by amount: a vault entering one of the
+//! [ALERTED_STATUSES] is alerted about according to the highest tier its value reaches. A tier
+//! selects at which level the alert is logged, whether the `notify_command` is run, and
+//! optionally a webhook to post a JSON payload describing the event to.
+//!
+//! Webhooks are posted from a separate thread, retrying with an exponential backoff. They are
+//! queued in a bounded channel: a dead endpoint may make us drop alerts, but never delays the
+//! processing of the vaults.
+
+use crate::{
+    config::AlertTierConfig, database::schema::DbVaultStatusChange, revaultd::VaultStatus,
+};
+use revault_tx::bitcoin::{Amount, Txid};
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+/// The statuses whose transitions to are alerted about depending on the value of the vault.
+pub const ALERTED_STATUSES: [VaultStatus; 3] = [
+    VaultStatus::Unvaulting,
+    VaultStatus::Canceling,
+    VaultStatus::EmergencyVaulting,
+];
+
+// How many webhook posts may be pending. Others are dropped.
+const WEBHOOK_QUEUE_SIZE: usize = 64;
+
+// How many times we try to post to a webhook before giving up.
+const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+
+// How long we wait before the first retry. It's doubled at each attempt.
+const WEBHOOK_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A plain HTTP URL to post alerts to. There is no TLS support, use a local relay to post to
+/// an HTTPS endpoint. The payload is sent in clear, so the configuration refuses URLs that are
+/// not on the loopback unless the operator accepts the risk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for WebhookUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("https://") {
+            return Err(format!(
+                "Webhook URL '{}': only plain HTTP is supported, use a local relay for HTTPS",
+                s
+            ));
+        }
+        if !s.starts_with("http://") {
+            return Err(format!("Webhook URL '{}' must start with 'http://'", s));
+        }
+
+        let rest = &s["http://".len()..];
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        // An IPv6 address is enclosed in brackets, and contains colons
+        let port_sep = match authority.rfind(']') {
+            Some(i) => authority[i..].find(':').map(|j| i + j),
+            None => authority.rfind(':'),
+        };
+        let (host, port) = match port_sep {
+            Some(i) => (
+                &authority[..i],
+                authority[i + 1..]
+                    .parse::<u16>()
+                    .map_err(|e| format!("Invalid port in webhook URL '{}': '{}'", s, e))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("No host in webhook URL '{}'", s));
+        }
+
+        Ok(WebhookUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl WebhookUrl {
+    /// Whether the host is this machine: "localhost" or a loopback IP address. A name resolving
+    /// to the loopback doesn't count, as the resolution may change.
+    pub fn is_loopback(&self) -> bool {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        host.eq_ignore_ascii_case("localhost")
+            || IpAddr::from_str(host)
+                .map(|ip| ip.is_loopback())
+                .unwrap_or(false)
+    }
+}
+
+impl fmt::Display for WebhookUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// An error posting to a webhook
+#[derive(Debug)]
+pub enum WebhookError {
+    Io(io::Error),
+    /// The endpoint answered with something else than a success status (Status line)
+    Status(String),
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Status(line) => write!(f, "Unexpected answer: '{}'", line),
+        }
+    }
+}
+
+impl From<io::Error> for WebhookError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// The tier a vault of this value belongs to, if any: the one with the highest `min_amount`
+/// the value reaches.
+pub fn alert_tier(tiers: &[AlertTierConfig], amount: Amount) -> Option<&AlertTierConfig> {
+    tiers
+        .iter()
+        .filter(|tier| tier.min_amount <= amount.as_sat())
+        .max_by_key(|tier| tier.min_amount)
+}
+
+/// The JSON payload posted to the webhook of `tier` for this status transition.
+pub fn webhook_payload(
+    tier: &AlertTierConfig,
+    change: &DbVaultStatusChange,
+    txid: Option<Txid>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "tier": tier.name,
+        "tier_min_amount": tier.min_amount,
+        "deposit_outpoint": change.deposit_outpoint.to_string(),
+        "amount": change.amount.as_sat(),
+        "old_status": change.old_status.to_string(),
+        "new_status": change.new_status.to_string(),
+        "txid": txid.map(|txid| txid.to_string()),
+    })
+}
+
+// Post this JSON body to the webhook, succeeding only if it answered with a 2xx status.
+fn http_post(url: &WebhookUrl, body: &str, timeout: Duration) -> Result<(), WebhookError> {
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Could not resolve '{}'", url.host),
+            )
+        })?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        url.host,
+        url.port,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    let status_line = status_line.trim_end();
+    let success = status_line.starts_with("HTTP/")
+        && status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .map(|code| (200..300).contains(&code))
+            .unwrap_or(false);
+    if !success {
+        return Err(WebhookError::Status(status_line.to_string()));
+    }
+
+    Ok(())
+}
+
+fn webhooks_loop(
+    receiver: mpsc::Receiver<(WebhookUrl, String)>,
+    timeout: Duration,
+    initial_backoff: Duration,
+) {
+    // Stops once the Alerter is dropped
+    for (url, body) in receiver {
+        let mut backoff = initial_backoff;
+        for attempt in 1..=WEBHOOK_MAX_ATTEMPTS {
+            match http_post(&url, &body, timeout) {
+                Ok(()) => break,
+                Err(e) if attempt == WEBHOOK_MAX_ATTEMPTS => log_event!(
+                    log::Level::Error,
+                    "webhook_failure",
+                    url = url,
+                    error = e;
+                    "Giving up posting to webhook '{}' after {} attempts: '{}'",
+                    url,
+                    attempt,
+                    e
+                ),
+                Err(e) => {
+                    log::warn!(
+                        "Error posting to webhook '{}', retrying in {:?}: '{}'",
+                        url,
+                        backoff,
+                        e
+                    );
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+}
+
+/// Alerts about the vaults entering one of the [ALERTED_STATUSES] according to the configured
+/// tiers.
+pub struct Alerter {
+    tiers: Vec<AlertTierConfig>,
+    webhooks: mpsc::SyncSender<(WebhookUrl, String)>,
+}
+
+impl Alerter {
+    /// Start the thread posting to the webhooks of the `tiers`, giving up on an attempt after
+    /// `timeout`.
+    pub fn start(tiers: Vec<AlertTierConfig>, timeout: Duration) -> Alerter {
+        Self::start_with_backoff(tiers, timeout, WEBHOOK_INITIAL_BACKOFF)
+    }
+
+    fn start_with_backoff(
+        tiers: Vec<AlertTierConfig>,
+        timeout: Duration,
+        initial_backoff: Duration,
+    ) -> Alerter {
+        let (webhooks, receiver) = mpsc::sync_channel(WEBHOOK_QUEUE_SIZE);
+        thread::spawn(move || webhooks_loop(receiver, timeout, initial_backoff));
+
+        Alerter { tiers, webhooks }
+    }
+
+    /// Whether a transition to this status is alerted about depending on the vault's value
+    pub fn is_alerted(&self, status: VaultStatus) -> bool {
+        ALERTED_STATUSES.contains(&status)
+    }
+
+    /// Alert about this status transition according to the tier of the vault, if any. Returns
+    /// the tier so the caller knows whether to run the notify command.
+    pub fn alert(
+        &self,
+        change: &DbVaultStatusChange,
+        txid: Option<Txid>,
+    ) -> Option<&AlertTierConfig> {
+        let tier = alert_tier(&self.tiers, change.amount)?;
+        let tier_name = tier.name.clone().unwrap_or_default();
+
+        if let Some(level) = tier.log_level.to_level() {
+            log_event!(
+                level,
+                "vault_alert",
+                outpoint = change.deposit_outpoint,
+                amount = change.amount.as_sat(),
+                status = change.new_status,
+                tier = tier_name;
+                "Vault at '{}' worth {} is now '{}'",
+                change.deposit_outpoint,
+                change.amount,
+                change.new_status
+            );
+        }
+
+        if let Some(ref url) = tier.webhook_url {
+            let body = webhook_payload(tier, change, txid).to_string();
+            match self.webhooks.try_send((url.clone(), body)) {
+                Ok(()) => {}
+                Err(mpsc::TrySendError::Full(_)) => log_event!(
+                    log::Level::Error,
+                    "webhook_failure",
+                    url = url,
+                    error = "queue full";
+                    "Too many pending webhook posts, dropping the alert for vault at '{}'",
+                    change.deposit_outpoint
+                ),
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    log::error!("Webhooks thread is gone, can't post the alert")
+                }
+            }
+        }
+
+        Some(tier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{alert_tier, webhook_payload, Alerter, WebhookUrl};
+    use crate::{
+        config::AlertTierConfig, database::schema::DbVaultStatusChange, revaultd::VaultStatus,
+    };
+
+    use revault_tx::bitcoin::{Amount, OutPoint, Txid};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        str::FromStr,
+        sync::mpsc,
+        thread, time,
+    };
+
+    fn tier(name: &str, min_amount: u64, webhook_url: Option<&str>) -> AlertTierConfig {
+        AlertTierConfig {
+            name: Some(name.to_string()),
+            min_amount,
+            log_level: log::LevelFilter::Warn,
+            run_notify_command: true,
+            webhook_url: webhook_url.map(|url| WebhookUrl::from_str(url).unwrap()),
+        }
+    }
+
+    fn status_change(amount: u64) -> DbVaultStatusChange {
+        DbVaultStatusChange {
+            id: 1,
+            vault_id: 1,
+            old_status: VaultStatus::Active,
+            new_status: VaultStatus::Unvaulting,
+            deposit_outpoint: OutPoint::from_str(
+                "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+            )
+            .unwrap(),
+            amount: Amount::from_sat(amount),
+            final_txid: None,
+        }
+    }
+
+    // A stub HTTP server answering with these status codes in turn, sending back the bodies
+    // it was posted.
+    fn stub_webhook(statuses: Vec<u16>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for (status, stream) in statuses.into_iter().zip(listener.incoming()) {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    let line = line.to_lowercase();
+                    if line.starts_with("content-length:") {
+                        content_length = line["content-length:".len()..].trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                reader
+                    .get_mut()
+                    .write_all(format!("HTTP/1.1 {} Whatever\r\n\r\n", status).as_bytes())
+                    .unwrap();
+                sender.send(String::from_utf8(body).unwrap()).unwrap();
+            }
+        });
+
+        (url, receiver)
+    }
+
+    #[test]
+    fn webhook_url() {
+        assert_eq!(
+            WebhookUrl::from_str("http://127.0.0.1:8080/alerts")
+                .unwrap()
+                .to_string(),
+            "http://127.0.0.1:8080/alerts"
+        );
+        assert_eq!(
+            WebhookUrl::from_str("http://alerts.example")
+                .unwrap()
+                .to_string(),
+            "http://alerts.example:80/"
+        );
+        assert_eq!(
+            WebhookUrl::from_str("http://[::1]:8080")
+                .unwrap()
+                .to_string(),
+            "http://[::1]:8080/"
+        );
+        assert!(WebhookUrl::from_str("https://alerts.example/")
+            .unwrap_err()
+            .contains("use a local relay for HTTPS"));
+        WebhookUrl::from_str("alerts.example").unwrap_err();
+        WebhookUrl::from_str("http://:8080/").unwrap_err();
+        WebhookUrl::from_str("http://alerts.example:http/").unwrap_err();
+    }
+
+    #[test]
+    fn tier_evaluation() {
+        let tiers = vec![
+            tier("ciso", 5_000_000_000, None),
+            tier("routine", 0, None),
+            tier("treasury", 100_000_000, None),
+        ];
+
+        let name = |amount| {
+            alert_tier(&tiers, Amount::from_sat(amount)).and_then(|tier| tier.name.clone())
+        };
+        assert_eq!(name(1_000_000), Some("routine".to_string()));
+        assert_eq!(name(99_999_999), Some("routine".to_string()));
+        assert_eq!(name(100_000_000), Some("treasury".to_string()));
+        assert_eq!(name(5_000_000_000), Some("ciso".to_string()));
+        assert_eq!(name(21_000_000 * 100_000_000), Some("ciso".to_string()));
+
+        // Below the lowest tier, it's not alerted about
+        let tiers = vec![tier("treasury", 100_000_000, None)];
+        assert!(alert_tier(&tiers, Amount::from_sat(1_000_000)).is_none());
+        assert!(alert_tier(&[], Amount::from_sat(1_000_000)).is_none());
+    }
+
+    #[test]
+    fn payload() {
+        let txid =
+            Txid::from_str("a4d4bf7e8d3a0b6e5ef4a2cbb0f20ff0d4ee1c2a4f6e82b6a4e3ae3c9c59e7d1")
+                .unwrap();
+        let tier = tier("ciso", 5_000_000_000, None);
+        assert_eq!(
+            webhook_payload(&tier, &status_change(5_000_000_000), Some(txid)),
+            serde_json::json!({
+                "tier": "ciso",
+                "tier_min_amount": 5_000_000_000u64,
+                "deposit_outpoint": "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+                "amount": 5_000_000_000u64,
+                "old_status": "active",
+                "new_status": "unvaulting",
+                "txid": "a4d4bf7e8d3a0b6e5ef4a2cbb0f20ff0d4ee1c2a4f6e82b6a4e3ae3c9c59e7d1",
+            })
+        );
+
+        let mut tier = tier;
+        tier.name = None;
+        let payload = webhook_payload(&tier, &status_change(5_000_000_000), None);
+        assert!(payload["tier"].is_null());
+        assert!(payload["txid"].is_null());
+    }
+
+    #[test]
+    fn webhook_retries() {
+        // The endpoint fails twice before accepting it
+        let (url, posted) = stub_webhook(vec![503, 500, 200]);
+        let alerter = Alerter::start_with_backoff(
+            vec![
+                tier("routine", 0, None),
+                tier("ciso", 5_000_000_000, Some(&url)),
+            ],
+            time::Duration::from_secs(5),
+            time::Duration::from_millis(10),
+        );
+
+        // The low tier has no webhook
+        assert_eq!(
+            alerter
+                .alert(&status_change(1_000_000), None)
+                .and_then(|tier| tier.name.clone()),
+            Some("routine".to_string())
+        );
+        alerter.alert(&status_change(5_000_000_000), None).unwrap();
+        let expected = webhook_payload(
+            &tier("ciso", 5_000_000_000, None),
+            &status_change(5_000_000_000),
+            None,
+        );
+        for _ in 0..3 {
+            let body = posted.recv_timeout(time::Duration::from_secs(10)).unwrap();
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+                expected
+            );
+        }
+        // Not posted anymore once it succeeded
+        assert!(posted
+            .recv_timeout(time::Duration::from_millis(200))
+            .is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! The amounts we exchange over the RPC interface.
+//!
+//! They are always serialized as an integer number of satoshis. We accept them either as an
+//! integer number of satoshis or as a string denominated in bitcoins (such as `"0.001"`), but
+//! never as a JSON floating point number: they can't represent all amounts exactly.
+
+use revault_tx::bitcoin::{self, util::amount::Denomination};
+
+use std::{fmt, ops};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The maximum number of satoshis there will ever be, 21 million bitcoins.
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+
+/// An amount of bitcoins, as exchanged over the RPC interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(bitcoin::Amount);
+
+impl Amount {
+    pub fn from_sat(sat: u64) -> Self {
+        Self(bitcoin::Amount::from_sat(sat))
+    }
+
+    pub fn as_sat(&self) -> u64 {
+        self.0.as_sat()
+    }
+}
+
+impl From<bitcoin::Amount> for Amount {
+    fn from(amount: bitcoin::Amount) -> Self {
+        Self(amount)
+    }
+}
+
+impl From<Amount> for bitcoin::Amount {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl ops::Add for Amount {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0 + other.0)
+    }
+}
+
+impl ops::AddAssign for Amount {
+    fn add_assign(&mut self, other: Self) {
+        self.0 += other.0
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(self.as_sat())
+    }
+}
+
+struct AmountVisitor;
+
+impl AmountVisitor {
+    fn checked<E: de::Error>(amount: bitcoin::Amount) -> Result<Amount, E> {
+        if amount.as_sat() > MAX_MONEY {
+            return Err(E::custom(format!(
+                "Amount of {} is more than 21 million bitcoins",
+                amount
+            )));
+        }
+        Ok(Amount(amount))
+    }
+}
+
+impl<'de> de::Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "an integer number of satoshis or a string amount in bitcoins"
+        )
+    }
+
+    fn visit_u64<E: de::Error>(self, sat: u64) -> Result<Amount, E> {
+        Self::checked(bitcoin::Amount::from_sat(sat))
+    }
+
+    fn visit_i64<E: de::Error>(self, sat: i64) -> Result<Amount, E> {
+        if sat < 0 {
+            return Err(E::custom(format!("Amount of {} sats is negative", sat)));
+        }
+        Self::checked(bitcoin::Amount::from_sat(sat as u64))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Amount, E> {
+        Err(E::custom(
+            "Amounts must be an integer number of satoshis, or a string amount in bitcoins \
+             (such as \"0.001\"), not a floating point number",
+        ))
+    }
+
+    fn visit_str<E: de::Error>(self, btc: &str) -> Result<Amount, E> {
+        let amount = bitcoin::Amount::from_str_in(btc, Denomination::Bitcoin)
+            .map_err(|e| E::custom(format!("Invalid amount '{}': {}", btc, e)))?;
+        Self::checked(amount)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_json(json: &str) -> Result<Amount, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn amount_roundtrip() {
+        for sat in &[0, 1, 546, 100_000_000, 2_099_999_997_690_000, MAX_MONEY] {
+            let amount = Amount::from_sat(*sat);
+            let json = serde_json::to_string(&amount).unwrap();
+            assert_eq!(json, sat.to_string());
+            assert_eq!(from_json(&json).unwrap(), amount);
+        }
+
+        // In bitcoins as a string
+        assert_eq!(from_json("\"1\"").unwrap(), Amount::from_sat(100_000_000));
+        assert_eq!(from_json("\"0.001\"").unwrap(), Amount::from_sat(100_000));
+        assert_eq!(from_json("\"0.00000001\"").unwrap(), Amount::from_sat(1));
+        assert_eq!(
+            from_json("\"21000000\"").unwrap(),
+            Amount::from_sat(MAX_MONEY)
+        );
+    }
+
+    #[test]
+    fn amount_rejected() {
+        // Floating point numbers, even those with an exact integer value
+        for json in &["0.001", "1.0", "1e8", "-0.5"] {
+            let err = from_json(json).unwrap_err();
+            assert!(err.contains("not a floating point number"), "{}", err);
+        }
+
+        // Negative amounts
+        assert!(from_json("-1").unwrap_err().contains("negative"));
+        assert!(from_json("\"-0.001\"").is_err());
+
+        // More than there will ever be
+        for json in &[
+            "2100000000000001",
+            "18446744073709551615",
+            "\"21000000.00000001\"",
+            "\"21000001\"",
+        ] {
+            let err = from_json(json).unwrap_err();
+            assert!(err.contains("21 million"), "{}", err);
+        }
+
+        // More precise than a satoshi, or not a number at all
+        assert!(from_json("\"0.000000001\"").is_err());
+        assert!(from_json("\"one\"").is_err());
+        assert!(from_json("\"\"").is_err());
+        assert!(from_json("null").is_err());
+        assert!(from_json("true").is_err());
+    }
+}
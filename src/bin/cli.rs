@@ -86,18 +86,17 @@ fn socket_file(conf_file: Option<PathBuf>) -> PathBuf {
         eprintln!("Error getting config: {}", e);
         process::exit(1);
     });
-    let data_dir = config
+    let mut data_dir = config
         .data_dir
         .unwrap_or_else(|| config_folder_path().unwrap());
-    let data_dir = data_dir.to_str().expect("Datadir is valid unicode");
-
-    [
-        data_dir,
-        config.bitcoind_config.network.to_string().as_str(),
-        "revaultd_rpc",
-    ]
-    .iter()
-    .collect()
+    data_dir.push(config.bitcoind_config.network.to_string());
+
+    // Same as revaultd: an absolute path is used as-is, a relative one is in the datadir
+    match config.rpc_socket_path {
+        Some(path) if path.is_absolute() => path,
+        Some(path) => data_dir.join(path),
+        None => data_dir.join("revaultd_rpc"),
+    }
 }
 
 fn trimmed(mut vec: Vec<u8>, bytes_read: usize) -> Vec<u8> {
@@ -141,6 +140,14 @@ fn main() {
                 eprintln!("Reading from {:?}: '{}'", &socket_file, e);
                 process::exit(1);
             });
+        // A streamed response is cut short by closing the connection
+        if n == 0 && total_read < raw_response.len() {
+            eprintln!(
+                "revaultd closed the connection before the end of the response ({} bytes read)",
+                total_read
+            );
+            process::exit(1);
+        }
         total_read += n;
         if total_read == raw_response.len() {
             raw_response.resize(2 * total_read, 0);
@@ -1,74 +1,434 @@
 use revault_net::sodiumoxide;
 use std::{
-    env,
-    io::{self, Write},
+    collections::HashMap,
+    env, fmt,
+    io::{self, BufRead, Write},
     path::PathBuf,
-    process, time,
+    process,
+    str::FromStr,
+    thread,
 };
 
-use revaultd::{config::Config, DaemonHandle};
+use revaultd::{
+    buildinfo::build_info,
+    config::{config_file_path, config_folder_path, noise_pubkey_from_str, Config, EXAMPLE_CONFIG},
+    logger::{setup_logger, LogLevels, LogLevelsHandle},
+    revault_net::noise::PublicKey as NoisePubkey,
+    setup::{setup, SetupParams},
+    DaemonControl, DaemonHandle,
+};
+
+// What we were asked to do
+enum Mode {
+    Run {
+        conf_file: Option<PathBuf>,
+        read_only: bool,
+        verify_derivations: bool,
+    },
+    DumpExampleConfig,
+    Version,
+    CheckConfig(PathBuf),
+    /// (Setup arguments)
+    Setup(Vec<String>),
+}
 
-fn parse_args(args: Vec<String>) -> Option<PathBuf> {
-    if args.len() == 1 {
-        return None;
+// The arguments to run the daemon, each at most once and in any order
+fn parse_run_args(args: &[&str]) -> Option<Mode> {
+    let (mut conf_file, mut read_only, mut verify_derivations) = (None, false, false);
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--conf" if conf_file.is_none() => conf_file = Some(PathBuf::from(args.next()?)),
+            "--read-only" if !read_only => read_only = true,
+            "--verify-derivations" if !verify_derivations => verify_derivations = true,
+            _ => return None,
+        }
     }
 
-    if args.len() != 3 {
+    Some(Mode::Run {
+        conf_file,
+        read_only,
+        verify_derivations,
+    })
+}
+
+fn parse_args(args: Vec<String>) -> Mode {
+    let mode = match args.iter().map(|a| a.as_str()).collect::<Vec<&str>>()[1..] {
+        ["--dump-example-config"] => Some(Mode::DumpExampleConfig),
+        ["--version"] => Some(Mode::Version),
+        ["--check-config", path] => Some(Mode::CheckConfig(PathBuf::from(path))),
+        ["--setup", ref setup_args @ ..] => Some(Mode::Setup(
+            setup_args.iter().map(|a| a.to_string()).collect(),
+        )),
+        ref run_args => parse_run_args(run_args),
+    };
+
+    mode.unwrap_or_else(|| {
         eprintln!("Unknown arguments '{:?}'.", args);
-        eprintln!("Only '--conf <configuration file path>' is supported.");
+        eprintln!(
+            "Usage: '[--conf <configuration file path>] [--read-only] [--verify-derivations]', \
+             '--dump-example-config', '--version', '--check-config <configuration file path>' \
+             or '--setup [<setup arguments>]'."
+        );
         process::exit(1);
+    })
+}
+
+// The parameters asked for by `--setup`: the name of their flag, their description, and whether
+// they may be left empty. Lists are comma-separated.
+const SETUP_PARAMS: &[(&str, &str, bool)] = &[
+    (
+        "network",
+        "Bitcoin network (bitcoin, testnet, signet or regtest)",
+        false,
+    ),
+    (
+        "data-dir",
+        "Data directory, the default one if left empty",
+        true,
+    ),
+    (
+        "bitcoind-cookie-path",
+        "Path to bitcoind's cookie file",
+        false,
+    ),
+    ("bitcoind-addr", "bitcoind's RPC address (ip:port)", false),
+    ("stakeholders-xpubs", "The stakeholders' xpubs", false),
+    ("managers-xpubs", "The managers' xpubs", false),
+    (
+        "managers-threshold",
+        "How many managers must sign a Spend transaction",
+        false,
+    ),
+    ("cosigners-keys", "The cosigning servers' public keys", true),
+    (
+        "unvault-csv",
+        "The relative timelock of the Unvault transactions, in blocks",
+        false,
+    ),
+    ("cpfp-xpubs", "The managers' CPFP xpubs", false),
+    (
+        "stakeholder-xpub",
+        "Your xpub, if you are a stakeholder",
+        true,
+    ),
+    (
+        "emergency-address",
+        "The Emergency address, if you are a stakeholder",
+        true,
+    ),
+    (
+        "watchtowers",
+        "Your watchtowers, as <Noise key>@<ip:port>",
+        true,
+    ),
+    ("manager-xpub", "Your xpub, if you are a manager", true),
+    (
+        "cosigning-servers",
+        "The cosigning servers, as <Noise key>@<ip:port>",
+        true,
+    ),
+    (
+        "coordinator-host",
+        "The Coordinator address (ip:port)",
+        false,
+    ),
+    (
+        "coordinator-noise-key",
+        "The Coordinator Noise static public key",
+        false,
+    ),
+];
+
+fn setup_usage() -> ! {
+    eprintln!(
+        "Usage: '--setup [--conf <configuration file path>] [--force] [--non-interactive] \
+         [--check-coordinator] [--<parameter> <value>]...'. The parameters not given are asked \
+         for, unless '--non-interactive'. They are:"
+    );
+    for (name, description, optional) in SETUP_PARAMS {
+        eprintln!(
+            "  --{}: {}{}",
+            name,
+            description,
+            if *optional { " (optional)" } else { "" }
+        );
+    }
+    process::exit(1);
+}
+
+// Ask for a parameter on the terminal, until we get a value if it's mandatory.
+fn prompt_setup_param(description: &str, optional: bool) -> String {
+    let stdin = io::stdin();
+    loop {
+        print!(
+            "{}{}: ",
+            description,
+            if optional { " (optional)" } else { "" }
+        );
+        io::stdout().flush().expect("Flushing stdout");
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            eprintln!("\nSetup aborted.");
+            process::exit(1);
+        }
+        let value = line.trim();
+        if optional || !value.is_empty() {
+            return value.to_string();
+        }
     }
+}
+
+fn parse_setup_value<T: FromStr>(name: &str, value: &str) -> T
+where
+    T::Err: fmt::Display,
+{
+    T::from_str(value).unwrap_or_else(|e| {
+        eprintln!("Invalid '{}' value '{}': {}", name, value, e);
+        process::exit(1);
+    })
+}
 
-    Some(PathBuf::from(args[2].to_owned()))
+fn parse_setup_list<T: FromStr>(name: &str, value: &str) -> Vec<T>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| parse_setup_value(name, v))
+        .collect()
 }
 
-fn setup_logger(log_level: log::LevelFilter) -> Result<(), fern::InitError> {
-    let dispatcher = fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{}][{}][{}] {}",
-                time::SystemTime::now()
-                    .duration_since(time::UNIX_EPOCH)
-                    .unwrap_or_else(|e| {
-                        println!("Can't get time since epoch: '{}'. Using a dummy value.", e);
-                        time::Duration::from_secs(0)
-                    })
-                    .as_secs(),
-                record.target(),
-                record.level(),
-                message
-            ))
+fn parse_setup_servers(name: &str, value: &str) -> Vec<(NoisePubkey, std::net::SocketAddr)> {
+    parse_setup_list::<String>(name, value)
+        .into_iter()
+        .map(|server| {
+            let mut parts = server.splitn(2, '@');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(host)) => (
+                    noise_pubkey_from_str(key).unwrap_or_else(|e| {
+                        eprintln!("Invalid '{}' Noise key '{}': {}", name, key, e);
+                        process::exit(1);
+                    }),
+                    parse_setup_value(name, host),
+                ),
+                _ => {
+                    eprintln!(
+                        "Invalid '{}' value '{}', expected <Noise key>@<ip:port>",
+                        name, server
+                    );
+                    process::exit(1);
+                }
+            }
         })
-        .level(log_level);
+        .collect()
+}
+
+// Write a configuration and initialize the data directory out of the deployment parameters,
+// given on the command line or asked for.
+fn setup_mode(args: Vec<String>) {
+    let (mut conf_file, mut force, mut interactive, mut check_coordinator) =
+        (None, false, true, false);
+    let mut given: HashMap<String, String> = HashMap::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => setup_usage(),
+            "--force" => force = true,
+            "--non-interactive" => interactive = false,
+            "--check-coordinator" => check_coordinator = true,
+            "--conf" => {
+                conf_file = Some(PathBuf::from(args.next().unwrap_or_else(|| setup_usage())))
+            }
+            flag if flag.starts_with("--")
+                && SETUP_PARAMS.iter().any(|(name, _, _)| *name == &flag[2..]) =>
+            {
+                let value = args.next().unwrap_or_else(|| setup_usage());
+                given.insert(flag[2..].to_string(), value);
+            }
+            _ => {
+                eprintln!("Unknown setup argument '{}'.", arg);
+                setup_usage();
+            }
+        }
+    }
+
+    let mut values: HashMap<&str, String> = HashMap::new();
+    for (name, description, optional) in SETUP_PARAMS {
+        let value = match given.remove(*name) {
+            Some(value) => value,
+            None if interactive => prompt_setup_param(description, *optional),
+            None if *optional => String::new(),
+            None => {
+                eprintln!("Missing '--{}' in non-interactive mode.", name);
+                process::exit(1);
+            }
+        };
+        values.insert(*name, value);
+    }
+    let value = |name: &str| values[name].as_str();
+    let optional_value = |name: &str| Some(value(name)).filter(|v| !v.is_empty());
 
-    dispatcher.chain(std::io::stdout()).apply()?;
+    let params = SetupParams {
+        config_file: conf_file.or_else(config_file_path).unwrap_or_else(|| {
+            eprintln!("Could not locate the default configuration file path, use '--conf'.");
+            process::exit(1);
+        }),
+        data_dir: optional_value("data-dir")
+            .map(PathBuf::from)
+            .or_else(config_folder_path)
+            .unwrap_or_else(|| {
+                eprintln!("Could not locate the default data directory, use '--data-dir'.");
+                process::exit(1);
+            }),
+        network: parse_setup_value("network", value("network")),
+        bitcoind_cookie_path: PathBuf::from(value("bitcoind-cookie-path")),
+        bitcoind_addr: parse_setup_value("bitcoind-addr", value("bitcoind-addr")),
+        stakeholders_xpubs: parse_setup_list("stakeholders-xpubs", value("stakeholders-xpubs")),
+        managers_xpubs: parse_setup_list("managers-xpubs", value("managers-xpubs")),
+        managers_threshold: parse_setup_value("managers-threshold", value("managers-threshold")),
+        cosigners_keys: parse_setup_list("cosigners-keys", value("cosigners-keys")),
+        unvault_csv: parse_setup_value("unvault-csv", value("unvault-csv")),
+        cpfp_xpubs: parse_setup_list("cpfp-xpubs", value("cpfp-xpubs")),
+        our_stakeholder_xpub: optional_value("stakeholder-xpub")
+            .map(|v| parse_setup_value("stakeholder-xpub", v)),
+        emergency_address: optional_value("emergency-address")
+            .map(|v| parse_setup_value("emergency-address", v)),
+        watchtowers: parse_setup_servers("watchtowers", value("watchtowers")),
+        our_manager_xpub: optional_value("manager-xpub")
+            .map(|v| parse_setup_value("manager-xpub", v)),
+        cosigning_servers: parse_setup_servers("cosigning-servers", value("cosigning-servers")),
+        coordinator_host: parse_setup_value("coordinator-host", value("coordinator-host")),
+        coordinator_noise_key: noise_pubkey_from_str(value("coordinator-noise-key"))
+            .unwrap_or_else(|e| {
+                eprintln!("Invalid 'coordinator-noise-key': {}", e);
+                process::exit(1);
+            }),
+        force,
+        check_coordinator,
+    };
 
-    Ok(())
+    match setup(&params) {
+        Ok(summary) => {
+            println!("{}", summary);
+            if check_coordinator {
+                println!("Successfully connected to the Coordinator.");
+            }
+        }
+        Err(e) => {
+            eprintln!("Error during setup: {}", e);
+            process::exit(1);
+        }
+    }
 }
 
-fn main() {
-    let args = env::args().collect();
-    let conf_file = parse_args(args);
+// Parse and validate the configuration file, without starting anything.
+fn check_config(conf_file: PathBuf) {
+    let config = Config::from_file(Some(conf_file)).unwrap_or_else(|e| {
+        eprintln!("Error parsing config: {}", e);
+        process::exit(1);
+    });
+    DaemonHandle::check_config(&config).unwrap_or_else(|e| {
+        eprintln!("Error checking config: {}", e);
+        process::exit(1);
+    });
+    println!("Configuration is valid.");
+}
 
-    // We use libsodium for Noise keys and Noise channels (through revault_net)
+// Block SIGHUP for this thread and the ones it will spawn, so it can be waited for by a
+// dedicated thread instead of interrupting whichever one it is delivered to.
+fn block_sighup() -> libc::sigset_t {
+    unsafe {
+        let mut sigset: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut sigset);
+        libc::sigaddset(&mut sigset, libc::SIGHUP);
+        if libc::pthread_sigmask(libc::SIG_BLOCK, &sigset, std::ptr::null_mut()) != 0 {
+            eprintln!("Error blocking SIGHUP");
+            process::exit(1);
+        }
+        sigset
+    }
+}
+
+// Reload the configuration file every time we receive a SIGHUP
+fn sighup_loop(sigset: libc::sigset_t, conf_file: Option<PathBuf>, control: DaemonControl) {
+    loop {
+        let mut signal: libc::c_int = 0;
+        if unsafe { libc::sigwait(&sigset, &mut signal) } != 0 {
+            log::error!("Error waiting for SIGHUP, configuration won't be reloaded anymore");
+            return;
+        }
+
+        log::info!("Received SIGHUP, reloading the configuration");
+        match Config::from_file(conf_file.clone()) {
+            Ok(config) => control.reload_config(&config),
+            Err(e) => log::error!(
+                "Error reloading the configuration, keeping the current one: '{}'",
+                e
+            ),
+        }
+    }
+}
+
+// We use libsodium for Noise keys and Noise channels (through revault_net)
+fn init_libsodium() {
     sodiumoxide::init().unwrap_or_else(|_| {
         eprintln!("Error init'ing libsodium");
         process::exit(1);
     });
+}
+
+fn main() {
+    let args = env::args().collect();
+    let (conf_file, read_only, verify_derivations) = match parse_args(args) {
+        Mode::Run {
+            conf_file,
+            read_only,
+            verify_derivations,
+        } => (conf_file, read_only, verify_derivations),
+        Mode::DumpExampleConfig => {
+            print!("{}", EXAMPLE_CONFIG);
+            return;
+        }
+        Mode::Version => {
+            println!("{}", build_info());
+            return;
+        }
+        Mode::CheckConfig(conf_file) => return check_config(conf_file),
+        Mode::Setup(setup_args) => {
+            init_libsodium();
+            return setup_mode(setup_args);
+        }
+    };
+
+    init_libsodium();
 
-    let config = Config::from_file(conf_file).unwrap_or_else(|e| {
+    let mut config = Config::from_file(conf_file.clone()).unwrap_or_else(|e| {
         eprintln!("Error parsing config: {}", e);
         process::exit(1);
     });
-    setup_logger(config.log_level).unwrap_or_else(|e| {
+    // The command line can only make it stricter
+    config.read_only |= read_only;
+    config.verify_derivations |= verify_derivations;
+    let log_levels =
+        LogLevelsHandle::new(LogLevels::new(config.log_level, config.log_modules.clone()));
+    setup_logger(log_levels.clone(), config.log_format).unwrap_or_else(|e| {
         eprintln!("Error setting up logger: {}", e);
         process::exit(1);
     });
 
-    let daemon_handle = DaemonHandle::start(config).unwrap_or_else(|e| {
+    // Before any thread is started
+    let sigset = block_sighup();
+    let daemon_handle = DaemonHandle::start(config, log_levels).unwrap_or_else(|e| {
         // The panic hook will log::error
         panic!("Starting Revault daemon: {}", e);
     });
+    let control = daemon_handle.control.clone();
+    // Detached, it dies with the process.
+    thread::spawn(move || sighup_loop(sigset, conf_file, control));
     // Listen for incoming commands, then shutdown when we are stopped
     daemon_handle
         .rpc_server()
@@ -0,0 +1,342 @@
+//! Someone may fiddle with our watchonly wallet behind our back, which would silently break our
+//! assumptions about the coins bitcoind reports to us. We periodically compare the descriptors
+//! it watches with the addresses we imported into it: the missing ones are imported back, the
+//! unexpected ones are reported.
+
+use crate::{
+    bitcoind::{
+        interface::BitcoinD,
+        rescan::{ImportKind, RescanImport, Rescanner},
+        BitcoindError,
+    },
+    database::{
+        actions::db_update_imported_index,
+        interface::{db_imported_index, db_rescan_timestamp},
+        schema::ScriptKind,
+    },
+    derivation::DerivationIndex,
+    revaultd::RevaultD,
+};
+
+use revault_tx::bitcoin::Address;
+
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The result of the comparison of our watchonly wallet with what we imported into it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletAudit {
+    /// When it was performed
+    pub checked_at: u64,
+    /// The number of our addresses the wallet watches
+    pub watched: usize,
+    /// Our addresses the wallet did not watch anymore. They are being imported back.
+    pub missing: Vec<Address>,
+    /// The addresses (or descriptors) the wallet watches that we never imported
+    pub unexpected: Vec<String>,
+    /// Whether we corrected the derivation indexes up to which we recorded our imports
+    pub imports_corrected: bool,
+}
+
+// The address of an `addr()` descriptor
+fn addr_descriptor_address(descriptor: &str) -> Option<Address> {
+    if descriptor.starts_with("addr(") && descriptor.ends_with(')') {
+        Address::from_str(&descriptor["addr(".len()..descriptor.len() - 1]).ok()
+    } else {
+        None
+    }
+}
+
+// How the content of the wallet compares to the addresses we derived
+#[derive(Debug, PartialEq)]
+struct WalletDrift {
+    watched: usize,
+    missing: Vec<(Address, DerivationIndex, ScriptKind)>,
+    unexpected: Vec<String>,
+    // For the deposit and unvault addresses, the highest derivation index up to which the wallet
+    // watches all of them, if any.
+    watched_up_to: (Option<DerivationIndex>, Option<DerivationIndex>),
+}
+
+// Compare the descriptors of the wallet with the addresses we derived, given the derivation
+// indexes up to which we imported them. The derived addresses must come in the order of their
+// derivation index.
+fn wallet_drift(
+    derived: &[(Address, DerivationIndex, ScriptKind)],
+    imported: (Option<DerivationIndex>, Option<DerivationIndex>),
+    descriptors: &[String],
+) -> WalletDrift {
+    let mut unexpected = Vec::new();
+    let mut wallet_addresses = HashSet::with_capacity(descriptors.len());
+    for desc in descriptors {
+        match addr_descriptor_address(desc) {
+            Some(address) => {
+                wallet_addresses.insert(address);
+            }
+            None => unexpected.push(desc.clone()),
+        }
+    }
+
+    let mut watched = 0;
+    let mut missing = Vec::new();
+    let mut ours = HashSet::with_capacity(derived.len());
+    let (mut deposit_up_to, mut unvault_up_to) = (None, None);
+    let (mut deposit_gap, mut unvault_gap) = (false, false);
+    for (address, index, kind) in derived {
+        let (imported, up_to, gap) = match kind {
+            ScriptKind::Deposit => (imported.0, &mut deposit_up_to, &mut deposit_gap),
+            ScriptKind::Unvault => (imported.1, &mut unvault_up_to, &mut unvault_gap),
+        };
+        ours.insert(address);
+
+        if wallet_addresses.contains(address) {
+            watched += 1;
+            if !*gap {
+                *up_to = Some(*index);
+            }
+        } else {
+            *gap = true;
+            if Some(*index) <= imported {
+                missing.push((address.clone(), *index, *kind));
+            }
+        }
+    }
+
+    let mut extras: Vec<String> = wallet_addresses
+        .iter()
+        .filter(|address| !ours.contains(address))
+        .map(|address| address.to_string())
+        .collect();
+    extras.sort();
+    unexpected.extend(extras);
+
+    WalletDrift {
+        watched,
+        missing,
+        unexpected,
+        watched_up_to: (deposit_up_to, unvault_up_to),
+    }
+}
+
+/// Compare the descriptors our watchonly wallet watches with the addresses we imported into
+/// it. The missing ones are imported back, rescanning the chain from the time they were needed
+/// as they may have been paid already. The unexpected ones are reported. If the wallet watches
+/// more of our addresses than we recorded (we stopped before recording an import), the record
+/// is corrected.
+///
+/// Must not be called while the rescanner is importing descriptors, as the addresses being
+/// imported would be reported missing.
+pub fn audit_wallet(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    rescanner: &Rescanner,
+) -> Result<WalletAudit, BitcoindError> {
+    let (db_path, wallet_id, checked_at) = {
+        let revaultd = revaultd.read().unwrap();
+        (
+            revaultd.db_file(),
+            revaultd
+                .wallet_id
+                .expect("Wallet id is set at startup in setup_db()"),
+            revaultd.clock.unix_timestamp(),
+        )
+    };
+    let imported = (
+        db_imported_index(&db_path, wallet_id, ScriptKind::Deposit)?,
+        db_imported_index(&db_path, wallet_id, ScriptKind::Unvault)?,
+    );
+    let derived = revaultd
+        .read()
+        .unwrap()
+        .addresses_to_import(None)
+        .collect::<Result<Vec<_>, _>>()?;
+    // A single call, however large the wallet.
+    let descriptors = bitcoind.watchonly_wallet_descriptors()?;
+    let drift = wallet_drift(&derived, imported, &descriptors);
+
+    if !drift.missing.is_empty() {
+        log_event!(
+            log::Level::Warn,
+            "wallet_missing_addresses",
+            count = drift.missing.len();
+            "{} of the addresses we imported were missing from our watchonly wallet. Importing \
+             them back, bitcoind is going to rescan the chain for them.",
+            drift.missing.len()
+        );
+        for (script_kind, import_kind) in &[
+            (ScriptKind::Deposit, ImportKind::Deposit),
+            (ScriptKind::Unvault, ImportKind::Unvault),
+        ] {
+            let missing: Vec<_> = drift
+                .missing
+                .iter()
+                .filter(|(_, _, kind)| kind == script_kind)
+                .collect();
+            let lowest_index = match missing.iter().map(|(_, index, _)| *index).min() {
+                Some(index) => index,
+                None => continue,
+            };
+            let descriptors = missing
+                .iter()
+                .map(|(address, _, _)| bitcoind.addr_descriptor(&address.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            rescanner.queue(RescanImport {
+                kind: *import_kind,
+                descriptors,
+                timestamp: db_rescan_timestamp(&db_path, wallet_id, *script_kind, lowest_index)?,
+            });
+        }
+    }
+
+    if !drift.unexpected.is_empty() {
+        log_event!(
+            log::Level::Warn,
+            "wallet_unexpected_entries",
+            entries = drift.unexpected.join(",");
+            "Our watchonly wallet watches {} entries we never imported: '{}'. Someone else is \
+             using it.",
+            drift.unexpected.len(),
+            drift.unexpected.join("', '")
+        );
+    }
+
+    let mut imports_corrected = false;
+    for (kind, imported, watched_up_to) in &[
+        (ScriptKind::Deposit, imported.0, drift.watched_up_to.0),
+        (ScriptKind::Unvault, imported.1, drift.watched_up_to.1),
+    ] {
+        if let Some(index) = watched_up_to.filter(|index| Some(*index) > *imported) {
+            log::info!(
+                "Our watchonly wallet watches our {:?} addresses up to derivation index {}, \
+                 but we only recorded importing them up to {:?}. Correcting it.",
+                kind,
+                index,
+                imported
+            );
+            // We can't tell when they were imported, as far as we know when the range preceding
+            // them was needed.
+            let first_needed = db_rescan_timestamp(&db_path, wallet_id, *kind, index)?;
+            db_update_imported_index(&db_path, wallet_id, *kind, index, first_needed)?;
+            imports_corrected = true;
+        }
+    }
+
+    let audit = WalletAudit {
+        checked_at,
+        watched: drift.watched,
+        missing: drift
+            .missing
+            .into_iter()
+            .map(|(address, _, _)| address)
+            .collect(),
+        unexpected: drift.unexpected,
+        imports_corrected,
+    };
+    revaultd.write().unwrap().wallet_audit = Some(audit.clone());
+
+    Ok(audit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{addr_descriptor_address, wallet_drift, WalletDrift};
+    use crate::{database::schema::ScriptKind, derivation::DerivationIndex};
+
+    use revault_tx::bitcoin::{hashes::Hash, Address, Network, Script, WScriptHash};
+
+    fn address(i: u8) -> Address {
+        let script = Script::new_v0_wsh(&WScriptHash::from_slice(&[i; 32]).unwrap());
+        Address::from_script(&script, Network::Regtest).unwrap()
+    }
+
+    fn addr_desc(i: u8) -> String {
+        format!("addr({})", address(i))
+    }
+
+    #[test]
+    fn wallet_drift_detection() {
+        let index = |i| DerivationIndex::new(i).unwrap();
+        // Deposit addresses are even, Unvault ones odd
+        let derived: Vec<_> = (0..4u8)
+            .flat_map(|i| {
+                vec![
+                    (address(2 * i), index(i as u32), ScriptKind::Deposit),
+                    (address(2 * i + 1), index(i as u32), ScriptKind::Unvault),
+                ]
+            })
+            .collect();
+        let imported = (Some(index(3)), Some(index(3)));
+
+        // All there, nothing else
+        let descriptors: Vec<String> = (0..8).map(addr_desc).collect();
+        assert_eq!(
+            wallet_drift(&derived, imported, &descriptors),
+            WalletDrift {
+                watched: 8,
+                missing: vec![],
+                unexpected: vec![],
+                watched_up_to: (Some(index(3)), Some(index(3))),
+            }
+        );
+
+        // Two went missing, and some were added
+        let mut descriptors: Vec<String> =
+            [0, 1, 3, 4, 5, 7].iter().map(|i| addr_desc(*i)).collect();
+        descriptors.push(addr_desc(42));
+        descriptors.push(
+            "wpkh(02c2a6b4b4a1a6b1d4c7e6ea0d1b1a9c0e2b6ea39c1fb8b0a5d9c7a1b2c3d4e5f6)".to_string(),
+        );
+        let drift = wallet_drift(&derived, imported, &descriptors);
+        assert_eq!(drift.watched, 6);
+        assert_eq!(
+            drift.missing,
+            vec![
+                (address(2), index(1), ScriptKind::Deposit),
+                (address(6), index(3), ScriptKind::Deposit)
+            ]
+        );
+        assert_eq!(
+            drift.unexpected,
+            vec![
+                "wpkh(02c2a6b4b4a1a6b1d4c7e6ea0d1b1a9c0e2b6ea39c1fb8b0a5d9c7a1b2c3d4e5f6)"
+                    .to_string(),
+                address(42).to_string()
+            ]
+        );
+        assert_eq!(drift.watched_up_to, (Some(index(0)), Some(index(3))));
+
+        // We only recorded importing the first ones, but the wallet watches them all
+        let descriptors: Vec<String> = (0..8).map(addr_desc).collect();
+        let drift = wallet_drift(&derived, (Some(index(1)), None), &descriptors);
+        assert!(drift.missing.is_empty());
+        assert!(drift.unexpected.is_empty());
+        assert_eq!(drift.watched_up_to, (Some(index(3)), Some(index(3))));
+
+        // The ones we didn't import yet aren't missing
+        let descriptors: Vec<String> = (0..4).map(addr_desc).collect();
+        let drift = wallet_drift(&derived, (Some(index(1)), Some(index(1))), &descriptors);
+        assert!(drift.missing.is_empty());
+        assert_eq!(drift.watched, 4);
+        assert_eq!(drift.watched_up_to, (Some(index(1)), Some(index(1))));
+
+        // An empty wallet misses them all
+        let drift = wallet_drift(&derived, imported, &[]);
+        assert_eq!(drift.missing.len(), 8);
+        assert_eq!(drift.watched_up_to, (None, None));
+    }
+
+    #[test]
+    fn addr_descriptors() {
+        assert_eq!(addr_descriptor_address(&addr_desc(1)), Some(address(1)));
+        assert_eq!(addr_descriptor_address("addr(notanaddress)"), None);
+        assert_eq!(
+            addr_descriptor_address(&format!("raw({:x})", address(1).script_pubkey())),
+            None
+        );
+    }
+}
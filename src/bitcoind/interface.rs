@@ -1,20 +1,31 @@
 use crate::config::BitcoindConfig;
-use crate::{bitcoind::BitcoindError, revaultd::BlockchainTip};
+use crate::{
+    bitcoind::{
+        pool::{ClientPool, Connector, PoolStats, PooledClient},
+        BitcoindError,
+    },
+    clock::Clock,
+    derivation::DerivationIndex,
+    revaultd::{BlockchainTip, FeerateEstimates},
+};
 use revault_tx::{
     bitcoin::{
-        blockdata::constants::COIN_VALUE, consensus::encode, util::bip32::ChildNumber,
-        util::psbt::PartiallySignedTransaction as Psbt, Amount, BlockHash, OutPoint, Script,
-        Transaction, TxOut, Txid,
+        blockdata::constants::COIN_VALUE, consensus::encode,
+        util::psbt::PartiallySignedTransaction as Psbt, Address, Amount, BlockHash, OutPoint,
+        Script, Transaction, TxOut, Txid,
     },
     transactions::{DUST_LIMIT, UNVAULT_CPFP_VALUE},
 };
 
 use std::{
-    any::Any,
     collections::HashMap,
     fs,
     str::FromStr,
-    time::{Duration, Instant},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 use jsonrpc::{
@@ -28,20 +39,41 @@ use serde_json::Value as Json;
 
 // The minimum deposit value according to revault_tx depends also on the unvault's
 // transaction fee. To have a one-value-fits-all, just take a 5% leeway.
-const MIN_DEPOSIT_VALUE: u64 = (DUST_LIMIT + UNVAULT_CPFP_VALUE) * 105 / 100;
+pub const MIN_DEPOSIT_VALUE: u64 = (DUST_LIMIT + UNVAULT_CPFP_VALUE) * 105 / 100;
 
-// If bitcoind takes more than 3 minutes to answer one of our queries, fail.
-const RPC_SOCKET_TIMEOUT: u64 = 180;
+/// The number of blocks mined on top of the one containing a coinbase transaction before its
+/// outputs may be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// The reason given by `test_mempool_accept` for a transaction of a package that bitcoind didn't
+/// evaluate, as a previous one was rejected.
+pub const NOT_EVALUATED: &str = "not evaluated";
+
+// The first backoff between two attempts at a request, doubled for each attempt.
+const RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+// The maximum backoff between two attempts at a request.
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(8);
 
 // Labels used to tag utxos in the watchonly wallet
 const DEPOSIT_UTXOS_LABEL: &str = "revault-deposit";
 const UNVAULT_UTXOS_LABEL: &str = "revault-unvault";
 const CPFP_UTXOS_LABEL: &str = "revault-cpfp";
 
+/// A handle to bitcoind's RPC server. Clones share the same connections.
+#[derive(Clone)]
 pub struct BitcoinD {
-    node_client: Client,
-    watchonly_client: Client,
-    cpfp_client: Client,
+    node_pool: Arc<ClientPool>,
+    watchonly_pool: Arc<ClientPool>,
+    cpfp_pool: Arc<ClientPool>,
+    retry_window: Duration,
+    // Whether bitcoind ever answered us. Until it did, not being able to reach it is most likely
+    // a configuration error that we'd better report right away.
+    reached: Arc<AtomicBool>,
+    clock: Arc<dyn Clock>,
+    // Kept around to be able to reconnect, as the cookie changes across bitcoind restarts.
+    config: BitcoindConfig,
+    watchonly_wallet_path: String,
+    cpfp_wallet_path: String,
 }
 
 macro_rules! params {
@@ -54,174 +86,306 @@ macro_rules! params {
     };
 }
 
+fn rpc_client(url: &str, cookie: &str, timeout: Duration) -> Result<Client, BitcoindError> {
+    Ok(Client::with_transport(
+        SimpleHttpTransport::builder()
+            .url(url)
+            .map_err(BitcoindError::from)?
+            .timeout(timeout)
+            .cookie_auth(cookie.to_string())
+            .build(),
+    ))
+}
+
+fn read_cookie(config: &BitcoindConfig) -> Result<String, BitcoindError> {
+    fs::read_to_string(&config.cookie_path)
+        .map_err(|e| BitcoindError::Custom(format!("Reading cookie file: {}", e.to_string())))
+}
+
+fn connector(url: String, cookie: String) -> Connector {
+    Arc::new(move |timeout| rpc_client(&url, &cookie, timeout))
+}
+
+// Send the request with this connection, not giving it back to the pool if bitcoind could not
+// be reached through it.
+fn pooled_request<T>(
+    mut client: PooledClient,
+    send: impl FnOnce(&Client) -> Result<T, BitcoindError>,
+) -> Result<T, BitcoindError> {
+    let res = send(&client);
+    if matches!(res, Err(ref e) if e.is_transient()) {
+        client.discard();
+    }
+    res
+}
+
+/// What to do after a request to bitcoind failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RetryDecision {
+    Retry(Duration),
+    GiveUp,
+}
+
+// We try to support bitcoind failing under our feet for a few dozens of seconds (for instance the
+// RPC work queue being exceeded), with an exponential backoff. But we never retry a request that
+// bitcoind answered with an error, such as a wallet error: it would answer the same. Nor do we
+// delay an early failure: if bitcoind was never `reached` we most likely got its RPC listening
+// address wrong.
+fn retry_decision(
+    error: &BitcoindError,
+    attempt: u32,
+    elapsed: Duration,
+    retry_window: Duration,
+    reached: bool,
+) -> RetryDecision {
+    let backoff = RETRY_INITIAL_BACKOFF
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(RETRY_MAX_BACKOFF)
+        .min(RETRY_MAX_BACKOFF);
+    let within_window = elapsed + backoff <= retry_window;
+
+    match error {
+        BitcoindError::Unreachable(_) | BitcoindError::Timeout if reached && within_window => {
+            RetryDecision::Retry(backoff)
+        }
+        BitcoindError::Server(jsonrpc::Error::Transport(err)) => {
+            match err.downcast_ref::<HttpError>() {
+                // This one can't happen after startup, we'd better fail quickly.
+                Some(HttpError::InvalidUrl { .. }) => RetryDecision::GiveUp,
+                // Weird. Try again once, just in case.
+                Some(HttpError::HttpParseError) if attempt == 0 => RetryDecision::Retry(backoff),
+                Some(HttpError::HttpParseError) => RetryDecision::GiveUp,
+                _ if within_window => RetryDecision::Retry(backoff),
+                _ => RetryDecision::GiveUp,
+            }
+        }
+        // A JSON serialization error? It should not happen, just try again once.
+        BitcoindError::Server(jsonrpc::Error::Json(_)) if attempt == 0 => {
+            RetryDecision::Retry(backoff)
+        }
+        _ => RetryDecision::GiveUp,
+    }
+}
+
+// Send a request to bitcoind until it succeeds or `retry_decision` tells us to give up.
+fn send_with_retry<T>(
+    clock: &dyn Clock,
+    retry_window: Duration,
+    reached: &AtomicBool,
+    mut send: impl FnMut() -> Result<T, jsonrpc::Error>,
+) -> Result<T, BitcoindError> {
+    let start = clock.now();
+    let mut attempt = 0;
+
+    loop {
+        let error = match send() {
+            Ok(res) => {
+                reached.store(true, Ordering::Relaxed);
+                return Ok(res);
+            }
+            Err(e) => BitcoindError::from(e),
+        };
+
+        match retry_decision(
+            &error,
+            attempt,
+            clock.elapsed(start),
+            retry_window,
+            reached.load(Ordering::Relaxed),
+        ) {
+            RetryDecision::Retry(backoff) => {
+                log::error!("Error when talking to bitcoind: '{}'", error);
+                log::debug!("Retrying RPC request to bitcoind in {:?}.", backoff);
+                clock.sleep(backoff);
+                attempt += 1;
+            }
+            RetryDecision::GiveUp => return Err(error),
+        }
+    }
+}
+
+fn send_request(
+    client: &Client,
+    clock: &dyn Clock,
+    retry_window: Duration,
+    reached: &AtomicBool,
+    method: &str,
+    params: &[Box<serde_json::value::RawValue>],
+) -> Result<Json, BitcoindError> {
+    let req = client.build_request(method, params);
+    log::trace!("Sending to bitcoind: {:#?}", req);
+
+    let resp = send_with_retry(clock, retry_window, reached, || {
+        client.send_request(req.clone())
+    })?;
+    // Errors returned by bitcoind itself are not retried.
+    let res = resp.result().map_err(BitcoindError::from)?;
+    log::trace!("Got from bitcoind: {:#?}", res);
+
+    Ok(res)
+}
+
+// The 'scanning' entry of 'getwalletinfo' is either 'false' or an object with the progress of the
+// ongoing rescan.
+fn scanning_progress(walletinfo: &Json) -> Option<f64> {
+    walletinfo
+        .get("scanning")
+        .and_then(|s| s.get("progress"))
+        .and_then(Json::as_f64)
+}
+
 impl BitcoinD {
     pub fn new(
         config: &BitcoindConfig,
         watchonly_wallet_path: String,
         cpfp_wallet_path: String,
+        clock: Arc<dyn Clock>,
     ) -> Result<BitcoinD, BitcoindError> {
-        let cookie_string = fs::read_to_string(&config.cookie_path).map_err(|e| {
-            BitcoindError::Custom(format!("Reading cookie file: {}", e.to_string()))
-        })?;
-
-        let node_client = Client::with_transport(
-            SimpleHttpTransport::builder()
-                .url(&config.addr.to_string())
-                .map_err(BitcoindError::from)?
-                .timeout(Duration::from_secs(RPC_SOCKET_TIMEOUT))
-                .cookie_auth(cookie_string.clone())
-                .build(),
-        );
-
-        let watchonly_url = format!("http://{}/wallet/{}", config.addr, watchonly_wallet_path);
-        let watchonly_client = Client::with_transport(
-            SimpleHttpTransport::builder()
-                .url(&watchonly_url)
-                .map_err(BitcoindError::from)?
-                .timeout(Duration::from_secs(RPC_SOCKET_TIMEOUT))
-                .cookie_auth(cookie_string.clone())
-                .build(),
-        );
-
-        let cpfp_url = format!("http://{}/wallet/{}", config.addr, cpfp_wallet_path);
-        let cpfp_client = Client::with_transport(
-            SimpleHttpTransport::builder()
-                .url(&cpfp_url)
-                .map_err(BitcoindError::from)?
-                .timeout(Duration::from_secs(RPC_SOCKET_TIMEOUT))
-                .cookie_auth(cookie_string)
-                .build(),
-        );
+        let cookie_string = read_cookie(config)?;
+        let [node_url, watchonly_url, cpfp_url] =
+            Self::urls(config, &watchonly_wallet_path, &cpfp_wallet_path);
+        let pool = |name: &'static str, url: String| -> Result<Arc<ClientPool>, BitcoindError> {
+            Ok(Arc::new(ClientPool::new(
+                name,
+                config.rpc_connections,
+                config.rpc_timeout_secs,
+                config.rescan_timeout_secs,
+                clock.clone(),
+                connector(url, cookie_string.clone()),
+            )?))
+        };
 
         Ok(BitcoinD {
-            node_client,
-            watchonly_client,
-            cpfp_client,
+            node_pool: pool("node", node_url)?,
+            watchonly_pool: pool("watchonly", watchonly_url)?,
+            cpfp_pool: pool("cpfp", cpfp_url)?,
+            retry_window: config.rpc_retry_window_secs,
+            reached: Arc::new(AtomicBool::new(false)),
+            clock,
+            config: config.clone(),
+            watchonly_wallet_path,
+            cpfp_wallet_path,
         })
     }
 
-    // Reasonably try to be robust to possible spurious communication error.
-    fn handle_error(&self, e: jsonrpc::Error, start: Instant) -> Result<(), BitcoindError> {
-        let now = Instant::now();
-
-        match e {
-            jsonrpc::Error::Transport(ref err) => {
-                log::error!("Transport error when talking to bitcoind: '{}'", err);
-
-                // This is *always* a simple_http::Error. Rule out the error that can
-                // not occur after startup (ie if we encounter them it must be startup
-                // and we better be failing quickly).
-                let any_err = err as &dyn Any;
-                if let Some(http_err) = any_err.downcast_ref::<HttpError>() {
-                    match http_err {
-                        HttpError::InvalidUrl { .. } => return Err(BitcoindError::Server(e)),
-                        // FIXME: allow it to be unreachable for a handful of seconds,
-                        // but not at startup!
-                        HttpError::SocketError(_) => return Err(BitcoindError::Server(e)),
-                        HttpError::HttpParseError => {
-                            // Weird. Try again once, just in case.
-                            if now.duration_since(start) > Duration::from_secs(1) {
-                                return Err(BitcoindError::Server(e));
-                            }
-                            std::thread::sleep(Duration::from_secs(1));
-                        }
-                        _ => {}
-                    }
-                }
-
-                // This one *may* happen. For a number of reasons, the obvious one may
-                // be the RPC work queue being exceeded. In this case, and since we'll
-                // usually fail if we err try again for a reasonable amount of time.
-                if now.duration_since(start) > Duration::from_secs(45) {
-                    return Err(BitcoindError::Server(e));
-                }
-                std::thread::sleep(Duration::from_secs(1));
-                log::debug!("Retrying RPC request to bitcoind.");
-            }
-            jsonrpc::Error::Json(ref err) => {
-                // Weird. A JSON serialization error? Just try again but
-                // fail fast anyways as it should not happen.
-                log::error!(
-                    "JSON serialization error when talking to bitcoind: '{}'",
-                    err
-                );
-                if now.duration_since(start) > Duration::from_secs(1) {
-                    return Err(BitcoindError::Server(e));
-                }
-                std::thread::sleep(Duration::from_millis(500));
-                log::debug!("Retrying RPC request to bitcoind.");
-            }
-            _ => return Err(BitcoindError::Server(e)),
-        };
+    fn urls(
+        config: &BitcoindConfig,
+        watchonly_wallet_path: &str,
+        cpfp_wallet_path: &str,
+    ) -> [String; 3] {
+        [
+            config.addr.to_string(),
+            format!("http://{}/wallet/{}", config.addr, watchonly_wallet_path),
+            format!("http://{}/wallet/{}", config.addr, cpfp_wallet_path),
+        ]
+    }
 
+    /// Re-read the cookie file and re-create our connections. To be called when bitcoind may
+    /// have restarted, as it generates a new cookie each time. This applies to all the handles
+    /// sharing our connections.
+    pub fn reconnect(&self) -> Result<(), BitcoindError> {
+        let cookie_string = read_cookie(&self.config)?;
+        let [node_url, watchonly_url, cpfp_url] = Self::urls(
+            &self.config,
+            &self.watchonly_wallet_path,
+            &self.cpfp_wallet_path,
+        );
+        self.node_pool
+            .reset(connector(node_url, cookie_string.clone()));
+        self.watchonly_pool
+            .reset(connector(watchonly_url, cookie_string.clone()));
+        self.cpfp_pool.reset(connector(cpfp_url, cookie_string));
         Ok(())
     }
 
+    /// How much our connections to bitcoind are used, for each endpoint.
+    pub fn connection_pools(&self) -> Vec<PoolStats> {
+        vec![
+            self.node_pool.stats(),
+            self.watchonly_pool.stats(),
+            self.cpfp_pool.stats(),
+        ]
+    }
+
     fn make_request<'a, 'b>(
         &self,
-        client: &Client,
+        pool: &ClientPool,
         method: &'a str,
         params: &'b [Box<serde_json::value::RawValue>],
     ) -> Result<Json, BitcoindError> {
-        let req = client.build_request(method, params);
-        log::trace!("Sending to bitcoind: {:#?}", req);
-
-        // Trying to be robust on bitcoind's spurious failures. We try to support bitcoind failing
-        // under our feet for a few dozens of seconds, while not delaying an early failure (for
-        // example, if we got the RPC listening address or path to the cookie wrong).
-        let start = Instant::now();
-        loop {
-            match client.send_request(req.clone()) {
-                Ok(resp) => {
-                    let res = resp.result().map_err(BitcoindError::Server)?;
-                    log::trace!("Got from bitcoind: {:#?}", res);
-
-                    return Ok(res);
-                }
-                Err(e) => {
-                    // Decide wether we should error, or not yet
-                    self.handle_error(e, start)?;
-                }
-            }
-        }
+        pooled_request(pool.get()?, |client| {
+            send_request(
+                client,
+                &*self.clock,
+                self.retry_window,
+                &self.reached,
+                method,
+                params,
+            )
+        })
     }
 
-    fn make_requests(
+    // Same as above, but on a dedicated connection with a longer timeout for the calls that may
+    // trigger a rescan.
+    fn make_long_request(
         &self,
-        client: &Client,
-        reqs: &[jsonrpc::Request],
-    ) -> Result<Vec<Json>, BitcoindError> {
+        pool: &ClientPool,
+        method: &str,
+        params: &[Box<serde_json::value::RawValue>],
+    ) -> Result<Json, BitcoindError> {
+        pooled_request(pool.get_long_lived()?, |client| {
+            send_request(
+                client,
+                &*self.clock,
+                self.retry_window,
+                &self.reached,
+                method,
+                params,
+            )
+        })
+    }
+
+    // Send a batch of requests, returning the result of each of them.
+    fn make_requests_each(
+        &self,
+        pool: &ClientPool,
+        calls: &[(&str, &[Box<serde_json::value::RawValue>])],
+    ) -> Result<Vec<Result<Json, BitcoindError>>, BitcoindError> {
+        let client = pool.get()?;
+        let reqs: Vec<jsonrpc::Request> = calls
+            .iter()
+            .map(|(method, params)| client.build_request(method, params))
+            .collect();
         log::trace!("Sending to bitcoind: {:#?}", reqs);
 
-        // Trying to be robust on bitcoind's spurious failures. We try to support bitcoind failing
-        // under our feet for a few dozens of seconds, while not delaying an early failure (for
-        // example, if we got the RPC listening address or path to the cookie wrong).
-        let start = Instant::now();
-        loop {
-            match client.send_batch(reqs) {
-                Ok(resp) => {
-                    let res = resp
-                        .into_iter()
-                        .flatten()
-                        .map(|resp| resp.result())
-                        .collect::<Result<Vec<Json>, jsonrpc::Error>>()
-                        .map_err(BitcoindError::Server)?;
-                    log::trace!("Got from bitcoind: {:#?}", res);
-
-                    // FIXME: why is rust-jsonrpc even returning a Vec of Option in the first
-                    // place??
-                    if res.len() != reqs.len() {
-                        return Err(BitcoindError::BatchMissingResponse);
-                    }
+        let resp = pooled_request(client, |client| {
+            send_with_retry(&*self.clock, self.retry_window, &self.reached, || {
+                client.send_batch(&reqs)
+            })
+        })?;
+        let res: Vec<Result<Json, BitcoindError>> = resp
+            .into_iter()
+            .flatten()
+            .map(|resp| resp.result().map_err(BitcoindError::from))
+            .collect();
+        log::trace!("Got from bitcoind: {:#?}", res);
 
-                    return Ok(res);
-                }
-                Err(e) => {
-                    // Decide wether we should error, or not yet
-                    self.handle_error(e, start)?;
-                }
-            }
+        // FIXME: why is rust-jsonrpc even returning a Vec of Option in the first
+        // place??
+        if res.len() != reqs.len() {
+            return Err(BitcoindError::BatchMissingResponse);
         }
+
+        Ok(res)
+    }
+
+    // Same as above, but fails if any of the requests failed.
+    fn make_requests(
+        &self,
+        pool: &ClientPool,
+        calls: &[(&str, &[Box<serde_json::value::RawValue>])],
+    ) -> Result<Vec<Json>, BitcoindError> {
+        self.make_requests_each(pool, calls)?.into_iter().collect()
     }
 
     fn make_node_request(
@@ -229,7 +393,7 @@ impl BitcoinD {
         method: &str,
         params: &[Box<serde_json::value::RawValue>],
     ) -> Result<Json, BitcoindError> {
-        self.make_request(&self.node_client, method, params)
+        self.make_request(&self.node_pool, method, params)
     }
 
     fn make_watchonly_request(
@@ -237,14 +401,14 @@ impl BitcoinD {
         method: &str,
         params: &[Box<serde_json::value::RawValue>],
     ) -> Result<Json, BitcoindError> {
-        self.make_request(&self.watchonly_client, method, params)
+        self.make_request(&self.watchonly_pool, method, params)
     }
 
     fn make_node_requests(
         &self,
-        requests: &[jsonrpc::Request],
+        calls: &[(&str, &[Box<serde_json::value::RawValue>])],
     ) -> Result<Vec<Json>, BitcoindError> {
-        self.make_requests(&self.node_client, requests)
+        self.make_requests(&self.node_pool, calls)
     }
 
     fn make_cpfp_request(
@@ -252,7 +416,7 @@ impl BitcoinD {
         method: &str,
         params: &[Box<serde_json::value::RawValue>],
     ) -> Result<Json, BitcoindError> {
-        self.make_request(&self.cpfp_client, method, params)
+        self.make_request(&self.cpfp_pool, method, params)
     }
 
     pub fn getblockchaininfo(&self) -> Result<Json, BitcoindError> {
@@ -278,6 +442,16 @@ impl BitcoinD {
         Ok(BlockchainTip { height, hash })
     }
 
+    /// The median time of the last 11 blocks of our tip, which the time of a block may not be
+    /// lower than.
+    pub fn median_time_past(&self) -> Result<u32, BitcoindError> {
+        Ok(self
+            .make_node_request("getblockchaininfo", &[])?
+            .get("mediantime")
+            .and_then(|t| t.as_u64())
+            .expect("No valid 'mediantime' in getblockchaininfo response?") as u32)
+    }
+
     pub fn synchronization_info(&self) -> Result<SyncInfo, BitcoindError> {
         let chaininfo = self.make_node_request("getblockchaininfo", &[])?;
         Ok(SyncInfo {
@@ -305,7 +479,8 @@ impl BitcoinD {
         wallet_path: String,
         watchonly: bool,
     ) -> Result<(), BitcoindError> {
-        let res = self.make_node_request(
+        let res = self.make_long_request(
+            &self.node_pool,
             "createwallet",
             &params!(
                 Json::String(wallet_path),
@@ -344,7 +519,8 @@ impl BitcoinD {
     }
 
     pub fn loadwallet_startup(&self, wallet_path: String) -> Result<(), BitcoindError> {
-        let res = self.make_node_request(
+        let res = self.make_long_request(
+            &self.node_pool,
             "loadwallet",
             &params!(
                 Json::String(wallet_path),
@@ -381,12 +557,41 @@ impl BitcoinD {
         }
     }
 
+    /// Whether this address is watched by our watchonly wallet. We use it as a marker to make
+    /// sure the wallet loaded at our path is ours.
+    pub fn watchonly_wallet_has_address(&self, address: &str) -> Result<bool, BitcoindError> {
+        let res = self.make_watchonly_request(
+            "getaddressinfo",
+            &params!(Json::String(address.to_string())),
+        )?;
+        let is_true = |key: &str| res.get(key).and_then(Json::as_bool) == Some(true);
+
+        Ok(is_true("ismine") || is_true("iswatchonly"))
+    }
+
+    /// All the descriptors imported into our watchonly wallet, without their checksum.
+    pub fn watchonly_wallet_descriptors(&self) -> Result<Vec<String>, BitcoindError> {
+        let res = self.make_watchonly_request("listdescriptors", &[])?;
+        let descriptors = res
+            .get("descriptors")
+            .and_then(Json::as_array)
+            .ok_or_else(|| {
+                BitcoindError::Custom(format!("No 'descriptors' in 'listdescriptors': {:?}", res))
+            })?;
+
+        Ok(descriptors
+            .iter()
+            .filter_map(|entry| entry.get("desc").and_then(Json::as_str))
+            .map(|desc| desc.split('#').next().unwrap_or(desc).to_string())
+            .collect())
+    }
+
     /// Constructs an `addr()` descriptor out of an address
     pub fn addr_descriptor(&self, address: &str) -> Result<String, BitcoindError> {
         let desc_wo_checksum = format!("addr({})", address);
 
         Ok(self
-            .make_watchonly_request(
+            .make_node_request(
                 "getdescriptorinfo",
                 &params!(Json::String(desc_wo_checksum)),
             )?
@@ -399,7 +604,7 @@ impl BitcoinD {
 
     fn bulk_import_descriptors(
         &self,
-        client: &Client,
+        pool: &ClientPool,
         descriptors: Vec<String>,
         timestamp: u32,
         label: String,
@@ -432,8 +637,8 @@ impl BitcoinD {
             })
             .collect();
 
-        let res = self.make_request(
-            client,
+        let res = self.make_long_request(
+            pool,
             "importdescriptors",
             &params!(Json::Array(all_descriptors)),
         )?;
@@ -454,7 +659,7 @@ impl BitcoinD {
         fresh_wallet: bool,
     ) -> Result<(), BitcoindError> {
         self.bulk_import_descriptors(
-            &self.watchonly_client,
+            &self.watchonly_pool,
             descriptors,
             timestamp,
             DEPOSIT_UTXOS_LABEL.to_string(),
@@ -470,7 +675,7 @@ impl BitcoinD {
         fresh_wallet: bool,
     ) -> Result<(), BitcoindError> {
         self.bulk_import_descriptors(
-            &self.watchonly_client,
+            &self.watchonly_pool,
             descriptors,
             timestamp,
             UNVAULT_UTXOS_LABEL.to_string(),
@@ -486,7 +691,7 @@ impl BitcoinD {
         fresh_wallet: bool,
     ) -> Result<(), BitcoindError> {
         self.bulk_import_descriptors(
-            &self.cpfp_client,
+            &self.cpfp_pool,
             vec![descriptor],
             timestamp,
             CPFP_UTXOS_LABEL.to_string(),
@@ -505,7 +710,8 @@ impl BitcoinD {
         desc_map.insert("timestamp".to_string(), Json::String("now".to_string()));
         desc_map.insert("label".to_string(), Json::String(label));
 
-        let res = self.make_watchonly_request(
+        let res = self.make_long_request(
+            &self.watchonly_pool,
             "importdescriptors",
             &params!(Json::Array(vec![Json::Object(desc_map,)])),
         )?;
@@ -527,43 +733,132 @@ impl BitcoinD {
         self.import_fresh_descriptor(descriptor, UNVAULT_UTXOS_LABEL.to_string())
     }
 
+    /// The progress of the rescan the watchonly (or cpfp) wallet is currently performing, if
+    /// any.
+    pub fn rescan_progress(&self, cpfp_wallet: bool) -> Result<Option<f64>, BitcoindError> {
+        let res = if cpfp_wallet {
+            self.make_cpfp_request("getwalletinfo", &[])?
+        } else {
+            self.make_watchonly_request("getwalletinfo", &[])?
+        };
+
+        Ok(scanning_progress(&res))
+    }
+
     pub fn list_unspent_deposits(
         &self,
         min_amount: Option<u64>,
     ) -> Result<Vec<ListUnspentEntry>, BitcoindError> {
-        self.list_unspent(
-            &self.watchonly_client,
-            min_amount,
-            Some(DEPOSIT_UTXOS_LABEL),
-        )
+        self.list_unspent(&self.watchonly_pool, min_amount, Some(DEPOSIT_UTXOS_LABEL))
+    }
+
+    /// The coinbase outputs paying to our deposit addresses that did not mature yet. Unlike
+    /// spendable outputs, they are not returned by 'listunspent'.
+    pub fn list_immature_deposits(
+        &self,
+        min_amount: u64,
+    ) -> Result<Vec<ListUnspentEntry>, BitcoindError> {
+        let tip = self.get_tip()?;
+        let params = if tip.height > COINBASE_MATURITY {
+            let since = self.getblockhash(tip.height - COINBASE_MATURITY)?;
+            params!(Json::String(since.to_string()))
+        } else {
+            vec![]
+        };
+        let lsb_res = self.make_watchonly_request("listsinceblock", &params)?;
+        let transactions = lsb_res
+            .get("transactions")
+            .and_then(Json::as_array)
+            .expect("API break: no or invalid 'transactions' in 'listsinceblock' result");
+
+        let mut immature = Vec::new();
+        for entry in transactions {
+            if entry.get("category").and_then(Json::as_str) != Some("immature")
+                || entry.get("label").and_then(Json::as_str) != Some(DEPOSIT_UTXOS_LABEL)
+            {
+                continue;
+            }
+            let value = entry
+                .get("amount")
+                .and_then(Json::as_f64)
+                .map(|amount| {
+                    Amount::from_btc(amount)
+                        .expect("API break: invalid 'amount' in 'listsinceblock' entry")
+                        .as_sat()
+                })
+                .expect("API break: no or invalid 'amount' in 'listsinceblock' entry");
+            if value < min_amount {
+                continue;
+            }
+            let txid = entry
+                .get("txid")
+                .and_then(Json::as_str)
+                .and_then(|t| Txid::from_str(t).ok())
+                .expect("API break: no or invalid 'txid' in 'listsinceblock' entry");
+            let vout = entry
+                .get("vout")
+                .and_then(Json::as_u64)
+                .expect("API break: no or invalid 'vout' in 'listsinceblock' entry")
+                as u32;
+            let script_pubkey = entry
+                .get("address")
+                .and_then(Json::as_str)
+                .and_then(|a| Address::from_str(a).ok())
+                .expect("API break: no or invalid 'address' in 'listsinceblock' entry")
+                .script_pubkey();
+            let confirmations = entry
+                .get("confirmations")
+                .and_then(Json::as_u64)
+                .expect("API break: no or invalid 'confirmations' in 'listsinceblock' entry");
+
+            immature.push(ListUnspentEntry {
+                outpoint: OutPoint { txid, vout },
+                txo: TxOut {
+                    value,
+                    script_pubkey,
+                },
+                label: Some(DEPOSIT_UTXOS_LABEL.to_string()),
+                confirmations,
+                derivation_index: None,
+            });
+        }
+
+        Ok(immature)
     }
 
     pub fn list_unspent_unvaults(
         &self,
         min_amount: Option<u64>,
     ) -> Result<Vec<ListUnspentEntry>, BitcoindError> {
-        self.list_unspent(
-            &self.watchonly_client,
-            min_amount,
-            Some(UNVAULT_UTXOS_LABEL),
-        )
+        self.list_unspent(&self.watchonly_pool, min_amount, Some(UNVAULT_UTXOS_LABEL))
     }
 
     pub fn list_unspent_cpfp(&self) -> Result<Vec<ListUnspentEntry>, BitcoindError> {
         // For some weird reason, listunspent with the cpfp wallet doesn't return the label
         // (maybe because we only have one descriptor anyways?), so we pass `None` as a label
-        self.list_unspent(&self.cpfp_client, None, None)
+        self.list_unspent(&self.cpfp_pool, None, None)
+    }
+
+    /// The value of the confirmed coins of the CPFP wallet
+    pub fn cpfp_balance(&self) -> Result<Amount, BitcoindError> {
+        Ok(self
+            .list_unspent_cpfp()?
+            .into_iter()
+            .filter(|utxo| utxo.confirmations > 0)
+            .fold(Amount::from_sat(0), |balance, utxo| {
+                balance + Amount::from_sat(utxo.txo.value)
+            }))
     }
 
     fn list_unspent(
         &self,
-        client: &Client,
+        pool: &ClientPool,
         min_amount: Option<u64>,
         label: Option<&'static str>,
     ) -> Result<Vec<ListUnspentEntry>, BitcoindError> {
         let req = if let Some(min_amount) = min_amount {
             self.make_request(
-                client,
+                pool,
                 "listunspent",
                 &params!(
                     Json::Number(0.into()),       // minconf
@@ -577,7 +872,7 @@ impl BitcoinD {
             )
         } else {
             self.make_request(
-                client,
+                pool,
                 "listunspent",
                 &params!(
                     Json::Number(0.into()), // minconf
@@ -637,6 +932,20 @@ impl BitcoinD {
             );
         }
 
+        // A deposit made by a coinbase transaction is only returned by listunspent once it
+        // matured, and is only confirmed from then on. But we want to know about it before.
+        for immature in self.list_immature_deposits(MIN_DEPOSIT_VALUE)? {
+            if spent_utxos.remove(&immature.outpoint).is_none() {
+                new_utxos.insert(
+                    immature.outpoint,
+                    UtxoInfo {
+                        txo: immature.txo,
+                        is_confirmed: false,
+                    },
+                );
+            }
+        }
+
         Ok(DepositsState {
             new_unconf: new_utxos,
             new_conf: confirmed_utxos,
@@ -747,7 +1056,7 @@ impl BitcoinD {
     pub fn broadcast_transaction(&self, tx: &Transaction) -> Result<(), BitcoindError> {
         let tx_hex = encode::serialize_hex(tx);
         log::debug!("Broadcasting '{}'", tx_hex);
-        self.make_watchonly_request("sendrawtransaction", &params!(Json::String(tx_hex)))
+        self.make_node_request("sendrawtransaction", &params!(Json::String(tx_hex)))
             .map(|_| ())
     }
 
@@ -758,21 +1067,112 @@ impl BitcoinD {
             .map(|tx| params!(Json::String(encode::serialize_hex(tx))))
             .collect();
         log::debug!("Batch-broadcasting {:?}", txs_hex);
-        let reqs: Vec<jsonrpc::Request> = txs_hex
+        let calls: Vec<(&str, &[Box<serde_json::value::RawValue>])> = txs_hex
+            .iter()
+            .map(|hex| ("sendrawtransaction", hex.as_ref()))
+            .collect();
+        self.make_node_requests(&calls).map(|_| ())
+    }
+
+    /// Broadcast a batch of transactions with 'sendrawtransaction'. Unlike
+    /// `broadcast_transactions`, the failure to broadcast one of them doesn't prevent the others
+    /// from going out: returns, for each transaction, the error bitcoind answered if any.
+    pub fn broadcast_transactions_each(
+        &self,
+        txs: &[Transaction],
+    ) -> Result<Vec<(Txid, Option<BitcoindError>)>, BitcoindError> {
+        let txs_hex: Vec<[Box<serde_json::value::RawValue>; 1]> = txs
+            .iter()
+            .map(|tx| params!(Json::String(encode::serialize_hex(tx))))
+            .collect();
+        log::debug!("Batch-broadcasting {:?}", txs_hex);
+        let calls: Vec<(&str, &[Box<serde_json::value::RawValue>])> = txs_hex
+            .iter()
+            .map(|hex| ("sendrawtransaction", hex.as_ref()))
+            .collect();
+
+        Ok(txs
+            .iter()
+            .zip(
+                self.make_requests_each(&self.node_pool, &calls)?
+                    .into_iter(),
+            )
+            .map(|(tx, res)| (tx.txid(), res.err()))
+            .collect())
+    }
+
+    /// Whether bitcoind accepts packages of transactions with 'submitpackage', on any network
+    /// (from version 28.0).
+    pub fn supports_package_submission(&self) -> Result<bool, BitcoindError> {
+        let version = self
+            .make_node_request("getnetworkinfo", &[])?
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .expect("API break, 'getnetworkinfo' didn't return a valid 'version'");
+        Ok(version >= 280_000)
+    }
+
+    /// Submit a package of transactions, a child along with its unconfirmed parents, to
+    /// bitcoind's mempool and broadcast it with 'submitpackage'. The child must be the last
+    /// transaction. Returns, for each transaction, the reason it was rejected if it was.
+    pub fn submit_package(
+        &self,
+        txs: &[Transaction],
+    ) -> Result<Vec<(Txid, Option<String>)>, BitcoindError> {
+        let txs_hex = txs
             .iter()
-            .map(|hex| {
-                self.node_client
-                    .build_request("sendrawtransaction", hex.as_ref())
+            .map(|tx| Json::String(encode::serialize_hex(tx)))
+            .collect();
+        log::debug!("Submitting package {:?}", txs_hex);
+        let res = self.make_node_request("submitpackage", &params!(Json::Array(txs_hex)))?;
+
+        let package_msg = res
+            .get("package_msg")
+            .and_then(|m| m.as_str())
+            .expect("API break, 'submitpackage' didn't return a 'package_msg'");
+        if package_msg == "success" {
+            return Ok(txs.iter().map(|tx| (tx.txid(), None)).collect());
+        }
+
+        // The results are keyed by wtxid, but each contains the txid. Some transactions may
+        // have been accepted even though the package as a whole wasn't, and the ones that
+        // weren't evaluated are missing.
+        let results: HashMap<Txid, Option<String>> = res
+            .get("tx-results")
+            .and_then(|r| r.as_object())
+            .expect("API break, 'submitpackage' didn't return 'tx-results'")
+            .values()
+            .map(|entry| {
+                let txid = entry
+                    .get("txid")
+                    .and_then(|t| t.as_str())
+                    .and_then(|t| Txid::from_str(t).ok())
+                    .expect("API break, 'submitpackage' entry didn't contain a valid txid");
+                let error = entry
+                    .get("error")
+                    .and_then(|e| e.as_str())
+                    .map(|e| e.to_string());
+                (txid, error)
             })
             .collect();
-        self.make_node_requests(&reqs).map(|_| ())
+        Ok(txs
+            .iter()
+            .map(|tx| {
+                let txid = tx.txid();
+                let error = results
+                    .get(&txid)
+                    .cloned()
+                    .unwrap_or_else(|| Some(package_msg.to_string()));
+                (txid, error)
+            })
+            .collect())
     }
 
     /// Broadcast a transaction that is already part of the wallet
     pub fn rebroadcast_wallet_tx(&self, txid: &Txid) -> Result<(), BitcoindError> {
         let tx = self.get_wallet_transaction(txid)?;
         log::debug!("Re-broadcasting '{}'", tx.hex);
-        self.make_watchonly_request("sendrawtransaction", &params!(Json::String(tx.hex)))
+        self.make_node_request("sendrawtransaction", &params!(Json::String(tx.hex)))
             .map(|_| ())
     }
 
@@ -865,17 +1265,120 @@ impl BitcoinD {
         Ok(None)
     }
 
+    /// Get the wallet transactions confirmed in the blocks after this one, along with those of
+    /// the blocks that were disconnected since. Unconfirmed transactions are not returned. See
+    /// `WalletTxsSince::resolve` for dealing with the duplicates across a reorg.
+    pub fn wallet_txs_since(
+        &self,
+        block_hash: &BlockHash,
+    ) -> Result<WalletTxsSince, BitcoindError> {
+        let lsb_res = self.make_watchonly_request(
+            "listsinceblock",
+            &params!(Json::String(block_hash.to_string())),
+        )?;
+
+        Ok(WalletTxsSince::from_listsinceblock(&lsb_res, block_hash))
+    }
+
     pub fn is_in_mempool(&self, txid: &Txid) -> Result<bool, BitcoindError> {
         match self.make_node_request("getmempoolentry", &params!(Json::String(txid.to_string()))) {
             Ok(_) => Ok(true),
-            Err(BitcoindError::Server(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
-                code: -5,
-                ..
-            }))) => Ok(false),
+            Err(BitcoindError::Rejected { code: -5, .. }) => Ok(false),
             Err(e) => Err(e),
         }
     }
 
+    /// The unconfirmed ancestry of this transaction, if it's in the mempool and spends the
+    /// outputs of unconfirmed transactions.
+    pub fn mempool_ancestry(&self, txid: &Txid) -> Result<Option<MempoolAncestry>, BitcoindError> {
+        let entry = match self
+            .make_node_request("getmempoolentry", &params!(Json::String(txid.to_string())))
+        {
+            Ok(entry) => entry,
+            Err(BitcoindError::Rejected { code: -5, .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let get_u64 = |field: &str| {
+            entry
+                .get(field)
+                .and_then(Json::as_u64)
+                .unwrap_or_else(|| panic!("API break, 'getmempoolentry' has no '{}'", field))
+        };
+        // Both include the transaction itself
+        let count = get_u64("ancestorcount").saturating_sub(1);
+        if count == 0 {
+            return Ok(None);
+        }
+        let vsize = get_u64("ancestorsize").saturating_sub(get_u64("vsize"));
+        let depth = self.mempool_depth(txid, &mut HashMap::new())?;
+
+        Ok(Some(MempoolAncestry {
+            count: count as u32,
+            vsize,
+            depth,
+        }))
+    }
+
+    // The length of the longest chain of unconfirmed ancestors of this mempool transaction. The
+    // recursion is bounded by bitcoind's limit on the number of ancestors (25 by default).
+    fn mempool_depth(
+        &self,
+        txid: &Txid,
+        depths: &mut HashMap<Txid, u32>,
+    ) -> Result<u32, BitcoindError> {
+        if let Some(depth) = depths.get(txid) {
+            return Ok(*depth);
+        }
+
+        let parents: Vec<Txid> = match self
+            .make_node_request("getmempoolentry", &params!(Json::String(txid.to_string())))
+        {
+            Ok(entry) => entry
+                .get("depends")
+                .and_then(Json::as_array)
+                .expect("API break, 'getmempoolentry' has no 'depends'")
+                .iter()
+                .filter_map(|t| t.as_str().and_then(|t| Txid::from_str(t).ok()))
+                .collect(),
+            // It was mined in the meantime
+            Err(BitcoindError::Rejected { code: -5, .. }) => vec![],
+            Err(e) => return Err(e),
+        };
+        let mut depth = 0;
+        for parent in parents {
+            depth = std::cmp::max(depth, self.mempool_depth(&parent, depths)? + 1);
+        }
+        depths.insert(*txid, depth);
+
+        Ok(depth)
+    }
+
+    /// The number of confirmations of the transaction creating this output, 0 if it's in the
+    /// mempool. None if the output doesn't exist (anymore), or was spent.
+    pub fn outpoint_confirmations(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<u32>, BitcoindError> {
+        let txout = self.make_node_request(
+            "gettxout",
+            &params!(
+                Json::String(outpoint.txid.to_string()),
+                Json::Number(outpoint.vout.into()),
+                Json::Bool(true), // include_mempool
+            ),
+        )?;
+        if txout.is_null() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            txout
+                .get("confirmations")
+                .and_then(Json::as_u64)
+                .expect("API break, 'gettxout' has no 'confirmations'") as u32,
+        ))
+    }
+
     /// Check whether a transaction is part of the wallet, and not stuck (as in is confirmed or
     /// part of the mempool).
     pub fn is_current(&self, txid: &Txid) -> Result<bool, BitcoindError> {
@@ -894,31 +1397,257 @@ impl BitcoinD {
         }
     }
 
+    // The feerate estimatesmartfee returns for this confirmation target, in BTC/kvbyte, if
+    // bitcoind has enough data to compute it.
+    fn estimate_smart_fee(&self, conf_target: u64) -> Option<f64> {
+        self.make_node_request(
+            "estimatesmartfee",
+            &params!(Json::Number(serde_json::Number::from(conf_target))),
+        )
+        .ok()?
+        .get("feerate")
+        .map(|n| n.as_f64().expect("feerate is f64"))
+    }
+
     /// Estimates the feerate needed for a tx to make it in the
     /// next block. Uses estimatesmartfee and, in case it returns an
     /// error, a default value.
     /// The value returned is in sats/kWU
     pub fn estimate_feerate(&self) -> Result<Option<u64>, BitcoindError> {
-        if let Ok(json) = self.make_node_request(
-            "estimatesmartfee",
-            &params!(Json::Number(serde_json::Number::from(2))),
-        ) {
-            if let Some(n) = json.get("feerate") {
-                let btc_kvb = n.as_f64().expect("feerate is f64");
-                // Math is hard
-                // btc/kvbyte -> sats/kbyte
-                let sats_kvb = btc_kvb * Amount::ONE_BTC.as_sat() as f64;
-                // sats/kbyte -> sats/vbyte
-                let sats_vb = sats_kvb / 1000.0;
-                // sats/vbyte -> sats/WU
-                let sats_wu = sats_vb / 4.0;
-                // sats/WU -> msats/WU
-                return Ok(Some((sats_wu * 1000.0) as u64));
-            }
+        if let Some(btc_kvb) = self.estimate_smart_fee(2) {
+            // Math is hard
+            // btc/kvbyte -> sats/kbyte
+            let sats_kvb = btc_kvb * Amount::ONE_BTC.as_sat() as f64;
+            // sats/kbyte -> sats/vbyte
+            let sats_vb = sats_kvb / 1000.0;
+            // sats/vbyte -> sats/WU
+            let sats_wu = sats_vb / 4.0;
+            // sats/WU -> msats/WU
+            return Ok(Some((sats_wu * 1000.0) as u64));
         }
         // TODO: Calculate the fallback feerate using the blockchain!
         Ok(None)
     }
+
+    /// The feerates estimatesmartfee returns for a confirmation within 2 and 144 blocks, in
+    /// sats/vbyte (rounded up).
+    pub fn feerate_estimates(&self) -> FeerateEstimates {
+        let sats_vb = |btc_kvb: f64| {
+            let sats_kvb = (btc_kvb * Amount::ONE_BTC.as_sat() as f64).round() as u64;
+            (sats_kvb + 999) / 1000
+        };
+
+        FeerateEstimates {
+            next_block: self.estimate_smart_fee(2).map(sats_vb),
+            next_day: self.estimate_smart_fee(144).map(sats_vb),
+        }
+    }
+
+    /// The minimum feerate for our node to relay a transaction, in sats/vbyte (rounded up)
+    pub fn min_relay_feerate(&self) -> Result<u64, BitcoindError> {
+        let btc_kvb = self
+            .make_node_request("getnetworkinfo", &[])?
+            .get("relayfee")
+            .and_then(|f| f.as_f64())
+            .expect("API break, 'getnetworkinfo' didn't return a valid 'relayfee'");
+        let sats_kvb = (btc_kvb * Amount::ONE_BTC.as_sat() as f64).round() as u64;
+        Ok((sats_kvb + 999) / 1000)
+    }
+
+    /// The minimum feerate for a transaction to enter the mempool of our node, in sats/vbyte
+    /// (rounded up). It's above the relay one when the mempool is full.
+    pub fn mempool_min_feerate(&self) -> Result<u64, BitcoindError> {
+        let btc_kvb = self
+            .make_node_request("getmempoolinfo", &[])?
+            .get("mempoolminfee")
+            .and_then(|f| f.as_f64())
+            .expect("API break, 'getmempoolinfo' didn't return a valid 'mempoolminfee'");
+        let sats_kvb = (btc_kvb * Amount::ONE_BTC.as_sat() as f64).round() as u64;
+        Ok((sats_kvb + 999) / 1000)
+    }
+
+    /// Check whether bitcoind would accept this package of transactions in its mempool,
+    /// without broadcasting them. Returns, for each transaction, the reason it was rejected
+    /// if it was.
+    pub fn test_mempool_accept(
+        &self,
+        txs: &[Transaction],
+    ) -> Result<Vec<(Txid, Option<String>)>, BitcoindError> {
+        let txs_hex = txs
+            .iter()
+            .map(|tx| Json::String(encode::serialize_hex(tx)))
+            .collect();
+        let res = self.make_node_request("testmempoolaccept", &params!(Json::Array(txs_hex)))?;
+
+        Ok(res
+            .as_array()
+            .expect("API break, 'testmempoolaccept' didn't return an array")
+            .iter()
+            .map(|entry| {
+                let txid = entry
+                    .get("txid")
+                    .and_then(|t| t.as_str())
+                    .and_then(|t| Txid::from_str(t).ok())
+                    .expect("API break, 'testmempoolaccept' entry didn't contain a valid txid");
+                // A transaction following a rejected one in the package is not evaluated, and
+                // doesn't have an 'allowed' entry.
+                let reject_reason = match entry.get("allowed").and_then(|a| a.as_bool()) {
+                    Some(true) => None,
+                    _ => Some(
+                        entry
+                            .get("reject-reason")
+                            .or_else(|| entry.get("package-error"))
+                            .and_then(|r| r.as_str())
+                            .unwrap_or(NOT_EVALUATED)
+                            .to_string(),
+                    ),
+                };
+                (txid, reject_reason)
+            })
+            .collect())
+    }
+}
+
+/// The unconfirmed transactions a mempool transaction depends on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MempoolAncestry {
+    /// The number of unconfirmed ancestors
+    pub count: u32,
+    /// Their total virtual size
+    pub vsize: u64,
+    /// The length of the longest chain of unconfirmed ancestors
+    pub depth: u32,
+}
+
+/// A wallet transaction as listed by `listsinceblock`, in the block it was confirmed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ListedTransaction {
+    pub blockheight: u32,
+    /// Its position in the block
+    pub blockindex: u64,
+    pub blockhash: BlockHash,
+    pub txid: Txid,
+}
+
+/// The wallet transactions `listsinceblock` returned. There may be an entry per output of a
+/// transaction we are interested in, and across a reorg a transaction is listed once in each of
+/// the blocks it was confirmed in, whether they are still part of the chain or not.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WalletTxsSince {
+    /// The transactions confirmed in a block
+    pub confirmed: Vec<ListedTransaction>,
+    /// The transactions of the blocks that were disconnected
+    pub removed: Vec<Txid>,
+}
+
+impl WalletTxsSince {
+    /// Parse the result of a `listsinceblock` since this block
+    pub fn from_listsinceblock(lsb_res: &Json, block_hash: &BlockHash) -> WalletTxsSince {
+        let entries = |field: &str| {
+            lsb_res
+                .get(field)
+                .map(|t| t.as_array())
+                .flatten()
+                .expect(&format!(
+                    "API break: no or invalid '{}' in 'listsinceblock' result (blockhash: {})",
+                    field, block_hash
+                ))
+        };
+        let txid = |entry: &Json| {
+            entry
+                .get("txid")
+                .map(|t| t.as_str().map(|t| Txid::from_str(t).ok()))
+                .flatten()
+                .flatten()
+                .expect(&format!(
+                    "API break: no or invalid 'txid' in 'listsinceblock' entry (blockhash: {})",
+                    block_hash
+                ))
+        };
+
+        let mut confirmed = Vec::new();
+        for entry in entries("transactions") {
+            let blockheight = match entry.get("blockheight").map(|h| h.as_u64()) {
+                Some(Some(height)) => height as u32,
+                _ => continue,
+            };
+            let blockindex = entry
+                .get("blockindex")
+                .map(|i| i.as_u64())
+                .flatten()
+                .expect(&format!(
+                "API break: no or invalid 'blockindex' in 'listsinceblock' entry (blockhash: {})",
+                block_hash
+            ));
+            let blockhash = entry
+                .get("blockhash")
+                .map(|h| h.as_str().map(|h| BlockHash::from_str(h).ok()))
+                .flatten()
+                .flatten()
+                .expect(&format!(
+                    "API break: no or invalid 'blockhash' in 'listsinceblock' entry (blockhash: {})",
+                    block_hash
+                ));
+            confirmed.push(ListedTransaction {
+                blockheight,
+                blockindex,
+                blockhash,
+                txid: txid(entry),
+            });
+        }
+
+        // Only present if the block we list since was disconnected
+        let removed = if lsb_res.get("removed").is_some() {
+            entries("removed").iter().map(txid).collect()
+        } else {
+            Vec::new()
+        };
+
+        WalletTxsSince { confirmed, removed }
+    }
+
+    /// Keep a single entry per confirmed transaction, for the block it was confirmed in on the
+    /// chain `active_hash` gives the block hashes of, ordered as they appear in this chain. The
+    /// transactions that were only confirmed in blocks that are not part of it anymore are
+    /// removed, unless they were confirmed again on this chain.
+    pub fn resolve<E>(
+        self,
+        mut active_hash: impl FnMut(u32) -> Result<BlockHash, E>,
+    ) -> Result<WalletTxsSince, E> {
+        let WalletTxsSince {
+            mut confirmed,
+            mut removed,
+        } = self;
+        confirmed.sort_unstable();
+        confirmed.dedup();
+
+        let mut active_hashes = HashMap::new();
+        let mut active = Vec::with_capacity(confirmed.len());
+        for tx in confirmed {
+            let hash = match active_hashes.get(&tx.blockheight) {
+                Some(hash) => *hash,
+                None => {
+                    let hash = active_hash(tx.blockheight)?;
+                    active_hashes.insert(tx.blockheight, hash);
+                    hash
+                }
+            };
+            if hash == tx.blockhash {
+                active.push(tx);
+            } else {
+                removed.push(tx.txid);
+            }
+        }
+
+        removed.sort_unstable();
+        removed.dedup();
+        removed.retain(|txid| !active.iter().any(|tx| &tx.txid == txid));
+
+        Ok(WalletTxsSince {
+            confirmed: active,
+            removed,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -970,7 +1699,7 @@ pub struct ListUnspentEntry {
     pub txo: TxOut,
     pub label: Option<String>,
     pub confirmations: u64,
-    pub derivation_index: Option<ChildNumber>,
+    pub derivation_index: Option<DerivationIndex>,
 }
 
 impl From<&Json> for ListUnspentEntry {
@@ -1027,8 +1756,8 @@ impl From<&Json> for ListUnspentEntry {
                     // Also we always use normal derivation
                     derivation_index = d[s + 1..e]
                         .parse()
-                        .map(|d| ChildNumber::Normal { index: d })
-                        .ok();
+                        .ok()
+                        .and_then(|d| DerivationIndex::new(d).ok());
                 }
             }
         }
@@ -1048,3 +1777,225 @@ impl From<&Json> for ListUnspentEntry {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_decision, scanning_progress, send_request, RetryDecision};
+    use crate::{
+        bitcoind::BitcoindError,
+        clock::{test_utils::MockClock, Clock},
+    };
+
+    use std::{
+        collections::VecDeque,
+        fmt, io,
+        sync::{atomic::AtomicBool, Arc, Mutex},
+        time::Duration,
+    };
+
+    use jsonrpc::{
+        client::{Client, Transport},
+        simple_http::Error as HttpError,
+        Request, Response,
+    };
+
+    // A transport that replays the given replies, in order.
+    struct MockTransport(Arc<Mutex<VecDeque<Result<Response, jsonrpc::Error>>>>);
+
+    impl Transport for MockTransport {
+        fn send_request(&self, _: Request) -> Result<Response, jsonrpc::Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("Unexpected request")
+        }
+
+        fn send_batch(&self, _: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+            unimplemented!()
+        }
+
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "mock")
+        }
+    }
+
+    fn socket_error(kind: io::ErrorKind) -> jsonrpc::Error {
+        jsonrpc::Error::Transport(Box::new(HttpError::SocketError(io::Error::from(kind))))
+    }
+
+    fn response(result: &str) -> Result<Response, jsonrpc::Error> {
+        Ok(serde_json::from_str(&format!("{{\"jsonrpc\":\"2.0\",\"id\":1,{}}}", result)).unwrap())
+    }
+
+    fn mock_client(
+        replies: Vec<Result<Response, jsonrpc::Error>>,
+    ) -> (
+        Client,
+        Arc<Mutex<VecDeque<Result<Response, jsonrpc::Error>>>>,
+    ) {
+        let replies = Arc::new(Mutex::new(replies.into_iter().collect()));
+        (
+            Client::with_transport(MockTransport(replies.clone())),
+            replies,
+        )
+    }
+
+    #[test]
+    fn bitcoind_retry_matrix() {
+        let window = Duration::from_secs(45);
+        let unreachable = BitcoindError::from(socket_error(io::ErrorKind::ConnectionRefused));
+        let timeout = BitcoindError::from(socket_error(io::ErrorKind::WouldBlock));
+        assert!(matches!(unreachable, BitcoindError::Unreachable(_)));
+        assert!(matches!(timeout, BitcoindError::Timeout));
+
+        // Transient errors are retried with an exponential, capped, backoff..
+        for err in &[&unreachable, &timeout] {
+            for (attempt, backoff) in
+                [(0, 500), (1, 1_000), (2, 2_000), (4, 8_000), (40, 8_000)].iter()
+            {
+                assert_eq!(
+                    retry_decision(err, *attempt, Duration::from_secs(1), window, true),
+                    RetryDecision::Retry(Duration::from_millis(*backoff))
+                );
+            }
+            // .. But only for so long.
+            assert_eq!(
+                retry_decision(err, 3, Duration::from_secs(42), window, true),
+                RetryDecision::GiveUp
+            );
+            // .. And not before bitcoind ever answered, as we most likely got its address wrong.
+            assert_eq!(
+                retry_decision(err, 0, Duration::from_secs(0), window, false),
+                RetryDecision::GiveUp
+            );
+        }
+
+        // Errors from bitcoind itself are never retried.
+        let wallet_err = BitcoindError::from(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+            code: -18,
+            message: "Requested wallet does not exist or is not loaded".to_string(),
+            data: None,
+        }));
+        assert!(matches!(wallet_err, BitcoindError::WalletNotLoaded(_)));
+        let rejected_with = |message: &str| {
+            BitcoindError::from(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+                code: -26,
+                message: message.to_string(),
+                data: None,
+            }))
+        };
+        let rejected = rejected_with("txn-mempool-conflict");
+        assert!(rejected.is_mempool_conflict());
+        assert!(rejected_with(
+            "insufficient fee, rejecting replacement 2f8e5c4e0a1f4e3b91d2b7c0a2f3e8d41b6e9c5a7d3f\
+             2e1c0b9a8f7e6d5c4b3a, less fees than conflicting txs; 0.00001 < 0.00092"
+        )
+        .is_mempool_conflict());
+        // Paying too little is not a conflict
+        for message in &[
+            "min relay fee not met, 100 < 141",
+            "mempool min fee not met, 141 < 2000",
+            "insufficient fee",
+        ] {
+            assert!(!rejected_with(message).is_mempool_conflict(), "{}", message);
+        }
+        for err in &[&wallet_err, &rejected] {
+            assert_eq!(
+                retry_decision(err, 0, Duration::from_secs(0), window, true),
+                RetryDecision::GiveUp
+            );
+        }
+
+        // Garbage is retried once.
+        let garbage = BitcoindError::from(jsonrpc::Error::Transport(Box::new(
+            HttpError::HttpParseError,
+        )));
+        assert_eq!(
+            retry_decision(&garbage, 0, Duration::from_secs(0), window, true),
+            RetryDecision::Retry(Duration::from_millis(500))
+        );
+        assert_eq!(
+            retry_decision(&garbage, 1, Duration::from_secs(0), window, true),
+            RetryDecision::GiveUp
+        );
+    }
+
+    #[test]
+    fn bitcoind_send_request_retries() {
+        let clock = MockClock::new(1_600_000_000);
+        let window = Duration::from_secs(45);
+
+        // We never reached bitcoind: it's not there, don't wait.
+        let reached = AtomicBool::new(false);
+        let (client, replies) = mock_client(vec![
+            Err(socket_error(io::ErrorKind::ConnectionRefused)),
+            response("\"result\":100"),
+        ]);
+        let start = clock.now();
+        let err =
+            send_request(&client, &clock, window, &reached, "getblockcount", &[]).unwrap_err();
+        assert!(matches!(err, BitcoindError::Unreachable(_)));
+        assert_eq!(replies.lock().unwrap().len(), 1);
+        assert_eq!(clock.elapsed(start), Duration::from_secs(0));
+        let res = send_request(&client, &clock, window, &reached, "getblockcount", &[]).unwrap();
+        assert_eq!(res, serde_json::json!(100));
+
+        // Once it answered, it may be unreachable for a few seconds then answer again.
+        let (client, replies) = mock_client(vec![
+            Err(socket_error(io::ErrorKind::ConnectionRefused)),
+            Err(socket_error(io::ErrorKind::WouldBlock)),
+            Err(socket_error(io::ErrorKind::ConnectionRefused)),
+            response("\"result\":100"),
+        ]);
+        let start = clock.now();
+        let res = send_request(&client, &clock, window, &reached, "getblockcount", &[]).unwrap();
+        assert_eq!(res, serde_json::json!(100));
+        assert!(replies.lock().unwrap().is_empty());
+        assert_eq!(
+            clock.elapsed(start),
+            Duration::from_millis(500 + 1_000 + 2_000)
+        );
+
+        // A wallet error is not retried.
+        let (client, replies) = mock_client(vec![
+            response("\"result\":null,\"error\":{\"code\":-18,\"message\":\"Wallet not loaded\"}"),
+            response("\"result\":100"),
+        ]);
+        let err = send_request(&client, &clock, window, &reached, "listunspent", &[]).unwrap_err();
+        assert!(matches!(err, BitcoindError::WalletNotLoaded(_)));
+        assert_eq!(replies.lock().unwrap().len(), 1);
+
+        // If bitcoind stays unreachable, we eventually give up.
+        let (client, replies) = mock_client(
+            (0..20)
+                .map(|_| Err(socket_error(io::ErrorKind::ConnectionRefused)))
+                .collect(),
+        );
+        let start = clock.now();
+        let err =
+            send_request(&client, &clock, window, &reached, "getblockcount", &[]).unwrap_err();
+        assert!(err.is_transient());
+        assert!(clock.elapsed(start) <= window);
+        assert!(!replies.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn bitcoind_scanning_progress() {
+        let walletinfo = |scanning: &str| -> serde_json::Value {
+            serde_json::from_str(&format!(
+                "{{\"walletname\":\"revaultd-watchonly-wallet-1\",\"scanning\":{}}}",
+                scanning
+            ))
+            .unwrap()
+        };
+
+        assert_eq!(scanning_progress(&walletinfo("false")), None);
+        assert_eq!(
+            scanning_progress(&walletinfo("{\"duration\":12,\"progress\":0.4}")),
+            Some(0.4)
+        );
+        // Older versions don't have it at all
+        assert_eq!(scanning_progress(&serde_json::json!({})), None);
+    }
+}
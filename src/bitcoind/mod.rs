@@ -1,17 +1,27 @@
+pub mod audit;
 pub mod interface;
 pub mod poller;
+pub mod pool;
+pub mod relay_floor;
+pub mod rescan;
 pub mod utils;
 
 use crate::config::BitcoindConfig;
-use crate::{database::DatabaseError, revaultd::RevaultD, threadmessages::BitcoindMessageOut};
+use crate::{
+    database::DatabaseError,
+    revaultd::{RevaultD, ScriptTypeError},
+    sdnotify::{self, Heartbeat},
+    threadmessages::{BitcoindMessageOut, StateMachineSender},
+};
 use interface::{BitcoinD, WalletTransaction};
-use poller::poller_main;
+use poller::{broadcast_unvaults, poller_main};
 use revault_tx::bitcoin::{Network, Txid};
 
 use std::{
+    io,
     sync::{
         atomic::{AtomicBool, Ordering},
-        mpsc::Receiver,
+        mpsc::{self, Receiver},
         Arc, RwLock,
     },
     thread,
@@ -30,6 +40,17 @@ pub enum BitcoindError {
     Custom(String),
     /// Or directly to bitcoind's RPC server
     Server(Error),
+    /// We could not get in touch with bitcoind at all
+    Unreachable(String),
+    /// bitcoind did not answer in time
+    Timeout,
+    /// The wallet we sent the request to is not loaded
+    WalletNotLoaded(String),
+    /// bitcoind answered with an error
+    Rejected {
+        code: i32,
+        msg: String,
+    },
     /// They replied to a batch request omitting some responses
     BatchMissingResponse,
     RevaultTx(revault_tx::Error),
@@ -40,10 +61,43 @@ impl BitcoindError {
     pub fn is_warming_up(&self) -> bool {
         match self {
             // https://github.com/bitcoin/bitcoin/blob/dca80ffb45fcc8e6eedb6dc481d500dedab4248b/src/rpc/protocol.h#L49
-            BitcoindError::Server(Error::Rpc(RpcError { code, .. })) => *code == -28,
+            BitcoindError::Rejected { code, .. } => *code == -28,
+            _ => false,
+        }
+    }
+
+    /// Was the transaction rejected because it conflicts with one already in the mempool?
+    pub fn is_mempool_conflict(&self) -> bool {
+        match self {
+            // RPC_VERIFY_REJECTED, along with the reject reason from the mempool acceptance. A
+            // replacement paying too little is a conflict, a transaction paying too little for
+            // the mempool is not.
+            BitcoindError::Rejected { code, msg } => {
+                *code == -26
+                    && (msg.contains("txn-mempool-conflict")
+                        || (msg.contains("insufficient fee")
+                            && msg.contains("rejecting replacement")))
+            }
             _ => false,
         }
     }
+
+    /// Did we try to load a wallet that is already loaded?
+    pub fn is_wallet_already_loaded(&self) -> bool {
+        match self {
+            // RPC_WALLET_ALREADY_LOADED, older versions return a generic wallet error.
+            BitcoindError::Rejected { code, msg } => {
+                *code == -35 || (*code == -4 && msg.contains("already loaded"))
+            }
+            _ => false,
+        }
+    }
+
+    /// Did we fail to communicate with bitcoind, as opposed to bitcoind refusing our request?
+    /// Such errors are expected to go away by themselves.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, BitcoindError::Unreachable(_) | BitcoindError::Timeout)
+    }
 }
 
 impl std::fmt::Display for BitcoindError {
@@ -51,6 +105,18 @@ impl std::fmt::Display for BitcoindError {
         match self {
             BitcoindError::Custom(ref s) => write!(f, "Bitcoind manager error: {}", s),
             BitcoindError::Server(ref e) => write!(f, "Bitcoind server error: {}", e),
+            BitcoindError::Unreachable(ref s) => write!(f, "Bitcoind is unreachable: {}", s),
+            BitcoindError::Timeout => write!(f, "Timed out waiting for bitcoind to answer"),
+            BitcoindError::WalletNotLoaded(ref s) => {
+                write!(f, "Bitcoind wallet is not loaded: {}", s)
+            }
+            BitcoindError::Rejected { code, ref msg } => {
+                write!(
+                    f,
+                    "Bitcoind rejected our request: '{}' (code {})",
+                    msg, code
+                )
+            }
             BitcoindError::BatchMissingResponse => write!(
                 f,
                 "Bitcoind server replied without enough responses to our batched request"
@@ -68,9 +134,40 @@ impl From<DatabaseError> for BitcoindError {
     }
 }
 
+impl From<Error> for BitcoindError {
+    fn from(e: Error) -> Self {
+        match e {
+            // https://github.com/bitcoin/bitcoin/blob/dca80ffb45fcc8e6eedb6dc481d500dedab4248b/src/rpc/protocol.h#L79
+            Error::Rpc(RpcError {
+                code: -18, message, ..
+            }) => Self::WalletNotLoaded(message),
+            Error::Rpc(RpcError { code, message, .. }) => Self::Rejected { code, msg: message },
+            Error::Transport(ref err) => {
+                // This is *always* a simple_http::Error.
+                let socket_err = match err.downcast_ref::<simple_http::Error>() {
+                    Some(simple_http::Error::SocketError(io_err)) => match io_err.kind() {
+                        // Unix returns WouldBlock on a read timeout, Windows TimedOut.
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Some(Self::Timeout),
+                        _ => Some(Self::Unreachable(io_err.to_string())),
+                    },
+                    _ => None,
+                };
+                socket_err.unwrap_or(Self::Server(e))
+            }
+            e => Self::Server(e),
+        }
+    }
+}
+
 impl From<simple_http::Error> for BitcoindError {
     fn from(e: simple_http::Error) -> Self {
-        Self::Server(Error::Transport(Box::new(e)))
+        Error::Transport(Box::new(e)).into()
+    }
+}
+
+impl From<ScriptTypeError> for BitcoindError {
+    fn from(e: ScriptTypeError) -> Self {
+        Self::Custom(format!("Deriving our addresses: {}", e))
     }
 }
 
@@ -127,6 +224,7 @@ pub fn start_bitcoind(revaultd: &mut RevaultD) -> Result<BitcoinD, BitcoindError
         revaultd
             .cpfp_wallet_file()
             .expect("Wallet id is set at startup in setup_db()"),
+        revaultd.clock.clone(),
     )
     .map_err(|e| {
         BitcoindError::Custom(format!("Could not connect to bitcoind: {}", e.to_string()))
@@ -135,6 +233,7 @@ pub fn start_bitcoind(revaultd: &mut RevaultD) -> Result<BitcoinD, BitcoindError
     while let Err(e) = bitcoind_sanity_checks(&bitcoind, &revaultd.bitcoind_config) {
         if e.is_warming_up() {
             log::info!("Bitcoind is warming up. Waiting for it to be back up.");
+            sdnotify::status("Waiting for bitcoind to warm up");
             thread::sleep(Duration::from_secs(3))
         } else {
             return Err(e);
@@ -165,21 +264,49 @@ pub fn bitcoind_main_loop(
     rx: Receiver<BitcoindMessageOut>,
     revaultd: Arc<RwLock<RevaultD>>,
     bitcoind: BitcoinD,
+    statemachine: StateMachineSender,
+    heartbeat: Heartbeat,
 ) -> Result<(), BitcoindError> {
     let bitcoind = Arc::new(RwLock::new(bitcoind));
     // The verification progress announced by bitcoind *at startup* thus won't be updated
     // after startup check. Should be *exactly* 1.0 when synced, but hey, floats so we are
     // careful.
     let sync_progress = Arc::new(RwLock::new(0.0f64));
+    // Set by the poller thread while bitcoind is rescanning the chain for our wallets.
+    let rescan_progress = Arc::new(RwLock::new(None));
+    // Set by the poller thread while the rescanner imports descriptors, to what they are for.
+    let rescan_window = Arc::new(RwLock::new(None));
+    // Set by the poller thread if it could not reach bitcoind the last time it tried to.
+    let reachable = Arc::new(AtomicBool::new(true));
     // Used to shutdown the poller thread
     let shutdown = Arc::new(AtomicBool::new(false));
+    // The wallet audits are performed by the poller thread, which owns the rescanner needed to
+    // import back the addresses our wallet lost.
+    let (audit_requests, audit_requests_rx) = mpsc::channel();
 
     // We use a thread to 1) wait for bitcoind to be synced 2) poll listunspent
     let poller_thread = std::thread::spawn({
+        let _revaultd = revaultd.clone();
         let _bitcoind = bitcoind.clone();
         let _sync_progress = sync_progress.clone();
+        let _rescan_progress = rescan_progress.clone();
+        let _rescan_window = rescan_window.clone();
+        let _reachable = reachable.clone();
         let _shutdown = shutdown.clone();
-        move || poller_main(revaultd, _bitcoind, _sync_progress, _shutdown)
+        move || {
+            poller_main(
+                _revaultd,
+                _bitcoind,
+                _sync_progress,
+                _rescan_progress,
+                _rescan_window,
+                _reachable,
+                _shutdown,
+                audit_requests_rx,
+                statemachine,
+                heartbeat,
+            )
+        }
     });
 
     for msg in rx {
@@ -194,13 +321,76 @@ pub fn bitcoind_main_loop(
                 return Ok(());
             }
             BitcoindMessageOut::SyncProgress(resp_tx) => {
-                resp_tx.send(*sync_progress.read().unwrap()).map_err(|e| {
+                // We are not synchronized until bitcoind is done rescanning for our wallets.
+                let progress = match *rescan_progress.read().unwrap() {
+                    Some(rescan) => sync_progress.read().unwrap().min(rescan).min(0.9999),
+                    None => *sync_progress.read().unwrap(),
+                };
+                resp_tx.send(progress).map_err(|e| {
                     BitcoindError::Custom(format!(
                         "Sending synchronization progress to main thread: {}",
                         e
                     ))
                 })?;
             }
+            BitcoindMessageOut::RescanWindow(resp_tx) => {
+                resp_tx.send(*rescan_window.read().unwrap()).map_err(|e| {
+                    BitcoindError::Custom(format!(
+                        "Sending the rescan window to main thread: {}",
+                        e
+                    ))
+                })?;
+            }
+            BitcoindMessageOut::Reachable(resp_tx) => {
+                resp_tx
+                    .send(reachable.load(Ordering::Relaxed))
+                    .map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Sending bitcoind reachability to main thread: {}",
+                            e
+                        ))
+                    })?;
+            }
+            BitcoindMessageOut::ConnectionPools(resp_tx) => {
+                resp_tx
+                    .send(bitcoind.read().unwrap().connection_pools())
+                    .map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Sending bitcoind connection pools usage to main thread: {}",
+                            e
+                        ))
+                    })?;
+            }
+            BitcoindMessageOut::MinRelayFeerate(resp_tx) => {
+                resp_tx
+                    .send(bitcoind.read().unwrap().min_relay_feerate())
+                    .map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Sending minimum relay feerate to main thread: {}",
+                            e
+                        ))
+                    })?;
+            }
+            BitcoindMessageOut::OutpointConfirmations(outpoint, resp_tx) => {
+                resp_tx
+                    .send(bitcoind.read().unwrap().outpoint_confirmations(&outpoint))
+                    .map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Sending confirmations of '{}' to main thread: {}",
+                            outpoint, e
+                        ))
+                    })?;
+            }
+            BitcoindMessageOut::CpfpBalance(resp_tx) => {
+                resp_tx
+                    .send(bitcoind.read().unwrap().cpfp_balance())
+                    .map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Sending CPFP wallet balance to main thread: {}",
+                            e
+                        ))
+                    })?;
+            }
             BitcoindMessageOut::WalletTransaction(txid, resp_tx) => {
                 log::trace!("Received 'wallettransaction' from main thread");
                 // FIXME: what if bitcoind isn't synced?
@@ -215,14 +405,72 @@ pub fn bitcoind_main_loop(
             }
             BitcoindMessageOut::BroadcastTransactions(txs, resp_tx) => {
                 log::trace!("Received 'broadcastransactions' from main thread");
-                resp_tx
-                    .send(bitcoind.read().unwrap().broadcast_transactions(&txs))
-                    .map_err(|e| {
-                        BitcoindError::Custom(format!(
-                            "Sending transactions broadcast result to main thread: {}",
+                let res = bitcoind.read().unwrap().broadcast_transactions(&txs);
+                for tx in txs.iter() {
+                    match res {
+                        Ok(()) => log_event!(
+                            log::Level::Info,
+                            "tx_broadcast",
+                            txid = tx.txid();
+                            "Broadcasted transaction '{}'",
+                            tx.txid()
+                        ),
+                        Err(ref e) => log_event!(
+                            log::Level::Error,
+                            "tx_broadcast_failure",
+                            txid = tx.txid(),
+                            error = e;
+                            "Error broadcasting transaction '{}': '{}'",
+                            tx.txid(),
                             e
-                        ))
-                    })?;
+                        ),
+                    }
+                }
+                resp_tx.send(res).map_err(|e| {
+                    BitcoindError::Custom(format!(
+                        "Sending transactions broadcast result to main thread: {}",
+                        e
+                    ))
+                })?;
+            }
+            BitcoindMessageOut::BroadcastTransactionsEach(txs, resp_tx) => {
+                log::trace!("Received 'broadcasttransactionseach' from main thread");
+                let res = bitcoind
+                    .read()
+                    .unwrap()
+                    .broadcast_transactions_each(&txs)
+                    .map(|results| {
+                        results
+                            .into_iter()
+                            .map(|(txid, error)| (txid, error.map(|e| e.to_string())))
+                            .collect()
+                    });
+                resp_tx.send(res).map_err(|e| {
+                    BitcoindError::Custom(format!(
+                        "Sending transactions broadcast results to main thread: {}",
+                        e
+                    ))
+                })?;
+            }
+            BitcoindMessageOut::BroadcastUnvaults(unvaults, priority, resp_tx) => {
+                log::trace!("Received 'broadcastunvaults' from main thread");
+                let res =
+                    broadcast_unvaults(&revaultd, &bitcoind.read().unwrap(), unvaults, priority);
+                resp_tx.send(res).map_err(|e| {
+                    BitcoindError::Custom(format!(
+                        "Sending Unvault transactions broadcast result to main thread: {}",
+                        e
+                    ))
+                })?;
+            }
+            BitcoindMessageOut::AuditWallet(resp_tx) => {
+                log::trace!("Received 'auditwallet' from main thread");
+                audit_requests.send(resp_tx).map_err(|e| {
+                    BitcoindError::Custom(format!(
+                        "Sending wallet audit request to poller thread: {}",
+                        e
+                    ))
+                })?;
             }
         }
     }
@@ -1,36 +1,62 @@
 use crate::config::BitcoindConfig;
 use crate::{
+    alerts::Alerter,
     bitcoind::{
-        interface::{BitcoinD, DepositsState, SyncInfo, UnvaultsState, UtxoInfo},
+        audit::{audit_wallet, WalletAudit},
+        interface::{
+            BitcoinD, DepositsState, ListedTransaction, SyncInfo, UnvaultsState, UtxoInfo,
+            COINBASE_MATURITY, MIN_DEPOSIT_VALUE, NOT_EVALUATED,
+        },
+        relay_floor::check_relay_floor,
+        rescan::{ImportKind, RescanImport, RescanWindow, Rescanner},
         utils::{
-            cancel_txid, emer_txid, populate_deposit_cache, populate_unvaults_cache,
-            presigned_transactions, unemer_txid, unvault_txin_from_deposit,
+            cancel_txid, emer_txid, populate_deposit_cache, populate_unvaults_cache, unemer_txid,
+            unvault_txin_from_deposit,
         },
         BitcoindError,
     },
+    commands::{utils::expire_stale_spends, EmergencyTxKind},
     database::{
         actions::{
-            db_cancel_unvault, db_confirm_deposit, db_confirm_unvault, db_emer_unvault,
-            db_insert_new_unconfirmed_vault, db_mark_broadcasted_spend, db_mark_canceled_unvault,
-            db_mark_emergencied_unvault, db_mark_emergencied_vault, db_mark_emergencying_vault,
-            db_mark_rebroadcastable_spend, db_mark_spent_unvault, db_spend_unvault,
+            db_abandon_vault, db_cancel_unvault, db_clear_replayed_txs, db_confirm_unvault,
+            db_deprecate_vault_spends, db_emer_unvault, db_insert_spend_destinations,
+            db_mark_broadcasted_spend, db_mark_canceled_unvault, db_mark_emergencied_unvault,
+            db_mark_emergencied_vault, db_mark_emergencying_vault, db_mark_rebroadcastable_spend,
+            db_mark_spendable_vault, db_mark_spent_unvault, db_raise_vault_flag,
+            db_record_replayed_tx, db_record_revocation_check, db_set_conflicts_competing,
+            db_settle_conflicts, db_spend_unvault, db_store_derived_scripts,
             db_unconfirm_cancel_dbtx, db_unconfirm_deposit_dbtx, db_unconfirm_emer_dbtx,
             db_unconfirm_spend_dbtx, db_unconfirm_unemer_dbtx, db_unconfirm_unvault_dbtx,
-            db_unvault_deposit, db_update_deposit_index, db_update_tip, db_update_tip_dbtx,
+            db_unmature_unvault_dbtx, db_unvault_deposit, db_update_imported_index,
+            db_update_tip_dbtx, db_update_unvault_height_dbtx,
         },
         interface::{
-            db_broadcastable_spend_transactions, db_cancel_dbtx, db_canceling_vaults,
-            db_cpfpable_spends, db_cpfpable_unvaults, db_emering_vaults, db_exec,
-            db_spending_vaults, db_tip, db_unemering_vaults, db_unvault_dbtx,
-            db_unvault_transaction, db_vault_by_deposit, db_vault_by_unvault_txid, db_vaults_dbtx,
-            db_wallet,
+            db_broadcastable_spend_transactions, db_cancel_dbtx, db_cancel_transaction,
+            db_canceling_vaults, db_cpfpable_spends, db_cpfpable_unvaults,
+            db_deposit_coinbase_height_dbtx, db_derived_scripts, db_emer_transaction,
+            db_emering_vaults, db_exec, db_imported_index, db_last_revocation_check,
+            db_replay_window, db_rescan_timestamp, db_spend_transaction, db_spending_vaults,
+            db_tip, db_unemering_vaults, db_unvault_dbtx, db_unvault_emer_transaction,
+            db_unvault_transaction, db_vault_by_deposit, db_vault_by_unvault_txid, db_vault_flags,
+            db_vaults, db_vaults_dbtx, db_vaults_from_spend, db_wallet,
         },
-        schema::DbVault,
+        schema::{DbTransaction, DbVault, ScriptKind, VaultFlagKind},
     },
+    derivation::DerivationIndex,
+    diskspace::DiskSpaceLevel,
+    hooks::{process_emergency_runs, process_status_changes, HookRunner},
+    indexallocator::IndexAllocError,
     revaultd::{BlockchainTip, RevaultD, VaultStatus},
+    sdnotify::{self, Heartbeat},
+    threadmessages::{
+        ChainEvent, ConfirmedTx, StateMachineSender, StateMachineThread, UnvaultsBroadcast,
+    },
 };
 use revault_tx::{
-    bitcoin::{consensus::encode, secp256k1, Amount, OutPoint, Txid},
+    bitcoin::{
+        consensus::encode, hashes::hex::FromHex, secp256k1, Amount, BlockHash, OutPoint, Script,
+        Transaction, Txid,
+    },
     error::TransactionCreationError,
     miniscript::descriptor::{DescriptorSecretKey, DescriptorXKey, KeyMap, Wildcard},
     scripts::CpfpDescriptor,
@@ -43,19 +69,24 @@ use revault_tx::{
 };
 
 use std::{
-    collections::{HashMap, HashSet},
+    cmp,
+    collections::{BTreeMap, HashMap, HashSet},
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, SyncSender},
         Arc, RwLock,
     },
     thread,
-    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant},
 };
 
 // At how many sats/kWU below the target feerate do we CPFP a transaction.
 const CPFP_THRESHOLD: u64 = 1_000;
 
+// How many transactions bitcoind accepts at most in a package.
+const MAX_PACKAGE_COUNT: usize = 25;
+
 // Try to broadcast fully signed spend transactions, only mature ones will get through
 fn maybe_broadcast_spend_transactions(
     revaultd: &Arc<RwLock<RevaultD>>,
@@ -66,6 +97,7 @@ fn maybe_broadcast_spend_transactions(
     for db_spendtx in db_broadcastable_spend_transactions(&db_path)? {
         let mut psbt = db_spendtx.psbt;
         let txid = psbt.txid();
+        rebroadcast_unvaults(revaultd, bitcoind, &txid)?;
         log::debug!("Trying to broadcast Spend tx '{}'", &txid);
 
         match psbt.finalize(&revaultd.read().unwrap().secp_ctx) {
@@ -79,21 +111,186 @@ fn maybe_broadcast_spend_transactions(
         let tx = psbt.into_psbt().extract_tx();
         match bitcoind.broadcast_transaction(&tx) {
             Ok(()) => {
-                log::info!("Succesfully broadcasted Spend tx '{}'", txid);
+                log_event!(
+                    log::Level::Info,
+                    "tx_broadcast",
+                    tx_type = "spend",
+                    txid = txid;
+                    "Succesfully broadcasted Spend tx '{}'",
+                    txid
+                );
                 // FIXME: that's not so robust as we'll never try it again. Better tracking should
                 // be part of the CPFP wallet work.
                 db_mark_broadcasted_spend(&db_path, &txid)?;
             }
+            Err(e) if e.is_mempool_conflict() => {
+                record_spend_conflicts(revaultd, bitcoind, &tx)?;
+            }
+            Err(e) => {
+                log_event!(
+                    log::Level::Error,
+                    "tx_broadcast_failure",
+                    tx_type = "spend",
+                    txid = txid,
+                    error = e;
+                    "Error broadcasting Spend tx '{}': '{}'",
+                    txid,
+                    e
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Broadcast again the Unvault transactions of this Spend that failed to be broadcast along with
+// the others, until they make it to the mempool or the flag is acknowledged.
+fn rebroadcast_unvaults(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    spend_txid: &Txid,
+) -> Result<(), BitcoindError> {
+    let db_path = revaultd.read().unwrap().db_file();
+
+    for db_vault in db_vaults_from_spend(&db_path, spend_txid)?.values() {
+        // Once the Unvault is seen the vault isn't active anymore
+        if db_vault.status != VaultStatus::Active
+            || !db_vault_flags(&db_path, db_vault.id)?.iter().any(|flag| {
+                flag.kind == VaultFlagKind::UnvaultBroadcastFailed && flag.cleared_at.is_none()
+            })
+        {
+            continue;
+        }
+        let unvault_tx = match db_unvault_transaction(&db_path, db_vault.id)? {
+            Some(db_tx) => db_tx.psbt.finalized_tx(&revaultd.read().unwrap().secp_ctx),
+            None => continue,
+        };
+        let unvault_tx = match unvault_tx {
+            Ok(tx) => tx,
             Err(e) => {
-                log::error!("Error broadcasting Spend tx '{}': '{}'", txid, e);
+                log::error!(
+                    "Error finalizing Unvault transaction of vault at '{}': '{}'",
+                    db_vault.deposit_outpoint,
+                    e
+                );
+                continue;
             }
+        };
+
+        let txid = unvault_tx.txid();
+        match bitcoind.broadcast_transaction(&unvault_tx) {
+            Ok(()) => log_event!(
+                log::Level::Info,
+                "tx_broadcast",
+                tx_type = "unvault",
+                txid = txid;
+                "Broadcasted Unvault transaction '{}' of vault at '{}' again",
+                txid,
+                db_vault.deposit_outpoint
+            ),
+            Err(e) => log_event!(
+                log::Level::Error,
+                "tx_broadcast_failure",
+                tx_type = "unvault",
+                txid = txid,
+                error = e;
+                "Error broadcasting Unvault transaction '{}' of vault at '{}' again: '{}'",
+                txid,
+                db_vault.deposit_outpoint,
+                e
+            ),
         }
     }
 
     Ok(())
 }
 
+// A Spend of ours was refused by bitcoind as it conflicts with a transaction in its mempool.
+// Most likely a stakeholder broadcast the Cancel for one of the vaults it spends. Record it, the
+// race will be settled by whichever confirms.
+fn record_spend_conflicts(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    spend_tx: &Transaction,
+) -> Result<(), BitcoindError> {
+    let (db_path, now) = {
+        let revaultd = revaultd.read().unwrap();
+        (revaultd.db_file(), revaultd.clock.unix_timestamp())
+    };
+    let spend_txid = spend_tx.txid();
+
+    for txin in spend_tx.input.iter() {
+        let db_vault = match db_vault_by_unvault_txid(&db_path, &txin.previous_output.txid)? {
+            Some((db_vault, _)) => db_vault,
+            None => continue,
+        };
+        let cancel_txid = cancel_txid(revaultd, &db_vault)?;
+        let competing_txid = if bitcoind.is_in_mempool(&cancel_txid)? {
+            Some(cancel_txid)
+        } else {
+            None
+        };
+
+        log::warn!(
+            "Spend tx '{}' conflicts with '{:?}' in mempool for vault at '{}'",
+            spend_txid,
+            competing_txid,
+            db_vault.deposit_outpoint
+        );
+        db_insert_mempool_conflict(
+            &db_path,
+            db_vault.id,
+            &spend_txid,
+            competing_txid.as_ref(),
+            now,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Remember the external scripts a confirmed Spend paid to, so we can warn about reusing them.
+// The change and CPFP outputs are ours and are not recorded.
+fn record_spend_destinations(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    db_path: &Path,
+    spend_tx: &Transaction,
+    blocktime: u32,
+) -> Result<(), BitcoindError> {
+    let spend_txid = spend_tx.txid();
+    let our_scripts: HashSet<Script> = db_derived_scripts(db_path)?
+        .into_iter()
+        .map(|script| script.script_pubkey)
+        .collect();
+    // The CPFP output is derived at the highest index of the vaults spent, as for the change.
+    let cpfp_script = db_vaults_from_spend(db_path, &spend_txid)?
+        .values()
+        .map(|db_vault| db_vault.derivation_index)
+        .max()
+        .map(|index| {
+            revaultd
+                .read()
+                .unwrap()
+                .derived_cpfp_descriptor(index)
+                .into_inner()
+                .script_pubkey()
+        });
+
+    let destinations: Vec<Script> = spend_tx
+        .output
+        .iter()
+        .map(|txo| &txo.script_pubkey)
+        .filter(|spk| !our_scripts.contains(spk) && cpfp_script.as_ref() != Some(spk))
+        .cloned()
+        .collect();
+    db_insert_spend_destinations(db_path, &destinations, &spend_txid, blocktime)?;
+
+    Ok(())
+}
+
 fn maybe_confirm_spend(
+    revaultd: &Arc<RwLock<RevaultD>>,
     db_path: &Path,
     bitcoind: &BitcoinD,
     db_vault: &DbVault,
@@ -101,8 +298,21 @@ fn maybe_confirm_spend(
 ) -> Result<bool, BitcoindError> {
     let tx = bitcoind.get_wallet_transaction(spend_txid)?;
     if let (Some(height), Some(time)) = (tx.blockheight, tx.blocktime) {
-        db_mark_spent_unvault(db_path, db_vault.id, time)?;
-        log::debug!(
+        let now = revaultd.read().unwrap().clock.unix_timestamp();
+        db_mark_spent_unvault(db_path, db_vault.id, time, now)?;
+        db_settle_conflicts(db_path, db_vault.id, spend_txid)?;
+        let spend_tx: Transaction = encode::deserialize(
+            &Vec::from_hex(&tx.hex).expect("bitcoind returned a wrong transaction format"),
+        )
+        .expect("bitcoind returned a wrong transaction format");
+        record_spend_destinations(revaultd, db_path, &spend_tx, time)?;
+        log_event!(
+            log::Level::Debug,
+            "vault_status",
+            outpoint = db_vault.deposit_outpoint,
+            from = db_vault.status,
+            to = VaultStatus::Spent,
+            txid = spend_txid;
             "Spend tx '{}', spending vault {:x?} was confirmed at height '{}'",
             &spend_txid,
             db_vault,
@@ -133,7 +343,7 @@ fn mark_confirmed_spends(
         let unvault_outpoint = unvault_txin.outpoint();
         let spend_txid = &db_vault.final_txid.expect("Must be set for 'spending'");
 
-        match maybe_confirm_spend(&db_path, bitcoind, &db_vault, spend_txid) {
+        match maybe_confirm_spend(revaultd, &db_path, bitcoind, &db_vault, spend_txid) {
             Ok(false) => {}
             Ok(true) => continue,
             Err(e) => {
@@ -147,10 +357,29 @@ fn mark_confirmed_spends(
         };
 
         if !bitcoind.is_in_mempool(spend_txid)? {
+            // The Cancel may have replaced it, or got confirmed before it.
+            let cancel_txid = cancel_txid(revaultd, &db_vault)?;
+            if bitcoind.is_current(&cancel_txid)? {
+                let now = revaultd.read().unwrap().clock.unix_timestamp();
+                db_cancel_unvault(&db_path, &unvault_tx.txid(), &cancel_txid, now)?;
+                report_late_cancel(&db_vault, &cancel_txid);
+                db_set_conflicts_competing(&db_path, db_vault.id, &cancel_txid)?;
+                if let Err(e) =
+                    maybe_confirm_cancel(revaultd, &db_path, bitcoind, &db_vault, &cancel_txid)
+                {
+                    log::error!(
+                        "Error checking if Cancel '{}' is confirmed: '{}'",
+                        &cancel_txid,
+                        e
+                    );
+                }
+                continue;
+            }
+
             // At least, is this transaction still in mempool?
             // If it was evicted, downgrade it to `unvaulted`, the listunspent polling loop will
             // take care of checking its new state immediately.
-            db_confirm_unvault(&db_path, &unvault_tx.txid())?;
+            mark_unvault_unspent(revaultd, bitcoind, &db_path, &db_vault, &unvault_tx.txid())?;
 
             let txo = unvault_txin.into_txout().into_txout();
             unvaults_cache.insert(
@@ -178,10 +407,43 @@ fn mark_confirmed_spends(
     Ok(())
 }
 
+// Set a vault whose Unvault output isn't being spent anymore back to 'unvaulted', or directly to
+// 'spendable' if the relative timelock of the Unvault output already expired.
+fn mark_unvault_unspent(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    db_path: &Path,
+    db_vault: &DbVault,
+    unvault_txid: &Txid,
+) -> Result<VaultStatus, BitcoindError> {
+    let tip = db_tip(db_path)?;
+    // If it's not confirmed, the polling loop will downgrade it to 'unvaulting' soon.
+    let unvault_height = bitcoind
+        .get_wallet_transaction(unvault_txid)?
+        .blockheight
+        .unwrap_or(tip.height);
+
+    let now = revaultd.read().unwrap().clock.unix_timestamp();
+    db_confirm_unvault(db_path, unvault_txid, unvault_height, now)?;
+    if revaultd
+        .read()
+        .unwrap()
+        .blocks_until_spendable(unvault_height, tip.height)
+        == 0
+    {
+        let now = revaultd.read().unwrap().clock.unix_timestamp();
+        db_mark_spendable_vault(db_path, db_vault.id, now)?;
+        return Ok(VaultStatus::Spendable);
+    }
+
+    Ok(VaultStatus::Unvaulted)
+}
+
 // The below procedures may set a vault as Unvaulted if the unconfirmed transaction spending the
 // Unvault is dropped from the mempool. This groups the code for doing so.
 fn mark_unvaulted(
     revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
     db_path: &Path,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
     db_vault: &DbVault,
@@ -191,14 +453,20 @@ fn mark_unvaulted(
         .unwrap()
         .psbt
         .assert_unvault();
-    let unvault_descriptor = revaultd.read().unwrap().unvault_descriptor.derive(
-        db_vault.derivation_index,
-        &revaultd.read().unwrap().secp_ctx,
-    );
+    let unvault_descriptor = revaultd
+        .read()
+        .unwrap()
+        .derived_unvault_descriptor(db_vault.derivation_index);
     let unvault_txin = unvault_tx.revault_unvault_txin(&unvault_descriptor);
     let unvault_outpoint = unvault_txin.outpoint();
 
-    db_confirm_unvault(db_path, &unvault_tx.tx().txid())?;
+    let new_status = mark_unvault_unspent(
+        revaultd,
+        bitcoind,
+        db_path,
+        db_vault,
+        &unvault_tx.tx().txid(),
+    )?;
 
     let txo = unvault_txin.into_txout().into_txout();
     unvaults_cache.insert(
@@ -208,18 +476,42 @@ fn mark_unvaulted(
             is_confirmed: true,
         },
     );
-    log::debug!(
+    log_event!(
+        log::Level::Debug,
+        "vault_status",
+        outpoint = db_vault.deposit_outpoint,
+        from = db_vault.status,
+        to = new_status;
         "Transaction spending Unvault '{}' was evicted from mempool. Downgrading vault at \
-         '{}' from '{}' to 'Unvaulted'",
+         '{}' from '{}' to '{}'",
         unvault_outpoint,
         &db_vault.deposit_outpoint,
         db_vault.status,
+        new_status,
     );
 
     Ok(())
 }
 
+// A vault that was spendable, or even being spent, got canceled. The Cancel remains valid once
+// the Unvault timelock expired and a stakeholder may broadcast it at any time: that's unusual
+// enough to be reported loudly. The notify command is always run for these transitions.
+fn report_late_cancel(db_vault: &DbVault, cancel_txid: &Txid) {
+    log_event!(
+        log::Level::Warn,
+        "late_cancel",
+        outpoint = db_vault.deposit_outpoint,
+        from = db_vault.status,
+        txid = cancel_txid;
+        "Vault at '{}' is being canceled by '{}' while '{}', after the Unvault timelock expired",
+        db_vault.deposit_outpoint,
+        cancel_txid,
+        db_vault.status
+    );
+}
+
 fn maybe_confirm_cancel(
+    revaultd: &Arc<RwLock<RevaultD>>,
     db_path: &Path,
     bitcoind: &BitcoinD,
     db_vault: &DbVault,
@@ -227,13 +519,34 @@ fn maybe_confirm_cancel(
 ) -> Result<bool, BitcoindError> {
     let tx = bitcoind.get_wallet_transaction(cancel_txid)?;
     if let (Some(height), Some(time)) = (tx.blockheight, tx.blocktime) {
-        db_mark_canceled_unvault(db_path, db_vault.id, time)?;
-        log::debug!(
+        let now = revaultd.read().unwrap().clock.unix_timestamp();
+        db_mark_canceled_unvault(db_path, db_vault.id, time, now)?;
+        db_settle_conflicts(db_path, db_vault.id, cancel_txid)?;
+        log_event!(
+            log::Level::Debug,
+            "vault_status",
+            outpoint = db_vault.deposit_outpoint,
+            from = db_vault.status,
+            to = VaultStatus::Canceled,
+            txid = cancel_txid;
             "Cancel tx '{}', spending vault {:x?} was confirmed at height '{}'",
             &cancel_txid,
             db_vault,
             height
         );
+        // Any Spend of this vault we may have can't confirm anymore.
+        for spend_txid in db_deprecate_vault_spends(db_path, db_vault.id, cancel_txid, time)? {
+            log_event!(
+                log::Level::Warn,
+                "spend_deprecated",
+                outpoint = db_vault.deposit_outpoint,
+                txid = spend_txid;
+                "Spend tx '{}' won't be broadcast anymore, vault at '{}' was canceled by '{}'",
+                spend_txid,
+                db_vault.deposit_outpoint,
+                cancel_txid
+            );
+        }
 
         return Ok(true);
     }
@@ -250,7 +563,7 @@ fn mark_confirmed_cancels(
 
     for (db_vault, cancel_tx) in db_canceling_vaults(&db_path)? {
         let cancel_txid = cancel_tx.txid();
-        match maybe_confirm_cancel(&db_path, bitcoind, &db_vault, &cancel_txid) {
+        match maybe_confirm_cancel(revaultd, &db_path, bitcoind, &db_vault, &cancel_txid) {
             Ok(false) => {}
             Ok(true) => continue,
             Err(e) => {
@@ -267,7 +580,7 @@ fn mark_confirmed_cancels(
             // At least, is this transaction still in mempool?
             // If it was evicted, downgrade it to `unvaulted`, the listunspent polling loop will
             // take care of checking its new state immediately.
-            mark_unvaulted(revaultd, &db_path, unvaults_cache, &db_vault)?;
+            mark_unvaulted(revaultd, bitcoind, &db_path, unvaults_cache, &db_vault)?;
         } else {
             log::trace!("Cancel tx '{}' is still unconfirmed", cancel_txid);
         }
@@ -277,6 +590,7 @@ fn mark_confirmed_cancels(
 }
 
 fn maybe_confirm_unemer(
+    revaultd: &Arc<RwLock<RevaultD>>,
     db_path: &Path,
     bitcoind: &BitcoinD,
     db_vault: &DbVault,
@@ -284,8 +598,15 @@ fn maybe_confirm_unemer(
 ) -> Result<bool, BitcoindError> {
     let transaction = bitcoind.get_wallet_transaction(unemer_txid)?;
     if let (Some(height), Some(blocktime)) = (transaction.blockheight, transaction.blocktime) {
-        db_mark_emergencied_unvault(db_path, db_vault.id, blocktime)?;
-        log::warn!(
+        let now = revaultd.read().unwrap().clock.unix_timestamp();
+        db_mark_emergencied_unvault(db_path, db_vault.id, blocktime, now)?;
+        log_event!(
+            log::Level::Warn,
+            "vault_status",
+            outpoint = db_vault.deposit_outpoint,
+            from = db_vault.status,
+            to = VaultStatus::UnvaultEmergencyVaulted,
+            txid = unemer_txid;
             "UnvaultEmergency tx '{}', spending vault {:x?} was confirmed at height '{}'",
             &unemer_txid,
             db_vault,
@@ -307,7 +628,7 @@ fn mark_confirmed_unemers(
 
     for (db_vault, unemer_tx) in db_unemering_vaults(&db_path)? {
         let unemer_txid = unemer_tx.txid();
-        match maybe_confirm_unemer(&db_path, bitcoind, &db_vault, &unemer_txid) {
+        match maybe_confirm_unemer(revaultd, &db_path, bitcoind, &db_vault, &unemer_txid) {
             Ok(false) => {}
             Ok(true) => continue,
             Err(e) => {
@@ -324,7 +645,7 @@ fn mark_confirmed_unemers(
             // At least, is this transaction still in mempool?
             // If it was evicted, downgrade it to `unvaulted`, the listunspent polling loop will
             // take care of checking its new state immediately.
-            mark_unvaulted(revaultd, &db_path, unvaults_cache, &db_vault)?;
+            mark_unvaulted(revaultd, bitcoind, &db_path, unvaults_cache, &db_vault)?;
             log::warn!(
                 "UnvaultEmergency tx '{}' was evicted from mempool.",
                 &unemer_txid,
@@ -340,6 +661,7 @@ fn mark_confirmed_unemers(
 }
 
 fn maybe_confirm_emer(
+    revaultd: &Arc<RwLock<RevaultD>>,
     db_path: &Path,
     bitcoind: &BitcoinD,
     db_vault: &DbVault,
@@ -347,8 +669,15 @@ fn maybe_confirm_emer(
 ) -> Result<bool, BitcoindError> {
     let transaction = bitcoind.get_wallet_transaction(emer_txid)?;
     if let (Some(height), Some(blocktime)) = (transaction.blockheight, transaction.blocktime) {
-        db_mark_emergencied_vault(db_path, db_vault.id, blocktime)?;
-        log::warn!(
+        let now = revaultd.read().unwrap().clock.unix_timestamp();
+        db_mark_emergencied_vault(db_path, db_vault.id, blocktime, now)?;
+        log_event!(
+            log::Level::Warn,
+            "vault_status",
+            outpoint = db_vault.deposit_outpoint,
+            from = db_vault.status,
+            to = VaultStatus::EmergencyVaulted,
+            txid = emer_txid;
             "Emergency tx '{}', spending vault {:x?} was confirmed at height '{}'",
             &emer_txid,
             db_vault,
@@ -370,7 +699,7 @@ fn mark_confirmed_emers(
 
     for (db_vault, emer_tx) in db_emering_vaults(&db_path)? {
         let emer_txid = emer_tx.txid();
-        match maybe_confirm_emer(&db_path, bitcoind, &db_vault, &emer_txid) {
+        match maybe_confirm_emer(revaultd, &db_path, bitcoind, &db_vault, &emer_txid) {
             Ok(false) => {}
             Ok(true) => continue,
             Err(e) => {
@@ -387,7 +716,7 @@ fn mark_confirmed_emers(
             // At least, is this transaction still in mempool?
             // If it was evicted, downgrade it to `unvaulted`, the listunspent polling loop will
             // take care of checking its new state immediately.
-            mark_unvaulted(revaultd, &db_path, unvaults_cache, &db_vault)?;
+            mark_unvaulted(revaultd, bitcoind, &db_path, unvaults_cache, &db_vault)?;
             log::warn!("Emergency tx '{}' was evicted from mempool.", &emer_txid,);
 
             // TODO: broadcast it again, as well as the other Emergency txs in this case!!
@@ -439,16 +768,15 @@ impl ToBeCpfped {
     }
 }
 
-// CPFP a bunch of transactions, bumping their feerate by at least `target_feerate`.
-// `target_feerate` is expressed in sat/kWU.
+// Create the transaction CPFPing a bunch of transactions, bumping their feerate by at least
+// `target_feerate`. `target_feerate` is expressed in sat/kWU.
 // All the transactions' feerate MUST be below `target_feerate`.
-fn cpfp_package(
-    revaultd: &Arc<RwLock<RevaultD>>,
+fn create_cpfp_transaction(
+    revaultd: &RevaultD,
     bitcoind: &BitcoinD,
-    to_be_cpfped: Vec<ToBeCpfped>,
+    to_be_cpfped: &[ToBeCpfped],
     target_feerate: u64,
-) -> Result<(), BitcoindError> {
-    let revaultd = revaultd.read().unwrap();
+) -> Result<Option<Transaction>, BitcoindError> {
     let cpfp_descriptor = &revaultd.cpfp_descriptor;
 
     // First of all, compute all the information we need from the to-be-cpfped transactions.
@@ -465,7 +793,7 @@ fn cpfp_package(
             Some(txin) => txins.push(txin),
             None => {
                 log::error!("No CPFP txin for tx '{}'", tx.txid());
-                return Ok(());
+                return Ok(None);
             }
         }
     }
@@ -504,34 +832,226 @@ fn cpfp_package(
                 "We wanted to feebump transactions '{:?}', but we don't have enough funds!",
                 txids
             );
-            return Ok(());
+            return Ok(None);
         }
         Err(e) => {
             log::error!("Error while creating CPFP transaction: '{}'", e);
-            return Ok(());
+            return Ok(None);
         }
     };
 
-    // Finally, sign and (try to) broadcast the CPFP transaction
+    // Finally, sign the CPFP transaction
     let (complete, psbt_signed) = bitcoind.sign_psbt(psbt.psbt())?;
     if !complete {
         log::error!(
             "Bitcoind returned a non-finalized CPFP PSBT: {}",
             base64::encode(encode::serialize(&psbt_signed))
         );
-        return Ok(());
+        return Ok(None);
     }
 
-    let final_tx = psbt_signed.extract_tx();
+    Ok(Some(psbt_signed.extract_tx()))
+}
+
+// CPFP a bunch of transactions, bumping their feerate by at least `target_feerate`.
+// `target_feerate` is expressed in sat/kWU.
+// All the transactions' feerate MUST be below `target_feerate`.
+fn cpfp_package(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    to_be_cpfped: Vec<ToBeCpfped>,
+    target_feerate: u64,
+) -> Result<(), BitcoindError> {
+    let final_tx = match create_cpfp_transaction(
+        &revaultd.read().unwrap(),
+        bitcoind,
+        &to_be_cpfped,
+        target_feerate,
+    )? {
+        Some(tx) => tx,
+        None => return Ok(()),
+    };
+    let txids: HashSet<Txid> = to_be_cpfped.iter().map(|tx| tx.txid()).collect();
+
     if let Err(e) = bitcoind.broadcast_transaction(&final_tx) {
-        log::error!("Error broadcasting '{:?}' CPFP tx: {}", txids, e);
+        log_event!(
+            log::Level::Error,
+            "tx_broadcast_failure",
+            tx_type = "cpfp",
+            txid = final_tx.txid(),
+            error = e;
+            "Error broadcasting '{:?}' CPFP tx: {}",
+            txids,
+            e
+        );
     } else {
-        log::info!("CPFPed transactions with ids '{:?}'", txids);
+        log_event!(
+            log::Level::Info,
+            "tx_broadcast",
+            tx_type = "cpfp",
+            txid = final_tx.txid();
+            "CPFPed transactions with ids '{:?}'",
+            txids
+        );
     }
 
     Ok(())
 }
 
+// The CPFP transaction to submit along with these Unvault transactions, if they have priority,
+// bitcoind accepts packages and their feerate is too low to make it to the next blocks.
+fn unvaults_cpfp(
+    revaultd: &RevaultD,
+    bitcoind: &BitcoinD,
+    unvaults: &[UnvaultTransaction],
+) -> Result<Option<(Vec<Txid>, Transaction)>, BitcoindError> {
+    if revaultd.cpfp_key.is_none()
+        || unvaults.len() >= MAX_PACKAGE_COUNT
+        || !bitcoind.supports_package_submission()?
+    {
+        return Ok(None);
+    }
+    let current_feerate = match bitcoind.estimate_feerate()? {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+
+    // They aren't broadcast yet, no need to check whether they are confirmed
+    let to_cpfp: Vec<ToBeCpfped> = unvaults
+        .iter()
+        .filter(|unvault| unvault.max_feerate() * 1_000 + CPFP_THRESHOLD < current_feerate)
+        .map(|unvault| ToBeCpfped::Unvault(unvault.clone()))
+        .collect();
+    if to_cpfp.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(
+        create_cpfp_transaction(revaultd, bitcoind, &to_cpfp, current_feerate)?
+            .map(|cpfp_tx| (to_cpfp.iter().map(|tx| tx.txid()).collect(), cpfp_tx)),
+    )
+}
+
+/// Broadcast the (finalized) Unvault transactions of a Spend all at once. If bitcoind would not
+/// accept any of them in its mempool, none is broadcast. If the Spend has priority, they are
+/// submitted along with a CPFP transaction when needed and bitcoind supports it.
+pub fn broadcast_unvaults(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    unvaults: Vec<UnvaultTransaction>,
+    priority: bool,
+) -> Result<UnvaultsBroadcast, BitcoindError> {
+    let txs: Vec<Transaction> = unvaults
+        .iter()
+        .map(|unvault| unvault.psbt().clone().extract_tx())
+        .collect();
+
+    // First make sure bitcoind would accept all of them. The transactions following a rejected
+    // one in a package aren't evaluated, only report the offending ones.
+    let mut rejected = Vec::new();
+    for chunk in txs.chunks(MAX_PACKAGE_COUNT) {
+        let results = bitcoind.test_mempool_accept(chunk)?;
+        let evaluated: Vec<_> = results
+            .iter()
+            .filter(|(_, reason)| reason.as_deref() != Some(NOT_EVALUATED))
+            .cloned()
+            .collect();
+        let results = if evaluated.is_empty() {
+            results
+        } else {
+            evaluated
+        };
+        rejected.extend(
+            results
+                .into_iter()
+                .filter_map(|(txid, reason)| reason.map(|reason| (txid, reason))),
+        );
+    }
+    if !rejected.is_empty() {
+        for (txid, reason) in rejected.iter() {
+            log_event!(
+                log::Level::Error,
+                "tx_broadcast_failure",
+                tx_type = "unvault",
+                txid = txid,
+                error = reason;
+                "Not broadcasting the Unvault transactions, bitcoind would not accept '{}': '{}'",
+                txid,
+                reason
+            );
+        }
+        return Ok(UnvaultsBroadcast::Rejected(rejected));
+    }
+
+    // Submit the ones that need it along with their CPFP transaction, if needed
+    if priority {
+        let cpfp = unvaults_cpfp(&revaultd.read().unwrap(), bitcoind, &unvaults)?;
+        if let Some((txids, cpfp_tx)) = cpfp {
+            let mut package: Vec<Transaction> = txs
+                .iter()
+                .filter(|tx| txids.contains(&tx.txid()))
+                .cloned()
+                .collect();
+            package.push(cpfp_tx.clone());
+            match bitcoind.submit_package(&package) {
+                Ok(results) if results.iter().all(|(_, error)| error.is_none()) => log_event!(
+                    log::Level::Info,
+                    "tx_broadcast",
+                    tx_type = "cpfp",
+                    txid = cpfp_tx.txid();
+                    "Submitted Unvault transactions with ids '{:?}' along with their CPFP \
+                     transaction",
+                    txids
+                ),
+                Ok(results) => log::warn!(
+                    "Could not submit Unvault transactions along with their CPFP transaction \
+                     '{}': '{:?}'. Broadcasting them on their own.",
+                    cpfp_tx.txid(),
+                    results
+                ),
+                Err(e) => log::warn!(
+                    "Could not submit Unvault transactions along with their CPFP transaction \
+                     '{}': '{}'. Broadcasting them on their own.",
+                    cpfp_tx.txid(),
+                    e
+                ),
+            }
+        }
+    }
+
+    // Then broadcast them all. The ones already submitted along with their CPFP are already in
+    // the mempool, which bitcoind doesn't treat as an error.
+    let mut failures = Vec::new();
+    for (txid, error) in bitcoind.broadcast_transactions_each(&txs)? {
+        match error {
+            None => log_event!(
+                log::Level::Info,
+                "tx_broadcast",
+                tx_type = "unvault",
+                txid = txid;
+                "Broadcasted Unvault transaction '{}'",
+                txid
+            ),
+            Some(e) => {
+                log_event!(
+                    log::Level::Error,
+                    "tx_broadcast_failure",
+                    tx_type = "unvault",
+                    txid = txid,
+                    error = e;
+                    "Error broadcasting Unvault transaction '{}': '{}'. Will retry at the next \
+                     block.",
+                    txid,
+                    e
+                );
+                failures.push((txid, e.to_string()));
+            }
+        }
+    }
+
+    Ok(UnvaultsBroadcast::Broadcast(failures))
+}
+
 // `target_feerate` is in sats/kWU
 fn should_cpfp(bitcoind: &BitcoinD, tx: &impl CpfpableTransaction, target_feerate: u64) -> bool {
     bitcoind
@@ -599,25 +1119,221 @@ fn maybe_cpfp_txs(
     Ok(())
 }
 
+// Check bitcoind would accept the revocation transactions of this vault in its mempool. Returns
+// the first rejected one along with the reason, if any.
+fn check_vault_revocations(
+    revaultd: &RevaultD,
+    bitcoind: &BitcoinD,
+    vault: &DbVault,
+) -> Result<Option<(Txid, String)>, BitcoindError> {
+    let db_path = revaultd.db_file();
+    let finalized = |db_tx: Option<DbTransaction>| match db_tx {
+        Some(db_tx) if db_tx.is_fully_signed => {
+            let txid = db_tx.psbt.txid();
+            Some(
+                db_tx
+                    .psbt
+                    .finalized_tx(&revaultd.secp_ctx)
+                    .map_err(|e| (txid, format!("Finalizing transaction: {}", e))),
+            )
+        }
+        _ => None,
+    };
+
+    // Each package is tested as a whole. We only have the Emergency if we are a stakeholder.
+    let mut packages = Vec::with_capacity(3);
+    if let Some(emer_tx) = finalized(db_emer_transaction(&db_path, vault.id)?) {
+        packages.push(vec![emer_tx]);
+    }
+    // The Cancel and Unvault Emergency spend the Unvault output, so they can only be tested
+    // along with the Unvault, which is only fully signed once the vault is Active.
+    if vault.status == VaultStatus::Active {
+        if let Some(unvault_tx) = finalized(db_unvault_transaction(&db_path, vault.id)?) {
+            let revocations = vec![
+                finalized(db_cancel_transaction(&db_path, vault.id)?),
+                finalized(db_unvault_emer_transaction(&db_path, vault.id)?),
+            ];
+            for revocation_tx in revocations.into_iter().flatten() {
+                packages.push(vec![unvault_tx.clone(), revocation_tx]);
+            }
+        }
+    }
+
+    for package in packages {
+        let package = match package.into_iter().collect::<Result<Vec<Transaction>, _>>() {
+            Ok(package) => package,
+            Err(rejected) => return Ok(Some(rejected)),
+        };
+        let results = bitcoind.test_mempool_accept(&package)?;
+        let revocation_txid = package.last().expect("Packages are never empty").txid();
+
+        // If the Unvault itself is rejected, the revocation transaction isn't evaluated and we
+        // can't tell anything about it. We'd only need to broadcast it once the Unvault made it
+        // to the mempool anyways.
+        if package.len() > 1 {
+            if let Some((txid, Some(reason))) = results.first() {
+                log::debug!(
+                    "Unvault transaction '{}' of vault at '{}' was rejected: '{}'. Can't check \
+                     revocation transaction '{}'.",
+                    txid,
+                    vault.deposit_outpoint,
+                    reason,
+                    revocation_txid
+                );
+                continue;
+            }
+        }
+
+        let rejection = results
+            .into_iter()
+            .find(|(txid, _)| *txid == revocation_txid)
+            .and_then(|(txid, reason)| reason.map(|reason| (txid, reason)));
+        if rejection.is_some() {
+            return Ok(rejection);
+        }
+    }
+
+    Ok(None)
+}
+
+// Log when the space available on disk crosses one of the thresholds. The commands storing new
+// transactions check it by themselves, we keep monitoring the chain whatever the level.
+fn check_disk_space(revaultd: &RevaultD, level: &mut Option<DiskSpaceLevel>) {
+    let space = match revaultd.disk_space() {
+        Ok(space) => space,
+        Err(e) => {
+            log::debug!("Could not check the space available on disk: '{}'", e);
+            return;
+        }
+    };
+    if *level == Some(space.level) {
+        return;
+    }
+
+    match space.level {
+        DiskSpaceLevel::Ok => {
+            if level.is_some() {
+                log_event!(
+                    log::Level::Info,
+                    "disk_space_ok",
+                    available = space.available;
+                    "Space available on disk is back to '{}' bytes.",
+                    space.available
+                );
+            }
+        }
+        DiskSpaceLevel::Low => log_event!(
+            log::Level::Warn,
+            "disk_space_low",
+            available = space.available,
+            threshold = revaultd.disk_space_warning;
+            "Only '{}' bytes left on disk, below the warning threshold of '{}' bytes.",
+            space.available,
+            revaultd.disk_space_warning
+        ),
+        DiskSpaceLevel::Critical => log_event!(
+            log::Level::Error,
+            "disk_space_critical",
+            available = space.available,
+            threshold = revaultd.disk_space_critical;
+            "!!!!! Only '{}' bytes left on disk, below the critical threshold of '{}' bytes. \
+             Refusing to store new transactions until some space is freed. !!!!!",
+            space.available,
+            revaultd.disk_space_critical
+        ),
+    }
+    *level = Some(space.level);
+}
+
+// Check bitcoind would accept in its mempool the revocation transactions of all the vaults we
+// may still need to revault, record the results and raise the alarm for the rejected ones.
+fn check_revocations(
+    revaultd: &RevaultD,
+    bitcoind: &BitcoinD,
+    hooks: Option<&HookRunner>,
+) -> Result<(), BitcoindError> {
+    let db_path = revaultd.db_file();
+    let checked_at = revaultd.clock.unix_timestamp();
+    let vaults: Vec<DbVault> = db_vaults(&db_path)?
+        .into_iter()
+        .filter(|v| matches!(v.status, VaultStatus::Secured | VaultStatus::Active))
+        .collect();
+    log::debug!(
+        "Checking the revocation transactions of {} vault(s)",
+        vaults.len()
+    );
+
+    for vault in vaults {
+        let rejected = check_vault_revocations(revaultd, bitcoind, &vault)?;
+        if let Some((ref txid, ref reason)) = rejected {
+            log_event!(
+                log::Level::Error,
+                "revocation_rejected",
+                outpoint = vault.deposit_outpoint,
+                txid = txid,
+                error = reason;
+                "!!!!! bitcoind would not accept revocation transaction '{}' of vault at '{}': \
+                 '{}'. It could not be broadcast if needed. !!!!!",
+                txid,
+                vault.deposit_outpoint,
+                reason
+            );
+            db_raise_vault_flag(
+                &db_path,
+                vault.id,
+                VaultFlagKind::RevocationRejected,
+                &format!(
+                    "bitcoind would not accept revocation transaction '{}': '{}'",
+                    txid, reason
+                ),
+                checked_at,
+            )?;
+            if let Some(hooks) = hooks {
+                hooks.notify_revocation_rejected(
+                    vault.deposit_outpoint,
+                    vault.status,
+                    *txid,
+                    reason.clone(),
+                );
+            }
+        }
+        db_record_revocation_check(
+            &db_path,
+            vault.id,
+            checked_at,
+            rejected
+                .as_ref()
+                .map(|(txid, reason)| (txid, reason.as_str())),
+        )?;
+    }
+
+    Ok(())
+}
+
 // Everything we do when the chain moves forward
 fn new_tip_event(
     revaultd: &Arc<RwLock<RevaultD>>,
     bitcoind: &BitcoinD,
+    statemachine: &StateMachineSender,
     new_tip: &BlockchainTip,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
 ) -> Result<(), BitcoindError> {
-    let db_path = revaultd.read().unwrap().db_file();
-
     // First we update it in DB
-    db_update_tip(&db_path, new_tip)?;
+    statemachine.emit(ChainEvent::TipChanged(*new_tip));
+    statemachine.flush();
+
+    // A read-only instance never broadcasts anything, it's up to the main one.
+    let read_only = revaultd.read().unwrap().read_only;
 
     // Then we CPFP our spends/unvaults, if we can
-    if revaultd.read().unwrap().is_manager() {
+    if revaultd.read().unwrap().is_manager() && !read_only {
         maybe_cpfp_txs(revaultd, bitcoind)?;
     }
 
     // Then we check if any Spend became mature yet
-    maybe_broadcast_spend_transactions(revaultd, bitcoind)?;
+    if !read_only {
+        maybe_broadcast_spend_transactions(revaultd, bitcoind)?;
+    }
 
     // Did some Spend transaction confirmed?
     mark_confirmed_spends(revaultd, bitcoind, unvaults_cache)?;
@@ -647,17 +1363,20 @@ fn unconfirm_unvault(
     // 'spending' or 'canceling' right away by the bitcoind polling loop as `listunspent` will
     // consider the Unvault transaction as spent if a wallet transaction was recorded spending it
     // **even if it becomes invalid**.
-    db_unconfirm_unvault_dbtx(db_tx, vault.id)?;
+    let now = revaultd.read().unwrap().clock.unix_timestamp();
+    db_unconfirm_unvault_dbtx(db_tx, vault.id, now)?;
 
     // bitcoind's wallet may need a small kick-in for large reorgs (which won't happen on mainnet
     // but hey).
-    match bitcoind.rebroadcast_wallet_tx(&unvault_txid) {
-        Ok(()) => {}
-        Err(e) => log::debug!(
-            "Error re-broadcasting Unvault tx '{}': '{}'",
-            unvault_txid,
-            e
-        ),
+    let read_only = revaultd.read().unwrap().read_only;
+    if !read_only {
+        if let Err(e) = bitcoind.rebroadcast_wallet_tx(&unvault_txid) {
+            log::debug!(
+                "Error re-broadcasting Unvault tx '{}': '{}'",
+                unvault_txid,
+                e
+            );
+        }
     }
 
     // Then either repopulate the cache (we remove the entry on spend) or update the
@@ -665,8 +1384,7 @@ fn unconfirm_unvault(
     let der_unvault_descriptor = revaultd
         .read()
         .unwrap()
-        .unvault_descriptor
-        .derive(vault.derivation_index, &revaultd.read().unwrap().secp_ctx);
+        .derived_unvault_descriptor(vault.derivation_index);
     let unvault_txin = unvault_tx.revault_unvault_txin(&der_unvault_descriptor);
     let unvault_outpoint = unvault_txin.outpoint();
     let txo = unvault_txin.into_txout().into_txout();
@@ -684,7 +1402,10 @@ fn unconfirm_unvault(
                 is_confirmed: false,
             },
         );
-    } else if matches!(vault.status, VaultStatus::Unvaulted) {
+    } else if matches!(
+        vault.status,
+        VaultStatus::Unvaulted | VaultStatus::Spendable
+    ) {
         // If it was only 'unvaulted', we still have it in the cache. (as conf'ed, so mark it as
         // unconf'ed now)
         unvaults_cache
@@ -705,9 +1426,10 @@ fn unconfirm_unvault(
             }
         };
         let cancel_txid = cancel_tx.txid();
-        match bitcoind.rebroadcast_wallet_tx(&cancel_txid) {
-            Ok(()) => {}
-            Err(e) => log::debug!("Error re-broadcasting Cancel tx '{}': '{}'", cancel_txid, e),
+        if !read_only {
+            if let Err(e) = bitcoind.rebroadcast_wallet_tx(&cancel_txid) {
+                log::debug!("Error re-broadcasting Cancel tx '{}': '{}'", cancel_txid, e);
+            }
         }
 
         // Still re-insert it, even if it'll be removed at the next `listunspent` poll
@@ -735,19 +1457,21 @@ fn unconfirm_vault(
 ) -> Result<(), BitcoindError> {
     // Just in case, rebroadcast the deposit transaction anyways
     let deposit_txid = vault.deposit_outpoint.txid;
-    match bitcoind.rebroadcast_wallet_tx(&deposit_txid) {
-        Ok(()) => {}
-        Err(e) => log::debug!(
-            "Error re-broadcasting Deposit tx '{}': '{}'",
-            deposit_txid,
-            e
-        ),
+    if !revaultd.read().unwrap().read_only {
+        if let Err(e) = bitcoind.rebroadcast_wallet_tx(&deposit_txid) {
+            log::debug!(
+                "Error re-broadcasting Deposit tx '{}': '{}'",
+                deposit_txid,
+                e
+            );
+        }
     }
 
     match vault.status {
         VaultStatus::Unconfirmed => unreachable!("Unconfirming a confirmed vault"),
         VaultStatus::Unvaulting
         | VaultStatus::Unvaulted
+        | VaultStatus::Spendable
         | VaultStatus::Spending
         | VaultStatus::Spent
         | VaultStatus::Canceling
@@ -785,7 +1509,8 @@ fn unconfirm_vault(
         | VaultStatus::EmergencyVaulting
         | VaultStatus::EmergencyVaulted => {
             // If it was still at the 'first layer', just mark it as unconfirmed.
-            db_unconfirm_deposit_dbtx(db_tx, vault.id)?;
+            let now = revaultd.read().unwrap().clock.unix_timestamp();
+            db_unconfirm_deposit_dbtx(db_tx, vault.id, now)?;
             deposits_cache
                 .get_mut(&vault.deposit_outpoint)
                 .expect("Not unvaulted yet")
@@ -868,7 +1593,10 @@ fn comprehensive_rescan(
         // First layer: if the deposit itself becomes unconfirmed, no need to go further: mark the
         // vault as unconfirmed and be done.
         let deposit_conf = tip.height.checked_sub(dep_height).expect("Checked above") + 1;
-        let min_conf = revaultd.read().unwrap().min_conf;
+        let min_conf = deposit_min_conf(
+            revaultd.read().unwrap().min_conf,
+            db_deposit_coinbase_height_dbtx(db_tx, vault.id)?.is_some(),
+        );
         if deposit_conf < min_conf as u32 {
             unconfirm_vault(
                 revaultd,
@@ -923,7 +1651,8 @@ fn comprehensive_rescan(
                     height
                 );
             } else {
-                db_unconfirm_emer_dbtx(db_tx, vault.id)?;
+                let now = revaultd.read().unwrap().clock.unix_timestamp();
+                db_unconfirm_emer_dbtx(db_tx, vault.id, now)?;
                 log::debug!(
                     "Vault {}'s Emergency transaction {} got unconfirmed.",
                     vault.deposit_outpoint,
@@ -938,6 +1667,7 @@ fn comprehensive_rescan(
         if matches!(
             vault.status,
             VaultStatus::Unvaulted
+                | VaultStatus::Spendable
                 | VaultStatus::Spending
                 | VaultStatus::Spent
                 | VaultStatus::Canceling
@@ -995,6 +1725,25 @@ fn comprehensive_rescan(
                 unv_height
             );
 
+            // It may have been confirmed in a different block, so the relative timelock might
+            // not be expired anymore.
+            db_update_unvault_height_dbtx(db_tx, &unvault_txid, unv_height)?;
+            if matches!(vault.status, VaultStatus::Spendable)
+                && revaultd
+                    .read()
+                    .unwrap()
+                    .blocks_until_spendable(unv_height, tip.height)
+                    > 0
+            {
+                let now = revaultd.read().unwrap().clock.unix_timestamp();
+                db_unmature_unvault_dbtx(db_tx, vault.id, now)?;
+                log::debug!(
+                    "Vault {}'s Unvault output isn't spendable anymore.",
+                    vault.deposit_outpoint
+                );
+                continue;
+            }
+
             // Third layer: if the Spend transaction *only* was confirmed and becomes unconfirmed,
             // we just mark it as such. The bitcoind wallet will take care of the rebroadcast.
             if matches!(vault.status, VaultStatus::Spent) {
@@ -1016,7 +1765,8 @@ fn comprehensive_rescan(
                         height
                     );
                 } else {
-                    db_unconfirm_spend_dbtx(db_tx, vault.id)?;
+                    let now = revaultd.read().unwrap().clock.unix_timestamp();
+                    db_unconfirm_spend_dbtx(db_tx, vault.id, now)?;
                     log::debug!(
                         "Vault {}'s Spend transaction {} got unconfirmed.",
                         vault.deposit_outpoint,
@@ -1046,7 +1796,8 @@ fn comprehensive_rescan(
                         height
                     );
                 } else {
-                    db_unconfirm_cancel_dbtx(db_tx, vault.id)?;
+                    let now = revaultd.read().unwrap().clock.unix_timestamp();
+                    db_unconfirm_cancel_dbtx(db_tx, vault.id, now)?;
                     log::debug!(
                         "Vault {}'s Cancel transaction {} got unconfirmed.",
                         vault.deposit_outpoint,
@@ -1085,7 +1836,8 @@ fn comprehensive_rescan(
                         height
                     );
                 } else {
-                    db_unconfirm_unemer_dbtx(db_tx, vault.id)?;
+                    let now = revaultd.read().unwrap().clock.unix_timestamp();
+                    db_unconfirm_unemer_dbtx(db_tx, vault.id, now)?;
                     log::debug!(
                         "Vault {}'s UnvaultEmergency transaction {} got unconfirmed.",
                         vault.deposit_outpoint,
@@ -1109,12 +1861,19 @@ fn comprehensive_rescan(
 fn update_tip(
     revaultd: &mut Arc<RwLock<RevaultD>>,
     bitcoind: &BitcoinD,
+    statemachine: &StateMachineSender,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
 ) -> Result<BlockchainTip, BitcoindError> {
     let current_tip = db_tip(&revaultd.read().unwrap().db_file())?;
     let tip = bitcoind.get_tip()?;
 
+    // The fee market may have changed with the new block. Keep track of it, to record it along
+    // with the presigned transactions that get signed until the next one.
+    if tip != current_tip || revaultd.read().unwrap().feerate_estimates.is_none() {
+        revaultd.write().unwrap().feerate_estimates = Some(bitcoind.feerate_estimates());
+    }
+
     // Nothing changed, shortcut.
     if tip == current_tip {
         return Ok(tip);
@@ -1125,7 +1884,7 @@ fn update_tip(
         let bit_curr_hash = bitcoind.getblockhash(current_tip.height)?;
         if bit_curr_hash == current_tip.hash || current_tip.height == 0 {
             // We moved forward, everything is fine.
-            new_tip_event(revaultd, bitcoind, &tip, unvaults_cache)?;
+            new_tip_event(revaultd, bitcoind, statemachine, &tip, unvaults_cache)?;
             return Ok(current_tip);
         }
     }
@@ -1148,15 +1907,376 @@ fn update_tip(
     Ok(current_tip)
 }
 
-// Which kind of transaction may spend the Unvault transaction.
+// What happened at a given height, as far as the replay of the blocks we missed is concerned.
+#[derive(Debug, Default)]
+struct ReplayedBlock {
+    // The wallet transactions that were confirmed in this block, in order.
+    txids: Vec<Txid>,
+    // The deposits that reached the confirmation depth we require at this height.
+    deposit_confirmations: Vec<OutPoint>,
+}
+
+// Process a transaction that was confirmed while we were down, as the poller would have if it
+// had been running at this height. The heights of the events it implies in the future are
+// recorded in `blocks`, up to `max_height`.
+#[allow(clippy::too_many_arguments)]
+fn replay_transaction(
+    revaultd: &mut Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    statemachine: &StateMachineSender,
+    deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
+    unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
+    previous_tip: &BlockchainTip,
+    height: u32,
+    tx: &Transaction,
+    blocks: &mut BTreeMap<u32, ReplayedBlock>,
+    max_height: u32,
+) -> Result<(), BitcoindError> {
+    let db_path = revaultd.read().unwrap().db_file();
+    let txid = tx.txid();
+
+    // Did it spend one of our Deposit or Unvault outputs?
+    for txin in &tx.input {
+        let outpoint = txin.previous_output;
+        if let Some(utxo) = deposits_cache.get(&outpoint).cloned() {
+            // It was obviously deep enough to be spent
+            if !utxo.is_confirmed {
+                handle_confirmed_deposit(bitcoind, statemachine, deposits_cache, outpoint)?;
+            }
+            statemachine.flush();
+            handle_spent_deposit(
+                revaultd,
+                &db_path,
+                bitcoind,
+                deposits_cache,
+                unvaults_cache,
+                previous_tip,
+                outpoint,
+                utxo,
+            )?;
+        } else if unvaults_cache.contains_key(&outpoint) {
+            handle_spent_unvault(
+                revaultd,
+                &db_path,
+                bitcoind,
+                unvaults_cache,
+                previous_tip,
+                &outpoint,
+            )?;
+        }
+    }
+
+    // Is it the confirmation of an Unvault we are tracking?
+    let mut unvault_confirmed = false;
+    for (outpoint, utxo) in unvaults_cache.iter_mut() {
+        if outpoint.txid == txid && !utxo.is_confirmed {
+            utxo.is_confirmed = true;
+            unvault_confirmed = true;
+        }
+    }
+    if unvault_confirmed {
+        statemachine.emit(ChainEvent::TxConfirmed {
+            kind: ConfirmedTx::Unvault {
+                blockheight: height,
+            },
+            txid,
+        });
+        statemachine.flush();
+        let spendable_height = height
+            + revaultd
+                .read()
+                .unwrap()
+                .blocks_until_spendable(height, height);
+        if spendable_height > height && spendable_height <= max_height {
+            blocks.entry(spendable_height).or_default();
+        }
+    }
+
+    // Is it the confirmation of a transaction that was already spending one of our vaults when
+    // we were stopped?
+    for (db_vault, _) in db_spending_vaults(&db_path)? {
+        if db_vault.final_txid == Some(txid) {
+            maybe_confirm_spend(revaultd, &db_path, bitcoind, &db_vault, &txid)?;
+        }
+    }
+    for (db_vault, cancel_tx) in db_canceling_vaults(&db_path)? {
+        if cancel_tx.txid() == txid {
+            maybe_confirm_cancel(revaultd, &db_path, bitcoind, &db_vault, &txid)?;
+        }
+    }
+    for (db_vault, emer_tx) in db_emering_vaults(&db_path)? {
+        if emer_tx.txid() == txid {
+            maybe_confirm_emer(revaultd, &db_path, bitcoind, &db_vault, &txid)?;
+        }
+    }
+    for (db_vault, unemer_tx) in db_unemering_vaults(&db_path)? {
+        if unemer_tx.txid() == txid {
+            maybe_confirm_unemer(revaultd, &db_path, bitcoind, &db_vault, &txid)?;
+        }
+    }
+
+    // Did it create new deposits, or confirm one we already knew about?
+    let min_conf = revaultd.read().unwrap().min_conf;
+    for (vout, txo) in tx.output.iter().enumerate() {
+        let outpoint = OutPoint {
+            txid,
+            vout: vout as u32,
+        };
+        if txo.value < MIN_DEPOSIT_VALUE
+            || !revaultd
+                .read()
+                .unwrap()
+                .derivation_index_map
+                .contains_key(&txo.script_pubkey)
+        {
+            continue;
+        }
+
+        if !deposits_cache.contains_key(&outpoint) {
+            // It may have been spent already
+            if db_vault_by_deposit(&db_path, &outpoint)?.is_some() {
+                continue;
+            }
+            handle_new_deposit(
+                revaultd,
+                bitcoind,
+                statemachine,
+                deposits_cache,
+                outpoint,
+                UtxoInfo {
+                    txo: txo.clone(),
+                    is_confirmed: false,
+                },
+            )?;
+        }
+
+        if deposits_cache.get(&outpoint).map(|utxo| utxo.is_confirmed) == Some(false) {
+            let confirmation_height =
+                height + deposit_min_conf(min_conf, tx.is_coin_base()).saturating_sub(1);
+            if confirmation_height <= height {
+                statemachine.flush();
+                handle_confirmed_deposit(bitcoind, statemachine, deposits_cache, outpoint)?;
+            } else if confirmation_height <= max_height {
+                blocks
+                    .entry(confirmation_height)
+                    .or_default()
+                    .deposit_confirmations
+                    .push(outpoint);
+            }
+        }
+    }
+    statemachine.flush();
+
+    Ok(())
+}
+
+// The wallet transactions to replay per height, up to `max_height`, leaving out those the replay
+// processed already. They must have been resolved against the chain beforehand.
+fn replay_schedule(
+    txs: &[ListedTransaction],
+    processed: &HashSet<(Txid, BlockHash)>,
+    max_height: u32,
+) -> BTreeMap<u32, ReplayedBlock> {
+    let mut blocks: BTreeMap<u32, ReplayedBlock> = BTreeMap::new();
+    for tx in txs {
+        if tx.blockheight <= max_height && !processed.contains(&(tx.txid, tx.blockhash)) {
+            blocks
+                .entry(tx.blockheight)
+                .or_default()
+                .txids
+                .push(tx.txid);
+        }
+    }
+
+    blocks
+}
+
+// While we were down, deposits may have been made, unvaulted and even spent. Polling the UTXO set
+// only tells us where they ended up, so before doing so we replay the blocks we missed one at a
+// time: the events the poller would have emitted are emitted with our tip at the height they
+// happened, for the vault transitions to be recorded at the right height.
+// Vaults can't move backward in the process: we only process the spending of an output that is in
+// our caches, and the state machine ignores the confirmations of the transactions of a vault that
+// is already past this point.
+fn replay_missed_blocks(
+    revaultd: &mut Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    statemachine: &StateMachineSender,
+    deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
+    unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
+) -> Result<(), BitcoindError> {
+    let db_path = revaultd.read().unwrap().db_file();
+    let stored_tip = db_tip(&db_path)?;
+    let tip = bitcoind.get_tip()?;
+
+    // There is nothing to replay for a fresh wallet, and update_tip() rescans everything in case
+    // of a reorg.
+    if stored_tip.height == 0
+        || tip.height <= stored_tip.height
+        || bitcoind.getblockhash(stored_tip.height)? != stored_tip.hash
+    {
+        db_clear_replayed_txs(&db_path)?;
+        return Ok(());
+    }
+
+    // Resume the replay we were doing before being stopped if its window is still part of the
+    // chain, without processing again the transactions it did: the transitions they imply may not
+    // be idempotent. Otherwise start from the block at our tip, as we may have been stopped before
+    // we were done with this one.
+    // The block at the current tip is left to the regular poll.
+    let (window, processed) = match db_replay_window(&db_path)? {
+        Some((window, processed))
+            if window.height < stored_tip.height
+                && bitcoind.getblockhash(window.height)? == window.hash =>
+        {
+            (window, processed)
+        }
+        _ => {
+            db_clear_replayed_txs(&db_path)?;
+            let height = stored_tip.height - 1;
+            let window = BlockchainTip {
+                height,
+                hash: bitcoind.getblockhash(height)?,
+            };
+            (window, HashSet::new())
+        }
+    };
+    let max_height = tip.height - 1;
+
+    // Across a reorg a transaction is listed for each block it was confirmed in, only the one on
+    // the chain we follow is honored.
+    let txs = bitcoind
+        .wallet_txs_since(&window.hash)?
+        .resolve(|height| bitcoind.getblockhash(height))?;
+    if !txs.removed.is_empty() {
+        // A block was disconnected since we checked our tip. Demote the vaults as for any reorg,
+        // the regular poll takes it from there.
+        log::warn!(
+            "Wallet transactions '{:?}' were reorged out while replaying the missed blocks",
+            txs.removed
+        );
+        db_clear_replayed_txs(&db_path)?;
+        db_exec(&db_path, |db_tx| {
+            comprehensive_rescan(revaultd, db_tx, bitcoind, deposits_cache, unvaults_cache)
+                .unwrap_or_else(|e| {
+                    log::error!("Error while rescaning vaults: '{}'", e);
+                    std::process::exit(1);
+                });
+            Ok(())
+        })?;
+        return Ok(());
+    }
+
+    let mut blocks = replay_schedule(&txs.confirmed, &processed, max_height);
+    if blocks.is_empty() {
+        db_clear_replayed_txs(&db_path)?;
+        return Ok(());
+    }
+    log::info!(
+        "Replaying the wallet transactions confirmed between height '{}' and '{}'",
+        window.height + 1,
+        max_height
+    );
+
+    let mut previous_tip = stored_tip;
+    while let Some(height) = blocks.keys().next().copied() {
+        let block = blocks.remove(&height).expect("We just got its height");
+        let block_tip = BlockchainTip {
+            height,
+            hash: bitcoind.getblockhash(height)?,
+        };
+        // The new tip and the confirmations of the block are committed together.
+        statemachine.emit(ChainEvent::TipChanged(block_tip));
+        for outpoint in block.deposit_confirmations {
+            if deposits_cache.get(&outpoint).map(|utxo| utxo.is_confirmed) == Some(false) {
+                handle_confirmed_deposit(bitcoind, statemachine, deposits_cache, outpoint)?;
+            }
+        }
+        statemachine.flush();
+
+        for txid in block.txids {
+            let wallet_tx = bitcoind.get_wallet_transaction(&txid)?;
+            let tx: Transaction = encode::deserialize(
+                &Vec::from_hex(&wallet_tx.hex)
+                    .expect("bitcoind returned a wrong transaction format"),
+            )
+            .expect("bitcoind returned a wrong transaction format");
+            replay_transaction(
+                revaultd,
+                bitcoind,
+                statemachine,
+                deposits_cache,
+                unvaults_cache,
+                &previous_tip,
+                height,
+                &tx,
+                &mut blocks,
+                max_height,
+            )?;
+            db_record_replayed_tx(&db_path, &window, &txid, &block_tip.hash)?;
+        }
+
+        previous_tip = block_tip;
+    }
+    db_clear_replayed_txs(&db_path)?;
+    log::info!(
+        "Done replaying the blocks up to height '{}'",
+        previous_tip.height
+    );
+
+    Ok(())
+}
+
+// Which kind of transaction may spend the Unvault transaction.
 #[derive(Debug)]
 enum UnvaultSpender {
     // The Cancel, spending via the stakeholders path to a new deposit
     Cancel(Txid),
-    // The Spend, any transaction spending via the managers path
+    // The Spend, a transaction spending via the managers path
     Spend(Txid),
     // The Emergency, spending via the stakeholders path to the EDV
     Emergency(Txid),
+    // A transaction we don't know of. As a manager we store all the Spend transactions we
+    // signed, so that's one not approved by us or not even using the managers path.
+    Unknown(Txid),
+}
+
+// Retrieve the transaction spending this outpoint from the watchonly wallet. It may have been
+// confirmed before our last poll (for instance if it wasn't current at the time), so we look
+// for it since the confirmation of the spent transaction.
+fn spender_txid(
+    bitcoind: &BitcoinD,
+    previous_tip: &BlockchainTip,
+    spent_outpoint: &OutPoint,
+) -> Result<Option<Txid>, BitcoindError> {
+    let spent_height = bitcoind
+        .get_wallet_transaction(&spent_outpoint.txid)
+        .ok()
+        .and_then(|tx| tx.blockheight);
+    let since = match spent_height {
+        Some(height) if height > 0 => bitcoind.getblockhash(height - 1)?,
+        _ => previous_tip.hash,
+    };
+
+    bitcoind.get_spender_txid(spent_outpoint, &since)
+}
+
+// Which of the emergency transactions of this vault this is, if any. Both are matched whatever
+// output was spent, so that one broadcast without us noticing (for instance by a watchtower) is
+// never mistaken for another transaction.
+fn emergency_kind(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    db_vault: &DbVault,
+    txid: &Txid,
+) -> Result<Option<EmergencyTxKind>, BitcoindError> {
+    if emer_txid(revaultd, db_vault)?.as_ref() == Some(txid) {
+        return Ok(Some(EmergencyTxKind::Emergency));
+    }
+    if unemer_txid(revaultd, db_vault)?.as_ref() == Some(txid) {
+        return Ok(Some(EmergencyTxKind::UnvaultEmergency));
+    }
+
+    Ok(None)
 }
 
 // Retrieve the transaction kind (and its txid) that spent an Unvault
@@ -1191,7 +2311,7 @@ fn unvault_spender(
     }
 
     // Finally, fetch the spending transaction
-    if let Some(spender_txid) = bitcoind.get_spender_txid(unvault_outpoint, &previous_tip.hash)? {
+    if let Some(spender_txid) = spender_txid(bitcoind, previous_tip, unvault_outpoint)? {
         // FIXME: be smarter, all the information are in the previous call, no need for a
         // second one.
 
@@ -1199,14 +2319,24 @@ fn unvault_spender(
         // In theory (read edge cases), the Cancel and UnEmer could have not been
         // current at the last bitcoind poll but could be now.
         // Be sure to not wrongly mark a Cancel or UnEmer as a Spend!
-        if spender_txid == cancel_txid || Some(spender_txid) == unemer_txid {
-            // Alright, the spender is the cancel or the unemer,
-            // but we just checked and they weren't current. We'll return None
-            // so the checker will call this function again.
+        if spender_txid == cancel_txid {
+            // Alright, the spender is the cancel but we just checked and it wasn't current.
+            // We'll return None so the checker will call this function again.
+            return Ok(None);
+        }
+        if emergency_kind(revaultd, &vault, &spender_txid)?.is_some() {
+            if bitcoind.is_current(&spender_txid)? {
+                return Ok(Some(UnvaultSpender::Emergency(spender_txid)));
+            }
             return Ok(None);
         }
 
         if bitcoind.is_current(&spender_txid)? {
+            // Only the managers store the Spend transactions, a stakeholder can't tell.
+            let is_manager = revaultd.read().unwrap().is_manager();
+            if is_manager && db_spend_transaction(&db_path, &spender_txid)?.is_none() {
+                return Ok(Some(UnvaultSpender::Unknown(spender_txid)));
+            }
             return Ok(Some(UnvaultSpender::Spend(spender_txid)));
         }
     }
@@ -1214,6 +2344,95 @@ fn unvault_spender(
     Ok(None)
 }
 
+// Raise the alarm about a vault whose Deposit or Unvault output was spent by a transaction we
+// don't know of. It's kept in a flag for an operator to investigate.
+fn flag_unknown_spender(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    db_path: &Path,
+    db_vault: &DbVault,
+    spent_outpoint: &OutPoint,
+    spender_txid: &Txid,
+) -> Result<(), BitcoindError> {
+    log_event!(
+        log::Level::Error,
+        "unknown_spender",
+        outpoint = db_vault.deposit_outpoint,
+        txid = spender_txid;
+        "!!!!! Output '{}' of vault at '{}' was spent by unknown transaction '{}' !!!!!",
+        spent_outpoint,
+        db_vault.deposit_outpoint,
+        spender_txid
+    );
+    let created_at = revaultd.read().unwrap().clock.unix_timestamp();
+    db_raise_vault_flag(
+        db_path,
+        db_vault.id,
+        VaultFlagKind::UnknownSpender,
+        &format!(
+            "Output '{}' was spent by unknown transaction '{}'",
+            spent_outpoint, spender_txid
+        ),
+        created_at,
+    )?;
+
+    Ok(())
+}
+
+// An unconfirmed deposit whose transaction is neither in the mempool nor mined, for instance
+// because it was replaced. We keep it in cache for a number of polls in case it comes back, and
+// give up on the vault past that. If it shows up again afterward, it's detected as a new deposit
+// and the state machine resurrects the vault.
+fn handle_evicted_deposit(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    db_path: &Path,
+    deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
+    evicted_deposits: &mut HashMap<OutPoint, u32>,
+    deposit_outpoint: OutPoint,
+) -> Result<(), BitcoindError> {
+    let polls = evicted_deposits.entry(deposit_outpoint).or_insert(0);
+    *polls += 1;
+    if *polls == 1 {
+        log::warn!(
+            "The transaction of unconfirmed deposit at '{}' left the mempool. It may have \
+             been replaced.",
+            deposit_outpoint
+        );
+    }
+    let (abandon_polls, now) = {
+        let revaultd = revaultd.read().unwrap();
+        (
+            revaultd.deposit_abandon_polls,
+            revaultd.clock.unix_timestamp(),
+        )
+    };
+    if *polls < abandon_polls {
+        return Ok(());
+    }
+
+    evicted_deposits.remove(&deposit_outpoint);
+    deposits_cache
+        .remove(&deposit_outpoint)
+        .expect("It was in spent_deposits, it must still be here.");
+    let db_vault = match db_vault_by_deposit(db_path, &deposit_outpoint)? {
+        Some(db_vault) => db_vault,
+        None => return Ok(()),
+    };
+    if db_abandon_vault(db_path, db_vault.id, now)? {
+        log_event!(
+            log::Level::Warn,
+            "deposit_abandoned",
+            outpoint = deposit_outpoint,
+            derivation_index = db_vault.derivation_index;
+            "Giving up on unconfirmed deposit at '{}', its transaction wasn't seen for {} polls. \
+             It will be resurrected if it shows up again.",
+            deposit_outpoint,
+            abandon_polls
+        );
+    }
+
+    Ok(())
+}
+
 // Update the state of a vault whose Unvault txo was spent.
 fn handle_spent_unvault(
     revaultd: &mut Arc<RwLock<RevaultD>>,
@@ -1223,9 +2442,13 @@ fn handle_spent_unvault(
     previous_tip: &BlockchainTip,
     unvault_outpoint: &OutPoint,
 ) -> Result<(), BitcoindError> {
-    match unvault_spender(revaultd, bitcoind, previous_tip, unvault_outpoint)? {
+    let spender = unvault_spender(revaultd, bitcoind, previous_tip, unvault_outpoint)?;
+    let is_unknown = matches!(spender, Some(UnvaultSpender::Unknown(_)));
+    match spender {
         Some(UnvaultSpender::Cancel(txid)) => {
-            db_cancel_unvault(db_path, &unvault_outpoint.txid, &txid)?;
+            let previous_vault = db_vault_by_unvault_txid(db_path, &unvault_outpoint.txid)?;
+            let now = revaultd.read().unwrap().clock.unix_timestamp();
+            db_cancel_unvault(db_path, &unvault_outpoint.txid, &txid, now)?;
             unvaults_cache
                 .remove(unvault_outpoint)
                 .expect("An unknown unvault got spent?");
@@ -1242,15 +2465,24 @@ fn handle_spent_unvault(
                         &unvault_outpoint.txid
                     ))
                 })?;
-            match maybe_confirm_cancel(db_path, bitcoind, &db_vault, &txid) {
+            if let Some((previous_vault, _)) = previous_vault {
+                if previous_vault.status == VaultStatus::Spendable {
+                    report_late_cancel(&previous_vault, &txid);
+                }
+            }
+            db_set_conflicts_competing(db_path, db_vault.id, &txid)?;
+            match maybe_confirm_cancel(revaultd, db_path, bitcoind, &db_vault, &txid) {
                 Ok(_) => {}
                 Err(e) => {
                     log::error!("Error checking if Cancel '{}' is confirmed: '{}'", &txid, e);
                 }
             }
         }
-        Some(UnvaultSpender::Spend(txid)) => {
-            db_spend_unvault(db_path, &unvault_outpoint.txid, &txid)?;
+        // We can't do much about an unknown spender but to raise the alarm, and track its
+        // confirmation as we would for a Spend.
+        Some(UnvaultSpender::Spend(txid)) | Some(UnvaultSpender::Unknown(txid)) => {
+            let now = revaultd.read().unwrap().clock.unix_timestamp();
+            db_spend_unvault(db_path, &unvault_outpoint.txid, &txid, now)?;
             unvaults_cache.remove(unvault_outpoint).ok_or_else(|| {
                 BitcoindError::Custom("An unknown unvault got spent?".to_string())
             })?;
@@ -1267,7 +2499,11 @@ fn handle_spent_unvault(
                         &unvault_outpoint.txid
                     ))
                 })?;
-            match maybe_confirm_spend(db_path, bitcoind, &db_vault, &txid) {
+            if is_unknown {
+                flag_unknown_spender(revaultd, db_path, &db_vault, unvault_outpoint, &txid)?;
+            }
+            db_set_conflicts_competing(db_path, db_vault.id, &txid)?;
+            match maybe_confirm_spend(revaultd, db_path, bitcoind, &db_vault, &txid) {
                 Ok(_) => {}
                 Err(e) => {
                     log::error!("Error checking if Spend '{}' is confirmed: '{}'", &txid, e);
@@ -1275,7 +2511,8 @@ fn handle_spent_unvault(
             }
         }
         Some(UnvaultSpender::Emergency(txid)) => {
-            db_emer_unvault(db_path, &unvault_outpoint.txid)?;
+            let now = revaultd.read().unwrap().clock.unix_timestamp();
+            db_emer_unvault(db_path, &unvault_outpoint.txid, now)?;
             unvaults_cache.remove(unvault_outpoint).ok_or_else(|| {
                 BitcoindError::Custom("An unknown unvault got spent?".to_string())
             })?;
@@ -1292,7 +2529,7 @@ fn handle_spent_unvault(
                         &unvault_outpoint.txid
                     ))
                 })?;
-            match maybe_confirm_unemer(db_path, bitcoind, &db_vault, &txid) {
+            match maybe_confirm_unemer(revaultd, db_path, bitcoind, &db_vault, &txid) {
                 Ok(_) => {}
                 Err(e) => {
                     log::error!(
@@ -1321,8 +2558,8 @@ fn handle_spent_unvault(
 // Update our state when a new UTXO appears that is paying to the Deposit descriptor
 fn handle_new_deposit(
     revaultd: &mut Arc<RwLock<RevaultD>>,
-    db_path: &Path,
     bitcoind: &BitcoinD,
+    statemachine: &StateMachineSender,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     outpoint: OutPoint,
     utxo: UtxoInfo,
@@ -1346,67 +2583,271 @@ fn handle_new_deposit(
             BitcoindError::Custom(format!("Unknown derivation index for: {:#?}", &utxo))
         })?;
 
-    // Note that the deposit *might* have already MIN_CONF confirmations, that's fine. We'll
-    // confim it during the next poll.
-    let amount = Amount::from_sat(utxo.txo.value);
-    db_insert_new_unconfirmed_vault(
-        db_path,
-        revaultd
-            .read()
-            .unwrap()
-            .wallet_id
-            .expect("Wallet id is set at startup in setup_db()"),
-        &outpoint,
-        &amount,
+    // The state machine tells apart external deposits from the outputs of our own Cancel and
+    // Spend transactions using the coins spent by the funding transaction.
+    let wallet_tx = bitcoind.get_wallet_transaction(&outpoint.txid)?;
+    // A deposit may be funded by transactions that aren't confirmed yet either. Being mined
+    // before its parents is invalid, so its own confirmations account for theirs too.
+    // An unconfirmed transaction that isn't in our mempool (for instance a non-standard one
+    // that was mined then reorged out) has no ancestry we can tell, and is left to the
+    // eviction logic.
+    let ancestry = if wallet_tx.blockheight.is_none() {
+        bitcoind.mempool_ancestry(&outpoint.txid)?
+    } else {
+        None
+    };
+    let funding_tx: Transaction = encode::deserialize(
+        &Vec::from_hex(&wallet_tx.hex).expect("bitcoind returned a wrong transaction format"),
+    )
+    .expect("bitcoind returned a wrong transaction format");
+    let funding_inputs = funding_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect();
+    let coinbase_height = wallet_tx.blockheight.filter(|_| funding_tx.is_coin_base());
+    if let Some(height) = coinbase_height {
+        log_event!(
+            log::Level::Info,
+            "coinbase_deposit",
+            outpoint = outpoint,
+            coinbase_height = height;
+            "Deposit at '{}' was made by a coinbase transaction mined at height {}. It will only \
+             be confirmed once it matures, at height {}.",
+            outpoint,
+            height,
+            height + COINBASE_MATURITY
+        );
+    }
+
+    statemachine.emit(ChainEvent::DepositDetected {
+        outpoint,
+        amount: Amount::from_sat(utxo.txo.value),
         derivation_index,
-    )?;
-    log::debug!(
-        "Got a new unconfirmed deposit at {} for {} ({})",
-        &outpoint,
-        &utxo.txo.script_pubkey,
-        &amount
-    );
+        funding_inputs,
+        ancestry,
+        coinbase_height,
+    });
     deposits_cache.insert(outpoint, utxo);
 
     // Mind the gap! https://www.youtube.com/watch?v=UOPyGKDQuRk
-    // FIXME: of course, that's rudimentary
-    let current_first_index = revaultd.read().unwrap().current_unused_index;
-    if derivation_index >= current_first_index {
-        let new_index = revaultd
-            .read()
-            .unwrap()
-            .current_unused_index
-            .increment()
-            .map_err(|e| {
-                // FIXME: we should probably go back to 0 at this point.
-                BitcoindError::Custom(format!("Deriving next index: {}", e))
-            })?;
-        db_update_deposit_index(&revaultd.read().unwrap().db_file(), new_index)?;
-        revaultd.write().unwrap().current_unused_index = new_index;
-        let next_addr = bitcoind
-            .addr_descriptor(&revaultd.read().unwrap().last_deposit_address().to_string())?;
-        bitcoind.import_fresh_deposit_descriptor(next_addr)?;
-        let next_addr = bitcoind
-            .addr_descriptor(&revaultd.read().unwrap().last_unvault_address().to_string())?;
-        bitcoind.import_fresh_unvault_descriptor(next_addr)?;
+    // Another participant's daemon may hand out deposit addresses faster than ours, so move past
+    // the index of this deposit rather than just to the next one. The window of addresses we
+    // watch is then extended accordingly, at the end of the poll. This goes through the
+    // allocator, as the RPC thread may be handing out a new address concurrently.
+    let advanced = {
+        let revaultd = revaultd.read().unwrap();
+        revaultd
+            .deposit_indexes
+            .advance_past(&revaultd.db_file(), derivation_index)
+            .map_err(|e| match e {
+                IndexAllocError::Database(e) => BitcoindError::from(e),
+                e => BitcoindError::Custom(format!(
+                    "Deriving next index after {}: {}",
+                    derivation_index, e
+                )),
+            })?
+    };
+    if let Some(current_first_index) = advanced {
+        let new_index = derivation_index.saturating_add(1);
+        let gap = derivation_index.as_u32() - current_first_index.as_u32();
+        if gap >= revaultd.read().unwrap().gap_limit() / 2 {
+            log_event!(
+                log::Level::Warn,
+                "deposit_index_gap",
+                outpoint = outpoint,
+                derivation_index = derivation_index,
+                gap = gap;
+                "Deposit at '{}' is at derivation index {}, {} indexes above our first unused one. \
+                 Another participant is handing out deposit addresses faster than us.",
+                outpoint,
+                derivation_index,
+                gap
+            );
+        }
 
         log::debug!(
-            "Incremented deposit derivation index from {}",
-            current_first_index
+            "Incremented deposit derivation index from {} to {}",
+            current_first_index,
+            new_index
         );
     }
 
     Ok(())
 }
 
+// Watch the deposit and unvault addresses up to the gap limit above our first unused derivation
+// index, as it moves with new deposits. The window never goes beyond the configured ceiling,
+// loudly.
+fn extend_deposit_window(
+    revaultd: &mut Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    rescanner: &Rescanner,
+) -> Result<(), BitcoindError> {
+    let (last_index, window_end, ceiling) = {
+        let revaultd = revaultd.read().unwrap();
+        (
+            revaultd.last_derived_index(),
+            revaultd.deposit_window_end(),
+            revaultd.deposit_index_ceiling,
+        )
+    };
+
+    if window_end > last_index {
+        let indexes: Vec<DerivationIndex> =
+            last_index.saturating_add(1).up_to(window_end).collect();
+        db_store_derived_scripts(&mut revaultd.write().unwrap(), &indexes)?;
+
+        if window_end == ceiling {
+            log_event!(
+                log::Level::Error,
+                "deposit_index_ceiling",
+                ceiling = ceiling;
+                "The deposit addresses we watch reached the derivation index ceiling ({}). \
+                 Deposits to addresses beyond it will NOT be detected. Find out which participant \
+                 hands out addresses so fast, and raise 'deposit_index_ceiling' if need be.",
+                ceiling
+            );
+        }
+    }
+
+    // This also catches up on the addresses we derived but could not import last time.
+    import_new_addresses(revaultd, bitcoind, rescanner)
+}
+
+// Import into the watchonly wallet the addresses we derived since the last import. If our first
+// unused index jumped because another participant handed out addresses faster than us, the ones
+// between may have been paid already: we rescan the chain for them.
+fn import_new_addresses(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+    rescanner: &Rescanner,
+) -> Result<(), BitcoindError> {
+    let (db_path, wallet_id, gap_limit) = {
+        let revaultd = revaultd.read().unwrap();
+        (
+            revaultd.db_file(),
+            revaultd
+                .wallet_id
+                .expect("Wallet id is set at startup in setup_db()"),
+            revaultd.gap_limit(),
+        )
+    };
+    let deposit_imported = db_imported_index(&db_path, wallet_id, ScriptKind::Deposit)?;
+    let unvault_imported = db_imported_index(&db_path, wallet_id, ScriptKind::Unvault)?;
+
+    // They come in the order of their derivation index.
+    let (mut deposit_addresses, mut unvault_addresses) = (Vec::new(), Vec::new());
+    let (mut first_deposit_index, mut last_deposit_index) = (None, None);
+    let (mut first_unvault_index, mut last_unvault_index) = (None, None);
+    for res in revaultd
+        .read()
+        .unwrap()
+        .addresses_to_import(cmp::min(deposit_imported, unvault_imported))
+    {
+        let (address, index, kind) = res?;
+        match kind {
+            ScriptKind::Deposit if Some(index) > deposit_imported => {
+                deposit_addresses.push(bitcoind.addr_descriptor(&address.to_string())?);
+                first_deposit_index = first_deposit_index.or(Some(index));
+                last_deposit_index = Some(index);
+            }
+            ScriptKind::Unvault if Some(index) > unvault_imported => {
+                unvault_addresses.push(bitcoind.addr_descriptor(&address.to_string())?);
+                first_unvault_index = first_unvault_index.or(Some(index));
+                last_unvault_index = Some(index);
+            }
+            _ => {}
+        }
+    }
+
+    if last_deposit_index.is_none() && last_unvault_index.is_none() {
+        return Ok(());
+    }
+    // They are needed from now on, as far as the chain is concerned.
+    let first_needed = bitcoind.median_time_past()?;
+
+    if let (Some(first_index), Some(last_index)) = (first_deposit_index, last_deposit_index) {
+        if (deposit_addresses.len() as u32) < gap_limit / 2 {
+            for descriptor in deposit_addresses {
+                bitcoind.import_fresh_deposit_descriptor(descriptor)?;
+            }
+        } else {
+            log_event!(
+                log::Level::Warn,
+                "deposit_window_rescan",
+                first_index = first_index,
+                last_index = last_index;
+                "Extending the deposit addresses we watch from derivation index {} to {}, and \
+                 rescanning the chain for deposits to them.",
+                first_index,
+                last_index
+            );
+            rescanner.queue(RescanImport {
+                kind: ImportKind::Deposit,
+                descriptors: deposit_addresses,
+                timestamp: db_rescan_timestamp(
+                    &db_path,
+                    wallet_id,
+                    ScriptKind::Deposit,
+                    first_index,
+                )?,
+            });
+        }
+        db_update_imported_index(
+            &db_path,
+            wallet_id,
+            ScriptKind::Deposit,
+            last_index,
+            first_needed,
+        )?;
+    }
+
+    if let (Some(first_index), Some(last_index)) = (first_unvault_index, last_unvault_index) {
+        if (unvault_addresses.len() as u32) < gap_limit / 2 {
+            for descriptor in unvault_addresses {
+                bitcoind.import_fresh_unvault_descriptor(descriptor)?;
+            }
+        } else {
+            rescanner.queue(RescanImport {
+                kind: ImportKind::Unvault,
+                descriptors: unvault_addresses,
+                timestamp: db_rescan_timestamp(
+                    &db_path,
+                    wallet_id,
+                    ScriptKind::Unvault,
+                    first_index,
+                )?,
+            });
+        }
+        db_update_imported_index(
+            &db_path,
+            wallet_id,
+            ScriptKind::Unvault,
+            last_index,
+            first_needed,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The number of confirmations a deposit needs to be confirmed. A coinbase output additionally
+/// needs to mature, for the presigned transactions spending it to be valid.
+pub fn deposit_min_conf(min_conf: u32, is_coinbase: bool) -> u32 {
+    if is_coinbase {
+        cmp::max(min_conf, COINBASE_MATURITY + 1)
+    } else {
+        min_conf
+    }
+}
+
 // Update our state when we notice a deeply-enough confirmed deposit UTXO
 fn handle_confirmed_deposit(
-    revaultd: &mut Arc<RwLock<RevaultD>>,
-    db_path: &Path,
     bitcoind: &BitcoinD,
+    statemachine: &StateMachineSender,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     outpoint: OutPoint,
-    utxo: UtxoInfo,
 ) -> Result<(), BitcoindError> {
     let tx = bitcoind.get_wallet_transaction(&outpoint.txid)?;
     let (blockheight, blocktime) =
@@ -1423,42 +2864,33 @@ fn handle_confirmed_deposit(
             return Ok(());
         };
 
-    let txo_value = utxo.txo.value;
-    // emer_tx and unemer_tx are None for managers
-    let (unvault_tx, cancel_tx, emer_tx, unemer_tx) =
-        match presigned_transactions(&revaultd.read().unwrap(), outpoint, utxo) {
-            Ok(txs) => txs,
-            Err(e) => {
-                log::error!(
-                    "Unexpected error deriving transaction for '{}', amount: '{}': '{}'",
-                    outpoint,
-                    txo_value,
-                    e
-                );
-                return Ok(());
-            }
-        };
-
-    db_confirm_deposit(
-        db_path,
-        &outpoint,
-        blockheight,
-        blocktime,
-        &unvault_tx,
-        &cancel_tx,
-        emer_tx.as_ref(),
-        unemer_tx.as_ref(),
-    )?;
+    statemachine.emit(ChainEvent::TxConfirmed {
+        kind: ConfirmedTx::Deposit {
+            vout: outpoint.vout,
+            blockheight,
+            blocktime,
+        },
+        txid: outpoint.txid,
+    });
     deposits_cache
         .get_mut(&outpoint)
         .ok_or_else(|| BitcoindError::Custom("An unknown vault got confirmed?".to_string()))?
         .is_confirmed = true;
 
-    log::debug!("Vault at {} is now confirmed", &outpoint);
-
     Ok(())
 }
 
+// Whether this deposit was flagged for depending on too many unconfirmed transactions, and no
+// one cleared the flag yet.
+fn deposit_ancestry_refused(db_path: &Path, outpoint: &OutPoint) -> Result<bool, BitcoindError> {
+    Ok(match db_vault_by_deposit(db_path, outpoint)? {
+        Some(db_vault) => db_vault_flags(db_path, db_vault.id)?.iter().any(|flag| {
+            flag.kind == VaultFlagKind::UnconfirmedAncestry && flag.cleared_at.is_none()
+        }),
+        None => false,
+    })
+}
+
 // Called when a deposit UTXO disappears from the listunspent result, ie it was spent. This tries
 // to figure out where it went.
 fn handle_spent_deposit(
@@ -1467,6 +2899,7 @@ fn handle_spent_deposit(
     bitcoind: &BitcoinD,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
+    previous_tip: &BlockchainTip,
     deposit_outpoint: OutPoint,
     utxo: UtxoInfo,
 ) -> Result<(), BitcoindError> {
@@ -1486,13 +2919,19 @@ fn handle_spent_deposit(
     // Was it spent by an Unvault tx? No worry if the Unvault txo was spent too, it'll be
     // noticed when we poll them next.
     if bitcoind.is_current(&unvault_outpoint.txid)? {
-        log::debug!(
+        log_event!(
+            log::Level::Debug,
+            "vault_status",
+            outpoint = deposit_outpoint,
+            to = VaultStatus::Unvaulting,
+            txid = unvault_outpoint.txid;
             "Found Unvault transaction '{}' in wallet for vault at '{}'",
             &unvault_outpoint.txid,
             &deposit_outpoint
         );
 
-        db_unvault_deposit(db_path, &unvault_outpoint.txid)?;
+        let now = revaultd.read().unwrap().clock.unix_timestamp();
+        db_unvault_deposit(db_path, &unvault_outpoint.txid, now)?;
         unvaults_cache.insert(
             unvault_outpoint,
             UtxoInfo {
@@ -1510,16 +2949,48 @@ fn handle_spent_deposit(
     // Was it spent by the Emergency transaction?
     let db_vault =
         db_vault_by_deposit(db_path, &deposit_outpoint)?.expect("Spent deposit doesn't exist?");
-    if let Some(emer_txid) = emer_txid(revaultd, &db_vault)? {
+    let emer_txid = emer_txid(revaultd, &db_vault)?;
+    if let Some(emer_txid) = emer_txid {
         if bitcoind.is_current(&emer_txid)? {
-            db_mark_emergencying_vault(db_path, db_vault.id)?;
+            log::warn!("Deposit at {} is now being emergencied", &deposit_outpoint);
+            let now = revaultd.read().unwrap().clock.unix_timestamp();
+            db_mark_emergencying_vault(db_path, db_vault.id, now)?;
             deposits_cache
                 .remove(&deposit_outpoint)
                 .expect("It was in spent_deposits, it must still be here.");
+            return Ok(());
         }
     }
 
-    // TODO: handle bypass
+    // Was it spent by a transaction we don't know of? If it's the Unvault that we just checked
+    // wasn't current, we'll check again at the next poll.
+    if let Some(spender_txid) = spender_txid(bitcoind, previous_tip, &deposit_outpoint)? {
+        if spender_txid != unvault_outpoint.txid && bitcoind.is_current(&spender_txid)? {
+            // It may be the Emergency, that wasn't current when we checked above.
+            if emergency_kind(revaultd, &db_vault, &spender_txid)?.is_some() {
+                log::warn!("Deposit at {} is now being emergencied", &deposit_outpoint);
+                let now = revaultd.read().unwrap().clock.unix_timestamp();
+                db_mark_emergencying_vault(db_path, db_vault.id, now)?;
+                deposits_cache
+                    .remove(&deposit_outpoint)
+                    .expect("It was in spent_deposits, it must still be here.");
+                return Ok(());
+            }
+
+            // There is no status for this, we just stop tracking it.
+            flag_unknown_spender(
+                revaultd,
+                db_path,
+                &db_vault,
+                &deposit_outpoint,
+                &spender_txid,
+            )?;
+            deposits_cache
+                .remove(&deposit_outpoint)
+                .expect("It was in spent_deposits, it must still be here.");
+            return Ok(());
+        }
+    }
 
     // Only remove the deposit from the cache if it's not in mempool nor in block chain.
     if bitcoind.is_current(&deposit_outpoint.txid)? {
@@ -1554,8 +3025,10 @@ fn handle_spent_deposit(
 fn update_utxos(
     revaultd: &mut Arc<RwLock<RevaultD>>,
     bitcoind: &BitcoinD,
+    statemachine: &StateMachineSender,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
+    evicted_deposits: &mut HashMap<OutPoint, u32>,
     previous_tip: &BlockchainTip,
 ) -> Result<(), BitcoindError> {
     let db_path = revaultd.read().unwrap().db_file();
@@ -1568,20 +3041,59 @@ fn update_utxos(
     } = bitcoind.sync_deposits(deposits_cache, revaultd.read().unwrap().min_conf)?;
 
     for (outpoint, utxo) in new_deposits {
-        handle_new_deposit(revaultd, &db_path, bitcoind, deposits_cache, outpoint, utxo)?;
+        handle_new_deposit(
+            revaultd,
+            bitcoind,
+            statemachine,
+            deposits_cache,
+            outpoint,
+            utxo,
+        )?;
     }
 
-    for (outpoint, utxo) in conf_deposits {
-        handle_confirmed_deposit(revaultd, &db_path, bitcoind, deposits_cache, outpoint, utxo)?;
+    for (outpoint, _) in conf_deposits {
+        // It's left unconfirmed in the cache, so we'll check again at the next poll whether the
+        // flag was cleared.
+        if deposit_ancestry_refused(&db_path, &outpoint)? {
+            log::debug!(
+                "Not confirming deposit at '{}' as its unconfirmed ancestry was refused",
+                outpoint
+            );
+            continue;
+        }
+        handle_confirmed_deposit(bitcoind, statemachine, deposits_cache, outpoint)?;
     }
 
+    // The spent deposits are looked up in database, make sure it's up to date.
+    statemachine.flush();
+
+    // The evicted deposits that aren't missing anymore are back in the mempool or mined.
+    evicted_deposits.retain(|outpoint, _| {
+        let missing = spent_deposits.contains_key(outpoint);
+        if !missing {
+            log::info!("Unconfirmed deposit at '{}' showed up again", outpoint);
+        }
+        missing
+    });
     for (outpoint, utxo) in spent_deposits {
+        if !utxo.is_confirmed && !bitcoind.is_current(&outpoint.txid)? {
+            handle_evicted_deposit(
+                revaultd,
+                &db_path,
+                deposits_cache,
+                evicted_deposits,
+                outpoint,
+            )?;
+            continue;
+        }
+        evicted_deposits.remove(&outpoint);
         handle_spent_deposit(
             revaultd,
             &db_path,
             bitcoind,
             deposits_cache,
             unvaults_cache,
+            previous_tip,
             outpoint,
             utxo,
         )?;
@@ -1594,13 +3106,29 @@ fn update_utxos(
     } = bitcoind.sync_unvaults(unvaults_cache)?;
 
     for (outpoint, _) in conf_unvaults {
-        db_confirm_unvault(&db_path, &outpoint.txid)?;
+        let blockheight =
+            if let Some(height) = bitcoind.get_wallet_transaction(&outpoint.txid)?.blockheight {
+                height
+            } else {
+                // Same as for deposits, it may have been unconfirmed in between the two calls.
+                // It's still unconfirmed in the cache so we'll check it again at the next poll.
+                log::error!(
+                    "Unvault transaction '{}' isn't confirmed but it's part of the \
+                     confirmed Unvaults returned by listunspent.",
+                    outpoint.txid
+                );
+                continue;
+            };
+        statemachine.emit(ChainEvent::TxConfirmed {
+            kind: ConfirmedTx::Unvault { blockheight },
+            txid: outpoint.txid,
+        });
         unvaults_cache
             .get_mut(&outpoint)
             .ok_or_else(|| BitcoindError::Custom("An unknown unvault got confirmed?".to_string()))?
             .is_confirmed = true;
-        log::debug!("Unvault transaction at {} is now confirmed", &outpoint);
     }
+    statemachine.flush();
 
     for (unvault_outpoint, _) in spent_unvaults {
         handle_spent_unvault(
@@ -1696,19 +3224,109 @@ fn bitcoind_sync_status(
 }
 
 // This creates the actual wallet file, and imports the descriptors
-fn maybe_create_wallet(revaultd: &mut RevaultD, bitcoind: &BitcoinD) -> Result<(), BitcoindError> {
+// Create our watchonly wallet on bitcoind and import all our addresses in it. If it's not a fresh
+// wallet, bitcoind will rescan the chain from the time the first of the ranges of addresses we
+// recorded was needed (the wallet creation date for a wallet that never extended them): the
+// imports are then performed in the background by the rescanner.
+fn create_watchonly_wallet(
+    revaultd: &mut RevaultD,
+    bitcoind: &BitcoinD,
+    rescanner: &Rescanner,
+    fresh_wallet: bool,
+) -> Result<(), BitcoindError> {
+    let wallet = db_wallet(&revaultd.db_file())?;
+    let bitcoind_wallet_path = revaultd
+        .watchonly_wallet_file()
+        .expect("Wallet id is set at startup in setup_db()");
+
+    bitcoind.createwallet_startup(bitcoind_wallet_path, true)?;
+    log::info!("Importing descriptors to bitcoind watchonly wallet.");
+
+    // Now, import descriptors.
+    // In theory, we could just import the vault (deposit) descriptor expressed using xpubs, give a
+    // range to bitcoind as the gap limit, and be fine.
+    // Unfortunately we cannot just import descriptors as is, since bitcoind does not support
+    // Miniscript ones yet. Worse, we actually need to derive them to pass them to bitcoind since
+    // the vault one (which we are interested about) won't be expressed with a `multi()` statement (
+    // currently supported by bitcoind) if there are more than 15 stakeholders.
+    // Therefore, we derive [max index] `addr()` descriptors to import into bitcoind, and handle
+    // the derivation index mess ourselves :'(
+    // As a consequence, we don't have enough information to opportunistically import a
+    // descriptor at the reception of a deposit anymore. Thus we need to blindly import *both*
+    // deposit and unvault descriptors..
+    // FIXME: maybe we actually have, with the derivation_index_map ?
+    let (mut deposit_addresses, mut unvault_addresses) = (Vec::new(), Vec::new());
+    for res in revaultd.addresses_to_import(None) {
+        let (address, _, kind) = res?;
+        let descriptor = bitcoind.addr_descriptor(&address.to_string())?;
+        match kind {
+            ScriptKind::Deposit => deposit_addresses.push(descriptor),
+            ScriptKind::Unvault => unvault_addresses.push(descriptor),
+        }
+    }
+
+    log::trace!("Importing deposit descriptors '{:?}'", &deposit_addresses);
+    if fresh_wallet {
+        bitcoind.startup_import_deposit_descriptors(deposit_addresses, wallet.timestamp, true)?;
+    } else {
+        rescanner.queue(RescanImport {
+            kind: ImportKind::Deposit,
+            descriptors: deposit_addresses,
+            timestamp: db_rescan_timestamp(
+                &revaultd.db_file(),
+                wallet.id,
+                ScriptKind::Deposit,
+                DerivationIndex::ZERO,
+            )?,
+        });
+    }
+
+    log::trace!("Importing unvault descriptors '{:?}'", &unvault_addresses);
+    if fresh_wallet {
+        bitcoind.startup_import_unvault_descriptors(unvault_addresses, wallet.timestamp, true)?;
+    } else {
+        rescanner.queue(RescanImport {
+            kind: ImportKind::Unvault,
+            descriptors: unvault_addresses,
+            timestamp: db_rescan_timestamp(
+                &revaultd.db_file(),
+                wallet.id,
+                ScriptKind::Unvault,
+                DerivationIndex::ZERO,
+            )?,
+        });
+    }
+
+    // The wallet may have been re-created, in which case we imported more than last time. We
+    // can't tell when these were needed, as far as we know since its creation.
+    let last_index = revaultd.last_derived_index();
+    for kind in &[ScriptKind::Deposit, ScriptKind::Unvault] {
+        db_update_imported_index(
+            &revaultd.db_file(),
+            wallet.id,
+            *kind,
+            last_index,
+            wallet.timestamp,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn maybe_create_wallet(
+    revaultd: &mut RevaultD,
+    bitcoind: &BitcoinD,
+    rescanner: &Rescanner,
+) -> Result<(), BitcoindError> {
     let wallet = db_wallet(&revaultd.db_file())?;
     let bitcoind_wallet_path = revaultd
         .watchonly_wallet_file()
         .expect("Wallet id is set at startup in setup_db()");
     // Did we just create the wallet ?
-    let curr_timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|dur| dur.as_secs())
-        .map_err(|e| {
-            BitcoindError::Custom(format!("Computing time since epoch: {}", e.to_string()))
-        })?;
-    let fresh_wallet = (curr_timestamp - wallet.timestamp as u64) < 30;
+    let curr_timestamp = revaultd.clock.try_unix_timestamp().map_err(|e| {
+        BitcoindError::Custom(format!("Computing time since epoch: {}", e.to_string()))
+    })?;
+    let fresh_wallet = curr_timestamp.saturating_sub(wallet.timestamp as u64) < 30;
 
     // TODO: sanity check descriptors are imported when migrating to 0.22
 
@@ -1722,37 +3340,7 @@ fn maybe_create_wallet(revaultd: &mut RevaultD, bitcoind: &BitcoinD) -> Result<(
             }
         }
 
-        bitcoind.createwallet_startup(bitcoind_wallet_path, true)?;
-        log::info!("Importing descriptors to bitcoind watchonly wallet.");
-
-        // Now, import descriptors.
-        // In theory, we could just import the vault (deposit) descriptor expressed using xpubs, give a
-        // range to bitcoind as the gap limit, and be fine.
-        // Unfortunately we cannot just import descriptors as is, since bitcoind does not support
-        // Miniscript ones yet. Worse, we actually need to derive them to pass them to bitcoind since
-        // the vault one (which we are interested about) won't be expressed with a `multi()` statement (
-        // currently supported by bitcoind) if there are more than 15 stakeholders.
-        // Therefore, we derive [max index] `addr()` descriptors to import into bitcoind, and handle
-        // the derivation index mess ourselves :'(
-        let addresses: Vec<_> = revaultd
-            .all_deposit_addresses()
-            .into_iter()
-            .map(|a| bitcoind.addr_descriptor(&a))
-            .collect::<Result<Vec<_>, _>>()?;
-        log::trace!("Importing deposit descriptors '{:?}'", &addresses);
-        bitcoind.startup_import_deposit_descriptors(addresses, wallet.timestamp, fresh_wallet)?;
-
-        // As a consequence, we don't have enough information to opportunistically import a
-        // descriptor at the reception of a deposit anymore. Thus we need to blindly import *both*
-        // deposit and unvault descriptors..
-        // FIXME: maybe we actually have, with the derivation_index_map ?
-        let addresses: Vec<_> = revaultd
-            .all_unvault_addresses()
-            .into_iter()
-            .map(|a| bitcoind.addr_descriptor(&a))
-            .collect::<Result<Vec<_>, _>>()?;
-        log::trace!("Importing unvault descriptors '{:?}'", &addresses);
-        bitcoind.startup_import_unvault_descriptors(addresses, wallet.timestamp, fresh_wallet)?;
+        create_watchonly_wallet(revaultd, bitcoind, rescanner, fresh_wallet)?;
     }
 
     if let Some(cpfp_key) = revaultd.cpfp_key {
@@ -1792,7 +3380,15 @@ fn maybe_create_wallet(revaultd: &mut RevaultD, bitcoind: &BitcoinD) -> Result<(
                 .inner()
                 .to_string_with_secret(&keymap);
 
-            bitcoind.startup_import_cpfp_descriptor(cpfp_desc, wallet.timestamp, fresh_wallet)?;
+            if fresh_wallet {
+                bitcoind.startup_import_cpfp_descriptor(cpfp_desc, wallet.timestamp, true)?;
+            } else {
+                rescanner.queue(RescanImport {
+                    kind: ImportKind::Cpfp,
+                    descriptors: vec![cpfp_desc],
+                    timestamp: wallet.timestamp,
+                });
+            }
         }
     } else {
         log::info!("Not creating the CPFP wallet, as we don't have a CPFP key");
@@ -1800,7 +3396,13 @@ fn maybe_create_wallet(revaultd: &mut RevaultD, bitcoind: &BitcoinD) -> Result<(
     Ok(())
 }
 
-fn maybe_load_wallet(revaultd: &RevaultD, bitcoind: &BitcoinD) -> Result<(), BitcoindError> {
+// Make sure our watchonly wallet is loaded on bitcoind, and that it is actually ours. bitcoind may
+// have been restarted in the meantime and not have loaded it back.
+fn maybe_load_wallet(
+    revaultd: &mut RevaultD,
+    bitcoind: &BitcoinD,
+    rescanner: &Rescanner,
+) -> Result<(), BitcoindError> {
     let bitcoind_wallet_path = revaultd
         .watchonly_wallet_file()
         .expect("Wallet id is set at startup in setup_db()");
@@ -1813,37 +3415,122 @@ fn maybe_load_wallet(revaultd: &RevaultD, bitcoind: &BitcoinD) -> Result<(), Bit
     {
         0 => {
             log::info!("Loading our watchonly wallet '{}'.", bitcoind_wallet_path);
-            bitcoind.loadwallet_startup(bitcoind_wallet_path)?;
-            Ok(())
+            match bitcoind.loadwallet_startup(bitcoind_wallet_path.clone()) {
+                Ok(()) => {}
+                // We raced with someone else loading it, fine.
+                Err(e) if e.is_wallet_already_loaded() => {
+                    log::debug!("Watchonly wallet was loaded in the meantime.");
+                }
+                // The wallet file is gone. Create it again, and have bitcoind rescan from the
+                // date we first created it.
+                Err(BitcoindError::WalletNotLoaded(msg)) => {
+                    log::warn!(
+                        "Watchonly wallet '{}' could not be found ('{}'). Re-creating it, this \
+                         will trigger a rescan.",
+                        bitcoind_wallet_path,
+                        msg
+                    );
+                    create_watchonly_wallet(revaultd, bitcoind, rescanner, false)?;
+                }
+                Err(e) => return Err(e),
+            }
         }
         1 => {
             log::info!(
                 "Watchonly wallet '{}' already loaded.",
                 bitcoind_wallet_path
             );
-            Ok(())
         }
-        n => Err(BitcoindError::Custom(format!(
-            "{} watchonly wallet '{}' are loaded on bitcoind.",
-            n, bitcoind_wallet_path
-        ))),
+        n => {
+            return Err(BitcoindError::Custom(format!(
+                "{} watchonly wallet '{}' are loaded on bitcoind.",
+                n, bitcoind_wallet_path
+            )))
+        }
+    }
+
+    // Don't use a wallet that isn't ours. We always import the first deposit address, check it's
+    // watched. If we are still importing our descriptors, we created the wallet ourselves.
+    if rescanner.is_busy() {
+        return Ok(());
+    }
+    let marker_address = revaultd.vault_address(DerivationIndex::ZERO)?.to_string();
+    if !bitcoind.watchonly_wallet_has_address(&marker_address)? {
+        return Err(BitcoindError::Custom(format!(
+            "The wallet loaded at '{}' does not watch our deposit address '{}'. Refusing to use \
+             a wallet that is not ours.",
+            bitcoind_wallet_path, marker_address
+        )));
+    }
+
+    Ok(())
+}
+
+// Check whether bitcoind is rescanning the chain for our wallets, and how far it got. Returns
+// whether it is. It may be rescanning for imports made before we were restarted, too.
+fn update_rescan_progress(
+    bitcoind: &BitcoinD,
+    rescanner: &Rescanner,
+    rescan_progress: &Arc<RwLock<Option<f64>>>,
+    rescan_window: &Arc<RwLock<Option<RescanWindow>>>,
+) -> Result<bool, BitcoindError> {
+    if let Some(e) = rescanner.error() {
+        // bitcoind keeps rescanning even if we stopped waiting for its answer.
+        if e.is_transient() {
+            log::warn!("Error while importing descriptors: '{}'", e);
+        } else {
+            return Err(BitcoindError::Custom(format!(
+                "Error while importing descriptors: {}",
+                e
+            )));
+        }
+    }
+
+    let current = rescanner.current();
+    *rescan_window.write().unwrap() = current;
+    let cpfp_wallet = current.map(|window| window.kind) == Some(ImportKind::Cpfp);
+    let progress = bitcoind.rescan_progress(cpfp_wallet)?;
+    let rescanning = rescanner.is_busy() || progress.is_some();
+
+    let mut rescan_progress = rescan_progress.write().unwrap();
+    match (*rescan_progress, rescanning) {
+        (None, true) => log::info!("Bitcoind is rescanning the chain for our wallets."),
+        (Some(_), false) => log::info!("Bitcoind is done rescanning the chain."),
+        _ => {}
     }
+    *rescan_progress = if rescanning {
+        // It may be in between two imports, keep the last progress around.
+        Some(progress.or(*rescan_progress).unwrap_or(0.0))
+    } else {
+        None
+    };
+
+    if let Some(progress) = *rescan_progress {
+        sdnotify::status(&format!(
+            "Waiting for bitcoind to rescan the chain ({:.2}%)",
+            progress * 100.0
+        ));
+    }
+
+    Ok(rescanning)
 }
 
 // Update the progress made by bitcoind toward the tip.
 fn update_sync_status(
     revaultd: &Arc<RwLock<RevaultD>>,
     bitcoind: &Arc<RwLock<BitcoinD>>,
+    rescanner: &Rescanner,
     sync_progress: &Arc<RwLock<f64>>,
     now: Instant,
     last_poll: &mut Option<Instant>,
     sync_waittime: &mut Option<Duration>,
+    heartbeat: &Heartbeat,
 ) -> Result<(), BitcoindError> {
     // While waiting for bitcoind to be synced, guesstimate how much time of block
     // connection we have left to not harass it with `getblockchaininfo`.
     if let Some(last) = last_poll {
         if let Some(waittime) = sync_waittime {
-            if now.duration_since(*last) < *waittime {
+            if now.saturating_duration_since(*last) < *waittime {
                 return Ok(());
             }
         }
@@ -1858,28 +3545,42 @@ fn update_sync_status(
 
     // Ok. Sync, done. Now just be sure the watchonly wallet is properly loaded, and
     // to create it if it's first run.
-    if *sync_progress.read().unwrap() as u32 >= 1 {
+    let progress = *sync_progress.read().unwrap();
+    if progress as u32 >= 1 {
         let mut revaultd = revaultd.write().unwrap();
         let bitcoind = bitcoind.read().unwrap();
-        maybe_create_wallet(&mut revaultd, &bitcoind).map_err(|e| {
+        let _phase = heartbeat.long_phase("Loading the watchonly wallet");
+        maybe_create_wallet(&mut revaultd, &bitcoind, rescanner).map_err(|e| {
             BitcoindError::Custom(format!("Error while creating wallet: {}", e.to_string()))
         })?;
-        maybe_load_wallet(&revaultd, &bitcoind).map_err(|e| {
+        maybe_load_wallet(&mut revaultd, &bitcoind, rescanner).map_err(|e| {
             BitcoindError::Custom(format!("Error while loading wallet: {}", e.to_string()))
         })?;
 
         log::info!("bitcoind now synced.");
+    } else {
+        sdnotify::status(&format!(
+            "Waiting for bitcoind to synchronize ({:.2}%)",
+            progress * 100.0
+        ));
     }
 
     *last_poll = Some(now);
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn poller_main(
     mut revaultd: Arc<RwLock<RevaultD>>,
     bitcoind: Arc<RwLock<BitcoinD>>,
     sync_progress: Arc<RwLock<f64>>,
+    rescan_progress: Arc<RwLock<Option<f64>>>,
+    rescan_window: Arc<RwLock<Option<RescanWindow>>>,
+    reachable: Arc<AtomicBool>,
     shutdown: Arc<AtomicBool>,
+    audit_requests: Receiver<SyncSender<Result<WalletAudit, BitcoindError>>>,
+    statemachine: StateMachineSender,
+    heartbeat: Heartbeat,
 ) -> Result<(), BitcoindError> {
     let mut last_poll = None;
     let mut sync_waittime = None;
@@ -1887,46 +3588,516 @@ pub fn poller_main(
     let mut deposits_cache = populate_deposit_cache(&revaultd.read().unwrap())?;
     // Same for the unvaults
     let mut unvaults_cache = populate_unvaults_cache(&revaultd.read().unwrap())?;
+    // The unconfirmed deposits whose transaction left the mempool, and for how many polls.
+    let mut evicted_deposits = HashMap::new();
     // When bitcoind is synced, we poll each 30s. On regtest we speed it up for testing.
     let poll_interval = revaultd.read().unwrap().bitcoind_config.poll_interval_secs;
+    let clock = revaultd.read().unwrap().clock.clone();
+    let db_path = revaultd.read().unwrap().db_file();
+    let hooks = {
+        let revaultd = revaultd.read().unwrap();
+        revaultd.notify_command.clone().map(|command| {
+            HookRunner::start(
+                command,
+                revaultd.notify_statuses.clone(),
+                revaultd.notify_timeout,
+            )
+        })
+    };
+    let alerter = {
+        let revaultd = revaultd.read().unwrap();
+        if revaultd.alert_tiers.is_empty() {
+            None
+        } else {
+            Some(Alerter::start(
+                revaultd.alert_tiers.clone(),
+                revaultd.webhook_timeout,
+            ))
+        }
+    };
+    // Whether we caught up with what happened while we were down, that is a poll didn't find
+    // anything new. Until then, status transitions are not notified.
+    let mut reconciled = false;
+    let revocation_check_interval = revaultd.read().unwrap().revocation_check_interval;
+    let mut last_revocation_check = db_last_revocation_check(&db_path)?.map(u64::from);
+    let wallet_audit_interval = revaultd.read().unwrap().wallet_audit_interval;
+    let mut last_wallet_audit: Option<Instant> = None;
+    let relay_floor_check_interval = revaultd.read().unwrap().relay_floor_check_interval;
+    let mut last_relay_floor_check: Option<Instant> = None;
+    // Whether we told the service manager we are up and running since our last hiccup.
+    let mut status_running = false;
+    // Whether we replayed the blocks that were mined while we were down.
+    let mut replayed = false;
+    // How the space left on disk compared to the thresholds at the last poll.
+    let mut disk_space_level = None;
+    // The imports that make bitcoind rescan the chain are performed on a separate thread, as
+    // they may take hours.
+    let rescanner = Rescanner::start(bitcoind.read().unwrap().clone());
+    // Whether bitcoind was rescanning the chain for our wallets at the last poll. Until it's done,
+    // the wallets don't know about all our coins so we don't update our vaults from them.
+    let mut rescanning = false;
 
     while !shutdown.load(Ordering::Relaxed) {
-        let now = Instant::now();
+        heartbeat.beat();
+        let now = clock.now();
+
+        // The log levels may have been changed for a limited time with 'setloglevel'
+        if revaultd
+            .read()
+            .unwrap()
+            .log_levels
+            .expire(clock.unix_timestamp())
+        {
+            log::info!("Restored the configured log levels");
+        }
 
-        if (*sync_progress.read().unwrap() as u32) < 1 {
+        if reconciled {
+            process_status_changes(&db_path, hooks.as_ref(), alerter.as_ref())?;
+        }
+        process_emergency_runs(&db_path, hooks.as_ref())?;
+
+        let synced = (*sync_progress.read().unwrap() as u32) >= 1;
+        // The addresses being imported would be reported missing.
+        let importing = !synced || rescanning || rescanner.is_busy();
+        while let Ok(resp_tx) = audit_requests.try_recv() {
+            let res = if importing {
+                Err(BitcoindError::Custom(
+                    "bitcoind is not done synchronizing or rescanning the chain for our wallets, \
+                     try again later."
+                        .to_string(),
+                ))
+            } else {
+                audit_wallet(&revaultd, &bitcoind.read().unwrap(), &rescanner)
+            };
+            if res.is_ok() {
+                last_wallet_audit = Some(now);
+            }
+            // The requester may have given up.
+            let _ = resp_tx.send(res);
+        }
+
+        let caches_len = (deposits_cache.len(), unvaults_cache.len());
+        let res = if !synced {
             update_sync_status(
                 &revaultd,
                 &bitcoind,
+                &rescanner,
                 &sync_progress,
                 now,
                 &mut last_poll,
                 &mut sync_waittime,
-            )?;
-            continue;
-        }
+                &heartbeat,
+            )
+        } else {
+            if let Some(last_poll) = last_poll {
+                if now.saturating_duration_since(last_poll) < poll_interval {
+                    thread::sleep(Duration::from_millis(500));
+                    continue;
+                }
+            }
 
-        if let Some(last_poll) = last_poll {
-            if now.duration_since(last_poll) < poll_interval {
-                thread::sleep(Duration::from_millis(500));
-                continue;
+            last_poll = Some(now);
+            let rescan = update_rescan_progress(
+                &bitcoind.read().unwrap(),
+                &rescanner,
+                &rescan_progress,
+                &rescan_window,
+            );
+            rescan.and_then(|r| {
+                rescanning = r;
+                if rescanning {
+                    return Ok(());
+                }
+
+                let bitcoind = bitcoind.read().unwrap();
+                if !replayed {
+                    replay_missed_blocks(
+                        &mut revaultd,
+                        &bitcoind,
+                        &statemachine,
+                        &mut deposits_cache,
+                        &mut unvaults_cache,
+                    )?;
+                    replayed = true;
+                }
+                let previous_tip = update_tip(
+                    &mut revaultd,
+                    &bitcoind,
+                    &statemachine,
+                    &mut deposits_cache,
+                    &mut unvaults_cache,
+                )?;
+                update_utxos(
+                    &mut revaultd,
+                    &bitcoind,
+                    &statemachine,
+                    &mut deposits_cache,
+                    &mut unvaults_cache,
+                    &mut evicted_deposits,
+                    &previous_tip,
+                )?;
+                extend_deposit_window(&mut revaultd, &bitcoind, &rescanner)
+            })
+        };
+        check_disk_space(&revaultd.read().unwrap(), &mut disk_space_level);
+
+        match res {
+            Ok(()) => {
+                if !reachable.swap(true, Ordering::Relaxed) {
+                    log::info!("Bitcoind is reachable again.");
+                }
+                if synced && !rescanning && !status_running {
+                    sdnotify::status("Running");
+                    status_running = true;
+                }
+                if synced && !rescanning && !reconciled {
+                    let changes = process_status_changes(&db_path, None, None)?;
+                    reconciled =
+                        changes == 0 && caches_len == (deposits_cache.len(), unvaults_cache.len());
+                    if reconciled {
+                        log::debug!("Caught up with the chain, now notifying status transitions.");
+                    }
+                }
+                // Don't check the revocation transactions of vaults we are not up to date with,
+                // they may have been spent in the meantime.
+                let timestamp = clock.unix_timestamp();
+                let revocation_check_due = revocation_check_interval.map(|interval| {
+                    last_revocation_check
+                        .map(|last| timestamp >= last + interval.as_secs())
+                        .unwrap_or(true)
+                });
+                if reconciled && revocation_check_due == Some(true) {
+                    match check_revocations(
+                        &revaultd.read().unwrap(),
+                        &bitcoind.read().unwrap(),
+                        hooks.as_ref(),
+                    ) {
+                        Ok(()) => last_revocation_check = Some(timestamp),
+                        // Try again at next poll
+                        Err(e) if e.is_transient() => {
+                            log::debug!("Could not check revocation transactions: '{}'", e)
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                // Only once we are up to date with the chain, the Unvaults may have been broadcast
+                // while we were down.
+                if reconciled {
+                    expire_stale_spends(&revaultd.read().unwrap())?;
+                }
+                let wallet_audit_due = wallet_audit_interval.map(|interval| {
+                    last_wallet_audit
+                        .map(|last| now.saturating_duration_since(last) >= interval)
+                        .unwrap_or(true)
+                });
+                if reconciled
+                    && !rescanning
+                    && !rescanner.is_busy()
+                    && wallet_audit_due == Some(true)
+                {
+                    match audit_wallet(&revaultd, &bitcoind.read().unwrap(), &rescanner) {
+                        Ok(_) => last_wallet_audit = Some(now),
+                        // Try again at next poll
+                        Err(e) if e.is_transient() => {
+                            log::debug!("Could not audit the watchonly wallet: '{}'", e)
+                        }
+                        Err(e) => {
+                            log::error!("Error auditing the watchonly wallet: '{}'", e);
+                            last_wallet_audit = Some(now);
+                        }
+                    }
+                }
+                // Only for the vaults we are up to date with, they may not be secured anymore.
+                let relay_floor_check_due = relay_floor_check_interval.map(|interval| {
+                    last_relay_floor_check
+                        .map(|last| now.saturating_duration_since(last) >= interval)
+                        .unwrap_or(true)
+                });
+                if reconciled && relay_floor_check_due == Some(true) {
+                    match check_relay_floor(&revaultd, &bitcoind.read().unwrap()) {
+                        Ok(_) => last_relay_floor_check = Some(now),
+                        // Try again at next poll
+                        Err(e) if e.is_transient() => {
+                            log::debug!("Could not check the relay floor of bitcoind: '{}'", e)
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
             }
-        }
+            // Don't exit if bitcoind is temporarily unavailable (it may be reindexing, or
+            // restarting). Wait for it to come back, meanwhile the daemon stays up.
+            Err(e) if e.is_transient() => {
+                if reachable.swap(false, Ordering::Relaxed) {
+                    log_event!(
+                        log::Level::Error,
+                        "connection_failure",
+                        peer = "bitcoind",
+                        error = e;
+                        "Could not reach bitcoind: '{}'. Will keep trying.",
+                        e
+                    );
+                    sdnotify::status("Could not reach bitcoind, retrying");
+                    status_running = false;
+                }
+                last_poll = Some(now);
+                thread::sleep(poll_interval.min(Duration::from_secs(5)));
 
-        last_poll = Some(now);
-        let previous_tip = update_tip(
-            &mut revaultd,
-            &bitcoind.read().unwrap(),
-            &mut deposits_cache,
-            &mut unvaults_cache,
-        )?;
-        update_utxos(
-            &mut revaultd,
-            &bitcoind.read().unwrap(),
-            &mut deposits_cache,
-            &mut unvaults_cache,
-            &previous_tip,
-        )?;
+                // If bitcoind restarted, it generated a new cookie.
+                if let Err(e) = bitcoind.read().unwrap().reconnect() {
+                    log::debug!("Could not reconnect to bitcoind: '{}'", e);
+                }
+
+                // We may have been interrupted while updating our state, start again from what
+                // is in database.
+                deposits_cache = populate_deposit_cache(&revaultd.read().unwrap())?;
+                unvaults_cache = populate_unvaults_cache(&revaultd.read().unwrap())?;
+            }
+            // bitcoind was restarted and did not load back our wallet.
+            Err(BitcoindError::WalletNotLoaded(msg)) => {
+                log::warn!("Our watchonly wallet is not loaded anymore: '{}'", msg);
+                let _phase = heartbeat.long_phase("Loading back the watchonly wallet");
+                status_running = false;
+                maybe_load_wallet(
+                    &mut revaultd.write().unwrap(),
+                    &bitcoind.read().unwrap(),
+                    &rescanner,
+                )?;
+                deposits_cache = populate_deposit_cache(&revaultd.read().unwrap())?;
+                unvaults_cache = populate_unvaults_cache(&revaultd.read().unwrap())?;
+            }
+            Err(e) => return Err(e),
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::replay_schedule;
+    use crate::{
+        bitcoind::interface::{ListedTransaction, WalletTxsSince},
+        database::{
+            actions::{db_clear_replayed_txs, db_record_replayed_tx, setup_db},
+            interface::{db_replay_window, db_vault_by_deposit},
+        },
+        derivation::DerivationIndex,
+        revaultd::{BlockchainTip, VaultStatus},
+        statemachine::StateMachine,
+        threadmessages::{ChainEvent, ConfirmedTx},
+        utils::test_utils::{dummy_revaultd, test_datadir, MockBitcoindThread, UserRole},
+    };
+    use revault_tx::bitcoin::{hashes::Hash, Amount, BlockHash, OutPoint, Txid};
+
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fs,
+        sync::{Arc, RwLock},
+    };
+
+    use serde_json::{json, Value as Json};
+
+    // The hash of the block at this height, on the chain we follow or on a fork of it
+    fn block_hash(height: u32, stale: bool) -> BlockHash {
+        BlockHash::hash(&[height as u8, stale as u8])
+    }
+
+    fn active_hash(height: u32) -> Result<BlockHash, ()> {
+        Ok(block_hash(height, false))
+    }
+
+    fn deposit_txid(i: u8) -> Txid {
+        Txid::hash(&[i])
+    }
+
+    // A 'listsinceblock' entry for an output of this transaction, as confirmed at this height
+    fn lsb_entry(txid: &Txid, vout: u32, confirmed: Option<(u32, u64, bool)>) -> Json {
+        let mut entry = json!({
+            "address": "bcrt1qrht43q4xt59vr9jytlmckgde6rcvhxcp392kx9",
+            "category": "receive",
+            "amount": 0.1,
+            "txid": txid.to_string(),
+            "vout": vout,
+        });
+        if let Some((height, index, stale)) = confirmed {
+            entry["blockheight"] = json!(height);
+            entry["blockindex"] = json!(index);
+            entry["blockhash"] = json!(block_hash(height, stale).to_string());
+        }
+        entry
+    }
+
+    // The events the poller emits when replaying these deposit transactions
+    fn replay_events(blocks: &BTreeMap<u32, super::ReplayedBlock>) -> Vec<Vec<ChainEvent>> {
+        blocks
+            .iter()
+            .map(|(height, block)| {
+                let mut events = vec![ChainEvent::TipChanged(BlockchainTip {
+                    height: *height,
+                    hash: block_hash(*height, false),
+                })];
+                for txid in &block.txids {
+                    events.push(ChainEvent::DepositDetected {
+                        outpoint: OutPoint::new(*txid, 0),
+                        amount: Amount::from_sat(10_000_000),
+                        derivation_index: DerivationIndex::new(3).unwrap(),
+                        funding_inputs: vec![],
+                        ancestry: None,
+                        coinbase_height: None,
+                    });
+                    events.push(ChainEvent::TxConfirmed {
+                        kind: ConfirmedTx::Deposit {
+                            vout: 0,
+                            blockheight: *height,
+                            blocktime: 1_600_000_000 + height,
+                        },
+                        txid: *txid,
+                    });
+                }
+                events
+            })
+            .collect()
+    }
+
+    // Deposits confirmed while we were down and across a reorg: one confirmed on the chain we
+    // follow and listed once per output, one confirmed on a fork then again on our chain, one
+    // only confirmed on the fork and one that was disconnected then confirmed again.
+    fn reorged_payload() -> Json {
+        json!({
+            "transactions": [
+                lsb_entry(&deposit_txid(0), 0, Some((101, 1, false))),
+                lsb_entry(&deposit_txid(0), 1, Some((101, 1, false))),
+                lsb_entry(&deposit_txid(1), 0, Some((103, 2, true))),
+                lsb_entry(&deposit_txid(1), 0, Some((104, 1, false))),
+                lsb_entry(&deposit_txid(2), 0, Some((103, 1, true))),
+                lsb_entry(&deposit_txid(3), 0, Some((102, 4, false))),
+                lsb_entry(&deposit_txid(4), 0, None),
+            ],
+            "removed": [
+                lsb_entry(&deposit_txid(3), 0, Some((102, 1, true))),
+                lsb_entry(&deposit_txid(3), 0, Some((102, 1, true))),
+            ],
+            "lastblock": block_hash(106, false).to_string(),
+        })
+    }
+
+    #[test]
+    fn replay_resolve_listsinceblock() {
+        let since = block_hash(100, false);
+        let txs = WalletTxsSince::from_listsinceblock(&reorged_payload(), &since);
+        assert_eq!(txs.confirmed.len(), 6);
+        assert_eq!(txs.removed, vec![deposit_txid(3), deposit_txid(3)]);
+
+        // Each transaction is kept once, in the block it was confirmed in on our chain. The one
+        // only confirmed on the fork is reported as removed, the one confirmed again isn't.
+        let txs = txs.resolve(active_hash).unwrap();
+        let listed = |txid: Txid, blockheight: u32, blockindex: u64| ListedTransaction {
+            blockheight,
+            blockindex,
+            blockhash: block_hash(blockheight, false),
+            txid,
+        };
+        assert_eq!(
+            txs.confirmed,
+            vec![
+                listed(deposit_txid(0), 101, 1),
+                listed(deposit_txid(3), 102, 4),
+                listed(deposit_txid(1), 104, 1),
+            ]
+        );
+        assert_eq!(txs.removed, vec![deposit_txid(2)]);
+
+        // Nothing is removed when listing since a block of our chain
+        let payload = json!({
+            "transactions": [
+                lsb_entry(&deposit_txid(0), 0, Some((101, 1, false))),
+                lsb_entry(&deposit_txid(0), 0, Some((101, 1, false))),
+            ],
+            "lastblock": block_hash(101, false).to_string(),
+        });
+        let txs = WalletTxsSince::from_listsinceblock(&payload, &since)
+            .resolve(active_hash)
+            .unwrap();
+        assert_eq!(txs.confirmed, vec![listed(deposit_txid(0), 101, 1)]);
+        assert!(txs.removed.is_empty());
+    }
+
+    #[test]
+    fn replay_duplicated_transactions() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let mut state_machine = StateMachine::new(
+            Arc::new(RwLock::new(revaultd)),
+            MockBitcoindThread::new(HashMap::new()),
+        );
+        let window = BlockchainTip {
+            height: 100,
+            hash: block_hash(100, false),
+        };
+        let txs = WalletTxsSince::from_listsinceblock(&reorged_payload(), &window.hash)
+            .resolve(active_hash)
+            .unwrap();
+
+        // We get interrupted after processing the first block. The block at the tip is left to
+        // the regular poll.
+        let blocks = replay_schedule(&txs.confirmed, &HashSet::new(), 105);
+        let events = replay_events(&blocks);
+        assert_eq!(
+            events.iter().map(|e| e.len()).collect::<Vec<_>>(),
+            [3, 3, 3]
+        );
+        state_machine.process_events(events[0].clone()).unwrap();
+        for txid in &blocks[&101].txids {
+            db_record_replayed_tx(&db_path, &window, txid, &block_hash(101, false)).unwrap();
+        }
+
+        // The replay is resumed without processing the first block's transaction again, even if
+        // bitcoind lists it again.
+        let (resumed_window, processed) = db_replay_window(&db_path).unwrap().unwrap();
+        assert_eq!(resumed_window, window);
+        assert_eq!(
+            processed,
+            vec![(deposit_txid(0), block_hash(101, false))]
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+        let blocks = replay_schedule(&txs.confirmed, &processed, 105);
+        assert_eq!(blocks.keys().copied().collect::<Vec<_>>(), [102, 104]);
+        let events = replay_events(&blocks);
+        assert_eq!(events.iter().flatten().count(), 6);
+        for (events, (height, block)) in events.into_iter().zip(blocks.iter()) {
+            state_machine.process_events(events).unwrap();
+            for txid in &block.txids {
+                db_record_replayed_tx(&db_path, &window, txid, &block_hash(*height, false))
+                    .unwrap();
+            }
+        }
+
+        // All the deposits but the one that only confirmed on the fork are registered once, at
+        // the height they confirmed at on our chain.
+        for (i, height) in [(0, 101), (3, 102), (1, 104)].iter() {
+            let db_vault = db_vault_by_deposit(&db_path, &OutPoint::new(deposit_txid(*i), 0))
+                .unwrap()
+                .unwrap();
+            assert_eq!(db_vault.status, VaultStatus::Funded);
+            assert_eq!(db_vault.blockheight, *height);
+        }
+        for i in [2, 4].iter() {
+            assert!(
+                db_vault_by_deposit(&db_path, &OutPoint::new(deposit_txid(*i), 0))
+                    .unwrap()
+                    .is_none()
+            );
+        }
+
+        // Nothing is left to replay, and the window is forgotten once done
+        let (_, processed) = db_replay_window(&db_path).unwrap().unwrap();
+        assert!(replay_schedule(&txs.confirmed, &processed, 105).is_empty());
+        db_clear_replayed_txs(&db_path).unwrap();
+        assert!(db_replay_window(&db_path).unwrap().is_none());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
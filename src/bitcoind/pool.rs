@@ -0,0 +1,500 @@
+//! A bounded pool of connections to one of bitcoind's RPC endpoints, shared by all the threads
+//! talking to it. Connections are reused instead of each caller creating its own, checked
+//! before being handed out again after some time unused, and replaced if they broke.
+//!
+//! Calls that may take hours (the imports triggering a rescan) get a dedicated connection with
+//! a longer timeout, that isn't counted against the bound so they don't starve the other
+//! callers.
+
+use crate::{bitcoind::BitcoindError, clock::Clock};
+
+use std::{
+    ops::Deref,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use jsonrpc::client::Client;
+use serde::{Deserialize, Serialize};
+
+/// After how long unused a connection is checked before being handed out again.
+pub const IDLE_CHECK_AFTER: Duration = Duration::from_secs(60);
+
+/// Creates a client to the endpoint, with the given timeout.
+pub type Connector = Arc<dyn Fn(Duration) -> Result<Client, BitcoindError> + Send + Sync>;
+
+/// How much a pool of connections is used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolStats {
+    /// The endpoint the connections are to
+    pub name: String,
+    pub max_size: usize,
+    pub in_use: usize,
+    pub idle: usize,
+    /// Callers currently waiting for a connection to be given back
+    pub waiting: usize,
+    /// Connections dedicated to long calls, not counted in `max_size`
+    pub long_lived: usize,
+    /// How many times a connection was handed out
+    pub checkouts: u64,
+    /// How many of these had to wait for a connection to be given back
+    pub waits: u64,
+    pub total_wait_ms: u64,
+    pub max_wait_ms: u64,
+    /// How many broken connections were replaced
+    pub replaced: u64,
+}
+
+struct IdleClient {
+    client: Client,
+    since: Instant,
+}
+
+struct PoolState {
+    connect: Connector,
+    // Bumped when the connector changes, connections created by an older one are dropped once
+    // given back.
+    generation: u64,
+    idle: Vec<IdleClient>,
+    in_use: usize,
+    waiting: usize,
+    long_lived: usize,
+    checkouts: u64,
+    waits: u64,
+    total_wait: Duration,
+    max_wait: Duration,
+    replaced: u64,
+}
+
+pub struct ClientPool {
+    name: &'static str,
+    max_size: usize,
+    timeout: Duration,
+    long_lived_timeout: Duration,
+    clock: Arc<dyn Clock>,
+    state: Mutex<PoolState>,
+    given_back: Condvar,
+}
+
+// A connection is healthy if bitcoind answers through it, even with an error (for instance
+// while it's warming up).
+fn is_healthy(client: &Client) -> bool {
+    client
+        .send_request(client.build_request("getblockcount", &[]))
+        .is_ok()
+}
+
+impl ClientPool {
+    /// Create a pool of at most `max_size` connections, starting with one to make sure we can.
+    pub fn new(
+        name: &'static str,
+        max_size: usize,
+        timeout: Duration,
+        long_lived_timeout: Duration,
+        clock: Arc<dyn Clock>,
+        connect: Connector,
+    ) -> Result<ClientPool, BitcoindError> {
+        assert!(max_size > 0, "Config checked it's at least 1");
+        let idle = vec![IdleClient {
+            client: connect(timeout)?,
+            since: clock.now(),
+        }];
+
+        Ok(ClientPool {
+            name,
+            max_size,
+            timeout,
+            long_lived_timeout,
+            clock,
+            state: Mutex::new(PoolState {
+                connect,
+                generation: 0,
+                idle,
+                in_use: 0,
+                waiting: 0,
+                long_lived: 0,
+                checkouts: 0,
+                waits: 0,
+                total_wait: Duration::from_secs(0),
+                max_wait: Duration::from_secs(0),
+                replaced: 0,
+            }),
+            given_back: Condvar::new(),
+        })
+    }
+
+    /// Get a connection, waiting for one to be given back if they are all in use. It's given
+    /// back to the pool once dropped.
+    pub fn get(&self) -> Result<PooledClient<'_>, BitcoindError> {
+        let start = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        let mut waited = false;
+        while state.idle.is_empty() && state.in_use >= self.max_size {
+            if !waited {
+                waited = true;
+                state.waiting += 1;
+            }
+            state = self.given_back.wait(state).unwrap();
+        }
+
+        state.in_use += 1;
+        state.checkouts += 1;
+        if waited {
+            let wait = self.clock.elapsed(start);
+            state.waiting -= 1;
+            state.waits += 1;
+            state.total_wait += wait;
+            state.max_wait = state.max_wait.max(wait);
+        }
+        // The most recently used connection is the most likely to still be alive.
+        let idle = state.idle.pop();
+        let (connect, generation) = (state.connect.clone(), state.generation);
+        drop(state);
+
+        // The slot is released on drop even if we fail to create a connection.
+        let mut pooled = PooledClient {
+            pool: self,
+            client: None,
+            generation,
+            long_lived: false,
+            discarded: false,
+        };
+        let client = match idle {
+            Some(idle) if self.clock.elapsed(idle.since) < IDLE_CHECK_AFTER => idle.client,
+            Some(idle) if is_healthy(&idle.client) => idle.client,
+            Some(_) => {
+                log::debug!(
+                    "Connection to bitcoind's {} endpoint is broken, replacing it.",
+                    self.name
+                );
+                self.state.lock().unwrap().replaced += 1;
+                connect(self.timeout)?
+            }
+            None => connect(self.timeout)?,
+        };
+        pooled.client = Some(client);
+
+        Ok(pooled)
+    }
+
+    /// Get a dedicated connection for a call that may take a long time. It isn't counted against
+    /// the size of the pool, and is closed once dropped.
+    pub fn get_long_lived(&self) -> Result<PooledClient<'_>, BitcoindError> {
+        let (connect, generation) = {
+            let mut state = self.state.lock().unwrap();
+            state.long_lived += 1;
+            state.checkouts += 1;
+            (state.connect.clone(), state.generation)
+        };
+
+        let mut pooled = PooledClient {
+            pool: self,
+            client: None,
+            generation,
+            long_lived: true,
+            discarded: false,
+        };
+        pooled.client = Some(connect(self.long_lived_timeout)?);
+
+        Ok(pooled)
+    }
+
+    /// Create the connections with this connector from now on, for instance with a new cookie.
+    /// The current connections are closed once not in use anymore.
+    pub fn reset(&self, connect: Connector) {
+        let mut state = self.state.lock().unwrap();
+        state.connect = connect;
+        state.generation += 1;
+        state.idle.clear();
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.state.lock().unwrap();
+        PoolStats {
+            name: self.name.to_string(),
+            max_size: self.max_size,
+            in_use: state.in_use,
+            idle: state.idle.len(),
+            waiting: state.waiting,
+            long_lived: state.long_lived,
+            checkouts: state.checkouts,
+            waits: state.waits,
+            total_wait_ms: state.total_wait.as_millis() as u64,
+            max_wait_ms: state.max_wait.as_millis() as u64,
+            replaced: state.replaced,
+        }
+    }
+
+    fn give_back(&self, pooled: &mut PooledClient) {
+        let mut state = self.state.lock().unwrap();
+        if pooled.long_lived {
+            state.long_lived -= 1;
+            return;
+        }
+
+        state.in_use -= 1;
+        match pooled.client.take() {
+            Some(_) if pooled.discarded => state.replaced += 1,
+            Some(client) if pooled.generation == state.generation => state.idle.push(IdleClient {
+                client,
+                since: self.clock.now(),
+            }),
+            _ => {}
+        }
+        self.given_back.notify_one();
+    }
+}
+
+/// A connection borrowed from a [ClientPool].
+pub struct PooledClient<'a> {
+    pool: &'a ClientPool,
+    // Only None while being created
+    client: Option<Client>,
+    generation: u64,
+    long_lived: bool,
+    discarded: bool,
+}
+
+impl PooledClient<'_> {
+    /// Don't give this connection back to the pool, for instance because bitcoind could not be
+    /// reached through it. The next caller will get a new one.
+    pub fn discard(&mut self) {
+        self.discarded = true;
+    }
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client
+            .as_ref()
+            .expect("The client is set once created")
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        let pool = self.pool;
+        pool.give_back(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientPool, Connector, IDLE_CHECK_AFTER};
+    use crate::{bitcoind::BitcoindError, clock::test_utils::MockClock};
+
+    use std::{
+        fmt, io,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use jsonrpc::{
+        client::{Client, Transport},
+        simple_http::Error as HttpError,
+        Request, Response,
+    };
+
+    // A fake bitcoind that can be made unreachable, and counts the connections open to it.
+    #[derive(Default)]
+    struct MockBitcoind {
+        down: AtomicBool,
+        open: AtomicUsize,
+        created: AtomicUsize,
+        requests: AtomicUsize,
+    }
+
+    struct MockTransport(Arc<MockBitcoind>);
+
+    impl Drop for MockTransport {
+        fn drop(&mut self) {
+            self.0.open.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn send_request(&self, _: Request) -> Result<Response, jsonrpc::Error> {
+            self.0.requests.fetch_add(1, Ordering::SeqCst);
+            if self.0.down.load(Ordering::SeqCst) {
+                return Err(jsonrpc::Error::Transport(Box::new(HttpError::SocketError(
+                    io::Error::from(io::ErrorKind::ConnectionRefused),
+                ))));
+            }
+            Ok(serde_json::from_str("{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":100}").unwrap())
+        }
+
+        fn send_batch(&self, _: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+            unimplemented!()
+        }
+
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "mock")
+        }
+    }
+
+    fn mock_pool(max_size: usize) -> (Arc<ClientPool>, Arc<MockBitcoind>, Arc<MockClock>) {
+        let bitcoind = Arc::new(MockBitcoind::default());
+        let clock = Arc::new(MockClock::new(1_600_000_000));
+        let connect: Connector = Arc::new({
+            let bitcoind = bitcoind.clone();
+            move |_| {
+                bitcoind.open.fetch_add(1, Ordering::SeqCst);
+                bitcoind.created.fetch_add(1, Ordering::SeqCst);
+                Ok(Client::with_transport(MockTransport(bitcoind.clone())))
+            }
+        });
+        let pool = ClientPool::new(
+            "node",
+            max_size,
+            Duration::from_secs(60),
+            Duration::from_secs(3600),
+            clock.clone(),
+            connect,
+        )
+        .unwrap();
+
+        (Arc::new(pool), bitcoind, clock)
+    }
+
+    fn getblockcount(client: &Client) -> Result<serde_json::Value, BitcoindError> {
+        client
+            .send_request(client.build_request("getblockcount", &[]))
+            .map_err(BitcoindError::from)?
+            .result()
+            .map_err(BitcoindError::from)
+    }
+
+    #[test]
+    fn pool_reuses_connections() {
+        let (pool, bitcoind, _) = mock_pool(2);
+
+        // Sequential calls always reuse the same connection
+        for _ in 0..10 {
+            getblockcount(&pool.get().unwrap()).unwrap();
+        }
+        assert_eq!(bitcoind.created.load(Ordering::SeqCst), 1);
+
+        // Concurrent ones open more, up to the bound
+        let (a, b) = (pool.get().unwrap(), pool.get().unwrap());
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 2);
+        let stats = pool.stats();
+        assert_eq!((stats.in_use, stats.idle, stats.checkouts), (2, 0, 12));
+
+        // Past the bound we wait for one to be given back
+        let waiter = thread::spawn({
+            let pool = pool.clone();
+            move || getblockcount(&pool.get().unwrap()).unwrap()
+        });
+        while pool.stats().waiting == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(pool.stats().checkouts, 12);
+        drop(a);
+        waiter.join().unwrap();
+        drop(b);
+
+        let stats = pool.stats();
+        assert_eq!((stats.waits, stats.waiting, stats.checkouts), (1, 0, 13));
+        assert_eq!((stats.in_use, stats.idle), (0, 2));
+        assert_eq!(bitcoind.created.load(Ordering::SeqCst), 2);
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn pool_replaces_broken_connections() {
+        let (pool, bitcoind, clock) = mock_pool(2);
+
+        // bitcoind goes away, the caller discards the connection it couldn't reach it through
+        bitcoind.down.store(true, Ordering::SeqCst);
+        {
+            let mut client = pool.get().unwrap();
+            assert!(getblockcount(&client).unwrap_err().is_transient());
+            client.discard();
+        }
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 0);
+        assert_eq!(pool.stats().replaced, 1);
+
+        // It's back, we get a new connection
+        bitcoind.down.store(false, Ordering::SeqCst);
+        getblockcount(&pool.get().unwrap()).unwrap();
+        assert_eq!(bitcoind.created.load(Ordering::SeqCst), 2);
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 1);
+
+        // A recently used connection is handed out without being checked..
+        let requests = bitcoind.requests.load(Ordering::SeqCst);
+        drop(pool.get().unwrap());
+        assert_eq!(bitcoind.requests.load(Ordering::SeqCst), requests);
+
+        // .. But one unused for a while is, and replaced if bitcoind can't be reached through it.
+        clock.advance(IDLE_CHECK_AFTER);
+        drop(pool.get().unwrap());
+        assert_eq!(bitcoind.requests.load(Ordering::SeqCst), requests + 1);
+        assert_eq!(bitcoind.created.load(Ordering::SeqCst), 2);
+        clock.advance(IDLE_CHECK_AFTER);
+        bitcoind.down.store(true, Ordering::SeqCst);
+        let client = pool.get().unwrap();
+        assert_eq!(bitcoind.created.load(Ordering::SeqCst), 3);
+        assert_eq!(pool.stats().replaced, 2);
+        bitcoind.down.store(false, Ordering::SeqCst);
+        getblockcount(&client).unwrap();
+        drop(client);
+
+        // No connection leaked along the way
+        let stats = pool.stats();
+        assert_eq!((stats.in_use, stats.idle), (0, 1));
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn pool_long_lived_connections() {
+        let (pool, bitcoind, _) = mock_pool(1);
+
+        // A long call doesn't prevent the others from getting a connection
+        let rescan = pool.get_long_lived().unwrap();
+        let client = pool.get().unwrap();
+        let stats = pool.stats();
+        assert_eq!((stats.long_lived, stats.in_use, stats.waits), (1, 1, 0));
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 2);
+
+        // It's closed once done instead of being kept around
+        drop(rescan);
+        drop(client);
+        let stats = pool.stats();
+        assert_eq!((stats.long_lived, stats.in_use, stats.idle), (0, 0, 1));
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn pool_reset() {
+        let (pool, bitcoind, _) = mock_pool(2);
+        let (a, b) = (pool.get().unwrap(), pool.get().unwrap());
+        drop(a);
+        assert_eq!(pool.stats().idle, 1);
+
+        // Connections created with the previous connector are closed, and not given back
+        let connect: Connector = Arc::new({
+            let bitcoind = bitcoind.clone();
+            move |_| {
+                bitcoind.open.fetch_add(1, Ordering::SeqCst);
+                bitcoind.created.fetch_add(1, Ordering::SeqCst);
+                Ok(Client::with_transport(MockTransport(bitcoind.clone())))
+            }
+        });
+        pool.reset(connect);
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 1);
+        drop(b);
+        let stats = pool.stats();
+        assert_eq!((stats.in_use, stats.idle), (0, 0));
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 0);
+
+        getblockcount(&pool.get().unwrap()).unwrap();
+        assert_eq!(bitcoind.created.load(Ordering::SeqCst), 3);
+        assert_eq!(bitcoind.open.load(Ordering::SeqCst), 1);
+    }
+}
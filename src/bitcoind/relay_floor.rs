@@ -0,0 +1,186 @@
+//! Our presigned transactions are only of any use if bitcoind relays them. A node configured
+//! with a high `minrelaytxfee`, or with a small `maxmempool` (hence a mempool minimum feerate
+//! rising quickly), won't relay those paying less. We periodically compare the feerate our
+//! presigned transactions got signed with to the floor bitcoind currently enforces.
+
+use crate::{
+    bitcoind::{interface::BitcoinD, BitcoindError},
+    database::interface::db_signed_feerates,
+    revaultd::RevaultD,
+};
+
+use revault_tx::bitcoin::{OutPoint, Txid};
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A presigned transaction paying a feerate too close to the relay floor of bitcoind
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LowFeerateTx {
+    pub deposit_outpoint: OutPoint,
+    pub txid: Txid,
+    /// The feerate it pays, in sats/vbyte
+    pub feerate: u64,
+    /// Whether it's below the floor, that is bitcoind would not relay it right now
+    pub below_floor: bool,
+}
+
+/// The result of the comparison of our presigned transactions' feerates with the relay floor
+/// of bitcoind
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayFloorCheck {
+    /// When it was performed
+    pub checked_at: u64,
+    /// The `minrelaytxfee` of bitcoind, in sats/vbyte
+    pub min_relay_feerate: u64,
+    /// The minimum feerate to enter its mempool, in sats/vbyte
+    pub mempool_min_feerate: u64,
+    /// By how much, in percent of the floor, our transactions must pay above it
+    pub margin_percent: u64,
+    /// The transactions paying less than the floor plus the margin
+    pub low_feerate_txs: Vec<LowFeerateTx>,
+}
+
+// The transactions paying less than the floor increased by the margin (in percent)
+fn low_feerate_txs(
+    signed_feerates: Vec<(OutPoint, Txid, u64)>,
+    floor: u64,
+    margin_percent: u64,
+) -> Vec<LowFeerateTx> {
+    signed_feerates
+        .into_iter()
+        .filter(|(_, _, feerate)| {
+            feerate.saturating_mul(100) < floor.saturating_mul(100 + margin_percent)
+        })
+        .map(|(deposit_outpoint, txid, feerate)| LowFeerateTx {
+            deposit_outpoint,
+            txid,
+            feerate,
+            below_floor: feerate < floor,
+        })
+        .collect()
+}
+
+/// Compare the feerates of the presigned transactions of our `Secured` and `Active` vaults, as
+/// recorded when they got fully signed, with the relay floor of bitcoind. Logs when the set of
+/// transactions too close to it changes.
+pub fn check_relay_floor(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &BitcoinD,
+) -> Result<RelayFloorCheck, BitcoindError> {
+    let (db_path, checked_at, margin_percent, previous) = {
+        let revaultd = revaultd.read().unwrap();
+        (
+            revaultd.db_file(),
+            revaultd.clock.unix_timestamp(),
+            revaultd.relay_floor_margin_percent,
+            revaultd.relay_floor_check.clone(),
+        )
+    };
+    let min_relay_feerate = bitcoind.min_relay_feerate()?;
+    let mempool_min_feerate = bitcoind.mempool_min_feerate()?;
+    let floor = std::cmp::max(min_relay_feerate, mempool_min_feerate);
+    let low_feerate_txs = low_feerate_txs(db_signed_feerates(&db_path)?, floor, margin_percent);
+
+    let previous_txids: HashSet<Txid> = previous
+        .iter()
+        .flat_map(|check| check.low_feerate_txs.iter().map(|tx| tx.txid))
+        .collect();
+    let txids: HashSet<Txid> = low_feerate_txs.iter().map(|tx| tx.txid).collect();
+    if txids != previous_txids {
+        if let Some(lowest) = low_feerate_txs.iter().min_by_key(|tx| tx.feerate) {
+            let below = low_feerate_txs.iter().filter(|tx| tx.below_floor).count();
+            log_event!(
+                log::Level::Error,
+                "relay_floor_breach",
+                count = low_feerate_txs.len(),
+                below_floor = below,
+                floor = floor,
+                lowest_feerate = lowest.feerate;
+                "!!!!! {} of our presigned transactions pay less than {}% above the relay floor \
+                 of bitcoind ({} sat/vB, its minimum relay feerate is {} and its mempool minimum \
+                 feerate {}), {} of them below it. The lowest pays {} sat/vB ('{}' of vault at \
+                 '{}'). They may not be relayed if needed. !!!!!",
+                low_feerate_txs.len(),
+                margin_percent,
+                floor,
+                min_relay_feerate,
+                mempool_min_feerate,
+                below,
+                lowest.feerate,
+                lowest.txid,
+                lowest.deposit_outpoint
+            );
+        } else {
+            log::info!(
+                "Our presigned transactions all pay more than {}% above the relay floor of \
+                 bitcoind ({} sat/vB) again.",
+                margin_percent,
+                floor
+            );
+        }
+    }
+
+    let check = RelayFloorCheck {
+        checked_at,
+        min_relay_feerate,
+        mempool_min_feerate,
+        margin_percent,
+        low_feerate_txs,
+    };
+    revaultd.write().unwrap().relay_floor_check = Some(check.clone());
+
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{low_feerate_txs, LowFeerateTx};
+
+    use revault_tx::bitcoin::{hashes::Hash, OutPoint, Txid};
+
+    #[test]
+    fn relay_floor_comparison() {
+        let txid = |i: u8| Txid::from_slice(&[i; 32]).unwrap();
+        let outpoint = OutPoint::new(txid(0), 0);
+        let signed = vec![
+            (outpoint, txid(1), 10),
+            (outpoint, txid(2), 24),
+            (outpoint, txid(3), 26),
+            (outpoint, txid(4), 100),
+        ];
+
+        // Without margin, only the ones below the floor
+        assert_eq!(
+            low_feerate_txs(signed.clone(), 25, 0),
+            vec![
+                LowFeerateTx {
+                    deposit_outpoint: outpoint,
+                    txid: txid(1),
+                    feerate: 10,
+                    below_floor: true,
+                },
+                LowFeerateTx {
+                    deposit_outpoint: outpoint,
+                    txid: txid(2),
+                    feerate: 24,
+                    below_floor: true,
+                }
+            ]
+        );
+
+        // With a margin, the ones paying slightly above it too
+        let low = low_feerate_txs(signed.clone(), 25, 20);
+        assert_eq!(low.len(), 3);
+        assert_eq!(low[2].txid, txid(3));
+        assert!(!low[2].below_floor);
+
+        // Paying exactly the floor plus the margin is fine
+        assert!(low_feerate_txs(signed.clone(), 5, 100).is_empty());
+        assert!(low_feerate_txs(signed, 1, 0).is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+//! Importing descriptors with a timestamp in the past makes bitcoind rescan the chain from
+//! there, which can take hours during which the call doesn't return. Such imports are performed
+//! on a dedicated thread and connection, one after the other in the order they were requested,
+//! so that the poller and the RPC server keep running meanwhile.
+
+use crate::bitcoind::{interface::BitcoinD, BitcoindError};
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+
+/// The kind of descriptors to import, which determines the wallet and label they go to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportKind {
+    Deposit,
+    Unvault,
+    Cpfp,
+}
+
+/// A set of descriptors to import, for which bitcoind will rescan the chain from `timestamp`.
+#[derive(Debug)]
+pub struct RescanImport {
+    pub kind: ImportKind,
+    pub descriptors: Vec<String>,
+    pub timestamp: u32,
+}
+
+/// What bitcoind is rescanning the chain for: the kind of descriptors being imported, from the
+/// block the timestamp of the import falls in to the tip.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RescanWindow {
+    pub kind: ImportKind,
+    pub from_timestamp: u32,
+}
+
+fn perform_import(bitcoind: &BitcoinD, import: RescanImport) -> Result<(), BitcoindError> {
+    let RescanImport {
+        kind,
+        descriptors,
+        timestamp,
+    } = import;
+
+    match kind {
+        ImportKind::Deposit => {
+            bitcoind.startup_import_deposit_descriptors(descriptors, timestamp, false)
+        }
+        ImportKind::Unvault => {
+            bitcoind.startup_import_unvault_descriptors(descriptors, timestamp, false)
+        }
+        ImportKind::Cpfp => {
+            let descriptor = descriptors
+                .into_iter()
+                .next()
+                .expect("We always import the CPFP descriptor");
+            bitcoind.startup_import_cpfp_descriptor(descriptor, timestamp, false)
+        }
+    }
+}
+
+/// A handle to the thread performing the imports that trigger a rescan.
+pub struct Rescanner {
+    imports: mpsc::Sender<RescanImport>,
+    errors: mpsc::Receiver<BitcoindError>,
+    // The number of imports queued or being performed
+    pending: Arc<AtomicUsize>,
+    // The window of the import being performed, if any
+    current: Arc<Mutex<Option<RescanWindow>>>,
+}
+
+impl Rescanner {
+    /// Start the thread performing the imports. Each of them is made on a dedicated connection
+    /// to bitcoind, not to starve the other callers. It stops once the returned handle is
+    /// dropped.
+    pub fn start(bitcoind: BitcoinD) -> Rescanner {
+        let (imports, imports_rx) = mpsc::channel::<RescanImport>();
+        let (errors_tx, errors) = mpsc::channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let current = Arc::new(Mutex::new(None));
+
+        thread::spawn({
+            let pending = pending.clone();
+            let current = current.clone();
+            move || {
+                for import in imports_rx {
+                    *current.lock().unwrap() = Some(RescanWindow {
+                        kind: import.kind,
+                        from_timestamp: import.timestamp,
+                    });
+                    log::info!(
+                        "Importing {} {:?} descriptor(s), bitcoind is going to rescan the chain \
+                         from timestamp {}. This may take a while.",
+                        import.descriptors.len(),
+                        import.kind,
+                        import.timestamp
+                    );
+
+                    // Re-read the cookie, bitcoind may have been restarted since we got queued.
+                    let kind = import.kind;
+                    let res = bitcoind
+                        .reconnect()
+                        .and_then(|()| perform_import(&bitcoind, import));
+                    match res {
+                        Ok(()) => log::info!("Done importing {:?} descriptor(s).", kind),
+                        Err(e) => {
+                            if errors_tx.send(e).is_err() {
+                                // The handle was dropped, we are shutting down.
+                                return;
+                            }
+                        }
+                    }
+
+                    *current.lock().unwrap() = None;
+                    pending.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        });
+
+        Rescanner {
+            imports,
+            errors,
+            pending,
+            current,
+        }
+    }
+
+    /// Queue this import, to be performed once the ones queued before it are done.
+    pub fn queue(&self, import: RescanImport) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.imports
+            .send(import)
+            .expect("The rescanner thread only stops once we are dropped");
+    }
+
+    /// Whether there are imports queued or being performed.
+    pub fn is_busy(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) > 0
+    }
+
+    /// The kind and start of the import being performed, if any.
+    pub fn current(&self) -> Option<RescanWindow> {
+        *self.current.lock().unwrap()
+    }
+
+    /// The error an import failed with, if any.
+    pub fn error(&self) -> Option<BitcoindError> {
+        self.errors.try_recv().ok()
+    }
+}
@@ -2,16 +2,19 @@ use crate::{
     bitcoind::{interface::UtxoInfo, BitcoindError},
     database::{
         interface::{
-            db_cancel_transaction, db_deposits, db_emer_transaction, db_unvault_emer_transaction,
-            db_unvault_from_deposit, db_unvaulted_vaults, db_vault_by_deposit,
+            db_abandoned_vaults, db_cancel_transaction, db_deposits, db_emer_transaction,
+            db_unvault_emer_transaction, db_unvault_from_deposit, db_unvaulted_vaults,
+            db_vault_by_deposit, db_vault_emergency_address,
         },
         schema::DbVault,
     },
+    derivation::DerivationIndex,
     revaultd::{RevaultD, VaultStatus},
 };
 use revault_tx::{
-    bitcoin::{Amount, OutPoint, TxOut, Txid},
+    bitcoin::{Address, Amount, OutPoint, TxOut, Txid},
     miniscript::DescriptorTrait,
+    scripts::EmergencyAddress,
     transactions::{
         transaction_chain, transaction_chain_manager, CancelTransaction, EmergencyTransaction,
         RevaultTransaction, UnvaultEmergencyTransaction, UnvaultTransaction,
@@ -25,11 +28,31 @@ use std::{
     sync::{Arc, RwLock},
 };
 
-/// Get fresh to-be-presigned transactions for this deposit utxo
+/// The address the Emergency transactions of a vault of this amount pay to: the one recorded for
+/// it if any, as the configured bands may have changed since, the configured one for its amount
+/// otherwise. None if we are not a stakeholder.
+pub fn vault_emergency_address(
+    revaultd: &RevaultD,
+    recorded: Option<Address>,
+    amount: Amount,
+) -> Option<EmergencyAddress> {
+    let addresses = revaultd.emergency_addresses.as_ref()?;
+    match recorded {
+        Some(address) => Some(
+            EmergencyAddress::from(address).expect("We only record configured Emergency addresses"),
+        ),
+        None => Some(addresses.for_amount(amount).clone()),
+    }
+}
+
+/// Get fresh to-be-presigned transactions for this deposit utxo, paying to this Emergency
+/// address if we are a stakeholder.
 pub fn presigned_transactions(
     revaultd: &RevaultD,
     outpoint: OutPoint,
-    utxo: UtxoInfo,
+    amount: Amount,
+    derivation_index: DerivationIndex,
+    emer_address: Option<EmergencyAddress>,
 ) -> Result<
     (
         UnvaultTransaction,
@@ -39,28 +62,18 @@ pub fn presigned_transactions(
     ),
     BitcoindError,
 > {
-    // We use the same derivation index for all descriptors.
-    let derivation_index = *revaultd
-        .derivation_index_map
-        .get(&utxo.txo.script_pubkey)
-        .ok_or_else(|| {
-            BitcoindError::Custom(format!("Unknown derivation index for: {:#?}", &utxo))
-        })?;
-
     // Reconstruct the deposit UTXO and derive all pre-signed transactions out of it
     // if we are a stakeholder, and only the Unvault and the Cancel if we are a manager.
+    // We use the same derivation index for all descriptors.
     if revaultd.is_stakeholder() {
-        let emer_address = revaultd
-            .emergency_address
-            .clone()
-            .expect("We are a stakeholder");
+        let emer_address = emer_address.expect("We are a stakeholder");
         let (unvault_tx, cancel_tx, emer_tx, unemer_tx) = transaction_chain(
             outpoint,
-            Amount::from_sat(utxo.txo.value),
+            amount,
             &revaultd.deposit_descriptor,
             &revaultd.unvault_descriptor,
             &revaultd.cpfp_descriptor,
-            derivation_index,
+            derivation_index.into(),
             emer_address,
             revaultd.lock_time,
             &revaultd.secp_ctx,
@@ -69,11 +82,11 @@ pub fn presigned_transactions(
     } else {
         let (unvault_tx, cancel_tx) = transaction_chain_manager(
             outpoint,
-            Amount::from_sat(utxo.txo.value),
+            amount,
             &revaultd.deposit_descriptor,
             &revaultd.unvault_descriptor,
             &revaultd.cpfp_descriptor,
-            derivation_index,
+            derivation_index.into(),
             revaultd.lock_time,
             &revaultd.secp_ctx,
         )?;
@@ -86,12 +99,19 @@ pub fn populate_deposit_cache(
     revaultd: &RevaultD,
 ) -> Result<HashMap<OutPoint, UtxoInfo>, BitcoindError> {
     let db_vaults = db_deposits(&revaultd.db_file())?;
+    let abandoned_vaults = db_abandoned_vaults(&revaultd.db_file())?;
     let mut cache = HashMap::with_capacity(db_vaults.len());
 
     for db_vault in db_vaults.into_iter() {
-        let der_deposit_descriptor = revaultd
-            .deposit_descriptor
-            .derive(db_vault.derivation_index, &revaultd.secp_ctx);
+        // An abandoned deposit is detected anew by the watchonly wallet if it ever comes back.
+        if abandoned_vaults.contains(&db_vault.id) {
+            log::debug!(
+                "Not loading abandoned deposit '{}' from db",
+                db_vault.deposit_outpoint
+            );
+            continue;
+        }
+        let der_deposit_descriptor = revaultd.derived_deposit_descriptor(db_vault.derivation_index);
         let script_pubkey = der_deposit_descriptor.inner().script_pubkey();
         let txo = TxOut {
             script_pubkey,
@@ -192,7 +212,7 @@ pub fn cancel_txid(
             &revaultd.deposit_descriptor,
             &revaultd.unvault_descriptor,
             &revaultd.cpfp_descriptor,
-            db_vault.derivation_index,
+            db_vault.derivation_index.into(),
             revaultd.lock_time,
             &revaultd.secp_ctx,
         )?;
@@ -215,17 +235,20 @@ pub fn unemer_txid(
             if let Some(unemer_db_tx) = db_unvault_emer_transaction(&db_path, db_vault.id)? {
                 unemer_db_tx.psbt.assert_unvault_emer()
             } else {
+                let emer_address = vault_emergency_address(
+                    &revaultd,
+                    db_vault_emergency_address(&db_path, db_vault.id)?,
+                    db_vault.amount,
+                )
+                .expect("Just checked we were a stakeholder");
                 let (_, _, _, unemer_tx) = transaction_chain(
                     db_vault.deposit_outpoint,
                     db_vault.amount,
                     &revaultd.deposit_descriptor,
                     &revaultd.unvault_descriptor,
                     &revaultd.cpfp_descriptor,
-                    db_vault.derivation_index,
-                    revaultd
-                        .emergency_address
-                        .clone()
-                        .expect("Just checked we were a stakeholder"),
+                    db_vault.derivation_index.into(),
+                    emer_address,
                     revaultd.lock_time,
                     &revaultd.secp_ctx,
                 )?;
@@ -250,17 +273,20 @@ pub fn emer_txid(
         let unemer_tx = if let Some(emer_db_tx) = db_emer_transaction(&db_path, db_vault.id)? {
             emer_db_tx.psbt.assert_emer()
         } else {
+            let emer_address = vault_emergency_address(
+                &revaultd,
+                db_vault_emergency_address(&db_path, db_vault.id)?,
+                db_vault.amount,
+            )
+            .expect("Just checked we were a stakeholder");
             let (_, _, emer_tx, _) = transaction_chain(
                 db_vault.deposit_outpoint,
                 db_vault.amount,
                 &revaultd.deposit_descriptor,
                 &revaultd.unvault_descriptor,
                 &revaultd.cpfp_descriptor,
-                db_vault.derivation_index,
-                revaultd
-                    .emergency_address
-                    .clone()
-                    .expect("Just checked we were a stakeholder"),
+                db_vault.derivation_index.into(),
+                emer_address,
                 revaultd.lock_time,
                 &revaultd.secp_ctx,
             )?;
@@ -0,0 +1,144 @@
+//! What binary is running. The metadata of the build is embedded at compile time by the build
+//! script, printed at startup and returned by the `version` command.
+
+use crate::VERSION;
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The metadata of the build of the daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    /// The commit it was built from, unless it wasn't built from a git repository
+    pub git_commit: Option<String>,
+    /// Whether the tracked files were modified, unless it wasn't built from a git repository
+    pub git_dirty: Option<bool>,
+    /// The day it was built, as YYYY-MM-DD (UTC)
+    pub build_date: String,
+    /// The cargo features it was compiled with
+    pub features: Vec<String>,
+    pub rustc_version: String,
+}
+
+// The build script sets the variables it couldn't fill to an empty string
+fn non_empty(value: &str) -> Option<&str> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// The metadata of the build of this binary
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION.to_string(),
+        git_commit: non_empty(env!("REVAULTD_GIT_COMMIT")).map(|c| c.to_string()),
+        git_dirty: non_empty(env!("REVAULTD_GIT_DIRTY")).map(|d| d == "true"),
+        build_date: env!("REVAULTD_BUILD_DATE").to_string(),
+        features: env!("REVAULTD_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(|f| f.to_string())
+            .collect(),
+        rustc_version: non_empty(env!("REVAULTD_RUSTC_VERSION"))
+            .unwrap_or("unknown rustc")
+            .to_string(),
+    }
+}
+
+/// A one-line summary, such as 'revaultd 0.3.1 (commit 0123456789ab-dirty, built 2021-11-02 with
+/// rustc 1.43.1 (8d69840ab 2020-05-04), features: jsonrpc_server)'
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "revaultd {} (", self.version)?;
+        match self.git_commit {
+            Some(ref commit) => write!(
+                f,
+                "commit {}{}",
+                &commit[..std::cmp::min(12, commit.len())],
+                if self.git_dirty == Some(true) {
+                    "-dirty"
+                } else {
+                    ""
+                }
+            )?,
+            None => write!(f, "unknown commit")?,
+        }
+        write!(
+            f,
+            ", built {} with {}, features: {})",
+            self.build_date,
+            self.rustc_version,
+            if self.features.is_empty() {
+                "none".to_string()
+            } else {
+                self.features.join(",")
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_info, BuildInfo};
+
+    #[test]
+    fn build_metadata() {
+        let info = build_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.git_commit.is_some(), info.git_dirty.is_some());
+        if let Some(ref commit) = info.git_commit {
+            assert_eq!(commit.len(), 40);
+            assert!(commit.chars().all(|c| c.is_ascii_hexdigit()));
+        }
+        let date: Vec<&str> = info.build_date.split('-').collect();
+        assert_eq!(
+            date.iter().map(|part| part.len()).collect::<Vec<_>>(),
+            vec![4, 2, 2]
+        );
+        assert!(date.iter().all(|part| part.parse::<u32>().is_ok()));
+        assert!(info.rustc_version.starts_with("rustc "));
+
+        // The features are the ones we were compiled with
+        let compiled: Vec<&str> = [
+            ("benches", cfg!(feature = "benches")),
+            ("jsonrpc_server", cfg!(feature = "jsonrpc_server")),
+            ("noise_test_vectors", cfg!(feature = "noise_test_vectors")),
+        ]
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| *feature)
+        .collect();
+        assert_eq!(info.features, compiled);
+    }
+
+    #[test]
+    fn banner() {
+        let mut info = BuildInfo {
+            version: "0.3.1".to_string(),
+            git_commit: Some("6914cb8a0f1e2d3c4b5a69788796a5b4c3d2e1f0".to_string()),
+            git_dirty: Some(true),
+            build_date: "2021-11-02".to_string(),
+            features: vec!["jsonrpc_server".to_string(), "benches".to_string()],
+            rustc_version: "rustc 1.43.1 (8d69840ab 2020-05-04)".to_string(),
+        };
+        assert_eq!(
+            info.to_string(),
+            "revaultd 0.3.1 (commit 6914cb8a0f1e-dirty, built 2021-11-02 with rustc 1.43.1 \
+             (8d69840ab 2020-05-04), features: jsonrpc_server,benches)"
+        );
+
+        // Built from a tarball
+        info.git_commit = None;
+        info.git_dirty = None;
+        info.features = vec![];
+        assert_eq!(
+            info.to_string(),
+            "revaultd 0.3.1 (unknown commit, built 2021-11-02 with rustc 1.43.1 \
+             (8d69840ab 2020-05-04), features: none)"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! A source of time for the daemon.
+//!
+//! We need two notions of time: a monotonic one to compute intervals (polling, backoff,
+//! keepalives) that is not affected by clock jumps, and the wall clock to timestamp the events
+//! we record in the database. Both are accessed through the [Clock] trait so the logic depending
+//! on them can be tested without actually sleeping.
+
+use std::{
+    fmt,
+    time::{Duration, Instant, SystemTime, SystemTimeError, UNIX_EPOCH},
+};
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Monotonic time, to be used for intervals and backoffs.
+    fn now(&self) -> Instant;
+
+    /// Wall clock time, as the number of seconds since the UNIX epoch. Errors if the clock is
+    /// set before it.
+    fn try_unix_timestamp(&self) -> Result<u64, SystemTimeError>;
+
+    /// Wall clock time, as the number of seconds since the UNIX epoch. A clock set before it
+    /// gives the epoch itself: we'd rather record a wrong time for an event than not record it.
+    fn unix_timestamp(&self) -> u64 {
+        self.try_unix_timestamp().unwrap_or_else(|e| {
+            log::error!("System clock is set before the UNIX epoch: '{}'", e);
+            0
+        })
+    }
+
+    /// Monotonic time elapsed since the given instant.
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+
+    /// Block the current thread for this duration.
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// The clock of the system we are running on.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn try_unix_timestamp(&self) -> Result<u64, SystemTimeError> {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|dur| dur.as_secs())
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::Clock;
+
+    use std::{
+        sync::Mutex,
+        time::{Duration, Instant, SystemTimeError},
+    };
+
+    /// A clock that only moves forward when told to.
+    #[derive(Debug)]
+    pub struct MockClock {
+        start: Instant,
+        start_timestamp: u64,
+        offset: Mutex<Duration>,
+    }
+
+    impl MockClock {
+        pub fn new(start_timestamp: u64) -> Self {
+            Self {
+                start: Instant::now(),
+                start_timestamp,
+                offset: Mutex::new(Duration::from_secs(0)),
+            }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            *self.offset.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.start + *self.offset.lock().unwrap()
+        }
+
+        fn try_unix_timestamp(&self) -> Result<u64, SystemTimeError> {
+            Ok(self.start_timestamp + self.offset.lock().unwrap().as_secs())
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_utils::MockClock, Clock};
+    use std::time::Duration;
+
+    #[test]
+    fn mock_clock_advance() {
+        let clock = MockClock::new(1_600_000_000);
+        let start = clock.now();
+        assert_eq!(clock.elapsed(start), Duration::from_secs(0));
+        assert_eq!(clock.unix_timestamp(), 1_600_000_000);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(clock.elapsed(start), Duration::from_secs(30));
+        assert_eq!(clock.unix_timestamp(), 1_600_000_030);
+
+        // Sleeping doesn't block, it just moves the clock forward
+        clock.sleep(Duration::from_secs(3600));
+        assert_eq!(clock.elapsed(start), Duration::from_secs(3630));
+    }
+}
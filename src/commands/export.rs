@@ -0,0 +1,308 @@
+//! CSV exports of the vaults and of the history of the funds, for accounting.
+//!
+//! Columns are in a fixed order, amounts are integer satoshis and dates are ISO-8601 UTC
+//! timestamps (empty if unknown). Rows are written to the output as they are computed.
+
+use crate::{
+    commands::{
+        utils::{gethistory, vaults_at_heights},
+        CommandError, HistoryEvent, HistoryEventKind, ListVaultsEntry, VaultHeightFilter,
+    },
+    revaultd::RevaultD,
+    threadmessages::BitcoindThread,
+};
+
+use std::{borrow::Cow, io};
+
+use serde::{Deserialize, Serialize};
+
+/// What to export.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportKind {
+    /// The current list of vaults
+    Vaults,
+    /// The deposit, cancel and spend events over a time range
+    History,
+}
+
+/// The maximum size of an export returned inline, in bytes. Larger ones must be written to a
+/// file.
+pub const MAX_INLINE_EXPORT_SIZE: usize = 1_000_000;
+
+/// The columns of a vaults export.
+pub const VAULTS_COLUMNS: &[&str] = &[
+    "deposit_outpoint",
+    "amount",
+    "status",
+    "derivation_index",
+    "address",
+    "origin",
+    "funded_at",
+    "secured_at",
+    "delegated_at",
+    "moved_at",
+    "flags",
+];
+
+/// The columns of a history export.
+pub const HISTORY_COLUMNS: &[&str] = &[
+    "date",
+    "kind",
+    "blockheight",
+    "txid",
+    "amount",
+    "fee",
+    "vaults",
+];
+
+// Quote a field if it contains a separator, a quote or a line break, doubling its quotes (RFC
+// 4180).
+fn csv_field(field: &str) -> Cow<str> {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Format a UNIX timestamp as an ISO-8601 UTC date, eg `2020-09-13T12:26:40Z`.
+pub fn iso8601(timestamp: u32) -> String {
+    let days = i64::from(timestamp / 86_400);
+    let secs = timestamp % 86_400;
+
+    // Civil date from the number of days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+fn optional_date(timestamp: Option<u32>) -> String {
+    timestamp.map(iso8601).unwrap_or_default()
+}
+
+// The kind of an event as named by `gethistory`.
+fn history_kind(kind: &HistoryEventKind) -> &'static str {
+    match kind {
+        HistoryEventKind::Deposit => "deposit",
+        HistoryEventKind::Cancel => "cancel",
+        HistoryEventKind::Spend => "spend",
+        HistoryEventKind::Emergency => "emergency",
+        HistoryEventKind::UnvaultEmergency => "unvault_emergency",
+    }
+}
+
+/// Writes CSV rows to an output, one at a time.
+pub struct CsvWriter<W: io::Write> {
+    out: W,
+    rows: u64,
+}
+
+impl<W: io::Write> CsvWriter<W> {
+    /// Start a CSV output with this header.
+    pub fn new(mut out: W, columns: &[&str]) -> io::Result<Self> {
+        Self::write_line(&mut out, columns)?;
+        Ok(Self { out, rows: 0 })
+    }
+
+    fn write_line<T: AsRef<str>>(out: &mut W, fields: &[T]) -> io::Result<()> {
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            out.write_all(csv_field(field.as_ref()).as_bytes())?;
+        }
+        out.write_all(b"\r\n")
+    }
+
+    pub fn write_row<T: AsRef<str>>(&mut self, fields: &[T]) -> io::Result<()> {
+        Self::write_line(&mut self.out, fields)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn write_vault(&mut self, vault: &ListVaultsEntry) -> io::Result<()> {
+        let flags = vault
+            .flags
+            .iter()
+            .map(|flag| format!("{}: {}", flag.kind, flag.message))
+            .collect::<Vec<_>>()
+            .join("; ");
+        self.write_row(&[
+            format!("{}:{}", vault.txid, vault.vout),
+            vault.amount.as_sat().to_string(),
+            vault.status.to_string(),
+            vault.derivation_index.to_string(),
+            vault.address.to_string(),
+            vault.origin.to_string(),
+            optional_date(vault.funded_at),
+            optional_date(vault.secured_at),
+            optional_date(vault.delegated_at),
+            optional_date(vault.moved_at),
+            flags,
+        ])
+    }
+
+    pub fn write_event(&mut self, event: &HistoryEvent) -> io::Result<()> {
+        let vaults = event
+            .vaults
+            .iter()
+            .map(|outpoint| outpoint.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.write_row(&[
+            iso8601(event.date),
+            history_kind(&event.kind).to_string(),
+            event.blockheight.to_string(),
+            event.txid.to_string(),
+            event
+                .amount
+                .map(|amount| amount.as_sat().to_string())
+                .unwrap_or_default(),
+            event
+                .fee
+                .map(|fee| fee.as_sat().to_string())
+                .unwrap_or_default(),
+            vaults,
+        ])
+    }
+
+    /// Flush the output, and get the number of rows written (not counting the header).
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.out.flush()?;
+        Ok(self.rows)
+    }
+}
+
+/// Write the CSV export to this output, returning the number of rows. The time range only
+/// applies to the history, which is exported in chronological order.
+pub fn export_csv<T: BitcoindThread, W: io::Write>(
+    revaultd: &RevaultD,
+    bitcoind_conn: &T,
+    kind: ExportKind,
+    start: u32,
+    end: u32,
+    out: W,
+) -> Result<u64, CommandError> {
+    match kind {
+        ExportKind::Vaults => {
+            let mut writer = CsvWriter::new(out, VAULTS_COLUMNS).map_err(CommandError::Export)?;
+            // Don't collect them, the export of a large deployment would otherwise be built in
+            // memory.
+            for vault in vaults_at_heights(revaultd, None, None, &VaultHeightFilter::default())
+                .expect("Database must be available")
+            {
+                let vault = vault.expect("Database must be available");
+                writer.write_vault(&vault).map_err(CommandError::Export)?;
+            }
+            writer.finish().map_err(CommandError::Export)
+        }
+        ExportKind::History => {
+            let kinds = [
+                HistoryEventKind::Deposit,
+                HistoryEventKind::Cancel,
+                HistoryEventKind::Spend,
+                HistoryEventKind::Emergency,
+                HistoryEventKind::UnvaultEmergency,
+            ];
+            // The SQLite limit is a signed integer
+            let events = gethistory(revaultd, bitcoind_conn, start, end, i64::MAX as u64, &kinds)?;
+            let mut writer = CsvWriter::new(out, HISTORY_COLUMNS).map_err(CommandError::Export)?;
+            // They are sorted from the most recent one
+            for event in events.iter().rev() {
+                writer.write_event(event).map_err(CommandError::Export)?;
+            }
+            writer.finish().map_err(CommandError::Export)
+        }
+    }
+}
+
+/// An in-memory output refusing to grow past a size limit.
+pub struct CappedBuffer {
+    pub buf: Vec<u8>,
+    limit: usize,
+}
+
+impl CappedBuffer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            limit,
+        }
+    }
+}
+
+impl io::Write for CappedBuffer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + data.len() > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Export is larger than {} bytes", self.limit),
+            ));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_field, iso8601, CappedBuffer, CsvWriter};
+
+    use std::io::Write;
+
+    #[test]
+    fn csv_escaping() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+
+        let mut out = Vec::new();
+        let mut writer = CsvWriter::new(&mut out, &["a", "b"]).unwrap();
+        writer.write_row(&["1", "x,\"y\""]).unwrap();
+        assert_eq!(writer.finish().unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a,b\r\n1,\"x,\"\"y\"\"\"\r\n"
+        );
+    }
+
+    #[test]
+    fn iso8601_dates() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601(1_600_000_000), "2020-09-13T12:26:40Z");
+        assert_eq!(iso8601(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso8601(u32::MAX), "2106-02-07T06:28:15Z");
+    }
+
+    #[test]
+    fn capped_buffer() {
+        let mut buf = CappedBuffer::new(4);
+        buf.write_all(b"abc").unwrap();
+        buf.write_all(b"de").unwrap_err();
+        buf.write_all(b"d").unwrap();
+        assert_eq!(buf.buf, b"abcd");
+    }
+}
@@ -0,0 +1,437 @@
+//! Locks serializing the mutating commands touching the same vaults or Spend transactions.
+//!
+//! The RPC handlers run concurrently, and some of them can take a while (eg `setspendtx` waiting
+//! on the cosigning servers). Two commands mutating the same vault, or the same Spend attempt,
+//! must not interleave. Commands on disjoint resources may proceed in parallel, and queries
+//! never take these locks.
+//!
+//! To avoid deadlocks, the resources are always locked in the order defined by
+//! [`LockedResource`]'s `Ord` implementation: Spend transactions first (by txid), then vaults (by
+//! deposit outpoint). A single call to [`ResourceLocks::lock`] takes care of sorting them. A
+//! command must not hold the guard of a first call to `lock` while making a second one.
+//! Acquiring a lock gives up after a timeout, and the command fails with a "busy" error instead.
+
+use crate::commands::CommandError;
+
+use revault_tx::bitcoin::{OutPoint, Txid};
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long a command waits for the resources it touches to be released by another one.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A resource mutating commands need exclusive access to.
+// NOTE: the order of the variants is the order in which the resources are locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockedResource {
+    /// A Spend attempt, by the txid of the Spend transaction
+    Spend(Txid),
+    /// A vault, by its deposit outpoint
+    Vault(OutPoint),
+}
+
+impl fmt::Display for LockedResource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Spend(txid) => write!(f, "Spend transaction '{}'", txid),
+            Self::Vault(outpoint) => write!(f, "vault at '{}'", outpoint),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LocksInner {
+    locked: Mutex<BTreeSet<LockedResource>>,
+    released: Condvar,
+}
+
+/// The set of resources currently locked by the commands. Cloning it gives a handle to the same
+/// set.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLocks {
+    inner: Arc<LocksInner>,
+}
+
+impl ResourceLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lock all these resources, in order, waiting at most `timeout` for each of them. The
+    /// resources are released once the returned guard is dropped.
+    ///
+    /// ## Errors
+    /// - `CommandError::Busy` if one of the resources is still held by another command after
+    /// the timeout. None of them are held then.
+    pub fn lock(
+        &self,
+        resources: impl IntoIterator<Item = LockedResource>,
+        timeout: Duration,
+    ) -> Result<ResourcesGuard, CommandError> {
+        let resources: BTreeSet<LockedResource> = resources.into_iter().collect();
+        let mut guard = ResourcesGuard {
+            locks: self.clone(),
+            resources: Vec::with_capacity(resources.len()),
+        };
+
+        let mut locked = self.inner.locked.lock().unwrap();
+        // A BTreeSet iterates in order
+        for resource in resources {
+            let deadline = Instant::now() + timeout;
+            while locked.contains(&resource) {
+                let now = Instant::now();
+                if now >= deadline {
+                    // Release the ones we got through the guard's destructor
+                    drop(locked);
+                    return Err(CommandError::Busy(resource));
+                }
+                locked = self
+                    .inner
+                    .released
+                    .wait_timeout(locked, deadline - now)
+                    .unwrap()
+                    .0;
+            }
+            locked.insert(resource);
+            guard.resources.push(resource);
+        }
+
+        Ok(guard)
+    }
+
+    fn release(&self, resources: &[LockedResource]) {
+        if resources.is_empty() {
+            return;
+        }
+        let mut locked = self.inner.locked.lock().unwrap();
+        for resource in resources {
+            locked.remove(resource);
+        }
+        self.inner.released.notify_all();
+    }
+}
+
+/// Keeps resources locked until dropped.
+#[derive(Debug)]
+pub struct ResourcesGuard {
+    locks: ResourceLocks,
+    resources: Vec<LockedResource>,
+}
+
+impl Drop for ResourcesGuard {
+    fn drop(&mut self) {
+        self.locks.release(&self.resources);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockedResource, ResourceLocks, LOCK_TIMEOUT};
+    use crate::{
+        commands::CommandError,
+        database::{
+            actions::{
+                db_confirm_deposit, db_insert_new_unconfirmed_vault, db_insert_spend, setup_db,
+            },
+            interface::{db_unvault_transaction, db_vault_by_deposit},
+        },
+        derivation::DerivationIndex,
+        threadmessages::{BitcoindMessageOut, SigFetcherMessageOut, StateMachineMessageOut},
+        utils::test_utils::{dummy_revaultd, test_datadir, UserRole},
+        DaemonControl,
+    };
+
+    use revault_tx::{
+        bitcoin::{Amount, OutPoint, Txid},
+        transactions::{
+            CancelTransaction, RevaultTransaction, SpendTransaction, UnvaultTransaction,
+        },
+    };
+
+    use std::{
+        fs,
+        str::FromStr,
+        sync::{mpsc, Arc, Mutex, RwLock},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint {
+            txid: Txid::from_str(
+                "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7",
+            )
+            .unwrap(),
+            vout,
+        }
+    }
+
+    fn vault(vout: u32) -> LockedResource {
+        LockedResource::Vault(outpoint(vout))
+    }
+
+    #[test]
+    fn resource_locks() {
+        let locks = ResourceLocks::new();
+        let short = Duration::from_millis(50);
+
+        // Disjoint sets can be held at the same time, overlapping ones can't
+        let guard_a = locks.lock(vec![vault(0), vault(1)], short).unwrap();
+        let guard_b = locks.lock(vec![vault(2)], short).unwrap();
+        match locks.lock(vec![vault(3), vault(1)], short) {
+            Err(CommandError::Busy(resource)) => assert_eq!(resource, vault(1)),
+            _ => panic!("Vault 1 is locked"),
+        }
+        // The failed attempt didn't keep the first vault locked
+        drop(locks.lock(vec![vault(3)], short).unwrap());
+
+        // Once released, they can be locked again
+        drop(guard_a);
+        let guard_c = locks.lock(vec![vault(1), vault(0)], short).unwrap();
+        drop(guard_b);
+        drop(guard_c);
+
+        // Duplicates don't lock a resource against itself
+        drop(locks.lock(vec![vault(0), vault(0)], short).unwrap());
+
+        // We wait for the resource to be released
+        let guard = locks.lock(vec![vault(0)], short).unwrap();
+        let t_locks = locks.clone();
+        let waiter = thread::spawn(move || {
+            t_locks
+                .lock(vec![vault(0)], Duration::from_secs(10))
+                .is_ok()
+        });
+        thread::sleep(short);
+        drop(guard);
+        assert!(waiter.join().unwrap());
+    }
+
+    // The Cancel broadcasts the state machine is processing, and the most it processed at once
+    #[derive(Default)]
+    struct Broadcasts {
+        ongoing: Vec<OutPoint>,
+        max_parallel: usize,
+    }
+
+    // A handle to the commands of a manager whose Spend transaction spends the vault at
+    // `outpoint(1)`, along with the txid of the Spend. The state machine answers the Cancel
+    // broadcasts after `hold`, and fails if asked to broadcast the same one twice at once.
+    fn locking_daemon_control(
+        hold: Arc<Mutex<Duration>>,
+        broadcasts: Arc<Mutex<Broadcasts>>,
+    ) -> (DaemonControl, Txid) {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir, UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let db_path = revaultd.db_file();
+        let unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAcRWqIPG85zGye1nuRlbwWKkko4g91Vd/508Ff6vKklpAAAAAAD9////AkANAwAAAAAAIgAgsT7u0Lo8o2WEfxS1nXWtQzsdJTMJnnOC5fwg0nYPvpowdQAAAAAAACIAIAx0DegrXfBr4D0XdetrGgAT2Q3AZANYm0rJL8L/Epp/AAAAAAABASuIlAMAAAAAACIAIGaHQ5brMNbT+WCtfE/WPW8gkmMir5NXAKRsQZAs9cT2AQMEAQAAAAEFR1IhAwYSJ4FeXdf/XPw6lFHpeMFeGvh88f+rWN2VtnaW75TNIQOn5Sg6nytLwT5FT9z5KmV/LMN1pZRsqbworUMwRdRN0lKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQN0Nj5YtWlqdUtE4VzrCy9fIUbgVSBiSedOJzYY9A0jLqxRh2R2qRQ2UoYTYXFkzWxHTxQLsYl/NGpeVIisa3apFChMb7eFLoSVfMHD7bU9EO0Qn2wqiKxsk1KHZ1IhA2KobMJZNs2+adObuXpg1Ny2DOg/nFo5bqGJdJZWSgKUIQL/DSNFGVoHc5rlzQ4+tEDFvETWR1/NXbg5axpIIYuAhVKvAtY0smgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhA3Q2Pli1aWp1S0ThXOsLL18hRuBVIGJJ504nNhj0DSMurFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        let spend_tx = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAciTbKS43sH49TJWX6xJ+MxqWfNQhRl+vkttRZ9sLUkHAAAAAAClAQAAAoAyAAAAAAAAIgAggxumgjPgMj5oHWn8QkvKqPIN0N5nuAbyQ+FEgOJZpjygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQBAwQBAAAAAQWqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            revaultd.wallet_id.unwrap(),
+            &outpoint(1),
+            &Amount::from_sat(612345),
+            DerivationIndex::new(349874).unwrap(),
+        )
+        .unwrap();
+        db_confirm_deposit(
+            &db_path,
+            &outpoint(1),
+            9,
+            9,
+            &unvault_tx,
+            &cancel_tx,
+            None,
+            None,
+            1_600_000_000,
+        )
+        .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint(1))
+            .unwrap()
+            .unwrap();
+        let db_unvault = db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        db_insert_spend(&db_path, &[db_unvault], &spend_tx).unwrap();
+
+        let (bitcoind_tx, _) = mpsc::channel::<BitcoindMessageOut>();
+        let (sigfetcher_tx, _) = mpsc::channel::<SigFetcherMessageOut>();
+        let (statemachine_tx, statemachine_rx) = mpsc::channel();
+        thread::spawn(move || {
+            for msg in statemachine_rx {
+                match msg {
+                    StateMachineMessageOut::BroadcastCancel(outpoint, reply) => {
+                        let (hold, broadcasts) = (*hold.lock().unwrap(), broadcasts.clone());
+                        thread::spawn(move || {
+                            {
+                                let mut broadcasts = broadcasts.lock().unwrap();
+                                assert!(!broadcasts.ongoing.contains(&outpoint));
+                                broadcasts.ongoing.push(outpoint);
+                                broadcasts.max_parallel =
+                                    broadcasts.max_parallel.max(broadcasts.ongoing.len());
+                            }
+                            thread::sleep(hold);
+                            broadcasts
+                                .lock()
+                                .unwrap()
+                                .ongoing
+                                .retain(|o| *o != outpoint);
+                            reply.send(Ok(())).unwrap();
+                        });
+                    }
+                    StateMachineMessageOut::Shutdown => return,
+                    _ => unreachable!(),
+                }
+            }
+        });
+
+        (
+            DaemonControl::new(
+                Arc::new(RwLock::new(revaultd)),
+                bitcoind_tx.into(),
+                sigfetcher_tx.into(),
+                statemachine_tx.into(),
+            ),
+            spend_tx.txid(),
+        )
+    }
+
+    // The Spend isn't signed, so 'setspendtx' fails once it locked the vault it spends
+    fn set_spend_tx(control: &DaemonControl, spend_txid: &Txid) {
+        match control.set_spend_tx(spend_txid, false, false) {
+            Err(CommandError::SpendNotEnoughSig(0, _)) => {}
+            r => panic!("Unexpected 'setspendtx' result: {:?}", r),
+        }
+    }
+
+    // 'revault' and 'setspendtx' calls on overlapping vaults from many threads don't deadlock,
+    // calls on disjoint vaults proceed in parallel, and a call waiting too long for a vault
+    // fails with a "busy" error.
+    #[test]
+    fn daemon_control_locks_stress() {
+        let hold = Arc::new(Mutex::new(Duration::from_secs(1)));
+        let broadcasts = Arc::new(Mutex::new(Broadcasts::default()));
+        let (control, spend_txid) = locking_daemon_control(hold.clone(), broadcasts.clone());
+        let datadir = control.revaultd.read().unwrap().data_dir.clone();
+
+        // Calls on disjoint vaults proceed in parallel
+        let start = Instant::now();
+        let revaults: Vec<_> = (2..6)
+            .map(|vout| {
+                let control = control.clone();
+                thread::spawn(move || control.revault(&outpoint(vout)).unwrap())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+        set_spend_tx(&control, &spend_txid);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        for revault in revaults {
+            revault.join().unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(broadcasts.lock().unwrap().max_parallel, 4);
+
+        // Interleaved calls on overlapping vaults don't deadlock
+        *hold.lock().unwrap() = Duration::from_millis(2);
+        let (done_tx, done_rx) = mpsc::channel();
+        for i in 0..8u32 {
+            let (control, done_tx) = (control.clone(), done_tx.clone());
+            thread::spawn(move || {
+                for j in 0..25u32 {
+                    match (i + j) % 3 {
+                        0 => set_spend_tx(&control, &spend_txid),
+                        1 => control.revault(&outpoint(1)).unwrap(),
+                        _ => control.revault(&outpoint(2 + j % 2)).unwrap(),
+                    }
+                }
+                done_tx.send(()).unwrap();
+            });
+        }
+        for _ in 0..8 {
+            done_rx
+                .recv_timeout(Duration::from_secs(60))
+                .expect("Deadlock or panic");
+        }
+
+        // A call waiting for a vault longer than the timeout gives up
+        *hold.lock().unwrap() = LOCK_TIMEOUT + Duration::from_secs(2);
+        let t_control = control.clone();
+        let holder = thread::spawn(move || t_control.revault(&outpoint(1)).unwrap());
+        thread::sleep(Duration::from_millis(200));
+        let t_control = control.clone();
+        let waiter = thread::spawn(move || t_control.revault(&outpoint(1)));
+        match control.set_spend_tx(&spend_txid, false, false) {
+            Err(CommandError::Busy(resource)) => assert_eq!(resource, vault(1)),
+            r => panic!("Unexpected 'setspendtx' result: {:?}", r),
+        }
+        match waiter.join().unwrap() {
+            Err(CommandError::Busy(resource)) => assert_eq!(resource, vault(1)),
+            r => panic!("Unexpected 'revault' result: {:?}", r),
+        }
+        holder.join().unwrap();
+        // Once released, the vault can be used again
+        set_spend_tx(&control, &spend_txid);
+
+        fs::remove_dir_all(&datadir).unwrap();
+    }
+
+    // Interleave many commands on overlapping sets of resources, each in its own order. They
+    // must neither deadlock nor ever hold the same resource at the same time.
+    #[test]
+    fn resource_locks_stress() {
+        let locks = ResourceLocks::new();
+        let held = Arc::new(Mutex::new(Vec::new()));
+        let spend = LockedResource::Spend(
+            Txid::from_str("4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040")
+                .unwrap(),
+        );
+
+        let threads: Vec<_> = (0..8u32)
+            .map(|i| {
+                let locks = locks.clone();
+                let held = held.clone();
+                thread::spawn(move || {
+                    for j in 0..100u32 {
+                        // Alternate between "setspendtx"-like commands, locking the Spend and
+                        // its vaults, and "revault"-like ones locking a single vault.
+                        let mut resources = if (i + j) % 2 == 0 {
+                            vec![vault((i + j) % 5), vault((i + 2 * j) % 5), spend]
+                        } else {
+                            vec![vault((i * j) % 5)]
+                        };
+                        if i % 2 == 0 {
+                            resources.reverse();
+                        }
+                        let _guard = locks
+                            .lock(resources.clone(), Duration::from_secs(30))
+                            .expect("Must not time out");
+                        resources.sort();
+                        resources.dedup();
+                        {
+                            let mut held = held.lock().unwrap();
+                            for resource in &resources {
+                                assert!(!held.contains(resource));
+                                held.push(*resource);
+                            }
+                        }
+                        thread::sleep(Duration::from_micros(100));
+                        held.lock()
+                            .unwrap()
+                            .retain(|resource| !resources.contains(resource));
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(held.lock().unwrap().is_empty());
+    }
+}
@@ -4,55 +4,120 @@
 //! All commands here assume an accessible and sane database. They will **panic** on a failure
 //! to query it.
 
-mod utils;
+pub(crate) mod export;
+pub(crate) mod locks;
+pub(crate) mod utils;
 pub use crate::{
-    bitcoind::{interface::WalletTransaction, BitcoindError},
-    communication::ServerStatus,
+    amount::Amount,
+    bitcoind::{
+        audit::WalletAudit,
+        interface::{WalletTransaction, COINBASE_MATURITY},
+        pool::PoolStats,
+        relay_floor::{LowFeerateTx, RelayFloorCheck},
+        rescan::{ImportKind, RescanWindow},
+        BitcoindError,
+    },
+    buildinfo::BuildInfo,
+    communication::{CoordinatorStatus, ServerStatus},
+    derivation::DerivationIndex,
+    diskspace::DiskSpaceLevel,
     revaultd::{BlockchainTip, VaultStatus},
+    schedule::SpendingSchedule,
 };
 use crate::{
+    bitcoind::{
+        poller::deposit_min_conf,
+        utils::{presigned_transactions, vault_emergency_address},
+    },
+    buildinfo::build_info,
     communication::{
-        announce_spend_transaction, check_spend_transaction_size, coord_share_rev_signatures,
-        coordinator_status, cosigners_status, fetch_cosigs_signatures, share_unvault_signatures,
-        watchtowers_status, wts_share_rev_signatures, CommunicationError,
+        announce_spend_transaction, check_spend_transaction_size, coordinator_status,
+        cosigners_status, rejected_messages, throttle::ThrottleStatus, watchtowers_status,
+        wts_share_rev_signatures, CommunicationError,
     },
+    config::noise_pubkey_fingerprint,
     database::{
         actions::{
-            db_delete_spend, db_insert_spend, db_mark_activating_vault,
-            db_mark_broadcastable_spend, db_mark_securing_vault, db_update_presigned_txs,
-            db_update_spend, db_update_vault_status,
+            db_append_audit_entry, db_claim_idempotency_key, db_clear_vault_flag,
+            db_defer_revocation_txs, db_delete_spend, db_initiate_wallet_rotation,
+            db_insert_emergency_descriptor, db_insert_migration_spend, db_insert_spend,
+            db_insert_spend_proposal, db_insert_spend_proposal_ack, db_mark_activating_vault,
+            db_mark_broadcastable_spend, db_mark_securing_vault, db_queue_coordinator_sigs,
+            db_raise_vault_flag, db_record_spend_announcement, db_release_idempotency_key,
+            db_set_idempotency_result, db_sync_watchdata, db_update_presigned_txs, db_update_spend,
+            db_update_vault_status,
         },
+        bitcointx::{RevaultTx, TransactionType},
         interface::{
-            db_cancel_transaction, db_emer_transaction, db_list_spends, db_spend_transaction,
-            db_tip, db_unvault_emer_transaction, db_unvault_transaction, db_vault_by_deposit,
-            db_vault_by_unvault_txid, db_vaults, db_vaults_from_spend, db_vaults_min_status,
+            db_audit_log, db_cancel_transaction, db_coordinator_outbox_len,
+            db_deposit_coinbase_height, db_derived_scripts, db_emer_transaction, db_final_txids,
+            db_last_emergency_descriptor, db_last_revocation_check, db_list_spends,
+            db_max_deposit_index, db_pending_rotation, db_revocation_checks, db_sig_missing,
+            db_spend_announcement, db_spend_deprecation, db_spend_expiration, db_spend_proposal,
+            db_spend_proposal_acks, db_spend_proposals, db_spend_transaction,
+            db_spend_unvaults_broadcast, db_stale_revocations, db_tip, db_tx_conflicts,
+            db_unvault_emer_transaction, db_unvault_transaction, db_vault_by_deposit,
+            db_vault_by_unvault_txid, db_vault_emergency_address, db_vault_final_txids,
+            db_vault_migration, db_vault_transitions, db_vaults, db_vaults_from_spend,
+            db_vaults_min_status, db_version_first_run, db_wallet_by_id, db_watchdata,
         },
+        schema::{
+            AnnouncementStatus, DbDepositAncestry, DbFinalTxid, DbMempoolConflict,
+            DbRevocationCheck, DbSigningContext, DbSpendAnnouncement, DbTransaction, DbVault,
+            DbVaultFlag, DepositOrigin, SpendExpiryReason, VaultFlagKind,
+        },
+        DatabaseError,
+    },
+    indexallocator::IndexAllocError,
+    logger::LogLevels,
+    revaultd::{
+        descriptor_address, descriptors_script_limits, descriptors_script_version, ScriptLimits,
+        ScriptTypeError,
     },
-    threadmessages::BitcoindThread,
+    setup::deployment_descriptors,
+    threadmessages::{BitcoindThread, StateMachineThread, UnvaultsBroadcast},
     DaemonControl, VERSION,
 };
+pub use export::ExportKind;
+use export::{export_csv, CappedBuffer, MAX_INLINE_EXPORT_SIZE};
+use locks::{LockedResource, LOCK_TIMEOUT};
 use utils::{
-    deser_amount_from_sats, deser_from_str, finalized_emer_txs, gethistory, listvaults_from_db,
-    presigned_txs, ser_amount, ser_to_string, serialize_option_tx_hex, vaults_from_deposits,
+    check_disk_space, check_emergency_key_proof, check_not_migrating, check_spend_destinations,
+    check_spend_fees, check_spend_proposal, check_spend_proposal_ack, cosigners_entries,
+    cpfp_reserve, derive_emergency_descriptor, deser_from_str, emergency_broadcast, expire_spend,
+    fetch_cosigs_signatures, gethistory, invalid_signature_diagnostic, listvaults_from_db,
+    manager_xpub, missing_our_signature_diagnostic, participants, presigned_txs,
+    reused_destinations, ser_to_string, serialize_option_tx_hex, sort_spend_txins,
+    spend_approval_threshold, spend_cosigners, spend_proposal_entry, spend_proposal_status,
+    spend_tx_with_change, spend_txouts, vaults_at_heights, vaults_from_deposits,
 };
 
+use revault_net::noise::PublicKey as NoisePubKey;
 use revault_tx::{
     bitcoin::{
-        consensus::encode, secp256k1, util::bip32, Address, Amount, Network, OutPoint,
-        PublicKey as BitcoinPubKey, Transaction as BitcoinTransaction, TxOut, Txid,
+        consensus::encode,
+        hashes::{hex::ToHex, sha256, Hash},
+        secp256k1,
+        util::bip32,
+        Address, Amount as BitcoinAmount, Network, OutPoint, PublicKey as BitcoinPubKey, Script,
+        Transaction as BitcoinTransaction, Txid,
     },
-    miniscript::DescriptorTrait,
+    miniscript::{descriptor::DescriptorPublicKey, DescriptorTrait},
     scripts::{CpfpDescriptor, DepositDescriptor, UnvaultDescriptor},
     transactions::{
-        spend_tx_from_deposits, transaction_chain, CancelTransaction, CpfpableTransaction,
-        EmergencyTransaction, RevaultTransaction, SpendTransaction, UnvaultEmergencyTransaction,
-        UnvaultTransaction,
+        transaction_chain, CancelTransaction, CpfpableTransaction, EmergencyTransaction,
+        RevaultTransaction, SpendTransaction, UnvaultEmergencyTransaction, UnvaultTransaction,
     },
     txins::DepositTxIn,
-    txouts::{DepositTxOut, SpendTxOut},
+    txouts::DepositTxOut,
 };
 
-use std::{collections::BTreeMap, fmt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io, iter,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -70,6 +135,10 @@ pub enum CommandError {
     Tx(revault_tx::Error),
     /// (Required, Actual)
     SpendFeerateTooLow(u64, u64),
+    /// (Given, Minimum)
+    SpendFeerateBelowRelay(u64, u64),
+    /// (Fees, Spent, Threshold)
+    SpendFeesTooHigh(BitcoinAmount, BitcoinAmount, BitcoinAmount),
     SpendTooLarge,
     SpendUnknownUnVault(Txid),
     UnknownSpend(Txid),
@@ -80,7 +149,67 @@ pub enum CommandError {
     MissingCpfpKey,
     ManagerOnly,
     StakeholderOnly,
+    /// Only the clients configured as watchtowers may call this command
+    WatchtowerClientOnly,
     Race,
+    /// (Rejected, Competing) transactions
+    MempoolConflict(Txid, Option<Txid>),
+    /// bitcoind would not accept the Unvault transactions of these vaults (Deposit, Reason)
+    UnvaultsRejected(Vec<(OutPoint, String)>),
+    /// We could not record the command in the audit log
+    AuditLog(DatabaseError),
+    /// (Given, Limit)
+    TooManyElements(usize, usize),
+    /// (Destination, Our network)
+    SpendWrongNetwork(Address, Network),
+    SpendBurnAddress(Address),
+    SpendSelfSend(Address),
+    SpendToUnvault(Address),
+    /// A confirmed Spend transaction already paid to these destinations
+    SpendReusedDestinations(Vec<Address>),
+    NoActiveFlag(OutPoint, VaultFlagKind),
+    /// (Available, Threshold) bytes
+    DiskSpaceCritical(u64, u64),
+    ReadOnly,
+    /// The idempotency key was used for another command, or with other parameters
+    IdempotencyKeyConflict(String),
+    /// The command sent with this idempotency key is still being executed
+    IdempotencyKeyInProgress(String),
+    /// We could not record the idempotency key
+    IdempotencyKey(DatabaseError),
+    UnknownSpendProposal(u32),
+    SpendProposalExpired(u32),
+    /// A Spend transaction may only be created for an approved proposal
+    SpendProposalRequired,
+    SpendProposalNotApproved(u32, SpendProposalStatus),
+    /// The Spend parameters are not the ones of this proposal
+    SpendProposalMismatch(u32),
+    SpendProposalInvalidAck(String),
+    SpendProposalAlreadyDecided(u32, bip32::Fingerprint),
+    /// The spending schedule doesn't allow it now (Start of the next window, if any)
+    OutsideSpendingSchedule(Option<u64>),
+    /// Another command kept this resource locked for too long
+    Busy(LockedResource),
+    /// We could not write the export
+    Export(io::Error),
+    /// The export is larger than the limit for returning it inline (Limit)
+    ExportTooLarge(usize),
+    /// We could not stream the response to the client
+    Stream(io::Error),
+    WalletRotationInProgress,
+    NoWalletRotation,
+    /// The vault is being moved to the new wallet of a rotation
+    VaultMigrating(OutPoint),
+    /// We could not derive an address from our descriptors
+    ScriptType(ScriptTypeError),
+    /// The Spend transaction was never announced, there is nothing to abort
+    SpendNotAnnounced(Txid),
+    /// The Unvault transactions of this Spend were already broadcast
+    SpendUnvaultsBroadcast(Txid),
+    /// We could not hand out a new deposit address
+    DepositIndex(IndexAllocError),
+    /// The deposit output of this vault doesn't exist anymore
+    DepositVanished(OutPoint),
 }
 
 impl fmt::Display for CommandError {
@@ -104,6 +233,17 @@ impl fmt::Display for CommandError {
                 "Required feerate ('{}') is significantly higher than actual feerate ('{}')",
                 req, actual
             ),
+            Self::SpendFeerateBelowRelay(given, min) => write!(
+                f,
+                "Feerate ('{}' sat/vB) is below our bitcoind's minimum relay feerate ('{}' sat/vB)",
+                given, min
+            ),
+            Self::SpendFeesTooHigh(fees, spent, threshold) => write!(
+                f,
+                "Spend transaction would pay '{}' of fees out of '{}' spent, above the threshold \
+                 of '{}'. Double-check the feerate (in sat/vB) or explicitly allow high fees.",
+                fees, spent, threshold
+            ),
             Self::SpendTooLarge => write!(
                 f,
                 "Spend transaction is too large, try spending less outpoints"
@@ -145,7 +285,167 @@ impl fmt::Display for CommandError {
             Self::ManagerOnly => {
                 write!(f, "This is a manager command")
             }
+            Self::WatchtowerClientOnly => {
+                write!(f, "This command is restricted to the watchtower clients")
+            }
             Self::Race => write!(f, "Internal error due to a race. Please try again."),
+            Self::MempoolConflict(rejected, Some(competing)) => write!(
+                f,
+                "Transaction '{}' conflicts with transaction '{}' in mempool",
+                rejected, competing
+            ),
+            Self::MempoolConflict(rejected, None) => write!(
+                f,
+                "Transaction '{}' conflicts with a transaction in mempool",
+                rejected
+            ),
+            Self::UnvaultsRejected(rejected) => write!(
+                f,
+                "bitcoind would not accept the Unvault transaction of vault(s) {}, none was \
+                 broadcast",
+                rejected
+                    .iter()
+                    .map(|(outpoint, reason)| format!("'{}' ('{}')", outpoint, reason))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::AuditLog(e) => write!(f, "Could not write to the audit log: '{}'", e),
+            Self::TooManyElements(given, limit) => write!(
+                f,
+                "Too many elements: got '{}' but the limit is '{}'",
+                given, limit
+            ),
+            Self::SpendWrongNetwork(addr, network) => {
+                write!(f, "Destination '{}' is not a '{}' address", addr, network)
+            }
+            Self::SpendBurnAddress(addr) => write!(
+                f,
+                "Destination '{}' is a burn address, coins sent there can't ever be spent",
+                addr
+            ),
+            Self::SpendSelfSend(addr) => write!(
+                f,
+                "Destination '{}' is one of our deposit addresses, the coins would not leave \
+                 the vaults. Explicitly allow sending to ourselves if that's intended.",
+                addr
+            ),
+            Self::SpendToUnvault(addr) => write!(
+                f,
+                "Destination '{}' is one of our Unvault addresses, coins sent there could not \
+                 be spent through the vaults' presigned transactions",
+                addr
+            ),
+            Self::SpendReusedDestinations(addrs) => write!(
+                f,
+                "Destination(s) '{}' were already paid to by a previous Spend transaction",
+                addrs
+                    .iter()
+                    .map(|addr| addr.to_string())
+                    .collect::<Vec<_>>()
+                    .join("', '")
+            ),
+            Self::NoActiveFlag(outpoint, kind) => {
+                write!(f, "Vault at '{}' has no active '{}' flag", outpoint, kind)
+            }
+            Self::DiskSpaceCritical(available, threshold) => write!(
+                f,
+                "Only '{}' bytes left on disk, below the critical threshold of '{}' bytes. \
+                 Not storing new transactions until some space is freed.",
+                available, threshold
+            ),
+            Self::ReadOnly => write!(
+                f,
+                "This daemon is running in read-only mode, it only monitors the vaults"
+            ),
+            Self::IdempotencyKeyConflict(key) => write!(
+                f,
+                "Idempotency key '{}' was already used for another command or other parameters",
+                key
+            ),
+            Self::IdempotencyKeyInProgress(key) => write!(
+                f,
+                "The command with idempotency key '{}' is still in progress",
+                key
+            ),
+            Self::IdempotencyKey(e) => write!(f, "Could not record the idempotency key: '{}'", e),
+            Self::UnknownSpendProposal(id) => write!(f, "Unknown spend proposal '{}'", id),
+            Self::SpendProposalExpired(id) => write!(f, "Spend proposal '{}' expired", id),
+            Self::SpendProposalRequired => write!(
+                f,
+                "Spend transactions need to be proposed and approved first, pass the id of \
+                 an approved spend proposal"
+            ),
+            Self::SpendProposalNotApproved(id, status) => write!(
+                f,
+                "Spend proposal '{}' is '{}', it needs to be approved",
+                id, status
+            ),
+            Self::SpendProposalMismatch(id) => write!(
+                f,
+                "The outpoints, destinations or feerate are not the ones of spend proposal '{}'",
+                id
+            ),
+            Self::SpendProposalInvalidAck(reason) => {
+                write!(f, "Invalid spend proposal ack: {}", reason)
+            }
+            Self::SpendProposalAlreadyDecided(id, fingerprint) => write!(
+                f,
+                "Manager '{}' already decided about spend proposal '{}'",
+                fingerprint, id
+            ),
+            Self::OutsideSpendingSchedule(Some(next)) => write!(
+                f,
+                "The spending schedule doesn't allow spending now. Next window starts at '{}' \
+                 (UNIX timestamp).",
+                next
+            ),
+            Self::OutsideSpendingSchedule(None) => write!(
+                f,
+                "The spending schedule doesn't allow spending now, nor in the coming week"
+            ),
+            Self::Export(e) => write!(f, "Could not write the export: '{}'", e),
+            Self::ExportTooLarge(limit) => write!(
+                f,
+                "The export is larger than '{}' bytes, write it to a file instead",
+                limit
+            ),
+            Self::Stream(e) => write!(f, "Could not stream the response: '{}'", e),
+            Self::Busy(resource) => write!(
+                f,
+                "Another command is using {}. Please try again later.",
+                resource
+            ),
+            Self::WalletRotationInProgress => {
+                write!(f, "The keys are already being rotated to a new wallet")
+            }
+            Self::NoWalletRotation => write!(
+                f,
+                "No rotation of the keys was initiated, see 'initiatewalletrotation'"
+            ),
+            Self::VaultMigrating(outpoint) => write!(
+                f,
+                "Vault at '{}' is being moved to the new wallet of the rotation of the keys",
+                outpoint
+            ),
+            Self::ScriptType(e) => write!(f, "{}", e),
+            Self::SpendNotAnnounced(txid) => write!(
+                f,
+                "Spend transaction '{}' was never announced, there is nothing to abort",
+                txid
+            ),
+            Self::SpendUnvaultsBroadcast(txid) => write!(
+                f,
+                "The Unvault transactions of Spend '{}' were already broadcast, use 'revault' \
+                 to cancel its vaults instead",
+                txid
+            ),
+            Self::DepositIndex(e) => write!(f, "{}", e),
+            Self::DepositVanished(outpoint) => write!(
+                f,
+                "The deposit at '{}' doesn't exist anymore: it was reorged out and replaced, or \
+                 double spent. The vault was flagged.",
+                outpoint
+            ),
         }
     }
 }
@@ -170,6 +470,18 @@ impl From<revault_tx::Error> for CommandError {
     }
 }
 
+impl From<ScriptTypeError> for CommandError {
+    fn from(e: ScriptTypeError) -> Self {
+        Self::ScriptType(e)
+    }
+}
+
+impl From<IndexAllocError> for CommandError {
+    fn from(e: IndexAllocError) -> Self {
+        Self::DepositIndex(e)
+    }
+}
+
 impl CommandError {
     pub fn code(&self) -> ErrorCode {
         match self {
@@ -184,10 +496,14 @@ impl CommandError {
                 CommunicationError::SpendTxStorage => ErrorCode::COORDINATOR_SPEND_STORE_ERROR,
                 CommunicationError::CosigAlreadySigned => ErrorCode::COSIGNER_ALREADY_SIGN_ERROR,
                 CommunicationError::CosigInsanePsbt => ErrorCode::COSIGNER_INSANE_ERROR,
+                CommunicationError::CosigTimeout => ErrorCode::COSIGNER_TIMEOUT_ERROR,
+                CommunicationError::InvalidMessage(_) => ErrorCode::TRANSPORT_ERROR,
             },
             CommandError::Bitcoind(_) => ErrorCode::BITCOIND_ERROR,
             CommandError::Tx(_) => ErrorCode::INTERNAL_ERROR,
-            CommandError::SpendFeerateTooLow(_, _) => ErrorCode::INVALID_PARAMS,
+            CommandError::SpendFeerateTooLow(_, _)
+            | CommandError::SpendFeerateBelowRelay(_, _)
+            | CommandError::SpendFeesTooHigh(..) => ErrorCode::INVALID_PARAMS,
             // TODO: some of these probably need specific error codes
             CommandError::SpendTooLarge
             | CommandError::SpendUnknownUnVault(_)
@@ -197,8 +513,52 @@ impl CommandError {
             | CommandError::SpendInvalidSig(_)
             | CommandError::MissingCpfpKey => ErrorCode::INVALID_PARAMS,
 
-            CommandError::StakeholderOnly | CommandError::ManagerOnly => ErrorCode::INVALID_REQUEST,
+            CommandError::StakeholderOnly
+            | CommandError::ManagerOnly
+            | CommandError::WatchtowerClientOnly => ErrorCode::INVALID_REQUEST,
             CommandError::Race => ErrorCode::INTERNAL_ERROR,
+            CommandError::MempoolConflict(..) => ErrorCode::MEMPOOL_CONFLICT_ERROR,
+            CommandError::UnvaultsRejected(_) => ErrorCode::UNVAULT_REJECTED_ERROR,
+            CommandError::AuditLog(_) => ErrorCode::INTERNAL_ERROR,
+            CommandError::TooManyElements(..) => ErrorCode::TOO_MANY_ELEMENTS_ERROR,
+            CommandError::SpendWrongNetwork(..) => ErrorCode::SPEND_WRONG_NETWORK_ERROR,
+            CommandError::SpendBurnAddress(_) => ErrorCode::SPEND_BURN_ADDRESS_ERROR,
+            CommandError::SpendSelfSend(_) => ErrorCode::SPEND_SELF_SEND_ERROR,
+            CommandError::SpendToUnvault(_) => ErrorCode::SPEND_TO_UNVAULT_ERROR,
+            CommandError::SpendReusedDestinations(_) => ErrorCode::SPEND_REUSED_DESTINATION_ERROR,
+            CommandError::NoActiveFlag(..) => ErrorCode::RESOURCE_NOT_FOUND_ERROR,
+            CommandError::DiskSpaceCritical(..) => ErrorCode::DISK_SPACE_CRITICAL_ERROR,
+            CommandError::ReadOnly => ErrorCode::READ_ONLY_ERROR,
+            CommandError::IdempotencyKeyConflict(_) => ErrorCode::IDEMPOTENCY_KEY_CONFLICT_ERROR,
+            CommandError::IdempotencyKeyInProgress(_) => {
+                ErrorCode::IDEMPOTENCY_KEY_IN_PROGRESS_ERROR
+            }
+            CommandError::IdempotencyKey(_) => ErrorCode::INTERNAL_ERROR,
+            CommandError::UnknownSpendProposal(_) => ErrorCode::RESOURCE_NOT_FOUND_ERROR,
+            CommandError::SpendProposalExpired(_) => ErrorCode::SPEND_PROPOSAL_EXPIRED_ERROR,
+            CommandError::SpendProposalRequired | CommandError::SpendProposalNotApproved(..) => {
+                ErrorCode::SPEND_PROPOSAL_NOT_APPROVED_ERROR
+            }
+            CommandError::SpendProposalMismatch(_) => ErrorCode::INVALID_PARAMS,
+            CommandError::SpendProposalInvalidAck(_)
+            | CommandError::SpendProposalAlreadyDecided(..) => {
+                ErrorCode::SPEND_PROPOSAL_INVALID_ACK_ERROR
+            }
+            CommandError::OutsideSpendingSchedule(_) => ErrorCode::SPENDING_SCHEDULE_ERROR,
+            CommandError::Busy(_) => ErrorCode::BUSY_ERROR,
+            CommandError::Export(_) => ErrorCode::INTERNAL_ERROR,
+            CommandError::ExportTooLarge(_) => ErrorCode::INVALID_PARAMS,
+            CommandError::Stream(_) => ErrorCode::INTERNAL_ERROR,
+            CommandError::WalletRotationInProgress | CommandError::NoWalletRotation => {
+                ErrorCode::WALLET_ROTATION_ERROR
+            }
+            CommandError::VaultMigrating(_) => ErrorCode::INVALID_STATUS_ERROR,
+            CommandError::ScriptType(_) => ErrorCode::INTERNAL_ERROR,
+            CommandError::SpendNotAnnounced(_) => ErrorCode::INVALID_STATUS_ERROR,
+            CommandError::SpendUnvaultsBroadcast(_) => ErrorCode::SPEND_UNVAULTS_BROADCAST_ERROR,
+            CommandError::DepositIndex(IndexAllocError::Database(_)) => ErrorCode::INTERNAL_ERROR,
+            CommandError::DepositIndex(_) => ErrorCode::INVALID_REQUEST,
+            CommandError::DepositVanished(_) => ErrorCode::DEPOSIT_VANISHED_ERROR,
         }
     }
 }
@@ -223,12 +583,56 @@ pub enum ErrorCode {
     COSIGNER_ALREADY_SIGN_ERROR = 13201,
     /// The Cosigning Server tried to fool us!
     COSIGNER_INSANE_ERROR = 13202,
+    /// The Cosigning Server did not answer in time
+    COSIGNER_TIMEOUT_ERROR = 13203,
     /// Bitcoind error
     BITCOIND_ERROR = 14000,
+    /// The transaction conflicts with another one in bitcoind's mempool
+    MEMPOOL_CONFLICT_ERROR = 14001,
+    /// bitcoind would not accept some of the Unvault transactions of a Spend
+    UNVAULT_REJECTED_ERROR = 14002,
+    /// The deposit output of the vault was reorged out and doesn't exist anymore
+    DEPOSIT_VANISHED_ERROR = 14003,
     /// Resource not found
     RESOURCE_NOT_FOUND_ERROR = 15000,
     /// Vault status was invalid
     INVALID_STATUS_ERROR = 15001,
+    /// More elements were given than the configured limit
+    TOO_MANY_ELEMENTS_ERROR = 15002,
+    /// A Spend destination is not an address for our network
+    SPEND_WRONG_NETWORK_ERROR = 15003,
+    /// A Spend destination is provably unspendable
+    SPEND_BURN_ADDRESS_ERROR = 15004,
+    /// A Spend destination is one of our deposit addresses
+    SPEND_SELF_SEND_ERROR = 15005,
+    /// A Spend destination is one of our Unvault addresses
+    SPEND_TO_UNVAULT_ERROR = 15006,
+    /// The idempotency key was already used for another command or other parameters
+    IDEMPOTENCY_KEY_CONFLICT_ERROR = 15007,
+    /// The command sent with this idempotency key is still being executed
+    IDEMPOTENCY_KEY_IN_PROGRESS_ERROR = 15008,
+    /// A Spend transaction was requested without an approved spend proposal
+    SPEND_PROPOSAL_NOT_APPROVED_ERROR = 15009,
+    /// The spend proposal can't be decided upon nor used anymore
+    SPEND_PROPOSAL_EXPIRED_ERROR = 15010,
+    /// The manager's decision about a spend proposal was refused
+    SPEND_PROPOSAL_INVALID_ACK_ERROR = 15011,
+    /// A Spend destination was already paid to and reusing destinations is forbidden
+    SPEND_REUSED_DESTINATION_ERROR = 15012,
+    /// The spending schedule doesn't allow spending now
+    SPENDING_SCHEDULE_ERROR = 15013,
+    /// Another command is modifying the same vault or Spend transaction
+    BUSY_ERROR = 15014,
+    /// The keys are already being rotated, or aren't
+    WALLET_ROTATION_ERROR = 15015,
+    /// The Unvault transactions of a Spend were broadcast, it can't be aborted anymore
+    SPEND_UNVAULTS_BROADCAST_ERROR = 15016,
+    /// Not enough space left on disk to store new transactions
+    DISK_SPACE_CRITICAL_ERROR = 16000,
+    /// The daemon is running in read-only mode
+    READ_ONLY_ERROR = 16001,
+    /// The daemon implements an older API version than the client requires
+    API_VERSION_ERROR = 16002,
 }
 
 macro_rules! stakeholder_only {
@@ -239,6 +643,22 @@ macro_rules! stakeholder_only {
     };
 }
 
+// Make sure we were not given more elements than the configured limit
+fn check_elements_limit(given: usize, limit: usize) -> Result<(), CommandError> {
+    if given > limit {
+        return Err(CommandError::TooManyElements(given, limit));
+    }
+    Ok(())
+}
+
+macro_rules! not_read_only {
+    ($revaultd:ident) => {
+        if $revaultd.read_only {
+            return Err(CommandError::ReadOnly);
+        }
+    };
+}
+
 macro_rules! manager_only {
     ($revaultd:ident) => {
         if !$revaultd.is_manager() {
@@ -248,31 +668,75 @@ macro_rules! manager_only {
 }
 
 impl DaemonControl {
+    /// Get the metadata of the build of this binary, and when it first ran on our database
+    pub fn get_version(&self) -> VersionResult {
+        let revaultd = self.revaultd.read().unwrap();
+        let first_run_at = db_version_first_run(&revaultd.db_file(), VERSION)
+            .expect("Database must be available")
+            .expect("Recorded at startup");
+
+        VersionResult {
+            build: build_info(),
+            first_run_at,
+        }
+    }
+
     /// Get information about the current state of the daemon
     pub fn get_info(&self) -> GetInfoResult {
         let revaultd = self.revaultd.read().unwrap();
+        revaultd.log_levels.expire(revaultd.clock.unix_timestamp());
 
         // This means blockheight == 0 for IBD.
         let BlockchainTip {
             height: blockheight,
             ..
         } = db_tip(&revaultd.db_file()).expect("Database must not be dead");
-        let number_of_vaults = self
-            .list_vaults(None, None)
+        let number_of_vaults = listvaults_from_db(&revaultd, None, None)
+            .expect("Database must be available")
             .iter()
             .filter(|l| {
                 l.status != VaultStatus::Spent
                     && l.status != VaultStatus::Canceled
                     && l.status != VaultStatus::Unvaulted
+                    && l.status != VaultStatus::Spendable
                     && l.status != VaultStatus::EmergencyVaulted
             })
             .count();
+        let rejected_revocations = db_revocation_checks(&revaultd.db_file())
+            .expect("Database must be available")
+            .into_iter()
+            .filter_map(RejectedRevocation::from_check)
+            .collect();
+        let revocations_checked_at =
+            db_last_revocation_check(&revaultd.db_file()).expect("Database must be available");
+        let disk_space = revaultd
+            .disk_space()
+            .map_err(|e| log::warn!("Could not check the space available on disk: '{}'", e))
+            .ok();
+        let db_size = std::fs::metadata(revaultd.db_file())
+            .expect("Database must be available")
+            .len();
+        let max_observed =
+            db_max_deposit_index(&revaultd.db_file()).expect("Database must be available");
+        let sig_backlog = db_sig_missing(&revaultd.db_file()).expect("Database must be available");
+        let unsecured_amount = sig_backlog
+            .keys()
+            .filter(|vault| matches!(vault.status, VaultStatus::Funded | VaultStatus::Securing))
+            .fold(Amount::from_sat(0), |total, vault| {
+                total + vault.amount.into()
+            });
 
         GetInfoResult {
             version: VERSION.to_string(),
+            build: build_info(),
             network: revaultd.bitcoind_config.network,
             blockheight: blockheight as i32,
             sync: self.bitcoind_conn.sync_progress(),
+            rescan: self.bitcoind_conn.rescan_window(),
+            bitcoind_reachable: self.bitcoind_conn.is_reachable(),
+            bitcoind_connections: self.bitcoind_conn.connection_pools(),
+            read_only: revaultd.read_only,
+            db_synchronous: revaultd.db_synchronous.to_string(),
             vaults: number_of_vaults,
             managers_threshold: revaultd.managers_threshold(),
             descriptors: GetInfoDescriptors {
@@ -280,7 +744,169 @@ impl DaemonControl {
                 unvault: revaultd.unvault_descriptor.clone(),
                 cpfp: revaultd.cpfp_descriptor.clone(),
             },
+            paths: GetInfoPaths {
+                data_dir: revaultd.data_dir.clone(),
+                db: revaultd.db_file(),
+                log: revaultd.log_file(),
+                rpc_socket: revaultd.rpc_socket_file(),
+            },
+            limits: GetInfoLimits {
+                max_spend_inputs: revaultd.max_spend_inputs,
+                max_batch_size: revaultd.max_batch_size,
+            },
+            deposit_indexes: GetInfoDepositIndexes {
+                first_unused: revaultd.deposit_indexes.current(),
+                reserved: revaultd.deposit_indexes.reserved(),
+                max_observed,
+                watched_up_to: revaultd.last_derived_index(),
+                ceiling: revaultd.deposit_index_ceiling,
+            },
+            health: GetInfoHealth {
+                revocations_checked_at,
+                rejected_revocations,
+                disk_space: disk_space.map(|space| space.level),
+                available_disk_space: disk_space.map(|space| space.available),
+                db_size,
+                rejected_messages: rejected_messages(),
+                wallet_audited_at: revaultd.wallet_audit.as_ref().map(|a| a.checked_at),
+                unexpected_wallet_entries: revaultd
+                    .wallet_audit
+                    .as_ref()
+                    .map(|a| a.unexpected.clone())
+                    .unwrap_or_default(),
+                relay_floor: revaultd.relay_floor_check.clone(),
+            },
+            signatures: GetInfoSignatures {
+                backlog: sig_backlog.len(),
+                unsecured_amount,
+            },
+            spending_schedule: revaultd.spending_schedule.clone(),
+            log_levels: LogLevelsInfo::from(revaultd.log_levels.current()),
+        }
+    }
+
+    /// Get the value of our vaults, by how protected it is.
+    pub fn get_balances(&self) -> GetBalancesResult {
+        let revaultd = self.revaultd.read().unwrap();
+        let mut balances = GetBalancesResult::default();
+
+        for vault in listvaults_from_db(&revaultd, None, None).expect("Database must be available")
+        {
+            // The deposit of an abandoned vault was most likely replaced, we don't have the funds.
+            if vault.abandoned {
+                continue;
+            }
+
+            // Nor do we count on a deposit whose unconfirmed ancestry we refused.
+            if vault.status == VaultStatus::Unconfirmed
+                && vault
+                    .flags
+                    .iter()
+                    .any(|flag| flag.kind == VaultFlagKind::UnconfirmedAncestry)
+            {
+                continue;
+            }
+
+            // The funds of a vault that was canceled to a new one are accounted for by the latter.
+            if vault.revaulted_to.is_some()
+                && matches!(vault.status, VaultStatus::Canceling | VaultStatus::Canceled)
+            {
+                continue;
+            }
+
+            let balance = if vault.awaiting_resecuring {
+                &mut balances.awaiting_resecuring
+            } else {
+                match vault.status {
+                    VaultStatus::Unconfirmed => &mut balances.unconfirmed,
+                    VaultStatus::Funded | VaultStatus::Securing => &mut balances.unsecured,
+                    VaultStatus::Secured | VaultStatus::Activating => &mut balances.secured,
+                    VaultStatus::Active => &mut balances.active,
+                    VaultStatus::Unvaulting
+                    | VaultStatus::Unvaulted
+                    | VaultStatus::Spendable
+                    | VaultStatus::Spending
+                    | VaultStatus::Canceling => &mut balances.moving,
+                    VaultStatus::EmergencyVaulting => &mut balances.emergency_vaulting,
+                    VaultStatus::UnvaultEmergencyVaulting => {
+                        &mut balances.unvault_emergency_vaulting
+                    }
+                    VaultStatus::Spent
+                    | VaultStatus::Canceled
+                    | VaultStatus::EmergencyVaulted
+                    | VaultStatus::UnvaultEmergencyVaulted => continue,
+                }
+            };
+            *balance += vault.amount;
+
+            let balance = match vault.origin {
+                DepositOrigin::External => &mut balances.by_origin.external,
+                DepositOrigin::Cancel => &mut balances.by_origin.cancel,
+                DepositOrigin::SpendChange => &mut balances.by_origin.spend_change,
+            };
+            *balance += vault.amount;
+        }
+
+        balances
+    }
+
+    /// Get the fees needed to bring the Unvault transactions being broadcast (and optionally
+    /// the ones of the 'active' vaults) to this feerate, in sat/vbyte, through their CPFP output.
+    /// Along with the value available in the CPFP wallet to pay for them, if we have one.
+    ///
+    /// ## Errors
+    /// - If called for a non-manager
+    /// - If we can't get the balance of the CPFP wallet from bitcoind
+    pub fn get_cpfp_reserve(
+        &self,
+        feerate_vb: u64,
+        include_active: bool,
+    ) -> Result<CpfpReserve, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        manager_only!(revaultd);
+        let db_path = revaultd.db_file();
+
+        let mut reserve = CpfpReserve {
+            feerate: feerate_vb,
+            vaults: Vec::new(),
+            total: Amount::from_sat(0),
+            available: None,
+        };
+        for db_vault in db_vaults(&db_path).expect("Database must be available") {
+            if !(db_vault.status == VaultStatus::Unvaulting
+                || (include_active && db_vault.status == VaultStatus::Active))
+            {
+                continue;
+            }
+
+            let unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
+                .expect("Database must be available")
+                .expect("Must be there for 'active' and 'unvaulting' vaults")
+                .psbt
+                .assert_unvault();
+            let (weight, fees) = (unvault_tx.max_weight(), unvault_tx.fees());
+            let vault_reserve = cpfp_reserve(weight, fees, feerate_vb);
+            reserve.total += Amount::from(vault_reserve);
+            reserve.vaults.push(CpfpReserveEntry {
+                deposit_outpoint: db_vault.deposit_outpoint,
+                status: db_vault.status,
+                unvault_txid: unvault_tx.txid(),
+                feerate: fees / ((weight + 3) / 4),
+                reserve: vault_reserve.into(),
+            });
         }
+
+        if revaultd.cpfp_key.is_some() {
+            reserve.available = Some(self.bitcoind_conn.cpfp_balance()?.into());
+        }
+
+        Ok(reserve)
+    }
+
+    /// Compare the content of our watchonly wallet with the addresses we imported into it. The
+    /// missing ones are imported back, the ones we never imported are reported.
+    pub fn audit_wallet(&self) -> Result<WalletAudit, CommandError> {
+        Ok(self.bitcoind_conn.audit_wallet()?)
     }
 
     /// List the current vaults, optionally filtered by status and/or deposit outpoints.
@@ -288,20 +914,256 @@ impl DaemonControl {
         &self,
         statuses: Option<&[VaultStatus]>,
         deposit_outpoints: Option<&[OutPoint]>,
-    ) -> Vec<ListVaultsEntry> {
+    ) -> Result<Vec<ListVaultsEntry>, CommandError> {
+        self.list_vaults_at_heights(statuses, deposit_outpoints, &VaultHeightFilter::default())
+    }
+
+    /// List the vaults, optionally filtered by status, deposit outpoints and funding height.
+    /// If `as_of_height` is set the vaults are listed with the status they had at this height.
+    pub fn list_vaults_at_heights(
+        &self,
+        statuses: Option<&[VaultStatus]>,
+        deposit_outpoints: Option<&[OutPoint]>,
+        height_filter: &VaultHeightFilter,
+    ) -> Result<Vec<ListVaultsEntry>, CommandError> {
+        let mut vaults = Vec::new();
+        self.for_each_vault_at_heights(statuses, deposit_outpoints, height_filter, |vault| {
+            vaults.push(vault);
+            Ok(())
+        })?;
+
+        Ok(vaults)
+    }
+
+    /// Same as [DaemonControl::list_vaults_at_heights], but hands the vaults to `on_vault` one
+    /// at a time as they are computed instead of collecting them. Stops at the first error it
+    /// returns.
+    pub fn for_each_vault_at_heights<F>(
+        &self,
+        statuses: Option<&[VaultStatus]>,
+        deposit_outpoints: Option<&[OutPoint]>,
+        height_filter: &VaultHeightFilter,
+        mut on_vault: F,
+    ) -> Result<(), CommandError>
+    where
+        F: FnMut(ListVaultsEntry) -> Result<(), CommandError>,
+    {
         let revaultd = self.revaultd.read().unwrap();
-        listvaults_from_db(&revaultd, statuses, deposit_outpoints)
+        if let Some(outpoints) = deposit_outpoints {
+            check_elements_limit(outpoints.len(), revaultd.max_batch_size)?;
+        }
+        if let (Some(min), Some(max)) = (
+            height_filter.min_funded_height,
+            height_filter.max_funded_height,
+        ) {
+            if min > max {
+                return Err(CommandError::InvalidParams(format!(
+                    "Minimum funding height '{}' is above the maximum one '{}'",
+                    min, max
+                )));
+            }
+        }
+
+        for vault in vaults_at_heights(&revaultd, statuses, deposit_outpoints, height_filter)
             .expect("Database must be available")
+        {
+            on_vault(vault.expect("Database must be available"))?;
+        }
+
+        Ok(())
     }
 
     /// Get the deposit address at the lowest still unused derivation index
-    pub fn get_deposit_address(&self) -> Address {
-        self.revaultd.read().unwrap().deposit_address()
+    pub fn get_deposit_address(&self) -> Result<Address, CommandError> {
+        Ok(self.revaultd.read().unwrap().deposit_address()?)
+    }
+
+    /// Get a deposit address no one else will get from us, at the lowest still unused
+    /// derivation index. The next one is then handed out by `get_deposit_address`.
+    ///
+    /// ## Errors
+    /// - If too many of the addresses handed out this way weren't paid yet
+    /// - If we don't watch the address at the next index (the ceiling was reached)
+    pub fn get_new_deposit_address(&self) -> Result<NewDepositAddress, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        let derivation_index = revaultd.deposit_indexes.allocate(
+            &revaultd.db_file(),
+            revaultd.max_reserved_indexes(),
+            revaultd.last_derived_index(),
+        )?;
+
+        Ok(NewDepositAddress {
+            address: revaultd.vault_address(derivation_index)?,
+            derivation_index,
+        })
     }
 
     // Internal only, used for testing
-    pub(crate) fn get_deposit_address_at(&self, index: bip32::ChildNumber) -> Address {
-        self.revaultd.read().unwrap().vault_address(index)
+    pub(crate) fn get_deposit_address_at(
+        &self,
+        index: DerivationIndex,
+    ) -> Result<Address, CommandError> {
+        Ok(self.revaultd.read().unwrap().vault_address(index)?)
+    }
+
+    /// Get the scripts, addresses and presigned transactions ids of the vault identified by this
+    /// outpoint, as derived at its own derivation index. Doesn't need bitcoind.
+    ///
+    /// ## Errors
+    /// - If called for an unknown vault
+    pub fn get_vault_details(
+        &self,
+        deposit_outpoint: OutPoint,
+    ) -> Result<VaultDetails, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        let vault = db_vault_by_deposit(&revaultd.db_file(), &deposit_outpoint)
+            .expect("Database must be available")
+            .ok_or_else(|| CommandError::UnknownOutpoint(deposit_outpoint))?;
+        let index = vault.derivation_index;
+        let network = revaultd.bitcoind_config.network;
+
+        let deposit_descriptor = revaultd.derived_deposit_descriptor(index);
+        let unvault_descriptor = revaultd.derived_unvault_descriptor(index);
+        let cpfp_descriptor = revaultd.derived_cpfp_descriptor(index);
+        let emer_address = vault_emergency_address(
+            &revaultd,
+            db_vault_emergency_address(&revaultd.db_file(), vault.id)
+                .expect("Database must be available"),
+            vault.amount,
+        );
+        let (unvault_tx, cancel_tx, emer_tx, unemer_tx) = presigned_transactions(
+            &revaultd,
+            deposit_outpoint,
+            vault.amount,
+            index,
+            emer_address.clone(),
+        )
+        .expect("We wouldn't have put a vault with an invalid chain in DB");
+        let final_txids = db_vault_final_txids(&revaultd.db_file(), vault.id)
+            .expect("Database must be available");
+
+        Ok(VaultDetails {
+            deposit_outpoint,
+            derivation_index: index,
+            deposit_address: descriptor_address(deposit_descriptor.inner(), network)?,
+            deposit_script_pubkey: deposit_descriptor.inner().script_pubkey(),
+            deposit_witness_script: deposit_descriptor.inner().explicit_script(),
+            unvault_address: descriptor_address(unvault_descriptor.inner(), network)?,
+            unvault_script_pubkey: unvault_descriptor.inner().script_pubkey(),
+            unvault_witness_script: unvault_descriptor.inner().explicit_script(),
+            cpfp_address: descriptor_address(cpfp_descriptor.inner(), network)?,
+            cpfp_script_pubkey: cpfp_descriptor.inner().script_pubkey(),
+            emergency_address: emer_address.map(|emer| emer.address().clone()),
+            txids: VaultTxids {
+                unvault: unvault_tx.txid(),
+                cancel: cancel_tx.txid(),
+                emergency: emer_tx.map(|tx| tx.txid()),
+                unvault_emergency: unemer_tx.map(|tx| tx.txid()),
+            },
+            final_txids: VaultFinalTxids::from_db(&final_txids),
+        })
+    }
+
+    /// Check that this descriptor of the stakeholders' deep-cold setup generates one of the
+    /// configured Emergency addresses, or re-verify the last stored one if none is given. A message signed by
+    /// one of its keys may be given as a stronger proof of control. Nothing is stored, unless
+    /// asked to and the descriptor matches.
+    ///
+    /// ## Errors
+    /// - If called for a non-stakeholder
+    /// - If the descriptor is invalid, or the derivation index is missing or unexpected
+    /// - If no descriptor is given and none was stored
+    /// - If asked to store it in read-only mode or if the disk space is critically low
+    pub fn verify_emergency_descriptor(
+        &self,
+        descriptor: Option<&str>,
+        derivation_index: Option<u32>,
+        proof: Option<&EmergencyKeyProof>,
+        store: bool,
+    ) -> Result<EmergencyDescriptorVerification, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        stakeholder_only!(revaultd);
+        if store {
+            not_read_only!(revaultd);
+            check_disk_space(&revaultd)?;
+        }
+        let db_path = revaultd.db_file();
+        let emergency_addresses = revaultd
+            .emergency_addresses
+            .as_ref()
+            .expect("We are a stakeholder");
+
+        let (descriptor, derivation_index, already_stored) = match descriptor {
+            Some(descriptor) => (descriptor.to_string(), derivation_index, false),
+            None => {
+                if derivation_index.is_some() {
+                    return Err(CommandError::InvalidParams(
+                        "A derivation index was given without a descriptor".to_string(),
+                    ));
+                }
+                let stored = db_last_emergency_descriptor(&db_path)
+                    .expect("Database must be available")
+                    .ok_or_else(|| {
+                        CommandError::InvalidParams(
+                            "No descriptor was given, and none was stored".to_string(),
+                        )
+                    })?;
+                (stored.descriptor, stored.derivation_index, true)
+            }
+        };
+
+        let derived =
+            derive_emergency_descriptor(&revaultd.secp_ctx, &descriptor, derivation_index)?;
+        let derived_address = derived
+            .address(revaultd.bitcoind_config.network)
+            .map_err(|e| {
+                CommandError::InvalidParams(format!(
+                    "Descriptor '{}' has no address: {}",
+                    descriptor, e
+                ))
+            })?;
+        let matches = emergency_addresses.contains(&derived_address);
+        // The one it generates, or the first one configured to tell what it should have been
+        let emergency_address = if matches {
+            derived_address.clone()
+        } else {
+            emergency_addresses.bands()[0].address.address().clone()
+        };
+        let proof_valid = proof
+            .map(|proof| check_emergency_key_proof(&revaultd.secp_ctx, &derived, proof))
+            .transpose()?;
+
+        if !matches {
+            log::warn!(
+                "Descriptor '{}' generates '{}', not one of our Emergency addresses",
+                descriptor,
+                derived_address
+            );
+        }
+        let stored = if already_stored {
+            true
+        } else if store && matches {
+            db_insert_emergency_descriptor(
+                &db_path,
+                &descriptor,
+                derivation_index,
+                revaultd.clock.unix_timestamp(),
+            )
+            .expect("Database must be available");
+            true
+        } else {
+            false
+        };
+
+        Ok(EmergencyDescriptorVerification {
+            descriptor,
+            derivation_index,
+            derived_address,
+            emergency_address,
+            matches,
+            proof_valid,
+            stored,
+        })
     }
 
     /// Get the revocation transactions for the vault identified by this outpoint.
@@ -329,17 +1191,19 @@ impl DaemonControl {
             ));
         };
 
-        let emer_address = revaultd
-            .emergency_address
-            .clone()
-            .expect("Must be stakeholder");
+        let emer_address = vault_emergency_address(
+            &revaultd,
+            db_vault_emergency_address(db_path, vault.id).expect("Database must be available"),
+            vault.amount,
+        )
+        .expect("Must be stakeholder");
         let (_, cancel_tx, emergency_tx, emergency_unvault_tx) = transaction_chain(
             deposit_outpoint,
             vault.amount,
             &revaultd.deposit_descriptor,
             &revaultd.unvault_descriptor,
             &revaultd.cpfp_descriptor,
-            vault.derivation_index,
+            vault.derivation_index.into(),
             emer_address,
             revaultd.lock_time,
             &revaultd.secp_ctx,
@@ -353,12 +1217,52 @@ impl DaemonControl {
         })
     }
 
+    /// Get the revocation transactions for all the vaults at these outpoints. If `outpoints` is
+    /// None, get them for the 'funded' vaults, up to the batch size limit.
+    ///
+    /// ## Errors
+    /// - If called by a non-stakeholder
+    /// - If given more outpoints than the batch size limit
+    /// - If any outpoint refers to an unknown or unconfirmed vault
+    pub fn get_revocation_txs_batch(
+        &self,
+        outpoints: Option<&[OutPoint]>,
+    ) -> Result<BTreeMap<OutPoint, RevocationTransactions>, CommandError> {
+        let outpoints = {
+            let revaultd = self.revaultd.read().unwrap();
+            stakeholder_only!(revaultd);
+            match outpoints {
+                Some(outpoints) => {
+                    check_elements_limit(outpoints.len(), revaultd.max_batch_size)?;
+                    outpoints.to_vec()
+                }
+                None => db_vaults(&revaultd.db_file())
+                    .expect("Database must be available")
+                    .into_iter()
+                    .filter(|v| v.status == VaultStatus::Funded)
+                    .take(revaultd.max_batch_size)
+                    .map(|v| v.deposit_outpoint)
+                    .collect(),
+            }
+        };
+
+        outpoints
+            .into_iter()
+            .map(|outpoint| Ok((outpoint, self.get_revocation_txs(outpoint)?)))
+            .collect()
+    }
+
     /// Set the signed revocation transactions for the vault at this outpoint.
     ///
     /// ## Errors
     /// - If called for a non-stakeholder
-    /// - If called for an unknown or not 'funded' vault
+    /// - If called for an unknown or not 'funded' vault (a vault 'funded' before its deposit
+    ///   got unconfirmed by a reorg is accepted: the signatures are kept until it's confirmed
+    ///   again)
     /// - If given insane revocation txs PSBTs (without our signatures, with invalid sigs, ..)
+    /// - If the deposit doesn't exist anymore, in which case the vault is flagged
+    /// - If running in read-only mode
+    /// - If the space left on disk is below the critical threshold
     pub fn set_revocation_txs(
         &self,
         deposit_outpoint: OutPoint,
@@ -367,52 +1271,94 @@ impl DaemonControl {
         unvault_emergency_tx: UnvaultEmergencyTransaction,
     ) -> Result<(), CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
         stakeholder_only!(revaultd);
+        check_disk_space(&revaultd)?;
         let db_path = revaultd.db_file();
         let secp_ctx = &revaultd.secp_ctx;
+        let _locks = self
+            .resource_locks
+            .lock(vec![LockedResource::Vault(deposit_outpoint)], LOCK_TIMEOUT)?;
 
         assert!(revaultd.is_stakeholder());
 
         // They may only send revocation transactions for confirmed and not-yet-presigned
-        // vaults.
+        // vaults. The deposit may also have been unconfirmed by a reorg since they fetched
+        // them, in which case we dropped its presigned transactions: check theirs against the
+        // ones we'll create anew once it's confirmed again.
         let db_vault = db_vault_by_deposit(&db_path, &deposit_outpoint)
             .expect("Database must be available")
             .ok_or_else(|| CommandError::UnknownOutpoint(deposit_outpoint))?;
-        if !matches!(db_vault.status, VaultStatus::Funded) {
-            return Err(CommandError::InvalidStatus(
-                db_vault.status,
-                VaultStatus::Funded,
-            ));
+        let db_txs = match db_vault.status {
+            VaultStatus::Funded => {
+                let cancel = db_cancel_transaction(&db_path, db_vault.id)
+                    .expect("The database must be available")
+                    .ok_or(CommandError::Race)?;
+                let emer = db_emer_transaction(&db_path, db_vault.id)
+                    .expect("The database must be available")
+                    .ok_or(CommandError::Race)?;
+                let unvault_emer = db_unvault_emer_transaction(&db_path, db_vault.id)
+                    .expect("The database must be available")
+                    .ok_or(CommandError::Race)?;
+                Some((cancel, emer, unvault_emer))
+            }
+            VaultStatus::Unconfirmed
+                if db_vault_transitions(&db_path, db_vault.id)
+                    .expect("Database must be available")
+                    .iter()
+                    .any(|t| t.status == VaultStatus::Funded) =>
+            {
+                None
+            }
+            status => return Err(CommandError::InvalidStatus(status, VaultStatus::Funded)),
+        };
+        let (mut cancel_psbt, mut emer_psbt, mut unvault_emer_psbt) = match db_txs {
+            Some((ref cancel, ref emer, ref unvault_emer)) => (
+                cancel.psbt.clone(),
+                emer.psbt.clone(),
+                unvault_emer.psbt.clone(),
+            ),
+            None => {
+                let emer_address = vault_emergency_address(
+                    &revaultd,
+                    db_vault_emergency_address(&db_path, db_vault.id)
+                        .expect("Database must be available"),
+                    db_vault.amount,
+                );
+                let (_, cancel, emer, unvault_emer) = presigned_transactions(
+                    &revaultd,
+                    deposit_outpoint,
+                    db_vault.amount,
+                    db_vault.derivation_index,
+                    emer_address,
+                )?;
+                (
+                    RevaultTx::Cancel(cancel),
+                    RevaultTx::Emergency(emer.expect("We are a stakeholder")),
+                    RevaultTx::UnvaultEmergency(unvault_emer.expect("We are a stakeholder")),
+                )
+            }
         };
 
         // Sanity check they didn't send us garbaged PSBTs
-        let mut cancel_db_tx = db_cancel_transaction(&db_path, db_vault.id)
-            .expect("The database must be available")
-            .ok_or(CommandError::Race)?;
         let rpc_txid = cancel_tx.tx().wtxid();
-        let db_txid = cancel_db_tx.psbt.wtxid();
+        let db_txid = cancel_psbt.wtxid();
         if rpc_txid != db_txid {
             return Err(CommandError::InvalidParams(format!(
                 "Invalid Cancel tx: db wtxid is '{}' but this PSBT's is '{}' ",
                 db_txid, rpc_txid
             )));
         }
-        let mut emer_db_tx = db_emer_transaction(&revaultd.db_file(), db_vault.id)
-            .expect("The database must be available")
-            .ok_or(CommandError::Race)?;
         let rpc_txid = emergency_tx.tx().wtxid();
-        let db_txid = emer_db_tx.psbt.wtxid();
+        let db_txid = emer_psbt.wtxid();
         if rpc_txid != db_txid {
             return Err(CommandError::InvalidParams(format!(
                 "Invalid Emergency tx: db wtxid is '{}' but this PSBT's is '{}' ",
                 db_txid, rpc_txid
             )));
         }
-        let mut unvault_emer_db_tx = db_unvault_emer_transaction(&revaultd.db_file(), db_vault.id)
-            .expect("The database must be available")
-            .ok_or(CommandError::Race)?;
         let rpc_txid = unvault_emergency_tx.tx().wtxid();
-        let db_txid = unvault_emer_db_tx.psbt.wtxid();
+        let db_txid = unvault_emer_psbt.wtxid();
         if rpc_txid != db_txid {
             return Err(CommandError::InvalidParams(format!(
                 "Invalid Unvault Emergency tx: db wtxid is '{}' but this PSBT's is '{}' ",
@@ -446,21 +1392,25 @@ impl DaemonControl {
             .our_stk_xpub_at(deriv_index)
             .expect("We are a stakeholder, checked at the beginning of the call.");
         if !cancel_sigs.contains_key(&our_pubkey) {
-            return Err(CommandError::InvalidParams(format!(
-                "No signature for ourselves ({}) in Cancel transaction",
-                our_pubkey
-            )));
-        }
+            return Err(CommandError::InvalidParams(
+                missing_our_signature_diagnostic(&revaultd, "Cancel", deriv_index, cancel_sigs),
+            ));
+        }
         // We use the same public key across the transaction chain, that's pretty
         // neat from an usability perspective.
         if !emer_sigs.contains_key(&our_pubkey) {
             return Err(CommandError::InvalidParams(
-                "No signature for ourselves in Emergency transaction".to_string(),
+                missing_our_signature_diagnostic(&revaultd, "Emergency", deriv_index, emer_sigs),
             ));
         }
         if !unvault_emer_sigs.contains_key(&our_pubkey) {
             return Err(CommandError::InvalidParams(
-                "No signature for ourselves in UnvaultEmergency transaction".to_string(),
+                missing_our_signature_diagnostic(
+                    &revaultd,
+                    "UnvaultEmergency",
+                    deriv_index,
+                    unvault_emer_sigs,
+                ),
             ));
         }
 
@@ -491,7 +1441,7 @@ impl DaemonControl {
             }
         }
 
-        // Add the signatures to the DB transactions.
+        // Add the signatures to our version of the transactions.
         for (key, sig) in cancel_sigs {
             if sig.is_empty() {
                 return Err(CommandError::InvalidParams(format!(
@@ -502,13 +1452,20 @@ impl DaemonControl {
             let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).map_err(|_| {
                 CommandError::InvalidParams(format!("Non DER signature in Cancel PSBT"))
             })?;
-            cancel_db_tx
-                .psbt
+            cancel_psbt
                 .add_signature(key.key, sig, secp_ctx)
                 .map_err(|e| {
                     CommandError::InvalidParams(format!(
-                        "Invalid signature '{}' in Cancel PSBT: '{}'",
-                        sig, e
+                        "Invalid signature '{}' in Cancel PSBT: '{}', {}",
+                        sig,
+                        e,
+                        invalid_signature_diagnostic(
+                            &revaultd,
+                            &cancel_psbt,
+                            deriv_index,
+                            key,
+                            sig
+                        )
                     ))
                 })?;
         }
@@ -522,13 +1479,14 @@ impl DaemonControl {
             let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).map_err(|_| {
                 CommandError::InvalidParams(format!("Non DER signature in Emergency PSBT"))
             })?;
-            emer_db_tx
-                .psbt
+            emer_psbt
                 .add_signature(key.key, sig, secp_ctx)
                 .map_err(|e| {
                     CommandError::InvalidParams(format!(
-                        "Invalid signature '{}' in Emergency PSBT: '{}'",
-                        sig, e
+                        "Invalid signature '{}' in Emergency PSBT: '{}', {}",
+                        sig,
+                        e,
+                        invalid_signature_diagnostic(&revaultd, &emer_psbt, deriv_index, key, sig)
                     ))
                 })?;
         }
@@ -542,23 +1500,104 @@ impl DaemonControl {
             let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).map_err(|_| {
                 CommandError::InvalidParams(format!("Non DER signature in UnvaultEmergency PSBT",))
             })?;
-            unvault_emer_db_tx
-                .psbt
+            unvault_emer_psbt
                 .add_signature(key.key, sig, secp_ctx)
                 .map_err(|e| {
                     CommandError::InvalidParams(format!(
-                        "Invalid signature '{}' in UnvaultEmergency PSBT: '{}'",
-                        sig, e
+                        "Invalid signature '{}' in UnvaultEmergency PSBT: '{}', {}",
+                        sig,
+                        e,
+                        invalid_signature_diagnostic(
+                            &revaultd,
+                            &unvault_emer_psbt,
+                            deriv_index,
+                            key,
+                            sig
+                        )
                     ))
                 })?;
         }
 
+        // The deposit may have been reorged out while they were signing, and we may not have
+        // noticed it yet. Check it against bitcoind before marking the vault as securing.
+        let rev_psbts = vec![cancel_psbt, emer_psbt, unvault_emer_psbt];
+        let min_conf = deposit_min_conf(
+            revaultd.min_conf,
+            db_deposit_coinbase_height(&db_path, db_vault.id)
+                .expect("Database must be available")
+                .is_some(),
+        );
+        let confirmations = match self
+            .bitcoind_conn
+            .outpoint_confirmations(deposit_outpoint)?
+        {
+            Some(confirmations) => confirmations,
+            None => {
+                db_raise_vault_flag(
+                    &db_path,
+                    db_vault.id,
+                    VaultFlagKind::DepositVanished,
+                    "Signatures for its revocation transactions were handed after its deposit \
+                     was reorged out and replaced, or double spent",
+                    revaultd.clock.unix_timestamp(),
+                )
+                .expect("Database must be available");
+                log_event!(
+                    log::Level::Error,
+                    "deposit_vanished",
+                    outpoint = deposit_outpoint;
+                    "Rejecting the signatures of the revocation transactions of vault at '{}': \
+                     its deposit doesn't exist anymore.",
+                    deposit_outpoint
+                );
+                return Err(CommandError::DepositVanished(deposit_outpoint));
+            }
+        };
+        let (cancel_db_tx, emer_db_tx, unvault_emer_db_tx) = match db_txs {
+            Some(db_txs) if confirmations >= min_conf => db_txs,
+            _ => {
+                // Keep them until it's confirmed again, the vault will be marked as securing
+                // then.
+                db_defer_revocation_txs(
+                    &db_path,
+                    db_vault.id,
+                    &rev_psbts,
+                    revaultd.clock.unix_timestamp(),
+                )
+                .expect("Database must be available");
+                log_event!(
+                    log::Level::Warn,
+                    "revocation_txs_deferred",
+                    outpoint = deposit_outpoint,
+                    confirmations = confirmations;
+                    "Deposit at '{}' only has {} confirmations (we need {}), keeping the \
+                     signatures of its revocation transactions until it's confirmed again.",
+                    deposit_outpoint,
+                    confirmations,
+                    min_conf
+                );
+                return Ok(());
+            }
+        };
+
         // Then add them to the PSBTs in database. Take care to update the vault
         // status if all signatures were given via the RPC.
-        let rev_txs = vec![cancel_db_tx, emer_db_tx, unvault_emer_db_tx];
-        db_update_presigned_txs(&db_path, &db_vault, rev_txs.clone(), secp_ctx)
+        let rev_txs: Vec<DbTransaction> = vec![cancel_db_tx, emer_db_tx, unvault_emer_db_tx]
+            .into_iter()
+            .zip(rev_psbts)
+            .map(|(db_tx, psbt)| DbTransaction { psbt, ..db_tx })
+            .collect();
+        db_update_presigned_txs(
+            &db_path,
+            &db_vault,
+            rev_txs.clone(),
+            revaultd.feerate_estimates,
+            secp_ctx,
+            revaultd.clock.unix_timestamp(),
+        )
+        .expect("The database must be available");
+        db_mark_securing_vault(&db_path, db_vault.id, revaultd.clock.unix_timestamp())
             .expect("The database must be available");
-        db_mark_securing_vault(&db_path, db_vault.id).expect("The database must be available");
 
         // Now, check whether this made all revocation transactions fully signed
         let emer_tx = db_emer_transaction(&db_path, db_vault.id)
@@ -588,6 +1627,7 @@ impl DaemonControl {
             if let Some(ref watchtowers) = revaultd.watchtowers {
                 wts_share_rev_signatures(
                     &revaultd.noise_secret,
+                    revaultd.bind_address,
                     &watchtowers,
                     db_vault.deposit_outpoint,
                     db_vault.derivation_index,
@@ -597,19 +1637,60 @@ impl DaemonControl {
                 )?;
             }
         }
-        db_update_vault_status(&db_path, &db_vault).expect("The database must be available");
+        db_update_vault_status(&db_path, &db_vault, revaultd.clock.unix_timestamp())
+            .expect("The database must be available");
 
-        // Share them with our felow stakeholders.
-        coord_share_rev_signatures(
-            revaultd.coordinator_host,
-            &revaultd.noise_secret,
-            &revaultd.coordinator_noisekey,
-            &rev_txs,
-        )?;
+        // Share them with our felow stakeholders. They are queued, the signature fetcher pushes
+        // them to the Coordinator.
+        let now = revaultd.clock.unix_timestamp();
+        for tx in &rev_txs {
+            db_queue_coordinator_sigs(&db_path, &tx.psbt.txid(), &tx.psbt.signatures(), now)
+                .expect("The database must be available");
+        }
 
         Ok(())
     }
 
+    /// Set the signed revocation transactions for all the vaults in this batch. Each vault is
+    /// processed (and stored, and locked against other commands) independently, so the outcome is
+    /// reported per vault.
+    ///
+    /// ## Errors
+    /// - If called for a non stakeholder
+    /// - If running in read-only mode
+    /// - If given more vaults than the batch size limit
+    pub fn set_revocation_txs_batch(
+        &self,
+        batch: BTreeMap<OutPoint, RevocationTransactions>,
+    ) -> Result<BTreeMap<OutPoint, Result<(), CommandError>>, CommandError> {
+        {
+            let revaultd = self.revaultd.read().unwrap();
+            not_read_only!(revaultd);
+            stakeholder_only!(revaultd);
+            check_elements_limit(batch.len(), revaultd.max_batch_size)?;
+        }
+
+        Ok(batch
+            .into_iter()
+            .map(|(outpoint, txs)| {
+                let res = self.set_revocation_txs(
+                    outpoint,
+                    txs.cancel_tx,
+                    txs.emergency_tx,
+                    txs.emergency_unvault_tx,
+                );
+                if let Err(ref e) = res {
+                    log::warn!(
+                        "Error setting revocation transactions for vault at '{}': '{}'",
+                        outpoint,
+                        e
+                    );
+                }
+                (outpoint, res)
+            })
+            .collect())
+    }
+
     /// Get the unvault transaction for the vault identified by this outpoint.
     /// Returns None if there are no *confirmed* vault at this outpoint.
     ///
@@ -638,19 +1719,13 @@ impl DaemonControl {
         }
 
         // Derive the descriptors needed to create the UnvaultTransaction
-        let deposit_descriptor = revaultd
-            .deposit_descriptor
-            .derive(vault.derivation_index, &revaultd.secp_ctx);
+        let deposit_descriptor = revaultd.derived_deposit_descriptor(vault.derivation_index);
         let deposit_txin = DepositTxIn::new(
             deposit_outpoint,
             DepositTxOut::new(vault.amount, &deposit_descriptor),
         );
-        let unvault_descriptor = revaultd
-            .unvault_descriptor
-            .derive(vault.derivation_index, &revaultd.secp_ctx);
-        let cpfp_descriptor = revaultd
-            .cpfp_descriptor
-            .derive(vault.derivation_index, &revaultd.secp_ctx);
+        let unvault_descriptor = revaultd.derived_unvault_descriptor(vault.derivation_index);
+        let cpfp_descriptor = revaultd.derived_cpfp_descriptor(vault.derivation_index);
 
         Ok(UnvaultTransaction::new(
             deposit_txin,
@@ -667,15 +1742,22 @@ impl DaemonControl {
     /// - If called for a non-stakeholder
     /// - If called for an unknown or not 'secured' vault
     /// - If passed an insane Unvault transaction (no sig for ourselves, invalid sig, ..)
+    /// - If running in read-only mode
+    /// - If the space left on disk is below the critical threshold
     pub fn set_unvault_tx(
         &self,
         deposit_outpoint: OutPoint,
         unvault_tx: UnvaultTransaction,
     ) -> Result<(), CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
         stakeholder_only!(revaultd);
+        check_disk_space(&revaultd)?;
         let db_path = revaultd.db_file();
         let secp_ctx = &revaultd.secp_ctx;
+        let _locks = self
+            .resource_locks
+            .lock(vec![LockedResource::Vault(deposit_outpoint)], LOCK_TIMEOUT)?;
 
         // If they haven't got all the signatures for the revocation transactions, we'd
         // better not send our unvault sig!
@@ -717,10 +1799,14 @@ impl DaemonControl {
         // They must have included *at least* a signature for our pubkey, and must not include an
         // unnecessary signature.
         if !sigs.contains_key(&our_key) {
-            return Err(CommandError::InvalidParams(format!(
-                "No signature for ourselves ({}) in Unvault transaction",
-                our_key
-            )));
+            return Err(CommandError::InvalidParams(
+                missing_our_signature_diagnostic(
+                    &revaultd,
+                    "Unvault",
+                    db_vault.derivation_index,
+                    sigs,
+                ),
+            ));
         }
 
         for (key, sig) in sigs {
@@ -747,23 +1833,41 @@ impl DaemonControl {
                 .add_signature(key.key, sig, secp_ctx)
                 .map_err(|e| {
                     CommandError::InvalidParams(format!(
-                        "Invalid signature '{}' in Unvault PSBT: '{}'",
-                        sig, e
+                        "Invalid signature '{}' in Unvault PSBT: '{}', {}",
+                        sig,
+                        e,
+                        invalid_signature_diagnostic(
+                            &revaultd,
+                            &unvault_db_tx.psbt,
+                            db_vault.derivation_index,
+                            key,
+                            sig
+                        )
                     ))
                 })?;
         }
 
         // Sanity checks passed. Store it then share it.
-        db_update_presigned_txs(&db_path, &db_vault, vec![unvault_db_tx.clone()], secp_ctx)
+        db_update_presigned_txs(
+            &db_path,
+            &db_vault,
+            vec![unvault_db_tx.clone()],
+            revaultd.feerate_estimates,
+            secp_ctx,
+            revaultd.clock.unix_timestamp(),
+        )
+        .expect("The database must be available");
+        db_mark_activating_vault(&db_path, db_vault.id, revaultd.clock.unix_timestamp())
             .expect("The database must be available");
-        db_mark_activating_vault(&db_path, db_vault.id).expect("The database must be available");
-        db_update_vault_status(&db_path, &db_vault).expect("The database must be available");
-        share_unvault_signatures(
-            revaultd.coordinator_host,
-            &revaultd.noise_secret,
-            &revaultd.coordinator_noisekey,
-            &unvault_db_tx,
-        )?;
+        db_update_vault_status(&db_path, &db_vault, revaultd.clock.unix_timestamp())
+            .expect("The database must be available");
+        db_queue_coordinator_sigs(
+            &db_path,
+            &unvault_db_tx.psbt.txid(),
+            &unvault_db_tx.psbt.signatures(),
+            revaultd.clock.unix_timestamp(),
+        )
+        .expect("The database must be available");
 
         Ok(())
     }
@@ -779,6 +1883,7 @@ impl DaemonControl {
         outpoints: &[OutPoint],
     ) -> Result<Vec<ListPresignedTxEntry>, CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        check_elements_limit(outpoints.len(), revaultd.max_batch_size)?;
         let db_path = revaultd.db_file();
         let db_vaults = if outpoints.is_empty() {
             db_vaults_min_status(&db_path, VaultStatus::Funded).expect("Database must be available")
@@ -789,6 +1894,187 @@ impl DaemonControl {
         presigned_txs(&revaultd, db_vaults).ok_or(CommandError::Race)
     }
 
+    /// List the vaults still to be protected by their revocation transactions that have some
+    /// signed at a feerate below `feerate` (in sat/vbyte), so that they may be signed again.
+    /// Transactions signed before we recorded the fee market at signing time are not accounted
+    /// for.
+    pub fn get_stale_revocations(&self, feerate: u64) -> Vec<StaleRevocationsEntry> {
+        let db_path = self.revaultd.read().unwrap().db_file();
+        let stale_txs =
+            db_stale_revocations(&db_path, feerate).expect("Database must be available");
+
+        let mut entries: Vec<StaleRevocationsEntry> = Vec::new();
+        for (db_vault, tx_type, txid, context) in stale_txs {
+            let tx = StaleRevocationTx {
+                tx_type: match tx_type {
+                    TransactionType::Cancel => "cancel",
+                    TransactionType::Emergency => "emergency",
+                    TransactionType::UnvaultEmergency => "unvault_emergency",
+                    TransactionType::Unvault => unreachable!("Only revocation transactions"),
+                }
+                .to_string(),
+                txid,
+                signing_context: context.into(),
+            };
+
+            // They are ordered by vault
+            match entries.last_mut() {
+                Some(entry) if entry.deposit_outpoint == db_vault.deposit_outpoint => {
+                    entry.transactions.push(tx)
+                }
+                _ => entries.push(StaleRevocationsEntry {
+                    deposit_outpoint: db_vault.deposit_outpoint,
+                    status: db_vault.status,
+                    transactions: vec![tx],
+                }),
+            }
+        }
+
+        entries
+    }
+
+    /// Get the data watchtowers need to enforce the revocation of our Active vaults on their
+    /// own, for those that changed after the `since` sequence number. The vaults that are not
+    /// Active anymore are marked as revoked.
+    ///
+    /// # Errors
+    /// - If we are not a stakeholder
+    /// - If `peer_noise_key` is set but is not one of the watchtower clients, if any are configured
+    pub fn get_watch_data(
+        &self,
+        since: u64,
+        peer_noise_key: Option<&NoisePubKey>,
+    ) -> Result<WatchDataResult, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        stakeholder_only!(revaultd);
+        if let Some(noise_key) = peer_noise_key {
+            if !revaultd.watchdata_clients.is_empty()
+                && !revaultd.watchdata_clients.contains(noise_key)
+            {
+                return Err(CommandError::WatchtowerClientOnly);
+            }
+        }
+        let db_path = revaultd.db_file();
+
+        db_sync_watchdata(&db_path).expect("Database must be available");
+        let watchdata = db_watchdata(&db_path, since).expect("Database must be available");
+        let sequence = watchdata
+            .last()
+            .map(|entry| entry.sequence)
+            .unwrap_or(since);
+
+        let mut vaults = Vec::with_capacity(watchdata.len());
+        for entry in watchdata {
+            let mut watch_entry = WatchDataEntry {
+                deposit_outpoint: entry.deposit_outpoint,
+                sequence: entry.sequence,
+                revoked: entry.revoked,
+                unvault_txid: None,
+                unvault_script_pubkey: None,
+                cancel_tx: None,
+                unvault_emergency_tx: None,
+            };
+            if entry.revoked {
+                vaults.push(watch_entry);
+                continue;
+            }
+
+            // It may have changed status since we synced, in which case it will be revoked at
+            // the next call.
+            let db_vault = match db_vault_by_deposit(&db_path, &entry.deposit_outpoint)
+                .expect("Database must be available")
+            {
+                Some(db_vault) if db_vault.status == VaultStatus::Active => db_vault,
+                _ => continue,
+            };
+            let (unvault_db_tx, cancel_db_tx, unemer_db_tx) = match (
+                db_unvault_transaction(&db_path, db_vault.id).expect("Database must be available"),
+                db_cancel_transaction(&db_path, db_vault.id).expect("Database must be available"),
+                db_unvault_emer_transaction(&db_path, db_vault.id)
+                    .expect("Database must be available"),
+            ) {
+                (Some(unvault), Some(cancel), Some(unemer)) => (unvault, cancel, unemer),
+                _ => continue,
+            };
+            let unvault_tx = unvault_db_tx.psbt.assert_unvault();
+            let mut cancel_tx = cancel_db_tx.psbt.assert_cancel();
+            let mut unemer_tx = unemer_db_tx.psbt.assert_unvault_emer();
+
+            let finalized = cancel_tx
+                .finalize(&revaultd.secp_ctx)
+                .and_then(|_| unemer_tx.finalize(&revaultd.secp_ctx));
+            if let Err(e) = finalized {
+                log::error!(
+                    "Could not finalize the revocation transactions of Active vault at '{}': '{}'",
+                    db_vault.deposit_outpoint,
+                    e
+                );
+                continue;
+            }
+            watch_entry.unvault_txid = Some(unvault_tx.txid());
+            watch_entry.unvault_script_pubkey = Some(
+                revaultd
+                    .derived_unvault_descriptor(db_vault.derivation_index)
+                    .inner()
+                    .script_pubkey(),
+            );
+            watch_entry.cancel_tx = Some(cancel_tx.into_psbt().extract_tx());
+            watch_entry.unvault_emergency_tx = Some(unemer_tx.into_psbt().extract_tx());
+            vaults.push(watch_entry);
+        }
+
+        Ok(WatchDataResult { sequence, vaults })
+    }
+
+    /// Get the final txids of the fully signed presigned transactions of all the vaults that may
+    /// still be revoked or spent, grouped by transaction type. They are computed when their
+    /// signature set completes, so external monitoring systems can watch for them before any
+    /// broadcast.
+    pub fn list_watch_txids(&self) -> ListWatchTxidsResult {
+        let revaultd = self.revaultd.read().unwrap();
+        let db_path = revaultd.db_file();
+
+        let vaults: HashMap<u32, DbVault> = db_vaults(&db_path)
+            .expect("Database must be available")
+            .into_iter()
+            .filter(|db_vault| {
+                !matches!(
+                    db_vault.status,
+                    VaultStatus::Canceled
+                        | VaultStatus::EmergencyVaulted
+                        | VaultStatus::UnvaultEmergencyVaulted
+                        | VaultStatus::Spent
+                )
+            })
+            .map(|db_vault| (db_vault.id, db_vault))
+            .collect();
+
+        let mut res = ListWatchTxidsResult {
+            unvault: Vec::new(),
+            cancel: Vec::new(),
+            emergency: Vec::new(),
+            unvault_emergency: Vec::new(),
+        };
+        for final_txid in db_final_txids(&db_path).expect("Database must be available") {
+            let db_vault = match vaults.get(&final_txid.vault_id) {
+                Some(db_vault) => db_vault,
+                None => continue,
+            };
+            let entry = WatchTxid {
+                deposit_outpoint: db_vault.deposit_outpoint,
+                txid: final_txid.txid,
+            };
+            match final_txid.tx_type {
+                TransactionType::Unvault => res.unvault.push(entry),
+                TransactionType::Cancel => res.cancel.push(entry),
+                TransactionType::Emergency => res.emergency.push(entry),
+                TransactionType::UnvaultEmergency => res.unvault_emergency.push(entry),
+            }
+        }
+
+        res
+    }
+
     /// List the onchain transactions for the vaults at these outpoints. If `outpoints` is empty, list
     /// the onchain transactions for all vaults.
     ///
@@ -800,6 +2086,7 @@ impl DaemonControl {
         outpoints: &[OutPoint],
     ) -> Result<Vec<ListOnchainTxEntry>, CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        check_elements_limit(outpoints.len(), revaultd.max_batch_size)?;
         let db_path = &revaultd.db_file();
 
         let db_vaults = if outpoints.is_empty() {
@@ -823,7 +2110,7 @@ impl DaemonControl {
             // simplicity bitcoind will tell us (but we could have some optimisation eventually here,
             // eg returning None early on Funded vaults).
             let (unvault, cancel, emergency, unvault_emergency, spend) = match db_vault.status {
-                VaultStatus::Unvaulting | VaultStatus::Unvaulted => {
+                VaultStatus::Unvaulting | VaultStatus::Unvaulted | VaultStatus::Spendable => {
                     let unvault_db_tx = db_unvault_transaction(db_path, db_vault.id)
                         .expect("Database must be available")
                         .ok_or(CommandError::Race)?;
@@ -915,37 +2202,82 @@ impl DaemonControl {
     /// # Errors
     /// - If called for a non-manager
     /// - If provided outpoints for unknown or not 'active' vaults
+    /// - If provided outpoints for vaults being moved to the new wallet of a rotation
     /// - If the Spend transaction creation fails (for instance due to too-high fees or dust outputs)
+    /// - If the required feerate is below our bitcoind's minimum relay feerate
     /// - If the created Spend transaction's feerate is more than 10% below the required feerate
+    /// - If the created Spend transaction would pay more fees than the configured limits, unless
+    ///   `allow_high_fees` is set
     /// - If the created Spend transaction is too large to be transmitted to the coordinator
+    /// - If a destination is not for our network, is a burn address, is one of our Unvault
+    ///   addresses or is one of our deposit addresses (unless `allow_self_send` is set)
+    /// - If spend proposals are required and `proposal_id` isn't given
+    /// - If `proposal_id` is given but this proposal isn't approved or is for other parameters
+    /// - If reusing destinations is forbidden and a confirmed Spend already paid to one of them
     pub fn get_spend_tx(
         &self,
         outpoints: &[OutPoint],
-        destinations: &BTreeMap<Address, u64>,
+        destinations: &BTreeMap<Address, Amount>,
         feerate_vb: u64,
+        allow_high_fees: bool,
+        allow_self_send: bool,
+        proposal_id: Option<u32>,
     ) -> Result<SpendTransaction, CommandError> {
         let revaultd = self.revaultd.read().unwrap();
         manager_only!(revaultd);
+        check_elements_limit(outpoints.len(), revaultd.max_spend_inputs)?;
+        if let Some(proposal_id) = proposal_id {
+            check_spend_proposal(&revaultd, proposal_id, outpoints, destinations, feerate_vb)?;
+        } else if revaultd.spend_approval_threshold.is_some() {
+            return Err(CommandError::SpendProposalRequired);
+        }
         let db_file = &revaultd.db_file();
 
+        let our_scripts = db_derived_scripts(db_file).expect("Database must be available");
+        check_spend_destinations(
+            destinations.keys(),
+            revaultd.bitcoind_config.network,
+            &our_scripts,
+            allow_self_send,
+        )?;
+        if revaultd.forbid_destination_reuse {
+            let reused = reused_destinations(&revaultd, destinations.keys());
+            if !reused.is_empty() {
+                return Err(CommandError::SpendReusedDestinations(reused));
+            }
+        }
+
         // FIXME: have a feerate type to avoid that
         assert!(feerate_vb > 0, "Spend feerate can't be null.");
+        let min_feerate_vb = self.bitcoind_conn.min_relay_feerate()?;
+        if feerate_vb < min_feerate_vb {
+            return Err(CommandError::SpendFeerateBelowRelay(
+                feerate_vb,
+                min_feerate_vb,
+            ));
+        }
 
         // Reconstruct the DepositTxin s from the outpoints and the vaults informations
         let mut txins = Vec::with_capacity(outpoints.len());
         // If we need a change output, use the highest derivation index of the vaults
         // spent. This avoids leaking a new address needlessly while not introducing
         // disrepancy between our indexes.
-        let mut change_index = bip32::ChildNumber::from(0);
+        let mut change_index = DerivationIndex::ZERO;
         for outpoint in outpoints {
             let vault = db_vault_by_deposit(db_file, outpoint)
                 .expect("Database must be available")
                 .ok_or_else(|| CommandError::UnknownOutpoint(*outpoint))?;
+            if db_vault_migration(db_file, vault.id)
+                .expect("Database must be available")
+                .is_some()
+            {
+                return Err(CommandError::VaultMigrating(*outpoint));
+            }
             if matches!(vault.status, VaultStatus::Active) {
                 if vault.derivation_index > change_index {
                     change_index = vault.derivation_index;
                 }
-                txins.push((*outpoint, vault.amount, vault.derivation_index));
+                txins.push((*outpoint, vault.amount, vault.derivation_index.into()));
             } else {
                 return Err(CommandError::InvalidStatus(
                     vault.status,
@@ -954,16 +2286,15 @@ impl DaemonControl {
             }
         }
 
-        let txos: Vec<SpendTxOut> = destinations
+        let spent_value = txins
             .iter()
-            .map(|(addr, value)| {
-                let script_pubkey = addr.script_pubkey();
-                SpendTxOut::new(TxOut {
-                    value: *value,
-                    script_pubkey,
-                })
-            })
-            .collect();
+            .fold(BitcoinAmount::from_sat(0), |sum, (_, amount, _)| {
+                sum + *amount
+            });
+
+        // Make sure any manager calling us with the same parameters gets the same transaction
+        sort_spend_txins(&mut txins);
+        let txos = spend_txouts(destinations);
 
         log::debug!(
             "Creating a Spend transaction with deposit txins: '{:?}' and txos: '{:?}'",
@@ -971,96 +2302,22 @@ impl DaemonControl {
             &txos
         );
 
-        // This adds the CPFP output so create a dummy one to accurately compute the
-        // feerate.
-        let nochange_tx = spend_tx_from_deposits(
-            txins.clone(),
-            txos.clone(),
-            None, // No change :)
-            &revaultd.deposit_descriptor,
-            &revaultd.unvault_descriptor,
-            &revaultd.cpfp_descriptor,
-            revaultd.lock_time,
-            /* Deactivate insane feerate check */
-            false,
-            &revaultd.secp_ctx,
-        )
-        .map_err(|e| revault_tx::Error::from(e))?;
-
-        log::debug!(
-            "Spend tx without change: '{}'",
-            nochange_tx.as_psbt_string()
-        );
-
-        // If the feerate of the transaction would be much lower (< 90/100) than what they
-        // requested for, tell them.
-        let nochange_feerate_vb = nochange_tx
-            .max_feerate()
-            .checked_mul(4)
-            .expect("bug in feerate computation");
-        if nochange_feerate_vb * 10 < feerate_vb * 9 {
-            return Err(CommandError::SpendFeerateTooLow(
-                feerate_vb,
-                nochange_feerate_vb,
-            ));
-        }
-
-        // Add a change output if it would not be dust according to our standard (200k sats
-        // atm, see DUST_LIMIT).
-        // 8 (amount) + 1 (len) + 1 (v0) + 1 (push) + 32 (witscript hash)
-        const P2WSH_TXO_WEIGHT: u64 = 43 * 4;
-        let with_change_weight = nochange_tx
-            .max_weight()
-            .checked_add(P2WSH_TXO_WEIGHT)
-            .expect("weight computation bug");
-        let cur_fees = nochange_tx.fees();
-        let want_fees = with_change_weight
-            // Mental gymnastic: sat/vbyte to sat/wu rounded up
-            .checked_mul(feerate_vb + 3)
-            .map(|vbyte| vbyte.checked_div(4).unwrap());
-        let change_value = want_fees.map(|f| cur_fees.checked_sub(f)).flatten();
-        log::debug!(
-            "Weight with change: '{}'  --  Fees without change: '{}'  --  Wanted feerate: '{}'  \
-                    --  Wanted fees: '{:?}'  --  Change value: '{:?}'",
-            with_change_weight,
-            cur_fees,
-            feerate_vb,
-            want_fees,
-            change_value
-        );
-
-        let change_txo = change_value.and_then(|change_value| {
-            // The overhead incurred to the value of the CPFP output by the change output
-            // See https://github.com/revault/practical-revault/blob/master/transactions.md#spend_tx
-            let cpfp_overhead = 16 * P2WSH_TXO_WEIGHT;
-            if change_value > revault_tx::transactions::DUST_LIMIT + cpfp_overhead {
-                let change_txo = DepositTxOut::new(
-                    // arithmetic checked above
-                    Amount::from_sat(change_value - cpfp_overhead),
-                    &revaultd
-                        .deposit_descriptor
-                        .derive(change_index, &revaultd.secp_ctx),
-                );
-                log::debug!("Adding a change txo: '{:?}'", change_txo);
-                Some(change_txo)
-            } else {
-                None
-            }
-        });
-
-        // Now we can hand them the resulting transaction (sanity checked for insane fees).
-        let tx_res = spend_tx_from_deposits(
+        let tx_res = spend_tx_with_change(
+            &revaultd,
             txins,
             txos,
-            change_txo,
-            &revaultd.deposit_descriptor,
-            &revaultd.unvault_descriptor,
-            &revaultd.cpfp_descriptor,
-            revaultd.lock_time,
-            true,
-            &revaultd.secp_ctx,
-        )
-        .map_err(|e| revault_tx::Error::from(e))?;
+            feerate_vb,
+            &revaultd.derived_deposit_descriptor(change_index),
+        )?;
+
+        if !allow_high_fees {
+            check_spend_fees(
+                BitcoinAmount::from_sat(tx_res.fees()),
+                spent_value,
+                revaultd.max_spend_fee_percent,
+                revaultd.max_spend_fee,
+            )?;
+        }
 
         if !check_spend_transaction_size(&revaultd, tx_res.clone()) {
             return Err(CommandError::SpendTooLarge);
@@ -1070,20 +2327,44 @@ impl DaemonControl {
         Ok(tx_res)
     }
 
+    /// Get the destinations that a confirmed Spend transaction of ours already paid to.
+    ///
+    /// ## Errors
+    /// - If called for a non-manager
+    pub fn reused_spend_destinations(
+        &self,
+        destinations: &BTreeMap<Address, Amount>,
+    ) -> Result<Vec<Address>, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        manager_only!(revaultd);
+
+        Ok(reused_destinations(&revaultd, destinations.keys()))
+    }
+
     /// Store a new or update an existing Spend transaction in database.
     ///
     /// ## Errors
     /// - If called for a non-manager
     /// - If the given Spend transaction refers to an unknown Unvault txid
     /// - If the Spend refers to an Unvault of a vault that isn't 'active'
+    /// - If the Spend refers to an Unvault of a vault being moved to the new wallet of a rotation
+    ///   by another Spend
+    /// - If running in read-only mode
+    /// - If the space left on disk is below the critical threshold
     pub fn update_spend_tx(&self, spend_tx: SpendTransaction) -> Result<(), CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
         manager_only!(revaultd);
+        check_disk_space(&revaultd)?;
         let db_path = revaultd.db_file();
         let spend_txid = spend_tx.tx().txid();
+        let _locks = self
+            .resource_locks
+            .lock(vec![LockedResource::Spend(spend_txid)], LOCK_TIMEOUT)?;
 
         // Fetch the Unvault it spends from the DB
         let spend_inputs = &spend_tx.tx().input;
+        check_elements_limit(spend_inputs.len(), revaultd.max_spend_inputs)?;
         let mut db_unvaults = Vec::with_capacity(spend_inputs.len());
         for txin in spend_inputs.iter() {
             let (db_vault, db_unvault) =
@@ -1097,6 +2378,7 @@ impl DaemonControl {
                     VaultStatus::Active,
                 ));
             }
+            check_not_migrating(&db_path, &db_vault, &spend_txid)?;
 
             db_unvaults.push(db_unvault);
         }
@@ -1122,10 +2404,15 @@ impl DaemonControl {
     ///
     /// ## Errors
     /// - If called for a non-manager
+    /// - If running in read-only mode
     pub fn del_spend_tx(&self, spend_txid: &Txid) -> Result<(), CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
         manager_only!(revaultd);
         let db_path = revaultd.db_file();
+        let _locks = self
+            .resource_locks
+            .lock(vec![LockedResource::Spend(*spend_txid)], LOCK_TIMEOUT)?;
         db_delete_spend(&db_path, spend_txid).expect("Database must be available");
         Ok(())
     }
@@ -1145,9 +2432,19 @@ impl DaemonControl {
         let spend_tx_map = db_list_spends(&db_path).expect("Database must be available");
         let mut listspend_entries = Vec::with_capacity(spend_tx_map.len());
         for (_, (db_spend, deposit_outpoints)) in spend_tx_map {
+            let spend_txid = db_spend.psbt.txid();
+            let deprecation =
+                db_spend_deprecation(&db_path, &spend_txid).expect("Database must be available");
+            let expiration =
+                db_spend_expiration(&db_path, &spend_txid).expect("Database must be available");
+
             // Filter by status
             if let Some(s) = &statuses {
-                let status = if let Some(true) = db_spend.broadcasted {
+                let status = if deprecation.is_some() {
+                    ListSpendStatus::Deprecated
+                } else if expiration.is_some() {
+                    ListSpendStatus::Expired
+                } else if let Some(true) = db_spend.broadcasted {
                     ListSpendStatus::Broadcasted
                 } else if let Some(false) = db_spend.broadcasted {
                     ListSpendStatus::Pending
@@ -1160,8 +2457,8 @@ impl DaemonControl {
                 }
             }
 
-            let spent_vaults = db_vaults_from_spend(&db_path, &db_spend.psbt.txid())
-                .expect("Database must be available");
+            let spent_vaults =
+                db_vaults_from_spend(&db_path, &spend_txid).expect("Database must be available");
 
             let derivation_index = spent_vaults
                 .values()
@@ -1169,13 +2466,11 @@ impl DaemonControl {
                 .max()
                 .expect("Spent vaults should not be empty");
             let cpfp_script_pubkey = revaultd
-                .cpfp_descriptor
-                .derive(derivation_index, &revaultd.secp_ctx)
+                .derived_cpfp_descriptor(derivation_index)
                 .into_inner()
                 .script_pubkey();
             let deposit_address = revaultd
-                .deposit_descriptor
-                .derive(derivation_index, &revaultd.secp_ctx)
+                .derived_deposit_descriptor(derivation_index)
                 .into_inner()
                 .script_pubkey();
             let mut cpfp_index = None;
@@ -1190,7 +2485,34 @@ impl DaemonControl {
                 }
             }
 
+            let conflicts = db_tx_conflicts(&db_path, &spend_txid)
+                .expect("Database must be available")
+                .into_iter()
+                .map(VaultConflict::from)
+                .collect();
+            let cosigners = spend_cosigners(&revaultd, &spend_txid);
+            let announcement = db_spend_announcement(&db_path, &spend_txid)
+                .expect("Database must be available")
+                .map(SpendAnnouncement::from);
+            let deprecation = deprecation.map(|db_deprecation| SpendDeprecation {
+                deposit_outpoint: spent_vaults
+                    .values()
+                    .find(|db_vault| db_vault.id == db_deprecation.vault_id)
+                    .expect("The canceled vault is spent by this Spend")
+                    .deposit_outpoint,
+                cancel_txid: db_deprecation.cancel_txid,
+                deprecated_at: db_deprecation.deprecated_at,
+            });
+            let expiration = expiration.map(|db_expiration| SpendExpiration {
+                reason: db_expiration.reason,
+                expired_at: db_expiration.expired_at,
+            });
             listspend_entries.push(ListSpendEntry {
+                conflicts,
+                cosigners,
+                announcement,
+                deprecation,
+                expiration,
                 psbt: db_spend.psbt,
                 deposit_outpoints,
                 cpfp_index: cpfp_index.expect("We always create a CPFP output"),
@@ -1211,16 +2533,57 @@ impl DaemonControl {
     /// - If `priority` is set to `true` and we don't have access to a CPFP private key
     /// - If the txid doesn't refer to a known Spend (must be stored using `updatespendtx` first)
     /// - If the Spend PSBT doesn't contain enough signatures, or contain invalid ones
+    /// - If the Spend spends a vault being moved to the new wallet of a rotation by another Spend
     /// - If the Spend is too large to be announced
-    pub fn set_spend_tx(&self, spend_txid: &Txid, priority: bool) -> Result<(), CommandError> {
+    /// - If running in read-only mode
+    /// - If the space left on disk is below the critical threshold
+    /// - If the spending schedule doesn't allow it now, unless `override_schedule` is set
+    /// - If another command is using this Spend, or one of its vaults, for too long
+    /// - If bitcoind would not accept some of the Unvault transactions, none of them is broadcast
+    pub fn set_spend_tx(
+        &self,
+        spend_txid: &Txid,
+        priority: bool,
+        override_schedule: bool,
+    ) -> Result<(), CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
         manager_only!(revaultd);
+        check_disk_space(&revaultd)?;
+        if let Some(ref schedule) = revaultd.spending_schedule {
+            let now = revaultd.clock.unix_timestamp();
+            if !schedule.allows(now) {
+                if !override_schedule {
+                    return Err(CommandError::OutsideSpendingSchedule(
+                        schedule.next_window(now),
+                    ));
+                }
+                log_event!(
+                    log::Level::Warn,
+                    "spending_schedule_override",
+                    txid = spend_txid;
+                    "Overriding the spending schedule to set Spend transaction '{}'",
+                    spend_txid
+                );
+            }
+        }
         let db_path = revaultd.db_file();
 
         if priority && revaultd.cpfp_key.is_none() {
             return Err(CommandError::MissingCpfpKey);
         }
 
+        // This may take a while (the cosigning servers may be slow to answer). Make sure no
+        // other command touches this Spend attempt nor the vaults it spends in the meantime.
+        let locked_vaults = db_vaults_from_spend(&db_path, &spend_txid)
+            .expect("Database must be available")
+            .into_iter()
+            .map(|(_, db_vault)| LockedResource::Vault(db_vault.deposit_outpoint));
+        let _locks = self.resource_locks.lock(
+            iter::once(LockedResource::Spend(*spend_txid)).chain(locked_vaults),
+            LOCK_TIMEOUT,
+        )?;
+
         // Get the referenced Spend and the vaults it spends from the DB
         let mut spend_tx = db_spend_transaction(&db_path, &spend_txid)
             .expect("Database must be available")
@@ -1231,6 +2594,11 @@ impl DaemonControl {
         if spent_vaults.len() < tx.input.len() {
             return Err(CommandError::SpendSpent(*spend_txid));
         }
+        for (_, db_vault) in spent_vaults.iter() {
+            check_not_migrating(&db_path, db_vault, spend_txid)?;
+        }
+        // The limit may have been lowered since it was stored
+        check_elements_limit(tx.input.len(), revaultd.max_spend_inputs)?;
 
         // Sanity check the Spend transaction is actually valid before announcing
         // it. revault_tx already implements the signature checks so don't duplicate
@@ -1269,12 +2637,7 @@ impl DaemonControl {
         let cosigs = revaultd.cosigs.as_ref().expect("We are manager");
         if !cosigs.is_empty() {
             log::debug!("Fetching signatures from Cosigning servers");
-            fetch_cosigs_signatures(
-                &revaultd.secp_ctx,
-                &revaultd.noise_secret,
-                &mut spend_tx.psbt,
-                cosigs,
-            )?;
+            fetch_cosigs_signatures(&revaultd, &mut spend_tx.psbt)?;
         }
         let mut finalized_spend = spend_tx.psbt.clone();
         finalized_spend.finalize(&revaultd.secp_ctx)?;
@@ -1284,14 +2647,20 @@ impl DaemonControl {
             .values()
             .map(|db_vault| db_vault.deposit_outpoint)
             .collect();
-        announce_spend_transaction(
-            revaultd.coordinator_host,
+        let coordinator = announce_spend_transaction(
+            &revaultd.coordinators,
             &revaultd.noise_secret,
-            &revaultd.coordinator_noisekey,
             finalized_spend,
             deposit_outpoints,
         )?;
         db_update_spend(&db_path, &spend_tx.psbt, priority).expect("Database must be available");
+        db_record_spend_announcement(
+            &db_path,
+            spend_txid,
+            &coordinator.noise_key.0,
+            revaultd.clock.unix_timestamp(),
+        )
+        .expect("Database must be available");
 
         // Finally we can broadcast the Unvault(s) transaction(s) and store the Spend
         // transaction for later broadcast
@@ -1299,7 +2668,7 @@ impl DaemonControl {
             "Broadcasting Unvault transactions with ids '{:?}'",
             spent_vaults.keys()
         );
-        let bitcoin_txs = spent_vaults
+        let unvault_txs = spent_vaults
             .values()
             .into_iter()
             .map(|db_vault| {
@@ -1309,84 +2678,200 @@ impl DaemonControl {
                     .psbt
                     .assert_unvault();
                 unvault_tx.finalize(&revaultd.secp_ctx)?;
-                Ok(unvault_tx.into_psbt().extract_tx())
+                Ok(unvault_tx)
             })
-            .collect::<Result<Vec<BitcoinTransaction>, CommandError>>()?;
-        self.bitcoind_conn.broadcast(bitcoin_txs)?;
+            .collect::<Result<Vec<UnvaultTransaction>, CommandError>>()?;
+        // The bitcoind thread may need to read the global state to CPFP them
+        let clock = revaultd.clock.clone();
+        drop(revaultd);
+        match self
+            .bitcoind_conn
+            .broadcast_unvaults(unvault_txs, priority)?
+        {
+            UnvaultsBroadcast::Rejected(rejected) => {
+                return Err(CommandError::UnvaultsRejected(
+                    rejected
+                        .into_iter()
+                        .map(|(txid, reason)| {
+                            let outpoint = spent_vaults
+                                .get(&txid)
+                                .map(|db_vault| db_vault.deposit_outpoint)
+                                .expect("We only broadcast the Unvaults of these vaults");
+                            (outpoint, reason)
+                        })
+                        .collect(),
+                ));
+            }
+            UnvaultsBroadcast::Broadcast(failures) => {
+                // The poller will broadcast them again until they make it to the mempool
+                for (txid, error) in failures {
+                    let db_vault = spent_vaults
+                        .get(&txid)
+                        .expect("We only broadcast the Unvaults of these vaults");
+                    db_raise_vault_flag(
+                        &db_path,
+                        db_vault.id,
+                        VaultFlagKind::UnvaultBroadcastFailed,
+                        &format!(
+                            "Unvault transaction '{}' of Spend '{}' could not be broadcast: '{}'",
+                            txid, spend_txid, error
+                        ),
+                        clock.unix_timestamp(),
+                    )
+                    .expect("Database must be available");
+                }
+            }
+        }
         db_mark_broadcastable_spend(&db_path, spend_txid).expect("Database must be available");
 
         Ok(())
     }
 
-    /// Broadcast the Cancel transaction for an unvaulted vault.
+    /// Give up on a Spend transaction we announced but whose Unvault transactions were not
+    /// broadcast yet, so its vaults may be spent by another one. This is what happens
+    /// automatically after `spend_expiry_secs`.
+    /// **Note**: this does nothing if the Spend already expired.
     ///
     /// ## Errors
-    /// - If the outpoint doesn't refer to an existing, unvaulted (or unvaulting) vault
-    /// - If the transaction broadcast fails for some reason
-    pub fn revault(&self, deposit_outpoint: &OutPoint) -> Result<(), CommandError> {
+    /// - If called for a non-manager
+    /// - If running in read-only mode
+    /// - If the txid doesn't refer to a known, announced, Spend
+    /// - If the Unvault transactions of the Spend were already broadcast
+    /// - If another command is using this Spend, or one of its vaults, for too long
+    pub fn abort_spend(&self, spend_txid: &Txid) -> Result<(), CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
+        manager_only!(revaultd);
         let db_path = revaultd.db_file();
 
-        // Checking that the vault is secured, otherwise we don't have the cancel
-        // transaction
-        let vault = db_vault_by_deposit(&db_path, deposit_outpoint)
-            .expect("Database must be accessible")
-            .ok_or_else(|| CommandError::UnknownOutpoint(*deposit_outpoint))?;
+        let locked_vaults = db_vaults_from_spend(&db_path, spend_txid)
+            .expect("Database must be available")
+            .into_iter()
+            .map(|(_, db_vault)| LockedResource::Vault(db_vault.deposit_outpoint));
+        let _locks = self.resource_locks.lock(
+            iter::once(LockedResource::Spend(*spend_txid)).chain(locked_vaults),
+            LOCK_TIMEOUT,
+        )?;
 
-        if !matches!(
-            vault.status,
-            VaultStatus::Unvaulting | VaultStatus::Unvaulted | VaultStatus::Spending
-        ) {
-            return Err(CommandError::InvalidStatus(
-                vault.status,
-                VaultStatus::Unvaulting,
-            ));
+        let db_spend = db_spend_transaction(&db_path, spend_txid)
+            .expect("Database must be available")
+            .ok_or(CommandError::UnknownSpend(*spend_txid))?;
+        if db_spend_expiration(&db_path, spend_txid)
+            .expect("Database must be available")
+            .is_some()
+        {
+            return Ok(());
         }
-
-        let mut cancel_tx = db_cancel_transaction(&db_path, vault.id)
+        if db_spend_announcement(&db_path, spend_txid)
             .expect("Database must be available")
-            .ok_or(CommandError::Race)?
-            .psbt
-            .assert_cancel();
+            .is_none()
+        {
+            return Err(CommandError::SpendNotAnnounced(*spend_txid));
+        }
+        if db_spend_unvaults_broadcast(&db_path, &db_spend).expect("Database must be available") {
+            return Err(CommandError::SpendUnvaultsBroadcast(*spend_txid));
+        }
 
-        cancel_tx.finalize(&revaultd.secp_ctx)?;
-        let transaction = cancel_tx.into_psbt().extract_tx();
-        log::debug!(
-            "Broadcasting Cancel transactions with id '{:?}'",
-            transaction.txid()
-        );
-        self.bitcoind_conn.broadcast(vec![transaction])?;
+        expire_spend(&revaultd, spend_txid, SpendExpiryReason::Aborted)
+            .expect("Database must be available");
 
         Ok(())
     }
 
-    /// Broadcast Emergency transactions for all existing vaults.
+    /// Broadcast the Cancel transaction for an unvaulted vault. As a last resort, it's not
+    /// refused when the space left on disk is critically low.
+    ///
+    /// ## Errors
+    /// - If the outpoint doesn't refer to an existing, unvaulted (or unvaulting) vault
+    /// - If the transaction broadcast fails for some reason
+    /// - If running in read-only mode
+    /// - If another command is modifying this vault for too long
+    pub fn revault(&self, deposit_outpoint: &OutPoint) -> Result<(), CommandError> {
+        {
+            let revaultd = self.revaultd.read().unwrap();
+            not_read_only!(revaultd);
+        }
+        let _locks = self
+            .resource_locks
+            .lock(vec![LockedResource::Vault(*deposit_outpoint)], LOCK_TIMEOUT)?;
+        self.statemachine_conn.broadcast_cancel(*deposit_outpoint)
+    }
+
+    /// Broadcast the Emergency transactions of all existing vaults, or their Unvault Emergency
+    /// transaction for those that were unvaulted, and report what became of each of them. The
+    /// transactions a previous call broadcast are not broadcast again: calling it again only
+    /// retries the vaults it failed for. As a last resort, it's not refused when the space left
+    /// on disk is critically low.
     ///
     /// ## Errors
     /// - If called for a non-stakeholder
-    pub fn emergency(&self) -> Result<(), CommandError> {
+    /// - If running in read-only mode
+    /// - If we could not communicate with bitcoind
+    pub fn emergency(&self) -> Result<Vec<EmergencyEntry>, CommandError> {
         let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
         stakeholder_only!(revaultd);
 
-        // FIXME: there is a ton of edge cases not covered here. We should additionally opt for a
-        // bulk method, like broadcasting all Emergency transactions in a thread forever without
-        // trying to be smart by differentiating between Emer and UnvaultEmer until we die or all
-        // vaults are confirmed in the EDV.
-        let emers = finalized_emer_txs(&revaultd)?;
-        self.bitcoind_conn.broadcast(emers)?;
+        emergency_broadcast(&revaultd, &self.bitcoind_conn)
+    }
 
-        Ok(())
+    /// Get our Noise static public key, along with its fingerprint for operators to compare it
+    /// out-of-band.
+    pub fn get_noise_static_pubkey(&self) -> NoiseStaticPubkeyResult {
+        let pubkey = self.revaultd.read().unwrap().noise_pubkey();
+
+        NoiseStaticPubkeyResult {
+            pubkey: pubkey.0.to_hex(),
+            fingerprint: noise_pubkey_fingerprint(&pubkey),
+        }
+    }
+
+    /// Get the descriptors we were configured with, along with the size of the scripts derived
+    /// from them and the room left before they hit the standardness limits.
+    pub fn get_descriptors(&self) -> Result<GetDescriptorsResult, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        let [deposit, unvault, cpfp] = descriptors_script_limits(
+            &revaultd.deposit_descriptor,
+            &revaultd.unvault_descriptor,
+            &revaultd.cpfp_descriptor,
+        )?;
+
+        Ok(GetDescriptorsResult {
+            deposit: DescriptorEntry::new(revaultd.deposit_descriptor.to_string(), deposit),
+            unvault: DescriptorEntry::new(revaultd.unvault_descriptor.to_string(), unvault),
+            cpfp: DescriptorEntry::new(revaultd.cpfp_descriptor.to_string(), cpfp),
+        })
+    }
+
+    /// Get the configured cosigning servers, along with whether they can currently be reached.
+    pub fn list_cosigners(&self) -> Vec<CosignerEntry> {
+        let revaultd = self.revaultd.read().unwrap();
+        cosigners_entries(&revaultd)
+    }
+
+    /// Get the configured participants to this deployment: stakeholders, managers, cosigning
+    /// servers and coordinator.
+    pub fn list_participants(&self) -> ListParticipantsResult {
+        let revaultd = self.revaultd.read().unwrap();
+        participants(&revaultd)
     }
 
     /// Get information about all the configured servers.
     pub fn get_servers_statuses(&self) -> ServersStatuses {
         let revaultd = self.revaultd.read().unwrap();
-        let coordinator = coordinator_status(&revaultd);
+        let (coordinator, coordinators) = coordinator_status(&revaultd);
+        let coordinator_queue = CoordinatorQueueStatus {
+            depth: db_coordinator_outbox_len(&revaultd.db_file())
+                .expect("Database must be available"),
+            throttle: revaultd.coordinators.throttle().status(),
+        };
         let cosigners = cosigners_status(&revaultd);
         let watchtowers = watchtowers_status(&revaultd);
 
         ServersStatuses {
             coordinator,
+            coordinators,
+            coordinator_queue,
             cosigners,
             watchtowers,
         }
@@ -1407,50 +2892,1327 @@ impl DaemonControl {
         let revaultd = self.revaultd.read().unwrap();
         gethistory(&revaultd, &self.bitcoind_conn, start, end, limit, kind)
     }
-}
 
-/// Descriptors the daemon was configured with
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetInfoDescriptors {
-    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
-    pub deposit: DepositDescriptor,
-    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
-    pub unvault: UnvaultDescriptor,
-    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
-    pub cpfp: CpfpDescriptor,
-}
+    /// Run a destructive `command`, recording it in the audit log before it is executed and its
+    /// result once it completed. The command is not run if we can't record it.
+    pub fn audited<T>(
+        &self,
+        method: &str,
+        params: &serde_json::Value,
+        peer_uid: Option<u32>,
+        command: impl FnOnce(&Self) -> Result<T, CommandError>,
+    ) -> Result<T, CommandError> {
+        let (db_path, clock) = {
+            let revaultd = self.revaultd.read().unwrap();
+            (revaultd.db_file(), revaultd.clock.clone())
+        };
+        let params_digest = sha256::Hash::hash(
+            &serde_json::to_vec(params).expect("Serializing a JSON value can't fail"),
+        );
 
-/// Information about the current state of the daemon
-#[derive(Debug, Clone, Serialize, Deserialize)]
+        db_append_audit_entry(
+            &db_path,
+            method,
+            &params_digest,
+            "pending",
+            clock.unix_timestamp(),
+            peer_uid,
+        )
+        .map_err(CommandError::AuditLog)?;
+
+        let res = command(self);
+        let outcome = match res {
+            Ok(_) => "success".to_string(),
+            Err(ref e) => format!("error: {}", e),
+        };
+        if let Err(e) = db_append_audit_entry(
+            &db_path,
+            method,
+            &params_digest,
+            &outcome,
+            clock.unix_timestamp(),
+            peer_uid,
+        ) {
+            // Too late to refuse, it's done.
+            log::error!(
+                "Could not record the outcome of '{}' in the audit log: '{}'",
+                method,
+                e
+            );
+        }
+
+        res
+    }
+
+    /// Run a mutating `command` at most once per idempotency `key`: a retry with the same key
+    /// gets the result of the first attempt instead, or an error if it's still in progress. The
+    /// key is released if the command failed so that it can be retried, and expires after the
+    /// configured retention. Without a key the command is just run.
+    pub fn idempotent(
+        &self,
+        key: Option<&str>,
+        method: &str,
+        params: &serde_json::Value,
+        command: impl FnOnce(&Self) -> Result<serde_json::Value, CommandError>,
+    ) -> Result<serde_json::Value, CommandError> {
+        let key = match key {
+            Some(key) => key,
+            None => return command(self),
+        };
+        let (db_path, clock, retention) = {
+            let revaultd = self.revaultd.read().unwrap();
+            (
+                revaultd.db_file(),
+                revaultd.clock.clone(),
+                revaultd.idempotency_retention,
+            )
+        };
+        let params_digest = sha256::Hash::hash(
+            &serde_json::to_vec(params).expect("Serializing a JSON value can't fail"),
+        );
+        let now = clock.unix_timestamp();
+
+        if let Some(entry) = db_claim_idempotency_key(
+            &db_path,
+            key,
+            method,
+            &params_digest,
+            now,
+            now.saturating_sub(retention.as_secs()),
+        )
+        .map_err(CommandError::IdempotencyKey)?
+        {
+            if entry.method != method || entry.params_digest != params_digest {
+                return Err(CommandError::IdempotencyKeyConflict(key.to_string()));
+            }
+            let result = entry
+                .result
+                .ok_or_else(|| CommandError::IdempotencyKeyInProgress(key.to_string()))?;
+            log::debug!(
+                "Returning the stored result of '{}' for idempotency key '{}'",
+                method,
+                key
+            );
+            return serde_json::from_str(&result).map_err(|e| {
+                CommandError::IdempotencyKey(DatabaseError(format!("Invalid stored result: {}", e)))
+            });
+        }
+
+        let res = command(self);
+        let stored = match res {
+            Ok(ref result) => db_set_idempotency_result(&db_path, key, &result.to_string()),
+            Err(_) => db_release_idempotency_key(&db_path, key),
+        };
+        if let Err(e) = stored {
+            log::error!(
+                "Could not update idempotency key '{}' for '{}': '{}'",
+                key,
+                method,
+                e
+            );
+        }
+
+        res
+    }
+
+    /// Draft a spend proposal, which enough managers need to approve before a Spend transaction
+    /// may be created for it with [DaemonControl::get_spend_tx]. Returns its id.
+    ///
+    /// ## Errors
+    /// - If called for a non-manager or in read-only mode
+    /// - If an outpoint is unknown, given twice or isn't for an 'active' vault
+    /// - If a destination is not for our network, is a burn address or is one of our Unvault
+    ///   addresses
+    /// - If the space left on disk is below the critical threshold
+    pub fn propose_spend(
+        &self,
+        outpoints: &[OutPoint],
+        destinations: &BTreeMap<Address, Amount>,
+        feerate_vb: u64,
+    ) -> Result<u32, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
+        manager_only!(revaultd);
+        check_elements_limit(outpoints.len(), revaultd.max_spend_inputs)?;
+        check_disk_space(&revaultd)?;
+        let db_path = revaultd.db_file();
+
+        // Sending to one of our deposit addresses may be explicitly allowed when creating the
+        // Spend transaction, it's checked then.
+        let our_scripts = db_derived_scripts(&db_path).expect("Database must be available");
+        check_spend_destinations(
+            destinations.keys(),
+            revaultd.bitcoind_config.network,
+            &our_scripts,
+            true,
+        )?;
+
+        let mut outpoints = outpoints.to_vec();
+        outpoints.sort();
+        if let Some(dup) = outpoints.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(CommandError::InvalidParams(format!(
+                "Outpoint '{}' was given twice",
+                dup[0]
+            )));
+        }
+        for outpoint in outpoints.iter() {
+            let vault = db_vault_by_deposit(&db_path, outpoint)
+                .expect("Database must be available")
+                .ok_or(CommandError::UnknownOutpoint(*outpoint))?;
+            if vault.status != VaultStatus::Active {
+                return Err(CommandError::InvalidStatus(
+                    vault.status,
+                    VaultStatus::Active,
+                ));
+            }
+        }
+
+        let destinations = destinations
+            .iter()
+            .map(|(address, amount)| (address.clone(), BitcoinAmount::from(*amount)))
+            .collect();
+        let now = revaultd.clock.unix_timestamp();
+        let proposal_id = db_insert_spend_proposal(
+            &db_path,
+            &outpoints,
+            &destinations,
+            feerate_vb,
+            now,
+            now.saturating_add(revaultd.spend_proposal_expiry.as_secs()),
+        )
+        .expect("Database must be available");
+        log::info!(
+            "New spend proposal '{}' for {} vault(s), it needs {} approval(s)",
+            proposal_id,
+            outpoints.len(),
+            spend_approval_threshold(&revaultd)
+        );
+
+        Ok(proposal_id)
+    }
+
+    /// List all the spend proposals, along with the decisions of the managers about them.
+    ///
+    /// ## Errors
+    /// - If called for a non-manager
+    pub fn list_spend_proposals(&self) -> Result<Vec<SpendProposalEntry>, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        manager_only!(revaultd);
+        let db_path = revaultd.db_file();
+        let now = revaultd.clock.unix_timestamp();
+
+        Ok(db_spend_proposals(&db_path)
+            .expect("Database must be available")
+            .into_iter()
+            .map(|proposal| {
+                let acks = db_spend_proposal_acks(&db_path, proposal.id)
+                    .expect("Database must be available");
+                spend_proposal_entry(&revaultd, proposal, acks, now)
+            })
+            .collect())
+    }
+
+    /// Record the decision of the manager whose xpub has this `fingerprint` about a spend
+    /// proposal. The `signature` is theirs of the proposal's approval digest if they `approve`
+    /// it, or of its rejection digest otherwise. Returns the status of the proposal once the
+    /// decision is taken into account.
+    ///
+    /// ## Errors
+    /// - If called for a non-manager or in read-only mode
+    /// - If the proposal is unknown or expired
+    /// - If the fingerprint isn't the one of a manager's xpub, or the signature is invalid
+    /// - If this manager already decided about this proposal
+    pub fn decide_spend_proposal(
+        &self,
+        proposal_id: u32,
+        fingerprint: &bip32::Fingerprint,
+        signature: &[u8],
+        approve: bool,
+    ) -> Result<SpendProposalStatus, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
+        manager_only!(revaultd);
+        let db_path = revaultd.db_file();
+        let now = revaultd.clock.unix_timestamp();
+
+        let proposal = db_spend_proposal(&db_path, proposal_id)
+            .expect("Database must be available")
+            .ok_or(CommandError::UnknownSpendProposal(proposal_id))?;
+        if now >= proposal.expires_at as u64 {
+            return Err(CommandError::SpendProposalExpired(proposal_id));
+        }
+
+        let xpub = manager_xpub(&revaultd, fingerprint).ok_or_else(|| {
+            CommandError::SpendProposalInvalidAck(format!(
+                "'{}' is not the fingerprint of a manager's xpub",
+                fingerprint
+            ))
+        })?;
+        check_spend_proposal_ack(
+            &revaultd.secp_ctx,
+            &xpub,
+            &proposal.ack_digest(approve),
+            signature,
+        )?;
+        let recorded = db_insert_spend_proposal_ack(
+            &db_path,
+            proposal_id,
+            fingerprint,
+            approve,
+            signature,
+            now,
+        )
+        .expect("Database must be available");
+        if !recorded {
+            return Err(CommandError::SpendProposalAlreadyDecided(
+                proposal_id,
+                *fingerprint,
+            ));
+        }
+
+        let acks =
+            db_spend_proposal_acks(&db_path, proposal_id).expect("Database must be available");
+        let status = spend_proposal_status(
+            &proposal,
+            &acks,
+            spend_approval_threshold(&revaultd),
+            revaultd.managers_xpubs().len(),
+            now,
+        );
+        log::info!(
+            "Manager '{}' {} spend proposal '{}', which is now '{}'",
+            revaultd.participant(*fingerprint),
+            if approve { "approved" } else { "rejected" },
+            proposal_id,
+            status
+        );
+
+        Ok(status)
+    }
+
+    /// Acknowledge the problem a vault was flagged for, once the underlying issue was fixed.
+    /// Refused in read-only mode.
+    pub fn clear_vault_flag(
+        &self,
+        deposit_outpoint: &OutPoint,
+        kind: VaultFlagKind,
+    ) -> Result<(), CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
+        let db_path = revaultd.db_file();
+        let vault = db_vault_by_deposit(&db_path, deposit_outpoint)
+            .expect("Database must be available")
+            .ok_or(CommandError::UnknownOutpoint(*deposit_outpoint))?;
+
+        let cleared =
+            db_clear_vault_flag(&db_path, vault.id, kind, revaultd.clock.unix_timestamp())
+                .expect("Database must be available");
+        if !cleared {
+            return Err(CommandError::NoActiveFlag(*deposit_outpoint, kind));
+        }
+
+        Ok(())
+    }
+
+    /// Get the levels at which we currently log.
+    pub fn get_log_levels(&self) -> LogLevelsInfo {
+        let revaultd = self.revaultd.read().unwrap();
+        revaultd.log_levels.expire(revaultd.clock.unix_timestamp());
+        LogLevelsInfo::from(revaultd.log_levels.current())
+    }
+
+    /// Log at this global level and with these module overrides (the current ones if not
+    /// given) right away. If a `duration` is given, the configured levels are restored once it
+    /// elapsed. Returns the previous levels.
+    pub fn set_log_levels(
+        &self,
+        level: log::LevelFilter,
+        modules: Option<HashMap<String, log::LevelFilter>>,
+        duration: Option<Duration>,
+    ) -> LogLevelsInfo {
+        let revaultd = self.revaultd.read().unwrap();
+        let modules = modules.unwrap_or_else(|| revaultd.log_levels.current().0.modules);
+        let revert_at = duration.map(|d| revaultd.clock.unix_timestamp() + d.as_secs());
+        let previous = revaultd
+            .log_levels
+            .set(LogLevels::new(level, modules.clone()), revert_at);
+        log::info!(
+            "Now logging at level '{}' with module overrides {:?}, until {:?}",
+            level,
+            modules,
+            revert_at
+        );
+
+        LogLevelsInfo::from(previous)
+    }
+
+    /// Export the vaults, or the history of the funds between the dates `start` and `end`, as
+    /// CSV. It's written to a new file at `path` inside the data directory if given, otherwise
+    /// returned inline if it's not larger than `MAX_INLINE_EXPORT_SIZE`.
+    ///
+    /// ## Errors
+    /// - If the path is not relative to the data directory, or if the file already exists
+    /// - If the export is too large to be returned inline
+    /// - If we could not write the export
+    pub fn export(
+        &self,
+        kind: ExportKind,
+        start: u32,
+        end: u32,
+        path: Option<&str>,
+    ) -> Result<ExportResult, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+
+        if let Some(path) = path {
+            let rel_path = Path::new(path);
+            if path.is_empty()
+                || !rel_path
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)))
+            {
+                return Err(CommandError::InvalidParams(format!(
+                    "Export path '{}' must be relative to the data directory, without '..'",
+                    path
+                )));
+            }
+            let path = revaultd.data_dir.join(rel_path);
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map_err(CommandError::Export)?;
+            let rows = export_csv(
+                &revaultd,
+                &self.bitcoind_conn,
+                kind,
+                start,
+                end,
+                io::BufWriter::new(file),
+            )?;
+            log::info!("Exported {} rows to '{}'", rows, path.display());
+
+            Ok(ExportResult {
+                rows,
+                path: Some(path),
+                csv: None,
+            })
+        } else {
+            let mut buf = CappedBuffer::new(MAX_INLINE_EXPORT_SIZE);
+            let rows = match export_csv(&revaultd, &self.bitcoind_conn, kind, start, end, &mut buf)
+            {
+                Err(CommandError::Export(_)) => {
+                    return Err(CommandError::ExportTooLarge(MAX_INLINE_EXPORT_SIZE))
+                }
+                res => res?,
+            };
+
+            Ok(ExportResult {
+                rows,
+                path: None,
+                csv: Some(String::from_utf8(buf.buf).expect("We only write strings")),
+            })
+        }
+    }
+
+    /// Write the CSV export of the vaults, or of the history of the funds between the dates
+    /// `start` and `end`, to this output as it's computed. Returns the number of rows.
+    pub fn export_to<W: io::Write>(
+        &self,
+        kind: ExportKind,
+        start: u32,
+        end: u32,
+        out: W,
+    ) -> Result<u64, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        export_csv(&revaultd, &self.bitcoind_conn, kind, start, end, out)
+    }
+
+    /// Get the audit log entries recorded between the dates `start` and `end`.
+    pub fn get_audit_log(&self, start: u32, end: u32) -> Result<Vec<AuditLogEntry>, CommandError> {
+        let db_path = self.revaultd.read().unwrap().db_file();
+        Ok(db_audit_log(&db_path, start, end)
+            .map_err(CommandError::AuditLog)?
+            .into_iter()
+            .map(AuditLogEntry::from)
+            .collect())
+    }
+
+    /// Walk the audit log hash chain to check it wasn't tampered with.
+    pub fn verify_audit_log(&self) -> Result<VerifyAuditLogResult, CommandError> {
+        let db_path = self.revaultd.read().unwrap().db_file();
+        let broken_at = db_verify_audit_log(&db_path).map_err(CommandError::AuditLog)?;
+
+        Ok(VerifyAuditLogResult {
+            valid: broken_at.is_none(),
+            broken_at,
+        })
+    }
+
+    /// Initiate the rotation of the keys of the deployment to a new wallet, with the descriptors
+    /// constructed from these keys and the same Unvault timelock. We keep using the current
+    /// wallet until we are restarted with the new descriptors, once all its vaults were moved.
+    ///
+    /// ## Errors
+    /// - If running in read-only mode
+    /// - If a rotation was already initiated
+    /// - If our new xpub isn't given for each of our roles, or isn't part of the new keys
+    /// - If the descriptors can't be constructed from these keys
+    pub fn initiate_wallet_rotation(
+        &self,
+        stakeholders_xpubs: &[bip32::ExtendedPubKey],
+        managers_xpubs: &[bip32::ExtendedPubKey],
+        managers_threshold: usize,
+        cosigners_keys: Vec<DescriptorPublicKey>,
+        cpfp_xpubs: &[bip32::ExtendedPubKey],
+        our_stakeholder_xpub: Option<bip32::ExtendedPubKey>,
+        our_manager_xpub: Option<bip32::ExtendedPubKey>,
+    ) -> Result<WalletRotationResult, CommandError> {
+        // Don't let two of them race to insert the new wallet
+        let revaultd = self.revaultd.write().unwrap();
+        not_read_only!(revaultd);
+        let db_path = revaultd.db_file();
+        let wallet_id = revaultd
+            .wallet_id
+            .expect("Wallet id is set at startup in setup_db()");
+
+        if db_pending_rotation(&db_path)
+            .expect("Database must be available")
+            .is_some()
+        {
+            return Err(CommandError::WalletRotationInProgress);
+        }
+        for (our_xpub, xpubs, is_role, role) in &[
+            (
+                our_stakeholder_xpub,
+                stakeholders_xpubs,
+                revaultd.is_stakeholder(),
+                "stakeholder",
+            ),
+            (
+                our_manager_xpub,
+                managers_xpubs,
+                revaultd.is_manager(),
+                "manager",
+            ),
+        ] {
+            match our_xpub {
+                Some(xpub) if !is_role => {
+                    return Err(CommandError::InvalidParams(format!(
+                        "We are not a {}, can't have a new {} xpub ('{}')",
+                        role, role, xpub
+                    )))
+                }
+                Some(xpub) if !xpubs.contains(xpub) => {
+                    return Err(CommandError::InvalidParams(format!(
+                        "Our new {} xpub '{}' is not part of the new {}s' xpubs",
+                        role, xpub, role
+                    )))
+                }
+                None if *is_role => {
+                    return Err(CommandError::InvalidParams(format!(
+                        "We are a {}, our new {} xpub is needed",
+                        role, role
+                    )))
+                }
+                _ => {}
+            }
+        }
+
+        let (deposit_descriptor, unvault_descriptor, cpfp_descriptor) = deployment_descriptors(
+            stakeholders_xpubs,
+            managers_xpubs,
+            managers_threshold,
+            cosigners_keys,
+            revaultd.unvault_csv(),
+            cpfp_xpubs,
+        )
+        .map_err(|e| CommandError::InvalidParams(e.to_string()))?;
+        let script_version =
+            descriptors_script_version(&deposit_descriptor, &unvault_descriptor, &cpfp_descriptor)
+                .map_err(|e| CommandError::InvalidParams(e.to_string()))?;
+        let new_wallet_id = db_initiate_wallet_rotation(
+            &db_path,
+            wallet_id,
+            &deposit_descriptor,
+            &unvault_descriptor,
+            &cpfp_descriptor,
+            script_version,
+            our_manager_xpub.as_ref(),
+            our_stakeholder_xpub.as_ref(),
+            revaultd.clock.unix_timestamp(),
+        )
+        .expect("Database must be available");
+        log_event!(
+            log::Level::Info,
+            "wallet_rotation_initiated",
+            old_wallet_id = wallet_id,
+            new_wallet_id = new_wallet_id;
+            "Initiated the rotation of the keys from wallet '{}' to wallet '{}'",
+            wallet_id,
+            new_wallet_id
+        );
+
+        Ok(WalletRotationResult {
+            wallet_id: new_wallet_id,
+            deposit_descriptor: deposit_descriptor.to_string(),
+            unvault_descriptor: unvault_descriptor.to_string(),
+            cpfp_descriptor: cpfp_descriptor.to_string(),
+        })
+    }
+
+    /// Move the 'active' vaults of the current wallet to the new wallet of the rotation of the
+    /// keys. Each batch of at most `max_spend_inputs` vaults gets a Spend transaction paying to a
+    /// fresh deposit address of the new wallet, to be signed and set as any other. The vaults
+    /// already being moved are skipped, so that calling it again resumes the migration (for
+    /// instance after deleting a Spend transaction that could not be broadcast).
+    ///
+    /// ## Errors
+    /// - If called for a non-manager
+    /// - If running in read-only mode
+    /// - If the space left on disk is below the critical threshold
+    /// - If no rotation was initiated
+    /// - If the feerate is below our bitcoind's minimum relay feerate
+    /// - If a Spend transaction would pay more fees than the configured limits, or be too large
+    ///   to be transmitted to the coordinator
+    /// - If another command is using one of the vaults for too long
+    pub fn migrate_vaults(
+        &self,
+        feerate_vb: u64,
+    ) -> Result<Vec<VaultMigrationEntry>, CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        not_read_only!(revaultd);
+        manager_only!(revaultd);
+        check_disk_space(&revaultd)?;
+        let db_path = revaultd.db_file();
+        let wallet_id = revaultd
+            .wallet_id
+            .expect("Wallet id is set at startup in setup_db()");
+
+        let rotation = db_pending_rotation(&db_path)
+            .expect("Database must be available")
+            .ok_or(CommandError::NoWalletRotation)?;
+        let new_wallet = db_wallet_by_id(&db_path, rotation.new_wallet_id)
+            .expect("Database must be available")
+            .expect("Rotations always refer to an existing wallet");
+
+        // FIXME: have a feerate type to avoid that
+        assert!(feerate_vb > 0, "Spend feerate can't be null.");
+        let min_feerate_vb = self.bitcoind_conn.min_relay_feerate()?;
+        if feerate_vb < min_feerate_vb {
+            return Err(CommandError::SpendFeerateBelowRelay(
+                feerate_vb,
+                min_feerate_vb,
+            ));
+        }
+
+        let mut vaults: Vec<DbVault> = db_vaults(&db_path)
+            .expect("Database must be available")
+            .into_iter()
+            .filter(|vault| vault.wallet_id == wallet_id && vault.status == VaultStatus::Active)
+            .collect();
+        vaults.sort_by_key(|vault| vault.deposit_outpoint);
+        let _locks = self.resource_locks.lock(
+            vaults
+                .iter()
+                .map(|vault| LockedResource::Vault(vault.deposit_outpoint)),
+            LOCK_TIMEOUT,
+        )?;
+        // Check it under the locks, as a Spend could have been stored for them in the meantime
+        vaults.retain(|vault| {
+            db_vault_migration(&db_path, vault.id)
+                .expect("Database must be available")
+                .is_none()
+        });
+
+        let mut migrations = Vec::with_capacity(vaults.len());
+        let mut derivation_index = new_wallet.deposit_derivation_index;
+        for batch in vaults.chunks(revaultd.max_spend_inputs) {
+            let mut txins = Vec::with_capacity(batch.len());
+            let mut unvault_txs = Vec::with_capacity(batch.len());
+            for vault in batch {
+                txins.push((
+                    vault.deposit_outpoint,
+                    vault.amount,
+                    vault.derivation_index.into(),
+                ));
+                unvault_txs.push(
+                    db_unvault_transaction(&db_path, vault.id)
+                        .expect("Database must be available")
+                        .expect("An 'active' vault has its Unvault transaction"),
+                );
+            }
+            let spent_value = txins
+                .iter()
+                .fold(BitcoinAmount::from_sat(0), |sum, (_, amount, _)| {
+                    sum + *amount
+                });
+            sort_spend_txins(&mut txins);
+
+            // The new wallet's vault: all the funds but the fees and the CPFP output's value
+            let new_deposit_descriptor = new_wallet
+                .deposit_descriptor
+                .derive(derivation_index.into(), &revaultd.secp_ctx);
+            let address = descriptor_address(
+                new_deposit_descriptor.inner(),
+                revaultd.bitcoind_config.network,
+            )?;
+            let spend_tx = spend_tx_with_change(
+                &revaultd,
+                txins,
+                Vec::new(),
+                feerate_vb,
+                &new_deposit_descriptor,
+            )?;
+            check_spend_fees(
+                BitcoinAmount::from_sat(spend_tx.fees()),
+                spent_value,
+                revaultd.max_spend_fee_percent,
+                revaultd.max_spend_fee,
+            )?;
+            if !check_spend_transaction_size(&revaultd, spend_tx.clone()) {
+                return Err(CommandError::SpendTooLarge);
+            }
+
+            db_insert_migration_spend(
+                &db_path,
+                &unvault_txs,
+                &spend_tx,
+                new_wallet.id,
+                derivation_index,
+                revaultd.clock.unix_timestamp(),
+            )
+            .expect("Database must be available");
+            log_event!(
+                log::Level::Info,
+                "vaults_migration",
+                txid = spend_tx.txid(),
+                vaults = batch.len();
+                "Created Spend transaction '{}' moving '{}' vault(s) to '{}'",
+                spend_tx.txid(),
+                batch.len(),
+                address
+            );
+
+            migrations.push(VaultMigrationEntry {
+                deposit_outpoints: batch.iter().map(|vault| vault.deposit_outpoint).collect(),
+                address,
+                spend_tx,
+            });
+            derivation_index = derivation_index.saturating_add(1);
+        }
+
+        Ok(migrations)
+    }
+}
+
+/// The new wallet the keys are being rotated to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletRotationResult {
+    pub wallet_id: u32,
+    pub deposit_descriptor: String,
+    pub unvault_descriptor: String,
+    pub cpfp_descriptor: String,
+}
+
+/// A Spend transaction moving vaults to a deposit address of the new wallet of a rotation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultMigrationEntry {
+    pub deposit_outpoints: Vec<OutPoint>,
+    pub address: Address,
+    pub spend_tx: SpendTransaction,
+}
+
+/// The result of an export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportResult {
+    /// The number of rows, not counting the header
+    pub rows: u64,
+    /// The file the export was written to, if any
+    pub path: Option<PathBuf>,
+    /// The export itself, if it was not written to a file
+    pub csv: Option<String>,
+}
+
+/// Descriptors the daemon was configured with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInfoDescriptors {
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub deposit: DepositDescriptor,
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub unvault: UnvaultDescriptor,
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub cpfp: CpfpDescriptor,
+}
+
+/// The effective paths the daemon is using
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInfoPaths {
+    pub data_dir: PathBuf,
+    pub db: PathBuf,
+    pub log: PathBuf,
+    pub rpc_socket: PathBuf,
+}
+
+/// The configured limits on the number of elements a command accepts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInfoLimits {
+    pub max_spend_inputs: usize,
+    pub max_batch_size: usize,
+}
+
+/// A deposit address reserved for a single depositor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewDepositAddress {
+    pub address: Address,
+    pub derivation_index: DerivationIndex,
+}
+
+/// Where we are in the derivation of deposit addresses, compared to the deposits we saw
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInfoDepositIndexes {
+    /// The index of the next deposit address we'll hand out
+    pub first_unused: DerivationIndex,
+    /// The indexes of the new addresses handed out with no deposit to them yet
+    pub reserved: Vec<DerivationIndex>,
+    /// The highest index we received a deposit at, if any
+    pub max_observed: Option<DerivationIndex>,
+    /// The highest index we watch deposits at
+    pub watched_up_to: DerivationIndex,
+    /// The highest index we would extend our watch to
+    pub ceiling: DerivationIndex,
+}
+
+/// A vault whose revocation transaction bitcoind would not accept in its mempool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RejectedRevocation {
+    pub deposit_outpoint: OutPoint,
+    pub txid: Txid,
+    pub reason: String,
+}
+
+impl RejectedRevocation {
+    fn from_check(check: DbRevocationCheck) -> Option<Self> {
+        match (check.rejected_txid, check.reject_reason) {
+            (Some(txid), Some(reason)) => Some(Self {
+                deposit_outpoint: check.deposit_outpoint,
+                txid,
+                reason,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The result of the periodic checks of the daemon's ability to protect the funds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInfoHealth {
+    /// When we last checked bitcoind would accept our revocation transactions, if ever
+    pub revocations_checked_at: Option<u32>,
+    pub rejected_revocations: Vec<RejectedRevocation>,
+    /// How the space available on the filesystem of the data directory compares to the
+    /// thresholds, and this space in bytes. None if it could not be checked.
+    pub disk_space: Option<DiskSpaceLevel>,
+    pub available_disk_space: Option<u64>,
+    /// The size of the database file, in bytes
+    pub db_size: u64,
+    /// The number of invalid messages from the servers we rejected since startup
+    pub rejected_messages: u64,
+    /// When we last compared our watchonly wallet with what we imported into it, if ever
+    pub wallet_audited_at: Option<u64>,
+    /// What it watched that we never imported, at the last audit
+    pub unexpected_wallet_entries: Vec<String>,
+    /// How our presigned transactions' feerates compared to the relay floor of bitcoind at the
+    /// last check, if any since startup
+    pub relay_floor: Option<RelayFloorCheck>,
+}
+
+/// The vaults we are still fetching signatures for from the Coordinator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInfoSignatures {
+    /// The number of vaults missing some signatures
+    pub backlog: usize,
+    /// The value of those whose revocation transactions are not all signed yet
+    pub unsecured_amount: Amount,
+}
+
+/// A descriptor and the size of the scripts derived from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DescriptorEntry {
+    pub descriptor: String,
+    /// Size of the witness script, in bytes
+    pub script_size: usize,
+    /// Maximum number of witness stack elements to satisfy it, not counting the script
+    pub witness_elements: usize,
+    /// Maximum weight of a witness satisfying it
+    pub satisfaction_weight: usize,
+    /// How many bytes could be added to the script before it's no longer standard to spend
+    pub script_size_headroom: i64,
+    /// How many elements could be added to the witness before it's no longer standard
+    pub witness_elements_headroom: i64,
+}
+
+impl DescriptorEntry {
+    fn new(descriptor: String, limits: ScriptLimits) -> Self {
+        Self {
+            descriptor,
+            script_size: limits.script_size,
+            witness_elements: limits.witness_elements,
+            satisfaction_weight: limits.satisfaction_weight,
+            script_size_headroom: limits.script_size_headroom(),
+            witness_elements_headroom: limits.witness_elements_headroom(),
+        }
+    }
+}
+
+/// The descriptors the daemon was configured with and the size of their scripts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetDescriptorsResult {
+    pub deposit: DescriptorEntry,
+    pub unvault: DescriptorEntry,
+    pub cpfp: DescriptorEntry,
+}
+
+/// The metadata of the build of the daemon, and when this version first ran
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionResult {
+    #[serde(flatten)]
+    pub build: BuildInfo,
+    /// When this version first ran on our database. It's when the stored scripts of our vaults
+    /// were last checked to derive identically, unless asked to check them at each startup.
+    pub first_run_at: u32,
+}
+
+/// Information about the current state of the daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetInfoResult {
     pub version: String,
+    /// The metadata of the build of this binary
+    pub build: BuildInfo,
     pub network: Network,
     pub blockheight: i32,
     pub sync: f64,
+    /// What bitcoind is rescanning the chain for, if we are importing descriptors
+    pub rescan: Option<RescanWindow>,
+    /// Whether we could reach bitcoind the last time we polled it
+    pub bitcoind_reachable: bool,
+    /// How much our connections to bitcoind are used, for each of its endpoints
+    pub bitcoind_connections: Vec<PoolStats>,
+    /// Whether we are only monitoring, refusing the commands that would change anything
+    pub read_only: bool,
+    /// How hard the database commits are made durable, the sqlite `synchronous` setting
+    pub db_synchronous: String,
     pub vaults: usize,
     pub managers_threshold: usize,
     pub descriptors: GetInfoDescriptors,
+    pub paths: GetInfoPaths,
+    pub limits: GetInfoLimits,
+    pub deposit_indexes: GetInfoDepositIndexes,
+    pub health: GetInfoHealth,
+    pub signatures: GetInfoSignatures,
+    /// When we may initiate a spend, if restricted
+    pub spending_schedule: Option<SpendingSchedule>,
+    /// The levels at which we currently log
+    pub log_levels: LogLevelsInfo,
+}
+
+/// The levels at which we log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogLevelsInfo {
+    pub level: String,
+    /// The levels of some modules (and their submodules), by module path
+    pub modules: BTreeMap<String, String>,
+    /// When the configured levels will be restored, if they were changed temporarily
+    pub revert_at: Option<u64>,
+}
+
+impl From<(LogLevels, Option<u64>)> for LogLevelsInfo {
+    fn from((levels, revert_at): (LogLevels, Option<u64>)) -> Self {
+        Self {
+            level: levels.level.to_string().to_lowercase(),
+            modules: levels
+                .modules
+                .into_iter()
+                .map(|(module, level)| (module, level.to_string().to_lowercase()))
+                .collect(),
+            revert_at,
+        }
+    }
+}
+
+/// Our Noise static public key, hex-encoded, and its short fingerprint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseStaticPubkeyResult {
+    pub pubkey: String,
+    pub fingerprint: String,
+}
+
+/// An entry of the audit log of destructive commands
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub method: String,
+    pub params_digest: String,
+    pub result: String,
+    pub timestamp: u32,
+    pub peer_uid: Option<u32>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl From<DbAuditEntry> for AuditLogEntry {
+    fn from(db_entry: DbAuditEntry) -> Self {
+        Self {
+            id: db_entry.id,
+            method: db_entry.method,
+            params_digest: db_entry.params_digest.to_hex(),
+            result: db_entry.result,
+            timestamp: db_entry.timestamp,
+            peer_uid: db_entry.peer_uid,
+            prev_hash: db_entry.prev_hash.to_hex(),
+            hash: db_entry.hash.to_hex(),
+        }
+    }
+}
+
+/// The result of walking the audit log hash chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyAuditLogResult {
+    pub valid: bool,
+    /// The id of the first entry that breaks the chain
+    pub broken_at: Option<i64>,
+}
+
+/// A participant's xpub, as present in the descriptors
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParticipantEntry {
+    pub xpub: String,
+    pub fingerprint: String,
+    /// The name we were configured with for this participant, if any
+    pub label: Option<String>,
+    /// Whether this xpub is the one we were configured with
+    pub ours: bool,
+}
+
+/// A cosigning server and whether we could connect to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CosignerEntry {
+    pub host: String,
+    pub noise_key: String,
+    pub noise_key_fingerprint: String,
+    /// The name we were configured with for this cosigning server, if any
+    pub label: Option<String>,
+    pub reachable: bool,
+}
+
+/// A coordinator we are configured with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoordinatorEntry {
+    pub host: String,
+    pub noise_key: String,
+    pub noise_key_fingerprint: String,
+}
+
+/// All the participants to this deployment. Cosigners are only known to managers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListParticipantsResult {
+    pub stakeholders: Vec<ParticipantEntry>,
+    pub managers: Vec<ParticipantEntry>,
+    pub managers_threshold: usize,
+    pub cosigners: Vec<CosignerEntry>,
+    pub coordinator: CoordinatorEntry,
+    /// The Coordinators to fail over to, by order of preference
+    pub backup_coordinators: Vec<CoordinatorEntry>,
 }
 
 /// Information about a vault.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListVaultsEntry {
-    #[serde(
-        serialize_with = "ser_amount",
-        deserialize_with = "deser_amount_from_sats"
-    )]
     pub amount: Amount,
     pub blockheight: u32,
     #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
     pub status: VaultStatus,
     pub txid: Txid,
     pub vout: u32,
-    pub derivation_index: bip32::ChildNumber,
+    pub derivation_index: DerivationIndex,
     pub address: Address,
     pub funded_at: Option<u32>,
     pub secured_at: Option<u32>,
     pub delegated_at: Option<u32>,
     pub moved_at: Option<u32>,
+    /// Transactions spending this vault that were rejected by bitcoind's mempool.
+    pub conflicts: Vec<VaultConflict>,
+    /// The problems this vault was flagged for, that were not cleared yet.
+    pub flags: Vec<VaultFlag>,
+    /// Whether the funds were canceled and the vault created by the Cancel isn't secured yet.
+    pub awaiting_resecuring: bool,
+    /// The vault whose Cancel transaction created this one, if any.
+    pub revaulted_from: Option<OutPoint>,
+    /// The vault created by the Cancel transaction of this one, if any.
+    pub revaulted_to: Option<OutPoint>,
+    /// Whether the funds come from an external deposit, or from the output of our own Cancel
+    /// or Spend transaction.
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub origin: DepositOrigin,
+    /// For a vault created by the change output of a Spend, the vaults this Spend consumed.
+    pub change_from: Vec<OutPoint>,
+    /// For 'unvaulted' and 'spendable' vaults, the height at which the Unvault confirmed.
+    pub unvault_height: Option<u32>,
+    /// For 'unvaulted' and 'spendable' vaults, the relative timelock of the Unvault output.
+    pub csv: Option<u32>,
+    /// For 'unvaulted' and 'spendable' vaults, the number of blocks to be mined before the
+    /// managers may spend the Unvault output.
+    pub blocks_until_spendable: Option<u32>,
+    /// The height at which the revocation transactions of the vault were last all signed, if
+    /// they still are.
+    pub secured_height: Option<u32>,
+    /// The height at which the vault was spent, canceled or emergency'd.
+    pub moved_height: Option<u32>,
+    /// Whether the deposit transaction left the mempool without confirming and we gave up on
+    /// it. It's resurrected if the transaction shows up again.
+    pub abandoned: bool,
+    /// For 'unconfirmed' vaults, the unconfirmed transactions the deposit transaction depended
+    /// on when we detected it, if any.
+    pub ancestry: Option<DepositAncestry>,
+    /// If the deposit transaction is a coinbase, when its output matures.
+    pub coinbase: Option<CoinbaseMaturity>,
+}
+
+/// Filters on the heights at which the vaults were funded, and the height to list them at.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VaultHeightFilter {
+    /// Only list vaults whose deposit confirmed at or after this height.
+    pub min_funded_height: Option<u32>,
+    /// Only list vaults whose deposit confirmed at or before this height.
+    pub max_funded_height: Option<u32>,
+    /// List the vaults with the status they had at this height, according to the recorded
+    /// transitions. Vaults we didn't know about at this height are not listed.
+    pub as_of_height: Option<u32>,
+}
+
+/// The value of our vaults, by how protected it is
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GetBalancesResult {
+    /// Deposits that are not confirmed yet
+    pub unconfirmed: Amount,
+    /// Vaults whose revocation transactions are not signed yet
+    pub unsecured: Amount,
+    /// Funds that were canceled to a new vault that isn't secured yet
+    pub awaiting_resecuring: Amount,
+    /// Vaults whose revocation transactions are signed
+    pub secured: Amount,
+    /// Vaults whose Unvault transaction is signed, that managers may spend
+    pub active: Amount,
+    /// Vaults being unvaulted, spent or canceled by a transaction that isn't confirmed yet
+    pub moving: Amount,
+    /// Vaults being sent to the EDV by their Emergency transaction, before they were unvaulted
+    pub emergency_vaulting: Amount,
+    /// Vaults being sent to the EDV by their Unvault Emergency transaction, after they were
+    /// unvaulted
+    pub unvault_emergency_vaulting: Amount,
+    /// The same funds, by where they come from
+    pub by_origin: BalancesByOrigin,
+}
+
+/// The fees needed to bring the Unvault transactions to a feerate through their CPFP output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpfpReserve {
+    /// The target feerate, in sat/vbyte
+    pub feerate: u64,
+    pub vaults: Vec<CpfpReserveEntry>,
+    /// The sum of the fees needed for all the vaults
+    pub total: Amount,
+    /// The value of the confirmed coins of the CPFP wallet, if we have a CPFP key
+    pub available: Option<Amount>,
+}
+
+/// The fees needed to bring the Unvault transaction of a vault to the target feerate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpfpReserveEntry {
+    pub deposit_outpoint: OutPoint,
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub status: VaultStatus,
+    pub unvault_txid: Txid,
+    /// The feerate of the Unvault transaction, in sat/vbyte (rounded down)
+    pub feerate: u64,
+    /// The fees the CPFP transaction must pay for it, on top of its own
+    pub reserve: Amount,
+}
+
+/// The value of our vaults, by how the funds got to us
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BalancesByOrigin {
+    /// Deposits from outside the wallet
+    pub external: Amount,
+    /// Outputs of the Cancel transaction of another vault
+    pub cancel: Amount,
+    /// Change outputs of our Spend transactions
+    pub spend_change: Amount,
+}
+
+/// A transaction that was rejected because it conflicted with another one in the mempool.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VaultConflict {
+    pub rejected_txid: Txid,
+    /// The transaction that was in the mempool, if we know about it.
+    pub competing_txid: Option<Txid>,
+    /// The transaction that eventually got confirmed, if any.
+    pub confirmed_txid: Option<Txid>,
+}
+
+impl From<DbMempoolConflict> for VaultConflict {
+    fn from(db_conflict: DbMempoolConflict) -> Self {
+        Self {
+            rejected_txid: db_conflict.rejected_txid,
+            competing_txid: db_conflict.competing_txid,
+            confirmed_txid: db_conflict.confirmed_txid,
+        }
+    }
+}
+
+/// The unconfirmed transactions a deposit transaction depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DepositAncestry {
+    /// How many of them there are.
+    pub unconfirmed_ancestors: u32,
+    /// Their total virtual size.
+    pub vsize: u64,
+    /// The length of the longest chain of them.
+    pub depth: u32,
+}
+
+impl From<DbDepositAncestry> for DepositAncestry {
+    fn from(db_ancestry: DbDepositAncestry) -> Self {
+        Self {
+            unconfirmed_ancestors: db_ancestry.ancestors,
+            vsize: db_ancestry.vsize,
+            depth: db_ancestry.depth,
+        }
+    }
+}
+
+/// When the output of a coinbase deposit transaction may be spent, and the vault confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CoinbaseMaturity {
+    /// The height the coinbase transaction was mined at.
+    pub height: u32,
+    /// The height from which its output may be spent.
+    pub mature_height: u32,
+    /// The number of blocks to be mined before it matures.
+    pub blocks_until_mature: u32,
+}
+
+impl CoinbaseMaturity {
+    pub fn new(height: u32, ref_height: u32) -> Self {
+        let mature_height = height + COINBASE_MATURITY;
+        Self {
+            height,
+            mature_height,
+            blocks_until_mature: mature_height.saturating_sub(ref_height),
+        }
+    }
+}
+
+/// A problem about a vault that needs the attention of an operator, until they clear it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VaultFlag {
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub kind: VaultFlagKind,
+    pub message: String,
+    pub created_at: u32,
+}
+
+impl From<DbVaultFlag> for VaultFlag {
+    fn from(db_flag: DbVaultFlag) -> Self {
+        Self {
+            kind: db_flag.kind,
+            message: db_flag.message,
+            created_at: db_flag.created_at,
+        }
+    }
+}
+
+/// Everything needed to recognize a given vault and its transactions on chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultDetails {
+    pub deposit_outpoint: OutPoint,
+    pub derivation_index: DerivationIndex,
+    pub deposit_address: Address,
+    pub deposit_script_pubkey: Script,
+    pub deposit_witness_script: Script,
+    pub unvault_address: Address,
+    pub unvault_script_pubkey: Script,
+    pub unvault_witness_script: Script,
+    pub cpfp_address: Address,
+    pub cpfp_script_pubkey: Script,
+    /// The one its Emergency transactions pay to, only known to stakeholders
+    pub emergency_address: Option<Address>,
+    pub txids: VaultTxids,
+    pub final_txids: VaultFinalTxids,
+}
+
+/// The txids of a vault's presigned transactions, whether they were broadcast or not
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultTxids {
+    pub unvault: Txid,
+    pub cancel: Txid,
+    /// Only known to stakeholders
+    pub emergency: Option<Txid>,
+    /// Only known to stakeholders
+    pub unvault_emergency: Option<Txid>,
+}
+
+/// The txids of a vault's presigned transactions once fully signed. Unset until we have all
+/// their signatures.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultFinalTxids {
+    pub unvault: Option<Txid>,
+    pub cancel: Option<Txid>,
+    pub emergency: Option<Txid>,
+    pub unvault_emergency: Option<Txid>,
+}
+
+impl VaultFinalTxids {
+    fn from_db(final_txids: &[DbFinalTxid]) -> Self {
+        let txid_of = |tx_type| {
+            final_txids
+                .iter()
+                .find(|final_txid| final_txid.tx_type == tx_type)
+                .map(|final_txid| final_txid.txid)
+        };
+
+        Self {
+            unvault: txid_of(TransactionType::Unvault),
+            cancel: txid_of(TransactionType::Cancel),
+            emergency: txid_of(TransactionType::Emergency),
+            unvault_emergency: txid_of(TransactionType::UnvaultEmergency),
+        }
+    }
+}
+
+/// A message signed by one of the keys of the stakeholders' deep-cold setup, as a proof of its
+/// control.
+#[derive(Debug, Clone)]
+pub struct EmergencyKeyProof {
+    pub pubkey: BitcoinPubKey,
+    pub message: String,
+    /// DER-encoded ECDSA signature of the SHA256 of the message
+    pub signature: Vec<u8>,
+}
+
+/// Whether a descriptor generates one of our Emergency addresses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyDescriptorVerification {
+    pub descriptor: String,
+    /// Only set for a ranged descriptor
+    pub derivation_index: Option<u32>,
+    pub derived_address: Address,
+    /// The derived address if it is one of ours, our first one otherwise
+    pub emergency_address: Address,
+    pub matches: bool,
+    /// Whether the signed message proves the control of one of its keys, if one was given
+    pub proof_valid: Option<bool>,
+    /// Whether it is stored for future re-verification
+    pub stored: bool,
 }
 
 /// Revocation transactions for a given vault
@@ -1469,6 +4231,122 @@ pub struct VaultPresignedTransaction<T: RevaultTransaction> {
     // FIXME: is it really necessary?.. It's mostly contained in the PSBT already
     #[serde(rename(serialize = "hex"), serialize_with = "serialize_option_tx_hex")]
     pub transaction: Option<BitcoinTransaction>,
+    /// Whether each stakeholder signed it
+    pub signers: Vec<PresignedTxSigner>,
+    pub estimates: PresignedTxEstimates,
+    /// The fee market when it got fully signed. None if it is not, or if it was signed before
+    /// we recorded it.
+    pub signing_context: Option<SigningContext>,
+}
+
+/// The fee market when the signature set of a presigned transaction completed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SigningContext {
+    /// The feerate its fixed fees imply, in sat/vbyte (rounded down)
+    pub feerate: u64,
+    /// bitcoind's estimate for a confirmation within 2 blocks, in sat/vbyte
+    pub next_block_estimate: Option<u64>,
+    /// bitcoind's estimate for a confirmation within 144 blocks, in sat/vbyte
+    pub next_day_estimate: Option<u64>,
+    /// The height of our tip
+    pub blockheight: u32,
+    pub signed_at: u32,
+}
+
+impl From<DbSigningContext> for SigningContext {
+    fn from(context: DbSigningContext) -> Self {
+        Self {
+            feerate: context.feerate,
+            next_block_estimate: context.next_block_estimate,
+            next_day_estimate: context.next_day_estimate,
+            blockheight: context.blockheight,
+            signed_at: context.signed_at,
+        }
+    }
+}
+
+/// A revocation transaction signed at a feerate below the one we were asked about
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StaleRevocationTx {
+    /// Either 'cancel', 'emergency' or 'unvault_emergency'
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub txid: Txid,
+    pub signing_context: SigningContext,
+}
+
+/// A vault with revocation transactions signed at a feerate below the one we were asked about
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StaleRevocationsEntry {
+    pub deposit_outpoint: OutPoint,
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub status: VaultStatus,
+    pub transactions: Vec<StaleRevocationTx>,
+}
+
+/// What a watchtower needs to enforce the revocation of a vault: the Unvault output to watch
+/// for and the finalized revocation transactions spending it. Only the outpoint is given for
+/// revoked entries, the vault isn't Active anymore.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WatchDataEntry {
+    pub deposit_outpoint: OutPoint,
+    /// When this entry last changed
+    pub sequence: u64,
+    pub revoked: bool,
+    pub unvault_txid: Option<Txid>,
+    pub unvault_script_pubkey: Option<Script>,
+    #[serde(serialize_with = "serialize_option_tx_hex")]
+    pub cancel_tx: Option<BitcoinTransaction>,
+    #[serde(serialize_with = "serialize_option_tx_hex")]
+    pub unvault_emergency_tx: Option<BitcoinTransaction>,
+}
+
+/// The watch data that changed since the sequence number we were given
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WatchDataResult {
+    /// The sequence number to fetch the next changes from
+    pub sequence: u64,
+    pub vaults: Vec<WatchDataEntry>,
+}
+
+/// The final txid of a vault's presigned transaction
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WatchTxid {
+    pub deposit_outpoint: OutPoint,
+    pub txid: Txid,
+}
+
+/// The final txids of the presigned transactions of the vaults that may still be revoked or
+/// spent, by transaction type
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListWatchTxidsResult {
+    pub unvault: Vec<WatchTxid>,
+    pub cancel: Vec<WatchTxid>,
+    pub emergency: Vec<WatchTxid>,
+    pub unvault_emergency: Vec<WatchTxid>,
+}
+
+/// The size of a presigned transaction once fully signed, and the feerate its fees imply
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PresignedTxEstimates {
+    /// Maximum weight, in weight units
+    pub weight: u64,
+    /// Maximum virtual size, in vbytes
+    pub vsize: u64,
+    /// The fees it pays, in satoshis. They are fixed at signing time.
+    pub fees: u64,
+    /// The feerate at the maximum virtual size, in sat/vbyte (rounded down)
+    pub feerate: u64,
+    /// The value of the CPFP output, only for the Unvault transaction
+    pub cpfp_value: Option<u64>,
+}
+
+/// A stakeholder's signature of a presigned transaction, if any
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresignedTxSigner {
+    pub fingerprint: String,
+    pub label: Option<String>,
+    pub signed: bool,
 }
 
 /// Information about a vault's presigned transactions.
@@ -1504,6 +4382,10 @@ pub enum ListSpendStatus {
     NonFinal,
     Pending,
     Broadcasted,
+    /// A vault it spends was canceled, it can't confirm anymore
+    Deprecated,
+    /// We gave up on it before its Unvaults were broadcast
+    Expired,
 }
 
 /// Information about a Spend transaction
@@ -1513,16 +4395,88 @@ pub struct ListSpendEntry {
     pub psbt: SpendTransaction,
     pub cpfp_index: usize,
     pub change_index: Option<usize>,
+    /// Mempool conflicts this Spend was involved in, either as the rejected or competing tx.
+    pub conflicts: Vec<VaultConflict>,
+    /// The configured cosigning servers and whether we already got their signatures.
+    pub cosigners: Vec<SpendCosignerEntry>,
+    /// Whether we announced it to the Coordinator, and what the Coordinator reported about it.
+    pub announcement: Option<SpendAnnouncement>,
+    /// Why it can't confirm anymore, if it was deprecated.
+    pub deprecation: Option<SpendDeprecation>,
+    /// Why we gave up on it, if it expired.
+    pub expiration: Option<SpendExpiration>,
+}
+
+/// What we know of the announcement of a Spend transaction to the Coordinator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpendAnnouncement {
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub status: AnnouncementStatus,
+    /// The Noise key of the Coordinator that acknowledged it, or reported it if we adopted it.
+    pub coordinator_noise_key: Option<String>,
+    /// When we announced it. Unknown if we adopted it.
+    pub announced_at: Option<u32>,
+    /// When we last checked it against the Coordinator.
+    pub reconciled_at: Option<u32>,
+}
+
+impl From<DbSpendAnnouncement> for SpendAnnouncement {
+    fn from(db_announcement: DbSpendAnnouncement) -> Self {
+        Self {
+            status: db_announcement.status,
+            coordinator_noise_key: db_announcement.coordinator_key.map(|key| key.to_hex()),
+            announced_at: db_announcement.announced_at,
+            reconciled_at: db_announcement.reconciled_at,
+        }
+    }
+}
+
+/// A Spend transaction that can't confirm anymore, as a vault it spends was canceled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpendDeprecation {
+    /// The deposit outpoint of the canceled vault
+    pub deposit_outpoint: OutPoint,
+    pub cancel_txid: Txid,
+    /// The time of the block the Cancel was confirmed in
+    pub deprecated_at: u32,
+}
+
+/// A Spend transaction we gave up on before its Unvault transactions were broadcast.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpendExpiration {
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+    pub reason: SpendExpiryReason,
+    pub expired_at: u32,
+}
+
+/// A cosigning server and whether it signed a given Spend transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpendCosignerEntry {
+    pub host: String,
+    pub noise_key: String,
+    pub signed: bool,
 }
 
 /// Information about the configured servers.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServersStatuses {
+    /// The coordinator in use
     pub coordinator: ServerStatus,
+    /// The main coordinator then the backup ones
+    pub coordinators: Vec<CoordinatorStatus>,
+    /// Our signatures waiting to be pushed to the coordinator
+    pub coordinator_queue: CoordinatorQueueStatus,
     pub cosigners: Vec<ServerStatus>,
     pub watchtowers: Vec<ServerStatus>,
 }
 
+/// The signatures queued to be pushed to the coordinator, and the pace at which we push them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoordinatorQueueStatus {
+    pub depth: u64,
+    pub throttle: ThrottleStatus,
+}
+
 /// The type of an accounting event.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum HistoryEventKind {
@@ -1532,6 +4486,10 @@ pub enum HistoryEventKind {
     Deposit,
     #[serde(rename = "spend")]
     Spend,
+    #[serde(rename = "emergency")]
+    Emergency,
+    #[serde(rename = "unvault_emergency")]
+    UnvaultEmergency,
 }
 
 impl fmt::Display for HistoryEventKind {
@@ -1540,6 +4498,8 @@ impl fmt::Display for HistoryEventKind {
             Self::Cancel => write!(f, "Cancel"),
             Self::Deposit => write!(f, "Deposit"),
             Self::Spend => write!(f, "Spend"),
+            Self::Emergency => write!(f, "Emergency"),
+            Self::UnvaultEmergency => write!(f, "UnvaultEmergency"),
         }
     }
 }
@@ -1550,8 +4510,110 @@ pub struct HistoryEvent {
     pub kind: HistoryEventKind,
     pub date: u32,
     pub blockheight: u32,
-    pub amount: Option<u64>,
-    pub fee: Option<u64>,
+    pub amount: Option<Amount>,
+    pub fee: Option<Amount>,
     pub txid: Txid,
     pub vaults: Vec<OutPoint>,
 }
+
+/// Status of a spend proposal
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendProposalStatus {
+    /// Not enough managers decided yet
+    Pending,
+    /// Enough managers approved it, a Spend transaction may be created for it
+    Approved,
+    /// Too many managers rejected it for it to ever be approved
+    Rejected,
+    /// It can't be decided upon nor used anymore
+    Expired,
+}
+
+impl fmt::Display for SpendProposalStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending"),
+            Self::Approved => write!(f, "approved"),
+            Self::Rejected => write!(f, "rejected"),
+            Self::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+/// The decision of a manager about a spend proposal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpendProposalAckEntry {
+    pub fingerprint: String,
+    /// The name we were configured with for this manager, if any
+    pub label: Option<String>,
+    pub approved: bool,
+    pub created_at: u32,
+}
+
+/// Information about a spend proposal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpendProposalEntry {
+    pub id: u32,
+    pub outpoints: Vec<OutPoint>,
+    pub destinations: BTreeMap<Address, Amount>,
+    pub feerate_vb: u64,
+    pub created_at: u32,
+    pub expires_at: u32,
+    pub status: SpendProposalStatus,
+    /// How many approvals are needed for it to be approved
+    pub threshold: usize,
+    /// What a manager needs to sign to approve it, or to reject it
+    pub approval_digest: String,
+    pub rejection_digest: String,
+    pub acks: Vec<SpendProposalAckEntry>,
+}
+
+/// What became of a vault when running the emergency procedure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum EmergencyOutcome {
+    /// Its Emergency (or Unvault Emergency) transaction was broadcast
+    Broadcast { txid: Txid },
+    /// A previous run broadcast its Emergency (or Unvault Emergency) transaction already, it
+    /// was not broadcast again
+    AlreadyBroadcast { txid: Txid },
+    /// It is not in a status we can broadcast a revocation transaction from
+    SkippedStatus {
+        #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_from_str")]
+        status: VaultStatus,
+    },
+    /// Its Emergency (or Unvault Emergency) transaction is not fully signed
+    MissingSignatures,
+    /// bitcoind would not accept its Emergency (or Unvault Emergency) transaction
+    Rejected { txid: Txid, reason: String },
+}
+
+/// Which transaction sends a vault to the EDV
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmergencyTxKind {
+    /// The Emergency, spending the deposit of a vault that was not unvaulted
+    Emergency,
+    /// The Unvault Emergency, spending the Unvault output of a vault that was
+    UnvaultEmergency,
+}
+
+impl fmt::Display for EmergencyTxKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Emergency => write!(f, "emergency"),
+            Self::UnvaultEmergency => write!(f, "unvault_emergency"),
+        }
+    }
+}
+
+/// What became of a vault when running the emergency procedure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmergencyEntry {
+    pub deposit_outpoint: OutPoint,
+    /// The transaction used for this vault, or that already sent it to the EDV, if any
+    pub transaction: Option<EmergencyTxKind>,
+    #[serde(flatten)]
+    pub outcome: EmergencyOutcome,
+}
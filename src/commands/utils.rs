@@ -3,35 +3,76 @@
 //! fetcher thread.
 
 use crate::{
+    amount::Amount,
     commands::{
-        CommandError, HistoryEvent, HistoryEventKind, ListPresignedTxEntry, ListVaultsEntry,
-        VaultPresignedTransaction,
+        CoinbaseMaturity, CommandError, CoordinatorEntry, CosignerEntry, DepositAncestry,
+        EmergencyEntry, EmergencyKeyProof, EmergencyOutcome, EmergencyTxKind, HistoryEvent,
+        HistoryEventKind, ListParticipantsResult, ListPresignedTxEntry, ListVaultsEntry,
+        ParticipantEntry, PresignedTxEstimates, PresignedTxSigner, SigningContext,
+        SpendCosignerEntry, SpendProposalAckEntry, SpendProposalEntry, SpendProposalStatus,
+        VaultConflict, VaultFlag, VaultHeightFilter, VaultPresignedTransaction,
     },
+    communication::{
+        add_cosig_signatures, cosigners_status, poll_cosigning_servers, CommunicationError,
+        CoordinatorEndpoint,
+    },
+    config::noise_pubkey_fingerprint,
     database::{
+        actions::{db_expire_spend, db_record_emergency_run, db_store_cosig_signatures},
+        bitcointx::RevaultTx,
         interface::{
-            db_cancel_transaction, db_emer_transaction, db_signed_emer_txs, db_signed_unemer_txs,
-            db_unvault_emer_transaction, db_unvault_transaction, db_vault_by_deposit, db_vaults,
+            db_abandoned_vaults, db_cancel_transaction, db_cosig_signatures, db_deposit_ancestry,
+            db_deposit_coinbase_height, db_emer_transaction, db_emergency_broadcast_txids,
+            db_list_spends, db_spend_destination, db_spend_proposal, db_spend_proposal_acks,
+            db_spend_unvaults_broadcast, db_stale_spends, db_tip, db_unvault_emer_transaction,
+            db_unvault_height, db_unvault_transaction, db_vault_by_deposit,
+            db_vault_change_sources, db_vault_child, db_vault_conflicts, db_vault_flags,
+            db_vault_migration, db_vault_origin, db_vault_origins, db_vault_parent,
+            db_vault_signing_contexts, db_vault_transitions, db_vaults,
             db_vaults_with_txids_in_period,
         },
-        schema::DbVault,
+        schema::{
+            DbDerivedScript, DbEmergencyOutcome, DbSpendProposal, DbSpendProposalAck, DbVault,
+            DbVaultTransition, DepositOrigin, EmergencyOutcomeKind, ScriptKind, SpendExpiryReason,
+        },
         DatabaseError,
     },
+    derivation::DerivationIndex,
+    diskspace::DiskSpaceLevel,
     revaultd::{RevaultD, VaultStatus},
     threadmessages::*,
 };
 
 use revault_tx::{
     bitcoin::{
-        consensus::encode, hashes::hex::FromHex, Amount, OutPoint,
-        Transaction as BitcoinTransaction, Txid,
+        consensus::encode,
+        hashes::{
+            hash160,
+            hex::{FromHex, ToHex},
+            sha256, Hash,
+        },
+        secp256k1,
+        util::{
+            address::Payload,
+            bip32::{ChildNumber, ExtendedPubKey, Fingerprint},
+        },
+        Address, Amount as BitcoinAmount, Network, OutPoint, PublicKey as BitcoinPubKey,
+        Transaction as BitcoinTransaction, TxOut, Txid,
     },
-    miniscript::DescriptorTrait,
-    transactions::RevaultTransaction,
+    miniscript::{
+        descriptor::DescriptorPublicKey, Descriptor, DescriptorTrait, ForEach, ForEachKey,
+        TranslatePk2,
+    },
+    scripts::DerivedDepositDescriptor,
+    transactions::{spend_tx_from_deposits, RevaultTransaction, SpendTransaction},
+    txouts::{DepositTxOut, SpendTxOut},
 };
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryInto,
     fmt,
+    path::PathBuf,
     str::FromStr,
 };
 
@@ -75,63 +116,241 @@ where
     S::from_str(&s).map_err(de::Error::custom)
 }
 
-/// Serialize an amount as sats
-pub fn ser_amount<S: Serializer>(amount: &Amount, s: S) -> Result<S::Ok, S::Error> {
-    s.serialize_u64(amount.as_sat())
+// Whether the funds of this vault were canceled and are not secured again yet. They are once the
+// revocation transactions of the vault created by the Cancel output are signed.
+fn awaiting_resecuring(db_vault: &DbVault, has_parent: bool, child: Option<&DbVault>) -> bool {
+    let unsecured = |status: VaultStatus| {
+        matches!(
+            status,
+            VaultStatus::Unconfirmed | VaultStatus::Funded | VaultStatus::Securing
+        )
+    };
+
+    if db_vault.status == VaultStatus::Canceled {
+        child.map(|child| unsecured(child.status)).unwrap_or(true)
+    } else {
+        has_parent && unsecured(db_vault.status)
+    }
+}
+
+// The height at which the vault last reached a status implying its revocation transactions are
+// all signed, given its (chronological) transitions.
+fn secured_height(transitions: &[DbVaultTransition]) -> Option<u32> {
+    transitions
+        .iter()
+        .fold(None, |height, transition| match transition.status {
+            VaultStatus::Unconfirmed | VaultStatus::Funded | VaultStatus::Securing => None,
+            _ => height.or(Some(transition.blockheight)),
+        })
 }
 
-/// Deserialize an amount from sats
-pub fn deser_amount_from_sats<'de, D>(deserializer: D) -> Result<Amount, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let a = u64::deserialize(deserializer)?;
-    Ok(Amount::from_sat(a))
+// The height at which the vault was moved out of our custody, given its (chronological)
+// transitions.
+fn moved_height(transitions: &[DbVaultTransition]) -> Option<u32> {
+    transitions
+        .last()
+        .filter(|transition| {
+            matches!(
+                transition.status,
+                VaultStatus::Spent
+                    | VaultStatus::Canceled
+                    | VaultStatus::EmergencyVaulted
+                    | VaultStatus::UnvaultEmergencyVaulted
+            )
+        })
+        .map(|transition| transition.blockheight)
 }
 
 /// List the vaults from DB, and filter out the info the RPC wants
-// FIXME: we could make this more efficient with smarter SQL queries
 pub fn listvaults_from_db(
     revaultd: &RevaultD,
     statuses: Option<&[VaultStatus]>,
     outpoints: Option<&[OutPoint]>,
 ) -> Result<Vec<ListVaultsEntry>, DatabaseError> {
-    db_vaults(&revaultd.db_file()).map(|db_vaults| {
-        db_vaults
-            .into_iter()
-            .filter_map(|db_vault| {
-                if let Some(ref statuses) = statuses {
-                    if !statuses.contains(&db_vault.status) {
-                        return None;
-                    }
-                }
+    listvaults_at_heights(revaultd, statuses, outpoints, &VaultHeightFilter::default())
+}
 
-                if let Some(ref outpoints) = &outpoints {
-                    if !outpoints.contains(&db_vault.deposit_outpoint) {
-                        return None;
-                    }
-                }
+/// List the vaults from DB, additionally filtering them by funding height. If an `as_of_height`
+/// is given, the status of each vault is the one it had at this height according to the
+/// recorded transitions.
+pub fn listvaults_at_heights(
+    revaultd: &RevaultD,
+    statuses: Option<&[VaultStatus]>,
+    outpoints: Option<&[OutPoint]>,
+    height_filter: &VaultHeightFilter,
+) -> Result<Vec<ListVaultsEntry>, DatabaseError> {
+    vaults_at_heights(revaultd, statuses, outpoints, height_filter)?.collect()
+}
 
-                let address = revaultd.vault_address(db_vault.derivation_index);
-                let op = db_vault.deposit_outpoint;
-                Some(ListVaultsEntry {
-                    amount: db_vault.amount,
-                    blockheight: db_vault.blockheight,
-                    status: db_vault.status,
-                    txid: op.txid,
-                    vout: op.vout,
-                    derivation_index: db_vault.derivation_index,
-                    funded_at: db_vault.funded_at,
-                    secured_at: db_vault.secured_at,
-                    delegated_at: db_vault.delegated_at,
-                    moved_at: db_vault.moved_at,
-                    address,
-                })
-            })
-            .collect()
+/// The vaults listed by [listvaults_at_heights], computed one at a time as they are iterated
+/// over rather than all collected in memory.
+// FIXME: we could make this more efficient with smarter SQL queries
+pub struct VaultsAtHeights<'a> {
+    revaultd: &'a RevaultD,
+    db_path: PathBuf,
+    ref_height: u32,
+    abandoned_vaults: HashSet<u32>,
+    db_vaults: std::vec::IntoIter<DbVault>,
+    statuses: Option<&'a [VaultStatus]>,
+    outpoints: Option<&'a [OutPoint]>,
+    height_filter: VaultHeightFilter,
+}
+
+/// Iterate over the vaults from DB, filtered as by [listvaults_at_heights].
+pub fn vaults_at_heights<'a>(
+    revaultd: &'a RevaultD,
+    statuses: Option<&'a [VaultStatus]>,
+    outpoints: Option<&'a [OutPoint]>,
+    height_filter: &VaultHeightFilter,
+) -> Result<VaultsAtHeights<'a>, DatabaseError> {
+    let db_path = revaultd.db_file();
+    let tip = db_tip(&db_path)?;
+
+    Ok(VaultsAtHeights {
+        revaultd,
+        ref_height: height_filter.as_of_height.unwrap_or(tip.height),
+        abandoned_vaults: db_abandoned_vaults(&db_path)?,
+        db_vaults: db_vaults(&db_path)?.into_iter(),
+        db_path,
+        statuses,
+        outpoints,
+        height_filter: *height_filter,
     })
 }
 
+impl VaultsAtHeights<'_> {
+    // The entry for this vault, unless it's filtered out
+    fn entry(&self, db_vault: DbVault) -> Result<Option<ListVaultsEntry>, DatabaseError> {
+        // The vaults of a wallet we rotated away from can't be derived from our descriptors
+        if Some(db_vault.wallet_id) != self.revaultd.wallet_id {
+            return Ok(None);
+        }
+
+        if let Some(outpoints) = self.outpoints {
+            if !outpoints.contains(&db_vault.deposit_outpoint) {
+                return Ok(None);
+            }
+        }
+
+        // An unconfirmed deposit has no funding height
+        if self.height_filter.min_funded_height.is_some()
+            || self.height_filter.max_funded_height.is_some()
+        {
+            let min = self.height_filter.min_funded_height.unwrap_or(0);
+            let max = self.height_filter.max_funded_height.unwrap_or(u32::MAX);
+            if db_vault.blockheight == 0 || db_vault.blockheight < min || db_vault.blockheight > max
+            {
+                return Ok(None);
+            }
+        }
+
+        let mut transitions = db_vault_transitions(&self.db_path, db_vault.id)?;
+        let db_vault = if let Some(as_of_height) = self.height_filter.as_of_height {
+            transitions.retain(|transition| transition.blockheight <= as_of_height);
+            match transitions.last() {
+                Some(transition) => DbVault {
+                    status: transition.status,
+                    ..db_vault
+                },
+                // We didn't know about this vault yet
+                None => return Ok(None),
+            }
+        } else {
+            db_vault
+        };
+
+        if let Some(statuses) = self.statuses {
+            if !statuses.contains(&db_vault.status) {
+                return Ok(None);
+            }
+        }
+
+        let conflicts = db_vault_conflicts(&self.db_path, db_vault.id)?
+            .into_iter()
+            .map(VaultConflict::from)
+            .collect();
+        let flags = db_vault_flags(&self.db_path, db_vault.id)?
+            .into_iter()
+            .filter(|flag| flag.cleared_at.is_none())
+            .map(VaultFlag::from)
+            .collect();
+        let parent = db_vault_parent(&self.db_path, db_vault.id)?;
+        let child = db_vault_child(&self.db_path, db_vault.id)?;
+        let origin = db_vault_origin(&self.db_path, db_vault.id)?;
+        let change_from = if origin == DepositOrigin::SpendChange {
+            db_vault_change_sources(&self.db_path, db_vault.id)?
+                .into_iter()
+                .map(|parent| parent.deposit_outpoint)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let unvault_height = if matches!(
+            db_vault.status,
+            VaultStatus::Unvaulted | VaultStatus::Spendable
+        ) {
+            db_unvault_height(&self.db_path, db_vault.id)?
+        } else {
+            None
+        };
+        let ancestry = if db_vault.status == VaultStatus::Unconfirmed {
+            db_deposit_ancestry(&self.db_path, db_vault.id)?.map(DepositAncestry::from)
+        } else {
+            None
+        };
+        let coinbase = db_deposit_coinbase_height(&self.db_path, db_vault.id)?
+            .map(|height| CoinbaseMaturity::new(height, self.ref_height));
+        let address = self.revaultd.vault_address(db_vault.derivation_index)?;
+        let op = db_vault.deposit_outpoint;
+        Ok(Some(ListVaultsEntry {
+            amount: db_vault.amount.into(),
+            blockheight: db_vault.blockheight,
+            status: db_vault.status,
+            txid: op.txid,
+            vout: op.vout,
+            derivation_index: db_vault.derivation_index,
+            funded_at: db_vault.funded_at,
+            secured_at: db_vault.secured_at,
+            delegated_at: db_vault.delegated_at,
+            moved_at: db_vault.moved_at,
+            address,
+            conflicts,
+            flags,
+            awaiting_resecuring: awaiting_resecuring(&db_vault, parent.is_some(), child.as_ref()),
+            revaulted_from: parent.map(|parent| parent.deposit_outpoint),
+            revaulted_to: child.map(|child| child.deposit_outpoint),
+            origin,
+            change_from,
+            unvault_height,
+            csv: unvault_height.map(|_| self.revaultd.unvault_csv()),
+            blocks_until_spendable: unvault_height.map(|height| {
+                self.revaultd
+                    .blocks_until_spendable(height, self.ref_height)
+            }),
+            secured_height: secured_height(&transitions),
+            moved_height: moved_height(&transitions),
+            abandoned: self.abandoned_vaults.contains(&db_vault.id),
+            ancestry,
+            coinbase,
+        }))
+    }
+}
+
+impl Iterator for VaultsAtHeights<'_> {
+    type Item = Result<ListVaultsEntry, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let db_vault = self.db_vaults.next()?;
+            match self.entry(db_vault) {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
 /// Get all vaults from a list of deposit outpoints, if they are not in a given status.
 ///
 /// # Errors
@@ -162,6 +381,129 @@ pub fn vaults_from_deposits(
     Ok(vaults)
 }
 
+// The size of a presigned transaction once fully signed, whatever signatures it currently has
+// (revault_tx accounts for the maximum satisfaction of each input's script), and the feerate
+// its fixed fees imply.
+fn presigned_tx_estimates<T: RevaultTransaction>(
+    tx: &T,
+    cpfp_value: Option<u64>,
+) -> PresignedTxEstimates {
+    let weight = tx.max_weight();
+    let vsize = (weight + 3) / 4;
+    let fees = tx.fees();
+
+    PresignedTxEstimates {
+        weight,
+        vsize,
+        fees,
+        feerate: fees / vsize,
+        cpfp_value,
+    }
+}
+
+// Whether each stakeholder signed this presigned transaction of the vault at this index
+fn presigned_tx_signers(
+    revaultd: &RevaultD,
+    derivation_index: DerivationIndex,
+    tx: &impl RevaultTransaction,
+) -> Vec<PresignedTxSigner> {
+    let sigs = &tx
+        .psbt()
+        .inputs
+        .get(0)
+        .expect("Presigned transactions always have a single input")
+        .partial_sigs;
+
+    revaultd
+        .stakeholders_xpubs_at(derivation_index)
+        .iter()
+        .zip(revaultd.stakeholders().into_iter())
+        .map(|(key, stakeholder)| PresignedTxSigner {
+            fingerprint: stakeholder.fingerprint.to_string(),
+            label: stakeholder.label,
+            signed: sigs.contains_key(key),
+        })
+        .collect()
+}
+
+/// Explain why this signature, given for `pubkey` on a presigned transaction of the vault at
+/// `derivation_index`, is invalid: whose key it was given for and whether it is valid for another
+/// stakeholder's key instead, ie whether it was most likely made with the wrong signing device.
+pub fn invalid_signature_diagnostic(
+    revaultd: &RevaultD,
+    tx: &RevaultTx,
+    derivation_index: DerivationIndex,
+    pubkey: &BitcoinPubKey,
+    sig: secp256k1::Signature,
+) -> String {
+    let expected = match revaultd.stakeholder_by_key_at(pubkey, derivation_index) {
+        Some(stakeholder) => format!("stakeholder {}", stakeholder),
+        None => "an unknown participant".to_string(),
+    };
+    let actual = revaultd
+        .stakeholders_xpubs_at(derivation_index)
+        .into_iter()
+        .zip(revaultd.stakeholders().into_iter())
+        .find(|(key, _)| {
+            key != pubkey
+                && tx
+                    .clone()
+                    .add_signature(key.key, sig, &revaultd.secp_ctx)
+                    .is_ok()
+        });
+
+    match actual {
+        Some((_, stakeholder)) => format!(
+            "the signature for {} (key '{}' at derivation index {}) is valid for stakeholder \
+             {}'s key instead, it was most likely made with the wrong signing device",
+            expected, pubkey, derivation_index, stakeholder
+        ),
+        None => format!(
+            "the signature for {} (key '{}' at derivation index {}) is not valid for any \
+             stakeholder's key",
+            expected, pubkey, derivation_index
+        ),
+    }
+}
+
+/// Explain that a PSBT given by the user lacks our signature. If other stakeholders signed it
+/// instead, it was most likely signed with their device instead of ours.
+pub fn missing_our_signature_diagnostic(
+    revaultd: &RevaultD,
+    tx_name: &str,
+    derivation_index: DerivationIndex,
+    sigs: &BTreeMap<BitcoinPubKey, Vec<u8>>,
+) -> String {
+    let our_key = revaultd
+        .our_stk_xpub_at(derivation_index)
+        .expect("We are a stakeholder");
+    let ourselves = revaultd
+        .stakeholder_by_key_at(&our_key, derivation_index)
+        .expect("Config checked our xpub is part of the stakeholders'");
+    let signers: Vec<String> = sigs
+        .keys()
+        .filter_map(|key| revaultd.stakeholder_by_key_at(key, derivation_index))
+        .map(|stakeholder| stakeholder.to_string())
+        .collect();
+
+    if signers.is_empty() {
+        format!(
+            "No signature for ourselves ({}, key '{}' at derivation index {}) in {} transaction",
+            ourselves, our_key, derivation_index, tx_name
+        )
+    } else {
+        format!(
+            "No signature for ourselves ({}, key '{}' at derivation index {}) in {} transaction, \
+             only for stakeholder(s) {}: was it signed with the wrong signing device?",
+            ourselves,
+            our_key,
+            derivation_index,
+            tx_name,
+            signers.join(", ")
+        )
+    }
+}
+
 // FIXME: make this a DB query altogether...
 /// List all the presigned transactions from these confirmed vaults.
 ///
@@ -177,11 +519,31 @@ pub fn presigned_txs(
     let mut tx_list = Vec::with_capacity(db_vaults.len());
     for db_vault in db_vaults {
         let vault_outpoint = db_vault.deposit_outpoint;
+        let signing_contexts =
+            db_vault_signing_contexts(db_path, db_vault.id).expect("Database must be available");
+        let signing_context = |presigned_id: u32| {
+            signing_contexts
+                .iter()
+                .find(|context| context.presigned_id == presigned_id)
+                .map(|context| SigningContext::from(*context))
+        };
 
-        let unvault_psbt = db_unvault_transaction(db_path, db_vault.id)
-            .expect("Database must be available")?
-            .psbt
-            .assert_unvault();
+        let unvault_db_tx =
+            db_unvault_transaction(db_path, db_vault.id).expect("Database must be available")?;
+        let unvault_id = unvault_db_tx.id;
+        let unvault_psbt = unvault_db_tx.psbt.assert_unvault();
+        let cpfp_script = revaultd
+            .derived_cpfp_descriptor(db_vault.derivation_index)
+            .inner()
+            .script_pubkey();
+        let cpfp_value = unvault_psbt
+            .psbt()
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|txo| txo.script_pubkey == cpfp_script)
+            .map(|txo| txo.value);
         let mut finalized_unvault = unvault_psbt.clone();
         let unvault = VaultPresignedTransaction {
             transaction: if finalized_unvault.finalize(&revaultd.secp_ctx).is_ok() {
@@ -189,11 +551,15 @@ pub fn presigned_txs(
             } else {
                 None
             },
+            signers: presigned_tx_signers(revaultd, db_vault.derivation_index, &unvault_psbt),
+            estimates: presigned_tx_estimates(&unvault_psbt, cpfp_value),
+            signing_context: signing_context(unvault_id),
             psbt: unvault_psbt,
         };
 
         let cancel_db_tx =
             db_cancel_transaction(db_path, db_vault.id).expect("Database must be available")?;
+        let cancel_id = cancel_db_tx.id;
         let cancel_psbt = cancel_db_tx.psbt.assert_cancel();
         let mut finalized_cancel = cancel_psbt.clone();
         let cancel = VaultPresignedTransaction {
@@ -202,6 +568,9 @@ pub fn presigned_txs(
             } else {
                 None
             },
+            signers: presigned_tx_signers(revaultd, db_vault.derivation_index, &cancel_psbt),
+            estimates: presigned_tx_estimates(&cancel_psbt, None),
+            signing_context: signing_context(cancel_id),
             psbt: cancel_psbt,
         };
 
@@ -210,6 +579,7 @@ pub fn presigned_txs(
         if revaultd.is_stakeholder() {
             let emer_db_tx =
                 db_emer_transaction(db_path, db_vault.id).expect("Database must be available")?;
+            let emer_id = emer_db_tx.id;
             let emer_psbt = emer_db_tx.psbt.assert_emer();
             let mut finalized_emer = emer_psbt.clone();
             emergency = Some(VaultPresignedTransaction {
@@ -218,11 +588,15 @@ pub fn presigned_txs(
                 } else {
                     None
                 },
+                signers: presigned_tx_signers(revaultd, db_vault.derivation_index, &emer_psbt),
+                estimates: presigned_tx_estimates(&emer_psbt, None),
+                signing_context: signing_context(emer_id),
                 psbt: emer_psbt,
             });
 
             let unemer_db_tx = db_unvault_emer_transaction(db_path, db_vault.id)
                 .expect("Database must be available")?;
+            let unemer_id = unemer_db_tx.id;
             let unemer_psbt = unemer_db_tx.psbt.assert_unvault_emer();
             let mut finalized_unemer = unemer_psbt.clone();
             unvault_emergency = Some(VaultPresignedTransaction {
@@ -231,6 +605,9 @@ pub fn presigned_txs(
                 } else {
                     None
                 },
+                signers: presigned_tx_signers(revaultd, db_vault.derivation_index, &unemer_psbt),
+                estimates: presigned_tx_estimates(&unemer_psbt, None),
+                signing_context: signing_context(unemer_id),
                 psbt: unemer_psbt,
             });
         }
@@ -247,359 +624,1322 @@ pub fn presigned_txs(
     Some(tx_list)
 }
 
-/// Get all the finalized Emergency transactions for each vault, depending on wether the Unvault
-/// was already broadcast or not (ie get the one spending from the deposit or the Unvault tx).
-pub fn finalized_emer_txs(revaultd: &RevaultD) -> Result<Vec<BitcoinTransaction>, CommandError> {
-    let db_path = revaultd.db_file();
+/// Sort the Spend transaction inputs in a canonical order: by deposit outpoint. Along with
+/// [spend_txouts] it makes two managers building a Spend with the same parameters end up with
+/// the exact same PSBT.
+pub fn sort_spend_txins(txins: &mut [(OutPoint, BitcoinAmount, ChildNumber)]) {
+    txins.sort_by_key(|(outpoint, _, _)| *outpoint);
+}
 
-    let emer_iter = db_signed_emer_txs(&db_path)
-        .expect("Database must be accessible")
-        .into_iter()
-        .map(|mut tx| {
-            tx.finalize(&revaultd.secp_ctx)?;
-            Ok(tx.into_psbt().extract_tx())
-        });
-    let unemer_iter = db_signed_unemer_txs(&db_path)
-        .expect("Database must be accessible")
+/// Create the destination outputs of a Spend transaction, sorted as per BIP69 (by amount, then
+/// by scriptPubKey) regardless of the order of the destinations. The CPFP and change outputs
+/// are added at fixed positions by revault_tx and are not part of this sorting.
+pub fn spend_txouts<'a, I>(destinations: I) -> Vec<SpendTxOut>
+where
+    I: IntoIterator<Item = (&'a Address, &'a Amount)>,
+{
+    let mut txouts: Vec<TxOut> = destinations
         .into_iter()
-        .map(|mut tx| {
-            tx.finalize(&revaultd.secp_ctx)?;
-            Ok(tx.into_psbt().extract_tx())
-        });
+        .map(|(addr, amount)| TxOut {
+            value: amount.as_sat(),
+            script_pubkey: addr.script_pubkey(),
+        })
+        .collect();
+    txouts.sort_by(|a, b| {
+        a.value
+            .cmp(&b.value)
+            .then_with(|| a.script_pubkey.as_bytes().cmp(b.script_pubkey.as_bytes()))
+    });
 
-    emer_iter
-        .chain(unemer_iter)
-        .collect::<Result<Vec<BitcoinTransaction>, revault_tx::Error>>()
-        .map_err(|e| e.into())
+    txouts.into_iter().map(SpendTxOut::new).collect()
 }
 
-/// gethistory retrieves a limited list of events which occured between two given dates.
-pub fn gethistory<T: BitcoindThread>(
+/// Refuse to spend a vault being moved to the new wallet of a rotation by any other Spend
+/// transaction than the one moving it.
+pub fn check_not_migrating(
+    db_path: &std::path::Path,
+    vault: &DbVault,
+    spend_txid: &Txid,
+) -> Result<(), CommandError> {
+    match db_vault_migration(db_path, vault.id).expect("Database must be available") {
+        Some(migration) if migration.spend_txid != *spend_txid => {
+            Err(CommandError::VaultMigrating(vault.deposit_outpoint))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Create a Spend transaction from these deposits to these destinations at this feerate, adding
+/// a change output paying to this deposit descriptor if it would not be dust.
+///
+/// ## Errors
+/// - If the feerate would be significantly lower than `feerate_vb`
+/// - If revault_tx refuses to create the transaction, for instance because of insane fees
+pub fn spend_tx_with_change(
     revaultd: &RevaultD,
-    bitcoind_conn: &T,
-    start: u32,
-    end: u32,
-    limit: u64,
-    kind: &[HistoryEventKind],
-) -> Result<Vec<HistoryEvent>, CommandError> {
-    let db_path = revaultd.db_file();
-    // All vaults which have one transaction (either the funding, the canceling, the unvaulting, the spending)
-    // inside the date range are retrieved.
-    // This list might include vaults that were consumed again outside the range.
-    let vaults = db_vaults_with_txids_in_period(&db_path, start, end, limit)
-        .expect("Database must be accessible");
+    txins: Vec<(OutPoint, BitcoinAmount, ChildNumber)>,
+    txos: Vec<SpendTxOut>,
+    feerate_vb: u64,
+    change_descriptor: &DerivedDepositDescriptor,
+) -> Result<SpendTransaction, CommandError> {
+    // This adds the CPFP output so create a dummy one to accurately compute the
+    // feerate.
+    let nochange_tx = spend_tx_from_deposits(
+        txins.clone(),
+        txos.clone(),
+        None, // No change :)
+        &revaultd.deposit_descriptor,
+        &revaultd.unvault_descriptor,
+        &revaultd.cpfp_descriptor,
+        revaultd.lock_time,
+        /* Deactivate insane feerate check */
+        false,
+        &revaultd.secp_ctx,
+    )
+    .map_err(|e| revault_tx::Error::from(e))?;
 
-    // Used to retrieve the deposit from the Cancel outputs. Not a vector since the Cancel only
-    // ever has a single deposit output.
-    let mut vaults_by_outpoint_txid: HashMap<Txid, &DbVault> = HashMap::with_capacity(vaults.len());
-    // Used for change detection when computing the deposit events.
-    let mut final_txids: HashSet<Txid> = HashSet::with_capacity(vaults.len());
-    for vault in &vaults {
-        vaults_by_outpoint_txid.insert(vault.deposit_outpoint.txid, vault);
-        if let Some(txid) = vault.final_txid {
-            final_txids.insert(txid);
-        }
+    log::debug!(
+        "Spend tx without change: '{}'",
+        nochange_tx.as_psbt_string()
+    );
+
+    // If the feerate of the transaction would be much lower (< 90/100) than what they
+    // requested for, tell them.
+    let nochange_feerate_vb = nochange_tx
+        .max_feerate()
+        .checked_mul(4)
+        .expect("bug in feerate computation");
+    if nochange_feerate_vb * 10 < feerate_vb * 9 {
+        return Err(CommandError::SpendFeerateTooLow(
+            feerate_vb,
+            nochange_feerate_vb,
+        ));
     }
-    // Map of the id and the vaults consumed by the final transaction.
-    let mut spends: HashMap<Txid, Vec<&DbVault>> = HashMap::with_capacity(vaults.len());
-    let mut events: Vec<HistoryEvent> = Vec::with_capacity(vaults.len());
 
-    for vault in &vaults {
-        if kind.contains(&HistoryEventKind::Deposit)
-            // A vault may be retrieved as a change of a cancel or a spend in order to compute
-            // change amount but not be in 'funded' state yet (because we usually require >1
-            // conf).
-            && vault.status != VaultStatus::Unconfirmed
-            // Vault could have been moved but not deposited during the period.
-            && vault.funded_at.expect("Vault is funded") >= start
-            && vault.funded_at.expect("Vault is funded") <= end
-            // Only deposits that are not a spend transaction change and not cancel output
-            // are considered as history events.
-            && !final_txids.contains(&vault.deposit_outpoint.txid)
-        {
-            events.push(HistoryEvent {
-                kind: HistoryEventKind::Deposit,
-                date: vault.funded_at.expect("Vault is funded"),
-                blockheight: vault.blockheight,
-                amount: Some(vault.amount.as_sat()),
-                fee: None,
-                txid: vault.deposit_outpoint.txid,
-                vaults: vec![vault.deposit_outpoint],
-            });
+    // Add a change output if it would not be dust according to our standard (200k sats
+    // atm, see DUST_LIMIT).
+    // 8 (amount) + 1 (len) + 1 (v0) + 1 (push) + 32 (witscript hash)
+    const P2WSH_TXO_WEIGHT: u64 = 43 * 4;
+    let with_change_weight = nochange_tx
+        .max_weight()
+        .checked_add(P2WSH_TXO_WEIGHT)
+        .expect("weight computation bug");
+    let cur_fees = nochange_tx.fees();
+    let want_fees = with_change_weight
+        // Mental gymnastic: sat/vbyte to sat/wu rounded up
+        .checked_mul(feerate_vb + 3)
+        .map(|vbyte| vbyte.checked_div(4).unwrap());
+    let change_value = want_fees.map(|f| cur_fees.checked_sub(f)).flatten();
+    log::debug!(
+        "Weight with change: '{}'  --  Fees without change: '{}'  --  Wanted feerate: '{}'  \
+                    --  Wanted fees: '{:?}'  --  Change value: '{:?}'",
+        with_change_weight,
+        cur_fees,
+        feerate_vb,
+        want_fees,
+        change_value
+    );
+
+    let change_txo = change_value.and_then(|change_value| {
+        // The overhead incurred to the value of the CPFP output by the change output
+        // See https://github.com/revault/practical-revault/blob/master/transactions.md#spend_tx
+        let cpfp_overhead = 16 * P2WSH_TXO_WEIGHT;
+        if change_value > revault_tx::transactions::DUST_LIMIT + cpfp_overhead {
+            let change_txo = DepositTxOut::new(
+                // arithmetic checked above
+                BitcoinAmount::from_sat(change_value - cpfp_overhead),
+                change_descriptor,
+            );
+            log::debug!("Adding a change txo: '{:?}'", change_txo);
+            Some(change_txo)
+        } else {
+            None
         }
+    });
 
-        if kind.contains(&HistoryEventKind::Cancel)
-            && vault.status == VaultStatus::Canceled
-            && vault.moved_at.expect("Vault is canceled") >= start
-            && vault.moved_at.expect("Vault is canceled") <= end
-        {
-            let txid = vault
-                .final_txid
-                .expect("Canceled vault must have a cancel txid");
+    // Now we can hand them the resulting transaction (sanity checked for insane fees).
+    spend_tx_from_deposits(
+        txins,
+        txos,
+        change_txo,
+        &revaultd.deposit_descriptor,
+        &revaultd.unvault_descriptor,
+        &revaultd.cpfp_descriptor,
+        revaultd.lock_time,
+        true,
+        &revaultd.secp_ctx,
+    )
+    .map_err(|e| CommandError::from(revault_tx::Error::from(e)))
+}
 
-            let cancel_tx = bitcoind_conn
-                .wallet_tx(txid)?
-                .expect("Cancel tx should be here");
+/// The participants whose xpub is in these descriptor keys, along with their label and whether
+/// it's our own xpub. The cosigning servers' keys are not participants and are skipped.
+fn participant_entries(
+    revaultd: &RevaultD,
+    xpubs: Vec<DescriptorPublicKey>,
+    our_xpub: &Option<ExtendedPubKey>,
+) -> Vec<ParticipantEntry> {
+    xpubs
+        .into_iter()
+        .filter_map(|xpub| match xpub {
+            DescriptorPublicKey::XPub(xpub) => Some(ParticipantEntry {
+                xpub: xpub.xkey.to_string(),
+                fingerprint: xpub.xkey.fingerprint().to_string(),
+                label: revaultd.participant(xpub.xkey.fingerprint()).label,
+                ours: our_xpub.as_ref() == Some(&xpub.xkey),
+            }),
+            // Cosigning servers' keys, not a participant's
+            DescriptorPublicKey::SinglePub(_) => None,
+        })
+        .collect()
+}
 
-            let cancel_height = match cancel_tx.blockheight {
-                Some(h) => h,
-                None => {
-                    // It can only happen if the cancel transaction was just reorg'ed out.
-                    // In this super edgy case, just ignore this entry.
-                    continue;
-                }
-            };
+/// The cosigning servers we are configured with (if we are a manager) and whether we can
+/// connect to them.
+pub fn cosigners_entries(revaultd: &RevaultD) -> Vec<CosignerEntry> {
+    let statuses = cosigners_status(revaultd);
 
-            let change_amount = vaults_by_outpoint_txid
-                .get(&txid)
-                // if the change output did not create a vault because of the dust limit
-                // the change_amount is equal to 0.
-                .map_or(0, |vault| vault.amount.as_sat());
+    revaultd
+        .cosigs
+        .as_ref()
+        .map(|cosigs| {
+            cosigs
+                .iter()
+                .zip(statuses.into_iter())
+                .enumerate()
+                .map(|(i, ((_, noise_key), status))| CosignerEntry {
+                    host: status.host,
+                    noise_key: noise_key.0.to_hex(),
+                    noise_key_fingerprint: noise_pubkey_fingerprint(noise_key),
+                    label: revaultd.cosigs_labels.get(i).cloned().flatten(),
+                    reachable: status.reachable,
+                })
+                .collect()
+        })
+        .unwrap_or_else(Vec::new)
+}
 
-            events.push(HistoryEvent {
-                kind: HistoryEventKind::Cancel,
-                date: vault.moved_at.expect("Tx should be confirmed"),
-                blockheight: cancel_height,
-                amount: None,
-                fee: Some(
-                    vault
-                        .amount
-                        .as_sat()
-                        .checked_sub(change_amount)
-                        .expect("Moved funds include funds going back"),
-                ),
-                txid,
-                vaults: vec![vault.deposit_outpoint],
-            });
-        }
+/// Make the cosigning servers sign this Spend transaction. The signatures they already sent us
+/// for it are re-used, so only the ones we don't have signatures from yet are polled. Each
+/// server's signatures are stored as soon as they are received, even if another one fails.
+pub fn fetch_cosigs_signatures(
+    revaultd: &RevaultD,
+    spend_tx: &mut SpendTransaction,
+) -> Result<(), CommunicationError> {
+    let db_path = revaultd.db_file();
+    let spend_txid = spend_tx.txid();
+    let cosigs = revaultd.cosigs.as_ref().expect("We are manager");
 
-        // In order to fill the spend map, only vaults that are
-        // consumed between the two dates are kept.
-        if kind.contains(&HistoryEventKind::Spend)
-            && vault.status == VaultStatus::Spent
-            && vault.moved_at.expect("Vault is spent") >= start
-            && vault.moved_at.expect("Vault is spent") <= end
+    let cached_sigs =
+        db_cosig_signatures(&db_path, &spend_txid).expect("Database must be available");
+    let mut missing = Vec::with_capacity(cosigs.len());
+    for (host, noise_key) in cosigs {
+        match cached_sigs
+            .iter()
+            .find(|cached| cached.cosig_noise_key == noise_key.0)
         {
-            let txid = vault
-                .final_txid
-                .expect("Spent vault must have a spend txid");
+            Some(cached) => {
+                log::debug!(
+                    "Using the signatures cosigning server at '{}' already sent us for spend '{}'",
+                    host,
+                    spend_txid
+                );
+                add_cosig_signatures(&revaultd.secp_ctx, spend_tx, cached.psbt.clone())?;
+            }
+            None => missing.push((host.clone(), *noise_key)),
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
 
-            if let Some(vlts) = spends.get_mut(&txid) {
-                vlts.push(vault);
-            } else {
-                spends.insert(txid, vec![vault]);
+    let results = poll_cosigning_servers(
+        &revaultd.noise_secret,
+        revaultd.bind_address,
+        spend_tx,
+        &missing,
+        revaultd.cosigs_timeout,
+    );
+    let mut error = None;
+    for ((host, noise_key), res) in missing.iter().zip(results.into_iter()) {
+        let res = res.and_then(|signed_tx| {
+            add_cosig_signatures(&revaultd.secp_ctx, spend_tx, signed_tx.clone())?;
+            Ok(signed_tx)
+        });
+        match res {
+            Ok(signed_tx) => {
+                db_store_cosig_signatures(&db_path, &spend_txid, &noise_key.0, &signed_tx)
+                    .expect("Database must be available")
+            }
+            Err(e) => {
+                log::error!(
+                    "Error fetching signatures from cosigning server at '{}' for spend '{}': '{}'",
+                    host,
+                    spend_txid,
+                    e
+                );
+                error.get_or_insert(e);
             }
         }
     }
 
-    if kind.contains(&HistoryEventKind::Spend) {
-        for (txid, spent_vaults) in spends {
-            let spend_tx = bitcoind_conn
-                .wallet_tx(txid)?
-                .expect("Spend tx should be here");
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
 
-            let spend_height = match spend_tx.blockheight {
-                Some(h) => h,
-                None => {
-                    // It can only happen if the spend transaction was just reorg'ed out.
-                    // In this super edgy case, just ignore this entry.
-                    continue;
-                }
-            };
-
-            let bytes =
-                Vec::from_hex(&spend_tx.hex).expect("bitcoind returned a wrong transaction format");
-            let tx: BitcoinTransaction =
-                encode::deserialize(&bytes).expect("bitcoind returned a wrong transaction format");
+/// The cosigning servers we are configured with and whether they already signed this Spend
+/// transaction.
+pub fn spend_cosigners(revaultd: &RevaultD, spend_txid: &Txid) -> Vec<SpendCosignerEntry> {
+    let cached_sigs =
+        db_cosig_signatures(&revaultd.db_file(), spend_txid).expect("Database must be available");
 
-            let derivation_index = spent_vaults
+    revaultd
+        .cosigs
+        .as_ref()
+        .map(|cosigs| {
+            cosigs
                 .iter()
-                .map(|v| v.derivation_index)
-                .max()
-                .expect("Spent vaults should not be empty");
+                .map(|(host, noise_key)| SpendCosignerEntry {
+                    host: host.to_string(),
+                    noise_key: noise_key.0.to_hex(),
+                    signed: cached_sigs
+                        .iter()
+                        .any(|cached| cached.cosig_noise_key == noise_key.0),
+                })
+                .collect()
+        })
+        .unwrap_or_else(Vec::new)
+}
 
-            let cpfp_script_pubkey = revaultd
-                .cpfp_descriptor
-                .derive(derivation_index, &revaultd.secp_ctx)
-                .into_inner()
-                .script_pubkey();
+fn coordinator_entry(endpoint: &CoordinatorEndpoint) -> CoordinatorEntry {
+    CoordinatorEntry {
+        host: endpoint.host.to_string(),
+        noise_key: endpoint.noise_key.0.to_hex(),
+        noise_key_fingerprint: noise_pubkey_fingerprint(&endpoint.noise_key),
+    }
+}
 
-            let deposit_address = revaultd
-                .deposit_descriptor
-                .derive(derivation_index, &revaultd.secp_ctx)
-                .into_inner()
-                .script_pubkey();
+/// A structured view of the participants to this deployment, without any secret.
+pub fn participants(revaultd: &RevaultD) -> ListParticipantsResult {
+    ListParticipantsResult {
+        stakeholders: participant_entries(
+            revaultd,
+            revaultd.stakeholders_xpubs(),
+            &revaultd.our_stk_xpub,
+        ),
+        managers: participant_entries(revaultd, revaultd.managers_xpubs(), &revaultd.our_man_xpub),
+        managers_threshold: revaultd.managers_threshold(),
+        cosigners: cosigners_entries(revaultd),
+        coordinator: coordinator_entry(revaultd.coordinators.main()),
+        backup_coordinators: revaultd.coordinators.endpoints()[1..]
+            .iter()
+            .map(coordinator_entry)
+            .collect(),
+    }
+}
 
-            let mut recipients_amount: u64 = 0;
-            let mut change_amount: u64 = 0;
-            for txout in tx.output {
-                if cpfp_script_pubkey == txout.script_pubkey {
-                    // this cpfp output is ignored and its amount is part of the fees
-                } else if deposit_address == txout.script_pubkey {
-                    change_amount += txout.value;
-                } else {
-                    recipients_amount += txout.value
-                }
-            }
+/// Make sure the fees of a Spend transaction are neither above the given percentage of the value
+/// spent nor above the absolute ceiling, as these are most likely the result of a typo.
+pub fn check_spend_fees(
+    fees: BitcoinAmount,
+    spent: BitcoinAmount,
+    max_percent: u64,
+    max_fees: BitcoinAmount,
+) -> Result<(), CommandError> {
+    let percent_threshold =
+        BitcoinAmount::from_sat(spent.as_sat().saturating_mul(max_percent) / 100);
+    let threshold = std::cmp::min(percent_threshold, max_fees);
 
-            // fees is the total of the deposits minus the total of the spend outputs.
-            // Fees include then the unvaulting fees and the spend fees.
-            let fees = spent_vaults
-                .iter()
-                .map(|vlt| vlt.amount.as_sat())
-                .sum::<u64>()
-                .checked_sub(recipients_amount + change_amount)
-                .expect("Funds moving include funds going back");
+    if fees > threshold {
+        return Err(CommandError::SpendFeesTooHigh(fees, spent, threshold));
+    }
+    Ok(())
+}
 
-            events.push(HistoryEvent {
-                date: spend_tx.received_time,
-                blockheight: spend_height,
-                kind: HistoryEventKind::Spend,
-                amount: Some(recipients_amount),
-                fee: Some(fees),
-                txid,
-                vaults: spent_vaults
-                    .iter()
-                    .map(|vault| vault.deposit_outpoint)
-                    .collect(),
-            })
+/// The fees to add to a transaction of this maximum weight paying these fees, for it to reach
+/// the target feerate in sat/vbyte. Zero if it already does.
+pub fn cpfp_reserve(max_weight: u64, fees: u64, feerate_vb: u64) -> BitcoinAmount {
+    let vsize = (max_weight + 3) / 4;
+    BitcoinAmount::from_sat(vsize.saturating_mul(feerate_vb).saturating_sub(fees))
+}
+
+/// Refuse to store new transactions in the database if the disk is almost full, we'd rather not
+/// risk corrupting it. If we can't tell, don't prevent the user from doing anything.
+pub fn check_disk_space(revaultd: &RevaultD) -> Result<(), CommandError> {
+    match revaultd.disk_space() {
+        Ok(space) if space.level == DiskSpaceLevel::Critical => Err(
+            CommandError::DiskSpaceCritical(space.available, revaultd.disk_space_critical),
+        ),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::warn!("Could not check the space available on disk: '{}'", e);
+            Ok(())
         }
     }
+}
 
-    // Because a vault represents a deposit event and maybe a second event (cancel or spend),
-    // the two timestamp `funded_at and `moved_at` must be taken in account. The list of vaults
-    // can not considered as an ordered list of events. All events must be first filtered and
-    // stored in a list before being ordered.
-    events.sort_by(|a, b| b.date.cmp(&a.date));
-    // Because a spend transaction may consume multiple vault and still count as one event,
-    // and because the list of events must be first ordered by event date. The limit is enforced
-    // at the end. (A limit was applied in the sql query only on the number of txids in the given period)
-    events.truncate(limit as usize);
-    Ok(events)
+// Base58 addresses don't make the difference between testnet and regtest, nor do any
+// addresses between testnet and signet.
+fn address_network_matches(address: &Address, network: Network) -> bool {
+    match (address.network, network) {
+        (addr_net, net) if addr_net == net => true,
+        (Network::Testnet, Network::Signet) => true,
+        (Network::Testnet, Network::Regtest) => {
+            !matches!(address.payload, Payload::WitnessProgram { .. })
+        }
+        _ => false,
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        bitcoind::interface::WalletTransaction,
-        database::{
-            actions::{
-                db_confirm_deposit, db_confirm_unvault, db_insert_new_unconfirmed_vault,
-                db_update_presigned_txs,
-            },
-            bitcointx::RevaultTx,
-            interface::{
-                db_cancel_transaction, db_emer_transaction, db_exec, db_unvault_emer_transaction,
-                db_unvault_transaction, db_vault_by_deposit,
-            },
-            schema::{DbTransaction, DbVault},
-        },
-        revaultd::{RevaultD, VaultStatus},
-        setup_db,
-        utils::test_utils::{
-            dummy_revaultd, insert_vault_in_db, test_datadir, MockBitcoindThread, UserRole,
-        },
+// Whether coins sent to this address are provably unspendable, or sent to an all-zero hash as
+// commonly done to burn coins.
+fn is_burn_address(address: &Address) -> bool {
+    if address.script_pubkey().is_provably_unspendable() {
+        return true;
+    }
+
+    let hash: &[u8] = match address.payload {
+        Payload::PubkeyHash(ref hash) => &hash[..],
+        Payload::ScriptHash(ref hash) => &hash[..],
+        Payload::WitnessProgram { ref program, .. } => program,
     };
-    use revault_tx::{
-        bitcoin::{
-            blockdata::transaction::OutPoint,
-            hash_types::Txid,
-            hashes::hex::FromHex,
-            secp256k1,
-            util::{amount::Amount, bip32::ChildNumber},
-            PublicKey as BitcoinPubKey,
-        },
-        transactions::{
-            CancelTransaction, EmergencyTransaction, RevaultTransaction,
-            UnvaultEmergencyTransaction, UnvaultTransaction,
-        },
+    hash.iter().all(|b| *b == 0)
+}
+
+/// Make sure we are not going to pay to an address for another network, to a burn address,
+/// to one of our Unvault addresses or, unless `allow_self_send` is set, to one of our deposit
+/// addresses.
+pub fn check_spend_destinations<'a, I>(
+    destinations: I,
+    network: Network,
+    our_scripts: &[DbDerivedScript],
+    allow_self_send: bool,
+) -> Result<(), CommandError>
+where
+    I: IntoIterator<Item = &'a Address>,
+{
+    for address in destinations {
+        if !address_network_matches(address, network) {
+            return Err(CommandError::SpendWrongNetwork(address.clone(), network));
+        }
+        if is_burn_address(address) {
+            return Err(CommandError::SpendBurnAddress(address.clone()));
+        }
+
+        let script_pubkey = address.script_pubkey();
+        match our_scripts
+            .iter()
+            .find(|script| script.script_pubkey == script_pubkey)
+            .map(|script| script.kind)
+        {
+            Some(ScriptKind::Unvault) => return Err(CommandError::SpendToUnvault(address.clone())),
+            Some(ScriptKind::Deposit) if !allow_self_send => {
+                return Err(CommandError::SpendSelfSend(address.clone()))
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The destinations a confirmed Spend transaction already paid to. Our own addresses are never
+/// recorded, so they can't be reused.
+pub fn reused_destinations<'a, I>(revaultd: &RevaultD, destinations: I) -> Vec<Address>
+where
+    I: IntoIterator<Item = &'a Address>,
+{
+    let db_path = revaultd.db_file();
+    destinations
+        .into_iter()
+        .filter(|address| {
+            db_spend_destination(&db_path, &address.script_pubkey())
+                .expect("Database must be available")
+                .is_some()
+        })
+        .cloned()
+        .collect()
+}
+
+/// How many managers need to approve a spend proposal. Unless configured otherwise, as many as
+/// needed to sign the Spend transaction.
+pub fn spend_approval_threshold(revaultd: &RevaultD) -> usize {
+    revaultd
+        .spend_approval_threshold
+        .unwrap_or_else(|| revaultd.managers_threshold())
+}
+
+/// The manager's xpub in the descriptors with this fingerprint, if any
+pub fn manager_xpub(revaultd: &RevaultD, fingerprint: &Fingerprint) -> Option<ExtendedPubKey> {
+    revaultd
+        .managers_xpubs()
+        .into_iter()
+        .filter_map(|xpub| match xpub {
+            DescriptorPublicKey::XPub(xpub) => Some(xpub.xkey),
+            DescriptorPublicKey::SinglePub(_) => None,
+        })
+        .find(|xkey| xkey.fingerprint() == *fingerprint)
+}
+
+/// Parse the descriptor claimed to generate the Emergency address, and derive it at
+/// `derivation_index` if it is ranged.
+pub fn derive_emergency_descriptor(
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    descriptor: &str,
+    derivation_index: Option<u32>,
+) -> Result<Descriptor<BitcoinPubKey>, CommandError> {
+    let parsed = Descriptor::<DescriptorPublicKey>::from_str(descriptor).map_err(|e| {
+        CommandError::InvalidParams(format!("Invalid descriptor '{}': {}", descriptor, e))
+    })?;
+    let parsed = match (parsed.is_deriveable(), derivation_index) {
+        (true, Some(index)) => parsed.derive(index),
+        (false, None) => parsed,
+        (true, None) => {
+            return Err(CommandError::InvalidParams(
+                "A derivation index is needed for a ranged descriptor".to_string(),
+            ))
+        }
+        (false, Some(_)) => {
+            return Err(CommandError::InvalidParams(
+                "The descriptor isn't ranged, no derivation index expected".to_string(),
+            ))
+        }
     };
-    use rusqlite::params;
-    use std::{collections::BTreeMap, fs, str::FromStr};
 
-    #[derive(Clone)]
-    struct TestVault {
-        db_vault: DbVault,
-        transactions: Option<TestTransactions>,
+    parsed
+        .translate_pk2(|xpk| xpk.derive_public_key(secp))
+        .map_err(|e| {
+            CommandError::InvalidParams(format!("Deriving descriptor '{}': {}", descriptor, e))
+        })
+}
+
+/// Check the proof is a valid DER-encoded ECDSA signature of the SHA256 of its message by one of
+/// the keys of this (derived) descriptor.
+pub fn check_emergency_key_proof(
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    descriptor: &Descriptor<BitcoinPubKey>,
+    proof: &EmergencyKeyProof,
+) -> Result<bool, CommandError> {
+    let signature = secp256k1::Signature::from_der(&proof.signature)
+        .map_err(|e| CommandError::InvalidParams(format!("Invalid DER signature: '{}'", e)))?;
+
+    // Keys may only appear hashed in the descriptor
+    let pubkey_hash = hash160::Hash::hash(&proof.pubkey.to_bytes());
+    let in_descriptor = !descriptor.for_each_key(|key| match key {
+        ForEach::Key(pk) => *pk != proof.pubkey,
+        ForEach::Hash(hash) => *hash != pubkey_hash,
+    });
+    if !in_descriptor {
+        log::debug!("'{}' is not a key of the descriptor", proof.pubkey);
+        return Ok(false);
     }
 
-    #[derive(Clone)]
-    struct TestTransactions {
-        pub initial_cancel: CancelTransaction,
-        pub final_cancel: Option<CancelTransaction>,
-        pub initial_unvault: UnvaultTransaction,
-        pub final_unvault: Option<UnvaultTransaction>,
-        pub initial_emer: EmergencyTransaction,
-        pub final_emer: Option<EmergencyTransaction>,
-        pub initial_unvault_emer: UnvaultEmergencyTransaction,
-        pub final_unvault_emer: Option<UnvaultEmergencyTransaction>,
+    let digest = sha256::Hash::hash(proof.message.as_bytes());
+    let message = secp256k1::Message::from_slice(&digest[..]).expect("Hashes are 32 bytes");
+    Ok(secp.verify(&message, &signature, &proof.pubkey.key).is_ok())
+}
+
+/// Check `signature` is a valid DER-encoded (low-S) ECDSA signature of `digest` by the key of
+/// this xpub.
+pub fn check_spend_proposal_ack(
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    xpub: &ExtendedPubKey,
+    digest: &sha256::Hash,
+    signature: &[u8],
+) -> Result<(), CommandError> {
+    let signature = secp256k1::Signature::from_der(signature).map_err(|e| {
+        CommandError::SpendProposalInvalidAck(format!("Invalid DER signature: '{}'", e))
+    })?;
+    let message = secp256k1::Message::from_slice(&digest[..]).expect("Hashes are 32 bytes");
+    secp.verify(&message, &signature, &xpub.public_key.key)
+        .map_err(|_| {
+            CommandError::SpendProposalInvalidAck(format!(
+                "Invalid signature for xpub '{}'",
+                xpub.fingerprint()
+            ))
+        })
+}
+
+/// The status of a spend proposal as of `now`, given the decisions of the managers so far.
+pub fn spend_proposal_status(
+    proposal: &DbSpendProposal,
+    acks: &[DbSpendProposalAck],
+    threshold: usize,
+    n_managers: usize,
+    now: u64,
+) -> SpendProposalStatus {
+    if now >= proposal.expires_at as u64 {
+        return SpendProposalStatus::Expired;
     }
 
-    fn update_presigned_tx<C>(
-        db_path: &std::path::PathBuf,
-        db_vault: &DbVault,
-        mut db_tx: DbTransaction,
-        sigs: &BTreeMap<BitcoinPubKey, Vec<u8>>,
-        secp: &secp256k1::Secp256k1<C>,
-    ) where
-        C: secp256k1::Verification,
-    {
-        for (key, sig) in sigs {
-            let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap();
-            match db_tx.psbt {
-                RevaultTx::Unvault(ref mut tx) => {
-                    tx.add_signature(0, key.key, sig, secp).unwrap();
-                }
-                RevaultTx::Cancel(ref mut tx) => {
-                    tx.add_signature(0, key.key, sig, secp).unwrap();
-                }
-                RevaultTx::Emergency(ref mut tx) => {
-                    tx.add_signature(0, key.key, sig, secp).unwrap();
-                }
-                RevaultTx::UnvaultEmergency(ref mut tx) => {
-                    tx.add_signature(0, key.key, sig, secp).unwrap();
-                }
+    let approvals = acks.iter().filter(|ack| ack.approved).count();
+    let rejections = acks.len() - approvals;
+    if approvals >= threshold {
+        SpendProposalStatus::Approved
+    } else if rejections > n_managers.saturating_sub(threshold) {
+        SpendProposalStatus::Rejected
+    } else {
+        SpendProposalStatus::Pending
+    }
+}
+
+/// Check the Spend transaction with these parameters may be created for spend proposal
+/// `proposal_id`: it must have been approved, and be for the very same parameters.
+pub fn check_spend_proposal(
+    revaultd: &RevaultD,
+    proposal_id: u32,
+    outpoints: &[OutPoint],
+    destinations: &BTreeMap<Address, Amount>,
+    feerate_vb: u64,
+) -> Result<(), CommandError> {
+    let db_path = revaultd.db_file();
+    let proposal = db_spend_proposal(&db_path, proposal_id)
+        .expect("Database must be available")
+        .ok_or(CommandError::UnknownSpendProposal(proposal_id))?;
+    let acks = db_spend_proposal_acks(&db_path, proposal_id).expect("Database must be available");
+
+    match spend_proposal_status(
+        &proposal,
+        &acks,
+        spend_approval_threshold(revaultd),
+        revaultd.managers_xpubs().len(),
+        revaultd.clock.unix_timestamp(),
+    ) {
+        SpendProposalStatus::Approved => {}
+        SpendProposalStatus::Expired => {
+            return Err(CommandError::SpendProposalExpired(proposal_id))
+        }
+        status => return Err(CommandError::SpendProposalNotApproved(proposal_id, status)),
+    }
+
+    let mut outpoints = outpoints.to_vec();
+    outpoints.sort();
+    let same_destinations = destinations.len() == proposal.destinations.len()
+        && destinations.iter().all(|(address, amount)| {
+            proposal.destinations.get(address) == Some(&BitcoinAmount::from(*amount))
+        });
+    if outpoints != proposal.outpoints || !same_destinations || feerate_vb != proposal.feerate {
+        return Err(CommandError::SpendProposalMismatch(proposal_id));
+    }
+
+    Ok(())
+}
+
+/// A spend proposal and the decisions of the managers about it, as presented to the user.
+pub fn spend_proposal_entry(
+    revaultd: &RevaultD,
+    proposal: DbSpendProposal,
+    acks: Vec<DbSpendProposalAck>,
+    now: u64,
+) -> SpendProposalEntry {
+    let threshold = spend_approval_threshold(revaultd);
+    let status = spend_proposal_status(
+        &proposal,
+        &acks,
+        threshold,
+        revaultd.managers_xpubs().len(),
+        now,
+    );
+
+    SpendProposalEntry {
+        id: proposal.id,
+        outpoints: proposal.outpoints.clone(),
+        destinations: proposal
+            .destinations
+            .iter()
+            .map(|(address, amount)| (address.clone(), Amount::from(*amount)))
+            .collect(),
+        feerate_vb: proposal.feerate,
+        created_at: proposal.created_at,
+        expires_at: proposal.expires_at,
+        status,
+        threshold,
+        approval_digest: proposal.ack_digest(true).to_hex(),
+        rejection_digest: proposal.ack_digest(false).to_hex(),
+        acks: acks
+            .into_iter()
+            .map(|ack| SpendProposalAckEntry {
+                fingerprint: ack.fingerprint.to_string(),
+                label: revaultd.participant(ack.fingerprint).label,
+                approved: ack.approved,
+                created_at: ack.created_at,
+            })
+            .collect(),
+    }
+}
+
+/// The Spend transactions we broadcasted that spend this vault
+pub fn broadcasted_spends_of(
+    db_path: &std::path::Path,
+    deposit_outpoint: &OutPoint,
+) -> Result<Vec<Txid>, DatabaseError> {
+    Ok(db_list_spends(db_path)?
+        .into_iter()
+        .filter_map(|(txid, (db_spend, outpoints))| {
+            if db_spend.broadcasted == Some(true) && outpoints.contains(deposit_outpoint) {
+                Some(txid)
+            } else {
+                None
             }
+        })
+        .collect())
+}
+
+/// Give up on a Spend transaction that is no longer in flight, freeing its vaults.
+pub fn expire_spend(
+    revaultd: &RevaultD,
+    spend_txid: &Txid,
+    reason: SpendExpiryReason,
+) -> Result<(), DatabaseError> {
+    if db_expire_spend(
+        &revaultd.db_file(),
+        spend_txid,
+        reason,
+        revaultd.clock.unix_timestamp(),
+    )? {
+        log_event!(
+            log::Level::Warn,
+            "spend_expired",
+            txid = spend_txid,
+            reason = reason;
+            "Spend transaction '{}' expired ({})",
+            spend_txid,
+            reason
+        );
+    }
+
+    Ok(())
+}
+
+/// Expire the Spend transactions we announced more than `spend_expiry` ago and whose Unvaults
+/// were never broadcast. Only makes sense for managers, as they are the ones pushing them.
+pub fn expire_stale_spends(revaultd: &RevaultD) -> Result<(), DatabaseError> {
+    if !revaultd.is_manager() {
+        return Ok(());
+    }
+
+    let db_path = revaultd.db_file();
+    let announced_before = revaultd
+        .clock
+        .unix_timestamp()
+        .saturating_sub(revaultd.spend_expiry.as_secs())
+        .try_into()
+        .unwrap_or(u32::MAX);
+    for db_spend in db_stale_spends(&db_path, announced_before)? {
+        if db_spend_unvaults_broadcast(&db_path, &db_spend)? {
+            continue;
         }
-        db_update_presigned_txs(db_path, db_vault, vec![db_tx], secp).unwrap();
+        expire_spend(revaultd, &db_spend.psbt.txid(), SpendExpiryReason::Timeout)?;
     }
 
-    /// Create 4 vaults: one unconfirmed, one funded, one secured and one active
-    fn create_vaults(revaultd: &RevaultD) -> Vec<TestVault> {
-        let db_file = revaultd.db_file();
-        let outpoints = vec![
-            // Unconfirmed
-            OutPoint::new(
-                Txid::from_str("fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b")
-                    .unwrap(),
-                0,
-            ),
-            // Funded
-            OutPoint::new(
-                Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f2")
-                    .unwrap(),
-                1,
-            ),
-            // Secured
-            OutPoint::new(
-                Txid::from_str("a9735f42110ce529386f612194a1e137a2a2679ac0e789ad7f470cd70c3c2c24")
-                    .unwrap(),
-                2,
-            ),
-            // Active
-            OutPoint::new(
-                Txid::from_str("cafa9f92be48ba41f9ee67e775b6c4afebd1bdbde5758792e9f30f6dea41e7fb")
-                    .unwrap(),
-                3,
-            ),
-        ];
+    Ok(())
+}
 
-        let transactions = vec![
+// How we record this outcome of the emergency procedure for this vault
+/// The transaction that is sending, or sent, a vault in this status to the EDV, if any.
+pub fn emergency_tx_kind(status: VaultStatus) -> Option<EmergencyTxKind> {
+    match status {
+        VaultStatus::EmergencyVaulting | VaultStatus::EmergencyVaulted => {
+            Some(EmergencyTxKind::Emergency)
+        }
+        VaultStatus::UnvaultEmergencyVaulting | VaultStatus::UnvaultEmergencyVaulted => {
+            Some(EmergencyTxKind::UnvaultEmergency)
+        }
+        _ => None,
+    }
+}
+
+fn db_emergency_outcome(vault_id: u32, outcome: &EmergencyOutcome) -> DbEmergencyOutcome {
+    let (kind, txid, detail) = match outcome {
+        EmergencyOutcome::Broadcast { txid } => {
+            (EmergencyOutcomeKind::Broadcast, Some(*txid), None)
+        }
+        EmergencyOutcome::AlreadyBroadcast { txid } => {
+            (EmergencyOutcomeKind::AlreadyBroadcast, Some(*txid), None)
+        }
+        EmergencyOutcome::SkippedStatus { status } => (
+            EmergencyOutcomeKind::SkippedStatus,
             None,
-            Some(TestTransactions {
-                initial_cancel:
-                CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAASDOvhSZlTSEcEoUq/CT7Cg3ILtc6sqt5qJKvAMq+LbIAAAAAAD9////AXYfpDUAAAAAIgAg9AncsIZc8g7mJdfT9infAeWlqjtxBs93ireDGnQn/DYAAAAAAAEBK7hhpDUAAAAAIgAgFZlOQkpDkFSsLUfyeMGVAOT3T88jZM7L/XlVZoJ2jnABAwSBAAAAAQWpIQMVlEoh50lasMhcdwnrmnCp2ROlGY5CrH+HtxQmfZDZ06xRh2R2qRS/INUX1CaP7Pbn5GmtGYu2wgqjnIisa3apFO/kceq8yo9w69g4VVtlFAf739qTiKxsk1KHZ1IhAnddfXi3N38A+aEQ74sUdeuV7sg+2L3ijTjMHMEAfq3cIQLWP96FqjfC5qKQkC2WhYbbLJx1FbNSAjsnMfwDnK0jD1KvARKyaCIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBR1IhA47+JRqdt+oloFosla9hWUYVf5YQKDbuq4KO13JS45KgIQMKcLWzABxb/9YBQe+bJRW3v3om8S2LNMGUKSp5K+PQ+1KuIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap(),
+            Some(status.to_string()),
+        ),
+        EmergencyOutcome::MissingSignatures => {
+            (EmergencyOutcomeKind::MissingSignatures, None, None)
+        }
+        EmergencyOutcome::Rejected { txid, reason } => (
+            EmergencyOutcomeKind::Rejected,
+            Some(*txid),
+            Some(reason.clone()),
+        ),
+    };
+
+    DbEmergencyOutcome {
+        vault_id,
+        kind,
+        txid,
+        detail,
+    }
+}
+
+/// Broadcast the Emergency transaction of the vaults that were not unvaulted, and the Unvault
+/// Emergency transaction of those that were, reporting what became of each vault. The
+/// transactions a previous run broadcast are not broadcast again, therefore running it again
+/// only retries the vaults it failed for.
+///
+/// The run is recorded in database, for it to be notified, and the vaults whose transaction
+/// bitcoind rejected are flagged.
+pub fn emergency_broadcast<T: BitcoindThread>(
+    revaultd: &RevaultD,
+    bitcoind_conn: &T,
+) -> Result<Vec<EmergencyEntry>, CommandError> {
+    let db_path = revaultd.db_file();
+    let already_broadcast =
+        db_emergency_broadcast_txids(&db_path).expect("Database must be accessible");
+
+    let mut outcomes = Vec::new();
+    let mut to_broadcast = Vec::new();
+    for db_vault in db_vaults(&db_path).expect("Database must be accessible") {
+        let (db_tx, tx_kind) = match db_vault.status {
+            VaultStatus::Funded
+            | VaultStatus::Securing
+            | VaultStatus::Secured
+            | VaultStatus::Activating
+            | VaultStatus::Active => (
+                db_emer_transaction(&db_path, db_vault.id),
+                EmergencyTxKind::Emergency,
+            ),
+            VaultStatus::Unvaulting
+            | VaultStatus::Unvaulted
+            | VaultStatus::Spending
+            | VaultStatus::Canceling
+            | VaultStatus::Spendable => (
+                db_unvault_emer_transaction(&db_path, db_vault.id),
+                EmergencyTxKind::UnvaultEmergency,
+            ),
+            status => {
+                // Report which one fired if it's already on its way to the EDV, whoever
+                // broadcast it.
+                let tx_kind = emergency_tx_kind(status);
+                outcomes.push((
+                    db_vault,
+                    tx_kind,
+                    EmergencyOutcome::SkippedStatus { status },
+                ));
+                continue;
+            }
+        };
+        let db_tx = db_tx.expect("Database must be accessible");
+
+        let outcome = match db_tx {
+            Some(db_tx) if db_tx.is_fully_signed => {
+                let txid = db_tx.psbt.txid();
+                if already_broadcast.contains(&txid) {
+                    EmergencyOutcome::AlreadyBroadcast { txid }
+                } else {
+                    match db_tx.psbt.finalized_tx(&revaultd.secp_ctx) {
+                        Ok(tx) => {
+                            to_broadcast.push((outcomes.len(), tx));
+                            EmergencyOutcome::Broadcast { txid }
+                        }
+                        Err(e) => EmergencyOutcome::Rejected {
+                            txid,
+                            reason: format!("Finalizing transaction: {}", e),
+                        },
+                    }
+                }
+            }
+            _ => EmergencyOutcome::MissingSignatures,
+        };
+        outcomes.push((db_vault, Some(tx_kind), outcome));
+    }
+
+    // The failure to broadcast one of them must not prevent the others from going out.
+    let (indexes, txs): (Vec<usize>, Vec<BitcoinTransaction>) = to_broadcast.into_iter().unzip();
+    let results = bitcoind_conn.broadcast_each(txs)?;
+    for (i, (txid, error)) in indexes.into_iter().zip(results) {
+        if let Some(reason) = error {
+            outcomes[i].2 = EmergencyOutcome::Rejected { txid, reason };
+        }
+    }
+
+    let mut db_outcomes = Vec::with_capacity(outcomes.len());
+    for (db_vault, _, outcome) in &outcomes {
+        let db_outcome = db_emergency_outcome(db_vault.id, outcome);
+        let level = match db_outcome.kind {
+            EmergencyOutcomeKind::Broadcast
+            | EmergencyOutcomeKind::AlreadyBroadcast
+            | EmergencyOutcomeKind::SkippedStatus => log::Level::Info,
+            EmergencyOutcomeKind::MissingSignatures => log::Level::Warn,
+            EmergencyOutcomeKind::Rejected => log::Level::Error,
+        };
+        log_event!(
+            level,
+            "emergency_outcome",
+            outpoint = db_vault.deposit_outpoint,
+            outcome = db_outcome.kind,
+            txid = db_outcome.txid.map(|txid| txid.to_string()).unwrap_or_default(),
+            detail = db_outcome.detail.clone().unwrap_or_default();
+            "Emergency for vault at '{}': {}{}",
+            db_vault.deposit_outpoint,
+            db_outcome.kind,
+            db_outcome
+                .detail
+                .as_ref()
+                .map(|detail| format!(" ('{}')", detail))
+                .unwrap_or_default()
+        );
+        db_outcomes.push(db_outcome);
+    }
+
+    // We are in an emergency, don't fail for a bookkeeping issue.
+    if let Err(e) = db_record_emergency_run(&db_path, revaultd.clock.unix_timestamp(), &db_outcomes)
+    {
+        log::error!("Error recording the emergency run: '{}'", e);
+    }
+
+    Ok(outcomes
+        .into_iter()
+        .map(|(db_vault, transaction, outcome)| EmergencyEntry {
+            deposit_outpoint: db_vault.deposit_outpoint,
+            transaction,
+            outcome,
+        })
+        .collect())
+}
+
+// The event of a vault sent to the EDV by its Emergency or Unvault Emergency transaction. The
+// amount is the value reaching the EDV, the fees are the rest of the value of the vault. Only
+// the stakeholders have these transactions.
+fn emergency_event<T: BitcoindThread>(
+    db_path: &std::path::Path,
+    bitcoind_conn: &T,
+    vault: &DbVault,
+    kind: HistoryEventKind,
+) -> Result<Option<HistoryEvent>, CommandError> {
+    let db_tx = match kind {
+        HistoryEventKind::Emergency => db_emer_transaction(db_path, vault.id),
+        _ => db_unvault_emer_transaction(db_path, vault.id),
+    }
+    .expect("Database must be accessible");
+    let txid = match db_tx {
+        Some(db_tx) => db_tx.psbt.txid(),
+        None => return Ok(None),
+    };
+    let emer_tx = match bitcoind_conn.wallet_tx(txid)? {
+        Some(tx) => tx,
+        None => return Ok(None),
+    };
+    // It can only be unconfirmed if it was just reorg'ed out, ignore it then.
+    let blockheight = match emer_tx.blockheight {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+
+    let bytes = Vec::from_hex(&emer_tx.hex).expect("bitcoind returned a wrong transaction format");
+    let tx: BitcoinTransaction =
+        encode::deserialize(&bytes).expect("bitcoind returned a wrong transaction format");
+    let edv_amount: u64 = tx.output.iter().map(|txout| txout.value).sum();
+
+    Ok(Some(HistoryEvent {
+        kind,
+        date: vault.moved_at.expect("Vault was moved"),
+        blockheight,
+        amount: Some(Amount::from_sat(edv_amount)),
+        // It may have been feebumped with external inputs
+        fee: vault
+            .amount
+            .as_sat()
+            .checked_sub(edv_amount)
+            .map(Amount::from_sat),
+        txid,
+        vaults: vec![vault.deposit_outpoint],
+    }))
+}
+
+/// gethistory retrieves a limited list of events which occured between two given dates.
+pub fn gethistory<T: BitcoindThread>(
+    revaultd: &RevaultD,
+    bitcoind_conn: &T,
+    start: u32,
+    end: u32,
+    limit: u64,
+    kind: &[HistoryEventKind],
+) -> Result<Vec<HistoryEvent>, CommandError> {
+    let db_path = revaultd.db_file();
+    // All vaults which have one transaction (either the funding, the canceling, the unvaulting, the spending)
+    // inside the date range are retrieved.
+    // This list might include vaults that were consumed again outside the range.
+    let vaults = db_vaults_with_txids_in_period(&db_path, start, end, limit)
+        .expect("Database must be accessible");
+
+    // Used to retrieve the deposit from the Cancel outputs. Not a vector since the Cancel only
+    // ever has a single deposit output.
+    let mut vaults_by_outpoint_txid: HashMap<Txid, &DbVault> = HashMap::with_capacity(vaults.len());
+    // Used for change detection when computing the deposit events.
+    let mut final_txids: HashSet<Txid> = HashSet::with_capacity(vaults.len());
+    for vault in &vaults {
+        vaults_by_outpoint_txid.insert(vault.deposit_outpoint.txid, vault);
+        if let Some(txid) = vault.final_txid {
+            final_txids.insert(txid);
+        }
+    }
+    // The recorded origin of the funds, for when the vault they come from isn't in the range.
+    let origins = db_vault_origins(&db_path).expect("Database must be accessible");
+    // Map of the id and the vaults consumed by the final transaction.
+    let mut spends: HashMap<Txid, Vec<&DbVault>> = HashMap::with_capacity(vaults.len());
+    let mut events: Vec<HistoryEvent> = Vec::with_capacity(vaults.len());
+
+    for vault in &vaults {
+        if kind.contains(&HistoryEventKind::Deposit)
+            // A vault may be retrieved as a change of a cancel or a spend in order to compute
+            // change amount but not be in 'funded' state yet (because we usually require >1
+            // conf).
+            && vault.status != VaultStatus::Unconfirmed
+            // Vault could have been moved but not deposited during the period.
+            && vault.funded_at.expect("Vault is funded") >= start
+            && vault.funded_at.expect("Vault is funded") <= end
+            // Only deposits that are not a spend transaction change and not cancel output
+            // are considered as history events.
+            && !final_txids.contains(&vault.deposit_outpoint.txid)
+            && origins
+                .get(&vault.id)
+                .map(|origin| *origin == DepositOrigin::External)
+                .unwrap_or(true)
+        {
+            events.push(HistoryEvent {
+                kind: HistoryEventKind::Deposit,
+                date: vault.funded_at.expect("Vault is funded"),
+                blockheight: vault.blockheight,
+                amount: Some(vault.amount.into()),
+                fee: None,
+                txid: vault.deposit_outpoint.txid,
+                vaults: vec![vault.deposit_outpoint],
+            });
+        }
+
+        if kind.contains(&HistoryEventKind::Cancel)
+            && vault.status == VaultStatus::Canceled
+            && vault.moved_at.expect("Vault is canceled") >= start
+            && vault.moved_at.expect("Vault is canceled") <= end
+        {
+            let txid = vault
+                .final_txid
+                .expect("Canceled vault must have a cancel txid");
+
+            let cancel_tx = bitcoind_conn
+                .wallet_tx(txid)?
+                .expect("Cancel tx should be here");
+
+            let cancel_height = match cancel_tx.blockheight {
+                Some(h) => h,
+                None => {
+                    // It can only happen if the cancel transaction was just reorg'ed out.
+                    // In this super edgy case, just ignore this entry.
+                    continue;
+                }
+            };
+
+            let change_amount = vaults_by_outpoint_txid
+                .get(&txid)
+                // if the change output did not create a vault because of the dust limit
+                // the change_amount is equal to 0.
+                .map_or(0, |vault| vault.amount.as_sat());
+
+            events.push(HistoryEvent {
+                kind: HistoryEventKind::Cancel,
+                date: vault.moved_at.expect("Tx should be confirmed"),
+                blockheight: cancel_height,
+                amount: None,
+                fee: Some(Amount::from_sat(
+                    vault
+                        .amount
+                        .as_sat()
+                        .checked_sub(change_amount)
+                        .expect("Moved funds include funds going back"),
+                )),
+                txid,
+                vaults: vec![vault.deposit_outpoint],
+            });
+        }
+
+        // Tell apart the vaults revaulted before and after they were unvaulted, it tells whether
+        // the funds were attacked pre- or post-unvault.
+        let emergency_kind = match vault.status {
+            VaultStatus::EmergencyVaulted => Some(HistoryEventKind::Emergency),
+            VaultStatus::UnvaultEmergencyVaulted => Some(HistoryEventKind::UnvaultEmergency),
+            _ => None,
+        };
+        if let Some(emergency_kind) = emergency_kind {
+            let moved_at = vault.moved_at.expect("Vault is emergencied");
+            if kind.contains(&emergency_kind) && moved_at >= start && moved_at <= end {
+                if let Some(event) =
+                    emergency_event(&db_path, bitcoind_conn, vault, emergency_kind)?
+                {
+                    events.push(event);
+                }
+            }
+        }
+
+        // In order to fill the spend map, only vaults that are
+        // consumed between the two dates are kept.
+        if kind.contains(&HistoryEventKind::Spend)
+            && vault.status == VaultStatus::Spent
+            && vault.moved_at.expect("Vault is spent") >= start
+            && vault.moved_at.expect("Vault is spent") <= end
+        {
+            let txid = vault
+                .final_txid
+                .expect("Spent vault must have a spend txid");
+
+            if let Some(vlts) = spends.get_mut(&txid) {
+                vlts.push(vault);
+            } else {
+                spends.insert(txid, vec![vault]);
+            }
+        }
+    }
+
+    if kind.contains(&HistoryEventKind::Spend) {
+        for (txid, spent_vaults) in spends {
+            let spend_tx = bitcoind_conn
+                .wallet_tx(txid)?
+                .expect("Spend tx should be here");
+
+            let spend_height = match spend_tx.blockheight {
+                Some(h) => h,
+                None => {
+                    // It can only happen if the spend transaction was just reorg'ed out.
+                    // In this super edgy case, just ignore this entry.
+                    continue;
+                }
+            };
+
+            let bytes =
+                Vec::from_hex(&spend_tx.hex).expect("bitcoind returned a wrong transaction format");
+            let tx: BitcoinTransaction =
+                encode::deserialize(&bytes).expect("bitcoind returned a wrong transaction format");
+
+            let derivation_index = spent_vaults
+                .iter()
+                .map(|v| v.derivation_index)
+                .max()
+                .expect("Spent vaults should not be empty");
+
+            let cpfp_script_pubkey = revaultd
+                .derived_cpfp_descriptor(derivation_index)
+                .into_inner()
+                .script_pubkey();
+
+            let deposit_address = revaultd
+                .derived_deposit_descriptor(derivation_index)
+                .into_inner()
+                .script_pubkey();
+
+            let mut recipients_amount: u64 = 0;
+            let mut change_amount: u64 = 0;
+            for txout in tx.output {
+                if cpfp_script_pubkey == txout.script_pubkey {
+                    // this cpfp output is ignored and its amount is part of the fees
+                } else if deposit_address == txout.script_pubkey {
+                    change_amount += txout.value;
+                } else {
+                    recipients_amount += txout.value
+                }
+            }
+
+            // fees is the total of the deposits minus the total of the spend outputs.
+            // Fees include then the unvaulting fees and the spend fees.
+            let fees = spent_vaults
+                .iter()
+                .map(|vlt| vlt.amount.as_sat())
+                .sum::<u64>()
+                .checked_sub(recipients_amount + change_amount)
+                .expect("Funds moving include funds going back");
+
+            events.push(HistoryEvent {
+                date: spend_tx.received_time,
+                blockheight: spend_height,
+                kind: HistoryEventKind::Spend,
+                amount: Some(Amount::from_sat(recipients_amount)),
+                fee: Some(Amount::from_sat(fees)),
+                txid,
+                vaults: spent_vaults
+                    .iter()
+                    .map(|vault| vault.deposit_outpoint)
+                    .collect(),
+            })
+        }
+    }
+
+    // Because a vault represents a deposit event and maybe a second event (cancel or spend),
+    // the two timestamp `funded_at and `moved_at` must be taken in account. The list of vaults
+    // can not considered as an ordered list of events. All events must be first filtered and
+    // stored in a list before being ordered.
+    events.sort_by(|a, b| b.date.cmp(&a.date));
+    // Because a spend transaction may consume multiple vault and still count as one event,
+    // and because the list of events must be first ordered by event date. The limit is enforced
+    // at the end. (A limit was applied in the sql query only on the number of txids in the given period)
+    events.truncate(limit as usize);
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amount::Amount as RpcAmount,
+        bitcoind::interface::WalletTransaction,
+        clock::test_utils::MockClock,
+        commands::{ExportKind, GetBalancesResult},
+        config::{xpub_fingerprint_from_str, EmergencyAddresses, EmergencyBand},
+        database::{
+            actions::{
+                db_confirm_deposit, db_confirm_unvault, db_delete_spend, db_emer_unvault,
+                db_insert_new_unconfirmed_vault, db_insert_spend, db_insert_spend_destinations,
+                db_mark_broadcastable_spend, db_mark_emergencied_unvault, db_raise_vault_flag,
+                db_record_spend_announcement, db_unvault_deposit, db_update_presigned_txs,
+                db_update_tip,
+            },
+            bitcointx::RevaultTx,
+            interface::{
+                db_cancel_transaction, db_emer_transaction, db_exec, db_spend_expiration,
+                db_unnotified_emergency_runs, db_unvault_emer_transaction, db_unvault_transaction,
+                db_vault_by_deposit,
+            },
+            schema::{DbTransaction, DbVault, VaultFlagKind},
+        },
+        diskspace::test_utils::MockFsStats,
+        revaultd::{BlockchainTip, RevaultD, VaultStatus},
+        setup_db,
+        utils::test_utils::{
+            dummy_revaultd, dummy_rpcutil, insert_vault_in_db, stub_cosigner, test_datadir,
+            MockBitcoindThread, UserRole,
+        },
+        DaemonControl,
+    };
+    use revault_net::sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::gen_keypair;
+    use revault_tx::{
+        bitcoin::{
+            bech32::u5,
+            blockdata::transaction::OutPoint,
+            hash_types::{BlockHash, PubkeyHash, ScriptHash, Txid},
+            hashes::{hex::FromHex, Hash},
+            secp256k1,
+            util::{
+                amount::Amount,
+                bip32::{ChildNumber, ExtendedPrivKey},
+            },
+            PublicKey as BitcoinPubKey, Script, SigHashType,
+        },
+        scripts::{DepositDescriptor, EmergencyAddress, UnvaultDescriptor},
+        transactions::{
+            CancelTransaction, EmergencyTransaction, RevaultTransaction,
+            UnvaultEmergencyTransaction, UnvaultTransaction,
+        },
+    };
+    use rusqlite::params;
+    use std::{
+        cell::Cell,
+        collections::BTreeMap,
+        fs,
+        str::FromStr,
+        sync::{atomic::Ordering, Arc},
+        time::Duration,
+    };
+
+    // Amounts in RPC responses must always be integers, never floating point numbers.
+    fn assert_no_float(value: &serde_json::Value) {
+        match value {
+            serde_json::Value::Number(n) => assert!(!n.is_f64(), "Float in response: {}", n),
+            serde_json::Value::Array(values) => values.iter().for_each(assert_no_float),
+            serde_json::Value::Object(map) => map.values().for_each(assert_no_float),
+            _ => {}
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestVault {
+        db_vault: DbVault,
+        transactions: Option<TestTransactions>,
+    }
+
+    #[derive(Clone)]
+    struct TestTransactions {
+        pub initial_cancel: CancelTransaction,
+        pub final_cancel: Option<CancelTransaction>,
+        pub initial_unvault: UnvaultTransaction,
+        pub final_unvault: Option<UnvaultTransaction>,
+        pub initial_emer: EmergencyTransaction,
+        pub final_emer: Option<EmergencyTransaction>,
+        pub initial_unvault_emer: UnvaultEmergencyTransaction,
+        pub final_unvault_emer: Option<UnvaultEmergencyTransaction>,
+    }
+
+    fn update_presigned_tx<C>(
+        db_path: &std::path::PathBuf,
+        db_vault: &DbVault,
+        mut db_tx: DbTransaction,
+        sigs: &BTreeMap<BitcoinPubKey, Vec<u8>>,
+        secp: &secp256k1::Secp256k1<C>,
+    ) where
+        C: secp256k1::Verification,
+    {
+        for (key, sig) in sigs {
+            let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap();
+            match db_tx.psbt {
+                RevaultTx::Unvault(ref mut tx) => {
+                    tx.add_signature(0, key.key, sig, secp).unwrap();
+                }
+                RevaultTx::Cancel(ref mut tx) => {
+                    tx.add_signature(0, key.key, sig, secp).unwrap();
+                }
+                RevaultTx::Emergency(ref mut tx) => {
+                    tx.add_signature(0, key.key, sig, secp).unwrap();
+                }
+                RevaultTx::UnvaultEmergency(ref mut tx) => {
+                    tx.add_signature(0, key.key, sig, secp).unwrap();
+                }
+            }
+        }
+        db_update_presigned_txs(db_path, db_vault, vec![db_tx], None, secp, 1_600_000_000).unwrap();
+    }
+
+    /// Create 4 vaults: one unconfirmed, one funded, one secured and one active
+    fn create_vaults(revaultd: &RevaultD) -> Vec<TestVault> {
+        let db_file = revaultd.db_file();
+        let outpoints = vec![
+            // Unconfirmed
+            OutPoint::new(
+                Txid::from_str("fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b")
+                    .unwrap(),
+                0,
+            ),
+            // Funded
+            OutPoint::new(
+                Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f2")
+                    .unwrap(),
+                1,
+            ),
+            // Secured
+            OutPoint::new(
+                Txid::from_str("a9735f42110ce529386f612194a1e137a2a2679ac0e789ad7f470cd70c3c2c24")
+                    .unwrap(),
+                2,
+            ),
+            // Active
+            OutPoint::new(
+                Txid::from_str("cafa9f92be48ba41f9ee67e775b6c4afebd1bdbde5758792e9f30f6dea41e7fb")
+                    .unwrap(),
+                3,
+            ),
+        ];
+
+        let transactions = vec![
+            None,
+            Some(TestTransactions {
+                initial_cancel:
+                CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAASDOvhSZlTSEcEoUq/CT7Cg3ILtc6sqt5qJKvAMq+LbIAAAAAAD9////AXYfpDUAAAAAIgAg9AncsIZc8g7mJdfT9infAeWlqjtxBs93ireDGnQn/DYAAAAAAAEBK7hhpDUAAAAAIgAgFZlOQkpDkFSsLUfyeMGVAOT3T88jZM7L/XlVZoJ2jnABAwSBAAAAAQWpIQMVlEoh50lasMhcdwnrmnCp2ROlGY5CrH+HtxQmfZDZ06xRh2R2qRS/INUX1CaP7Pbn5GmtGYu2wgqjnIisa3apFO/kceq8yo9w69g4VVtlFAf739qTiKxsk1KHZ1IhAnddfXi3N38A+aEQ74sUdeuV7sg+2L3ijTjMHMEAfq3cIQLWP96FqjfC5qKQkC2WhYbbLJx1FbNSAjsnMfwDnK0jD1KvARKyaCIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBR1IhA47+JRqdt+oloFosla9hWUYVf5YQKDbuq4KO13JS45KgIQMKcLWzABxb/9YBQe+bJRW3v3om8S2LNMGUKSp5K+PQ+1KuIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap(),
                 final_cancel: None,
                 initial_unvault:
                 UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAajRZE5yVgzG9McmOyy/WdcYdrGrK15bB5N/Hg8zhKOkAQAAAAD9////ArhhpDUAAAAAIgAgFZlOQkpDkFSsLUfyeMGVAOT3T88jZM7L/XlVZoJ2jnAwdQAAAAAAACIAILKCCA/RbV3QMPMrwwQmk4Ark4w1WyElM27WtBgftq6ZAAAAAAABASsA6aQ1AAAAACIAIPQJ3LCGXPIO5iXX0/Yp3wHlpao7cQbPd4q3gxp0J/w2AQMEAQAAAAEFR1IhA47+JRqdt+oloFosla9hWUYVf5YQKDbuq4KO13JS45KgIQMKcLWzABxb/9YBQe+bJRW3v3om8S2LNMGUKSp5K+PQ+1KuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGpIQMVlEoh50lasMhcdwnrmnCp2ROlGY5CrH+HtxQmfZDZ06xRh2R2qRS/INUX1CaP7Pbn5GmtGYu2wgqjnIisa3apFO/kceq8yo9w69g4VVtlFAf739qTiKxsk1KHZ1IhAnddfXi3N38A+aEQ74sUdeuV7sg+2L3ijTjMHMEAfq3cIQLWP96FqjfC5qKQkC2WhYbbLJx1FbNSAjsnMfwDnK0jD1KvARKyaCICAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBJSEDjv4lGp236iWgWiyVr2FZRhV/lhAoNu6rgo7XclLjkqCsUYciAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap(),
@@ -641,928 +1981,3119 @@ mod tests {
                 UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAARU919uuOZ2HHyRUrQsCrT2s98u7j8/xW6DXMzO7+eYFAAAAAAD9////AaRl6QsAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBK9Cn6QsAAAAAIgAg8R4ZLx3Zf/A7VOcZ7PtAzhSLo5olkqqYP+voLCRFn2QBAwSBAAAAAQWoIQIGcwwFZVqf0EVFaTWYlQmxQ4oTCIkj+5KOstDoG1vgOaxRh2R2qRRvoS9lQLcmcOnmhvvrvSYgPxBG3oisa3apFEWuYAoAKnNlB7Zvc0vqNpOAwYMciKxsk1KHZ1IhAw9kuSKu4v1ZfxBLxss7Zw8cosbEmxBxoabAEFddlP5aIQKr5HWxmew9YvpXb67hajNP24b/sm3Odb7Ouq7fMorD/lKvU7JoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap(),
                 final_unvault_emer: Some(UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAARU919uuOZ2HHyRUrQsCrT2s98u7j8/xW6DXMzO7+eYFAAAAAAD9////AaRl6QsAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBK9Cn6QsAAAAAIgAg8R4ZLx3Zf/A7VOcZ7PtAzhSLo5olkqqYP+voLCRFn2QiAgJDFo5gffr4sTpOPvcI45wr3VtVCTh/yw/SiCJaCIjiV0gwRQIhAPSxaISa+1b4SqwfE0WfmXUe3YfIc7zIJfO1PA3ZdUqUAiBJ5JUqCXojmLZilVZGhgVHHwxpu5Kl4z9VftegjciTyoEiAgL7lssn6CH7Mq08DaEfDfNM0gXXyaxn3/g/VygUS+2hBUcwRAIgMval9daakP00a+64tfLtVXcX8iX/RDD+8ds4Ki9qn14CIEeo8hDkwNVJxMiNgM6QQ9I5RLtPPQnVxhzkle3q9lCdgQEDBIEAAAABBaghAgZzDAVlWp/QRUVpNZiVCbFDihMIiSP7ko6y0OgbW+A5rFGHZHapFG+hL2VAtyZw6eaG++u9JiA/EEbeiKxrdqkURa5gCgAqc2UHtm9zS+o2k4DBgxyIrGyTUodnUiEDD2S5Iq7i/Vl/EEvGyztnDxyixsSbEHGhpsAQV12U/lohAqvkdbGZ7D1i+ldvruFqM0/bhv+ybc51vs66rt8yisP+Uq9TsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAAiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap()),
             }),
-        ];
+        ];
+
+        for outpoint in &outpoints {
+            db_insert_new_unconfirmed_vault(
+                &db_file,
+                1,
+                &outpoint,
+                &Amount::ONE_BTC,
+                DerivationIndex::new(0).unwrap(),
+            )
+            .unwrap();
+        }
+
+        // First vault: Funded
+        db_confirm_deposit(
+            &db_file,
+            &outpoints[1],
+            9, // blockheight
+            9, // blocktime
+            &transactions[1].as_ref().unwrap().initial_unvault,
+            &transactions[1].as_ref().unwrap().initial_cancel,
+            Some(&transactions[1].as_ref().unwrap().initial_emer),
+            Some(&transactions[1].as_ref().unwrap().initial_unvault_emer),
+            1_600_000_000,
+        )
+        .unwrap();
+        assert_eq!(
+            db_vault_by_deposit(&db_file, &outpoints[1])
+                .unwrap()
+                .unwrap()
+                .status,
+            VaultStatus::Funded
+        );
+
+        // Second vault: Secured
+        db_confirm_deposit(
+            &db_file,
+            &outpoints[2],
+            9, // blockheight
+            9, // blocktime
+            &transactions[2].as_ref().unwrap().initial_unvault,
+            &transactions[2].as_ref().unwrap().initial_cancel,
+            Some(&transactions[2].as_ref().unwrap().initial_emer),
+            Some(&transactions[2].as_ref().unwrap().initial_unvault_emer),
+            1_600_000_000,
+        )
+        .unwrap();
+
+        let vaults: Vec<_> = outpoints
+            .iter()
+            .map(|o| db_vault_by_deposit(&db_file, &o).unwrap().unwrap())
+            .collect();
+
+        let tx_db = db_cancel_transaction(&db_file, vaults[2].id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_file,
+            &vaults[2],
+            tx_db,
+            &transactions[2]
+                .as_ref()
+                .unwrap()
+                .final_cancel
+                .as_ref()
+                .unwrap()
+                .psbt()
+                .inputs[0]
+                .partial_sigs,
+            &revaultd.secp_ctx,
+        );
+
+        let tx_db = db_emer_transaction(&db_file, vaults[2].id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_file,
+            &vaults[2],
+            tx_db,
+            &transactions[2]
+                .as_ref()
+                .unwrap()
+                .final_emer
+                .as_ref()
+                .unwrap()
+                .psbt()
+                .inputs[0]
+                .partial_sigs,
+            &revaultd.secp_ctx,
+        );
+
+        let tx_db = db_unvault_emer_transaction(&db_file, vaults[2].id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_file,
+            &vaults[2],
+            tx_db,
+            &transactions[2]
+                .as_ref()
+                .unwrap()
+                .final_unvault_emer
+                .as_ref()
+                .unwrap()
+                .psbt()
+                .inputs[0]
+                .partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        db_exec(&db_file, |tx| {
+            tx.execute(
+                "UPDATE vaults SET status = (?1), secured_at = strftime('%s','now') \
+             WHERE vaults.id = (?2)",
+                params![VaultStatus::Secured as u32, vaults[2].id,],
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            db_vault_by_deposit(&db_file, &outpoints[2])
+                .unwrap()
+                .unwrap()
+                .status,
+            VaultStatus::Secured
+        );
+
+        // Third vault: Active
+        db_confirm_deposit(
+            &db_file,
+            &vaults[3].deposit_outpoint,
+            9, // blockheight
+            9, // blocktime
+            &transactions[3].as_ref().unwrap().initial_unvault,
+            &transactions[3].as_ref().unwrap().initial_cancel,
+            Some(&transactions[3].as_ref().unwrap().initial_emer),
+            Some(&transactions[3].as_ref().unwrap().initial_unvault_emer),
+            1_600_000_000,
+        )
+        .unwrap();
+
+        let tx_db = db_cancel_transaction(&db_file, vaults[3].id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_file,
+            &vaults[3],
+            tx_db,
+            &transactions[3]
+                .as_ref()
+                .unwrap()
+                .final_cancel
+                .as_ref()
+                .unwrap()
+                .psbt()
+                .inputs[0]
+                .partial_sigs,
+            &revaultd.secp_ctx,
+        );
+
+        let tx_db = db_emer_transaction(&db_file, vaults[3].id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_file,
+            &vaults[3],
+            tx_db,
+            &transactions[3]
+                .as_ref()
+                .unwrap()
+                .final_emer
+                .as_ref()
+                .unwrap()
+                .psbt()
+                .inputs[0]
+                .partial_sigs,
+            &revaultd.secp_ctx,
+        );
+
+        let tx_db = db_unvault_emer_transaction(&db_file, vaults[3].id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_file,
+            &vaults[3],
+            tx_db,
+            &transactions[3]
+                .as_ref()
+                .unwrap()
+                .final_unvault_emer
+                .as_ref()
+                .unwrap()
+                .psbt()
+                .inputs[0]
+                .partial_sigs,
+            &revaultd.secp_ctx,
+        );
+
+        let tx_db = db_unvault_transaction(&db_file, vaults[3].id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_file,
+            &vaults[3],
+            tx_db,
+            &transactions[3]
+                .as_ref()
+                .unwrap()
+                .final_unvault
+                .as_ref()
+                .unwrap()
+                .psbt()
+                .inputs[0]
+                .partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        db_exec(&db_file, |tx| {
+            tx.execute(
+                "UPDATE vaults SET status = (?1), secured_at = strftime('%s','now'), delegated_at = strftime('%s','now') \
+             WHERE vaults.id = (?2)",
+                params![VaultStatus::Active as u32, vaults[3].id],
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            db_vault_by_deposit(&db_file, &outpoints[3])
+                .unwrap()
+                .unwrap()
+                .status,
+            VaultStatus::Active
+        );
+
+        vaults
+            .into_iter()
+            .zip(transactions)
+            .map(|(v, txs)| TestVault {
+                db_vault: db_vault_by_deposit(&db_file, &v.deposit_outpoint)
+                    .unwrap()
+                    .unwrap(),
+                transactions: txs,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_listvaults_from_db() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let vaults = create_vaults(&revaultd);
+
+        // Checking that the result is sane
+        for v in &vaults {
+            let res = &listvaults_from_db(
+                &revaultd,
+                Some(&[v.db_vault.status]),
+                Some(&[v.db_vault.deposit_outpoint]),
+            )
+            .unwrap()[0];
+            assert_eq!(res.amount.as_sat(), v.db_vault.amount.as_sat());
+            assert_eq!(res.blockheight, v.db_vault.blockheight);
+            assert_eq!(res.status, v.db_vault.status);
+            assert_eq!(res.txid, v.db_vault.deposit_outpoint.txid);
+            assert_eq!(res.vout, v.db_vault.deposit_outpoint.vout);
+            assert_eq!(res.derivation_index, DerivationIndex::new(0).unwrap());
+        }
+
+        // Checking that filters work
+        assert_eq!(listvaults_from_db(&revaultd, None, None).unwrap().len(), 4);
+        assert_eq!(
+            listvaults_from_db(&revaultd, Some(&[VaultStatus::Unconfirmed]), None)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            listvaults_from_db(
+                &revaultd,
+                Some(&[VaultStatus::Unconfirmed]),
+                Some(&[vaults[1].db_vault.deposit_outpoint])
+            )
+            .unwrap()
+            .len(),
+            0
+        );
+        assert_eq!(
+            listvaults_from_db(
+                &revaultd,
+                None,
+                Some(&[
+                    vaults[0].db_vault.deposit_outpoint,
+                    vaults[1].db_vault.deposit_outpoint
+                ])
+            )
+            .unwrap()
+            .len(),
+            2
+        );
+        assert_eq!(
+            listvaults_from_db(
+                &revaultd,
+                Some(&[
+                    VaultStatus::Unconfirmed,
+                    VaultStatus::Funded,
+                    VaultStatus::Secured
+                ]),
+                None,
+            )
+            .unwrap()
+            .len(),
+            3
+        );
+        assert_eq!(
+            listvaults_from_db(
+                &revaultd,
+                Some(&[
+                    VaultStatus::Unconfirmed,
+                    VaultStatus::Funded,
+                    VaultStatus::Secured,
+                    VaultStatus::Active,
+                ]),
+                None,
+            )
+            .unwrap()
+            .len(),
+            4
+        );
+
+        // The amounts are serialized as integers
+        let entries = listvaults_from_db(&revaultd, None, None).unwrap();
+        let json = serde_json::to_value(&entries).unwrap();
+        assert_no_float(&json);
+        for (entry, value) in entries.iter().zip(json.as_array().unwrap()) {
+            assert_eq!(value["amount"].as_u64(), Some(entry.amount.as_sat()));
+            assert_eq!(value["origin"], "external");
+        }
+        let balances = GetBalancesResult {
+            unconfirmed: entries[0].amount,
+            secured: RpcAmount::from_sat(1),
+            ..GetBalancesResult::default()
+        };
+        let json = serde_json::to_value(&balances).unwrap();
+        assert_no_float(&json);
+        assert_eq!(
+            json["unconfirmed"].as_u64(),
+            Some(entries[0].amount.as_sat())
+        );
+        assert_eq!(json["unsecured"].as_u64(), Some(0));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_listvaults_at_heights() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_file = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        let set_tip = |height: u32| {
+            db_update_tip(
+                &db_file,
+                &BlockchainTip {
+                    height,
+                    hash: BlockHash::from_slice(&[height as u8; 32]).unwrap(),
+                },
+            )
+            .unwrap()
+        };
+        let set_status = |outpoint: &OutPoint, status: VaultStatus, blockheight: u32| {
+            db_exec(&db_file, |tx| {
+                tx.execute(
+                    "UPDATE vaults SET status = (?1), blockheight = (?2) \
+                     WHERE deposit_txid = (?3) AND deposit_vout = (?4)",
+                    params![
+                        status as u32,
+                        blockheight,
+                        outpoint.txid.to_vec(),
+                        outpoint.vout
+                    ],
+                )?;
+                Ok(())
+            })
+            .unwrap()
+        };
+        let insert = |outpoint: &OutPoint| {
+            insert_vault_in_db(
+                &db_file,
+                1,
+                outpoint,
+                &Amount::ONE_BTC,
+                0,
+                DerivationIndex::new(0).unwrap(),
+                None,
+                None,
+                VaultStatus::Unconfirmed,
+                None,
+            )
+        };
+        let list = |filter: VaultHeightFilter| -> Vec<(OutPoint, VaultStatus)> {
+            listvaults_at_heights(&revaultd, None, None, &filter)
+                .unwrap()
+                .into_iter()
+                .map(|entry| (OutPoint::new(entry.txid, entry.vout), entry.status))
+                .collect()
+        };
+        let as_of = |height: u32| {
+            list(VaultHeightFilter {
+                as_of_height: Some(height),
+                ..VaultHeightFilter::default()
+            })
+        };
+        let entry = |outpoint: &OutPoint, as_of_height: Option<u32>| {
+            listvaults_at_heights(
+                &revaultd,
+                None,
+                Some(&[*outpoint]),
+                &VaultHeightFilter {
+                    as_of_height,
+                    ..VaultHeightFilter::default()
+                },
+            )
+            .unwrap()
+            .pop()
+            .unwrap()
+        };
+
+        let vault_a = OutPoint::new(
+            Txid::from_str("fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b")
+                .unwrap(),
+            0,
+        );
+        let vault_b = OutPoint::new(
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f2")
+                .unwrap(),
+            1,
+        );
+        let vault_c = OutPoint::new(
+            Txid::from_str("a9735f42110ce529386f612194a1e137a2a2679ac0e789ad7f470cd70c3c2c24")
+                .unwrap(),
+            2,
+        );
+
+        // A goes through its whole lifecycle until it's canceled, and B is created by its
+        // Cancel. C gets its deposit reorged out and confirmed again.
+        set_tip(100);
+        insert(&vault_a);
+        set_tip(101);
+        set_status(&vault_a, VaultStatus::Funded, 101);
+        set_tip(103);
+        set_status(&vault_a, VaultStatus::Securing, 101);
+        set_tip(104);
+        set_status(&vault_a, VaultStatus::Secured, 101);
+        insert(&vault_c);
+        set_tip(105);
+        set_status(&vault_c, VaultStatus::Funded, 105);
+        set_tip(106);
+        set_status(&vault_a, VaultStatus::Active, 101);
+        set_tip(107);
+        set_status(&vault_c, VaultStatus::Unconfirmed, 0);
+        set_tip(108);
+        set_status(&vault_a, VaultStatus::Unvaulting, 101);
+        set_status(&vault_c, VaultStatus::Funded, 108);
+        set_tip(109);
+        set_status(&vault_a, VaultStatus::Unvaulted, 101);
+        set_tip(110);
+        set_status(&vault_a, VaultStatus::Canceling, 101);
+        set_tip(111);
+        set_status(&vault_a, VaultStatus::Canceled, 101);
+        insert(&vault_b);
+        set_tip(112);
+        set_status(&vault_b, VaultStatus::Funded, 112);
+        set_tip(115);
+        set_status(&vault_b, VaultStatus::Securing, 112);
+        set_status(&vault_b, VaultStatus::Secured, 112);
+        set_tip(120);
+
+        // The current state
+        assert_eq!(
+            list(VaultHeightFilter::default()),
+            vec![
+                (vault_a, VaultStatus::Canceled),
+                (vault_c, VaultStatus::Funded),
+                (vault_b, VaultStatus::Secured)
+            ]
+        );
+        let a = entry(&vault_a, None);
+        assert_eq!(a.secured_height, Some(104));
+        assert_eq!(a.moved_height, Some(111));
+        let b = entry(&vault_b, None);
+        assert_eq!(b.secured_height, Some(115));
+        assert_eq!(b.moved_height, None);
+        let c = entry(&vault_c, None);
+        assert_eq!(c.secured_height, None);
+
+        // Going back in time
+        assert!(as_of(99).is_empty());
+        assert_eq!(as_of(100), vec![(vault_a, VaultStatus::Unconfirmed)]);
+        assert_eq!(as_of(102), vec![(vault_a, VaultStatus::Funded)]);
+        assert_eq!(
+            as_of(105),
+            vec![
+                (vault_a, VaultStatus::Secured),
+                (vault_c, VaultStatus::Funded)
+            ]
+        );
+        assert_eq!(
+            as_of(107),
+            vec![
+                (vault_a, VaultStatus::Active),
+                (vault_c, VaultStatus::Unconfirmed)
+            ]
+        );
+        assert_eq!(
+            as_of(110),
+            vec![
+                (vault_a, VaultStatus::Canceling),
+                (vault_c, VaultStatus::Funded)
+            ]
+        );
+        assert_eq!(
+            as_of(111),
+            vec![
+                (vault_a, VaultStatus::Canceled),
+                (vault_c, VaultStatus::Funded),
+                (vault_b, VaultStatus::Unconfirmed)
+            ]
+        );
+        assert_eq!(as_of(114), as_of(112));
+        assert_eq!(as_of(115), list(VaultHeightFilter::default()));
+        let a = entry(&vault_a, Some(110));
+        assert_eq!(a.secured_height, Some(104));
+        assert_eq!(a.moved_height, None);
+        let a = entry(&vault_a, Some(103));
+        assert_eq!(a.secured_height, None);
+        let b = entry(&vault_b, Some(114));
+        assert_eq!(b.secured_height, None);
+
+        // The status filter applies to the historical status
+        let secured: Vec<_> = listvaults_at_heights(
+            &revaultd,
+            Some(&[VaultStatus::Secured]),
+            None,
+            &VaultHeightFilter {
+                as_of_height: Some(105),
+                ..VaultHeightFilter::default()
+            },
+        )
+        .unwrap()
+        .into_iter()
+        .map(|entry| OutPoint::new(entry.txid, entry.vout))
+        .collect();
+        assert_eq!(secured, vec![vault_a]);
+
+        // Filtering by funding height
+        let funded_between = |min: Option<u32>, max: Option<u32>| -> Vec<OutPoint> {
+            list(VaultHeightFilter {
+                min_funded_height: min,
+                max_funded_height: max,
+                as_of_height: None,
+            })
+            .into_iter()
+            .map(|(outpoint, _)| outpoint)
+            .collect()
+        };
+        assert_eq!(funded_between(Some(101), Some(101)), vec![vault_a]);
+        assert_eq!(funded_between(Some(102), None), vec![vault_c, vault_b]);
+        assert_eq!(funded_between(None, Some(110)), vec![vault_a, vault_c]);
+        assert!(funded_between(Some(113), Some(200)).is_empty());
+        // Both filters may be combined
+        assert_eq!(
+            list(VaultHeightFilter {
+                min_funded_height: Some(105),
+                max_funded_height: None,
+                as_of_height: Some(111),
+            }),
+            vec![
+                (vault_c, VaultStatus::Funded),
+                (vault_b, VaultStatus::Unconfirmed)
+            ]
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_vaults_from_deposits() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_file = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let vaults = create_vaults(&revaultd);
+        let outpoints: Vec<_> = vaults.iter().map(|v| v.db_vault.deposit_outpoint).collect();
+
+        assert_eq!(
+            vaults_from_deposits(&db_file, &outpoints, &vec![],)
+                .unwrap()
+                .len(),
+            4
+        );
+        assert_eq!(
+            vaults_from_deposits(&db_file, &outpoints[1..], &vec![VaultStatus::Unconfirmed],)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // Returning an error if one of the vaults has an invalid status
+        assert!(
+            vaults_from_deposits(&db_file, &outpoints, &vec![VaultStatus::Unconfirmed])
+                .unwrap_err()
+                .to_string()
+                .contains(
+                    &CommandError::InvalidStatusFor(VaultStatus::Unconfirmed, outpoints[0])
+                        .to_string()
+                )
+        );
+
+        // Returning an error if the outpoint is unknown
+        let wrong_outpoint = OutPoint::new(
+            Txid::from_str("abababababababababababababababababababababababababababababababab")
+                .unwrap(),
+            2,
+        );
+        assert!(
+            vaults_from_deposits(&db_file, &vec![wrong_outpoint], &vec![],)
+                .unwrap_err()
+                .to_string()
+                .contains(&CommandError::UnknownOutpoint(wrong_outpoint).to_string())
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_presigned_txs() {
+        let datadir_stk = test_datadir();
+        let datadir_man = test_datadir();
+        let mut stake_revaultd = dummy_revaultd(datadir_stk.clone(), UserRole::Stakeholder);
+        let mut man_revaultd = dummy_revaultd(datadir_man.clone(), UserRole::Manager);
+        setup_db(&mut stake_revaultd).unwrap();
+        let vaults = create_vaults(&stake_revaultd);
+        setup_db(&mut man_revaultd).unwrap();
+        let _ = create_vaults(&man_revaultd);
+
+        // vault[0] is not confirmed, no presigned txs here!
+        assert!(presigned_txs(&stake_revaultd, vec![vaults[0].db_vault.clone()]).is_none());
+
+        // vault[1] is funded, no txs is final
+        // The stakeholder has all the txs
+        let stake_txs = presigned_txs(&stake_revaultd, vec![vaults[1].db_vault.clone()]).unwrap();
+        assert_eq!(stake_txs.len(), 1);
+        assert_eq!(
+            stake_txs[0].vault_outpoint,
+            vaults[1].db_vault.deposit_outpoint
+        );
+        assert_eq!(
+            stake_txs[0].cancel.psbt,
+            vaults[1].transactions.as_ref().unwrap().initial_cancel
+        );
+        assert!(stake_txs[0].cancel.transaction.is_none());
+        assert_eq!(
+            stake_txs[0].unvault.psbt,
+            vaults[1].transactions.as_ref().unwrap().initial_unvault
+        );
+        assert!(stake_txs[0].unvault.transaction.is_none());
+        assert_eq!(
+            stake_txs[0].emergency.as_ref().unwrap().psbt,
+            vaults[1].transactions.as_ref().unwrap().initial_emer
+        );
+        assert!(stake_txs[0]
+            .emergency
+            .as_ref()
+            .unwrap()
+            .transaction
+            .is_none());
+        assert_eq!(
+            stake_txs[0].unvault_emergency.as_ref().unwrap().psbt,
+            vaults[1]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .initial_unvault_emer
+        );
+        assert!(stake_txs[0]
+            .unvault_emergency
+            .as_ref()
+            .unwrap()
+            .transaction
+            .is_none());
+
+        // The manager has the same txs, but no emergency
+        let man_txs = presigned_txs(&man_revaultd, vec![vaults[1].db_vault.clone()]).unwrap();
+        assert_eq!(man_txs.len(), 1);
+        assert_eq!(
+            man_txs[0].vault_outpoint,
+            vaults[1].db_vault.deposit_outpoint
+        );
+        assert_eq!(
+            man_txs[0].cancel.psbt,
+            vaults[1].transactions.as_ref().unwrap().initial_cancel
+        );
+        assert!(man_txs[0].cancel.transaction.is_none());
+        assert_eq!(
+            man_txs[0].unvault.psbt,
+            vaults[1].transactions.as_ref().unwrap().initial_unvault
+        );
+        assert!(man_txs[0].unvault.transaction.is_none());
+        assert!(man_txs[0].emergency.is_none());
+        assert!(man_txs[0].unvault_emergency.is_none());
+
+        // vault[2] is secured, the unvault tx is not final
+        // The stakeholder has all the txs
+        let stake_txs = presigned_txs(&stake_revaultd, vec![vaults[2].db_vault.clone()]).unwrap();
+        assert_eq!(stake_txs.len(), 1);
+        assert_eq!(
+            stake_txs[0].vault_outpoint,
+            vaults[2].db_vault.deposit_outpoint
+        );
+        assert_eq!(
+            stake_txs[0].cancel.psbt,
+            *vaults[2]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_cancel
+                .as_ref()
+                .unwrap()
+        );
+        assert!(stake_txs[0].cancel.transaction.is_some());
+        assert_eq!(
+            stake_txs[0].unvault.psbt,
+            vaults[2].transactions.as_ref().unwrap().initial_unvault
+        );
+        assert!(stake_txs[0].unvault.transaction.is_none());
+        assert_eq!(
+            stake_txs[0].emergency.as_ref().unwrap().psbt,
+            *vaults[2]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_emer
+                .as_ref()
+                .unwrap()
+        );
+        assert!(stake_txs[0]
+            .emergency
+            .as_ref()
+            .unwrap()
+            .transaction
+            .is_some());
+        assert_eq!(
+            stake_txs[0].unvault_emergency.as_ref().unwrap().psbt,
+            *vaults[2]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_unvault_emer
+                .as_ref()
+                .unwrap()
+        );
+        assert!(stake_txs[0]
+            .unvault_emergency
+            .as_ref()
+            .unwrap()
+            .transaction
+            .is_some());
 
-        for outpoint in &outpoints {
-            db_insert_new_unconfirmed_vault(
-                &db_file,
-                1,
-                &outpoint,
-                &Amount::ONE_BTC,
-                ChildNumber::from_normal_idx(0).unwrap(),
-            )
-            .unwrap();
-        }
+        // The manager has the same txs, but no emergency
+        let man_txs = presigned_txs(&man_revaultd, vec![vaults[2].db_vault.clone()]).unwrap();
+        assert_eq!(man_txs.len(), 1);
+        assert_eq!(
+            man_txs[0].vault_outpoint,
+            vaults[2].db_vault.deposit_outpoint
+        );
+        assert_eq!(
+            man_txs[0].cancel.psbt,
+            *vaults[2]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_cancel
+                .as_ref()
+                .unwrap()
+        );
+        assert!(man_txs[0].cancel.transaction.is_some());
+        assert_eq!(
+            man_txs[0].unvault.psbt,
+            vaults[2].transactions.as_ref().unwrap().initial_unvault
+        );
+        assert!(man_txs[0].unvault.transaction.is_none());
+        assert!(man_txs[0].emergency.is_none());
+        assert!(man_txs[0].unvault_emergency.is_none());
 
-        // First vault: Funded
-        db_confirm_deposit(
-            &db_file,
-            &outpoints[1],
-            9, // blockheight
-            9, // blocktime
-            &transactions[1].as_ref().unwrap().initial_unvault,
-            &transactions[1].as_ref().unwrap().initial_cancel,
-            Some(&transactions[1].as_ref().unwrap().initial_emer),
-            Some(&transactions[1].as_ref().unwrap().initial_unvault_emer),
-        )
-        .unwrap();
+        // vault[3] is active, every tx is final
+        // The stakeholder has all the txs
+        let stake_txs = presigned_txs(&stake_revaultd, vec![vaults[3].db_vault.clone()]).unwrap();
+        assert_eq!(stake_txs.len(), 1);
         assert_eq!(
-            db_vault_by_deposit(&db_file, &outpoints[1])
+            stake_txs[0].vault_outpoint,
+            vaults[3].db_vault.deposit_outpoint
+        );
+        assert_eq!(
+            stake_txs[0].cancel.psbt,
+            *vaults[3]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_cancel
+                .as_ref()
+                .unwrap()
+        );
+        assert!(stake_txs[0].cancel.transaction.is_some());
+        assert_eq!(
+            stake_txs[0].unvault.psbt,
+            *vaults[3]
+                .transactions
+                .as_ref()
                 .unwrap()
+                .final_unvault
+                .as_ref()
                 .unwrap()
-                .status,
-            VaultStatus::Funded
+        );
+        assert!(stake_txs[0].unvault.transaction.is_some());
+        assert_eq!(
+            stake_txs[0].emergency.as_ref().unwrap().psbt,
+            *vaults[3]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_emer
+                .as_ref()
+                .unwrap()
+        );
+        assert!(stake_txs[0]
+            .emergency
+            .as_ref()
+            .unwrap()
+            .transaction
+            .is_some());
+        assert_eq!(
+            stake_txs[0].unvault_emergency.as_ref().unwrap().psbt,
+            *vaults[3]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_unvault_emer
+                .as_ref()
+                .unwrap()
+        );
+        assert!(stake_txs[0]
+            .unvault_emergency
+            .as_ref()
+            .unwrap()
+            .transaction
+            .is_some());
+
+        // The manager has the same txs, but no emergency
+        let man_txs = presigned_txs(&man_revaultd, vec![vaults[3].db_vault.clone()]).unwrap();
+        assert_eq!(man_txs.len(), 1);
+        assert_eq!(
+            man_txs[0].vault_outpoint,
+            vaults[3].db_vault.deposit_outpoint
+        );
+        assert_eq!(
+            man_txs[0].cancel.psbt,
+            *vaults[3]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_cancel
+                .as_ref()
+                .unwrap()
+        );
+        assert!(man_txs[0].cancel.transaction.is_some());
+        assert_eq!(
+            man_txs[0].unvault.psbt,
+            *vaults[3]
+                .transactions
+                .as_ref()
+                .unwrap()
+                .final_unvault
+                .as_ref()
+                .unwrap()
+        );
+        assert!(man_txs[0].unvault.transaction.is_some());
+        assert!(man_txs[0].emergency.is_none());
+        assert!(man_txs[0].unvault_emergency.is_none());
+
+        fs::remove_dir_all(&datadir_stk).unwrap_or_else(|_| ());
+        fs::remove_dir_all(&datadir_man).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_presigned_txs_estimates() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let vaults = create_vaults(&revaultd);
+
+        // vault[1] has no final tx, vault[3] has all of them
+        let unsigned = presigned_txs(&revaultd, vec![vaults[1].db_vault.clone()])
+            .unwrap()
+            .remove(0);
+        let signed = presigned_txs(&revaultd, vec![vaults[3].db_vault.clone()])
+            .unwrap()
+            .remove(0);
+
+        fn check_estimates<T: RevaultTransaction>(tx: &VaultPresignedTransaction<T>) {
+            let estimates = tx.estimates;
+            assert_eq!(estimates.vsize, (estimates.weight + 3) / 4);
+            assert_eq!(estimates.feerate, estimates.fees / estimates.vsize);
+            assert_eq!(estimates.fees, tx.psbt.fees());
+
+            // The estimate is for the maximum satisfaction, so it must never be below the
+            // actual weight of the fully-signed transaction and must be within 1% of it.
+            if let Some(ref transaction) = tx.transaction {
+                let actual = transaction.get_weight() as u64;
+                assert!(estimates.weight >= actual);
+                assert!((estimates.weight - actual) * 100 <= actual);
+            }
+        }
+
+        check_estimates(&signed.unvault);
+        check_estimates(&signed.cancel);
+        check_estimates(signed.emergency.as_ref().unwrap());
+        check_estimates(signed.unvault_emergency.as_ref().unwrap());
+        assert!(signed.unvault.transaction.is_some());
+        assert!(signed.cancel.transaction.is_some());
+
+        // Estimates don't depend on whether the transaction is signed yet
+        check_estimates(&unsigned.unvault);
+        check_estimates(&unsigned.cancel);
+        check_estimates(unsigned.emergency.as_ref().unwrap());
+        check_estimates(unsigned.unvault_emergency.as_ref().unwrap());
+        assert!(unsigned.unvault.transaction.is_none());
+
+        // Only the Unvault has a CPFP output
+        assert!(signed.cancel.estimates.cpfp_value.is_none());
+        assert!(signed.emergency.unwrap().estimates.cpfp_value.is_none());
+        assert!(signed
+            .unvault_emergency
+            .unwrap()
+            .estimates
+            .cpfp_value
+            .is_none());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_emergency_broadcast() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
+        setup_db(&mut revaultd).unwrap();
+        let db_file = revaultd.db_file();
+        let vaults = create_vaults(&revaultd);
+
+        // Let's calculate all our emer, unemer txs
+        // vault[2]
+        let mut emer2 = vaults[2]
+            .transactions
+            .as_ref()
+            .unwrap()
+            .final_emer
+            .clone()
+            .unwrap();
+        emer2.finalize(&revaultd.secp_ctx).unwrap();
+        let emer2 = emer2.into_psbt().extract_tx();
+        let mut unvault_emer2 = vaults[2]
+            .transactions
+            .as_ref()
+            .unwrap()
+            .final_unvault_emer
+            .clone()
+            .unwrap();
+        unvault_emer2.finalize(&revaultd.secp_ctx).unwrap();
+        let unvault_emer2 = unvault_emer2.into_psbt().extract_tx();
+
+        // vault[3]
+        let mut emer3 = vaults[3]
+            .transactions
+            .as_ref()
+            .unwrap()
+            .final_emer
+            .clone()
+            .unwrap();
+        emer3.finalize(&revaultd.secp_ctx).unwrap();
+        let emer3 = emer3.into_psbt().extract_tx();
+        let mut unvault_emer3 = vaults[3]
+            .transactions
+            .as_ref()
+            .unwrap()
+            .final_unvault_emer
+            .clone()
+            .unwrap();
+        unvault_emer3.finalize(&revaultd.secp_ctx).unwrap();
+        let unvault_emer3 = unvault_emer3.into_psbt().extract_tx();
+
+        let outcomes = |entries: Vec<EmergencyEntry>| -> Vec<EmergencyOutcome> {
+            assert_eq!(
+                entries
+                    .iter()
+                    .map(|e| e.deposit_outpoint)
+                    .collect::<Vec<_>>(),
+                vaults
+                    .iter()
+                    .map(|v| v.db_vault.deposit_outpoint)
+                    .collect::<Vec<_>>()
+            );
+            entries.into_iter().map(|e| e.outcome).collect()
+        };
+
+        // The Unconfirmed vault is skipped, the Funded one doesn't have its Emergency signed
+        // yet, bitcoind rejects the Emergency of the Active one.
+        let bitcoind_conn = MockBitcoindThread::new(HashMap::new())
+            .rejecting(emer3.txid(), "min relay fee not met");
+        let entries = emergency_broadcast(&revaultd, &bitcoind_conn).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.transaction).collect::<Vec<_>>(),
+            vec![
+                None,
+                Some(EmergencyTxKind::Emergency),
+                Some(EmergencyTxKind::Emergency),
+                Some(EmergencyTxKind::Emergency)
+            ]
+        );
+        assert_eq!(
+            outcomes(entries),
+            vec![
+                EmergencyOutcome::SkippedStatus {
+                    status: VaultStatus::Unconfirmed
+                },
+                EmergencyOutcome::MissingSignatures,
+                EmergencyOutcome::Broadcast { txid: emer2.txid() },
+                EmergencyOutcome::Rejected {
+                    txid: emer3.txid(),
+                    reason: "min relay fee not met".to_string()
+                },
+            ]
+        );
+        let flags = db_vault_flags(&db_file, vaults[3].db_vault.id).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].kind, VaultFlagKind::EmergencyRejected);
+        assert!(flags[0].cleared_at.is_none());
+        // The run is recorded, to be notified as a whole
+        let runs = db_unnotified_emergency_runs(&db_file).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].outcomes.len(), 4);
+        assert_eq!(
+            runs[0].outcomes[3],
+            DbEmergencyOutcome {
+                vault_id: vaults[3].db_vault.id,
+                kind: EmergencyOutcomeKind::Rejected,
+                txid: Some(emer3.txid()),
+                detail: Some("min relay fee not met".to_string()),
+            }
+        );
+
+        // Running it again only retries the vaults it failed for
+        let bitcoind_conn = MockBitcoindThread::new(HashMap::new());
+        assert_eq!(
+            outcomes(emergency_broadcast(&revaultd, &bitcoind_conn).unwrap()),
+            vec![
+                EmergencyOutcome::SkippedStatus {
+                    status: VaultStatus::Unconfirmed
+                },
+                EmergencyOutcome::MissingSignatures,
+                EmergencyOutcome::AlreadyBroadcast { txid: emer2.txid() },
+                EmergencyOutcome::Broadcast { txid: emer3.txid() },
+            ]
+        );
+        let flags = db_vault_flags(&db_file, vaults[3].db_vault.id).unwrap();
+        assert!(flags[0].cleared_at.is_some());
+        assert_eq!(db_unnotified_emergency_runs(&db_file).unwrap().len(), 2);
+
+        // Let's upgraude vault[2] to Unvaulted...
+        // (we can, as we're manually touching the db, even if we don't even have the fully signed
+        // unvault!)
+        let unvault_txid = vaults[2]
+            .transactions
+            .as_ref()
+            .unwrap()
+            .initial_unvault
+            .psbt()
+            .global
+            .unsigned_tx
+            .txid();
+        db_unvault_deposit(&db_file, &unvault_txid, 1_600_000_000).unwrap();
+        db_confirm_unvault(&db_file, &unvault_txid, 102, 1_600_000_000).unwrap();
+        // Its Unvault Emergency gets broadcast then, the Active one still has its Emergency
+        let entries = emergency_broadcast(&revaultd, &bitcoind_conn).unwrap();
+        assert_eq!(
+            entries[2..]
+                .iter()
+                .map(|e| e.transaction)
+                .collect::<Vec<_>>(),
+            vec![
+                Some(EmergencyTxKind::UnvaultEmergency),
+                Some(EmergencyTxKind::Emergency)
+            ]
+        );
+        assert_eq!(
+            outcomes(entries)[2..].to_vec(),
+            vec![
+                EmergencyOutcome::Broadcast {
+                    txid: unvault_emer2.txid()
+                },
+                EmergencyOutcome::AlreadyBroadcast { txid: emer3.txid() },
+            ]
+        );
+
+        // Once on its way to the EDV, whoever broadcast it, we tell which transaction did
+        db_emer_unvault(&db_file, &unvault_txid, 1_600_000_000).unwrap();
+        let entries = emergency_broadcast(&revaultd, &bitcoind_conn).unwrap();
+        assert_eq!(
+            entries[2].transaction,
+            Some(EmergencyTxKind::UnvaultEmergency)
+        );
+        assert_eq!(
+            outcomes(entries)[2],
+            EmergencyOutcome::SkippedStatus {
+                status: VaultStatus::UnvaultEmergencyVaulting
+            }
         );
 
-        // Second vault: Secured
-        db_confirm_deposit(
+        // And once it's confirmed it's reported in the history as such
+        db_mark_emergencied_unvault(
             &db_file,
-            &outpoints[2],
-            9, // blockheight
-            9, // blocktime
-            &transactions[2].as_ref().unwrap().initial_unvault,
-            &transactions[2].as_ref().unwrap().initial_cancel,
-            Some(&transactions[2].as_ref().unwrap().initial_emer),
-            Some(&transactions[2].as_ref().unwrap().initial_unvault_emer),
+            vaults[2].db_vault.id,
+            1_600_000_000,
+            1_600_000_000,
         )
         .unwrap();
-
-        let vaults: Vec<_> = outpoints
-            .iter()
-            .map(|o| db_vault_by_deposit(&db_file, &o).unwrap().unwrap())
-            .collect();
-
-        let tx_db = db_cancel_transaction(&db_file, vaults[2].id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
-            &db_file,
-            &vaults[2],
-            tx_db,
-            &transactions[2]
-                .as_ref()
-                .unwrap()
-                .final_cancel
-                .as_ref()
-                .unwrap()
-                .psbt()
-                .inputs[0]
-                .partial_sigs,
-            &revaultd.secp_ctx,
+        let mut wallet_txs = HashMap::new();
+        wallet_txs.insert(
+            unvault_emer2.txid(),
+            WalletTransaction {
+                hex: encode::serialize_hex(&unvault_emer2),
+                received_time: 1_600_000_000,
+                blocktime: Some(1_600_000_000),
+                blockheight: Some(103),
+            },
         );
-
-        let tx_db = db_emer_transaction(&db_file, vaults[2].id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
-            &db_file,
-            &vaults[2],
-            tx_db,
-            &transactions[2]
-                .as_ref()
-                .unwrap()
-                .final_emer
-                .as_ref()
+        let bitcoind_conn = MockBitcoindThread::new(wallet_txs);
+        let kinds = [HistoryEventKind::Emergency];
+        assert!(
+            gethistory(&revaultd, &bitcoind_conn, 0, u32::MAX, 10, &kinds)
                 .unwrap()
-                .psbt()
-                .inputs[0]
-                .partial_sigs,
-            &revaultd.secp_ctx,
+                .is_empty()
+        );
+        let kinds = [HistoryEventKind::UnvaultEmergency];
+        let events = gethistory(&revaultd, &bitcoind_conn, 0, u32::MAX, 10, &kinds).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, HistoryEventKind::UnvaultEmergency);
+        assert_eq!(events[0].txid, unvault_emer2.txid());
+        assert_eq!(events[0].blockheight, 103);
+        assert_eq!(events[0].vaults, vec![vaults[2].db_vault.deposit_outpoint]);
+        assert_eq!(
+            events[0].amount.unwrap().as_sat() + events[0].fee.unwrap().as_sat(),
+            vaults[2].db_vault.amount.as_sat()
         );
 
-        let tx_db = db_unvault_emer_transaction(&db_file, vaults[2].id)
+        // Let's upgrade vault[3] to Unvaulted too: it's its Unvault Emergency we broadcast now
+        let unvault_txid = vaults[3]
+            .transactions
+            .as_ref()
             .unwrap()
-            .unwrap();
-        update_presigned_tx(
-            &db_file,
-            &vaults[2],
-            tx_db,
-            &transactions[2]
-                .as_ref()
-                .unwrap()
-                .final_unvault_emer
-                .as_ref()
-                .unwrap()
-                .psbt()
-                .inputs[0]
-                .partial_sigs,
-            &revaultd.secp_ctx,
+            .initial_unvault
+            .psbt()
+            .global
+            .unsigned_tx
+            .txid();
+        db_unvault_deposit(&db_file, &unvault_txid, 1_600_000_000).unwrap();
+        db_confirm_unvault(&db_file, &unvault_txid, 104, 1_600_000_000).unwrap();
+        let entries = emergency_broadcast(&revaultd, &bitcoind_conn).unwrap();
+        assert_eq!(
+            entries[3].transaction,
+            Some(EmergencyTxKind::UnvaultEmergency)
         );
-        db_exec(&db_file, |tx| {
-            tx.execute(
-                "UPDATE vaults SET status = (?1), secured_at = strftime('%s','now') \
-             WHERE vaults.id = (?2)",
-                params![VaultStatus::Secured as u32, vaults[2].id,],
-            )
-            .unwrap();
-            Ok(())
-        })
-        .unwrap();
         assert_eq!(
-            db_vault_by_deposit(&db_file, &outpoints[2])
-                .unwrap()
-                .unwrap()
-                .status,
-            VaultStatus::Secured
+            outcomes(entries)[3],
+            EmergencyOutcome::Broadcast {
+                txid: unvault_emer3.txid()
+            }
         );
 
-        // Third vault: Active
-        db_confirm_deposit(
+        fs::remove_dir_all(&datadir).unwrap();
+    }
+
+    #[test]
+    fn test_gethistory() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let db_file = revaultd.db_file();
+
+        let cancel_tx_hex = "0200000000010158a2f36c1be4e32f59d26930f64c452c81ffb1fc66bb96e8ddb477f0bacfb6660000000000fdffffff011042f4050000000022002025bfbde3ae8bd9381b9ddb837acdab48a3110cbeb05e84cb57b28d77e27794480747304402201b4c8061cb2fb8c086fdedba3787cd30bc765b0f4ba47b22e3b37fe5289ded4602204da79a4637ad85c4d17f9a840c4b50336b7072f5ca482507d9d1070ecf7f105e812103d7c8e6ff708e052a15e4c23f16c7143c2b0c98473a7fd6c1d10850a274319a5a483045022100860539abb4a1172625fcdb913759c54364063a28d707c22204d18ec5b4ffeede0220442e1560b506da56cefd4e4d603c3381c036de777e264f923fe6d81e8d6870cb8121034c8ecf7547552e8c3ca3e0d1189f09b23b92bce3c73186800e7089230cecf6810000ed5121027cf27be4980b5945b1fb4594c0a9cef39278d8a8d59ad0f001acf72a45d17fea21029091080c1f00962c6ef10d0f5346d5aecab6f65543670aa78ae21520b8a3cd2221027fa470475563f20c96b997224fe86bc9564ccad9adb77e083ccd3bdfc94fe9ed53ae6476a914927ea7d35721b017ffed4941ec764b36f730faa288ac6b76a91416b43735b6c445fa5f279c2afa70bfaf375f075388ac6c93528767522102abe475b199ec3d62fa576faee16a334fdb86ffb26dce75becebaaedf328ac3fe21030f64b922aee2fd597f104bc6cb3b670f1ca2c6c49b1071a1a6c010575d94fe5a52af0112b26800000000";
+        let spend_tx_hex = "0200000001b4243a48b54cc360e754e0175a985a49b67cf4615d8523ec5aa46d42421cdf7d0000000000504200000280b2010000000000220020b9be8f8574f8da64bb1cb6668f6134bc4706df7936eeab8411f9d82de20a895b08280954020000000000000000";
+
+        let cancel_tx: BitcoinTransaction =
+            encode::deserialize(&Vec::from_hex(cancel_tx_hex).unwrap()).unwrap();
+
+        let spend_tx: BitcoinTransaction =
+            encode::deserialize(&Vec::from_hex(spend_tx_hex).unwrap()).unwrap();
+
+        let deposit1_outpoint = OutPoint::new(
+            Txid::from_str("fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b")
+                .unwrap(),
+            0,
+        );
+
+        let deposit2_outpoint = OutPoint::new(
+            Txid::from_str("cafa9f92be48ba41f9ee67e775b6c4afebd1bdbde5758792e9f30f6dea41e7fb")
+                .unwrap(),
+            1,
+        );
+
+        insert_vault_in_db(
             &db_file,
-            &vaults[3].deposit_outpoint,
-            9, // blockheight
-            9, // blocktime
-            &transactions[3].as_ref().unwrap().initial_unvault,
-            &transactions[3].as_ref().unwrap().initial_cancel,
-            Some(&transactions[3].as_ref().unwrap().initial_emer),
-            Some(&transactions[3].as_ref().unwrap().initial_unvault_emer),
-        )
-        .unwrap();
+            1,
+            &deposit1_outpoint,
+            &Amount::ONE_BTC,
+            1,
+            DerivationIndex::new(0).unwrap(),
+            Some(1),
+            Some(2),
+            VaultStatus::Canceled,
+            Some(&cancel_tx.txid()),
+        );
 
-        let tx_db = db_cancel_transaction(&db_file, vaults[3].id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
+        insert_vault_in_db(
             &db_file,
-            &vaults[3],
-            tx_db,
-            &transactions[3]
-                .as_ref()
-                .unwrap()
-                .final_cancel
-                .as_ref()
-                .unwrap()
-                .psbt()
-                .inputs[0]
-                .partial_sigs,
-            &revaultd.secp_ctx,
+            1,
+            &OutPoint::new(cancel_tx.txid(), 0),
+            &Amount::from_sat(cancel_tx.output[0].value),
+            2,
+            DerivationIndex::new(1).unwrap(),
+            Some(2),
+            None,
+            VaultStatus::Funded,
+            None,
         );
 
-        let tx_db = db_emer_transaction(&db_file, vaults[3].id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
+        insert_vault_in_db(
             &db_file,
-            &vaults[3],
-            tx_db,
-            &transactions[3]
-                .as_ref()
-                .unwrap()
-                .final_emer
-                .as_ref()
-                .unwrap()
-                .psbt()
-                .inputs[0]
-                .partial_sigs,
-            &revaultd.secp_ctx,
+            1,
+            &deposit2_outpoint,
+            &Amount::from_sat(200_000_000_000),
+            1,
+            DerivationIndex::new(0).unwrap(),
+            Some(3),
+            Some(4),
+            VaultStatus::Spent,
+            Some(&spend_tx.txid()),
         );
 
-        let tx_db = db_unvault_emer_transaction(&db_file, vaults[3].id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
+        // change of the spend
+        insert_vault_in_db(
             &db_file,
-            &vaults[3],
-            tx_db,
-            &transactions[3]
-                .as_ref()
-                .unwrap()
-                .final_unvault_emer
-                .as_ref()
-                .unwrap()
-                .psbt()
-                .inputs[0]
-                .partial_sigs,
-            &revaultd.secp_ctx,
+            1,
+            &OutPoint::new(spend_tx.txid(), 0),
+            &Amount::from_sat(spend_tx.output[0].value),
+            2,
+            DerivationIndex::new(1).unwrap(),
+            Some(4),
+            None,
+            VaultStatus::Funded,
+            None,
+        );
+
+        let mut txs = HashMap::new();
+        txs.insert(
+            cancel_tx.txid(),
+            WalletTransaction {
+                hex: cancel_tx_hex.to_string(),
+                received_time: 2,
+                blocktime: Some(2),
+                blockheight: Some(2),
+            },
+        );
+        txs.insert(
+            spend_tx.txid(),
+            WalletTransaction {
+                hex: spend_tx_hex.to_string(),
+                received_time: 4,
+                blocktime: Some(4),
+                blockheight: Some(4),
+            },
+        );
+        let bitcoind_conn = MockBitcoindThread::new(txs);
+
+        let events = gethistory(
+            &revaultd,
+            &bitcoind_conn,
+            0,
+            4,
+            20,
+            &[
+                HistoryEventKind::Deposit,
+                HistoryEventKind::Cancel,
+                HistoryEventKind::Spend,
+            ],
+        )
+        .unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].txid, spend_tx.txid());
+        assert_eq!(events[0].kind, HistoryEventKind::Spend);
+        assert_eq!(events[0].date, 4);
+        assert_eq!(
+            events[0].amount.unwrap().as_sat(),
+            spend_tx.output[0].value + spend_tx.output[1].value
+        );
+        assert_eq!(
+            events[0].fee.unwrap().as_sat(),
+            200_000_000_000 - spend_tx.output[0].value - spend_tx.output[1].value,
         );
+        assert_eq!(events[0].vaults, vec![deposit2_outpoint]);
 
-        let tx_db = db_unvault_transaction(&db_file, vaults[3].id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
-            &db_file,
-            &vaults[3],
-            tx_db,
-            &transactions[3]
-                .as_ref()
-                .unwrap()
-                .final_unvault
-                .as_ref()
-                .unwrap()
-                .psbt()
-                .inputs[0]
-                .partial_sigs,
-            &revaultd.secp_ctx,
+        assert_eq!(events[1].txid, deposit2_outpoint.txid);
+        assert_eq!(events[1].kind, HistoryEventKind::Deposit);
+        assert_eq!(events[1].date, 3);
+        assert_eq!(events[1].fee, None);
+        assert_eq!(events[1].amount.unwrap().as_sat(), 200_000_000_000);
+        assert_eq!(events[1].vaults, vec![deposit2_outpoint]);
+
+        assert_eq!(events[2].txid, cancel_tx.txid());
+        assert_eq!(events[2].kind, HistoryEventKind::Cancel);
+        assert!(events[2].amount.is_none());
+        assert_eq!(events[2].date, 2);
+        assert_eq!(
+            events[2].fee.unwrap().as_sat(),
+            Amount::ONE_BTC.as_sat() - cancel_tx.output[0].value
         );
-        db_exec(&db_file, |tx| {
-            tx.execute(
-                "UPDATE vaults SET status = (?1), secured_at = strftime('%s','now'), delegated_at = strftime('%s','now') \
-             WHERE vaults.id = (?2)",
-                params![VaultStatus::Active as u32, vaults[3].id],
-            )
-            .unwrap();
-            Ok(())
-        })
-        .unwrap();
+        assert_eq!(events[3].txid, deposit1_outpoint.txid);
+        assert_eq!(events[3].kind, HistoryEventKind::Deposit);
+        assert_eq!(events[3].vaults, vec![deposit1_outpoint]);
 
+        // retrieve events later in history
+        let events = gethistory(
+            &revaultd,
+            &bitcoind_conn,
+            0,
+            2,
+            20,
+            &[
+                HistoryEventKind::Deposit,
+                HistoryEventKind::Cancel,
+                HistoryEventKind::Spend,
+            ],
+        )
+        .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].date, 2);
+        assert_eq!(events[0].txid, cancel_tx.txid());
+        assert_eq!(events[0].kind, HistoryEventKind::Cancel);
+        assert!(events[0].amount.is_none());
         assert_eq!(
-            db_vault_by_deposit(&db_file, &outpoints[3])
-                .unwrap()
-                .unwrap()
-                .status,
-            VaultStatus::Active
+            events[0].fee.unwrap().as_sat(),
+            Amount::ONE_BTC.as_sat() - cancel_tx.output[0].value
         );
+        assert_eq!(events[0].vaults, vec![deposit1_outpoint]);
 
-        vaults
-            .into_iter()
-            .zip(transactions)
-            .map(|(v, txs)| TestVault {
-                db_vault: db_vault_by_deposit(&db_file, &v.deposit_outpoint)
-                    .unwrap()
-                    .unwrap(),
-                transactions: txs,
-            })
-            .collect()
+        assert_eq!(events[1].date, 1);
+        assert_eq!(events[1].txid, deposit1_outpoint.txid);
+        assert_eq!(events[1].kind, HistoryEventKind::Deposit);
+        assert_eq!(events[1].fee, None);
+        assert_eq!(events[1].amount.unwrap().as_sat(), Amount::ONE_BTC.as_sat());
+        assert_eq!(events[1].vaults, vec![deposit1_outpoint]);
+
+        let json = serde_json::to_value(&events).unwrap();
+        assert_no_float(&json);
+        assert_eq!(json[1]["amount"].as_u64(), Some(Amount::ONE_BTC.as_sat()));
+        assert!(json[0]["amount"].is_null());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
 
     #[test]
-    fn test_listvaults_from_db() {
-        let datadir = test_datadir();
-        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
-        setup_db(&mut revaultd).unwrap();
-        let vaults = create_vaults(&revaultd);
-
-        // Checking that the result is sane
-        for v in &vaults {
-            let res = &listvaults_from_db(
-                &revaultd,
-                Some(&[v.db_vault.status]),
-                Some(&[v.db_vault.deposit_outpoint]),
-            )
-            .unwrap()[0];
-            assert_eq!(res.amount, v.db_vault.amount);
-            assert_eq!(res.blockheight, v.db_vault.blockheight);
-            assert_eq!(res.status, v.db_vault.status);
-            assert_eq!(res.txid, v.db_vault.deposit_outpoint.txid);
-            assert_eq!(res.vout, v.db_vault.deposit_outpoint.vout);
-            assert_eq!(
-                res.derivation_index,
-                ChildNumber::from_normal_idx(0).unwrap()
-            );
+    fn test_participants() {
+        // Our own secrets and bitcoind's credentials must never be part of the output
+        fn check_no_secret(revaultd: &RevaultD, json: &str) {
+            assert!(!json.contains(&revaultd.noise_secret.0.to_hex()));
+            assert!(!json.contains(revaultd.bitcoind_config.cookie_path.to_str().unwrap()));
         }
 
-        // Checking that filters work
-        assert_eq!(listvaults_from_db(&revaultd, None, None).unwrap().len(), 4);
+        let stk_xpub = "xpub6EKrK11LwLcNyJ4arJnCtxPGAuxSYPX35fMfJcmadvTSue6YZn2W9kEUHy7PFyQsy7zkrbmhxtevsgwsfyCiRBayJdWSTohRQua43jMw9FQ";
+        let man_xpub = "xpub6De9kLSb2vvujzLqBbQvcnfaNfCGv8vBKNikWsvu3yDL6CpdNEE5CKH9J6TpT6ARFsiAdTpH7iJdA8tgAwNeo46FH9CSTv6CURBJSCPAeUu";
+        let coordinator_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402";
+
+        // Stakeholder only: no cosigner
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
+        let res = participants(&revaultd);
+        let json = serde_json::to_string(&res).unwrap();
+        check_no_secret(&revaultd, &json);
+        let res: ListParticipantsResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(res.stakeholders.len(), 2);
+        assert_eq!(res.managers.len(), 1);
+        assert_eq!(res.managers_threshold, 1);
+        assert!(res.cosigners.is_empty());
+        let ours: Vec<&ParticipantEntry> = res.stakeholders.iter().filter(|p| p.ours).collect();
+        assert_eq!(ours.len(), 1);
+        assert_eq!(ours[0].xpub, stk_xpub);
+        assert_eq!(ours[0].fingerprint.len(), 8);
+        assert!(res.managers.iter().all(|p| !p.ours));
+        assert_eq!(res.coordinator.host, "127.0.0.1:1");
+        assert_eq!(res.coordinator.noise_key, coordinator_key);
+        assert_eq!(res.coordinator.noise_key_fingerprint, "d915:6397:3102:4541");
+        assert!(res.backup_coordinators.is_empty());
+        assert!(res.stakeholders.iter().all(|p| p.label.is_none()));
+
+        // Participants we were given a label for are displayed with it
+        let fingerprint = xpub_fingerprint_from_str(&ours[0].fingerprint).unwrap();
+        revaultd.key_labels.insert(fingerprint, "Alice".to_string());
+        let res = participants(&revaultd);
+        let ours: Vec<&ParticipantEntry> = res.stakeholders.iter().filter(|p| p.ours).collect();
+        assert_eq!(ours[0].label, Some("Alice".to_string()));
+        assert!(res
+            .stakeholders
+            .iter()
+            .filter(|p| !p.ours)
+            .all(|p| p.label.is_none()));
         assert_eq!(
-            listvaults_from_db(&revaultd, Some(&[VaultStatus::Unconfirmed]), None)
-                .unwrap()
-                .len(),
-            1
+            revaultd.participant(fingerprint).to_string(),
+            format!("Alice ({})", ours[0].fingerprint)
         );
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+
+        // Manager only: the cosigner is there, but can't be reached
+        let datadir = test_datadir();
+        let revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
+        let json = serde_json::to_string(&participants(&revaultd)).unwrap();
+        check_no_secret(&revaultd, &json);
+        let res: ListParticipantsResult = serde_json::from_str(&json).unwrap();
+        assert!(res.stakeholders.iter().all(|p| !p.ours));
+        assert_eq!(res.managers.len(), 1);
+        assert!(res.managers[0].ours);
+        assert_eq!(res.managers[0].xpub, man_xpub);
+        assert_eq!(res.cosigners.len(), 1);
+        assert_eq!(res.cosigners[0].host, "127.0.0.1:1");
         assert_eq!(
-            listvaults_from_db(
-                &revaultd,
-                Some(&[VaultStatus::Unconfirmed]),
-                Some(&[vaults[1].db_vault.deposit_outpoint])
-            )
-            .unwrap()
-            .len(),
-            0
+            res.cosigners[0].noise_key,
+            "087629614d227ff2b9ed5f2ce2eb7cd527d2d18f866b24009647251fce58de38"
         );
+        assert!(!res.cosigners[0].reachable);
+        assert_eq!(res.cosigners[0].label, None);
         assert_eq!(
-            listvaults_from_db(
-                &revaultd,
-                None,
-                Some(&[
-                    vaults[0].db_vault.deposit_outpoint,
-                    vaults[1].db_vault.deposit_outpoint
-                ])
-            )
-            .unwrap()
-            .len(),
-            2
+            serde_json::to_string(&cosigners_entries(&revaultd)).unwrap(),
+            serde_json::to_string(&res.cosigners).unwrap()
         );
-        assert_eq!(
-            listvaults_from_db(
-                &revaultd,
-                Some(&[
-                    VaultStatus::Unconfirmed,
-                    VaultStatus::Funded,
-                    VaultStatus::Secured
-                ]),
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+
+        // Both
+        let datadir = test_datadir();
+        let revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let json = serde_json::to_string(&participants(&revaultd)).unwrap();
+        check_no_secret(&revaultd, &json);
+        let res: ListParticipantsResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(res.stakeholders.iter().filter(|p| p.ours).count(), 1);
+        assert_eq!(res.managers.iter().filter(|p| p.ours).count(), 1);
+        assert_eq!(res.cosigners.len(), 1);
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_spend_tx_deterministic() {
+        use revault_tx::txouts::RevaultTxOut;
+
+        let datadir = test_datadir();
+        let revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
+
+        let mut txins = vec![
+            (
+                OutPoint::from_str(
+                    "fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b:1",
+                )
+                .unwrap(),
+                Amount::from_sat(200_000_000),
+                ChildNumber::from(2),
+            ),
+            (
+                OutPoint::from_str(
+                    "fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b:0",
+                )
+                .unwrap(),
+                Amount::from_sat(150_000_000),
+                ChildNumber::from(5),
+            ),
+            (
+                OutPoint::from_str(
+                    "1a6ba3f56c6fca6dc1bdfc7dd4cd4a9ac9c4de65c5ed8e8ff45f36a7c26c98e0:3",
+                )
+                .unwrap(),
+                Amount::from_sat(100_000_000),
+                ChildNumber::from(0),
+            ),
+        ];
+        let destinations = vec![
+            (
+                revaultd
+                    .vault_address(DerivationIndex::new(10).unwrap())
+                    .unwrap(),
+                120_000_000,
+            ),
+            (
+                revaultd
+                    .vault_address(DerivationIndex::new(11).unwrap())
+                    .unwrap(),
+                80_000_000,
+            ),
+            (
+                revaultd
+                    .unvault_address(DerivationIndex::new(12).unwrap())
+                    .unwrap(),
+                120_000_000,
+            ),
+            (
+                revaultd
+                    .cpfp_address(DerivationIndex::new(13).unwrap())
+                    .unwrap(),
+                50_000_000,
+            ),
+        ]
+        .into_iter()
+        .map(|(addr, sats)| (addr, RpcAmount::from_sat(sats)))
+        .collect::<Vec<_>>();
+
+        let build_spend = |mut txins: Vec<(OutPoint, Amount, ChildNumber)>,
+                           destinations: HashMap<Address, RpcAmount>| {
+            sort_spend_txins(&mut txins);
+            let txouts = spend_txouts(&destinations);
+            revault_tx::transactions::spend_tx_from_deposits(
+                txins,
+                txouts,
                 None,
+                &revaultd.deposit_descriptor,
+                &revaultd.unvault_descriptor,
+                &revaultd.cpfp_descriptor,
+                revaultd.lock_time,
+                false,
+                &revaultd.secp_ctx,
             )
             .unwrap()
-            .len(),
-            3
+            .as_psbt_string()
+        };
+
+        let first = build_spend(txins.clone(), destinations.iter().cloned().collect());
+        txins.reverse();
+        txins.swap(0, 1);
+        let second = build_spend(txins, destinations.iter().rev().cloned().collect());
+        assert_eq!(first, second);
+
+        // Outputs are BIP69-sorted
+        let txouts = spend_txouts(&destinations.iter().cloned().collect::<HashMap<_, _>>());
+        for pair in txouts.windows(2) {
+            let (a, b) = (pair[0].txout(), pair[1].txout());
+            assert!(
+                a.value < b.value
+                    || (a.value == b.value
+                        && a.script_pubkey.as_bytes() < b.script_pubkey.as_bytes())
+            );
+        }
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    // A stakeholder giving us a signature made with another stakeholder's signing device is
+    // told so, with the stakeholders identified by their label.
+    #[test]
+    fn test_invalid_signature_diagnostic() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
+        let secp = secp256k1::Secp256k1::new();
+
+        // Two stakeholders we have the keys of, we are Alice
+        let xprivs: Vec<ExtendedPrivKey> = (1..3)
+            .map(|i| ExtendedPrivKey::new_master(Network::Bitcoin, &[i; 32]).unwrap())
+            .collect();
+        let xpubs: Vec<ExtendedPubKey> = xprivs
+            .iter()
+            .map(|xpriv| ExtendedPubKey::from_private(&secp, xpriv))
+            .collect();
+        revaultd.deposit_descriptor =
+            DepositDescriptor::from_str(&format!("wsh(multi(2,{}/*,{}/*))", xpubs[0], xpubs[1]))
+                .unwrap();
+        revaultd.our_stk_xpub = Some(xpubs[0]);
+        revaultd
+            .key_labels
+            .insert(xpubs[0].fingerprint(), "Alice".to_string());
+        revaultd
+            .key_labels
+            .insert(xpubs[1].fingerprint(), "Bob".to_string());
+        let alice = format!("Alice ({})", xpubs[0].fingerprint());
+        let bob = format!("Bob ({})", xpubs[1].fingerprint());
+
+        let index = DerivationIndex::new(3).unwrap();
+        let keys = revaultd.stakeholders_xpubs_at(index);
+        let privkeys: Vec<secp256k1::SecretKey> = xprivs
+            .iter()
+            .map(|xpriv| {
+                xpriv
+                    .derive_priv(&secp, &[index.into()])
+                    .unwrap()
+                    .private_key
+                    .key
+            })
+            .collect();
+        let cancel = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAASDOvhSZlTSEcEoUq/CT7Cg3ILtc6sqt5qJKvAMq+LbIAAAAAAD9////AXYfpDUAAAAAIgAg9AncsIZc8g7mJdfT9infAeWlqjtxBs93ireDGnQn/DYAAAAAAAEBK7hhpDUAAAAAIgAgFZlOQkpDkFSsLUfyeMGVAOT3T88jZM7L/XlVZoJ2jnABAwSBAAAAAQWpIQMVlEoh50lasMhcdwnrmnCp2ROlGY5CrH+HtxQmfZDZ06xRh2R2qRS/INUX1CaP7Pbn5GmtGYu2wgqjnIisa3apFO/kceq8yo9w69g4VVtlFAf739qTiKxsk1KHZ1IhAnddfXi3N38A+aEQ74sUdeuV7sg+2L3ijTjMHMEAfq3cIQLWP96FqjfC5qKQkC2WhYbbLJx1FbNSAjsnMfwDnK0jD1KvARKyaCIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBR1IhA47+JRqdt+oloFosla9hWUYVf5YQKDbuq4KO13JS45KgIQMKcLWzABxb/9YBQe+bJRW3v3om8S2LNMGUKSp5K+PQ+1KuIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let sighash = secp256k1::Message::from_slice(
+            &cancel
+                .signature_hash(0, SigHashType::AllPlusAnyoneCanPay)
+                .unwrap(),
+        )
+        .unwrap();
+        let tx = RevaultTx::Cancel(cancel);
+        let bob_sig = secp.sign(&sighash, &privkeys[1]);
+
+        // Bob's signature given for Alice's key
+        assert!(tx
+            .clone()
+            .add_signature(keys[0].key, bob_sig, &secp)
+            .is_err());
+        let msg = invalid_signature_diagnostic(&revaultd, &tx, index, &keys[0], bob_sig);
+        assert!(
+            msg.contains(&format!("for stakeholder {}", alice)),
+            "{}",
+            msg
         );
-        assert_eq!(
-            listvaults_from_db(
-                &revaultd,
-                Some(&[
-                    VaultStatus::Unconfirmed,
-                    VaultStatus::Funded,
-                    VaultStatus::Secured,
-                    VaultStatus::Active,
-                ]),
-                None,
-            )
+        assert!(msg.contains(&keys[0].to_string()), "{}", msg);
+        assert!(
+            msg.contains(&format!("valid for stakeholder {}'s key", bob)),
+            "{}",
+            msg
+        );
+        assert!(msg.contains("wrong signing device"), "{}", msg);
+
+        // A signature that's valid for nobody
+        let garbage_sig = secp.sign(
+            &secp256k1::Message::from_slice(&[1; 32]).unwrap(),
+            &privkeys[1],
+        );
+        let msg = invalid_signature_diagnostic(&revaultd, &tx, index, &keys[0], garbage_sig);
+        assert!(
+            msg.contains("not valid for any stakeholder's key"),
+            "{}",
+            msg
+        );
+        assert!(!msg.contains("wrong signing device"), "{}", msg);
+
+        // A PSBT signed with Bob's device only
+        let mut raw_sig = bob_sig.serialize_der().to_vec();
+        raw_sig.push(SigHashType::AllPlusAnyoneCanPay.as_u32() as u8);
+        let mut sigs = BTreeMap::new();
+        sigs.insert(keys[1], raw_sig);
+        let msg = missing_our_signature_diagnostic(&revaultd, "Cancel", index, &sigs);
+        assert!(
+            msg.contains(&format!("for ourselves ({}", alice)),
+            "{}",
+            msg
+        );
+        assert!(
+            msg.contains(&format!("only for stakeholder(s) {}", bob)),
+            "{}",
+            msg
+        );
+        let msg = missing_our_signature_diagnostic(&revaultd, "Cancel", index, &BTreeMap::new());
+        assert!(!msg.contains("wrong signing device"), "{}", msg);
+
+        // Without labels, stakeholders are identified by their fingerprint only
+        revaultd.key_labels.clear();
+        let msg = invalid_signature_diagnostic(&revaultd, &tx, index, &keys[0], bob_sig);
+        assert!(
+            msg.contains(&format!(
+                "valid for stakeholder {}'s key",
+                xpubs[1].fingerprint()
+            )),
+            "{}",
+            msg
+        );
+
+        // The signing progress of each stakeholder
+        let mut signed_tx = tx.clone();
+        signed_tx
+            .add_signature(keys[1].key, bob_sig, &secp)
+            .unwrap();
+        revaultd
+            .key_labels
+            .insert(xpubs[1].fingerprint(), "Bob".to_string());
+        let signers = presigned_tx_signers(&revaultd, index, signed_tx.unwrap_cancel());
+        assert_eq!(signers.len(), 2);
+        assert_eq!(signers[0].fingerprint, xpubs[0].fingerprint().to_string());
+        assert_eq!(signers[0].label, None);
+        assert!(!signers[0].signed);
+        assert_eq!(signers[1].label, Some("Bob".to_string()));
+        assert!(signers[1].signed);
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_check_spend_fees() {
+        let spent = Amount::from_sat(10_000_000);
+        let max_fees = Amount::from_sat(1_000_000);
+
+        // Up to the percentage of the value spent, included
+        check_spend_fees(Amount::from_sat(500_000), spent, 5, max_fees).unwrap();
+        match check_spend_fees(Amount::from_sat(500_001), spent, 5, max_fees) {
+            Err(CommandError::SpendFeesTooHigh(fees, s, threshold)) => {
+                assert_eq!(fees, Amount::from_sat(500_001));
+                assert_eq!(s, spent);
+                assert_eq!(threshold, Amount::from_sat(500_000));
+            }
+            e => panic!("Unexpected result: {:?}", e),
+        }
+        check_spend_fees(Amount::from_sat(0), spent, 0, max_fees).unwrap();
+        check_spend_fees(Amount::from_sat(1), spent, 0, max_fees).unwrap_err();
+
+        // But never above the absolute ceiling
+        check_spend_fees(Amount::from_sat(1_000_000), spent, 50, max_fees).unwrap();
+        match check_spend_fees(Amount::from_sat(1_000_001), spent, 50, max_fees) {
+            Err(CommandError::SpendFeesTooHigh(_, _, threshold)) => {
+                assert_eq!(threshold, max_fees)
+            }
+            e => panic!("Unexpected result: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_cpfp_reserve() {
+        // 1_201 WU is 301 vbytes, which at 10 sat/vbyte is 3_010 sats of fees
+        assert_eq!(cpfp_reserve(1_201, 1_000, 10), Amount::from_sat(2_010));
+        assert_eq!(cpfp_reserve(1_200, 1_000, 10), Amount::from_sat(2_000));
+        assert_eq!(cpfp_reserve(1_200, 2_999, 10), Amount::from_sat(1));
+        // Nothing to add if it already pays enough
+        assert_eq!(cpfp_reserve(1_200, 3_000, 10), Amount::from_sat(0));
+        assert_eq!(cpfp_reserve(1_200, 50_000, 10), Amount::from_sat(0));
+        // A typical Unvault transaction (about 2 kWU) at a panic feerate
+        assert_eq!(cpfp_reserve(2_011, 2_265, 500), Amount::from_sat(249_235));
+
+        // Over all the 'unvaulting' vaults, and the 'active' ones if asked to
+        let datadir = test_datadir();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Manager);
+        let vaults = {
+            let mut revaultd = control.revaultd.write().unwrap();
+            setup_db(&mut revaultd).unwrap();
+            create_vaults(&revaultd)
+        };
+        let db_path = control.revaultd.read().unwrap().db_file();
+        let reserve = control.get_cpfp_reserve(100, false).unwrap();
+        assert!(reserve.vaults.is_empty());
+        assert_eq!(reserve.total, RpcAmount::from_sat(0));
+        // We don't have a CPFP key
+        assert!(reserve.available.is_none());
+
+        let unvault_tx = db_unvault_transaction(&db_path, vaults[3].db_vault.id)
             .unwrap()
-            .len(),
-            4
+            .unwrap()
+            .psbt
+            .assert_unvault();
+        let expected = cpfp_reserve(unvault_tx.max_weight(), unvault_tx.fees(), 100);
+        assert!(expected > Amount::from_sat(0));
+        let reserve = control.get_cpfp_reserve(100, true).unwrap();
+        assert_eq!(reserve.vaults.len(), 1);
+        assert_eq!(reserve.vaults[0].status, VaultStatus::Active);
+        assert_eq!(reserve.vaults[0].unvault_txid, unvault_tx.txid());
+        assert_eq!(reserve.vaults[0].reserve, RpcAmount::from(expected));
+        assert_eq!(reserve.total, RpcAmount::from(expected));
+
+        crate::database::actions::db_unvault_deposit(&db_path, &unvault_tx.txid(), 1_600_000_000)
+            .unwrap();
+        let reserve = control.get_cpfp_reserve(100, false).unwrap();
+        assert_eq!(reserve.vaults.len(), 1);
+        assert_eq!(reserve.vaults[0].status, VaultStatus::Unvaulting);
+        assert_eq!(reserve.total, RpcAmount::from(expected));
+        // It scales with the feerate
+        let reserve = control.get_cpfp_reserve(200, false).unwrap();
+        assert_eq!(
+            reserve.total,
+            RpcAmount::from(cpfp_reserve(
+                unvault_tx.max_weight(),
+                unvault_tx.fees(),
+                200
+            ))
         );
 
+        // Only for managers
+        let datadir_stk = test_datadir();
+        let stk_control = dummy_rpcutil(datadir_stk.clone(), UserRole::Stakeholder);
+        setup_db(&mut stk_control.revaultd.write().unwrap()).unwrap();
+        assert!(matches!(
+            stk_control.get_cpfp_reserve(100, false),
+            Err(CommandError::ManagerOnly)
+        ));
+
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+        fs::remove_dir_all(&datadir_stk).unwrap_or_else(|_| ());
     }
 
     #[test]
-    fn test_vaults_from_deposits() {
+    fn test_check_disk_space() {
         let datadir = test_datadir();
-        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
-        let db_file = revaultd.db_file();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
+        revaultd.disk_space_warning = 1_000_000;
+        revaultd.disk_space_critical = 100_000;
+        let fs_stats = Arc::new(MockFsStats::new(10_000_000));
+        revaultd.fs_stats = fs_stats.clone();
+
+        check_disk_space(&revaultd).unwrap();
+        // Only a warning when it's low
+        fs_stats.set_available(999_999);
+        check_disk_space(&revaultd).unwrap();
+        fs_stats.set_available(100_000);
+        check_disk_space(&revaultd).unwrap();
+
+        fs_stats.set_available(99_999);
+        match check_disk_space(&revaultd) {
+            Err(CommandError::DiskSpaceCritical(available, threshold)) => {
+                assert_eq!(available, 99_999);
+                assert_eq!(threshold, 100_000);
+            }
+            e => panic!("Unexpected result: {:?}", e),
+        }
+
+        // Back to normal once some space was freed
+        fs_stats.set_available(200_000);
+        check_disk_space(&revaultd).unwrap();
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_idempotent_commands() {
+        let datadir = test_datadir();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Stakeholder);
+        let clock = Arc::new(MockClock::new(1_600_000_000));
+        {
+            let mut revaultd = control.revaultd.write().unwrap();
+            setup_db(&mut revaultd).unwrap();
+            revaultd.clock = clock.clone();
+        }
+        let params = serde_json::json!([
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1"
+        ]);
+        let executions = Cell::new(0);
+        let command = |_: &DaemonControl| {
+            executions.set(executions.get() + 1);
+            Ok(serde_json::json!({ "execution": executions.get() }))
+        };
+
+        // Without a key, the command is always executed
+        control
+            .idempotent(None, "revault", &params, &command)
+            .unwrap();
+        control
+            .idempotent(None, "revault", &params, &command)
+            .unwrap();
+        assert_eq!(executions.get(), 2);
+
+        // With a key, a retry returns the first result
+        let first = control
+            .idempotent(Some("key"), "revault", &params, &command)
+            .unwrap();
+        assert_eq!(first, serde_json::json!({ "execution": 3 }));
+        let retry = control
+            .idempotent(Some("key"), "revault", &params, &command)
+            .unwrap();
+        assert_eq!(retry, first);
+        assert_eq!(executions.get(), 3);
+
+        // The key can't be reused for another command or other parameters
+        match control.idempotent(Some("key"), "emergency", &params, &command) {
+            Err(CommandError::IdempotencyKeyConflict(key)) => assert_eq!(key, "key"),
+            e => panic!("Unexpected result: {:?}", e),
+        }
+        match control.idempotent(Some("key"), "revault", &serde_json::json!([]), &command) {
+            Err(CommandError::IdempotencyKeyConflict(key)) => assert_eq!(key, "key"),
+            e => panic!("Unexpected result: {:?}", e),
+        }
+        assert_eq!(executions.get(), 3);
+
+        // A retry while the command is still running doesn't execute it twice
+        control
+            .idempotent(Some("slow"), "revault", &params, |control| {
+                match control.idempotent(Some("slow"), "revault", &params, &command) {
+                    Err(CommandError::IdempotencyKeyInProgress(key)) => assert_eq!(key, "slow"),
+                    e => panic!("Unexpected result: {:?}", e),
+                }
+                Ok(serde_json::json!({}))
+            })
+            .unwrap();
+        assert_eq!(executions.get(), 3);
+
+        // If the command failed, it can be retried with the same key
+        control
+            .idempotent(Some("failing"), "revault", &params, |_| {
+                Err(CommandError::Race)
+            })
+            .unwrap_err();
+        control
+            .idempotent(Some("failing"), "revault", &params, &command)
+            .unwrap();
+        assert_eq!(executions.get(), 4);
+
+        // Once expired, the key is forgotten and the command executed again
+        clock.advance(Duration::from_secs(24 * 3600));
+        control
+            .idempotent(Some("key"), "revault", &params, &command)
+            .unwrap();
+        assert_eq!(executions.get(), 4);
+        clock.advance(Duration::from_secs(1));
+        let after_expiry = control
+            .idempotent(Some("key"), "revault", &params, &command)
+            .unwrap();
+        assert_eq!(after_expiry, serde_json::json!({ "execution": 5 }));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    // A cosigning server is only polled once for a given Spend transaction, even if another
+    // one failed and we need to retry.
+    #[test]
+    fn test_fetch_cosigs_signatures_cache() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
         setup_db(&mut revaultd).unwrap();
-        let vaults = create_vaults(&revaultd);
-        let outpoints: Vec<_> = vaults.iter().map(|v| v.db_vault.deposit_outpoint).collect();
+        let db_path = revaultd.db_file();
 
-        assert_eq!(
-            vaults_from_deposits(&db_file, &outpoints, &vec![],)
-                .unwrap()
-                .len(),
-            4
-        );
-        assert_eq!(
-            vaults_from_deposits(&db_file, &outpoints[1..], &vec![VaultStatus::Unconfirmed],)
-                .unwrap()
-                .len(),
-            3
-        );
+        let spend = SpendTransaction::from_psbt_str("cHNidP8BAP0ZAQIAAAADSe9QbkOAlLapVzhNT2J2sCWXMqe2x7g7klEr3N6+p8AAAAAAAAYAAABwwCBKiamcBn5fj0oQ3WcAU+twE4XemcK4G2nlprqBKAAAAAAABgAAAAwCYIUh0y2bkIH8BVZ/evuFulOCxOyGr/rvZnR2k/9aAAAAAAAGAAAABFCoAAAAAAAAIgAgvXwvxBU2X03+pufsytFJ2dS4BKVXIKMQmyxUXTbPJPmA8PoCAAAAABYAFCMDXGnefAb487YxeNjnpbUzH+pEQEtMAAAAAAAWABT+rq2LTo+bnAo3ZQoeUg0F6xVZbIx3EwIAAAAAIgAgfAYV/vqzwEWHS6kVMjA1yQRbIQqq//o7m4ik0eSSlasAAAAAAAEBKzb0VQMAAAAAIgAgEyIAQqFnv+D0rMmVvusK3TC6fPyFk7aU1PZ8+Ttm23IBAwQBAAAAAQXBUiEDNWoO4CCloCul5eCLd1+XLPxP4LMIsUy+XM01wlm59wIhAqQ3tGeAeMBPPR26fn0kuL0CS0AybrDlu8NwIzFOOukzIQJoBBIwDWTXwjMse2MiB8/kIcFOZACiADcmZltiEl85N1OuZHapFIe9/DRONZOp5OAQ6RCrIDclCDEjiKxrdqkUJs2E27SQYhbh4yxNkO+lDnFqCCaIrGyTa3apFBtcD9uL3TRJt1uCIj2J8Ub4YjvgiKxsk1OHZ1ayaCIGAmgEEjANZNfCMyx7YyIHz+QhwU5kAKIANyZmW2ISXzk3CO9FHBcBAAAAIgYCpDe0Z4B4wE89Hbp+fSS4vQJLQDJusOW7w3AjMU466TMIW+FtfgEAAAAiBgM1ag7gIKWgK6Xl4It3X5cs/E/gswixTL5czTXCWbn3AgjDFaC/AQAAAAABASs2rG0BAAAAACIAIICUwlAfLlUkhU44Hpkj/LEDNAdwME4fm3jtWfXwMwL7AQMEAQAAAAEFwVIhAgSNQIWSNnYSrfEl8juzTKw9o3BjYQ+DgbyizShqKzIcIQN+tRtybpIxVK9IdwxsTxFgy2YsiQqtnGvnowXelPblJiEC25bXunBKDpmrAvXiBbJ/+x9Oo5pL+8FhKgAqXSesn0VTrmR2qRTWWGTXm1UxE4rqqD2FkiKS94r8YYisa3apFCBven2wd5QCFoHAl/iRHg+9SJkgiKxsk2t2qRRP/mE3OesTO6kSJOgsBAoyLTfO8oisbJNTh2dWsmgiBgIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHAjDFaC/AAAAACIGAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FCO9FHBcAAAAAIgYDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYIW+FtfgAAAAAAAQErtgyYAAAAAAAiACCAlMJQHy5VJIVOOB6ZI/yxAzQHcDBOH5t47Vn18DMC+wEDBAEAAAABBcFSIQIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHCEDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYhAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FU65kdqkU1lhk15tVMROK6qg9hZIikveK/GGIrGt2qRQgb3p9sHeUAhaBwJf4kR4PvUiZIIisbJNrdqkUT/5hNznrEzupEiToLAQKMi03zvKIrGyTU4dnVrJoIgYCBI1AhZI2dhKt8SXyO7NMrD2jcGNhD4OBvKLNKGorMhwIwxWgvwAAAAAiBgLblte6cEoOmasC9eIFsn/7H06jmkv7wWEqACpdJ6yfRQjvRRwXAAAAACIGA361G3JukjFUr0h3DGxPEWDLZiyJCq2ca+ejBd6U9uUmCFvhbX4AAAAAACICArFlfWaPsqMsvdC+/3Hise+ubUHtj4n5Uz7qaI0bCfCWCBhRVloBAAAAIgICtg6ewcvt4XnF35qT+j9KoCYt4+vS8hXmOn1NsO/QppUIgryGbgEAAAAiAgOJmnB0i/XOb8ITGRA3itrYfvWx6/B8PGMiu2SYfOACFQhTR/BbAQAAAAAAACICAr+BTfGuO1VRPxE1DJoFIsH1Vu5Dk5lSullVQjCXjVlICEPuDksBAAAAIgIC+G7/TA9DNgnMf4Nup2Py3XAF8UCLmziV3Vw4Z2KsJcwIpbzhFQEAAAAiAgOpos5KhVRQaTPJTi3mk12g5sApoQNVGdOpMcMmn7C7gwieIH0+AQAAAAA=").unwrap();
+        let spend_txid = spend.txid();
+        db_insert_spend(&db_path, &[], &spend).unwrap();
 
-        // Returning an error if one of the vaults has an invalid status
-        assert!(
-            vaults_from_deposits(&db_file, &outpoints, &vec![VaultStatus::Unconfirmed])
-                .unwrap_err()
-                .to_string()
-                .contains(
-                    &CommandError::InvalidStatusFor(VaultStatus::Unconfirmed, outpoints[0])
-                        .to_string()
-                )
-        );
+        // The first server signs, the second one refuses to.
+        let secp = secp256k1::Secp256k1::new();
+        let privkey = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&secp, &privkey);
+        let sighash =
+            secp256k1::Message::from_slice(&spend.signature_hash(0, SigHashType::All).unwrap())
+                .unwrap();
+        let mut signed_spend = spend.clone();
+        signed_spend
+            .add_signature(0, pubkey, secp.sign(&sighash, &privkey), &secp)
+            .unwrap();
+        let (client_pubkey, client_privkey) = gen_keypair();
+        revaultd.noise_secret = client_privkey;
+        let (signer_addr, signer_key, signer_requests) =
+            stub_cosigner(client_pubkey, Duration::from_millis(10), Some(signed_spend));
+        let (refuser_addr, refuser_key, refuser_requests) =
+            stub_cosigner(client_pubkey, Duration::from_millis(10), None);
+        revaultd.cosigs = Some(vec![
+            (signer_addr.into(), signer_key),
+            (refuser_addr.into(), refuser_key),
+        ]);
 
-        // Returning an error if the outpoint is unknown
-        let wrong_outpoint = OutPoint::new(
-            Txid::from_str("abababababababababababababababababababababababababababababababab")
-                .unwrap(),
-            2,
-        );
-        assert!(
-            vaults_from_deposits(&db_file, &vec![wrong_outpoint], &vec![],)
-                .unwrap_err()
-                .to_string()
-                .contains(&CommandError::UnknownOutpoint(wrong_outpoint).to_string())
-        );
+        let mut spend_tx = spend.clone();
+        assert!(matches!(
+            fetch_cosigs_signatures(&revaultd, &mut spend_tx),
+            Err(CommunicationError::CosigAlreadySigned)
+        ));
+        assert_eq!(signer_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(refuser_requests.load(Ordering::SeqCst), 1);
+        let cached = db_cosig_signatures(&db_path, &spend_txid).unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].cosig_noise_key, signer_key.0);
+        let cosigners = spend_cosigners(&revaultd, &spend_txid);
+        assert!(cosigners[0].signed);
+        assert!(!cosigners[1].signed);
+
+        // On retry we use the signatures we already have and only poll the other server.
+        let mut spend_tx = spend.clone();
+        assert!(matches!(
+            fetch_cosigs_signatures(&revaultd, &mut spend_tx),
+            Err(CommunicationError::CosigAlreadySigned)
+        ));
+        assert_eq!(signer_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(refuser_requests.load(Ordering::SeqCst), 2);
+        assert_eq!(spend_tx.psbt().inputs[0].partial_sigs.len(), 1);
+
+        // Once the second server is gone, we don't poll anyone anymore.
+        revaultd.cosigs = Some(vec![(signer_addr.into(), signer_key)]);
+        let mut spend_tx = spend.clone();
+        fetch_cosigs_signatures(&revaultd, &mut spend_tx).unwrap();
+        assert_eq!(signer_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(spend_tx.psbt().inputs[0].partial_sigs.len(), 1);
+
+        // The cached signatures are dropped along with the Spend
+        db_delete_spend(&db_path, &spend_txid).unwrap();
+        assert!(db_cosig_signatures(&db_path, &spend_txid)
+            .unwrap()
+            .is_empty());
 
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
 
     #[test]
-    fn test_presigned_txs() {
-        let datadir_stk = test_datadir();
-        let datadir_man = test_datadir();
-        let mut stake_revaultd = dummy_revaultd(datadir_stk.clone(), UserRole::Stakeholder);
-        let mut man_revaultd = dummy_revaultd(datadir_man.clone(), UserRole::Manager);
-        setup_db(&mut stake_revaultd).unwrap();
-        let vaults = create_vaults(&stake_revaultd);
-        setup_db(&mut man_revaultd).unwrap();
-        let _ = create_vaults(&man_revaultd);
+    fn test_spend_expiry() {
+        let datadir = test_datadir();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Manager);
+        let clock = Arc::new(MockClock::new(1_600_000_000));
+        let db_path = {
+            let mut revaultd = control.revaultd.write().unwrap();
+            setup_db(&mut revaultd).unwrap();
+            revaultd.clock = clock.clone();
+            revaultd.spend_expiry = Duration::from_secs(3600);
+            revaultd.db_file()
+        };
+        let vaults = create_vaults(&control.revaultd.read().unwrap());
+        let active_vault = &vaults[3].db_vault;
+        let db_unvault = db_unvault_transaction(&db_path, active_vault.id)
+            .unwrap()
+            .unwrap();
+        let spend = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAciTbKS43sH49TJWX6xJ+MxqWfNQhRl+vkttRZ9sLUkHAAAAAAClAQAAAoAyAAAAAAAAIgAggxumgjPgMj5oHWn8QkvKqPIN0N5nuAbyQ+FEgOJZpjygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQBAwQBAAAAAQWqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let spend_txid = spend.txid();
 
-        // vault[0] is not confirmed, no presigned txs here!
-        assert!(presigned_txs(&stake_revaultd, vec![vaults[0].db_vault.clone()]).is_none());
+        // We can only give up on a Spend we announced
+        match control.abort_spend(&spend_txid) {
+            Err(CommandError::UnknownSpend(txid)) => assert_eq!(txid, spend_txid),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        db_insert_spend(&db_path, &[db_unvault], &spend).unwrap();
+        match control.abort_spend(&spend_txid) {
+            Err(CommandError::SpendNotAnnounced(txid)) => assert_eq!(txid, spend_txid),
+            res => panic!("Unexpected result: {:?}", res),
+        }
 
-        // vault[1] is funded, no txs is final
-        // The stakeholder has all the txs
-        let stake_txs = presigned_txs(&stake_revaultd, vec![vaults[1].db_vault.clone()]).unwrap();
-        assert_eq!(stake_txs.len(), 1);
-        assert_eq!(
-            stake_txs[0].vault_outpoint,
-            vaults[1].db_vault.deposit_outpoint
-        );
-        assert_eq!(
-            stake_txs[0].cancel.psbt,
-            vaults[1].transactions.as_ref().unwrap().initial_cancel
-        );
-        assert!(stake_txs[0].cancel.transaction.is_none());
-        assert_eq!(
-            stake_txs[0].unvault.psbt,
-            vaults[1].transactions.as_ref().unwrap().initial_unvault
-        );
-        assert!(stake_txs[0].unvault.transaction.is_none());
-        assert_eq!(
-            stake_txs[0].emergency.as_ref().unwrap().psbt,
-            vaults[1].transactions.as_ref().unwrap().initial_emer
-        );
-        assert!(stake_txs[0]
-            .emergency
-            .as_ref()
+        // It expires once it was announced more than spend_expiry ago
+        db_record_spend_announcement(&db_path, &spend_txid, &[3; 32], 1_600_000_000).unwrap();
+        clock.advance(Duration::from_secs(3599));
+        expire_stale_spends(&control.revaultd.read().unwrap()).unwrap();
+        assert!(db_spend_expiration(&db_path, &spend_txid)
             .unwrap()
-            .transaction
             .is_none());
+        clock.advance(Duration::from_secs(1));
+        expire_stale_spends(&control.revaultd.read().unwrap()).unwrap();
+        let expiration = db_spend_expiration(&db_path, &spend_txid).unwrap().unwrap();
+        assert_eq!(expiration.reason, SpendExpiryReason::Timeout);
+        assert_eq!(expiration.expired_at, 1_600_003_600);
+        // Aborting it now does nothing
+        control.abort_spend(&spend_txid).unwrap();
         assert_eq!(
-            stake_txs[0].unvault_emergency.as_ref().unwrap().psbt,
-            vaults[1]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .initial_unvault_emer
+            db_spend_expiration(&db_path, &spend_txid).unwrap(),
+            Some(expiration)
         );
-        assert!(stake_txs[0]
-            .unvault_emergency
-            .as_ref()
-            .unwrap()
-            .transaction
-            .is_none());
 
-        // The manager has the same txs, but no emergency
-        let man_txs = presigned_txs(&man_revaultd, vec![vaults[1].db_vault.clone()]).unwrap();
-        assert_eq!(man_txs.len(), 1);
-        assert_eq!(
-            man_txs[0].vault_outpoint,
-            vaults[1].db_vault.deposit_outpoint
-        );
-        assert_eq!(
-            man_txs[0].cancel.psbt,
-            vaults[1].transactions.as_ref().unwrap().initial_cancel
-        );
-        assert!(man_txs[0].cancel.transaction.is_none());
-        assert_eq!(
-            man_txs[0].unvault.psbt,
-            vaults[1].transactions.as_ref().unwrap().initial_unvault
-        );
-        assert!(man_txs[0].unvault.transaction.is_none());
-        assert!(man_txs[0].emergency.is_none());
-        assert!(man_txs[0].unvault_emergency.is_none());
+        // Once announced again, it may be aborted before it expires
+        db_record_spend_announcement(&db_path, &spend_txid, &[3; 32], 1_600_003_600).unwrap();
+        control.abort_spend(&spend_txid).unwrap();
+        let expiration = db_spend_expiration(&db_path, &spend_txid).unwrap().unwrap();
+        assert_eq!(expiration.reason, SpendExpiryReason::Aborted);
 
-        // vault[2] is secured, the unvault tx is not final
-        // The stakeholder has all the txs
-        let stake_txs = presigned_txs(&stake_revaultd, vec![vaults[2].db_vault.clone()]).unwrap();
-        assert_eq!(stake_txs.len(), 1);
-        assert_eq!(
-            stake_txs[0].vault_outpoint,
-            vaults[2].db_vault.deposit_outpoint
-        );
-        assert_eq!(
-            stake_txs[0].cancel.psbt,
-            *vaults[2]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .final_cancel
-                .as_ref()
-                .unwrap()
-        );
-        assert!(stake_txs[0].cancel.transaction.is_some());
-        assert_eq!(
-            stake_txs[0].unvault.psbt,
-            vaults[2].transactions.as_ref().unwrap().initial_unvault
-        );
-        assert!(stake_txs[0].unvault.transaction.is_none());
-        assert_eq!(
-            stake_txs[0].emergency.as_ref().unwrap().psbt,
-            *vaults[2]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .final_emer
-                .as_ref()
-                .unwrap()
-        );
-        assert!(stake_txs[0]
-            .emergency
-            .as_ref()
+        // But not after its Unvaults were broadcast, and it doesn't expire either
+        db_record_spend_announcement(&db_path, &spend_txid, &[3; 32], 1_600_003_600).unwrap();
+        db_mark_broadcastable_spend(&db_path, &spend_txid).unwrap();
+        match control.abort_spend(&spend_txid) {
+            Err(CommandError::SpendUnvaultsBroadcast(txid)) => assert_eq!(txid, spend_txid),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        clock.advance(Duration::from_secs(3600));
+        expire_stale_spends(&control.revaultd.read().unwrap()).unwrap();
+        assert!(db_spend_expiration(&db_path, &spend_txid)
             .unwrap()
-            .transaction
-            .is_some());
+            .is_none());
+
+        // Unless the broadcast of its Unvault failed
+        db_raise_vault_flag(
+            &db_path,
+            active_vault.id,
+            VaultFlagKind::UnvaultBroadcastFailed,
+            "Unvault could not be broadcast",
+            1_600_007_200,
+        )
+        .unwrap();
+        expire_stale_spends(&control.revaultd.read().unwrap()).unwrap();
         assert_eq!(
-            stake_txs[0].unvault_emergency.as_ref().unwrap().psbt,
-            *vaults[2]
-                .transactions
-                .as_ref()
+            db_spend_expiration(&db_path, &spend_txid)
                 .unwrap()
-                .final_unvault_emer
-                .as_ref()
                 .unwrap()
+                .reason,
+            SpendExpiryReason::Timeout
         );
-        assert!(stake_txs[0]
-            .unvault_emergency
-            .as_ref()
-            .unwrap()
-            .transaction
-            .is_some());
 
-        // The manager has the same txs, but no emergency
-        let man_txs = presigned_txs(&man_revaultd, vec![vaults[2].db_vault.clone()]).unwrap();
-        assert_eq!(man_txs.len(), 1);
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_check_spend_destinations() {
+        let datadir = test_datadir();
+        let revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
+        let secp = &revaultd.secp_ctx;
+
+        let deposit_script = revaultd
+            .deposit_descriptor
+            .derive(ChildNumber::from(3), secp)
+            .into_inner()
+            .script_pubkey();
+        let unvault_script = revaultd
+            .unvault_descriptor
+            .derive(ChildNumber::from(3), secp)
+            .into_inner()
+            .script_pubkey();
+        let our_scripts = vec![
+            DbDerivedScript {
+                script_pubkey: deposit_script.clone(),
+                derivation_index: DerivationIndex::new(3).unwrap(),
+                kind: ScriptKind::Deposit,
+            },
+            DbDerivedScript {
+                script_pubkey: unvault_script.clone(),
+                derivation_index: DerivationIndex::new(3).unwrap(),
+                kind: ScriptKind::Unvault,
+            },
+        ];
+        let check = |addr: &Address, allow_self_send: bool| {
+            check_spend_destinations(vec![addr], Network::Regtest, &our_scripts, allow_self_send)
+        };
+
+        // A regular destination for our network
+        let regtest_addr =
+            Address::from_str("bcrt1qewc2348370pgw8kjz8gy09z8xyh0d9fxde6nzamd3txc9gkmjqmq8m4cdq")
+                .unwrap();
+        check(&regtest_addr, false).unwrap();
+
+        // Base58 addresses are the same for testnet and regtest, not bech32 ones
+        let base58_addr = Address {
+            network: Network::Testnet,
+            payload: Payload::ScriptHash(ScriptHash::hash(&[1; 32])),
+        };
+        check(&base58_addr, false).unwrap();
+        let testnet_addr = Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+        match check(&testnet_addr, false) {
+            Err(CommandError::SpendWrongNetwork(addr, Network::Regtest)) => {
+                assert_eq!(addr, testnet_addr)
+            }
+            e => panic!("Unexpected result: {:?}", e),
+        }
+        let mainnet_addr = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        match check(&mainnet_addr, false) {
+            Err(CommandError::SpendWrongNetwork(addr, Network::Regtest)) => {
+                assert_eq!(addr, mainnet_addr)
+            }
+            e => panic!("Unexpected result: {:?}", e),
+        }
+        // Testnet addresses are fine on signet though
+        check_spend_destinations(vec![&testnet_addr], Network::Signet, &our_scripts, false)
+            .unwrap();
+
+        // All-zero hashes are refused whatever the type of address
+        for payload in vec![
+            Payload::PubkeyHash(PubkeyHash::from_inner([0; 20])),
+            Payload::ScriptHash(ScriptHash::from_inner([0; 20])),
+            Payload::WitnessProgram {
+                version: u5::try_from_u8(0).unwrap(),
+                program: vec![0; 32],
+            },
+        ] {
+            let burn_addr = Address {
+                network: Network::Regtest,
+                payload,
+            };
+            match check(&burn_addr, true) {
+                Err(CommandError::SpendBurnAddress(addr)) => assert_eq!(addr, burn_addr),
+                e => panic!("Unexpected result: {:?}", e),
+            }
+        }
+
+        // Paying to one of our deposit addresses must be explicitly allowed
+        let deposit_addr = Address::from_script(&deposit_script, Network::Regtest).unwrap();
+        match check(&deposit_addr, false) {
+            Err(CommandError::SpendSelfSend(addr)) => assert_eq!(addr, deposit_addr),
+            e => panic!("Unexpected result: {:?}", e),
+        }
+        check(&deposit_addr, true).unwrap();
+
+        // Paying to one of our Unvault addresses never is
+        let unvault_addr = Address::from_script(&unvault_script, Network::Regtest).unwrap();
+        for allow_self_send in &[false, true] {
+            match check(&unvault_addr, *allow_self_send) {
+                Err(CommandError::SpendToUnvault(addr)) => assert_eq!(addr, unvault_addr),
+                e => panic!("Unexpected result: {:?}", e),
+            }
+        }
+
+        // The first invalid destination is reported
+        match check_spend_destinations(
+            vec![&regtest_addr, &deposit_addr, &mainnet_addr],
+            Network::Regtest,
+            &our_scripts,
+            false,
+        ) {
+            Err(CommandError::SpendSelfSend(addr)) => assert_eq!(addr, deposit_addr),
+            e => panic!("Unexpected result: {:?}", e),
+        }
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_spend_proposal_status() {
+        let proposal = DbSpendProposal {
+            id: 1,
+            outpoints: vec![],
+            destinations: BTreeMap::new(),
+            feerate: 10,
+            created_at: 1_600_000_000,
+            expires_at: 1_600_003_600,
+        };
+        let ack = |i: u8, approved: bool| DbSpendProposalAck {
+            id: i as i64,
+            proposal_id: 1,
+            fingerprint: Fingerprint::from(&[i; 4][..]),
+            approved,
+            signature: vec![],
+            created_at: 1_600_000_000,
+        };
+        let now = 1_600_000_001;
+
+        // 2-of-3: pending until 2 approve, rejected once 2 reject
         assert_eq!(
-            man_txs[0].vault_outpoint,
-            vaults[2].db_vault.deposit_outpoint
+            spend_proposal_status(&proposal, &[], 2, 3, now),
+            SpendProposalStatus::Pending
         );
+        let acks = vec![ack(1, true), ack(2, false)];
         assert_eq!(
-            man_txs[0].cancel.psbt,
-            *vaults[2]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .final_cancel
-                .as_ref()
-                .unwrap()
+            spend_proposal_status(&proposal, &acks, 2, 3, now),
+            SpendProposalStatus::Pending
         );
-        assert!(man_txs[0].cancel.transaction.is_some());
+        let acks = vec![ack(1, true), ack(2, false), ack(3, true)];
         assert_eq!(
-            man_txs[0].unvault.psbt,
-            vaults[2].transactions.as_ref().unwrap().initial_unvault
+            spend_proposal_status(&proposal, &acks, 2, 3, now),
+            SpendProposalStatus::Approved
         );
-        assert!(man_txs[0].unvault.transaction.is_none());
-        assert!(man_txs[0].emergency.is_none());
-        assert!(man_txs[0].unvault_emergency.is_none());
-
-        // vault[3] is active, every tx is final
-        // The stakeholder has all the txs
-        let stake_txs = presigned_txs(&stake_revaultd, vec![vaults[3].db_vault.clone()]).unwrap();
-        assert_eq!(stake_txs.len(), 1);
+        let acks = vec![ack(1, false), ack(2, false)];
         assert_eq!(
-            stake_txs[0].vault_outpoint,
-            vaults[3].db_vault.deposit_outpoint
+            spend_proposal_status(&proposal, &acks, 2, 3, now),
+            SpendProposalStatus::Rejected
         );
+
+        // 3-of-3: a single rejection is enough
         assert_eq!(
-            stake_txs[0].cancel.psbt,
-            *vaults[3]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .final_cancel
-                .as_ref()
-                .unwrap()
+            spend_proposal_status(&proposal, &[ack(1, false)], 3, 3, now),
+            SpendProposalStatus::Rejected
         );
-        assert!(stake_txs[0].cancel.transaction.is_some());
+
+        // Past its expiration, approved or not, it can't be used
+        let acks = vec![ack(1, true), ack(2, true)];
         assert_eq!(
-            stake_txs[0].unvault.psbt,
-            *vaults[3]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .final_unvault
-                .as_ref()
-                .unwrap()
+            spend_proposal_status(&proposal, &acks, 2, 3, 1_600_003_599),
+            SpendProposalStatus::Approved
         );
-        assert!(stake_txs[0].unvault.transaction.is_some());
         assert_eq!(
-            stake_txs[0].emergency.as_ref().unwrap().psbt,
-            *vaults[3]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .final_emer
-                .as_ref()
-                .unwrap()
+            spend_proposal_status(&proposal, &acks, 2, 3, 1_600_003_600),
+            SpendProposalStatus::Expired
         );
-        assert!(stake_txs[0]
-            .emergency
-            .as_ref()
-            .unwrap()
-            .transaction
-            .is_some());
-        assert_eq!(
-            stake_txs[0].unvault_emergency.as_ref().unwrap().psbt,
-            *vaults[3]
-                .transactions
-                .as_ref()
+    }
+
+    #[test]
+    fn test_check_spend_proposal_ack() {
+        let secp = secp256k1::Secp256k1::new();
+        let verif_secp = secp256k1::Secp256k1::verification_only();
+        let xpriv = ExtendedPrivKey::new_master(Network::Bitcoin, &[1; 32]).unwrap();
+        let xpub = ExtendedPubKey::from_private(&secp, &xpriv);
+        let other_xpriv = ExtendedPrivKey::new_master(Network::Bitcoin, &[2; 32]).unwrap();
+        let digest = sha256::Hash::hash(b"proposal");
+        let sign = |xpriv: &ExtendedPrivKey, digest: &sha256::Hash| {
+            let msg = secp256k1::Message::from_slice(&digest[..]).unwrap();
+            secp.sign(&msg, &xpriv.private_key.key)
+                .serialize_der()
+                .to_vec()
+        };
+
+        check_spend_proposal_ack(&verif_secp, &xpub, &digest, &sign(&xpriv, &digest)).unwrap();
+
+        // Signed by someone else, for something else, or not a signature at all
+        for signature in &[
+            sign(&other_xpriv, &digest),
+            sign(&xpriv, &sha256::Hash::hash(b"another proposal")),
+            vec![0x30, 0x01, 0x02],
+        ] {
+            match check_spend_proposal_ack(&verif_secp, &xpub, &digest, signature) {
+                Err(CommandError::SpendProposalInvalidAck(_)) => {}
+                res => panic!("Unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_spend_proposals() {
+        let datadir = test_datadir();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Manager);
+        let clock = Arc::new(MockClock::new(1_600_000_000));
+        let secp = secp256k1::Secp256k1::new();
+
+        // Three managers we have the keys of, two of which need to approve a proposal
+        let xprivs: Vec<ExtendedPrivKey> = (1..4)
+            .map(|i| ExtendedPrivKey::new_master(Network::Bitcoin, &[i; 32]).unwrap())
+            .collect();
+        let xpubs: Vec<ExtendedPubKey> = xprivs
+            .iter()
+            .map(|xpriv| ExtendedPubKey::from_private(&secp, xpriv))
+            .collect();
+        let db_path = {
+            let mut revaultd = control.revaultd.write().unwrap();
+            let stk_xpubs = revaultd.stakeholders_xpubs();
+            revaultd.unvault_descriptor = UnvaultDescriptor::from_str(&format!(
+                "wsh(andor(thresh(2,pk({}/*),s:pk({}/*),s:pk({}/*)),and_v(v:multi(2,\
+                 0332e5c86d0938a83ed80d13c5644ec92fd16b9d7184bb35d6ead8227b4ad47803,\
+                 02807e5c4f7b228aa9f1aef77effde768356480f5268376644464e714d0205eb1c),older(6)),\
+                 thresh(2,pkh({}),a:pkh({}))))",
+                xpubs[0], xpubs[1], xpubs[2], stk_xpubs[0], stk_xpubs[1]
+            ))
+            .unwrap();
+            revaultd.our_man_xpub = Some(xpubs[0]);
+            revaultd.spend_approval_threshold = Some(2);
+            revaultd.spend_proposal_expiry = Duration::from_secs(3600);
+            revaultd.clock = clock.clone();
+            setup_db(&mut revaultd).unwrap();
+            revaultd.db_file()
+        };
+        let sign = |manager: usize, proposal_id: u32, approve: bool| {
+            let digest = db_spend_proposal(&db_path, proposal_id)
                 .unwrap()
-                .final_unvault_emer
-                .as_ref()
                 .unwrap()
+                .ack_digest(approve);
+            let msg = secp256k1::Message::from_slice(&digest[..]).unwrap();
+            secp.sign(&msg, &xprivs[manager].private_key.key)
+                .serialize_der()
+                .to_vec()
+        };
+        let fingerprint = |manager: usize| xpubs[manager].fingerprint();
+
+        // We can only propose to spend active vaults
+        let outpoints = [
+            OutPoint::from_str(
+                "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+            )
+            .unwrap(),
+            OutPoint::from_str(
+                "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+            )
+            .unwrap(),
+        ];
+        let mut destinations = BTreeMap::new();
+        destinations.insert(
+            Address::from_str("bcrt1qewc2348370pgw8kjz8gy09z8xyh0d9fxde6nzamd3txc9gkmjqmq8m4cdq")
+                .unwrap(),
+            RpcAmount::from_sat(1_000_000),
         );
-        assert!(stake_txs[0]
-            .unvault_emergency
-            .as_ref()
-            .unwrap()
-            .transaction
-            .is_some());
+        match control.propose_spend(&outpoints, &destinations, 10) {
+            Err(CommandError::UnknownOutpoint(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        for outpoint in outpoints.iter() {
+            insert_vault_in_db(
+                &db_path,
+                1,
+                outpoint,
+                &Amount::from_sat(2_000_000),
+                100,
+                DerivationIndex::new(0).unwrap(),
+                Some(1_600_000_000),
+                None,
+                VaultStatus::Active,
+                None,
+            );
+        }
+        match control.propose_spend(&[outpoints[0], outpoints[0]], &destinations, 10) {
+            Err(CommandError::InvalidParams(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let proposal_id = control
+            .propose_spend(&outpoints, &destinations, 10)
+            .unwrap();
+        let proposals = control.list_spend_proposals().unwrap();
+        assert_eq!(proposals.len(), 1);
+        assert_eq!(proposals[0].id, proposal_id);
+        assert_eq!(proposals[0].status, SpendProposalStatus::Pending);
+        assert_eq!(proposals[0].threshold, 2);
+        assert_eq!(proposals[0].expires_at, 1_600_003_600);
+        assert_eq!(proposals[0].destinations, destinations);
+        let mut sorted_outpoints = outpoints.to_vec();
+        sorted_outpoints.sort();
+        assert_eq!(proposals[0].outpoints, sorted_outpoints);
 
-        // The manager has the same txs, but no emergency
-        let man_txs = presigned_txs(&man_revaultd, vec![vaults[3].db_vault.clone()]).unwrap();
-        assert_eq!(man_txs.len(), 1);
+        // A Spend transaction can't be created until it's approved
+        match control.get_spend_tx(&outpoints, &destinations, 10, false, false, None) {
+            Err(CommandError::SpendProposalRequired) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match control.get_spend_tx(
+            &outpoints,
+            &destinations,
+            10,
+            false,
+            false,
+            Some(proposal_id),
+        ) {
+            Err(CommandError::SpendProposalNotApproved(id, SpendProposalStatus::Pending)) => {
+                assert_eq!(id, proposal_id)
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Acks must be signed by the key of the manager whose fingerprint is given, for this
+        // very decision.
+        let stranger = ExtendedPubKey::from_private(
+            &secp,
+            &ExtendedPrivKey::new_master(Network::Bitcoin, &[42; 32]).unwrap(),
+        );
+        for (fingerprint, signature) in &[
+            (fingerprint(0), sign(1, proposal_id, true)),
+            (fingerprint(0), sign(0, proposal_id, false)),
+            (stranger.fingerprint(), sign(0, proposal_id, true)),
+            (fingerprint(0), vec![0xde, 0xad]),
+        ] {
+            match control.decide_spend_proposal(proposal_id, fingerprint, signature, true) {
+                Err(CommandError::SpendProposalInvalidAck(_)) => {}
+                res => panic!("Unexpected result: {:?}", res),
+            }
+        }
+        assert!(control.list_spend_proposals().unwrap()[0].acks.is_empty());
+        match control.decide_spend_proposal(42, &fingerprint(0), &sign(0, proposal_id, true), true)
+        {
+            Err(CommandError::UnknownSpendProposal(42)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // One approval and one rejection out of three managers, the last one decides
         assert_eq!(
-            man_txs[0].vault_outpoint,
-            vaults[3].db_vault.deposit_outpoint
+            control
+                .decide_spend_proposal(
+                    proposal_id,
+                    &fingerprint(0),
+                    &sign(0, proposal_id, true),
+                    true
+                )
+                .unwrap(),
+            SpendProposalStatus::Pending
         );
+        match control.decide_spend_proposal(
+            proposal_id,
+            &fingerprint(0),
+            &sign(0, proposal_id, false),
+            false,
+        ) {
+            Err(CommandError::SpendProposalAlreadyDecided(id, fg)) => {
+                assert_eq!((id, fg), (proposal_id, fingerprint(0)))
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
         assert_eq!(
-            man_txs[0].cancel.psbt,
-            *vaults[3]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .final_cancel
-                .as_ref()
-                .unwrap()
+            control
+                .decide_spend_proposal(
+                    proposal_id,
+                    &fingerprint(1),
+                    &sign(1, proposal_id, false),
+                    false
+                )
+                .unwrap(),
+            SpendProposalStatus::Pending
         );
-        assert!(man_txs[0].cancel.transaction.is_some());
         assert_eq!(
-            man_txs[0].unvault.psbt,
-            *vaults[3]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .final_unvault
-                .as_ref()
-                .unwrap()
+            control
+                .decide_spend_proposal(
+                    proposal_id,
+                    &fingerprint(2),
+                    &sign(2, proposal_id, true),
+                    true
+                )
+                .unwrap(),
+            SpendProposalStatus::Approved
+        );
+        let proposal = control.list_spend_proposals().unwrap().remove(0);
+        assert_eq!(proposal.status, SpendProposalStatus::Approved);
+        assert_eq!(
+            proposal
+                .acks
+                .iter()
+                .map(|ack| (ack.fingerprint.clone(), ack.approved))
+                .collect::<Vec<_>>(),
+            vec![
+                (fingerprint(0).to_string(), true),
+                (fingerprint(1).to_string(), false),
+                (fingerprint(2).to_string(), true)
+            ]
         );
-        assert!(man_txs[0].unvault.transaction.is_some());
-        assert!(man_txs[0].emergency.is_none());
-        assert!(man_txs[0].unvault_emergency.is_none());
 
-        fs::remove_dir_all(&datadir_stk).unwrap_or_else(|_| ());
-        fs::remove_dir_all(&datadir_man).unwrap_or_else(|_| ());
+        // It's only valid for these very parameters
+        match control.get_spend_tx(
+            &outpoints,
+            &destinations,
+            11,
+            false,
+            false,
+            Some(proposal_id),
+        ) {
+            Err(CommandError::SpendProposalMismatch(id)) => assert_eq!(id, proposal_id),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match control.get_spend_tx(
+            &outpoints[..1],
+            &destinations,
+            10,
+            false,
+            false,
+            Some(proposal_id),
+        ) {
+            Err(CommandError::SpendProposalMismatch(id)) => assert_eq!(id, proposal_id),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Two rejections make another proposal impossible to approve
+        let rejected_id = control
+            .propose_spend(&outpoints[..1], &destinations, 10)
+            .unwrap();
+        for manager in 1..3 {
+            control
+                .decide_spend_proposal(
+                    rejected_id,
+                    &fingerprint(manager),
+                    &sign(manager, rejected_id, false),
+                    false,
+                )
+                .unwrap();
+        }
+        match control.get_spend_tx(
+            &outpoints[..1],
+            &destinations,
+            10,
+            false,
+            false,
+            Some(rejected_id),
+        ) {
+            Err(CommandError::SpendProposalNotApproved(_, SpendProposalStatus::Rejected)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Once they expired, proposals can't be decided upon nor used anymore
+        let expiring_id = control
+            .propose_spend(&outpoints[1..], &destinations, 10)
+            .unwrap();
+        clock.advance(Duration::from_secs(3600));
+        assert!(control
+            .list_spend_proposals()
+            .unwrap()
+            .iter()
+            .all(|proposal| proposal.status == SpendProposalStatus::Expired));
+        match control.decide_spend_proposal(
+            expiring_id,
+            &fingerprint(0),
+            &sign(0, expiring_id, true),
+            true,
+        ) {
+            Err(CommandError::SpendProposalExpired(id)) => assert_eq!(id, expiring_id),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match control.get_spend_tx(
+            &outpoints,
+            &destinations,
+            10,
+            false,
+            false,
+            Some(proposal_id),
+        ) {
+            Err(CommandError::SpendProposalExpired(id)) => assert_eq!(id, proposal_id),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
 
     #[test]
-    fn test_finalized_emer_txs() {
+    fn test_reused_destinations() {
         let datadir = test_datadir();
-        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
-        setup_db(&mut revaultd).unwrap();
-        let db_file = revaultd.db_file();
-        let vaults = create_vaults(&revaultd);
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Manager);
+        let db_path = {
+            let mut revaultd = control.revaultd.write().unwrap();
+            setup_db(&mut revaultd).unwrap();
+            revaultd.db_file()
+        };
+        let outpoints = [OutPoint::from_str(
+            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+        )
+        .unwrap()];
+        let paid_addr = Address::from_str("bcrt1qqqqsyqcyq5rqwzqfpg9scrgwpugpzysnard0ew").unwrap();
+        let other_addr =
+            Address::from_str("bcrt1qewc2348370pgw8kjz8gy09z8xyh0d9fxde6nzamd3txc9gkmjqmq8m4cdq")
+                .unwrap();
+        let mut destinations = BTreeMap::new();
+        destinations.insert(paid_addr.clone(), RpcAmount::from_sat(1_000_000));
+        destinations.insert(other_addr.clone(), RpcAmount::from_sat(1_000_000));
 
-        // Let's calculate all our emer, unemer txs
-        // vault[2]
-        let mut emer2 = vaults[2]
-            .transactions
-            .as_ref()
-            .unwrap()
-            .final_emer
-            .clone()
-            .unwrap();
-        emer2.finalize(&revaultd.secp_ctx).unwrap();
-        let emer2 = emer2.into_psbt().extract_tx();
-        let mut unvault_emer2 = vaults[2]
-            .transactions
-            .as_ref()
+        // Nothing was paid to yet
+        assert!(control
+            .reused_spend_destinations(&destinations)
             .unwrap()
-            .final_unvault_emer
-            .clone()
-            .unwrap();
-        unvault_emer2.finalize(&revaultd.secp_ctx).unwrap();
-        let unvault_emer2 = unvault_emer2.into_psbt().extract_tx();
+            .is_empty());
 
-        // vault[3]
-        let mut emer3 = vaults[3]
-            .transactions
-            .as_ref()
-            .unwrap()
-            .final_emer
-            .clone()
-            .unwrap();
-        emer3.finalize(&revaultd.secp_ctx).unwrap();
-        let emer3 = emer3.into_psbt().extract_tx();
-        let mut unvault_emer3 = vaults[3]
-            .transactions
-            .as_ref()
-            .unwrap()
-            .final_unvault_emer
-            .clone()
-            .unwrap();
-        unvault_emer3.finalize(&revaultd.secp_ctx).unwrap();
-        let unvault_emer3 = unvault_emer3.into_psbt().extract_tx();
+        // A confirmed Spend paid to one of them
+        db_insert_spend_destinations(
+            &db_path,
+            &[paid_addr.script_pubkey()],
+            &Txid::from_str("c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7")
+                .unwrap(),
+            1_600_000_000,
+        )
+        .unwrap();
+        assert_eq!(
+            control.reused_spend_destinations(&destinations).unwrap(),
+            vec![paid_addr.clone()]
+        );
 
-        // I will get all the emergency txs, as I don't have >= unvaulting vaults
-        let txs = finalized_emer_txs(&revaultd).unwrap();
-        // One secured vault and one active vault
-        assert_eq!(txs.len(), 2);
-        assert!(txs.contains(&emer2));
-        assert!(txs.contains(&emer3));
+        // In strict mode, we won't even create the Spend
+        control.revaultd.write().unwrap().forbid_destination_reuse = true;
+        match control.get_spend_tx(&outpoints, &destinations, 10, false, false, None) {
+            Err(CommandError::SpendReusedDestinations(addrs)) => assert_eq!(addrs, vec![paid_addr]),
+            res => panic!("Unexpected result: {:?}", res),
+        }
 
-        // Let's upgraude vault[2] to Unvaulted...
-        // (we can, as we're manually touching the db, even if we don't even have the fully signed
-        // unvault!)
-        db_confirm_unvault(
-            &db_file,
-            &vaults[2]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .initial_unvault
-                .psbt()
-                .global
-                .unsigned_tx
-                .txid(),
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_spending_schedule() {
+        let datadir = test_datadir();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Manager);
+        // Friday 2021-01-08 17:59 UTC
+        let clock = Arc::new(MockClock::new(1_610_128_740));
+        {
+            let mut revaultd = control.revaultd.write().unwrap();
+            setup_db(&mut revaultd).unwrap();
+            revaultd.clock = clock.clone();
+            revaultd.spending_schedule = Some(
+                toml::from_str(
+                    r#"
+                    timezone = "UTC"
+                    [[windows]]
+                    days = ["mon", "tue", "wed", "thu", "fri"]
+                    start = "09:00"
+                    end = "18:00"
+                    "#,
+                )
+                .unwrap(),
+            );
+        }
+        let spend_txid =
+            Txid::from_str("c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7")
+                .unwrap();
+
+        // Within business hours, the schedule doesn't get in the way
+        match control.set_spend_tx(&spend_txid, false, false) {
+            Err(CommandError::UnknownSpend(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Outside, it does and tells us when we may try again: on Monday morning
+        clock.advance(Duration::from_secs(60));
+        match control.set_spend_tx(&spend_txid, false, false) {
+            Err(CommandError::OutsideSpendingSchedule(next)) => {
+                assert_eq!(next, Some(1_610_355_600))
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        // Unless it's overridden
+        match control.set_spend_tx(&spend_txid, false, true) {
+            Err(CommandError::UnknownSpend(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Monday morning, we can go
+        clock.advance(Duration::from_secs(1_610_355_600 - 1_610_128_800));
+        match control.set_spend_tx(&spend_txid, false, false) {
+            Err(CommandError::UnknownSpend(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_get_vault_details() {
+        let datadir_stk = test_datadir();
+        let datadir_man = test_datadir();
+        let stk_control = dummy_rpcutil(datadir_stk.clone(), UserRole::Stakeholder);
+        let man_control = dummy_rpcutil(datadir_man.clone(), UserRole::Manager);
+        let outpoint = OutPoint::from_str(
+            "fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b:1",
         )
         .unwrap();
-        // I will get one emer and one unvault_emer
-        let txs = finalized_emer_txs(&revaultd).unwrap();
-        assert_eq!(txs.len(), 2);
-        assert!(txs.contains(&unvault_emer2));
-        assert!(txs.contains(&emer3));
-
-        // Let's upgraude vault[3] to Unvaulted...
-        db_confirm_unvault(
-            &db_file,
-            &vaults[3]
-                .transactions
-                .as_ref()
-                .unwrap()
-                .initial_unvault
-                .psbt()
-                .global
-                .unsigned_tx
-                .txid(),
+        let index = DerivationIndex::new(7).unwrap();
+        for control in &[&stk_control, &man_control] {
+            let mut revaultd = control.revaultd.write().unwrap();
+            setup_db(&mut revaultd).unwrap();
+            insert_vault_in_db(
+                &revaultd.db_file(),
+                1,
+                &outpoint,
+                &Amount::ONE_BTC,
+                1,
+                index,
+                None,
+                None,
+                VaultStatus::Unconfirmed,
+                None,
+            );
+        }
+
+        let unknown = OutPoint::from_str(
+            "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f2:0",
         )
         .unwrap();
-        // Two unvault emer!
-        let txs = finalized_emer_txs(&revaultd).unwrap();
-        assert_eq!(txs.len(), 2);
-        assert!(txs.contains(&unvault_emer2));
-        assert!(txs.contains(&unvault_emer3));
+        match stk_control.get_vault_details(unknown) {
+            Err(CommandError::UnknownOutpoint(o)) => assert_eq!(o, unknown),
+            res => panic!("Unexpected result: {:?}", res),
+        }
 
-        fs::remove_dir_all(&datadir).unwrap();
+        // Everything is derived at the vault's index, even before it's confirmed
+        let stk_details = stk_control.get_vault_details(outpoint).unwrap();
+        {
+            let revaultd = stk_control.revaultd.read().unwrap();
+            assert_eq!(stk_details.derivation_index, index);
+            assert_eq!(
+                stk_details.deposit_address,
+                revaultd.vault_address(index).unwrap()
+            );
+            assert_ne!(
+                stk_details.deposit_address,
+                revaultd
+                    .vault_address(DerivationIndex::new(0).unwrap())
+                    .unwrap()
+            );
+            assert_eq!(
+                stk_details.unvault_address,
+                revaultd.unvault_address(index).unwrap()
+            );
+            assert_eq!(
+                stk_details.cpfp_address,
+                revaultd.cpfp_address(index).unwrap()
+            );
+            assert_eq!(
+                stk_details.emergency_address.as_ref(),
+                revaultd
+                    .emergency_addresses
+                    .as_ref()
+                    .map(|e| e.for_amount(Amount::ONE_BTC).address())
+            );
+        }
+        assert_eq!(
+            stk_details.deposit_script_pubkey,
+            stk_details.deposit_address.script_pubkey()
+        );
+        assert_eq!(
+            stk_details.unvault_script_pubkey,
+            Script::new_v0_wsh(&stk_details.unvault_witness_script.wscript_hash())
+        );
+        assert_eq!(
+            stk_details.deposit_script_pubkey,
+            Script::new_v0_wsh(&stk_details.deposit_witness_script.wscript_hash())
+        );
+        assert!(stk_details.txids.emergency.is_some());
+        assert!(stk_details.txids.unvault_emergency.is_some());
+
+        // Managers derive the same, but don't know about the Emergency
+        let man_details = man_control.get_vault_details(outpoint).unwrap();
+        assert_eq!(
+            man_details.unvault_script_pubkey,
+            stk_details.unvault_script_pubkey
+        );
+        assert_eq!(man_details.txids.unvault, stk_details.txids.unvault);
+        assert_eq!(man_details.txids.cancel, stk_details.txids.cancel);
+        assert!(man_details.emergency_address.is_none());
+        assert!(man_details.txids.emergency.is_none());
+        assert!(man_details.txids.unvault_emergency.is_none());
+
+        fs::remove_dir_all(&datadir_stk).unwrap_or_else(|_| ());
+        fs::remove_dir_all(&datadir_man).unwrap_or_else(|_| ());
     }
 
     #[test]
-    fn test_gethistory() {
+    fn test_revocation_txs_batch() {
         let datadir = test_datadir();
-        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
-        setup_db(&mut revaultd).unwrap();
-        let db_file = revaultd.db_file();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Stakeholder);
+        let vaults = {
+            let mut revaultd = control.revaultd.write().unwrap();
+            setup_db(&mut revaultd).unwrap();
+            create_vaults(&revaultd)
+        };
+        let funded = vaults[1].db_vault.deposit_outpoint;
+        let unconfirmed = vaults[0].db_vault.deposit_outpoint;
 
-        let cancel_tx_hex = "0200000000010158a2f36c1be4e32f59d26930f64c452c81ffb1fc66bb96e8ddb477f0bacfb6660000000000fdffffff011042f4050000000022002025bfbde3ae8bd9381b9ddb837acdab48a3110cbeb05e84cb57b28d77e27794480747304402201b4c8061cb2fb8c086fdedba3787cd30bc765b0f4ba47b22e3b37fe5289ded4602204da79a4637ad85c4d17f9a840c4b50336b7072f5ca482507d9d1070ecf7f105e812103d7c8e6ff708e052a15e4c23f16c7143c2b0c98473a7fd6c1d10850a274319a5a483045022100860539abb4a1172625fcdb913759c54364063a28d707c22204d18ec5b4ffeede0220442e1560b506da56cefd4e4d603c3381c036de777e264f923fe6d81e8d6870cb8121034c8ecf7547552e8c3ca3e0d1189f09b23b92bce3c73186800e7089230cecf6810000ed5121027cf27be4980b5945b1fb4594c0a9cef39278d8a8d59ad0f001acf72a45d17fea21029091080c1f00962c6ef10d0f5346d5aecab6f65543670aa78ae21520b8a3cd2221027fa470475563f20c96b997224fe86bc9564ccad9adb77e083ccd3bdfc94fe9ed53ae6476a914927ea7d35721b017ffed4941ec764b36f730faa288ac6b76a91416b43735b6c445fa5f279c2afa70bfaf375f075388ac6c93528767522102abe475b199ec3d62fa576faee16a334fdb86ffb26dce75becebaaedf328ac3fe21030f64b922aee2fd597f104bc6cb3b670f1ca2c6c49b1071a1a6c010575d94fe5a52af0112b26800000000";
-        let spend_tx_hex = "0200000001b4243a48b54cc360e754e0175a985a49b67cf4615d8523ec5aa46d42421cdf7d0000000000504200000280b2010000000000220020b9be8f8574f8da64bb1cb6668f6134bc4706df7936eeab8411f9d82de20a895b08280954020000000000000000";
+        // Only the 'funded' vaults are selected
+        let batch = control.get_revocation_txs_batch(None).unwrap();
+        assert_eq!(batch.keys().collect::<Vec<_>>(), vec![&funded]);
+        assert_eq!(
+            control
+                .get_revocation_txs_batch(Some(&[funded][..]))
+                .unwrap()[&funded]
+                .cancel_tx
+                .txid(),
+            control.get_revocation_txs(funded).unwrap().cancel_tx.txid()
+        );
+        match control.get_revocation_txs_batch(Some(&[funded, unconfirmed][..])) {
+            Err(CommandError::InvalidStatus(VaultStatus::Unconfirmed, VaultStatus::Funded)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
 
-        let cancel_tx: BitcoinTransaction =
-            encode::deserialize(&Vec::from_hex(cancel_tx_hex).unwrap()).unwrap();
+        // Each vault is reported on independently
+        let mut to_set = BTreeMap::new();
+        to_set.insert(funded, batch[&funded].clone());
+        to_set.insert(unconfirmed, batch[&funded].clone());
+        let results = control.set_revocation_txs_batch(to_set.clone()).unwrap();
+        match results[&funded] {
+            Err(CommandError::InvalidParams(_)) => {}
+            ref res => panic!("Unexpected result: {:?}", res),
+        }
+        match results[&unconfirmed] {
+            Err(CommandError::InvalidStatus(VaultStatus::Unconfirmed, VaultStatus::Funded)) => {}
+            ref res => panic!("Unexpected result: {:?}", res),
+        }
 
-        let spend_tx: BitcoinTransaction =
-            encode::deserialize(&Vec::from_hex(spend_tx_hex).unwrap()).unwrap();
+        // The batch size limit applies to both
+        control.revaultd.write().unwrap().max_batch_size = 1;
+        match control.get_revocation_txs_batch(Some(&[funded, unconfirmed][..])) {
+            Err(CommandError::TooManyElements(2, 1)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match control.set_revocation_txs_batch(to_set) {
+            Err(CommandError::TooManyElements(2, 1)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
 
-        let deposit1_outpoint = OutPoint::new(
-            Txid::from_str("fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b")
-                .unwrap(),
-            0,
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_verify_emergency_descriptor() {
+        let datadir = test_datadir();
+        let datadir_man = test_datadir();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Stakeholder);
+        let man_control = dummy_rpcutil(datadir_man.clone(), UserRole::Manager);
+        setup_db(&mut control.revaultd.write().unwrap()).unwrap();
+        let secp = secp256k1::Secp256k1::new();
+
+        // A 2-of-2 of two xpubs, we have the private key of the first one
+        let xpriv = ExtendedPrivKey::new_master(Network::Bitcoin, &[4; 32]).unwrap();
+        let xpub = ExtendedPubKey::from_private(&secp, &xpriv);
+        let other_xpub = ExtendedPubKey::from_private(
+            &secp,
+            &ExtendedPrivKey::new_master(Network::Bitcoin, &[5; 32]).unwrap(),
         );
+        let descriptor = format!("wsh(multi(2,{}/*,{}/*))", xpub, other_xpub);
+        let index = ChildNumber::from(3);
+        let privkey = xpriv.derive_priv(&secp, &[index]).unwrap().private_key;
+        let sign = |msg: &str| {
+            let digest = sha256::Hash::hash(msg.as_bytes());
+            let msg = secp256k1::Message::from_slice(&digest[..]).unwrap();
+            secp.sign(&msg, &privkey.key).serialize_der().to_vec()
+        };
+        let proof = EmergencyKeyProof {
+            pubkey: privkey.public_key(&secp),
+            message: "I control the Emergency address".to_string(),
+            signature: sign("I control the Emergency address"),
+        };
 
-        let deposit2_outpoint = OutPoint::new(
-            Txid::from_str("cafa9f92be48ba41f9ee67e775b6c4afebd1bdbde5758792e9f30f6dea41e7fb")
+        match man_control.verify_emergency_descriptor(Some(&descriptor), Some(3), None, false) {
+            Err(CommandError::StakeholderOnly) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        for (desc, index) in &[
+            ("wsh(multi(2,aaa,bbb))", None),
+            (descriptor.as_str(), None),
+            ("wsh(multi(2,0332e5c86d0938a83ed80d13c5644ec92fd16b9d7184bb35d6ead8227b4ad47803,02807e5c4f7b228aa9f1aef77effde768356480f5268376644464e714d0205eb1c))", Some(3)),
+        ] {
+            match control.verify_emergency_descriptor(Some(desc), *index, None, false) {
+                Err(CommandError::InvalidParams(_)) => {}
+                res => panic!("Unexpected result: {:?}", res),
+            }
+        }
+        // Nothing to re-verify yet
+        match control.verify_emergency_descriptor(None, None, None, false) {
+            Err(CommandError::InvalidParams(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // It doesn't generate the configured Emergency address, so it's not stored even if
+        // asked to. The proof is still checked.
+        let res = control
+            .verify_emergency_descriptor(Some(&descriptor), Some(3), Some(&proof), true)
+            .unwrap();
+        assert!(!res.matches);
+        assert!(!res.stored);
+        assert_eq!(res.proof_valid, Some(true));
+        assert_eq!(res.derivation_index, Some(3));
+        assert_ne!(res.derived_address, res.emergency_address);
+        match control.verify_emergency_descriptor(None, None, None, false) {
+            Err(CommandError::InvalidParams(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Now make it one of our Emergency addresses, for the vaults of 1 BTC or more
+        {
+            let mut revaultd = control.revaultd.write().unwrap();
+            let first = revaultd.emergency_addresses.as_ref().unwrap().bands()[0]
+                .address
+                .clone();
+            revaultd.emergency_addresses = Some(
+                EmergencyAddresses::from_bands(vec![
+                    EmergencyBand {
+                        address: first,
+                        min_amount: None,
+                        max_amount: Some(RpcAmount::from_sat(100_000_000)),
+                    },
+                    EmergencyBand {
+                        address: EmergencyAddress::from(res.derived_address.clone()).unwrap(),
+                        min_amount: Some(RpcAmount::from_sat(100_000_000)),
+                        max_amount: None,
+                    },
+                ])
                 .unwrap(),
-            1,
-        );
+            );
+        }
+        let res = control
+            .verify_emergency_descriptor(Some(&descriptor), Some(3), None, false)
+            .unwrap();
+        assert!(res.matches);
+        assert!(!res.stored);
+        assert_eq!(res.proof_valid, None);
+        assert_eq!(res.derived_address, res.emergency_address);
+        // At another index it doesn't match anymore
+        let res = control
+            .verify_emergency_descriptor(Some(&descriptor), Some(4), None, false)
+            .unwrap();
+        assert!(!res.matches);
 
-        insert_vault_in_db(
-            &db_file,
-            1,
-            &deposit1_outpoint,
-            &Amount::ONE_BTC,
-            1,
-            ChildNumber::from_normal_idx(0).unwrap(),
-            Some(1),
-            Some(2),
-            VaultStatus::Canceled,
-            Some(&cancel_tx.txid()),
+        // A signature of another message, or by a key that isn't part of the descriptor, isn't
+        // a valid proof.
+        let wrong_msg = EmergencyKeyProof {
+            message: "Another message".to_string(),
+            ..proof.clone()
+        };
+        let wrong_key = EmergencyKeyProof {
+            pubkey: other_xpub.derive_pub(&secp, &[index]).unwrap().public_key,
+            ..proof.clone()
+        };
+        for wrong_proof in &[wrong_msg, wrong_key] {
+            let res = control
+                .verify_emergency_descriptor(Some(&descriptor), Some(3), Some(wrong_proof), false)
+                .unwrap();
+            assert!(res.matches);
+            assert_eq!(res.proof_valid, Some(false));
+        }
+        let invalid_sig = EmergencyKeyProof {
+            signature: vec![0; 12],
+            ..proof.clone()
+        };
+        match control.verify_emergency_descriptor(
+            Some(&descriptor),
+            Some(3),
+            Some(&invalid_sig),
+            false,
+        ) {
+            Err(CommandError::InvalidParams(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Store it, we can then re-verify it without giving it again
+        let res = control
+            .verify_emergency_descriptor(Some(&descriptor), Some(3), Some(&proof), true)
+            .unwrap();
+        assert!(res.matches && res.stored);
+        assert_eq!(res.proof_valid, Some(true));
+        let res = control
+            .verify_emergency_descriptor(None, None, None, false)
+            .unwrap();
+        assert!(res.matches && res.stored);
+        assert_eq!(res.descriptor, descriptor);
+        assert_eq!(res.derivation_index, Some(3));
+
+        // Storing is refused in read-only mode, checking isn't
+        control.revaultd.write().unwrap().read_only = true;
+        match control.verify_emergency_descriptor(Some(&descriptor), Some(3), None, true) {
+            Err(CommandError::ReadOnly) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert!(
+            control
+                .verify_emergency_descriptor(Some(&descriptor), Some(3), None, false)
+                .unwrap()
+                .matches
         );
 
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+        fs::remove_dir_all(&datadir_man).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_export() {
+        let datadir = test_datadir();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::Stakeholder);
+        setup_db(&mut control.revaultd.write().unwrap()).unwrap();
+        let db_path = control.revaultd.read().unwrap().db_file();
+
+        let funded_outpoint = OutPoint::from_str(
+            "fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b:0",
+        )
+        .unwrap();
         insert_vault_in_db(
-            &db_file,
+            &db_path,
             1,
-            &OutPoint::new(cancel_tx.txid(), 0),
-            &Amount::from_sat(cancel_tx.output[0].value),
-            2,
-            ChildNumber::from_normal_idx(1).unwrap(),
-            Some(2),
+            &funded_outpoint,
+            &Amount::ONE_BTC,
+            101,
+            DerivationIndex::new(0).unwrap(),
+            Some(1_600_000_000),
             None,
             VaultStatus::Funded,
             None,
         );
-
-        insert_vault_in_db(
-            &db_file,
-            1,
-            &deposit2_outpoint,
-            &Amount::from_sat(200_000_000_000),
-            1,
-            ChildNumber::from_normal_idx(0).unwrap(),
-            Some(3),
-            Some(4),
-            VaultStatus::Spent,
-            Some(&spend_tx.txid()),
-        );
-
-        // change of the spend
+        let funded_vault = db_vault_by_deposit(&db_path, &funded_outpoint)
+            .unwrap()
+            .unwrap();
+        db_raise_vault_flag(
+            &db_path,
+            funded_vault.id,
+            VaultFlagKind::ConflictingDeposit,
+            "Detected again for 1 BTC, known as \"other\"",
+            1_600_000_001,
+        )
+        .unwrap();
+        let unconfirmed_outpoint = OutPoint::from_str(
+            "cafa9f92be48ba41f9ee67e775b6c4afebd1bdbde5758792e9f30f6dea41e7fb:1",
+        )
+        .unwrap();
         insert_vault_in_db(
-            &db_file,
+            &db_path,
             1,
-            &OutPoint::new(spend_tx.txid(), 0),
-            &Amount::from_sat(spend_tx.output[0].value),
-            2,
-            ChildNumber::from_normal_idx(1).unwrap(),
-            Some(4),
+            &unconfirmed_outpoint,
+            &Amount::from_sat(50_000),
+            0,
+            DerivationIndex::new(1).unwrap(),
             None,
-            VaultStatus::Funded,
+            None,
+            VaultStatus::Unconfirmed,
             None,
         );
+        let (addr_0, addr_1) = {
+            let revaultd = control.revaultd.read().unwrap();
+            (
+                revaultd
+                    .vault_address(DerivationIndex::new(0).unwrap())
+                    .unwrap(),
+                revaultd
+                    .vault_address(DerivationIndex::new(1).unwrap())
+                    .unwrap(),
+            )
+        };
 
-        let mut txs = HashMap::new();
-        txs.insert(
-            cancel_tx.txid(),
-            WalletTransaction {
-                hex: cancel_tx_hex.to_string(),
-                received_time: 2,
-                blocktime: Some(2),
-                blockheight: Some(2),
-            },
-        );
-        txs.insert(
-            spend_tx.txid(),
-            WalletTransaction {
-                hex: spend_tx_hex.to_string(),
-                received_time: 4,
-                blocktime: Some(4),
-                blockheight: Some(4),
-            },
-        );
-        let bitcoind_conn = MockBitcoindThread::new(txs);
-
-        let events = gethistory(
-            &revaultd,
-            &bitcoind_conn,
-            0,
-            4,
-            20,
-            &[
-                HistoryEventKind::Deposit,
-                HistoryEventKind::Cancel,
-                HistoryEventKind::Spend,
-            ],
-        )
-        .unwrap();
-        assert_eq!(events.len(), 4);
-        assert_eq!(events[0].txid, spend_tx.txid());
-        assert_eq!(events[0].kind, HistoryEventKind::Spend);
-        assert_eq!(events[0].date, 4);
-        assert_eq!(
-            events[0].amount.unwrap(),
-            spend_tx.output[0].value + spend_tx.output[1].value
-        );
-        assert_eq!(
-            events[0].fee.unwrap(),
-            200_000_000_000 - spend_tx.output[0].value - spend_tx.output[1].value,
+        // The vaults, with the flag message quoted
+        let vaults_csv = format!(
+            "deposit_outpoint,amount,status,derivation_index,address,origin,funded_at,\
+             secured_at,delegated_at,moved_at,flags\r\n\
+             {},100000000,funded,0,{},external,2020-09-13T12:26:40Z,,,,\
+             \"conflicting_deposit: Detected again for 1 BTC, known as \"\"other\"\"\"\r\n\
+             {},50000,unconfirmed,1,{},external,,,,,\r\n",
+            funded_outpoint, addr_0, unconfirmed_outpoint, addr_1
         );
-        assert_eq!(events[0].vaults, vec![deposit2_outpoint]);
-
-        assert_eq!(events[1].txid, deposit2_outpoint.txid);
-        assert_eq!(events[1].kind, HistoryEventKind::Deposit);
-        assert_eq!(events[1].date, 3);
-        assert_eq!(events[1].fee, None);
-        assert_eq!(events[1].amount, Some(200_000_000_000));
-        assert_eq!(events[1].vaults, vec![deposit2_outpoint]);
+        let res = control.export(ExportKind::Vaults, 0, 0, None).unwrap();
+        assert_eq!(res.rows, 2);
+        assert!(res.path.is_none());
+        assert_eq!(res.csv.unwrap(), vaults_csv);
 
-        assert_eq!(events[2].txid, cancel_tx.txid());
-        assert_eq!(events[2].kind, HistoryEventKind::Cancel);
-        assert!(events[2].amount.is_none());
-        assert_eq!(events[2].date, 2);
-        assert_eq!(
-            events[2].fee.unwrap(),
-            Amount::ONE_BTC.as_sat() - cancel_tx.output[0].value
+        // The history, only the confirmed deposit is part of it
+        let history_csv = format!(
+            "date,kind,blockheight,txid,amount,fee,vaults\r\n\
+             2020-09-13T12:26:40Z,deposit,101,{},100000000,,{}\r\n",
+            funded_outpoint.txid, funded_outpoint
         );
-        assert_eq!(events[3].txid, deposit1_outpoint.txid);
-        assert_eq!(events[3].kind, HistoryEventKind::Deposit);
-        assert_eq!(events[3].vaults, vec![deposit1_outpoint]);
+        let res = control
+            .export(ExportKind::History, 0, u32::MAX, None)
+            .unwrap();
+        assert_eq!(res.rows, 1);
+        assert_eq!(res.csv.unwrap(), history_csv);
+        let res = control
+            .export(ExportKind::History, 1_600_000_001, u32::MAX, None)
+            .unwrap();
+        assert_eq!(res.rows, 0);
 
-        // retrieve events later in history
-        let events = gethistory(
-            &revaultd,
-            &bitcoind_conn,
-            0,
-            2,
-            20,
-            &[
-                HistoryEventKind::Deposit,
-                HistoryEventKind::Cancel,
-                HistoryEventKind::Spend,
-            ],
-        )
-        .unwrap();
-        assert_eq!(events.len(), 2);
-        assert_eq!(events[0].date, 2);
-        assert_eq!(events[0].txid, cancel_tx.txid());
-        assert_eq!(events[0].kind, HistoryEventKind::Cancel);
-        assert!(events[0].amount.is_none());
+        // It can be written to a new file in the datadir
+        let res = control
+            .export(ExportKind::Vaults, 0, 0, Some("vaults.csv"))
+            .unwrap();
+        assert_eq!(res.rows, 2);
+        assert!(res.csv.is_none());
+        let path = res.path.unwrap();
         assert_eq!(
-            events[0].fee.unwrap(),
-            Amount::ONE_BTC.as_sat() - cancel_tx.output[0].value
+            path,
+            control.revaultd.read().unwrap().data_dir.join("vaults.csv")
         );
-        assert_eq!(events[0].vaults, vec![deposit1_outpoint]);
-
-        assert_eq!(events[1].date, 1);
-        assert_eq!(events[1].txid, deposit1_outpoint.txid);
-        assert_eq!(events[1].kind, HistoryEventKind::Deposit);
-        assert_eq!(events[1].fee, None);
-        assert_eq!(events[1].amount, Some(Amount::ONE_BTC.as_sat()));
-        assert_eq!(events[1].vaults, vec![deposit1_outpoint]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), vaults_csv);
+        // But not overwrite one
+        assert!(matches!(
+            control.export(ExportKind::Vaults, 0, 0, Some("vaults.csv")),
+            Err(CommandError::Export(_))
+        ));
+        // Nor outside the datadir
+        for path in &["../vaults.csv", "/tmp/vaults.csv", ""] {
+            assert!(matches!(
+                control.export(ExportKind::Vaults, 0, 0, Some(path)),
+                Err(CommandError::InvalidParams(_))
+            ));
+        }
 
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
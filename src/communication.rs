@@ -1,24 +1,80 @@
-use crate::{database::schema::DbTransaction, revaultd::RevaultD};
+#[cfg(unix)]
+pub mod bind;
+pub mod throttle;
+
+use crate::{
+    clock::Clock,
+    communication::throttle::{PushThrottle, DEFAULT_PUSH_BURST, DEFAULT_PUSH_RATE},
+    database::schema::{DbQueuedSig, DbTransaction},
+    derivation::DerivationIndex,
+    endpoint::Endpoint,
+    revaultd::RevaultD,
+    rpcstats::{phase_timer, Phase, PhaseTimer},
+};
 
 use revault_net::{
     message::{
-        coordinator::{self, GetSigs, SetSpendResult, SetSpendTx, Sigs},
+        coordinator::{self, GetSigs, GetSpendTx, SetSpendResult, SetSpendTx, Sigs, SpendTx},
         cosigner::{SignRequest, SignResult},
-        watchtower,
+        watchtower, RequestParams,
     },
     transport::KKTransport,
 };
 use revault_tx::{
-    bitcoin::{
-        consensus::encode, hashes::hex::ToHex, secp256k1, util::bip32::ChildNumber, OutPoint, Txid,
-    },
+    bitcoin::{consensus::encode, hashes::hex::ToHex, secp256k1, OutPoint, Txid},
     miniscript::DescriptorTrait,
     transactions::{RevaultTransaction, SpendTransaction},
 };
 
-use std::{collections::BTreeMap, fmt};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+// The framing (and the limit on the size of a single message) is handled by revault_net, these
+// are the limits we enforce on the content of the messages we get from the servers before
+// processing them any further.
+
+/// The maximum number of signatures for a single transaction in a message from a server: there
+/// is at most one per participant, and deployments are well below this.
+pub const MAX_SIGS_PER_TX: usize = 100;
+/// The maximum size of a signature in a PSBT: a DER-encoded ECDSA signature is at most 72 bytes,
+/// followed by the sighash type.
+pub const MAX_PSBT_SIG_SIZE: usize = 73;
+
+// The number of messages from the servers we rejected since startup.
+static REJECTED_MESSAGES: AtomicU64 = AtomicU64::new(0);
 
-use serde::{Deserialize, Serialize};
+/// The number of messages from the servers we rejected as invalid since startup.
+pub fn rejected_messages() -> u64 {
+    REJECTED_MESSAGES.load(Ordering::Relaxed)
+}
+
+// Record the rejection of an invalid message from this server. The connection is dropped by
+// the caller bailing out with the returned error.
+fn reject_message(peer: impl fmt::Display, reason: String) -> CommunicationError {
+    REJECTED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+    log_event!(
+        log::Level::Warn,
+        "message_rejected",
+        peer = peer,
+        error = reason;
+        "Rejecting message from '{}': {}",
+        peer,
+        reason
+    );
+    CommunicationError::InvalidMessage(reason)
+}
 
 /// The kind of signature the WT refused
 #[derive(Debug)]
@@ -51,6 +107,12 @@ pub enum CommunicationError {
     CosigAlreadySigned,
     /// The Cosigning Server tried to fool us!
     CosigInsanePsbt,
+    /// The Cosigning Server did not answer in time
+    CosigTimeout,
+    /// A server sent us a message over our limits, or that doesn't match our request
+    InvalidMessage(String),
+    /// The Coordinator told us to slow down, and for how long if it said so
+    RateLimited(Option<Duration>),
 }
 
 impl fmt::Display for CommunicationError {
@@ -75,6 +137,14 @@ impl fmt::Display for CommunicationError {
                     signed a Spend transaction spending one of these vaults."
             ),
             Self::CosigInsanePsbt => write!(f, "Cosigning server error: they sent an insane PSBT"),
+            Self::CosigTimeout => write!(f, "Cosigning server error: it did not answer in time"),
+            Self::InvalidMessage(reason) => write!(f, "Invalid message from server: {}", reason),
+            Self::RateLimited(Some(retry_after)) => write!(
+                f,
+                "Coordinator error: it rate limited us for {}s",
+                retry_after.as_secs()
+            ),
+            Self::RateLimited(None) => write!(f, "Coordinator error: it rate limited us"),
         }
     }
 }
@@ -87,12 +157,452 @@ impl From<revault_net::Error> for CommunicationError {
     }
 }
 
+/// The kind of server we are connecting to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerKind {
+    Coordinator,
+    Cosigner,
+    Watchtower,
+}
+
+impl fmt::Display for ServerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerKind::Coordinator => write!(f, "coordinator"),
+            ServerKind::Cosigner => write!(f, "cosigner"),
+            ServerKind::Watchtower => write!(f, "watchtower"),
+        }
+    }
+}
+
+impl From<ServerKind> for Phase {
+    fn from(kind: ServerKind) -> Self {
+        match kind {
+            ServerKind::Coordinator => Phase::Coordinator,
+            ServerKind::Cosigner => Phase::Cosigner,
+            ServerKind::Watchtower => Phase::Watchtower,
+        }
+    }
+}
+
+/// A connection to one of the servers. It is logged when established, along with the Noise key
+/// the server proved to have, and when closed.
+pub struct ServerConnection {
+    transport: KKTransport,
+    kind: ServerKind,
+    host: Endpoint,
+    noise_key: revault_net::noise::PublicKey,
+    established: Instant,
+    // The time the connection is open for is accounted to the RPC call, if any
+    _timer: PhaseTimer,
+}
+
+impl ServerConnection {
+    /// Connect to the server at this address, which must have this static Noise key. A hostname
+    /// is resolved now, and each of its addresses tried in turn. If a `bind_address` is given,
+    /// the connection goes out from it.
+    pub fn connect(
+        kind: ServerKind,
+        host: &Endpoint,
+        bind_address: Option<IpAddr>,
+        noise_secret: &revault_net::noise::SecretKey,
+        noise_key: &revault_net::noise::PublicKey,
+    ) -> Result<ServerConnection, revault_net::Error> {
+        let timer = phase_timer(kind.into());
+        let mut last_error = None;
+        for addr in host.socket_addrs()? {
+            #[cfg(any(test, feature = "noise_test_vectors"))]
+            crate::utils::noise_vectors::initiating(addr, noise_secret, noise_key);
+            let connected = match bind_address {
+                Some(source) => bound_transport(source, addr, noise_secret, noise_key)
+                    .map(|(t, local)| (t, Some(local))),
+                None => KKTransport::connect(addr, noise_secret, noise_key).map(|t| (t, None)),
+            };
+            let (transport, local) = match connected {
+                Ok(connected) => connected,
+                Err(e) => {
+                    last_error = Some(handshake_failed(addr, e));
+                    continue;
+                }
+            };
+            // The KK handshake only succeeds if they have the static key we expect.
+            match local {
+                Some(local) => log_event!(
+                    log::Level::Info,
+                    "connection_open",
+                    kind = kind,
+                    peer = host,
+                    ip = addr.ip(),
+                    local = local,
+                    noise_key = noise_key.0.to_hex();
+                    "Connected to {} at '{}' from '{}' (Noise key '{}')",
+                    kind,
+                    host,
+                    local,
+                    noise_key.0.to_hex()
+                ),
+                None => log_event!(
+                    log::Level::Info,
+                    "connection_open",
+                    kind = kind,
+                    peer = host,
+                    ip = addr.ip(),
+                    noise_key = noise_key.0.to_hex();
+                    "Connected to {} at '{}' (Noise key '{}')",
+                    kind,
+                    host,
+                    noise_key.0.to_hex()
+                ),
+            }
+
+            return Ok(ServerConnection {
+                transport,
+                kind,
+                host: host.clone(),
+                noise_key: *noise_key,
+                established: Instant::now(),
+                _timer: timer,
+            });
+        }
+
+        Err(last_error.expect("Resolving never returns an empty list of addresses"))
+    }
+
+    /// The static Noise key the server proved to have.
+    pub fn noise_key(&self) -> &revault_net::noise::PublicKey {
+        &self.noise_key
+    }
+}
+
+// In the tests against stub servers, find out why the handshake failed.
+#[cfg(any(test, feature = "noise_test_vectors"))]
+fn handshake_failed(host: SocketAddr, error: revault_net::Error) -> revault_net::Error {
+    crate::utils::noise_vectors::initiator_failed(host, &error);
+    error
+}
+
+#[cfg(not(any(test, feature = "noise_test_vectors")))]
+fn handshake_failed(_: SocketAddr, error: revault_net::Error) -> revault_net::Error {
+    error
+}
+
+#[cfg(unix)]
+fn bound_transport(
+    source: IpAddr,
+    addr: SocketAddr,
+    noise_secret: &revault_net::noise::SecretKey,
+    noise_key: &revault_net::noise::PublicKey,
+) -> Result<(KKTransport, SocketAddr), revault_net::Error> {
+    bind::connect_transport_from(source, addr, noise_secret, noise_key)
+}
+
+#[cfg(not(unix))]
+fn bound_transport(
+    _: IpAddr,
+    _: SocketAddr,
+    _: &revault_net::noise::SecretKey,
+    _: &revault_net::noise::PublicKey,
+) -> Result<(KKTransport, SocketAddr), revault_net::Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "Binding the connections to the servers is only supported on Unix",
+    )
+    .into())
+}
+
+impl Deref for ServerConnection {
+    type Target = KKTransport;
+
+    fn deref(&self) -> &KKTransport {
+        &self.transport
+    }
+}
+
+impl DerefMut for ServerConnection {
+    fn deref_mut(&mut self) -> &mut KKTransport {
+        &mut self.transport
+    }
+}
+
+impl Drop for ServerConnection {
+    fn drop(&mut self) {
+        let duration = self.established.elapsed();
+        log_event!(
+            log::Level::Info,
+            "connection_closed",
+            kind = self.kind,
+            peer = self.host,
+            duration_ms = duration.as_millis();
+            "Disconnected from {} at '{}' after {}ms",
+            self.kind,
+            self.host,
+            duration.as_millis()
+        );
+    }
+}
+
+/// A Coordinator we may exchange signatures through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoordinatorEndpoint {
+    pub host: Endpoint,
+    pub noise_key: revault_net::noise::PublicKey,
+}
+
+// What we learned of a Coordinator from our last attempts to talk to it.
+#[derive(Debug, Clone, Default)]
+struct CoordinatorHealth {
+    // None if we never tried to connect to it
+    reachable: Option<bool>,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct CoordinatorsState {
+    active: usize,
+    health: Vec<CoordinatorHealth>,
+}
+
+/// Whether this error means we should try another Coordinator: it's unreachable, the handshake
+/// failed or it sent us garbage. A Coordinator refusing to store something or telling us to slow
+/// down answered us, failing over wouldn't help.
+pub fn coordinator_failed(error: &CommunicationError) -> bool {
+    matches!(
+        error,
+        CommunicationError::Net(_) | CommunicationError::InvalidMessage(_)
+    )
+}
+
+/// The Coordinators of the deployment: the main one first, then the backups by order of
+/// preference. They are always tried in this order, so we get back to the main one as soon as it
+/// is up again. The messages we push to them go through a single throttle, paced by the daemon
+/// clock. Cloning it gives a handle to the same state.
+#[derive(Debug, Clone)]
+pub struct Coordinators {
+    endpoints: Arc<Vec<CoordinatorEndpoint>>,
+    state: Arc<Mutex<CoordinatorsState>>,
+    clock: Arc<dyn Clock>,
+    throttle: Arc<PushThrottle>,
+    bind_address: Option<IpAddr>,
+}
+
+impl Coordinators {
+    /// There must be at least one endpoint, the main Coordinator.
+    pub fn new(endpoints: Vec<CoordinatorEndpoint>, clock: Arc<dyn Clock>) -> Self {
+        assert!(!endpoints.is_empty(), "There must be a main Coordinator");
+        let state = CoordinatorsState {
+            active: 0,
+            health: vec![CoordinatorHealth::default(); endpoints.len()],
+        };
+        Self {
+            endpoints: Arc::new(endpoints),
+            state: Arc::new(Mutex::new(state)),
+            throttle: Arc::new(PushThrottle::new(
+                DEFAULT_PUSH_RATE,
+                DEFAULT_PUSH_BURST,
+                clock.clone(),
+            )),
+            clock,
+            bind_address: None,
+        }
+    }
+
+    /// Connect to them from this source address.
+    pub fn with_bind_address(mut self, bind_address: Option<IpAddr>) -> Self {
+        self.bind_address = bind_address;
+        self
+    }
+
+    /// Push to them at this pace (messages per second, and burst) instead of the default one.
+    pub fn with_push_rate(mut self, rate: u32, burst: u32) -> Self {
+        self.throttle = Arc::new(PushThrottle::new(rate, burst, self.clock.clone()));
+        self
+    }
+
+    /// The pace at which we push messages to them.
+    pub fn throttle(&self) -> &PushThrottle {
+        &self.throttle
+    }
+
+    /// The main Coordinator, followed by the backups.
+    pub fn endpoints(&self) -> &[CoordinatorEndpoint] {
+        &self.endpoints
+    }
+
+    /// The main Coordinator.
+    pub fn main(&self) -> &CoordinatorEndpoint {
+        &self.endpoints[0]
+    }
+
+    /// The Coordinator we last successfully talked to (the main one until then).
+    pub fn active(&self) -> CoordinatorEndpoint {
+        self.endpoints[self.state.lock().unwrap().active].clone()
+    }
+
+    fn record_success(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.health[index] = CoordinatorHealth {
+            reachable: Some(true),
+            consecutive_failures: 0,
+            last_error: None,
+        };
+        if state.active != index {
+            let (previous, current) = (&self.endpoints[state.active], &self.endpoints[index]);
+            log_event!(
+                log::Level::Warn,
+                "coordinator_switch",
+                previous = previous.host,
+                peer = current.host;
+                "Now using the coordinator at '{}' instead of the one at '{}'",
+                current.host,
+                previous.host
+            );
+            state.active = index;
+        }
+    }
+
+    fn record_failure(&self, index: usize, error: &CommunicationError) {
+        let mut state = self.state.lock().unwrap();
+        let health = &mut state.health[index];
+        health.reachable = Some(!matches!(error, CommunicationError::Net(_)));
+        health.consecutive_failures += 1;
+        health.last_error = Some(error.to_string());
+    }
+
+    /// Connect to the first Coordinator that completes the handshake, by order of preference,
+    /// skipping the `excluded` ones (by index in `endpoints()`), which must not be all of them.
+    /// Returns the index of the one we are connected to along with the connection.
+    pub fn connect(
+        &self,
+        noise_secret: &revault_net::noise::SecretKey,
+        excluded: &[usize],
+    ) -> Result<(usize, ServerConnection), CommunicationError> {
+        let mut last_error = None;
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            if excluded.contains(&i) {
+                continue;
+            }
+            match ServerConnection::connect(
+                ServerKind::Coordinator,
+                &endpoint.host,
+                self.bind_address,
+                noise_secret,
+                &endpoint.noise_key,
+            ) {
+                Ok(conn) => return Ok((i, conn)),
+                Err(e) => {
+                    let e = CommunicationError::Net(e);
+                    self.report_failure(i, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("At least one coordinator must not be excluded"))
+    }
+
+    /// Record that the Coordinator at this index was unreachable or misbehaved, so that we fail
+    /// over to the next one.
+    pub fn report_failure(&self, index: usize, error: &CommunicationError) {
+        let endpoint = &self.endpoints[index];
+        log_event!(
+            log::Level::Warn,
+            "coordinator_failure",
+            peer = endpoint.host,
+            error = error;
+            "Coordinator at '{}' failed: '{}'",
+            endpoint.host,
+            error
+        );
+        self.record_failure(index, error);
+    }
+
+    /// Record that we successfully exchanged with the Coordinator at this index, which makes it
+    /// the active one.
+    pub fn report_success(&self, index: usize) {
+        self.record_success(index);
+    }
+
+    /// Run `f` over a connection to the first Coordinator that is reachable and behaves, by
+    /// order of preference. If it fails at the network level or sends an invalid message, `f` is
+    /// run again with the next one. Returns the Coordinator `f` succeeded with, along with its
+    /// result.
+    pub fn with_coordinator<T, F>(
+        &self,
+        noise_secret: &revault_net::noise::SecretKey,
+        mut f: F,
+    ) -> Result<(CoordinatorEndpoint, T), CommunicationError>
+    where
+        F: FnMut(&mut ServerConnection) -> Result<T, CommunicationError>,
+    {
+        let mut excluded = Vec::with_capacity(self.endpoints.len());
+        loop {
+            let (i, mut conn) = self.connect(noise_secret, &excluded)?;
+            match f(&mut conn) {
+                Ok(res) => {
+                    self.report_success(i);
+                    return Ok((self.endpoints[i].clone(), res));
+                }
+                Err(e) if coordinator_failed(&e) => {
+                    self.report_failure(i, &e);
+                    if excluded.len() + 1 == self.endpoints.len() {
+                        return Err(e);
+                    }
+                    excluded.push(i);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Make a dummy connection to each Coordinator to check whether it's up, and get their
+    /// statuses.
+    pub fn probe(&self, noise_secret: &revault_net::noise::SecretKey) -> Vec<CoordinatorStatus> {
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            match ServerConnection::connect(
+                ServerKind::Coordinator,
+                &endpoint.host,
+                self.bind_address,
+                noise_secret,
+                &endpoint.noise_key,
+            ) {
+                Ok(_) => {
+                    // Don't make it the active one, we did not exchange anything with it.
+                    let mut state = self.state.lock().unwrap();
+                    state.health[i].reachable = Some(true);
+                }
+                Err(e) => self.record_failure(i, &CommunicationError::Net(e)),
+            }
+        }
+
+        self.statuses()
+    }
+
+    /// What we know of each Coordinator, without connecting to them.
+    pub fn statuses(&self) -> Vec<CoordinatorStatus> {
+        let state = self.state.lock().unwrap();
+        self.endpoints
+            .iter()
+            .zip(state.health.iter())
+            .enumerate()
+            .map(|(i, (endpoint, health))| CoordinatorStatus {
+                host: endpoint.host.to_string(),
+                noise_key: endpoint.noise_key.0.to_hex(),
+                active: i == state.active,
+                reachable: health.reachable.unwrap_or(false),
+                consecutive_failures: health.consecutive_failures,
+                last_error: health.last_error.clone(),
+            })
+            .collect()
+    }
+}
+
 // Send a `sigs` (https://github.com/revault/practical-revault/blob/master/messages.md#sigs)
 // message to a watchtower.
 fn send_wt_sigs_msg(
     transport: &mut KKTransport,
     deposit_outpoint: OutPoint,
-    derivation_index: ChildNumber,
+    derivation_index: DerivationIndex,
     emer_tx: &DbTransaction,
     cancel_tx: &DbTransaction,
     unemer_tx: &DbTransaction,
@@ -105,7 +615,7 @@ fn send_wt_sigs_msg(
     let sig_msg = watchtower::Sigs {
         signatures,
         deposit_outpoint,
-        derivation_index,
+        derivation_index: derivation_index.into(),
     };
 
     log::debug!("Sending signatures to watchtower: '{:?}'", sig_msg);
@@ -125,6 +635,34 @@ fn send_wt_sigs_msg(
     Ok(())
 }
 
+// What the Coordinator answers to the messages we push to it: the result, or an error telling us
+// to slow down (`{"error": "rate_limited", "retry_after": <seconds>}`, the delay being optional).
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PushReply<T> {
+    Error(PushError),
+    Result(T),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+enum PushError {
+    RateLimited { retry_after: Option<u64> },
+}
+
+// Push this message to the Coordinator, its rate limiting us being an error.
+fn send_push<T: DeserializeOwned>(
+    transport: &mut KKTransport,
+    msg: &RequestParams,
+) -> Result<T, CommunicationError> {
+    match transport.send_req(msg)? {
+        PushReply::Result(result) => Ok(result),
+        PushReply::Error(PushError::RateLimited { retry_after }) => Err(
+            CommunicationError::RateLimited(retry_after.map(Duration::from_secs)),
+        ),
+    }
+}
+
 // Send a `sig` (https://github.com/revault/practical-revault/blob/master/messages.md#sig-1)
 // message to the server for all the sigs of this mapping.
 // Note that we are looping, but most (if not all) will only have a single signature
@@ -144,7 +682,7 @@ pub fn send_coord_sig_msg(
             id,
         };
         log::debug!("Sending sig '{:?}' to sync server", sig_msg,);
-        let sig_result: coordinator::SigResult = transport.send_req(&sig_msg.into())?;
+        let sig_result: coordinator::SigResult = send_push(transport, &sig_msg.into())?;
         log::debug!("Got from coordinator: '{:?}'", sig_result);
         if !sig_result.ack {
             return Err(CommunicationError::SignatureStorage);
@@ -157,15 +695,22 @@ pub fn send_coord_sig_msg(
 /// Share the revocation transactions' signatures with all our watchtowers.
 pub fn wts_share_rev_signatures(
     noise_secret: &revault_net::noise::SecretKey,
-    watchtowers: &[(std::net::SocketAddr, revault_net::noise::PublicKey)],
+    bind_address: Option<IpAddr>,
+    watchtowers: &[(Endpoint, revault_net::noise::PublicKey)],
     deposit_outpoint: OutPoint,
-    derivation_index: ChildNumber,
+    derivation_index: DerivationIndex,
     emer_tx: &DbTransaction,
     cancel_tx: &DbTransaction,
     unemer_tx: &DbTransaction,
 ) -> Result<(), CommunicationError> {
     for (wt_host, wt_noisekey) in watchtowers {
-        let mut transport = KKTransport::connect(*wt_host, noise_secret, wt_noisekey)?;
+        let mut transport = ServerConnection::connect(
+            ServerKind::Watchtower,
+            wt_host,
+            bind_address,
+            noise_secret,
+            wt_noisekey,
+        )?;
 
         send_wt_sigs_msg(
             &mut transport,
@@ -180,36 +725,62 @@ pub fn wts_share_rev_signatures(
     Ok(())
 }
 
-/// Send the signatures for the 3 revocation txs to the Coordinator
-pub fn coord_share_rev_signatures(
-    coordinator_host: std::net::SocketAddr,
+/// Push these signatures from the outbox to the Coordinator, in order and at the pace its
+/// throttle allows. We stop without waiting once out of budget or if the Coordinator tells us to
+/// slow down, the rest being pushed by a later call. `delivered` is called with the Noise key of
+/// the Coordinator for each signature it acknowledged, as soon as it did, so that it can be
+/// removed from the outbox. Returns the number of signatures delivered.
+pub fn push_queued_signatures<F>(
+    coordinators: &Coordinators,
     noise_secret: &revault_net::noise::SecretKey,
-    coordinator_noisekey: &revault_net::noise::PublicKey,
-    rev_txs: &[DbTransaction],
-) -> Result<(), CommunicationError> {
-    let mut transport = KKTransport::connect(coordinator_host, noise_secret, coordinator_noisekey)?;
-
-    for tx in rev_txs {
-        send_coord_sig_msg(&mut transport, tx.psbt.txid(), tx.psbt.signatures())?;
+    queued: &[DbQueuedSig],
+    mut delivered: F,
+) -> Result<usize, CommunicationError>
+where
+    F: FnMut(&revault_net::noise::PublicKey, &DbQueuedSig),
+{
+    let throttle = coordinators.throttle();
+    if queued.is_empty() || throttle.status().available == 0 {
+        return Ok(0);
     }
 
-    Ok(())
-}
-
-/// Send the unvault signature to the Coordinator
-pub fn share_unvault_signatures(
-    coordinator_host: std::net::SocketAddr,
-    noise_secret: &revault_net::noise::SecretKey,
-    coordinator_noisekey: &revault_net::noise::PublicKey,
-    unvault_tx: &DbTransaction,
-) -> Result<(), CommunicationError> {
-    let mut transport = KKTransport::connect(coordinator_host, noise_secret, coordinator_noisekey)?;
+    // If we fail over, carry on from the first one not delivered yet.
+    let mut next = 0;
+    coordinators.with_coordinator(noise_secret, |transport| {
+        while let Some(queued_sig) = queued.get(next) {
+            if throttle.try_acquire().is_err() {
+                break;
+            }
+            let mut sigs = BTreeMap::new();
+            sigs.insert(queued_sig.pubkey, queued_sig.signature);
+            match send_coord_sig_msg(transport, queued_sig.txid, sigs) {
+                Ok(()) => {
+                    throttle.accepted();
+                    delivered(transport.noise_key(), queued_sig);
+                    next += 1;
+                }
+                Err(CommunicationError::RateLimited(retry_after)) => {
+                    let hold_off = throttle.rate_limited(retry_after);
+                    log_event!(
+                        log::Level::Info,
+                        "coordinator_rate_limited",
+                        peer = transport.host,
+                        retry_after_secs = hold_off.as_secs();
+                        "The coordinator at '{}' rate limited us, holding off for {}s with {} \
+                         signature(s) queued",
+                        transport.host,
+                        hold_off.as_secs(),
+                        queued.len() - next
+                    );
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    })?;
 
-    send_coord_sig_msg(
-        &mut transport,
-        unvault_tx.psbt.txid(),
-        unvault_tx.psbt.signatures(),
-    )
+    Ok(next)
 }
 
 // A hack to workaround the immutability of the SpendTransaction.
@@ -222,15 +793,81 @@ fn strip_signatures(spend_tx: SpendTransaction) -> SpendTransaction {
     SpendTransaction::from_raw_psbt(&encode::serialize(&psbt)).expect("We just deserialized it")
 }
 
-/// Make the cosigning servers sign this Spend transaction.
-/// This method checks that the signatures are valid, but it doesn't
-/// check that the cosigners are returning signatures in the first place.
-pub fn fetch_cosigs_signatures<C: secp256k1::Verification>(
-    secp: &secp256k1::Secp256k1<C>,
+// Ask a single Cosigning Server to sign this Spend transaction.
+fn request_cosig_signatures(
+    host: &Endpoint,
+    bind_address: Option<IpAddr>,
     noise_secret: &revault_net::noise::SecretKey,
-    spend_tx: &mut SpendTransaction,
-    cosigs: &[(std::net::SocketAddr, revault_net::noise::PublicKey)],
-) -> Result<(), CommunicationError> {
+    noise_key: &revault_net::noise::PublicKey,
+    msg: SignRequest,
+) -> Result<SpendTransaction, CommunicationError> {
+    let mut transport = ServerConnection::connect(
+        ServerKind::Cosigner,
+        host,
+        bind_address,
+        noise_secret,
+        noise_key,
+    )?;
+    log::debug!(
+        "Polling cosigning server at '{}' (key: '{}') for spend '{}'",
+        host,
+        noise_key.0.to_hex(),
+        msg.tx.txid(),
+    );
+
+    let requested_txid = msg.tx.txid();
+    let sign_res: SignResult = transport.send_req(&msg.into())?;
+    let signed_tx = sign_res.tx.ok_or(CommunicationError::CosigAlreadySigned)?;
+    check_signed_spend(requested_txid, &signed_tx).map_err(|e| reject_message(host, e))?;
+    log::debug!("Cosigning server returned: '{}'", &signed_tx,);
+
+    Ok(signed_tx)
+}
+
+// A Cosigning Server must send back the very Spend transaction we asked it to sign, with
+// sane signatures.
+fn check_signed_spend(requested_txid: Txid, signed_tx: &SpendTransaction) -> Result<(), String> {
+    let txid = signed_tx.txid();
+    if txid != requested_txid {
+        return Err(format!(
+            "Spend transaction '{}' instead of '{}'",
+            txid, requested_txid
+        ));
+    }
+    for psbtin in signed_tx.psbt().inputs.iter() {
+        if psbtin.partial_sigs.len() > MAX_SIGS_PER_TX {
+            return Err(format!(
+                "'{}' signatures for an input, the limit is '{}'",
+                psbtin.partial_sigs.len(),
+                MAX_SIGS_PER_TX
+            ));
+        }
+        if let Some(sig) = psbtin
+            .partial_sigs
+            .values()
+            .find(|sig| sig.len() > MAX_PSBT_SIG_SIZE)
+        {
+            return Err(format!(
+                "'{}' bytes signature, the limit is '{}'",
+                sig.len(),
+                MAX_PSBT_SIG_SIZE
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Ask all the given Cosigning Servers for their signatures of this Spend transaction at
+/// once, and wait at most `timeout` for them to answer.
+/// Returns the Spend transaction signed by each of them, in the same order as `cosigs`.
+pub fn poll_cosigning_servers(
+    noise_secret: &revault_net::noise::SecretKey,
+    bind_address: Option<IpAddr>,
+    spend_tx: &SpendTransaction,
+    cosigs: &[(Endpoint, revault_net::noise::PublicKey)],
+    timeout: Duration,
+) -> Vec<Result<SpendTransaction, CommunicationError>> {
     // Strip the signatures before polling the Cosigning Server. It does not check them
     // anyways, and it makes us hit the Noise message size limit fairly quickly.
     let tx = strip_signatures(spend_tx.clone());
@@ -240,56 +877,106 @@ pub fn fetch_cosigs_signatures<C: secp256k1::Verification>(
         msg
     );
 
-    for (host, noise_key) in cosigs {
-        // FIXME: connect should take a reference... This copy is useless
-        let mut transport = KKTransport::connect(*host, noise_secret, noise_key)?;
-        log::debug!(
-            "Polling cosigning server at '{}' (key: '{}') for spend '{}'",
-            host,
-            noise_key.0.to_hex(),
-            spend_tx.txid(),
-        );
+    let deadline = Instant::now() + timeout;
+    let (sender, receiver) = mpsc::channel();
+    for (i, (host, noise_key)) in cosigs.iter().enumerate() {
+        let (host, noise_key) = (host.clone(), *noise_key);
+        let (noise_secret, msg, sender) = (noise_secret.clone(), msg.clone(), sender.clone());
+        thread::spawn(move || {
+            let res = request_cosig_signatures(&host, bind_address, &noise_secret, &noise_key, msg);
+            // We may have given up on this server already, in which case the receiver is gone.
+            let _ = sender.send((i, res));
+        });
+    }
 
-        let sign_res: SignResult = transport.send_req(&msg.clone().into())?;
-        let signed_tx = sign_res.tx.ok_or(CommunicationError::CosigAlreadySigned)?;
-        log::debug!("Cosigning server returned: '{}'", &signed_tx,);
-
-        for (i, psbtin) in signed_tx.into_psbt().inputs.into_iter().enumerate() {
-            for (key, sig) in psbtin.partial_sigs {
-                let (_, rawsig) = sig
-                    .split_last()
-                    .ok_or(CommunicationError::CosigInsanePsbt)?;
-                let sig = secp256k1::Signature::from_der(rawsig)
-                    .map_err(|_| CommunicationError::CosigInsanePsbt)?;
-                spend_tx
-                    .add_signature(i, key.key, sig, secp)
-                    .map_err(|_| CommunicationError::CosigInsanePsbt)?;
-            }
+    let mut results: Vec<Option<Result<SpendTransaction, CommunicationError>>> =
+        cosigs.iter().map(|_| None).collect();
+    for _ in 0..cosigs.len() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        match receiver.recv_timeout(deadline - now) {
+            Ok((i, res)) => results[i] = Some(res),
+            Err(_) => break,
+        }
+    }
+
+    results
+        .into_iter()
+        .zip(cosigs.iter())
+        .map(|(res, (host, _))| {
+            res.unwrap_or_else(|| {
+                log::warn!("Cosigning server at '{}' did not answer in time", host);
+                Err(CommunicationError::CosigTimeout)
+            })
+        })
+        .collect()
+}
+
+/// Add the signatures a Cosigning Server sent us to our Spend transaction.
+/// This checks that the signatures are valid, but it doesn't check that
+/// the cosigner returned signatures in the first place.
+pub fn add_cosig_signatures<C: secp256k1::Verification>(
+    secp: &secp256k1::Secp256k1<C>,
+    spend_tx: &mut SpendTransaction,
+    signed_tx: SpendTransaction,
+) -> Result<(), CommunicationError> {
+    for (i, psbtin) in signed_tx.into_psbt().inputs.into_iter().enumerate() {
+        for (key, sig) in psbtin.partial_sigs {
+            let (_, rawsig) = sig
+                .split_last()
+                .ok_or(CommunicationError::CosigInsanePsbt)?;
+            let sig = secp256k1::Signature::from_der(rawsig)
+                .map_err(|_| CommunicationError::CosigInsanePsbt)?;
+            spend_tx
+                .add_signature(i, key.key, sig, secp)
+                .map_err(|_| CommunicationError::CosigInsanePsbt)?;
         }
     }
 
     Ok(())
 }
 
-/// Sends the spend transaction for a certain outpoint to the coordinator
+/// Sends the spend transaction for a certain outpoint to the coordinator. Returns the
+/// Coordinator that acknowledged it.
 pub fn announce_spend_transaction(
-    coordinator_host: std::net::SocketAddr,
+    coordinators: &Coordinators,
     noise_secret: &revault_net::noise::SecretKey,
-    coordinator_noisekey: &revault_net::noise::PublicKey,
     spend_tx: SpendTransaction,
     deposit_outpoints: Vec<OutPoint>,
-) -> Result<(), CommunicationError> {
-    let mut transport = KKTransport::connect(coordinator_host, noise_secret, coordinator_noisekey)?;
-
+) -> Result<CoordinatorEndpoint, CommunicationError> {
     let msg = SetSpendTx::from_spend_tx(deposit_outpoints, spend_tx);
     log::debug!("Sending Spend tx to Coordinator: '{:?}'", msg);
-    let resp: SetSpendResult = transport.send_req(&msg.into())?;
+    let (coordinator, ()) = coordinators.with_coordinator(noise_secret, |transport| {
+        let resp: SetSpendResult = match send_push(transport, &msg.clone().into()) {
+            Err(CommunicationError::RateLimited(retry_after)) => {
+                coordinators.throttle().rate_limited(retry_after);
+                return Err(CommunicationError::RateLimited(retry_after));
+            }
+            res => res?,
+        };
+        log::debug!("Got from Coordinator: '{:?}'", resp);
+        if !resp.ack {
+            return Err(CommunicationError::SpendTxStorage);
+        }
+        Ok(())
+    })?;
+
+    Ok(coordinator)
+}
+
+/// Get the id of the Spend transaction the Coordinator stores for this deposit, if any.
+pub fn get_announced_spend(
+    transport: &mut KKTransport,
+    deposit_outpoint: OutPoint,
+) -> Result<Option<Txid>, CommunicationError> {
+    let msg = GetSpendTx { deposit_outpoint };
+    log::debug!("Sending to Coordinator: '{:?}'", msg);
+    let resp: SpendTx = transport.send_req(&msg.into())?;
     log::debug!("Got from Coordinator: '{:?}'", resp);
-    if !resp.ack {
-        return Err(CommunicationError::SpendTxStorage);
-    }
 
-    Ok(())
+    Ok(resp.spend_tx.map(|tx| tx.txid()))
 }
 
 /// Get the signatures for this presigned transaction from the Coordinator.
@@ -301,30 +988,57 @@ pub fn get_presigs(
 
     log::debug!("Sending to sync server: '{:?}'", getsigs_msg,);
     let resp: Sigs = transport.send_req(&getsigs_msg.into())?;
+    check_sigs(&resp).map_err(|e| reject_message("coordinator", e))?;
     log::debug!("Got sigs {:?} from coordinator.", resp);
 
     Ok(resp.signatures)
 }
 
+fn check_sigs(sigs: &Sigs) -> Result<(), String> {
+    if sigs.signatures.len() > MAX_SIGS_PER_TX {
+        return Err(format!(
+            "'{}' signatures for a transaction, the limit is '{}'",
+            sigs.signatures.len(),
+            MAX_SIGS_PER_TX
+        ));
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ServerStatus {
     pub host: String,
     pub reachable: bool,
 }
 
-/// Make a dummy connection to the coordinator to check whether it's up
-pub fn coordinator_status(revaultd: &RevaultD) -> ServerStatus {
-    let reachable = KKTransport::connect(
-        revaultd.coordinator_host,
-        &revaultd.noise_secret,
-        &revaultd.coordinator_noisekey,
-    )
-    .is_ok();
+/// What we know of a Coordinator: whether it's the one we use and how it behaved lately.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CoordinatorStatus {
+    pub host: String,
+    pub noise_key: String,
+    pub active: bool,
+    pub reachable: bool,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
 
-    ServerStatus {
-        host: revaultd.coordinator_host.to_string(),
-        reachable,
-    }
+/// Make a dummy connection to the coordinators to check whether they're up. Returns the status of
+/// the active one along with the details of all of them.
+pub fn coordinator_status(revaultd: &RevaultD) -> (ServerStatus, Vec<CoordinatorStatus>) {
+    let statuses = revaultd.coordinators.probe(&revaultd.noise_secret);
+    let active = statuses
+        .iter()
+        .find(|status| status.active)
+        .expect("There is always an active coordinator");
+
+    (
+        ServerStatus {
+            host: active.host.clone(),
+            reachable: active.reachable,
+        },
+        statuses,
+    )
 }
 
 /// Make a dummy connection to the cosigning servers to check whether they're up
@@ -332,7 +1046,17 @@ pub fn cosigners_status(revaultd: &RevaultD) -> Vec<ServerStatus> {
     let mut cosigners = Vec::new();
     if let Some(c) = &revaultd.cosigs {
         for (host, key) in c {
-            let reachable = KKTransport::connect(*host, &revaultd.noise_secret, key).is_ok();
+            // A read-only instance never connects to the cosigning servers, not even to check
+            // they are up.
+            let reachable = !revaultd.read_only
+                && ServerConnection::connect(
+                    ServerKind::Cosigner,
+                    host,
+                    revaultd.bind_address,
+                    &revaultd.noise_secret,
+                    key,
+                )
+                .is_ok();
 
             cosigners.push(ServerStatus {
                 host: host.to_string(),
@@ -348,7 +1072,14 @@ pub fn watchtowers_status(revaultd: &RevaultD) -> Vec<ServerStatus> {
     let mut watchtowers = Vec::new();
     if let Some(w) = &revaultd.watchtowers {
         for (host, key) in w {
-            let reachable = KKTransport::connect(*host, &revaultd.noise_secret, key).is_ok();
+            let reachable = ServerConnection::connect(
+                ServerKind::Watchtower,
+                host,
+                revaultd.bind_address,
+                &revaultd.noise_secret,
+                key,
+            )
+            .is_ok();
 
             watchtowers.push(ServerStatus {
                 host: host.to_string(),
@@ -389,15 +1120,25 @@ pub fn check_spend_transaction_size(revaultd: &RevaultD, spend_tx: SpendTransact
     msg.len() <= revault_net::noise::NOISE_PLAINTEXT_MAX_SIZE
 }
 
+#[cfg(test)]
+mod conformance;
+
 #[cfg(test)]
 mod tests {
     use crate::{
+        clock::SystemClock,
         communication::*,
         database::{
             bitcointx::{RevaultTx, TransactionType},
-            schema::DbTransaction,
+            schema::{DbQueuedSig, DbTransaction},
+        },
+        utils::{
+            noise_vectors::{
+                initiator_failure, scripted, test_keypair, HandshakeMessage, HandshakeSide,
+                KeyMismatch, StubServer,
+            },
+            test_utils::{dummy_revaultd, stub_cosigner, test_datadir, UserRole},
         },
-        utils::test_utils::{dummy_revaultd, test_datadir, UserRole},
     };
     use revault_net::{
         message, sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::gen_keypair,
@@ -414,7 +1155,15 @@ mod tests {
             UnvaultEmergencyTransaction, UnvaultTransaction,
         },
     };
-    use std::{collections::BTreeMap, fs, net::TcpListener, str::FromStr, thread};
+    use std::{
+        collections::BTreeMap,
+        fs,
+        net::TcpListener,
+        str::FromStr,
+        sync::atomic::Ordering,
+        thread,
+        time::{Duration, Instant},
+    };
 
     fn create_keys(
         ctx: &secp256k1::Secp256k1<secp256k1::All>,
@@ -430,6 +1179,72 @@ mod tests {
         (private_key, public_key)
     }
 
+    fn connect_coordinator(
+        server: &StubServer,
+        noise_secret: &revault_net::noise::SecretKey,
+    ) -> ServerConnection {
+        ServerConnection::connect(
+            ServerKind::Coordinator,
+            &server.addr().into(),
+            None,
+            noise_secret,
+            &server.noise_key(),
+        )
+        .expect("Client channel connecting")
+    }
+
+    fn single_coordinator(
+        host: std::net::SocketAddr,
+        noise_key: revault_net::noise::PublicKey,
+    ) -> Coordinators {
+        Coordinators::new(
+            vec![CoordinatorEndpoint {
+                host: host.into(),
+                noise_key,
+            }],
+            Arc::new(SystemClock),
+        )
+    }
+
+    // Our signatures of these transactions, as queued in the outbox
+    fn queued_sigs(txs: &[DbTransaction]) -> Vec<DbQueuedSig> {
+        txs.iter()
+            .flat_map(|tx| {
+                let txid = tx.psbt.txid();
+                tx.psbt
+                    .signatures()
+                    .into_iter()
+                    .map(move |(pubkey, signature)| (txid, pubkey, signature))
+            })
+            .enumerate()
+            .map(|(id, (txid, pubkey, signature))| DbQueuedSig {
+                id: id as i64,
+                txid,
+                pubkey,
+                signature,
+                queued_at: 0,
+            })
+            .collect()
+    }
+
+    fn poll_and_add_signatures<C: secp256k1::Verification>(
+        secp: &secp256k1::Secp256k1<C>,
+        noise_secret: &revault_net::noise::SecretKey,
+        spend_tx: &mut SpendTransaction,
+        cosigs: &[(Endpoint, revault_net::noise::PublicKey)],
+    ) -> Result<(), CommunicationError> {
+        for res in poll_cosigning_servers(
+            noise_secret,
+            None,
+            spend_tx,
+            cosigs,
+            Duration::from_secs(30),
+        ) {
+            add_cosig_signatures(secp, spend_tx, res?)?;
+        }
+        Ok(())
+    }
+
     // This time the coordinator won't ack our signatures :(
     #[test]
     fn test_send_coord_sig_msg_not_acked() {
@@ -442,43 +1257,26 @@ mod tests {
         let signature = secp256k1::Signature::from_str("304402201a3109a4a6445c1e56416bc39520aada5c8ad089e69ee4f1a40a0901de1a435302204b281ba97da2ab2e40eb65943ae414cc4307406c5eb177b1c646606839a2e99d").unwrap();
         sigs.insert(public_key.key, signature.clone());
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
-
-        // client thread
-        let cli_thread = thread::spawn(move || {
-            let mut cli_transport = KKTransport::connect(addr, &client_privkey, &server_pubkey)
-                .expect("Client channel connecting");
-            assert!(
-                send_coord_sig_msg(&mut cli_transport, txid.clone(), sigs.clone())
-                    .unwrap_err()
-                    .to_string()
-                    .contains(&CommunicationError::SignatureStorage.to_string())
-            );
-        });
-
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::Sig(
+                message::coordinator::SigResult { ack: false },
+            )]));
 
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::CoordSig(coordinator::Sig {
-                        pubkey: public_key.key,
-                        signature,
-                        id: txid
-                    }),
-                );
-                Some(message::ResponseResult::Sig(
-                    message::coordinator::SigResult { ack: false },
-                ))
-            })
-            .unwrap();
-        cli_thread.join().unwrap();
+        let mut conn = connect_coordinator(&server, &client_privkey);
+        assert!(send_coord_sig_msg(&mut conn, txid, sigs)
+            .unwrap_err()
+            .to_string()
+            .contains(&CommunicationError::SignatureStorage.to_string()));
+        assert_eq!(
+            server.take_requests(),
+            vec![message::RequestParams::CoordSig(coordinator::Sig {
+                pubkey: public_key.key,
+                signature,
+                id: txid
+            })]
+        );
     }
 
     // This time the server likes our signatures! :tada:
@@ -493,42 +1291,122 @@ mod tests {
         let signature = secp256k1::Signature::from_str("304402201a3109a4a6445c1e56416bc39520aada5c8ad089e69ee4f1a40a0901de1a435302204b281ba97da2ab2e40eb65943ae414cc4307406c5eb177b1c646606839a2e99d").unwrap();
         sigs.insert(public_key.key, signature.clone());
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
-
-        // client thread
-        let cli_thread = thread::spawn(move || {
-            let mut cli_transport = KKTransport::connect(addr, &client_privkey, &server_pubkey)
-                .expect("Client channel connecting");
-            send_coord_sig_msg(&mut cli_transport, txid.clone(), sigs.clone()).unwrap();
-        });
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::Sig(
+                message::coordinator::SigResult { ack: true },
+            )]));
+
+        let mut conn = connect_coordinator(&server, &client_privkey);
+        send_coord_sig_msg(&mut conn, txid, sigs).unwrap();
+        assert_eq!(
+            server.take_requests(),
+            vec![message::RequestParams::CoordSig(coordinator::Sig {
+                pubkey: public_key.key,
+                signature,
+                id: txid
+            })]
+        );
+    }
 
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
+    #[test]
+    fn test_server_connection() {
+        let txid =
+            Txid::from_str("fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b")
+                .unwrap();
+        let ctx = secp256k1::Secp256k1::new();
+        let (_, public_key) = create_keys(&ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
+        let mut sigs = BTreeMap::new();
+        let signature = secp256k1::Signature::from_str("304402201a3109a4a6445c1e56416bc39520aada5c8ad089e69ee4f1a40a0901de1a435302204b281ba97da2ab2e40eb65943ae414cc4307406c5eb177b1c646606839a2e99d").unwrap();
+        sigs.insert(public_key.key, signature);
 
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::CoordSig(coordinator::Sig {
-                        pubkey: public_key.key,
-                        signature,
-                        id: txid
-                    }),
-                );
-                Some(message::ResponseResult::Sig(
-                    message::coordinator::SigResult { ack: true },
-                ))
-            })
-            .unwrap();
-        cli_thread.join().unwrap();
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::Sig(
+                message::coordinator::SigResult { ack: true },
+            )]));
+
+        // The connection can be used as a transport
+        let mut conn = connect_coordinator(&server, &client_privkey);
+        assert_eq!(conn.kind, ServerKind::Coordinator);
+        assert_eq!(conn.host, server.addr().into());
+        send_coord_sig_msg(&mut conn, txid, sigs).unwrap();
+        assert_eq!(server.take_requests().len(), 1);
+
+        // A hostname is resolved when connecting
+        let host = Endpoint::from_str(&format!("localhost:{}", server.addr().port())).unwrap();
+        let conn = ServerConnection::connect(
+            ServerKind::Coordinator,
+            &host,
+            None,
+            &client_privkey,
+            &server.noise_key(),
+        )
+        .expect("Connecting by hostname");
+        assert_eq!(conn.host, host);
+        let unresolvable = Endpoint::from_str("revaultd-test.invalid:8383").unwrap();
+        let err = ServerConnection::connect(
+            ServerKind::Coordinator,
+            &unresolvable,
+            None,
+            &client_privkey,
+            &server.noise_key(),
+        )
+        .map(|_| ())
+        .unwrap_err();
+        assert!(err.to_string().contains("revaultd-test.invalid"), "{}", err);
+
+        assert_eq!(ServerKind::Coordinator.to_string(), "coordinator");
+        assert_eq!(ServerKind::Cosigner.to_string(), "cosigner");
+        assert_eq!(ServerKind::Watchtower.to_string(), "watchtower");
     }
 
+    // The connection goes out from the configured address, and is otherwise the same.
+    #[cfg(target_os = "linux")]
     #[test]
-    fn test_share_rev_signatures_not_acked() {
+    fn test_server_connection_bind_address() {
+        let txid =
+            Txid::from_str("fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b")
+                .unwrap();
+        let ctx = secp256k1::Secp256k1::new();
+        let (_, public_key) = create_keys(&ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
+        let mut sigs = BTreeMap::new();
+        let signature = secp256k1::Signature::from_str("304402201a3109a4a6445c1e56416bc39520aada5c8ad089e69ee4f1a40a0901de1a435302204b281ba97da2ab2e40eb65943ae414cc4307406c5eb177b1c646606839a2e99d").unwrap();
+        sigs.insert(public_key.key, signature);
+
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::Sig(
+                message::coordinator::SigResult { ack: true },
+            )]));
+        let mut conn = ServerConnection::connect(
+            ServerKind::Coordinator,
+            &server.addr().into(),
+            Some("127.0.0.2".parse().unwrap()),
+            &client_privkey,
+            &server.noise_key(),
+        )
+        .expect("Connecting from 127.0.0.2");
+        send_coord_sig_msg(&mut conn, txid, sigs).unwrap();
+        assert_eq!(server.take_requests().len(), 1);
+
+        // An address that isn't ours can't be used
+        ServerConnection::connect(
+            ServerKind::Coordinator,
+            &server.addr().into(),
+            Some("192.0.2.1".parse().unwrap()),
+            &client_privkey,
+            &server.noise_key(),
+        )
+        .map(|_| ())
+        .expect_err("Connecting from an address that isn't ours");
+    }
+
+    #[test]
+    fn test_push_rev_signatures_not_acked() {
         let ctx = secp256k1::Secp256k1::new();
         let (private_key, public_key) =
             create_keys(&ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
@@ -553,45 +1431,36 @@ mod tests {
             is_fully_signed: false,
         };
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
-
-        // client thread
-        let cli_thread = thread::spawn(move || {
-            assert!(
-                coord_share_rev_signatures(addr, &client_privkey, &server_pubkey, &[db_tx])
-                    .unwrap_err()
-                    .to_string()
-                    .contains(&CommunicationError::SignatureStorage.to_string())
-            );
-        });
-
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
-
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::CoordSig(coordinator::Sig {
-                        pubkey: public_key.key,
-                        signature,
-                        id: other_cancel.txid(),
-                    }),
-                );
-                Some(message::ResponseResult::Sig(
-                    message::coordinator::SigResult { ack: false },
-                ))
-            })
-            .unwrap();
-        cli_thread.join().unwrap();
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::Sig(
+                message::coordinator::SigResult { ack: false },
+            )]));
+
+        let mut delivered = Vec::new();
+        assert!(push_queued_signatures(
+            &single_coordinator(server.addr(), server.noise_key()),
+            &client_privkey,
+            &queued_sigs(&[db_tx]),
+            |_, queued_sig| delivered.push(queued_sig.id),
+        )
+        .unwrap_err()
+        .to_string()
+        .contains(&CommunicationError::SignatureStorage.to_string()));
+        assert!(delivered.is_empty());
+        assert_eq!(
+            server.take_requests(),
+            vec![message::RequestParams::CoordSig(coordinator::Sig {
+                pubkey: public_key.key,
+                signature,
+                id: other_cancel.txid(),
+            })]
+        );
     }
 
     #[test]
-    fn test_share_rev_signatures() {
+    fn test_push_rev_signatures() {
         let ctx = secp256k1::Secp256k1::new();
         let (privkey, public_key) = create_keys(&ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
         let mut cancel =
@@ -654,78 +1523,51 @@ mod tests {
             is_fully_signed: false,
         };
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let ack = || message::ResponseResult::Sig(message::coordinator::SigResult { ack: true });
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![ack(), ack(), ack()]));
 
-        // client thread
-        let cli_thread = thread::spawn(move || {
-            coord_share_rev_signatures(
-                addr,
+        let mut delivered = Vec::new();
+        assert_eq!(
+            push_queued_signatures(
+                &single_coordinator(server.addr(), server.noise_key()),
                 &client_privkey,
-                &server_pubkey,
-                &[db_cancel, db_emer, db_unemer],
+                &queued_sigs(&[db_cancel, db_emer, db_unemer]),
+                |coordinator_key, queued_sig| {
+                    assert_eq!(coordinator_key, &server.noise_key());
+                    delivered.push(queued_sig.id)
+                },
             )
-            .unwrap();
-        });
-
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
-
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::CoordSig(coordinator::Sig {
-                        pubkey: public_key.key,
-                        signature: signature_cancel,
-                        id: other_cancel.txid(),
-                    }),
-                );
-                Some(message::ResponseResult::Sig(
-                    message::coordinator::SigResult { ack: true },
-                ))
-            })
-            .unwrap();
-
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::CoordSig(coordinator::Sig {
-                        pubkey: public_key.key,
-                        signature: signature_emer,
-                        id: other_emer.txid(),
-                    }),
-                );
-                Some(message::ResponseResult::Sig(
-                    message::coordinator::SigResult { ack: true },
-                ))
-            })
-            .unwrap();
-
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::CoordSig(coordinator::Sig {
-                        pubkey: public_key.key,
-                        signature: signature_unemer,
-                        id: other_unvault_emer.txid(),
-                    }),
-                );
-                Some(message::ResponseResult::Sig(
-                    message::coordinator::SigResult { ack: true },
-                ))
-            })
-            .unwrap();
-        cli_thread.join().unwrap();
+            .unwrap(),
+            3
+        );
+        assert_eq!(delivered, vec![0, 1, 2]);
+        assert_eq!(
+            server.take_requests(),
+            vec![
+                message::RequestParams::CoordSig(coordinator::Sig {
+                    pubkey: public_key.key,
+                    signature: signature_cancel,
+                    id: other_cancel.txid(),
+                }),
+                message::RequestParams::CoordSig(coordinator::Sig {
+                    pubkey: public_key.key,
+                    signature: signature_emer,
+                    id: other_emer.txid(),
+                }),
+                message::RequestParams::CoordSig(coordinator::Sig {
+                    pubkey: public_key.key,
+                    signature: signature_unemer,
+                    id: other_unvault_emer.txid(),
+                }),
+            ]
+        );
     }
 
     #[test]
-    fn test_share_unvault_signatures() {
+    fn test_push_unvault_signatures() {
         let mut unvault =
                 UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAajRZE5yVgzG9McmOyy/WdcYdrGrK15bB5N/Hg8zhKOkAQAAAAD9////ArhhpDUAAAAAIgAgFZlOQkpDkFSsLUfyeMGVAOT3T88jZM7L/XlVZoJ2jnAwdQAAAAAAACIAILKCCA/RbV3QMPMrwwQmk4Ark4w1WyElM27WtBgftq6ZAAAAAAABASsA6aQ1AAAAACIAIPQJ3LCGXPIO5iXX0/Yp3wHlpao7cQbPd4q3gxp0J/w2AQMEAQAAAAEFR1IhA47+JRqdt+oloFosla9hWUYVf5YQKDbuq4KO13JS45KgIQMKcLWzABxb/9YBQe+bJRW3v3om8S2LNMGUKSp5K+PQ+1KuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGpIQMVlEoh50lasMhcdwnrmnCp2ROlGY5CrH+HtxQmfZDZ06xRh2R2qRS/INUX1CaP7Pbn5GmtGYu2wgqjnIisa3apFO/kceq8yo9w69g4VVtlFAf739qTiKxsk1KHZ1IhAnddfXi3N38A+aEQ74sUdeuV7sg+2L3ijTjMHMEAfq3cIQLWP96FqjfC5qKQkC2WhYbbLJx1FbNSAjsnMfwDnK0jD1KvARKyaCICAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBJSEDjv4lGp236iWgWiyVr2FZRhV/lhAoNu6rgo7XclLjkqCsUYciAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
         let ctx = secp256k1::Secp256k1::new();
@@ -746,40 +1588,37 @@ mod tests {
             is_fully_signed: false,
         };
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
-
-        // client thread
-        let cli_thread = thread::spawn(move || {
-            share_unvault_signatures(addr, &client_privkey, &server_pubkey, &db_unvault).unwrap();
-        });
-
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
-
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::CoordSig(coordinator::Sig {
-                        pubkey: public_key.key,
-                        signature,
-                        id: other_unvault.txid(),
-                    }),
-                );
-                Some(message::ResponseResult::Sig(
-                    message::coordinator::SigResult { ack: true },
-                ))
-            })
-            .unwrap();
-        cli_thread.join().unwrap();
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::Sig(
+                message::coordinator::SigResult { ack: true },
+            )]));
+
+        let mut delivered = Vec::new();
+        assert_eq!(
+            push_queued_signatures(
+                &single_coordinator(server.addr(), server.noise_key()),
+                &client_privkey,
+                &queued_sigs(&[db_unvault]),
+                |_, queued_sig| delivered.push(queued_sig.id),
+            )
+            .unwrap(),
+            1
+        );
+        assert_eq!(delivered, vec![0]);
+        assert_eq!(
+            server.take_requests(),
+            vec![message::RequestParams::CoordSig(coordinator::Sig {
+                pubkey: public_key.key,
+                signature,
+                id: other_unvault.txid(),
+            })]
+        );
     }
 
     #[test]
-    fn test_share_unvault_signatures_not_acked() {
+    fn test_push_unvault_signatures_not_acked() {
         let mut unvault =
                 UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAajRZE5yVgzG9McmOyy/WdcYdrGrK15bB5N/Hg8zhKOkAQAAAAD9////ArhhpDUAAAAAIgAgFZlOQkpDkFSsLUfyeMGVAOT3T88jZM7L/XlVZoJ2jnAwdQAAAAAAACIAILKCCA/RbV3QMPMrwwQmk4Ark4w1WyElM27WtBgftq6ZAAAAAAABASsA6aQ1AAAAACIAIPQJ3LCGXPIO5iXX0/Yp3wHlpao7cQbPd4q3gxp0J/w2AQMEAQAAAAEFR1IhA47+JRqdt+oloFosla9hWUYVf5YQKDbuq4KO13JS45KgIQMKcLWzABxb/9YBQe+bJRW3v3om8S2LNMGUKSp5K+PQ+1KuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGpIQMVlEoh50lasMhcdwnrmnCp2ROlGY5CrH+HtxQmfZDZ06xRh2R2qRS/INUX1CaP7Pbn5GmtGYu2wgqjnIisa3apFO/kceq8yo9w69g4VVtlFAf739qTiKxsk1KHZ1IhAnddfXi3N38A+aEQ74sUdeuV7sg+2L3ijTjMHMEAfq3cIQLWP96FqjfC5qKQkC2WhYbbLJx1FbNSAjsnMfwDnK0jD1KvARKyaCICAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBJSEDjv4lGp236iWgWiyVr2FZRhV/lhAoNu6rgo7XclLjkqCsUYciAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
         let ctx = secp256k1::Secp256k1::new();
@@ -800,41 +1639,212 @@ mod tests {
             is_fully_signed: false,
         };
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::Sig(
+                message::coordinator::SigResult { ack: false },
+            )]));
+
+        let mut delivered = Vec::new();
+        assert!(push_queued_signatures(
+            &single_coordinator(server.addr(), server.noise_key()),
+            &client_privkey,
+            &queued_sigs(&[db_unvault]),
+            |_, queued_sig| delivered.push(queued_sig.id),
+        )
+        .unwrap_err()
+        .to_string()
+        .contains(&CommunicationError::SignatureStorage.to_string()));
+        assert!(delivered.is_empty());
+        assert_eq!(
+            server.take_requests(),
+            vec![message::RequestParams::CoordSig(coordinator::Sig {
+                pubkey: public_key.key,
+                signature,
+                id: other_unvault.txid(),
+            })]
+        );
+    }
 
-        // client thread
-        let cli_thread = thread::spawn(move || {
-            assert!(
-                share_unvault_signatures(addr, &client_privkey, &server_pubkey, &db_unvault,)
-                    .unwrap_err()
-                    .to_string()
-                    .contains(&CommunicationError::SignatureStorage.to_string())
-            );
-        });
+    // A coordinator acknowledging the signature it is sent on a single connection.
+    fn stub_coordinator(
+        label: &str,
+        listener: TcpListener,
+        client_pubkey: revault_net::noise::PublicKey,
+    ) -> StubServer {
+        StubServer::builder(label)
+            .listener(listener)
+            .client(client_pubkey)
+            .connections(1)
+            .start(scripted(vec![message::ResponseResult::Sig(
+                message::coordinator::SigResult { ack: true },
+            )]))
+    }
 
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
+    #[test]
+    fn test_coordinator_failover() {
+        let txid =
+            Txid::from_str("fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b")
+                .unwrap();
+        let ctx = secp256k1::Secp256k1::new();
+        let (_, public_key) = create_keys(&ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
+        let mut sigs = BTreeMap::new();
+        let signature = secp256k1::Signature::from_str("304402201a3109a4a6445c1e56416bc39520aada5c8ad089e69ee4f1a40a0901de1a435302204b281ba97da2ab2e40eb65943ae414cc4307406c5eb177b1c646606839a2e99d").unwrap();
+        sigs.insert(public_key.key, signature);
 
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::CoordSig(coordinator::Sig {
-                        pubkey: public_key.key,
-                        signature,
-                        id: other_unvault.txid(),
-                    }),
-                );
-                Some(message::ResponseResult::Sig(
-                    message::coordinator::SigResult { ack: false },
-                ))
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let primary_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let backup_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let primary_addr = primary_listener.local_addr().unwrap();
+        let primary = CoordinatorEndpoint {
+            host: primary_addr.into(),
+            noise_key: test_keypair("primary").0,
+        };
+        let backup = CoordinatorEndpoint {
+            host: backup_listener.local_addr().unwrap().into(),
+            noise_key: test_keypair("backup").0,
+        };
+        let coordinators =
+            Coordinators::new(vec![primary.clone(), backup.clone()], Arc::new(SystemClock));
+        let share_sigs = |coordinators: &Coordinators| {
+            coordinators
+                .with_coordinator(&client_privkey, |conn| {
+                    send_coord_sig_msg(conn, txid, sigs.clone())
+                })
+                .map(|(endpoint, _)| endpoint)
+        };
+
+        // Both are up, we use the main one.
+        let server = stub_coordinator("primary", primary_listener, client_pubkey);
+        assert_eq!(share_sigs(&coordinators).unwrap(), primary);
+        assert_eq!(coordinators.active(), primary);
+        // Now kill it, we fail over to the backup.
+        server.join();
+        let server = stub_coordinator(
+            "backup",
+            backup_listener.try_clone().unwrap(),
+            client_pubkey,
+        );
+        assert_eq!(share_sigs(&coordinators).unwrap(), backup);
+        server.join();
+        let statuses = coordinators.statuses();
+        assert_eq!(statuses.len(), 2);
+        assert!(!statuses[0].active && !statuses[0].reachable);
+        assert_eq!(statuses[0].consecutive_failures, 1);
+        assert!(statuses[0].last_error.is_some());
+        assert!(statuses[1].active && statuses[1].reachable);
+        assert_eq!(statuses[1].consecutive_failures, 0);
+        assert_eq!(statuses[1].host, backup.host.to_string());
+
+        // Something comes back at the main one's address, but without its key. The handshake
+        // fails and we stick to the backup.
+        let impostor = stub_coordinator(
+            "impostor",
+            TcpListener::bind(primary_addr).unwrap(),
+            client_pubkey,
+        );
+        let server = stub_coordinator(
+            "backup",
+            backup_listener.try_clone().unwrap(),
+            client_pubkey,
+        );
+        assert_eq!(share_sigs(&coordinators).unwrap(), backup);
+        server.join();
+        let failures = impostor.join();
+        assert_eq!(coordinators.statuses()[0].consecutive_failures, 2);
+        // Both sides tell it's the key of the server which isn't the one we expected.
+        let mismatch = KeyMismatch {
+            of: HandshakeSide::Responder,
+            expected: vec![primary.noise_key],
+            presented: test_keypair("impostor").0,
+        };
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].side, HandshakeSide::Responder);
+        assert_eq!(failures[0].message, HandshakeMessage::First);
+        assert_eq!(failures[0].mismatch.as_ref(), Some(&mismatch));
+        let failure = initiator_failure(primary_addr).unwrap();
+        assert_eq!(failure.side, HandshakeSide::Initiator);
+        assert_eq!(failure.message, HandshakeMessage::Second);
+        assert_eq!(failure.mismatch, Some(mismatch));
+
+        // Once the main one is up again, we get back to it.
+        let primary_listener = TcpListener::bind(primary_addr).unwrap();
+        let server = stub_coordinator("primary", primary_listener, client_pubkey);
+        assert_eq!(share_sigs(&coordinators).unwrap(), primary);
+        server.join();
+        let statuses = coordinators.statuses();
+        assert!(statuses[0].active && statuses[0].reachable);
+        assert_eq!(statuses[0].consecutive_failures, 0);
+        assert!(!statuses[1].active);
+
+        // If none of them is up, we get the error of the last one.
+        drop(backup_listener);
+        share_sigs(&coordinators).unwrap_err();
+        let statuses = coordinators.statuses();
+        assert_eq!(statuses[0].consecutive_failures, 1);
+        assert_eq!(statuses[1].consecutive_failures, 1);
+        assert!(statuses[0].active);
+    }
+
+    #[test]
+    fn test_handshake_diagnostics() {
+        // The keys are always the same for a label
+        assert_eq!(test_keypair("client").0, test_keypair("client").0);
+        assert_ne!(test_keypair("client").0, test_keypair("coordinator").0);
+
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let (intruder_pubkey, intruder_privkey) = test_keypair("intruder");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![]));
+
+        // A client the server doesn't know
+        ServerConnection::connect(
+            ServerKind::Coordinator,
+            &server.addr().into(),
+            None,
+            &intruder_privkey,
+            &server.noise_key(),
+        )
+        .unwrap_err();
+        let failure = initiator_failure(server.addr()).unwrap();
+        assert_eq!(
+            failure.mismatch,
+            Some(KeyMismatch {
+                of: HandshakeSide::Initiator,
+                expected: vec![client_pubkey],
+                presented: intruder_pubkey,
             })
-            .unwrap();
-        cli_thread.join().unwrap();
+        );
+        assert!(failure
+            .to_string()
+            .contains("the initiator presented static key"));
+
+        // A known one, expecting another server
+        let failures = loop {
+            let failures = server.handshake_failures();
+            if !failures.is_empty() {
+                break failures;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(failures[0].side, HandshakeSide::Responder);
+        ServerConnection::connect(
+            ServerKind::Coordinator,
+            &server.addr().into(),
+            None,
+            &client_privkey,
+            &test_keypair("backup").0,
+        )
+        .unwrap_err();
+        let failure = initiator_failure(server.addr()).unwrap();
+        assert_eq!(failure.mismatch.unwrap().of, HandshakeSide::Responder);
+
+        // Then it all goes fine
+        let conn = connect_coordinator(&server, &client_privkey);
+        drop(conn);
+        assert!(server.take_requests().is_empty());
     }
 
     #[test]
@@ -852,13 +1862,13 @@ mod tests {
             (gen_keypair(), gen_keypair());
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = listener.local_addr().unwrap();
-        let cosigs = vec![(addr, server_pubkey)];
+        let cosigs = vec![(Endpoint::from(addr), server_pubkey)];
 
         // client thread
         let cli_thread = thread::spawn(move || {
             // Our spend has no partial sigs...
             assert_eq!(spend.psbt().inputs.get(0).unwrap().partial_sigs.len(), 0);
-            fetch_cosigs_signatures(&ctx, &client_privkey, &mut spend, &cosigs).unwrap();
+            poll_and_add_signatures(&ctx, &client_privkey, &mut spend, &cosigs).unwrap();
             // Now our spend has one :)
             assert_eq!(spend.psbt().inputs.get(0).unwrap().partial_sigs.len(), 1);
         });
@@ -900,12 +1910,12 @@ mod tests {
             (gen_keypair(), gen_keypair());
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = listener.local_addr().unwrap();
-        let cosigs = vec![(addr, server_pubkey)];
+        let cosigs = vec![(Endpoint::from(addr), server_pubkey)];
 
         // client thread
         let cli_thread = thread::spawn(move || {
             assert!(
-                fetch_cosigs_signatures(&secp, &client_privkey, &mut spend, &cosigs)
+                poll_and_add_signatures(&secp, &client_privkey, &mut spend, &cosigs)
                     .unwrap_err()
                     .to_string()
                     .contains(&CommunicationError::CosigAlreadySigned.to_string())
@@ -945,7 +1955,7 @@ mod tests {
             (gen_keypair(), gen_keypair());
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = listener.local_addr().unwrap();
-        let cosigs = vec![(addr, server_pubkey)];
+        let cosigs = vec![(Endpoint::from(addr), server_pubkey)];
 
         psbt.inputs[0]
             .partial_sigs
@@ -956,7 +1966,7 @@ mod tests {
         // client thread
         let cli_thread = thread::spawn(move || {
             assert!(
-                fetch_cosigs_signatures(&ctx, &client_privkey, &mut spend, &cosigs)
+                poll_and_add_signatures(&ctx, &client_privkey, &mut spend, &cosigs)
                     .unwrap_err()
                     .to_string()
                     .contains(&CommunicationError::CosigInsanePsbt.to_string())
@@ -979,6 +1989,76 @@ mod tests {
         cli_thread.join().unwrap();
     }
 
+    // The cosigning servers are polled all at once, so it only takes as long as the slowest.
+    #[test]
+    fn test_poll_cosigning_servers_parallel() {
+        let spend = SpendTransaction::from_psbt_str("cHNidP8BAP0ZAQIAAAADSe9QbkOAlLapVzhNT2J2sCWXMqe2x7g7klEr3N6+p8AAAAAAAAYAAABwwCBKiamcBn5fj0oQ3WcAU+twE4XemcK4G2nlprqBKAAAAAAABgAAAAwCYIUh0y2bkIH8BVZ/evuFulOCxOyGr/rvZnR2k/9aAAAAAAAGAAAABFCoAAAAAAAAIgAgvXwvxBU2X03+pufsytFJ2dS4BKVXIKMQmyxUXTbPJPmA8PoCAAAAABYAFCMDXGnefAb487YxeNjnpbUzH+pEQEtMAAAAAAAWABT+rq2LTo+bnAo3ZQoeUg0F6xVZbIx3EwIAAAAAIgAgfAYV/vqzwEWHS6kVMjA1yQRbIQqq//o7m4ik0eSSlasAAAAAAAEBKzb0VQMAAAAAIgAgEyIAQqFnv+D0rMmVvusK3TC6fPyFk7aU1PZ8+Ttm23IBAwQBAAAAAQXBUiEDNWoO4CCloCul5eCLd1+XLPxP4LMIsUy+XM01wlm59wIhAqQ3tGeAeMBPPR26fn0kuL0CS0AybrDlu8NwIzFOOukzIQJoBBIwDWTXwjMse2MiB8/kIcFOZACiADcmZltiEl85N1OuZHapFIe9/DRONZOp5OAQ6RCrIDclCDEjiKxrdqkUJs2E27SQYhbh4yxNkO+lDnFqCCaIrGyTa3apFBtcD9uL3TRJt1uCIj2J8Ub4YjvgiKxsk1OHZ1ayaCIGAmgEEjANZNfCMyx7YyIHz+QhwU5kAKIANyZmW2ISXzk3CO9FHBcBAAAAIgYCpDe0Z4B4wE89Hbp+fSS4vQJLQDJusOW7w3AjMU466TMIW+FtfgEAAAAiBgM1ag7gIKWgK6Xl4It3X5cs/E/gswixTL5czTXCWbn3AgjDFaC/AQAAAAABASs2rG0BAAAAACIAIICUwlAfLlUkhU44Hpkj/LEDNAdwME4fm3jtWfXwMwL7AQMEAQAAAAEFwVIhAgSNQIWSNnYSrfEl8juzTKw9o3BjYQ+DgbyizShqKzIcIQN+tRtybpIxVK9IdwxsTxFgy2YsiQqtnGvnowXelPblJiEC25bXunBKDpmrAvXiBbJ/+x9Oo5pL+8FhKgAqXSesn0VTrmR2qRTWWGTXm1UxE4rqqD2FkiKS94r8YYisa3apFCBven2wd5QCFoHAl/iRHg+9SJkgiKxsk2t2qRRP/mE3OesTO6kSJOgsBAoyLTfO8oisbJNTh2dWsmgiBgIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHAjDFaC/AAAAACIGAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FCO9FHBcAAAAAIgYDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYIW+FtfgAAAAAAAQErtgyYAAAAAAAiACCAlMJQHy5VJIVOOB6ZI/yxAzQHcDBOH5t47Vn18DMC+wEDBAEAAAABBcFSIQIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHCEDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYhAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FU65kdqkU1lhk15tVMROK6qg9hZIikveK/GGIrGt2qRQgb3p9sHeUAhaBwJf4kR4PvUiZIIisbJNrdqkUT/5hNznrEzupEiToLAQKMi03zvKIrGyTU4dnVrJoIgYCBI1AhZI2dhKt8SXyO7NMrD2jcGNhD4OBvKLNKGorMhwIwxWgvwAAAAAiBgLblte6cEoOmasC9eIFsn/7H06jmkv7wWEqACpdJ6yfRQjvRRwXAAAAACIGA361G3JukjFUr0h3DGxPEWDLZiyJCq2ca+ejBd6U9uUmCFvhbX4AAAAAACICArFlfWaPsqMsvdC+/3Hise+ubUHtj4n5Uz7qaI0bCfCWCBhRVloBAAAAIgICtg6ewcvt4XnF35qT+j9KoCYt4+vS8hXmOn1NsO/QppUIgryGbgEAAAAiAgOJmnB0i/XOb8ITGRA3itrYfvWx6/B8PGMiu2SYfOACFQhTR/BbAQAAAAAAACICAr+BTfGuO1VRPxE1DJoFIsH1Vu5Dk5lSullVQjCXjVlICEPuDksBAAAAIgIC+G7/TA9DNgnMf4Nup2Py3XAF8UCLmziV3Vw4Z2KsJcwIpbzhFQEAAAAiAgOpos5KhVRQaTPJTi3mk12g5sApoQNVGdOpMcMmn7C7gwieIH0+AQAAAAA=").unwrap();
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let cosigs: Vec<_> = [400, 800, 1200]
+            .iter()
+            .map(|latency| {
+                let (addr, key, _) = stub_cosigner(
+                    client_pubkey,
+                    Duration::from_millis(*latency),
+                    Some(spend.clone()),
+                );
+                (Endpoint::from(addr), key)
+            })
+            .collect();
+
+        let start = Instant::now();
+        let results = poll_cosigning_servers(
+            &client_privkey,
+            None,
+            &spend,
+            &cosigs,
+            Duration::from_secs(30),
+        );
+        let elapsed = start.elapsed();
+        assert_eq!(results.len(), 3);
+        for res in results {
+            assert_eq!(res.unwrap(), spend);
+        }
+        assert!(elapsed >= Duration::from_millis(1200), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(2400), "{:?}", elapsed);
+    }
+
+    // We don't wait for the slow cosigning servers past the timeout, but still get the answers
+    // from the others.
+    #[test]
+    fn test_poll_cosigning_servers_timeout() {
+        let spend = SpendTransaction::from_psbt_str("cHNidP8BAP0ZAQIAAAADSe9QbkOAlLapVzhNT2J2sCWXMqe2x7g7klEr3N6+p8AAAAAAAAYAAABwwCBKiamcBn5fj0oQ3WcAU+twE4XemcK4G2nlprqBKAAAAAAABgAAAAwCYIUh0y2bkIH8BVZ/evuFulOCxOyGr/rvZnR2k/9aAAAAAAAGAAAABFCoAAAAAAAAIgAgvXwvxBU2X03+pufsytFJ2dS4BKVXIKMQmyxUXTbPJPmA8PoCAAAAABYAFCMDXGnefAb487YxeNjnpbUzH+pEQEtMAAAAAAAWABT+rq2LTo+bnAo3ZQoeUg0F6xVZbIx3EwIAAAAAIgAgfAYV/vqzwEWHS6kVMjA1yQRbIQqq//o7m4ik0eSSlasAAAAAAAEBKzb0VQMAAAAAIgAgEyIAQqFnv+D0rMmVvusK3TC6fPyFk7aU1PZ8+Ttm23IBAwQBAAAAAQXBUiEDNWoO4CCloCul5eCLd1+XLPxP4LMIsUy+XM01wlm59wIhAqQ3tGeAeMBPPR26fn0kuL0CS0AybrDlu8NwIzFOOukzIQJoBBIwDWTXwjMse2MiB8/kIcFOZACiADcmZltiEl85N1OuZHapFIe9/DRONZOp5OAQ6RCrIDclCDEjiKxrdqkUJs2E27SQYhbh4yxNkO+lDnFqCCaIrGyTa3apFBtcD9uL3TRJt1uCIj2J8Ub4YjvgiKxsk1OHZ1ayaCIGAmgEEjANZNfCMyx7YyIHz+QhwU5kAKIANyZmW2ISXzk3CO9FHBcBAAAAIgYCpDe0Z4B4wE89Hbp+fSS4vQJLQDJusOW7w3AjMU466TMIW+FtfgEAAAAiBgM1ag7gIKWgK6Xl4It3X5cs/E/gswixTL5czTXCWbn3AgjDFaC/AQAAAAABASs2rG0BAAAAACIAIICUwlAfLlUkhU44Hpkj/LEDNAdwME4fm3jtWfXwMwL7AQMEAQAAAAEFwVIhAgSNQIWSNnYSrfEl8juzTKw9o3BjYQ+DgbyizShqKzIcIQN+tRtybpIxVK9IdwxsTxFgy2YsiQqtnGvnowXelPblJiEC25bXunBKDpmrAvXiBbJ/+x9Oo5pL+8FhKgAqXSesn0VTrmR2qRTWWGTXm1UxE4rqqD2FkiKS94r8YYisa3apFCBven2wd5QCFoHAl/iRHg+9SJkgiKxsk2t2qRRP/mE3OesTO6kSJOgsBAoyLTfO8oisbJNTh2dWsmgiBgIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHAjDFaC/AAAAACIGAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FCO9FHBcAAAAAIgYDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYIW+FtfgAAAAAAAQErtgyYAAAAAAAiACCAlMJQHy5VJIVOOB6ZI/yxAzQHcDBOH5t47Vn18DMC+wEDBAEAAAABBcFSIQIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHCEDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYhAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FU65kdqkU1lhk15tVMROK6qg9hZIikveK/GGIrGt2qRQgb3p9sHeUAhaBwJf4kR4PvUiZIIisbJNrdqkUT/5hNznrEzupEiToLAQKMi03zvKIrGyTU4dnVrJoIgYCBI1AhZI2dhKt8SXyO7NMrD2jcGNhD4OBvKLNKGorMhwIwxWgvwAAAAAiBgLblte6cEoOmasC9eIFsn/7H06jmkv7wWEqACpdJ6yfRQjvRRwXAAAAACIGA361G3JukjFUr0h3DGxPEWDLZiyJCq2ca+ejBd6U9uUmCFvhbX4AAAAAACICArFlfWaPsqMsvdC+/3Hise+ubUHtj4n5Uz7qaI0bCfCWCBhRVloBAAAAIgICtg6ewcvt4XnF35qT+j9KoCYt4+vS8hXmOn1NsO/QppUIgryGbgEAAAAiAgOJmnB0i/XOb8ITGRA3itrYfvWx6/B8PGMiu2SYfOACFQhTR/BbAQAAAAAAACICAr+BTfGuO1VRPxE1DJoFIsH1Vu5Dk5lSullVQjCXjVlICEPuDksBAAAAIgIC+G7/TA9DNgnMf4Nup2Py3XAF8UCLmziV3Vw4Z2KsJcwIpbzhFQEAAAAiAgOpos5KhVRQaTPJTi3mk12g5sApoQNVGdOpMcMmn7C7gwieIH0+AQAAAAA=").unwrap();
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let (fast_addr, fast_key, fast_requests) = stub_cosigner(
+            client_pubkey,
+            Duration::from_millis(100),
+            Some(spend.clone()),
+        );
+        let (slow_addr, slow_key, slow_requests) =
+            stub_cosigner(client_pubkey, Duration::from_secs(10), Some(spend.clone()));
+        let cosigs = vec![
+            (Endpoint::from(slow_addr), slow_key),
+            (Endpoint::from(fast_addr), fast_key),
+        ];
+
+        let start = Instant::now();
+        let mut results = poll_cosigning_servers(
+            &client_privkey,
+            None,
+            &spend,
+            &cosigs,
+            Duration::from_secs(1),
+        );
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(results.pop().unwrap().unwrap(), spend);
+        assert!(matches!(
+            results.pop().unwrap(),
+            Err(CommunicationError::CosigTimeout)
+        ));
+        assert_eq!(fast_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(slow_requests.load(Ordering::SeqCst), 1);
+    }
+
     #[test]
     #[should_panic(expected = "assertion failed: tx.is_finalized()")]
     fn test_announce_spend_transaction_not_finalized() {
@@ -1017,8 +2097,13 @@ mod tests {
                 .expect("Server channel binding and accepting");
         });
 
-        announce_spend_transaction(addr, &client_privkey, &server_pubkey, spend, outpoints)
-            .unwrap();
+        announce_spend_transaction(
+            &single_coordinator(addr, server_pubkey),
+            &client_privkey,
+            spend,
+            outpoints,
+        )
+        .unwrap();
 
         server_thread.join().unwrap();
     }
@@ -1055,44 +2140,28 @@ mod tests {
         let other_spend = spend.clone();
         let other_outpoints = outpoints.clone();
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
-
-        // client thread
-        let cli_thread = thread::spawn(move || {
-            assert!(announce_spend_transaction(
-                addr,
-                &client_privkey,
-                &server_pubkey,
-                spend,
-                outpoints,
-            )
-            .unwrap_err()
-            .to_string()
-            .contains(&CommunicationError::SpendTxStorage.to_string()));
-        });
-
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
-
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::SetSpendTx(SetSpendTx::from_spend_tx(
-                        other_outpoints,
-                        other_spend,
-                    ))
-                );
-                Some(message::ResponseResult::SetSpend(
-                    message::coordinator::SetSpendResult { ack: false },
-                ))
-            })
-            .unwrap();
-        cli_thread.join().unwrap();
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::SetSpend(
+                message::coordinator::SetSpendResult { ack: false },
+            )]));
+
+        assert!(announce_spend_transaction(
+            &single_coordinator(server.addr(), server.noise_key()),
+            &client_privkey,
+            spend,
+            outpoints,
+        )
+        .unwrap_err()
+        .to_string()
+        .contains(&CommunicationError::SpendTxStorage.to_string()));
+        assert_eq!(
+            server.take_requests(),
+            vec![message::RequestParams::SetSpendTx(
+                SetSpendTx::from_spend_tx(other_outpoints, other_spend)
+            )]
+        );
     }
 
     #[test]
@@ -1127,36 +2196,26 @@ mod tests {
         let other_spend = spend.clone();
         let other_outpoints = outpoints.clone();
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
-
-        // client thread
-        let cli_thread = thread::spawn(move || {
-            announce_spend_transaction(addr, &client_privkey, &server_pubkey, spend, outpoints)
-                .unwrap();
-        });
-
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
-
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::SetSpendTx(SetSpendTx::from_spend_tx(
-                        other_outpoints,
-                        other_spend,
-                    ))
-                );
-                Some(message::ResponseResult::SetSpend(
-                    message::coordinator::SetSpendResult { ack: true },
-                ))
-            })
-            .unwrap();
-        cli_thread.join().unwrap();
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::SetSpend(
+                message::coordinator::SetSpendResult { ack: true },
+            )]));
+
+        announce_spend_transaction(
+            &single_coordinator(server.addr(), server.noise_key()),
+            &client_privkey,
+            spend,
+            outpoints,
+        )
+        .unwrap();
+        assert_eq!(
+            server.take_requests(),
+            vec![message::RequestParams::SetSpendTx(
+                SetSpendTx::from_spend_tx(other_outpoints, other_spend)
+            )]
+        );
     }
 
     #[test]
@@ -1168,38 +2227,24 @@ mod tests {
         sigs.insert(public_key.key, signature);
         let other_sigs = sigs.clone();
 
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
+        let (client_pubkey, client_privkey) = test_keypair("client");
+        let server = StubServer::builder("coordinator")
+            .client(client_pubkey)
+            .start(scripted(vec![message::ResponseResult::Sigs(
+                message::coordinator::Sigs {
+                    signatures: other_sigs,
+                },
+            )]));
         let txid =
             Txid::from_str("cafa9f92be48ba41f9ee67e775b6c4afebd1bdbde5758792e9f30f6dea41e7fb")
                 .unwrap();
-        let same_txid = txid.clone();
-
-        let cli_thread = thread::spawn(move || {
-            let mut transport =
-                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
-            let signatures = get_presigs(&mut transport, txid).unwrap();
-            assert_eq!(signatures, sigs);
-        });
-
-        let mut server_transport =
-            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
-                .expect("Server channel binding and accepting");
 
-        server_transport
-            .read_req(|params| {
-                assert_eq!(
-                    &params,
-                    &message::RequestParams::GetSigs(GetSigs { id: same_txid })
-                );
-                Some(message::ResponseResult::Sigs(message::coordinator::Sigs {
-                    signatures: other_sigs,
-                }))
-            })
-            .unwrap();
-        cli_thread.join().unwrap();
+        let mut conn = connect_coordinator(&server, &client_privkey);
+        assert_eq!(get_presigs(&mut conn, txid).unwrap(), sigs);
+        assert_eq!(
+            server.take_requests(),
+            vec![message::RequestParams::GetSigs(GetSigs { id: txid })]
+        );
     }
 
     #[test]
@@ -1214,4 +2259,131 @@ mod tests {
 
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
+
+    #[test]
+    fn test_check_sigs() {
+        let ctx = secp256k1::Secp256k1::new();
+        let signature = secp256k1::Signature::from_str("304402201a3109a4a6445c1e56416bc39520aada5c8ad089e69ee4f1a40a0901de1a435302204b281ba97da2ab2e40eb65943ae414cc4307406c5eb177b1c646606839a2e99d").unwrap();
+        let mut signatures = BTreeMap::new();
+        for i in 0..MAX_SIGS_PER_TX {
+            let (_, public_key) =
+                create_keys(&ctx, &[i as u8 + 1; secp256k1::constants::SECRET_KEY_SIZE]);
+            signatures.insert(public_key.key, signature);
+        }
+        let mut sigs = Sigs { signatures };
+        check_sigs(&sigs).unwrap();
+
+        // One too many
+        let (_, public_key) = create_keys(&ctx, &[200; secp256k1::constants::SECRET_KEY_SIZE]);
+        sigs.signatures.insert(public_key.key, signature);
+        check_sigs(&sigs).unwrap_err();
+    }
+
+    #[test]
+    fn test_check_signed_spend() {
+        let ctx = secp256k1::Secp256k1::new();
+        let spend = SpendTransaction::from_psbt_str("cHNidP8BAP0ZAQIAAAADSe9QbkOAlLapVzhNT2J2sCWXMqe2x7g7klEr3N6+p8AAAAAAAAYAAABwwCBKiamcBn5fj0oQ3WcAU+twE4XemcK4G2nlprqBKAAAAAAABgAAAAwCYIUh0y2bkIH8BVZ/evuFulOCxOyGr/rvZnR2k/9aAAAAAAAGAAAABFCoAAAAAAAAIgAgvXwvxBU2X03+pufsytFJ2dS4BKVXIKMQmyxUXTbPJPmA8PoCAAAAABYAFCMDXGnefAb487YxeNjnpbUzH+pEQEtMAAAAAAAWABT+rq2LTo+bnAo3ZQoeUg0F6xVZbIx3EwIAAAAAIgAgfAYV/vqzwEWHS6kVMjA1yQRbIQqq//o7m4ik0eSSlasAAAAAAAEBKzb0VQMAAAAAIgAgEyIAQqFnv+D0rMmVvusK3TC6fPyFk7aU1PZ8+Ttm23IBAwQBAAAAAQXBUiEDNWoO4CCloCul5eCLd1+XLPxP4LMIsUy+XM01wlm59wIhAqQ3tGeAeMBPPR26fn0kuL0CS0AybrDlu8NwIzFOOukzIQJoBBIwDWTXwjMse2MiB8/kIcFOZACiADcmZltiEl85N1OuZHapFIe9/DRONZOp5OAQ6RCrIDclCDEjiKxrdqkUJs2E27SQYhbh4yxNkO+lDnFqCCaIrGyTa3apFBtcD9uL3TRJt1uCIj2J8Ub4YjvgiKxsk1OHZ1ayaCIGAmgEEjANZNfCMyx7YyIHz+QhwU5kAKIANyZmW2ISXzk3CO9FHBcBAAAAIgYCpDe0Z4B4wE89Hbp+fSS4vQJLQDJusOW7w3AjMU466TMIW+FtfgEAAAAiBgM1ag7gIKWgK6Xl4It3X5cs/E/gswixTL5czTXCWbn3AgjDFaC/AQAAAAABASs2rG0BAAAAACIAIICUwlAfLlUkhU44Hpkj/LEDNAdwME4fm3jtWfXwMwL7AQMEAQAAAAEFwVIhAgSNQIWSNnYSrfEl8juzTKw9o3BjYQ+DgbyizShqKzIcIQN+tRtybpIxVK9IdwxsTxFgy2YsiQqtnGvnowXelPblJiEC25bXunBKDpmrAvXiBbJ/+x9Oo5pL+8FhKgAqXSesn0VTrmR2qRTWWGTXm1UxE4rqqD2FkiKS94r8YYisa3apFCBven2wd5QCFoHAl/iRHg+9SJkgiKxsk2t2qRRP/mE3OesTO6kSJOgsBAoyLTfO8oisbJNTh2dWsmgiBgIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHAjDFaC/AAAAACIGAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FCO9FHBcAAAAAIgYDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYIW+FtfgAAAAAAAQErtgyYAAAAAAAiACCAlMJQHy5VJIVOOB6ZI/yxAzQHcDBOH5t47Vn18DMC+wEDBAEAAAABBcFSIQIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHCEDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYhAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FU65kdqkU1lhk15tVMROK6qg9hZIikveK/GGIrGt2qRQgb3p9sHeUAhaBwJf4kR4PvUiZIIisbJNrdqkUT/5hNznrEzupEiToLAQKMi03zvKIrGyTU4dnVrJoIgYCBI1AhZI2dhKt8SXyO7NMrD2jcGNhD4OBvKLNKGorMhwIwxWgvwAAAAAiBgLblte6cEoOmasC9eIFsn/7H06jmkv7wWEqACpdJ6yfRQjvRRwXAAAAACIGA361G3JukjFUr0h3DGxPEWDLZiyJCq2ca+ejBd6U9uUmCFvhbX4AAAAAACICArFlfWaPsqMsvdC+/3Hise+ubUHtj4n5Uz7qaI0bCfCWCBhRVloBAAAAIgICtg6ewcvt4XnF35qT+j9KoCYt4+vS8hXmOn1NsO/QppUIgryGbgEAAAAiAgOJmnB0i/XOb8ITGRA3itrYfvWx6/B8PGMiu2SYfOACFQhTR/BbAQAAAAAAACICAr+BTfGuO1VRPxE1DJoFIsH1Vu5Dk5lSullVQjCXjVlICEPuDksBAAAAIgIC+G7/TA9DNgnMf4Nup2Py3XAF8UCLmziV3Vw4Z2KsJcwIpbzhFQEAAAAiAgOpos5KhVRQaTPJTi3mk12g5sApoQNVGdOpMcMmn7C7gwieIH0+AQAAAAA=").unwrap();
+        let txid = spend.txid();
+        check_signed_spend(txid, &spend).unwrap();
+
+        // The cosigner must sign the transaction we sent it, not another one
+        let other_txid =
+            Txid::from_str("cafa9f92be48ba41f9ee67e775b6c4afebd1bdbde5758792e9f30f6dea41e7fb")
+                .unwrap();
+        assert!(check_signed_spend(other_txid, &spend)
+            .unwrap_err()
+            .contains("instead of"));
+
+        // A signature can't be that large
+        let (_, public_key) = create_keys(&ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
+        let mut psbt = spend.clone().into_psbt();
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(public_key, vec![0x30; MAX_PSBT_SIG_SIZE + 1]);
+        let large_sig_spend = SpendTransaction::from_raw_psbt(&encode::serialize(&psbt)).unwrap();
+        assert!(check_signed_spend(txid, &large_sig_spend)
+            .unwrap_err()
+            .contains("bytes signature"));
+
+        // Nor can there be that many of them
+        let mut psbt = spend.into_psbt();
+        for i in 0..=MAX_SIGS_PER_TX {
+            let (_, public_key) =
+                create_keys(&ctx, &[i as u8 + 1; secp256k1::constants::SECRET_KEY_SIZE]);
+            psbt.inputs[0]
+                .partial_sigs
+                .insert(public_key, vec![0x30; MAX_PSBT_SIG_SIZE]);
+        }
+        let many_sigs_spend = SpendTransaction::from_raw_psbt(&encode::serialize(&psbt)).unwrap();
+        assert!(check_signed_spend(txid, &many_sigs_spend)
+            .unwrap_err()
+            .contains("signatures for an input"));
+
+        // Rejecting is accounted for
+        let rejected = rejected_messages();
+        match reject_message("127.0.0.1:1", "test".to_string()) {
+            CommunicationError::InvalidMessage(reason) => assert_eq!(reason, "test"),
+            e => panic!("Unexpected error: {}", e),
+        }
+        // Other tests may be rejecting messages concurrently
+        assert!(rejected_messages() > rejected);
+    }
+
+    // A poor man's fuzzer for the decoding and checking of the messages from the servers: it
+    // must never panic whatever the bytes we get.
+    #[test]
+    fn fuzz_server_messages() {
+        let spend = SpendTransaction::from_psbt_str("cHNidP8BAP0ZAQIAAAADSe9QbkOAlLapVzhNT2J2sCWXMqe2x7g7klEr3N6+p8AAAAAAAAYAAABwwCBKiamcBn5fj0oQ3WcAU+twE4XemcK4G2nlprqBKAAAAAAABgAAAAwCYIUh0y2bkIH8BVZ/evuFulOCxOyGr/rvZnR2k/9aAAAAAAAGAAAABFCoAAAAAAAAIgAgvXwvxBU2X03+pufsytFJ2dS4BKVXIKMQmyxUXTbPJPmA8PoCAAAAABYAFCMDXGnefAb487YxeNjnpbUzH+pEQEtMAAAAAAAWABT+rq2LTo+bnAo3ZQoeUg0F6xVZbIx3EwIAAAAAIgAgfAYV/vqzwEWHS6kVMjA1yQRbIQqq//o7m4ik0eSSlasAAAAAAAEBKzb0VQMAAAAAIgAgEyIAQqFnv+D0rMmVvusK3TC6fPyFk7aU1PZ8+Ttm23IBAwQBAAAAAQXBUiEDNWoO4CCloCul5eCLd1+XLPxP4LMIsUy+XM01wlm59wIhAqQ3tGeAeMBPPR26fn0kuL0CS0AybrDlu8NwIzFOOukzIQJoBBIwDWTXwjMse2MiB8/kIcFOZACiADcmZltiEl85N1OuZHapFIe9/DRONZOp5OAQ6RCrIDclCDEjiKxrdqkUJs2E27SQYhbh4yxNkO+lDnFqCCaIrGyTa3apFBtcD9uL3TRJt1uCIj2J8Ub4YjvgiKxsk1OHZ1ayaCIGAmgEEjANZNfCMyx7YyIHz+QhwU5kAKIANyZmW2ISXzk3CO9FHBcBAAAAIgYCpDe0Z4B4wE89Hbp+fSS4vQJLQDJusOW7w3AjMU466TMIW+FtfgEAAAAiBgM1ag7gIKWgK6Xl4It3X5cs/E/gswixTL5czTXCWbn3AgjDFaC/AQAAAAABASs2rG0BAAAAACIAIICUwlAfLlUkhU44Hpkj/LEDNAdwME4fm3jtWfXwMwL7AQMEAQAAAAEFwVIhAgSNQIWSNnYSrfEl8juzTKw9o3BjYQ+DgbyizShqKzIcIQN+tRtybpIxVK9IdwxsTxFgy2YsiQqtnGvnowXelPblJiEC25bXunBKDpmrAvXiBbJ/+x9Oo5pL+8FhKgAqXSesn0VTrmR2qRTWWGTXm1UxE4rqqD2FkiKS94r8YYisa3apFCBven2wd5QCFoHAl/iRHg+9SJkgiKxsk2t2qRRP/mE3OesTO6kSJOgsBAoyLTfO8oisbJNTh2dWsmgiBgIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHAjDFaC/AAAAACIGAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FCO9FHBcAAAAAIgYDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYIW+FtfgAAAAAAAQErtgyYAAAAAAAiACCAlMJQHy5VJIVOOB6ZI/yxAzQHcDBOH5t47Vn18DMC+wEDBAEAAAABBcFSIQIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHCEDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYhAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FU65kdqkU1lhk15tVMROK6qg9hZIikveK/GGIrGt2qRQgb3p9sHeUAhaBwJf4kR4PvUiZIIisbJNrdqkUT/5hNznrEzupEiToLAQKMi03zvKIrGyTU4dnVrJoIgYCBI1AhZI2dhKt8SXyO7NMrD2jcGNhD4OBvKLNKGorMhwIwxWgvwAAAAAiBgLblte6cEoOmasC9eIFsn/7H06jmkv7wWEqACpdJ6yfRQjvRRwXAAAAACIGA361G3JukjFUr0h3DGxPEWDLZiyJCq2ca+ejBd6U9uUmCFvhbX4AAAAAACICArFlfWaPsqMsvdC+/3Hise+ubUHtj4n5Uz7qaI0bCfCWCBhRVloBAAAAIgICtg6ewcvt4XnF35qT+j9KoCYt4+vS8hXmOn1NsO/QppUIgryGbgEAAAAiAgOJmnB0i/XOb8ITGRA3itrYfvWx6/B8PGMiu2SYfOACFQhTR/BbAQAAAAAAACICAr+BTfGuO1VRPxE1DJoFIsH1Vu5Dk5lSullVQjCXjVlICEPuDksBAAAAIgIC+G7/TA9DNgnMf4Nup2Py3XAF8UCLmziV3Vw4Z2KsJcwIpbzhFQEAAAAiAgOpos5KhVRQaTPJTi3mk12g5sApoQNVGdOpMcMmn7C7gwieIH0+AQAAAAA=").unwrap();
+        let signature = secp256k1::Signature::from_str("304402201a3109a4a6445c1e56416bc39520aada5c8ad089e69ee4f1a40a0901de1a435302204b281ba97da2ab2e40eb65943ae414cc4307406c5eb177b1c646606839a2e99d").unwrap();
+        let ctx = secp256k1::Secp256k1::new();
+        let (_, public_key) = create_keys(&ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
+        let mut signatures = BTreeMap::new();
+        signatures.insert(public_key.key, signature);
+        let txid = spend.txid();
+        let seeds = vec![
+            serde_json::to_vec(&Sigs { signatures }).unwrap(),
+            serde_json::to_vec(&SignResult {
+                tx: Some(spend.clone()),
+            })
+            .unwrap(),
+            serde_json::to_vec(&SignResult { tx: None }).unwrap(),
+        ];
+
+        // Xorshift, deterministic so a failure can be reproduced
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..10_000 {
+            let msg: Vec<u8> = match next() % 4 {
+                // Random bytes
+                0 => (0..next() % 512).map(|_| next() as u8).collect(),
+                // A valid message, truncated
+                1 => {
+                    let seed = &seeds[next() as usize % seeds.len()];
+                    seed[..next() as usize % seed.len()].to_vec()
+                }
+                // A valid message with some flipped bytes
+                _ => {
+                    let mut msg = seeds[next() as usize % seeds.len()].clone();
+                    for _ in 0..1 + next() % 4 {
+                        let i = next() as usize % msg.len();
+                        msg[i] ^= next() as u8;
+                    }
+                    msg
+                }
+            };
+
+            if let Ok(sigs) = serde_json::from_slice::<Sigs>(&msg) {
+                let _ = check_sigs(&sigs);
+            }
+            if let Ok(SignResult { tx: Some(tx) }) = serde_json::from_slice::<SignResult>(&msg) {
+                let _ = check_signed_spend(txid, &tx);
+            }
+        }
+    }
 }
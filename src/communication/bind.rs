@@ -0,0 +1,240 @@
+//! Connections to the servers from a configured source address, for hosts with several
+//! interfaces that must reach the servers through a specific one (a VPN for instance).
+//!
+//! revault_net creates the socket it performs the Noise handshake over, and the standard library
+//! can't bind a socket before connecting it. So we connect the socket to the server ourselves,
+//! bound to the source address, and have revault_net connect to a loopback listener relaying
+//! to it. The Noise channel is still end-to-end between us and the server: the relay only ever
+//! sees ciphertext. Another local process connecting to the listener before us would only make
+//! this connection fail, as it can't complete the handshake with the server in our stead.
+
+use revault_net::{noise, transport::KKTransport};
+
+use std::{
+    io::{self, Read, Write},
+    mem,
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    os::unix::io::{AsRawFd, FromRawFd},
+    thread,
+    time::Duration,
+};
+
+// How long we wait for the server to accept the TCP connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+// A socket address as the system calls take it.
+fn raw_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr = libc::in_addr {
+                s_addr: u32::from_ne_bytes(addr.ip().octets()),
+            };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr = libc::in6_addr {
+                s6_addr: addr.ip().octets(),
+            };
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as libc::socklen_t)
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Open a TCP connection to `addr` going out from `source`.
+pub fn connect_from(source: IpAddr, addr: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    if source.is_ipv4() != addr.is_ipv4() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Can't reach '{}' from source address '{}'", addr, source),
+        ));
+    }
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = check(unsafe { libc::socket(family, libc::SOCK_STREAM, 0) })?;
+    // From now on it's closed when dropped, including on error.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+    // Don't leak it to the notify command and the hooks.
+    check(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+
+    let (source_addr, source_len) = raw_sockaddr(&SocketAddr::new(source, 0));
+    check(unsafe {
+        libc::bind(
+            fd,
+            &source_addr as *const _ as *const libc::sockaddr,
+            source_len,
+        )
+    })
+    .map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Binding to source address '{}': {}", source, e),
+        )
+    })?;
+
+    // Connect without blocking, so that we can give up after the timeout.
+    stream.set_nonblocking(true)?;
+    let (dest_addr, dest_len) = raw_sockaddr(&addr);
+    let ret = unsafe {
+        libc::connect(
+            fd,
+            &dest_addr as *const _ as *const libc::sockaddr,
+            dest_len,
+        )
+    };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(e);
+        }
+        let mut pollfd = libc::pollfd {
+            fd: stream.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        if check(unsafe { libc::poll(&mut pollfd, 1, timeout_ms) })? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Connecting to '{}' timed out", addr),
+            ));
+        }
+        if let Some(e) = stream.take_error()? {
+            return Err(e);
+        }
+    }
+    stream.set_nonblocking(false)?;
+
+    Ok(stream)
+}
+
+// Copy what `src` sends to `dst` until either closes the connection. A read timeout on `src`
+// ends the copy, unless it was lifted while we were waiting.
+fn copy(src: &mut TcpStream, dst: &mut TcpStream) -> io::Result<()> {
+    let mut buf = [0; 8192];
+    loop {
+        let n = match src.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e)
+                if (e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut)
+                    && src.read_timeout()?.is_none() =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+        dst.write_all(&buf[..n])?;
+    }
+}
+
+/// Copy what one side sends to the other, until either closes the connection.
+pub(crate) fn relay(local: TcpStream, remote: TcpStream) -> io::Result<()> {
+    let (mut local_read, mut remote_write) = (local.try_clone()?, remote.try_clone()?);
+    let upstream = thread::spawn(move || {
+        let _ = copy(&mut local_read, &mut remote_write);
+        // Unblock the other direction
+        let _ = local_read.shutdown(Shutdown::Both);
+        let _ = remote_write.shutdown(Shutdown::Both);
+    });
+
+    let (mut remote_read, mut local_write) = (remote, local);
+    let _ = copy(&mut remote_read, &mut local_write);
+    let _ = remote_read.shutdown(Shutdown::Both);
+    let _ = local_write.shutdown(Shutdown::Both);
+    upstream
+        .join()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Relay thread panicked"))
+}
+
+/// Connect to the server at `addr` from `source` and perform the Noise KK handshake with it.
+/// Returns the channel along with the local address the server sees us connecting from.
+pub fn connect_transport_from(
+    source: IpAddr,
+    addr: SocketAddr,
+    noise_secret: &noise::SecretKey,
+    noise_key: &noise::PublicKey,
+) -> Result<(KKTransport, SocketAddr), revault_net::Error> {
+    let remote = connect_from(source, addr, CONNECT_TIMEOUT)?;
+    let local_addr = remote.local_addr()?;
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let relay_addr = listener.local_addr()?;
+    thread::spawn(move || match listener.accept() {
+        Ok((local, _)) => {
+            if let Err(e) = relay(local, remote) {
+                log::error!("Relaying connection to '{}': '{}'", addr, e);
+            }
+        }
+        Err(e) => log::error!("Accepting relayed connection to '{}': '{}'", addr, e),
+    });
+    let transport = KKTransport::connect(relay_addr, noise_secret, noise_key)?;
+
+    Ok((transport, local_addr))
+}
+
+// Only Linux routes the whole 127.0.0.0/8 to the loopback interface by default.
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::connect_from;
+
+    use std::{
+        io::{Read, Write},
+        net::{IpAddr, Ipv4Addr, TcpListener},
+        time::Duration,
+    };
+
+    #[test]
+    fn connect_from_source() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let source = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+
+        let mut stream = connect_from(
+            source,
+            listener.local_addr().unwrap(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let (mut accepted, peer) = listener.accept().unwrap();
+        assert_eq!(peer.ip(), source);
+        assert_eq!(stream.local_addr().unwrap(), peer);
+        stream.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        accepted.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // An address that is not ours can't be bound
+        let not_ours = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        connect_from(
+            not_ours,
+            listener.local_addr().unwrap(),
+            Duration::from_secs(5),
+        )
+        .expect_err("Binding to an address that isn't ours");
+        // Nor can we reach an IPv6 server from an IPv4 address
+        connect_from(source, "[::1]:1".parse().unwrap(), Duration::from_secs(5))
+            .expect_err("Address family mismatch");
+    }
+}
@@ -0,0 +1,635 @@
+//! The contract of our side of the protocol with the Coordinator and the Cosigning Servers
+//! (https://github.com/revault/practical-revault/blob/master/messages.md), as golden
+//! transcripts of the exchanges the daemon performs: signature push, signature fetch, Spend
+//! announcement and signature request to a Cosigning Server.
+//!
+//! A transcript is the method and the exact bytes of the parameters of each request we send,
+//! along with the bytes the server answers. A [WireServer] replays the answers over a real
+//! Noise KK connection and records the raw requests it received, which must match the
+//! transcript byte for byte: a refactor changing anything we put on the wire fails here first.
+//! The answers cover every documented response, the errors, the shapes of other versions of the
+//! protocol and a response type we don't know, which must all be handled without bringing
+//! anything down.
+//!
+//! The coordinator may also answer a pushed message (`sig` or `set_spend_tx`) with
+//! `{"error":"rate_limited","retry_after":<seconds>}`, `retry_after` being optional. This is an
+//! extension of ours to the protocol, documented along with the coordinator queue in doc/API.md,
+//! and the [rate_limited] transcript is its contract.
+//!
+//! The framing is revault_net's: a request is `{"method", "params", "id"}` and a response
+//! `{"result", "id"}`. The id is random, the server echoes it back. Run the suite alone with
+//! `cargo test communication::conformance`.
+
+use crate::{
+    clock::SystemClock,
+    communication::{
+        announce_spend_transaction, get_presigs, poll_cosigning_servers, send_coord_sig_msg,
+        CommunicationError, CoordinatorEndpoint, Coordinators, ServerConnection, ServerKind,
+        MAX_SIGS_PER_TX,
+    },
+    endpoint::Endpoint,
+    utils::noise_vectors::test_keypair,
+};
+
+use revault_net::{
+    message::{coordinator::Sigs, cosigner::SignResult},
+    noise::{PublicKey as NoisePubKey, SecretKey as NoisePrivKey},
+    transport::KKTransport,
+};
+use revault_tx::{
+    bitcoin::{consensus::encode, secp256k1, OutPoint, SigHashType, Txid},
+    transactions::{RevaultTransaction, SpendTransaction},
+};
+
+use std::{
+    collections::BTreeMap,
+    net::{SocketAddr, TcpListener},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+// An unsigned Spend transaction of 3 vaults
+const SPEND_PSBT: &str = "cHNidP8BAP0ZAQIAAAADSe9QbkOAlLapVzhNT2J2sCWXMqe2x7g7klEr3N6+p8AAAAAAAAYAAABwwCBKiamcBn5fj0oQ3WcAU+twE4XemcK4G2nlprqBKAAAAAAABgAAAAwCYIUh0y2bkIH8BVZ/evuFulOCxOyGr/rvZnR2k/9aAAAAAAAGAAAABFCoAAAAAAAAIgAgvXwvxBU2X03+pufsytFJ2dS4BKVXIKMQmyxUXTbPJPmA8PoCAAAAABYAFCMDXGnefAb487YxeNjnpbUzH+pEQEtMAAAAAAAWABT+rq2LTo+bnAo3ZQoeUg0F6xVZbIx3EwIAAAAAIgAgfAYV/vqzwEWHS6kVMjA1yQRbIQqq//o7m4ik0eSSlasAAAAAAAEBKzb0VQMAAAAAIgAgEyIAQqFnv+D0rMmVvusK3TC6fPyFk7aU1PZ8+Ttm23IBAwQBAAAAAQXBUiEDNWoO4CCloCul5eCLd1+XLPxP4LMIsUy+XM01wlm59wIhAqQ3tGeAeMBPPR26fn0kuL0CS0AybrDlu8NwIzFOOukzIQJoBBIwDWTXwjMse2MiB8/kIcFOZACiADcmZltiEl85N1OuZHapFIe9/DRONZOp5OAQ6RCrIDclCDEjiKxrdqkUJs2E27SQYhbh4yxNkO+lDnFqCCaIrGyTa3apFBtcD9uL3TRJt1uCIj2J8Ub4YjvgiKxsk1OHZ1ayaCIGAmgEEjANZNfCMyx7YyIHz+QhwU5kAKIANyZmW2ISXzk3CO9FHBcBAAAAIgYCpDe0Z4B4wE89Hbp+fSS4vQJLQDJusOW7w3AjMU466TMIW+FtfgEAAAAiBgM1ag7gIKWgK6Xl4It3X5cs/E/gswixTL5czTXCWbn3AgjDFaC/AQAAAAABASs2rG0BAAAAACIAIICUwlAfLlUkhU44Hpkj/LEDNAdwME4fm3jtWfXwMwL7AQMEAQAAAAEFwVIhAgSNQIWSNnYSrfEl8juzTKw9o3BjYQ+DgbyizShqKzIcIQN+tRtybpIxVK9IdwxsTxFgy2YsiQqtnGvnowXelPblJiEC25bXunBKDpmrAvXiBbJ/+x9Oo5pL+8FhKgAqXSesn0VTrmR2qRTWWGTXm1UxE4rqqD2FkiKS94r8YYisa3apFCBven2wd5QCFoHAl/iRHg+9SJkgiKxsk2t2qRRP/mE3OesTO6kSJOgsBAoyLTfO8oisbJNTh2dWsmgiBgIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHAjDFaC/AAAAACIGAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FCO9FHBcAAAAAIgYDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYIW+FtfgAAAAAAAQErtgyYAAAAAAAiACCAlMJQHy5VJIVOOB6ZI/yxAzQHcDBOH5t47Vn18DMC+wEDBAEAAAABBcFSIQIEjUCFkjZ2Eq3xJfI7s0ysPaNwY2EPg4G8os0oaisyHCEDfrUbcm6SMVSvSHcMbE8RYMtmLIkKrZxr56MF3pT25SYhAtuW17pwSg6ZqwL14gWyf/sfTqOaS/vBYSoAKl0nrJ9FU65kdqkU1lhk15tVMROK6qg9hZIikveK/GGIrGt2qRQgb3p9sHeUAhaBwJf4kR4PvUiZIIisbJNrdqkUT/5hNznrEzupEiToLAQKMi03zvKIrGyTU4dnVrJoIgYCBI1AhZI2dhKt8SXyO7NMrD2jcGNhD4OBvKLNKGorMhwIwxWgvwAAAAAiBgLblte6cEoOmasC9eIFsn/7H06jmkv7wWEqACpdJ6yfRQjvRRwXAAAAACIGA361G3JukjFUr0h3DGxPEWDLZiyJCq2ca+ejBd6U9uUmCFvhbX4AAAAAACICArFlfWaPsqMsvdC+/3Hise+ubUHtj4n5Uz7qaI0bCfCWCBhRVloBAAAAIgICtg6ewcvt4XnF35qT+j9KoCYt4+vS8hXmOn1NsO/QppUIgryGbgEAAAAiAgOJmnB0i/XOb8ITGRA3itrYfvWx6/B8PGMiu2SYfOACFQhTR/BbAQAAAAAAACICAr+BTfGuO1VRPxE1DJoFIsH1Vu5Dk5lSullVQjCXjVlICEPuDksBAAAAIgIC+G7/TA9DNgnMf4Nup2Py3XAF8UCLmziV3Vw4Z2KsJcwIpbzhFQEAAAAiAgOpos5KhVRQaTPJTi3mk12g5sApoQNVGdOpMcMmn7C7gwieIH0+AQAAAAA=";
+// The network transaction of SPEND_PSBT, base64-encoded
+const SPEND_TX: &str = "AgAAAANJ71BuQ4CUtqlXOE1PYnawJZcyp7bHuDuSUSvc3r6nwAAAAAAABgAAAHDAIEqJqZwGfl+PShDdZwBT63AThd6ZwrgbaeWmuoEoAAAAAAAGAAAADAJghSHTLZuQgfwFVn96+4W6U4LE7Iav+u9mdHaT/1oAAAAAAAYAAAAEUKgAAAAAAAAiACC9fC/EFTZfTf6m5+zK0UnZ1LgEpVcgoxCbLFRdNs8k+YDw+gIAAAAAFgAUIwNcad58BvjztjF42OeltTMf6kRAS0wAAAAAABYAFP6urYtOj5ucCjdlCh5SDQXrFVlsjHcTAgAAAAAiACB8BhX++rPARYdLqRUyMDXJBFshCqr/+jubiKTR5JKVqwAAAAA=";
+const SPEND_TXID: &str = "e97ebbcea719fe1a5437a6b83a589e54573a889fab25d6d627712b07a023e7c4";
+// The deposits it spends
+const DEPOSIT_OUTPOINTS: [&str; 3] = [
+    "c0a7bededc2b51923bb8c7b6a7329725b076624f4d3857a9b69480436e50ef49:0",
+    "2881baa6e5691bb8c299de851370eb530067dd104a8f5f7e069ca9894a20c070:0",
+    "5aff93767466effaaf86ecc48253ba85fb7a7f5605fc81909b2dd3218560020c:0",
+];
+// A presigned transaction, and a signature of it by the key with the secret [1; 32]
+const PRESIGNED_TXID: &str = "fcb6ab963b654c773de786f4ac92c132b3d2e816ccea37af9592aa0b4aaec04b";
+const PUBKEY: &str = "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f";
+const SIGNATURE: &str = "304402201a3109a4a6445c1e56416bc39520aada5c8ad089e69ee4f1a40a0901de1a435302204b281ba97da2ab2e40eb65943ae414cc4307406c5eb177b1c646606839a2e99d";
+
+/// A request as received by the server: the method and the raw bytes of the parameters
+#[derive(Debug, PartialEq)]
+struct Sent {
+    method: String,
+    params: String,
+}
+
+fn sent(method: &str, params: &str) -> Sent {
+    Sent {
+        method: method.to_string(),
+        params: params.to_string(),
+    }
+}
+
+#[derive(Deserialize)]
+struct WireRequest<'a> {
+    method: String,
+    #[serde(borrow)]
+    params: &'a RawValue,
+    id: serde_json::Value,
+}
+
+/// What the server writes back to a request
+enum Reply {
+    /// A response with this raw result, and the id of the request
+    Result(String),
+    /// These raw bytes, as is
+    Raw(String),
+}
+
+/// A server replaying a transcript on a single connection. Once the replies are exhausted it
+/// closes the connection.
+struct WireServer {
+    addr: SocketAddr,
+    noise_key: NoisePubKey,
+    thread: thread::JoinHandle<Vec<Sent>>,
+}
+
+impl WireServer {
+    fn start(label: &str, client: NoisePubKey, replies: Vec<Reply>) -> Self {
+        let (noise_key, noise_secret) = test_keypair(label);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let thread = thread::spawn(move || {
+            let mut transport = KKTransport::accept(&listener, &noise_secret, &[client])
+                .expect("Server channel binding and accepting");
+            let mut received = Vec::new();
+            for reply in replies {
+                let raw_req = match transport.read() {
+                    Ok(raw_req) => raw_req,
+                    Err(_) => break,
+                };
+                let raw_req = String::from_utf8(raw_req).expect("Requests are JSON");
+                let req: WireRequest = serde_json::from_str(&raw_req)
+                    .unwrap_or_else(|e| panic!("Invalid request envelope '{}': {}", raw_req, e));
+                received.push(sent(&req.method, req.params.get()));
+                let raw_resp = match reply {
+                    Reply::Result(raw_result) => {
+                        format!(r#"{{"result":{},"id":{}}}"#, raw_result, req.id)
+                    }
+                    Reply::Raw(raw) => raw,
+                };
+                transport.write(raw_resp.as_bytes()).unwrap();
+            }
+            received
+        });
+
+        WireServer {
+            addr,
+            noise_key,
+            thread,
+        }
+    }
+
+    fn endpoint(&self) -> CoordinatorEndpoint {
+        CoordinatorEndpoint {
+            host: self.addr.into(),
+            noise_key: self.noise_key,
+        }
+    }
+
+    fn cosigner(&self) -> (Endpoint, NoisePubKey) {
+        (self.addr.into(), self.noise_key)
+    }
+
+    fn connect(&self, noise_secret: &NoisePrivKey) -> ServerConnection {
+        ServerConnection::connect(
+            ServerKind::Coordinator,
+            &self.addr.into(),
+            None,
+            noise_secret,
+            &self.noise_key,
+        )
+        .expect("Client channel connecting")
+    }
+
+    /// The requests it received, once the client is done with it
+    fn transcript(self) -> Vec<Sent> {
+        self.thread.join().expect("Wire server panicked")
+    }
+}
+
+fn result(raw: &str) -> Reply {
+    Reply::Result(raw.to_string())
+}
+
+fn presigned_txid() -> Txid {
+    Txid::from_str(PRESIGNED_TXID).unwrap()
+}
+
+fn signatures() -> BTreeMap<secp256k1::PublicKey, secp256k1::Signature> {
+    let mut sigs = BTreeMap::new();
+    sigs.insert(
+        secp256k1::PublicKey::from_str(PUBKEY).unwrap(),
+        secp256k1::Signature::from_str(SIGNATURE).unwrap(),
+    );
+    sigs
+}
+
+fn spend() -> SpendTransaction {
+    SpendTransaction::from_psbt_str(SPEND_PSBT).unwrap()
+}
+
+fn deposit_outpoints() -> Vec<OutPoint> {
+    DEPOSIT_OUTPOINTS
+        .iter()
+        .map(|o| OutPoint::from_str(o).unwrap())
+        .collect()
+}
+
+// The Spend transaction signed by the key with the secret [1; 32]
+fn signed_spend() -> SpendTransaction {
+    let secp = secp256k1::Secp256k1::new();
+    let privkey = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&secp, &privkey);
+    let mut spend = spend();
+    let sighash =
+        secp256k1::Message::from_slice(&spend.signature_hash(0, SigHashType::All).unwrap())
+            .unwrap();
+    let signature = secp.sign(&sighash, &privkey);
+    spend.add_signature(0, pubkey, signature, &secp).unwrap();
+    spend
+}
+
+fn sig_params(id: &str) -> String {
+    format!(
+        r#"{{"pubkey":"{}","signature":"{}","id":"{}"}}"#,
+        PUBKEY, SIGNATURE, id
+    )
+}
+
+fn set_spend_params() -> String {
+    format!(
+        r#"{{"deposit_outpoints":["{}","{}","{}"],"transaction":"{}"}}"#,
+        DEPOSIT_OUTPOINTS[0], DEPOSIT_OUTPOINTS[1], DEPOSIT_OUTPOINTS[2], SPEND_TX
+    )
+}
+
+fn sign_params() -> String {
+    format!(r#"{{"tx":"{}"}}"#, SPEND_PSBT)
+}
+
+fn assert_net_error<T: std::fmt::Debug>(res: Result<T, CommunicationError>) {
+    match res {
+        Err(CommunicationError::Net(_)) => {}
+        res => panic!("Expected a network error, got '{:?}'", res),
+    }
+}
+
+#[test]
+fn signature_push() {
+    let (client_pubkey, client_secret) = test_keypair("client");
+    let other_txid = Txid::from_str(SPEND_TXID).unwrap();
+
+    // The signatures of several transactions are pushed one `sig` at a time, on the same
+    // connection, and acknowledged
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![result(r#"{"ack":true}"#), result(r#"{"ack":true}"#)],
+    );
+    let mut conn = server.connect(&client_secret);
+    send_coord_sig_msg(&mut conn, presigned_txid(), signatures()).unwrap();
+    send_coord_sig_msg(&mut conn, other_txid, signatures()).unwrap();
+    drop(conn);
+    assert_eq!(
+        server.transcript(),
+        vec![
+            sent("sig", &sig_params(PRESIGNED_TXID)),
+            sent("sig", &sig_params(SPEND_TXID)),
+        ]
+    );
+
+    // The Coordinator failed to store it
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![result(r#"{"ack":false}"#)],
+    );
+    let mut conn = server.connect(&client_secret);
+    match send_coord_sig_msg(&mut conn, presigned_txid(), signatures()) {
+        Err(CommunicationError::SignatureStorage) => {}
+        res => panic!("{:?}", res),
+    }
+    drop(conn);
+    assert_eq!(
+        server.transcript(),
+        vec![sent("sig", &sig_params(PRESIGNED_TXID))]
+    );
+
+    // No signature, nothing sent
+    let server = WireServer::start("coordinator", client_pubkey, vec![]);
+    let mut conn = server.connect(&client_secret);
+    send_coord_sig_msg(&mut conn, presigned_txid(), BTreeMap::new()).unwrap();
+    drop(conn);
+    assert_eq!(server.transcript(), vec![]);
+}
+
+#[test]
+fn signature_fetch() {
+    let (client_pubkey, client_secret) = test_keypair("client");
+    let get_sigs = || sent("get_sigs", &format!(r#"{{"id":"{}"}}"#, PRESIGNED_TXID));
+
+    // The signatures the Coordinator has, if any
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![
+            result(&format!(
+                r#"{{"signatures":{{"{}":"{}"}}}}"#,
+                PUBKEY, SIGNATURE
+            )),
+            result(r#"{"signatures":{}}"#),
+        ],
+    );
+    let mut conn = server.connect(&client_secret);
+    assert_eq!(
+        get_presigs(&mut conn, presigned_txid()).unwrap(),
+        signatures()
+    );
+    assert!(get_presigs(&mut conn, presigned_txid()).unwrap().is_empty());
+    drop(conn);
+    assert_eq!(server.transcript(), vec![get_sigs(), get_sigs()]);
+
+    // More signatures than there can be participants
+    let secp = secp256k1::Secp256k1::signing_only();
+    let signature = secp256k1::Signature::from_str(SIGNATURE).unwrap();
+    let too_many: BTreeMap<_, _> = (1..=MAX_SIGS_PER_TX + 1)
+        .map(|i| {
+            let secret = secp256k1::SecretKey::from_slice(&[i as u8; 32]).unwrap();
+            (
+                secp256k1::PublicKey::from_secret_key(&secp, &secret),
+                signature,
+            )
+        })
+        .collect();
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![Reply::Result(
+            serde_json::to_string(&Sigs {
+                signatures: too_many,
+            })
+            .unwrap(),
+        )],
+    );
+    let mut conn = server.connect(&client_secret);
+    match get_presigs(&mut conn, presigned_txid()) {
+        Err(CommunicationError::InvalidMessage(_)) => {}
+        res => panic!("{:?}", res),
+    }
+    drop(conn);
+    assert_eq!(server.transcript(), vec![get_sigs()]);
+}
+
+#[test]
+fn spend_announcement() {
+    let (client_pubkey, client_secret) = test_keypair("client");
+
+    // Stored by the Coordinator
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![result(r#"{"ack":true}"#)],
+    );
+    let coordinators = Coordinators::new(vec![server.endpoint()], Arc::new(SystemClock));
+    let coordinator =
+        announce_spend_transaction(&coordinators, &client_secret, spend(), deposit_outpoints())
+            .unwrap();
+    assert_eq!(coordinator, server.endpoint());
+    assert_eq!(
+        server.transcript(),
+        vec![sent("set_spend_tx", &set_spend_params())]
+    );
+
+    // The Coordinator failed to store it. It did answer, we don't fail over.
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![result(r#"{"ack":false}"#)],
+    );
+    let coordinators = Coordinators::new(vec![server.endpoint()], Arc::new(SystemClock));
+    match announce_spend_transaction(&coordinators, &client_secret, spend(), deposit_outpoints()) {
+        Err(CommunicationError::SpendTxStorage) => {}
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(
+        server.transcript(),
+        vec![sent("set_spend_tx", &set_spend_params())]
+    );
+}
+
+// A Coordinator shared by many participants may tell us to slow down, for some time or not. It
+// answered: this is not a reason to fail over.
+#[test]
+fn rate_limited() {
+    let (client_pubkey, client_secret) = test_keypair("client");
+
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![
+            result(r#"{"error":"rate_limited","retry_after":30}"#),
+            result(r#"{"error":"rate_limited"}"#),
+        ],
+    );
+    let mut conn = server.connect(&client_secret);
+    match send_coord_sig_msg(&mut conn, presigned_txid(), signatures()) {
+        Err(CommunicationError::RateLimited(Some(retry_after))) => {
+            assert_eq!(retry_after, Duration::from_secs(30))
+        }
+        res => panic!("{:?}", res),
+    }
+    match send_coord_sig_msg(&mut conn, presigned_txid(), signatures()) {
+        Err(CommunicationError::RateLimited(None)) => {}
+        res => panic!("{:?}", res),
+    }
+    drop(conn);
+    assert_eq!(
+        server.transcript(),
+        vec![
+            sent("sig", &sig_params(PRESIGNED_TXID)),
+            sent("sig", &sig_params(PRESIGNED_TXID)),
+        ]
+    );
+
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![result(r#"{"error":"rate_limited","retry_after":30}"#)],
+    );
+    let coordinators = Coordinators::new(vec![server.endpoint()], Arc::new(SystemClock));
+    match announce_spend_transaction(&coordinators, &client_secret, spend(), deposit_outpoints()) {
+        Err(CommunicationError::RateLimited(Some(_))) => {}
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(coordinators.statuses()[0].consecutive_failures, 0);
+    let throttle = coordinators.throttle().status();
+    assert_eq!(throttle.rate_limited, 1);
+    assert!(throttle.held_off_secs.unwrap() >= 29);
+    assert_eq!(
+        server.transcript(),
+        vec![sent("set_spend_tx", &set_spend_params())]
+    );
+}
+
+#[test]
+fn cosigner_sign_request() {
+    let (client_pubkey, client_secret) = test_keypair("client");
+    let poll = |server: &WireServer, spend_tx: &SpendTransaction| {
+        poll_cosigning_servers(
+            &client_secret,
+            None,
+            spend_tx,
+            &[server.cosigner()],
+            Duration::from_secs(30),
+        )
+        .pop()
+        .expect("One result per server")
+    };
+
+    // It signed it
+    let signed = signed_spend();
+    let server = WireServer::start(
+        "cosigner",
+        client_pubkey,
+        vec![Reply::Result(
+            serde_json::to_string(&SignResult {
+                tx: Some(signed.clone()),
+            })
+            .unwrap(),
+        )],
+    );
+    assert_eq!(poll(&server, &spend()).unwrap(), signed);
+    assert_eq!(server.transcript(), vec![sent("sign", &sign_params())]);
+
+    // It already signed another Spend of these vaults. The request is the same whatever the
+    // signatures we already have, they are stripped.
+    let server = WireServer::start("cosigner", client_pubkey, vec![result(r#"{"tx":null}"#)]);
+    match poll(&server, &signed) {
+        Err(CommunicationError::CosigAlreadySigned) => {}
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(server.transcript(), vec![sent("sign", &sign_params())]);
+
+    // It sent back another transaction
+    let mut other = spend().into_psbt();
+    other.global.unsigned_tx.lock_time = 42;
+    let other = SpendTransaction::from_raw_psbt(&encode::serialize(&other)).unwrap();
+    let server = WireServer::start(
+        "cosigner",
+        client_pubkey,
+        vec![Reply::Result(
+            serde_json::to_string(&SignResult { tx: Some(other) }).unwrap(),
+        )],
+    );
+    match poll(&server, &spend()) {
+        Err(CommunicationError::InvalidMessage(_)) => {}
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(server.transcript(), vec![sent("sign", &sign_params())]);
+}
+
+// A server speaking another version of the protocol answers with shapes we don't know. They are
+// errors, not crashes.
+#[test]
+fn protocol_version_mismatch() {
+    let (client_pubkey, client_secret) = test_keypair("client");
+
+    // The acknowledgement under another name, and of another type
+    for ack in &[r#"{"acked":true}"#, r#"{"ack":"true"}"#, r#"{"ack":1}"#] {
+        let server = WireServer::start("coordinator", client_pubkey, vec![result(ack)]);
+        let mut conn = server.connect(&client_secret);
+        assert_net_error(send_coord_sig_msg(
+            &mut conn,
+            presigned_txid(),
+            signatures(),
+        ));
+        drop(conn);
+        assert_eq!(
+            server.transcript(),
+            vec![sent("sig", &sig_params(PRESIGNED_TXID))]
+        );
+    }
+
+    // The signatures as a list of pairs rather than a mapping
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![result(&format!(
+            r#"{{"signatures":[["{}","{}"]]}}"#,
+            PUBKEY, SIGNATURE
+        ))],
+    );
+    let mut conn = server.connect(&client_secret);
+    assert_net_error(get_presigs(&mut conn, presigned_txid()));
+    drop(conn);
+    assert_eq!(
+        server.transcript(),
+        vec![sent(
+            "get_sigs",
+            &format!(r#"{{"id":"{}"}}"#, PRESIGNED_TXID)
+        )]
+    );
+
+    // The network transaction rather than the PSBT
+    let server = WireServer::start(
+        "cosigner",
+        client_pubkey,
+        vec![result(&format!(r#"{{"tx":"{}"}}"#, SPEND_TX))],
+    );
+    assert_net_error(
+        poll_cosigning_servers(
+            &client_secret,
+            None,
+            &spend(),
+            &[server.cosigner()],
+            Duration::from_secs(30),
+        )
+        .pop()
+        .unwrap(),
+    );
+    assert_eq!(server.transcript(), vec![sent("sign", &sign_params())]);
+
+    // A server not knowing the method answering with a JSONRPC error
+    let server = WireServer::start(
+        "coordinator",
+        client_pubkey,
+        vec![Reply::Raw(
+            r#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"Method not found"},"id":null}"#
+                .to_string(),
+        )],
+    );
+    let coordinators = Coordinators::new(vec![server.endpoint()], Arc::new(SystemClock));
+    assert_net_error(announce_spend_transaction(
+        &coordinators,
+        &client_secret,
+        spend(),
+        deposit_outpoints(),
+    ));
+    assert_eq!(
+        server.transcript(),
+        vec![sent("set_spend_tx", &set_spend_params())]
+    );
+}
+
+// A response of a type we don't know is an error for this exchange only: we fail over to the
+// next Coordinator, and the other Cosigning Servers are still polled.
+#[test]
+fn unknown_response_type() {
+    let (client_pubkey, client_secret) = test_keypair("client");
+    let unknown = || result(r#"{"status":"queued","retry_in":30}"#);
+
+    let main = WireServer::start("coordinator", client_pubkey, vec![unknown()]);
+    let backup = WireServer::start(
+        "backup coordinator",
+        client_pubkey,
+        vec![result(r#"{"ack":true}"#)],
+    );
+    let coordinators = Coordinators::new(
+        vec![main.endpoint(), backup.endpoint()],
+        Arc::new(SystemClock),
+    );
+    let coordinator =
+        announce_spend_transaction(&coordinators, &client_secret, spend(), deposit_outpoints())
+            .unwrap();
+    assert_eq!(coordinator, backup.endpoint());
+    let statuses = coordinators.statuses();
+    assert_eq!(statuses[0].consecutive_failures, 1);
+    assert!(statuses[1].active);
+    assert_eq!(
+        main.transcript(),
+        vec![sent("set_spend_tx", &set_spend_params())]
+    );
+    assert_eq!(
+        backup.transcript(),
+        vec![sent("set_spend_tx", &set_spend_params())]
+    );
+
+    let signed = signed_spend();
+    let confused = WireServer::start("cosigner", client_pubkey, vec![unknown()]);
+    let cosigner = WireServer::start(
+        "other cosigner",
+        client_pubkey,
+        vec![Reply::Result(
+            serde_json::to_string(&SignResult {
+                tx: Some(signed.clone()),
+            })
+            .unwrap(),
+        )],
+    );
+    let mut results = poll_cosigning_servers(
+        &client_secret,
+        None,
+        &spend(),
+        &[confused.cosigner(), cosigner.cosigner()],
+        Duration::from_secs(30),
+    );
+    assert_eq!(results.pop().unwrap().unwrap(), signed);
+    assert_net_error(results.pop().unwrap());
+    assert_eq!(confused.transcript(), vec![sent("sign", &sign_params())]);
+    assert_eq!(cosigner.transcript(), vec![sent("sign", &sign_params())]);
+}
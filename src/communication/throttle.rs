@@ -0,0 +1,233 @@
+//! The pace at which we push messages to the Coordinator. A Coordinator shared by all the
+//! participants may limit how fast each of them sends, which a stakeholder signing the revocation
+//! transactions of hundreds of vaults at once would exceed. We stay below a configured rate with a
+//! token bucket, and if the Coordinator still tells us to slow down we hold off for as long as it
+//! asks, or back off exponentially if it does not say.
+
+use crate::clock::Clock;
+
+use std::{
+    cmp,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The default number of messages per second we push to the Coordinator on average
+pub const DEFAULT_PUSH_RATE: u32 = 20;
+/// The default number of messages we push to the Coordinator in a row
+pub const DEFAULT_PUSH_BURST: u32 = 100;
+
+// How long to hold off the first time the Coordinator rate limits us without saying for how long.
+// Doubled each time it does it again, until it accepts a message.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(64);
+// We don't take a Coordinator asking us to hold off for longer than this at its word
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+struct ThrottleState {
+    // How many messages we may send right away. Refilled at `rate` per second up to `burst`,
+    // from `last_refill` which is in the future while we are holding off.
+    tokens: f64,
+    last_refill: Instant,
+    held_until: Option<Instant>,
+    // The hold off to use the next time we are rate limited without being told for how long
+    backoff: Duration,
+    rate_limited: u64,
+}
+
+/// Where the throttle of the pushes to the Coordinator stands
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ThrottleStatus {
+    /// The number of messages per second we push on average
+    pub rate: u32,
+    /// The number of messages we may push in a row
+    pub burst: u32,
+    /// The number of messages we may push right now
+    pub available: u32,
+    /// For how many more seconds we hold off at the Coordinator's request, if we do
+    pub held_off_secs: Option<u64>,
+    /// How many times the Coordinator told us to slow down since startup
+    pub rate_limited: u64,
+}
+
+/// A token bucket for the messages we push to the Coordinator, shared by all the threads pushing
+/// to it.
+#[derive(Debug)]
+pub struct PushThrottle {
+    rate: u32,
+    burst: u32,
+    clock: Arc<dyn Clock>,
+    state: Mutex<ThrottleState>,
+}
+
+impl PushThrottle {
+    /// Allow `rate` messages per second on average, and `burst` in a row. Both must be at least
+    /// 1.
+    pub fn new(rate: u32, burst: u32, clock: Arc<dyn Clock>) -> Self {
+        assert!(rate > 0 && burst > 0, "Checked by the configuration");
+        let state = ThrottleState {
+            tokens: burst as f64,
+            last_refill: clock.now(),
+            held_until: None,
+            backoff: MIN_BACKOFF,
+            rate_limited: 0,
+        };
+        Self {
+            rate,
+            burst,
+            clock,
+            state: Mutex::new(state),
+        }
+    }
+
+    // Credit the tokens earned since the last refill, and forget about the hold off once over.
+    fn refill(&self, state: &mut ThrottleState) -> Instant {
+        let now = self.clock.now();
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        if elapsed > Duration::from_secs(0) {
+            state.tokens =
+                (state.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.burst as f64);
+            state.last_refill = now;
+        }
+        if state.held_until.map(|until| until <= now).unwrap_or(false) {
+            state.held_until = None;
+        }
+        now
+    }
+
+    /// Take the budget to push a message now, if we have it. Otherwise returns how long until we
+    /// have it.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.refill(&mut state);
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let refill = Duration::from_secs_f64((1.0 - state.tokens) / self.rate as f64);
+        Err(state.last_refill.saturating_duration_since(now) + refill)
+    }
+
+    /// The Coordinator accepted a message: if it rate limits us again without saying for how
+    /// long, start over from the shortest hold off.
+    pub fn accepted(&self) {
+        self.state.lock().unwrap().backoff = MIN_BACKOFF;
+    }
+
+    /// The Coordinator told us to slow down, for this long if it said so. We don't push
+    /// anything until then, and start again from an empty bucket. Returns how long we hold off.
+    pub fn rate_limited(&self, retry_after: Option<Duration>) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let hold_off = match retry_after {
+            Some(retry_after) => cmp::min(retry_after, MAX_RETRY_AFTER),
+            None => {
+                let backoff = state.backoff;
+                state.backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+                backoff
+            }
+        };
+        let until = self.clock.now() + hold_off;
+        state.tokens = 0.0;
+        state.last_refill = until;
+        state.held_until = Some(until);
+        state.rate_limited += 1;
+
+        hold_off
+    }
+
+    pub fn status(&self) -> ThrottleStatus {
+        let mut state = self.state.lock().unwrap();
+        let now = self.refill(&mut state);
+        ThrottleStatus {
+            rate: self.rate,
+            burst: self.burst,
+            available: state.tokens as u32,
+            held_off_secs: state
+                .held_until
+                .map(|until| until.saturating_duration_since(now).as_secs()),
+            rate_limited: state.rate_limited,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PushThrottle, MAX_BACKOFF, MAX_RETRY_AFTER};
+    use crate::clock::test_utils::MockClock;
+
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn throttle_token_bucket() {
+        let clock = Arc::new(MockClock::new(1_600_000_000));
+        let throttle = PushThrottle::new(2, 4, clock.clone());
+
+        // A burst, then at the rate
+        for _ in 0..4 {
+            throttle.try_acquire().unwrap();
+        }
+        assert_eq!(throttle.try_acquire(), Err(Duration::from_millis(500)));
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(throttle.try_acquire(), Err(Duration::from_millis(250)));
+        clock.advance(Duration::from_millis(250));
+        throttle.try_acquire().unwrap();
+        throttle.try_acquire().unwrap_err();
+
+        // It doesn't fill up beyond the burst
+        clock.advance(Duration::from_secs(3600));
+        let status = throttle.status();
+        assert_eq!(status.available, 4);
+        assert_eq!(status.held_off_secs, None);
+        for _ in 0..4 {
+            throttle.try_acquire().unwrap();
+        }
+        throttle.try_acquire().unwrap_err();
+    }
+
+    #[test]
+    fn throttle_rate_limited() {
+        let clock = Arc::new(MockClock::new(1_600_000_000));
+        let throttle = PushThrottle::new(10, 100, clock.clone());
+
+        // We hold off for as long as we are told, then start from an empty bucket
+        assert_eq!(
+            throttle.rate_limited(Some(Duration::from_secs(30))),
+            Duration::from_secs(30)
+        );
+        let status = throttle.status();
+        assert_eq!(status.available, 0);
+        assert_eq!(status.held_off_secs, Some(30));
+        assert_eq!(status.rate_limited, 1);
+        assert_eq!(
+            throttle.try_acquire(),
+            Err(Duration::from_secs(30) + Duration::from_millis(100))
+        );
+        clock.advance(Duration::from_secs(30));
+        throttle.try_acquire().unwrap_err();
+        assert_eq!(throttle.status().held_off_secs, None);
+        clock.advance(Duration::from_millis(100));
+        throttle.try_acquire().unwrap();
+        throttle.try_acquire().unwrap_err();
+
+        // Without a delay, exponentially until it accepts a message again
+        let mut hold_offs = Vec::new();
+        for _ in 0..9 {
+            hold_offs.push(throttle.rate_limited(None).as_secs());
+        }
+        assert_eq!(hold_offs, vec![1, 2, 4, 8, 16, 32, 64, 64, 64]);
+        assert_eq!(MAX_BACKOFF, Duration::from_secs(64));
+        throttle.accepted();
+        assert_eq!(throttle.rate_limited(None), Duration::from_secs(1));
+        assert_eq!(throttle.status().rate_limited, 11);
+
+        // Nor do we hold off forever
+        assert_eq!(
+            throttle.rate_limited(Some(Duration::from_secs(u32::MAX as u64))),
+            MAX_RETRY_AFTER
+        );
+    }
+}
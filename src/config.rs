@@ -1,8 +1,33 @@
-use std::{net::SocketAddr, path::PathBuf, str::FromStr, time::Duration, vec::Vec};
+use crate::{
+    alerts::WebhookUrl,
+    amount::Amount,
+    communication::{
+        throttle::{DEFAULT_PUSH_BURST, DEFAULT_PUSH_RATE},
+        ServerKind,
+    },
+    derivation::DerivationIndex,
+    endpoint::{default_port, Endpoint},
+    revaultd::VaultStatus,
+    schedule::SpendingSchedule,
+};
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+    vec::Vec,
+};
 
 use revault_net::noise::PublicKey as NoisePubkey;
 use revault_tx::{
-    bitcoin::{hashes::hex::FromHex, util::bip32, Network},
+    bitcoin::{
+        self,
+        hashes::hex::{FromHex, ToHex},
+        util::bip32,
+        Network,
+    },
     miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey, Wildcard},
     scripts::{CpfpDescriptor, DepositDescriptor, EmergencyAddress, UnvaultDescriptor},
 };
@@ -25,9 +50,72 @@ where
     D: Deserializer<'de>,
 {
     let data = String::deserialize(deserializer)?;
-    FromHex::from_hex(&data)
-        .map_err(de::Error::custom)
+    noise_pubkey_from_str(&data).map_err(de::Error::custom)
+}
+
+// Strip the separators one may use when copying a key or a fingerprint by hand.
+fn strip_key_separators(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != '-')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Parse a Noise static public key from its hex encoding, in any grouping. It may be followed
+/// by its fingerprint as communicated out-of-band, as in `<key>#<fingerprint>`, in which case
+/// the key is refused if it doesn't match.
+pub fn noise_pubkey_from_str(s: &str) -> Result<NoisePubkey, String> {
+    let mut parts = s.splitn(2, '#');
+    let key_str = parts.next().expect("splitn always returns the first part");
+    let pubkey = FromHex::from_hex(&strip_key_separators(key_str))
         .map(NoisePubkey)
+        .map_err(|e| format!("Invalid Noise public key '{}': {}", s, e))?;
+
+    if let Some(fingerprint) = parts.next() {
+        if !noise_fingerprint_matches(&pubkey, fingerprint) {
+            return Err(format!(
+                "Fingerprint mismatch for Noise key '{}': expected '{}' but the key's \
+                 fingerprint is '{}'",
+                pubkey.0.to_hex(),
+                fingerprint.trim(),
+                noise_pubkey_fingerprint(&pubkey)
+            ));
+        }
+    }
+
+    Ok(pubkey)
+}
+
+/// A short fingerprint of a Noise static public key meant to be compared out-of-band by
+/// operators: the first 8 bytes of the key, hex-encoded in groups of 2 bytes.
+pub fn noise_pubkey_fingerprint(pubkey: &NoisePubkey) -> String {
+    pubkey.0[..8]
+        .chunks(2)
+        .map(|group| group.to_hex())
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// Whether a fingerprint, in any grouping, is the one of this Noise static public key.
+pub fn noise_fingerprint_matches(pubkey: &NoisePubkey, fingerprint: &str) -> bool {
+    strip_key_separators(fingerprint) == strip_key_separators(&noise_pubkey_fingerprint(pubkey))
+}
+
+/// Parse the fingerprint of an xpub, in any grouping.
+pub fn xpub_fingerprint_from_str(s: &str) -> Option<bip32::Fingerprint> {
+    bip32::Fingerprint::from_hex(&strip_key_separators(s)).ok()
+}
+
+fn deserialize_derivation_path<'de, D>(
+    deserializer: D,
+) -> Result<Option<bip32::DerivationPath>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let path_str = String::deserialize(deserializer)?;
+    bip32::DerivationPath::from_str(&path_str)
+        .map(Some)
+        .map_err(|e| de::Error::custom(format!("Invalid derivation path '{}': '{}'", path_str, e)))
 }
 
 fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
@@ -38,6 +126,34 @@ where
     Ok(Duration::from_secs(secs))
 }
 
+fn deserialize_vault_statuses<'de, D>(deserializer: D) -> Result<Vec<VaultStatus>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|status| VaultStatus::from_str(status).map_err(de::Error::custom))
+        .collect()
+}
+
+fn deserialize_webhook_url<'de, D>(deserializer: D) -> Result<Option<WebhookUrl>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let url = String::deserialize(deserializer)?;
+    WebhookUrl::from_str(&url)
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+fn deserialize_endpoint<'de, D>(deserializer: D) -> Result<Endpoint, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let endpoint = String::deserialize(deserializer)?;
+    Endpoint::from_str(&endpoint).map_err(de::Error::custom)
+}
+
 fn deserialize_loglevel<'de, D>(deserializer: D) -> Result<log::LevelFilter, D::Error>
 where
     D: Deserializer<'de>,
@@ -46,6 +162,23 @@ where
     log::LevelFilter::from_str(&level_str).map_err(de::Error::custom)
 }
 
+fn deserialize_log_modules<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, log::LevelFilter>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let levels = HashMap::<String, String>::deserialize(deserializer)?;
+    levels
+        .into_iter()
+        .map(|(module, level)| {
+            log::LevelFilter::from_str(&level)
+                .map(|level| (module, level))
+                .map_err(de::Error::custom)
+        })
+        .collect()
+}
+
 fn default_loglevel() -> log::LevelFilter {
     log::LevelFilter::Info
 }
@@ -54,18 +187,204 @@ fn default_poll_interval() -> Duration {
     Duration::from_secs(30)
 }
 
+fn default_rpc_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_rescan_timeout() -> Duration {
+    Duration::from_secs(3600)
+}
+
+fn default_rpc_retry_window() -> Duration {
+    Duration::from_secs(45)
+}
+
+fn default_rpc_connections() -> usize {
+    4
+}
+
 fn default_sig_poll_interval() -> Duration {
     Duration::from_secs(60)
 }
 
+fn default_max_spend_inputs() -> usize {
+    50
+}
+
+fn default_max_batch_size() -> usize {
+    1000
+}
+
+fn default_sigfetch_batch_size() -> usize {
+    50
+}
+
+fn default_coordinator_push_rate() -> u32 {
+    DEFAULT_PUSH_RATE
+}
+
+fn default_coordinator_push_burst() -> u32 {
+    DEFAULT_PUSH_BURST
+}
+
+fn default_max_spend_fee_percent() -> u64 {
+    5
+}
+
+fn default_max_spend_fee() -> u64 {
+    10_000_000
+}
+
+fn default_notify_statuses() -> Vec<VaultStatus> {
+    vec![
+        VaultStatus::Unvaulting,
+        VaultStatus::Canceling,
+        VaultStatus::EmergencyVaulting,
+    ]
+}
+
+fn default_notify_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_alert_loglevel() -> log::LevelFilter {
+    log::LevelFilter::Warn
+}
+
+fn default_run_notify_command() -> bool {
+    true
+}
+
+fn default_webhook_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_revocation_check_interval() -> Duration {
+    Duration::from_secs(24 * 3600)
+}
+
+fn default_wallet_audit_interval() -> Duration {
+    Duration::from_secs(3600)
+}
+
+fn default_relay_floor_check_interval() -> Duration {
+    Duration::from_secs(600)
+}
+
+fn default_relay_floor_margin_percent() -> u64 {
+    20
+}
+
+fn default_slow_rpc_call_ms() -> u64 {
+    2000
+}
+
+fn default_idempotency_retention() -> Duration {
+    Duration::from_secs(24 * 3600)
+}
+
+fn default_disk_space_warning() -> u64 {
+    1024
+}
+
+fn default_disk_space_critical() -> u64 {
+    100
+}
+
 fn default_minconf() -> u32 {
     6
 }
 
+fn default_deposit_abandon_polls() -> u32 {
+    60
+}
+
+fn default_deposit_index_ceiling() -> DerivationIndex {
+    DerivationIndex::new(100_000).expect("Not hardened")
+}
+
 fn default_cosig_servers() -> Vec<CosignerConfig> {
     vec![]
 }
 
+fn default_cosigners_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_spend_proposal_expiry() -> Duration {
+    Duration::from_secs(24 * 3600)
+}
+
+fn default_spend_expiry() -> Duration {
+    Duration::from_secs(3 * 24 * 3600)
+}
+
+/// A commented example configuration, documenting all the settings. It is valid as is for a
+/// stakeholder-manager on regtest.
+pub const EXAMPLE_CONFIG: &str = include_str!("example_config.toml");
+
+/// The format of the log messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Human
+    }
+}
+
+/// In what order to fetch the signatures of the vaults missing some
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigFetchOrder {
+    /// The largest deposits first
+    Value,
+    /// The deposits that were confirmed first
+    Oldest,
+}
+
+impl Default for SigFetchOrder {
+    fn default() -> Self {
+        SigFetchOrder::Value
+    }
+}
+
+/// How hard sqlite should try to make our commits durable, its `synchronous` setting. The
+/// discriminants are sqlite's own values for the pragma.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbSynchronous {
+    /// Never sync to disk. A power loss may corrupt the database, only for tests.
+    Off = 0,
+    /// Sync less often, a power loss may roll back the latest commits.
+    Normal = 1,
+    /// Sync the journal and the database on each commit
+    Full = 2,
+    /// As `Full`, and also sync the directory after deleting the journal
+    Extra = 3,
+}
+
+impl Default for DbSynchronous {
+    fn default() -> Self {
+        DbSynchronous::Full
+    }
+}
+
+impl std::fmt::Display for DbSynchronous {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DbSynchronous::Off => write!(f, "off"),
+            DbSynchronous::Normal => write!(f, "normal"),
+            DbSynchronous::Full => write!(f, "full"),
+            DbSynchronous::Extra => write!(f, "extra"),
+        }
+    }
+}
+
 /// Everything we need to know for talking to bitcoind serenely
 #[derive(Debug, Clone, Deserialize)]
 pub struct BitcoindConfig {
@@ -81,6 +400,29 @@ pub struct BitcoindConfig {
         default = "default_poll_interval"
     )]
     pub poll_interval_secs: Duration,
+    /// How long to wait for bitcoind to answer a request
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_rpc_timeout"
+    )]
+    pub rpc_timeout_secs: Duration,
+    /// How long to wait for bitcoind to answer a request that may trigger a rescan, such as
+    /// importing descriptors or loading a wallet
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_rescan_timeout"
+    )]
+    pub rescan_timeout_secs: Duration,
+    /// For how long to retry a request that failed because bitcoind could not be reached
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_rpc_retry_window"
+    )]
+    pub rpc_retry_window_secs: Duration,
+    /// How many connections to keep open to each of bitcoind's RPC endpoints (the node and
+    /// each of our wallets). The calls that may trigger a rescan use their own.
+    #[serde(default = "default_rpc_connections")]
+    pub rpc_connections: usize,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -95,7 +437,9 @@ pub struct ScriptsConfig {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct WatchtowerConfig {
-    pub host: SocketAddr,
+    /// The address of the watchtower, as `host[:port]`
+    #[serde(deserialize_with = "deserialize_endpoint")]
+    pub host: Endpoint,
     #[serde(deserialize_with = "deserialize_noisepubkey")]
     pub noise_key: NoisePubkey,
 }
@@ -105,14 +449,166 @@ pub struct WatchtowerConfig {
 pub struct StakeholderConfig {
     pub xpub: bip32::ExtendedPubKey,
     pub watchtowers: Vec<WatchtowerConfig>,
-    pub emergency_address: EmergencyAddress,
+    /// A single address, or a list of addresses each with the band of amounts routed to it
+    pub emergency_address: EmergencyAddresses,
+}
+
+/// An Emergency address, and the amounts of the vaults whose Emergency transactions pay to it
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmergencyBand {
+    pub address: EmergencyAddress,
+    /// The smallest amount routed to this address (default: no minimum)
+    pub min_amount: Option<Amount>,
+    /// The amount from which vaults are routed to another address, excluded (default: no
+    /// maximum)
+    pub max_amount: Option<Amount>,
+}
+
+/// Where the Emergency transactions of our vaults pay to: a single address, or one per band of
+/// vault amounts. The bands don't overlap and cover all amounts, so that the Emergency
+/// destination of a vault only depends on its amount and all stakeholders agree on it.
+#[derive(Debug, Clone)]
+pub struct EmergencyAddresses(Vec<EmergencyBand>);
+
+impl EmergencyAddresses {
+    /// All vaults go to this address
+    pub fn single(address: EmergencyAddress) -> Self {
+        Self(vec![EmergencyBand {
+            address,
+            min_amount: None,
+            max_amount: None,
+        }])
+    }
+
+    /// Check that the bands don't overlap and cover all amounts
+    pub fn from_bands(mut bands: Vec<EmergencyBand>) -> Result<Self, String> {
+        if bands.is_empty() {
+            return Err("There must be at least one Emergency address".to_string());
+        }
+        for band in bands.iter() {
+            if let (Some(min), Some(max)) = (band.min_amount, band.max_amount) {
+                if min >= max {
+                    return Err(format!(
+                        "Emergency address '{}' has an empty band of amounts (from {} to {})",
+                        band.address.address(),
+                        min,
+                        max
+                    ));
+                }
+            }
+        }
+
+        bands.sort_by_key(|band| band.min_amount.unwrap_or_default());
+        if let Some(min) = bands[0].min_amount.filter(|min| min.as_sat() > 0) {
+            return Err(format!(
+                "Vaults of less than {} have no Emergency address",
+                min
+            ));
+        }
+        for pair in bands.windows(2) {
+            let (band, next) = (&pair[0], &pair[1]);
+            let next_min = next.min_amount.unwrap_or_default();
+            match band.max_amount {
+                Some(max) if max < next_min => {
+                    return Err(format!(
+                        "Vaults from {} to {} have no Emergency address",
+                        max, next_min
+                    ))
+                }
+                Some(max) if max == next_min => {}
+                _ => {
+                    return Err(format!(
+                        "The amounts of Emergency addresses '{}' and '{}' overlap",
+                        band.address.address(),
+                        next.address.address()
+                    ))
+                }
+            }
+        }
+        if let Some(max) = bands[bands.len() - 1].max_amount {
+            return Err(format!(
+                "Vaults of {} or more have no Emergency address",
+                max
+            ));
+        }
+
+        Ok(Self(bands))
+    }
+
+    /// The bands, by increasing amounts
+    pub fn bands(&self) -> &[EmergencyBand] {
+        &self.0
+    }
+
+    /// The address the Emergency transactions of a vault of this amount pay to
+    pub fn for_amount(&self, amount: bitcoin::Amount) -> &EmergencyAddress {
+        let amount = Amount::from(amount);
+        &self
+            .0
+            .iter()
+            .rev()
+            .find(|band| band.min_amount.unwrap_or_default() <= amount)
+            .expect("The bands cover all amounts")
+            .address
+    }
+
+    /// Whether this is one of the addresses
+    pub fn contains(&self, address: &bitcoin::Address) -> bool {
+        self.0.iter().any(|band| band.address.address() == address)
+    }
+}
+
+impl<'de> Deserialize<'de> for EmergencyAddresses {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EmergencyVisitor;
+
+        impl<'de> de::Visitor<'de> for EmergencyVisitor {
+            type Value = EmergencyAddresses;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(
+                    f,
+                    "an Emergency address, or a list of Emergency addresses with their amounts"
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<EmergencyAddresses, E> {
+                EmergencyAddress::deserialize(de::IntoDeserializer::into_deserializer(s))
+                    .map(EmergencyAddresses::single)
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(
+                self,
+                seq: A,
+            ) -> Result<EmergencyAddresses, A::Error> {
+                let bands =
+                    Vec::<EmergencyBand>::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+                EmergencyAddresses::from_bands(bands).map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(EmergencyVisitor)
+    }
 }
 
 // Same fields as the WatchtowerConfig struct for now, but leave them separate.
 #[derive(Debug, Clone, Deserialize)]
 pub struct CosignerConfig {
     // TODO: Tor
-    pub host: SocketAddr,
+    #[serde(deserialize_with = "deserialize_endpoint")]
+    pub host: Endpoint,
+    #[serde(deserialize_with = "deserialize_noisepubkey")]
+    pub noise_key: NoisePubkey,
+    /// Optionally, a human-readable name for this cosigning server
+    pub label: Option<String>,
+}
+
+/// A Coordinator to fail over to if the main one is unreachable or misbehaves
+#[derive(Debug, Clone, Deserialize)]
+pub struct CoordinatorConfig {
+    // TODO: Tor
+    #[serde(deserialize_with = "deserialize_endpoint")]
+    pub host: Endpoint,
     #[serde(deserialize_with = "deserialize_noisepubkey")]
     pub noise_key: NoisePubkey,
 }
@@ -123,6 +619,72 @@ pub struct ManagerConfig {
     pub xpub: bip32::ExtendedPubKey,
     #[serde(default = "default_cosig_servers")]
     pub cosigners: Vec<CosignerConfig>,
+    /// For how long to wait for the cosigning servers to answer a signature request (default: 30s)
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_cosigners_timeout"
+    )]
+    pub cosigners_timeout_secs: Duration,
+    /// How many managers need to approve a spend proposal before a Spend transaction may be
+    /// created for it. If not set, Spend transactions don't need to be proposed first.
+    pub spend_approval_threshold: Option<usize>,
+    /// For how long a spend proposal can be approved and used (default: a day)
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_spend_proposal_expiry"
+    )]
+    pub spend_proposal_expiry_secs: Duration,
+    /// For how long a Spend transaction we announced may not progress (none of its Unvault
+    /// transactions broadcast) before we give up on it (default: 3 days)
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_spend_expiry"
+    )]
+    pub spend_expiry_secs: Duration,
+    /// Refuse to create a Spend transaction paying to an external address a confirmed Spend
+    /// already paid to, instead of only warning about it (default: false)
+    #[serde(default)]
+    pub forbid_destination_reuse: bool,
+    /// When we may initiate a spend. Reloaded on SIGHUP.
+    pub spending_schedule: Option<SpendingSchedule>,
+    /// The derivation path from the master key of the `cpfp_secret` seed to the key used in the
+    /// CPFP descriptor (default: the master key itself)
+    #[serde(default, deserialize_with = "deserialize_derivation_path")]
+    pub cpfp_key_path: Option<bip32::DerivationPath>,
+}
+
+/// A client allowed to connect to the JSONRPC interface over TCP
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcClientConfig {
+    #[serde(deserialize_with = "deserialize_noisepubkey")]
+    pub noise_key: NoisePubkey,
+    /// Whether this client is a watchtower allowed to pull the data to enforce revocation with
+    /// `getwatchdata`. If any client is, the others can't.
+    #[serde(default)]
+    pub watchtower: bool,
+}
+
+/// How to alert about the vaults worth at least `min_amount` entering the `unvaulting`,
+/// `canceling` or `emergencyvaulting` status
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AlertTierConfig {
+    /// Optionally, a name for the tier passed along in the webhook payload
+    pub name: Option<String>,
+    /// The value from which vaults belong to this tier, in satoshis
+    pub min_amount: u64,
+    /// At which level to log the alert (default: "warn"), "off" to not log it
+    #[serde(
+        deserialize_with = "deserialize_loglevel",
+        default = "default_alert_loglevel"
+    )]
+    pub log_level: log::LevelFilter,
+    /// Whether to run the `notify_command` (default: true)
+    #[serde(default = "default_run_notify_command")]
+    pub run_notify_command: bool,
+    /// Optionally, a plain HTTP URL to post a JSON description of the event to. It must be on
+    /// the loopback unless `i_understand_the_risks` is set.
+    #[serde(default, deserialize_with = "deserialize_webhook_url")]
+    pub webhook_url: Option<WebhookUrl>,
 }
 
 /// Static informations we require to operate
@@ -136,8 +698,9 @@ pub struct Config {
     /// Some() if we are a manager
     pub manager_config: Option<ManagerConfig>,
     // TODO: support hidden services
-    /// The host of the sync server
-    pub coordinator_host: SocketAddr,
+    /// The address of the sync server, as `host[:port]`
+    #[serde(deserialize_with = "deserialize_endpoint")]
+    pub coordinator_host: Endpoint,
     /// The Noise static public key of the sync server
     #[serde(deserialize_with = "deserialize_noisepubkey")]
     pub coordinator_noise_key: NoisePubkey,
@@ -147,19 +710,176 @@ pub struct Config {
         default = "default_sig_poll_interval"
     )]
     pub coordinator_poll_seconds: Duration,
+    /// The Coordinators to fail over to, by order of preference, if the above one is unreachable
+    #[serde(default)]
+    pub backup_coordinators: Vec<CoordinatorConfig>,
+    /// Optionally, the local address to connect to the servers (Coordinators, cosigning servers
+    /// and watchtowers) from
+    pub bind_address: Option<IpAddr>,
+    /// In what order to fetch the signatures of the vaults missing some (default: by value)
+    #[serde(default)]
+    pub sigfetch_order: SigFetchOrder,
+    /// For how many vaults at most to fetch signatures in a row, before processing the other
+    /// events of the signature fetcher
+    #[serde(default = "default_sigfetch_batch_size")]
+    pub sigfetch_batch_size: usize,
+    /// How many signatures per second we may push to the Coordinator on average (default: 20)
+    #[serde(default = "default_coordinator_push_rate")]
+    pub coordinator_push_rate: u32,
+    /// How many signatures we may push to the Coordinator in a row before being held to the
+    /// above rate (default: 100)
+    #[serde(default = "default_coordinator_push_burst")]
+    pub coordinator_push_burst: u32,
     /// An optional custom data directory
     pub data_dir: Option<PathBuf>,
+    /// Optional custom paths for the database, the log file and the RPC socket. Relative paths
+    /// are resolved against the data directory.
+    pub db_path: Option<PathBuf>,
+    pub log_path: Option<PathBuf>,
+    pub rpc_socket_path: Option<PathBuf>,
+    /// Whether to create the parent directory of the RPC socket if it does not exist
+    pub create_rpc_socket_dir: Option<bool>,
+    /// Optionally, also serve the JSONRPC interface over TCP on this address
+    pub rpc_listen: Option<SocketAddr>,
+    /// The clients allowed to connect to the JSONRPC interface over TCP
+    #[serde(default)]
+    pub rpc_clients: Vec<RpcClientConfig>,
+    /// Allow `rpc_listen` to be a non-loopback address, and alert tiers to post to a non-loopback
+    /// webhook in plain HTTP
+    #[serde(default)]
+    pub i_understand_the_risks: bool,
     /// Whether to daemonize the process
     pub daemon: Option<bool>,
+    /// Only monitor: refuse the commands that would change anything, and don't push anything
+    /// to the servers nor broadcast any transaction
+    #[serde(default)]
+    pub read_only: bool,
+    /// Derive again the scripts of our vaults at startup and check they are the ones we stored,
+    /// even if this version of revaultd already ran on the database
+    #[serde(default)]
+    pub verify_derivations: bool,
+    /// How hard to try to make the database commits durable (default: "full"). "off" is
+    /// refused on mainnet.
+    #[serde(default)]
+    pub db_synchronous: DbSynchronous,
+    /// Overwrite with zeros the content we delete from the database (default: false)
+    #[serde(default)]
+    pub db_secure_delete: bool,
     /// What messages to log
     #[serde(
         deserialize_with = "deserialize_loglevel",
         default = "default_loglevel"
     )]
     pub log_level: log::LevelFilter,
+    /// What messages to log from some modules, by module path, instead of `log_level`
+    #[serde(default, deserialize_with = "deserialize_log_modules")]
+    pub log_modules: HashMap<String, log::LevelFilter>,
+    /// How to format log messages, human-readable ("human") or one JSON object per line ("json")
+    #[serde(default)]
+    pub log_format: LogFormat,
     /// After how many blocks should we consider a deposit as confirmed?
     #[serde(default = "default_minconf")]
     pub min_conf: u32,
+    /// After how many polls of bitcoind to give up on an unconfirmed deposit whose transaction
+    /// is neither in the mempool nor mined
+    #[serde(default = "default_deposit_abandon_polls")]
+    pub deposit_abandon_polls: u32,
+    /// Refuse deposits depending on a longer chain of unconfirmed transactions than this (default:
+    /// no limit)
+    pub max_deposit_ancestry_depth: Option<u32>,
+    /// Refuse deposits depending on unconfirmed transactions weighing more than this many
+    /// virtual bytes in total (default: no limit)
+    pub max_deposit_ancestry_vsize: Option<u64>,
+    /// The derivation index up to which we may extend the deposit addresses we watch, when
+    /// deposits are observed beyond our window
+    #[serde(default = "default_deposit_index_ceiling")]
+    pub deposit_index_ceiling: DerivationIndex,
+    /// The maximum number of vaults a Spend transaction may consume
+    #[serde(default = "default_max_spend_inputs")]
+    pub max_spend_inputs: usize,
+    /// The maximum number of elements (eg outpoints) a single RPC command accepts
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    /// The maximum fees a Spend transaction may pay, as a percentage of the value of the spent
+    /// vaults
+    #[serde(default = "default_max_spend_fee_percent")]
+    pub max_spend_fee_percent: u64,
+    /// The maximum fees a Spend transaction may pay, in satoshis
+    #[serde(default = "default_max_spend_fee")]
+    pub max_spend_fee: u64,
+    /// A command to run when a vault transitions to one of the `notify_statuses`, or gets
+    /// canceled after its Unvault timelock expired. It is passed the deposit outpoint, the
+    /// previous status, the new status and the relevant txid.
+    pub notify_command: Option<PathBuf>,
+    /// The statuses to run the `notify_command` for (default: the alarming ones)
+    #[serde(
+        deserialize_with = "deserialize_vault_statuses",
+        default = "default_notify_statuses"
+    )]
+    pub notify_statuses: Vec<VaultStatus>,
+    /// After how long to kill a `notify_command` that did not exit (default: 30s)
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_notify_timeout"
+    )]
+    pub notify_timeout_secs: Duration,
+    /// How to alert about the vaults being unvaulted, canceled or emergency vaulted depending
+    /// on their value. If set, they take precedence over the `notify_statuses` for these statuses.
+    #[serde(default)]
+    pub alert_tiers: Vec<AlertTierConfig>,
+    /// After how long to give up on an attempt to post to a webhook (default: 10s)
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_webhook_timeout"
+    )]
+    pub webhook_timeout_secs: Duration,
+    /// How often to check bitcoind would still accept our revocation transactions in its
+    /// mempool (default: daily). 0 disables the check.
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_revocation_check_interval"
+    )]
+    pub revocation_check_interval_secs: Duration,
+    /// How often to check the watchonly wallet of bitcoind watches what we imported into it,
+    /// and only that (default: hourly). 0 disables the check.
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_wallet_audit_interval"
+    )]
+    pub wallet_audit_interval_secs: Duration,
+    /// How often to check our presigned transactions pay more than the relay floor of bitcoind
+    /// (default: every 10 minutes, and at startup). 0 disables the check.
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_relay_floor_check_interval"
+    )]
+    pub relay_floor_check_interval_secs: Duration,
+    /// By how much, in percent of the relay floor, our presigned transactions must pay above it
+    /// not to be reported (default: 20)
+    #[serde(default = "default_relay_floor_margin_percent")]
+    pub relay_floor_margin_percent: u64,
+    /// Below how much free space on the filesystem of the data directory to warn, in MiB
+    #[serde(default = "default_disk_space_warning")]
+    pub disk_space_warning_mb: u64,
+    /// Below how much free space on the filesystem of the data directory to refuse storing new
+    /// transactions, in MiB
+    #[serde(default = "default_disk_space_critical")]
+    pub disk_space_critical_mb: u64,
+    /// For how long to remember the result of a command sent with an idempotency key (default:
+    /// a day)
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        default = "default_idempotency_retention"
+    )]
+    pub idempotency_retention_secs: Duration,
+    /// Above how many milliseconds an RPC call is logged as slow, along with where the time was
+    /// spent (default: 2000)
+    #[serde(default = "default_slow_rpc_call_ms")]
+    pub slow_rpc_call_ms: u64,
+    /// Human-readable names for the participants, by fingerprint of their xpub in the
+    /// descriptors
+    #[serde(default)]
+    pub key_labels: HashMap<String, String>,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -192,6 +912,198 @@ impl From<std::io::Error> for ConfigError {
 
 impl std::error::Error for ConfigError {}
 
+// The labels must be for keys we know about, a typo in a fingerprint would otherwise go
+// unnoticed.
+fn check_key_labels(config: &Config) -> Result<(), ConfigError> {
+    let mut xpubs = config.scripts_config.deposit_descriptor.xpubs();
+    xpubs.append(&mut config.scripts_config.unvault_descriptor.xpubs());
+    let fingerprints: Vec<bip32::Fingerprint> = xpubs
+        .into_iter()
+        .filter_map(|xpub| match xpub {
+            DescriptorPublicKey::XPub(xpub) => Some(xpub.xkey.fingerprint()),
+            DescriptorPublicKey::SinglePub(_) => None,
+        })
+        .collect();
+
+    for (fingerprint, label) in config.key_labels.iter() {
+        let known = xpub_fingerprint_from_str(fingerprint)
+            .map(|fg| fingerprints.contains(&fg))
+            .unwrap_or(false);
+        if !known {
+            return Err(ConfigError::Unexpected(format!(
+                "Label '{}' is for '{}', which is not the fingerprint of any xpub in the \
+                 descriptors",
+                label, fingerprint
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// The BIP32 fingerprint of an xpub, or the same first 4 bytes of the key hash for the (single)
+// keys of the cosigning servers.
+fn key_fingerprint(key: &DescriptorPublicKey) -> bip32::Fingerprint {
+    match key {
+        DescriptorPublicKey::XPub(xpub) => xpub.xkey.fingerprint(),
+        DescriptorPublicKey::SinglePub(single) => {
+            bip32::Fingerprint::from(&single.key.pubkey_hash()[0..4])
+        }
+    }
+}
+
+// A participant must have a single role, and a single key. A key reused across participants
+// would silently lower the threshold of the scripts.
+fn check_participant_keys(config: &Config) -> Result<(), ConfigError> {
+    let stk_xpubs = config.scripts_config.deposit_descriptor.xpubs();
+    for (i, xpub) in stk_xpubs.iter().enumerate() {
+        if stk_xpubs[..i].contains(xpub) {
+            return Err(ConfigError::Unexpected(format!(
+                "Stakeholder xpub with fingerprint '{}' appears more than once in the deposit \
+                 descriptor",
+                key_fingerprint(xpub)
+            )));
+        }
+    }
+
+    // The stakeholders' xpubs appear once in the Unvault descriptor, then come the managers'
+    // ones and the cosigning servers' keys.
+    let mut seen: Vec<DescriptorPublicKey> = Vec::new();
+    let mut man_pubkeys = Vec::new();
+    for key in config.scripts_config.unvault_descriptor.xpubs() {
+        let fingerprint = key_fingerprint(&key);
+        if seen.contains(&key) {
+            return Err(ConfigError::Unexpected(match key {
+                DescriptorPublicKey::XPub(_) if stk_xpubs.contains(&key) => format!(
+                    "Xpub with fingerprint '{}' is used both as a stakeholder and as a manager \
+                     key",
+                    fingerprint
+                ),
+                DescriptorPublicKey::XPub(_) => format!(
+                    "Manager xpub with fingerprint '{}' appears more than once in the unvault \
+                     descriptor",
+                    fingerprint
+                ),
+                DescriptorPublicKey::SinglePub(_) => format!(
+                    "Cosigning server key with fingerprint '{}' appears more than once in the \
+                     unvault descriptor",
+                    fingerprint
+                ),
+            }));
+        }
+        if let DescriptorPublicKey::XPub(ref xpub) = key {
+            if !stk_xpubs.contains(&key) {
+                man_pubkeys.push(xpub.xkey.public_key);
+            }
+        }
+        seen.push(key);
+    }
+
+    for key in seen.iter() {
+        if let DescriptorPublicKey::SinglePub(single) = key {
+            if man_pubkeys.contains(&single.key) {
+                return Err(ConfigError::Unexpected(format!(
+                    "Cosigning server key with fingerprint '{}' is the key of a manager's xpub",
+                    key_fingerprint(key)
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The servers given without a port listen on the default one of their kind on our network
+fn set_default_ports(config: &mut Config) {
+    let network = config.bitcoind_config.network;
+    config
+        .coordinator_host
+        .set_default_port(default_port(ServerKind::Coordinator, network));
+    for coordinator in config.backup_coordinators.iter_mut() {
+        coordinator
+            .host
+            .set_default_port(default_port(ServerKind::Coordinator, network));
+    }
+    if let Some(ref mut stk_config) = config.stakeholder_config {
+        for wt in stk_config.watchtowers.iter_mut() {
+            wt.host
+                .set_default_port(default_port(ServerKind::Watchtower, network));
+        }
+    }
+    if let Some(ref mut man_config) = config.manager_config {
+        for cosigner in man_config.cosigners.iter_mut() {
+            cosigner
+                .host
+                .set_default_port(default_port(ServerKind::Cosigner, network));
+        }
+    }
+}
+
+// Serving the JSONRPC interface on the network is opt-in, and must be restricted to some clients.
+fn check_rpc_listen(config: &Config) -> Result<(), ConfigError> {
+    if let Some(addr) = config.rpc_listen {
+        if !addr.ip().is_loopback() && !config.i_understand_the_risks {
+            return Err(ConfigError::Unexpected(format!(
+                "Refusing to serve the JSONRPC interface on non-loopback address '{}'. Set \
+                 'i_understand_the_risks = true' to do it anyway.",
+                addr
+            )));
+        }
+
+        if config.rpc_clients.is_empty() {
+            return Err(ConfigError::Unexpected(
+                "'rpc_listen' is set but no client is allowed to connect in 'rpc_clients'"
+                    .to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// A vault belongs to a single tier, the one with the highest threshold it reaches. The webhooks
+// are posted in plain HTTP, so posting the vaults' details across the network is opt-in.
+fn check_alert_tiers(config: &Config) -> Result<(), ConfigError> {
+    for (i, tier) in config.alert_tiers.iter().enumerate() {
+        if let Some(ref url) = tier.webhook_url {
+            if !url.is_loopback() && !config.i_understand_the_risks {
+                return Err(ConfigError::Unexpected(format!(
+                    "Refusing to post alerts in plain HTTP to non-loopback webhook '{}'. Use a \
+                     local relay for HTTPS, or set 'i_understand_the_risks = true' to do it \
+                     anyway.",
+                    url
+                )));
+            }
+        }
+
+        if config.alert_tiers[..i]
+            .iter()
+            .any(|other| other.min_amount == tier.min_amount)
+        {
+            return Err(ConfigError::Unexpected(format!(
+                "Two alert tiers have the same 'min_amount' ({})",
+                tier.min_amount
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// Not syncing the database to disk is only acceptable for throwaway test deployments, as a power
+// loss may corrupt it.
+fn check_db_synchronous(config: &Config) -> Result<(), ConfigError> {
+    if config.db_synchronous == DbSynchronous::Off
+        && config.bitcoind_config.network == Network::Bitcoin
+    {
+        return Err(ConfigError::Unexpected(
+            "'db_synchronous = \"off\"' is refused on mainnet".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
 /// Get the absolute path to the revault configuration folder.
 ///
 /// It's a "revault/<network>/" directory in the XDG standard configuration directory for
@@ -220,7 +1132,7 @@ pub fn config_folder_path() -> Option<PathBuf> {
     None
 }
 
-fn config_file_path() -> Option<PathBuf> {
+pub fn config_file_path() -> Option<PathBuf> {
     config_folder_path().map(|mut path| {
         path.push("revault.toml");
         path
@@ -237,8 +1149,42 @@ impl Config {
         let config_file =
             custom_path.unwrap_or(config_file_path().ok_or_else(|| ConfigError::DatadirNotFound)?);
 
-        let config = toml::from_slice::<Config>(&std::fs::read(&config_file)?)
+        Self::from_toml(&std::fs::read(&config_file)?)
+    }
+
+    /// Parse and check the content of a configuration file.
+    pub fn from_toml(content: &[u8]) -> Result<Config, ConfigError> {
+        let mut config = toml::from_slice::<Config>(content)
             .map_err(|e| ConfigError::ReadingFile(format!("Parsing configuration file: {}", e)))?;
+        set_default_ports(&mut config);
+
+        check_rpc_listen(&config)?;
+        check_key_labels(&config)?;
+        check_participant_keys(&config)?;
+        check_db_synchronous(&config)?;
+        check_alert_tiers(&config)?;
+        if config.disk_space_critical_mb > config.disk_space_warning_mb {
+            return Err(ConfigError::Unexpected(format!(
+                "The critical disk space threshold ({}MiB) must not be above the warning one ({}MiB)",
+                config.disk_space_critical_mb, config.disk_space_warning_mb
+            )));
+        }
+        if config.sigfetch_batch_size == 0 {
+            return Err(ConfigError::Unexpected(
+                "'sigfetch_batch_size' must be at least 1".to_string(),
+            ));
+        }
+        if config.coordinator_push_rate == 0 || config.coordinator_push_burst == 0 {
+            return Err(ConfigError::Unexpected(
+                "'coordinator_push_rate' and 'coordinator_push_burst' must be at least 1"
+                    .to_string(),
+            ));
+        }
+        if config.bitcoind_config.rpc_connections == 0 {
+            return Err(ConfigError::Unexpected(
+                "'rpc_connections' must be at least 1".to_string(),
+            ));
+        }
 
         let stk_xpubs = config.scripts_config.deposit_descriptor.xpubs();
 
@@ -282,20 +1228,25 @@ impl Config {
 
             if !stk_xpubs.iter().any(|x| x == &our_desc_xpub) {
                 return Err(ConfigError::Unexpected(format!(
-                    r#"Our "stakeholder_config" xpub is not part of the given stakeholders' xpubs: {}"#,
+                    r#"Our "stakeholder_config" xpub (fingerprint '{}') is not part of the given stakeholders' xpubs: {}"#,
+                    stk_config.xpub.fingerprint(),
                     stk_config.xpub
                 )));
             }
 
-            let emer_addr_net = stk_config.emergency_address.address().network;
-            // Signet addresses have testnet type
-            let signet_special_case =
-                bitcoind_net == Network::Signet && emer_addr_net == Network::Testnet;
-            if emer_addr_net != bitcoind_net && !signet_special_case {
-                return Err(ConfigError::Unexpected(format!(
-                    r#"Our "emergency_address" is for '{}' but bitcoind is on '{}'"#,
-                    emer_addr_net, bitcoind_net
-                )));
+            for band in stk_config.emergency_address.bands() {
+                let emer_addr_net = band.address.address().network;
+                // Signet addresses have testnet type
+                let signet_special_case =
+                    bitcoind_net == Network::Signet && emer_addr_net == Network::Testnet;
+                if emer_addr_net != bitcoind_net && !signet_special_case {
+                    return Err(ConfigError::Unexpected(format!(
+                        r#"Our "emergency_address" '{}' is for '{}' but bitcoind is on '{}'"#,
+                        band.address.address(),
+                        emer_addr_net,
+                        bitcoind_net
+                    )));
+                }
             }
         }
 
@@ -324,10 +1275,27 @@ impl Config {
 
             if !man_xpubs.iter().any(|x| x == &our_desc_xpub) {
                 return Err(ConfigError::Unexpected(format!(
-                    r#"Our "manager_config" xpub is not part of the given managers' xpubs: {}"#,
+                    r#"Our "manager_config" xpub (fingerprint '{}') is not part of the given managers' xpubs: {}"#,
+                    man_config.xpub.fingerprint(),
                     man_config.xpub
                 )));
             }
+
+            if let Some(threshold) = man_config.spend_approval_threshold {
+                if threshold == 0 || threshold > man_xpubs.len() {
+                    return Err(ConfigError::Unexpected(format!(
+                        r#""spend_approval_threshold" must be between 1 and the number of managers ({}), got {}"#,
+                        man_xpubs.len(),
+                        threshold
+                    )));
+                }
+            }
+
+            if let Some(ref schedule) = man_config.spending_schedule {
+                schedule
+                    .check()
+                    .map_err(|e| ConfigError::Unexpected(e.to_string()))?;
+            }
         }
 
         Ok(config)
@@ -336,7 +1304,76 @@ impl Config {
 
 #[cfg(test)]
 mod tests {
-    use super::{config_file_path, Config};
+    use super::{
+        check_alert_tiers, check_db_synchronous, check_key_labels, check_participant_keys,
+        check_rpc_listen, config_file_path, noise_fingerprint_matches, noise_pubkey_fingerprint,
+        noise_pubkey_from_str, AlertTierConfig, BitcoindConfig, Config, CoordinatorConfig,
+        CosignerConfig, DbSynchronous, EmergencyAddresses, LogFormat, ManagerConfig,
+        RpcClientConfig, ScriptsConfig, StakeholderConfig, WatchtowerConfig, EXAMPLE_CONFIG,
+    };
+    use crate::{alerts::WebhookUrl, revaultd::VaultStatus, utils::test_utils::test_datadir};
+    use revault_tx::bitcoin::{Address, Amount, Network};
+
+    use std::{collections::HashMap, fs, path::PathBuf, str::FromStr, time::Duration};
+
+    use serde::{
+        de::{self, DeserializeOwned, Visitor},
+        forward_to_deserialize_any, Deserializer,
+    };
+
+    // Gets the names of the fields of a struct out of its Deserialize implementation.
+    struct FieldsCollector<'a>(&'a mut Vec<&'static str>);
+
+    impl<'de, 'a> Deserializer<'de> for FieldsCollector<'a> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("Not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0.extend_from_slice(fields);
+            Err(de::Error::custom("Fields collected"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option
+            unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    fn struct_fields<T: DeserializeOwned>() -> Vec<&'static str> {
+        let mut fields = vec![];
+        assert!(T::deserialize(FieldsCollector(&mut fields)).is_err());
+        fields
+    }
+
+    // The keys in each table of the example config, whether they are commented out or not.
+    fn example_config_keys() -> HashMap<String, Vec<String>> {
+        let mut keys = HashMap::new();
+        let mut table = String::new();
+
+        for line in EXAMPLE_CONFIG.lines() {
+            let line = line.trim_start_matches('#').trim();
+            if line.starts_with('[') {
+                table = line.trim_matches(|c| c == '[' || c == ']').to_string();
+            } else if let Some(i) = line.find(" = ") {
+                let key = &line[..i];
+                if key.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+                    keys.entry(table.clone())
+                        .or_insert_with(Vec::new)
+                        .push(key.to_string());
+                }
+            }
+        }
+
+        keys
+    }
 
     // Test the format of the configuration file
     #[test]
@@ -367,13 +1404,60 @@ mod tests {
             watchtowers = [ { host = "127.0.0.1:1", noise_key = "46084f8a7da40ef7ffc38efa5af8a33a742b90f920885d17c533bb2a0b680cb3" } ]
             emergency_address = "bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej"
         "#;
-        toml::from_str::<Config>(toml_str).expect("Deserializing stakeholder toml_str");
+        let config =
+            toml::from_str::<Config>(toml_str).expect("Deserializing stakeholder toml_str");
+        assert_eq!(config.log_format, LogFormat::Human);
+        assert!(!config.read_only);
+        assert!(!config.verify_derivations);
+        assert_eq!(config.db_synchronous, DbSynchronous::Full);
+        assert!(!config.db_secure_delete);
+        assert!(config.bind_address.is_none());
+        assert!(config.notify_command.is_none());
+        assert_eq!(
+            config.revocation_check_interval_secs,
+            Duration::from_secs(24 * 3600)
+        );
+        assert_eq!(config.wallet_audit_interval_secs, Duration::from_secs(3600));
+        assert_eq!(
+            config.relay_floor_check_interval_secs,
+            Duration::from_secs(600)
+        );
+        assert_eq!(config.relay_floor_margin_percent, 20);
+        assert_eq!(config.disk_space_warning_mb, 1024);
+        assert_eq!(config.disk_space_critical_mb, 100);
+        assert!(config.alert_tiers.is_empty());
+        assert_eq!(config.webhook_timeout_secs, Duration::from_secs(10));
+        assert_eq!(
+            config.idempotency_retention_secs,
+            Duration::from_secs(24 * 3600)
+        );
+        assert_eq!(config.slow_rpc_call_ms, 2000);
+        assert_eq!(
+            config.notify_statuses,
+            vec![
+                VaultStatus::Unvaulting,
+                VaultStatus::Canceling,
+                VaultStatus::EmergencyVaulting
+            ]
+        );
 
         // A valid manager config
         let toml_str = r#"
             daemon = false
             log_level = "trace"
+            log_format = "json"
             data_dir = "/home/wizardsardine/custom/folder/"
+            read_only = true
+            notify_command = "/usr/local/bin/page_the_team"
+            notify_statuses = ["spending", "canceled"]
+            revocation_check_interval_secs = 0
+            wallet_audit_interval_secs = 0
+            relay_floor_check_interval_secs = 0
+            relay_floor_margin_percent = 50
+            disk_space_warning_mb = 2048
+            disk_space_critical_mb = 512
+            idempotency_retention_secs = 600
+            slow_rpc_call_ms = 500
 
             coordinator_host = "127.0.0.1:1"
             coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
@@ -394,7 +1478,31 @@ mod tests {
             xpub = "xpub6AtVcKWPpZ9t3Aa3VvzWid1dzJFeXPfNntPbkGsYjNrp7uhXpzSL5QVMCmaHqUzbVUGENEwbBbzF9E8emTxQeP3AzbMjfzvwSDkwUrxg2G4"
             cosigners = [ { host = "127.0.0.1:1", noise_key = "087629614d227ff2b9ed5f2ce2eb7cd527d2d18f866b24009647251fce58de38" } ]
         "#;
-        toml::from_str::<Config>(toml_str).expect("Deserializing manager toml_str");
+        let config = toml::from_str::<Config>(toml_str).expect("Deserializing manager toml_str");
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert!(config.read_only);
+        assert_eq!(
+            config.notify_command,
+            Some(PathBuf::from("/usr/local/bin/page_the_team"))
+        );
+        assert_eq!(
+            config.notify_statuses,
+            vec![VaultStatus::Spending, VaultStatus::Canceled]
+        );
+        assert_eq!(
+            config.revocation_check_interval_secs,
+            Duration::from_secs(0)
+        );
+        assert_eq!(config.wallet_audit_interval_secs, Duration::from_secs(0));
+        assert_eq!(
+            config.relay_floor_check_interval_secs,
+            Duration::from_secs(0)
+        );
+        assert_eq!(config.relay_floor_margin_percent, 50);
+        assert_eq!(config.disk_space_warning_mb, 2048);
+        assert_eq!(config.disk_space_critical_mb, 512);
+        assert_eq!(config.idempotency_retention_secs, Duration::from_secs(600));
+        assert_eq!(config.slow_rpc_call_ms, 500);
 
         // A valid manager config (no cosigning server)
         let toml_str = r#"
@@ -496,6 +1604,465 @@ mod tests {
         config_res.expect_err("Deserializing an invalid toml_str");
     }
 
+    #[test]
+    fn example_config() {
+        // The example documents all the settings, and only existing ones.
+        let tables = vec![
+            ("", struct_fields::<Config>()),
+            ("bitcoind_config", struct_fields::<BitcoindConfig>()),
+            ("scripts_config", struct_fields::<ScriptsConfig>()),
+            ("stakeholder_config", struct_fields::<StakeholderConfig>()),
+            (
+                "stakeholder_config.watchtowers",
+                struct_fields::<WatchtowerConfig>(),
+            ),
+            ("manager_config", struct_fields::<ManagerConfig>()),
+            (
+                "manager_config.cosigners",
+                struct_fields::<CosignerConfig>(),
+            ),
+            ("rpc_clients", struct_fields::<RpcClientConfig>()),
+            ("backup_coordinators", struct_fields::<CoordinatorConfig>()),
+            ("alert_tiers", struct_fields::<AlertTierConfig>()),
+            // Keyed by fingerprint, not by field name
+            ("key_labels", vec![]),
+            // Keyed by module path
+            ("log_modules", vec![]),
+        ];
+        let mut example_keys = example_config_keys();
+        for (table, fields) in tables.iter() {
+            let mut expected: Vec<String> = fields
+                .iter()
+                .filter(|field| {
+                    let subtable = if table.is_empty() {
+                        field.to_string()
+                    } else {
+                        format!("{}.{}", table, field)
+                    };
+                    !tables.iter().any(|(t, _)| *t == subtable)
+                })
+                .map(|field| field.to_string())
+                .collect();
+            expected.sort();
+            let mut keys = example_keys.remove(*table).unwrap_or_else(Vec::new);
+            keys.sort();
+            assert_eq!(keys, expected, "In table '{}'", table);
+        }
+        assert!(example_keys.is_empty(), "{:?}", example_keys);
+
+        // And it's valid
+        let datadir = test_datadir();
+        fs::create_dir_all(&datadir).unwrap();
+        let config_path = datadir.join("revaultd.toml");
+        fs::write(&config_path, EXAMPLE_CONFIG).unwrap();
+        let config = Config::from_file(Some(config_path)).expect("Parsing example config");
+        assert!(config.stakeholder_config.is_some() && config.manager_config.is_some());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn rpc_listen_config() {
+        let toml_str = r#"
+            daemon = false
+            data_dir = "/home/wizardsardine/custom/folder/"
+            rpc_listen = "127.0.0.1:8484"
+            bind_address = "127.0.0.2"
+            rpc_clients = [ { noise_key = "087629614d227ff2b9ed5f2ce2eb7cd527d2d18f866b24009647251fce58de38" } ]
+
+            coordinator_host = "127.0.0.1:1"
+            coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
+
+            [scripts_config]
+            cpfp_descriptor = "wsh(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)))#cwycq5xu"
+            deposit_descriptor = "wsh(multi(2,xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*,xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))#n3cj9mhy"
+            unvault_descriptor = "wsh(andor(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)),and_v(v:multi(2,03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a,0295e7f5d12a2061f1fd2286cefec592dff656a19f55f4f01305d6aa56630880ce),older(4)),thresh(2,pkh(xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*),a:pkh(xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))))#532k8uvf"
+
+            [bitcoind_config]
+            network = "bitcoin"
+            cookie_path = "/home/user/.bitcoin/.cookie"
+            addr = "127.0.0.1:8332"
+        "#;
+        let mut config = toml::from_str::<Config>(toml_str).expect("Deserializing toml_str");
+        assert_eq!(config.rpc_clients.len(), 1);
+        assert_eq!(config.bind_address, Some("127.0.0.2".parse().unwrap()));
+        check_rpc_listen(&config).expect("Loopback with a client is fine");
+
+        // A non-loopback address must be explicitly allowed
+        config.rpc_listen = Some("0.0.0.0:8484".parse().unwrap());
+        check_rpc_listen(&config).expect_err("Non-loopback without the flag");
+        config.i_understand_the_risks = true;
+        check_rpc_listen(&config).expect("Non-loopback with the flag");
+
+        // There must be at least one client
+        config.rpc_clients.clear();
+        check_rpc_listen(&config).expect_err("No client");
+    }
+
+    #[test]
+    fn server_endpoints_config() {
+        let toml_str = |coordinator_host: &str| {
+            format!(
+                r#"
+            daemon = false
+            data_dir = "/home/wizardsardine/custom/folder/"
+
+            coordinator_host = "{}"
+            coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
+
+            [[backup_coordinators]]
+            host = "[2001:db8::1]:4242"
+            noise_key = "087629614d227ff2b9ed5f2ce2eb7cd527d2d18f866b24009647251fce58de38"
+
+            [scripts_config]
+            cpfp_descriptor = "wsh(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)))#cwycq5xu"
+            deposit_descriptor = "wsh(multi(2,xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*,xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))#n3cj9mhy"
+            unvault_descriptor = "wsh(andor(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)),and_v(v:multi(2,03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a,0295e7f5d12a2061f1fd2286cefec592dff656a19f55f4f01305d6aa56630880ce),older(4)),thresh(2,pkh(xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*),a:pkh(xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))))#532k8uvf"
+
+            [bitcoind_config]
+            network = "bitcoin"
+            cookie_path = "/home/user/.bitcoin/.cookie"
+            addr = "127.0.0.1:8332"
+        "#,
+                coordinator_host
+            )
+        };
+
+        // A hostname without port gets the default one of the network, explicit ports are kept
+        let mut config = toml::from_str::<Config>(&toml_str("coordinator.revault.example"))
+            .expect("Deserializing toml_str");
+        set_default_ports(&mut config);
+        assert_eq!(
+            config.coordinator_host.to_string(),
+            "coordinator.revault.example:8383"
+        );
+        assert_eq!(
+            config.backup_coordinators[0].host.to_string(),
+            "[2001:db8::1]:4242"
+        );
+
+        // It's not resolved when loading the configuration
+        Config::from_toml(toml_str("revaultd-test.invalid:8383").as_bytes())
+            .expect("Unresolvable hostname");
+
+        for invalid in &["::1:8383", "coordinator_host.example", "127.0.0.1:0"] {
+            let err = toml::from_str::<Config>(&toml_str(invalid)).expect_err(invalid);
+            assert!(
+                err.to_string().contains("Invalid server address"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn db_synchronous_config() {
+        let toml_str = r#"
+            daemon = false
+            data_dir = "/home/wizardsardine/custom/folder/"
+            db_synchronous = "off"
+
+            coordinator_host = "127.0.0.1:1"
+            coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
+
+            [scripts_config]
+            cpfp_descriptor = "wsh(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)))#cwycq5xu"
+            deposit_descriptor = "wsh(multi(2,xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*,xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))#n3cj9mhy"
+            unvault_descriptor = "wsh(andor(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)),and_v(v:multi(2,03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a,0295e7f5d12a2061f1fd2286cefec592dff656a19f55f4f01305d6aa56630880ce),older(4)),thresh(2,pkh(xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*),a:pkh(xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))))#532k8uvf"
+
+            [bitcoind_config]
+            network = "bitcoin"
+            cookie_path = "/home/user/.bitcoin/.cookie"
+            addr = "127.0.0.1:8332"
+        "#;
+        let mut config = toml::from_str::<Config>(toml_str).expect("Deserializing toml_str");
+        assert_eq!(config.db_synchronous, DbSynchronous::Off);
+        check_db_synchronous(&config).expect_err("Not syncing on mainnet");
+
+        config.bitcoind_config.network = Network::Regtest;
+        check_db_synchronous(&config).expect("Not syncing on regtest");
+        config.bitcoind_config.network = Network::Bitcoin;
+        config.db_synchronous = DbSynchronous::Normal;
+        check_db_synchronous(&config).expect("Syncing less often on mainnet");
+    }
+
+    #[test]
+    fn alert_tiers_config() {
+        let toml_str = r#"
+            daemon = false
+            data_dir = "/home/wizardsardine/custom/folder/"
+            webhook_timeout_secs = 3
+
+            coordinator_host = "127.0.0.1:1"
+            coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
+
+            [scripts_config]
+            cpfp_descriptor = "wsh(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)))#cwycq5xu"
+            deposit_descriptor = "wsh(multi(2,xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*,xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))#n3cj9mhy"
+            unvault_descriptor = "wsh(andor(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)),and_v(v:multi(2,03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a,0295e7f5d12a2061f1fd2286cefec592dff656a19f55f4f01305d6aa56630880ce),older(4)),thresh(2,pkh(xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*),a:pkh(xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))))#532k8uvf"
+
+            [bitcoind_config]
+            network = "bitcoin"
+            cookie_path = "/home/user/.bitcoin/.cookie"
+            addr = "127.0.0.1:8332"
+
+            [[alert_tiers]]
+            min_amount = 0
+            log_level = "info"
+            run_notify_command = false
+
+            [[alert_tiers]]
+            name = "large"
+            min_amount = 100000000
+            webhook_url = "http://127.0.0.1:8080/revault"
+        "#;
+        let mut config = toml::from_str::<Config>(toml_str).expect("Deserializing toml_str");
+        assert_eq!(config.webhook_timeout_secs, Duration::from_secs(3));
+        assert_eq!(config.alert_tiers.len(), 2);
+        assert_eq!(config.alert_tiers[0].log_level, log::LevelFilter::Info);
+        assert!(!config.alert_tiers[0].run_notify_command);
+        assert!(config.alert_tiers[0].webhook_url.is_none());
+        assert_eq!(config.alert_tiers[1].name.as_deref(), Some("large"));
+        assert_eq!(config.alert_tiers[1].log_level, log::LevelFilter::Warn);
+        assert!(config.alert_tiers[1].run_notify_command);
+        assert_eq!(
+            config.alert_tiers[1]
+                .webhook_url
+                .as_ref()
+                .map(|url| url.to_string()),
+            Some("http://127.0.0.1:8080/revault".to_string())
+        );
+        check_alert_tiers(&config).expect("Distinct thresholds");
+
+        // Two tiers for the same amount would be ambiguous
+        config.alert_tiers[1].min_amount = 0;
+        check_alert_tiers(&config).expect_err("Duplicate threshold");
+        config.alert_tiers[1].min_amount = 100_000_000;
+
+        // Posting in clear across the network must be explicitly allowed
+        config.alert_tiers[1].webhook_url = Some(WebhookUrl::from_str("http://[::1]/").unwrap());
+        check_alert_tiers(&config).expect("IPv6 loopback webhook");
+        config.alert_tiers[1].webhook_url =
+            Some(WebhookUrl::from_str("http://localhost:8080/revault").unwrap());
+        check_alert_tiers(&config).expect("Local webhook");
+        config.alert_tiers[1].webhook_url =
+            Some(WebhookUrl::from_str("http://alerts.example.com/revault").unwrap());
+        check_alert_tiers(&config).expect_err("Remote webhook without the flag");
+        config.i_understand_the_risks = true;
+        check_alert_tiers(&config).expect("Remote webhook with the flag");
+
+        // Only plain HTTP is supported
+        let toml_str = toml_str.replace("http://127.0.0.1:8080", "https://127.0.0.1:8080");
+        toml::from_str::<Config>(&toml_str).expect_err("HTTPS webhook");
+    }
+
+    #[test]
+    fn key_labels_config() {
+        let toml_str = r#"
+            daemon = false
+            data_dir = "/home/wizardsardine/custom/folder/"
+
+            coordinator_host = "127.0.0.1:1"
+            coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
+
+            [scripts_config]
+            cpfp_descriptor = "wsh(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)))#cwycq5xu"
+            deposit_descriptor = "wsh(multi(2,xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*,xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))#n3cj9mhy"
+            unvault_descriptor = "wsh(andor(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)),and_v(v:multi(2,03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a,0295e7f5d12a2061f1fd2286cefec592dff656a19f55f4f01305d6aa56630880ce),older(4)),thresh(2,pkh(xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*),a:pkh(xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))))#532k8uvf"
+
+            [bitcoind_config]
+            network = "bitcoin"
+            cookie_path = "/home/user/.bitcoin/.cookie"
+            addr = "127.0.0.1:8332"
+
+            [key_labels]
+            efc58b83 = "Alice"
+            "ff0d:513d" = "Bob"
+            "007FCAEA" = "The manager"
+        "#;
+        let mut config = toml::from_str::<Config>(toml_str).expect("Deserializing toml_str");
+        assert_eq!(config.key_labels.len(), 3);
+        check_key_labels(&config).expect("Stakeholders' and manager's fingerprints, any grouping");
+
+        // A label for a key that isn't in the descriptors is most likely a typo
+        config
+            .key_labels
+            .insert("efc58b84".to_string(), "Alice's typo".to_string());
+        check_key_labels(&config).expect_err("Unknown fingerprint");
+        config.key_labels.remove("efc58b84");
+        config
+            .key_labels
+            .insert("Alice".to_string(), "Not a fingerprint".to_string());
+        check_key_labels(&config).expect_err("Invalid fingerprint");
+    }
+
+    #[test]
+    fn participant_keys_config() {
+        // Two stakeholders, a manager and two cosigning servers
+        let (stk_a, stk_b, man) = (
+            "xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU",
+            "xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux",
+            "xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb",
+        );
+        let (cosig_a, cosig_b) = (
+            "03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a",
+            "0295e7f5d12a2061f1fd2286cefec592dff656a19f55f4f01305d6aa56630880ce",
+        );
+        // The key of the manager's xpub, as a cosigning server key
+        let man_key = "02649503d4aa6e020bb0616de9fc052ce95245a57ca15b1df19767fb4941d87416";
+        let toml_str = |stks: (&str, &str), mans: &[&str], cosigs: (&str, &str), ours: &str| {
+            let man_pks: Vec<String> = mans
+                .iter()
+                .enumerate()
+                .map(|(i, man)| format!("{}pk({}/*)", if i == 0 { "" } else { "a:" }, man))
+                .collect();
+            let man_thresh = format!("thresh({},{})", mans.len(), man_pks.join(","));
+            format!(
+                r#"
+                daemon = false
+                data_dir = "/home/wizardsardine/custom/folder/"
+
+                coordinator_host = "127.0.0.1:1"
+                coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
+
+                [scripts_config]
+                cpfp_descriptor = "wsh(thresh(1,pk({man}/*)))"
+                deposit_descriptor = "wsh(multi(2,{stk_a}/*,{stk_b}/*))"
+                unvault_descriptor = "wsh(andor({man_thresh},and_v(v:multi(2,{cosig_a},{cosig_b}),older(4)),thresh(2,pkh({stk_a}/*),a:pkh({stk_b}/*))))"
+
+                [bitcoind_config]
+                network = "bitcoin"
+                cookie_path = "/home/user/.bitcoin/.cookie"
+                addr = "127.0.0.1:8332"
+
+                {ours}
+            "#,
+                man = man,
+                stk_a = stks.0,
+                stk_b = stks.1,
+                man_thresh = man_thresh,
+                cosig_a = cosigs.0,
+                cosig_b = cosigs.1,
+                ours = ours,
+            )
+        };
+        let check = |toml_str: String| {
+            let config = toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+            check_participant_keys(&config)
+                .map_err(|e| e.to_string())
+                .and_then(|_| Config::from_toml(toml_str.as_bytes()).map_err(|e| e.to_string()))
+                .map(|_| ())
+        };
+
+        check(toml_str((stk_a, stk_b), &[man], (cosig_a, cosig_b), "")).expect("Distinct keys");
+
+        // Each key of a stakeholder must be distinct
+        let err = check(toml_str((stk_a, stk_a), &[man], (cosig_a, cosig_b), ""))
+            .expect_err("Duplicated stakeholder");
+        assert!(
+            err.contains("Stakeholder xpub with fingerprint 'efc58b83'"),
+            "{}",
+            err
+        );
+
+        // A stakeholder can't be a manager too
+        let err = check(toml_str(
+            (stk_a, stk_b),
+            &[man, stk_b],
+            (cosig_a, cosig_b),
+            "",
+        ))
+        .expect_err("Stakeholder as manager");
+        assert!(
+            err.contains("fingerprint 'ff0d513d' is used both as a stakeholder and as a manager"),
+            "{}",
+            err
+        );
+
+        // Each key of a manager must be distinct
+        let err = check(toml_str(
+            (stk_a, stk_b),
+            &[man, man],
+            (cosig_a, cosig_b),
+            "",
+        ))
+        .expect_err("Duplicated manager");
+        assert!(
+            err.contains("Manager xpub with fingerprint '007fcaea'"),
+            "{}",
+            err
+        );
+
+        // Each key of a cosigning server must be distinct
+        let err = check(toml_str((stk_a, stk_b), &[man], (cosig_a, cosig_a), ""))
+            .expect_err("Duplicated cosigning server");
+        assert!(
+            err.contains("Cosigning server key with fingerprint"),
+            "{}",
+            err
+        );
+        assert!(err.contains("appears more than once"), "{}", err);
+
+        // A cosigning server can't use the key of a manager
+        let err = check(toml_str((stk_a, stk_b), &[man], (cosig_a, man_key), ""))
+            .expect_err("Cosigning server with a manager's key");
+        assert!(err.contains("is the key of a manager's xpub"), "{}", err);
+
+        // Our own xpubs must be part of the ones for our role
+        let stk_config = |xpub: &str| {
+            format!(
+                r#"
+                [stakeholder_config]
+                xpub = "{}"
+                watchtowers = []
+                emergency_address = "bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej"
+                "#,
+                xpub
+            )
+        };
+        let man_config = |xpub: &str| {
+            format!(
+                r#"
+                [manager_config]
+                xpub = "{}"
+                cosigners = []
+                "#,
+                xpub
+            )
+        };
+        check(toml_str(
+            (stk_a, stk_b),
+            &[man],
+            (cosig_a, cosig_b),
+            &(stk_config(stk_b) + &man_config(man)),
+        ))
+        .expect("Our keys are part of the descriptors");
+        let err = check(toml_str(
+            (stk_a, stk_b),
+            &[man],
+            (cosig_a, cosig_b),
+            &stk_config(man),
+        ))
+        .expect_err("Our stakeholder xpub is a manager's");
+        assert!(
+            err.contains(r#"Our "stakeholder_config" xpub (fingerprint '007fcaea')"#),
+            "{}",
+            err
+        );
+        let err = check(toml_str(
+            (stk_a, stk_b),
+            &[man],
+            (cosig_a, cosig_b),
+            &man_config(stk_a),
+        ))
+        .expect_err("Our manager xpub is a stakeholder's");
+        assert!(
+            err.contains(r#"Our "manager_config" xpub (fingerprint 'efc58b83')"#),
+            "{}",
+            err
+        );
+    }
+
     #[test]
     fn config_directory() {
         let filepath = config_file_path().expect("Getting config file path");
@@ -516,4 +2083,207 @@ mod tests {
             .as_path()
             .ends_with(r#"AppData\Roaming\Revault\revault.toml"#));
     }
+
+    #[test]
+    fn noise_key_encodings() {
+        let hex = "46084f8a7da40ef7ffc38efa5af8a33a742b90f920885d17c533bb2a0b680cb3";
+        let pubkey = noise_pubkey_from_str(hex).unwrap();
+        assert_eq!(noise_pubkey_fingerprint(&pubkey), "4608:4f8a:7da4:0ef7");
+
+        // The grouped form and separators are normalized to the full key
+        let grouped =
+            "4608:4f8a:7da4:0ef7:ffc3:8efa:5af8:a33a:742b:90f9:2088:5d17:c533:bb2a:0b68:0cb3";
+        assert_eq!(noise_pubkey_from_str(grouped).unwrap().0, pubkey.0);
+        let spaced =
+            "4608 4F8A 7DA4 0EF7 FFC3 8EFA 5AF8 A33A 742B 90F9 2088 5D17 C533 BB2A 0B68 0CB3";
+        assert_eq!(noise_pubkey_from_str(spaced).unwrap().0, pubkey.0);
+
+        // A fingerprint alone isn't a key
+        noise_pubkey_from_str("4608:4f8a:7da4:0ef7").unwrap_err();
+        noise_pubkey_from_str("not hex").unwrap_err();
+
+        // Any grouping of the fingerprint matches, but not another key's
+        assert!(noise_fingerprint_matches(&pubkey, "4608:4f8a:7da4:0ef7"));
+        assert!(noise_fingerprint_matches(&pubkey, "46084F8A 7DA40EF7"));
+        assert!(noise_fingerprint_matches(&pubkey, "46084f8a7da40ef7"));
+        assert!(!noise_fingerprint_matches(&pubkey, "4608:4f8a:7da4:0ef8"));
+        assert!(!noise_fingerprint_matches(&pubkey, "4608:4f8a"));
+
+        // The key may be followed by its fingerprint, which is checked and then dropped
+        let with_fingerprint = format!("{}#4608:4f8a:7da4:0ef7", hex);
+        assert_eq!(
+            noise_pubkey_from_str(&with_fingerprint).unwrap().0,
+            pubkey.0
+        );
+        let with_fingerprint = format!("{} # 46084F8A7DA40EF7", grouped);
+        assert_eq!(
+            noise_pubkey_from_str(&with_fingerprint).unwrap().0,
+            pubkey.0
+        );
+        let mismatch = format!("{}#d915:6397:3102:4541", hex);
+        assert!(noise_pubkey_from_str(&mismatch)
+            .unwrap_err()
+            .contains("Fingerprint mismatch"));
+        noise_pubkey_from_str(&format!("{}#", hex)).unwrap_err();
+
+        // Which is what is done for the keys in the configuration
+        let watchtower = |noise_key: &str| {
+            toml::from_str::<WatchtowerConfig>(&format!(
+                "host = \"127.0.0.1:1\"\nnoise_key = \"{}\"",
+                noise_key
+            ))
+        };
+        let wt = watchtower(&format!("{}#4608:4f8a:7da4:0ef7", hex)).unwrap();
+        assert_eq!(wt.noise_key.0, pubkey.0);
+        watchtower(&mismatch).unwrap_err();
+    }
+
+    #[test]
+    fn emergency_address_bands() {
+        const ADDR_A: &str = "bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej";
+        const ADDR_B: &str = "bc1qqesfhzgryvzcpyj303ttg5yj8z5vmtlm4s74zk2fpqrx8mhga5hqp8s5fh";
+        const ADDR_C: &str = "bc1qvpk88fvxm86kvm4flpl04uzyaz5zk5lehzf738d47t79ghlr494qaehthc";
+        let stakeholder_config = |emergency: &str| {
+            toml::from_str::<StakeholderConfig>(&format!(
+                r#"
+                xpub = "xpub6AP3nZhB34Zoan3KCL9bAdnwNHdzMbskLudpbchwTfkHwnNDXYf1769gzozjgzDNUF7iwa5nCdhE5byrcx5PDKFCUDByeuqiHa382EKhcay"
+                watchtowers = []
+                {}
+                "#,
+                emergency
+            ))
+            .map(|config| config.emergency_address)
+            .map_err(|e| e.to_string())
+        };
+        let routed = |addresses: &EmergencyAddresses, sat: u64| {
+            addresses
+                .for_amount(Amount::from_sat(sat))
+                .address()
+                .to_string()
+        };
+
+        // A single address gets all vaults
+        let single = stakeholder_config(&format!(r#"emergency_address = "{}""#, ADDR_A)).unwrap();
+        assert_eq!(single.bands().len(), 1);
+        for sat in &[0, 1, 100_000_000, 2_100_000_000_000_000] {
+            assert_eq!(routed(&single, *sat), ADDR_A);
+        }
+        assert!(single.contains(&Address::from_str(ADDR_A).unwrap()));
+        assert!(!single.contains(&Address::from_str(ADDR_B).unwrap()));
+
+        // Bands, in any order in the configuration, in satoshis or bitcoins. The minimum is
+        // included, the maximum excluded.
+        let bands = stakeholder_config(&format!(
+            r#"
+            [[emergency_address]]
+            address = "{}"
+            min_amount = "1"
+            [[emergency_address]]
+            address = "{}"
+            max_amount = 10000000
+            [[emergency_address]]
+            address = "{}"
+            min_amount = 10000000
+            max_amount = "1"
+            "#,
+            ADDR_C, ADDR_A, ADDR_B
+        ))
+        .unwrap();
+        assert_eq!(
+            bands
+                .bands()
+                .iter()
+                .map(|band| band.address.address().to_string())
+                .collect::<Vec<_>>(),
+            vec![ADDR_A, ADDR_B, ADDR_C]
+        );
+        assert_eq!(routed(&bands, 0), ADDR_A);
+        assert_eq!(routed(&bands, 9_999_999), ADDR_A);
+        assert_eq!(routed(&bands, 10_000_000), ADDR_B);
+        assert_eq!(routed(&bands, 99_999_999), ADDR_B);
+        assert_eq!(routed(&bands, 100_000_000), ADDR_C);
+        assert_eq!(routed(&bands, 2_100_000_000_000_000), ADDR_C);
+        assert!(bands.contains(&Address::from_str(ADDR_B).unwrap()));
+
+        // A single band with no bounds is the same as a single address
+        let one_band = stakeholder_config(&format!(
+            r#"emergency_address = [ {{ address = "{}" }} ]"#,
+            ADDR_B
+        ))
+        .unwrap();
+        assert_eq!(routed(&one_band, 42), ADDR_B);
+
+        // The bands must cover all amounts, and not overlap
+        for (bands, error) in &[
+            ("[]", "at least one Emergency address"),
+            (
+                r#"[ { address = "A", min_amount = 1000 } ]"#,
+                "less than 0.00001000 BTC have no Emergency address",
+            ),
+            (
+                r#"[ { address = "A", max_amount = 1000 } ]"#,
+                "0.00001000 BTC or more have no Emergency address",
+            ),
+            (
+                r#"[ { address = "A", max_amount = 1000 }, { address = "B", min_amount = 2000 } ]"#,
+                "from 0.00001000 BTC to 0.00002000 BTC have no Emergency address",
+            ),
+            (
+                r#"[ { address = "A", max_amount = 2000 }, { address = "B", min_amount = 1000 } ]"#,
+                "overlap",
+            ),
+            (r#"[ { address = "A" }, { address = "B" } ]"#, "overlap"),
+            (
+                r#"[ { address = "A", min_amount = 0, max_amount = 0 } ]"#,
+                "empty band",
+            ),
+            (r#"[ { address = "A", max_amount = -1 } ]"#, "negative"),
+            (r#"[ { min_amount = 0 } ]"#, "address"),
+        ] {
+            let bands = bands
+                .replace(r#""A""#, &format!(r#""{}""#, ADDR_A))
+                .replace(r#""B""#, &format!(r#""{}""#, ADDR_B));
+            let err = stakeholder_config(&format!("emergency_address = {}", bands)).unwrap_err();
+            assert!(err.contains(error), "'{}' for '{}'", err, bands);
+        }
+
+        // Bands in a stakeholder-manager configuration
+        let toml_str = format!(
+            r#"
+            daemon = false
+            coordinator_host = "127.0.0.1:1"
+            coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
+
+            [scripts_config]
+            cpfp_descriptor = "wsh(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)))#cwycq5xu"
+            deposit_descriptor = "wsh(multi(2,xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*,xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))#n3cj9mhy"
+            unvault_descriptor = "wsh(andor(thresh(1,pk(xpub6BaZSKgpaVvibu2k78QsqeDWXp92xLHZxiu1WoqLB9hKhsBf3miBUDX7PJLgSPvkj66ThVHTqdnbXpeu8crXFmDUd4HeM4s4miQS2xsv3Qb/*)),and_v(v:multi(2,03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a,0295e7f5d12a2061f1fd2286cefec592dff656a19f55f4f01305d6aa56630880ce),older(4)),thresh(2,pkh(xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/*),a:pkh(xpub6AaffFGfH6WXfm6pwWzmUMuECQnoLeB3agMKaLyEBZ5ZVfwtnS5VJKqXBt8o5ooCWVy2H87GsZshp7DeKE25eWLyd1Ccuh2ZubQUkgpiVux/*))))#532k8uvf"
+
+            [bitcoind_config]
+            network = "bitcoin"
+            cookie_path = "/home/user/.bitcoin/.cookie"
+            addr = "127.0.0.1:8332"
+
+            [manager_config]
+            xpub = "xpub6AtVcKWPpZ9t3Aa3VvzWid1dzJFeXPfNntPbkGsYjNrp7uhXpzSL5QVMCmaHqUzbVUGENEwbBbzF9E8emTxQeP3AzbMjfzvwSDkwUrxg2G4"
+
+            [stakeholder_config]
+            xpub = "xpub6AP3nZhB34Zoan3KCL9bAdnwNHdzMbskLudpbchwTfkHwnNDXYf1769gzozjgzDNUF7iwa5nCdhE5byrcx5PDKFCUDByeuqiHa382EKhcay"
+            watchtowers = []
+
+            [[stakeholder_config.emergency_address]]
+            address = "{}"
+            max_amount = "0.5"
+
+            [[stakeholder_config.emergency_address]]
+            address = "{}"
+            min_amount = "0.5"
+            "#,
+            ADDR_A, ADDR_B
+        );
+        let config = toml::from_str::<Config>(&toml_str).expect("Bands in a full configuration");
+        let addresses = config.stakeholder_config.unwrap().emergency_address;
+        assert_eq!(routed(&addresses, 49_999_999), ADDR_A);
+        assert_eq!(routed(&addresses, 50_000_000), ADDR_B);
+    }
 }
@@ -0,0 +1,191 @@
+//! Detach from the terminal to run in the background.
+//!
+//! We double-fork (with a `setsid` in between) so that the daemon is not a session leader and
+//! can't acquire a controlling terminal. The original process doesn't exit right away: it waits
+//! for the daemon to tell it through a pipe whether it started successfully, so that whoever
+//! started us gets a meaningful exit code. It exits with 0 once the daemon is ready to accept
+//! commands, and with 1 (printing the error) otherwise.
+
+use std::{
+    cmp,
+    ffi::CString,
+    fs,
+    io::{self, Read, Write},
+    os::unix::{ffi::OsStrExt, io::FromRawFd},
+    path::{Path, PathBuf},
+    process,
+};
+
+// Sent by the daemon through the pipe, followed by the error message for a failure.
+const READY: u8 = 0;
+const FAILED: u8 = 1;
+
+// Past this, don't try to close the descriptors one by one if we can't list those open.
+const MAX_CLOSED_FD: libc::c_long = 65_536;
+
+/// Held by the daemon to notify the original process of the outcome of the startup.
+#[derive(Debug)]
+pub struct Readiness {
+    pipe: fs::File,
+    pid_file: PathBuf,
+}
+
+impl Readiness {
+    /// We are up and running. Write our PID file and let the original process exit with 0.
+    pub fn ready(mut self) -> io::Result<()> {
+        if let Err(e) = fs::write(&self.pid_file, format!("{}\n", process::id())) {
+            self.failed(&format!("Writing the PID file: '{}'", e));
+            return Err(e);
+        }
+        self.pipe.write_all(&[READY])
+    }
+
+    /// We could not start. Let the original process print this error and exit with 1.
+    pub fn failed(mut self, error: &str) {
+        let mut msg = vec![FAILED];
+        msg.extend_from_slice(error.as_bytes());
+        // If the original process is gone there is no one to tell anyways.
+        let _ = self.pipe.write_all(&msg);
+    }
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+// Wait for the daemon to report on its startup and exit accordingly.
+fn wait_for_daemon(mut pipe: fs::File, log_file: &Path) -> ! {
+    let mut msg = Vec::new();
+    if let Err(e) = pipe.read_to_end(&mut msg) {
+        eprintln!("Error waiting for revaultd to start: '{}'", e);
+        process::exit(1);
+    }
+
+    match msg.split_first() {
+        Some((&READY, _)) => process::exit(0),
+        Some((&FAILED, error)) => {
+            eprintln!(
+                "Error starting revaultd: {}",
+                String::from_utf8_lossy(error)
+            );
+        }
+        _ => {
+            eprintln!(
+                "revaultd exited during startup, see the log file at '{}'",
+                log_file.display()
+            );
+        }
+    }
+    process::exit(1);
+}
+
+// The file descriptors that may be open. The open files limit may be huge (or unlimited), so
+// rather list those actually open where we can.
+fn inherited_fds() -> Vec<libc::c_int> {
+    #[cfg(target_os = "linux")]
+    {
+        // Collect them before closing any, as the directory listing has its own descriptor (that
+        // is closed once we are done with it).
+        if let Ok(entries) = fs::read_dir("/proc/self/fd") {
+            return entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .collect();
+        }
+    }
+
+    let max_fd = match unsafe { libc::sysconf(libc::_SC_OPEN_MAX) } {
+        n if n > 0 => cmp::min(n, MAX_CLOSED_FD) as libc::c_int,
+        _ => 1024,
+    };
+    (0..max_fd).collect()
+}
+
+// Set up the environment of the daemon process.
+fn setup_daemon(data_dir: &Path, log_file: &Path, keep_fd: libc::c_int) -> io::Result<()> {
+    let data_dir = path_to_cstring(data_dir)?;
+    let log_file = path_to_cstring(log_file)?;
+    let dev_null = CString::new("/dev/null").expect("No nul byte");
+
+    unsafe {
+        cvt(libc::chdir(data_dir.as_ptr()))?;
+        libc::umask(0o077);
+
+        // Don't leak the descriptors we inherited from the process that started us.
+        for fd in inherited_fds() {
+            if fd > libc::STDERR_FILENO && fd != keep_fd {
+                libc::close(fd);
+            }
+        }
+
+        // Logs (and panics) are written to stdout and stderr, so redirect them to the log file.
+        let null_fd = cvt(libc::open(dev_null.as_ptr(), libc::O_RDONLY))?;
+        let log_fd = cvt(libc::open(
+            log_file.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND,
+            0o600 as libc::c_uint,
+        ))?;
+        cvt(libc::dup2(null_fd, libc::STDIN_FILENO))?;
+        cvt(libc::dup2(log_fd, libc::STDOUT_FILENO))?;
+        cvt(libc::dup2(log_fd, libc::STDERR_FILENO))?;
+        libc::close(null_fd);
+        libc::close(log_fd);
+    }
+
+    Ok(())
+}
+
+/// Detach from the terminal. Only returns in the daemon process, the original process exits
+/// once the daemon reported on its startup through the returned `Readiness`.
+///
+/// Must be called before any thread is started.
+pub fn daemonize(data_dir: &Path, log_file: &Path, pid_file: PathBuf) -> io::Result<Readiness> {
+    let mut fds = [0 as libc::c_int; 2];
+    unsafe {
+        cvt(libc::pipe(fds.as_mut_ptr()))?;
+    }
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+
+    match unsafe { cvt(libc::fork())? } {
+        0 => {}
+        _ => {
+            unsafe {
+                libc::close(write_fd);
+            }
+            wait_for_daemon(unsafe { fs::File::from_raw_fd(read_fd) }, log_file);
+        }
+    }
+
+    // The first child, start a new session and fork again so we are not its leader.
+    unsafe {
+        libc::close(read_fd);
+        if libc::setsid() < 0 {
+            libc::_exit(1);
+        }
+        match libc::fork() {
+            0 => {}
+            pid if pid > 0 => libc::_exit(0),
+            _ => libc::_exit(1),
+        }
+    }
+
+    let readiness = Readiness {
+        pipe: unsafe { fs::File::from_raw_fd(write_fd) },
+        pid_file,
+    };
+    if let Err(e) = setup_daemon(data_dir, log_file, write_fd) {
+        let msg = format!("Setting up the daemon process: '{}'", e);
+        readiness.failed(&msg);
+        return Err(e);
+    }
+
+    Ok(readiness)
+}
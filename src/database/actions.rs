@@ -2,14 +2,25 @@ use crate::{
     database::{
         bitcointx::{RevaultTx, TransactionType},
         interface::*,
-        schema::{DbTransaction, DbVault, SCHEMA},
+        schema::{
+            audit_entry_hash, AnnouncementStatus, DbDerivedScript, DbEmergencyOutcome,
+            DbIdempotencyKey, DbQueuedSig, DbTransaction, DbVault, DbWallet, DepositOrigin,
+            EmergencyOutcomeKind, ScriptKind, SpendExpiryReason, VaultFlagKind, MIGRATIONS, SCHEMA,
+        },
         DatabaseError, DB_VERSION,
     },
-    revaultd::{BlockchainTip, RevaultD, VaultStatus},
+    derivation::DerivationIndex,
+    revaultd::{BlockchainTip, FeerateEstimates, RevaultD, ScriptVersion, VaultStatus},
+    VERSION,
 };
 use revault_tx::{
-    bitcoin::{secp256k1, util::bip32::ChildNumber, Amount, OutPoint, Txid},
-    miniscript::descriptor::DescriptorTrait,
+    bitcoin::{
+        hashes::{hex::ToHex, sha256, Hash},
+        secp256k1,
+        util::bip32::{ExtendedPubKey, Fingerprint},
+        Address, Amount, BlockHash, OutPoint, Script, Txid,
+    },
+    scripts::{CpfpDescriptor, DepositDescriptor, UnvaultDescriptor},
     transactions::{
         CancelTransaction, EmergencyTransaction, RevaultTransaction, SpendTransaction,
         UnvaultEmergencyTransaction, UnvaultTransaction,
@@ -17,10 +28,10 @@ use revault_tx::{
 };
 
 use std::{
-    convert::TryInto,
-    fs,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+    fmt, fs,
     path::Path,
-    time::{SystemTime, UNIX_EPOCH},
 };
 
 use rusqlite::params;
@@ -68,16 +79,16 @@ fn create_db_file(db_path: &Path) -> Result<(), std::io::Error> {
 // information
 fn create_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
     let db_path = revaultd.db_file();
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|dur| timestamp_to_u32(dur.as_secs()))
+    let timestamp = revaultd
+        .clock
+        .try_unix_timestamp()
+        .map(timestamp_to_u32)
         .map_err(|e| DatabaseError(format!("Computing time since epoch: {}", e.to_string())))?;
     let deposit_descriptor = revaultd.deposit_descriptor.to_string();
     let unvault_descriptor = revaultd.unvault_descriptor.to_string();
     let cpfp_descriptor = revaultd.cpfp_descriptor.to_string();
     let our_man_xpub_str = revaultd.our_man_xpub.as_ref().map(|xpub| xpub.to_string());
     let our_stk_xpub_str = revaultd.our_stk_xpub.as_ref().map(|xpub| xpub.to_string());
-    let raw_unused_index: u32 = revaultd.current_unused_index.into();
 
     // Rusqlite could create it for us, but we want custom permissions
     create_db_file(&db_path)
@@ -102,8 +113,8 @@ fn create_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
         .map_err(|e| DatabaseError(format!("Inserting version: {}", e.to_string())))?;
         tx.execute(
             "INSERT INTO wallets (timestamp, deposit_descriptor, unvault_descriptor,\
-            cpfp_descriptor, our_manager_xpub, our_stakeholder_xpub, deposit_derivation_index) \
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            cpfp_descriptor, our_manager_xpub, our_stakeholder_xpub, deposit_derivation_index, \
+            script_version) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
             params![
                 timestamp,
                 deposit_descriptor,
@@ -111,7 +122,8 @@ fn create_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
                 cpfp_descriptor,
                 our_man_xpub_str,
                 our_stk_xpub_str,
-                raw_unused_index,
+                revaultd.deposit_indexes.current(),
+                revaultd.script_version as u32,
             ],
         )
         .map_err(|e| DatabaseError(format!("Inserting wallet: {}", e.to_string())))?;
@@ -120,20 +132,51 @@ fn create_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
     })
 }
 
+// Upgrade the database to the current version, one version at a time.
+fn migrate_db(db_path: &Path, from_version: u32) -> Result<(), DatabaseError> {
+    for version in from_version..DB_VERSION {
+        log::info!(
+            "Upgrading database from version '{}' to version '{}'",
+            version,
+            version + 1
+        );
+        db_exec(db_path, |tx| {
+            tx.execute_batch(MIGRATIONS[version as usize])
+                .map_err(|e| DatabaseError(format!("Migrating database: {}", e.to_string())))?;
+            tx.execute("UPDATE version SET version = (?1)", params![version + 1])
+                .map_err(|e| DatabaseError(format!("Updating version: {}", e.to_string())))?;
+
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
 // Called on startup to check database integrity
 fn check_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
     let db_path = revaultd.db_file();
-    let wallet = db_wallet(&db_path)?;
 
-    // Check if their database is not from the future.
-    // We'll eventually do migration here if version < VERSION, but be strict until then.
+    // Check if their database is not from the future, and upgrade it if it's from the past.
     let version = db_version(&db_path)?;
-    if version != DB_VERSION {
+    if version > DB_VERSION {
         return Err(DatabaseError(format!(
             "Unexpected database version: got '{}', expected '{}'",
             version, DB_VERSION
         )));
     }
+    if version < DB_VERSION {
+        migrate_db(&db_path, version)?;
+    }
+
+    // Don't refuse to start if the audit log was tampered with, we'd prevent the stakeholders
+    // from revaulting. But make sure it's noticed.
+    if let Some(broken_id) = db_verify_audit_log(&db_path)? {
+        log::error!(
+            "!!!!! The audit log hash chain is broken at entry '{}'. It was tampered with. !!!!!",
+            broken_id
+        );
+    }
 
     // Then that we are on the right network..
     let db_net = db_network(&db_path)?;
@@ -144,6 +187,20 @@ fn check_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
         )));
     }
 
+    // If the keys are being rotated and we were started with the descriptors of the new wallet,
+    // it's time to switch to it.
+    if let Some(rotation) = db_pending_rotation(&db_path)? {
+        let new_wallet = db_wallet_by_id(&db_path, rotation.new_wallet_id)?
+            .expect("Rotations always refer to an existing wallet");
+        if revaultd.deposit_descriptor == new_wallet.deposit_descriptor
+            && revaultd.unvault_descriptor == new_wallet.unvault_descriptor
+            && revaultd.cpfp_descriptor == new_wallet.cpfp_descriptor
+        {
+            complete_wallet_rotation(revaultd, rotation.old_wallet_id, rotation.new_wallet_id)?;
+        }
+    }
+    let wallet = db_wallet(&db_path)?;
+
     // .. And managing the same Scripts!
     if revaultd.deposit_descriptor != wallet.deposit_descriptor {
         return Err(DatabaseError(format!(
@@ -163,6 +220,315 @@ fn check_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
             revaultd.cpfp_descriptor, wallet.cpfp_descriptor
         )));
     }
+    if revaultd.script_version != wallet.script_version {
+        return Err(DatabaseError(format!(
+            "Database script version mismatch: '{}' (config) vs '{}' (database)",
+            revaultd.script_version, wallet.script_version
+        )));
+    }
+
+    Ok(())
+}
+
+// Make the new wallet of a rotation the current one. All the vaults of the old one must have
+// been moved already, as we won't track them anymore.
+fn complete_wallet_rotation(
+    revaultd: &RevaultD,
+    old_wallet_id: u32,
+    new_wallet_id: u32,
+) -> Result<(), DatabaseError> {
+    let db_path = revaultd.db_file();
+    let remaining = db_vaults(&db_path)?
+        .into_iter()
+        .filter(|vault| {
+            vault.wallet_id == old_wallet_id
+                && !matches!(
+                    vault.status,
+                    VaultStatus::Spent
+                        | VaultStatus::Canceled
+                        | VaultStatus::EmergencyVaulted
+                        | VaultStatus::UnvaultEmergencyVaulted
+                )
+        })
+        .count();
+    if remaining > 0 {
+        return Err(DatabaseError(format!(
+            "Can't switch to the new wallet of the rotation: '{}' vault(s) of the current \
+             one were not moved yet",
+            remaining
+        )));
+    }
+
+    let completed_at = timestamp_to_u32(revaultd.clock.unix_timestamp());
+    db_exec(&db_path, |tx| {
+        tx.execute(
+            "UPDATE wallet_rotations SET completed_at = (?1) WHERE new_wallet_id = (?2)",
+            params![completed_at, new_wallet_id],
+        )
+        .map_err(|e| DatabaseError(format!("Completing wallet rotation: {}", e.to_string())))?;
+
+        Ok(())
+    })?;
+    log::info!(
+        "Completed the rotation of the keys, wallet '{}' replaces wallet '{}'",
+        new_wallet_id,
+        old_wallet_id
+    );
+
+    Ok(())
+}
+
+// Derive the deposit and unvault scripts at this derivation index
+fn derive_scripts(revaultd: &RevaultD, index: DerivationIndex) -> [DbDerivedScript; 2] {
+    [
+        DbDerivedScript {
+            script_pubkey: revaultd
+                .derived_deposit_descriptor(index)
+                .inner()
+                .script_pubkey(),
+            derivation_index: index,
+            kind: ScriptKind::Deposit,
+        },
+        DbDerivedScript {
+            script_pubkey: revaultd
+                .derived_unvault_descriptor(index)
+                .inner()
+                .script_pubkey(),
+            derivation_index: index,
+            kind: ScriptKind::Unvault,
+        },
+    ]
+}
+
+// Add derived scripts to our in-memory index
+fn cache_derived_scripts(revaultd: &mut RevaultD, scripts: Vec<DbDerivedScript>) {
+    for script in scripts {
+        let map = match script.kind {
+            ScriptKind::Deposit => &mut revaultd.derivation_index_map,
+            ScriptKind::Unvault => &mut revaultd.unvault_derivation_index_map,
+        };
+        map.insert(script.script_pubkey, script.derivation_index);
+    }
+}
+
+/// Derive the deposit and unvault scripts at these derivation indexes, store them in the
+/// database and add them to our in-memory index.
+pub fn db_store_derived_scripts(
+    revaultd: &mut RevaultD,
+    indexes: &[DerivationIndex],
+) -> Result<(), DatabaseError> {
+    let db_path = revaultd.db_file();
+    let wallet_id = revaultd
+        .wallet_id
+        .expect("Wallet id is set at startup in setup_db()");
+    let scripts: Vec<DbDerivedScript> = indexes
+        .iter()
+        .flat_map(|index| derive_scripts(revaultd, *index).to_vec())
+        .collect();
+
+    db_exec(&db_path, |tx| {
+        for script in scripts.iter() {
+            tx.execute(
+                "INSERT OR IGNORE INTO derived_scripts (wallet_id, script_pubkey, \
+                 derivation_index, kind) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    wallet_id,
+                    script.script_pubkey.as_bytes(),
+                    script.derivation_index,
+                    script.kind as u32
+                ],
+            )
+            .map_err(|e| DatabaseError(format!("Inserting derived script: {}", e.to_string())))?;
+        }
+
+        Ok(())
+    })?;
+    cache_derived_scripts(revaultd, scripts);
+
+    Ok(())
+}
+
+/// Record that we imported the addresses of this kind into the watchonly wallet up to this
+/// derivation index. The ones above the ranges we recorded so far are recorded as a new range,
+/// first needed at `first_needed` (a timestamp).
+pub fn db_update_imported_index(
+    db_path: &Path,
+    wallet_id: u32,
+    kind: ScriptKind,
+    index: DerivationIndex,
+    first_needed: u32,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT INTO imported_addresses (wallet_id, kind, derivation_index) \
+             VALUES (?1, ?2, ?3) \
+             ON CONFLICT (wallet_id, kind) DO UPDATE \
+             SET derivation_index = excluded.derivation_index",
+            params![wallet_id, kind as u32, index],
+        )
+        .map_err(|e| DatabaseError(format!("Updating imported index: {}", e.to_string())))?;
+
+        let recorded_up_to: Option<DerivationIndex> = tx
+            .query_row(
+                "SELECT MAX(last_index) FROM imported_ranges \
+                 WHERE wallet_id = (?1) AND kind = (?2)",
+                params![wallet_id, kind as u32],
+                |row| row.get(0),
+            )
+            .map_err(|e| DatabaseError(format!("Querying imported ranges: {}", e.to_string())))?;
+        let first_index = match recorded_up_to {
+            Some(up_to) if up_to >= index => return Ok(()),
+            Some(up_to) => up_to.saturating_add(1),
+            None => DerivationIndex::ZERO,
+        };
+        tx.execute(
+            "INSERT INTO imported_ranges (wallet_id, kind, first_index, last_index, \
+             first_needed_timestamp) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![wallet_id, kind as u32, first_index, index, first_needed],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting imported range: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// A script we stored for a vault which isn't what its descriptor derives anymore
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivationMismatch {
+    pub deposit_outpoint: OutPoint,
+    pub derivation_index: DerivationIndex,
+    pub kind: ScriptKind,
+    pub stored: Script,
+    pub derived: Script,
+}
+
+impl fmt::Display for DerivationMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "vault at '{}' (derivation index {}): the stored {} script is '{}' but '{}' is \
+             derived now",
+            self.deposit_outpoint,
+            self.derivation_index,
+            match self.kind {
+                ScriptKind::Deposit => "deposit",
+                ScriptKind::Unvault => "unvault",
+            },
+            self.stored.as_bytes().to_hex(),
+            self.derived.as_bytes().to_hex()
+        )
+    }
+}
+
+/// Derive again the deposit and unvault scripts of all our vaults at their derivation index,
+/// from the descriptors of their wallet, and compare them byte for byte to the ones we stored.
+pub fn db_derivation_mismatches(
+    revaultd: &RevaultD,
+) -> Result<Vec<DerivationMismatch>, DatabaseError> {
+    let db_path = revaultd.db_file();
+    let mut wallets: HashMap<u32, (DbWallet, HashMap<(DerivationIndex, ScriptKind), Script>)> =
+        HashMap::new();
+    let mut mismatches = Vec::new();
+
+    for vault in db_vaults(&db_path)? {
+        if !wallets.contains_key(&vault.wallet_id) {
+            let wallet = db_wallet_by_id(&db_path, vault.wallet_id)?
+                .expect("Vaults always refer to an existing wallet");
+            let stored = db_wallet_derived_scripts(&db_path, wallet.id)?
+                .into_iter()
+                .map(|script| ((script.derivation_index, script.kind), script.script_pubkey))
+                .collect();
+            wallets.insert(wallet.id, (wallet, stored));
+        }
+        let (wallet, stored) = &wallets[&vault.wallet_id];
+
+        let index = vault.derivation_index;
+        let derived = [
+            (
+                ScriptKind::Deposit,
+                wallet
+                    .deposit_descriptor
+                    .derive(index.into(), &revaultd.secp_ctx)
+                    .inner()
+                    .script_pubkey(),
+            ),
+            (
+                ScriptKind::Unvault,
+                wallet
+                    .unvault_descriptor
+                    .derive(index.into(), &revaultd.secp_ctx)
+                    .inner()
+                    .script_pubkey(),
+            ),
+        ];
+        for (kind, derived) in derived.iter() {
+            // Databases created before we stored the scripts may miss some, they are derived at
+            // startup.
+            if let Some(stored) = stored.get(&(index, *kind)) {
+                if stored != derived {
+                    mismatches.push(DerivationMismatch {
+                        deposit_outpoint: vault.deposit_outpoint,
+                        derivation_index: index,
+                        kind: *kind,
+                        stored: stored.clone(),
+                        derived: derived.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Record that this version of revaultd ran on this database, unless it already did.
+pub fn db_record_binary_version(
+    db_path: &Path,
+    version: &str,
+    timestamp: u32,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT OR IGNORE INTO binary_versions (version, first_run_at) VALUES (?1, ?2)",
+            params![version, timestamp],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting binary version: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+// Called on startup, after an upgrade or when asked to, to make sure the scripts of our vaults
+// derive as they did when we stored them. A dependency upgrade could change how they derive, and
+// we wouldn't recognize them anymore.
+fn check_derivations(revaultd: &RevaultD) -> Result<(), DatabaseError> {
+    let db_path = revaultd.db_file();
+    let first_run = db_version_first_run(&db_path, VERSION)?.is_none();
+
+    if first_run || revaultd.verify_derivations {
+        log::info!("Verifying that the stored scripts of our vaults derive identically");
+        let mismatches = db_derivation_mismatches(revaultd)?;
+        if !mismatches.is_empty() {
+            return Err(DatabaseError(format!(
+                "{} stored script(s) are not derived identically by revaultd {} anymore:\n{}",
+                mismatches.len(),
+                VERSION,
+                mismatches
+                    .iter()
+                    .map(|mismatch| format!("  - {}", mismatch))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            )));
+        }
+    }
+    // Only once it passed, so that we check again at the next startup otherwise
+    if first_run {
+        db_record_binary_version(
+            &db_path,
+            VERSION,
+            timestamp_to_u32(revaultd.clock.unix_timestamp()),
+        )?;
+    }
 
     Ok(())
 }
@@ -173,26 +539,64 @@ fn state_from_db(revaultd: &mut RevaultD) -> Result<(), DatabaseError> {
     let wallet = db_wallet(&db_path)?;
 
     revaultd.tip = Some(db_tip(&db_path)?);
+    revaultd.wallet_id = Some(wallet.id);
+
+    // We don't know which of the addresses below our next index were handed out before we
+    // restarted. Those in reach of the limit on reserved indexes that weren't paid were either
+    // handed out and not paid yet, or skipped for a deposit above them: they are gaps.
+    let next_index = wallet.deposit_derivation_index;
+    let vault_indexes: HashSet<DerivationIndex> = db_vaults(&db_path)?
+        .into_iter()
+        .filter(|v| v.wallet_id == wallet.id)
+        .map(|v| v.derivation_index)
+        .collect();
+    let unpaid: Vec<DerivationIndex> = next_index
+        .saturating_sub(revaultd.max_reserved_indexes() as u32)
+        .up_to(next_index)
+        .filter(|index| *index < next_index && !vault_indexes.contains(index))
+        .collect();
+    if !unpaid.is_empty() {
+        log::info!(
+            "{} deposit addresses below the next one we hand out ({}) were not paid yet, at \
+             derivation indexes {:?}",
+            unpaid.len(),
+            next_index,
+            unpaid
+        );
+    }
+    revaultd.deposit_indexes.reset(next_index, unpaid);
 
-    revaultd.current_unused_index = wallet.deposit_derivation_index;
     // Of course, it's no good... Miniscript on bitcoind soon :tm:
     // FIXME: in the meantime, reversed gap limit?
-    let raw_index: u32 = revaultd.current_unused_index.into();
-    (0..raw_index + revaultd.gap_limit()).for_each(|i| {
-        // FIXME: this should fail instead of creating a hardened index
-        let index = ChildNumber::from(i);
-        revaultd.derivation_index_map.insert(
-            revaultd
-                .deposit_descriptor
-                .derive(index, &revaultd.secp_ctx)
-                .inner()
-                .address(revaultd.bitcoind_config.network)
-                .expect("deposit_descriptor is a wsh")
-                .script_pubkey(),
-            index,
+    // Deriving the scripts is slow, so we store them once derived.
+    let derived_scripts = db_wallet_derived_scripts(&db_path, wallet.id)?;
+    let (mut deposit_indexes, mut unvault_indexes) = (HashSet::new(), HashSet::new());
+    for script in derived_scripts.iter() {
+        match script.kind {
+            ScriptKind::Deposit => deposit_indexes.insert(script.derivation_index),
+            ScriptKind::Unvault => unvault_indexes.insert(script.derivation_index),
+        };
+    }
+    cache_derived_scripts(revaultd, derived_scripts);
+
+    // Then derive the ones we are missing up to the gap limit. Databases created before we
+    // stored the scripts have none, in which case we also back-fill the scripts of the vaults
+    // we know about as their index may be out of the current window.
+    let last_index = next_index.saturating_add(revaultd.gap_limit() - 1);
+    let missing_indexes: Vec<DerivationIndex> = DerivationIndex::ZERO
+        .up_to(last_index)
+        .chain(vault_indexes.into_iter())
+        .filter(|index| !deposit_indexes.contains(index) || !unvault_indexes.contains(index))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    if !missing_indexes.is_empty() {
+        log::debug!(
+            "Deriving and storing scripts for {} derivation indexes",
+            missing_indexes.len()
         );
-    });
-    revaultd.wallet_id = Some(wallet.id);
+        db_store_derived_scripts(revaultd, &missing_indexes)?;
+    }
 
     Ok(())
 }
@@ -202,6 +606,10 @@ fn state_from_db(revaultd: &mut RevaultD) -> Result<(), DatabaseError> {
 /// to populate the wallets table if the database does not exist and are always replaced here by
 /// the one from the database (compilation from config policy is non-deterministic!)
 pub fn setup_db(revaultd: &mut RevaultD) -> Result<(), DatabaseError> {
+    set_db_synchronous(revaultd.db_synchronous);
+    if revaultd.db_secure_delete {
+        enable_db_secure_delete();
+    }
     let db_path = revaultd.db_file();
     if !db_path.exists() {
         log::info!("No database at {:?}, creating a new one.", db_path);
@@ -209,6 +617,7 @@ pub fn setup_db(revaultd: &mut RevaultD) -> Result<(), DatabaseError> {
     }
 
     check_db(revaultd)?;
+    check_derivations(revaultd)?;
     state_from_db(revaultd)?;
 
     Ok(())
@@ -227,6 +636,42 @@ pub fn db_update_tip_dbtx(
         .map(|_| ())
 }
 
+/// Record that the replay since the `window` block processed this transaction, confirmed in this
+/// block.
+pub fn db_record_replayed_tx(
+    db_path: &Path,
+    window: &BlockchainTip,
+    txid: &Txid,
+    blockhash: &BlockHash,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT OR IGNORE INTO replayed_transactions (window_height, window_hash, txid, \
+             blockhash) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                window.height,
+                window.hash.to_vec(),
+                txid.to_vec(),
+                blockhash.to_vec()
+            ],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting replayed tx: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// Forget about the transactions processed by the replay of the missed blocks, once it completed
+/// or to start a new one.
+pub fn db_clear_replayed_txs(db_path: &Path) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute("DELETE FROM replayed_transactions", params![])
+            .map_err(|e| DatabaseError(format!("Deleting replayed txs: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
 /// Set the current best block hash and height
 pub fn db_update_tip(db_path: &Path, tip: &BlockchainTip) -> Result<(), DatabaseError> {
     db_exec(db_path, |db_tx| db_update_tip_dbtx(db_tx, tip))
@@ -234,9 +679,8 @@ pub fn db_update_tip(db_path: &Path, tip: &BlockchainTip) -> Result<(), Database
 
 pub fn db_update_deposit_index(
     db_path: &Path,
-    new_index: ChildNumber,
+    new_index: DerivationIndex,
 ) -> Result<(), DatabaseError> {
-    let new_index: u32 = new_index.into();
     db_exec(db_path, |tx| {
         tx.execute(
             "UPDATE wallets SET deposit_derivation_index = (?1)",
@@ -248,37 +692,84 @@ pub fn db_update_deposit_index(
     })
 }
 
-/// Insert a new deposit in the database
+/// The outcome of inserting a new deposit in the database
+#[derive(Debug, Clone, PartialEq)]
+pub enum VaultInsertion {
+    Inserted,
+    /// We already track a vault for this deposit outpoint, it was left untouched. It's up to the
+    /// caller to check it matches the deposit it tried to insert.
+    AlreadyExists(DbVault),
+}
+
+/// Insert a new deposit in the database, unless we already track a vault at this outpoint.
 #[allow(clippy::too_many_arguments)]
 pub fn db_insert_new_unconfirmed_vault(
     db_path: &Path,
     wallet_id: u32,
     deposit_outpoint: &OutPoint,
     amount: &Amount,
-    derivation_index: ChildNumber,
-) -> Result<(), DatabaseError> {
+    derivation_index: DerivationIndex,
+) -> Result<VaultInsertion, DatabaseError> {
+    let mut insertion = VaultInsertion::Inserted;
     db_exec(db_path, |tx| {
-        let derivation_index: u32 = derivation_index.into();
-        tx.execute(
-            "INSERT INTO vaults ( \
-                wallet_id, status, blockheight, deposit_txid, deposit_vout, amount, derivation_index, \
-                funded_at, secured_at, delegated_at, moved_at, final_txid \
-            ) \
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, NULL, NULL, NULL, NULL, NULL)",
-            params![
-                wallet_id,
-                VaultStatus::Unconfirmed as u32,
-                0, // FIXME: it should probably be NULL instead, but no big deal
-                deposit_outpoint.txid.to_vec(),
-                deposit_outpoint.vout,
-                amount_to_i64(amount),
-                derivation_index,
-            ],
-        )
-        .map_err(|e| DatabaseError(format!("Inserting vault: {}", e.to_string())))?;
-
+        insertion = db_insert_new_unconfirmed_vault_dbtx(
+            tx,
+            wallet_id,
+            deposit_outpoint,
+            amount,
+            derivation_index,
+        )?;
         Ok(())
-    })
+    })?;
+
+    Ok(insertion)
+}
+
+/// Insert a new deposit from an existing database transaction, unless we already track a vault
+/// at this outpoint.
+pub fn db_insert_new_unconfirmed_vault_dbtx(
+    tx: &rusqlite::Transaction,
+    wallet_id: u32,
+    deposit_outpoint: &OutPoint,
+    amount: &Amount,
+    derivation_index: DerivationIndex,
+) -> Result<VaultInsertion, DatabaseError> {
+    let existing: Option<DbVault> = tx
+        .prepare(
+            "SELECT * FROM vaults \
+             WHERE wallet_id = (?1) AND deposit_txid = (?2) AND deposit_vout = (?3)",
+        )?
+        .query(params![
+            wallet_id,
+            deposit_outpoint.txid.to_vec(),
+            deposit_outpoint.vout
+        ])?
+        .next()?
+        .map(|row| row.try_into())
+        .transpose()?;
+    if let Some(db_vault) = existing {
+        return Ok(VaultInsertion::AlreadyExists(db_vault));
+    }
+
+    tx.execute(
+        "INSERT INTO vaults ( \
+            wallet_id, status, blockheight, deposit_txid, deposit_vout, amount, derivation_index, \
+            funded_at, secured_at, delegated_at, moved_at, final_txid \
+        ) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, NULL, NULL, NULL, NULL, NULL)",
+        params![
+            wallet_id,
+            VaultStatus::Unconfirmed as u32,
+            0, // FIXME: it should probably be NULL instead, but no big deal
+            deposit_outpoint.txid.to_vec(),
+            deposit_outpoint.vout,
+            amount_to_i64(amount),
+            derivation_index,
+        ],
+    )
+    .map_err(|e| DatabaseError(format!("Inserting vault: {}", e.to_string())))?;
+
+    Ok(VaultInsertion::Inserted)
 }
 
 macro_rules! db_store_unsigned_transactions {
@@ -304,6 +795,89 @@ macro_rules! db_store_unsigned_transactions {
     };
 }
 
+// The current status of this vault.
+fn dbtx_vault_status(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+) -> Result<VaultStatus, DatabaseError> {
+    let status: u32 = db_tx
+        .query_row(
+            "SELECT status FROM vaults WHERE id = (?1)",
+            params![vault_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| {
+            DatabaseError(format!(
+                "Getting status of vault '{}': {}",
+                vault_id,
+                e.to_string()
+            ))
+        })?;
+
+    VaultStatus::try_from(status).map_err(|_| {
+        DatabaseError(format!(
+            "Unknown status '{}' for vault '{}'",
+            status, vault_id
+        ))
+    })
+}
+
+// The ids of the vaults with an Unvault transaction of this txid (there is at most one).
+fn dbtx_vault_ids_from_unvault_txid(
+    db_tx: &rusqlite::Transaction,
+    unvault_txid: &Txid,
+) -> Result<Vec<u32>, DatabaseError> {
+    Ok(db_tx
+        .prepare("SELECT vault_id FROM presigned_transactions WHERE txid = (?1)")?
+        .query_map(params![unvault_txid.to_vec()], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<u32>>>()?)
+}
+
+/// Move a vault to this status, if it may go there from its current one (see
+/// [VaultStatus::transition]). This is the only place where the status of a vault gets updated,
+/// the callers update the other fields accordingly if the transition happened.
+///
+/// An invalid transition is refused: the vault is left as is and flagged for an operator to look
+/// into it, as either we have a bug or our view of the block chain got confused.
+/// Returns whether the vault is now in this status.
+fn dbtx_transition_vault(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    to: VaultStatus,
+    now: u64,
+) -> Result<bool, DatabaseError> {
+    let from = dbtx_vault_status(db_tx, vault_id)?;
+    if let Err(e) = from.transition(to) {
+        log_event!(
+            log::Level::Error,
+            "invalid_transition",
+            vault_id = vault_id,
+            from = from,
+            to = to;
+            "!!!!! Refusing to update vault '{}': {} !!!!!",
+            vault_id,
+            e
+        );
+        db_raise_vault_flag_dbtx(
+            db_tx,
+            vault_id,
+            VaultFlagKind::InvalidTransition,
+            &e.to_string(),
+            now,
+        )?;
+        return Ok(false);
+    }
+
+    db_tx
+        .execute(
+            "UPDATE vaults SET status = (?1) WHERE id = (?2)",
+            params![to as u32, vault_id],
+        )
+        .map_err(|e| DatabaseError(format!("Updating vault to '{}': {}", to, e.to_string())))?;
+
+    Ok(true)
+}
+
 /// Mark an unconfirmed deposit as being in 'Funded' state (confirmed), as well as storing the
 /// unsigned "presigned-transactions".
 /// The `emer_tx` and `unemer_tx` may only be passed for stakeholders.
@@ -317,8 +891,37 @@ pub fn db_confirm_deposit(
     cancel_tx: &CancelTransaction,
     emer_tx: Option<&EmergencyTransaction>,
     unemer_tx: Option<&UnvaultEmergencyTransaction>,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |db_tx| {
+        db_confirm_deposit_dbtx(
+            db_tx,
+            outpoint,
+            blockheight,
+            blocktime,
+            unvault_tx,
+            cancel_tx,
+            emer_tx,
+            unemer_tx,
+            now,
+        )
+    })
+}
+
+/// Same as [db_confirm_deposit], from an existing database transaction.
+#[allow(clippy::too_many_arguments)]
+pub fn db_confirm_deposit_dbtx(
+    db_tx: &rusqlite::Transaction,
+    outpoint: &OutPoint,
+    blockheight: u32,
+    blocktime: u32,
+    unvault_tx: &UnvaultTransaction,
+    cancel_tx: &CancelTransaction,
+    emer_tx: Option<&EmergencyTransaction>,
+    unemer_tx: Option<&UnvaultEmergencyTransaction>,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    let vault_id = db_vault_by_deposit(db_path, outpoint)?
+    let vault_id = db_vault_by_deposit_dbtx(db_tx, outpoint)?
         .ok_or_else(|| {
             DatabaseError(format!(
                 "Confirming '{}' but it does not exist in db?",
@@ -327,30 +930,31 @@ pub fn db_confirm_deposit(
         })?
         .id;
 
-    db_exec(db_path, |db_tx| {
-        db_tx
-            .execute(
-                "UPDATE vaults SET status = (?1), blockheight = (?2), funded_at = (?3) WHERE id = (?4)",
-                params![VaultStatus::Funded as u32, blockheight, blocktime, vault_id,],
-            )
-            .map_err(|e| DatabaseError(format!("Updating vault to 'funded': {}", e.to_string())))?;
+    if !dbtx_transition_vault(db_tx, vault_id, VaultStatus::Funded, now)? {
+        return Ok(());
+    }
+    db_tx
+        .execute(
+            "UPDATE vaults SET blockheight = (?1), funded_at = (?2) WHERE id = (?3)",
+            params![blockheight, blocktime, vault_id,],
+        )
+        .map_err(|e| DatabaseError(format!("Updating vault to 'funded': {}", e.to_string())))?;
 
-        match (emer_tx, unemer_tx) {
-            (Some(emer_tx), Some(unemer_tx)) => {
-                db_store_unsigned_transactions!(
-                    db_tx,
-                    vault_id,
-                    [unvault_tx, cancel_tx, emer_tx, unemer_tx]
-                );
-            }
-            (None, None) => {
-                db_store_unsigned_transactions!(db_tx, vault_id, [unvault_tx, cancel_tx]);
-            }
-            _ => unreachable!(),
+    match (emer_tx, unemer_tx) {
+        (Some(emer_tx), Some(unemer_tx)) => {
+            db_store_unsigned_transactions!(
+                db_tx,
+                vault_id,
+                [unvault_tx, cancel_tx, emer_tx, unemer_tx]
+            );
+        }
+        (None, None) => {
+            db_store_unsigned_transactions!(db_tx, vault_id, [unvault_tx, cancel_tx]);
         }
+        _ => unreachable!(),
+    }
 
-        Ok(())
-    })
+    Ok(())
 }
 
 /// Drop all presigned transactions for a vault, therefore dropping all Spend attempts as well and mark
@@ -358,12 +962,16 @@ pub fn db_confirm_deposit(
 pub fn db_unconfirm_deposit_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
+    if !dbtx_transition_vault(db_tx, vault_id, VaultStatus::Unconfirmed, now)? {
+        return Ok(());
+    }
+
     // FIXME: don't delete everything. This is unnecessary and confusing.
 
-    // This is going to cascade and DELETE the spend_inputs.
     db_tx.execute(
-        "DELETE FROM spend_transactions WHERE id = ( \
+        "DELETE FROM spend_cosig_signatures WHERE spend_id = ( \
             SELECT sin.spend_id FROM presigned_transactions as ptx \
             INNER JOIN spend_inputs as sin ON ptx.id = sin.unvault_id \
             WHERE ptx.vault_id = (?1) \
@@ -371,25 +979,80 @@ pub fn db_unconfirm_deposit_dbtx(
         params![vault_id],
     )?;
     db_tx.execute(
-        "DELETE FROM presigned_transactions WHERE vault_id = (?1)",
+        "DELETE FROM spend_announcements WHERE spend_id = ( \
+            SELECT sin.spend_id FROM presigned_transactions as ptx \
+            INNER JOIN spend_inputs as sin ON ptx.id = sin.unvault_id \
+            WHERE ptx.vault_id = (?1) \
+         )",
         params![vault_id],
     )?;
     db_tx.execute(
-        "UPDATE vaults SET status = (?1), blockheight = (?2), \
-         funded_at = NULL, secured_at = NULL, delegated_at = NULL \
-         WHERE id = (?3)",
-        params![VaultStatus::Unconfirmed as u32, 0, vault_id],
+        "DELETE FROM spend_deprecations WHERE spend_id = ( \
+            SELECT sin.spend_id FROM presigned_transactions as ptx \
+            INNER JOIN spend_inputs as sin ON ptx.id = sin.unvault_id \
+            WHERE ptx.vault_id = (?1) \
+         )",
+        params![vault_id],
     )?;
-
+    db_tx.execute(
+        "DELETE FROM spend_expirations WHERE spend_id = ( \
+            SELECT sin.spend_id FROM presigned_transactions as ptx \
+            INNER JOIN spend_inputs as sin ON ptx.id = sin.unvault_id \
+            WHERE ptx.vault_id = (?1) \
+         )",
+        params![vault_id],
+    )?;
+    db_tx.execute(
+        "DELETE FROM vault_migrations WHERE spend_txid IN ( \
+            SELECT stx.txid FROM presigned_transactions as ptx \
+            INNER JOIN spend_inputs as sin ON ptx.id = sin.unvault_id \
+            INNER JOIN spend_transactions as stx ON stx.id = sin.spend_id \
+            WHERE ptx.vault_id = (?1) \
+         )",
+        params![vault_id],
+    )?;
+    // This is going to cascade and DELETE the spend_inputs.
+    db_tx.execute(
+        "DELETE FROM spend_transactions WHERE id = ( \
+            SELECT sin.spend_id FROM presigned_transactions as ptx \
+            INNER JOIN spend_inputs as sin ON ptx.id = sin.unvault_id \
+            WHERE ptx.vault_id = (?1) \
+         )",
+        params![vault_id],
+    )?;
+    db_tx.execute(
+        "DELETE FROM signing_contexts WHERE presigned_id IN ( \
+            SELECT id FROM presigned_transactions WHERE vault_id = (?1) \
+         )",
+        params![vault_id],
+    )?;
+    db_tx.execute(
+        "DELETE FROM final_txids WHERE presigned_id IN ( \
+            SELECT id FROM presigned_transactions WHERE vault_id = (?1) \
+         )",
+        params![vault_id],
+    )?;
+    db_tx.execute(
+        "DELETE FROM presigned_transactions WHERE vault_id = (?1)",
+        params![vault_id],
+    )?;
+    db_tx.execute(
+        "UPDATE vaults SET blockheight = (?1), \
+         funded_at = NULL, secured_at = NULL, delegated_at = NULL \
+         WHERE id = (?2)",
+        params![0, vault_id],
+    )?;
+
     Ok(())
 }
 
-/// Update the vault status and enforce that moved_at is NULL
+/// Update the vault status and enforce that moved_at is NULL. Returns whether it was downgraded.
 fn dbtx_downgrade(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
     status: VaultStatus,
-) -> Result<(), DatabaseError> {
+    now: u64,
+) -> Result<bool, DatabaseError> {
     // Because the status is downgraded, status cannot be one of the statuses
     // of the end of a vault lifecycle.
     assert!(!matches!(
@@ -399,52 +1062,71 @@ fn dbtx_downgrade(
             | VaultStatus::UnvaultEmergencyVaulted
             | VaultStatus::EmergencyVaulted
     ));
+    if !dbtx_transition_vault(db_tx, vault_id, status, now)? {
+        return Ok(false);
+    }
     db_tx.execute(
-        "UPDATE vaults SET status = (?1), moved_at = NULL WHERE id = (?2)",
-        params![status as u32, vault_id],
+        "UPDATE vaults SET moved_at = NULL WHERE id = (?1)",
+        params![vault_id],
     )?;
 
-    Ok(())
+    Ok(true)
 }
 
 /// Downgrade a vault from 'unvaulted' to 'unvaulting'
 pub fn db_unconfirm_unvault_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    dbtx_downgrade(db_tx, vault_id, VaultStatus::Unvaulting)
+    if dbtx_downgrade(db_tx, vault_id, VaultStatus::Unvaulting, now)? {
+        db_tx.execute(
+            "DELETE FROM unvault_confirmations WHERE vault_id = (?1)",
+            params![vault_id],
+        )?;
+    }
+
+    Ok(())
 }
 
 /// Downgrade a vault from 'spent' to 'spending'
 pub fn db_unconfirm_spend_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    dbtx_downgrade(db_tx, vault_id, VaultStatus::Spending)
+    dbtx_downgrade(db_tx, vault_id, VaultStatus::Spending, now)?;
+    Ok(())
 }
 
 /// Downgrade a vault from 'canceled' to 'canceling'
 pub fn db_unconfirm_cancel_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    dbtx_downgrade(db_tx, vault_id, VaultStatus::Canceling)
+    dbtx_downgrade(db_tx, vault_id, VaultStatus::Canceling, now)?;
+    Ok(())
 }
 
 /// Downgrade a vault from 'emergencied' to 'emergencying'
 pub fn db_unconfirm_emer_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    dbtx_downgrade(db_tx, vault_id, VaultStatus::EmergencyVaulting)
+    dbtx_downgrade(db_tx, vault_id, VaultStatus::EmergencyVaulting, now)?;
+    Ok(())
 }
 
 /// Downgrade a vault from 'unvaultemergencied' to 'unvaultemergencying'
 pub fn db_unconfirm_unemer_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    dbtx_downgrade(db_tx, vault_id, VaultStatus::UnvaultEmergencyVaulting)
+    dbtx_downgrade(db_tx, vault_id, VaultStatus::UnvaultEmergencyVaulting, now)?;
+    Ok(())
 }
 
 /// Update vault status from its unvault transaction ID.
@@ -452,6 +1134,7 @@ fn db_status_from_unvault_txid(
     db_path: &Path,
     unvault_txid: &Txid,
     status: VaultStatus,
+    now: u64,
 ) -> Result<(), DatabaseError> {
     // Theses statuses cannot be set without their respective timestamps.
     assert!(!matches!(
@@ -465,12 +1148,9 @@ fn db_status_from_unvault_txid(
             | VaultStatus::UnvaultEmergencyVaulted
     ));
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1) \
-             WHERE vaults.id IN (SELECT vault_id FROM presigned_transactions WHERE txid = (?2))",
-            params![status as u32, unvault_txid.to_vec(),],
-        )
-        .map_err(|e| DatabaseError(format!("Updating vault to '{}': {}", status, e.to_string())))?;
+        for vault_id in dbtx_vault_ids_from_unvault_txid(tx, unvault_txid)? {
+            dbtx_transition_vault(tx, vault_id, status, now)?;
+        }
 
         Ok(())
     })
@@ -481,6 +1161,7 @@ fn db_status_and_final_txid_from_unvault_txid(
     unvault_txid: &Txid,
     status: VaultStatus,
     final_txid: &Txid,
+    now: u64,
 ) -> Result<(), DatabaseError> {
     // Since the final txid is given, status can only be spending/spent or canceling/canceled.
     assert!(matches!(
@@ -488,25 +1169,118 @@ fn db_status_and_final_txid_from_unvault_txid(
         VaultStatus::Canceling | VaultStatus::Canceled | VaultStatus::Spending | VaultStatus::Spent
     ));
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1), final_txid = (?2) \
-             WHERE vaults.id IN (SELECT vault_id FROM presigned_transactions WHERE txid = (?3))",
-            params![status as u32, final_txid.to_vec(), unvault_txid.to_vec(),],
-        )
-        .map_err(|e| DatabaseError(format!("Updating vault to '{}': {}", status, e.to_string())))?;
+        for vault_id in dbtx_vault_ids_from_unvault_txid(tx, unvault_txid)? {
+            if dbtx_transition_vault(tx, vault_id, status, now)? {
+                tx.execute(
+                    "UPDATE vaults SET final_txid = (?1) WHERE id = (?2)",
+                    params![final_txid.to_vec(), vault_id],
+                )
+                .map_err(|e| {
+                    DatabaseError(format!("Updating vault to '{}': {}", status, e.to_string()))
+                })?;
+            }
+        }
 
         Ok(())
     })
 }
 
 /// Mark an active vault as being in 'unvaulting' state from the Unvault txid
-pub fn db_unvault_deposit(db_path: &Path, unvault_txid: &Txid) -> Result<(), DatabaseError> {
-    db_status_from_unvault_txid(db_path, unvault_txid, VaultStatus::Unvaulting)
+pub fn db_unvault_deposit(
+    db_path: &Path,
+    unvault_txid: &Txid,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    db_status_from_unvault_txid(db_path, unvault_txid, VaultStatus::Unvaulting, now)
+}
+
+// Record the height at which the Unvault transaction with this txid confirmed
+fn db_set_unvault_height_from_txid(
+    db_tx: &rusqlite::Transaction,
+    unvault_txid: &Txid,
+    blockheight: u32,
+) -> Result<(), DatabaseError> {
+    db_tx
+        .execute(
+            "INSERT INTO unvault_confirmations (vault_id, blockheight) \
+             SELECT vault_id, (?2) FROM presigned_transactions WHERE txid = (?1) \
+             ON CONFLICT (vault_id) DO UPDATE SET blockheight = excluded.blockheight",
+            params![unvault_txid.to_vec(), blockheight],
+        )
+        .map_err(|e| DatabaseError(format!("Recording Unvault height: {}", e.to_string())))?;
+
+    Ok(())
+}
+
+/// Mark a vault as being in the 'unvaulted' state out of the Unvault txid, and record the
+/// height at which the Unvault confirmed.
+pub fn db_confirm_unvault(
+    db_path: &Path,
+    unvault_txid: &Txid,
+    blockheight: u32,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        db_confirm_unvault_dbtx(tx, unvault_txid, blockheight, now)
+    })
+}
+
+/// Same as [db_confirm_unvault], from an existing database transaction.
+pub fn db_confirm_unvault_dbtx(
+    db_tx: &rusqlite::Transaction,
+    unvault_txid: &Txid,
+    blockheight: u32,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    let mut confirmed = false;
+    for vault_id in dbtx_vault_ids_from_unvault_txid(db_tx, unvault_txid)? {
+        confirmed |= dbtx_transition_vault(db_tx, vault_id, VaultStatus::Unvaulted, now)?;
+    }
+    if !confirmed {
+        return Ok(());
+    }
+    db_set_unvault_height_from_txid(db_tx, unvault_txid, blockheight)
+}
+
+/// Update the height at which the Unvault transaction with this txid confirmed, after a reorg.
+pub fn db_update_unvault_height_dbtx(
+    db_tx: &rusqlite::Transaction,
+    unvault_txid: &Txid,
+    blockheight: u32,
+) -> Result<(), DatabaseError> {
+    db_set_unvault_height_from_txid(db_tx, unvault_txid, blockheight)
+}
+
+/// Mark a vault as 'spendable', once the relative timelock of its Unvault output expired.
+pub fn db_mark_spendable_vault(
+    db_path: &Path,
+    vault_id: u32,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        db_mark_spendable_vault_dbtx(tx, vault_id, now)
+    })
+}
+
+/// Same as [db_mark_spendable_vault], from an existing database transaction.
+pub fn db_mark_spendable_vault_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    dbtx_transition_vault(db_tx, vault_id, VaultStatus::Spendable, now)?;
+
+    Ok(())
 }
 
-/// Mark a vault as being in the 'unvaulted' state, out of the Unvault txid
-pub fn db_confirm_unvault(db_path: &Path, unvault_txid: &Txid) -> Result<(), DatabaseError> {
-    db_status_from_unvault_txid(db_path, unvault_txid, VaultStatus::Unvaulted)
+/// Downgrade a vault from 'spendable' to 'unvaulted'
+pub fn db_unmature_unvault_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    dbtx_downgrade(db_tx, vault_id, VaultStatus::Unvaulted, now)?;
+    Ok(())
 }
 
 /// Mark a vault as being in the 'canceling' state, out of the Unvault txid
@@ -514,12 +1288,14 @@ pub fn db_cancel_unvault(
     db_path: &Path,
     unvault_txid: &Txid,
     cancel_txid: &Txid,
+    now: u64,
 ) -> Result<(), DatabaseError> {
     db_status_and_final_txid_from_unvault_txid(
         db_path,
         unvault_txid,
         VaultStatus::Canceling,
         cancel_txid,
+        now,
     )
 }
 
@@ -528,18 +1304,25 @@ pub fn db_spend_unvault(
     db_path: &Path,
     unvault_txid: &Txid,
     spend_txid: &Txid,
+    now: u64,
 ) -> Result<(), DatabaseError> {
     db_status_and_final_txid_from_unvault_txid(
         db_path,
         unvault_txid,
         VaultStatus::Spending,
         spend_txid,
+        now,
     )
 }
 
 /// Mark a vault as being in the 'unvault_emergency_vaulting' state, out of the Unvault txid
-pub fn db_emer_unvault(db_path: &Path, unvault_txid: &Txid) -> Result<(), DatabaseError> {
-    db_status_from_unvault_txid(db_path, unvault_txid, VaultStatus::UnvaultEmergencyVaulting)
+pub fn db_emer_unvault(db_path: &Path, unvault_txid: &Txid, now: u64) -> Result<(), DatabaseError> {
+    db_status_from_unvault_txid(
+        db_path,
+        unvault_txid,
+        VaultStatus::UnvaultEmergencyVaulting,
+        now,
+    )
 }
 
 /// Update vault status and moved_at timestamp with the given status and blocktime.
@@ -548,6 +1331,7 @@ fn db_mark_vault_as_moved(
     vault_id: u32,
     status: VaultStatus,
     blocktime: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
     // Because vault is moved and last transaction is confirmed, status must match the statuses of
     // the end of the vault lifecycle.
@@ -559,11 +1343,12 @@ fn db_mark_vault_as_moved(
             | VaultStatus::UnvaultEmergencyVaulted
     ));
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1), moved_at = (?2) \
-             WHERE vaults.id = (?3)",
-            params![status as u32, blocktime, vault_id],
-        )?;
+        if dbtx_transition_vault(tx, vault_id, status, now)? {
+            tx.execute(
+                "UPDATE vaults SET moved_at = (?1) WHERE vaults.id = (?2)",
+                params![blocktime, vault_id],
+            )?;
+        }
 
         Ok(())
     })
@@ -574,8 +1359,9 @@ pub fn db_mark_spent_unvault(
     db_path: &Path,
     vault_id: u32,
     blocktime: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    db_mark_vault_as_moved(db_path, vault_id, VaultStatus::Spent, blocktime)
+    db_mark_vault_as_moved(db_path, vault_id, VaultStatus::Spent, blocktime, now)
 }
 
 /// Update vault status to `canceled` and set moved_at with given blocktime.
@@ -583,8 +1369,9 @@ pub fn db_mark_canceled_unvault(
     db_path: &Path,
     vault_id: u32,
     blocktime: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    db_mark_vault_as_moved(db_path, vault_id, VaultStatus::Canceled, blocktime)
+    db_mark_vault_as_moved(db_path, vault_id, VaultStatus::Canceled, blocktime, now)
 }
 
 /// Update vault status to `emergencied` and set moved_at with given blocktime.
@@ -592,22 +1379,24 @@ pub fn db_mark_emergencied_unvault(
     db_path: &Path,
     vault_id: u32,
     blocktime: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
     db_mark_vault_as_moved(
         db_path,
         vault_id,
         VaultStatus::UnvaultEmergencyVaulted,
         blocktime,
+        now,
     )
 }
 
-pub fn db_mark_emergencying_vault(db_path: &Path, vault_id: u32) -> Result<(), DatabaseError> {
+pub fn db_mark_emergencying_vault(
+    db_path: &Path,
+    vault_id: u32,
+    now: u64,
+) -> Result<(), DatabaseError> {
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1) \
-             WHERE vaults.id = (?2)",
-            params![VaultStatus::EmergencyVaulting as u32, vault_id],
-        )?;
+        dbtx_transition_vault(tx, vault_id, VaultStatus::EmergencyVaulting, now)?;
 
         Ok(())
     })
@@ -617,41 +1406,42 @@ pub fn db_mark_emergencied_vault(
     db_path: &Path,
     vault_id: u32,
     blocktime: u32,
+    now: u64,
 ) -> Result<(), DatabaseError> {
-    db_mark_vault_as_moved(db_path, vault_id, VaultStatus::EmergencyVaulted, blocktime)
+    db_mark_vault_as_moved(
+        db_path,
+        vault_id,
+        VaultStatus::EmergencyVaulted,
+        blocktime,
+        now,
+    )
 }
 
 /// Mark that we actually signed this vault's revocation txs, and stored the signatures for it.
-pub fn db_mark_securing_vault(db_path: &Path, vault_id: u32) -> Result<(), DatabaseError> {
+pub fn db_mark_securing_vault(
+    db_path: &Path,
+    vault_id: u32,
+    now: u64,
+) -> Result<(), DatabaseError> {
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1) \
-             WHERE vaults.id = (?2) AND vaults.status = (?3)",
-            params![
-                VaultStatus::Securing as u32,
-                vault_id,
-                VaultStatus::Funded as u32
-            ],
-        )
-        .map_err(|e| DatabaseError(format!("Updating vault to 'securing': {}", e.to_string())))?;
+        if dbtx_vault_status(tx, vault_id)? == VaultStatus::Funded {
+            dbtx_transition_vault(tx, vault_id, VaultStatus::Securing, now)?;
+        }
 
         Ok(())
     })
 }
 
 /// Mark that we actually signed this vault's Unvault tx, and stored the signature for it.
-pub fn db_mark_activating_vault(db_path: &Path, vault_id: u32) -> Result<(), DatabaseError> {
+pub fn db_mark_activating_vault(
+    db_path: &Path,
+    vault_id: u32,
+    now: u64,
+) -> Result<(), DatabaseError> {
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1) \
-             WHERE vaults.id = (?2) AND vaults.status = (?3)",
-            params![
-                VaultStatus::Activating as u32,
-                vault_id,
-                VaultStatus::Secured as u32
-            ],
-        )
-        .map_err(|e| DatabaseError(format!("Updating vault to 'securing': {}", e.to_string())))?;
+        if dbtx_vault_status(tx, vault_id)? == VaultStatus::Secured {
+            dbtx_transition_vault(tx, vault_id, VaultStatus::Activating, now)?;
+        }
 
         Ok(())
     })
@@ -677,7 +1467,7 @@ where
 // Merge the signatures for two transactions into the first one
 //
 // The two transaction MUST be of the same type.
-fn db_txs_merge_sigs(
+pub(crate) fn db_txs_merge_sigs(
     tx_a: &mut DbTransaction,
     tx_b: &DbTransaction,
     secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
@@ -700,14 +1490,75 @@ fn db_txs_merge_sigs(
     }
 }
 
+// Record the fee market at the time the signature set of this presigned transaction completed
+fn dbtx_record_signing_context(
+    db_tx: &rusqlite::Transaction,
+    presigned_id: u32,
+    feerate: u64,
+    estimates: Option<FeerateEstimates>,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    let estimates = estimates.unwrap_or_default();
+    db_tx.execute(
+        "INSERT OR IGNORE INTO signing_contexts (presigned_id, feerate, next_block_estimate, \
+         next_day_estimate, blockheight, signed_at) \
+         VALUES (?1, ?2, ?3, ?4, (SELECT blockheight FROM tip), ?5)",
+        params![
+            presigned_id,
+            feerate as i64,
+            estimates.next_block.map(|f| f as i64),
+            estimates.next_day.map(|f| f as i64),
+            timestamp_to_u32(now),
+        ],
+    )?;
+
+    Ok(())
+}
+
+// Record the txid of this fully signed presigned transaction, as computed by finalizing a copy
+// of it. Only updated if it changed.
+fn dbtx_record_final_txid(
+    db_tx: &rusqlite::Transaction,
+    transaction: &DbTransaction,
+    secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+    now: u64,
+) -> Result<(), DatabaseError> {
+    let final_txid = transaction
+        .psbt
+        .clone()
+        .finalized_tx(secp)
+        .map_err(|e| {
+            DatabaseError(format!(
+                "Finalizing fully signed transaction '{}': {}",
+                transaction.psbt.txid(),
+                e
+            ))
+        })?
+        .txid();
+    db_tx.execute(
+        "INSERT INTO final_txids (presigned_id, txid, computed_at) \
+         VALUES (?1, ?2, ?3) \
+         ON CONFLICT (presigned_id) DO UPDATE \
+         SET txid = excluded.txid, computed_at = excluded.computed_at \
+         WHERE txid != excluded.txid",
+        params![transaction.id, final_txid.to_vec(), timestamp_to_u32(now)],
+    )?;
+
+    Ok(())
+}
+
 /// Update the transactions of a given vault with the signatures of the given transactions.
+/// For those that get fully signed, record the feerate they imply along with the fee estimates
+/// at this time, and their final txid.
 ///
 /// The provided transactions MUST be valid, there signatures aren't checked.
 pub fn db_update_presigned_txs(
     db_path: &Path,
     db_vault: &DbVault,
     transactions: Vec<DbTransaction>,
+    feerate_estimates: Option<FeerateEstimates>,
     secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+    now: u64,
 ) -> Result<(), DatabaseError> {
     db_exec(db_path, move |db_tx| {
         for mut transaction in transactions {
@@ -730,6 +1581,18 @@ pub fn db_update_presigned_txs(
                 "UPDATE presigned_transactions SET psbt = (?1), fullysigned = (?2) WHERE id = (?3)",
                 params![transaction.psbt.ser(), is_fully_signed, transaction.id],
             )?;
+            if is_fully_signed && !db_transaction.is_fully_signed {
+                dbtx_record_signing_context(
+                    db_tx,
+                    transaction.id,
+                    transaction.psbt.feerate(),
+                    feerate_estimates,
+                    now,
+                )?;
+            }
+            if is_fully_signed {
+                dbtx_record_final_txid(db_tx, &transaction, secp, now)?;
+            }
         }
 
         Ok(())
@@ -738,7 +1601,11 @@ pub fn db_update_presigned_txs(
 
 /// Update vault status to active or secured if the vault presigned_transactions are fully signed
 /// and the associated timestamps secured_at and delegated_at in the case they are null.
-pub fn db_update_vault_status(db_path: &Path, db_vault: &DbVault) -> Result<(), DatabaseError> {
+pub fn db_update_vault_status(
+    db_path: &Path,
+    db_vault: &DbVault,
+    now: u64,
+) -> Result<(), DatabaseError> {
     assert!(matches!(
         db_vault.status,
         VaultStatus::Unconfirmed
@@ -749,6 +1616,19 @@ pub fn db_update_vault_status(db_path: &Path, db_vault: &DbVault) -> Result<(),
     ));
 
     db_exec(db_path, |db_tx| {
+        // It may have moved forward since it was read.
+        let status = dbtx_vault_status(db_tx, db_vault.id)?;
+        if !matches!(
+            status,
+            VaultStatus::Unconfirmed
+                | VaultStatus::Funded
+                | VaultStatus::Securing
+                | VaultStatus::Secured
+                | VaultStatus::Activating
+        ) {
+            return Ok(());
+        }
+
         let db_transactions: Vec<DbTransaction> = db_tx
             .prepare("SELECT * FROM presigned_transactions WHERE vault_id = (?1)")?
             .query_map(params![db_vault.id], |row| row.try_into())?
@@ -770,23 +1650,26 @@ pub fn db_update_vault_status(db_path: &Path, db_vault: &DbVault) -> Result<(),
         }
 
         if all_signed {
-            db_tx.execute(
-                "UPDATE vaults \
-                 SET status = (?1), secured_at = ifnull(secured_at, strftime('%s','now')), delegated_at = strftime('%s','now') \
-                 WHERE vaults.id = (?2)",
-                params![VaultStatus::Active as u32, db_vault.id],
-            )?;
+            if dbtx_transition_vault(db_tx, db_vault.id, VaultStatus::Active, now)? {
+                db_tx.execute(
+                    "UPDATE vaults \
+                     SET secured_at = ifnull(secured_at, (?1)), delegated_at = (?1) \
+                     WHERE vaults.id = (?2)",
+                    params![timestamp_to_u32(now), db_vault.id],
+                )?;
+            }
         } else if all_but_unvault_signed
             && matches!(
-                db_vault.status,
+                status,
                 VaultStatus::Unconfirmed | VaultStatus::Funded | VaultStatus::Securing
             )
+            && dbtx_transition_vault(db_tx, db_vault.id, VaultStatus::Secured, now)?
         {
             db_tx.execute(
                 "UPDATE vaults \
-                 SET status = (?1), secured_at = strftime('%s','now') \
+                 SET secured_at = (?1) \
                  WHERE vaults.id = (?2)",
-                params![VaultStatus::Secured as u32, db_vault.id],
+                params![timestamp_to_u32(now), db_vault.id],
             )?;
         }
 
@@ -824,6 +1707,103 @@ pub fn db_insert_spend(
     })
 }
 
+/// Initiate the rotation of the keys from the current wallet to a new one with these
+/// descriptors, returning the id of the new wallet.
+pub fn db_initiate_wallet_rotation(
+    db_path: &Path,
+    old_wallet_id: u32,
+    deposit_descriptor: &DepositDescriptor,
+    unvault_descriptor: &UnvaultDescriptor,
+    cpfp_descriptor: &CpfpDescriptor,
+    script_version: ScriptVersion,
+    our_man_xpub: Option<&ExtendedPubKey>,
+    our_stk_xpub: Option<&ExtendedPubKey>,
+    initiated_at: u64,
+) -> Result<u32, DatabaseError> {
+    let initiated_at = timestamp_to_u32(initiated_at);
+    let mut new_wallet_id = 0;
+
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT INTO wallets (timestamp, deposit_descriptor, unvault_descriptor,\
+            cpfp_descriptor, our_manager_xpub, our_stakeholder_xpub, deposit_derivation_index, \
+            script_version) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                initiated_at,
+                deposit_descriptor.to_string(),
+                unvault_descriptor.to_string(),
+                cpfp_descriptor.to_string(),
+                our_man_xpub.map(|xpub| xpub.to_string()),
+                our_stk_xpub.map(|xpub| xpub.to_string()),
+                DerivationIndex::ZERO,
+                script_version as u32,
+            ],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting wallet: {}", e.to_string())))?;
+        let id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO wallet_rotations (old_wallet_id, new_wallet_id, initiated_at) \
+             VALUES (?1, ?2, ?3)",
+            params![old_wallet_id, id, initiated_at],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting wallet rotation: {}", e.to_string())))?;
+        new_wallet_id = id as u32;
+
+        Ok(())
+    })?;
+
+    Ok(new_wallet_id)
+}
+
+/// Store the Spend transaction moving these vaults to the deposit address of the new wallet of a
+/// rotation at this derivation index, and record their migration.
+pub fn db_insert_migration_spend(
+    db_path: &Path,
+    unvault_txs: &[DbTransaction],
+    spend_tx: &SpendTransaction,
+    new_wallet_id: u32,
+    derivation_index: DerivationIndex,
+    created_at: u64,
+) -> Result<(), DatabaseError> {
+    let spend_txid = spend_tx.txid();
+    let spend_psbt = spend_tx.as_psbt_serialized();
+    let created_at = timestamp_to_u32(created_at);
+
+    db_exec(db_path, |db_tx| {
+        db_tx.execute(
+            "INSERT INTO spend_transactions (psbt, txid, broadcasted, has_priority) VALUES (?1, ?2, NULL, false)",
+            params![spend_psbt, spend_txid.to_vec()],
+        )?;
+        let spend_id = db_tx.last_insert_rowid();
+
+        for unvault_tx in unvault_txs {
+            db_tx.execute(
+                "INSERT INTO spend_inputs (unvault_id, spend_id) VALUES (?1, ?2)",
+                params![unvault_tx.id, spend_id],
+            )?;
+            db_tx.execute(
+                "INSERT INTO vault_migrations (vault_id, spend_txid, derivation_index, created_at) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    unvault_tx.vault_id,
+                    spend_txid.to_vec(),
+                    derivation_index,
+                    created_at
+                ],
+            )?;
+        }
+
+        // Don't hand out this address for a deposit once the new wallet is the current one
+        db_tx.execute(
+            "UPDATE wallets SET deposit_derivation_index = MAX(deposit_derivation_index, (?1)) \
+             WHERE id = (?2)",
+            params![derivation_index.saturating_add(1), new_wallet_id],
+        )?;
+
+        Ok(())
+    })
+}
+
 pub fn db_update_spend(
     db_path: &Path,
     spend_tx: &SpendTransaction,
@@ -843,6 +1823,30 @@ pub fn db_update_spend(
 
 pub fn db_delete_spend(db_path: &Path, spend_txid: &Txid) -> Result<(), DatabaseError> {
     db_exec(db_path, |db_tx| {
+        db_tx.execute(
+            "DELETE FROM vault_migrations WHERE spend_txid = (?1)",
+            params![spend_txid.to_vec()],
+        )?;
+        db_tx.execute(
+            "DELETE FROM spend_cosig_signatures WHERE spend_id = (SELECT id FROM \
+                spend_transactions WHERE txid = (?1))",
+            params![spend_txid.to_vec()],
+        )?;
+        db_tx.execute(
+            "DELETE FROM spend_announcements WHERE spend_id = (SELECT id FROM \
+                spend_transactions WHERE txid = (?1))",
+            params![spend_txid.to_vec()],
+        )?;
+        db_tx.execute(
+            "DELETE FROM spend_deprecations WHERE spend_id = (SELECT id FROM \
+                spend_transactions WHERE txid = (?1))",
+            params![spend_txid.to_vec()],
+        )?;
+        db_tx.execute(
+            "DELETE FROM spend_expirations WHERE spend_id = (SELECT id FROM \
+                spend_transactions WHERE txid = (?1))",
+            params![spend_txid.to_vec()],
+        )?;
         db_tx.execute(
             "DELETE FROM spend_inputs WHERE spend_id = (SELECT id FROM \
                 spend_transactions WHERE txid = (?1))",
@@ -856,10 +1860,181 @@ pub fn db_delete_spend(db_path: &Path, spend_txid: &Txid) -> Result<(), Database
     })
 }
 
-pub fn db_mark_broadcastable_spend(db_path: &Path, spend_txid: &Txid) -> Result<(), DatabaseError> {
+/// Store the signatures a cosigning server sent us for this Spend transaction, replacing
+/// the previous ones it may have sent.
+pub fn db_store_cosig_signatures(
+    db_path: &Path,
+    spend_txid: &Txid,
+    cosig_noise_key: &[u8; 32],
+    signed_tx: &SpendTransaction,
+) -> Result<(), DatabaseError> {
+    let signed_psbt = signed_tx.as_psbt_serialized();
+
     db_exec(db_path, |db_tx| {
         db_tx.execute(
-            "UPDATE spend_transactions SET broadcasted = 0 WHERE txid = (?1)",
+            "INSERT OR REPLACE INTO spend_cosig_signatures (spend_id, cosig_noise_key, psbt) \
+             VALUES ((SELECT id FROM spend_transactions WHERE txid = (?1)), (?2), (?3))",
+            params![spend_txid.to_vec(), cosig_noise_key.to_vec(), signed_psbt],
+        )?;
+        Ok(())
+    })
+}
+
+/// Record that we announced this Spend transaction to the Coordinator with this Noise key,
+/// which acknowledged it. If it had expired, it's in flight again.
+pub fn db_record_spend_announcement(
+    db_path: &Path,
+    spend_txid: &Txid,
+    coordinator_key: &[u8; 32],
+    announced_at: u64,
+) -> Result<(), DatabaseError> {
+    let announced_at = timestamp_to_u32(announced_at);
+    db_exec(db_path, |db_tx| {
+        db_tx.execute(
+            "DELETE FROM spend_expirations WHERE spend_id = (SELECT id FROM \
+                spend_transactions WHERE txid = (?1))",
+            params![spend_txid.to_vec()],
+        )?;
+        db_tx.execute(
+            "INSERT OR REPLACE INTO spend_announcements \
+             (spend_id, coordinator_key, announced_at, status) \
+             VALUES ((SELECT id FROM spend_transactions WHERE txid = (?1)), (?2), (?3), (?4))",
+            params![
+                spend_txid.to_vec(),
+                coordinator_key.to_vec(),
+                announced_at,
+                AnnouncementStatus::Announced as u32
+            ],
+        )?;
+        Ok(())
+    })
+}
+
+/// Record what the Coordinator with this Noise key reported about the announcement of this
+/// Spend transaction. If we had not recorded announcing it, it's recorded without an
+/// announcement time.
+pub fn db_reconcile_spend_announcement(
+    db_path: &Path,
+    spend_txid: &Txid,
+    coordinator_key: &[u8; 32],
+    status: AnnouncementStatus,
+    reconciled_at: u64,
+) -> Result<(), DatabaseError> {
+    let reconciled_at = timestamp_to_u32(reconciled_at);
+    db_exec(db_path, |db_tx| {
+        db_tx.execute(
+            "INSERT INTO spend_announcements \
+             (spend_id, coordinator_key, status, reconciled_at) \
+             VALUES ((SELECT id FROM spend_transactions WHERE txid = (?1)), (?2), (?3), (?4)) \
+             ON CONFLICT (spend_id) DO UPDATE \
+             SET status = excluded.status, reconciled_at = excluded.reconciled_at",
+            params![
+                spend_txid.to_vec(),
+                coordinator_key.to_vec(),
+                status as u32,
+                reconciled_at
+            ],
+        )?;
+        Ok(())
+    })
+}
+
+/// Deprecate the Spend transactions spending this vault, which was canceled by `cancel_txid`
+/// in a block at `blocktime`: they can't confirm anymore. Returns the txids of those that were
+/// not deprecated yet.
+pub fn db_deprecate_vault_spends(
+    db_path: &Path,
+    vault_id: u32,
+    cancel_txid: &Txid,
+    blocktime: u32,
+) -> Result<Vec<Txid>, DatabaseError> {
+    let mut spend_txids = Vec::new();
+    db_exec(db_path, |db_tx| {
+        let spends = db_tx
+            .prepare(
+                "SELECT stx.id, stx.txid FROM spend_transactions as stx \
+                 INNER JOIN spend_inputs as sin ON stx.id = sin.spend_id \
+                 INNER JOIN presigned_transactions as ptx ON ptx.id = sin.unvault_id \
+                 WHERE ptx.vault_id = (?1) \
+                 AND stx.id NOT IN (SELECT spend_id FROM spend_deprecations)",
+            )?
+            .query_map(params![vault_id], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(i64, Vec<u8>)>>>()?;
+
+        for (spend_id, spend_txid) in spends {
+            db_tx.execute(
+                "INSERT INTO spend_deprecations (spend_id, vault_id, cancel_txid, deprecated_at) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![spend_id, vault_id, cancel_txid.to_vec(), blocktime],
+            )?;
+            spend_txids.push(Txid::from_slice(&spend_txid).expect("We store it"));
+        }
+
+        Ok(())
+    })?;
+
+    Ok(spend_txids)
+}
+
+/// Give up on this Spend transaction, none of whose Unvault transactions was broadcast: it's not
+/// waiting to be broadcast anymore, and the Unvault transactions of its vaults are not broadcast
+/// again. Returns false if it had already expired.
+pub fn db_expire_spend(
+    db_path: &Path,
+    spend_txid: &Txid,
+    reason: SpendExpiryReason,
+    expired_at: u64,
+) -> Result<bool, DatabaseError> {
+    let mut expired = false;
+    db_exec(db_path, |db_tx| {
+        let inserted = db_tx.execute(
+            "INSERT OR IGNORE INTO spend_expirations (spend_id, reason, expired_at) \
+             SELECT id, (?2), (?3) FROM spend_transactions WHERE txid = (?1)",
+            params![
+                spend_txid.to_vec(),
+                reason as u32,
+                timestamp_to_u32(expired_at)
+            ],
+        )?;
+        if inserted == 0 {
+            return Ok(());
+        }
+        expired = true;
+
+        db_tx.execute(
+            "UPDATE spend_transactions SET broadcasted = NULL WHERE txid = (?1)",
+            params![spend_txid.to_vec()],
+        )?;
+        let vault_ids = db_tx
+            .prepare(
+                "SELECT ptx.vault_id FROM spend_transactions as stx \
+                 INNER JOIN spend_inputs as sin ON stx.id = sin.spend_id \
+                 INNER JOIN presigned_transactions as ptx ON ptx.id = sin.unvault_id \
+                 WHERE stx.txid = (?1)",
+            )?
+            .query_map(params![spend_txid.to_vec()], |row| row.get::<_, u32>(0))?
+            .collect::<rusqlite::Result<Vec<u32>>>()?;
+        for vault_id in vault_ids {
+            db_clear_vault_flag_dbtx(
+                db_tx,
+                vault_id,
+                VaultFlagKind::UnvaultBroadcastFailed,
+                expired_at,
+            )?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(expired)
+}
+
+pub fn db_mark_broadcastable_spend(db_path: &Path, spend_txid: &Txid) -> Result<(), DatabaseError> {
+    db_exec(db_path, |db_tx| {
+        db_tx.execute(
+            "UPDATE spend_transactions SET broadcasted = 0 WHERE txid = (?1)",
             params![spend_txid.to_vec()],
         )?;
         Ok(())
@@ -894,987 +2069,4124 @@ pub fn db_mark_rebroadcastable_spend(
     Ok(())
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::database::schema::DbSpendTransaction;
-    use crate::utils::test_utils::{dummy_revaultd, test_datadir, UserRole};
-    use revault_tx::{
-        bitcoin::{
-            Network, OutPoint, PrivateKey as BitcoinPrivKey, PublicKey as BitcoinPubKey,
-            SigHashType,
-        },
-        transactions::{CancelTransaction, EmergencyTransaction, UnvaultEmergencyTransaction},
-    };
+/// Record that bitcoind refused `rejected_txid` as it conflicts with a transaction in its mempool.
+/// If we already knew about this conflict, only fill the competing txid if it was unknown.
+pub fn db_insert_mempool_conflict(
+    db_path: &Path,
+    vault_id: u32,
+    rejected_txid: &Txid,
+    competing_txid: Option<&Txid>,
+    detected_at: u64,
+) -> Result<(), DatabaseError> {
+    let detected_at = timestamp_to_u32(detected_at);
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT INTO mempool_conflicts (vault_id, rejected_txid, competing_txid, detected_at) \
+             VALUES (?1, ?2, ?3, ?4) \
+             ON CONFLICT (vault_id, rejected_txid) DO UPDATE \
+             SET competing_txid = COALESCE(competing_txid, excluded.competing_txid)",
+            params![
+                vault_id,
+                rejected_txid.to_vec(),
+                competing_txid.map(|txid| txid.to_vec()),
+                detected_at
+            ],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting mempool conflict: {}", e.to_string())))?;
 
-    use std::{collections, fs, str::FromStr};
+        Ok(())
+    })
+}
 
-    /// Force the status in database for a given vault.
-    fn db_mark_vault_as(
-        db_path: &Path,
-        vault_id: u32,
-        status: VaultStatus,
-    ) -> Result<(), DatabaseError> {
-        db_exec(db_path, |tx| {
-            tx.execute(
-                "UPDATE vaults SET status = (?1) \
-             WHERE vaults.id = (?2)",
-                params![status as u32, vault_id,],
-            )
-            .map_err(|e| {
-                DatabaseError(format!("Updating vault to '{}': {}", status, e.to_string()))
-            })?;
+/// We found the transaction spending the Unvault output of this vault, it's the competing one
+/// for all the conflicts we recorded for a transaction of ours that isn't this one.
+pub fn db_set_conflicts_competing(
+    db_path: &Path,
+    vault_id: u32,
+    spender_txid: &Txid,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "UPDATE mempool_conflicts SET competing_txid = (?2) \
+             WHERE vault_id = (?1) AND competing_txid IS NULL AND rejected_txid != (?2)",
+            params![vault_id, spender_txid.to_vec()],
+        )
+        .map_err(|e| DatabaseError(format!("Updating mempool conflicts: {}", e.to_string())))?;
 
-            Ok(())
-        })
-    }
+        Ok(())
+    })
+}
 
-    fn create_keys(
-        ctx: &secp256k1::Secp256k1<secp256k1::All>,
-        secret_slice: &[u8],
-    ) -> (BitcoinPrivKey, BitcoinPubKey) {
-        let secret_key = secp256k1::SecretKey::from_slice(secret_slice).unwrap();
-        let private_key = BitcoinPrivKey {
-            compressed: true,
-            network: Network::Regtest,
-            key: secret_key,
+/// The race for this vault is settled: `confirmed_txid` made it into a block.
+pub fn db_settle_conflicts(
+    db_path: &Path,
+    vault_id: u32,
+    confirmed_txid: &Txid,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "UPDATE mempool_conflicts SET confirmed_txid = (?2) \
+             WHERE vault_id = (?1) AND confirmed_txid IS NULL",
+            params![vault_id, confirmed_txid.to_vec()],
+        )
+        .map_err(|e| DatabaseError(format!("Settling mempool conflicts: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// Append an entry to the audit log, chaining it to the last one. Returns the id of the new
+/// entry.
+pub fn db_append_audit_entry(
+    db_path: &Path,
+    method: &str,
+    params_digest: &sha256::Hash,
+    result: &str,
+    timestamp: u64,
+    peer_uid: Option<u32>,
+) -> Result<i64, DatabaseError> {
+    let timestamp = timestamp_to_u32(timestamp);
+    let mut entry_id = 0;
+    db_exec(db_path, |tx| {
+        let prev_hash = match tx
+            .prepare("SELECT hash FROM audit_log ORDER BY id DESC LIMIT 1")?
+            .query(params![])?
+            .next()?
+        {
+            Some(row) => sha256::Hash::from_slice(&row.get::<_, Vec<u8>>(0)?)
+                .map_err(|e| DatabaseError(format!("Invalid hash in audit log: {}", e)))?,
+            None => sha256::Hash::from_inner([0; 32]),
         };
-        let public_key = BitcoinPubKey::from_private_key(&ctx, &private_key);
-        (private_key, public_key)
-    }
+        let hash = audit_entry_hash(
+            &prev_hash,
+            method,
+            params_digest,
+            result,
+            timestamp,
+            peer_uid,
+        );
 
-    fn revault_tx_add_sig(
-        tx: &mut impl RevaultTransaction,
-        input_index: usize,
-        sighash_type: SigHashType,
-        secp_ctx: &secp256k1::Secp256k1<secp256k1::All>,
-    ) {
-        let (privkey, pubkey) = create_keys(secp_ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
-        let signature_hash =
-            secp256k1::Message::from_slice(&tx.signature_hash(input_index, sighash_type).unwrap())
-                .unwrap();
-        let signature = secp_ctx.sign(&signature_hash, &privkey.key);
-        tx.add_signature(input_index, pubkey.key, signature, secp_ctx)
-            .unwrap();
-    }
+        tx.execute(
+            "INSERT INTO audit_log (method, params_digest, result, timestamp, peer_uid, \
+             prev_hash, hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                method,
+                params_digest.to_vec(),
+                result,
+                timestamp,
+                peer_uid,
+                prev_hash.to_vec(),
+                hash.to_vec()
+            ],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting audit log entry: {}", e.to_string())))?;
 
-    fn update_presigned_tx<C>(
-        db_path: &std::path::PathBuf,
-        db_vault: &DbVault,
-        mut db_tx: DbTransaction,
-        sigs: &collections::BTreeMap<BitcoinPubKey, Vec<u8>>,
-        secp: &secp256k1::Secp256k1<C>,
-    ) where
-        C: secp256k1::Verification,
-    {
-        for (key, sig) in sigs {
-            let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap();
-            match db_tx.psbt {
-                RevaultTx::Unvault(ref mut tx) => {
-                    tx.add_signature(0, key.key, sig, secp).unwrap();
-                }
-                RevaultTx::Cancel(ref mut tx) => {
-                    tx.add_signature(0, key.key, sig, secp).unwrap();
-                }
-                RevaultTx::Emergency(ref mut tx) => {
-                    tx.add_signature(0, key.key, sig, secp).unwrap();
-                }
-                RevaultTx::UnvaultEmergency(ref mut tx) => {
-                    tx.add_signature(0, key.key, sig, secp).unwrap();
-                }
-            }
-        }
-        db_update_presigned_txs(db_path, db_vault, vec![db_tx], secp).unwrap();
-    }
+        entry_id = tx.last_insert_rowid();
+        Ok(())
+    })?;
 
-    #[test]
-    fn test_db_creation() {
-        let datadir = test_datadir();
-        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+    Ok(entry_id)
+}
 
-        create_db(&mut revaultd).unwrap();
-        // There must be a wallet entry now, and there is only one so its id must
-        // be 0.
-        assert_eq!(db_wallet(&revaultd.db_file()).unwrap().id, 1);
-        // We can't create it twice
-        create_db(&mut revaultd).unwrap_err();
-        // The version is right
-        check_db(&mut revaultd).unwrap();
-        // But it would not open a database created for a different network
-        revaultd.bitcoind_config.network = Network::Testnet;
-        check_db(&mut revaultd).unwrap_err();
-        revaultd.bitcoind_config.network = Network::Bitcoin;
-        // Neither would it accept to open a database from the future!
-        db_exec(&revaultd.db_file(), |tx| {
-            tx.execute("UPDATE version SET version = (?1)", params![DB_VERSION + 1])
-                .unwrap();
-            Ok(())
-        })
-        .unwrap();
-        check_db(&mut revaultd).unwrap_err();
+/// Claim an idempotency key for running `method` with these parameters, after forgetting about
+/// the keys created before `expired_before`. Returns None if we claimed it, or the existing
+/// entry for this key otherwise.
+pub fn db_claim_idempotency_key(
+    db_path: &Path,
+    key: &str,
+    method: &str,
+    params_digest: &sha256::Hash,
+    created_at: u64,
+    expired_before: u64,
+) -> Result<Option<DbIdempotencyKey>, DatabaseError> {
+    let created_at = timestamp_to_u32(created_at);
+    let expired_before = timestamp_to_u32(expired_before);
+    let mut existing: Option<DbIdempotencyKey> = None;
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "DELETE FROM idempotency_keys WHERE created_at < (?1)",
+            params![expired_before],
+        )
+        .map_err(|e| DatabaseError(format!("Pruning idempotency keys: {}", e.to_string())))?;
 
-        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
-    }
+        let claimed = tx
+            .execute(
+                "INSERT OR IGNORE INTO idempotency_keys (key, method, params_digest, created_at) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![key, method, params_digest.to_vec(), created_at],
+            )
+            .map_err(|e| DatabaseError(format!("Claiming idempotency key: {}", e.to_string())))?
+            > 0;
+        if !claimed {
+            existing = tx
+                .prepare("SELECT * FROM idempotency_keys WHERE key = (?1)")?
+                .query(params![key])?
+                .next()?
+                .map(|row| row.try_into())
+                .transpose()?;
+        }
 
-    #[test]
-    fn test_db_fetch_deposits() {
-        let datadir = test_datadir();
-        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
-        let db_path = revaultd.db_file();
+        Ok(())
+    })?;
 
-        setup_db(&mut revaultd).unwrap();
+    Ok(existing)
+}
 
-        let wallet_id = 1;
-        let first_deposit_outpoint = OutPoint::from_str(
-            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
-        )
-        .unwrap();
-        let amount = Amount::from_sat(123456);
-        let derivation_index = ChildNumber::from(3);
-        db_insert_new_unconfirmed_vault(
-            &db_path,
-            wallet_id,
-            &first_deposit_outpoint,
-            &amount,
-            derivation_index,
+/// Store the JSON result of the command run under this idempotency key.
+pub fn db_set_idempotency_result(
+    db_path: &Path,
+    key: &str,
+    result: &str,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "UPDATE idempotency_keys SET result = (?1) WHERE key = (?2)",
+            params![result, key],
         )
-        .unwrap();
+        .map_err(|e| DatabaseError(format!("Storing idempotency key result: {}", e.to_string())))?;
 
-        let wallet_id = 1;
+        Ok(())
+    })
+}
+
+/// Forget about an idempotency key whose command failed, so that it can be retried.
+pub fn db_release_idempotency_key(db_path: &Path, key: &str) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "DELETE FROM idempotency_keys WHERE key = (?1) AND result IS NULL",
+            params![key],
+        )
+        .map_err(|e| DatabaseError(format!("Releasing idempotency key: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// Remove the vault status transitions up to (and including) the one with id `up_to_id`, once
+/// they were processed.
+pub fn db_remove_vault_status_changes(db_path: &Path, up_to_id: i64) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "DELETE FROM vault_status_changes WHERE id <= (?1)",
+            params![up_to_id],
+        )
+        .map_err(|e| DatabaseError(format!("Removing vault status changes: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// Record the result of the check of the revocation transactions of this vault, along with
+/// the first one bitcoind rejected and why, if any.
+pub fn db_record_revocation_check(
+    db_path: &Path,
+    vault_id: u32,
+    checked_at: u64,
+    rejected: Option<(&Txid, &str)>,
+) -> Result<(), DatabaseError> {
+    let checked_at = timestamp_to_u32(checked_at);
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT INTO revocation_checks (vault_id, checked_at, rejected_txid, reject_reason) \
+             VALUES (?1, ?2, ?3, ?4) \
+             ON CONFLICT (vault_id) DO UPDATE \
+             SET checked_at = excluded.checked_at, rejected_txid = excluded.rejected_txid, \
+             reject_reason = excluded.reject_reason",
+            params![
+                vault_id,
+                checked_at,
+                rejected.map(|(txid, _)| txid.to_vec()),
+                rejected.map(|(_, reason)| reason),
+            ],
+        )
+        .map_err(|e| DatabaseError(format!("Recording revocation check: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// Flag this vault as needing attention, unless it already has an active flag of this kind.
+/// Returns whether the flag was newly raised.
+pub fn db_raise_vault_flag(
+    db_path: &Path,
+    vault_id: u32,
+    kind: VaultFlagKind,
+    message: &str,
+    created_at: u64,
+) -> Result<bool, DatabaseError> {
+    let mut raised = false;
+    db_exec(db_path, |tx| {
+        raised = db_raise_vault_flag_dbtx(tx, vault_id, kind, message, created_at)?;
+        Ok(())
+    })?;
+
+    Ok(raised)
+}
+
+/// Same as [db_raise_vault_flag], from an existing database transaction.
+pub fn db_raise_vault_flag_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    kind: VaultFlagKind,
+    message: &str,
+    created_at: u64,
+) -> Result<bool, DatabaseError> {
+    let created_at = timestamp_to_u32(created_at);
+    Ok(db_tx
+        .execute(
+            "INSERT OR IGNORE INTO vault_flags (vault_id, kind, message, created_at) \
+             VALUES (?1, ?2, ?3, ?4)",
+            params![vault_id, kind as u32, message, created_at],
+        )
+        .map_err(|e| DatabaseError(format!("Raising vault flag: {}", e.to_string())))?
+        > 0)
+}
+
+/// Clear the active flag of this kind on this vault, if any. Returns whether there was one.
+pub fn db_clear_vault_flag(
+    db_path: &Path,
+    vault_id: u32,
+    kind: VaultFlagKind,
+    cleared_at: u64,
+) -> Result<bool, DatabaseError> {
+    let mut cleared = false;
+    db_exec(db_path, |tx| {
+        cleared = db_clear_vault_flag_dbtx(tx, vault_id, kind, cleared_at)?;
+        Ok(())
+    })?;
+
+    Ok(cleared)
+}
+
+/// Same as [db_clear_vault_flag], from an existing database transaction.
+pub fn db_clear_vault_flag_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    kind: VaultFlagKind,
+    cleared_at: u64,
+) -> Result<bool, DatabaseError> {
+    let cleared_at = timestamp_to_u32(cleared_at);
+    Ok(db_tx
+        .execute(
+            "UPDATE vault_flags SET cleared_at = (?1) \
+             WHERE vault_id = (?2) AND kind = (?3) AND cleared_at IS NULL",
+            params![cleared_at, vault_id, kind as u32],
+        )
+        .map_err(|e| DatabaseError(format!("Clearing vault flag: {}", e.to_string())))?
+        > 0)
+}
+
+/// Record a run of the emergency procedure along with what became of each vault. The vaults
+/// whose transaction bitcoind rejected are flagged, and the flag is cleared for the ones whose
+/// transaction got broadcast. Returns the id of the run.
+pub fn db_record_emergency_run(
+    db_path: &Path,
+    ran_at: u64,
+    outcomes: &[DbEmergencyOutcome],
+) -> Result<i64, DatabaseError> {
+    let mut run_id = 0;
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT INTO emergency_runs (ran_at) VALUES (?1)",
+            params![timestamp_to_u32(ran_at)],
+        )
+        .map_err(|e| DatabaseError(format!("Recording emergency run: {}", e.to_string())))?;
+        run_id = tx.last_insert_rowid();
+
+        for outcome in outcomes {
+            tx.execute(
+                "INSERT INTO emergency_outcomes (run_id, vault_id, outcome, txid, detail) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    run_id,
+                    outcome.vault_id,
+                    outcome.kind as u32,
+                    outcome.txid.map(|txid| txid.to_vec()),
+                    outcome.detail,
+                ],
+            )
+            .map_err(|e| {
+                DatabaseError(format!("Recording emergency outcome: {}", e.to_string()))
+            })?;
+
+            match outcome.kind {
+                EmergencyOutcomeKind::Rejected => {
+                    let message = format!(
+                        "bitcoind rejected its emergency transaction '{}': '{}'",
+                        outcome
+                            .txid
+                            .map(|txid| txid.to_string())
+                            .unwrap_or_default(),
+                        outcome.detail.as_deref().unwrap_or_default()
+                    );
+                    db_raise_vault_flag_dbtx(
+                        tx,
+                        outcome.vault_id,
+                        VaultFlagKind::EmergencyRejected,
+                        &message,
+                        ran_at,
+                    )?;
+                }
+                EmergencyOutcomeKind::Broadcast => {
+                    db_clear_vault_flag_dbtx(
+                        tx,
+                        outcome.vault_id,
+                        VaultFlagKind::EmergencyRejected,
+                        ran_at,
+                    )?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(run_id)
+}
+
+/// Mark the runs of the emergency procedure up to this one as notified.
+pub fn db_mark_emergency_runs_notified(db_path: &Path, up_to_id: i64) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "UPDATE emergency_runs SET notified = 1 WHERE id <= (?1)",
+            params![up_to_id],
+        )
+        .map_err(|e| {
+            DatabaseError(format!(
+                "Marking emergency runs notified: {}",
+                e.to_string()
+            ))
+        })?;
+
+        Ok(())
+    })
+}
+
+/// Give up on this 'unconfirmed' vault, as its deposit transaction left the mempool without
+/// being mined. Returns whether it was abandoned, that is it's still 'unconfirmed' and wasn't
+/// already.
+pub fn db_abandon_vault(
+    db_path: &Path,
+    vault_id: u32,
+    abandoned_at: u64,
+) -> Result<bool, DatabaseError> {
+    let abandoned_at = timestamp_to_u32(abandoned_at);
+    let mut abandoned = false;
+    db_exec(db_path, |tx| {
+        abandoned = tx
+            .execute(
+                "INSERT INTO deposit_abandonments (vault_id, abandoned_at) \
+                 SELECT (?1), (?2) WHERE EXISTS ( \
+                    SELECT 1 FROM vaults WHERE id = (?1) AND status = (?3) \
+                 ) AND NOT EXISTS ( \
+                    SELECT 1 FROM deposit_abandonments \
+                    WHERE vault_id = (?1) AND resurrected_at IS NULL \
+                 )",
+                params![vault_id, abandoned_at, VaultStatus::Unconfirmed as u32],
+            )
+            .map_err(|e| DatabaseError(format!("Abandoning vault: {}", e.to_string())))?
+            > 0;
+
+        Ok(())
+    })?;
+
+    Ok(abandoned)
+}
+
+/// Stop giving up on this vault, as its deposit showed up again. Returns whether it was
+/// abandoned.
+pub fn db_resurrect_vault_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    resurrected_at: u64,
+) -> Result<bool, DatabaseError> {
+    let resurrected_at = timestamp_to_u32(resurrected_at);
+    Ok(db_tx
+        .execute(
+            "UPDATE deposit_abandonments SET resurrected_at = (?1) \
+             WHERE vault_id = (?2) AND resurrected_at IS NULL",
+            params![resurrected_at, vault_id],
+        )
+        .map_err(|e| DatabaseError(format!("Resurrecting vault: {}", e.to_string())))?
+        > 0)
+}
+
+/// Keep the signed revocation transactions of a vault whose deposit isn't confirmed (deeply
+/// enough) anymore, until it confirms again. They replace the ones previously kept, if any.
+///
+/// The provided transactions MUST be valid, their signatures aren't checked.
+pub fn db_defer_revocation_txs(
+    db_path: &Path,
+    vault_id: u32,
+    transactions: &[RevaultTx],
+    received_at: u64,
+) -> Result<(), DatabaseError> {
+    let received_at = timestamp_to_u32(received_at);
+    db_exec(db_path, |db_tx| {
+        for tx in transactions {
+            db_tx
+                .execute(
+                    "INSERT OR REPLACE INTO deferred_revocation_txs \
+                     (vault_id, type, psbt, txid, received_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        vault_id,
+                        TransactionType::from(tx) as u32,
+                        tx.ser(),
+                        tx.txid().to_vec(),
+                        received_at
+                    ],
+                )
+                .map_err(|e| {
+                    DatabaseError(format!("Deferring revocation tx: {}", e.to_string()))
+                })?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Store the revocation transactions kept for this vault by [db_defer_revocation_txs] in place
+/// of its presigned transactions, now that they were created again as its deposit confirmed.
+/// The ones which don't match a presigned transaction are dropped. If any was stored, the vault
+/// is marked as 'securing'. Returns how many were.
+pub fn db_apply_deferred_revocation_txs_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    now: u64,
+) -> Result<usize, DatabaseError> {
+    let applied = db_tx
+        .execute(
+            "UPDATE presigned_transactions SET psbt = ( \
+                SELECT d.psbt FROM deferred_revocation_txs as d \
+                WHERE d.vault_id = presigned_transactions.vault_id \
+                AND d.type = presigned_transactions.type \
+                AND d.txid = presigned_transactions.txid \
+             ) WHERE vault_id = (?1) AND EXISTS ( \
+                SELECT 1 FROM deferred_revocation_txs as d \
+                WHERE d.vault_id = presigned_transactions.vault_id \
+                AND d.type = presigned_transactions.type \
+                AND d.txid = presigned_transactions.txid \
+             )",
+            params![vault_id],
+        )
+        .map_err(|e| DatabaseError(format!("Applying revocation txs: {}", e.to_string())))?;
+    db_tx.execute(
+        "DELETE FROM deferred_revocation_txs WHERE vault_id = (?1)",
+        params![vault_id],
+    )?;
+
+    if applied > 0 && dbtx_vault_status(db_tx, vault_id)? == VaultStatus::Funded {
+        dbtx_transition_vault(db_tx, vault_id, VaultStatus::Securing, now)?;
+    }
+
+    Ok(applied)
+}
+
+/// Record that the vault `child_id` was created by the Cancel transaction of `parent_id`.
+pub fn db_insert_vault_successor(
+    db_path: &Path,
+    parent_id: u32,
+    child_id: u32,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        db_insert_vault_successor_dbtx(tx, parent_id, child_id)
+    })
+}
+
+/// Same as [db_insert_vault_successor], from an existing database transaction.
+pub fn db_insert_vault_successor_dbtx(
+    db_tx: &rusqlite::Transaction,
+    parent_id: u32,
+    child_id: u32,
+) -> Result<(), DatabaseError> {
+    db_tx
+        .execute(
+            "INSERT OR IGNORE INTO vault_successors (parent_id, child_id) VALUES (?1, ?2)",
+            params![parent_id, child_id],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting vault successor: {}", e.to_string())))?;
+
+    Ok(())
+}
+
+/// Record how the funds of this vault got to us, along with the vaults consumed by the Spend
+/// transaction whose change output created it if any. The first recorded origin is kept.
+pub fn db_set_vault_origin(
+    db_path: &Path,
+    vault_id: u32,
+    origin: DepositOrigin,
+    change_sources: &[u32],
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        db_set_vault_origin_dbtx(tx, vault_id, origin, change_sources)
+    })
+}
+
+/// Same as [db_set_vault_origin], from an existing database transaction.
+pub fn db_set_vault_origin_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    origin: DepositOrigin,
+    change_sources: &[u32],
+) -> Result<(), DatabaseError> {
+    db_tx
+        .execute(
+            "INSERT OR IGNORE INTO vault_origins (vault_id, origin) VALUES (?1, ?2)",
+            params![vault_id, origin as u32],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting vault origin: {}", e.to_string())))?;
+
+    for parent_id in change_sources {
+        db_tx
+            .execute(
+                "INSERT OR IGNORE INTO vault_change_sources (child_id, parent_id) \
+                 VALUES (?1, ?2)",
+                params![vault_id, parent_id],
+            )
+            .map_err(|e| {
+                DatabaseError(format!("Inserting vault change source: {}", e.to_string()))
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Record the unconfirmed transactions the deposit of this vault depends on: their number,
+/// total virtual size and the length of the longest chain of them. Recording it twice is a
+/// no-op.
+pub fn db_insert_deposit_ancestry_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    ancestors: u32,
+    vsize: u64,
+    depth: u32,
+) -> Result<(), DatabaseError> {
+    db_tx
+        .execute(
+            "INSERT OR IGNORE INTO deposit_ancestries (vault_id, ancestors, vsize, depth) \
+             VALUES (?1, ?2, ?3, ?4)",
+            params![vault_id, ancestors, vsize, depth],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting deposit ancestry: {}", e.to_string())))?;
+
+    Ok(())
+}
+
+/// Record the address the Emergency transactions of this vault pay to. The first one recorded is
+/// kept.
+pub fn db_set_vault_emergency_address_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    address: &Address,
+) -> Result<(), DatabaseError> {
+    db_tx
+        .execute(
+            "INSERT OR IGNORE INTO vault_emergency_addresses (vault_id, address) VALUES (?1, ?2)",
+            params![vault_id, address.to_string()],
+        )
+        .map_err(|e| {
+            DatabaseError(format!(
+                "Inserting vault Emergency address: {}",
+                e.to_string()
+            ))
+        })?;
+
+    Ok(())
+}
+
+/// Record that the deposit of this vault was created by a coinbase transaction mined at this
+/// height. Recording it twice is a no-op.
+pub fn db_insert_deposit_coinbase_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    blockheight: u32,
+) -> Result<(), DatabaseError> {
+    db_tx
+        .execute(
+            "INSERT OR IGNORE INTO deposit_coinbases (vault_id, blockheight) VALUES (?1, ?2)",
+            params![vault_id, blockheight],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting deposit coinbase: {}", e.to_string())))?;
+
+    Ok(())
+}
+
+/// Store a new spend proposal, returning its id.
+pub fn db_insert_spend_proposal(
+    db_path: &Path,
+    outpoints: &[OutPoint],
+    destinations: &BTreeMap<Address, Amount>,
+    feerate: u64,
+    created_at: u64,
+    expires_at: u64,
+) -> Result<u32, DatabaseError> {
+    let (created_at, expires_at) = (timestamp_to_u32(created_at), timestamp_to_u32(expires_at));
+    let mut proposal_id = 0;
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT INTO spend_proposals (feerate, created_at, expires_at) VALUES (?1, ?2, ?3)",
+            params![feerate as i64, created_at, expires_at],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting spend proposal: {}", e.to_string())))?;
+        let id = tx.last_insert_rowid();
+
+        for outpoint in outpoints {
+            tx.execute(
+                "INSERT INTO spend_proposal_inputs (proposal_id, deposit_txid, deposit_vout) \
+                 VALUES (?1, ?2, ?3)",
+                params![id, outpoint.txid.to_vec(), outpoint.vout],
+            )
+            .map_err(|e| {
+                DatabaseError(format!("Inserting spend proposal input: {}", e.to_string()))
+            })?;
+        }
+        for (address, amount) in destinations {
+            tx.execute(
+                "INSERT INTO spend_proposal_outputs (proposal_id, address, amount) \
+                 VALUES (?1, ?2, ?3)",
+                params![id, address.to_string(), amount_to_i64(amount)],
+            )
+            .map_err(|e| {
+                DatabaseError(format!(
+                    "Inserting spend proposal output: {}",
+                    e.to_string()
+                ))
+            })?;
+        }
+
+        proposal_id = id as u32;
+        Ok(())
+    })?;
+
+    Ok(proposal_id)
+}
+
+/// Record the decision of the manager whose xpub has this fingerprint about a spend proposal.
+/// Returns false if they already decided.
+pub fn db_insert_spend_proposal_ack(
+    db_path: &Path,
+    proposal_id: u32,
+    fingerprint: &Fingerprint,
+    approved: bool,
+    signature: &[u8],
+    created_at: u64,
+) -> Result<bool, DatabaseError> {
+    let created_at = timestamp_to_u32(created_at);
+    let mut inserted = false;
+    db_exec(db_path, |tx| {
+        inserted =
+            tx.execute(
+                "INSERT OR IGNORE INTO spend_proposal_acks (proposal_id, fingerprint, approved, \
+                 signature, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    proposal_id,
+                    fingerprint.as_bytes().to_vec(),
+                    approved,
+                    signature,
+                    created_at
+                ],
+            )
+            .map_err(|e| {
+                DatabaseError(format!("Inserting spend proposal ack: {}", e.to_string()))
+            })? > 0;
+
+        Ok(())
+    })?;
+
+    Ok(inserted)
+}
+
+/// Record the external scripts a confirmed Spend transaction paid to. Only the first Spend to
+/// pay to a script is kept.
+pub fn db_insert_spend_destinations(
+    db_path: &Path,
+    script_pubkeys: &[Script],
+    spend_txid: &Txid,
+    blocktime: u32,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        for script_pubkey in script_pubkeys {
+            tx.execute(
+                "INSERT OR IGNORE INTO spend_destinations (script_pubkey, spend_txid, used_at) \
+                 VALUES (?1, ?2, ?3)",
+                params![script_pubkey.as_bytes(), spend_txid.to_vec(), blocktime],
+            )
+            .map_err(|e| {
+                DatabaseError(format!("Inserting spend destination: {}", e.to_string()))
+            })?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Keep a descriptor that was verified to generate the Emergency address.
+pub fn db_insert_emergency_descriptor(
+    db_path: &Path,
+    descriptor: &str,
+    derivation_index: Option<u32>,
+    stored_at: u64,
+) -> Result<(), DatabaseError> {
+    let stored_at = timestamp_to_u32(stored_at);
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT INTO emergency_descriptors (descriptor, derivation_index, stored_at) \
+             VALUES (?1, ?2, ?3)",
+            params![descriptor, derivation_index, stored_at],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting emergency descriptor: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// Record that the Coordinator with this Noise static public key acknowledged having our
+/// signature for these presigned transactions.
+pub fn db_ack_coordinator_sigs(
+    db_path: &Path,
+    coordinator_key: &[u8],
+    txids: &[Txid],
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        for txid in txids {
+            tx.execute(
+                "INSERT OR IGNORE INTO coordinator_sig_acks (coordinator_key, txid) \
+                 VALUES (?1, ?2)",
+                params![coordinator_key, txid.to_vec()],
+            )
+            .map_err(|e| DatabaseError(format!("Inserting coordinator ack: {}", e.to_string())))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Queue these signatures of this presigned transaction to be pushed to the Coordinator. The
+/// ones already queued are left in place.
+pub fn db_queue_coordinator_sigs(
+    db_path: &Path,
+    txid: &Txid,
+    sigs: &BTreeMap<secp256k1::PublicKey, secp256k1::Signature>,
+    queued_at: u64,
+) -> Result<(), DatabaseError> {
+    let queued_at = timestamp_to_u32(queued_at);
+    db_exec(db_path, |tx| {
+        for (pubkey, signature) in sigs {
+            tx.execute(
+                "INSERT OR IGNORE INTO coordinator_outbox (txid, pubkey, signature, queued_at) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    txid.to_vec(),
+                    pubkey.serialize().to_vec(),
+                    signature.serialize_der().to_vec(),
+                    queued_at
+                ],
+            )
+            .map_err(|e| DatabaseError(format!("Queuing coordinator sig: {}", e.to_string())))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// The Coordinator with this Noise key acknowledged this queued signature: remove it from the
+/// outbox and record the acknowledgement.
+pub fn db_dequeue_coordinator_sig(
+    db_path: &Path,
+    queued: &DbQueuedSig,
+    coordinator_key: &[u8],
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "DELETE FROM coordinator_outbox WHERE id = (?1)",
+            params![queued.id],
+        )
+        .map_err(|e| DatabaseError(format!("Dequeuing coordinator sig: {}", e.to_string())))?;
+        tx.execute(
+            "INSERT OR IGNORE INTO coordinator_sig_acks (coordinator_key, txid) VALUES (?1, ?2)",
+            params![coordinator_key, queued.txid.to_vec()],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting coordinator ack: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// Bring the watchdata in line with the vaults' status: watch the vaults that got Active and
+/// revoke the ones that are not anymore (or are gone), each change getting the next sequence
+/// number.
+pub fn db_sync_watchdata(db_path: &Path) -> Result<(), DatabaseError> {
+    db_exec(db_path, |db_tx| {
+        let active: Vec<OutPoint> = db_vaults_dbtx(db_tx)?
+            .into_iter()
+            .filter(|db_vault| db_vault.status == VaultStatus::Active)
+            .map(|db_vault| db_vault.deposit_outpoint)
+            .collect();
+        let watchdata = db_watchdata_dbtx(db_tx, 0)?;
+        let mut sequence = watchdata.last().map(|entry| entry.sequence).unwrap_or(0);
+
+        for entry in watchdata.iter() {
+            if entry.revoked || active.contains(&entry.deposit_outpoint) {
+                continue;
+            }
+            sequence += 1;
+            db_tx.execute(
+                "UPDATE watchdata SET revoked = 1, sequence = (?1) \
+                 WHERE deposit_txid = (?2) AND deposit_vout = (?3)",
+                params![
+                    sequence as i64,
+                    entry.deposit_outpoint.txid.to_vec(),
+                    entry.deposit_outpoint.vout
+                ],
+            )?;
+        }
+
+        for outpoint in active.iter() {
+            let watched = watchdata
+                .iter()
+                .any(|entry| entry.deposit_outpoint == *outpoint && !entry.revoked);
+            if watched {
+                continue;
+            }
+            // It may have been revoked and be Active again, after a reorg.
+            sequence += 1;
+            db_tx.execute(
+                "INSERT OR REPLACE INTO watchdata (deposit_txid, deposit_vout, sequence, revoked) \
+                 VALUES (?1, ?2, ?3, 0)",
+                params![outpoint.txid.to_vec(), outpoint.vout, sequence as i64],
+            )?;
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bitcoind::utils::{presigned_transactions, vault_emergency_address};
+    use crate::clock::test_utils::MockClock;
+    use crate::config::DbSynchronous;
+    use crate::database::{
+        interface::{
+            db_abandoned_vaults, db_audit_log, db_coordinator_unacked_txs,
+            db_deferred_revocation_txids, db_deposit_abandonments, db_derived_scripts,
+            db_final_txids, db_imported_index, db_imported_ranges, db_last_emergency_descriptor,
+            db_last_revocation_check, db_max_deposit_index, db_rescan_timestamp,
+            db_revocation_checks, db_signed_feerates, db_spend_deprecation, db_spend_destination,
+            db_spend_expiration, db_spend_proposal, db_spend_proposal_acks, db_spend_proposals,
+            db_spend_unvaults_broadcast, db_stale_revocations, db_stale_spends, db_vault_conflicts,
+            db_vault_final_txids, db_vault_flags, db_vault_signing_contexts,
+            db_vault_status_changes, db_verify_audit_log, db_watchdata,
+        },
+        schema::{
+            DbEmergencyDescriptor, DbImportedRange, DbSpendAnnouncement, DbSpendDeprecation,
+            DbSpendDestination, DbSpendExpiration, DbSpendProposal, DbSpendTransaction,
+            DbVaultMigration, DbWalletRotation, SCHEMA_V0,
+        },
+    };
+    use crate::setup::deployment_descriptors;
+    use crate::utils::test_utils::{dummy_revaultd, test_datadir, UserRole};
+    use revault_tx::{
+        bitcoin::{
+            BlockHash, Network, OutPoint, PrivateKey as BitcoinPrivKey, PublicKey as BitcoinPubKey,
+            SigHashType,
+        },
+        transactions::{CancelTransaction, EmergencyTransaction, UnvaultEmergencyTransaction},
+    };
+
+    use std::{collections, fs, str::FromStr, sync::Arc, time::Duration};
+
+    /// Force the status in database for a given vault.
+    fn db_mark_vault_as(
+        db_path: &Path,
+        vault_id: u32,
+        status: VaultStatus,
+    ) -> Result<(), DatabaseError> {
+        db_exec(db_path, |tx| {
+            tx.execute(
+                "UPDATE vaults SET status = (?1) \
+             WHERE vaults.id = (?2)",
+                params![status as u32, vault_id,],
+            )
+            .map_err(|e| {
+                DatabaseError(format!("Updating vault to '{}': {}", status, e.to_string()))
+            })?;
+
+            Ok(())
+        })
+    }
+
+    fn create_keys(
+        ctx: &secp256k1::Secp256k1<secp256k1::All>,
+        secret_slice: &[u8],
+    ) -> (BitcoinPrivKey, BitcoinPubKey) {
+        let secret_key = secp256k1::SecretKey::from_slice(secret_slice).unwrap();
+        let private_key = BitcoinPrivKey {
+            compressed: true,
+            network: Network::Regtest,
+            key: secret_key,
+        };
+        let public_key = BitcoinPubKey::from_private_key(&ctx, &private_key);
+        (private_key, public_key)
+    }
+
+    fn revault_tx_add_sig(
+        tx: &mut impl RevaultTransaction,
+        input_index: usize,
+        sighash_type: SigHashType,
+        secp_ctx: &secp256k1::Secp256k1<secp256k1::All>,
+    ) {
+        let (privkey, pubkey) = create_keys(secp_ctx, &[1; secp256k1::constants::SECRET_KEY_SIZE]);
+        let signature_hash =
+            secp256k1::Message::from_slice(&tx.signature_hash(input_index, sighash_type).unwrap())
+                .unwrap();
+        let signature = secp_ctx.sign(&signature_hash, &privkey.key);
+        tx.add_signature(input_index, pubkey.key, signature, secp_ctx)
+            .unwrap();
+    }
+
+    fn update_presigned_tx<C>(
+        db_path: &std::path::PathBuf,
+        db_vault: &DbVault,
+        mut db_tx: DbTransaction,
+        sigs: &collections::BTreeMap<BitcoinPubKey, Vec<u8>>,
+        secp: &secp256k1::Secp256k1<C>,
+    ) where
+        C: secp256k1::Verification,
+    {
+        for (key, sig) in sigs {
+            let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap();
+            match db_tx.psbt {
+                RevaultTx::Unvault(ref mut tx) => {
+                    tx.add_signature(0, key.key, sig, secp).unwrap();
+                }
+                RevaultTx::Cancel(ref mut tx) => {
+                    tx.add_signature(0, key.key, sig, secp).unwrap();
+                }
+                RevaultTx::Emergency(ref mut tx) => {
+                    tx.add_signature(0, key.key, sig, secp).unwrap();
+                }
+                RevaultTx::UnvaultEmergency(ref mut tx) => {
+                    tx.add_signature(0, key.key, sig, secp).unwrap();
+                }
+            }
+        }
+        db_update_presigned_txs(db_path, db_vault, vec![db_tx], None, secp, 1_600_000_000).unwrap();
+    }
+
+    #[test]
+    fn test_db_creation() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+
+        create_db(&mut revaultd).unwrap();
+        // There must be a wallet entry now, and there is only one so its id must
+        // be 0.
+        assert_eq!(db_wallet(&revaultd.db_file()).unwrap().id, 1);
+        // We can't create it twice
+        create_db(&mut revaultd).unwrap_err();
+        // The version is right
+        check_db(&mut revaultd).unwrap();
+        // But it would not open a database created for a different network
+        revaultd.bitcoind_config.network = Network::Testnet;
+        check_db(&mut revaultd).unwrap_err();
+        revaultd.bitcoind_config.network = Network::Bitcoin;
+        // Neither would it accept to open a database from the future!
+        db_exec(&revaultd.db_file(), |tx| {
+            tx.execute("UPDATE version SET version = (?1)", params![DB_VERSION + 1])
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        check_db(&mut revaultd).unwrap_err();
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    // The structure of the tables, indexes and triggers of the database along with the content
+    // of the tables, as SQLite sees it.
+    fn db_dump(db_path: &Path) -> Vec<(String, Vec<Vec<rusqlite::types::Value>>)> {
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        let query = |sql: &str| -> Vec<Vec<rusqlite::types::Value>> {
+            let mut stmt = conn.prepare(sql).unwrap();
+            let n_columns = stmt.column_count();
+            let rows = stmt
+                .query_map(params![], |row| {
+                    (0..n_columns).map(|i| row.get(i)).collect()
+                })
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            rows
+        };
+
+        let mut dump = Vec::new();
+        for entry in query(
+            "SELECT type, name, sql FROM sqlite_master WHERE name NOT LIKE 'sqlite_%' \
+             ORDER BY type, name",
+        ) {
+            let (kind, name) = match (&entry[0], &entry[1]) {
+                (rusqlite::types::Value::Text(kind), rusqlite::types::Value::Text(name)) => {
+                    (kind.clone(), name.clone())
+                }
+                _ => panic!("Unexpected sqlite_master entry: {:?}", entry),
+            };
+            match kind.as_str() {
+                "table" => {
+                    for pragma in &["table_info", "foreign_key_list"] {
+                        dump.push((
+                            format!("{} {}", pragma, name),
+                            query(&format!("PRAGMA {}({})", pragma, name)),
+                        ));
+                    }
+                    // Not by creation order, which differs for the indexes created by migrations
+                    dump.push((
+                        format!("index_list {}", name),
+                        query(&format!(
+                            "SELECT name, \"unique\", origin, partial FROM pragma_index_list('{}') \
+                             ORDER BY name",
+                            name
+                        )),
+                    ));
+                    dump.push((
+                        format!("rows {}", name),
+                        query(&format!("SELECT * FROM {}", name)),
+                    ));
+                }
+                "index" => dump.push((
+                    format!("index_xinfo {}", name),
+                    query(&format!("PRAGMA index_xinfo({})", name)),
+                )),
+                _ => dump.push((format!("{} {}", kind, name), vec![entry])),
+            }
+        }
+
+        dump
+    }
+
+    #[test]
+    fn test_db_migration() {
+        let fresh_datadir = test_datadir();
+        let fresh_revaultd = dummy_revaultd(fresh_datadir.clone(), UserRole::ManagerStakeholder);
+        let fresh_db_path = fresh_revaultd.db_file();
+        let old_datadir = test_datadir();
+        let old_revaultd = dummy_revaultd(old_datadir.clone(), UserRole::ManagerStakeholder);
+        let old_db_path = old_revaultd.db_file();
+
+        // A database at the current version, and one at the first version with the same content
+        create_db(&fresh_revaultd).unwrap();
+        let wallet = db_wallet(&fresh_db_path).unwrap();
+        create_db_file(&old_db_path).unwrap();
+        db_exec(&old_db_path, |tx| {
+            tx.execute_batch(SCHEMA_V0).unwrap();
+            tx.execute("INSERT INTO version (version) VALUES (0)", params![])
+                .unwrap();
+            tx.execute(
+                "INSERT INTO tip (network, blockheight, blockhash) VALUES (?1, ?2, ?3)",
+                params![
+                    old_revaultd.bitcoind_config.network.to_string(),
+                    0,
+                    vec![0u8; 32]
+                ],
+            )
+            .unwrap();
+            tx.execute(
+                "INSERT INTO wallets (timestamp, deposit_descriptor, unvault_descriptor,\
+                cpfp_descriptor, our_manager_xpub, our_stakeholder_xpub, \
+                deposit_derivation_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    wallet.timestamp,
+                    old_revaultd.deposit_descriptor.to_string(),
+                    old_revaultd.unvault_descriptor.to_string(),
+                    old_revaultd.cpfp_descriptor.to_string(),
+                    old_revaultd
+                        .our_man_xpub
+                        .as_ref()
+                        .map(|xpub| xpub.to_string()),
+                    old_revaultd
+                        .our_stk_xpub
+                        .as_ref()
+                        .map(|xpub| xpub.to_string()),
+                    old_revaultd.deposit_indexes.current(),
+                ],
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        // A vault, inserted as it was at the first version
+        for db_path in &[&fresh_db_path, &old_db_path] {
+            db_exec(db_path, |tx| {
+                tx.execute(
+                    "INSERT INTO vaults (wallet_id, status, blockheight, deposit_txid, \
+                     deposit_vout, amount, derivation_index, funded_at, secured_at, \
+                     delegated_at, moved_at, final_txid) \
+                     VALUES (1, 0, 0, ?1, 0, 123456, 3, NULL, NULL, NULL, NULL, NULL)",
+                    params![vec![1u8; 32]],
+                )
+                .unwrap();
+                Ok(())
+            })
+            .unwrap();
+        }
+
+        // Opening it migrates it to the current version, to the same database as a fresh one
+        check_db(&old_revaultd).unwrap();
+        assert_eq!(db_version(&old_db_path).unwrap(), DB_VERSION);
+        assert_eq!(db_dump(&old_db_path), db_dump(&fresh_db_path));
+        // Which can be used as one
+        let vaults = db_vaults(&old_db_path).unwrap();
+        assert_eq!(vaults.len(), 1);
+        assert_eq!(vaults[0].status, VaultStatus::Unconfirmed);
+        assert_eq!(vaults[0].amount, Amount::from_sat(123456));
+        check_db(&old_revaultd).unwrap();
+
+        fs::remove_dir_all(&fresh_datadir).unwrap_or_else(|_| ());
+        fs::remove_dir_all(&old_datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_fetch_deposits() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+
+        setup_db(&mut revaultd).unwrap();
+        assert_eq!(db_max_deposit_index(&db_path).unwrap(), None);
+
+        let wallet_id = 1;
+        let first_deposit_outpoint = OutPoint::from_str(
+            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(123456);
+        let derivation_index = DerivationIndex::new(3).unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            wallet_id,
+            &first_deposit_outpoint,
+            &amount,
+            derivation_index,
+        )
+        .unwrap();
+
+        let wallet_id = 1;
         let second_deposit_outpoint = OutPoint::from_str(
             "e56808d17a866de5a1d0874894c84a759a7cabc8763694966cc6423f4c597a7f:0",
         )
         .unwrap();
-        let amount = Amount::from_sat(456789);
-        let derivation_index = ChildNumber::from(12);
-        db_insert_new_unconfirmed_vault(
+        let amount = Amount::from_sat(456789);
+        let derivation_index = DerivationIndex::new(12).unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            wallet_id,
+            &second_deposit_outpoint,
+            &amount,
+            derivation_index,
+        )
+        .unwrap();
+
+        let wallet_id = 1;
+        let third_deposit_outpoint = OutPoint::from_str(
+            "616efc37747c8cafc2f99692177a5400bad81b671d8d35ffa347d84b246e9a83:0",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(428000);
+        let derivation_index = DerivationIndex::new(15).unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            wallet_id,
+            &third_deposit_outpoint,
+            &amount,
+            derivation_index,
+        )
+        .unwrap();
+
+        // By the way, trying to insert for an inexistant wallet will fail the
+        // db constraint
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            wallet_id + 1,
+            &third_deposit_outpoint,
+            &amount,
+            derivation_index,
+        )
+        .unwrap_err();
+
+        // Inserting a deposit we already track is a no-op, whatever its amount and derivation
+        // index. We are handed back the vault we already have.
+        let existing = db_vault_by_deposit(&db_path, &third_deposit_outpoint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            db_insert_new_unconfirmed_vault(
+                &db_path,
+                wallet_id,
+                &third_deposit_outpoint,
+                &amount,
+                derivation_index,
+            )
+            .unwrap(),
+            VaultInsertion::AlreadyExists(existing)
+        );
+        assert_eq!(
+            db_insert_new_unconfirmed_vault(
+                &db_path,
+                wallet_id,
+                &third_deposit_outpoint,
+                &Amount::from_sat(1),
+                DerivationIndex::new(16).unwrap(),
+            )
+            .unwrap(),
+            VaultInsertion::AlreadyExists(existing)
+        );
+        assert_eq!(
+            db_vault_by_deposit(&db_path, &third_deposit_outpoint)
+                .unwrap()
+                .unwrap(),
+            existing
+        );
+        // The highest index is the one of the deposits we actually track
+        assert_eq!(
+            db_max_deposit_index(&db_path).unwrap(),
+            DerivationIndex::new(15).ok()
+        );
+
+        // And the UNIQUE constraint would anyways prevent a duplicate from being inserted.
+        db_exec(&db_path, |tx| {
+            tx.execute(
+                "INSERT INTO vaults (wallet_id, status, blockheight, deposit_txid, deposit_vout, \
+                 amount, derivation_index) VALUES (?1, ?2, 0, ?3, ?4, 1, 16)",
+                params![
+                    wallet_id,
+                    VaultStatus::Unconfirmed as u32,
+                    third_deposit_outpoint.txid.to_vec(),
+                    third_deposit_outpoint.vout
+                ],
+            )
+            .unwrap_err();
+            Ok(())
+        })
+        .unwrap();
+
+        // Now retrieve the deposits; there must all be there
+        let deposit_outpoints: Vec<OutPoint> = db_deposits(&db_path)
+            .unwrap()
+            .into_iter()
+            .map(|db_vault| db_vault.deposit_outpoint)
+            .collect();
+        assert_eq!(deposit_outpoints.len(), 3);
+        assert!(deposit_outpoints.contains(&first_deposit_outpoint));
+        assert!(deposit_outpoints.contains(&second_deposit_outpoint));
+        assert!(deposit_outpoints.contains(&third_deposit_outpoint));
+
+        // Now if we mark the first as being unvaulted we'll only fetch the two last ones
+        db_exec(&db_path, |tx| {
+            tx.execute(
+                "UPDATE vaults SET status = (?1) \
+                 WHERE deposit_txid = (?2) AND deposit_vout = (?3) ",
+                params![
+                    VaultStatus::Unvaulting as u32,
+                    first_deposit_outpoint.txid.to_vec(),
+                    first_deposit_outpoint.vout
+                ],
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        let deposit_outpoints: Vec<OutPoint> = db_deposits(&db_path)
+            .unwrap()
+            .into_iter()
+            .map(|db_vault| db_vault.deposit_outpoint)
+            .collect();
+        assert_eq!(deposit_outpoints.len(), 2);
+        assert!(!deposit_outpoints.contains(&first_deposit_outpoint));
+        assert!(deposit_outpoints.contains(&second_deposit_outpoint));
+        assert!(deposit_outpoints.contains(&third_deposit_outpoint));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_store_presigned_txs() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        let secp_ctx = secp256k1::Secp256k1::new();
+
+        setup_db(&mut revaultd).unwrap();
+
+        // Let's insert a deposit
+        let wallet_id = 1;
+        let outpoint = OutPoint::from_str(
+            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(123456);
+        let derivation_index = DerivationIndex::new(33334).unwrap();
+        db_insert_new_unconfirmed_vault(&db_path, wallet_id, &outpoint, &amount, derivation_index)
+            .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+
+        // We can store unsigned transactions
+        let fresh_emer_tx = EmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAVqQwvZ+XLjEW+P90WnqdbVWkC1riPNhF8j9Ca4dM0RiAAAAAAD9////AfhgAwAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK4iUAwAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQBAwSBAAAAAQVHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAAiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        let fresh_unemer_tx = UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAZHNg0DZSHTBSpVaGwH2apdYBRu88ZeeB/XmrijJpvH5AAAAAAD9////AdLKAgAAAAAAIgAg8Wcu+wsgQXcO9MAiWSMtqsVSQkptpfTXJ51MFSdhJAoAAAAAAAEBK0ANAwAAAAAAIgAgtSqMFDOQ2FkdNrt/yUTzVjikth3tOm+um6yLFzLTilcBAwSBAAAAAQWrIQJF6Amv78N3ctJ3+oSlIasXN3/N8H/bu2si9Vu3QNBRuKxRh2R2qRS77fZRBsFKSf1uP2HBT3uhL1oRloisa3apFIPfFe62NUR/RApmlyj0VsJJdJ4CiKxsk1KHZ1IhA5scAvk3lvCVQmoWDTHhcd8utuA6Swf2PolVbdB7yVwnIQIXS76HRC/hWucQkpC43HriwIukm1se8QRc9nIlODCN81KvA37BALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAcRWqIPG85zGye1nuRlbwWKkko4g91Vd/508Ff6vKklpAAAAAAD9////AkANAwAAAAAAIgAgsT7u0Lo8o2WEfxS1nXWtQzsdJTMJnnOC5fwg0nYPvpowdQAAAAAAACIAIAx0DegrXfBr4D0XdetrGgAT2Q3AZANYm0rJL8L/Epp/AAAAAAABASuIlAMAAAAAACIAIGaHQ5brMNbT+WCtfE/WPW8gkmMir5NXAKRsQZAs9cT2AQMEAQAAAAEFR1IhAwYSJ4FeXdf/XPw6lFHpeMFeGvh88f+rWN2VtnaW75TNIQOn5Sg6nytLwT5FT9z5KmV/LMN1pZRsqbworUMwRdRN0lKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQN0Nj5YtWlqdUtE4VzrCy9fIUbgVSBiSedOJzYY9A0jLqxRh2R2qRQ2UoYTYXFkzWxHTxQLsYl/NGpeVIisa3apFChMb7eFLoSVfMHD7bU9EO0Qn2wqiKxsk1KHZ1IhA2KobMJZNs2+adObuXpg1Ny2DOg/nFo5bqGJdJZWSgKUIQL/DSNFGVoHc5rlzQ4+tEDFvETWR1/NXbg5axpIIYuAhVKvAtY0smgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhA3Q2Pli1aWp1S0ThXOsLL18hRuBVIGJJ504nNhj0DSMurFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+
+        let blockheight = 700000;
+        let blocktime = 700000;
+        db_confirm_deposit(
+            &db_path,
+            &outpoint,
+            blockheight,
+            blocktime,
+            &fresh_unvault_tx,
+            &fresh_cancel_tx,
+            Some(&fresh_emer_tx),
+            Some(&fresh_unemer_tx),
+            1_600_000_000,
+        )
+        .unwrap();
+
+        // There is no *fully signed* Emergency transaction at this point!
+        let is_fully_signed = |db_tx: Result<Option<DbTransaction>, DatabaseError>| {
+            db_tx.unwrap().unwrap().is_fully_signed
+        };
+        assert!(!is_fully_signed(db_emer_transaction(&db_path, db_vault.id)));
+        assert!(!is_fully_signed(db_unvault_emer_transaction(
+            &db_path,
+            db_vault.id
+        )));
+
+        // Sanity check we can add sigs to them now
+        let stored_cancel_tx = db_cancel_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored_cancel_tx.psbt.unwrap_cancel().psbt().inputs[0]
+                .partial_sigs
+                .len(),
+            0
+        );
+        let mut cancel_tx = fresh_cancel_tx.clone();
+        revault_tx_add_sig(
+            &mut cancel_tx,
+            0,
+            SigHashType::AllPlusAnyoneCanPay,
+            &secp_ctx,
+        );
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_cancel_tx,
+            &cancel_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        let stored_cancel_tx = db_cancel_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored_cancel_tx.psbt.unwrap_cancel().psbt().inputs[0]
+                .partial_sigs
+                .len(),
+            1
+        );
+
+        let stored_emer_tx = db_emer_transaction(&db_path, db_vault.id).unwrap().unwrap();
+        assert_eq!(
+            stored_emer_tx.psbt.unwrap_emer().psbt().inputs[0]
+                .partial_sigs
+                .len(),
+            0
+        );
+        let mut emer_tx = fresh_emer_tx.clone();
+        revault_tx_add_sig(&mut emer_tx, 0, SigHashType::AllPlusAnyoneCanPay, &secp_ctx);
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_emer_tx,
+            &emer_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        let stored_emer_tx = db_emer_transaction(&db_path, db_vault.id).unwrap().unwrap();
+        assert_eq!(
+            stored_emer_tx.psbt.unwrap_emer().psbt().inputs[0]
+                .partial_sigs
+                .len(),
+            1
+        );
+
+        let stored_unemer_tx = db_unvault_emer_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored_unemer_tx.psbt.unwrap_unvault_emer().psbt().inputs[0]
+                .partial_sigs
+                .len(),
+            0
+        );
+        let mut unemer_tx = fresh_unemer_tx.clone();
+        revault_tx_add_sig(
+            &mut unemer_tx,
+            0,
+            SigHashType::AllPlusAnyoneCanPay,
+            &secp_ctx,
+        );
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_unemer_tx,
+            &unemer_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        let stored_unemer_tx = db_unvault_emer_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored_unemer_tx.psbt.unwrap_unvault_emer().psbt().inputs[0]
+                .partial_sigs
+                .len(),
+            1
+        );
+
+        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored_unvault_tx.psbt.unwrap_unvault().psbt().inputs[0]
+                .partial_sigs
+                .len(),
+            0
+        );
+        let mut unvault_tx = fresh_unvault_tx.clone();
+        revault_tx_add_sig(&mut unvault_tx, 0, SigHashType::All, &secp_ctx);
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_unvault_tx,
+            &unvault_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored_unvault_tx.psbt.assert_unvault().psbt().inputs[0]
+                .partial_sigs
+                .len(),
+            1
+        );
+
+        // They can also be queried
+        assert_eq!(
+            emer_tx,
+            db_emer_transaction(&db_path, db_vault.id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .assert_emer()
+        );
+        assert_eq!(
+            cancel_tx,
+            db_cancel_transaction(&db_path, db_vault.id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .assert_cancel()
+        );
+        assert_eq!(
+            unemer_tx,
+            db_unvault_emer_transaction(&db_path, db_vault.id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .assert_unvault_emer()
+        );
+        assert_eq!(
+            unvault_tx,
+            db_unvault_transaction(&db_path, db_vault.id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .assert_unvault()
+        );
+        let sig_mis_map = db_sig_missing(&db_path).unwrap();
+        assert_eq!(sig_mis_map.len(), 1);
+        assert_eq!(
+            sig_mis_map
+                .get(sig_mis_map.keys().next().unwrap())
+                .unwrap()
+                .len(),
+            4
+        );
+
+        // And removed, if there is eg a reorg.
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(&db_tx, db_vault.id, 1_600_000_000).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert!(db_emer_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .is_none());
+        assert!(db_cancel_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .is_none());
+        assert!(db_unvault_emer_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .is_none());
+        assert!(db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .is_none());
+        let db_vault = db_vault_by_deposit(&db_path, &db_vault.deposit_outpoint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Unconfirmed);
+        assert!(db_vault.delegated_at.is_none());
+        assert!(db_vault.secured_at.is_none());
+        assert!(db_vault.funded_at.is_none());
+
+        // And re-added of course
+        db_confirm_deposit(
+            &db_path,
+            &outpoint,
+            blockheight,
+            blocktime,
+            &fresh_unvault_tx,
+            &fresh_cancel_tx,
+            Some(&fresh_emer_tx),
+            Some(&fresh_unemer_tx),
+            1_600_000_000,
+        )
+        .unwrap();
+        // But not twice! (UNIQUE on the psbt field)
+        db_confirm_deposit(
+            &db_path,
+            &outpoint,
+            blockheight,
+            blocktime,
+            &fresh_unvault_tx,
+            &fresh_cancel_tx,
+            Some(&fresh_emer_tx),
+            Some(&fresh_unemer_tx),
+            1_600_000_000,
+        )
+        .unwrap_err();
+
+        // We can mark the Emergency transaction as fully signed
+        db_exec(&db_path, |tx| {
+            tx.execute(
+                "UPDATE presigned_transactions SET fullysigned = 1 WHERE txid = (?1)",
+                params![fresh_emer_tx.txid().to_vec()],
+            )?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(is_fully_signed(db_emer_transaction(&db_path, db_vault.id)));
+        assert!(!is_fully_signed(db_unvault_emer_transaction(
+            &db_path,
+            db_vault.id
+        )));
+        let sig_mis_map = db_sig_missing(&db_path).unwrap();
+        assert_eq!(sig_mis_map.len(), 1);
+        assert_eq!(
+            sig_mis_map
+                .get(sig_mis_map.keys().next().unwrap())
+                .unwrap()
+                .len(),
+            3
+        );
+        // And the UnvaultEmergency one, once the vault is Unvaulting
+        db_unvault_deposit(&db_path, &fresh_unvault_tx.txid(), 1_600_000_000).unwrap();
+        db_exec(&db_path, |tx| {
+            tx.execute(
+                "UPDATE presigned_transactions SET fullysigned = 1 WHERE txid = (?1)",
+                params![fresh_unemer_tx.txid().to_vec()],
+            )?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(is_fully_signed(db_unvault_emer_transaction(
+            &db_path,
+            db_vault.id
+        )));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    // There we trigger a concurrent write access to the database by inserting a deposit and
+    // updating its presigned transaction in two different thread. It should be fine and one of
+    // them just lock thanks to the unlock_notify feature of SQLite https://sqlite.org/unlock_notify.html
+    #[test]
+    fn test_db_concurrent_write() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        let secp_ctx = secp256k1::Secp256k1::new();
+
+        setup_db(&mut revaultd).unwrap();
+
+        // Let's insert a deposit
+        let wallet_id = 1;
+        let outpoint = OutPoint::from_str(
+            "adaa5a4b9fb07c860f8de460727b6bad4b5ab01d2e7f90f6f3f15a0080020168:0",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(123456);
+        let derivation_index = DerivationIndex::new(33334).unwrap();
+        db_insert_new_unconfirmed_vault(&db_path, wallet_id, &outpoint, &amount, derivation_index)
+            .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+
+        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAT7KJ+fkvbKBDobFTsm31LqtMUfhTiR5tWA5XJA9oYgOAAAAAAD9////AkANAwAAAAAAIgAgbMJH4U4sOCdd1R9PVUuEbmS4bkbnNNlJaqxZBqXHwCcwdQAAAAAAACIAIM8vNQyMFHWpzTmNSefLOTf0spivub9JuegPqYdx0rLvAAAAAAABASuIlAMAAAAAACIAIONmt9fso2OE03OxwV4EkzSucRgHSh3ylMy/KcBayrRaAQMEAQAAAAEFR1IhAum/3N5NY9BZnqXIJxEBNzNEhHwCOY4WQ5xdZZ9XN4+dIQNwiQrXHbeULZ18BN3FOfnYK48NrsVzMDAXVEiu7HfvylKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQOxOjPIG6CKHguqGMBsMRvG/RIiZzCbu7GDMDGmmvH6FqxRh2R2qRSrdyIjb58/y1mAP+ccckOFvfAe04isa3apFAexihzQF+l8AqKa+Y/5XVddSavViKxsk1KHZ1IhAwOygpbYC9yckzxzYFmjVTs4cZzaRTJ97nCHwbFZ6PCaIQLBejnrZMZEk984LSigxiITRc96BSWvsT2wJVMCkLKSe1KvAlAFsmgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhA7E6M8gboIoeC6oYwGwxG8b9EiJnMJu7sYMwMaaa8foWrFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+
+        let blockheight = 2300000;
+        let blocktime = 2300000;
+        db_confirm_deposit(
+            &db_path,
+            &outpoint,
+            blockheight,
+            blocktime,
+            &fresh_unvault_tx,
+            &fresh_cancel_tx,
+            None,
+            None,
+            1_600_000_000,
+        )
+        .unwrap();
+
+        let stored_cancel_tx = db_cancel_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        let mut cancel_tx = fresh_cancel_tx.clone();
+        revault_tx_add_sig(
+            &mut cancel_tx,
+            0,
+            SigHashType::AllPlusAnyoneCanPay,
+            &secp_ctx,
+        );
+        let handle = std::thread::spawn({
+            let db_path = db_path.clone();
+            let cancel_tx = cancel_tx.clone();
+            let secp = revaultd.secp_ctx.clone();
+            let stored_cancel_tx_b = stored_cancel_tx.clone();
+            move || {
+                for _ in 0..10 {
+                    update_presigned_tx(
+                        &db_path,
+                        &db_vault,
+                        stored_cancel_tx_b.clone(),
+                        &cancel_tx.psbt().inputs[0].partial_sigs,
+                        &secp,
+                    );
+                }
+            }
+        });
+        for _ in 0..10 {
+            update_presigned_tx(
+                &db_path,
+                &db_vault,
+                stored_cancel_tx.clone(),
+                &cancel_tx.psbt().inputs[0].partial_sigs,
+                &revaultd.secp_ctx,
+            );
+        }
+        handle.join().unwrap();
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_spend_storage() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAfF2iPeJqz13zFlW6eLAM+uDu5IhUqcQxtMWQx7z5Y8lAAAAAAD9////AkANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQwdQAAAAAAACIAIIMbpoIz4DI+aB1p/EJLyqjyDdDeZ7gG8kPhRIDiWaY8AAAAAAABASuIlAMAAAAAACIAIA9CgZ1cg/hn3iy3buDZvU5zUnQ9NzutToR/r42YZyu3AQMEAQAAAAEFR1IhA9P6hV8yf6HkNofzleom06eqkUxZayWHJnOMNlMtqvD3IQJo5Mj6Wf3ktrwEB3IQXFmgApibojplpNykg0hA8XV6SFKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let fullysigned_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAfF2iPeJqz13zFlW6eLAM+uDu5IhUqcQxtMWQx7z5Y8lAAAAAAD9////AkANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQwdQAAAAAAACIAIIMbpoIz4DI+aB1p/EJLyqjyDdDeZ7gG8kPhRIDiWaY8AAAAAAABASuIlAMAAAAAACIAIA9CgZ1cg/hn3iy3buDZvU5zUnQ9NzutToR/r42YZyu3IgICaOTI+ln95La8BAdyEFxZoAKYm6I6ZaTcpINIQPF1ekhHMEQCIGwH+/OfgUAbJwthOxnMAR4zoLf/ispCH50wqin3TERrAiBak0Xw5+dQ3Od68PWZ65UPLQXG070wCX9pfcInGVUiagEiAgPT+oVfMn+h5DaH85XqJtOnqpFMWWslhyZzjDZTLarw90cwRAIgA/69zvbYYHbKpBId51MVBeS0xIMF/DZJJ+9/UytAh/0CIBG6NR6AulGTLlMGP6bYMqMQ9HRKlAVFSvEK8dVQ2FdeAQEDBAEAAAABBUdSIQPT+oVfMn+h5DaH85XqJtOnqpFMWWslhyZzjDZTLarw9yECaOTI+ln95La8BAdyEFxZoAKYm6I6ZaTcpINIQPF1ekhSriIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBqiEDH7uO3i4mHhzemNwtVZNHJIJlonzMuSFIWjx2zRC1fd2sUYdkdqkU7YhsQQ+SqzEEFOlBsds7CjDH+pyIrGt2qRQgyrXvSLg3hdA+BgPyUVDV+MYLfoisbJNSh2dSIQM0zz54678zxZovq2jUerGPFk7dSjbrFcKfNrlnm81g8CECpdtZmV+1gEIUb3YYcKlALkHyHpoPc5EwgsjEPkPAlRVSrwKlAbJoIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQElIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRhyICAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAA==").unwrap();
+
+        setup_db(&mut revaultd).unwrap();
+
+        // Let's insert a deposit
+        let wallet_id = 1;
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(612345);
+        let derivation_index = DerivationIndex::new(349874).unwrap();
+        db_insert_new_unconfirmed_vault(&db_path, wallet_id, &outpoint, &amount, derivation_index)
+            .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+
+        // Have the Unvault tx fully signed
+        db_confirm_deposit(
+            &db_path,
+            &outpoint,
+            9,
+            9,
+            &fresh_unvault_tx,
+            &fresh_cancel_tx,
+            None,
+            None,
+            1_600_000_000,
+        )
+        .unwrap();
+        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_unvault_tx,
+            &fullysigned_unvault_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Active).unwrap();
+
+        // We can store a Spend tx spending a single unvault and query it
+        let spend_tx = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAciTbKS43sH49TJWX6xJ+MxqWfNQhRl+vkttRZ9sLUkHAAAAAAClAQAAAoAyAAAAAAAAIgAggxumgjPgMj5oHWn8QkvKqPIN0N5nuAbyQ+FEgOJZpjygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQBAwQBAAAAAQWqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let spend_tx_inputs = &spend_tx.tx().input;
+        assert_eq!(spend_tx_inputs.len(), 1);
+        let (_, db_unvault) =
+            db_vault_by_unvault_txid(&db_path, &spend_tx_inputs[0].previous_output.txid)
+                .unwrap()
+                .unwrap();
+        db_insert_spend(&db_path, &[db_unvault.clone()], &spend_tx).unwrap();
+        let spend_txid = spend_tx.txid();
+        assert_eq!(
+            db_list_spends(&db_path).unwrap().get(&spend_txid),
+            Some(&(
+                DbSpendTransaction {
+                    id: 1,
+                    psbt: spend_tx.clone(),
+                    broadcasted: None,
+                    has_priority: false,
+                },
+                vec![outpoint]
+            ))
+        );
+
+        // We can update it, eg with a Spend with more sigs
+        let spend_tx = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAciTbKS43sH49TJWX6xJ+MxqWfNQhRl+vkttRZ9sLUkHAAAAAAClAQAAAoAyAAAAAAAAIgAggxumgjPgMj5oHWn8QkvKqPIN0N5nuAbyQ+FEgOJZpjygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQBAwQBAAAAAQWqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        db_update_spend(&db_path, &spend_tx, true).unwrap();
+        let spend_txid = spend_tx.txid();
+        assert_eq!(
+            db_list_spends(&db_path).unwrap().get(&spend_txid),
+            Some(&(
+                DbSpendTransaction {
+                    id: 1,
+                    psbt: spend_tx.clone(),
+                    broadcasted: None,
+                    has_priority: true,
+                },
+                vec![outpoint]
+            ))
+        );
+        // Not in the CPFPable as it's not broadcasted
+        assert!(!db_cpfpable_spends(&db_path).unwrap().contains(&spend_tx));
+
+        // We can record its announcement, and what the Coordinator said about it
+        assert!(db_spend_announcement(&db_path, &spend_txid)
+            .unwrap()
+            .is_none());
+        let coordinator_key = [3; 32];
+        db_record_spend_announcement(&db_path, &spend_txid, &coordinator_key, 1_000).unwrap();
+        assert_eq!(
+            db_spend_announcement(&db_path, &spend_txid).unwrap(),
+            Some(DbSpendAnnouncement {
+                spend_id: 1,
+                coordinator_key: Some(coordinator_key),
+                announced_at: Some(1_000),
+                status: AnnouncementStatus::Announced,
+                reconciled_at: None,
+            })
+        );
+        db_reconcile_spend_announcement(
+            &db_path,
+            &spend_txid,
+            &[4; 32],
+            AnnouncementStatus::Confirmed,
+            2_000,
+        )
+        .unwrap();
+        assert_eq!(
+            db_spend_announcement(&db_path, &spend_txid).unwrap(),
+            Some(DbSpendAnnouncement {
+                spend_id: 1,
+                coordinator_key: Some(coordinator_key),
+                announced_at: Some(1_000),
+                status: AnnouncementStatus::Confirmed,
+                reconciled_at: Some(2_000),
+            })
+        );
+
+        // It's stale once it was announced long enough ago, but only expires if its Unvault
+        // wasn't broadcast.
+        assert!(db_stale_spends(&db_path, 999).unwrap().is_empty());
+        let db_spend = db_stale_spends(&db_path, 1_000).unwrap().pop().unwrap();
+        assert_eq!(db_spend.psbt.txid(), spend_txid);
+        assert!(!db_spend_unvaults_broadcast(&db_path, &db_spend).unwrap());
+        db_mark_broadcastable_spend(&db_path, &spend_txid).unwrap();
+        let db_spend = db_spend_transaction(&db_path, &spend_txid)
+            .unwrap()
+            .unwrap();
+        assert!(db_spend_unvaults_broadcast(&db_path, &db_spend).unwrap());
+        db_raise_vault_flag(
+            &db_path,
+            db_unvault.vault_id,
+            VaultFlagKind::UnvaultBroadcastFailed,
+            "Unvault could not be broadcast",
+            2_000,
+        )
+        .unwrap();
+        assert!(!db_spend_unvaults_broadcast(&db_path, &db_spend).unwrap());
+
+        // Expiring it frees its vault, and it's only expired once
+        assert!(db_expire_spend(&db_path, &spend_txid, SpendExpiryReason::Timeout, 2_500).unwrap());
+        assert_eq!(
+            db_spend_expiration(&db_path, &spend_txid).unwrap(),
+            Some(DbSpendExpiration {
+                spend_id: 1,
+                reason: SpendExpiryReason::Timeout,
+                expired_at: 2_500,
+            })
+        );
+        assert!(
+            !db_expire_spend(&db_path, &spend_txid, SpendExpiryReason::Aborted, 2_600).unwrap()
+        );
+        assert!(db_stale_spends(&db_path, 3_000).unwrap().is_empty());
+        assert!(db_broadcastable_spend_transactions(&db_path)
+            .unwrap()
+            .is_empty());
+        assert!(db_vault_flags(&db_path, db_unvault.vault_id)
+            .unwrap()
+            .iter()
+            .all(|flag| flag.cleared_at == Some(2_500)));
+
+        // Announcing it again puts it back in flight
+        db_record_spend_announcement(&db_path, &spend_txid, &coordinator_key, 2_700).unwrap();
+        assert!(db_spend_expiration(&db_path, &spend_txid)
+            .unwrap()
+            .is_none());
+
+        // Once its vault is canceled, it's deprecated and never broadcast
+        db_mark_broadcastable_spend(&db_path, &spend_txid).unwrap();
+        assert_eq!(
+            db_broadcastable_spend_transactions(&db_path).unwrap().len(),
+            1
+        );
+        assert!(db_spend_deprecation(&db_path, &spend_txid)
+            .unwrap()
+            .is_none());
+        let cancel_txid =
+            Txid::from_str("a4d4bf7e8d3a0b6e5ef4a2cbb0f20ff0d4ee1c2a4f6e82b6a4e3ae3c9c59e7d1")
+                .unwrap();
+        assert_eq!(
+            db_deprecate_vault_spends(&db_path, db_unvault.vault_id, &cancel_txid, 3_000).unwrap(),
+            vec![spend_txid]
+        );
+        assert_eq!(
+            db_spend_deprecation(&db_path, &spend_txid).unwrap(),
+            Some(DbSpendDeprecation {
+                spend_id: 1,
+                vault_id: db_unvault.vault_id,
+                cancel_txid,
+                deprecated_at: 3_000,
+            })
+        );
+        assert!(db_broadcastable_spend_transactions(&db_path)
+            .unwrap()
+            .is_empty());
+        // It's only deprecated once
+        assert!(
+            db_deprecate_vault_spends(&db_path, db_unvault.vault_id, &cancel_txid, 4_000)
+                .unwrap()
+                .is_empty()
+        );
+
+        // And delete it
+        db_delete_spend(&db_path, &spend_tx.txid()).unwrap();
+        assert!(db_list_spends(&db_path).unwrap().get(&spend_txid).is_none());
+        assert!(db_spend_announcement(&db_path, &spend_txid)
+            .unwrap()
+            .is_none());
+        assert!(db_spend_deprecation(&db_path, &spend_txid)
+            .unwrap()
+            .is_none());
+
+        // And this works with multiple unvaults too
+
+        // Re-insert the previous one so we have many references to the first Unvault
+        db_insert_spend(&db_path, &[db_unvault.clone()], &spend_tx).unwrap();
+        db_update_spend(&db_path, &spend_tx, true).unwrap();
+
+        // Same as above with a new vault
+        let cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAAc+BIbsSvYK/BWRNOAjazIlLfjlVzCCtXvoyN5/bydgEAAAAAAD9////AdLKAgAAAAAAIgAgFy2HNuxbT516bQQBY3R04IkEja348wJveLmF73Tj/owAAAAAAAEBK0ANAwAAAAAAIgAgZw+cwq8wJzworIDuy6s8cpOo3uF8fYyL5pECqg0UVagBAwSBAAAAAQWrIQLDtCYN0BlQw/h5zAcF0yXft2G7vAjkRsD9B9uoiyr1x6xRh2R2qRTGFACwvLOTrJHUPKb3ifnio7mt0Yisa3apFOZTIiKdGP+9rilwd09H1kOsfB/PiKxsk1KHZ1IhAtGKwcs21FeGy2qY+fzQ9uvI4X5ThtCqkwHsGtKQx0jYIQP93zm1sGAtxTNxsYQTkoXt26FoyKWNh1sx6hmk1yVzYlKvA8aOALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiEDwco8fBTB0TgIyRuiySVtsjqY8/PNWc6ibssXFBu8J9ghAnfnwLjDsJCtzPdRRymjx6qxGfklMzRjSB7kXPcIClLuUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAe7jtZYQ3avFhc+JxU4paq8e26NIkAB1zHLgv6mKWxgBAAAAAAD9////AkANAwAAAAAAIgAgdS3fC7QX+PKWZBful8J229uixPOW012CYpKMH7rU8T4wdQAAAAAAACIAIAGCzzZ7K80GkoO2mUdCVIFx7Tum52UXob8ascs1uZucAAAAAAABASuIlAMAAAAAACIAIFvpTQQruW8AB+k+csGMaThNLBAzppkxo+k4Hb2SZ4hKAQMEAQAAAAEFR1IhAvkWJfB/ssW9YaE7llH/y/1FBJ/LK+ybOJiT8j+O4cnhIQIS4abTQKWATfsTrVsEPfkCUHvxY4M0F+ZDz502NXMy1FKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQLbrZUUxHTNBLySX7XjBBa5auxTfjuUSHxE3vJ1JXfdlaxRh2R2qRS8iuykW8lDZdRWXgK4UY0gLAkjX4isa3apFJ+lQ0Ybj4Eig7391TEtxikgP4DDiKxsk1KHZ1IhAqTEmYMxdjLU50wj/Hw9X2Pf7WRDSCnO4P4qpJ5h+PNOIQO2p8sldVsHhhEimy+ZW0E1L3vX5d9mqQ0d01XVdx3DWVKvAocbsmgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAtutlRTEdM0EvJJfteMEFrlq7FN+O5RIfETe8nUld92VrFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let fullysigned_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAe7jtZYQ3avFhc+JxU4paq8e26NIkAB1zHLgv6mKWxgBAAAAAAD9////AkANAwAAAAAAIgAgdS3fC7QX+PKWZBful8J229uixPOW012CYpKMH7rU8T4wdQAAAAAAACIAIAGCzzZ7K80GkoO2mUdCVIFx7Tum52UXob8ascs1uZucAAAAAAABASuIlAMAAAAAACIAIFvpTQQruW8AB+k+csGMaThNLBAzppkxo+k4Hb2SZ4hKIgICEuGm00ClgE37E61bBD35AlB78WODNBfmQ8+dNjVzMtRHMEQCID2my9yVWxgLSDKDBL5PmF9FVZC6b8mLu598Rq8oebjQAiB3FC3br7rS6bkKOKa4h9Ml1nicuPWpXTWjAWrALVTd+gEiAgL5FiXwf7LFvWGhO5ZR/8v9RQSfyyvsmziYk/I/juHJ4UcwRAIgGfJBreyXt5Isv9PjLRJCFy5jVrMGieGsvV01LTPf3/gCICS81/Mvot0WYdlXC+FnAQ4AprXIQH+g1pnDomBGO+UZAQEDBAEAAAABBUdSIQL5FiXwf7LFvWGhO5ZR/8v9RQSfyyvsmziYk/I/juHJ4SECEuGm00ClgE37E61bBD35AlB78WODNBfmQ8+dNjVzMtRSriIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBqiEC262VFMR0zQS8kl+14wQWuWrsU347lEh8RN7ydSV33ZWsUYdkdqkUvIrspFvJQ2XUVl4CuFGNICwJI1+IrGt2qRSfpUNGG4+BIoO9/dUxLcYpID+Aw4isbJNSh2dSIQKkxJmDMXYy1OdMI/x8PV9j3+1kQ0gpzuD+KqSeYfjzTiEDtqfLJXVbB4YRIpsvmVtBNS971+XfZqkNHdNV1Xcdw1lSrwKHG7JoIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQElIQLbrZUUxHTNBLySX7XjBBa5auxTfjuUSHxE3vJ1JXfdlaxRhyICAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAA==").unwrap();
+        let wallet_id = 1;
+        let outpoint_b = OutPoint::from_str(
+            "2117d7c3461ca013a099d8e60b0bcc6c33aec95db49f636c479ab85117479a91:0",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(112245);
+        let derivation_index = DerivationIndex::new(643874).unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            wallet_id,
+            &outpoint_b,
+            &amount,
+            derivation_index,
+        )
+        .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint_b).unwrap().unwrap();
+        db_confirm_deposit(
+            &db_path,
+            &outpoint_b,
+            9,
+            9,
+            &fresh_unvault_tx,
+            &cancel_tx,
+            None,
+            None,
+            1_600_000_000,
+        )
+        .unwrap();
+        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_unvault_tx,
+            &fullysigned_unvault_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Active).unwrap();
+
+        let spend_tx_b = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAXHqOcTAJnPyXEF1cxFATe4S6yHLGZm+s0aj9mUTtKgVAAAAAACHGwAAAoAyAAAAAAAAIgAgAYLPNnsrzQaSg7aZR0JUgXHtO6bnZRehvxqxyzW5m5ygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgdS3fC7QX+PKWZBful8J229uixPOW012CYpKMH7rU8T4BAwQBAAAAAQWqIQLbrZUUxHTNBLySX7XjBBa5auxTfjuUSHxE3vJ1JXfdlaxRh2R2qRS8iuykW8lDZdRWXgK4UY0gLAkjX4isa3apFJ+lQ0Ybj4Eig7391TEtxikgP4DDiKxsk1KHZ1IhAqTEmYMxdjLU50wj/Hw9X2Pf7WRDSCnO4P4qpJ5h+PNOIQO2p8sldVsHhhEimy+ZW0E1L3vX5d9mqQ0d01XVdx3DWVKvAocbsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAtutlRTEdM0EvJJfteMEFrlq7FN+O5RIfETe8nUld92VrFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let spend_tx_b_inputs = &spend_tx_b.tx().input;
+        assert_eq!(spend_tx_b_inputs.len(), 1);
+        let (_, db_unvault_b) =
+            db_vault_by_unvault_txid(&db_path, &spend_tx_b_inputs[0].previous_output.txid)
+                .unwrap()
+                .unwrap();
+        db_insert_spend(&db_path, &[db_unvault, db_unvault_b], &spend_tx_b).unwrap();
+        let spend_txid = spend_tx.txid();
+        assert_eq!(
+            db_list_spends(&db_path).unwrap().get(&spend_txid),
+            Some(&(
+                DbSpendTransaction {
+                    id: 1,
+                    psbt: spend_tx.clone(),
+                    broadcasted: None,
+                    has_priority: true,
+                },
+                vec![outpoint]
+            ))
+        );
+        let spend_txid_b = spend_tx_b.txid();
+        assert_eq!(
+            db_list_spends(&db_path).unwrap().get(&spend_txid_b),
+            Some(&(
+                DbSpendTransaction {
+                    id: 2,
+                    psbt: spend_tx_b.clone(),
+                    broadcasted: None,
+                    has_priority: false,
+                },
+                vec![outpoint, outpoint_b]
+            ))
+        );
+
+        let spend_tx_b = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAXHqOcTAJnPyXEF1cxFATe4S6yHLGZm+s0aj9mUTtKgVAAAAAACHGwAAAoAyAAAAAAAAIgAgAYLPNnsrzQaSg7aZR0JUgXHtO6bnZRehvxqxyzW5m5ygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgdS3fC7QX+PKWZBful8J229uixPOW012CYpKMH7rU8T4iAgKkxJmDMXYy1OdMI/x8PV9j3+1kQ0gpzuD+KqSeYfjzTkgwRQIhAMwdbbLXqH49pRfZR6PtSzNg/MB+DuVo1xs7rPTZQ12RAiBDSHEGyQaE1K+wknL2IFnhWXKn+/YSfSMtMg9u4zepNwEiAgLbrZUUxHTNBLySX7XjBBa5auxTfjuUSHxE3vJ1JXfdlUgwRQIhAM0sVhdfZtv9Uofdo2oSfwOqTL5gmveVElUl8zxDgZ9wAiB82EHY4wySIWec9cZHbmhLY72LSwyGrqDANNMTun6JNwEiAgO2p8sldVsHhhEimy+ZW0E1L3vX5d9mqQ0d01XVdx3DWUcwRAIgRhhHxuXx5X2eniy4tMP4wP2xoBD+XZlxMQiF9HoXIDYCIEfKdXOOILXSFKeOZ2v6nomllEQOyjuBUk+0LhK7+55mAQEDBAEAAAABBaohAtutlRTEdM0EvJJfteMEFrlq7FN+O5RIfETe8nUld92VrFGHZHapFLyK7KRbyUNl1FZeArhRjSAsCSNfiKxrdqkUn6VDRhuPgSKDvf3VMS3GKSA/gMOIrGyTUodnUiECpMSZgzF2MtTnTCP8fD1fY9/tZENIKc7g/iqknmH4804hA7anyyV1WweGESKbL5lbQTUve9fl32apDR3TVdV3HcNZUq8ChxuyaCIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBJSEC262VFMR0zQS8kl+14wQWuWrsU347lEh8RN7ydSV33ZWsUYciAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAAiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        db_update_spend(&db_path, &spend_tx_b, false).unwrap();
+
+        // There are 2 Unvaults referenced by this Spend
+        let db_spend = db_spend_transaction(&db_path, &spend_tx_b.txid())
+            .unwrap()
+            .unwrap();
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let unvault_psbts = conn
+            .prepare(
+                "SELECT ptx.psbt FROM presigned_transactions as ptx \
+             INNER JOIN spend_inputs as sin ON ptx.id = sin.unvault_id \
+             INNER JOIN spend_transactions as stx ON stx.id = sin.spend_id \
+             WHERE stx.id = (?1)",
+            )
+            .unwrap()
+            .query_map(rusqlite::params![db_spend.id], |row| {
+                row.get::<_, Vec<u8>>(0)
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<Vec<u8>>>>()
+            .unwrap();
+        assert_eq!(unvault_psbts.len(), 2);
+        for psbt in unvault_psbts {
+            UnvaultTransaction::from_psbt_serialized(&psbt).unwrap();
+        }
+
+        // Thus there are 2 vaults too
+        let spent_outpoints: Vec<OutPoint> = db_vaults_from_spend(&db_path, &spend_tx_b.txid())
+            .unwrap()
+            .into_iter()
+            .map(|(_, db_vault)| db_vault.deposit_outpoint)
+            .collect();
+        assert_eq!(spent_outpoints.len(), 2);
+        assert!(spent_outpoints.contains(&outpoint));
+        assert!(spent_outpoints.contains(&outpoint_b));
+
+        let spend_txid = spend_tx.txid();
+        assert!(db_spend_transaction(&db_path, &spend_txid)
+            .unwrap()
+            .unwrap()
+            .broadcasted
+            .is_none());
+        assert_eq!(
+            db_broadcastable_spend_transactions(&db_path).unwrap().len(),
+            0
+        );
+        db_mark_broadcastable_spend(&db_path, &spend_txid).unwrap();
+        assert_eq!(
+            db_broadcastable_spend_transactions(&db_path).unwrap().len(),
+            1
+        );
+        // Since the spend is broadcastable, its unvaults are cpfpable
+        assert_eq!(db_cpfpable_spends(&db_path).unwrap().len(), 0);
+        assert_eq!(db_cpfpable_unvaults(&db_path).unwrap().len(), 1);
+        assert!(!db_spend_transaction(&db_path, &spend_txid)
+            .unwrap()
+            .unwrap()
+            .broadcasted
+            .unwrap(),);
+        db_mark_broadcasted_spend(&db_path, &spend_txid).unwrap();
+        assert_eq!(
+            db_broadcastable_spend_transactions(&db_path).unwrap().len(),
+            0
+        );
+        assert!(db_spend_transaction(&db_path, &spend_txid)
+            .unwrap()
+            .unwrap()
+            .broadcasted
+            .unwrap());
+        // Now the spend is cpfpable as it's broadcasted
+        assert_eq!(db_cpfpable_spends(&db_path).unwrap().len(), 1);
+        assert_eq!(db_cpfpable_unvaults(&db_path).unwrap().len(), 0);
+
+        // And we can delete the transaction
+        db_delete_spend(&db_path, &spend_txid).unwrap();
+        assert!(db_spend_transaction(&db_path, &spend_txid)
+            .unwrap()
+            .is_none());
+
+        // And if we unconfirm the vault, it'll delete the last remaining transaction
+        let txid_b = spend_tx_b.txid();
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(&db_tx, db_vault.id, 1_600_000_000).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert!(db_spend_transaction(&db_path, &txid_b).unwrap().is_none());
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_update_vault_status() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        let clock = Arc::new(MockClock::new(1_600_000_000));
+        revaultd.clock = clock.clone();
+
+        setup_db(&mut revaultd).unwrap();
+
+        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////ARQJVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UBAwSBAAAAAQVhIQPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjqxRh2R2qRTSH6G2Ru92gsQ8Zo4dNgTsvMy2L4isa3apFLP0U8urvhbV0H973pOSBuRg+k7xiKxsk1KHZ1iyaCIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAiBgPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjgglHWAJAQAAAAAiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAAA==").unwrap();
+        let fresh_emergency_tx = EmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ATgcVQEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBKwDMVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsBAwSBAAAAAQVHUiEDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYhA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgUq4iBgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACIGA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAAAA=").unwrap();
+        let fresh_unvaultemergency_tx = UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////AWZ5VAEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UBAwSBAAAAAQVhIQPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjqxRh2R2qRTSH6G2Ru92gsQ8Zo4dNgTsvMy2L4isa3apFLP0U8urvhbV0H973pOSBuRg+k7xiKxsk1KHZ1iyaCIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAiBgPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjgglHWAJAQAAAAAA").unwrap();
+        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ArhEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UwdQAAAAAAACIAILzK9vum6/lhgKe5jxw305+0hoD0nTIyaO2YhNSGPZYbAAAAAAABASsAzFUBAAAAACIAIJBJZLPtAXAEcDz1wPd55+4BNNyZu/t6G6qiizOcGuzrAQMEAQAAAAEFR1IhA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGIQO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYFKuIgYDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiBgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAAAAiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAIgIDwCr2aH/yTKugv5gL94kaje+nlTukczWC88/V6l4oDo4IJR1gCQEAAAAAIgICpNYvWPZyxsUYf7xyXokNYDytbr1bx10GK6jRxJ19+r4I+93szQEAAAAA").unwrap();
+
+        let fullysigned_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////ARQJVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxkcwRAIgB/3p+T4lseNEnwmN7iohmyMUiIUsDDnGU58iftbc6lcCIDTeEOYzwqyCkU65rRqQ45q6DHsL/M8SxvTS87vaaMQhgSICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgSDBFAiEA039gXXzN9fjgENN6EOP8LE9HwDxHcJSVRpCCw5DJUKoCIDdwWFSHtJR/3gnubCtUNPF2dJzinCsVFjW2MfQfjYDGgQEDBIEAAAABBWEhA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OrFGHZHapFNIfobZG73aCxDxmjh02BOy8zLYviKxrdqkUs/RTy6u+FtXQf3vek5IG5GD6TvGIrGyTUodnWLJoIgYDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiBgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAACIGA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OCCUdYAkBAAAAACICA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgIDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAA").unwrap();
+        let fullysigned_emergency_tx = EmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ATgcVQEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBKwDMVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxkcwRAIgKDAJyH5ixlG6HgsUtvWgYbpRv6vuthbwjaIc6nxa220CIDfUJJe5RgnmPgWXnQdjiMp/nLNETh1fbi2KV3u6YxRbgSICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgSDBFAiEAyFE0qIblxbDV3ocAUcbrLEvIMXi/c5H2Z+PbkGA43xUCIDagAFsbwHijNkp7QFMkr2M7YVhXODcJ0JO6mzznRcWegQEDBIEAAAABBUdSIQO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxiEDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2BSriIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAAAA==").unwrap();
+        let fullysigned_unvaultemergency_tx = UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////AWZ5VAEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxkgwRQIhAKpNCciNFBhFAnGRgOwnSQWmXXd+MGECPxqPyDU795EzAiBkLV2iCdA5S0ggYIYiANaXsRrZCxHRiRdBGQaDgWyviYEiAgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYEcwRAIgYvx1ukm7/2LGEgUb2JrZouzbNi9Otlqdf9FhKbDaZiICIDtx72rpajYo/xtLQGXUrFmoogOOasxEZztmNZ0x0Gk3gQEDBIEAAAABBWEhA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OrFGHZHapFNIfobZG73aCxDxmjh02BOy8zLYviKxrdqkUs/RTy6u+FtXQf3vek5IG5GD6TvGIrGyTUodnWLJoIgYDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiBgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAACIGA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OCCUdYAkBAAAAAAA=").unwrap();
+        let fullysigned_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ArhEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UwdQAAAAAAACIAILzK9vum6/lhgKe5jxw305+0hoD0nTIyaO2YhNSGPZYbAAAAAAABASsAzFUBAAAAACIAIJBJZLPtAXAEcDz1wPd55+4BNNyZu/t6G6qiizOcGuzrIgIDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsZHMEQCICc3av7U4xVy0x35E2BdzIDjR1+F/0NVdCwnkcmNGgNCAiB5pHElbUup/2JRAopn/gQuLGt+uCHhFQy01IOrsje3ZwEiAgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYEcwRAIgBj4yp1mne9ibY3k6pdpIrdbdAG+MZuOuBdV9Nanzl7cCIFtfEBSqRcruzwGPK0KniC7buW4ow9o6+ELAeH83ZuQDAQEDBAEAAAABBUdSIQO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxiEDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2BSriIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAAIgIDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiAgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAACICA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OCCUdYAkBAAAAACICAqTWL1j2csbFGH+8cl6JDWA8rW69W8ddBiuo0cSdffq+CPvd7M0BAAAAAA==").unwrap();
+
+        let wallet_id = 1;
+        let outpoint_b = OutPoint::from_str(
+            "da2245566282477c233a70ec684c7ca42ee2505b07dbe38e1af993c470120b7b:0",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(22400000);
+        let derivation_index = DerivationIndex::new(1).unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            wallet_id,
+            &outpoint_b,
+            &amount,
+            derivation_index,
+        )
+        .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint_b).unwrap().unwrap();
+        db_confirm_deposit(
+            &db_path,
+            &outpoint_b,
+            9,
+            9,
+            &fresh_unvault_tx,
+            &fresh_cancel_tx,
+            Some(&fresh_emergency_tx),
+            Some(&fresh_unvaultemergency_tx),
+            1_600_000_000,
+        )
+        .unwrap();
+
+        let stored_cancel_tx = db_cancel_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_cancel_tx.clone(),
+            &fullysigned_cancel_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+
+        let stored_emergency_tx = db_emer_transaction(&db_path, db_vault.id).unwrap().unwrap();
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_emergency_tx,
+            &fullysigned_emergency_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+
+        let stored_unvaultemergency_tx = db_unvault_emer_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_unvaultemergency_tx,
+            &fullysigned_unvaultemergency_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+
+        db_update_vault_status(&db_path, &db_vault, revaultd.clock.unix_timestamp()).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &db_vault.deposit_outpoint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Secured);
+        assert!(db_vault.funded_at.is_some());
+        // The event is timestamped by the daemon clock
+        assert_eq!(db_vault.secured_at, Some(1_600_000_000));
+        assert!(db_vault.delegated_at.is_none());
+        // The fee market was recorded for the revocation transactions once fully signed
+        let contexts = db_vault_signing_contexts(&db_path, db_vault.id).unwrap();
+        assert_eq!(contexts.len(), 3);
+        for context in contexts {
+            assert_eq!(context.blockheight, 0);
+            assert!(context.next_block_estimate.is_none() && context.next_day_estimate.is_none());
+        }
+        let cancel_context = db_vault_signing_contexts(&db_path, db_vault.id)
+            .unwrap()
+            .into_iter()
+            .find(|context| context.presigned_id == stored_cancel_tx.id)
+            .unwrap();
+        assert_eq!(
+            cancel_context.feerate,
+            RevaultTx::Cancel(fullysigned_cancel_tx.clone()).feerate()
+        );
+        // So was their final txid, the one they'll have once broadcast
+        let final_txids = db_vault_final_txids(&db_path, db_vault.id).unwrap();
+        assert_eq!(final_txids.len(), 3);
+        let cancel_final_txid = final_txids
+            .iter()
+            .find(|final_txid| final_txid.tx_type == TransactionType::Cancel)
+            .unwrap();
+        let mut finalized_cancel_tx = fullysigned_cancel_tx.clone();
+        finalized_cancel_tx.finalize(&revaultd.secp_ctx).unwrap();
+        assert_eq!(
+            cancel_final_txid.txid,
+            finalized_cancel_tx.into_psbt().extract_tx().txid()
+        );
+
+        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+        update_presigned_tx(
+            &db_path,
+            &db_vault,
+            stored_unvault_tx,
+            &fullysigned_unvault_tx.psbt().inputs[0].partial_sigs,
+            &revaultd.secp_ctx,
+        );
+        clock.advance(Duration::from_secs(3600));
+        db_update_vault_status(&db_path, &db_vault, revaultd.clock.unix_timestamp()).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &db_vault.deposit_outpoint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Active);
+        assert!(db_vault.funded_at.is_some());
+        assert_eq!(db_vault.secured_at, Some(1_600_000_000));
+        assert_eq!(db_vault.delegated_at, Some(1_600_003_600));
+        assert_eq!(
+            db_vault_signing_contexts(&db_path, db_vault.id)
+                .unwrap()
+                .len(),
+            4
+        );
+        assert_eq!(
+            db_vault_final_txids(&db_path, db_vault.id).unwrap().len(),
+            4
+        );
+        assert_eq!(db_final_txids(&db_path).unwrap().len(), 4);
+        // The feerates of all of them are checked against the relay floor of bitcoind
+        let signed_feerates = db_signed_feerates(&db_path).unwrap();
+        assert_eq!(signed_feerates.len(), 4);
+        assert!(signed_feerates.contains(&(
+            db_vault.deposit_outpoint,
+            fullysigned_cancel_tx.txid(),
+            cancel_context.feerate
+        )));
+
+        // The acknowledgements of our signatures are tracked per coordinator
+        let (coord_a, coord_b) = ([1; 32], [2; 32]);
+        let unacked = db_coordinator_unacked_txs(&db_path, &coord_a).unwrap();
+        assert_eq!(unacked.len(), 4);
+        assert!(unacked.iter().all(|(vault, _)| vault.id == db_vault.id));
+        let txids = [fresh_cancel_tx.txid(), fresh_unvault_tx.txid()];
+        db_ack_coordinator_sigs(&db_path, &coord_a, &txids).unwrap();
+        db_ack_coordinator_sigs(&db_path, &coord_a, &txids[..1]).unwrap();
+        let unacked = db_coordinator_unacked_txs(&db_path, &coord_a).unwrap();
+        assert_eq!(unacked.len(), 2);
+        assert!(unacked
+            .iter()
+            .all(|(_, tx)| !txids.contains(&tx.psbt.txid())));
+        assert_eq!(
+            db_coordinator_unacked_txs(&db_path, &coord_b)
+                .unwrap()
+                .len(),
+            4
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_stale_revocations() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        let cancel_tx_a = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////ARQJVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UBAwSBAAAAAQVhIQPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjqxRh2R2qRTSH6G2Ru92gsQ8Zo4dNgTsvMy2L4isa3apFLP0U8urvhbV0H973pOSBuRg+k7xiKxsk1KHZ1iyaCIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAiBgPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjgglHWAJAQAAAAAiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAAA==").unwrap();
+        let emer_tx_a = EmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ATgcVQEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBKwDMVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsBAwSBAAAAAQVHUiEDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYhA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgUq4iBgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACIGA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAAAA=").unwrap();
+        let unemer_tx_a = UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////AWZ5VAEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UBAwSBAAAAAQVhIQPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjqxRh2R2qRTSH6G2Ru92gsQ8Zo4dNgTsvMy2L4isa3apFLP0U8urvhbV0H973pOSBuRg+k7xiKxsk1KHZ1iyaCIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAiBgPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjgglHWAJAQAAAAAA").unwrap();
+        let unvault_tx_a = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ArhEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UwdQAAAAAAACIAILzK9vum6/lhgKe5jxw305+0hoD0nTIyaO2YhNSGPZYbAAAAAAABASsAzFUBAAAAACIAIJBJZLPtAXAEcDz1wPd55+4BNNyZu/t6G6qiizOcGuzrAQMEAQAAAAEFR1IhA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGIQO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYFKuIgYDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiBgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAAAAiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAIgIDwCr2aH/yTKugv5gL94kaje+nlTukczWC88/V6l4oDo4IJR1gCQEAAAAAIgICpNYvWPZyxsUYf7xyXokNYDytbr1bx10GK6jRxJ19+r4I+93szQEAAAAA").unwrap();
+        let cancel_tx_b = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        let unvault_tx_b = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAT7KJ+fkvbKBDobFTsm31LqtMUfhTiR5tWA5XJA9oYgOAAAAAAD9////AkANAwAAAAAAIgAgbMJH4U4sOCdd1R9PVUuEbmS4bkbnNNlJaqxZBqXHwCcwdQAAAAAAACIAIM8vNQyMFHWpzTmNSefLOTf0spivub9JuegPqYdx0rLvAAAAAAABASuIlAMAAAAAACIAIONmt9fso2OE03OxwV4EkzSucRgHSh3ylMy/KcBayrRaAQMEAQAAAAEFR1IhAum/3N5NY9BZnqXIJxEBNzNEhHwCOY4WQ5xdZZ9XN4+dIQNwiQrXHbeULZ18BN3FOfnYK48NrsVzMDAXVEiu7HfvylKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQOxOjPIG6CKHguqGMBsMRvG/RIiZzCbu7GDMDGmmvH6FqxRh2R2qRSrdyIjb58/y1mAP+ccckOFvfAe04isa3apFAexihzQF+l8AqKa+Y/5XVddSavViKxsk1KHZ1IhAwOygpbYC9yckzxzYFmjVTs4cZzaRTJ97nCHwbFZ6PCaIQLBejnrZMZEk984LSigxiITRc96BSWvsT2wJVMCkLKSe1KvAlAFsmgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhA7E6M8gboIoeC6oYwGwxG8b9EiJnMJu7sYMwMaaa8foWrFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+
+        let outpoint_a = OutPoint::from_str(
+            "da2245566282477c233a70ec684c7ca42ee2505b07dbe38e1af993c470120b7b:0",
+        )
+        .unwrap();
+        let outpoint_b = OutPoint::from_str(
+            "adaa5a4b9fb07c860f8de460727b6bad4b5ab01d2e7f90f6f3f15a0080020168:0",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(22400000);
+        for (i, outpoint) in [outpoint_a, outpoint_b].iter().enumerate() {
+            let index = DerivationIndex::new(i as u32).unwrap();
+            db_insert_new_unconfirmed_vault(&db_path, 1, outpoint, &amount, index).unwrap();
+        }
+        db_confirm_deposit(
             &db_path,
-            wallet_id,
-            &second_deposit_outpoint,
-            &amount,
-            derivation_index,
+            &outpoint_a,
+            9,
+            9,
+            &unvault_tx_a,
+            &cancel_tx_a,
+            Some(&emer_tx_a),
+            Some(&unemer_tx_a),
+            1_600_000_000,
+        )
+        .unwrap();
+        db_confirm_deposit(
+            &db_path,
+            &outpoint_b,
+            9,
+            9,
+            &unvault_tx_b,
+            &cancel_tx_b,
+            None,
+            None,
+            1_600_000_000,
         )
         .unwrap();
+        let vault_a = db_vault_by_deposit(&db_path, &outpoint_a).unwrap().unwrap();
+        let vault_b = db_vault_by_deposit(&db_path, &outpoint_b).unwrap().unwrap();
+        db_mark_vault_as(&db_path, vault_a.id, VaultStatus::Secured).unwrap();
+        db_mark_vault_as(&db_path, vault_b.id, VaultStatus::Active).unwrap();
+
+        // Seed the revocation transactions with various feerates. The Unvault is not a
+        // revocation transaction, it's never stale.
+        let estimates = Some(FeerateEstimates {
+            next_block: Some(40),
+            next_day: None,
+        });
+        let seeds = [
+            (db_cancel_transaction(&db_path, vault_a.id), 1),
+            (db_emer_transaction(&db_path, vault_a.id), 5),
+            (db_unvault_emer_transaction(&db_path, vault_a.id), 10),
+            (db_unvault_transaction(&db_path, vault_a.id), 1),
+            (db_cancel_transaction(&db_path, vault_b.id), 3),
+        ];
+        db_exec(&db_path, |db_tx| {
+            for (presigned_tx, feerate) in seeds.iter() {
+                let presigned_id = presigned_tx.as_ref().unwrap().as_ref().unwrap().id;
+                dbtx_record_signing_context(
+                    db_tx,
+                    presigned_id,
+                    *feerate,
+                    estimates,
+                    1_600_000_000,
+                )?;
+            }
+            Ok(())
+        })
+        .unwrap();
 
-        let wallet_id = 1;
-        let third_deposit_outpoint = OutPoint::from_str(
-            "616efc37747c8cafc2f99692177a5400bad81b671d8d35ffa347d84b246e9a83:0",
+        let stale = |feerate| {
+            db_stale_revocations(&db_path, feerate)
+                .unwrap()
+                .into_iter()
+                .map(|(db_vault, tx_type, txid, context)| {
+                    assert_eq!(context.next_block_estimate, Some(40));
+                    assert!(context.next_day_estimate.is_none());
+                    (db_vault.id, tx_type, txid, context.feerate)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(stale(1).is_empty());
+        assert_eq!(
+            stale(4),
+            vec![
+                (vault_a.id, TransactionType::Cancel, cancel_tx_a.txid(), 1),
+                (vault_b.id, TransactionType::Cancel, cancel_tx_b.txid(), 3),
+            ]
+        );
+        assert_eq!(
+            stale(11),
+            vec![
+                (vault_a.id, TransactionType::Cancel, cancel_tx_a.txid(), 1),
+                (vault_a.id, TransactionType::Emergency, emer_tx_a.txid(), 5),
+                (
+                    vault_a.id,
+                    TransactionType::UnvaultEmergency,
+                    unemer_tx_a.txid(),
+                    10
+                ),
+                (vault_b.id, TransactionType::Cancel, cancel_tx_b.txid(), 3),
+            ]
+        );
+
+        // It's only recorded once, when the transaction gets fully signed
+        db_exec(&db_path, |db_tx| {
+            let presigned_id = seeds[0].0.as_ref().unwrap().as_ref().unwrap().id;
+            dbtx_record_signing_context(db_tx, presigned_id, 100, None, 1_600_000_000)
+        })
+        .unwrap();
+        assert_eq!(stale(4).len(), 2);
+
+        // The revocation transactions of a vault that was unvaulted are not stale anymore
+        db_mark_vault_as(&db_path, vault_b.id, VaultStatus::Unvaulting).unwrap();
+        assert_eq!(stale(4).len(), 1);
+
+        // Nor are they once the deposit is unconfirmed
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(db_tx, vault_a.id, 1_600_000_000)
+        })
+        .unwrap();
+        assert!(stale(11).is_empty());
+        assert!(db_vault_signing_contexts(&db_path, vault_a.id)
+            .unwrap()
+            .is_empty());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_sync_watchdata() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        let outpoints: Vec<OutPoint> = (0..3)
+            .map(|i| {
+                OutPoint::from_str(&format!(
+                    "da2245566282477c233a70ec684c7ca42ee2505b07dbe38e1af993c470120b7b:{}",
+                    i
+                ))
+                .unwrap()
+            })
+            .collect();
+        let amount = Amount::from_sat(22400000);
+        let mut ids = Vec::with_capacity(outpoints.len());
+        for (i, outpoint) in outpoints.iter().enumerate() {
+            let index = DerivationIndex::new(i as u32).unwrap();
+            db_insert_new_unconfirmed_vault(&db_path, 1, outpoint, &amount, index).unwrap();
+            ids.push(db_vault_by_deposit(&db_path, outpoint).unwrap().unwrap().id);
+        }
+        let watchdata = |since| {
+            db_watchdata(&db_path, since)
+                .unwrap()
+                .into_iter()
+                .map(|entry| (entry.deposit_outpoint, entry.sequence, entry.revoked))
+                .collect::<Vec<_>>()
+        };
+
+        // Nothing to watch until a vault is Active
+        db_mark_vault_as(&db_path, ids[0], VaultStatus::Secured).unwrap();
+        db_sync_watchdata(&db_path).unwrap();
+        assert!(watchdata(0).is_empty());
+
+        db_mark_vault_as(&db_path, ids[0], VaultStatus::Active).unwrap();
+        db_mark_vault_as(&db_path, ids[1], VaultStatus::Active).unwrap();
+        db_sync_watchdata(&db_path).unwrap();
+        assert_eq!(
+            watchdata(0),
+            vec![(outpoints[0], 1, false), (outpoints[1], 2, false)]
+        );
+
+        // Syncing again without any change doesn't bump the sequence
+        db_sync_watchdata(&db_path).unwrap();
+        assert_eq!(watchdata(0).len(), 2);
+        assert!(watchdata(2).is_empty());
+
+        // A vault leaving the Active status is revoked, a new Active one is watched
+        db_mark_vault_as(&db_path, ids[0], VaultStatus::Canceling).unwrap();
+        db_mark_vault_as(&db_path, ids[2], VaultStatus::Active).unwrap();
+        db_sync_watchdata(&db_path).unwrap();
+        assert_eq!(
+            watchdata(2),
+            vec![(outpoints[0], 3, true), (outpoints[2], 4, false)]
+        );
+        assert_eq!(
+            watchdata(0),
+            vec![
+                (outpoints[1], 2, false),
+                (outpoints[0], 3, true),
+                (outpoints[2], 4, false)
+            ]
+        );
+
+        // A revoked vault that gets Active again (after a reorg) is watched again
+        db_mark_vault_as(&db_path, ids[0], VaultStatus::Active).unwrap();
+        db_sync_watchdata(&db_path).unwrap();
+        assert_eq!(watchdata(4), vec![(outpoints[0], 5, false)]);
+
+        // So is one whose deposit got unconfirmed
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(db_tx, ids[1], 1_600_000_000)
+        })
+        .unwrap();
+        db_sync_watchdata(&db_path).unwrap();
+        assert_eq!(watchdata(5), vec![(outpoints[1], 6, true)]);
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_mempool_conflicts() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
         )
         .unwrap();
-        let amount = Amount::from_sat(428000);
-        let derivation_index = ChildNumber::from(15);
         db_insert_new_unconfirmed_vault(
             &db_path,
-            wallet_id,
-            &third_deposit_outpoint,
-            &amount,
-            derivation_index,
+            1,
+            &outpoint,
+            &Amount::from_sat(612345),
+            DerivationIndex::new(349874).unwrap(),
         )
         .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert!(db_vault_conflicts(&db_path, db_vault.id)
+            .unwrap()
+            .is_empty());
 
-        // By the way, trying to insert for an inexistant wallet will fail the
-        // db constraint
+        let spend_txid =
+            Txid::from_str("a4d4bf7e8d3a0b6e5ef4a2cbb0f20ff0d4ee1c2a4f6e82b6a4e3ae3c9c59e7d1")
+                .unwrap();
+        let cancel_txid =
+            Txid::from_str("6e5ef4a2cbb0f20ff0d4ee1c2a4f6e82b6a4e3ae3c9c59e7d1a4d4bf7e8d3a0b")
+                .unwrap();
+
+        // We don't know yet what the Spend conflicted with
+        db_insert_mempool_conflict(&db_path, db_vault.id, &spend_txid, None, 1_600_000_000)
+            .unwrap();
+        let conflicts = db_vault_conflicts(&db_path, db_vault.id).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].rejected_txid, spend_txid);
+        assert_eq!(conflicts[0].competing_txid, None);
+        assert_eq!(conflicts[0].detected_at, 1_600_000_000);
+
+        // Recording it twice doesn't duplicate it, and we learn about the Cancel once it's seen
+        db_insert_mempool_conflict(&db_path, db_vault.id, &spend_txid, None, 1_600_000_030)
+            .unwrap();
+        db_set_conflicts_competing(&db_path, db_vault.id, &cancel_txid).unwrap();
+        let conflicts = db_vault_conflicts(&db_path, db_vault.id).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].competing_txid, Some(cancel_txid));
+        assert_eq!(conflicts[0].confirmed_txid, None);
+
+        // The race is settled once one of them confirms
+        db_settle_conflicts(&db_path, db_vault.id, &cancel_txid).unwrap();
+        let conflicts = db_vault_conflicts(&db_path, db_vault.id).unwrap();
+        assert_eq!(conflicts[0].confirmed_txid, Some(cancel_txid));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_revocation_checks() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
         db_insert_new_unconfirmed_vault(
             &db_path,
-            wallet_id + 1,
-            &third_deposit_outpoint,
-            &amount,
-            derivation_index,
+            1,
+            &outpoint,
+            &Amount::from_sat(612345),
+            DerivationIndex::new(349874).unwrap(),
         )
-        .unwrap_err();
+        .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Secured).unwrap();
+        assert_eq!(db_last_revocation_check(&db_path).unwrap(), None);
 
-        // Now retrieve the deposits; there must all be there
-        let deposit_outpoints: Vec<OutPoint> = db_deposits(&db_path)
-            .unwrap()
-            .into_iter()
-            .map(|db_vault| db_vault.deposit_outpoint)
-            .collect();
-        assert_eq!(deposit_outpoints.len(), 3);
-        assert!(deposit_outpoints.contains(&first_deposit_outpoint));
-        assert!(deposit_outpoints.contains(&second_deposit_outpoint));
-        assert!(deposit_outpoints.contains(&third_deposit_outpoint));
+        let emer_txid =
+            Txid::from_str("a4d4bf7e8d3a0b6e5ef4a2cbb0f20ff0d4ee1c2a4f6e82b6a4e3ae3c9c59e7d1")
+                .unwrap();
+        db_record_revocation_check(
+            &db_path,
+            db_vault.id,
+            1_600_000_000,
+            Some((&emer_txid, "min relay fee not met")),
+        )
+        .unwrap();
+        let checks = db_revocation_checks(&db_path).unwrap();
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].deposit_outpoint, outpoint);
+        assert_eq!(checks[0].rejected_txid, Some(emer_txid));
+        assert_eq!(
+            checks[0].reject_reason.as_deref(),
+            Some("min relay fee not met")
+        );
+        assert_eq!(
+            db_last_revocation_check(&db_path).unwrap(),
+            Some(1_600_000_000)
+        );
 
-        // Now if we mark the first as being unvaulted we'll only fetch the two last ones
-        db_exec(&db_path, |tx| {
-            tx.execute(
-                "UPDATE vaults SET status = (?1) \
-                 WHERE deposit_txid = (?2) AND deposit_vout = (?3) ",
-                params![
-                    VaultStatus::Unvaulting as u32,
-                    first_deposit_outpoint.txid.to_vec(),
-                    first_deposit_outpoint.vout
-                ],
-            )
-            .unwrap();
-            Ok(())
-        })
+        // A new check overwrites the previous result
+        db_record_revocation_check(&db_path, db_vault.id, 1_600_086_400, None).unwrap();
+        let checks = db_revocation_checks(&db_path).unwrap();
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].checked_at, 1_600_086_400);
+        assert_eq!(checks[0].rejected_txid, None);
+        assert_eq!(checks[0].reject_reason, None);
+
+        // We only care about the vaults we could still revault from
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Spent).unwrap();
+        assert!(db_revocation_checks(&db_path).unwrap().is_empty());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_vault_flags() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            1,
+            &outpoint,
+            &Amount::from_sat(612345),
+            DerivationIndex::new(349874).unwrap(),
+        )
         .unwrap();
-        let deposit_outpoints: Vec<OutPoint> = db_deposits(&db_path)
-            .unwrap()
-            .into_iter()
-            .map(|db_vault| db_vault.deposit_outpoint)
-            .collect();
-        assert_eq!(deposit_outpoints.len(), 2);
-        assert!(!deposit_outpoints.contains(&first_deposit_outpoint));
-        assert!(deposit_outpoints.contains(&second_deposit_outpoint));
-        assert!(deposit_outpoints.contains(&third_deposit_outpoint));
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert!(db_vault_flags(&db_path, db_vault.id).unwrap().is_empty());
+
+        let kind = VaultFlagKind::RevocationRejected;
+        assert!(!db_clear_vault_flag(&db_path, db_vault.id, kind, 1_600_000_000).unwrap());
+        assert!(db_raise_vault_flag(
+            &db_path,
+            db_vault.id,
+            kind,
+            "min relay fee not met",
+            1_600_000_000
+        )
+        .unwrap());
+        // Raising it again while it's active is a no-op
+        assert!(!db_raise_vault_flag(&db_path, db_vault.id, kind, "other", 1_600_000_001).unwrap());
+        let flags = db_vault_flags(&db_path, db_vault.id).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].kind, kind);
+        assert_eq!(flags[0].message, "min relay fee not met");
+        assert_eq!(flags[0].created_at, 1_600_000_000);
+        assert_eq!(flags[0].cleared_at, None);
+
+        // It survives a restart
+        setup_db(&mut revaultd).unwrap();
+        assert_eq!(db_vault_flags(&db_path, db_vault.id).unwrap(), flags);
+
+        assert!(db_clear_vault_flag(&db_path, db_vault.id, kind, 1_600_000_002).unwrap());
+        assert!(!db_clear_vault_flag(&db_path, db_vault.id, kind, 1_600_000_003).unwrap());
+        let flags = db_vault_flags(&db_path, db_vault.id).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].cleared_at, Some(1_600_000_002));
+
+        // Once cleared, it may be raised again
+        assert!(db_raise_vault_flag(&db_path, db_vault.id, kind, "again", 1_600_000_004).unwrap());
+        let flags = db_vault_flags(&db_path, db_vault.id).unwrap();
+        assert_eq!(flags.len(), 2);
+        assert_eq!(flags[1].message, "again");
+        assert_eq!(flags[1].cleared_at, None);
+
+        // Flags of different kinds are independent
+        let other_kind = VaultFlagKind::UnknownSpender;
+        assert!(
+            db_raise_vault_flag(&db_path, db_vault.id, other_kind, "txid", 1_600_000_005).unwrap()
+        );
+        assert!(db_clear_vault_flag(&db_path, db_vault.id, kind, 1_600_000_006).unwrap());
+        let flags = db_vault_flags(&db_path, db_vault.id).unwrap();
+        assert_eq!(flags.len(), 3);
+        assert_eq!(flags[2].kind, other_kind);
+        assert_eq!(flags[2].cleared_at, None);
 
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
 
     #[test]
-    fn test_db_store_presigned_txs() {
+    fn test_db_invalid_transition() {
         let datadir = test_datadir();
         let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
         let db_path = revaultd.db_file();
-        let secp_ctx = secp256k1::Secp256k1::new();
-
         setup_db(&mut revaultd).unwrap();
 
-        // There is no fully signed Emergency transaction at this point.
-        assert!(db_signed_emer_txs(&db_path).unwrap().is_empty());
-        assert!(db_signed_unemer_txs(&db_path).unwrap().is_empty());
-
-        // Let's insert a deposit
-        let wallet_id = 1;
         let outpoint = OutPoint::from_str(
-            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            1,
+            &outpoint,
+            &Amount::from_sat(612345),
+            DerivationIndex::new(349874).unwrap(),
         )
         .unwrap();
-        let amount = Amount::from_sat(123456);
-        let derivation_index = ChildNumber::from(33334);
-        db_insert_new_unconfirmed_vault(&db_path, wallet_id, &outpoint, &amount, derivation_index)
-            .unwrap();
         let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
 
-        // We can store unsigned transactions
-        let fresh_emer_tx = EmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAVqQwvZ+XLjEW+P90WnqdbVWkC1riPNhF8j9Ca4dM0RiAAAAAAD9////AfhgAwAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK4iUAwAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQBAwSBAAAAAQVHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAAiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
-        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
-        let fresh_unemer_tx = UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAZHNg0DZSHTBSpVaGwH2apdYBRu88ZeeB/XmrijJpvH5AAAAAAD9////AdLKAgAAAAAAIgAg8Wcu+wsgQXcO9MAiWSMtqsVSQkptpfTXJ51MFSdhJAoAAAAAAAEBK0ANAwAAAAAAIgAgtSqMFDOQ2FkdNrt/yUTzVjikth3tOm+um6yLFzLTilcBAwSBAAAAAQWrIQJF6Amv78N3ctJ3+oSlIasXN3/N8H/bu2si9Vu3QNBRuKxRh2R2qRS77fZRBsFKSf1uP2HBT3uhL1oRloisa3apFIPfFe62NUR/RApmlyj0VsJJdJ4CiKxsk1KHZ1IhA5scAvk3lvCVQmoWDTHhcd8utuA6Swf2PolVbdB7yVwnIQIXS76HRC/hWucQkpC43HriwIukm1se8QRc9nIlODCN81KvA37BALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
-        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAcRWqIPG85zGye1nuRlbwWKkko4g91Vd/508Ff6vKklpAAAAAAD9////AkANAwAAAAAAIgAgsT7u0Lo8o2WEfxS1nXWtQzsdJTMJnnOC5fwg0nYPvpowdQAAAAAAACIAIAx0DegrXfBr4D0XdetrGgAT2Q3AZANYm0rJL8L/Epp/AAAAAAABASuIlAMAAAAAACIAIGaHQ5brMNbT+WCtfE/WPW8gkmMir5NXAKRsQZAs9cT2AQMEAQAAAAEFR1IhAwYSJ4FeXdf/XPw6lFHpeMFeGvh88f+rWN2VtnaW75TNIQOn5Sg6nytLwT5FT9z5KmV/LMN1pZRsqbworUMwRdRN0lKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQN0Nj5YtWlqdUtE4VzrCy9fIUbgVSBiSedOJzYY9A0jLqxRh2R2qRQ2UoYTYXFkzWxHTxQLsYl/NGpeVIisa3apFChMb7eFLoSVfMHD7bU9EO0Qn2wqiKxsk1KHZ1IhA2KobMJZNs2+adObuXpg1Ny2DOg/nFo5bqGJdJZWSgKUIQL/DSNFGVoHc5rlzQ4+tEDFvETWR1/NXbg5axpIIYuAhVKvAtY0smgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhA3Q2Pli1aWp1S0ThXOsLL18hRuBVIGJJ504nNhj0DSMurFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        // An unconfirmed deposit can't be spent, the vault is left untouched but flagged.
+        db_mark_spent_unvault(&db_path, db_vault.id, 1_600_000_000, 1_600_000_000).unwrap();
+        let vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(vault.status, VaultStatus::Unconfirmed);
+        assert_eq!(vault.moved_at, None);
+        assert!(db_vault_status_changes(&db_path).unwrap().is_empty());
+        let flags = db_vault_flags(&db_path, db_vault.id).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].kind, VaultFlagKind::InvalidTransition);
+        assert_eq!(
+            flags[0].message,
+            "Invalid vault status transition from 'unconfirmed' to 'spent'"
+        );
 
-        let blockheight = 700000;
-        let blocktime = 700000;
-        db_confirm_deposit(
+        // Same for the updates from an existing database transaction
+        db_exec(&db_path, |db_tx| {
+            db_mark_spendable_vault_dbtx(db_tx, db_vault.id, 1_600_000_000)
+        })
+        .unwrap();
+        assert_eq!(
+            db_vault_by_deposit(&db_path, &outpoint)
+                .unwrap()
+                .unwrap()
+                .status,
+            VaultStatus::Unconfirmed
+        );
+
+        // Staying in the same status is fine
+        db_clear_vault_flag(
             &db_path,
-            &outpoint,
-            blockheight,
-            blocktime,
-            &fresh_unvault_tx,
-            &fresh_cancel_tx,
-            Some(&fresh_emer_tx),
-            Some(&fresh_unemer_tx),
+            db_vault.id,
+            VaultFlagKind::InvalidTransition,
+            1_600_000_001,
         )
         .unwrap();
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(db_tx, db_vault.id, 1_600_000_000)
+        })
+        .unwrap();
+        assert!(db_vault_flags(&db_path, db_vault.id)
+            .unwrap()
+            .iter()
+            .all(|flag| flag.cleared_at.is_some()));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
 
-        // There is still no *fully signed* Emergency transaction at this point!
-        assert!(db_signed_emer_txs(&db_path).unwrap().is_empty());
-        assert!(db_signed_unemer_txs(&db_path).unwrap().is_empty());
+    #[test]
+    fn test_db_derived_scripts() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
 
-        // Sanity check we can add sigs to them now
-        let stored_cancel_tx = db_cancel_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .unwrap();
+        // All the scripts up to the gap limit were derived and stored
+        let gap_limit = revaultd.gap_limit();
         assert_eq!(
-            stored_cancel_tx.psbt.unwrap_cancel().psbt().inputs[0]
-                .partial_sigs
-                .len(),
-            0
+            db_derived_scripts(&db_path).unwrap().len(),
+            gap_limit as usize * 2
         );
-        let mut cancel_tx = fresh_cancel_tx.clone();
-        revault_tx_add_sig(
-            &mut cancel_tx,
-            0,
-            SigHashType::AllPlusAnyoneCanPay,
-            &secp_ctx,
+        assert_eq!(revaultd.derivation_index_map.len(), gap_limit as usize);
+        assert_eq!(
+            revaultd.unvault_derivation_index_map.len(),
+            gap_limit as usize
         );
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_cancel_tx,
-            &cancel_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+        let index = DerivationIndex::new(42).unwrap();
+        assert_eq!(
+            revaultd
+                .derivation_index_map
+                .get(&revaultd.vault_address(index).unwrap().script_pubkey()),
+            Some(&index)
         );
-        let stored_cancel_tx = db_cancel_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .unwrap();
         assert_eq!(
-            stored_cancel_tx.psbt.unwrap_cancel().psbt().inputs[0]
-                .partial_sigs
-                .len(),
-            1
+            revaultd
+                .unvault_derivation_index_map
+                .get(&revaultd.unvault_address(index).unwrap().script_pubkey()),
+            Some(&index)
         );
 
-        let stored_emer_tx = db_emer_transaction(&db_path, db_vault.id).unwrap().unwrap();
+        // We can extend the range, and storing a script twice is a no-op
+        let next_index = DerivationIndex::new(gap_limit).unwrap();
+        db_store_derived_scripts(&mut revaultd, &[next_index, index]).unwrap();
         assert_eq!(
-            stored_emer_tx.psbt.unwrap_emer().psbt().inputs[0]
-                .partial_sigs
-                .len(),
-            0
+            db_derived_scripts(&db_path).unwrap().len(),
+            gap_limit as usize * 2 + 2
         );
-        let mut emer_tx = fresh_emer_tx.clone();
-        revault_tx_add_sig(&mut emer_tx, 0, SigHashType::AllPlusAnyoneCanPay, &secp_ctx);
-        update_presigned_tx(
+        assert_eq!(
+            revaultd
+                .derivation_index_map
+                .get(&revaultd.vault_address(next_index).unwrap().script_pubkey()),
+            Some(&next_index)
+        );
+
+        // They are loaded from the database at startup
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        assert_eq!(revaultd.derivation_index_map.len(), gap_limit as usize + 1);
+        assert_eq!(
+            revaultd.unvault_derivation_index_map.len(),
+            gap_limit as usize + 1
+        );
+
+        // A database created before we stored the scripts gets them back-filled at startup,
+        // including the ones of the vaults out of the current window.
+        let vault_index = DerivationIndex::new(349874).unwrap();
+        db_insert_new_unconfirmed_vault(
             &db_path,
-            &db_vault,
-            stored_emer_tx,
-            &emer_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+            1,
+            &OutPoint::from_str(
+                "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+            )
+            .unwrap(),
+            &Amount::from_sat(612345),
+            vault_index,
+        )
+        .unwrap();
+        db_exec(&db_path, |tx| {
+            tx.execute("DELETE FROM derived_scripts", params![])
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        assert_eq!(
+            db_derived_scripts(&db_path).unwrap().len(),
+            gap_limit as usize * 2 + 2
         );
-        let stored_emer_tx = db_emer_transaction(&db_path, db_vault.id).unwrap().unwrap();
         assert_eq!(
-            stored_emer_tx.psbt.unwrap_emer().psbt().inputs[0]
-                .partial_sigs
-                .len(),
-            1
+            revaultd
+                .derivation_index_map
+                .get(&revaultd.vault_address(vault_index).unwrap().script_pubkey()),
+            Some(&vault_index)
+        );
+        assert_eq!(
+            revaultd.unvault_derivation_index_map.get(
+                &revaultd
+                    .unvault_address(vault_index)
+                    .unwrap()
+                    .script_pubkey()
+            ),
+            Some(&vault_index)
         );
 
-        let stored_unemer_tx = db_unvault_emer_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .unwrap();
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_verify_derivations() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        // The first run of this version was recorded, a later run doesn't change it
+        let first_run_at = db_version_first_run(&db_path, VERSION).unwrap().unwrap();
+        assert_eq!(db_version_first_run(&db_path, "0.0.1").unwrap(), None);
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
         assert_eq!(
-            stored_unemer_tx.psbt.unwrap_unvault_emer().psbt().inputs[0]
-                .partial_sigs
-                .len(),
-            0
+            db_version_first_run(&db_path, VERSION).unwrap(),
+            Some(first_run_at)
         );
-        let mut unemer_tx = fresh_unemer_tx.clone();
-        revault_tx_add_sig(
-            &mut unemer_tx,
-            0,
-            SigHashType::AllPlusAnyoneCanPay,
-            &secp_ctx,
+
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let index = DerivationIndex::new(17).unwrap();
+        db_insert_new_unconfirmed_vault(&db_path, 1, &outpoint, &Amount::from_sat(612345), index)
+            .unwrap();
+        assert!(db_derivation_mismatches(&revaultd).unwrap().is_empty());
+
+        // Simulate a change in how the unvault script derives by tampering with the stored one
+        let derived = revaultd
+            .derived_unvault_descriptor(index)
+            .inner()
+            .script_pubkey();
+        let stored = revaultd
+            .derived_unvault_descriptor(DerivationIndex::new(18).unwrap())
+            .inner()
+            .script_pubkey();
+        db_exec(&db_path, |tx| {
+            tx.execute(
+                "UPDATE derived_scripts SET script_pubkey = (?1) \
+                 WHERE derivation_index = (?2) AND kind = (?3)",
+                params![stored.as_bytes(), index, ScriptKind::Unvault as u32],
+            )
+            .unwrap();
+            // Not to conflict with the tampered one
+            tx.execute(
+                "DELETE FROM derived_scripts WHERE derivation_index = 18",
+                params![],
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            db_derivation_mismatches(&revaultd).unwrap(),
+            vec![DerivationMismatch {
+                deposit_outpoint: outpoint,
+                derivation_index: index,
+                kind: ScriptKind::Unvault,
+                stored: stored.clone(),
+                derived: derived.clone(),
+            }]
         );
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_unemer_tx,
-            &unemer_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+
+        // This version already ran, so it's not checked at startup unless asked to
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        revaultd.verify_derivations = true;
+        let err = setup_db(&mut revaultd).unwrap_err().to_string();
+        assert!(
+            err.contains("1 stored script(s) are not derived identically"),
+            "{}",
+            err
         );
-        let stored_unemer_tx = db_unvault_emer_transaction(&db_path, db_vault.id)
-            .unwrap()
+        assert!(err.contains(&format!(
+            "vault at '{}' (derivation index 17): the stored unvault script is '{}' but '{}' is \
+             derived now",
+            outpoint,
+            stored.as_bytes().to_hex(),
+            derived.as_bytes().to_hex()
+        )));
+
+        // After an upgrade, it's checked at startup. The new version isn't recorded until it
+        // passes, so it's checked again at the next startup.
+        db_exec(&db_path, |tx| {
+            tx.execute("UPDATE binary_versions SET version = '0.0.1'", params![])
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        for _ in 0..2 {
+            let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+            let err = setup_db(&mut revaultd).unwrap_err().to_string();
+            assert!(err.contains(&stored.as_bytes().to_hex()), "{}", err);
+            assert_eq!(db_version_first_run(&db_path, VERSION).unwrap(), None);
+        }
+
+        // Once the stored script is the one derived, it starts and records the new version
+        db_exec(&db_path, |tx| {
+            tx.execute(
+                "UPDATE derived_scripts SET script_pubkey = (?1) \
+                 WHERE derivation_index = (?2) AND kind = (?3)",
+                params![derived.as_bytes(), index, ScriptKind::Unvault as u32],
+            )
             .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        assert!(db_version_first_run(&db_path, VERSION).unwrap().is_some());
+        assert!(db_version_first_run(&db_path, "0.0.1").unwrap().is_some());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_imported_index() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let wallet_id = revaultd.wallet_id.unwrap();
+
+        // Nothing was imported in a fresh database
         assert_eq!(
-            stored_unemer_tx.psbt.unwrap_unvault_emer().psbt().inputs[0]
-                .partial_sigs
-                .len(),
-            1
+            db_imported_index(&db_path, wallet_id, ScriptKind::Deposit).unwrap(),
+            None
+        );
+        assert_eq!(
+            db_imported_index(&db_path, wallet_id, ScriptKind::Unvault).unwrap(),
+            None
         );
 
-        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
-            .unwrap()
+        // It's tracked per kind, and can go backward if the wallet is re-created.
+        let index = DerivationIndex::new(99).unwrap();
+        db_update_imported_index(&db_path, wallet_id, ScriptKind::Deposit, index, 0).unwrap();
+        assert_eq!(
+            db_imported_index(&db_path, wallet_id, ScriptKind::Deposit).unwrap(),
+            Some(index)
+        );
+        assert_eq!(
+            db_imported_index(&db_path, wallet_id, ScriptKind::Unvault).unwrap(),
+            None
+        );
+        let index = DerivationIndex::new(12).unwrap();
+        db_update_imported_index(&db_path, wallet_id, ScriptKind::Deposit, index, 0).unwrap();
+        db_update_imported_index(&db_path, wallet_id, ScriptKind::Unvault, index, 0).unwrap();
+        assert_eq!(
+            db_imported_index(&db_path, wallet_id, ScriptKind::Deposit).unwrap(),
+            Some(index)
+        );
+        assert_eq!(
+            db_imported_index(&db_path, wallet_id, ScriptKind::Unvault).unwrap(),
+            Some(index)
+        );
+
+        // Upgrading a database assumes the scripts it derived were all imported
+        db_store_derived_scripts(&mut revaultd, &[DerivationIndex::new(120).unwrap()]).unwrap();
+        db_exec(&db_path, |tx| {
+            tx.execute_batch(
+                "DROP TABLE imported_addresses; DROP TABLE signing_contexts; \
+                 DROP TABLE watchdata; DROP TABLE wallet_rotations; \
+                 DROP TABLE vault_migrations; DROP TABLE deposit_ancestries; \
+                 DROP TABLE spend_announcements; DROP TABLE final_txids; \
+                 DROP TABLE spend_deprecations; DROP TABLE deposit_coinbases; \
+                 ALTER TABLE wallets DROP COLUMN script_version; \
+                 DROP TABLE emergency_outcomes; DROP TABLE emergency_runs; \
+                 DROP TABLE spend_expirations; DROP TABLE deferred_revocation_txs; \
+                 DROP TABLE imported_ranges; DROP TABLE vault_emergency_addresses; \
+                 DROP TABLE binary_versions; DROP TABLE replayed_transactions; \
+                 DROP TABLE coordinator_outbox; \
+                 UPDATE version SET version = 18;",
+            )
             .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        check_db(&revaultd).unwrap();
         assert_eq!(
-            stored_unvault_tx.psbt.unwrap_unvault().psbt().inputs[0]
-                .partial_sigs
-                .len(),
-            0
+            db_imported_index(&db_path, wallet_id, ScriptKind::Deposit).unwrap(),
+            DerivationIndex::new(120).ok()
         );
-        let mut unvault_tx = fresh_unvault_tx.clone();
-        revault_tx_add_sig(&mut unvault_tx, 0, SigHashType::All, &secp_ctx);
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_unvault_tx,
-            &unvault_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+        assert_eq!(
+            db_imported_index(&db_path, wallet_id, ScriptKind::Unvault).unwrap(),
+            DerivationIndex::new(120).ok()
         );
-        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .unwrap();
+        // Needed since the creation of the wallet, as far as we know
         assert_eq!(
-            stored_unvault_tx.psbt.assert_unvault().psbt().inputs[0]
-                .partial_sigs
-                .len(),
-            1
+            db_imported_ranges(&db_path, wallet_id, ScriptKind::Deposit).unwrap(),
+            vec![DbImportedRange {
+                first_index: DerivationIndex::ZERO,
+                last_index: DerivationIndex::new(120).unwrap(),
+                first_needed_timestamp: db_wallet(&db_path).unwrap().timestamp,
+            }]
         );
 
-        // They can also be queried
+        // The existing wallet is a P2WSH one. We refuse to load a wallet of an unknown script
+        // type, instead of panicking.
         assert_eq!(
-            emer_tx,
-            db_emer_transaction(&db_path, db_vault.id)
-                .unwrap()
-                .unwrap()
-                .psbt
-                .assert_emer()
+            db_wallet(&db_path).unwrap().script_version,
+            ScriptVersion::WitnessV0
         );
+        db_exec(&db_path, |tx| {
+            tx.execute("UPDATE wallets SET script_version = 1", params![])
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert!(db_wallet(&db_path)
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown script version '1'"));
+        assert!(check_db(&revaultd).is_err());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_imported_ranges() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let wallet_id = revaultd.wallet_id.unwrap();
+        let wallet_timestamp = db_wallet(&db_path).unwrap().timestamp;
+        let index = |i| DerivationIndex::new(i).unwrap();
+
+        // Before anything was imported, we'd rescan from the creation of the wallet
+        assert!(db_imported_ranges(&db_path, wallet_id, ScriptKind::Deposit)
+            .unwrap()
+            .is_empty());
         assert_eq!(
-            cancel_tx,
-            db_cancel_transaction(&db_path, db_vault.id)
-                .unwrap()
-                .unwrap()
-                .psbt
-                .assert_cancel()
+            db_rescan_timestamp(&db_path, wallet_id, ScriptKind::Deposit, index(0)).unwrap(),
+            wallet_timestamp
         );
-        assert_eq!(
-            unemer_tx,
-            db_unvault_emer_transaction(&db_path, db_vault.id)
-                .unwrap()
-                .unwrap()
-                .psbt
-                .assert_unvault_emer()
+
+        // The initial range, then two extensions needed far apart
+        let (first_ext, second_ext) = (
+            wallet_timestamp + 30 * 24 * 3600,
+            wallet_timestamp + 60 * 24 * 3600,
         );
+        db_update_imported_index(
+            &db_path,
+            wallet_id,
+            ScriptKind::Deposit,
+            index(100),
+            wallet_timestamp,
+        )
+        .unwrap();
+        db_update_imported_index(
+            &db_path,
+            wallet_id,
+            ScriptKind::Deposit,
+            index(201),
+            first_ext,
+        )
+        .unwrap();
+        db_update_imported_index(
+            &db_path,
+            wallet_id,
+            ScriptKind::Deposit,
+            index(302),
+            second_ext,
+        )
+        .unwrap();
         assert_eq!(
-            unvault_tx,
-            db_unvault_transaction(&db_path, db_vault.id)
-                .unwrap()
-                .unwrap()
-                .psbt
-                .assert_unvault()
+            db_imported_ranges(&db_path, wallet_id, ScriptKind::Deposit).unwrap(),
+            vec![
+                DbImportedRange {
+                    first_index: index(0),
+                    last_index: index(100),
+                    first_needed_timestamp: wallet_timestamp,
+                },
+                DbImportedRange {
+                    first_index: index(101),
+                    last_index: index(201),
+                    first_needed_timestamp: first_ext,
+                },
+                DbImportedRange {
+                    first_index: index(202),
+                    last_index: index(302),
+                    first_needed_timestamp: second_ext,
+                },
+            ]
         );
-        let sig_mis_map = db_sig_missing(&db_path).unwrap();
-        assert_eq!(sig_mis_map.len(), 1);
+        // Importing again up to an index we already recorded doesn't change them
+        db_update_imported_index(&db_path, wallet_id, ScriptKind::Deposit, index(250), 0).unwrap();
         assert_eq!(
-            sig_mis_map
-                .get(sig_mis_map.keys().next().unwrap())
+            db_imported_ranges(&db_path, wallet_id, ScriptKind::Deposit)
                 .unwrap()
                 .len(),
-            4
+            3
         );
+        // They are tracked per kind
+        assert!(db_imported_ranges(&db_path, wallet_id, ScriptKind::Unvault)
+            .unwrap()
+            .is_empty());
+
+        // Addresses of the initial range may have been paid since the wallet was created, the
+        // ones of an extension since the range before it was needed.
+        for (from_index, timestamp) in &[
+            (0, wallet_timestamp),
+            (100, wallet_timestamp),
+            (101, wallet_timestamp),
+            (201, wallet_timestamp),
+            (202, first_ext),
+            (302, first_ext),
+            // The next extension, not recorded yet
+            (303, second_ext),
+        ] {
+            assert_eq!(
+                db_rescan_timestamp(&db_path, wallet_id, ScriptKind::Deposit, index(*from_index))
+                    .unwrap(),
+                *timestamp,
+                "from index {}",
+                from_index
+            );
+        }
 
-        // And removed, if there is eg a reorg.
-        db_exec(&db_path, |db_tx| {
-            db_unconfirm_deposit_dbtx(&db_tx, db_vault.id).unwrap();
-            Ok(())
-        })
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_wallet_rotation() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
+        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAfF2iPeJqz13zFlW6eLAM+uDu5IhUqcQxtMWQx7z5Y8lAAAAAAD9////AkANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQwdQAAAAAAACIAIIMbpoIz4DI+aB1p/EJLyqjyDdDeZ7gG8kPhRIDiWaY8AAAAAAABASuIlAMAAAAAACIAIA9CgZ1cg/hn3iy3buDZvU5zUnQ9NzutToR/r42YZyu3AQMEAQAAAAEFR1IhA9P6hV8yf6HkNofzleom06eqkUxZayWHJnOMNlMtqvD3IQJo5Mj6Wf3ktrwEB3IQXFmgApibojplpNykg0hA8XV6SFKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        setup_db(&mut revaultd).unwrap();
+        let old_wallet_id = revaultd.wallet_id.unwrap();
+
+        // Initiating a rotation records a new wallet, but we keep using the current one
+        let xpub = |s: &str| ExtendedPubKey::from_str(s).unwrap();
+        let stakeholders_xpubs = vec![
+            xpub("tpubDDcdMK347DzJfWk9a8uLuskgoSKTUPn1GtMYz91Q1WdptaY14BTF4KUFiQ3cq5K6ji4bgALTEy6HUuf3TULS4f98wYpaYLcAbym33g4hsPY"),
+            xpub("tpubDFUyR1hbbP14sGZ2St39RgJ9wUk4enPVYahtA5nPwPPWsGUNVFZt2ujRLShf4JqFXbJQLrgvFTodtXCWEnYQqUnMYzLaAWjuXsZnQTYZS5C"),
+        ];
+        let managers_xpubs = vec![
+            xpub("tpubDCvsodKgyz8apUfySTojc8WU6UPDXmv32Ct9hZqWGE7a6FcWpHoJ3Efa9zUqLWHX6PHthHXQehG1mrFtFVXNMcwt6kGwCuYk6adMDreyVGu"),
+        ];
+        let (deposit_descriptor, unvault_descriptor, cpfp_descriptor) = deployment_descriptors(
+            &stakeholders_xpubs,
+            &managers_xpubs,
+            1,
+            vec![],
+            revaultd.unvault_csv(),
+            &[xpub("tpubDEAoArgp5Xu4jD5KsLuMN88zuh8g3Tzxm3JMG9wNUNGNsAbzBn4bLzHjjQJCtKWy3ZHt5bR7vvBfFpdY59sHaYi9cqXYaJ2sqKnsStzsNLb")],
+        )
         .unwrap();
-        assert!(db_emer_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .is_none());
-        assert!(db_cancel_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .is_none());
-        assert!(db_unvault_emer_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .is_none());
-        assert!(db_unvault_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .is_none());
-        let db_vault = db_vault_by_deposit(&db_path, &db_vault.deposit_outpoint)
-            .unwrap()
-            .unwrap();
-        assert_eq!(db_vault.status, VaultStatus::Unconfirmed);
-        assert!(db_vault.delegated_at.is_none());
-        assert!(db_vault.secured_at.is_none());
-        assert!(db_vault.funded_at.is_none());
+        assert_eq!(db_pending_rotation(&db_path).unwrap(), None);
+        let new_wallet_id = db_initiate_wallet_rotation(
+            &db_path,
+            old_wallet_id,
+            &deposit_descriptor,
+            &unvault_descriptor,
+            &cpfp_descriptor,
+            ScriptVersion::WitnessV0,
+            Some(&managers_xpubs[0]),
+            Some(&stakeholders_xpubs[0]),
+            1_600_000_000,
+        )
+        .unwrap();
+        assert_ne!(new_wallet_id, old_wallet_id);
+        assert_eq!(
+            db_pending_rotation(&db_path).unwrap(),
+            Some(DbWalletRotation {
+                old_wallet_id,
+                new_wallet_id,
+                initiated_at: 1_600_000_000,
+                completed_at: None,
+            })
+        );
+        assert_eq!(db_wallet(&db_path).unwrap().id, old_wallet_id);
+        let new_wallet = db_wallet_by_id(&db_path, new_wallet_id).unwrap().unwrap();
+        assert_eq!(new_wallet.deposit_descriptor, deposit_descriptor);
+        assert_eq!(new_wallet.deposit_derivation_index, DerivationIndex::ZERO);
 
-        // And re-added of course
-        db_confirm_deposit(
+        // Let's have an active vault in the current wallet
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        db_insert_new_unconfirmed_vault(
             &db_path,
+            old_wallet_id,
             &outpoint,
-            blockheight,
-            blocktime,
-            &fresh_unvault_tx,
-            &fresh_cancel_tx,
-            Some(&fresh_emer_tx),
-            Some(&fresh_unemer_tx),
+            &Amount::from_sat(612345),
+            DerivationIndex::new(349874).unwrap(),
         )
         .unwrap();
-        // But not twice! (UNIQUE on the psbt field)
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
         db_confirm_deposit(
             &db_path,
             &outpoint,
-            blockheight,
-            blocktime,
+            9,
+            9,
             &fresh_unvault_tx,
             &fresh_cancel_tx,
-            Some(&fresh_emer_tx),
-            Some(&fresh_unemer_tx),
+            None,
+            None,
+            1_600_000_000,
         )
-        .unwrap_err();
+        .unwrap();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Active).unwrap();
+        let db_unvault = db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap();
+
+        // Its migration is recorded along with the Spend, and the deposit address it pays to
+        // won't be handed out by the new wallet
+        let spend_tx = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAciTbKS43sH49TJWX6xJ+MxqWfNQhRl+vkttRZ9sLUkHAAAAAAClAQAAAoAyAAAAAAAAIgAggxumgjPgMj5oHWn8QkvKqPIN0N5nuAbyQ+FEgOJZpjygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQBAwQBAAAAAQWqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        let spend_txid = spend_tx.txid();
+        db_insert_migration_spend(
+            &db_path,
+            &[db_unvault.clone()],
+            &spend_tx,
+            new_wallet_id,
+            DerivationIndex::ZERO,
+            1_600_000_001,
+        )
+        .unwrap();
+        assert_eq!(
+            db_vault_migration(&db_path, db_vault.id).unwrap(),
+            Some(DbVaultMigration {
+                vault_id: db_vault.id,
+                spend_txid,
+                derivation_index: DerivationIndex::ZERO,
+                created_at: 1_600_000_001,
+            })
+        );
+        assert!(db_spend_transaction(&db_path, &spend_txid)
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            db_wallet_by_id(&db_path, new_wallet_id)
+                .unwrap()
+                .unwrap()
+                .deposit_derivation_index,
+            DerivationIndex::new(1).unwrap()
+        );
+
+        // Deleting the Spend makes the vault available for migration again
+        db_delete_spend(&db_path, &spend_txid).unwrap();
+        assert_eq!(db_vault_migration(&db_path, db_vault.id).unwrap(), None);
+        db_insert_migration_spend(
+            &db_path,
+            &[db_unvault],
+            &spend_tx,
+            new_wallet_id,
+            DerivationIndex::new(1).unwrap(),
+            1_600_000_002,
+        )
+        .unwrap();
+        assert!(db_vault_migration(&db_path, db_vault.id).unwrap().is_some());
+
+        // We can't switch to the new wallet as long as a vault of the current one remains
+        revaultd.deposit_descriptor = deposit_descriptor;
+        revaultd.unvault_descriptor = unvault_descriptor;
+        revaultd.cpfp_descriptor = cpfp_descriptor;
+        check_db(&revaultd).unwrap_err();
+        assert_eq!(db_wallet(&db_path).unwrap().id, old_wallet_id);
+
+        // Once it's moved, starting with the new descriptors completes the rotation
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Spent).unwrap();
+        check_db(&revaultd).unwrap();
+        assert_eq!(db_pending_rotation(&db_path).unwrap(), None);
+        assert_eq!(db_wallet(&db_path).unwrap().id, new_wallet_id);
+        setup_db(&mut revaultd).unwrap();
+        assert_eq!(revaultd.wallet_id, Some(new_wallet_id));
+        assert_eq!(
+            revaultd.deposit_indexes.current(),
+            DerivationIndex::new(2).unwrap()
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    // Whatever the synchronous mode, the commits are applied in order and each is visible
+    // to the next connection.
+    #[test]
+    fn test_db_synchronous() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        revaultd.db_synchronous = DbSynchronous::Off;
+        setup_db(&mut revaultd).unwrap();
+
+        let digest = sha256::Hash::hash(b"synchronous");
+        let handle = std::thread::spawn({
+            let db_path = db_path.clone();
+            move || {
+                for i in 0..10 {
+                    db_append_audit_entry(&db_path, "sync", &digest, "pending", i, None).unwrap();
+                }
+            }
+        });
+        let mut height = 0;
+        for mode in [
+            DbSynchronous::Off,
+            DbSynchronous::Normal,
+            DbSynchronous::Full,
+            DbSynchronous::Extra,
+        ]
+        .iter()
+        {
+            set_db_synchronous(*mode);
+            for _ in 0..5 {
+                height += 1;
+                let tip = BlockchainTip {
+                    height,
+                    hash: BlockHash::from_slice(&[height as u8; 32]).unwrap(),
+                };
+                db_update_tip(&db_path, &tip).unwrap();
+                assert_eq!(db_tip(&db_path).unwrap(), tip);
+                db_append_audit_entry(&db_path, "sync", &digest, "success", height as u64, None)
+                    .unwrap();
+            }
+        }
+        handle.join().unwrap();
+        set_db_synchronous(DbSynchronous::Full);
+
+        // The entries of both writers chain to each other in the order they were committed
+        let entries = db_audit_log(&db_path, 0, u32::MAX).unwrap();
+        assert_eq!(entries.len(), 30);
+        for (i, entry) in entries.iter().enumerate().skip(1) {
+            assert_eq!(entry.id, entries[i - 1].id + 1);
+            assert_eq!(entry.prev_hash, entries[i - 1].hash);
+        }
+        assert_eq!(db_verify_audit_log(&db_path).unwrap(), None);
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    // Once configured, the content we delete is overwritten in the database file.
+    #[test]
+    fn test_db_secure_delete() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        revaultd.db_secure_delete = true;
+        setup_db(&mut revaultd).unwrap();
+        assert!(db_secure_delete());
+
+        db_exec(&db_path, |tx| {
+            let enabled: u32 = tx.query_row("PRAGMA secure_delete", params![], |row| row.get(0))?;
+            assert_eq!(enabled, 1);
+            Ok(())
+        })
+        .unwrap();
 
-        // If we mark the Emergency transaction as fully signed, it'll get returned by the
-        // fetcher.
+        let marker = b"revocation material to wipe".repeat(16);
+        let file_has_marker = || {
+            fs::read(&db_path)
+                .unwrap()
+                .windows(marker.len())
+                .any(|window| window == &marker[..])
+        };
         db_exec(&db_path, |tx| {
             tx.execute(
-                "UPDATE presigned_transactions SET fullysigned = 1 WHERE txid = (?1)",
-                params![fresh_emer_tx.txid().to_vec()],
+                "CREATE TABLE secure_delete_test (data BLOB NOT NULL)",
+                params![],
+            )?;
+            tx.execute(
+                "INSERT INTO secure_delete_test (data) VALUES (?1)",
+                params![marker],
             )?;
             Ok(())
         })
         .unwrap();
-        assert_eq!(db_signed_emer_txs(&db_path).unwrap().len(), 1);
-        assert!(db_signed_unemer_txs(&db_path).unwrap().is_empty());
-        let sig_mis_map = db_sig_missing(&db_path).unwrap();
-        assert_eq!(sig_mis_map.len(), 1);
-        assert_eq!(
-            sig_mis_map
-                .get(sig_mis_map.keys().next().unwrap())
-                .unwrap()
-                .len(),
-            3
+        assert!(file_has_marker());
+        db_exec(&db_path, |tx| {
+            tx.execute("DELETE FROM secure_delete_test", params![])?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(!file_has_marker());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_audit_log() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        // An empty log is a valid one
+        assert_eq!(db_verify_audit_log(&db_path).unwrap(), None);
+
+        let digest = sha256::Hash::hash(
+            b"[\"c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1\"]",
         );
-        // If we mark the UnvaultEmergency transaction as fully signed and the vault as
-        // Unvaulting, it'll get returned by the unemer fetcher instead.
-        db_unvault_deposit(&db_path, &fresh_unvault_tx.txid()).unwrap();
+        for (i, result) in [
+            "pending",
+            "success",
+            "pending",
+            "error: Invalid vault status",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let id = db_append_audit_entry(
+                &db_path,
+                "revault",
+                &digest,
+                result,
+                1_600_000_000 + i as u64,
+                Some(1000),
+            )
+            .unwrap();
+            assert_eq!(id, i as i64 + 1);
+        }
+        assert_eq!(db_verify_audit_log(&db_path).unwrap(), None);
+
+        // Entries chain to each other, and can be queried by date
+        let entries = db_audit_log(&db_path, 1_600_000_001, 1_600_000_002).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].prev_hash, entries[0].hash);
+        assert_eq!(entries[0].result, "success");
+        assert_eq!(entries[0].peer_uid, Some(1000));
+
+        // The log can't be modified through the regular interface
+        db_exec(&db_path, |tx| {
+            tx.execute(
+                "UPDATE audit_log SET result = 'success' WHERE id = 4",
+                params![],
+            )?;
+            Ok(())
+        })
+        .unwrap_err();
+        db_exec(&db_path, |tx| {
+            tx.execute("DELETE FROM audit_log WHERE id = 4", params![])?;
+            Ok(())
+        })
+        .unwrap_err();
+
+        // But someone with access to the file could. Verification would fail at the tampered
+        // entry.
+        db_exec(&db_path, |tx| {
+            tx.execute_batch(
+                "DROP TRIGGER audit_log_no_update; \
+                 UPDATE audit_log SET result = 'error: Invalid vault status' WHERE id = 2;",
+            )?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(db_verify_audit_log(&db_path).unwrap(), Some(2));
+
+        // Even if they recompute its hash, the next entry doesn't commit to it anymore.
+        let tampered = db_audit_log(&db_path, 1_600_000_001, 1_600_000_001)
+            .unwrap()
+            .pop()
+            .unwrap();
         db_exec(&db_path, |tx| {
             tx.execute(
-                "UPDATE presigned_transactions SET fullysigned = 1 WHERE txid = (?1)",
-                params![fresh_unemer_tx.txid().to_vec()],
+                "UPDATE audit_log SET hash = (?1) WHERE id = 2",
+                params![tampered.expected_hash().to_vec()],
             )?;
             Ok(())
         })
         .unwrap();
-        assert!(db_signed_emer_txs(&db_path).unwrap().is_empty());
-        assert_eq!(db_signed_unemer_txs(&db_path).unwrap().len(), 1);
+        assert_eq!(db_verify_audit_log(&db_path).unwrap(), Some(3));
 
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
 
-    // There we trigger a concurrent write access to the database by inserting a deposit and
-    // updating its presigned transaction in two different thread. It should be fine and one of
-    // them just lock thanks to the unlock_notify feature of SQLite https://sqlite.org/unlock_notify.html
     #[test]
-    fn test_db_concurrent_write() {
+    fn test_db_idempotency_keys() {
         let datadir = test_datadir();
         let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
         let db_path = revaultd.db_file();
-        let secp_ctx = secp256k1::Secp256k1::new();
-
         setup_db(&mut revaultd).unwrap();
 
-        // Let's insert a deposit
-        let wallet_id = 1;
-        let outpoint = OutPoint::from_str(
-            "adaa5a4b9fb07c860f8de460727b6bad4b5ab01d2e7f90f6f3f15a0080020168:0",
-        )
-        .unwrap();
-        let amount = Amount::from_sat(123456);
-        let derivation_index = ChildNumber::from(33334);
-        db_insert_new_unconfirmed_vault(&db_path, wallet_id, &outpoint, &amount, derivation_index)
+        let digest = sha256::Hash::hash(b"[]");
+        let other_digest = sha256::Hash::hash(b"[true]");
+
+        // The first one to use a key claims it, it's then in progress until we store a result
+        assert_eq!(
+            db_claim_idempotency_key(&db_path, "a", "emergency", &digest, 1_000, 0).unwrap(),
+            None
+        );
+        let entry = db_claim_idempotency_key(&db_path, "a", "setspendtx", &other_digest, 1_001, 0)
+            .unwrap()
             .unwrap();
-        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(
+            entry,
+            DbIdempotencyKey {
+                key: "a".to_string(),
+                method: "emergency".to_string(),
+                params_digest: digest,
+                result: None,
+                created_at: 1_000,
+            }
+        );
+        db_set_idempotency_result(&db_path, "a", "{}").unwrap();
+        let entry = db_claim_idempotency_key(&db_path, "a", "emergency", &digest, 1_002, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.result, Some("{}".to_string()));
+        // Keys are independent
+        assert_eq!(
+            db_claim_idempotency_key(&db_path, "b", "emergency", &digest, 1_002, 0).unwrap(),
+            None
+        );
 
-        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
-        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAT7KJ+fkvbKBDobFTsm31LqtMUfhTiR5tWA5XJA9oYgOAAAAAAD9////AkANAwAAAAAAIgAgbMJH4U4sOCdd1R9PVUuEbmS4bkbnNNlJaqxZBqXHwCcwdQAAAAAAACIAIM8vNQyMFHWpzTmNSefLOTf0spivub9JuegPqYdx0rLvAAAAAAABASuIlAMAAAAAACIAIONmt9fso2OE03OxwV4EkzSucRgHSh3ylMy/KcBayrRaAQMEAQAAAAEFR1IhAum/3N5NY9BZnqXIJxEBNzNEhHwCOY4WQ5xdZZ9XN4+dIQNwiQrXHbeULZ18BN3FOfnYK48NrsVzMDAXVEiu7HfvylKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQOxOjPIG6CKHguqGMBsMRvG/RIiZzCbu7GDMDGmmvH6FqxRh2R2qRSrdyIjb58/y1mAP+ccckOFvfAe04isa3apFAexihzQF+l8AqKa+Y/5XVddSavViKxsk1KHZ1IhAwOygpbYC9yckzxzYFmjVTs4cZzaRTJ97nCHwbFZ6PCaIQLBejnrZMZEk984LSigxiITRc96BSWvsT2wJVMCkLKSe1KvAlAFsmgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhA7E6M8gboIoeC6oYwGwxG8b9EiJnMJu7sYMwMaaa8foWrFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
+        // A failed command releases its key, but a completed one is kept
+        db_release_idempotency_key(&db_path, "b").unwrap();
+        db_release_idempotency_key(&db_path, "a").unwrap();
+        assert_eq!(
+            db_claim_idempotency_key(&db_path, "b", "revault", &other_digest, 1_003, 0).unwrap(),
+            None
+        );
+        assert!(
+            db_claim_idempotency_key(&db_path, "a", "emergency", &digest, 1_003, 0)
+                .unwrap()
+                .is_some()
+        );
 
-        let blockheight = 2300000;
-        let blocktime = 2300000;
-        db_confirm_deposit(
-            &db_path,
-            &outpoint,
-            blockheight,
-            blocktime,
-            &fresh_unvault_tx,
-            &fresh_cancel_tx,
-            None,
-            None,
-        )
+        // Once expired, a key can be claimed again
+        assert_eq!(
+            db_claim_idempotency_key(&db_path, "a", "revault", &other_digest, 2_000, 1_001)
+                .unwrap(),
+            None
+        );
+        // And the other expired keys are pruned as well
+        db_exec(&db_path, |tx| {
+            let count: u32 =
+                tx.query_row("SELECT COUNT(*) FROM idempotency_keys", params![], |row| {
+                    row.get(0)
+                })?;
+            assert_eq!(count, 2);
+            Ok(())
+        })
         .unwrap();
-
-        let stored_cancel_tx = db_cancel_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .unwrap();
-        let mut cancel_tx = fresh_cancel_tx.clone();
-        revault_tx_add_sig(
-            &mut cancel_tx,
-            0,
-            SigHashType::AllPlusAnyoneCanPay,
-            &secp_ctx,
+        assert_eq!(
+            db_claim_idempotency_key(&db_path, "a", "revault", &other_digest, 2_001, 1_004)
+                .unwrap()
+                .unwrap()
+                .created_at,
+            2_000
         );
-        let handle = std::thread::spawn({
-            let db_path = db_path.clone();
-            let cancel_tx = cancel_tx.clone();
-            let secp = revaultd.secp_ctx.clone();
-            let stored_cancel_tx_b = stored_cancel_tx.clone();
-            move || {
-                for _ in 0..10 {
-                    update_presigned_tx(
-                        &db_path,
-                        &db_vault,
-                        stored_cancel_tx_b.clone(),
-                        &cancel_tx.psbt().inputs[0].partial_sigs,
-                        &secp,
-                    );
-                }
-            }
-        });
-        for _ in 0..10 {
-            update_presigned_tx(
-                &db_path,
-                &db_vault,
-                stored_cancel_tx.clone(),
-                &cancel_tx.psbt().inputs[0].partial_sigs,
-                &revaultd.secp_ctx,
-            );
-        }
-        handle.join().unwrap();
+        db_exec(&db_path, |tx| {
+            let count: u32 =
+                tx.query_row("SELECT COUNT(*) FROM idempotency_keys", params![], |row| {
+                    row.get(0)
+                })?;
+            assert_eq!(count, 1);
+            Ok(())
+        })
+        .unwrap();
+
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
 
     #[test]
-    fn test_db_spend_storage() {
+    fn test_db_vault_status_changes() {
         let datadir = test_datadir();
         let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
         let db_path = revaultd.db_file();
-        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
-        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAfF2iPeJqz13zFlW6eLAM+uDu5IhUqcQxtMWQx7z5Y8lAAAAAAD9////AkANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQwdQAAAAAAACIAIIMbpoIz4DI+aB1p/EJLyqjyDdDeZ7gG8kPhRIDiWaY8AAAAAAABASuIlAMAAAAAACIAIA9CgZ1cg/hn3iy3buDZvU5zUnQ9NzutToR/r42YZyu3AQMEAQAAAAEFR1IhA9P6hV8yf6HkNofzleom06eqkUxZayWHJnOMNlMtqvD3IQJo5Mj6Wf3ktrwEB3IQXFmgApibojplpNykg0hA8XV6SFKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
-        let fullysigned_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAfF2iPeJqz13zFlW6eLAM+uDu5IhUqcQxtMWQx7z5Y8lAAAAAAD9////AkANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQwdQAAAAAAACIAIIMbpoIz4DI+aB1p/EJLyqjyDdDeZ7gG8kPhRIDiWaY8AAAAAAABASuIlAMAAAAAACIAIA9CgZ1cg/hn3iy3buDZvU5zUnQ9NzutToR/r42YZyu3IgICaOTI+ln95La8BAdyEFxZoAKYm6I6ZaTcpINIQPF1ekhHMEQCIGwH+/OfgUAbJwthOxnMAR4zoLf/ispCH50wqin3TERrAiBak0Xw5+dQ3Od68PWZ65UPLQXG070wCX9pfcInGVUiagEiAgPT+oVfMn+h5DaH85XqJtOnqpFMWWslhyZzjDZTLarw90cwRAIgA/69zvbYYHbKpBId51MVBeS0xIMF/DZJJ+9/UytAh/0CIBG6NR6AulGTLlMGP6bYMqMQ9HRKlAVFSvEK8dVQ2FdeAQEDBAEAAAABBUdSIQPT+oVfMn+h5DaH85XqJtOnqpFMWWslhyZzjDZTLarw9yECaOTI+ln95La8BAdyEFxZoAKYm6I6ZaTcpINIQPF1ekhSriIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBqiEDH7uO3i4mHhzemNwtVZNHJIJlonzMuSFIWjx2zRC1fd2sUYdkdqkU7YhsQQ+SqzEEFOlBsds7CjDH+pyIrGt2qRQgyrXvSLg3hdA+BgPyUVDV+MYLfoisbJNSh2dSIQM0zz54678zxZovq2jUerGPFk7dSjbrFcKfNrlnm81g8CECpdtZmV+1gEIUb3YYcKlALkHyHpoPc5EwgsjEPkPAlRVSrwKlAbJoIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQElIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRhyICAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAA==").unwrap();
-
         setup_db(&mut revaultd).unwrap();
 
-        // Let's insert a deposit
-        let wallet_id = 1;
         let outpoint = OutPoint::from_str(
             "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
         )
         .unwrap();
-        let amount = Amount::from_sat(612345);
-        let derivation_index = ChildNumber::from(349874);
-        db_insert_new_unconfirmed_vault(&db_path, wallet_id, &outpoint, &amount, derivation_index)
-            .unwrap();
-        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
-
-        // Have the Unvault tx fully signed
-        db_confirm_deposit(
+        db_insert_new_unconfirmed_vault(
             &db_path,
+            1,
             &outpoint,
-            9,
-            9,
-            &fresh_unvault_tx,
-            &fresh_cancel_tx,
-            None,
-            None,
+            &Amount::from_sat(612345),
+            DerivationIndex::new(349874).unwrap(),
         )
         .unwrap();
-        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_unvault_tx,
-            &fullysigned_unvault_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+
+        // Inserting a vault isn't a transition
+        assert!(db_vault_status_changes(&db_path).unwrap().is_empty());
+
+        // Each status update is recorded, but not an update to the same status
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Funded).unwrap();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Funded).unwrap();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Unvaulting).unwrap();
+        let changes = db_vault_status_changes(&db_path).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].vault_id, db_vault.id);
+        assert_eq!(changes[0].deposit_outpoint, outpoint);
+        assert_eq!(changes[0].old_status, VaultStatus::Unconfirmed);
+        assert_eq!(changes[0].new_status, VaultStatus::Funded);
+        assert_eq!(changes[1].old_status, VaultStatus::Funded);
+        assert_eq!(changes[1].new_status, VaultStatus::Unvaulting);
+
+        // Once processed they are removed, but not the ones recorded in the meantime
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Unvaulted).unwrap();
+        db_remove_vault_status_changes(&db_path, changes[1].id).unwrap();
+        let changes = db_vault_status_changes(&db_path).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].old_status, VaultStatus::Unvaulting);
+        assert_eq!(changes[0].new_status, VaultStatus::Unvaulted);
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_spend_proposals() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        let outpoints = vec![
+            OutPoint::from_str(
+                "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+            )
+            .unwrap(),
+            OutPoint::from_str(
+                "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+            )
+            .unwrap(),
+        ];
+        let mut destinations = BTreeMap::new();
+        destinations.insert(
+            Address::from_str("bcrt1qewc2348370pgw8kjz8gy09z8xyh0d9fxde6nzamd3txc9gkmjqmq8m4cdq")
+                .unwrap(),
+            Amount::from_sat(1_000_000),
+        );
+        destinations.insert(
+            Address::from_str("bcrt1qqqqsyqcyq5rqwzqfpg9scrgwpugpzysnard0ew").unwrap(),
+            Amount::from_sat(42_000),
         );
-        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Active).unwrap();
 
-        // We can store a Spend tx spending a single unvault and query it
-        let spend_tx = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAciTbKS43sH49TJWX6xJ+MxqWfNQhRl+vkttRZ9sLUkHAAAAAAClAQAAAoAyAAAAAAAAIgAggxumgjPgMj5oHWn8QkvKqPIN0N5nuAbyQ+FEgOJZpjygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQBAwQBAAAAAQWqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
-        let spend_tx_inputs = &spend_tx.tx().input;
-        assert_eq!(spend_tx_inputs.len(), 1);
-        let (_, db_unvault) =
-            db_vault_by_unvault_txid(&db_path, &spend_tx_inputs[0].previous_output.txid)
-                .unwrap()
+        assert!(db_spend_proposals(&db_path).unwrap().is_empty());
+        assert!(db_spend_proposal(&db_path, 1).unwrap().is_none());
+        let id = db_insert_spend_proposal(&db_path, &outpoints, &destinations, 12, 1_000, 4_600)
+            .unwrap();
+        let other_id =
+            db_insert_spend_proposal(&db_path, &outpoints[..1], &destinations, 3, 1_001, 4_601)
                 .unwrap();
-        db_insert_spend(&db_path, &[db_unvault.clone()], &spend_tx).unwrap();
-        let spend_txid = spend_tx.txid();
+        let proposal = db_spend_proposal(&db_path, id).unwrap().unwrap();
         assert_eq!(
-            db_list_spends(&db_path).unwrap().get(&spend_txid),
-            Some(&(
-                DbSpendTransaction {
-                    id: 1,
-                    psbt: spend_tx.clone(),
-                    broadcasted: None,
-                    has_priority: false,
-                },
-                vec![outpoint]
-            ))
+            proposal,
+            DbSpendProposal {
+                id,
+                outpoints: outpoints.clone(),
+                destinations: destinations.clone(),
+                feerate: 12,
+                created_at: 1_000,
+                expires_at: 4_600,
+            }
         );
-
-        // We can update it, eg with a Spend with more sigs
-        let spend_tx = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAciTbKS43sH49TJWX6xJ+MxqWfNQhRl+vkttRZ9sLUkHAAAAAAClAQAAAoAyAAAAAAAAIgAggxumgjPgMj5oHWn8QkvKqPIN0N5nuAbyQ+FEgOJZpjygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQBAwQBAAAAAQWqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAx+7jt4uJh4c3pjcLVWTRySCZaJ8zLkhSFo8ds0QtX3drFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
-        db_update_spend(&db_path, &spend_tx, true).unwrap();
-        let spend_txid = spend_tx.txid();
+        let proposals = db_spend_proposals(&db_path).unwrap();
+        assert_eq!(proposals.len(), 2);
+        assert_eq!(proposals[0], proposal);
+        assert_eq!(proposals[1].id, other_id);
+        assert_eq!(proposals[1].outpoints, outpoints[..1].to_vec());
+
+        // Approving and rejecting don't commit to the same thing
+        assert_ne!(proposal.ack_digest(true), proposal.ack_digest(false));
+        assert_ne!(proposal.ack_digest(true), proposals[1].ack_digest(true));
+
+        // A manager may only decide once on a given proposal
+        let (fg_a, fg_b) = (
+            Fingerprint::from(&[1, 2, 3, 4][..]),
+            Fingerprint::from(&[5, 6, 7, 8][..]),
+        );
+        assert!(db_insert_spend_proposal_ack(&db_path, id, &fg_a, true, &[0xaa], 1_002).unwrap());
+        assert!(!db_insert_spend_proposal_ack(&db_path, id, &fg_a, false, &[0xbb], 1_003).unwrap());
+        assert!(
+            db_insert_spend_proposal_ack(&db_path, other_id, &fg_a, false, &[0xcc], 1_003).unwrap()
+        );
+        assert!(db_insert_spend_proposal_ack(&db_path, id, &fg_b, false, &[0xdd], 1_004).unwrap());
+        let acks = db_spend_proposal_acks(&db_path, id).unwrap();
         assert_eq!(
-            db_list_spends(&db_path).unwrap().get(&spend_txid),
-            Some(&(
-                DbSpendTransaction {
-                    id: 1,
-                    psbt: spend_tx.clone(),
-                    broadcasted: None,
-                    has_priority: true,
-                },
-                vec![outpoint]
-            ))
+            acks.iter()
+                .map(|ack| (
+                    ack.fingerprint,
+                    ack.approved,
+                    ack.signature.clone(),
+                    ack.created_at
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (fg_a, true, vec![0xaa], 1_002),
+                (fg_b, false, vec![0xdd], 1_004)
+            ]
         );
-        // Not in the CPFPable as it's not broadcasted
-        assert!(!db_cpfpable_spends(&db_path).unwrap().contains(&spend_tx));
+        assert_eq!(db_spend_proposal_acks(&db_path, other_id).unwrap().len(), 1);
 
-        // And delete it
-        db_delete_spend(&db_path, &spend_tx.txid()).unwrap();
-        assert!(db_list_spends(&db_path).unwrap().get(&spend_txid).is_none());
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
 
-        // And this works with multiple unvaults too
+    #[test]
+    fn test_db_spend_destinations() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
 
-        // Re-insert the previous one so we have many references to the first Unvault
-        db_insert_spend(&db_path, &[db_unvault.clone()], &spend_tx).unwrap();
-        db_update_spend(&db_path, &spend_tx, true).unwrap();
+        let script_a = Address::from_str("bcrt1qqqqsyqcyq5rqwzqfpg9scrgwpugpzysnard0ew")
+            .unwrap()
+            .script_pubkey();
+        let script_b =
+            Address::from_str("bcrt1qewc2348370pgw8kjz8gy09z8xyh0d9fxde6nzamd3txc9gkmjqmq8m4cdq")
+                .unwrap()
+                .script_pubkey();
+        let first_txid =
+            Txid::from_str("4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040")
+                .unwrap();
+        let second_txid =
+            Txid::from_str("c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7")
+                .unwrap();
 
-        // Same as above with a new vault
-        let cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAAc+BIbsSvYK/BWRNOAjazIlLfjlVzCCtXvoyN5/bydgEAAAAAAD9////AdLKAgAAAAAAIgAgFy2HNuxbT516bQQBY3R04IkEja348wJveLmF73Tj/owAAAAAAAEBK0ANAwAAAAAAIgAgZw+cwq8wJzworIDuy6s8cpOo3uF8fYyL5pECqg0UVagBAwSBAAAAAQWrIQLDtCYN0BlQw/h5zAcF0yXft2G7vAjkRsD9B9uoiyr1x6xRh2R2qRTGFACwvLOTrJHUPKb3ifnio7mt0Yisa3apFOZTIiKdGP+9rilwd09H1kOsfB/PiKxsk1KHZ1IhAtGKwcs21FeGy2qY+fzQ9uvI4X5ThtCqkwHsGtKQx0jYIQP93zm1sGAtxTNxsYQTkoXt26FoyKWNh1sx6hmk1yVzYlKvA8aOALJoIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQFHUiEDwco8fBTB0TgIyRuiySVtsjqY8/PNWc6ibssXFBu8J9ghAnfnwLjDsJCtzPdRRymjx6qxGfklMzRjSB7kXPcIClLuUq4iAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
-        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAe7jtZYQ3avFhc+JxU4paq8e26NIkAB1zHLgv6mKWxgBAAAAAAD9////AkANAwAAAAAAIgAgdS3fC7QX+PKWZBful8J229uixPOW012CYpKMH7rU8T4wdQAAAAAAACIAIAGCzzZ7K80GkoO2mUdCVIFx7Tum52UXob8ascs1uZucAAAAAAABASuIlAMAAAAAACIAIFvpTQQruW8AB+k+csGMaThNLBAzppkxo+k4Hb2SZ4hKAQMEAQAAAAEFR1IhAvkWJfB/ssW9YaE7llH/y/1FBJ/LK+ybOJiT8j+O4cnhIQIS4abTQKWATfsTrVsEPfkCUHvxY4M0F+ZDz502NXMy1FKuIgYCEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQGqIQLbrZUUxHTNBLySX7XjBBa5auxTfjuUSHxE3vJ1JXfdlaxRh2R2qRS8iuykW8lDZdRWXgK4UY0gLAkjX4isa3apFJ+lQ0Ybj4Eig7391TEtxikgP4DDiKxsk1KHZ1IhAqTEmYMxdjLU50wj/Hw9X2Pf7WRDSCnO4P4qpJ5h+PNOIQO2p8sldVsHhhEimy+ZW0E1L3vX5d9mqQ0d01XVdx3DWVKvAocbsmgiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAtutlRTEdM0EvJJfteMEFrlq7FN+O5RIfETe8nUld92VrFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
-        let fullysigned_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAe7jtZYQ3avFhc+JxU4paq8e26NIkAB1zHLgv6mKWxgBAAAAAAD9////AkANAwAAAAAAIgAgdS3fC7QX+PKWZBful8J229uixPOW012CYpKMH7rU8T4wdQAAAAAAACIAIAGCzzZ7K80GkoO2mUdCVIFx7Tum52UXob8ascs1uZucAAAAAAABASuIlAMAAAAAACIAIFvpTQQruW8AB+k+csGMaThNLBAzppkxo+k4Hb2SZ4hKIgICEuGm00ClgE37E61bBD35AlB78WODNBfmQ8+dNjVzMtRHMEQCID2my9yVWxgLSDKDBL5PmF9FVZC6b8mLu598Rq8oebjQAiB3FC3br7rS6bkKOKa4h9Ml1nicuPWpXTWjAWrALVTd+gEiAgL5FiXwf7LFvWGhO5ZR/8v9RQSfyyvsmziYk/I/juHJ4UcwRAIgGfJBreyXt5Isv9PjLRJCFy5jVrMGieGsvV01LTPf3/gCICS81/Mvot0WYdlXC+FnAQ4AprXIQH+g1pnDomBGO+UZAQEDBAEAAAABBUdSIQL5FiXwf7LFvWGhO5ZR/8v9RQSfyyvsmziYk/I/juHJ4SECEuGm00ClgE37E61bBD35AlB78WODNBfmQ8+dNjVzMtRSriIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBqiEC262VFMR0zQS8kl+14wQWuWrsU347lEh8RN7ydSV33ZWsUYdkdqkUvIrspFvJQ2XUVl4CuFGNICwJI1+IrGt2qRSfpUNGG4+BIoO9/dUxLcYpID+Aw4isbJNSh2dSIQKkxJmDMXYy1OdMI/x8PV9j3+1kQ0gpzuD+KqSeYfjzTiEDtqfLJXVbB4YRIpsvmVtBNS971+XfZqkNHdNV1Xcdw1lSrwKHG7JoIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAAQElIQLbrZUUxHTNBLySX7XjBBa5auxTfjuUSHxE3vJ1JXfdlaxRhyICAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAA==").unwrap();
-        let wallet_id = 1;
-        let outpoint_b = OutPoint::from_str(
-            "2117d7c3461ca013a099d8e60b0bcc6c33aec95db49f636c479ab85117479a91:0",
-        )
-        .unwrap();
-        let amount = Amount::from_sat(112245);
-        let derivation_index = ChildNumber::from(643874);
-        db_insert_new_unconfirmed_vault(
-            &db_path,
-            wallet_id,
-            &outpoint_b,
-            &amount,
-            derivation_index,
-        )
-        .unwrap();
-        let db_vault = db_vault_by_deposit(&db_path, &outpoint_b).unwrap().unwrap();
-        db_confirm_deposit(
+        assert!(db_spend_destination(&db_path, &script_a).unwrap().is_none());
+        db_insert_spend_destinations(&db_path, &[script_a.clone()], &first_txid, 1_000).unwrap();
+        // Only the first Spend to pay to a script is kept
+        db_insert_spend_destinations(
             &db_path,
-            &outpoint_b,
-            9,
-            9,
-            &fresh_unvault_tx,
-            &cancel_tx,
-            None,
-            None,
+            &[script_a.clone(), script_b.clone()],
+            &second_txid,
+            2_000,
         )
         .unwrap();
-        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_unvault_tx,
-            &fullysigned_unvault_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+        assert_eq!(
+            db_spend_destination(&db_path, &script_a).unwrap(),
+            Some(DbSpendDestination {
+                script_pubkey: script_a,
+                spend_txid: first_txid,
+                used_at: 1_000,
+            })
+        );
+        assert_eq!(
+            db_spend_destination(&db_path, &script_b).unwrap(),
+            Some(DbSpendDestination {
+                script_pubkey: script_b,
+                spend_txid: second_txid,
+                used_at: 2_000,
+            })
         );
-        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Active).unwrap();
 
-        let spend_tx_b = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAXHqOcTAJnPyXEF1cxFATe4S6yHLGZm+s0aj9mUTtKgVAAAAAACHGwAAAoAyAAAAAAAAIgAgAYLPNnsrzQaSg7aZR0JUgXHtO6bnZRehvxqxyzW5m5ygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgdS3fC7QX+PKWZBful8J229uixPOW012CYpKMH7rU8T4BAwQBAAAAAQWqIQLbrZUUxHTNBLySX7XjBBa5auxTfjuUSHxE3vJ1JXfdlaxRh2R2qRS8iuykW8lDZdRWXgK4UY0gLAkjX4isa3apFJ+lQ0Ybj4Eig7391TEtxikgP4DDiKxsk1KHZ1IhAqTEmYMxdjLU50wj/Hw9X2Pf7WRDSCnO4P4qpJ5h+PNOIQO2p8sldVsHhhEimy+ZW0E1L3vX5d9mqQ0d01XVdx3DWVKvAocbsmgiBgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAABASUhAtutlRTEdM0EvJJfteMEFrlq7FN+O5RIfETe8nUld92VrFGHIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAAIgICEnb0lxeNI9466wDX+tqlq23zYNacTlLVLLLxtcVobG0INaO2mQoAAAAA").unwrap();
-        let spend_tx_b_inputs = &spend_tx_b.tx().input;
-        assert_eq!(spend_tx_b_inputs.len(), 1);
-        let (_, db_unvault_b) =
-            db_vault_by_unvault_txid(&db_path, &spend_tx_b_inputs[0].previous_output.txid)
-                .unwrap()
-                .unwrap();
-        db_insert_spend(&db_path, &[db_unvault, db_unvault_b], &spend_tx_b).unwrap();
-        let spend_txid = spend_tx.txid();
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_db_emergency_descriptors() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        assert!(db_last_emergency_descriptor(&db_path).unwrap().is_none());
+        let ranged = "wsh(multi(2,xpub6EKrK11LwLcNyJ4arJnCtxPGAuxSYPX35fMfJcmadvTSue6YZn2W9kEUHy7PFyQsy7zkrbmhxtevsgwsfyCiRBayJdWSTohRQua43jMw9FQ/*,xpub6BhQvtXJmw6hksh9rRRfdLjaWjQiNMZWtkM5ebn8QkAgh5na2Un6mCDABwkUmHhPCMYtsM9zHY5jxbQ86ayvjfY8XtavbovB6NcNy8KyQLa/*))";
+        db_insert_emergency_descriptor(&db_path, ranged, Some(12), 1_000).unwrap();
         assert_eq!(
-            db_list_spends(&db_path).unwrap().get(&spend_txid),
-            Some(&(
-                DbSpendTransaction {
-                    id: 1,
-                    psbt: spend_tx.clone(),
-                    broadcasted: None,
-                    has_priority: true,
-                },
-                vec![outpoint]
-            ))
+            db_last_emergency_descriptor(&db_path).unwrap(),
+            Some(DbEmergencyDescriptor {
+                id: 1,
+                descriptor: ranged.to_string(),
+                derivation_index: Some(12),
+                stored_at: 1_000,
+            })
         );
-        let spend_txid_b = spend_tx_b.txid();
+
+        // The last one stored is the one we re-verify
+        let single = "wsh(multi(2,0332e5c86d0938a83ed80d13c5644ec92fd16b9d7184bb35d6ead8227b4ad47803,02807e5c4f7b228aa9f1aef77effde768356480f5268376644464e714d0205eb1c))";
+        db_insert_emergency_descriptor(&db_path, single, None, 2_000).unwrap();
         assert_eq!(
-            db_list_spends(&db_path).unwrap().get(&spend_txid_b),
-            Some(&(
-                DbSpendTransaction {
-                    id: 2,
-                    psbt: spend_tx_b.clone(),
-                    broadcasted: None,
-                    has_priority: false,
-                },
-                vec![outpoint, outpoint_b]
-            ))
+            db_last_emergency_descriptor(&db_path).unwrap(),
+            Some(DbEmergencyDescriptor {
+                id: 2,
+                descriptor: single.to_string(),
+                derivation_index: None,
+                stored_at: 2_000,
+            })
         );
 
-        let spend_tx_b = SpendTransaction::from_psbt_str("cHNidP8BAGcCAAAAAXHqOcTAJnPyXEF1cxFATe4S6yHLGZm+s0aj9mUTtKgVAAAAAACHGwAAAoAyAAAAAAAAIgAgAYLPNnsrzQaSg7aZR0JUgXHtO6bnZRehvxqxyzW5m5ygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgdS3fC7QX+PKWZBful8J229uixPOW012CYpKMH7rU8T4iAgKkxJmDMXYy1OdMI/x8PV9j3+1kQ0gpzuD+KqSeYfjzTkgwRQIhAMwdbbLXqH49pRfZR6PtSzNg/MB+DuVo1xs7rPTZQ12RAiBDSHEGyQaE1K+wknL2IFnhWXKn+/YSfSMtMg9u4zepNwEiAgLbrZUUxHTNBLySX7XjBBa5auxTfjuUSHxE3vJ1JXfdlUgwRQIhAM0sVhdfZtv9Uofdo2oSfwOqTL5gmveVElUl8zxDgZ9wAiB82EHY4wySIWec9cZHbmhLY72LSwyGrqDANNMTun6JNwEiAgO2p8sldVsHhhEimy+ZW0E1L3vX5d9mqQ0d01XVdx3DWUcwRAIgRhhHxuXx5X2eniy4tMP4wP2xoBD+XZlxMQiF9HoXIDYCIEfKdXOOILXSFKeOZ2v6nomllEQOyjuBUk+0LhK7+55mAQEDBAEAAAABBaohAtutlRTEdM0EvJJfteMEFrlq7FN+O5RIfETe8nUld92VrFGHZHapFLyK7KRbyUNl1FZeArhRjSAsCSNfiKxrdqkUn6VDRhuPgSKDvf3VMS3GKSA/gMOIrGyTUodnUiECpMSZgzF2MtTnTCP8fD1fY9/tZENIKc7g/iqknmH4804hA7anyyV1WweGESKbL5lbQTUve9fl32apDR3TVdV3HcNZUq8ChxuyaCIGAhJ29JcXjSPeOusA1/rapatt82DWnE5S1Syy8bXFaGxtCDWjtpkKAAAAAAEBJSEC262VFMR0zQS8kl+14wQWuWrsU347lEh8RN7ydSV33ZWsUYciAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAAiAgISdvSXF40j3jrrANf62qWrbfNg1pxOUtUssvG1xWhsbQg1o7aZCgAAAAA=").unwrap();
-        db_update_spend(&db_path, &spend_tx_b, false).unwrap();
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
 
-        // There are 2 Unvaults referenced by this Spend
-        let db_spend = db_spend_transaction(&db_path, &spend_tx_b.txid())
-            .unwrap()
-            .unwrap();
-        let conn = rusqlite::Connection::open(&db_path).unwrap();
-        let unvault_psbts = conn
-            .prepare(
-                "SELECT ptx.psbt FROM presigned_transactions as ptx \
-             INNER JOIN spend_inputs as sin ON ptx.id = sin.unvault_id \
-             INNER JOIN spend_transactions as stx ON stx.id = sin.spend_id \
-             WHERE stx.id = (?1)",
+    #[test]
+    fn test_db_vault_origins() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+
+        let mut vault_ids = Vec::new();
+        for (i, txid) in [
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7",
+            "a9735f42110ce529386f612194a1e137a2a2679ac0e789ad7f470cd70c3c2c24",
+            "6e4977728e7db0fce3040d5a55b8b1e5cb29a1f6ac4d8bb4e6bcbff4ad3d9b3b",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let outpoint = OutPoint::from_str(&format!("{}:0", txid)).unwrap();
+            db_insert_new_unconfirmed_vault(
+                &db_path,
+                1,
+                &outpoint,
+                &Amount::from_sat(612345),
+                DerivationIndex::new(i as u32).unwrap(),
             )
-            .unwrap()
-            .query_map(rusqlite::params![db_spend.id], |row| {
-                row.get::<_, Vec<u8>>(0)
-            })
-            .unwrap()
-            .collect::<rusqlite::Result<Vec<Vec<u8>>>>()
             .unwrap();
-        assert_eq!(unvault_psbts.len(), 2);
-        for psbt in unvault_psbts {
-            UnvaultTransaction::from_psbt_serialized(&psbt).unwrap();
+            vault_ids.push(
+                db_vault_by_deposit(&db_path, &outpoint)
+                    .unwrap()
+                    .unwrap()
+                    .id,
+            );
         }
 
-        // Thus there are 2 vaults too
-        let spent_outpoints: Vec<OutPoint> = db_vaults_from_spend(&db_path, &spend_tx_b.txid())
-            .unwrap()
-            .into_iter()
-            .map(|(_, db_vault)| db_vault.deposit_outpoint)
-            .collect();
-        assert_eq!(spent_outpoints.len(), 2);
-        assert!(spent_outpoints.contains(&outpoint));
-        assert!(spent_outpoints.contains(&outpoint_b));
-
-        let spend_txid = spend_tx.txid();
-        assert!(db_spend_transaction(&db_path, &spend_txid)
-            .unwrap()
-            .unwrap()
-            .broadcasted
-            .is_none());
-        assert_eq!(
-            db_broadcastable_spend_transactions(&db_path).unwrap().len(),
-            0
-        );
-        db_mark_broadcastable_spend(&db_path, &spend_txid).unwrap();
+        // Not recorded means external
         assert_eq!(
-            db_broadcastable_spend_transactions(&db_path).unwrap().len(),
-            1
+            db_vault_origin(&db_path, vault_ids[0]).unwrap(),
+            DepositOrigin::External
         );
-        // Since the spend is broadcastable, its unvaults are cpfpable
-        assert_eq!(db_cpfpable_spends(&db_path).unwrap().len(), 0);
-        assert_eq!(db_cpfpable_unvaults(&db_path).unwrap().len(), 1);
-        assert!(!db_spend_transaction(&db_path, &spend_txid)
-            .unwrap()
-            .unwrap()
-            .broadcasted
-            .unwrap(),);
-        db_mark_broadcasted_spend(&db_path, &spend_txid).unwrap();
+        assert!(db_vault_origins(&db_path).unwrap().is_empty());
+
+        db_set_vault_origin(&db_path, vault_ids[0], DepositOrigin::External, &[]).unwrap();
+        db_set_vault_origin(&db_path, vault_ids[1], DepositOrigin::Cancel, &[]).unwrap();
+        db_set_vault_origin(
+            &db_path,
+            vault_ids[2],
+            DepositOrigin::SpendChange,
+            &[vault_ids[0], vault_ids[1]],
+        )
+        .unwrap();
+        // The first recorded origin is kept
+        db_set_vault_origin(&db_path, vault_ids[1], DepositOrigin::External, &[]).unwrap();
+
+        let origins = db_vault_origins(&db_path).unwrap();
+        assert_eq!(origins.len(), 3);
+        assert_eq!(origins[&vault_ids[0]], DepositOrigin::External);
+        assert_eq!(origins[&vault_ids[1]], DepositOrigin::Cancel);
+        assert_eq!(origins[&vault_ids[2]], DepositOrigin::SpendChange);
         assert_eq!(
-            db_broadcastable_spend_transactions(&db_path).unwrap().len(),
-            0
+            db_vault_change_sources(&db_path, vault_ids[2])
+                .unwrap()
+                .into_iter()
+                .map(|vault| vault.id)
+                .collect::<Vec<_>>(),
+            vec![vault_ids[0], vault_ids[1]]
         );
-        assert!(db_spend_transaction(&db_path, &spend_txid)
-            .unwrap()
-            .unwrap()
-            .broadcasted
-            .unwrap());
-        // Now the spend is cpfpable as it's broadcasted
-        assert_eq!(db_cpfpable_spends(&db_path).unwrap().len(), 1);
-        assert_eq!(db_cpfpable_unvaults(&db_path).unwrap().len(), 0);
-
-        // And we can delete the transaction
-        db_delete_spend(&db_path, &spend_txid).unwrap();
-        assert!(db_spend_transaction(&db_path, &spend_txid)
+        assert!(db_vault_change_sources(&db_path, vault_ids[1])
             .unwrap()
-            .is_none());
+            .is_empty());
 
-        // And if we unconfirm the vault, it'll delete the last remaining transaction
-        let txid_b = spend_tx_b.txid();
-        db_exec(&db_path, |db_tx| {
-            db_unconfirm_deposit_dbtx(&db_tx, db_vault.id).unwrap();
-            Ok(())
-        })
-        .unwrap();
-        assert!(db_spend_transaction(&db_path, &txid_b).unwrap().is_none());
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
 
     #[test]
-    fn test_db_update_vault_status() {
+    fn test_db_deposit_abandonments() {
         let datadir = test_datadir();
-        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
         let db_path = revaultd.db_file();
-
         setup_db(&mut revaultd).unwrap();
 
-        let fresh_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////ARQJVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UBAwSBAAAAAQVhIQPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjqxRh2R2qRTSH6G2Ru92gsQ8Zo4dNgTsvMy2L4isa3apFLP0U8urvhbV0H973pOSBuRg+k7xiKxsk1KHZ1iyaCIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAiBgPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjgglHWAJAQAAAAAiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAAA==").unwrap();
-        let fresh_emergency_tx = EmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ATgcVQEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBKwDMVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsBAwSBAAAAAQVHUiEDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYhA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgUq4iBgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACIGA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAAAA=").unwrap();
-        let fresh_unvaultemergency_tx = UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////AWZ5VAEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UBAwSBAAAAAQVhIQPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjqxRh2R2qRTSH6G2Ru92gsQ8Zo4dNgTsvMy2L4isa3apFLP0U8urvhbV0H973pOSBuRg+k7xiKxsk1KHZ1iyaCIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAiBgPAKvZof/JMq6C/mAv3iRqN76eVO6RzNYLzz9XqXigOjgglHWAJAQAAAAAA").unwrap();
-        let fresh_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ArhEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UwdQAAAAAAACIAILzK9vum6/lhgKe5jxw305+0hoD0nTIyaO2YhNSGPZYbAAAAAAABASsAzFUBAAAAACIAIJBJZLPtAXAEcDz1wPd55+4BNNyZu/t6G6qiizOcGuzrAQMEAQAAAAEFR1IhA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGIQO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYFKuIgYDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiBgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAAAAiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxgjWfX/pAQAAACICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgCHKpXyIBAAAAIgIDwCr2aH/yTKugv5gL94kaje+nlTukczWC88/V6l4oDo4IJR1gCQEAAAAAIgICpNYvWPZyxsUYf7xyXokNYDytbr1bx10GK6jRxJ19+r4I+93szQEAAAAA").unwrap();
-
-        let fullysigned_cancel_tx = CancelTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////ARQJVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxkcwRAIgB/3p+T4lseNEnwmN7iohmyMUiIUsDDnGU58iftbc6lcCIDTeEOYzwqyCkU65rRqQ45q6DHsL/M8SxvTS87vaaMQhgSICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgSDBFAiEA039gXXzN9fjgENN6EOP8LE9HwDxHcJSVRpCCw5DJUKoCIDdwWFSHtJR/3gnubCtUNPF2dJzinCsVFjW2MfQfjYDGgQEDBIEAAAABBWEhA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OrFGHZHapFNIfobZG73aCxDxmjh02BOy8zLYviKxrdqkUs/RTy6u+FtXQf3vek5IG5GD6TvGIrGyTUodnWLJoIgYDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiBgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAACIGA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OCCUdYAkBAAAAACICA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgIDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAA").unwrap();
-        let fullysigned_emergency_tx = EmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ATgcVQEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBKwDMVQEAAAAAIgAgkElks+0BcARwPPXA93nn7gE03Jm7+3obqqKLM5wa7OsiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxkcwRAIgKDAJyH5ixlG6HgsUtvWgYbpRv6vuthbwjaIc6nxa220CIDfUJJe5RgnmPgWXnQdjiMp/nLNETh1fbi2KV3u6YxRbgSICA75bJ5L6gzXPpkmirlSEkBrvGGj8b235SiTd3nuDSYdgSDBFAiEAyFE0qIblxbDV3ocAUcbrLEvIMXi/c5H2Z+PbkGA43xUCIDagAFsbwHijNkp7QFMkr2M7YVhXODcJ0JO6mzznRcWegQEDBIEAAAABBUdSIQO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxiEDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2BSriIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAAAA==").unwrap();
-        let fullysigned_unvaultemergency_tx = UnvaultEmergencyTransaction::from_psbt_str("cHNidP8BAF4CAAAAAfihVFC0qjTyRXe/NFNqD5H41QqyRbKs6hABmmmmPYFcAAAAAAD9////AWZ5VAEAAAAAIgAgy7Co1PHzwoce0hHQR5RHMS72lSZudTF3bYrNgqLbkDYAAAAAAAEBK7hEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UiAgO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxkgwRQIhAKpNCciNFBhFAnGRgOwnSQWmXXd+MGECPxqPyDU795EzAiBkLV2iCdA5S0ggYIYiANaXsRrZCxHRiRdBGQaDgWyviYEiAgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYEcwRAIgYvx1ukm7/2LGEgUb2JrZouzbNi9Otlqdf9FhKbDaZiICIDtx72rpajYo/xtLQGXUrFmoogOOasxEZztmNZ0x0Gk3gQEDBIEAAAABBWEhA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OrFGHZHapFNIfobZG73aCxDxmjh02BOy8zLYviKxrdqkUs/RTy6u+FtXQf3vek5IG5GD6TvGIrGyTUodnWLJoIgYDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiBgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAACIGA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OCCUdYAkBAAAAAAA=").unwrap();
-        let fullysigned_unvault_tx = UnvaultTransaction::from_psbt_str("cHNidP8BAIkCAAAAAXsLEnDEk/kajuPbB1tQ4i6kfExo7HA6I3xHgmJWRSLaAAAAAAD9////ArhEVQEAAAAAIgAgbsu/Z4HxJp0NLrRFQTCKGQckU0lArG3qqpSIVinrf8UwdQAAAAAAACIAILzK9vum6/lhgKe5jxw305+0hoD0nTIyaO2YhNSGPZYbAAAAAAABASsAzFUBAAAAACIAIJBJZLPtAXAEcDz1wPd55+4BNNyZu/t6G6qiizOcGuzrIgIDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsZHMEQCICc3av7U4xVy0x35E2BdzIDjR1+F/0NVdCwnkcmNGgNCAiB5pHElbUup/2JRAopn/gQuLGt+uCHhFQy01IOrsje3ZwEiAgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYEcwRAIgBj4yp1mne9ibY3k6pdpIrdbdAG+MZuOuBdV9Nanzl7cCIFtfEBSqRcruzwGPK0KniC7buW4ow9o6+ELAeH83ZuQDAQEDBAEAAAABBUdSIQO4ER9KODPckKKoradfHuRgDi0TBYxiGq5s64vB3pSqxiEDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2BSriIGA7gRH0o4M9yQoqitp18e5GAOLRMFjGIarmzri8HelKrGCNZ9f+kBAAAAIgYDvlsnkvqDNc+mSaKuVISQGu8YaPxvbflKJN3ee4NJh2AIcqlfIgEAAAAAIgIDuBEfSjgz3JCiqK2nXx7kYA4tEwWMYhqubOuLwd6UqsYI1n1/6QEAAAAiAgO+WyeS+oM1z6ZJoq5UhJAa7xho/G9t+Uok3d57g0mHYAhyqV8iAQAAACICA8Aq9mh/8kyroL+YC/eJGo3vp5U7pHM1gvPP1epeKA6OCCUdYAkBAAAAACICAqTWL1j2csbFGH+8cl6JDWA8rW69W8ddBiuo0cSdffq+CPvd7M0BAAAAAA==").unwrap();
-
-        let wallet_id = 1;
-        let outpoint_b = OutPoint::from_str(
-            "da2245566282477c233a70ec684c7ca42ee2505b07dbe38e1af993c470120b7b:0",
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:0",
         )
         .unwrap();
-        let amount = Amount::from_sat(22400000);
-        let derivation_index = ChildNumber::from(1);
         db_insert_new_unconfirmed_vault(
             &db_path,
-            wallet_id,
-            &outpoint_b,
-            &amount,
-            derivation_index,
-        )
-        .unwrap();
-        let db_vault = db_vault_by_deposit(&db_path, &outpoint_b).unwrap().unwrap();
-        db_confirm_deposit(
-            &db_path,
-            &outpoint_b,
-            9,
-            9,
-            &fresh_unvault_tx,
-            &fresh_cancel_tx,
-            Some(&fresh_emergency_tx),
-            Some(&fresh_unvaultemergency_tx),
+            1,
+            &outpoint,
+            &Amount::from_sat(612345),
+            DerivationIndex::new(3).unwrap(),
         )
         .unwrap();
-
-        let stored_cancel_tx = db_cancel_transaction(&db_path, db_vault.id)
+        let vault_id = db_vault_by_deposit(&db_path, &outpoint)
+            .unwrap()
             .unwrap()
+            .id;
+        assert!(db_abandoned_vaults(&db_path).unwrap().is_empty());
+
+        // Only once until it's resurrected
+        assert!(db_abandon_vault(&db_path, vault_id, 1_000).unwrap());
+        assert!(!db_abandon_vault(&db_path, vault_id, 1_001).unwrap());
+        assert!(db_abandoned_vaults(&db_path).unwrap().contains(&vault_id));
+
+        let resurrect = |resurrected_at| {
+            let mut resurrected = false;
+            db_exec(&db_path, |tx| {
+                resurrected = db_resurrect_vault_dbtx(tx, vault_id, resurrected_at)?;
+                Ok(())
+            })
             .unwrap();
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_cancel_tx,
-            &fullysigned_cancel_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+            resurrected
+        };
+        assert!(resurrect(2_000));
+        assert!(!resurrect(2_001));
+        assert!(db_abandoned_vaults(&db_path).unwrap().is_empty());
+
+        // It may be abandoned again, and the history is kept
+        assert!(db_abandon_vault(&db_path, vault_id, 3_000).unwrap());
+        let abandonments = db_deposit_abandonments(&db_path, vault_id).unwrap();
+        assert_eq!(abandonments.len(), 2);
+        assert_eq!(
+            (abandonments[0].abandoned_at, abandonments[0].resurrected_at),
+            (1_000, Some(2_000))
         );
-
-        let stored_emergency_tx = db_emer_transaction(&db_path, db_vault.id).unwrap().unwrap();
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_emergency_tx,
-            &fullysigned_emergency_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+        assert_eq!(
+            (abandonments[1].abandoned_at, abandonments[1].resurrected_at),
+            (3_000, None)
         );
 
-        let stored_unvaultemergency_tx = db_unvault_emer_transaction(&db_path, db_vault.id)
-            .unwrap()
-            .unwrap();
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_unvaultemergency_tx,
-            &fullysigned_unvaultemergency_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
-        );
+        // Only 'unconfirmed' vaults can be abandoned
+        assert!(resurrect(4_000));
+        db_mark_vault_as(&db_path, vault_id, VaultStatus::Funded).unwrap();
+        assert!(!db_abandon_vault(&db_path, vault_id, 5_000).unwrap());
+        assert!(db_abandoned_vaults(&db_path).unwrap().is_empty());
 
-        db_update_vault_status(&db_path, &db_vault).unwrap();
-        let db_vault = db_vault_by_deposit(&db_path, &db_vault.deposit_outpoint)
-            .unwrap()
-            .unwrap();
-        assert_eq!(db_vault.status, VaultStatus::Secured);
-        assert!(db_vault.funded_at.is_some());
-        assert!(db_vault.secured_at.is_some());
-        assert!(db_vault.delegated_at.is_none());
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
 
-        let stored_unvault_tx = db_unvault_transaction(&db_path, db_vault.id)
+    #[test]
+    fn test_db_deferred_revocation_txs() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
+        let db_path = revaultd.db_file();
+        let secp_ctx = secp256k1::Secp256k1::new();
+        setup_db(&mut revaultd).unwrap();
+
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(10_000_000);
+        let derivation_index = DerivationIndex::new(7).unwrap();
+        db_insert_new_unconfirmed_vault(&db_path, 1, &outpoint, &amount, derivation_index).unwrap();
+        let vault_id = db_vault_by_deposit(&db_path, &outpoint)
             .unwrap()
-            .unwrap();
-        update_presigned_tx(
-            &db_path,
-            &db_vault,
-            stored_unvault_tx,
-            &fullysigned_unvault_tx.psbt().inputs[0].partial_sigs,
-            &revaultd.secp_ctx,
+            .unwrap()
+            .id;
+        let emer_address = vault_emergency_address(&revaultd, None, amount);
+        let (unvault_tx, cancel_tx, emer_tx, unemer_tx) =
+            presigned_transactions(&revaultd, outpoint, amount, derivation_index, emer_address)
+                .unwrap();
+        let (emer_tx, unemer_tx) = (emer_tx.unwrap(), unemer_tx.unwrap());
+
+        // Signatures handed while the deposit is unconfirmed are kept aside, once per type
+        let mut signed_cancel_tx = cancel_tx.clone();
+        revault_tx_add_sig(
+            &mut signed_cancel_tx,
+            0,
+            SigHashType::AllPlusAnyoneCanPay,
+            &secp_ctx,
         );
-        db_update_vault_status(&db_path, &db_vault).unwrap();
-        let db_vault = db_vault_by_deposit(&db_path, &db_vault.deposit_outpoint)
+        let mut signed_emer_tx = emer_tx.clone();
+        revault_tx_add_sig(
+            &mut signed_emer_tx,
+            0,
+            SigHashType::AllPlusAnyoneCanPay,
+            &secp_ctx,
+        );
+        let mut signed_unemer_tx = unemer_tx.clone();
+        revault_tx_add_sig(
+            &mut signed_unemer_tx,
+            0,
+            SigHashType::AllPlusAnyoneCanPay,
+            &secp_ctx,
+        );
+        let deferred = vec![
+            RevaultTx::Cancel(signed_cancel_tx.clone()),
+            RevaultTx::Emergency(signed_emer_tx.clone()),
+            RevaultTx::UnvaultEmergency(signed_unemer_tx.clone()),
+        ];
+        db_defer_revocation_txs(&db_path, vault_id, &deferred, 1_000).unwrap();
+        db_defer_revocation_txs(&db_path, vault_id, &deferred, 1_001).unwrap();
+        assert_eq!(
+            db_deferred_revocation_txids(&db_path, vault_id).unwrap(),
+            vec![cancel_tx.txid(), emer_tx.txid(), unemer_tx.txid()]
+        );
+
+        // Once it confirms they are stored in place of the fresh ones and the vault is securing
+        let mut applied = 0;
+        db_exec(&db_path, |db_tx| {
+            db_confirm_deposit_dbtx(
+                db_tx,
+                &outpoint,
+                100,
+                1_000,
+                &unvault_tx,
+                &cancel_tx,
+                Some(&emer_tx),
+                Some(&unemer_tx),
+                1_600_000_000,
+            )?;
+            applied = db_apply_deferred_revocation_txs_dbtx(db_tx, vault_id, 1_600_000_000)?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(applied, 3);
+        assert_eq!(
+            db_cancel_transaction(&db_path, vault_id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .assert_cancel(),
+            signed_cancel_tx
+        );
+        assert_eq!(
+            db_emer_transaction(&db_path, vault_id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .assert_emer(),
+            signed_emer_tx
+        );
+        assert_eq!(
+            db_unvault_emer_transaction(&db_path, vault_id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .assert_unvault_emer(),
+            signed_unemer_tx
+        );
+        assert_eq!(
+            db_vault_by_deposit(&db_path, &outpoint)
+                .unwrap()
+                .unwrap()
+                .status,
+            VaultStatus::Securing
+        );
+        assert!(db_deferred_revocation_txids(&db_path, vault_id)
             .unwrap()
-            .unwrap();
-        assert_eq!(db_vault.status, VaultStatus::Active);
-        assert!(db_vault.funded_at.is_some());
-        assert!(db_vault.secured_at.is_some());
-        assert!(db_vault.delegated_at.is_some());
+            .is_empty());
 
         fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
     }
@@ -1,7 +1,7 @@
 /// This is where we regroup all logic related to the storage and management of
 /// in-DB pre-signed *Bitcoin* transactions (not to be confused with DB txs).
 use revault_tx::{
-    bitcoin::{secp256k1, Txid, Wtxid},
+    bitcoin::{secp256k1, Transaction, Txid, Wtxid},
     transactions::{
         CancelTransaction, EmergencyTransaction, RevaultTransaction, UnvaultEmergencyTransaction,
         UnvaultTransaction,
@@ -47,6 +47,17 @@ tx_type_from_tx!(CancelTransaction, Cancel);
 tx_type_from_tx!(EmergencyTransaction, Emergency);
 tx_type_from_tx!(UnvaultEmergencyTransaction, UnvaultEmergency);
 
+impl From<&RevaultTx> for TransactionType {
+    fn from(tx: &RevaultTx) -> Self {
+        match tx {
+            RevaultTx::Unvault(_) => Self::Unvault,
+            RevaultTx::Cancel(_) => Self::Cancel,
+            RevaultTx::Emergency(_) => Self::Emergency,
+            RevaultTx::UnvaultEmergency(_) => Self::UnvaultEmergency,
+        }
+    }
+}
+
 // FIXME: move it into its own file
 /// A transaction stored in the 'presigned_transactions' table
 #[derive(Debug, PartialEq, Clone)]
@@ -115,6 +126,44 @@ impl RevaultTx {
         }
     }
 
+    /// The feerate its fixed fees imply at its maximum size once fully signed, in sats/vbyte
+    /// (rounded down)
+    pub fn feerate(&self) -> u64 {
+        let (weight, fees) = match self {
+            RevaultTx::Unvault(ref tx) => (tx.max_weight(), tx.fees()),
+            RevaultTx::Cancel(ref tx) => (tx.max_weight(), tx.fees()),
+            RevaultTx::Emergency(ref tx) => (tx.max_weight(), tx.fees()),
+            RevaultTx::UnvaultEmergency(ref tx) => (tx.max_weight(), tx.fees()),
+        };
+
+        fees / ((weight + 3) / 4)
+    }
+
+    /// Finalize the PSBT and extract the network transaction from it
+    pub fn finalized_tx<C: secp256k1::Verification>(
+        self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<Transaction, revault_tx::Error> {
+        Ok(match self {
+            RevaultTx::Unvault(mut tx) => {
+                tx.finalize(secp)?;
+                tx.into_psbt().extract_tx()
+            }
+            RevaultTx::Cancel(mut tx) => {
+                tx.finalize(secp)?;
+                tx.into_psbt().extract_tx()
+            }
+            RevaultTx::Emergency(mut tx) => {
+                tx.finalize(secp)?;
+                tx.into_psbt().extract_tx()
+            }
+            RevaultTx::UnvaultEmergency(mut tx) => {
+                tx.finalize(secp)?;
+                tx.into_psbt().extract_tx()
+            }
+        })
+    }
+
     /// Get the signatures of this presigned transaction.
     /// All presigned transactions only have a single input (at least before fee-bumping,
     /// but such transactions are never stored in our DB).
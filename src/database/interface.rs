@@ -1,16 +1,31 @@
 use crate::{
+    config::DbSynchronous,
     database::{
         bitcointx::{RevaultTx, TransactionType},
-        schema::{DbSpendTransaction, DbTransaction, DbVault, DbWallet},
+        schema::{
+            AnnouncementStatus, DbAuditEntry, DbCosigSignatures, DbDepositAbandonment,
+            DbDepositAncestry, DbDerivedScript, DbEmergencyDescriptor, DbEmergencyOutcome,
+            DbEmergencyRun, DbFinalTxid, DbIdempotencyKey, DbImportedRange, DbMempoolConflict,
+            DbQueuedSig, DbRevocationCheck, DbSigningContext, DbSpendAnnouncement,
+            DbSpendDeprecation, DbSpendDestination, DbSpendExpiration, DbSpendProposal,
+            DbSpendProposalAck, DbSpendTransaction, DbTransaction, DbVault, DbVaultFlag,
+            DbVaultMigration, DbVaultStatusChange, DbVaultTransition, DbWallet, DbWalletRotation,
+            DbWatchData, DepositOrigin, EmergencyOutcomeKind, ScriptKind, SpendExpiryReason,
+            VaultFlagKind,
+        },
         DatabaseError,
     },
-    revaultd::{BlockchainTip, VaultStatus},
+    derivation::DerivationIndex,
+    revaultd::{BlockchainTip, ScriptVersion, VaultStatus},
+    rpcstats::{phase_timer, Phase},
 };
 use revault_tx::{
     bitcoin::{
         consensus::encode,
-        util::bip32::{ChildNumber, ExtendedPubKey},
-        Amount, BlockHash, Network, OutPoint, Txid,
+        hashes::{hex::ToHex, sha256, Hash},
+        secp256k1,
+        util::bip32::{ExtendedPubKey, Fingerprint},
+        Address, Amount, BlockHash, Network, OutPoint, Script, Txid,
     },
     scripts::{CpfpDescriptor, DepositDescriptor, UnvaultDescriptor},
     transactions::{
@@ -21,10 +36,11 @@ use revault_tx::{
 
 use std::{
     boxed::Box,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::{TryFrom, TryInto},
     path::Path,
     str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
 };
 
 use rusqlite::{
@@ -37,14 +53,61 @@ use rusqlite::{
 // Therefore the below routines for now create a new connection and can be used from any thread.
 // For concurrent write accesses, we rely on the 'unlock_notify' feature of SQLite: https://sqlite.org/unlock_notify.html
 
+// The `synchronous` setting of the connections we write with, set once at startup.
+static DB_SYNCHRONOUS: AtomicU8 = AtomicU8::new(DbSynchronous::Full as u8);
+
+/// Set how hard sqlite should try to make our commits durable, from now on.
+pub fn set_db_synchronous(mode: DbSynchronous) {
+    DB_SYNCHRONOUS.store(mode as u8, Ordering::Relaxed);
+}
+
+/// How hard sqlite currently tries to make our commits durable.
+pub fn db_synchronous() -> DbSynchronous {
+    match DB_SYNCHRONOUS.load(Ordering::Relaxed) {
+        0 => DbSynchronous::Off,
+        1 => DbSynchronous::Normal,
+        3 => DbSynchronous::Extra,
+        _ => DbSynchronous::Full,
+    }
+}
+
+// The `secure_delete` setting of the connections we write with. Once enabled at startup, it's
+// never disabled again.
+static DB_SECURE_DELETE: AtomicBool = AtomicBool::new(false);
+
+/// Have sqlite overwrite the content we delete, from now on.
+pub fn enable_db_secure_delete() {
+    DB_SECURE_DELETE.store(true, Ordering::Relaxed);
+}
+
+/// Whether sqlite currently overwrites the content we delete.
+pub fn db_secure_delete() -> bool {
+    DB_SECURE_DELETE.load(Ordering::Relaxed)
+}
+
 /// Perform a set of modifications to the database inside a single transaction
 pub fn db_exec<F>(path: &Path, modifications: F) -> Result<(), DatabaseError>
 where
     F: FnOnce(&Transaction) -> Result<(), DatabaseError>,
 {
+    let _timer = phase_timer(Phase::Database);
     let mut conn = Connection::open(path)
         .map_err(|e| DatabaseError(format!("Opening database: {}", e.to_string())))?;
     conn.busy_timeout(std::time::Duration::from_secs(60))?;
+    // We don't use the WAL, so in "full" mode the rollback journal and the database are both
+    // synced before the commit returns: there is no checkpoint to wait for, even for the
+    // transactions that must not be lost (broadcasts, audit log).
+    conn.execute_batch(&format!("PRAGMA synchronous = {}", db_synchronous() as u8))
+        .map_err(|e| DatabaseError(format!("Setting synchronous mode: {}", e.to_string())))?;
+    // This zeroes the content we delete in the database file only. The rollback journal of the
+    // transaction still holds the original pages and is unlinked, not overwritten, at commit.
+    // Neither does it reach copies made below sqlite (filesystem journal, copy-on-write
+    // snapshots, SSD wear levelling) or backups of the database.
+    conn.execute_batch(&format!(
+        "PRAGMA secure_delete = {}",
+        db_secure_delete() as u8
+    ))
+    .map_err(|e| DatabaseError(format!("Setting secure delete: {}", e.to_string())))?;
     let tx = conn
         .transaction_with_behavior(TransactionBehavior::Immediate)
         .map_err(|e| DatabaseError(format!("Creating transaction: {}", e.to_string())))?;
@@ -63,6 +126,7 @@ where
     P::Item: ToSql,
     F: FnMut(&Row<'_>) -> rusqlite::Result<T>,
 {
+    let _timer = phase_timer(Phase::Database);
     let conn = Connection::open(path)
         .map_err(|e| DatabaseError(format!("Opening database for query: {}", e.to_string())))?;
 
@@ -111,19 +175,77 @@ pub fn db_version(db_path: &Path) -> Result<u32, DatabaseError> {
         .ok_or_else(|| DatabaseError("No row in version table?".to_string()))
 }
 
+/// Get when this version of revaultd first ran on this database, if it ever did.
+pub fn db_version_first_run(db_path: &Path, version: &str) -> Result<Option<u32>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT first_run_at FROM binary_versions WHERE version = (?1)",
+        params![version],
+        |row| row.get(0),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+/// The window of the replay of the missed blocks that was interrupted, if any, along with the
+/// transactions it processed already and the block each was confirmed in.
+pub fn db_replay_window(
+    db_path: &Path,
+) -> Result<Option<(BlockchainTip, HashSet<(Txid, BlockHash)>)>, DatabaseError> {
+    let rows = db_query(
+        db_path,
+        "SELECT window_height, window_hash, txid, blockhash FROM replayed_transactions",
+        params![],
+        |row| {
+            let window = tip_from_row(row)?;
+            let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(2)?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+            let blockhash: BlockHash = encode::deserialize(&row.get::<_, Vec<u8>>(3)?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+            Ok((window, txid, blockhash))
+        },
+    )?;
+
+    let window = match rows.first() {
+        Some((window, _, _)) => *window,
+        None => return Ok(None),
+    };
+    Ok(Some((
+        window,
+        rows.into_iter()
+            .filter(|(w, _, _)| w == &window)
+            .map(|(_, txid, blockhash)| (txid, blockhash))
+            .collect(),
+    )))
+}
+
+fn tip_from_row(row: &Row) -> rusqlite::Result<BlockchainTip> {
+    let height = row.get::<_, u32>(0)?;
+    let hash: BlockHash = encode::deserialize(&row.get::<_, Vec<u8>>(1)?)
+        .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+
+    Ok(BlockchainTip { height, hash })
+}
+
 /// Get our tip from the database
 pub fn db_tip(db_path: &Path) -> Result<BlockchainTip, DatabaseError> {
     let mut rows = db_query(
         db_path,
         "SELECT blockheight, blockhash FROM tip",
         params![],
-        |row| {
-            let height = row.get::<_, u32>(0)?;
-            let hash: BlockHash = encode::deserialize(&row.get::<_, Vec<u8>>(1)?)
-                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+        tip_from_row,
+    )?;
 
-            Ok(BlockchainTip { height, hash })
-        },
+    rows.pop()
+        .ok_or_else(|| DatabaseError("No row in tip table?".to_string()))
+}
+
+/// Get our tip from an existing database transaction
+pub fn db_tip_dbtx(db_tx: &Transaction) -> Result<BlockchainTip, DatabaseError> {
+    let mut rows = db_query_tx(
+        db_tx,
+        "SELECT blockheight, blockhash FROM tip",
+        params![],
+        tip_from_row,
     )?;
 
     rows.pop()
@@ -141,9 +263,10 @@ pub fn db_network(db_path: &Path) -> Result<Network, DatabaseError> {
         .ok_or_else(|| DatabaseError("No row in tip table?".to_string()))
 }
 
-/// Get the database wallet. We only support single wallet, so this always return the first row.
-pub fn db_wallet(db_path: &Path) -> Result<DbWallet, DatabaseError> {
-    let mut rows = db_query(db_path, "SELECT * FROM wallets", params![], |row| {
+impl TryFrom<&Row<'_>> for DbWallet {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
         let id = row.get(0)?;
         let timestamp = row.get(1)?;
 
@@ -188,8 +311,9 @@ pub fn db_wallet(db_path: &Path) -> Result<DbWallet, DatabaseError> {
             "The database is messed up, and we could not catch the error."
         );
 
-        let deposit_derivation_index: u32 = row.get(7)?;
-        let deposit_derivation_index: ChildNumber = deposit_derivation_index.into();
+        let deposit_derivation_index: DerivationIndex = row.get(7)?;
+        let script_version = ScriptVersion::try_from(row.get::<_, u32>(8)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
 
         Ok(DbWallet {
             id,
@@ -200,13 +324,38 @@ pub fn db_wallet(db_path: &Path) -> Result<DbWallet, DatabaseError> {
             our_man_xpub,
             our_stk_xpub,
             deposit_derivation_index,
+            script_version,
         })
-    })?;
+    }
+}
+
+/// Get the current database wallet. Several rows exist after a rotation of the keys was
+/// initiated: the wallet it's rotating to isn't the current one until the rotation completes.
+pub fn db_wallet(db_path: &Path) -> Result<DbWallet, DatabaseError> {
+    let mut rows = db_query(
+        db_path,
+        "SELECT * FROM wallets WHERE id NOT IN ( \
+            SELECT new_wallet_id FROM wallet_rotations WHERE completed_at IS NULL \
+         ) ORDER BY id",
+        params![],
+        |row| row.try_into(),
+    )?;
 
     rows.pop()
         .ok_or_else(|| DatabaseError("No row in wallet table?".to_string()))
 }
 
+/// Get a database wallet by its id
+pub fn db_wallet_by_id(db_path: &Path, wallet_id: u32) -> Result<Option<DbWallet>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM wallets WHERE id = (?1)",
+        params![wallet_id],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| rows.pop())
+}
+
 impl TryFrom<&Row<'_>> for DbVault {
     type Error = rusqlite::Error;
 
@@ -226,7 +375,7 @@ impl TryFrom<&Row<'_>> for DbVault {
             vout: row.get(5)?,
         };
         let amount = Amount::from_sat(row.get::<_, i64>(6)? as u64);
-        let derivation_index = ChildNumber::from(row.get::<_, u32>(7)?);
+        let derivation_index: DerivationIndex = row.get(7)?;
         let funded_at = row.get(8)?;
         let secured_at = row.get(9)?;
         let delegated_at = row.get(10)?;
@@ -303,6 +452,20 @@ pub fn db_vault_by_deposit(
     .map(|mut vault_list| vault_list.pop())
 }
 
+/// Get a vault from a deposit outpoint, from an existing database transaction.
+pub fn db_vault_by_deposit_dbtx(
+    db_tx: &Transaction,
+    deposit: &OutPoint,
+) -> Result<Option<DbVault>, DatabaseError> {
+    db_query_tx(
+        db_tx,
+        "SELECT * FROM vaults WHERE deposit_txid = (?1) AND deposit_vout = (?2)",
+        params![deposit.txid.to_vec(), deposit.vout],
+        |row| row.try_into(),
+    )
+    .map(|mut vault_list| vault_list.pop())
+}
+
 /// Get the vaults that were unvaulted but for which the Unvault was not spent yet from the DB.
 pub fn db_unvaulted_vaults(
     db_path: &Path,
@@ -311,11 +474,12 @@ pub fn db_unvaulted_vaults(
         db_path,
         "SELECT vaults.*, ptx.psbt FROM vaults INNER JOIN presigned_transactions as ptx \
          ON ptx.vault_id = vaults.id \
-         WHERE ptx.type = (?1) AND vaults.status IN ((?2), (?3))",
+         WHERE ptx.type = (?1) AND vaults.status IN ((?2), (?3), (?4))",
         params![
             TransactionType::Unvault as u32,
             VaultStatus::Unvaulted as u32,
             VaultStatus::Unvaulting as u32,
+            VaultStatus::Spendable as u32,
         ],
         |row| {
             let db_vault: DbVault = row.try_into()?;
@@ -611,6 +775,32 @@ pub fn db_unvault_emer_transaction(
     .map(|mut rows| rows.pop())
 }
 
+const VAULT_BY_UNVAULT_TXID_QUERY: &str =
+    "SELECT vaults.*, ptx.id, ptx.psbt, ptx.fullysigned FROM presigned_transactions as ptx \
+     INNER JOIN vaults ON vaults.id = ptx.vault_id \
+     WHERE ptx.txid = (?1) and type = (?2)";
+
+fn vault_and_unvault_from_row(row: &Row) -> rusqlite::Result<(DbVault, DbTransaction)> {
+    let db_vault: DbVault = row.try_into()?;
+    let offset = 13;
+
+    // FIXME: there is probably a more extensible way to implement the from()s so we don't
+    // have to change all those when adding a column
+    let id: u32 = row.get(offset)?;
+    let psbt: Vec<u8> = row.get(offset + 1)?;
+    let psbt = UnvaultTransaction::from_psbt_serialized(&psbt).expect("We store it");
+    let is_fully_signed = row.get(offset + 2)?;
+    let db_tx = DbTransaction {
+        id,
+        vault_id: db_vault.id,
+        tx_type: TransactionType::Unvault,
+        psbt: RevaultTx::Unvault(psbt),
+        is_fully_signed,
+    };
+
+    Ok((db_vault, db_tx))
+}
+
 /// Get a vault and its Unvault transaction out of an Unvault txid
 pub fn db_vault_by_unvault_txid(
     db_path: &Path,
@@ -618,30 +808,24 @@ pub fn db_vault_by_unvault_txid(
 ) -> Result<Option<(DbVault, DbTransaction)>, DatabaseError> {
     Ok(db_query(
         db_path,
-        "SELECT vaults.*, ptx.id, ptx.psbt, ptx.fullysigned FROM presigned_transactions as ptx \
-         INNER JOIN vaults ON vaults.id = ptx.vault_id \
-         WHERE ptx.txid = (?1) and type = (?2)",
+        VAULT_BY_UNVAULT_TXID_QUERY,
         params![txid.to_vec(), TransactionType::Unvault as u32],
-        |row| {
-            let db_vault: DbVault = row.try_into()?;
-            let offset = 13;
-
-            // FIXME: there is probably a more extensible way to implement the from()s so we don't
-            // have to change all those when adding a column
-            let id: u32 = row.get(offset)?;
-            let psbt: Vec<u8> = row.get(offset + 1)?;
-            let psbt = UnvaultTransaction::from_psbt_serialized(&psbt).expect("We store it");
-            let is_fully_signed = row.get(offset + 2)?;
-            let db_tx = DbTransaction {
-                id,
-                vault_id: db_vault.id,
-                tx_type: TransactionType::Unvault,
-                psbt: RevaultTx::Unvault(psbt),
-                is_fully_signed,
-            };
+        vault_and_unvault_from_row,
+    )?
+    .pop())
+}
 
-            Ok((db_vault, db_tx))
-        },
+/// Get a vault and its Unvault transaction out of an Unvault txid, from an existing database
+/// transaction.
+pub fn db_vault_by_unvault_txid_dbtx(
+    db_tx: &Transaction,
+    txid: &Txid,
+) -> Result<Option<(DbVault, DbTransaction)>, DatabaseError> {
+    Ok(db_query_tx(
+        db_tx,
+        VAULT_BY_UNVAULT_TXID_QUERY,
+        params![txid.to_vec(), TransactionType::Unvault as u32],
+        vault_and_unvault_from_row,
     )?
     .pop())
 }
@@ -682,51 +866,96 @@ pub fn db_sig_missing(
     Ok(vault_map)
 }
 
-/// Get all the Emergency transactions of the "secured" (Emergency signed) vaults that were not yet
-/// Unvaulted.
-pub fn db_signed_emer_txs(db_path: &Path) -> Result<Vec<EmergencyTransaction>, DatabaseError> {
-    // FIXME: Get rid of this footguny v.status < (?2)
+/// Get the presigned transactions of the vaults we shared signatures for, which the Coordinator
+/// with this Noise static public key did not acknowledge having our signature for. Only the
+/// vaults that may still be Unvaulted or revoked with them are considered.
+pub fn db_coordinator_unacked_txs(
+    db_path: &Path,
+    coordinator_key: &[u8],
+) -> Result<Vec<(DbVault, DbTransaction)>, DatabaseError> {
     db_query(
         db_path,
-        "SELECT ptx.* FROM presigned_transactions as ptx INNER JOIN vaults as v ON ptx.vault_id = v.id \
-         WHERE ptx.fullysigned = 1 AND ptx.type = (?1) AND v.status < (?2)",
+        "SELECT v.*, ptx.* \
+         FROM presigned_transactions as ptx INNER JOIN vaults as v ON ptx.vault_id = v.id \
+         WHERE v.status IN (?2, ?3, ?4, ?5) AND NOT EXISTS ( \
+            SELECT 1 FROM coordinator_sig_acks as a \
+            WHERE a.coordinator_key = (?1) AND a.txid = ptx.txid \
+         )",
         params![
-            TransactionType::Emergency as u32,
-            VaultStatus::Unvaulting as u32,
+            coordinator_key,
+            VaultStatus::Securing as u32,
+            VaultStatus::Secured as u32,
+            VaultStatus::Activating as u32,
+            VaultStatus::Active as u32
         ],
-        |row| {
-            let db_tx: DbTransaction = row.try_into()?;
-            Ok(match db_tx.psbt {
-                RevaultTx::Emergency(tx) => tx,
-                _ => unreachable!("Inconsistency between TransactionType and RevaultTx variant?"),
-            })
-        },
+        |row| Ok((row.try_into()?, db_tx_from_row(row, 13)?)),
     )
 }
 
-/// Get all the UnvaultEmergency transactions of the Unvaulted vaults that were not yet Spent
-pub fn db_signed_unemer_txs(
+impl TryFrom<&Row<'_>> for DbQueuedSig {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(1)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+        let pubkey = secp256k1::PublicKey::from_slice(&row.get::<_, Vec<u8>>(2)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+        let signature = secp256k1::Signature::from_der(&row.get::<_, Vec<u8>>(3)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+
+        Ok(DbQueuedSig {
+            id: row.get(0)?,
+            txid,
+            pubkey,
+            signature,
+            queued_at: row.get(4)?,
+        })
+    }
+}
+
+/// The first `limit` signatures queued to be pushed to the Coordinator, by order of queuing.
+pub fn db_coordinator_outbox(
     db_path: &Path,
-) -> Result<Vec<UnvaultEmergencyTransaction>, DatabaseError> {
+    limit: u32,
+) -> Result<Vec<DbQueuedSig>, DatabaseError> {
     db_query(
         db_path,
-        "SELECT ptx.* FROM presigned_transactions as ptx INNER JOIN vaults as v on ptx.vault_id = v.id \
-         WHERE ptx.fullysigned = 1 AND ptx.type = (?1) AND v.status IN ((?2), (?3), (?4), (?5))",
-        params![
-            TransactionType::UnvaultEmergency as u32,
-            VaultStatus::Unvaulting as u32,
-            VaultStatus::Unvaulted as u32,
-            VaultStatus::Spending as u32,
-            VaultStatus::Canceling as u32,
-        ],
-        |row| {
-            let db_tx: DbTransaction = row.try_into()?;
-            Ok(match db_tx.psbt {
-                RevaultTx::UnvaultEmergency(tx) => tx,
-                _ => unreachable!("Inconsistency between TransactionType and RevaultTx variant?"),
-            })
-        },
+        "SELECT id, txid, pubkey, signature, queued_at FROM coordinator_outbox \
+         ORDER BY id LIMIT (?1)",
+        params![limit],
+        |row| row.try_into(),
+    )
+}
+
+/// The number of signatures queued to be pushed to the Coordinator.
+pub fn db_coordinator_outbox_len(db_path: &Path) -> Result<u64, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT COUNT(*) FROM coordinator_outbox",
+        params![],
+        |row| row.get::<_, i64>(0).map(|count| count as u64),
     )
+    .map(|mut rows| rows.pop().unwrap_or(0))
+}
+
+impl TryFrom<&Row<'_>> for DbCosigSignatures {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let spend_id: i64 = row.get(1)?;
+        let noise_key: Vec<u8> = row.get(2)?;
+        let mut cosig_noise_key = [0; 32];
+        cosig_noise_key.copy_from_slice(&noise_key);
+        let psbt: Vec<u8> = row.get(3)?;
+        let psbt = SpendTransaction::from_psbt_serialized(&psbt)
+            .expect("We store it with as_psbt_serialized");
+
+        Ok(DbCosigSignatures {
+            spend_id,
+            cosig_noise_key,
+            psbt,
+        })
+    }
 }
 
 impl TryFrom<&Row<'_>> for DbSpendTransaction {
@@ -799,7 +1028,8 @@ pub fn db_broadcastable_spend_transactions(
 ) -> Result<Vec<DbSpendTransaction>, DatabaseError> {
     db_query(
         db_path,
-        "SELECT * FROM spend_transactions WHERE broadcasted = 0",
+        "SELECT * FROM spend_transactions WHERE broadcasted = 0 \
+         AND id NOT IN (SELECT spend_id FROM spend_deprecations)",
         params![],
         |row| row.try_into(),
     )
@@ -819,6 +1049,176 @@ pub fn db_spend_transaction(
     .pop())
 }
 
+/// Get the signatures the cosigning servers already sent us for this Spend transaction.
+pub fn db_cosig_signatures(
+    db_path: &Path,
+    spend_txid: &Txid,
+) -> Result<Vec<DbCosigSignatures>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT csig.* FROM spend_cosig_signatures as csig \
+         INNER JOIN spend_transactions as stx ON stx.id = csig.spend_id \
+         WHERE stx.txid = (?1) ORDER BY csig.id",
+        params![spend_txid.to_vec()],
+        |row| row.try_into(),
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbSpendAnnouncement {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let coordinator_key = row.get::<_, Option<Vec<u8>>>(1)?.map(|key| {
+            let mut coordinator_key = [0; 32];
+            coordinator_key.copy_from_slice(&key);
+            coordinator_key
+        });
+        let status = row.get::<_, u32>(3)?;
+        let status = AnnouncementStatus::try_from(status).map_err(|_| {
+            FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Unknown announcement status '{}'",
+                status
+            ))))
+        })?;
+
+        Ok(DbSpendAnnouncement {
+            spend_id: row.get(0)?,
+            coordinator_key,
+            announced_at: row.get(2)?,
+            status,
+            reconciled_at: row.get(4)?,
+        })
+    }
+}
+
+/// Get what we know of the announcement of this Spend transaction to the Coordinator, if we
+/// ever announced it.
+pub fn db_spend_announcement(
+    db_path: &Path,
+    spend_txid: &Txid,
+) -> Result<Option<DbSpendAnnouncement>, DatabaseError> {
+    Ok(db_query(
+        db_path,
+        "SELECT ann.* FROM spend_announcements as ann \
+         INNER JOIN spend_transactions as stx ON stx.id = ann.spend_id \
+         WHERE stx.txid = (?1)",
+        params![spend_txid.to_vec()],
+        |row| row.try_into(),
+    )?
+    .pop())
+}
+
+impl TryFrom<&Row<'_>> for DbSpendDeprecation {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let cancel_txid: Txid =
+            encode::deserialize(&row.get::<_, Vec<u8>>(2)?).expect("We store it");
+
+        Ok(DbSpendDeprecation {
+            spend_id: row.get(0)?,
+            vault_id: row.get(1)?,
+            cancel_txid,
+            deprecated_at: row.get(3)?,
+        })
+    }
+}
+
+/// Get why this Spend transaction can't confirm anymore, if it was deprecated.
+pub fn db_spend_deprecation(
+    db_path: &Path,
+    spend_txid: &Txid,
+) -> Result<Option<DbSpendDeprecation>, DatabaseError> {
+    Ok(db_query(
+        db_path,
+        "SELECT dep.* FROM spend_deprecations as dep \
+         INNER JOIN spend_transactions as stx ON stx.id = dep.spend_id \
+         WHERE stx.txid = (?1)",
+        params![spend_txid.to_vec()],
+        |row| row.try_into(),
+    )?
+    .pop())
+}
+
+impl TryFrom<&Row<'_>> for DbSpendExpiration {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let reason = row.get::<_, u32>(1)?;
+        let reason = SpendExpiryReason::try_from(reason).map_err(|_| {
+            FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Unknown Spend expiry reason '{}'",
+                reason
+            ))))
+        })?;
+
+        Ok(DbSpendExpiration {
+            spend_id: row.get(0)?,
+            reason,
+            expired_at: row.get(2)?,
+        })
+    }
+}
+
+/// Get why we gave up on this Spend transaction, if it expired.
+pub fn db_spend_expiration(
+    db_path: &Path,
+    spend_txid: &Txid,
+) -> Result<Option<DbSpendExpiration>, DatabaseError> {
+    Ok(db_query(
+        db_path,
+        "SELECT exp.* FROM spend_expirations as exp \
+         INNER JOIN spend_transactions as stx ON stx.id = exp.spend_id \
+         WHERE stx.txid = (?1)",
+        params![spend_txid.to_vec()],
+        |row| row.try_into(),
+    )?
+    .pop())
+}
+
+/// Get the Spend transactions we announced at or before `announced_before`, and that were
+/// neither broadcast, deprecated nor expired since. An adopted announcement counts from the
+/// time the Coordinator reported it.
+pub fn db_stale_spends(
+    db_path: &Path,
+    announced_before: u32,
+) -> Result<Vec<DbSpendTransaction>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT stx.* FROM spend_transactions as stx \
+         INNER JOIN spend_announcements as ann ON ann.spend_id = stx.id \
+         WHERE (stx.broadcasted IS NULL OR stx.broadcasted = 0) \
+         AND COALESCE(ann.announced_at, ann.reconciled_at) <= (?1) \
+         AND stx.id NOT IN (SELECT spend_id FROM spend_deprecations) \
+         AND stx.id NOT IN (SELECT spend_id FROM spend_expirations)",
+        params![announced_before],
+        |row| row.try_into(),
+    )
+}
+
+/// Whether some of the Unvault transactions of this Spend transaction were broadcast, as far as
+/// we know: a vault it spends isn't active anymore, or its Unvault was broadcast along with the
+/// others when the Spend was set and did not fail.
+pub fn db_spend_unvaults_broadcast(
+    db_path: &Path,
+    db_spend: &DbSpendTransaction,
+) -> Result<bool, DatabaseError> {
+    for db_vault in db_vaults_from_spend(db_path, &db_spend.psbt.txid())?.values() {
+        if db_vault.status != VaultStatus::Active {
+            return Ok(true);
+        }
+        if db_spend.broadcasted.is_some()
+            && !db_vault_flags(db_path, db_vault.id)?.iter().any(|flag| {
+                flag.kind == VaultFlagKind::UnvaultBroadcastFailed && flag.cleared_at.is_none()
+            })
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
 /// Get a mapping of Spend transaction inputs to the vault they ultimately spend. Note that we
 /// can't have two Unvault outputs in a single Unvault transaction therefore it's fine to use the
 /// txid for identifying the Unvault output.
@@ -885,7 +1285,7 @@ pub fn db_cpfpable_unvaults(db_path: &Path) -> Result<Vec<UnvaultTransaction>, D
 }
 
 /// This function returns the vaults that are deposit, change deposit or spend output of
-/// a limited number of tx which occured between two dates.
+/// a limited number of tx which occured between two dates, or that were sent to the EDV then.
 pub fn db_vaults_with_txids_in_period(
     db_path: &Path,
     start: u32,
@@ -911,6 +1311,14 @@ pub fn db_vaults_with_txids_in_period(
                     AND status IN ((?5), (?6)) \
                     ORDER BY moved_at DESC LIMIT (?3) \
                 ) \
+                UNION \
+                SELECT * FROM ( \
+                    SELECT deposit_txid AS txid, moved_at AS date FROM vaults \
+                    WHERE moved_at >= (?1) \
+                    AND moved_at <= (?2) \
+                    AND status IN ((?7), (?8)) \
+                    ORDER BY moved_at DESC LIMIT (?3) \
+                ) \
                 ORDER BY date DESC LIMIT (?3)
             ) \
         ) \
@@ -924,21 +1332,1236 @@ pub fn db_vaults_with_txids_in_period(
             VaultStatus::Unconfirmed as u32,
             VaultStatus::Canceled as u32,
             VaultStatus::Spent as u32,
+            VaultStatus::EmergencyVaulted as u32,
+            VaultStatus::UnvaultEmergencyVaulted as u32,
         ],
         |row| row.try_into(),
     )
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::database::actions::{db_confirm_deposit, db_insert_new_unconfirmed_vault, setup_db};
-    use crate::utils::test_utils::{dummy_revaultd, test_datadir, UserRole};
-    use revault_tx::{bitcoin::OutPoint, transactions::transaction_chain};
+impl TryFrom<&Row<'_>> for DbMempoolConflict {
+    type Error = rusqlite::Error;
 
-    use std::{fs, str::FromStr};
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let id = row.get(0)?;
+        let vault_id = row.get(1)?;
+        let rejected_txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(2)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+        let competing_txid = row
+            .get::<_, Option<Vec<u8>>>(3)?
+            .map(|raw_txid| encode::deserialize(&raw_txid).expect("We only store valid txids"));
+        let confirmed_txid = row
+            .get::<_, Option<Vec<u8>>>(4)?
+            .map(|raw_txid| encode::deserialize(&raw_txid).expect("We only store valid txids"));
+        let detected_at = row.get(5)?;
 
-    fn db_vault_set_final_txid(
+        Ok(DbMempoolConflict {
+            id,
+            vault_id,
+            rejected_txid,
+            competing_txid,
+            confirmed_txid,
+            detected_at,
+        })
+    }
+}
+
+/// Get the mempool conflicts we recorded for this vault
+pub fn db_vault_conflicts(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Vec<DbMempoolConflict>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM mempool_conflicts WHERE vault_id = (?1)",
+        params![vault_id],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the mempool conflicts this transaction was part of, either as the rejected or the
+/// competing one.
+pub fn db_tx_conflicts(
+    db_path: &Path,
+    txid: &Txid,
+) -> Result<Vec<DbMempoolConflict>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM mempool_conflicts WHERE rejected_txid = (?1) OR competing_txid = (?1)",
+        params![txid.to_vec()],
+        |row| row.try_into(),
+    )
+}
+
+fn hash_from_row(row: &Row, index: usize) -> Result<sha256::Hash, rusqlite::Error> {
+    sha256::Hash::from_slice(&row.get::<_, Vec<u8>>(index)?)
+        .map_err(|e| FromSqlError::Other(Box::new(e)).into())
+}
+
+impl TryFrom<&Row<'_>> for DbAuditEntry {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(DbAuditEntry {
+            id: row.get(0)?,
+            method: row.get(1)?,
+            params_digest: hash_from_row(row, 2)?,
+            result: row.get(3)?,
+            timestamp: row.get(4)?,
+            peer_uid: row.get(5)?,
+            prev_hash: hash_from_row(row, 6)?,
+            hash: hash_from_row(row, 7)?,
+        })
+    }
+}
+
+impl TryFrom<&Row<'_>> for DbIdempotencyKey {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(DbIdempotencyKey {
+            key: row.get(0)?,
+            method: row.get(1)?,
+            params_digest: hash_from_row(row, 2)?,
+            result: row.get(3)?,
+            created_at: row.get(4)?,
+        })
+    }
+}
+
+/// Get the audit log entries recorded between `start` and `end` (inclusive, as UNIX timestamps)
+pub fn db_audit_log(
+    db_path: &Path,
+    start: u32,
+    end: u32,
+) -> Result<Vec<DbAuditEntry>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM audit_log WHERE timestamp >= (?1) AND timestamp <= (?2) ORDER BY id",
+        params![start, end],
+        |row| row.try_into(),
+    )
+}
+
+/// Walk the audit log hash chain, returning the id of the first entry that doesn't commit to
+/// its content or to the previous entry. None if the whole chain is valid.
+pub fn db_verify_audit_log(db_path: &Path) -> Result<Option<i64>, DatabaseError> {
+    let entries: Vec<DbAuditEntry> = db_query(
+        db_path,
+        "SELECT * FROM audit_log ORDER BY id",
+        params![],
+        |row| row.try_into(),
+    )?;
+
+    let mut prev_hash = sha256::Hash::from_inner([0; 32]);
+    for entry in entries {
+        if entry.prev_hash != prev_hash || entry.hash != entry.expected_hash() {
+            return Ok(Some(entry.id));
+        }
+        prev_hash = entry.hash;
+    }
+
+    Ok(None)
+}
+
+fn status_from_row(row: &Row, index: usize) -> Result<VaultStatus, rusqlite::Error> {
+    let status = row.get::<_, u32>(index)?;
+    status.try_into().map_err(|_| {
+        FromSqlError::Other(Box::new(DatabaseError(format!(
+            "Unknown vault status '{}'",
+            status
+        ))))
+        .into()
+    })
+}
+
+impl TryFrom<&Row<'_>> for DbVaultStatusChange {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let deposit_txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(4)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+        let final_txid = row
+            .get::<_, Option<Vec<u8>>>(6)?
+            .map(|raw_txid| encode::deserialize(&raw_txid).expect("We only store valid txids"));
+
+        Ok(DbVaultStatusChange {
+            id: row.get(0)?,
+            vault_id: row.get(1)?,
+            old_status: status_from_row(row, 2)?,
+            new_status: status_from_row(row, 3)?,
+            deposit_outpoint: OutPoint {
+                txid: deposit_txid,
+                vout: row.get(5)?,
+            },
+            amount: Amount::from_sat(row.get::<_, i64>(7)? as u64),
+            final_txid,
+        })
+    }
+}
+
+/// Get the vault status transitions recorded since they were last cleared, oldest first.
+pub fn db_vault_status_changes(db_path: &Path) -> Result<Vec<DbVaultStatusChange>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT changes.id, changes.vault_id, changes.old_status, changes.new_status, \
+         vaults.deposit_txid, vaults.deposit_vout, vaults.final_txid, vaults.amount \
+         FROM vault_status_changes changes \
+         INNER JOIN vaults ON vaults.id = changes.vault_id \
+         ORDER BY changes.id",
+        params![],
+        |row| row.try_into(),
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbVaultTransition {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(DbVaultTransition {
+            vault_id: row.get(0)?,
+            status: status_from_row(row, 1)?,
+            blockheight: row.get(2)?,
+        })
+    }
+}
+
+/// Get the history of the status of this vault, in the order it was recorded.
+pub fn db_vault_transitions(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Vec<DbVaultTransition>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vault_id, status, blockheight FROM vault_transitions \
+         WHERE vault_id = (?1) ORDER BY id",
+        params![vault_id],
+        |row| row.try_into(),
+    )
+}
+
+const VAULT_BY_CANCEL_TXID_QUERY: &str = "SELECT vaults.* FROM presigned_transactions as ptx \
+     INNER JOIN vaults ON vaults.id = ptx.vault_id \
+     WHERE ptx.txid = (?1) AND ptx.type = (?2)";
+
+/// Get the vault whose Cancel transaction has this txid, if any.
+pub fn db_vault_by_cancel_txid(
+    db_path: &Path,
+    txid: &Txid,
+) -> Result<Option<DbVault>, DatabaseError> {
+    db_query(
+        db_path,
+        VAULT_BY_CANCEL_TXID_QUERY,
+        params![txid.to_vec(), TransactionType::Cancel as u32],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+/// Get the vault whose Cancel transaction has this txid, if any, from an existing database
+/// transaction.
+pub fn db_vault_by_cancel_txid_dbtx(
+    db_tx: &Transaction,
+    txid: &Txid,
+) -> Result<Option<DbVault>, DatabaseError> {
+    db_query_tx(
+        db_tx,
+        VAULT_BY_CANCEL_TXID_QUERY,
+        params![txid.to_vec(), TransactionType::Cancel as u32],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+const VAULT_PARENT_QUERY: &str = "SELECT vaults.* FROM vault_successors \
+     INNER JOIN vaults ON vaults.id = vault_successors.parent_id \
+     WHERE vault_successors.child_id = (?1)";
+
+/// Get the canceled vault whose Cancel transaction created this vault, if any.
+pub fn db_vault_parent(db_path: &Path, vault_id: u32) -> Result<Option<DbVault>, DatabaseError> {
+    db_query(db_path, VAULT_PARENT_QUERY, params![vault_id], |row| {
+        row.try_into()
+    })
+    .map(|mut rows| rows.pop())
+}
+
+/// Get the canceled vault whose Cancel transaction created this vault, if any, from an existing
+/// database transaction.
+pub fn db_vault_parent_dbtx(
+    db_tx: &Transaction,
+    vault_id: u32,
+) -> Result<Option<DbVault>, DatabaseError> {
+    db_query_tx(db_tx, VAULT_PARENT_QUERY, params![vault_id], |row| {
+        row.try_into()
+    })
+    .map(|mut rows| rows.pop())
+}
+
+/// Get the vault created by the Cancel transaction of this vault, if any.
+pub fn db_vault_child(db_path: &Path, vault_id: u32) -> Result<Option<DbVault>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vaults.* FROM vault_successors \
+         INNER JOIN vaults ON vaults.id = vault_successors.child_id \
+         WHERE vault_successors.parent_id = (?1)",
+        params![vault_id],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+fn deposit_origin(origin: u32) -> rusqlite::Result<DepositOrigin> {
+    DepositOrigin::try_from(origin).map_err(|_| {
+        FromSqlError::Other(Box::new(DatabaseError(format!(
+            "Unknown deposit origin '{}'",
+            origin
+        ))))
+        .into()
+    })
+}
+
+/// Get how the funds of this vault got to us. Vaults we didn't record it for are assumed to
+/// come from an external deposit.
+pub fn db_vault_origin(db_path: &Path, vault_id: u32) -> Result<DepositOrigin, DatabaseError> {
+    Ok(db_query(
+        db_path,
+        "SELECT origin FROM vault_origins WHERE vault_id = (?1)",
+        params![vault_id],
+        |row| deposit_origin(row.get(0)?),
+    )?
+    .pop()
+    .unwrap_or(DepositOrigin::External))
+}
+
+/// Get the recorded origin of the funds of all the vaults, by vault id.
+pub fn db_vault_origins(db_path: &Path) -> Result<HashMap<u32, DepositOrigin>, DatabaseError> {
+    let mut origins = HashMap::new();
+
+    db_query(
+        db_path,
+        "SELECT vault_id, origin FROM vault_origins",
+        params![],
+        |row| {
+            origins.insert(row.get(0)?, deposit_origin(row.get(1)?)?);
+            Ok(())
+        },
+    )?;
+
+    Ok(origins)
+}
+
+/// Get the vaults consumed by the Spend transaction whose change output created this vault.
+pub fn db_vault_change_sources(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Vec<DbVault>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vaults.* FROM vault_change_sources \
+         INNER JOIN vaults ON vaults.id = vault_change_sources.parent_id \
+         WHERE vault_change_sources.child_id = (?1) ORDER BY vaults.id",
+        params![vault_id],
+        |row| row.try_into(),
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbRevocationCheck {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let deposit_txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(1)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+        let rejected_txid = row
+            .get::<_, Option<Vec<u8>>>(4)?
+            .map(|raw_txid| encode::deserialize(&raw_txid).expect("We only store valid txids"));
+
+        Ok(DbRevocationCheck {
+            vault_id: row.get(0)?,
+            deposit_outpoint: OutPoint {
+                txid: deposit_txid,
+                vout: row.get(2)?,
+            },
+            checked_at: row.get(3)?,
+            rejected_txid,
+            reject_reason: row.get(5)?,
+        })
+    }
+}
+
+/// Get the result of the last check of the revocation transactions of the vaults that are
+/// still `Secured` or `Active`.
+pub fn db_revocation_checks(db_path: &Path) -> Result<Vec<DbRevocationCheck>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT checks.vault_id, vaults.deposit_txid, vaults.deposit_vout, checks.checked_at, \
+         checks.rejected_txid, checks.reject_reason \
+         FROM revocation_checks checks \
+         INNER JOIN vaults ON vaults.id = checks.vault_id \
+         WHERE vaults.status IN ((?1), (?2))",
+        params![VaultStatus::Secured as u32, VaultStatus::Active as u32],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the last time we checked the revocation transactions of any vault, if ever.
+pub fn db_last_revocation_check(db_path: &Path) -> Result<Option<u32>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT MAX(checked_at) FROM revocation_checks",
+        params![],
+        |row| row.get::<_, Option<u32>>(0),
+    )
+    .map(|mut rows| rows.pop().flatten())
+}
+
+/// Get the highest derivation index we ever received a deposit at, if any.
+pub fn db_max_deposit_index(db_path: &Path) -> Result<Option<DerivationIndex>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT MAX(derivation_index) FROM vaults",
+        params![],
+        |row| row.get::<_, Option<DerivationIndex>>(0),
+    )
+    .map(|mut rows| rows.pop().flatten())
+}
+
+/// Get the height at which the Unvault transaction of this vault confirmed, if it did.
+pub fn db_unvault_height(db_path: &Path, vault_id: u32) -> Result<Option<u32>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT blockheight FROM unvault_confirmations WHERE vault_id = (?1)",
+        params![vault_id],
+        |row| row.get::<_, u32>(0),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+const UNVAULTED_HEIGHTS_QUERY: &str = "SELECT vaults.*, uc.blockheight FROM vaults \
+     INNER JOIN unvault_confirmations as uc ON uc.vault_id = vaults.id \
+     WHERE vaults.status = (?1)";
+
+fn vault_and_height_from_row(row: &Row) -> rusqlite::Result<(DbVault, u32)> {
+    let db_vault: DbVault = row.try_into()?;
+    let unvault_height: u32 = row.get(13)?;
+
+    Ok((db_vault, unvault_height))
+}
+
+/// Get the 'unvaulted' vaults along with the height at which their Unvault transaction
+/// confirmed.
+pub fn db_unvaulted_heights(db_path: &Path) -> Result<Vec<(DbVault, u32)>, DatabaseError> {
+    db_query(
+        db_path,
+        UNVAULTED_HEIGHTS_QUERY,
+        params![VaultStatus::Unvaulted as u32],
+        vault_and_height_from_row,
+    )
+}
+
+/// Same as [db_unvaulted_heights], from an existing database transaction.
+pub fn db_unvaulted_heights_dbtx(
+    db_tx: &Transaction,
+) -> Result<Vec<(DbVault, u32)>, DatabaseError> {
+    db_query_tx(
+        db_tx,
+        UNVAULTED_HEIGHTS_QUERY,
+        params![VaultStatus::Unvaulted as u32],
+        vault_and_height_from_row,
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbDerivedScript {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let script_pubkey = Script::from(row.get::<_, Vec<u8>>(0)?);
+        let derivation_index: DerivationIndex = row.get(1)?;
+        let kind = row.get::<_, u32>(2)?;
+        let kind = ScriptKind::try_from(kind).map_err(|_| {
+            FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Unknown script kind '{}'",
+                kind
+            ))))
+        })?;
+
+        Ok(DbDerivedScript {
+            script_pubkey,
+            derivation_index,
+            kind,
+        })
+    }
+}
+
+/// Get all the scripts we derived from our descriptors.
+pub fn db_derived_scripts(db_path: &Path) -> Result<Vec<DbDerivedScript>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT script_pubkey, derivation_index, kind FROM derived_scripts",
+        params![],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the scripts we derived from the descriptors of this wallet.
+pub fn db_wallet_derived_scripts(
+    db_path: &Path,
+    wallet_id: u32,
+) -> Result<Vec<DbDerivedScript>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT script_pubkey, derivation_index, kind FROM derived_scripts WHERE wallet_id = (?1)",
+        params![wallet_id],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the highest derivation index up to which we imported the addresses of this kind of this
+/// wallet into its watchonly wallet, if we ever did.
+pub fn db_imported_index(
+    db_path: &Path,
+    wallet_id: u32,
+    kind: ScriptKind,
+) -> Result<Option<DerivationIndex>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT derivation_index FROM imported_addresses WHERE wallet_id = (?1) AND kind = (?2)",
+        params![wallet_id, kind as u32],
+        |row| row.get::<_, DerivationIndex>(0),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+/// Get the ranges of derivation indexes of this kind of this wallet we imported the addresses
+/// of into its watchonly wallet, in the order of their indexes.
+pub fn db_imported_ranges(
+    db_path: &Path,
+    wallet_id: u32,
+    kind: ScriptKind,
+) -> Result<Vec<DbImportedRange>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT first_index, last_index, first_needed_timestamp FROM imported_ranges \
+         WHERE wallet_id = (?1) AND kind = (?2) ORDER BY first_index",
+        params![wallet_id, kind as u32],
+        |row| {
+            Ok(DbImportedRange {
+                first_index: row.get(0)?,
+                last_index: row.get(1)?,
+                first_needed_timestamp: row.get(2)?,
+            })
+        },
+    )
+}
+
+/// Get the time from which bitcoind needs to rescan the chain for coins sent to the addresses
+/// of this kind from this derivation index onward. Another participant may hand out addresses
+/// beyond the ones we watch, so an address may have been paid as soon as the range preceding
+/// the one it's in was needed. The creation of the wallet if we never imported a range at or
+/// below this index.
+pub fn db_rescan_timestamp(
+    db_path: &Path,
+    wallet_id: u32,
+    kind: ScriptKind,
+    from_index: DerivationIndex,
+) -> Result<u32, DatabaseError> {
+    let ranges = db_imported_ranges(db_path, wallet_id, kind)?;
+    // The ranges were needed one after the other, the earliest is the one of the lowest index.
+    let preceding = ranges
+        .iter()
+        .filter(|range| range.last_index < from_index)
+        .last();
+    let containing = ranges
+        .iter()
+        .find(|range| range.first_index <= from_index && from_index <= range.last_index);
+
+    match preceding.or(containing) {
+        Some(range) => Ok(range.first_needed_timestamp),
+        None => db_query(
+            db_path,
+            "SELECT timestamp FROM wallets WHERE id = (?1)",
+            params![wallet_id],
+            |row| row.get::<_, u32>(0),
+        )?
+        .pop()
+        .ok_or_else(|| DatabaseError(format!("No wallet with id '{}'", wallet_id))),
+    }
+}
+
+impl TryFrom<&Row<'_>> for DbVaultFlag {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let kind = row.get::<_, u32>(2)?;
+        let kind = VaultFlagKind::try_from(kind).map_err(|_| {
+            FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Unknown vault flag kind '{}'",
+                kind
+            ))))
+        })?;
+
+        Ok(DbVaultFlag {
+            id: row.get(0)?,
+            vault_id: row.get(1)?,
+            kind,
+            message: row.get(3)?,
+            created_at: row.get(4)?,
+            cleared_at: row.get(5)?,
+        })
+    }
+}
+
+/// Get all the flags ever raised on this vault, including the cleared ones, by creation order.
+pub fn db_vault_flags(db_path: &Path, vault_id: u32) -> Result<Vec<DbVaultFlag>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM vault_flags WHERE vault_id = (?1) ORDER BY id",
+        params![vault_id],
+        |row| row.try_into(),
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbEmergencyOutcome {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let kind = row.get::<_, u32>(1)?;
+        let kind = EmergencyOutcomeKind::try_from(kind).map_err(|_| {
+            FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Unknown emergency outcome '{}'",
+                kind
+            ))))
+        })?;
+        let txid = row
+            .get::<_, Option<Vec<u8>>>(2)?
+            .map(|raw_txid| encode::deserialize(&raw_txid).expect("We only store valid txids"));
+
+        Ok(DbEmergencyOutcome {
+            vault_id: row.get(0)?,
+            kind,
+            txid,
+            detail: row.get(3)?,
+        })
+    }
+}
+
+/// Get the txids of the emergency transactions any run of the emergency procedure broadcast.
+pub fn db_emergency_broadcast_txids(db_path: &Path) -> Result<HashSet<Txid>, DatabaseError> {
+    db_query::<_, _, DbEmergencyOutcome>(
+        db_path,
+        "SELECT vault_id, outcome, txid, detail FROM emergency_outcomes WHERE outcome = (?1)",
+        params![EmergencyOutcomeKind::Broadcast as u32],
+        |row| row.try_into(),
+    )
+    .map(|outcomes| outcomes.into_iter().filter_map(|o| o.txid).collect())
+}
+
+/// Get the runs of the emergency procedure we did not notify yet, oldest first.
+pub fn db_unnotified_emergency_runs(db_path: &Path) -> Result<Vec<DbEmergencyRun>, DatabaseError> {
+    let runs = db_query(
+        db_path,
+        "SELECT id, ran_at FROM emergency_runs WHERE notified = 0 ORDER BY id",
+        params![],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, u32>(1)?)),
+    )?;
+
+    runs.into_iter()
+        .map(|(id, ran_at)| {
+            let outcomes = db_query(
+                db_path,
+                "SELECT vault_id, outcome, txid, detail FROM emergency_outcomes \
+                 WHERE run_id = (?1) ORDER BY id",
+                params![id],
+                |row| row.try_into(),
+            )?;
+            Ok(DbEmergencyRun {
+                id,
+                ran_at,
+                outcomes,
+            })
+        })
+        .collect()
+}
+
+impl TryFrom<&Row<'_>> for DbDepositAbandonment {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(DbDepositAbandonment {
+            id: row.get(0)?,
+            vault_id: row.get(1)?,
+            abandoned_at: row.get(2)?,
+            resurrected_at: row.get(3)?,
+        })
+    }
+}
+
+/// Get the times this vault was abandoned and resurrected, oldest first.
+pub fn db_deposit_abandonments(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Vec<DbDepositAbandonment>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM deposit_abandonments WHERE vault_id = (?1) ORDER BY id",
+        params![vault_id],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the ids of the vaults that are currently abandoned.
+pub fn db_abandoned_vaults(db_path: &Path) -> Result<HashSet<u32>, DatabaseError> {
+    Ok(db_query(
+        db_path,
+        "SELECT vault_id FROM deposit_abandonments WHERE resurrected_at IS NULL",
+        params![],
+        |row| row.get(0),
+    )?
+    .into_iter()
+    .collect())
+}
+
+/// Get the txids of the revocation transactions kept for this vault until its deposit confirms
+/// again.
+pub fn db_deferred_revocation_txids(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Vec<Txid>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT txid FROM deferred_revocation_txs WHERE vault_id = (?1) ORDER BY type",
+        params![vault_id],
+        |row| {
+            let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(0)?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+            Ok(txid)
+        },
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbDepositAncestry {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(DbDepositAncestry {
+            vault_id: row.get(0)?,
+            ancestors: row.get(1)?,
+            vsize: row.get(2)?,
+            depth: row.get(3)?,
+        })
+    }
+}
+
+/// Get the unconfirmed transactions this vault's deposit depended on when we detected it, if
+/// there were any.
+pub fn db_deposit_ancestry(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Option<DbDepositAncestry>, DatabaseError> {
+    Ok(db_query(
+        db_path,
+        "SELECT * FROM deposit_ancestries WHERE vault_id = (?1)",
+        params![vault_id],
+        |row| row.try_into(),
+    )?
+    .pop())
+}
+
+const DEPOSIT_COINBASE_QUERY: &str =
+    "SELECT blockheight FROM deposit_coinbases WHERE vault_id = (?1)";
+
+/// Get the height of the coinbase transaction that created this vault's deposit, if it was
+/// created by one.
+pub fn db_deposit_coinbase_height(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Option<u32>, DatabaseError> {
+    db_query(db_path, DEPOSIT_COINBASE_QUERY, params![vault_id], |row| {
+        row.get(0)
+    })
+    .map(|mut rows| rows.pop())
+}
+
+/// Get the height of the coinbase transaction that created this vault's deposit, if it was
+/// created by one, from an existing database transaction.
+pub fn db_deposit_coinbase_height_dbtx(
+    db_tx: &Transaction,
+    vault_id: u32,
+) -> Result<Option<u32>, DatabaseError> {
+    db_query_tx(db_tx, DEPOSIT_COINBASE_QUERY, params![vault_id], |row| {
+        row.get(0)
+    })
+    .map(|mut rows| rows.pop())
+}
+
+const VAULT_EMERGENCY_ADDRESS_QUERY: &str =
+    "SELECT address FROM vault_emergency_addresses WHERE vault_id = (?1)";
+
+fn emergency_address_from_row(row: &Row) -> rusqlite::Result<Address> {
+    Address::from_str(&row.get::<_, String>(0)?)
+        .map_err(|e| FromSqlError::Other(Box::new(e)).into())
+}
+
+/// Get the address recorded for the Emergency transactions of this vault, if any.
+pub fn db_vault_emergency_address(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Option<Address>, DatabaseError> {
+    db_query(
+        db_path,
+        VAULT_EMERGENCY_ADDRESS_QUERY,
+        params![vault_id],
+        emergency_address_from_row,
+    )
+    .map(|mut rows| rows.pop())
+}
+
+/// Get the address recorded for the Emergency transactions of this vault, if any, from an
+/// existing database transaction.
+pub fn db_vault_emergency_address_dbtx(
+    db_tx: &Transaction,
+    vault_id: u32,
+) -> Result<Option<Address>, DatabaseError> {
+    db_query_tx(
+        db_tx,
+        VAULT_EMERGENCY_ADDRESS_QUERY,
+        params![vault_id],
+        emergency_address_from_row,
+    )
+    .map(|mut rows| rows.pop())
+}
+
+// The id, feerate, creation and expiration dates of a "spend_proposals" row.
+fn spend_proposal_row(row: &Row) -> rusqlite::Result<(u32, i64, u32, u32)> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+}
+
+// Fetch the inputs and outputs of the spend proposal in this "spend_proposals" row.
+fn spend_proposal_from_row(
+    db_path: &Path,
+    (id, feerate, created_at, expires_at): (u32, i64, u32, u32),
+) -> Result<DbSpendProposal, DatabaseError> {
+    let outpoints = db_query(
+        db_path,
+        "SELECT deposit_txid, deposit_vout FROM spend_proposal_inputs \
+         WHERE proposal_id = (?1) ORDER BY id",
+        params![id],
+        |row| {
+            let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(0)?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+            Ok(OutPoint {
+                txid,
+                vout: row.get(1)?,
+            })
+        },
+    )?;
+    let destinations = db_query(
+        db_path,
+        "SELECT address, amount FROM spend_proposal_outputs WHERE proposal_id = (?1)",
+        params![id],
+        |row| {
+            let address = Address::from_str(&row.get::<_, String>(0)?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+            Ok((address, Amount::from_sat(row.get::<_, i64>(1)? as u64)))
+        },
+    )?
+    .into_iter()
+    .collect();
+
+    Ok(DbSpendProposal {
+        id,
+        outpoints,
+        destinations,
+        feerate: feerate as u64,
+        created_at,
+        expires_at,
+    })
+}
+
+/// Get all the spend proposals, by creation order.
+pub fn db_spend_proposals(db_path: &Path) -> Result<Vec<DbSpendProposal>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM spend_proposals ORDER BY id",
+        params![],
+        spend_proposal_row,
+    )?
+    .into_iter()
+    .map(|row| spend_proposal_from_row(db_path, row))
+    .collect()
+}
+
+/// Get the spend proposal with this id, if any.
+pub fn db_spend_proposal(
+    db_path: &Path,
+    proposal_id: u32,
+) -> Result<Option<DbSpendProposal>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM spend_proposals WHERE id = (?1)",
+        params![proposal_id],
+        spend_proposal_row,
+    )?
+    .pop()
+    .map(|row| spend_proposal_from_row(db_path, row))
+    .transpose()
+}
+
+impl TryFrom<&Row<'_>> for DbSpendProposalAck {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let fingerprint = row.get::<_, Vec<u8>>(2)?;
+        if fingerprint.len() != 4 {
+            return Err(FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Invalid xpub fingerprint '{}'",
+                fingerprint.to_hex()
+            ))))
+            .into());
+        }
+
+        Ok(DbSpendProposalAck {
+            id: row.get(0)?,
+            proposal_id: row.get(1)?,
+            fingerprint: Fingerprint::from(&fingerprint[..]),
+            approved: row.get(3)?,
+            signature: row.get(4)?,
+            created_at: row.get(5)?,
+        })
+    }
+}
+
+/// Get the decisions of the managers about this spend proposal, by creation order.
+pub fn db_spend_proposal_acks(
+    db_path: &Path,
+    proposal_id: u32,
+) -> Result<Vec<DbSpendProposalAck>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM spend_proposal_acks WHERE proposal_id = (?1) ORDER BY id",
+        params![proposal_id],
+        |row| row.try_into(),
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbSpendDestination {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let script_pubkey = Script::from(row.get::<_, Vec<u8>>(0)?);
+        let spend_txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(1)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+        let used_at = row.get(2)?;
+
+        Ok(DbSpendDestination {
+            script_pubkey,
+            spend_txid,
+            used_at,
+        })
+    }
+}
+
+/// Get the first confirmed Spend that paid to this external script, if any
+pub fn db_spend_destination(
+    db_path: &Path,
+    script_pubkey: &Script,
+) -> Result<Option<DbSpendDestination>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT script_pubkey, spend_txid, used_at FROM spend_destinations \
+         WHERE script_pubkey = (?1)",
+        params![script_pubkey.as_bytes()],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+/// Get the last descriptor that was stored as generating the Emergency address, if any
+pub fn db_last_emergency_descriptor(
+    db_path: &Path,
+) -> Result<Option<DbEmergencyDescriptor>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT id, descriptor, derivation_index, stored_at FROM emergency_descriptors \
+         ORDER BY id DESC LIMIT 1",
+        params![],
+        |row| {
+            Ok(DbEmergencyDescriptor {
+                id: row.get(0)?,
+                descriptor: row.get(1)?,
+                derivation_index: row.get(2)?,
+                stored_at: row.get(3)?,
+            })
+        },
+    )
+    .map(|mut rows| rows.pop())
+}
+
+fn signing_context_from_row(
+    row: &Row,
+    index_offset: usize,
+) -> Result<DbSigningContext, rusqlite::Error> {
+    Ok(DbSigningContext {
+        presigned_id: row.get(index_offset)?,
+        feerate: row.get::<_, i64>(index_offset + 1)? as u64,
+        next_block_estimate: row
+            .get::<_, Option<i64>>(index_offset + 2)?
+            .map(|f| f as u64),
+        next_day_estimate: row
+            .get::<_, Option<i64>>(index_offset + 3)?
+            .map(|f| f as u64),
+        blockheight: row.get(index_offset + 4)?,
+        signed_at: row.get(index_offset + 5)?,
+    })
+}
+
+/// Get the fee market at the time the presigned transactions of this vault got fully signed.
+/// There is no entry for the transactions that aren't, or that were signed before we recorded
+/// it.
+pub fn db_vault_signing_contexts(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Vec<DbSigningContext>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT sc.presigned_id, sc.feerate, sc.next_block_estimate, sc.next_day_estimate, \
+         sc.blockheight, sc.signed_at FROM signing_contexts as sc \
+         INNER JOIN presigned_transactions as ptx ON ptx.id = sc.presigned_id \
+         WHERE ptx.vault_id = (?1)",
+        params![vault_id],
+        |row| signing_context_from_row(row, 0),
+    )
+}
+
+/// Get the feerate, at the time they got fully signed, of the presigned transactions of the
+/// vaults that are `Secured` or `Active`, along with the deposit outpoint of their vault and
+/// their txid.
+pub fn db_signed_feerates(db_path: &Path) -> Result<Vec<(OutPoint, Txid, u64)>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vaults.deposit_txid, vaults.deposit_vout, ptx.txid, sc.feerate \
+         FROM signing_contexts as sc \
+         INNER JOIN presigned_transactions as ptx ON ptx.id = sc.presigned_id \
+         INNER JOIN vaults ON vaults.id = ptx.vault_id \
+         WHERE vaults.status IN ((?1), (?2)) ORDER BY vaults.id, ptx.type",
+        params![VaultStatus::Secured as u32, VaultStatus::Active as u32],
+        |row| {
+            let deposit_txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(0)?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+            let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(2)?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+            let feerate: i64 = row.get(3)?;
+            Ok((
+                OutPoint {
+                    txid: deposit_txid,
+                    vout: row.get(1)?,
+                },
+                txid,
+                feerate as u64,
+            ))
+        },
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbFinalTxid {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let db_tx_type: u32 = row.get(1)?;
+        let tx_type: TransactionType = db_tx_type.try_into().map_err(|_| {
+            FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Unsane db: got an invalid tx type: '{}'",
+                db_tx_type
+            ))))
+        })?;
+        let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(2)?).expect("We store it");
+
+        Ok(DbFinalTxid {
+            vault_id: row.get(0)?,
+            tx_type,
+            txid,
+            computed_at: row.get(3)?,
+        })
+    }
+}
+
+/// Get the final txids of the fully signed presigned transactions of all vaults, by vault.
+pub fn db_final_txids(db_path: &Path) -> Result<Vec<DbFinalTxid>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT ptx.vault_id, ptx.type, ft.txid, ft.computed_at FROM final_txids as ft \
+         INNER JOIN presigned_transactions as ptx ON ptx.id = ft.presigned_id \
+         ORDER BY ptx.vault_id, ptx.type",
+        params![],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the final txids of the fully signed presigned transactions of this vault.
+pub fn db_vault_final_txids(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Vec<DbFinalTxid>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT ptx.vault_id, ptx.type, ft.txid, ft.computed_at FROM final_txids as ft \
+         INNER JOIN presigned_transactions as ptx ON ptx.id = ft.presigned_id \
+         WHERE ptx.vault_id = (?1) ORDER BY ptx.type",
+        params![vault_id],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the revocation transactions of the vaults that are still `Secured`, `Activating` or
+/// `Active` which were signed at a feerate below `feerate` (in sats/vbyte), along with their
+/// type, txid and the fee market when they were.
+pub fn db_stale_revocations(
+    db_path: &Path,
+    feerate: u64,
+) -> Result<Vec<(DbVault, TransactionType, Txid, DbSigningContext)>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vaults.*, ptx.type, ptx.txid, sc.presigned_id, sc.feerate, \
+         sc.next_block_estimate, sc.next_day_estimate, sc.blockheight, sc.signed_at \
+         FROM signing_contexts as sc \
+         INNER JOIN presigned_transactions as ptx ON ptx.id = sc.presigned_id \
+         INNER JOIN vaults ON vaults.id = ptx.vault_id \
+         WHERE ptx.type IN ((?1), (?2), (?3)) AND sc.feerate < (?4) \
+         AND vaults.status IN ((?5), (?6), (?7)) \
+         ORDER BY vaults.id, ptx.type",
+        params![
+            TransactionType::Cancel as u32,
+            TransactionType::Emergency as u32,
+            TransactionType::UnvaultEmergency as u32,
+            feerate as i64,
+            VaultStatus::Secured as u32,
+            VaultStatus::Activating as u32,
+            VaultStatus::Active as u32,
+        ],
+        |row| {
+            let db_vault: DbVault = row.try_into()?;
+            let tx_type = row.get::<_, u32>(13)?;
+            let tx_type: TransactionType = tx_type.try_into().map_err(|_| {
+                FromSqlError::Other(Box::new(DatabaseError(format!(
+                    "Unsane db: got an invalid tx type: '{}'",
+                    tx_type
+                ))))
+            })?;
+            let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(14)?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+
+            Ok((db_vault, tx_type, txid, signing_context_from_row(row, 15)?))
+        },
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbWatchData {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(0)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+
+        Ok(DbWatchData {
+            deposit_outpoint: OutPoint {
+                txid,
+                vout: row.get(1)?,
+            },
+            sequence: row.get::<_, i64>(2)? as u64,
+            revoked: row.get(3)?,
+        })
+    }
+}
+
+/// Get the watchdata entries that changed after this sequence number, by order of change
+pub fn db_watchdata(db_path: &Path, since: u64) -> Result<Vec<DbWatchData>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT deposit_txid, deposit_vout, sequence, revoked FROM watchdata \
+         WHERE sequence > (?1) ORDER BY sequence",
+        params![since as i64],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the watchdata entries that changed after this sequence number, by order of change, from
+/// an already-created transaction
+pub fn db_watchdata_dbtx(
+    db_tx: &Transaction,
+    since: u64,
+) -> Result<Vec<DbWatchData>, DatabaseError> {
+    db_query_tx(
+        db_tx,
+        "SELECT deposit_txid, deposit_vout, sequence, revoked FROM watchdata \
+         WHERE sequence > (?1) ORDER BY sequence",
+        params![since as i64],
+        |row| row.try_into(),
+    )
+}
+
+impl TryFrom<&Row<'_>> for DbWalletRotation {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(DbWalletRotation {
+            old_wallet_id: row.get(0)?,
+            new_wallet_id: row.get(1)?,
+            initiated_at: row.get(2)?,
+            completed_at: row.get(3)?,
+        })
+    }
+}
+
+/// Get the rotation of the keys to a new wallet that was initiated but not completed yet, if any
+pub fn db_pending_rotation(db_path: &Path) -> Result<Option<DbWalletRotation>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM wallet_rotations WHERE completed_at IS NULL",
+        params![],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+impl TryFrom<&Row<'_>> for DbVaultMigration {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let spend_txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(1)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+
+        Ok(DbVaultMigration {
+            vault_id: row.get(0)?,
+            spend_txid,
+            derivation_index: row.get(2)?,
+            created_at: row.get(3)?,
+        })
+    }
+}
+
+/// Get the migration of this vault to the new wallet of a rotation, if it's being migrated
+pub fn db_vault_migration(
+    db_path: &Path,
+    vault_id: u32,
+) -> Result<Option<DbVaultMigration>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM vault_migrations WHERE vault_id = (?1)",
+        params![vault_id],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| rows.pop())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::actions::{db_confirm_deposit, db_insert_new_unconfirmed_vault, setup_db};
+    use crate::utils::test_utils::{dummy_revaultd, test_datadir, UserRole};
+    use revault_tx::{bitcoin::OutPoint, transactions::transaction_chain};
+
+    use std::{fs, str::FromStr};
+
+    fn db_vault_set_final_txid(
         db_path: &Path,
         db_vault: &DbVault,
         final_txid: &Txid,
@@ -976,7 +2599,7 @@ mod test {
                 vout: i,
             };
             let amount = Amount::from_sat(3456798 * i as u64);
-            let derivation_index = ChildNumber::from(i * 10);
+            let derivation_index = DerivationIndex::new(i * 10).unwrap();
             db_insert_new_unconfirmed_vault(
                 &db_path,
                 wallet_id,
@@ -991,8 +2614,13 @@ mod test {
                 &revaultd.deposit_descriptor,
                 &revaultd.unvault_descriptor,
                 &revaultd.cpfp_descriptor,
-                derivation_index,
-                revaultd.emergency_address.clone().unwrap(),
+                derivation_index.into(),
+                revaultd
+                    .emergency_addresses
+                    .as_ref()
+                    .unwrap()
+                    .for_amount(amount)
+                    .clone(),
                 revaultd.lock_time,
                 &revaultd.secp_ctx,
             )
@@ -1006,6 +2634,7 @@ mod test {
                 &cancel_tx,
                 Some(&emer_tx),
                 Some(&unemer_tx),
+                1_600_000_000,
             )
             .unwrap();
         }
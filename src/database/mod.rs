@@ -3,6 +3,7 @@ pub mod bitcointx;
 pub mod interface;
 pub mod schema;
 
+use crate::revaultd::ScriptTypeError;
 use revault_tx::bitcoin::util::psbt::Error as PsbtError;
 
 // FIXME: make this an enum and have actual specific errors
@@ -35,4 +36,10 @@ impl From<PsbtError> for DatabaseError {
     }
 }
 
-pub const DB_VERSION: u32 = 0;
+impl From<ScriptTypeError> for DatabaseError {
+    fn from(e: ScriptTypeError) -> Self {
+        Self(format!("Script type error: {}", e))
+    }
+}
+
+pub const DB_VERSION: u32 = 36;
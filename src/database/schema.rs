@@ -1,16 +1,21 @@
 use crate::{
     database::bitcointx::{RevaultTx, TransactionType},
-    revaultd::VaultStatus,
+    derivation::DerivationIndex,
+    revaultd::{ScriptVersion, VaultStatus},
 };
 use revault_tx::{
     bitcoin::{
-        util::bip32::{ChildNumber, ExtendedPubKey},
-        Amount, OutPoint, Txid,
+        hashes::{sha256, Hash, HashEngine},
+        secp256k1,
+        util::bip32::{ExtendedPubKey, Fingerprint},
+        Address, Amount, OutPoint, Script, Txid,
     },
     scripts::{CpfpDescriptor, DepositDescriptor, UnvaultDescriptor},
     transactions::SpendTransaction,
 };
 
+use std::{collections::BTreeMap, convert::TryFrom, fmt, str::FromStr};
+
 pub const SCHEMA: &str = "\
 CREATE TABLE version (
     version INTEGER NOT NULL
@@ -22,6 +27,1414 @@ CREATE TABLE tip (
     blockhash BLOB NOT NULL
 );
 
+/* This stores metadata about our wallets. There is a single one, unless the
+ * keys were rotated (see wallet_rotations). This MUST be in sync with
+ * bitcoind's wallet.
+ * The script_version is the type of the scripts derived from the descriptors,
+ * only 0 (P2WSH) for now.
+ */
+CREATE TABLE wallets (
+    id INTEGER PRIMARY KEY NOT NULL,
+    timestamp INTEGER NOT NULL,
+    deposit_descriptor TEXT NOT NULL,
+    unvault_descriptor TEXT NOT NULL,
+    cpfp_descriptor TEXT NOT NULL,
+    our_manager_xpub TEXT,
+    our_stakeholder_xpub TEXT,
+    deposit_derivation_index INTEGER NOT NULL,
+    script_version INTEGER NOT NULL DEFAULT 0
+);
+
+/* This stores the vaults we heard about. The deposit may be unconfirmed,
+ * in which case the blockheight will be 0 (FIXME: should be NULL instead?).
+ * For any vault entry a deposit transaction MUST be present in bitcoind's
+ * wallet.
+ * The final_txid is stored to not harass bitcoind trying to guess the
+ * spending txid or the canceling txid out of a deposit outpoint.
+ * It MUST be NOT NULL if status is 'spending', 'spent', 'canceling'
+ * or 'canceled'.
+ */
+CREATE TABLE vaults (
+    id INTEGER PRIMARY KEY NOT NULL,
+    wallet_id INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    blockheight INTEGER NOT NULL,
+    deposit_txid BLOB NOT NULL,
+    deposit_vout INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    derivation_index INTEGER NOT NULL,
+    funded_at INTEGER,
+    secured_at INTEGER,
+    delegated_at INTEGER,
+    moved_at INTEGER,
+    final_txid BLOB,
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* This stores transactions we presign:
+ * - Emergency (only for stakeholders)
+ * - Unvault
+ * - Cancel
+ * - Unvault Emergency (only for stakeholders)
+ */
+CREATE TABLE presigned_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    type INTEGER NOT NULL,
+    psbt BLOB UNIQUE NOT NULL,
+    txid BLOB UNIQUE NOT NULL,
+    fullysigned BOOLEAN NOT NULL CHECK (fullysigned IN (0,1)),
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* A bridge between the Unvault transactions a Spend transaction
+ * may refer and the possible Spend transactions an Unvault one
+ * may be associated with.
+ */
+CREATE TABLE spend_inputs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    unvault_id INTEGER NOT NULL,
+    spend_id INTEGER NOT NULL,
+    FOREIGN KEY (unvault_id) REFERENCES presigned_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE
+);
+
+/* This stores Spend transactions we created. A txid column is there to
+ * ease research.
+ * The 'broadcasted' column indicates wether a Spend transaction is:
+ *  - Not elligible for broadcast (NULL)
+ *  - Waiting to be broadcasted (0)
+ *  - Already broadcasted (1)
+ * The 'has_priority' column indicates wether a Spend would automatically
+ * be CPFPed if not confirmed in the first block after broadcast
+ */
+CREATE TABLE spend_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    psbt BLOB UNIQUE NOT NULL,
+    txid BLOB UNIQUE NOT NULL,
+    broadcasted BOOLEAN CHECK (broadcasted IN (NULL, 0,1)),
+    has_priority BOOLEAN NOT NULL CHECK (has_priority IN (0,1)) DEFAULT 0
+);
+
+/* The signatures the cosigning servers sent us for a Spend transaction, so that
+ * we don't need to ask them again if announcing it fails. The psbt is the one
+ * they returned, containing only their signatures.
+ */
+CREATE TABLE spend_cosig_signatures (
+    id INTEGER PRIMARY KEY NOT NULL,
+    spend_id INTEGER NOT NULL,
+    cosig_noise_key BLOB NOT NULL,
+    psbt BLOB NOT NULL,
+    UNIQUE (spend_id, cosig_noise_key),
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE
+);
+
+/* Transactions of ours that bitcoind refused because they conflict with another
+ * transaction in its mempool. Typically a Cancel racing a Spend for the same Unvault
+ * output. The competing transaction may not be known when the conflict is detected, in
+ * which case it is filled once the poller finds the spender of the Unvault output. The
+ * confirmed_txid is set once the race is settled by the confirmation of either.
+ */
+CREATE TABLE mempool_conflicts (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    rejected_txid BLOB NOT NULL,
+    competing_txid BLOB,
+    confirmed_txid BLOB,
+    detected_at INTEGER NOT NULL,
+    UNIQUE (vault_id, rejected_txid),
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* An append-only log of the destructive commands we were asked to perform.
+ * Each entry commits to the previous one through prev_hash, so that tampering
+ * with an entry breaks the chain from there on. An entry is written before
+ * the command is executed (with a 'pending' result) and another one once it
+ * completed.
+ */
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY NOT NULL,
+    method TEXT NOT NULL,
+    params_digest BLOB NOT NULL,
+    result TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    peer_uid INTEGER,
+    prev_hash BLOB NOT NULL,
+    hash BLOB NOT NULL
+);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END;
+
+/* A journal of the vault status transitions, filled by a trigger so that no
+ * status update can be missed. Entries are consumed (and deleted) by the
+ * poller which runs the status change hooks.
+ */
+CREATE TABLE vault_status_changes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    old_status INTEGER NOT NULL,
+    new_status INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE TRIGGER vault_status_change AFTER UPDATE OF status ON vaults
+WHEN OLD.status != NEW.status
+BEGIN
+    INSERT INTO vault_status_changes (vault_id, old_status, new_status)
+    VALUES (NEW.id, OLD.status, NEW.status);
+END;
+
+/* The last time we checked bitcoind would accept the revocation transactions
+ * of a vault in its mempool, and the first one it rejected if any.
+ */
+CREATE TABLE revocation_checks (
+    vault_id INTEGER UNIQUE NOT NULL,
+    checked_at INTEGER NOT NULL,
+    rejected_txid BLOB,
+    reject_reason TEXT,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* Links a canceled vault to the vault created by the output of its Cancel
+ * transaction, which needs its own presigned transactions to be signed before
+ * the funds are secured again.
+ */
+CREATE TABLE vault_successors (
+    parent_id INTEGER UNIQUE NOT NULL,
+    child_id INTEGER UNIQUE NOT NULL,
+    FOREIGN KEY (parent_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (child_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The scripts we derived from our descriptors, along with their derivation
+ * index, so that we don't need to derive them again at each startup. The
+ * kind is either 0 (deposit) or 1 (unvault).
+ */
+CREATE TABLE derived_scripts (
+    id INTEGER PRIMARY KEY NOT NULL,
+    wallet_id INTEGER NOT NULL,
+    script_pubkey BLOB UNIQUE NOT NULL,
+    derivation_index INTEGER NOT NULL,
+    kind INTEGER NOT NULL CHECK (kind IN (0,1)),
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The height at which the Unvault transaction of a vault confirmed, to know
+ * when its relative timelock expires. Only relevant for 'unvaulted' and
+ * 'spendable' vaults.
+ */
+CREATE TABLE unvault_confirmations (
+    vault_id INTEGER UNIQUE NOT NULL,
+    blockheight INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The history of the status of each vault, along with the height of our tip
+ * when it reached it. Unlike vault_status_changes it is never pruned, and is
+ * used to reconstruct the status of the vaults as of a past height.
+ */
+CREATE TABLE vault_transitions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    blockheight INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE TRIGGER vault_transition_insert AFTER INSERT ON vaults
+BEGIN
+    INSERT INTO vault_transitions (vault_id, status, blockheight)
+    VALUES (NEW.id, NEW.status, COALESCE((SELECT blockheight FROM tip), 0));
+END;
+
+CREATE TRIGGER vault_transition_update AFTER UPDATE OF status ON vaults
+WHEN OLD.status != NEW.status
+BEGIN
+    INSERT INTO vault_transitions (vault_id, status, blockheight)
+    VALUES (NEW.id, NEW.status, COALESCE((SELECT blockheight FROM tip), 0));
+END;
+
+/* Problems about a vault that need the attention of an operator, such as its
+ * revocation transactions being rejected by bitcoind. A flag is active until
+ * an operator clears it, and may be raised again afterward. The kind is one
+ * of the VaultFlagKind variants.
+ */
+CREATE TABLE vault_flags (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    cleared_at INTEGER,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE UNIQUE INDEX vault_active_flags ON vault_flags (vault_id, kind)
+WHERE cleared_at IS NULL;
+
+/* The mutating commands sent with an idempotency key, so that a client retrying
+ * one (for instance after a timeout) gets the result of the first attempt instead
+ * of executing it twice. The result is NULL while the command is in progress, and
+ * the entry is removed if it failed. Entries expire after the configured retention.
+ */
+CREATE TABLE idempotency_keys (
+    key TEXT PRIMARY KEY NOT NULL,
+    method TEXT NOT NULL,
+    params_digest BLOB NOT NULL,
+    result TEXT,
+    created_at INTEGER NOT NULL
+);
+
+/* We never track two vaults for the same deposit outpoint. */
+CREATE UNIQUE INDEX vault_deposit_outpoint ON vaults (wallet_id, deposit_txid, deposit_vout);
+
+/* Spend proposals a manager drafted, which enough managers need to approve
+ * before a Spend transaction may be created for them if the approval workflow
+ * is enabled. They can't be acked nor used past expires_at.
+ */
+CREATE TABLE spend_proposals (
+    id INTEGER PRIMARY KEY NOT NULL,
+    feerate INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+/* The deposits a spend proposal would spend. */
+CREATE TABLE spend_proposal_inputs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    proposal_id INTEGER NOT NULL,
+    deposit_txid BLOB NOT NULL,
+    deposit_vout INTEGER NOT NULL,
+    FOREIGN KEY (proposal_id) REFERENCES spend_proposals (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The destinations of a spend proposal, the amount is in sats. */
+CREATE TABLE spend_proposal_outputs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    proposal_id INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    FOREIGN KEY (proposal_id) REFERENCES spend_proposals (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The decision of a manager, identified by the fingerprint of their xpub, about
+ * a spend proposal along with their signature of it. A manager may only decide
+ * once per proposal.
+ */
+CREATE TABLE spend_proposal_acks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    proposal_id INTEGER NOT NULL,
+    fingerprint BLOB NOT NULL,
+    approved BOOLEAN NOT NULL CHECK (approved IN (0,1)),
+    signature BLOB NOT NULL,
+    created_at INTEGER NOT NULL,
+    UNIQUE (proposal_id, fingerprint),
+    FOREIGN KEY (proposal_id) REFERENCES spend_proposals (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The external scripts a confirmed Spend transaction paid to, along with the
+ * first Spend that did, to detect address reuse. Our own scripts are never
+ * recorded here.
+ */
+CREATE TABLE spend_destinations (
+    id INTEGER PRIMARY KEY NOT NULL,
+    script_pubkey BLOB UNIQUE NOT NULL,
+    spend_txid BLOB NOT NULL,
+    used_at INTEGER NOT NULL
+);
+
+/* The descriptors of the stakeholders' deep-cold setup that were verified to
+ * generate the Emergency address, if we were asked to keep them for future
+ * re-verification. The derivation index is only set for a ranged descriptor.
+ */
+CREATE TABLE emergency_descriptors (
+    id INTEGER PRIMARY KEY NOT NULL,
+    descriptor TEXT NOT NULL,
+    derivation_index INTEGER,
+    stored_at INTEGER NOT NULL
+);
+
+/* How the funds of a vault got to us: an external deposit, the output of the
+ * Cancel transaction of another vault, or the change output of a Spend
+ * transaction. Vaults detected before we recorded it may not have an entry, in
+ * which case they are assumed to come from an external deposit.
+ */
+CREATE TABLE vault_origins (
+    vault_id INTEGER UNIQUE NOT NULL,
+    origin INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* Links a vault funded by the change output of a Spend transaction to the
+ * vaults this Spend consumed.
+ */
+CREATE TABLE vault_change_sources (
+    child_id INTEGER NOT NULL,
+    parent_id INTEGER NOT NULL,
+    UNIQUE (child_id, parent_id),
+    FOREIGN KEY (child_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (parent_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The periods during which an unconfirmed deposit was given up on, after its
+ * transaction left the mempool (for instance because it was replaced) and
+ * wasn't mined. A vault is abandoned as long as its last entry was not
+ * resurrected, which happens if the deposit shows up again.
+ */
+CREATE TABLE deposit_abandonments (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    abandoned_at INTEGER NOT NULL,
+    resurrected_at INTEGER,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The presigned transactions a Coordinator acknowledged having our signature
+ * for, keyed by its Noise static public key. We may have to send them again
+ * to another Coordinator after failing over to it.
+ */
+CREATE TABLE coordinator_sig_acks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    coordinator_key BLOB NOT NULL,
+    txid BLOB NOT NULL,
+    UNIQUE (coordinator_key, txid)
+);
+
+/* The highest derivation index up to which we imported the addresses of each
+ * kind into bitcoind's watchonly wallet, so that we only import the ones we
+ * derive afterward. The kind is either 0 (deposit) or 1 (unvault).
+ */
+CREATE TABLE imported_addresses (
+    wallet_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL CHECK (kind IN (0,1)),
+    derivation_index INTEGER NOT NULL,
+    UNIQUE (wallet_id, kind),
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The fee market when the signature set of a presigned transaction completed:
+ * the feerate its fixed fees imply, bitcoind's estimates of the feerate needed
+ * to confirm within 2 and 144 blocks (NULL if it had none), all in sat/vbyte,
+ * and the height of our tip then.
+ */
+CREATE TABLE signing_contexts (
+    presigned_id INTEGER UNIQUE NOT NULL,
+    feerate INTEGER NOT NULL,
+    next_block_estimate INTEGER,
+    next_day_estimate INTEGER,
+    blockheight INTEGER NOT NULL,
+    signed_at INTEGER NOT NULL,
+    FOREIGN KEY (presigned_id) REFERENCES presigned_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The vaults we passed to the watchtowers pulling from us through getwatchdata.
+ * Each change (a vault getting Active, or leaving this status) gets the next
+ * sequence number so that they can only fetch what changed since their last
+ * call. Rows are kept once revoked, as the vault itself may be gone.
+ */
+CREATE TABLE watchdata (
+    deposit_txid BLOB NOT NULL,
+    deposit_vout INTEGER NOT NULL,
+    sequence INTEGER UNIQUE NOT NULL,
+    revoked BOOLEAN NOT NULL CHECK (revoked IN (0,1)),
+    UNIQUE (deposit_txid, deposit_vout)
+);
+
+/* A rotation of the keys of the deployment to a new wallet. The old wallet
+ * remains the current one until the rotation is completed, at startup with the
+ * descriptors of the new one once all the vaults of the old one are gone.
+ */
+CREATE TABLE wallet_rotations (
+    old_wallet_id INTEGER UNIQUE NOT NULL,
+    new_wallet_id INTEGER UNIQUE NOT NULL,
+    initiated_at INTEGER NOT NULL,
+    completed_at INTEGER,
+    FOREIGN KEY (old_wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (new_wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The vaults being moved to the new wallet of a rotation, by the Spend
+ * transaction paying to its deposit address at this derivation index. They are
+ * removed along with the Spend transaction, so that the migration resumes.
+ */
+CREATE TABLE vault_migrations (
+    vault_id INTEGER UNIQUE NOT NULL,
+    spend_txid BLOB NOT NULL,
+    derivation_index INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The unconfirmed transactions the transaction of a deposit depended on when
+ * we detected it: their number, total virtual size and the length of the
+ * longest chain of them.
+ */
+CREATE TABLE deposit_ancestries (
+    vault_id INTEGER UNIQUE NOT NULL,
+    ancestors INTEGER NOT NULL,
+    vsize INTEGER NOT NULL,
+    depth INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The announcements of our Spend transactions to the Coordinator: when and to
+ * which Coordinator (by its Noise key) we announced it, and what the Coordinator
+ * reported for it when we reconciled at startup. The status is either:
+ *  - Announced, not checked against the Coordinator yet (0)
+ *  - Confirmed by the Coordinator (1)
+ *  - Adopted: the Coordinator had it but we had not recorded announcing it (2)
+ *  - Missing: the Coordinator did not have it anymore (3)
+ * The announcement time is NULL for adopted announcements, for which the
+ * Coordinator is the one that reported it.
+ */
+CREATE TABLE spend_announcements (
+    spend_id INTEGER UNIQUE NOT NULL,
+    coordinator_key BLOB,
+    announced_at INTEGER,
+    status INTEGER NOT NULL CHECK (status IN (0,1,2,3)),
+    reconciled_at INTEGER,
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE
+);
+
+/* The txid of the presigned transactions once fully signed, as computed by
+ * finalizing them when their signature set completed. Updated if they are ever
+ * signed again.
+ */
+CREATE TABLE final_txids (
+    presigned_id INTEGER UNIQUE NOT NULL,
+    txid BLOB NOT NULL,
+    computed_at INTEGER NOT NULL,
+    FOREIGN KEY (presigned_id) REFERENCES presigned_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The Spend transactions that can't confirm anymore, as a vault they spend was
+ * canceled. This may happen after the Unvault timelock expired, the Cancel
+ * remaining valid. They are kept for the record, but never broadcast. The
+ * deprecation time is the one of the block the Cancel was confirmed in.
+ */
+CREATE TABLE spend_deprecations (
+    spend_id INTEGER UNIQUE NOT NULL,
+    vault_id INTEGER NOT NULL,
+    cancel_txid BLOB NOT NULL,
+    deprecated_at INTEGER NOT NULL,
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The deposits created by a coinbase transaction, and the height it was mined
+ * at. Their output can't be spent before it matures, 100 blocks later.
+ */
+CREATE TABLE deposit_coinbases (
+    vault_id INTEGER UNIQUE NOT NULL,
+    blockheight INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The runs of the 'emergency' command, and whether the notify command was run
+ * for them yet. The poller notifies each run once, as a whole.
+ */
+CREATE TABLE emergency_runs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ran_at INTEGER NOT NULL,
+    notified INTEGER NOT NULL CHECK (notified IN (0,1)) DEFAULT 0
+);
+
+/* What became of each vault in a run of the 'emergency' command. The outcome
+ * is one of the EmergencyOutcomeKind variants. The txid is the one of the
+ * Emergency or Unvault Emergency transaction of the vault, if it has one, and
+ * the detail the status of a skipped vault or the reason bitcoind rejected the
+ * transaction.
+ */
+CREATE TABLE emergency_outcomes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    run_id INTEGER NOT NULL,
+    vault_id INTEGER NOT NULL,
+    outcome INTEGER NOT NULL,
+    txid BLOB,
+    detail TEXT,
+    FOREIGN KEY (run_id) REFERENCES emergency_runs (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The Spend transactions we announced but that never got any of their Unvault
+ * transactions broadcast, and were given up on: either automatically, as they
+ * did not progress for longer than the configured expiry, or through the
+ * 'abortspend' command. The reason is one of the SpendExpiryReason variants.
+ * They are kept for the record, but never broadcast.
+ */
+CREATE TABLE spend_expirations (
+    spend_id INTEGER UNIQUE NOT NULL,
+    reason INTEGER NOT NULL CHECK (reason IN (0,1)),
+    expired_at INTEGER NOT NULL,
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE
+);
+
+/* The revocation transactions a stakeholder signed for a vault whose deposit got
+ * unconfirmed while they were signing them. Our presigned transactions are dropped
+ * along with the confirmation, so these are kept until the deposit confirms again
+ * and the presigned transactions are created anew. The type is one of the
+ * TransactionType variants.
+ */
+CREATE TABLE deferred_revocation_txs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    type INTEGER NOT NULL,
+    psbt BLOB NOT NULL,
+    txid BLOB NOT NULL,
+    received_at INTEGER NOT NULL,
+    UNIQUE (vault_id, type),
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The ranges of derivation indexes of each kind whose addresses we imported
+ * into bitcoind's watchonly wallet, and when they were first needed: the
+ * creation of the wallet for the first one, the time the derivation index
+ * moved enough for us to import the following ones (the median time past of
+ * the tip then, to compare with block times). Rescans for the addresses of a
+ * range need not start before the range preceding it was needed. The kind is
+ * either 0 (deposit) or 1 (unvault).
+ */
+CREATE TABLE imported_ranges (
+    id INTEGER PRIMARY KEY NOT NULL,
+    wallet_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL CHECK (kind IN (0,1)),
+    first_index INTEGER NOT NULL,
+    last_index INTEGER NOT NULL,
+    first_needed_timestamp INTEGER NOT NULL,
+    UNIQUE (wallet_id, kind, first_index),
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The address the Emergency transactions of a vault pay to, as chosen by the
+ * configured amount bands when its deposit first confirmed. Changing the
+ * configuration later only affects new vaults. Vaults confirmed before it was
+ * recorded use the configured address for their amount.
+ */
+CREATE TABLE vault_emergency_addresses (
+    vault_id INTEGER UNIQUE NOT NULL,
+    address TEXT NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The versions of revaultd that ran on this database, and when each first did.
+ * The scripts we stored are derived again and compared on the first run of a
+ * new version, as a dependency upgrade could change how they derive.
+ */
+CREATE TABLE binary_versions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    version TEXT UNIQUE NOT NULL,
+    first_run_at INTEGER NOT NULL
+);
+
+/* The wallet transactions processed by a replay of the blocks we missed while
+ * we were down, along with the block they were confirmed in. The replay window
+ * is the block we list the wallet transactions since: a replay that was
+ * interrupted is resumed from it, without processing these again. The rows are
+ * deleted once the replay completed.
+ */
+CREATE TABLE replayed_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    window_height INTEGER NOT NULL,
+    window_hash BLOB NOT NULL,
+    txid BLOB NOT NULL,
+    blockhash BLOB NOT NULL,
+    UNIQUE (txid, blockhash)
+);
+
+/* Our signatures of presigned transactions waiting to be pushed to the
+ * Coordinator, by order of queuing. They are pushed at the pace the Coordinator
+ * accepts, and deleted once one acknowledged them.
+ */
+CREATE TABLE coordinator_outbox (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    pubkey BLOB NOT NULL,
+    signature BLOB NOT NULL,
+    queued_at INTEGER NOT NULL,
+    UNIQUE (txid, pubkey)
+);
+
+CREATE INDEX vault_status ON vaults (status);
+CREATE INDEX vault_transactions ON presigned_transactions (vault_id);
+";
+
+/// The statements to upgrade the database from a version to the next one: the entry at index
+/// `n` migrates a database at version `n` to version `n + 1`.
+pub const MIGRATIONS: &[&str] = &[
+    "\
+CREATE TABLE mempool_conflicts (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    rejected_txid BLOB NOT NULL,
+    competing_txid BLOB,
+    confirmed_txid BLOB,
+    detected_at INTEGER NOT NULL,
+    UNIQUE (vault_id, rejected_txid),
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* An append-only log of the destructive commands we were asked to perform.
+ * Each entry commits to the previous one through prev_hash, so that tampering
+ * with an entry breaks the chain from there on. An entry is written before
+ * the command is executed (with a 'pending' result) and another one once it
+ * completed.
+ */
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY NOT NULL,
+    method TEXT NOT NULL,
+    params_digest BLOB NOT NULL,
+    result TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    peer_uid INTEGER,
+    prev_hash BLOB NOT NULL,
+    hash BLOB NOT NULL
+);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END;
+",
+    "\
+/* A journal of the vault status transitions, filled by a trigger so that no
+ * status update can be missed. Entries are consumed (and deleted) by the
+ * poller which runs the status change hooks.
+ */
+CREATE TABLE vault_status_changes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    old_status INTEGER NOT NULL,
+    new_status INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE TRIGGER vault_status_change AFTER UPDATE OF status ON vaults
+WHEN OLD.status != NEW.status
+BEGIN
+    INSERT INTO vault_status_changes (vault_id, old_status, new_status)
+    VALUES (NEW.id, OLD.status, NEW.status);
+END;
+",
+    "\
+/* The last time we checked bitcoind would accept the revocation transactions
+ * of a vault in its mempool, and the first one it rejected if any.
+ */
+CREATE TABLE revocation_checks (
+    vault_id INTEGER UNIQUE NOT NULL,
+    checked_at INTEGER NOT NULL,
+    rejected_txid BLOB,
+    reject_reason TEXT,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* Links a canceled vault to the vault created by the output of its Cancel
+ * transaction, which needs its own presigned transactions to be signed before
+ * the funds are secured again.
+ */
+CREATE TABLE vault_successors (
+    parent_id INTEGER UNIQUE NOT NULL,
+    child_id INTEGER UNIQUE NOT NULL,
+    FOREIGN KEY (parent_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (child_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The scripts we derived from our descriptors, along with their derivation
+ * index, so that we don't need to derive them again at each startup. The
+ * kind is either 0 (deposit) or 1 (unvault).
+ */
+CREATE TABLE derived_scripts (
+    id INTEGER PRIMARY KEY NOT NULL,
+    wallet_id INTEGER NOT NULL,
+    script_pubkey BLOB UNIQUE NOT NULL,
+    derivation_index INTEGER NOT NULL,
+    kind INTEGER NOT NULL CHECK (kind IN (0,1)),
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The height at which the Unvault transaction of a vault confirmed, to know
+ * when its relative timelock expires. Only relevant for 'unvaulted' and
+ * 'spendable' vaults.
+ */
+CREATE TABLE unvault_confirmations (
+    vault_id INTEGER UNIQUE NOT NULL,
+    blockheight INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The history of the status of each vault, along with the height of our tip
+ * when it reached it. Unlike vault_status_changes it is never pruned, and is
+ * used to reconstruct the status of the vaults as of a past height.
+ */
+CREATE TABLE vault_transitions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    blockheight INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE TRIGGER vault_transition_insert AFTER INSERT ON vaults
+BEGIN
+    INSERT INTO vault_transitions (vault_id, status, blockheight)
+    VALUES (NEW.id, NEW.status, COALESCE((SELECT blockheight FROM tip), 0));
+END;
+
+CREATE TRIGGER vault_transition_update AFTER UPDATE OF status ON vaults
+WHEN OLD.status != NEW.status
+BEGIN
+    INSERT INTO vault_transitions (vault_id, status, blockheight)
+    VALUES (NEW.id, NEW.status, COALESCE((SELECT blockheight FROM tip), 0));
+END;
+
+/* We don't know the history of the existing vaults, assume they were funded at
+ * their deposit height and reached their current status at the current tip.
+ */
+INSERT INTO vault_transitions (vault_id, status, blockheight)
+SELECT id, 1, blockheight FROM vaults WHERE blockheight > 0;
+INSERT INTO vault_transitions (vault_id, status, blockheight)
+SELECT id, status, COALESCE((SELECT blockheight FROM tip), 0) FROM vaults
+WHERE NOT (status = 1 AND blockheight > 0);
+",
+    "\
+/* The signatures the cosigning servers sent us for a Spend transaction, so that
+ * we don't need to ask them again if announcing it fails. The psbt is the one
+ * they returned, containing only their signatures.
+ */
+CREATE TABLE spend_cosig_signatures (
+    id INTEGER PRIMARY KEY NOT NULL,
+    spend_id INTEGER NOT NULL,
+    cosig_noise_key BLOB NOT NULL,
+    psbt BLOB NOT NULL,
+    UNIQUE (spend_id, cosig_noise_key),
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE
+);
+",
+    "\
+/* Problems about a vault that need the attention of an operator, such as its
+ * revocation transactions being rejected by bitcoind. A flag is active until
+ * an operator clears it, and may be raised again afterward. The kind is one
+ * of the VaultFlagKind variants.
+ */
+CREATE TABLE vault_flags (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    cleared_at INTEGER,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE UNIQUE INDEX vault_active_flags ON vault_flags (vault_id, kind)
+WHERE cleared_at IS NULL;
+",
+    "\
+/* The mutating commands sent with an idempotency key, so that a client retrying
+ * one (for instance after a timeout) gets the result of the first attempt instead
+ * of executing it twice. The result is NULL while the command is in progress, and
+ * the entry is removed if it failed. Entries expire after the configured retention.
+ */
+CREATE TABLE idempotency_keys (
+    key TEXT PRIMARY KEY NOT NULL,
+    method TEXT NOT NULL,
+    params_digest BLOB NOT NULL,
+    result TEXT,
+    created_at INTEGER NOT NULL
+);
+",
+    "\
+/* We never track two vaults for the same deposit outpoint. */
+CREATE UNIQUE INDEX vault_deposit_outpoint ON vaults (wallet_id, deposit_txid, deposit_vout);
+",
+    "\
+/* Spend proposals a manager drafted, which enough managers need to approve
+ * before a Spend transaction may be created for them if the approval workflow
+ * is enabled. They can't be acked nor used past expires_at.
+ */
+CREATE TABLE spend_proposals (
+    id INTEGER PRIMARY KEY NOT NULL,
+    feerate INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+/* The deposits a spend proposal would spend. */
+CREATE TABLE spend_proposal_inputs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    proposal_id INTEGER NOT NULL,
+    deposit_txid BLOB NOT NULL,
+    deposit_vout INTEGER NOT NULL,
+    FOREIGN KEY (proposal_id) REFERENCES spend_proposals (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The destinations of a spend proposal, the amount is in sats. */
+CREATE TABLE spend_proposal_outputs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    proposal_id INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    FOREIGN KEY (proposal_id) REFERENCES spend_proposals (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The decision of a manager, identified by the fingerprint of their xpub, about
+ * a spend proposal along with their signature of it. A manager may only decide
+ * once per proposal.
+ */
+CREATE TABLE spend_proposal_acks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    proposal_id INTEGER NOT NULL,
+    fingerprint BLOB NOT NULL,
+    approved BOOLEAN NOT NULL CHECK (approved IN (0,1)),
+    signature BLOB NOT NULL,
+    created_at INTEGER NOT NULL,
+    UNIQUE (proposal_id, fingerprint),
+    FOREIGN KEY (proposal_id) REFERENCES spend_proposals (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The external scripts a confirmed Spend transaction paid to, along with the
+ * first Spend that did, to detect address reuse. Our own scripts are never
+ * recorded here.
+ */
+CREATE TABLE spend_destinations (
+    id INTEGER PRIMARY KEY NOT NULL,
+    script_pubkey BLOB UNIQUE NOT NULL,
+    spend_txid BLOB NOT NULL,
+    used_at INTEGER NOT NULL
+);
+",
+    "\
+/* The descriptors of the stakeholders' deep-cold setup that were verified to
+ * generate the Emergency address, if we were asked to keep them for future
+ * re-verification. The derivation index is only set for a ranged descriptor.
+ */
+CREATE TABLE emergency_descriptors (
+    id INTEGER PRIMARY KEY NOT NULL,
+    descriptor TEXT NOT NULL,
+    derivation_index INTEGER,
+    stored_at INTEGER NOT NULL
+);
+",
+    "\
+/* How the funds of a vault got to us: an external deposit, the output of the
+ * Cancel transaction of another vault, or the change output of a Spend
+ * transaction. Vaults detected before we recorded it may not have an entry, in
+ * which case they are assumed to come from an external deposit.
+ */
+CREATE TABLE vault_origins (
+    vault_id INTEGER UNIQUE NOT NULL,
+    origin INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* Links a vault funded by the change output of a Spend transaction to the
+ * vaults this Spend consumed.
+ */
+CREATE TABLE vault_change_sources (
+    child_id INTEGER NOT NULL,
+    parent_id INTEGER NOT NULL,
+    UNIQUE (child_id, parent_id),
+    FOREIGN KEY (child_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (parent_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+INSERT INTO vault_origins (vault_id, origin) SELECT child_id, 1 FROM vault_successors;
+",
+    "\
+/* The periods during which an unconfirmed deposit was given up on, after its
+ * transaction left the mempool (for instance because it was replaced) and
+ * wasn't mined. A vault is abandoned as long as its last entry was not
+ * resurrected, which happens if the deposit shows up again.
+ */
+CREATE TABLE deposit_abandonments (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    abandoned_at INTEGER NOT NULL,
+    resurrected_at INTEGER,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The presigned transactions a Coordinator acknowledged having our signature
+ * for, keyed by its Noise static public key. We may have to send them again
+ * to another Coordinator after failing over to it.
+ */
+CREATE TABLE coordinator_sig_acks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    coordinator_key BLOB NOT NULL,
+    txid BLOB NOT NULL,
+    UNIQUE (coordinator_key, txid)
+);
+",
+    "\
+/* The highest derivation index up to which we imported the addresses of each
+ * kind into bitcoind's watchonly wallet, so that we only import the ones we
+ * derive afterward. The kind is either 0 (deposit) or 1 (unvault).
+ */
+CREATE TABLE imported_addresses (
+    wallet_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL CHECK (kind IN (0,1)),
+    derivation_index INTEGER NOT NULL,
+    UNIQUE (wallet_id, kind),
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The addresses of the scripts we derived were all imported already. */
+INSERT INTO imported_addresses (wallet_id, kind, derivation_index)
+SELECT wallet_id, kind, MAX(derivation_index) FROM derived_scripts
+GROUP BY wallet_id, kind;
+",
+    "\
+/* The fee market when the signature set of a presigned transaction completed:
+ * the feerate its fixed fees imply, bitcoind's estimates of the feerate needed
+ * to confirm within 2 and 144 blocks (NULL if it had none), all in sat/vbyte,
+ * and the height of our tip then.
+ */
+CREATE TABLE signing_contexts (
+    presigned_id INTEGER UNIQUE NOT NULL,
+    feerate INTEGER NOT NULL,
+    next_block_estimate INTEGER,
+    next_day_estimate INTEGER,
+    blockheight INTEGER NOT NULL,
+    signed_at INTEGER NOT NULL,
+    FOREIGN KEY (presigned_id) REFERENCES presigned_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "/* The vaults we passed to the watchtowers pulling from us through getwatchdata.
+ * Each change (a vault getting Active, or leaving this status) gets the next
+ * sequence number so that they can only fetch what changed since their last
+ * call. Rows are kept once revoked, as the vault itself may be gone.
+ */
+CREATE TABLE watchdata (
+    deposit_txid BLOB NOT NULL,
+    deposit_vout INTEGER NOT NULL,
+    sequence INTEGER UNIQUE NOT NULL,
+    revoked BOOLEAN NOT NULL CHECK (revoked IN (0,1)),
+    UNIQUE (deposit_txid, deposit_vout)
+);
+",
+    "\
+/* A rotation of the keys of the deployment to a new wallet. The old wallet
+ * remains the current one until the rotation is completed, at startup with the
+ * descriptors of the new one once all the vaults of the old one are gone.
+ */
+CREATE TABLE wallet_rotations (
+    old_wallet_id INTEGER UNIQUE NOT NULL,
+    new_wallet_id INTEGER UNIQUE NOT NULL,
+    initiated_at INTEGER NOT NULL,
+    completed_at INTEGER,
+    FOREIGN KEY (old_wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (new_wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The vaults being moved to the new wallet of a rotation, by the Spend
+ * transaction paying to its deposit address at this derivation index. They are
+ * removed along with the Spend transaction, so that the migration resumes.
+ */
+CREATE TABLE vault_migrations (
+    vault_id INTEGER UNIQUE NOT NULL,
+    spend_txid BLOB NOT NULL,
+    derivation_index INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The unconfirmed transactions the transaction of a deposit depended on when
+ * we detected it: their number, total virtual size and the length of the
+ * longest chain of them.
+ */
+CREATE TABLE deposit_ancestries (
+    vault_id INTEGER UNIQUE NOT NULL,
+    ancestors INTEGER NOT NULL,
+    vsize INTEGER NOT NULL,
+    depth INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The announcements of our Spend transactions to the Coordinator: when and to
+ * which Coordinator (by its Noise key) we announced it, and what the Coordinator
+ * reported for it when we reconciled at startup. The status is either:
+ *  - Announced, not checked against the Coordinator yet (0)
+ *  - Confirmed by the Coordinator (1)
+ *  - Adopted: the Coordinator had it but we had not recorded announcing it (2)
+ *  - Missing: the Coordinator did not have it anymore (3)
+ * The announcement time is NULL for adopted announcements, for which the
+ * Coordinator is the one that reported it.
+ */
+CREATE TABLE spend_announcements (
+    spend_id INTEGER UNIQUE NOT NULL,
+    coordinator_key BLOB,
+    announced_at INTEGER,
+    status INTEGER NOT NULL CHECK (status IN (0,1,2,3)),
+    reconciled_at INTEGER,
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE
+);
+",
+    "\
+/* The txid of the presigned transactions once fully signed, as computed by
+ * finalizing them when their signature set completed. Updated if they are ever
+ * signed again.
+ */
+CREATE TABLE final_txids (
+    presigned_id INTEGER UNIQUE NOT NULL,
+    txid BLOB NOT NULL,
+    computed_at INTEGER NOT NULL,
+    FOREIGN KEY (presigned_id) REFERENCES presigned_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* The witness isn't committed to by the txid, the transactions already fully
+ * signed are filled from the unsigned transaction of their PSBT.
+ */
+INSERT INTO final_txids (presigned_id, txid, computed_at)
+SELECT id, txid, strftime('%s','now') FROM presigned_transactions WHERE fullysigned = 1;
+",
+    "\
+/* The Spend transactions that can't confirm anymore, as a vault they spend was
+ * canceled. This may happen after the Unvault timelock expired, the Cancel
+ * remaining valid. They are kept for the record, but never broadcast. The
+ * deprecation time is the one of the block the Cancel was confirmed in.
+ */
+CREATE TABLE spend_deprecations (
+    spend_id INTEGER UNIQUE NOT NULL,
+    vault_id INTEGER NOT NULL,
+    cancel_txid BLOB NOT NULL,
+    deprecated_at INTEGER NOT NULL,
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The deposits created by a coinbase transaction, and the height it was mined
+ * at. Their output can't be spent before it matures, 100 blocks later.
+ */
+CREATE TABLE deposit_coinbases (
+    vault_id INTEGER UNIQUE NOT NULL,
+    blockheight INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* All the existing wallets are P2WSH ones. */
+ALTER TABLE wallets ADD COLUMN script_version INTEGER NOT NULL DEFAULT 0;
+",
+    "\
+/* The runs of the 'emergency' command, and whether the notify command was run
+ * for them yet. The poller notifies each run once, as a whole.
+ */
+CREATE TABLE emergency_runs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ran_at INTEGER NOT NULL,
+    notified INTEGER NOT NULL CHECK (notified IN (0,1)) DEFAULT 0
+);
+
+/* What became of each vault in a run of the 'emergency' command. The outcome
+ * is one of the EmergencyOutcomeKind variants. The txid is the one of the
+ * Emergency or Unvault Emergency transaction of the vault, if it has one, and
+ * the detail the status of a skipped vault or the reason bitcoind rejected the
+ * transaction.
+ */
+CREATE TABLE emergency_outcomes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    run_id INTEGER NOT NULL,
+    vault_id INTEGER NOT NULL,
+    outcome INTEGER NOT NULL,
+    txid BLOB,
+    detail TEXT,
+    FOREIGN KEY (run_id) REFERENCES emergency_runs (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The Spend transactions we announced but that never got any of their Unvault
+ * transactions broadcast, and were given up on: either automatically, as they
+ * did not progress for longer than the configured expiry, or through the
+ * 'abortspend' command. The reason is one of the SpendExpiryReason variants.
+ * They are kept for the record, but never broadcast.
+ */
+CREATE TABLE spend_expirations (
+    spend_id INTEGER UNIQUE NOT NULL,
+    reason INTEGER NOT NULL CHECK (reason IN (0,1)),
+    expired_at INTEGER NOT NULL,
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE
+);
+",
+    "\
+/* The revocation transactions a stakeholder signed for a vault whose deposit got
+ * unconfirmed while they were signing them. Our presigned transactions are dropped
+ * along with the confirmation, so these are kept until the deposit confirms again
+ * and the presigned transactions are created anew. The type is one of the
+ * TransactionType variants.
+ */
+CREATE TABLE deferred_revocation_txs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    type INTEGER NOT NULL,
+    psbt BLOB NOT NULL,
+    txid BLOB NOT NULL,
+    received_at INTEGER NOT NULL,
+    UNIQUE (vault_id, type),
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The ranges of derivation indexes of each kind whose addresses we imported
+ * into bitcoind's watchonly wallet, and when they were first needed: the
+ * creation of the wallet for the first one, the time the derivation index
+ * moved enough for us to import the following ones (the median time past of
+ * the tip then, to compare with block times). Rescans for the addresses of a
+ * range need not start before the range preceding it was needed. The kind is
+ * either 0 (deposit) or 1 (unvault).
+ */
+CREATE TABLE imported_ranges (
+    id INTEGER PRIMARY KEY NOT NULL,
+    wallet_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL CHECK (kind IN (0,1)),
+    first_index INTEGER NOT NULL,
+    last_index INTEGER NOT NULL,
+    first_needed_timestamp INTEGER NOT NULL,
+    UNIQUE (wallet_id, kind, first_index),
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* We don't know when the addresses imported so far were first needed, as far
+ * as we know it was at the creation of their wallet.
+ */
+INSERT INTO imported_ranges (wallet_id, kind, first_index, last_index, first_needed_timestamp)
+SELECT i.wallet_id, i.kind, 0, i.derivation_index, w.timestamp
+FROM imported_addresses AS i INNER JOIN wallets AS w ON w.id = i.wallet_id;
+",
+    "\
+/* The address the Emergency transactions of a vault pay to, as chosen by the
+ * configured amount bands when its deposit first confirmed. Changing the
+ * configuration later only affects new vaults. Vaults confirmed before it was
+ * recorded use the configured address for their amount.
+ */
+CREATE TABLE vault_emergency_addresses (
+    vault_id INTEGER UNIQUE NOT NULL,
+    address TEXT NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+",
+    "\
+/* The versions of revaultd that ran on this database, and when each first did.
+ * The scripts we stored are derived again and compared on the first run of a
+ * new version, as a dependency upgrade could change how they derive.
+ */
+CREATE TABLE binary_versions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    version TEXT UNIQUE NOT NULL,
+    first_run_at INTEGER NOT NULL
+);
+",
+    "\
+/* The wallet transactions processed by a replay of the blocks we missed while
+ * we were down, along with the block they were confirmed in. The replay window
+ * is the block we list the wallet transactions since: a replay that was
+ * interrupted is resumed from it, without processing these again. The rows are
+ * deleted once the replay completed.
+ */
+CREATE TABLE replayed_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    window_height INTEGER NOT NULL,
+    window_hash BLOB NOT NULL,
+    txid BLOB NOT NULL,
+    blockhash BLOB NOT NULL,
+    UNIQUE (txid, blockhash)
+);
+",
+    "\
+/* Our signatures of presigned transactions waiting to be pushed to the
+ * Coordinator, by order of queuing. They are pushed at the pace the Coordinator
+ * accepts, and deleted once one acknowledged them.
+ */
+CREATE TABLE coordinator_outbox (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    pubkey BLOB NOT NULL,
+    signature BLOB NOT NULL,
+    queued_at INTEGER NOT NULL,
+    UNIQUE (txid, pubkey)
+);
+",
+];
+
+/// The schema of the first version of the database, to test the migrations from it
+#[cfg(test)]
+pub const SCHEMA_V0: &str = "\
+CREATE TABLE version (
+    version INTEGER NOT NULL
+);
+
+CREATE TABLE tip (
+    network TEXT NOT NULL,
+    blockheight INTEGER NOT NULL,
+    blockhash BLOB NOT NULL
+);
+
 /* This stores metadata about our wallet. We only support single wallet for
  * now (and the foreseeable future). This MUST be in sync with bitcoind's
  * wallet.
@@ -130,7 +1543,26 @@ pub struct DbWallet {
     pub cpfp_descriptor: CpfpDescriptor,
     pub our_man_xpub: Option<ExtendedPubKey>,
     pub our_stk_xpub: Option<ExtendedPubKey>,
-    pub deposit_derivation_index: ChildNumber,
+    pub deposit_derivation_index: DerivationIndex,
+    pub script_version: ScriptVersion,
+}
+
+/// A row in the "wallet_rotations" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbWalletRotation {
+    pub old_wallet_id: u32,
+    pub new_wallet_id: u32,
+    pub initiated_at: u32,
+    pub completed_at: Option<u32>,
+}
+
+/// A row in the "vault_migrations" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbVaultMigration {
+    pub vault_id: u32,
+    pub spend_txid: Txid,
+    pub derivation_index: DerivationIndex,
+    pub created_at: u32,
 }
 
 /// A row of the "vaults" table
@@ -142,7 +1574,7 @@ pub struct DbVault {
     pub blockheight: u32,
     pub deposit_outpoint: OutPoint,
     pub amount: Amount,
-    pub derivation_index: ChildNumber,
+    pub derivation_index: DerivationIndex,
     pub funded_at: Option<u32>,
     pub secured_at: Option<u32>,
     pub delegated_at: Option<u32>,
@@ -161,6 +1593,34 @@ pub struct DbTransaction {
     pub is_fully_signed: bool,
 }
 
+/// A row in the "signing_contexts" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbSigningContext {
+    pub presigned_id: u32,
+    pub feerate: u64,
+    pub next_block_estimate: Option<u64>,
+    pub next_day_estimate: Option<u64>,
+    pub blockheight: u32,
+    pub signed_at: u32,
+}
+
+/// A row in the "final_txids" table, along with the vault and type of the transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbFinalTxid {
+    pub vault_id: u32,
+    pub tx_type: TransactionType,
+    pub txid: Txid,
+    pub computed_at: u32,
+}
+
+/// A row in the "watchdata" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbWatchData {
+    pub deposit_outpoint: OutPoint,
+    pub sequence: u64,
+    pub revoked: bool,
+}
+
 /// A row in the "spend_inputs" table
 #[derive(Debug)]
 pub struct DbSpendInput {
@@ -169,6 +1629,447 @@ pub struct DbSpendInput {
     pub spend_id: u32,
 }
 
+/// A row in the "mempool_conflicts" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbMempoolConflict {
+    pub id: i64,
+    pub vault_id: u32,
+    pub rejected_txid: Txid,
+    pub competing_txid: Option<Txid>,
+    pub confirmed_txid: Option<Txid>,
+    pub detected_at: u32,
+}
+
+/// The kind of script we derived from our descriptors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptKind {
+    Deposit = 0,
+    Unvault = 1,
+}
+
+impl TryFrom<u32> for ScriptKind {
+    type Error = ();
+
+    fn try_from(n: u32) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(Self::Deposit),
+            1 => Ok(Self::Unvault),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A row in the "derived_scripts" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbDerivedScript {
+    pub script_pubkey: Script,
+    pub derivation_index: DerivationIndex,
+    pub kind: ScriptKind,
+}
+
+/// A row in the "imported_ranges" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbImportedRange {
+    pub first_index: DerivationIndex,
+    pub last_index: DerivationIndex,
+    pub first_needed_timestamp: u32,
+}
+
+/// A row in the "revocation_checks" table, along with the deposit outpoint of the vault.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbRevocationCheck {
+    pub vault_id: u32,
+    pub deposit_outpoint: OutPoint,
+    pub checked_at: u32,
+    pub rejected_txid: Option<Txid>,
+    pub reject_reason: Option<String>,
+}
+
+/// A row in the "audit_log" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbAuditEntry {
+    pub id: i64,
+    pub method: String,
+    pub params_digest: sha256::Hash,
+    pub result: String,
+    pub timestamp: u32,
+    pub peer_uid: Option<u32>,
+    pub prev_hash: sha256::Hash,
+    pub hash: sha256::Hash,
+}
+
+impl DbAuditEntry {
+    /// The hash this entry should have given its content and the previous entry's hash.
+    pub fn expected_hash(&self) -> sha256::Hash {
+        audit_entry_hash(
+            &self.prev_hash,
+            &self.method,
+            &self.params_digest,
+            &self.result,
+            self.timestamp,
+            self.peer_uid,
+        )
+    }
+}
+
+/// The hash committing to an audit log entry, chaining it to the previous one.
+pub fn audit_entry_hash(
+    prev_hash: &sha256::Hash,
+    method: &str,
+    params_digest: &sha256::Hash,
+    result: &str,
+    timestamp: u32,
+    peer_uid: Option<u32>,
+) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&prev_hash[..]);
+    // Length-prefix the variable-length fields so they can't be shifted into one another.
+    engine.input(&(method.len() as u64).to_be_bytes());
+    engine.input(method.as_bytes());
+    engine.input(&params_digest[..]);
+    engine.input(&(result.len() as u64).to_be_bytes());
+    engine.input(result.as_bytes());
+    engine.input(&timestamp.to_be_bytes());
+    match peer_uid {
+        Some(uid) => {
+            engine.input(&[1]);
+            engine.input(&uid.to_be_bytes());
+        }
+        None => engine.input(&[0]),
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+/// A row in the "idempotency_keys" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbIdempotencyKey {
+    pub key: String,
+    pub method: String,
+    pub params_digest: sha256::Hash,
+    /// The JSON result of the command, None while it is in progress
+    pub result: Option<String>,
+    pub created_at: u32,
+}
+
+/// A row in the "vault_status_changes" table, along with the vault information needed to
+/// report it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbVaultStatusChange {
+    pub id: i64,
+    pub vault_id: u32,
+    pub old_status: VaultStatus,
+    pub new_status: VaultStatus,
+    pub deposit_outpoint: OutPoint,
+    pub amount: Amount,
+    pub final_txid: Option<Txid>,
+}
+
+/// A row in the "vault_transitions" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbVaultTransition {
+    pub vault_id: u32,
+    pub status: VaultStatus,
+    pub blockheight: u32,
+}
+
+/// A row in the "coordinator_outbox" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbQueuedSig {
+    pub id: i64,
+    pub txid: Txid,
+    pub pubkey: secp256k1::PublicKey,
+    pub signature: secp256k1::Signature,
+    /// When it was queued, as a UNIX timestamp
+    pub queued_at: u32,
+}
+
+/// A row in the "spend_cosig_signatures" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbCosigSignatures {
+    pub spend_id: i64,
+    pub cosig_noise_key: [u8; 32],
+    pub psbt: SpendTransaction,
+}
+
+/// The kind of problem a vault was flagged for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VaultFlagKind {
+    /// bitcoind would not accept one of its revocation transactions in its mempool
+    RevocationRejected = 0,
+    /// Its Deposit or Unvault output was spent by a transaction we don't know of
+    UnknownSpender = 1,
+    /// Its deposit was detected again with a different amount or derivation index
+    ConflictingDeposit = 2,
+    /// We were about to move it to a status it can't be in from its current one
+    InvalidTransition = 3,
+    /// Its deposit was funded by a chain of unconfirmed transactions beyond our limits
+    UnconfirmedAncestry = 4,
+    /// The Coordinator stores a Spend transaction for it that we never created
+    UnknownSpendAnnouncement = 5,
+    /// Its Unvault transaction could not be broadcast along with the others of a Spend
+    UnvaultBroadcastFailed = 6,
+    /// bitcoind would not accept its Emergency (or Unvault Emergency) transaction when we ran
+    /// the emergency procedure
+    EmergencyRejected = 7,
+    /// Its deposit output was reorged out and doesn't exist anymore, while we were handed
+    /// signatures for its revocation transactions
+    DepositVanished = 8,
+}
+
+impl TryFrom<u32> for VaultFlagKind {
+    type Error = ();
+
+    fn try_from(n: u32) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(Self::RevocationRejected),
+            1 => Ok(Self::UnknownSpender),
+            2 => Ok(Self::ConflictingDeposit),
+            3 => Ok(Self::InvalidTransition),
+            4 => Ok(Self::UnconfirmedAncestry),
+            5 => Ok(Self::UnknownSpendAnnouncement),
+            6 => Ok(Self::UnvaultBroadcastFailed),
+            7 => Ok(Self::EmergencyRejected),
+            8 => Ok(Self::DepositVanished),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for VaultFlagKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::RevocationRejected => write!(f, "revocation_rejected"),
+            Self::UnknownSpender => write!(f, "unknown_spender"),
+            Self::ConflictingDeposit => write!(f, "conflicting_deposit"),
+            Self::InvalidTransition => write!(f, "invalid_transition"),
+            Self::UnconfirmedAncestry => write!(f, "unconfirmed_ancestry"),
+            Self::UnknownSpendAnnouncement => write!(f, "unknown_spend_announcement"),
+            Self::UnvaultBroadcastFailed => write!(f, "unvault_broadcast_failed"),
+            Self::EmergencyRejected => write!(f, "emergency_rejected"),
+            Self::DepositVanished => write!(f, "deposit_vanished"),
+        }
+    }
+}
+
+impl FromStr for VaultFlagKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "revocation_rejected" => Ok(Self::RevocationRejected),
+            "unknown_spender" => Ok(Self::UnknownSpender),
+            "conflicting_deposit" => Ok(Self::ConflictingDeposit),
+            "invalid_transition" => Ok(Self::InvalidTransition),
+            "unconfirmed_ancestry" => Ok(Self::UnconfirmedAncestry),
+            "unknown_spend_announcement" => Ok(Self::UnknownSpendAnnouncement),
+            "unvault_broadcast_failed" => Ok(Self::UnvaultBroadcastFailed),
+            "emergency_rejected" => Ok(Self::EmergencyRejected),
+            "deposit_vanished" => Ok(Self::DepositVanished),
+            _ => Err(format!("Unknown vault flag kind '{}'", s)),
+        }
+    }
+}
+
+/// How the funds of a vault got to us
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepositOrigin {
+    /// A deposit from outside the wallet
+    External = 0,
+    /// The output of the Cancel transaction of another vault
+    Cancel = 1,
+    /// The change output of a Spend transaction
+    SpendChange = 2,
+}
+
+impl TryFrom<u32> for DepositOrigin {
+    type Error = ();
+
+    fn try_from(n: u32) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(Self::External),
+            1 => Ok(Self::Cancel),
+            2 => Ok(Self::SpendChange),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for DepositOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::External => write!(f, "external"),
+            Self::Cancel => write!(f, "cancel"),
+            Self::SpendChange => write!(f, "spend_change"),
+        }
+    }
+}
+
+impl FromStr for DepositOrigin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "external" => Ok(Self::External),
+            "cancel" => Ok(Self::Cancel),
+            "spend_change" => Ok(Self::SpendChange),
+            _ => Err(format!("Unknown deposit origin '{}'", s)),
+        }
+    }
+}
+
+/// What we know of the announcement of a Spend transaction to the Coordinator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnnouncementStatus {
+    /// We announced it and did not check it against the Coordinator since
+    Announced = 0,
+    /// The Coordinator reported storing it
+    Confirmed = 1,
+    /// The Coordinator had it but we had not recorded announcing it
+    Adopted = 2,
+    /// The Coordinator did not have it anymore
+    Missing = 3,
+}
+
+impl TryFrom<u32> for AnnouncementStatus {
+    type Error = ();
+
+    fn try_from(n: u32) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(Self::Announced),
+            1 => Ok(Self::Confirmed),
+            2 => Ok(Self::Adopted),
+            3 => Ok(Self::Missing),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for AnnouncementStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Announced => write!(f, "announced"),
+            Self::Confirmed => write!(f, "confirmed"),
+            Self::Adopted => write!(f, "adopted"),
+            Self::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+impl FromStr for AnnouncementStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "announced" => Ok(Self::Announced),
+            "confirmed" => Ok(Self::Confirmed),
+            "adopted" => Ok(Self::Adopted),
+            "missing" => Ok(Self::Missing),
+            _ => Err(format!("Unknown announcement status '{}'", s)),
+        }
+    }
+}
+
+/// A row in the "vault_flags" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbVaultFlag {
+    pub id: i64,
+    pub vault_id: u32,
+    pub kind: VaultFlagKind,
+    pub message: String,
+    pub created_at: u32,
+    pub cleared_at: Option<u32>,
+}
+
+/// A row in the "deposit_abandonments" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbDepositAbandonment {
+    pub id: i64,
+    pub vault_id: u32,
+    pub abandoned_at: u32,
+    pub resurrected_at: Option<u32>,
+}
+
+/// A row in the "deposit_ancestries" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbDepositAncestry {
+    pub vault_id: u32,
+    pub ancestors: u32,
+    pub vsize: u64,
+    pub depth: u32,
+}
+
+/// A row in the "spend_proposals" table, along with its inputs and outputs
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbSpendProposal {
+    pub id: u32,
+    pub outpoints: Vec<OutPoint>,
+    pub destinations: BTreeMap<Address, Amount>,
+    pub feerate: u64,
+    pub created_at: u32,
+    pub expires_at: u32,
+}
+
+impl DbSpendProposal {
+    /// The digest a manager signs to approve this proposal, or to reject it. The proposal id
+    /// and creation date are committed to so that an ack can't be replayed for another proposal
+    /// with the same content.
+    pub fn ack_digest(&self, approve: bool) -> sha256::Hash {
+        let tag: &[u8] = if approve {
+            b"revault spend proposal approval"
+        } else {
+            b"revault spend proposal rejection"
+        };
+        let mut engine = sha256::Hash::engine();
+        engine.input(&(tag.len() as u64).to_be_bytes());
+        engine.input(tag);
+        engine.input(&self.id.to_be_bytes());
+        engine.input(&self.created_at.to_be_bytes());
+        engine.input(&self.expires_at.to_be_bytes());
+        engine.input(&self.feerate.to_be_bytes());
+        engine.input(&(self.outpoints.len() as u64).to_be_bytes());
+        for outpoint in self.outpoints.iter() {
+            engine.input(&outpoint.txid[..]);
+            engine.input(&outpoint.vout.to_be_bytes());
+        }
+        engine.input(&(self.destinations.len() as u64).to_be_bytes());
+        for (address, amount) in self.destinations.iter() {
+            let script_pubkey = address.script_pubkey();
+            engine.input(&(script_pubkey.len() as u64).to_be_bytes());
+            engine.input(script_pubkey.as_bytes());
+            engine.input(&amount.as_sat().to_be_bytes());
+        }
+        sha256::Hash::from_engine(engine)
+    }
+}
+
+/// A row in the "spend_destinations" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbSpendDestination {
+    pub script_pubkey: Script,
+    pub spend_txid: Txid,
+    pub used_at: u32,
+}
+
+/// A row in the "emergency_descriptors" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbEmergencyDescriptor {
+    pub id: i64,
+    pub descriptor: String,
+    pub derivation_index: Option<u32>,
+    pub stored_at: u32,
+}
+
+/// A row in the "spend_proposal_acks" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbSpendProposalAck {
+    pub id: i64,
+    pub proposal_id: u32,
+    pub fingerprint: Fingerprint,
+    pub approved: bool,
+    pub signature: Vec<u8>,
+    pub created_at: u32,
+}
+
 /// A row in the "spend_transactions" table
 #[derive(Debug, PartialEq)]
 pub struct DbSpendTransaction {
@@ -178,3 +2079,131 @@ pub struct DbSpendTransaction {
     pub has_priority: bool,
     // txid is intentionally not there as it's already part of the psbt
 }
+
+/// A row in the "spend_deprecations" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbSpendDeprecation {
+    pub spend_id: i64,
+    pub vault_id: u32,
+    pub cancel_txid: Txid,
+    pub deprecated_at: u32,
+}
+
+/// Why we gave up on a Spend transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendExpiryReason {
+    /// It did not progress for longer than the configured expiry
+    Timeout = 0,
+    /// A manager aborted it
+    Aborted = 1,
+}
+
+impl TryFrom<u32> for SpendExpiryReason {
+    type Error = ();
+
+    fn try_from(n: u32) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(Self::Timeout),
+            1 => Ok(Self::Aborted),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for SpendExpiryReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timeout"),
+            Self::Aborted => write!(f, "aborted"),
+        }
+    }
+}
+
+impl FromStr for SpendExpiryReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timeout" => Ok(Self::Timeout),
+            "aborted" => Ok(Self::Aborted),
+            _ => Err(format!("Unknown Spend expiry reason '{}'", s)),
+        }
+    }
+}
+
+/// A row in the "spend_expirations" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbSpendExpiration {
+    pub spend_id: i64,
+    pub reason: SpendExpiryReason,
+    pub expired_at: u32,
+}
+
+/// A row in the "spend_announcements" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbSpendAnnouncement {
+    pub spend_id: i64,
+    pub coordinator_key: Option<[u8; 32]>,
+    pub announced_at: Option<u32>,
+    pub status: AnnouncementStatus,
+    pub reconciled_at: Option<u32>,
+}
+
+/// What became of a vault in a run of the emergency procedure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmergencyOutcomeKind {
+    /// Its Emergency (or Unvault Emergency) transaction was broadcast
+    Broadcast = 0,
+    /// Its Emergency (or Unvault Emergency) transaction was broadcast by a previous run
+    AlreadyBroadcast = 1,
+    /// It was not in a status we could broadcast a revocation transaction from
+    SkippedStatus = 2,
+    /// Its Emergency (or Unvault Emergency) transaction is not fully signed
+    MissingSignatures = 3,
+    /// bitcoind would not accept its Emergency (or Unvault Emergency) transaction
+    Rejected = 4,
+}
+
+impl TryFrom<u32> for EmergencyOutcomeKind {
+    type Error = ();
+
+    fn try_from(n: u32) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(Self::Broadcast),
+            1 => Ok(Self::AlreadyBroadcast),
+            2 => Ok(Self::SkippedStatus),
+            3 => Ok(Self::MissingSignatures),
+            4 => Ok(Self::Rejected),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for EmergencyOutcomeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Broadcast => write!(f, "broadcast"),
+            Self::AlreadyBroadcast => write!(f, "already_broadcast"),
+            Self::SkippedStatus => write!(f, "skipped_status"),
+            Self::MissingSignatures => write!(f, "missing_signatures"),
+            Self::Rejected => write!(f, "rejected"),
+        }
+    }
+}
+
+/// A row in the "emergency_outcomes" table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbEmergencyOutcome {
+    pub vault_id: u32,
+    pub kind: EmergencyOutcomeKind,
+    pub txid: Option<Txid>,
+    pub detail: Option<String>,
+}
+
+/// A row in the "emergency_runs" table, along with the outcomes of the run
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbEmergencyRun {
+    pub id: i64,
+    pub ran_at: u32,
+    pub outcomes: Vec<DbEmergencyOutcome>,
+}
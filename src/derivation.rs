@@ -0,0 +1,220 @@
+//! The derivation indexes of our deposit, unvault and CPFP addresses.
+//!
+//! We only ever use normal (unhardened) derivation, so a derivation index is always below 2^31.
+//! This is checked once when creating it (from the database, from a RPC parameter, from
+//! bitcoind..) instead of when deriving.
+
+use revault_tx::bitcoin::util::bip32::ChildNumber;
+
+use std::{
+    convert::{TryFrom, TryInto},
+    error, fmt,
+};
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The first hardened index, (1 << 31). Derivation indexes are strictly below it.
+pub const HARDENED_INDEX: u32 = 1 << 31;
+
+/// An attempt to create a derivation index from a hardened one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardenedIndexError(pub u32);
+
+impl fmt::Display for HardenedIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Derivation index {} is hardened, we only use normal derivation",
+            self.0
+        )
+    }
+}
+
+impl error::Error for HardenedIndexError {}
+
+/// A normal (unhardened) derivation index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DerivationIndex(u32);
+
+impl DerivationIndex {
+    pub const ZERO: DerivationIndex = DerivationIndex(0);
+    pub const MAX: DerivationIndex = DerivationIndex(HARDENED_INDEX - 1);
+
+    pub fn new(index: u32) -> Result<Self, HardenedIndexError> {
+        if index >= HARDENED_INDEX {
+            return Err(HardenedIndexError(index));
+        }
+        Ok(Self(index))
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+
+    /// The index `n` indexes above this one, if it is not hardened.
+    pub fn checked_add(self, n: u32) -> Option<Self> {
+        self.0.checked_add(n).and_then(|i| Self::new(i).ok())
+    }
+
+    /// The index `n` indexes above this one, or the last unhardened one.
+    pub fn saturating_add(self, n: u32) -> Self {
+        self.checked_add(n).unwrap_or(Self::MAX)
+    }
+
+    /// The index `n` indexes below this one, or the first one.
+    pub fn saturating_sub(self, n: u32) -> Self {
+        Self(self.0.saturating_sub(n))
+    }
+
+    /// The indexes from this one up to `last`, included.
+    pub fn up_to(self, last: DerivationIndex) -> impl Iterator<Item = DerivationIndex> {
+        (self.0..=last.0).map(DerivationIndex)
+    }
+}
+
+impl TryFrom<u32> for DerivationIndex {
+    type Error = HardenedIndexError;
+
+    fn try_from(index: u32) -> Result<Self, Self::Error> {
+        Self::new(index)
+    }
+}
+
+impl TryFrom<ChildNumber> for DerivationIndex {
+    type Error = HardenedIndexError;
+
+    fn try_from(child_number: ChildNumber) -> Result<Self, Self::Error> {
+        match child_number {
+            ChildNumber::Normal { index } => Self::new(index),
+            ChildNumber::Hardened { index } => Err(HardenedIndexError(index | HARDENED_INDEX)),
+        }
+    }
+}
+
+impl From<DerivationIndex> for ChildNumber {
+    fn from(index: DerivationIndex) -> Self {
+        ChildNumber::Normal { index: index.0 }
+    }
+}
+
+impl From<DerivationIndex> for u32 {
+    fn from(index: DerivationIndex) -> Self {
+        index.0
+    }
+}
+
+impl fmt::Display for DerivationIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for DerivationIndex {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u32(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for DerivationIndex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let index = u32::deserialize(deserializer)?;
+        index.try_into().map_err(de::Error::custom)
+    }
+}
+
+impl ToSql for DerivationIndex {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0))
+    }
+}
+
+impl FromSql for DerivationIndex {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let index = u32::column_result(value)?;
+        index
+            .try_into()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivation_index_bounds() {
+        assert_eq!(DerivationIndex::new(0).unwrap(), DerivationIndex::ZERO);
+        assert_eq!(
+            DerivationIndex::new(HARDENED_INDEX - 1).unwrap(),
+            DerivationIndex::MAX
+        );
+        assert_eq!(
+            DerivationIndex::new(HARDENED_INDEX),
+            Err(HardenedIndexError(HARDENED_INDEX))
+        );
+        assert!(DerivationIndex::try_from(u32::MAX).is_err());
+
+        // Adding never gets us to a hardened index
+        let index = DerivationIndex::new(100).unwrap();
+        assert_eq!(index.checked_add(1), DerivationIndex::new(101).ok());
+        assert_eq!(index.saturating_add(100).as_u32(), 200);
+        assert_eq!(DerivationIndex::MAX.checked_add(1), None);
+        assert_eq!(DerivationIndex::MAX.checked_add(u32::MAX), None);
+        assert_eq!(DerivationIndex::MAX.saturating_add(1), DerivationIndex::MAX);
+        assert_eq!(
+            DerivationIndex::new(HARDENED_INDEX - 10)
+                .unwrap()
+                .saturating_add(100),
+            DerivationIndex::MAX
+        );
+        assert_eq!(index.saturating_sub(101), DerivationIndex::ZERO);
+        assert_eq!(index.saturating_sub(1).as_u32(), 99);
+
+        let indexes: Vec<u32> = index
+            .up_to(index.saturating_add(2))
+            .map(u32::from)
+            .collect();
+        assert_eq!(indexes, vec![100, 101, 102]);
+        assert_eq!(index.up_to(DerivationIndex::ZERO).count(), 0);
+        assert_eq!(DerivationIndex::MAX.up_to(DerivationIndex::MAX).count(), 1);
+    }
+
+    #[test]
+    fn derivation_index_child_number() {
+        let index = DerivationIndex::new(42).unwrap();
+        let child_number: ChildNumber = index.into();
+        assert_eq!(child_number, ChildNumber::Normal { index: 42 });
+        assert_eq!(DerivationIndex::try_from(child_number).unwrap(), index);
+
+        let hardened = ChildNumber::Hardened { index: 42 };
+        assert_eq!(
+            DerivationIndex::try_from(hardened),
+            Err(HardenedIndexError(HARDENED_INDEX + 42))
+        );
+        // ChildNumber::from() silently creates a hardened one above 2^31, we don't.
+        assert!(DerivationIndex::try_from(ChildNumber::from(HARDENED_INDEX)).is_err());
+    }
+
+    #[test]
+    fn derivation_index_serde() {
+        let index = DerivationIndex::new(1_234).unwrap();
+        let json = serde_json::to_string(&index).unwrap();
+        assert_eq!(json, "1234");
+        assert_eq!(
+            serde_json::from_str::<DerivationIndex>(&json).unwrap(),
+            index
+        );
+
+        assert_eq!(
+            serde_json::from_str::<DerivationIndex>("2147483647").unwrap(),
+            DerivationIndex::MAX
+        );
+        let err = serde_json::from_str::<DerivationIndex>("2147483648")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is hardened"), "{}", err);
+        serde_json::from_str::<DerivationIndex>("-1").unwrap_err();
+        serde_json::from_str::<DerivationIndex>("\"12\"").unwrap_err();
+    }
+}
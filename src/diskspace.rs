@@ -0,0 +1,173 @@
+//! Monitoring of the free space on the filesystem holding our data directory.
+//!
+//! Running out of disk space while writing to the database could corrupt it at the worst
+//! possible moment. Below a warning threshold we log it and report it in `getinfo`, below a
+//! critical one we refuse the commands that would store new transactions in the database. We
+//! keep monitoring the chain in both cases. The filesystem statistics are accessed through the
+//! [FsStats] trait so the thresholds can be tested without actually filling a disk.
+
+use std::{fmt, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+pub trait FsStats: fmt::Debug + Send + Sync {
+    /// The number of bytes available to us on the filesystem holding this path.
+    fn available_space(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// The filesystems of the system we are running on.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemFsStats;
+
+impl FsStats for SystemFsStats {
+    #[cfg(unix)]
+    fn available_space(&self, path: &Path) -> io::Result<u64> {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The blocks available to unprivileged users, not the free ones.
+        Ok((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+    }
+
+    #[cfg(not(unix))]
+    fn available_space(&self, _: &Path) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Checking the available disk space is only supported on UNIX",
+        ))
+    }
+}
+
+/// How worried we are about the space left on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskSpaceLevel {
+    Ok,
+    /// Below the warning threshold
+    Low,
+    /// Below the critical threshold, we don't store new transactions anymore
+    Critical,
+}
+
+impl fmt::Display for DiskSpaceLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Low => write!(f, "low"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// The space available on the filesystem holding our data directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpace {
+    /// In bytes
+    pub available: u64,
+    pub level: DiskSpaceLevel,
+}
+
+impl DiskSpace {
+    /// Check the space available for `path`, against thresholds in bytes.
+    pub fn check(
+        fs_stats: &dyn FsStats,
+        path: &Path,
+        warning: u64,
+        critical: u64,
+    ) -> io::Result<DiskSpace> {
+        let available = fs_stats.available_space(path)?;
+        let level = if available < critical {
+            DiskSpaceLevel::Critical
+        } else if available < warning {
+            DiskSpaceLevel::Low
+        } else {
+            DiskSpaceLevel::Ok
+        };
+
+        Ok(DiskSpace { available, level })
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::FsStats;
+
+    use std::{
+        io,
+        path::Path,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    /// A filesystem with as much space available as it's told to.
+    #[derive(Debug)]
+    pub struct MockFsStats {
+        available: AtomicU64,
+    }
+
+    impl MockFsStats {
+        pub fn new(available: u64) -> Self {
+            Self {
+                available: AtomicU64::new(available),
+            }
+        }
+
+        pub fn set_available(&self, available: u64) {
+            self.available.store(available, Ordering::SeqCst);
+        }
+    }
+
+    impl FsStats for MockFsStats {
+        fn available_space(&self, _: &Path) -> io::Result<u64> {
+            Ok(self.available.load(Ordering::SeqCst))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_utils::MockFsStats, DiskSpace, DiskSpaceLevel, FsStats, SystemFsStats};
+
+    use std::path::Path;
+
+    #[test]
+    fn disk_space_levels() {
+        let path = Path::new(".");
+        let fs_stats = MockFsStats::new(2_000);
+
+        let space = DiskSpace::check(&fs_stats, path, 1_000, 100).unwrap();
+        assert_eq!(space.available, 2_000);
+        assert_eq!(space.level, DiskSpaceLevel::Ok);
+
+        // The thresholds are the minimum we require
+        fs_stats.set_available(1_000);
+        let space = DiskSpace::check(&fs_stats, path, 1_000, 100).unwrap();
+        assert_eq!(space.level, DiskSpaceLevel::Ok);
+        fs_stats.set_available(999);
+        let space = DiskSpace::check(&fs_stats, path, 1_000, 100).unwrap();
+        assert_eq!(space.level, DiskSpaceLevel::Low);
+
+        fs_stats.set_available(100);
+        let space = DiskSpace::check(&fs_stats, path, 1_000, 100).unwrap();
+        assert_eq!(space.level, DiskSpaceLevel::Low);
+        fs_stats.set_available(99);
+        let space = DiskSpace::check(&fs_stats, path, 1_000, 100).unwrap();
+        assert_eq!(space.level, DiskSpaceLevel::Critical);
+        assert_eq!(space.available, 99);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn system_fs_stats() {
+        // There is some space left for us on the disk we are building on
+        assert!(SystemFsStats.available_space(Path::new(".")).unwrap() > 0);
+        assert!(SystemFsStats
+            .available_space(Path::new("/this/does/not/exist"))
+            .is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! The address of a server we connect to (the Coordinator, a cosigning server or a watchtower),
+//! as given in the configuration: `host:port`, or a bare host to use the default port of the
+//! kind of server on our network. The host is an IP address, IPv6 ones being enclosed in
+//! brackets, or a hostname.
+//!
+//! Only the syntax is checked when loading the configuration. Hostnames are resolved each time
+//! we connect, so that a server moving to another address doesn't need a restart and a
+//! resolution failure is just a connection failure.
+
+use crate::communication::ServerKind;
+
+use revault_tx::bitcoin::Network;
+
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    str::FromStr,
+};
+
+/// The port a kind of server listens on by default on this network
+pub fn default_port(kind: ServerKind, network: Network) -> u16 {
+    let base = match kind {
+        ServerKind::Coordinator => 8383,
+        ServerKind::Cosigner => 8384,
+        ServerKind::Watchtower => 8385,
+    };
+    match network {
+        Network::Bitcoin => base,
+        Network::Testnet => base + 10_000,
+        Network::Regtest => base + 20_000,
+        Network::Signet => base + 30_000,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Host {
+    Ip(IpAddr),
+    Name(String),
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Host::Ip(IpAddr::V6(ip)) => write!(f, "[{}]", ip),
+            Host::Ip(IpAddr::V4(ip)) => write!(f, "{}", ip),
+            Host::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// A hostname as per RFC 1123: dot-separated labels of at most 63 letters, digits and hyphens,
+// not starting nor ending with a hyphen, and 253 characters at most.
+fn check_hostname(name: &str) -> Result<(), String> {
+    if name.len() > 253 {
+        return Err(format!("hostname '{}' is too long", name));
+    }
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid label '{}' in hostname '{}'", label, name));
+        }
+        if label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(format!("invalid label '{}' in hostname '{}'", label, name));
+        }
+    }
+    // Top-level domains are never numeric, it's a mistyped IPv4 address
+    if name
+        .rsplit('.')
+        .next()
+        .map(|tld| tld.chars().all(|c| c.is_ascii_digit()))
+        .unwrap_or(false)
+    {
+        return Err(format!("'{}' is not a valid IPv4 address", name));
+    }
+
+    Ok(())
+}
+
+/// A server's address as configured. The port is None until the configuration applied the
+/// default one of the network.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Endpoint {
+    host: Host,
+    port: Option<u16>,
+}
+
+impl Endpoint {
+    pub fn host(&self) -> &Host {
+        &self.host
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Use this port if none was given
+    pub fn set_default_port(&mut self, port: u16) {
+        self.port.get_or_insert(port);
+    }
+
+    /// The addresses to try to connect to, resolving the hostname if need be.
+    pub fn socket_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        let port = self.port.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No port for server at '{}'", self),
+            )
+        })?;
+        match self.host {
+            Host::Ip(ip) => Ok(vec![SocketAddr::new(ip, port)]),
+            Host::Name(ref name) => {
+                let addrs: Vec<SocketAddr> = (name.as_str(), port)
+                    .to_socket_addrs()
+                    .map_err(|e| io::Error::new(e.kind(), format!("Resolving '{}': {}", name, e)))?
+                    .collect();
+                if addrs.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Resolving '{}': no address", name),
+                    ));
+                }
+                Ok(addrs)
+            }
+        }
+    }
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Self {
+        Endpoint {
+            host: Host::Ip(addr.ip()),
+            port: Some(addr.port()),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| format!("Invalid server address '{}': {}", s, reason);
+        let parse_port = |port: &str| match port.parse::<u16>() {
+            Ok(0) | Err(_) => Err(invalid(format!("invalid port '{}'", port))),
+            Ok(port) => Ok(Some(port)),
+        };
+
+        let (host, port) = if s.starts_with('[') {
+            let end = s
+                .find(']')
+                .ok_or_else(|| invalid("missing closing bracket".to_string()))?;
+            let ip = Ipv6Addr::from_str(&s[1..end])
+                .map_err(|_| invalid(format!("'{}' is not an IPv6 address", &s[1..end])))?;
+            let port = match &s[end + 1..] {
+                "" => None,
+                rest if rest.starts_with(':') => parse_port(&rest[1..])?,
+                rest => return Err(invalid(format!("unexpected '{}' after the address", rest))),
+            };
+            (Host::Ip(IpAddr::V6(ip)), port)
+        } else {
+            let (host, port) = match s.find(':') {
+                Some(i) if s[i + 1..].contains(':') => {
+                    return Err(invalid(
+                        "IPv6 addresses must be enclosed in brackets, as in '[::1]:8383'"
+                            .to_string(),
+                    ))
+                }
+                Some(i) => (&s[..i], parse_port(&s[i + 1..])?),
+                None => (s, None),
+            };
+            if host.is_empty() {
+                return Err(invalid("no host".to_string()));
+            }
+            let host = match Ipv4Addr::from_str(host) {
+                Ok(ip) => Host::Ip(IpAddr::V4(ip)),
+                Err(_) => {
+                    check_hostname(host).map_err(invalid)?;
+                    Host::Name(host.to_string())
+                }
+            };
+            (host, port)
+        };
+
+        Ok(Endpoint { host, port })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => write!(f, "{}", self.host),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_port, Endpoint, Host};
+    use crate::communication::ServerKind;
+
+    use revault_tx::bitcoin::Network;
+
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+        str::FromStr,
+    };
+
+    #[test]
+    fn endpoint_syntax() {
+        let endpoint = |s: &str| Endpoint::from_str(s).unwrap();
+
+        // IPv4, with and without port
+        let ipv4 = endpoint("127.0.0.1:8383");
+        assert_eq!(ipv4.host(), &Host::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert_eq!(ipv4.port(), Some(8383));
+        assert_eq!(endpoint("10.0.0.2").port(), None);
+
+        // IPv6, in brackets
+        let ipv6 = endpoint("[::1]:8383");
+        assert_eq!(ipv6.host(), &Host::Ip(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert_eq!(ipv6.port(), Some(8383));
+        assert_eq!(ipv6.to_string(), "[::1]:8383");
+        let ipv6 = endpoint("[2001:db8::8a2e:370:7334]");
+        assert_eq!(ipv6.port(), None);
+        assert_eq!(ipv6.to_string(), "[2001:db8::8a2e:370:7334]");
+
+        // Hostnames, with and without port
+        let name = endpoint("coordinator.revault.example:1234");
+        assert_eq!(
+            name.host(),
+            &Host::Name("coordinator.revault.example".to_string())
+        );
+        assert_eq!(name.port(), Some(1234));
+        assert_eq!(name.to_string(), "coordinator.revault.example:1234");
+        assert_eq!(endpoint("localhost").port(), None);
+        endpoint("cosigner-1.internal");
+        endpoint("wt0");
+        endpoint("xyz2h5vj5ymk43wdlrkqbbedsnvb3d6dj5skfwrngcnktc4vqjgutgyd.onion:8383");
+
+        for invalid in &[
+            "",
+            ":8383",
+            "127.0.0.1:",
+            "127.0.0.1:0",
+            "127.0.0.1:65536",
+            "127.0.0.1:port",
+            "256.0.0.1:8383",
+            "10.0.0",
+            "::1",
+            "::1:8383",
+            "2001:db8::1",
+            "[::1",
+            "[::1]8383",
+            "[::1]:",
+            "[127.0.0.1]:8383",
+            "[not.an.ip]:8383",
+            "coordinator..example",
+            "-coordinator.example",
+            "coordinator-.example:8383",
+            "coordinator_1.example",
+            "coordinator.example.",
+            "http://coordinator.example",
+            "coordinator.example:8383/path",
+            "coördinator.example",
+        ] {
+            assert!(
+                Endpoint::from_str(invalid).is_err(),
+                "'{}' should be invalid",
+                invalid
+            );
+        }
+        assert!(Endpoint::from_str(&format!("{}.example", "a".repeat(64))).is_err());
+        assert!(Endpoint::from_str(&["a".repeat(63); 4].join(".")).is_err());
+    }
+
+    #[test]
+    fn endpoint_default_port() {
+        let mut endpoint = Endpoint::from_str("coordinator.example").unwrap();
+        assert!(endpoint.socket_addrs().is_err());
+        endpoint.set_default_port(default_port(ServerKind::Coordinator, Network::Testnet));
+        assert_eq!(endpoint.to_string(), "coordinator.example:18383");
+
+        // An explicit port is kept
+        let mut endpoint = Endpoint::from_str("[::1]:4242").unwrap();
+        endpoint.set_default_port(default_port(ServerKind::Cosigner, Network::Bitcoin));
+        assert_eq!(
+            endpoint.socket_addrs().unwrap(),
+            vec!["[::1]:4242".parse::<SocketAddr>().unwrap()]
+        );
+
+        // Each kind of server has its own, on each network
+        let mut ports = Vec::new();
+        for kind in &[
+            ServerKind::Coordinator,
+            ServerKind::Cosigner,
+            ServerKind::Watchtower,
+        ] {
+            for network in &[
+                Network::Bitcoin,
+                Network::Testnet,
+                Network::Signet,
+                Network::Regtest,
+            ] {
+                ports.push(default_port(*kind, *network));
+            }
+        }
+        let mut unique = ports.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), ports.len());
+    }
+
+    #[test]
+    fn endpoint_resolution() {
+        let addr: SocketAddr = "127.0.0.1:8383".parse().unwrap();
+        assert_eq!(Endpoint::from(addr).socket_addrs().unwrap(), vec![addr]);
+
+        // Resolved at connection time, the error mentions the hostname
+        let endpoint = Endpoint::from_str("revaultd-test.invalid:8383").unwrap();
+        let err = endpoint.socket_addrs().unwrap_err();
+        assert!(err.to_string().contains("revaultd-test.invalid"), "{}", err);
+    }
+}
@@ -0,0 +1,562 @@
+//! Run a user-supplied command on vault status transitions, for operators to plug their own
+//! alerting.
+//!
+//! Status transitions are journaled in database by a trigger, whoever performed them (the
+//! poller or an RPC command). The poller regularly drains the journal and hands the
+//! transitions to the [HookRunner], which runs the command in a separate thread: a slow or
+//! failing command never delays nor affects the processing of the vaults.
+//!
+//! The command is also run, regardless of the configured statuses, for the vaults canceled
+//! after their Unvault timelock expired, and for each vault whose revocation transactions
+//! bitcoind would currently refuse. For the latter it is passed `revocation_rejected` in place
+//! of the new status, and the reason as a last argument.
+//!
+//! Finally it is run once for each run of the emergency procedure, which is journaled in
+//! database as well, with a summary of what became of the vaults.
+//!
+//! If `alert_tiers` are configured, the transitions to the [crate::alerts::ALERTED_STATUSES]
+//! are handed to the [Alerter] instead, whose tier for the vault value decides whether the
+//! command is run.
+
+use crate::{
+    alerts::Alerter,
+    database::{
+        actions::{db_mark_emergency_runs_notified, db_remove_vault_status_changes},
+        interface::{
+            db_cancel_transaction, db_emer_transaction, db_unnotified_emergency_runs,
+            db_unvault_emer_transaction, db_unvault_transaction, db_vault_status_changes,
+        },
+        schema::{DbEmergencyRun, DbTransaction, DbVaultStatusChange, EmergencyOutcomeKind},
+        DatabaseError,
+    },
+    revaultd::VaultStatus,
+};
+use revault_tx::bitcoin::{OutPoint, Txid};
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// How many commands may be running at the same time. Others are queued.
+const MAX_RUNNING_HOOKS: usize = 4;
+
+// How often we check on the running commands.
+const HOOKS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An event to run the notify command for
+#[derive(Debug, Clone, PartialEq)]
+enum Notification {
+    /// A vault status transition
+    StatusChange {
+        outpoint: OutPoint,
+        old_status: VaultStatus,
+        new_status: VaultStatus,
+        txid: Option<Txid>,
+    },
+    /// bitcoind would not accept a revocation transaction of this vault in its mempool
+    RevocationRejected {
+        outpoint: OutPoint,
+        status: VaultStatus,
+        txid: Txid,
+        reason: String,
+    },
+    /// A run of the emergency procedure, with the number of vaults for each outcome
+    Emergency {
+        broadcast: usize,
+        already_broadcast: usize,
+        skipped: usize,
+        missing_signatures: usize,
+        rejected: usize,
+    },
+}
+
+impl Notification {
+    // What it is about, for logging
+    fn subject(&self) -> String {
+        match self {
+            Notification::StatusChange { outpoint, .. }
+            | Notification::RevocationRejected { outpoint, .. } => {
+                format!("vault at '{}'", outpoint)
+            }
+            Notification::Emergency { .. } => "emergency run".to_string(),
+        }
+    }
+
+    fn emergency(run: &DbEmergencyRun) -> Notification {
+        let count = |kind| run.outcomes.iter().filter(|o| o.kind == kind).count();
+        Notification::Emergency {
+            broadcast: count(EmergencyOutcomeKind::Broadcast),
+            already_broadcast: count(EmergencyOutcomeKind::AlreadyBroadcast),
+            skipped: count(EmergencyOutcomeKind::SkippedStatus),
+            missing_signatures: count(EmergencyOutcomeKind::MissingSignatures),
+            rejected: count(EmergencyOutcomeKind::Rejected),
+        }
+    }
+}
+
+struct RunningHook {
+    child: Child,
+    notification: Notification,
+    started_at: Instant,
+}
+
+/// Runs the `notify_command` in the background for the transitions to the configured statuses.
+pub struct HookRunner {
+    statuses: Vec<VaultStatus>,
+    sender: mpsc::Sender<Notification>,
+}
+
+impl HookRunner {
+    /// Start the thread running `command` for the transitions to any of `statuses`, killing
+    /// it if it did not exit after `timeout`.
+    pub fn start(command: PathBuf, statuses: Vec<VaultStatus>, timeout: Duration) -> HookRunner {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || hooks_loop(command, timeout, receiver));
+
+        HookRunner { statuses, sender }
+    }
+
+    /// Whether a transition to this status should be notified
+    pub fn is_notified(&self, status: VaultStatus) -> bool {
+        self.statuses.contains(&status)
+    }
+
+    fn notify(&self, notification: Notification) {
+        let notified = match notification {
+            Notification::StatusChange {
+                old_status,
+                new_status,
+                ..
+            } => self.is_notified(new_status) || is_late_cancel(old_status, new_status),
+            Notification::RevocationRejected { .. } | Notification::Emergency { .. } => true,
+        };
+        if notified {
+            self.send(notification);
+        }
+    }
+
+    // Run the command for this notification, whatever the configured statuses.
+    fn send(&self, notification: Notification) {
+        // The thread only stops once we are dropped
+        self.sender
+            .send(notification)
+            .expect("Hooks thread is running");
+    }
+
+    /// Notify that bitcoind currently rejects the revocation transaction `txid` of the vault
+    /// at `outpoint`, for this `reason`.
+    pub fn notify_revocation_rejected(
+        &self,
+        outpoint: OutPoint,
+        status: VaultStatus,
+        txid: Txid,
+        reason: String,
+    ) {
+        self.notify(Notification::RevocationRejected {
+            outpoint,
+            status,
+            txid,
+            reason,
+        });
+    }
+}
+
+// A vault getting canceled once its Unvault timelock expired. It's always notified, whatever
+// the configured statuses.
+fn is_late_cancel(old_status: VaultStatus, new_status: VaultStatus) -> bool {
+    matches!(old_status, VaultStatus::Spendable | VaultStatus::Spending)
+        && new_status == VaultStatus::Canceling
+}
+
+fn spawn_hook(command: &Path, notification: &Notification) -> std::io::Result<Child> {
+    let mut command = Command::new(command);
+    match notification {
+        Notification::StatusChange {
+            outpoint,
+            old_status,
+            new_status,
+            txid,
+        } => command
+            .arg(outpoint.to_string())
+            .arg(old_status.to_string())
+            .arg(new_status.to_string())
+            .arg(txid.map(|txid| txid.to_string()).unwrap_or_default()),
+        Notification::RevocationRejected {
+            outpoint,
+            status,
+            txid,
+            reason,
+        } => command
+            .arg(outpoint.to_string())
+            .arg(status.to_string())
+            .arg("revocation_rejected")
+            .arg(txid.to_string())
+            .arg(reason),
+        Notification::Emergency {
+            broadcast,
+            already_broadcast,
+            skipped,
+            missing_signatures,
+            rejected,
+        } => command
+            .arg("emergency")
+            .arg(broadcast.to_string())
+            .arg(already_broadcast.to_string())
+            .arg(skipped.to_string())
+            .arg(missing_signatures.to_string())
+            .arg(rejected.to_string()),
+    };
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+}
+
+// Check on a running command. Returns true if we are done with it.
+fn hook_done(hook: &mut RunningHook, command: &Path, timeout: Duration) -> bool {
+    let subject = hook.notification.subject();
+    match hook.child.try_wait() {
+        Ok(Some(status)) => {
+            if !status.success() {
+                log_event!(
+                    log::Level::Error,
+                    "hook_failure",
+                    subject = subject,
+                    error = status;
+                    "Notify command '{}' failed for {}: '{}'",
+                    command.display(),
+                    subject,
+                    status
+                );
+            }
+            true
+        }
+        Ok(None) => {
+            if hook.started_at.elapsed() < timeout {
+                return false;
+            }
+            log_event!(
+                log::Level::Error,
+                "hook_failure",
+                subject = subject,
+                error = "timeout";
+                "Notify command '{}' for {} did not exit after {:?}, killing it.",
+                command.display(),
+                subject,
+                timeout
+            );
+            if let Err(e) = hook.child.kill().and_then(|_| hook.child.wait()) {
+                log::error!("Error killing notify command: '{}'", e);
+            }
+            true
+        }
+        Err(e) => {
+            log::error!("Error waiting for notify command: '{}'", e);
+            true
+        }
+    }
+}
+
+fn hooks_loop(command: PathBuf, timeout: Duration, receiver: mpsc::Receiver<Notification>) {
+    let mut queue = VecDeque::new();
+    let mut running: Vec<RunningHook> = Vec::with_capacity(MAX_RUNNING_HOOKS);
+    let mut disconnected = false;
+
+    while !disconnected || !queue.is_empty() || !running.is_empty() {
+        // Wait for new notifications, but regularly check on the running commands if any.
+        let received = if running.is_empty() && queue.is_empty() {
+            receiver
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+        } else if disconnected {
+            thread::sleep(HOOKS_POLL_INTERVAL);
+            Err(mpsc::RecvTimeoutError::Timeout)
+        } else {
+            receiver.recv_timeout(HOOKS_POLL_INTERVAL)
+        };
+        match received {
+            Ok(notification) => queue.push_back(notification),
+            Err(mpsc::RecvTimeoutError::Disconnected) => disconnected = true,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+        }
+
+        let mut i = 0;
+        while i < running.len() {
+            if hook_done(&mut running[i], &command, timeout) {
+                running.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        while running.len() < MAX_RUNNING_HOOKS {
+            let notification = match queue.pop_front() {
+                Some(n) => n,
+                None => break,
+            };
+            log::debug!("Running notify command for '{:?}'", notification);
+            match spawn_hook(&command, &notification) {
+                Ok(child) => running.push(RunningHook {
+                    child,
+                    notification,
+                    started_at: Instant::now(),
+                }),
+                Err(e) => log_event!(
+                    log::Level::Error,
+                    "hook_failure",
+                    subject = notification.subject(),
+                    error = e;
+                    "Could not run notify command '{}': '{}'",
+                    command.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+// The transaction that made the vault transition to its new status, if any.
+fn transition_txid(
+    db_path: &Path,
+    change: &DbVaultStatusChange,
+) -> Result<Option<Txid>, DatabaseError> {
+    let presigned_txid = |tx: Option<DbTransaction>| tx.map(|tx| tx.psbt.txid());
+
+    Ok(match change.new_status {
+        VaultStatus::Unconfirmed | VaultStatus::Funded => Some(change.deposit_outpoint.txid),
+        VaultStatus::Unvaulting | VaultStatus::Unvaulted | VaultStatus::Spendable => {
+            presigned_txid(db_unvault_transaction(db_path, change.vault_id)?)
+        }
+        VaultStatus::Canceling | VaultStatus::Canceled => match change.final_txid {
+            Some(txid) => Some(txid),
+            None => presigned_txid(db_cancel_transaction(db_path, change.vault_id)?),
+        },
+        VaultStatus::EmergencyVaulting | VaultStatus::EmergencyVaulted => {
+            presigned_txid(db_emer_transaction(db_path, change.vault_id)?)
+        }
+        VaultStatus::UnvaultEmergencyVaulting | VaultStatus::UnvaultEmergencyVaulted => {
+            presigned_txid(db_unvault_emer_transaction(db_path, change.vault_id)?)
+        }
+        VaultStatus::Spending | VaultStatus::Spent => change.final_txid,
+        VaultStatus::Securing
+        | VaultStatus::Secured
+        | VaultStatus::Activating
+        | VaultStatus::Active => None,
+    })
+}
+
+/// Drain the journal of vault status transitions, notifying them to the `hooks` and the
+/// `alerter` if any. Returns the number of transitions drained.
+pub fn process_status_changes(
+    db_path: &Path,
+    hooks: Option<&HookRunner>,
+    alerter: Option<&Alerter>,
+) -> Result<usize, DatabaseError> {
+    let changes = db_vault_status_changes(db_path)?;
+    let (last_id, n_changes) = match changes.last() {
+        Some(change) => (change.id, changes.len()),
+        None => return Ok(0),
+    };
+
+    for change in changes {
+        let late_cancel = is_late_cancel(change.old_status, change.new_status);
+        // The tiers, if any, take over the configured statuses for the transitions they cover
+        let alerter = alerter.filter(|alerter| alerter.is_alerted(change.new_status));
+        let notified = hooks
+            .map(|hooks| hooks.is_notified(change.new_status) || late_cancel)
+            .unwrap_or(false);
+        if alerter.is_none() && !notified {
+            continue;
+        }
+
+        let txid = transition_txid(db_path, &change)?;
+        let run_command = match alerter {
+            Some(alerter) => alerter
+                .alert(&change, txid)
+                .map(|tier| tier.run_notify_command)
+                .unwrap_or(false),
+            None => notified,
+        };
+        if let Some(hooks) = hooks {
+            if run_command || late_cancel {
+                hooks.send(Notification::StatusChange {
+                    outpoint: change.deposit_outpoint,
+                    old_status: change.old_status,
+                    new_status: change.new_status,
+                    txid,
+                });
+            }
+        }
+    }
+
+    db_remove_vault_status_changes(db_path, last_id)?;
+
+    Ok(n_changes)
+}
+
+/// Notify the runs of the emergency procedure to the `hooks` if any, each of them once and as a
+/// whole. Returns the number of runs processed.
+pub fn process_emergency_runs(
+    db_path: &Path,
+    hooks: Option<&HookRunner>,
+) -> Result<usize, DatabaseError> {
+    let runs = db_unnotified_emergency_runs(db_path)?;
+    let last_id = match runs.last() {
+        Some(run) => run.id,
+        None => return Ok(0),
+    };
+
+    if let Some(hooks) = hooks {
+        for run in &runs {
+            hooks.notify(Notification::emergency(run));
+        }
+    }
+
+    db_mark_emergency_runs_notified(db_path, last_id)?;
+
+    Ok(runs.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HookRunner, Notification};
+    use crate::{revaultd::VaultStatus, utils::test_utils::test_datadir};
+
+    use revault_tx::bitcoin::{OutPoint, Txid};
+    use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf, str::FromStr, thread, time};
+
+    fn write_script(datadir: &PathBuf, name: &str, content: &str) -> PathBuf {
+        let path = datadir.join(name);
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o700)).unwrap();
+        fs::canonicalize(path).unwrap()
+    }
+
+    fn wait_for_lines(path: &PathBuf, count: usize) -> Vec<String> {
+        for _ in 0..50 {
+            if let Ok(content) = fs::read_to_string(path) {
+                let lines: Vec<String> = content.lines().map(String::from).collect();
+                if lines.len() >= count {
+                    return lines;
+                }
+            }
+            thread::sleep(time::Duration::from_millis(100));
+        }
+        panic!("Timed out waiting for the notify command");
+    }
+
+    #[test]
+    fn hook_runner() {
+        let datadir = test_datadir();
+        fs::create_dir_all(&datadir).unwrap();
+        let out_file = fs::canonicalize(&datadir).unwrap().join("notified");
+        let command = write_script(
+            &datadir,
+            "notify.sh",
+            &format!("#!/bin/sh\necho \"$@\" >> {}\n", out_file.display()),
+        );
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let txid =
+            Txid::from_str("a4d4bf7e8d3a0b6e5ef4a2cbb0f20ff0d4ee1c2a4f6e82b6a4e3ae3c9c59e7d1")
+                .unwrap();
+
+        let hooks = HookRunner::start(
+            command,
+            vec![VaultStatus::Unvaulting],
+            time::Duration::from_secs(10),
+        );
+        // Not a status we care about
+        hooks.notify(Notification::StatusChange {
+            outpoint,
+            old_status: VaultStatus::Unconfirmed,
+            new_status: VaultStatus::Funded,
+            txid: Some(outpoint.txid),
+        });
+        // Nor is a Cancel before the Unvault timelock expired
+        hooks.notify(Notification::StatusChange {
+            outpoint,
+            old_status: VaultStatus::Unvaulted,
+            new_status: VaultStatus::Canceling,
+            txid: None,
+        });
+        hooks.notify(Notification::StatusChange {
+            outpoint,
+            old_status: VaultStatus::Active,
+            new_status: VaultStatus::Unvaulting,
+            txid: Some(txid),
+        });
+        // But a Cancel after the Unvault timelock expired always is
+        hooks.notify(Notification::StatusChange {
+            outpoint,
+            old_status: VaultStatus::Spendable,
+            new_status: VaultStatus::Canceling,
+            txid: Some(txid),
+        });
+        // Rejected revocations are always notified
+        hooks.notify_revocation_rejected(
+            outpoint,
+            VaultStatus::Secured,
+            txid,
+            "min relay fee not met".to_string(),
+        );
+        // As are the runs of the emergency procedure
+        hooks.notify(Notification::Emergency {
+            broadcast: 2,
+            already_broadcast: 0,
+            skipped: 1,
+            missing_signatures: 1,
+            rejected: 1,
+        });
+        let mut lines = wait_for_lines(&out_file, 4);
+        // They may run concurrently
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                format!("{} active unvaulting {}", outpoint, txid),
+                format!(
+                    "{} secured revocation_rejected {} min relay fee not met",
+                    outpoint, txid
+                ),
+                format!("{} spendable canceling {}", outpoint, txid),
+                "emergency 2 0 1 1 1".to_string(),
+            ]
+        );
+
+        // A command not exiting in time is killed
+        let command = write_script(
+            &datadir,
+            "stuck.sh",
+            &format!(
+                "#!/bin/sh\necho started >> {0}\nsleep 2\necho finished >> {0}\n",
+                out_file.display()
+            ),
+        );
+        let hooks = HookRunner::start(
+            command,
+            vec![VaultStatus::EmergencyVaulting],
+            time::Duration::from_millis(200),
+        );
+        hooks.notify(Notification::StatusChange {
+            outpoint,
+            old_status: VaultStatus::Active,
+            new_status: VaultStatus::EmergencyVaulting,
+            txid: Some(txid),
+        });
+        wait_for_lines(&out_file, 5);
+        thread::sleep(time::Duration::from_secs(3));
+        let content = fs::read_to_string(&out_file).unwrap();
+        assert!(content.ends_with("started\n"));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
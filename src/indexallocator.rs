@@ -0,0 +1,323 @@
+//! The derivation index of the next deposit address we hand out is shared by the RPC thread,
+//! handing out addresses, and the poller, moving past the index of the deposits it detects.
+//! Both go through the [IndexAllocator], which persists the index in the database before
+//! updating it in memory and serializes the changes, so that an address can't be handed out
+//! twice nor an index skipped by a race between the two.
+//!
+//! `getdepositaddress` peeks at the next index, which stays the same until a deposit is received
+//! (hence shared by all participants). `getnewdepositaddress` reserves it and moves to the next
+//! one, for callers needing a distinct address per depositor. The reserved indexes which were
+//! not paid yet are kept track of, and their number limited, so that we never hand out addresses
+//! far beyond the ones the other participants watch.
+
+use crate::{
+    database::{actions::db_update_deposit_index, DatabaseError},
+    derivation::DerivationIndex,
+};
+
+use std::{collections::BTreeSet, error, fmt, path::Path, sync::Mutex};
+
+#[derive(Debug)]
+pub enum IndexAllocError {
+    Database(DatabaseError),
+    /// (Reserved, Limit) addresses were handed out without being paid yet
+    TooManyReserved(usize, usize),
+    /// (Index, Watched up to) we would not detect a deposit to it
+    NotWatched(DerivationIndex, DerivationIndex),
+    /// The last unhardened index was reached
+    Exhausted,
+}
+
+impl fmt::Display for IndexAllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Database(e) => write!(f, "Persisting the deposit derivation index: '{}'", e),
+            Self::TooManyReserved(reserved, limit) => write!(
+                f,
+                "{} new deposit addresses were handed out and not paid yet (limit is {}), \
+                 use one of them",
+                reserved, limit
+            ),
+            Self::NotWatched(index, watched_up_to) => write!(
+                f,
+                "Deposit address at derivation index {} is above the ones we watch (up to {})",
+                index, watched_up_to
+            ),
+            Self::Exhausted => write!(f, "The last unhardened derivation index was reached"),
+        }
+    }
+}
+
+impl error::Error for IndexAllocError {}
+
+impl From<DatabaseError> for IndexAllocError {
+    fn from(e: DatabaseError) -> Self {
+        Self::Database(e)
+    }
+}
+
+#[derive(Debug)]
+struct Indexes {
+    // The index of the next deposit address we hand out
+    next: DerivationIndex,
+    // The ones reserved by `allocate` with no deposit detected yet
+    reserved: BTreeSet<DerivationIndex>,
+}
+
+/// The owner of the derivation index of the next deposit address we hand out
+#[derive(Debug)]
+pub struct IndexAllocator(Mutex<Indexes>);
+
+impl IndexAllocator {
+    pub fn new(next: DerivationIndex) -> Self {
+        Self(Mutex::new(Indexes {
+            next,
+            reserved: BTreeSet::new(),
+        }))
+    }
+
+    /// Start over from the state of the database, as loaded at startup
+    pub fn reset(
+        &self,
+        next: DerivationIndex,
+        reserved: impl IntoIterator<Item = DerivationIndex>,
+    ) {
+        let mut indexes = self.0.lock().unwrap();
+        indexes.next = next;
+        indexes.reserved = reserved.into_iter().filter(|i| *i < next).collect();
+    }
+
+    /// The index of the next deposit address we hand out, without reserving it
+    pub fn current(&self) -> DerivationIndex {
+        self.0.lock().unwrap().next
+    }
+
+    /// The indexes handed out by [IndexAllocator::allocate] we didn't detect a deposit at yet
+    pub fn reserved(&self) -> Vec<DerivationIndex> {
+        self.0.lock().unwrap().reserved.iter().copied().collect()
+    }
+
+    /// Reserve the next index, and move to the following one. Fails if `max_reserved` of them
+    /// are still unpaid, or if we don't watch the deposits to it.
+    pub fn allocate(
+        &self,
+        db_path: &Path,
+        max_reserved: usize,
+        watched_up_to: DerivationIndex,
+    ) -> Result<DerivationIndex, IndexAllocError> {
+        let mut indexes = self.0.lock().unwrap();
+        let index = indexes.next;
+        if indexes.reserved.len() >= max_reserved {
+            return Err(IndexAllocError::TooManyReserved(
+                indexes.reserved.len(),
+                max_reserved,
+            ));
+        }
+        if index > watched_up_to {
+            return Err(IndexAllocError::NotWatched(index, watched_up_to));
+        }
+        let next = index.checked_add(1).ok_or(IndexAllocError::Exhausted)?;
+
+        db_update_deposit_index(db_path, next)?;
+        indexes.next = next;
+        indexes.reserved.insert(index);
+
+        Ok(index)
+    }
+
+    /// A deposit was detected at this index: move past it if need be. Returns the index we were
+    /// at if we moved.
+    pub fn advance_past(
+        &self,
+        db_path: &Path,
+        index: DerivationIndex,
+    ) -> Result<Option<DerivationIndex>, IndexAllocError> {
+        let mut indexes = self.0.lock().unwrap();
+        indexes.reserved.remove(&index);
+        if index < indexes.next {
+            return Ok(None);
+        }
+        // FIXME: we should probably go back to 0 at this point.
+        let next = index.checked_add(1).ok_or(IndexAllocError::Exhausted)?;
+
+        db_update_deposit_index(db_path, next)?;
+        let previous = indexes.next;
+        indexes.next = next;
+
+        Ok(Some(previous))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IndexAllocError, IndexAllocator};
+    use crate::{
+        database::{actions::setup_db, interface::db_wallet},
+        derivation::DerivationIndex,
+        utils::test_utils::{dummy_revaultd, test_datadir, UserRole},
+    };
+
+    use std::{
+        collections::BTreeSet,
+        fs,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn allocation() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let db_path = revaultd.db_file();
+        let index = |i| DerivationIndex::new(i).unwrap();
+        let allocator = IndexAllocator::new(index(0));
+
+        // Peeking doesn't move, allocating does and persists it
+        assert_eq!(allocator.current(), index(0));
+        assert_eq!(allocator.current(), index(0));
+        assert_eq!(
+            allocator.allocate(&db_path, 3, index(99)).unwrap(),
+            index(0)
+        );
+        assert_eq!(
+            allocator.allocate(&db_path, 3, index(99)).unwrap(),
+            index(1)
+        );
+        assert_eq!(allocator.current(), index(2));
+        assert_eq!(
+            db_wallet(&db_path).unwrap().deposit_derivation_index,
+            index(2)
+        );
+        assert_eq!(allocator.reserved(), vec![index(0), index(1)]);
+
+        // A deposit to a reserved address releases it
+        assert_eq!(allocator.advance_past(&db_path, index(0)).unwrap(), None);
+        assert_eq!(allocator.reserved(), vec![index(1)]);
+
+        // A deposit above makes us move past it, the reserved ones are kept
+        assert_eq!(
+            allocator.advance_past(&db_path, index(5)).unwrap(),
+            Some(index(2))
+        );
+        assert_eq!(allocator.current(), index(6));
+        assert_eq!(
+            db_wallet(&db_path).unwrap().deposit_derivation_index,
+            index(6)
+        );
+
+        // We don't hand out too many unpaid addresses, nor unwatched ones
+        assert_eq!(
+            allocator.allocate(&db_path, 3, index(99)).unwrap(),
+            index(6)
+        );
+        assert_eq!(
+            allocator.allocate(&db_path, 3, index(99)).unwrap(),
+            index(7)
+        );
+        match allocator.allocate(&db_path, 3, index(99)) {
+            Err(IndexAllocError::TooManyReserved(3, 3)) => {}
+            e => panic!("{:?}", e),
+        }
+        allocator.advance_past(&db_path, index(7)).unwrap();
+        match allocator.allocate(&db_path, 3, index(7)) {
+            Err(IndexAllocError::NotWatched(i, _)) if i == index(8) => {}
+            e => panic!("{:?}", e),
+        }
+        assert_eq!(allocator.current(), index(8));
+        assert_eq!(allocator.reserved(), vec![index(1), index(6)]);
+
+        // Restarting from the database
+        allocator.reset(index(8), vec![index(6), index(9)]);
+        assert_eq!(allocator.reserved(), vec![index(6)]);
+
+        // Nothing after the last unhardened index
+        allocator.reset(DerivationIndex::MAX, vec![]);
+        match allocator.advance_past(&db_path, DerivationIndex::MAX) {
+            Err(IndexAllocError::Exhausted) => {}
+            e => panic!("{:?}", e),
+        }
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn concurrent_allocation() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let db_path = revaultd.db_file();
+        let allocator = Arc::new(IndexAllocator::new(DerivationIndex::ZERO));
+        const MAX_RESERVED: usize = 5;
+        let watched_up_to = DerivationIndex::new(10_000).unwrap();
+
+        // RPC calls handing out new addresses, retrying when too many are unpaid
+        let rpc_threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (allocator, db_path) = (allocator.clone(), db_path.clone());
+                thread::spawn(move || {
+                    let mut allocated = Vec::new();
+                    while allocated.len() < 25 {
+                        match allocator.allocate(&db_path, MAX_RESERVED, watched_up_to) {
+                            Ok(index) => allocated.push(index),
+                            Err(IndexAllocError::TooManyReserved(..)) => thread::yield_now(),
+                            Err(e) => panic!("{}", e),
+                        }
+                    }
+                    allocated
+                })
+            })
+            .collect();
+
+        // The poller detecting deposits to the reserved addresses, and from time to time one
+        // far above from another participant, which makes us skip the indexes below it
+        let skipped = Arc::new(Mutex::new(BTreeSet::new()));
+        let poller = {
+            let (allocator, db_path, skipped) =
+                (allocator.clone(), db_path.clone(), skipped.clone());
+            thread::spawn(move || {
+                let mut paid = 0;
+                while paid < 100 {
+                    match allocator.reserved().first() {
+                        Some(index) => {
+                            assert_eq!(allocator.advance_past(&db_path, *index).unwrap(), None);
+                            paid += 1;
+                        }
+                        None => {
+                            thread::yield_now();
+                            continue;
+                        }
+                    }
+                    if paid % 10 == 0 {
+                        let far = allocator.current().saturating_add(3);
+                        if let Some(previous) = allocator.advance_past(&db_path, far).unwrap() {
+                            skipped.lock().unwrap().extend(previous.up_to(far));
+                        }
+                    }
+                }
+            })
+        };
+
+        let mut allocated = BTreeSet::new();
+        for thread in rpc_threads {
+            for index in thread.join().unwrap() {
+                // Never handed out twice
+                assert!(allocated.insert(index), "{} was allocated twice", index);
+            }
+        }
+        poller.join().unwrap();
+
+        // No index was skipped but the ones below a deposit from another participant
+        let skipped = skipped.lock().unwrap();
+        assert_eq!(allocated.len(), 100);
+        assert!(allocated.is_disjoint(&skipped));
+        let next = allocator.current();
+        assert!(DerivationIndex::ZERO
+            .up_to(next.saturating_sub(1))
+            .all(|i| allocated.contains(&i) || skipped.contains(&i)));
+        assert!(allocator.reserved().is_empty());
+        assert_eq!(db_wallet(&db_path).unwrap().deposit_derivation_index, next);
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
@@ -3,13 +3,32 @@
 //! `server` mod.
 
 use crate::{
-    commands::{CommandError, HistoryEventKind, ListSpendStatus},
+    commands::{
+        Amount, CommandError, EmergencyKeyProof, ErrorCode, ExportKind, HistoryEventKind,
+        ListSpendStatus, RevocationTransactions, VaultHeightFilter,
+    },
+    config::xpub_fingerprint_from_str,
+    database::schema::VaultFlagKind,
+    derivation::DerivationIndex,
+    jsonrpc::{
+        help::{
+            api_version, available_features, method_help, parse_api_version, API_VERSION, METHODS,
+        },
+        stream::{JsonStringWriter, ResponseStream},
+    },
     revaultd::VaultStatus,
-    DaemonControl,
+    rpcstats::{timed_call, Phase, RpcStats},
+    DaemonControl, VERSION,
 };
 
+use revault_net::noise::PublicKey as NoisePubKey;
 use revault_tx::{
-    bitcoin::{util::bip32, Address, OutPoint, Txid},
+    bitcoin::{
+        hashes::hex::{FromHex, ToHex},
+        util::bip32::ExtendedPubKey,
+        Address, OutPoint, PublicKey as BitcoinPubKey, Txid,
+    },
+    miniscript::descriptor::DescriptorPublicKey,
     transactions::{
         CancelTransaction, EmergencyTransaction, SpendTransaction, UnvaultEmergencyTransaction,
         UnvaultTransaction,
@@ -17,16 +36,20 @@ use revault_tx::{
 };
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::Write,
     str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
+    time::Duration,
 };
 
 use jsonrpc_core::{types::error::ErrorCode::ServerError, Error as JsonRpcError};
 use jsonrpc_derive::rpc;
+use serde::Deserialize;
 use serde_json::json;
 
 impl From<CommandError> for JsonRpcError {
@@ -39,21 +62,137 @@ impl From<CommandError> for JsonRpcError {
     }
 }
 
+/// The vaults to get the revocation transactions of: a single one, a list of them, or those
+/// with a given status.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum RevocationTxsSelector {
+    Outpoint(OutPoint),
+    Outpoints(Vec<OutPoint>),
+    Status { status: String },
+}
+
+/// Either the outpoint of a single vault, or a mapping of outpoints to the signed revocation
+/// transactions of each vault.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum RevocationTxsParam {
+    Outpoint(OutPoint),
+    Batch(BTreeMap<OutPoint, RevocationTransactions>),
+}
+
+/// A message signed by one of the keys of the stakeholders' deep-cold setup, with the public key
+/// and the DER signature hex-encoded.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmergencyKeyProofParam {
+    pub pubkey: String,
+    pub message: String,
+    pub signature: String,
+}
+
 #[derive(Clone)]
 pub struct JsonRpcMetaData {
     pub shutdown: Arc<AtomicBool>,
     pub daemon_control: DaemonControl,
+    /// The UID of the client that sent the request, if we could get it
+    pub peer_uid: Option<u32>,
+    /// The Noise static public key of the client that sent the request, if it did over TCP
+    pub peer_noise_key: Option<NoisePubKey>,
+    /// The latencies of the calls, shared by the UNIX socket and the TCP interfaces
+    pub rpc_stats: Arc<Mutex<RpcStats>>,
+    /// The response to the request, if the handler may stream it (on the UNIX socket)
+    pub response_stream: Option<Arc<ResponseStream>>,
 }
 impl jsonrpc_core::Metadata for JsonRpcMetaData {}
 
 impl JsonRpcMetaData {
     pub fn new(daemon_control: DaemonControl) -> Self {
+        let now = daemon_control
+            .revaultd
+            .read()
+            .unwrap()
+            .clock
+            .unix_timestamp();
         JsonRpcMetaData {
             shutdown: Arc::from(AtomicBool::from(false)),
             daemon_control,
+            peer_uid: None,
+            peer_noise_key: None,
+            rpc_stats: Arc::new(Mutex::new(RpcStats::new(now))),
+            response_stream: None,
+        }
+    }
+
+    /// Handle a call to this method, recording how long it took. If it took longer than the
+    /// configured threshold, log where the time was spent.
+    pub fn timed<T, F: FnOnce() -> T>(&self, method: &str, handler: F) -> T {
+        let (res, duration, phases) = timed_call(handler);
+        self.rpc_stats.lock().unwrap().record(method, duration);
+
+        let slow_rpc_call = self.daemon_control.revaultd.read().unwrap().slow_rpc_call;
+        if duration > slow_rpc_call {
+            let ms = |phase| phases.get(phase).as_millis();
+            let slowest = phases
+                .slowest()
+                .map(|phase| phase.to_string())
+                .unwrap_or_else(|| "none".to_string());
+            log_event!(
+                log::Level::Warn,
+                "slow_rpc_call",
+                method = method,
+                duration_ms = duration.as_millis(),
+                slowest_phase = slowest,
+                database_ms = ms(Phase::Database),
+                bitcoind_ms = ms(Phase::Bitcoind),
+                coordinator_ms = ms(Phase::Coordinator),
+                cosigner_ms = ms(Phase::Cosigner),
+                watchtower_ms = ms(Phase::Watchtower);
+                "Slow RPC call '{}': {}ms, mostly in '{}' (database: {}ms, bitcoind: {}ms, \
+                 coordinator: {}ms, cosigners: {}ms, watchtowers: {}ms)",
+                method,
+                duration.as_millis(),
+                slowest,
+                ms(Phase::Database),
+                ms(Phase::Bitcoind),
+                ms(Phase::Coordinator),
+                ms(Phase::Cosigner),
+                ms(Phase::Watchtower)
+            );
+        }
+
+        res
+    }
+
+    /// The same metadata, for a request coming from this client
+    pub fn with_peer_uid(&self, peer_uid: Option<u32>) -> Self {
+        JsonRpcMetaData {
+            peer_uid,
+            ..self.clone()
+        }
+    }
+
+    /// The same metadata, for a request coming from this client over TCP
+    pub fn with_peer_noise_key(&self, peer_noise_key: NoisePubKey) -> Self {
+        JsonRpcMetaData {
+            peer_noise_key: Some(peer_noise_key),
+            ..self.clone()
         }
     }
 
+    /// The same metadata, for a request whose response may be streamed
+    pub fn with_response_stream(&self, response_stream: Arc<ResponseStream>) -> Self {
+        JsonRpcMetaData {
+            response_stream: Some(response_stream),
+            ..self.clone()
+        }
+    }
+
+    /// Whether we are a stakeholder, and whether we are a manager
+    pub fn roles(&self) -> (bool, bool) {
+        let revaultd = self.daemon_control.revaultd.read().unwrap();
+        (revaultd.is_stakeholder(), revaultd.is_manager())
+    }
+
     pub fn is_shutdown(&self) -> bool {
         self.shutdown.load(Ordering::Relaxed)
     }
@@ -76,17 +215,56 @@ pub trait RpcApi {
     #[rpc(meta, name = "getinfo")]
     fn getinfo(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
 
-    /// Print all available commands
+    /// Get the version of the daemon and the metadata of its build
+    #[rpc(meta, name = "version")]
+    fn version(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Print all available commands, or describe the parameters and result of one of them
     #[rpc(meta, name = "help")]
-    fn help(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+    fn help(
+        &self,
+        meta: Self::Metadata,
+        method: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
 
-    /// Get a list of current vaults, which can be sorted by txids or status
+    /// Fail if the daemon implements an older API version than the client requires, otherwise
+    /// tell it the API version and the features available to it
+    #[rpc(meta, name = "hello")]
+    fn hello(
+        &self,
+        meta: Self::Metadata,
+        min_api_version: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// List the commands available to us, depending on our role
+    #[rpc(meta, name = "listcommands")]
+    fn listcommands(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get a list of current vaults, which can be sorted by txids, status or funding height.
+    /// Optionally get the vaults as they were at a given height.
     #[rpc(meta, name = "listvaults")]
     fn listvaults(
         &self,
         meta: Self::Metadata,
         statuses: Option<Vec<String>>,
         outpoints: Option<Vec<OutPoint>>,
+        min_funded_height: Option<u32>,
+        max_funded_height: Option<u32>,
+        as_of_height: Option<u32>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the value of our vaults, by how protected it is
+    #[rpc(meta, name = "getbalances")]
+    fn getbalances(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the fees needed to CPFP the Unvault transactions being broadcast at a feerate, and
+    /// the value available in the CPFP wallet
+    #[rpc(meta, name = "getcpfpreserve")]
+    fn getcpfpreserve(
+        &self,
+        meta: Self::Metadata,
+        feerate: u64,
+        include_active: Option<bool>,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
     /// Get an address to receive funds to the stakeholders' descriptor
@@ -94,28 +272,55 @@ pub trait RpcApi {
     fn getdepositaddress(
         &self,
         meta: Self::Metadata,
-        index: Option<bip32::ChildNumber>,
+        index: Option<DerivationIndex>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get a deposit address no one else will get from us
+    #[rpc(meta, name = "getnewdepositaddress")]
+    fn getnewdepositaddress(&self, meta: Self::Metadata)
+        -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the addresses, scripts and presigned transactions ids of a vault identified by its
+    /// deposit outpoint.
+    #[rpc(meta, name = "getvaultdetails")]
+    fn getvaultdetails(
+        &self,
+        meta: Self::Metadata,
+        outpoint: OutPoint,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Check that a descriptor of the stakeholders' deep-cold setup generates our Emergency
+    /// address, optionally along with a message signed by one of its keys.
+    #[rpc(meta, name = "verifyemergencydescriptor")]
+    fn verifyemergencydescriptor(
+        &self,
+        meta: Self::Metadata,
+        descriptor: Option<String>,
+        derivation_index: Option<u32>,
+        proof: Option<EmergencyKeyProofParam>,
+        store: Option<bool>,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
     /// Get the cancel and both emergency transactions for a vault identified by its deposit
-    /// outpoint.
+    /// outpoint, for a list of vaults, or for the 'funded' vaults.
     #[rpc(meta, name = "getrevocationtxs")]
     fn getrevocationtxs(
         &self,
         meta: Self::Metadata,
-        outpoint: OutPoint,
+        selector: RevocationTxsSelector,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
     /// Give the signed cancel, emergency, and unvault_emergency transactions (as
-    /// base64-encoded PSBTs) for a vault identified by its deposit outpoint.
+    /// base64-encoded PSBTs) for a vault identified by its deposit outpoint, or for
+    /// a batch of vaults.
     #[rpc(meta, name = "revocationtxs")]
     fn revocationtxs(
         &self,
         meta: Self::Metadata,
-        outpoint: OutPoint,
-        cancel_tx: CancelTransaction,
-        emergency_tx: EmergencyTransaction,
-        emergency_unvault_tx: UnvaultEmergencyTransaction,
+        outpoint: RevocationTxsParam,
+        cancel_tx: Option<CancelTransaction>,
+        emergency_tx: Option<EmergencyTransaction>,
+        emergency_unvault_tx: Option<UnvaultEmergencyTransaction>,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
     /// Get the fresh Unvault transactions for a vault identified by its deposit
@@ -137,6 +342,18 @@ pub trait RpcApi {
         unvault_tx: UnvaultTransaction,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
+    /// Get what watchtowers need to enforce the revocation of the Active vaults
+    #[rpc(meta, name = "getwatchdata")]
+    fn getwatchdata(
+        &self,
+        meta: Self::Metadata,
+        since: Option<u64>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// List the final txids of the fully signed presigned transactions, by type
+    #[rpc(meta, name = "listwatchtxids")]
+    fn listwatchtxids(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
     /// Retrieve the presigned transactions of a list of vaults
     #[rpc(meta, name = "listpresignedtransactions")]
     fn listpresignedtransactions(
@@ -145,6 +362,14 @@ pub trait RpcApi {
         outpoints: Option<Vec<OutPoint>>,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
+    /// List the vaults with revocation transactions signed at a feerate below this one
+    #[rpc(meta, name = "getstalerevocations")]
+    fn getstalerevocations(
+        &self,
+        meta: Self::Metadata,
+        feerate: u64,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
     /// Retrieve the onchain transactions of a list of vaults
     #[rpc(meta, name = "listonchaintransactions")]
     fn listonchaintransactions(
@@ -158,8 +383,46 @@ pub trait RpcApi {
         &self,
         meta: Self::Metadata,
         outpoint: Vec<OutPoint>,
-        outputs: BTreeMap<Address, u64>,
+        outputs: BTreeMap<Address, Amount>,
         feerate: u64,
+        allow_high_fees: Option<bool>,
+        allow_self_send: Option<bool>,
+        proposal_id: Option<u32>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Draft a spend proposal, to be approved by enough managers before a Spend transaction can
+    /// be created for it
+    #[rpc(meta, name = "proposespend")]
+    fn proposespend(
+        &self,
+        meta: Self::Metadata,
+        outpoints: Vec<OutPoint>,
+        outputs: BTreeMap<Address, Amount>,
+        feerate: u64,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// List the spend proposals and the managers' decisions about them
+    #[rpc(meta, name = "listspendproposals")]
+    fn listspendproposals(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Record a manager's signed approval of a spend proposal
+    #[rpc(meta, name = "approvespend")]
+    fn approvespend(
+        &self,
+        meta: Self::Metadata,
+        proposal_id: u32,
+        fingerprint: String,
+        signature: String,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Record a manager's signed rejection of a spend proposal
+    #[rpc(meta, name = "rejectspend")]
+    fn rejectspend(
+        &self,
+        meta: Self::Metadata,
+        proposal_id: u32,
+        fingerprint: String,
+        signature: String,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
     #[rpc(meta, name = "updatespendtx")]
@@ -189,6 +452,15 @@ pub trait RpcApi {
         meta: Self::Metadata,
         spend_txid: Txid,
         priority: Option<bool>,
+        idempotency_key: Option<String>,
+        override_schedule: Option<bool>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    #[rpc(meta, name = "abortspend")]
+    fn abortspend(
+        &self,
+        meta: Self::Metadata,
+        spend_txid: Txid,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
     #[rpc(meta, name = "revault")]
@@ -196,14 +468,36 @@ pub trait RpcApi {
         &self,
         meta: Self::Metadata,
         deposit_outpoint: OutPoint,
+        idempotency_key: Option<String>,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
     #[rpc(meta, name = "emergency")]
-    fn emergency(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+    fn emergency(
+        &self,
+        meta: Self::Metadata,
+        idempotency_key: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
 
     #[rpc(meta, name = "getserverstatus")]
     fn getserverstatus(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
 
+    /// Get the configured cosigning servers and their reachability
+    #[rpc(meta, name = "listcosigners")]
+    fn listcosigners(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the participants to this deployment
+    #[rpc(meta, name = "listparticipants")]
+    fn listparticipants(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the descriptors and how far their scripts are from the standardness limits
+    #[rpc(meta, name = "getdescriptors")]
+    fn getdescriptors(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get our Noise static public key and its fingerprint
+    #[rpc(meta, name = "getnoisestaticpubkey")]
+    fn getnoisestaticpubkey(&self, meta: Self::Metadata)
+        -> jsonrpc_core::Result<serde_json::Value>;
+
     /// Retrieve the accounting history
     #[rpc(meta, name = "gethistory")]
     fn gethistory(
@@ -214,6 +508,87 @@ pub trait RpcApi {
         end: u32,
         limit: u64,
     ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Acknowledge the problem a vault was flagged for
+    #[rpc(meta, name = "clearvaultflag")]
+    fn clearvaultflag(
+        &self,
+        meta: Self::Metadata,
+        deposit_outpoint: OutPoint,
+        kind: String,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Retrieve the audit log of destructive commands
+    #[rpc(meta, name = "getauditlog")]
+    fn getauditlog(
+        &self,
+        meta: Self::Metadata,
+        start: Option<u32>,
+        end: Option<u32>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Check the audit log wasn't tampered with
+    #[rpc(meta, name = "verifyauditlog")]
+    fn verifyauditlog(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Export the vaults or the history of the funds as CSV
+    #[rpc(meta, name = "export")]
+    fn export(
+        &self,
+        meta: Self::Metadata,
+        kind: ExportKind,
+        start: Option<u32>,
+        end: Option<u32>,
+        path: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Initiate the rotation of the keys of the deployment to a new wallet
+    #[rpc(meta, name = "initiatewalletrotation")]
+    fn initiatewalletrotation(
+        &self,
+        meta: Self::Metadata,
+        stakeholders_xpubs: Vec<String>,
+        managers_xpubs: Vec<String>,
+        managers_threshold: usize,
+        cosigners_keys: Vec<String>,
+        cpfp_xpubs: Vec<String>,
+        our_stakeholder_xpub: Option<String>,
+        our_manager_xpub: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Create the Spend transactions moving the active vaults to the new wallet of a rotation
+    #[rpc(meta, name = "migratevaults")]
+    fn migratevaults(
+        &self,
+        meta: Self::Metadata,
+        feerate: u64,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the levels at which we log
+    #[rpc(meta, name = "getloglevel")]
+    fn getloglevel(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Change the levels at which we log, optionally for a limited time
+    #[rpc(meta, name = "setloglevel")]
+    fn setloglevel(
+        &self,
+        meta: Self::Metadata,
+        level: String,
+        modules: Option<HashMap<String, String>>,
+        duration_seconds: Option<u64>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Compare our watchonly wallet with the addresses we imported into it
+    #[rpc(meta, name = "auditwallet")]
+    fn auditwallet(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the latencies of the RPC calls by method, optionally resetting them
+    #[rpc(meta, name = "getrpcstats")]
+    fn getrpcstats(
+        &self,
+        meta: Self::Metadata,
+        reset: Option<bool>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
 }
 
 macro_rules! parse_vault_status {
@@ -224,6 +599,48 @@ macro_rules! parse_vault_status {
     };
 }
 
+// Shared by approvespend and rejectspend
+fn decide_spend(
+    meta: JsonRpcMetaData,
+    method: &str,
+    proposal_id: u32,
+    fingerprint: String,
+    signature: String,
+    approve: bool,
+) -> jsonrpc_core::Result<serde_json::Value> {
+    let fingerprint = xpub_fingerprint_from_str(&fingerprint).ok_or_else(|| {
+        JsonRpcError::invalid_params(format!("Invalid xpub fingerprint '{}'", fingerprint))
+    })?;
+    let signature = Vec::<u8>::from_hex(&signature).map_err(|e| {
+        JsonRpcError::invalid_params(format!("Invalid signature hex '{}': {}", signature, e))
+    })?;
+
+    let status = meta.daemon_control.audited(
+        method,
+        &json!([proposal_id, fingerprint.to_string(), signature.to_hex()]),
+        meta.peer_uid,
+        |control| control.decide_spend_proposal(proposal_id, &fingerprint, &signature, approve),
+    )?;
+    Ok(json!({ "status": status }))
+}
+
+// Parse the key of a participant or a cosigning server, naming the parameter it's from
+fn parse_key<T: FromStr>(param: &str, key: &str) -> jsonrpc_core::Result<T>
+where
+    T::Err: fmt::Display,
+{
+    T::from_str(key).map_err(|e| {
+        JsonRpcError::invalid_params(format!("Invalid key '{}' in '{}': {}", key, param, e))
+    })
+}
+
+fn parse_keys<T: FromStr>(param: &str, keys: &[String]) -> jsonrpc_core::Result<Vec<T>>
+where
+    T::Err: fmt::Display,
+{
+    keys.iter().map(|key| parse_key(param, key)).collect()
+}
+
 pub struct RpcImpl;
 impl RpcApi for RpcImpl {
     type Metadata = JsonRpcMetaData;
@@ -235,80 +652,102 @@ impl RpcApi for RpcImpl {
     }
 
     fn getinfo(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
-        Ok(json!(meta.daemon_control.get_info()))
+        let (is_stakeholder, is_manager) = meta.roles();
+        let mut info = json!(meta.daemon_control.get_info());
+        info["api_version"] = json!(api_version());
+        info["features"] = json!(available_features(is_stakeholder, is_manager));
+        Ok(info)
+    }
+
+    fn version(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.get_version()))
+    }
+
+    fn help(
+        &self,
+        _: Self::Metadata,
+        method: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        if let Some(method) = method {
+            let help = method_help(&method).ok_or_else(|| {
+                JsonRpcError::invalid_params(format!("Unknown command '{}'", method))
+            })?;
+            return Ok(json!(help));
+        }
+
+        // One entry per command with its parameters, optional ones are enclosed in brackets
+        let commands: serde_json::Map<String, serde_json::Value> = METHODS
+            .iter()
+            .map(|method| {
+                let params: Vec<String> = method
+                    .params
+                    .iter()
+                    .map(|p| {
+                        if p.optional {
+                            format!("[{}]", p.name)
+                        } else {
+                            p.name.to_string()
+                        }
+                    })
+                    .collect();
+                (method.name.to_string(), json!(params))
+            })
+            .collect();
+        Ok(serde_json::Value::Object(commands))
     }
 
-    fn help(&self, _: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+    fn hello(
+        &self,
+        meta: Self::Metadata,
+        min_api_version: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        if let Some(min_api_version) = min_api_version {
+            let required = parse_api_version(&min_api_version).ok_or_else(|| {
+                JsonRpcError::invalid_params(format!(
+                    "Invalid API version '{}', expected 'major.minor.patch'",
+                    min_api_version
+                ))
+            })?;
+            if API_VERSION < required {
+                return Err(JsonRpcError {
+                    code: ServerError(ErrorCode::API_VERSION_ERROR as i64),
+                    message: format!(
+                        "The daemon implements API version {} but at least {} is required. \
+                         Please upgrade revaultd.",
+                        api_version(),
+                        min_api_version.trim()
+                    ),
+                    data: None,
+                });
+            }
+        }
+
+        let (is_stakeholder, is_manager) = meta.roles();
         Ok(json!({
-            "stop": [
-
-            ],
-            "getinfo": [
-
-            ],
-            "getdepositaddress": [
-                "[index]",
-            ],
-            "getserverstatus": [
-
-            ],
-            "listvaults": [
-                "[status]",
-                "[outpoints]",
-            ],
-            "listpresignedtransactions": [
-                "[outpoints]",
-            ],
-            "listonchaintransactions": [
-                "[outpoints]",
-            ],
-            "getrevocationtxs": [
-                "outpoint",
-            ],
-            "revocationtxs": [
-
-            ],
-            "getunvaulttx": [
-                "outpoint",
-            ],
-            "unvaulttx": [
-
-            ],
-            "getspendtx": [
-                "outpoints",
-                "outputs",
-                "feerate",
-            ],
-            "updatespendtx": [
-                "spend_tx",
-            ],
-            "delspendtx": [
-                "spend_txid",
-            ],
-            "listspendtxs": [
-
-            ],
-            "setspendtx": [
-                "spend_txid",
-                "[priority]",
-            ],
-            "gethistory": [
-                "[kind]",
-                "cursor",
-                "limit",
-                "kind",
-            ],
-            "emergency": [
-
-            ],
+            "api_version": api_version(),
+            "version": VERSION,
+            "features": available_features(is_stakeholder, is_manager),
         }))
     }
 
+    fn listcommands(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        let (is_stakeholder, is_manager) = meta.roles();
+        let commands: Vec<serde_json::Value> = METHODS
+            .iter()
+            .filter(|m| m.availability.allows(is_stakeholder, is_manager))
+            .map(|m| json!({ "name": m.name, "description": m.description }))
+            .collect();
+        Ok(json!({ "commands": commands }))
+    }
+
     fn listvaults(
         &self,
         meta: Self::Metadata,
         statuses: Option<Vec<String>>,
         outpoints: Option<Vec<OutPoint>>,
+        min_funded_height: Option<u32>,
+        max_funded_height: Option<u32>,
+        as_of_height: Option<u32>,
     ) -> jsonrpc_core::Result<serde_json::Value> {
         let statuses = if let Some(statuses) = statuses {
             // If they give an empty array, it's not that they don't want any result, but rather
@@ -327,49 +766,195 @@ impl RpcApi for RpcImpl {
             None
         };
 
+        let height_filter = VaultHeightFilter {
+            min_funded_height,
+            max_funded_height,
+            as_of_height,
+        };
+        if let Some(ref stream) = meta.response_stream {
+            stream.rows("vaults", |rows| {
+                meta.daemon_control.for_each_vault_at_heights(
+                    statuses.as_deref(),
+                    outpoints.as_deref(),
+                    &height_filter,
+                    |vault| rows.push(&vault),
+                )
+            })?;
+            return Ok(serde_json::Value::Null);
+        }
+        let res = meta.daemon_control.list_vaults_at_heights(
+            statuses.as_deref(),
+            outpoints.as_deref(),
+            &height_filter,
+        )?;
+        Ok(json!({ "vaults": res }))
+    }
+
+    fn getbalances(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.get_balances()))
+    }
+
+    fn getcpfpreserve(
+        &self,
+        meta: Self::Metadata,
+        feerate_vb: u64,
+        include_active: Option<bool>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        if feerate_vb < 1 {
+            return Err(JsonRpcError::invalid_params(
+                "Feerate can't be <1".to_string(),
+            ));
+        }
+
         let res = meta
             .daemon_control
-            .list_vaults(statuses.as_deref(), outpoints.as_deref());
-        Ok(json!({ "vaults": res }))
+            .get_cpfp_reserve(feerate_vb, include_active.unwrap_or(false))?;
+        Ok(json!(res))
     }
 
     fn getdepositaddress(
         &self,
         meta: Self::Metadata,
-        index: Option<bip32::ChildNumber>,
+        index: Option<DerivationIndex>,
     ) -> jsonrpc_core::Result<serde_json::Value> {
         let address = if let Some(index) = index {
-            meta.daemon_control.get_deposit_address_at(index)
+            meta.daemon_control.get_deposit_address_at(index)?
         } else {
-            meta.daemon_control.get_deposit_address()
+            meta.daemon_control.get_deposit_address()?
         };
         Ok(json!({ "address": address.to_string() }))
     }
 
-    fn getrevocationtxs(
+    fn getnewdepositaddress(
+        &self,
+        meta: Self::Metadata,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.get_new_deposit_address()?))
+    }
+
+    fn getvaultdetails(
         &self,
         meta: Self::Metadata,
         outpoint: OutPoint,
     ) -> jsonrpc_core::Result<serde_json::Value> {
-        let res = meta.daemon_control.get_revocation_txs(outpoint)?;
+        let res = meta.daemon_control.get_vault_details(outpoint)?;
         Ok(json!(res))
     }
 
-    fn revocationtxs(
+    fn verifyemergencydescriptor(
         &self,
         meta: Self::Metadata,
-        outpoint: OutPoint,
-        cancel_tx: CancelTransaction,
-        emergency_tx: EmergencyTransaction,
-        unvault_emergency_tx: UnvaultEmergencyTransaction,
-    ) -> jsonrpc_core::Result<serde_json::Value> {
-        meta.daemon_control.set_revocation_txs(
-            outpoint,
-            cancel_tx,
-            emergency_tx,
-            unvault_emergency_tx,
+        descriptor: Option<String>,
+        derivation_index: Option<u32>,
+        proof: Option<EmergencyKeyProofParam>,
+        store: Option<bool>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let proof = proof
+            .map(|proof| -> jsonrpc_core::Result<EmergencyKeyProof> {
+                let pubkey = BitcoinPubKey::from_str(&proof.pubkey).map_err(|e| {
+                    JsonRpcError::invalid_params(format!(
+                        "Invalid public key '{}': {}",
+                        proof.pubkey, e
+                    ))
+                })?;
+                let signature = Vec::<u8>::from_hex(&proof.signature).map_err(|e| {
+                    JsonRpcError::invalid_params(format!(
+                        "Invalid signature hex '{}': {}",
+                        proof.signature, e
+                    ))
+                })?;
+                Ok(EmergencyKeyProof {
+                    pubkey,
+                    message: proof.message,
+                    signature,
+                })
+            })
+            .transpose()?;
+
+        let res = meta.daemon_control.verify_emergency_descriptor(
+            descriptor.as_deref(),
+            derivation_index,
+            proof.as_ref(),
+            store.unwrap_or(false),
         )?;
-        Ok(json!({}))
+        Ok(json!(res))
+    }
+
+    fn getrevocationtxs(
+        &self,
+        meta: Self::Metadata,
+        selector: RevocationTxsSelector,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let batch = match selector {
+            RevocationTxsSelector::Outpoint(outpoint) => {
+                let res = meta.daemon_control.get_revocation_txs(outpoint)?;
+                return Ok(json!(res));
+            }
+            RevocationTxsSelector::Outpoints(outpoints) => meta
+                .daemon_control
+                .get_revocation_txs_batch(Some(&outpoints[..]))?,
+            RevocationTxsSelector::Status { status } => {
+                if parse_vault_status!(status)? != VaultStatus::Funded {
+                    return Err(JsonRpcError::invalid_params(format!(
+                        "Only 'funded' vaults have revocation transactions to sign, not '{}'",
+                        status
+                    )));
+                }
+                meta.daemon_control.get_revocation_txs_batch(None)?
+            }
+        };
+
+        Ok(json!({ "revocation_txs": batch }))
+    }
+
+    fn revocationtxs(
+        &self,
+        meta: Self::Metadata,
+        outpoint: RevocationTxsParam,
+        cancel_tx: Option<CancelTransaction>,
+        emergency_tx: Option<EmergencyTransaction>,
+        unvault_emergency_tx: Option<UnvaultEmergencyTransaction>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        match (outpoint, cancel_tx, emergency_tx, unvault_emergency_tx) {
+            (
+                RevocationTxsParam::Outpoint(outpoint),
+                Some(cancel_tx),
+                Some(emergency_tx),
+                Some(unvault_emergency_tx),
+            ) => {
+                meta.daemon_control.set_revocation_txs(
+                    outpoint,
+                    cancel_tx,
+                    emergency_tx,
+                    unvault_emergency_tx,
+                )?;
+                Ok(json!({}))
+            }
+            (RevocationTxsParam::Batch(batch), None, None, None) => {
+                let results: BTreeMap<String, serde_json::Value> = meta
+                    .daemon_control
+                    .set_revocation_txs_batch(batch)?
+                    .into_iter()
+                    .map(|(outpoint, res)| {
+                        let entry = match res {
+                            Ok(()) => json!({ "success": true, "error": null }),
+                            Err(e) => json!({
+                                "success": false,
+                                "error": JsonRpcError::from(e),
+                            }),
+                        };
+                        (outpoint.to_string(), entry)
+                    })
+                    .collect();
+                Ok(json!({ "results": results }))
+            }
+            (RevocationTxsParam::Outpoint(_), ..) => Err(JsonRpcError::invalid_params(
+                "The Cancel, Emergency and Unvault Emergency transactions must all be given",
+            )),
+            (RevocationTxsParam::Batch(_), ..) => Err(JsonRpcError::invalid_params(
+                "The transactions must be part of the batch",
+            )),
+        }
     }
 
     fn getunvaulttx(
@@ -393,6 +978,22 @@ impl RpcApi for RpcImpl {
         Ok(json!({}))
     }
 
+    fn getwatchdata(
+        &self,
+        meta: Self::Metadata,
+        since: Option<u64>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let res = meta
+            .daemon_control
+            .get_watch_data(since.unwrap_or(0), meta.peer_noise_key.as_ref())?;
+        Ok(json!(res))
+    }
+
+    fn listwatchtxids(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        let res = meta.daemon_control.list_watch_txids();
+        Ok(json!(res))
+    }
+
     fn listpresignedtransactions(
         &self,
         meta: Self::Metadata,
@@ -404,6 +1005,16 @@ impl RpcApi for RpcImpl {
         Ok(json!({ "presigned_transactions": pres_txs }))
     }
 
+    fn getstalerevocations(
+        &self,
+        meta: Self::Metadata,
+        feerate: u64,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!({
+            "vaults": meta.daemon_control.get_stale_revocations(feerate),
+        }))
+    }
+
     fn listonchaintransactions(
         &self,
         meta: Self::Metadata,
@@ -421,8 +1032,11 @@ impl RpcApi for RpcImpl {
         &self,
         meta: Self::Metadata,
         outpoints: Vec<OutPoint>,
-        destinations: BTreeMap<Address, u64>,
+        destinations: BTreeMap<Address, Amount>,
         feerate_vb: u64,
+        allow_high_fees: Option<bool>,
+        allow_self_send: Option<bool>,
+        proposal_id: Option<u32>,
     ) -> jsonrpc_core::Result<serde_json::Value> {
         if feerate_vb < 1 {
             return Err(JsonRpcError::invalid_params(
@@ -430,14 +1044,84 @@ impl RpcApi for RpcImpl {
             ));
         }
 
-        let tx = meta
+        let tx = meta.daemon_control.get_spend_tx(
+            &outpoints,
+            &destinations,
+            feerate_vb,
+            allow_high_fees.unwrap_or(false),
+            allow_self_send.unwrap_or(false),
+            proposal_id,
+        )?;
+        let reused_destinations = meta
             .daemon_control
-            .get_spend_tx(&outpoints, &destinations, feerate_vb)?;
+            .reused_spend_destinations(&destinations)?;
         Ok(json!({
             "spend_tx": tx,
+            "reused_destinations": reused_destinations,
         }))
     }
 
+    fn proposespend(
+        &self,
+        meta: Self::Metadata,
+        outpoints: Vec<OutPoint>,
+        destinations: BTreeMap<Address, Amount>,
+        feerate_vb: u64,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        if feerate_vb < 1 {
+            return Err(JsonRpcError::invalid_params(
+                "Feerate can't be <1".to_string(),
+            ));
+        }
+
+        let id = meta.daemon_control.audited(
+            "proposespend",
+            &json!([outpoints, destinations, feerate_vb]),
+            meta.peer_uid,
+            |control| control.propose_spend(&outpoints, &destinations, feerate_vb),
+        )?;
+        Ok(json!({ "id": id }))
+    }
+
+    fn listspendproposals(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        let proposals = meta.daemon_control.list_spend_proposals()?;
+        Ok(json!({ "spend_proposals": proposals }))
+    }
+
+    fn approvespend(
+        &self,
+        meta: Self::Metadata,
+        proposal_id: u32,
+        fingerprint: String,
+        signature: String,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        decide_spend(
+            meta,
+            "approvespend",
+            proposal_id,
+            fingerprint,
+            signature,
+            true,
+        )
+    }
+
+    fn rejectspend(
+        &self,
+        meta: Self::Metadata,
+        proposal_id: u32,
+        fingerprint: String,
+        signature: String,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        decide_spend(
+            meta,
+            "rejectspend",
+            proposal_id,
+            fingerprint,
+            signature,
+            false,
+        )
+    }
+
     fn updatespendtx(
         &self,
         meta: Self::Metadata,
@@ -470,9 +1154,42 @@ impl RpcApi for RpcImpl {
         meta: Self::Metadata,
         spend_txid: Txid,
         priority: Option<bool>,
+        idempotency_key: Option<String>,
+        override_schedule: Option<bool>,
     ) -> jsonrpc_core::Result<serde_json::Value> {
         let priority = priority.unwrap_or(false);
-        meta.daemon_control.set_spend_tx(&spend_txid, priority)?;
+        let override_schedule = override_schedule.unwrap_or(false);
+        let params = json!([spend_txid, priority, override_schedule]);
+        // Make overriding the spending schedule stand out in the audit log
+        let audit_method = if override_schedule {
+            "setspendtx_override_schedule"
+        } else {
+            "setspendtx"
+        };
+        Ok(meta.daemon_control.idempotent(
+            idempotency_key.as_deref(),
+            "setspendtx",
+            &params,
+            |control| {
+                control.audited(audit_method, &params, meta.peer_uid, |control| {
+                    control.set_spend_tx(&spend_txid, priority, override_schedule)
+                })?;
+                Ok(json!({}))
+            },
+        )?)
+    }
+
+    fn abortspend(
+        &self,
+        meta: Self::Metadata,
+        spend_txid: Txid,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        meta.daemon_control.audited(
+            "abortspend",
+            &json!([spend_txid]),
+            meta.peer_uid,
+            |control| control.abort_spend(&spend_txid),
+        )?;
         Ok(json!({}))
     }
 
@@ -480,13 +1197,54 @@ impl RpcApi for RpcImpl {
         &self,
         meta: Self::Metadata,
         deposit_outpoint: OutPoint,
+        idempotency_key: Option<String>,
     ) -> jsonrpc_core::Result<serde_json::Value> {
-        meta.daemon_control.revault(&deposit_outpoint)?;
-        Ok(json!({}))
+        let params = json!([deposit_outpoint]);
+        Ok(meta.daemon_control.idempotent(
+            idempotency_key.as_deref(),
+            "revault",
+            &params,
+            |control| {
+                control.audited("revault", &params, meta.peer_uid, |control| {
+                    control.revault(&deposit_outpoint)
+                })?;
+                Ok(json!({}))
+            },
+        )?)
     }
 
-    fn emergency(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
-        meta.daemon_control.emergency()?;
+    fn emergency(
+        &self,
+        meta: Self::Metadata,
+        idempotency_key: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let params = json!([]);
+        Ok(meta.daemon_control.idempotent(
+            idempotency_key.as_deref(),
+            "emergency",
+            &params,
+            |control| {
+                let vaults = control.audited("emergency", &params, meta.peer_uid, |control| {
+                    control.emergency()
+                })?;
+                Ok(json!({ "vaults": vaults }))
+            },
+        )?)
+    }
+
+    fn clearvaultflag(
+        &self,
+        meta: Self::Metadata,
+        deposit_outpoint: OutPoint,
+        kind: String,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let kind = VaultFlagKind::from_str(&kind).map_err(JsonRpcError::invalid_params)?;
+        meta.daemon_control.audited(
+            "clearvaultflag",
+            &json!([deposit_outpoint, kind.to_string()]),
+            meta.peer_uid,
+            |control| control.clear_vault_flag(&deposit_outpoint, kind),
+        )?;
         Ok(json!({}))
     }
 
@@ -495,6 +1253,27 @@ impl RpcApi for RpcImpl {
         Ok(json!(status))
     }
 
+    fn listcosigners(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!({
+            "cosigners": meta.daemon_control.list_cosigners(),
+        }))
+    }
+
+    fn listparticipants(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.list_participants()))
+    }
+
+    fn getdescriptors(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.get_descriptors()?))
+    }
+
+    fn getnoisestaticpubkey(
+        &self,
+        meta: Self::Metadata,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.get_noise_static_pubkey()))
+    }
+
     /// get_history retrieves a limited list of events which occured between two given dates.
     fn gethistory(
         &self,
@@ -507,8 +1286,205 @@ impl RpcApi for RpcImpl {
         let events = meta
             .daemon_control
             .get_history(start, end, limit, kind.as_ref())?;
+        if let Some(ref stream) = meta.response_stream {
+            stream.rows("events", |rows| {
+                events.iter().try_for_each(|event| rows.push(event))
+            })?;
+            return Ok(serde_json::Value::Null);
+        }
         Ok(json!({
             "events": events,
         }))
     }
+
+    fn getauditlog(
+        &self,
+        meta: Self::Metadata,
+        start: Option<u32>,
+        end: Option<u32>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let entries = meta
+            .daemon_control
+            .get_audit_log(start.unwrap_or(0), end.unwrap_or(u32::MAX))?;
+        Ok(json!({
+            "entries": entries,
+        }))
+    }
+
+    fn verifyauditlog(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.verify_audit_log()?))
+    }
+
+    fn export(
+        &self,
+        meta: Self::Metadata,
+        kind: ExportKind,
+        start: Option<u32>,
+        end: Option<u32>,
+        path: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        // Streamed, an inline export doesn't need to fit in memory
+        if let (None, Some(stream)) = (&path, &meta.response_stream) {
+            stream.result(|out| {
+                out.write_all(br#"{"csv":""#)
+                    .map_err(CommandError::Stream)?;
+                let rows = meta.daemon_control.export_to(
+                    kind,
+                    start.unwrap_or(0),
+                    end.unwrap_or(u32::MAX),
+                    JsonStringWriter(&mut *out),
+                )?;
+                write!(out, r#"","path":null,"rows":{}}}"#, rows).map_err(CommandError::Stream)
+            })?;
+            return Ok(serde_json::Value::Null);
+        }
+        let res = meta.daemon_control.export(
+            kind,
+            start.unwrap_or(0),
+            end.unwrap_or(u32::MAX),
+            path.as_deref(),
+        )?;
+        Ok(json!(res))
+    }
+
+    fn initiatewalletrotation(
+        &self,
+        meta: Self::Metadata,
+        stakeholders_xpubs: Vec<String>,
+        managers_xpubs: Vec<String>,
+        managers_threshold: usize,
+        cosigners_keys: Vec<String>,
+        cpfp_xpubs: Vec<String>,
+        our_stakeholder_xpub: Option<String>,
+        our_manager_xpub: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let params = json!([
+            stakeholders_xpubs,
+            managers_xpubs,
+            managers_threshold,
+            cosigners_keys,
+            cpfp_xpubs,
+            our_stakeholder_xpub,
+            our_manager_xpub
+        ]);
+        let stakeholders_xpubs: Vec<ExtendedPubKey> =
+            parse_keys("stakeholders_xpubs", &stakeholders_xpubs)?;
+        let managers_xpubs: Vec<ExtendedPubKey> = parse_keys("managers_xpubs", &managers_xpubs)?;
+        let cosigners_keys: Vec<DescriptorPublicKey> =
+            parse_keys("cosigners_keys", &cosigners_keys)?;
+        let cpfp_xpubs: Vec<ExtendedPubKey> = parse_keys("cpfp_xpubs", &cpfp_xpubs)?;
+        let our_stakeholder_xpub: Option<ExtendedPubKey> = our_stakeholder_xpub
+            .as_ref()
+            .map(|xpub| parse_key("our_stakeholder_xpub", xpub))
+            .transpose()?;
+        let our_manager_xpub: Option<ExtendedPubKey> = our_manager_xpub
+            .as_ref()
+            .map(|xpub| parse_key("our_manager_xpub", xpub))
+            .transpose()?;
+
+        let res = meta.daemon_control.audited(
+            "initiatewalletrotation",
+            &params,
+            meta.peer_uid,
+            |control| {
+                control.initiate_wallet_rotation(
+                    &stakeholders_xpubs,
+                    &managers_xpubs,
+                    managers_threshold,
+                    cosigners_keys,
+                    &cpfp_xpubs,
+                    our_stakeholder_xpub,
+                    our_manager_xpub,
+                )
+            },
+        )?;
+        Ok(json!(res))
+    }
+
+    fn migratevaults(
+        &self,
+        meta: Self::Metadata,
+        feerate_vb: u64,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        if feerate_vb < 1 {
+            return Err(JsonRpcError::invalid_params(
+                "Feerate can't be <1".to_string(),
+            ));
+        }
+
+        let migrations = meta.daemon_control.audited(
+            "migratevaults",
+            &json!([feerate_vb]),
+            meta.peer_uid,
+            |control| control.migrate_vaults(feerate_vb),
+        )?;
+        Ok(json!({ "migrations": migrations }))
+    }
+
+    fn getloglevel(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.get_log_levels()))
+    }
+
+    fn setloglevel(
+        &self,
+        meta: Self::Metadata,
+        level: String,
+        modules: Option<HashMap<String, String>>,
+        duration_seconds: Option<u64>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let parse_level = |level: &str| {
+            log::LevelFilter::from_str(level).map_err(|_| {
+                JsonRpcError::invalid_params(format!("'{}' is not a valid log level", level))
+            })
+        };
+        let level = parse_level(&level)?;
+        let modules = modules
+            .map(|modules| {
+                modules
+                    .into_iter()
+                    .map(|(module, level)| Ok((module, parse_level(&level)?)))
+                    .collect::<jsonrpc_core::Result<HashMap<_, _>>>()
+            })
+            .transpose()?;
+        if duration_seconds == Some(0) {
+            return Err(JsonRpcError::invalid_params(
+                "Duration can't be 0".to_string(),
+            ));
+        }
+
+        let previous = meta.daemon_control.set_log_levels(
+            level,
+            modules,
+            duration_seconds.map(Duration::from_secs),
+        );
+        Ok(json!({ "previous": previous }))
+    }
+
+    fn auditwallet(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!(meta.daemon_control.audit_wallet()?))
+    }
+
+    fn getrpcstats(
+        &self,
+        meta: Self::Metadata,
+        reset: Option<bool>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let mut stats = meta.rpc_stats.lock().unwrap();
+        let res = json!({
+            "since": stats.since(),
+            "methods": stats.methods(),
+        });
+        if reset == Some(true) {
+            let now = meta
+                .daemon_control
+                .revaultd
+                .read()
+                .unwrap()
+                .clock
+                .unix_timestamp();
+            stats.reset(now);
+        }
+
+        Ok(res)
+    }
 }
@@ -0,0 +1,1456 @@
+//! A declarative description of the RPC commands, their parameters and (for the main queries)
+//! the fields of their result. It backs the `help` and `listcommands` commands, and the tests
+//! make sure it doesn't drift from the handlers in the `api` mod.
+//! The version of the RPC interface and the features it provides to clients are declared here
+//! as well.
+
+use serde::{Serialize, Serializer};
+
+/// Which participants may use a command
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Availability {
+    All,
+    Stakeholder,
+    Manager,
+}
+
+impl Availability {
+    pub fn allows(&self, is_stakeholder: bool, is_manager: bool) -> bool {
+        match self {
+            Availability::All => true,
+            Availability::Stakeholder => is_stakeholder,
+            Availability::Manager => is_manager,
+        }
+    }
+}
+
+// The default values are written as JSON literals, return them as JSON values.
+fn ser_default<S: Serializer>(default: &Option<&'static str>, s: S) -> Result<S::Ok, S::Error> {
+    default
+        .map(|d| serde_json::from_str::<serde_json::Value>(d).expect("Defaults are valid JSON"))
+        .serialize(s)
+}
+
+/// A positional parameter of a command
+#[derive(Debug, Clone, Serialize)]
+pub struct ParamHelp {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    pub optional: bool,
+    /// The value used when the parameter is not given, as a JSON literal
+    #[serde(serialize_with = "ser_default")]
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+/// A field of the object returned by a command
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldHelp {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    pub description: &'static str,
+}
+
+/// A command, its parameters in order and the fields of its result
+#[derive(Debug, Clone, Serialize)]
+pub struct MethodHelp {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub availability: Availability,
+    pub params: &'static [ParamHelp],
+    pub result: &'static [FieldHelp],
+}
+
+const fn required(name: &'static str, ty: &'static str, description: &'static str) -> ParamHelp {
+    ParamHelp {
+        name,
+        ty,
+        optional: false,
+        default: None,
+        description,
+    }
+}
+
+const fn optional(
+    name: &'static str,
+    ty: &'static str,
+    default: Option<&'static str>,
+    description: &'static str,
+) -> ParamHelp {
+    ParamHelp {
+        name,
+        ty,
+        optional: true,
+        default,
+        description,
+    }
+}
+
+const fn field(name: &'static str, ty: &'static str, description: &'static str) -> FieldHelp {
+    FieldHelp {
+        name,
+        ty,
+        description,
+    }
+}
+
+const IDEMPOTENCY_KEY: ParamHelp = optional(
+    "idempotency_key",
+    "string",
+    None,
+    "Execute the command only once for retries with this key",
+);
+
+/// All the commands, in the order they are documented
+pub const METHODS: &[MethodHelp] = &[
+    MethodHelp {
+        name: "help",
+        description: "Display all available commands, or the description of one of them",
+        availability: Availability::All,
+        params: &[optional(
+            "method",
+            "string",
+            None,
+            "The command to describe",
+        )],
+        result: &[],
+    },
+    MethodHelp {
+        name: "hello",
+        description: "Check the daemon is recent enough for us, and get the features it provides",
+        availability: Availability::All,
+        params: &[optional(
+            "min_api_version",
+            "string",
+            None,
+            "The oldest API version we can work with, as 'major.minor.patch'",
+        )],
+        result: &[
+            field("api_version", "string", "The version of the RPC interface"),
+            field("version", "string", "The version of the daemon"),
+            field(
+                "features",
+                "array",
+                "The features available to us, as for 'getinfo'",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "listcommands",
+        description: "List the commands available to us",
+        availability: Availability::All,
+        params: &[],
+        result: &[field(
+            "commands",
+            "array",
+            "The name and description of each command",
+        )],
+    },
+    MethodHelp {
+        name: "stop",
+        description: "Stops the revault daemon",
+        availability: Availability::All,
+        params: &[],
+        result: &[],
+    },
+    MethodHelp {
+        name: "getinfo",
+        description: "Display general information",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field("version", "string", "The version of the daemon"),
+            field(
+                "build",
+                "object",
+                "The metadata of the build, as returned by 'version'",
+            ),
+            field("api_version", "string", "The version of the RPC interface"),
+            field(
+                "features",
+                "array",
+                "The features available to us, depending on our role",
+            ),
+            field("network", "string", "The Bitcoin network we are running on"),
+            field("blockheight", "integer", "Current block height"),
+            field("sync", "float", "The synchronization progress"),
+            field(
+                "rescan",
+                "object",
+                "What bitcoind is rescanning the chain for while we import descriptors, or null",
+            ),
+            field(
+                "bitcoind_reachable",
+                "bool",
+                "Whether bitcoind could be reached the last time we polled it",
+            ),
+            field(
+                "bitcoind_connections",
+                "array",
+                "The usage of our connections to each of bitcoind's endpoints",
+            ),
+            field(
+                "read_only",
+                "bool",
+                "Whether the daemon only monitors the vaults",
+            ),
+            field(
+                "db_synchronous",
+                "string",
+                "How hard the database commits are made durable: off, normal, full or extra",
+            ),
+            field("vaults", "integer", "Current number of vaults"),
+            field(
+                "managers_threshold",
+                "integer",
+                "Number of managers needed for spending",
+            ),
+            field("descriptors", "object", "The three Miniscript descriptors"),
+            field("paths", "object", "The effective paths used by the daemon"),
+            field("limits", "object", "The configured limits"),
+            field(
+                "deposit_indexes",
+                "object",
+                "The derivation indexes of deposit addresses we watch and saw",
+            ),
+            field("health", "object", "The result of the periodic checks"),
+            field(
+                "signatures",
+                "object",
+                "The vaults we are still fetching signatures for",
+            ),
+            field(
+                "spending_schedule",
+                "object or null",
+                "The configured spending schedule",
+            ),
+            field(
+                "log_levels",
+                "object",
+                "The levels at which we currently log",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "version",
+        description: "Get the version of the daemon and the metadata of its build",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field("version", "string", "The version of the daemon"),
+            field(
+                "git_commit",
+                "string or null",
+                "The commit it was built from, null if not built from a git repository",
+            ),
+            field(
+                "git_dirty",
+                "bool or null",
+                "Whether the tracked files were modified, null if not built from git",
+            ),
+            field("build_date", "string", "The day it was built, as YYYY-MM-DD (UTC)"),
+            field(
+                "features",
+                "array of string",
+                "The cargo features it was compiled with",
+            ),
+            field(
+                "rustc_version",
+                "string",
+                "The version of the compiler it was built with",
+            ),
+            field(
+                "first_run_at",
+                "integer",
+                "When this version first ran on the database (UNIX timestamp)",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "getdepositaddress",
+        description: "Get an address to build a deposit transaction",
+        availability: Availability::All,
+        params: &[optional(
+            "index",
+            "integer",
+            None,
+            "The derivation index of the address, the next unused one if not given",
+        )],
+        result: &[field("address", "string", "The deposit address")],
+    },
+    MethodHelp {
+        name: "getnewdepositaddress",
+        description: "Get a deposit address no one else will get from us",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field("address", "string", "The deposit address"),
+            field(
+                "derivation_index",
+                "integer",
+                "The derivation index it's reserved at until a deposit is made to it",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "getserverstatus",
+        description: "Retrieve the status of the servers",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field(
+                "coordinator",
+                "object",
+                "The status of the Coordinator in use",
+            ),
+            field(
+                "coordinators",
+                "array",
+                "Whether each Coordinator is the active one, and its health",
+            ),
+            field(
+                "coordinator_queue",
+                "object",
+                "The number of signatures waiting to be pushed to the Coordinator, and the pace \
+                 at which we push them",
+            ),
+            field("cosigners", "array", "The status of each Cosigning Server"),
+            field("watchtowers", "array", "The status of each watchtower"),
+        ],
+    },
+    MethodHelp {
+        name: "getdescriptors",
+        description: "Get the descriptors and the size of their scripts",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field(
+                "deposit",
+                "object",
+                "The deposit descriptor and the size of its scripts",
+            ),
+            field(
+                "unvault",
+                "object",
+                "The unvault descriptor and the size of its scripts",
+            ),
+            field(
+                "cpfp",
+                "object",
+                "The CPFP descriptor and the size of its scripts",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "getnoisestaticpubkey",
+        description: "Get our Noise static public key and its fingerprint",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field(
+                "pubkey",
+                "string",
+                "Our Noise static public key, hex-encoded",
+            ),
+            field("fingerprint", "string", "A short fingerprint of the key"),
+        ],
+    },
+    MethodHelp {
+        name: "listcosigners",
+        description: "List the configured cosigning servers",
+        availability: Availability::All,
+        params: &[],
+        result: &[field(
+            "cosigners",
+            "array",
+            "The host, Noise key and reachability of each Cosigning Server",
+        )],
+    },
+    MethodHelp {
+        name: "listparticipants",
+        description: "List the participants to this deployment",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field("stakeholders", "array", "The stakeholders' xpubs"),
+            field("managers", "array", "The managers' xpubs"),
+            field(
+                "managers_threshold",
+                "integer",
+                "Number of managers needed for spending",
+            ),
+            field("cosigners", "array", "The Cosigning Servers"),
+            field("coordinator", "object", "The Coordinator"),
+            field(
+                "backup_coordinators",
+                "array",
+                "The Coordinators to fail over to",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "listvaults",
+        description: "Display a paginated list of vaults",
+        availability: Availability::All,
+        params: &[
+            optional(
+                "statuses",
+                "array of string",
+                Some("[]"),
+                "Only list the vaults with one of these statuses",
+            ),
+            optional(
+                "outpoints",
+                "array of string",
+                Some("[]"),
+                "Only list the vaults with these deposit outpoints",
+            ),
+            optional(
+                "min_funded_height",
+                "integer",
+                None,
+                "Only list the vaults funded at or after this height",
+            ),
+            optional(
+                "max_funded_height",
+                "integer",
+                None,
+                "Only list the vaults funded at or before this height",
+            ),
+            optional(
+                "as_of_height",
+                "integer",
+                None,
+                "List the vaults as they were at this height",
+            ),
+        ],
+        result: &[field(
+            "vaults",
+            "array",
+            "The amount, status, deposit outpoint, address and timestamps of each vault",
+        )],
+    },
+    MethodHelp {
+        name: "getbalances",
+        description: "Display the value of the vaults by protection level",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field(
+                "unconfirmed",
+                "integer",
+                "Deposits that are not confirmed yet",
+            ),
+            field(
+                "unsecured",
+                "integer",
+                "Vaults whose revocation transactions are not signed yet",
+            ),
+            field(
+                "awaiting_resecuring",
+                "integer",
+                "Funds canceled to a vault that isn't secured yet",
+            ),
+            field(
+                "secured",
+                "integer",
+                "Vaults whose revocation transactions are signed",
+            ),
+            field(
+                "active",
+                "integer",
+                "Vaults whose Unvault transaction is signed",
+            ),
+            field(
+                "moving",
+                "integer",
+                "Vaults being unvaulted, spent or canceled by an unconfirmed transaction",
+            ),
+            field(
+                "emergency_vaulting",
+                "integer",
+                "Vaults being sent to the EDV by their Emergency transaction",
+            ),
+            field(
+                "unvault_emergency_vaulting",
+                "integer",
+                "Vaults being sent to the EDV by their Unvault Emergency transaction",
+            ),
+            field(
+                "by_origin",
+                "object",
+                "The same funds, by external deposits, Cancel outputs and Spend change",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "getcpfpreserve",
+        description: "Display the fees needed to CPFP the Unvault transactions at a feerate",
+        availability: Availability::Manager,
+        params: &[
+            required("feerate", "integer", "The target feerate, in sat/vbyte"),
+            optional(
+                "include_active",
+                "bool",
+                Some("false"),
+                "Also account for the vaults that may be unvaulted",
+            ),
+        ],
+        result: &[
+            field("feerate", "integer", "The target feerate, in sat/vbyte"),
+            field(
+                "vaults",
+                "array",
+                "The current feerate of the Unvault transaction of each vault, and the fees needed",
+            ),
+            field("total", "integer", "The fees needed for all the vaults"),
+            field(
+                "available",
+                "integer or null",
+                "The confirmed value of the CPFP wallet, null without CPFP key",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "listpresignedtransactions",
+        description: "List presigned transactions of a confirmed vault",
+        availability: Availability::All,
+        params: &[optional(
+            "outpoints",
+            "array of string",
+            Some("[]"),
+            "The deposit outpoints of the vaults, all of them if empty",
+        )],
+        result: &[field(
+            "presigned_transactions",
+            "array",
+            "The presigned transactions of each vault",
+        )],
+    },
+    MethodHelp {
+        name: "getstalerevocations",
+        description: "List vaults with revocation transactions signed below a feerate",
+        availability: Availability::All,
+        params: &[required(
+            "feerate",
+            "integer",
+            "The feerate, in sat/vbyte, below which a revocation transaction is stale",
+        )],
+        result: &[field(
+            "vaults",
+            "array",
+            "The vaults still to be protected and their stale revocation transactions",
+        )],
+    },
+    MethodHelp {
+        name: "getwatchdata",
+        description: "Get what watchtowers need to enforce the revocation of the Active vaults",
+        availability: Availability::Stakeholder,
+        params: &[optional(
+            "since",
+            "integer",
+            Some("0"),
+            "Only return what changed after this sequence number",
+        )],
+        result: &[
+            field(
+                "sequence",
+                "integer",
+                "The sequence number to fetch the next changes from",
+            ),
+            field(
+                "vaults",
+                "array",
+                "The vaults that got Active, or were revoked, after 'since'",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "listwatchtxids",
+        description: "List the final txids of the fully signed presigned transactions",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field(
+                "unvault",
+                "array",
+                "The deposit outpoint and final txid of the Unvault transactions",
+            ),
+            field(
+                "cancel",
+                "array",
+                "The deposit outpoint and final txid of the Cancel transactions",
+            ),
+            field(
+                "emergency",
+                "array",
+                "The deposit outpoint and final txid of the Emergency transactions",
+            ),
+            field(
+                "unvault_emergency",
+                "array",
+                "The deposit outpoint and final txid of the Unvault Emergency transactions",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "listonchaintransactions",
+        description: "List broadcast transactions of a vault",
+        availability: Availability::All,
+        params: &[optional(
+            "outpoints",
+            "array of string",
+            Some("[]"),
+            "The deposit outpoints of the vaults, all of them if empty",
+        )],
+        result: &[field(
+            "onchain_transactions",
+            "array",
+            "The broadcast transactions of each vault",
+        )],
+    },
+    MethodHelp {
+        name: "getvaultdetails",
+        description: "Get the scripts and presigned txids of a vault",
+        availability: Availability::All,
+        params: &[required(
+            "outpoint",
+            "string",
+            "The deposit outpoint of the vault",
+        )],
+        result: &[
+            field(
+                "deposit_outpoint",
+                "string",
+                "The deposit outpoint of the vault",
+            ),
+            field(
+                "derivation_index",
+                "integer",
+                "The derivation index of the vault",
+            ),
+            field("deposit_address", "string", "The address of the deposit"),
+            field(
+                "unvault_address",
+                "string",
+                "The address of the Unvault output",
+            ),
+            field("cpfp_address", "string", "The address of the CPFP output"),
+            field(
+                "emergency_address",
+                "string or null",
+                "The Emergency address of this vault, only known to stakeholders",
+            ),
+            field("txids", "object", "The txids of the presigned transactions"),
+            field(
+                "final_txids",
+                "object",
+                "The txids of the presigned transactions once fully signed, null until then",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "verifyemergencydescriptor",
+        description: "Check a descriptor generates an Emergency address",
+        availability: Availability::Stakeholder,
+        params: &[
+            optional(
+                "descriptor",
+                "string",
+                None,
+                "The descriptor of the deep-cold setup, the stored one if not given",
+            ),
+            optional(
+                "derivation_index",
+                "integer",
+                None,
+                "The index to derive a ranged descriptor at",
+            ),
+            optional(
+                "proof",
+                "object",
+                None,
+                "A message signed by one of its keys, with the pubkey and the DER signature",
+            ),
+            optional(
+                "store",
+                "bool",
+                Some("false"),
+                "Store the descriptor for future re-verification if it matches",
+            ),
+        ],
+        result: &[
+            field("descriptor", "string", "The verified descriptor"),
+            field(
+                "derivation_index",
+                "integer or null",
+                "The index it was derived at",
+            ),
+            field("derived_address", "string", "The address it generates"),
+            field(
+                "emergency_address",
+                "string",
+                "The derived address if it matches, our first Emergency address otherwise",
+            ),
+            field(
+                "matches",
+                "bool",
+                "Whether it generates one of our Emergency addresses",
+            ),
+            field(
+                "proof_valid",
+                "bool or null",
+                "Whether the signed message is valid, if one was given",
+            ),
+            field(
+                "stored",
+                "bool",
+                "Whether it is stored for future re-verification",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "getrevocationtxs",
+        description: "Retrieve the Revault revocation transactions to sign",
+        availability: Availability::Stakeholder,
+        params: &[required(
+            "selector",
+            "string, array of string or object",
+            "A deposit outpoint, a list of them, or {\"status\": \"funded\"}",
+        )],
+        result: &[
+            field(
+                "cancel_tx",
+                "string",
+                "The Cancel transaction, for a single vault",
+            ),
+            field(
+                "emergency_tx",
+                "string",
+                "The Emergency transaction, for a single vault",
+            ),
+            field(
+                "emergency_unvault_tx",
+                "string",
+                "The Unvault Emergency transaction, for a single vault",
+            ),
+            field(
+                "revocation_txs",
+                "object",
+                "The revocation transactions of each vault, for a batch",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "revocationtxs",
+        description: "Give back the revocation transactions signed",
+        availability: Availability::Stakeholder,
+        params: &[
+            required(
+                "outpoint",
+                "string or object",
+                "A deposit outpoint, or a mapping of deposit outpoints to signed transactions",
+            ),
+            optional(
+                "cancel_tx",
+                "string",
+                None,
+                "The signed Cancel transaction, for a single vault",
+            ),
+            optional(
+                "emergency_tx",
+                "string",
+                None,
+                "The signed Emergency transaction, for a single vault",
+            ),
+            optional(
+                "emergency_unvault_tx",
+                "string",
+                None,
+                "The signed Unvault Emergency transaction, for a single vault",
+            ),
+        ],
+        result: &[field(
+            "results",
+            "object",
+            "The outcome for each vault, for a batch",
+        )],
+    },
+    MethodHelp {
+        name: "getunvaulttx",
+        description: "Retrieve the Revault unvault transaction to sign",
+        availability: Availability::Stakeholder,
+        params: &[required(
+            "outpoint",
+            "string",
+            "The deposit outpoint of the vault",
+        )],
+        result: &[field("unvault_tx", "string", "The Unvault transaction")],
+    },
+    MethodHelp {
+        name: "unvaulttx",
+        description: "Give back the unvault transaction signed",
+        availability: Availability::Stakeholder,
+        params: &[
+            required("outpoint", "string", "The deposit outpoint of the vault"),
+            required("unvault_tx", "string", "The signed Unvault transaction"),
+        ],
+        result: &[],
+    },
+    MethodHelp {
+        name: "getspendtx",
+        description: "Retrieve the Revault spend transaction to sign",
+        availability: Availability::Manager,
+        params: &[
+            required(
+                "outpoints",
+                "array of string",
+                "The deposit outpoints of the vaults to spend",
+            ),
+            required(
+                "outputs",
+                "object",
+                "A mapping of addresses to the amount to pay them",
+            ),
+            required("feerate", "integer", "The feerate, in sat/vbyte"),
+            optional(
+                "allow_high_fees",
+                "bool",
+                Some("false"),
+                "Don't refuse a transaction paying unreasonably high fees",
+            ),
+            optional(
+                "allow_self_send",
+                "bool",
+                Some("false"),
+                "Don't refuse paying to one of our own addresses",
+            ),
+            optional(
+                "proposal_id",
+                "integer",
+                None,
+                "The approved spend proposal this transaction is for",
+            ),
+        ],
+        result: &[
+            field("spend_tx", "string", "The Spend transaction"),
+            field(
+                "reused_destinations",
+                "array",
+                "The destinations we already paid to",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "proposespend",
+        description: "Draft a spend proposal for the managers to approve",
+        availability: Availability::Manager,
+        params: &[
+            required(
+                "outpoints",
+                "array of string",
+                "The deposit outpoints of the vaults to spend",
+            ),
+            required(
+                "outputs",
+                "object",
+                "A mapping of addresses to the amount to pay them",
+            ),
+            required("feerate", "integer", "The feerate, in sat/vbyte"),
+        ],
+        result: &[field("id", "integer", "The identifier of the proposal")],
+    },
+    MethodHelp {
+        name: "listspendproposals",
+        description: "List spend proposals and the managers' decisions",
+        availability: Availability::Manager,
+        params: &[],
+        result: &[field(
+            "spend_proposals",
+            "array",
+            "Each proposal, its status and the managers' decisions",
+        )],
+    },
+    MethodHelp {
+        name: "approvespend",
+        description: "Record a manager's approval of a spend proposal",
+        availability: Availability::Manager,
+        params: &[
+            required("proposal_id", "integer", "The identifier of the proposal"),
+            required(
+                "fingerprint",
+                "string",
+                "The fingerprint of the manager's xpub",
+            ),
+            required(
+                "signature",
+                "string",
+                "The manager's signature of the decision, hex-encoded",
+            ),
+        ],
+        result: &[field("status", "string", "The status of the proposal")],
+    },
+    MethodHelp {
+        name: "rejectspend",
+        description: "Record a manager's rejection of a spend proposal",
+        availability: Availability::Manager,
+        params: &[
+            required("proposal_id", "integer", "The identifier of the proposal"),
+            required(
+                "fingerprint",
+                "string",
+                "The fingerprint of the manager's xpub",
+            ),
+            required(
+                "signature",
+                "string",
+                "The manager's signature of the decision, hex-encoded",
+            ),
+        ],
+        result: &[field("status", "string", "The status of the proposal")],
+    },
+    MethodHelp {
+        name: "updatespendtx",
+        description: "Store or update the stored Spend transaction",
+        availability: Availability::Manager,
+        params: &[required("spend_tx", "string", "The Spend transaction")],
+        result: &[],
+    },
+    MethodHelp {
+        name: "delspendtx",
+        description: "Delete a stored Spend transaction",
+        availability: Availability::Manager,
+        params: &[required(
+            "spend_txid",
+            "string",
+            "The txid of the Spend transaction",
+        )],
+        result: &[],
+    },
+    MethodHelp {
+        name: "listspendtxs",
+        description: "List all stored Spend transactions",
+        availability: Availability::Manager,
+        params: &[optional(
+            "status",
+            "array of string",
+            None,
+            "Only list the Spend transactions with one of these statuses",
+        )],
+        result: &[field(
+            "spend_txs",
+            "array",
+            "The Spend transactions and the vaults they spend",
+        )],
+    },
+    MethodHelp {
+        name: "setspendtx",
+        description: "Announce and broadcast this Spend transaction",
+        availability: Availability::Manager,
+        params: &[
+            required("spend_txid", "string", "The txid of the Spend transaction"),
+            optional(
+                "priority",
+                "bool",
+                Some("false"),
+                "Whether to CPFP the transaction if it gets stuck",
+            ),
+            IDEMPOTENCY_KEY,
+            optional(
+                "override_schedule",
+                "bool",
+                Some("false"),
+                "Broadcast it even outside of the spending schedule",
+            ),
+        ],
+        result: &[],
+    },
+    MethodHelp {
+        name: "abortspend",
+        description: "Give up on an announced Spend transaction whose Unvaults weren't broadcast",
+        availability: Availability::Manager,
+        params: &[required(
+            "spend_txid",
+            "string",
+            "The txid of the Spend transaction",
+        )],
+        result: &[],
+    },
+    MethodHelp {
+        name: "revault",
+        description: "Cancel an unvaulting vault",
+        availability: Availability::All,
+        params: &[
+            required(
+                "deposit_outpoint",
+                "string",
+                "The deposit outpoint of the vault",
+            ),
+            IDEMPOTENCY_KEY,
+        ],
+        result: &[],
+    },
+    MethodHelp {
+        name: "emergency",
+        description: "Broadcast all Emergency signed transactions",
+        availability: Availability::Stakeholder,
+        params: &[IDEMPOTENCY_KEY],
+        result: &[field(
+            "vaults",
+            "array",
+            "What became of each vault: its deposit outpoint, the transaction used and the outcome \
+             of the broadcast",
+        )],
+    },
+    MethodHelp {
+        name: "gethistory",
+        description: "Retrieve history of funds",
+        availability: Availability::All,
+        params: &[
+            required(
+                "kind",
+                "array of string",
+                "The kinds of events to retrieve, among deposit, cancel, spend, emergency and \
+                 unvault_emergency",
+            ),
+            required("start", "integer", "Only events from this timestamp"),
+            required("end", "integer", "Only events until this timestamp"),
+            required("limit", "integer", "The maximum number of events"),
+        ],
+        result: &[field(
+            "events",
+            "array",
+            "The kind, date, amount, fee and txid of each event",
+        )],
+    },
+    MethodHelp {
+        name: "clearvaultflag",
+        description: "Acknowledge a problem a vault was flagged for",
+        availability: Availability::All,
+        params: &[
+            required(
+                "deposit_outpoint",
+                "string",
+                "The deposit outpoint of the vault",
+            ),
+            required("kind", "string", "The kind of flag to clear"),
+        ],
+        result: &[],
+    },
+    MethodHelp {
+        name: "getauditlog",
+        description: "Retrieve the audit log of destructive commands",
+        availability: Availability::All,
+        params: &[
+            optional(
+                "start",
+                "integer",
+                Some("0"),
+                "Only entries from this timestamp",
+            ),
+            optional(
+                "end",
+                "integer",
+                Some("4294967295"),
+                "Only entries until this timestamp",
+            ),
+        ],
+        result: &[field("entries", "array", "The audit log entries")],
+    },
+    MethodHelp {
+        name: "verifyauditlog",
+        description: "Check the audit log hash chain",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field("valid", "bool", "Whether the hash chain is intact"),
+            field(
+                "broken_at",
+                "integer or null",
+                "The first entry whose hash doesn't match",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "export",
+        description: "Export the vaults or the history of the funds as CSV",
+        availability: Availability::All,
+        params: &[
+            required("kind", "string", "What to export, 'vaults' or 'history'"),
+            optional(
+                "start",
+                "integer",
+                Some("0"),
+                "For the history, only events from this timestamp",
+            ),
+            optional(
+                "end",
+                "integer",
+                Some("4294967295"),
+                "For the history, only events until this timestamp",
+            ),
+            optional(
+                "path",
+                "string",
+                None,
+                "A new file to write the export to, relative to the data directory",
+            ),
+        ],
+        result: &[
+            field(
+                "rows",
+                "integer",
+                "The number of rows, not counting the header",
+            ),
+            field(
+                "path",
+                "string or null",
+                "The file the export was written to, if any",
+            ),
+            field(
+                "csv",
+                "string or null",
+                "The export, if it was not written to a file",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "initiatewalletrotation",
+        description: "Initiate the rotation of the keys of the deployment to a new wallet, to \
+                      which 'migratevaults' moves the active vaults",
+        availability: Availability::All,
+        params: &[
+            required(
+                "stakeholders_xpubs",
+                "array of strings",
+                "The new xpubs of the stakeholders",
+            ),
+            required(
+                "managers_xpubs",
+                "array of strings",
+                "The new xpubs of the managers",
+            ),
+            required(
+                "managers_threshold",
+                "integer",
+                "The number of managers needed to spend from the new wallet",
+            ),
+            required(
+                "cosigners_keys",
+                "array of strings",
+                "The public keys of the cosigning servers",
+            ),
+            required(
+                "cpfp_xpubs",
+                "array of strings",
+                "The new xpubs of the CPFP descriptor",
+            ),
+            optional(
+                "our_stakeholder_xpub",
+                "string",
+                None,
+                "Our new xpub, if we are a stakeholder",
+            ),
+            optional(
+                "our_manager_xpub",
+                "string",
+                None,
+                "Our new xpub, if we are a manager",
+            ),
+        ],
+        result: &[
+            field("wallet_id", "integer", "The id of the new wallet"),
+            field(
+                "deposit_descriptor",
+                "string",
+                "The Deposit descriptor of the new wallet",
+            ),
+            field(
+                "unvault_descriptor",
+                "string",
+                "The Unvault descriptor of the new wallet",
+            ),
+            field(
+                "cpfp_descriptor",
+                "string",
+                "The CPFP descriptor of the new wallet",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "migratevaults",
+        description: "Create the Spend transactions moving the active vaults to the new wallet \
+                      of the rotation of the keys, skipping those already being moved",
+        availability: Availability::Manager,
+        params: &[required(
+            "feerate",
+            "integer",
+            "The feerate of the Spend transactions, in sat/vbyte",
+        )],
+        result: &[field(
+            "migrations",
+            "array of objects",
+            "The deposit outpoints of each batch of vaults, the address it's moved to and the \
+             Spend transaction to sign and set",
+        )],
+    },
+    MethodHelp {
+        name: "getloglevel",
+        description: "Get the levels at which we currently log",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field("level", "string", "The global log level"),
+            field(
+                "modules",
+                "object",
+                "The level of some modules and their submodules, by module path",
+            ),
+            field(
+                "revert_at",
+                "integer or null",
+                "When the configured levels will be restored, if they were changed temporarily",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "setloglevel",
+        description: "Change the levels at which we log right away, optionally for a limited time",
+        availability: Availability::All,
+        params: &[
+            required(
+                "level",
+                "string",
+                "One of 'off', 'error', 'warn', 'info', 'debug' or 'trace'",
+            ),
+            optional(
+                "modules",
+                "object",
+                None,
+                "The level of some modules and their submodules by module path, the current \
+                 ones if not given",
+            ),
+            optional(
+                "duration_seconds",
+                "integer",
+                None,
+                "After how long to restore the configured levels, never if not given",
+            ),
+        ],
+        result: &[field(
+            "previous",
+            "object",
+            "The levels in use until now, as returned by 'getloglevel'",
+        )],
+    },
+    MethodHelp {
+        name: "auditwallet",
+        description: "Compare our watchonly wallet with the addresses we imported into it",
+        availability: Availability::All,
+        params: &[],
+        result: &[
+            field("checked_at", "integer", "When the comparison was made"),
+            field(
+                "watched",
+                "integer",
+                "How many of our addresses the wallet watches",
+            ),
+            field(
+                "missing",
+                "array of string",
+                "Our addresses the wallet lost, being imported back",
+            ),
+            field(
+                "unexpected",
+                "array of string",
+                "The addresses or descriptors the wallet watches that we never imported",
+            ),
+            field(
+                "imports_corrected",
+                "bool",
+                "Whether the record of the addresses we imported was behind the wallet",
+            ),
+        ],
+    },
+    MethodHelp {
+        name: "getrpcstats",
+        description: "Get the number of calls and their latencies, by RPC method",
+        availability: Availability::All,
+        params: &[optional(
+            "reset",
+            "bool",
+            Some("false"),
+            "Whether to start over recording after returning them",
+        )],
+        result: &[
+            field(
+                "since",
+                "integer",
+                "Since when the calls are recorded, startup or the last reset",
+            ),
+            field(
+                "methods",
+                "array of objects",
+                "For each method called, the number of 'calls' and the 'p50_us', 'p95_us' and \
+                 'max_us' latencies in microseconds",
+            ),
+        ],
+    },
+];
+
+/// Get the description of this command, if it exists
+pub fn method_help(name: &str) -> Option<&'static MethodHelp> {
+    METHODS.iter().find(|m| m.name == name)
+}
+
+/// The version of the RPC interface. The minor is bumped when commands, parameters or fields are
+/// added, the major when existing ones change or are removed.
+pub const API_VERSION: (u32, u32, u32) = (1, 9, 0);
+
+/// The version of the RPC interface, as 'major.minor.patch'
+pub fn api_version() -> String {
+    let (major, minor, patch) = API_VERSION;
+    format!("{}.{}.{}", major, minor, patch)
+}
+
+/// Parse an API version given as 'major.minor.patch'
+pub fn parse_api_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().split('.').map(|p| p.parse::<u32>().ok());
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None) => {
+            Some((major, minor, patch))
+        }
+        _ => None,
+    }
+}
+
+/// A capability of the daemon clients can check for instead of probing for commands
+#[derive(Debug, Clone, Serialize)]
+pub struct FeatureHelp {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The commands providing it. It is only available if they all are.
+    pub methods: &'static [&'static str],
+}
+
+/// All the features, each registered along with the commands it relies on
+pub const FEATURES: &[FeatureHelp] = &[
+    FeatureHelp {
+        name: "version_negotiation",
+        description: "Check the daemon is recent enough before using it",
+        methods: &["hello"],
+    },
+    FeatureHelp {
+        name: "command_discovery",
+        description: "Machine-readable description of the commands",
+        methods: &["help", "listcommands"],
+    },
+    FeatureHelp {
+        name: "batch_revocations",
+        description: "Get and give back the revocation transactions of many vaults at once",
+        methods: &["getrevocationtxs", "revocationtxs"],
+    },
+    FeatureHelp {
+        name: "stale_revocations",
+        description: "List the vaults whose revocation transactions should be re-signed",
+        methods: &["getstalerevocations"],
+    },
+    FeatureHelp {
+        name: "emergency_descriptor_verification",
+        description: "Check a descriptor generates the Emergency address",
+        methods: &["verifyemergencydescriptor"],
+    },
+    FeatureHelp {
+        name: "watchtower_data",
+        description: "Export the data for watchtowers to guard the Active vaults",
+        methods: &["getwatchdata", "listwatchtxids"],
+    },
+    FeatureHelp {
+        name: "cpfp_reserve",
+        description: "Estimate the fees needed to CPFP the Unvaults",
+        methods: &["getcpfpreserve"],
+    },
+    FeatureHelp {
+        name: "spend_proposals",
+        description: "Have spends approved by the managers before creating them",
+        methods: &[
+            "proposespend",
+            "listspendproposals",
+            "approvespend",
+            "rejectspend",
+        ],
+    },
+    FeatureHelp {
+        name: "vault_flags",
+        description: "Acknowledge the problems vaults were flagged for",
+        methods: &["clearvaultflag"],
+    },
+    FeatureHelp {
+        name: "audit_log",
+        description: "Retrieve and check the audit log of destructive commands",
+        methods: &["getauditlog", "verifyauditlog"],
+    },
+    FeatureHelp {
+        name: "csv_export",
+        description: "Export the vaults or the history of funds as CSV",
+        methods: &["export"],
+    },
+    FeatureHelp {
+        name: "wallet_rotation",
+        description: "Rotate the keys to a new wallet",
+        methods: &["initiatewalletrotation"],
+    },
+    FeatureHelp {
+        name: "vault_migration",
+        description: "Move the active vaults to the new wallet",
+        methods: &["migratevaults"],
+    },
+    FeatureHelp {
+        name: "runtime_log_levels",
+        description: "Change the levels at which we log without restarting",
+        methods: &["getloglevel", "setloglevel"],
+    },
+    FeatureHelp {
+        name: "wallet_audit",
+        description: "Check our watchonly wallet watches what we imported, and nothing else",
+        methods: &["auditwallet"],
+    },
+    FeatureHelp {
+        name: "spend_expiry",
+        description: "Give up on the Spend transactions whose Unvaults weren't broadcast",
+        methods: &["abortspend"],
+    },
+    FeatureHelp {
+        name: "rpc_stats",
+        description: "Get the latencies of the RPC calls",
+        methods: &["getrpcstats"],
+    },
+    FeatureHelp {
+        name: "new_deposit_addresses",
+        description: "Hand out a distinct deposit address to each depositor",
+        methods: &["getnewdepositaddress"],
+    },
+    FeatureHelp {
+        name: "build_info",
+        description: "Get the metadata of the build of the daemon",
+        methods: &["version"],
+    },
+];
+
+/// The name of the features available to a participant with these roles
+pub fn available_features(is_stakeholder: bool, is_manager: bool) -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|feature| {
+            feature.methods.iter().all(|name| {
+                method_help(name)
+                    .map(|m| m.availability.allows(is_stakeholder, is_manager))
+                    .unwrap_or(false)
+            })
+        })
+        .map(|feature| feature.name)
+        .collect()
+}
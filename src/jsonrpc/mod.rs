@@ -1,2 +1,5 @@
 mod api;
+mod help;
 pub mod server;
+pub(crate) mod stream;
+pub mod tcp_server;
@@ -1,14 +1,23 @@
 //! Here we handle incoming connections and communication on the RPC socket.
 //! Actual JSONRPC2 commands are handled in the `api` mod.
 
-use crate::jsonrpc::api::{JsonRpcMetaData, RpcApi, RpcImpl};
+use crate::jsonrpc::{
+    api::{JsonRpcMetaData, RpcApi, RpcImpl},
+    stream::{Chunk, PendingResponse, ResponseQueue, ResponseStream},
+    tcp_server::tcp_rpcserver_loop,
+};
 use crate::DaemonControl;
 
 use std::{
     collections::{HashMap, VecDeque},
+    env,
     io::{self, Write},
+    mem,
+    net::TcpListener,
+    os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
     path::PathBuf,
-    sync::{Arc, RwLock},
+    process,
+    sync::{mpsc, Arc, RwLock},
     thread,
 };
 
@@ -121,25 +130,137 @@ fn write_byte_stream(stream: &mut UnixStream, resp: Vec<u8>) -> Result<Option<Ve
 
 // Used to check if, when receiving an event for a token, we have an ongoing connection and stream
 // for it.
-type ConnectionMap = HashMap<Token, (UnixStream, Arc<RwLock<VecDeque<Vec<u8>>>>)>;
+type ConnectionMap = HashMap<Token, (UnixStream, ResponseQueue, Option<u32>)>;
+
+// The UID of the process on the other end of the socket, used to attribute the commands recorded
+// in the audit log.
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> Option<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
 
+    if ret == 0 {
+        Some(cred.uid)
+    } else {
+        log::error!(
+            "Could not get the credentials of the RPC client: '{}'",
+            io::Error::last_os_error()
+        );
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> Option<u32> {
+    let (mut uid, mut gid) = (0, 0);
+    let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+
+    if ret == 0 {
+        Some(uid)
+    } else {
+        log::error!(
+            "Could not get the credentials of the RPC client: '{}'",
+            io::Error::last_os_error()
+        );
+        None
+    }
+}
+
+// The handler may stream the response instead of returning it
 fn handle_single_request(
     jsonrpc_io: Arc<RwLock<jsonrpc_core::MetaIoHandler<JsonRpcMetaData>>>,
     metadata: JsonRpcMetaData,
-    resp_queue: Arc<RwLock<VecDeque<Vec<u8>>>>,
+    resp_queue: ResponseQueue,
+    stream: Arc<ResponseStream>,
     message: MethodCall,
 ) {
-    let res = jsonrpc_io
-        .read()
-        .unwrap()
-        .handle_call(Call::MethodCall(message), metadata)
-        .wait()
-        .expect("jsonrpc_core says: Handler calls can never fail.")
-        .expect("This is a method call, there is always a response.");
+    let method = message.method.clone();
+    let res = metadata.clone().timed(&method, || {
+        jsonrpc_io
+            .read()
+            .unwrap()
+            .handle_call(
+                Call::MethodCall(message),
+                metadata.with_response_stream(stream.clone()),
+            )
+            .wait()
+            .expect("jsonrpc_core says: Handler calls can never fail.")
+            .expect("This is a method call, there is always a response.")
+    });
+    if stream.started() {
+        log::trace!("Streamed the response to '{}'", method);
+        return;
+    }
     let resp = Response::Single(res);
     let resp_bytes = serde_json::to_vec(&resp).expect("jsonrpc_core says: This should never fail.");
 
-    resp_queue.write().unwrap().push_back(resp_bytes);
+    resp_queue
+        .write()
+        .unwrap()
+        .push_back(PendingResponse::Bytes(resp_bytes));
+}
+
+// Write as much of the pending responses as we can without blocking. Returns false if a streamed
+// response was cut short, in which case the connection must be closed for the client to notice.
+fn write_responses(
+    stream: &mut UnixStream,
+    resp_queue: &ResponseQueue,
+    token: Token,
+) -> Result<bool, io::Error> {
+    // FIFO
+    loop {
+        // We can't use while let Some(resp) because deadlock
+        let resp = match resp_queue.write().unwrap().pop_front() {
+            Some(resp) => resp,
+            None => return Ok(true),
+        };
+
+        let (bytes, receiver) = match resp {
+            PendingResponse::Bytes(bytes) => (bytes, None),
+            PendingResponse::Stream(receiver) => match receiver.try_recv() {
+                Ok(Chunk::Data(bytes)) => (bytes, Some(receiver)),
+                Ok(Chunk::End) => continue,
+                // The handler is still computing the next chunk
+                Err(mpsc::TryRecvError::Empty) => {
+                    resp_queue
+                        .write()
+                        .unwrap()
+                        .push_front(PendingResponse::Stream(receiver));
+                    return Ok(true);
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    log::error!("Streamed response for {:?} was cut short", token);
+                    return Ok(false);
+                }
+            },
+        };
+
+        log::trace!("Writing response for {:?} ({} bytes)", token, bytes.len());
+        // If we could not write the data, don't lose track of it! This would only reasonably
+        // happen on `WouldBlock`.
+        let remaining = write_byte_stream(stream, bytes)?;
+        let mut queue = resp_queue.write().unwrap();
+        if let Some(receiver) = receiver {
+            queue.push_front(PendingResponse::Stream(receiver));
+        }
+        if let Some(remaining) = remaining {
+            queue.push_front(PendingResponse::Bytes(remaining));
+            return Ok(true);
+        }
+    }
 }
 
 // Read request from the stream, parse it as JSON and handle the JSONRPC command.
@@ -151,10 +272,10 @@ fn handle_single_request(
 fn read_handle_request(
     cache: &mut Vec<u8>,
     stream: &mut UnixStream,
-    resp_queue: &mut Arc<RwLock<VecDeque<Vec<u8>>>>,
+    resp_queue: &mut ResponseQueue,
     jsonrpc_io: &Arc<RwLock<jsonrpc_core::MetaIoHandler<JsonRpcMetaData>>>,
     metadata: &JsonRpcMetaData,
-    handler_threads: &mut VecDeque<thread::JoinHandle<()>>,
+    handler_threads: &mut VecDeque<(thread::JoinHandle<()>, Arc<ResponseStream>)>,
 ) -> Result<(), io::Error> {
     // We use an optional index if there is some left unparsed bytes, because borrow checker :)
     let mut leftover = None;
@@ -177,27 +298,38 @@ fn read_handle_request(
                 let t_io_handler = jsonrpc_io.clone();
                 let t_meta = metadata.clone();
                 let t_queue = resp_queue.clone();
+                let t_stream = Arc::new(ResponseStream::new(m.id.clone(), resp_queue.clone()));
 
                 // We special case the 'stop' command to treat it synchronously, as we could miss
                 // the "read closed" event in the main loop and hang up forever otherwise.
                 // FIXME: We could not have a handler for it, and just write the raw response by
                 // hand.
                 if m.method.as_str() == "stop" {
-                    handle_single_request(t_io_handler, t_meta, t_queue, m);
+                    handle_single_request(t_io_handler, t_meta, t_queue, t_stream, m);
                 } else {
                     // If there are too many threads spawned, wait for the oldest one to complete.
+                    // Not for one streaming its response though, as it's waiting for us to write
+                    // it out.
                     // FIXME: we can be smarter than that..
                     if handler_threads.len() >= MAX_HANDLER_THREADS {
-                        handler_threads
-                            .pop_front()
-                            .expect("Just checked the length")
-                            .join()
-                            .unwrap();
+                        match handler_threads.iter().position(|(_, s)| !s.streaming()) {
+                            Some(i) => handler_threads
+                                .remove(i)
+                                .expect("Just found it")
+                                .0
+                                .join()
+                                .unwrap(),
+                            None => log::debug!(
+                                "All handler threads are streaming a response, spawning another one"
+                            ),
+                        }
                     }
 
-                    handler_threads.push_back(thread::spawn(move || {
-                        handle_single_request(t_io_handler, t_meta, t_queue, m)
-                    }));
+                    let stream = t_stream.clone();
+                    let handle = thread::spawn(move || {
+                        handle_single_request(t_io_handler, t_meta, t_queue, t_stream, m)
+                    });
+                    handler_threads.push_back((handle, stream));
                 }
             }
             // Parsing error? Assume it's a message we'll be able to read later.
@@ -242,7 +374,7 @@ fn mio_loop(
     let mut read_cache_map: HashMap<Token, Vec<u8>> = HashMap::with_capacity(8);
     let jsonrpc_io = Arc::from(RwLock::from(jsonrpc_io));
     // Handle to thread currently handling commands we were sent.
-    let mut handler_threads: VecDeque<std::thread::JoinHandle<_>> =
+    let mut handler_threads: VecDeque<(thread::JoinHandle<_>, Arc<ResponseStream>)> =
         VecDeque::with_capacity(MAX_HANDLER_THREADS);
 
     poller
@@ -259,6 +391,7 @@ fn mio_loop(
                     match listener.accept() {
                         Ok((mut stream, _)) => {
                             let curr_token = Token(unique_token.0);
+                            let uid = peer_uid(&stream);
                             unique_token.0 += 1;
 
                             // So we actually know they want to discuss :)
@@ -273,7 +406,8 @@ fn mio_loop(
                                 curr_token,
                                 (
                                     stream,
-                                    Arc::new(RwLock::new(VecDeque::<Vec<u8>>::with_capacity(32))),
+                                    Arc::new(RwLock::new(VecDeque::with_capacity(32))),
+                                    uid,
                                 ),
                             );
 
@@ -290,11 +424,19 @@ fn mio_loop(
                         }
                     }
                 }
+
+                // We may have been told to stop through the TCP interface.
+                if metadata.is_shutdown() && connections_map.is_empty() {
+                    while let Some(t) = handler_threads.pop_front() {
+                        t.0.join().unwrap();
+                    }
+                    return Ok(());
+                }
             } else if connections_map.contains_key(&event.token()) {
                 // Under normal circumstances we are always interested in both
                 // Writable (do we got something for them from the resp_queue?)
                 // and Readable (do they have something for us?) events
-                let (stream, resp_queue) = connections_map
+                let (stream, resp_queue, uid) = connections_map
                     .get_mut(&event.token())
                     .expect("We checked it existed just above.");
                 poller.registry().reregister(
@@ -314,46 +456,34 @@ fn mio_loop(
                         stream,
                         resp_queue,
                         &jsonrpc_io,
-                        &metadata,
+                        &metadata.with_peer_uid(*uid),
                         &mut handler_threads,
                     )?;
                 }
 
+                let mut cut_short = false;
                 if event.is_writable() {
-                    // FIFO
-                    loop {
-                        // We can't use while let Some(resp) because deadlock
-                        let resp = match resp_queue.write().unwrap().pop_front() {
-                            Some(resp) => resp,
-                            None => break,
-                        };
-
-                        log::trace!(
-                            "Writing response for {:?} ({} bytes)",
-                            event.token(),
-                            resp.len()
-                        );
-                        // If we could not write the data, don't lose track of it! This would only
-                        // reasonably happen on `WouldBlock`.
-                        match write_byte_stream(stream, resp) {
-                            Ok(Some(resp)) => resp_queue.write().unwrap().push_front(resp),
-                            Ok(None) => {}
-                            Err(e) => {
-                                log::error!("Error writing resp for {:?}: '{}'", event.token(), e)
-                            }
+                    match write_responses(stream, resp_queue, event.token()) {
+                        Ok(complete) => cut_short = !complete,
+                        Err(e) => {
+                            log::error!("Error writing resp for {:?}: '{}'", event.token(), e)
                         }
                     }
                 }
 
-                if event.is_read_closed() || event.is_error() {
+                if cut_short || event.is_read_closed() || event.is_error() {
                     log::trace!("Dropping connection for {:?}", event.token());
-                    connections_map.remove(&event.token());
+                    if let Some((_, resp_queue, _)) = connections_map.remove(&event.token()) {
+                        // The handlers streaming a response to it share the queue, drop the
+                        // receiving ends for them to stop right away.
+                        resp_queue.write().unwrap().clear();
+                    }
 
                     // If this was the last connection alive and we are shutting down,
                     // actually shut down.
                     if metadata.is_shutdown() && connections_map.is_empty() {
                         while let Some(t) = handler_threads.pop_front() {
-                            t.join().unwrap();
+                            t.0.join().unwrap();
                         }
                         return Ok(());
                     }
@@ -399,28 +529,207 @@ pub fn rpcserver_setup(socket_path: PathBuf) -> Result<UnixListener, io::Error>
     listener
 }
 
-/// The main event loop for the JSONRPC interface, polling the UDS listener
+// The first file descriptor passed by the service manager, as per sd_listen_fds(3).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// The name the service manager must give the RPC socket it passes us (`FileDescriptorName=`
+/// in the socket unit).
+pub const RPC_SOCKET_FDNAME: &str = "revaultd_rpc";
+
+// Get the file descriptor named `RPC_SOCKET_FDNAME` passed by the service manager, if any.
+fn activated_fd() -> Result<Option<RawFd>, String> {
+    let listen_fds = match env::var("LISTEN_FDS") {
+        Ok(fds) => fds,
+        Err(_) => return Ok(None),
+    };
+
+    // It may have been set for our parent (for instance if we daemonized).
+    let listen_pid = env::var("LISTEN_PID")
+        .map_err(|_| "LISTEN_FDS is set but LISTEN_PID is not".to_string())?;
+    if listen_pid.parse::<u32>().ok() != Some(process::id()) {
+        return Err(format!(
+            "LISTEN_PID '{}' is not our PID '{}'",
+            listen_pid,
+            process::id()
+        ));
+    }
+
+    let n_fds = listen_fds
+        .parse::<RawFd>()
+        .map_err(|e| format!("Invalid LISTEN_FDS '{}': '{}'", listen_fds, e))?;
+    let names = env::var("LISTEN_FDNAMES")
+        .map_err(|_| "LISTEN_FDS is set but LISTEN_FDNAMES is not".to_string())?;
+    let names: Vec<&str> = names.split(':').collect();
+    if names.len() as RawFd != n_fds {
+        return Err(format!(
+            "LISTEN_FDS is '{}' but LISTEN_FDNAMES has {} names",
+            n_fds,
+            names.len()
+        ));
+    }
+
+    match names.iter().position(|name| *name == RPC_SOCKET_FDNAME) {
+        Some(i) => Ok(Some(SD_LISTEN_FDS_START + i as RawFd)),
+        None => Err(format!(
+            "No file descriptor named '{}' in LISTEN_FDNAMES",
+            RPC_SOCKET_FDNAME
+        )),
+    }
+}
+
+// Whether this file descriptor is a socket in listening state.
+fn is_listening(fd: RawFd) -> Result<bool, io::Error> {
+    let mut accept_conn: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut accept_conn as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(accept_conn != 0)
+}
+
+// Check the file descriptor passed to us is a listening UNIX socket and take ownership of it.
+fn activated_listener_from_fd(fd: RawFd) -> Result<UnixListener, String> {
+    match is_listening(fd) {
+        Ok(true) => {}
+        Ok(false) => return Err(format!("File descriptor '{}' is not listening", fd)),
+        Err(e) => return Err(format!("File descriptor '{}' is not a socket: '{}'", fd, e)),
+    }
+
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+    let checked = listener
+        .local_addr()
+        .and_then(|_| listener.set_nonblocking(true));
+    if let Err(e) = checked {
+        // It's not ours to close.
+        listener.into_raw_fd();
+        return Err(format!(
+            "File descriptor '{}' is not a UNIX socket: '{}'",
+            fd, e
+        ));
+    }
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+
+    Ok(UnixListener::from_std(listener))
+}
+
+/// Get the listening UNIX socket passed by the service manager through the socket activation
+/// protocol (`LISTEN_FDS`, `LISTEN_FDNAMES`), if any. We must not unlink it, it's the service
+/// manager's.
+/// Returns `None` if we were not passed any or if it is not usable, in which case we should
+/// create the socket ourselves.
+pub fn activated_listener() -> Option<UnixListener> {
+    let fd = match activated_fd() {
+        Ok(Some(fd)) => fd,
+        Ok(None) => return None,
+        Err(e) => {
+            log::error!(
+                "Invalid socket activation environment, not using it: '{}'",
+                e
+            );
+            return None;
+        }
+    };
+
+    match activated_listener_from_fd(fd) {
+        Ok(listener) => {
+            log::info!(
+                "Using the RPC socket passed by the service manager (fd '{}')",
+                fd
+            );
+            Some(listener)
+        }
+        Err(e) => {
+            log::error!(
+                "Invalid socket passed by the service manager, not using it: '{}'",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// A handler for all our JSONRPC commands
+pub(super) fn jsonrpc_io_handler() -> jsonrpc_core::MetaIoHandler<JsonRpcMetaData> {
+    let mut jsonrpc_io = jsonrpc_core::MetaIoHandler::<JsonRpcMetaData, _>::default();
+    jsonrpc_io.extend_with(RpcImpl.to_delegate());
+    jsonrpc_io
+}
+
+/// The main event loop for the JSONRPC interface, polling the UDS listener. If a TCP listener is
+/// given, the interface is also served over it in a separate thread.
 pub fn rpcserver_loop(
     listener: UnixListener,
+    tcp_listener: Option<TcpListener>,
     daemon_control: DaemonControl,
 ) -> Result<(), io::Error> {
-    let mut jsonrpc_io = jsonrpc_core::MetaIoHandler::<JsonRpcMetaData, _>::default();
-    jsonrpc_io.extend_with(RpcImpl.to_delegate());
-    let metadata = JsonRpcMetaData::new(daemon_control);
+    let metadata = JsonRpcMetaData::new(daemon_control.clone());
+    let mut tcp_thread = None;
+
+    if let Some(tcp_listener) = tcp_listener {
+        let (noise_secret, clients, rpc_socket) = {
+            let revaultd = daemon_control.revaultd.read().unwrap();
+            (
+                revaultd.noise_secret.clone(),
+                revaultd.rpc_clients.clone(),
+                revaultd.rpc_socket_file(),
+            )
+        };
+        let tcp_metadata = metadata.clone();
+        tcp_thread = Some(thread::spawn(move || {
+            tcp_rpcserver_loop(
+                tcp_listener,
+                jsonrpc_io_handler(),
+                tcp_metadata,
+                noise_secret,
+                clients,
+                rpc_socket,
+            )
+        }));
+    }
 
     log::info!("JSONRPC server started.");
-    return mio_loop(listener, jsonrpc_io, metadata);
+    mio_loop(listener, jsonrpc_io_handler(), metadata)?;
+    // We were told to stop, the TCP listener notices it on its own.
+    if let Some(tcp_thread) = tcp_thread {
+        tcp_thread.join().unwrap();
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{read_bytes_from_stream, rpcserver_loop, rpcserver_setup, trimmed};
-    use crate::utils::test_utils::{dummy_rpcutil, test_datadir, UserRole};
+    use super::{
+        activated_listener, jsonrpc_io_handler, read_bytes_from_stream, rpcserver_loop,
+        rpcserver_setup, trimmed, RPC_SOCKET_FDNAME,
+    };
+    use crate::{
+        commands::ErrorCode,
+        jsonrpc::{
+            api::JsonRpcMetaData,
+            help::{api_version, API_VERSION, FEATURES, METHODS},
+        },
+        rpcstats::{phase_timer, Phase},
+        utils::test_utils::{dummy_rpcutil, test_datadir, UserRole},
+    };
 
     use std::{
-        fs,
+        env, fs,
         io::{Cursor, Read, Write},
-        thread,
+        os::unix::io::{IntoRawFd, RawFd},
+        process, thread,
         time::Duration,
     };
 
@@ -438,7 +747,7 @@ mod tests {
 
         let socket = rpcserver_setup(rpc_socket_path.clone()).unwrap();
         let server_loop_thread = thread::spawn(move || {
-            rpcserver_loop(socket, rpcutils).unwrap_or_else(|e| {
+            rpcserver_loop(socket, None, rpcutils).unwrap_or_else(|e| {
                 panic!("Error in JSONRPC server event loop: {}", e.to_string());
             })
         });
@@ -512,6 +821,89 @@ mod tests {
         fs::remove_dir_all(&datadir).unwrap();
     }
 
+    // Pretend the service manager passed us this file descriptor, along with dummy ones for
+    // all the (already used) ones before it.
+    fn set_activation_env(fd: RawFd) {
+        let mut names = vec!["unknown"; fd as usize - 3];
+        names.push(RPC_SOCKET_FDNAME);
+        env::set_var("LISTEN_PID", process::id().to_string());
+        env::set_var("LISTEN_FDS", names.len().to_string());
+        env::set_var("LISTEN_FDNAMES", names.join(":"));
+    }
+
+    // The LISTEN_* variables are process-wide, so everything using them is tested here.
+    #[test]
+    fn socket_activation() {
+        let datadir = test_datadir();
+        let rpcutils = dummy_rpcutil(datadir.clone(), UserRole::ManagerStakeholder);
+        let mut rpc_socket_path = rpcutils.revaultd.read().unwrap().data_dir.clone();
+        rpc_socket_path.push("activated_rpc");
+
+        // Not socket-activated
+        env::remove_var("LISTEN_FDS");
+        assert!(activated_listener().is_none());
+
+        // A connected socket is not a listening one
+        let (mut sock_a, mut sock_b) = UnixStream::pair().unwrap();
+        set_activation_env(sock_a.try_clone().unwrap().into_raw_fd());
+        assert!(activated_listener().is_none());
+        // And we did not close it
+        sock_a.write_all(b"still here").unwrap();
+        let mut buf = [0; 10];
+        sock_b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"still here");
+
+        // The "service manager" creates and binds the socket
+        let listener_fd = std::os::unix::net::UnixListener::bind(&rpc_socket_path)
+            .unwrap()
+            .into_raw_fd();
+
+        // Inconsistent environments are ignored
+        set_activation_env(listener_fd);
+        env::set_var("LISTEN_PID", (process::id() + 1).to_string());
+        assert!(activated_listener().is_none());
+        set_activation_env(listener_fd);
+        env::set_var("LISTEN_FDS", "1");
+        assert!(activated_listener().is_none());
+        set_activation_env(listener_fd);
+        env::set_var("LISTEN_FDNAMES", "unknown");
+        assert!(activated_listener().is_none());
+        set_activation_env(listener_fd);
+        env::remove_var("LISTEN_FDNAMES");
+        assert!(activated_listener().is_none());
+
+        // We serve on the inherited socket
+        set_activation_env(listener_fd);
+        let socket = activated_listener().unwrap();
+        for var in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(var);
+        }
+        let server_loop_thread = thread::spawn(move || {
+            rpcserver_loop(socket, None, rpcutils).unwrap_or_else(|e| {
+                panic!("Error in JSONRPC server event loop: {}", e.to_string());
+            })
+        });
+        let mut sock = UnixStream::connect(&rpc_socket_path).unwrap();
+        let msg = String::from(r#"{"jsonrpc": "2.0", "id": 1, "method": "aaa", "params": []}"#);
+        let mut response = vec![0; 256];
+        sock.write_all(msg.as_bytes()).unwrap();
+        let read = sock.read(&mut response).unwrap();
+        assert_eq!(
+            String::from_utf8(trimmed(response, read)).unwrap(),
+            r#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"Method not found"},"id":1}"#
+        );
+        let msg = String::from(r#"{"jsonrpc": "2.0", "id": 0, "method": "stop", "params": []}"#);
+        sock.write_all(msg.as_bytes()).unwrap();
+        sock.flush().unwrap();
+        drop(sock);
+        server_loop_thread.join().unwrap();
+
+        // It's not ours to remove
+        assert!(rpc_socket_path.exists());
+
+        fs::remove_dir_all(&datadir).unwrap();
+    }
+
     #[test]
     fn test_bytes_reader() {
         let samples = [vec![22; 22], vec![1; 522], vec![189; 28903]];
@@ -523,4 +915,215 @@ mod tests {
             assert_eq!(&res.unwrap().unwrap(), data);
         }
     }
+
+    // The help must describe every command, with the parameters their handler accepts.
+    #[test]
+    fn help_matches_handlers() {
+        let datadir = test_datadir();
+        let metadata =
+            JsonRpcMetaData::new(dummy_rpcutil(datadir.clone(), UserRole::ManagerStakeholder));
+        let io = jsonrpc_io_handler();
+
+        let mut registered: Vec<&str> = io.iter().map(|(name, _)| name.as_str()).collect();
+        registered.sort_unstable();
+        let mut described: Vec<&str> = METHODS.iter().map(|m| m.name).collect();
+        described.sort_unstable();
+        assert_eq!(registered, described);
+
+        // Calls the method with this number of null parameters, returns the error code if any.
+        let call = |method: &str, n_params: usize| -> Option<i64> {
+            let req = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": method,
+                "params": vec![serde_json::Value::Null; n_params],
+            });
+            let resp = io
+                .handle_request_sync(&req.to_string(), metadata.clone())
+                .expect("Not a notification");
+            let resp: serde_json::Value = serde_json::from_str(&resp).unwrap();
+            resp["error"]["code"].as_i64()
+        };
+        const INVALID_PARAMS: i64 = -32602;
+
+        for method in METHODS {
+            // The parameters are in the same order, and optional ones are last.
+            let n_required = method.params.iter().filter(|p| !p.optional).count();
+            assert!(
+                method.params[..n_required].iter().all(|p| !p.optional),
+                "Optional parameter before a required one for '{}'",
+                method.name
+            );
+
+            // It doesn't take more parameters than described..
+            assert_eq!(
+                call(method.name, method.params.len() + 1),
+                Some(INVALID_PARAMS),
+                "'{}' takes more parameters than described",
+                method.name
+            );
+            // ..Nor does it require fewer.
+            if n_required > 0 {
+                assert_eq!(
+                    call(method.name, n_required - 1),
+                    Some(INVALID_PARAMS),
+                    "'{}' has fewer required parameters than described",
+                    method.name
+                );
+            }
+        }
+
+        fs::remove_dir_all(&datadir).unwrap();
+    }
+
+    // The features are those of the registered handlers available to each role, and clients
+    // can ask for a minimum API version.
+    #[test]
+    fn features_and_hello() {
+        let io = jsonrpc_io_handler();
+        let registered: Vec<&str> = io.iter().map(|(name, _)| name.as_str()).collect();
+        for (i, feature) in FEATURES.iter().enumerate() {
+            assert!(
+                FEATURES[..i].iter().all(|f| f.name != feature.name),
+                "Feature '{}' is registered twice",
+                feature.name
+            );
+            assert!(!feature.methods.is_empty());
+            for method in feature.methods {
+                assert!(
+                    registered.contains(method),
+                    "Feature '{}' relies on '{}', which has no handler",
+                    feature.name,
+                    method
+                );
+            }
+        }
+
+        // Calls 'hello' as this participant, returns the result or the error code.
+        let hello = |role: UserRole,
+                     min_api_version: serde_json::Value|
+         -> Result<serde_json::Value, i64> {
+            let datadir = test_datadir();
+            let metadata = JsonRpcMetaData::new(dummy_rpcutil(datadir.clone(), role));
+            let req = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "hello",
+                "params": [min_api_version],
+            });
+            let resp = io
+                .handle_request_sync(&req.to_string(), metadata)
+                .expect("Not a notification");
+            fs::remove_dir_all(&datadir).unwrap();
+            let resp: serde_json::Value = serde_json::from_str(&resp).unwrap();
+            match resp["error"]["code"].as_i64() {
+                Some(code) => Err(code),
+                None => Ok(resp["result"].clone()),
+            }
+        };
+        let features = |role: UserRole| -> Vec<String> {
+            serde_json::from_value(
+                hello(role, serde_json::Value::Null).unwrap()["features"].clone(),
+            )
+            .unwrap()
+        };
+
+        let stk_features = features(UserRole::Stakeholder);
+        let man_features = features(UserRole::Manager);
+        let stkman_features = features(UserRole::ManagerStakeholder);
+        for common in &[
+            "version_negotiation",
+            "audit_log",
+            "wallet_rotation",
+            "wallet_audit",
+        ] {
+            assert!(stk_features.iter().any(|f| f == common));
+            assert!(man_features.iter().any(|f| f == common));
+        }
+        for stk_only in &["batch_revocations", "watchtower_data"] {
+            assert!(stk_features.iter().any(|f| f == stk_only));
+            assert!(man_features.iter().all(|f| f != stk_only));
+        }
+        for man_only in &["spend_proposals", "cpfp_reserve", "vault_migration"] {
+            assert!(man_features.iter().any(|f| f == man_only));
+            assert!(stk_features.iter().all(|f| f != man_only));
+        }
+        assert_eq!(
+            stkman_features,
+            FEATURES
+                .iter()
+                .map(|f| f.name.to_string())
+                .collect::<Vec<_>>()
+        );
+
+        // We can require an older or the current version, but not a more recent one
+        let res = hello(UserRole::Manager, serde_json::json!(api_version())).unwrap();
+        assert_eq!(res["api_version"], serde_json::json!(api_version()));
+        assert!(hello(UserRole::Manager, serde_json::json!("0.9.12")).is_ok());
+        let (major, minor, _) = API_VERSION;
+        for newer in &[
+            format!("{}.{}.0", major, minor + 1),
+            format!("{}.0.0", major + 1),
+        ] {
+            assert_eq!(
+                hello(UserRole::Manager, serde_json::json!(newer)),
+                Err(ErrorCode::API_VERSION_ERROR as i64)
+            );
+        }
+        assert_eq!(
+            hello(UserRole::Manager, serde_json::json!("1.0")),
+            Err(-32602)
+        );
+    }
+
+    // The calls are timed by method, and their stats can be reset
+    #[test]
+    fn rpc_stats() {
+        let datadir = test_datadir();
+        let metadata =
+            JsonRpcMetaData::new(dummy_rpcutil(datadir.clone(), UserRole::ManagerStakeholder));
+        let io = jsonrpc_io_handler();
+        let getrpcstats = |reset: bool| -> serde_json::Value {
+            let req = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "getrpcstats",
+                "params": [reset],
+            });
+            let resp = metadata.timed("getrpcstats", || {
+                io.handle_request_sync(&req.to_string(), metadata.clone())
+                    .expect("Not a notification")
+            });
+            serde_json::from_str::<serde_json::Value>(&resp).unwrap()["result"].clone()
+        };
+
+        // An artificially slow handler, waiting on the database
+        for _ in 0..3 {
+            metadata.timed("slowcall", || {
+                let _timer = phase_timer(Phase::Database);
+                thread::sleep(Duration::from_millis(30));
+            });
+        }
+        let stats = getrpcstats(false);
+        let slowcall = &stats["methods"][0];
+        assert_eq!(slowcall["method"], "slowcall");
+        assert_eq!(slowcall["calls"], 3);
+        for latency in &["p50_us", "p95_us", "max_us"] {
+            assert!(slowcall[latency].as_u64().unwrap() >= 30_000);
+        }
+
+        // The previous call to 'getrpcstats' was recorded too
+        let stats = getrpcstats(true);
+        assert_eq!(stats["methods"][0]["method"], "getrpcstats");
+        assert_eq!(stats["methods"][0]["calls"], 1);
+        assert_eq!(stats["methods"][1]["method"], "slowcall");
+
+        // Only the one resetting them since
+        let stats = getrpcstats(false);
+        let methods = stats["methods"].as_array().unwrap();
+        assert_eq!(methods.len(), 1);
+        assert_eq!(methods[0]["method"], "getrpcstats");
+
+        fs::remove_dir_all(&datadir).unwrap();
+    }
 }
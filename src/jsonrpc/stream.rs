@@ -0,0 +1,361 @@
+//! Large results, such as the vaults of a deployment with tens of thousands of them, are
+//! streamed on the RPC socket instead of being entirely serialized in memory before being
+//! written. The handler serializes the result into chunks as it computes it, and the server loop
+//! writes them to the socket as they come. They are passed through a bounded channel, so that a
+//! slow client makes the handler wait instead of the chunks piling up: a few of them are in
+//! memory at any time, whatever the size of the response. The bytes on the wire are the same
+//! JSONRPC response.
+
+use crate::commands::CommandError;
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use jsonrpc_core::Id;
+use serde::Serialize;
+
+// The size of the chunks a streamed response is sent in
+const CHUNK_SIZE: usize = 16 * 1024;
+
+// How many chunks may be waiting to be written to the socket
+const MAX_PENDING_CHUNKS: usize = 4;
+
+// How long we wait for the client to read the response before giving up on it. The handler may
+// hold the lock on our state in the meantime.
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A part of a streamed response
+#[derive(Debug)]
+pub enum Chunk {
+    Data(Vec<u8>),
+    /// The response was entirely sent. A stream closed without it was cut short.
+    End,
+}
+
+/// A response waiting to be written on the socket
+#[derive(Debug)]
+pub enum PendingResponse {
+    /// Entirely serialized
+    Bytes(Vec<u8>),
+    /// Being streamed by the handler
+    Stream(mpsc::Receiver<Chunk>),
+}
+
+/// The responses to be written on a connection, in order
+pub type ResponseQueue = Arc<RwLock<VecDeque<PendingResponse>>>;
+
+/// The response to a call on the socket, which the handler may stream instead of returning the
+/// result.
+#[derive(Debug)]
+pub struct ResponseStream {
+    id: Id,
+    queue: ResponseQueue,
+    started: AtomicBool,
+    // The handler is done sending chunks
+    ended: AtomicBool,
+}
+
+impl ResponseStream {
+    pub fn new(id: Id, queue: ResponseQueue) -> Self {
+        Self {
+            id,
+            queue,
+            started: AtomicBool::new(false),
+            ended: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the handler started streaming the response, in which case the result it returns
+    /// must not be sent.
+    pub fn started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    /// Whether the handler is in the middle of streaming the response, and may be waiting for
+    /// the server loop to write the chunks out.
+    pub fn streaming(&self) -> bool {
+        self.started() && !self.ended.load(Ordering::SeqCst)
+    }
+
+    /// Stream the result of the call, as written by `write_result`. If it fails before anything
+    /// was sent the error is returned for the handler to respond with it, otherwise the response
+    /// is cut short.
+    pub fn result<T, F>(&self, write_result: F) -> Result<T, CommandError>
+    where
+        F: FnOnce(&mut ChunkWriter<'_>) -> Result<T, CommandError>,
+    {
+        let mut out = ChunkWriter::new(self);
+        out.write_all(br#"{"jsonrpc":"2.0","result":"#)
+            .map_err(CommandError::Stream)?;
+        let res = write_result(&mut out)?;
+        out.write_all(br#","id":"#)
+            .and_then(|_| serde_json::to_writer(&mut out, &self.id).map_err(io::Error::from))
+            .and_then(|_| out.write_all(b"}"))
+            .and_then(|_| out.finish())
+            .map_err(CommandError::Stream)?;
+
+        Ok(res)
+    }
+
+    /// Stream `{"<field>": [<rows>]}` as the result of the call, the rows being pushed by
+    /// `push_rows` as they are computed.
+    pub fn rows<F>(&self, field: &str, push_rows: F) -> Result<(), CommandError>
+    where
+        F: FnOnce(&mut RowWriter<'_, '_>) -> Result<(), CommandError>,
+    {
+        self.result(|out| {
+            out.write_all(b"{")
+                .and_then(|_| serde_json::to_writer(&mut *out, field).map_err(io::Error::from))
+                .and_then(|_| out.write_all(b":["))
+                .map_err(CommandError::Stream)?;
+            let mut rows = RowWriter { out, empty: true };
+            push_rows(&mut rows)?;
+            rows.out.write_all(b"]}").map_err(CommandError::Stream)
+        })
+    }
+}
+
+/// Sends what's written to it in chunks to the server loop.
+pub struct ChunkWriter<'a> {
+    stream: &'a ResponseStream,
+    sender: mpsc::SyncSender<Chunk>,
+    // Until it's handed to the server loop, along with the first chunk
+    receiver: Option<mpsc::Receiver<Chunk>>,
+    buf: Vec<u8>,
+}
+
+impl<'a> ChunkWriter<'a> {
+    fn new(stream: &'a ResponseStream) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(MAX_PENDING_CHUNKS);
+        Self {
+            stream,
+            sender,
+            receiver: Some(receiver),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    fn send(&mut self, chunk: Chunk) -> io::Result<()> {
+        if let Some(receiver) = self.receiver.take() {
+            self.stream
+                .queue
+                .write()
+                .unwrap()
+                .push_back(PendingResponse::Stream(receiver));
+            self.stream.started.store(true, Ordering::SeqCst);
+        }
+
+        let deadline = Instant::now() + STALL_TIMEOUT;
+        let mut chunk = chunk;
+        loop {
+            match self.sender.try_send(chunk) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::TrySendError::Full(c)) => {
+                    if Instant::now() > deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "The client is not reading the response",
+                        ));
+                    }
+                    chunk = c;
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "The client went away",
+                    ))
+                }
+            }
+        }
+    }
+
+    fn send_buf(&mut self) -> io::Result<()> {
+        let data = mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.send(Chunk::Data(data))
+    }
+
+    // Send what's left and mark the end of the response
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.send_buf()?;
+        }
+        self.send(Chunk::End)
+    }
+}
+
+impl Drop for ChunkWriter<'_> {
+    fn drop(&mut self) {
+        self.stream.ended.store(true, Ordering::SeqCst);
+    }
+}
+
+impl io::Write for ChunkWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send_buf()?;
+        }
+        Ok(data.len())
+    }
+
+    // Chunks are sent once full, or at the end of the response
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the rows of a JSON array, one at a time.
+pub struct RowWriter<'a, 'b> {
+    out: &'a mut ChunkWriter<'b>,
+    empty: bool,
+}
+
+impl RowWriter<'_, '_> {
+    pub fn push<T: Serialize>(&mut self, row: &T) -> Result<(), CommandError> {
+        if !self.empty {
+            self.out.write_all(b",").map_err(CommandError::Stream)?;
+        }
+        self.empty = false;
+        serde_json::to_writer(&mut *self.out, row).map_err(|e| CommandError::Stream(e.into()))
+    }
+}
+
+/// Writes what's written to it as the content of a JSON string, escaped. Each write must be
+/// valid UTF-8.
+pub struct JsonStringWriter<W: io::Write>(pub W);
+
+impl<W: io::Write> io::Write for JsonStringWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let s =
+            std::str::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let quoted = serde_json::to_string(s).expect("Serializing a str");
+        self.0.write_all(quoted[1..quoted.len() - 1].as_bytes())?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Chunk, JsonStringWriter, PendingResponse, ResponseQueue, ResponseStream, CHUNK_SIZE,
+    };
+    use crate::commands::CommandError;
+
+    use std::{
+        collections::VecDeque,
+        io::{self, Write},
+        sync::{mpsc, Arc, RwLock},
+    };
+
+    fn new_queue() -> ResponseQueue {
+        Arc::new(RwLock::new(VecDeque::new()))
+    }
+
+    fn pop_stream(queue: &ResponseQueue) -> mpsc::Receiver<Chunk> {
+        match queue.write().unwrap().pop_front() {
+            Some(PendingResponse::Stream(receiver)) => receiver,
+            r => panic!("Not a streamed response: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn stream_errors() {
+        let queue = new_queue();
+        let large_row = "a".repeat(CHUNK_SIZE);
+
+        // Failing before anything was sent, the handler can respond with the error
+        let stream = ResponseStream::new(jsonrpc_core::Id::Num(1), queue.clone());
+        stream
+            .rows("rows", |rows| {
+                rows.push(&"a")?;
+                Err(CommandError::Race)
+            })
+            .unwrap_err();
+        assert!(!stream.started());
+        assert!(queue.read().unwrap().is_empty());
+
+        // Failing afterward, the response is cut short
+        let stream = ResponseStream::new(jsonrpc_core::Id::Num(2), queue.clone());
+        stream
+            .rows("rows", |rows| {
+                rows.push(&large_row)?;
+                Err(CommandError::Race)
+            })
+            .unwrap_err();
+        assert!(stream.started());
+        let receiver = pop_stream(&queue);
+        match receiver.recv().unwrap() {
+            Chunk::Data(data) => {
+                assert!(data.starts_with(br#"{"jsonrpc":"2.0","result":{"rows":["aaa"#))
+            }
+            Chunk::End => panic!("Nothing was streamed"),
+        }
+        assert!(receiver.recv().is_err());
+
+        // The client went away while we were streaming
+        let stream = ResponseStream::new(jsonrpc_core::Id::Num(3), queue.clone());
+        let res = stream.rows("rows", |rows| {
+            rows.push(&large_row)?;
+            queue.write().unwrap().clear();
+            rows.push(&large_row)
+        });
+        match res {
+            Err(CommandError::Stream(e)) => assert_eq!(e.kind(), io::ErrorKind::BrokenPipe),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn streamed_string() {
+        let queue = new_queue();
+        let stream = ResponseStream::new(jsonrpc_core::Id::Str("a".to_string()), queue.clone());
+        let rows = stream
+            .result(|out| {
+                out.write_all(br#"{"csv":""#)
+                    .map_err(CommandError::Stream)?;
+                let mut csv = JsonStringWriter(&mut *out);
+                csv.write_all(b"a,\"b\"\r\n")
+                    .map_err(CommandError::Stream)?;
+                csv.write_all("\u{e9}\\\n".as_bytes())
+                    .map_err(CommandError::Stream)?;
+                out.write_all(br#"","rows":2}"#)
+                    .map_err(CommandError::Stream)?;
+                Ok(2)
+            })
+            .unwrap();
+        assert_eq!(rows, 2);
+
+        let receiver = pop_stream(&queue);
+        let mut resp = Vec::new();
+        while let Chunk::Data(data) = receiver.recv().unwrap() {
+            resp.extend(data);
+        }
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&resp).unwrap(),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "result": {"csv": "a,\"b\"\r\n\u{e9}\\\n", "rows": 2},
+                "id": "a",
+            })
+        );
+
+        // Not UTF-8
+        JsonStringWriter(Vec::new())
+            .write_all(&[0xff, 0xfe])
+            .unwrap_err();
+    }
+}
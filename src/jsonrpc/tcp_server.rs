@@ -0,0 +1,358 @@
+//! Here we serve the same JSONRPC interface over TCP, for deployments where the client can't
+//! access the UNIX socket (eg a GUI running on another machine).
+//! Connections are encrypted and authenticated by a Noise KK handshake using our static key: only
+//! the configured clients may connect.
+
+use crate::{communication::bind::relay, jsonrpc::api::JsonRpcMetaData};
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use jsonrpc_core::MethodCall;
+use revault_net::{
+    noise::{PublicKey as NoisePubKey, SecretKey as NoisePrivKey},
+    transport::KKTransport,
+};
+
+// How long we wait for a connection before checking whether we were told to stop.
+const ACCEPT_POLL_TIMEOUT_MS: libc::c_int = 500;
+
+// How long we wait before accepting connections again after failing to.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// The maximum number of connections, being authenticated or served, at any time. Any further
+// connection is closed right away.
+const MAX_CONNECTIONS: usize = 16;
+
+// How long a peer may stay silent during the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long a peer may take to read what we send it.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bind to the TCP address at `addr`
+pub fn tcp_rpcserver_setup(addr: SocketAddr) -> Result<TcpListener, io::Error> {
+    TcpListener::bind(addr)
+}
+
+// The method of this request, if it's a single valid call
+fn method_name(req: &str) -> Option<String> {
+    serde_json::from_str::<MethodCall>(req)
+        .ok()
+        .map(|call| call.method)
+}
+
+// Handle the requests of an authenticated client until it disconnects.
+fn handle_client(
+    mut transport: KKTransport,
+    jsonrpc_io: Arc<jsonrpc_core::MetaIoHandler<JsonRpcMetaData>>,
+    metadata: JsonRpcMetaData,
+    rpc_socket: PathBuf,
+) {
+    let metadata = metadata.with_peer_noise_key(transport.remote_static());
+    loop {
+        let req = match transport.read() {
+            Ok(req) => req,
+            Err(e) => {
+                log::debug!("TCP JSONRPC client disconnected: '{}'", e);
+                return;
+            }
+        };
+        log::trace!("Got JSONRPC request over TCP '{:?}'", req);
+
+        // An invalid utf-8 request will be answered with a parse error.
+        let req = String::from_utf8_lossy(&req);
+        let handle = || jsonrpc_io.handle_request_sync(&req, metadata.clone());
+        // Only single calls are timed, like over the UNIX socket.
+        let resp = match method_name(&req) {
+            Some(method) => metadata.timed(&method, handle),
+            None => handle(),
+        };
+        if let Some(resp) = resp {
+            if let Err(e) = transport.write(resp.as_bytes()) {
+                log::error!("Error writing response to TCP JSONRPC client: '{}'", e);
+                return;
+            }
+        }
+
+        // We were told to stop. The UNIX socket loop only checks for it upon a new event, so
+        // give it one.
+        if metadata.is_shutdown() {
+            if let Err(e) = UnixStream::connect(&rpc_socket) {
+                log::error!("Could not wake up the JSONRPC server for stopping: '{}'", e);
+            }
+            return;
+        }
+    }
+}
+
+// Wait for a connection to be pending on the listener, at most for ACCEPT_POLL_TIMEOUT_MS.
+fn connection_pending(listener: &TcpListener) -> Result<bool, io::Error> {
+    let mut pollfd = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut pollfd, 1, ACCEPT_POLL_TIMEOUT_MS) } {
+        n if n < 0 => {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(e)
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(pollfd.revents & libc::POLLIN != 0),
+    }
+}
+
+// One of the MAX_CONNECTIONS, given back when dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn take(connections: &Arc<AtomicUsize>) -> Option<ConnectionSlot> {
+        // Only the listener loop takes slots, so it can't overshoot.
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ConnectionSlot(connections.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Perform the Noise handshake on this connection. revault_net only performs it on the connections
+// it accepts itself, so we relay this one to it through the loopback. As for the connections to
+// the servers, the relay only ever sees ciphertext and a local process racing us to the relay
+// listener can only make this connection fail.
+fn handshake(
+    stream: TcpStream,
+    noise_secret: &NoisePrivKey,
+    clients: &[NoisePubKey],
+) -> Result<KKTransport, revault_net::Error> {
+    // Don't let a peer stall the handshake, nor stop reading our responses.
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    let relay_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let relay_addr = relay_listener.local_addr()?;
+    let remote = stream.try_clone()?;
+    thread::spawn(move || {
+        if let Err(e) = TcpStream::connect(relay_addr).and_then(|local| relay(local, remote)) {
+            log::debug!("Relaying TCP JSONRPC connection: '{}'", e);
+        }
+    });
+    let transport = KKTransport::accept(&relay_listener, noise_secret, clients)?;
+
+    // An authenticated client may stay idle between its requests.
+    stream.set_read_timeout(None)?;
+    Ok(transport)
+}
+
+// Authenticate a connection and serve the client if it's one of ours. This happens in a thread
+// of its own: a peer stalling the handshake only holds its own connection.
+fn accept_client(
+    stream: TcpStream,
+    _slot: ConnectionSlot,
+    noise_secret: Arc<NoisePrivKey>,
+    clients: Arc<Vec<NoisePubKey>>,
+    jsonrpc_io: Arc<jsonrpc_core::MetaIoHandler<JsonRpcMetaData>>,
+    metadata: JsonRpcMetaData,
+    rpc_socket: PathBuf,
+) {
+    match handshake(stream, &noise_secret, &clients) {
+        Ok(transport) => handle_client(transport, jsonrpc_io, metadata, rpc_socket),
+        Err(e) => log_event!(
+            log::Level::Warn,
+            "connection_failure",
+            peer = "rpc_client",
+            error = e;
+            "Refused TCP JSONRPC connection: '{}'",
+            e
+        ),
+    }
+}
+
+/// The main loop for the JSONRPC interface over TCP. Accepts connections from the given
+/// `clients` and serves each of them in its own thread, until we are told to stop.
+pub fn tcp_rpcserver_loop(
+    listener: TcpListener,
+    jsonrpc_io: jsonrpc_core::MetaIoHandler<JsonRpcMetaData>,
+    metadata: JsonRpcMetaData,
+    noise_secret: NoisePrivKey,
+    clients: Vec<NoisePubKey>,
+    rpc_socket: PathBuf,
+) {
+    let jsonrpc_io = Arc::new(jsonrpc_io);
+    let (noise_secret, clients) = (Arc::new(noise_secret), Arc::new(clients));
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    // A pending connection may be gone by the time we accept it
+    if let Err(e) = listener.set_nonblocking(true) {
+        log::error!("Error setting up the JSONRPC TCP listener: '{}'", e);
+        return;
+    }
+    log::info!(
+        "JSONRPC TCP server started on '{:?}'.",
+        listener.local_addr()
+    );
+    while !metadata.is_shutdown() {
+        match connection_pending(&listener) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                log::error!("Error polling the JSONRPC TCP listener: '{}'", e);
+                return;
+            }
+        }
+
+        let (stream, peer) = match listener.accept() {
+            Ok(conn) => conn,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => {
+                log::error!("Error accepting JSONRPC TCP connection: '{}'", e);
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        // Dropping the stream closes the connection.
+        let slot = match ConnectionSlot::take(&connections) {
+            Some(slot) => slot,
+            None => {
+                log_event!(
+                    log::Level::Warn,
+                    "connection_failure",
+                    peer = "rpc_client",
+                    ip = peer.ip(),
+                    error = "too many connections";
+                    "Refused TCP JSONRPC connection from '{}': already {} connections",
+                    peer,
+                    MAX_CONNECTIONS
+                );
+                continue;
+            }
+        };
+
+        let (secret, keys) = (noise_secret.clone(), clients.clone());
+        let (io, meta, socket) = (jsonrpc_io.clone(), metadata.clone(), rpc_socket.clone());
+        thread::spawn(move || accept_client(stream, slot, secret, keys, io, meta, socket));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tcp_rpcserver_loop, tcp_rpcserver_setup, HANDSHAKE_TIMEOUT, MAX_CONNECTIONS};
+    use crate::{
+        jsonrpc::{api::JsonRpcMetaData, server::jsonrpc_io_handler},
+        utils::test_utils::{dummy_rpcutil, test_datadir, UserRole},
+    };
+
+    use revault_net::{
+        sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::gen_keypair, transport::KKTransport,
+    };
+    use std::{
+        fs,
+        io::{self, Read},
+        net::TcpStream,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn tcp_authenticated_clients() {
+        let datadir = test_datadir();
+        let control = dummy_rpcutil(datadir.clone(), UserRole::ManagerStakeholder);
+        let (noise_secret, server_pubkey, rpc_socket) = {
+            let revaultd = control.revaultd.read().unwrap();
+            (
+                revaultd.noise_secret.clone(),
+                revaultd.noise_pubkey(),
+                revaultd.rpc_socket_file(),
+            )
+        };
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let (_, intruder_privkey) = gen_keypair();
+
+        let listener = tcp_rpcserver_setup("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metadata = JsonRpcMetaData::new(control);
+        let tcp_metadata = metadata.clone();
+        let server_thread = thread::spawn(move || {
+            tcp_rpcserver_loop(
+                listener,
+                jsonrpc_io_handler(),
+                tcp_metadata,
+                noise_secret,
+                vec![client_pubkey],
+                rpc_socket,
+            )
+        });
+
+        // Past the limit, connections are closed right away
+        let mut stallers: Vec<TcpStream> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        thread::sleep(Duration::from_millis(200));
+        let mut refused = TcpStream::connect(addr).unwrap();
+        refused
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        match refused.read(&mut [0; 1]) {
+            Ok(0) => {}
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+            res => panic!("Connection past the limit was not closed: {:?}", res),
+        }
+
+        // A peer that never completes the handshake doesn't hold the others
+        let mut staller = stallers.pop().unwrap();
+        drop(stallers);
+        thread::sleep(Duration::from_millis(200));
+
+        // A client whose key was not whitelisted can't even complete the handshake
+        assert!(KKTransport::connect(addr, &intruder_privkey, &server_pubkey).is_err());
+
+        // An authorized one can use the interface
+        let mut transport =
+            KKTransport::connect(addr, &client_privkey, &server_pubkey).expect("Authorized client");
+        transport
+            .write(br#"{"jsonrpc": "2.0", "id": 0, "method": "help", "params": []}"#)
+            .unwrap();
+        let resp: serde_json::Value = serde_json::from_slice(&transport.read().unwrap()).unwrap();
+        assert_eq!(resp["id"], 0);
+        assert!(resp["result"]["stop"].is_array());
+
+        // Including on the same connection, and with invalid requests
+        transport.write(b"{\"jsonrpc\": \"2.0\", \"id\"").unwrap();
+        let resp: serde_json::Value = serde_json::from_slice(&transport.read().unwrap()).unwrap();
+        assert_eq!(resp["error"]["code"], -32700);
+
+        // And is eventually disconnected
+        staller
+            .set_read_timeout(Some(HANDSHAKE_TIMEOUT + Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(staller.read(&mut [0; 1]).unwrap(), 0);
+
+        // The listener stops even with no new connection to wake it up
+        metadata.shutdown();
+        server_thread.join().unwrap();
+
+        fs::remove_dir_all(&datadir).unwrap();
+    }
+}
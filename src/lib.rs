@@ -1,40 +1,76 @@
 pub use revault_net;
 pub use revault_tx;
 
+// Must come first for the logging macros to be available in the other modules.
+#[macro_use]
+pub mod logger;
+
+mod alerts;
+pub mod amount;
 mod bitcoind;
+pub mod buildinfo;
+mod clock;
 pub mod commands;
 mod communication;
 pub mod config;
+mod daemonize;
 mod database;
+pub mod derivation;
+mod diskspace;
+mod endpoint;
+mod hooks;
+mod indexallocator;
 #[cfg(all(not(windows), feature = "jsonrpc_server"))]
 mod jsonrpc;
 mod revaultd;
+mod rpcstats;
+pub mod schedule;
+mod sdnotify;
+pub mod setup;
 mod sigfetcher;
+mod statemachine;
 mod threadmessages;
 mod utils;
 
 // FIXME: make it an integer
 pub const VERSION: &str = "0.3.1";
 
-pub use crate::revaultd::{CpfpKeyError, DatadirError, NoiseKeyError};
+pub use crate::revaultd::{
+    CpfpKeyError, DatadirError, NoiseKeyError, ScriptLimitError, ScriptTypeError,
+};
+#[cfg(feature = "benches")]
+#[doc(hidden)]
+pub use crate::utils::bench_utils;
+#[cfg(feature = "noise_test_vectors")]
+#[doc(hidden)]
+pub use crate::utils::noise_vectors;
 use crate::{
     bitcoind::{bitcoind_main_loop, start_bitcoind, BitcoindError},
-    config::Config,
+    buildinfo::build_info,
+    commands::locks::ResourceLocks,
+    config::{noise_pubkey_fingerprint, Config},
+    daemonize::{daemonize, Readiness},
     database::{actions::setup_db, DatabaseError},
+    logger::{LogLevels, LogLevelsHandle},
     revaultd::RevaultD,
+    sdnotify::Heartbeat,
     sigfetcher::signature_fetcher_loop,
-    threadmessages::{BitcoindSender, BitcoindThread, SigFetcherSender, SigFetcherThread},
+    statemachine::state_machine_loop,
+    threadmessages::{
+        BitcoindSender, BitcoindThread, SigFetcherSender, SigFetcherThread, StateMachineSender,
+        StateMachineThread,
+    },
 };
 use revault_tx::bitcoin::hashes::hex::ToHex;
 
 use std::{
-    error, fmt, io, panic, process,
-    sync::{mpsc, Arc, RwLock},
+    error, fmt, fs, io,
+    net::IpAddr,
+    panic, process,
+    sync::{mpsc, Arc, Mutex, RwLock},
     thread,
 };
 
-use daemonize_simple::Daemonize;
-
 // A panic in any thread should stop the main thread, and print the panic.
 fn setup_panic_hook() {
     panic::set_hook(Box::new(move |panic_info| {
@@ -73,6 +109,10 @@ pub enum StartupError {
     Datadir(DatadirError),
     Db(DatabaseError),
     Bitcoind(BitcoindError),
+    ScriptLimit(ScriptLimitError),
+    ScriptType(ScriptTypeError),
+    /// We can't connect to the servers from the configured local address
+    BindAddress(IpAddr, io::Error),
 }
 
 impl fmt::Display for StartupError {
@@ -84,6 +124,13 @@ impl fmt::Display for StartupError {
             Self::Datadir(e) => write!(f, "{}", e),
             Self::Db(e) => write!(f, "Database error when starting revaultd: '{}'", e),
             Self::Bitcoind(e) => write!(f, "Bitcoind error when starting revaultd: '{}'", e),
+            Self::ScriptLimit(e) => write!(f, "{}", e),
+            Self::ScriptType(e) => write!(f, "{}", e),
+            Self::BindAddress(addr, e) => write!(
+                f,
+                "Can't connect to the servers from 'bind_address' '{}': '{}'",
+                addr, e
+            ),
         }
     }
 }
@@ -125,11 +172,25 @@ impl From<NoiseKeyError> for StartupError {
     }
 }
 
+impl From<ScriptLimitError> for StartupError {
+    fn from(e: ScriptLimitError) -> Self {
+        Self::ScriptLimit(e)
+    }
+}
+
+impl From<ScriptTypeError> for StartupError {
+    fn from(e: ScriptTypeError) -> Self {
+        Self::ScriptType(e)
+    }
+}
+
 #[derive(Clone)]
 pub struct DaemonControl {
     revaultd: Arc<RwLock<RevaultD>>,
     bitcoind_conn: BitcoindSender,
     sigfetcher_conn: SigFetcherSender,
+    statemachine_conn: StateMachineSender,
+    resource_locks: ResourceLocks,
 }
 
 impl DaemonControl {
@@ -137,11 +198,14 @@ impl DaemonControl {
         revaultd: Arc<RwLock<RevaultD>>,
         bitcoind_conn: BitcoindSender,
         sigfetcher_conn: SigFetcherSender,
+        statemachine_conn: StateMachineSender,
     ) -> Self {
         Self {
             revaultd,
             bitcoind_conn,
             sigfetcher_conn,
+            statemachine_conn,
+            resource_locks: ResourceLocks::new(),
         }
     }
 
@@ -151,26 +215,69 @@ impl DaemonControl {
         self.sigfetcher_conn.shutdown();
     }
 
-    /// Start and bind the server to the configured UNIX socket
+    /// Apply the settings of this new configuration that can be changed without restarting. For
+    /// now, only the spending schedule and the log levels.
+    pub fn reload_config(&self, config: &Config) {
+        let mut revaultd = self.revaultd.write().unwrap();
+        revaultd.spending_schedule = config
+            .manager_config
+            .as_ref()
+            .and_then(|config| config.spending_schedule.clone());
+        revaultd
+            .log_levels
+            .reconfigure(LogLevels::new(config.log_level, config.log_modules.clone()));
+        log::info!(
+            "Reloaded configuration. Spending schedule: {:?}",
+            revaultd.spending_schedule
+        );
+    }
+
+    /// Start and bind the server to the configured UNIX socket, unless the service manager
+    /// passed us one already (socket activation).
     #[cfg(all(not(windows), feature = "jsonrpc_server"))]
     pub fn rpc_server_setup(&self) -> Result<jsonrpc::server::UnixListener, io::Error> {
+        if let Some(listener) = jsonrpc::server::activated_listener() {
+            return Ok(listener);
+        }
+
         let socket_file = self.revaultd.read().unwrap().rpc_socket_file();
         jsonrpc::server::rpcserver_setup(socket_file)
     }
+
+    /// Bind to the configured TCP address, if any
+    #[cfg(all(not(windows), feature = "jsonrpc_server"))]
+    pub fn tcp_rpc_server_setup(&self) -> Result<Option<std::net::TcpListener>, io::Error> {
+        self.revaultd
+            .read()
+            .unwrap()
+            .rpc_listen
+            .map(jsonrpc::tcp_server::tcp_rpcserver_setup)
+            .transpose()
+    }
 }
 
 pub struct DaemonHandle {
     pub control: DaemonControl,
     bitcoind_thread: thread::JoinHandle<()>,
     sigfetcher_thread: thread::JoinHandle<()>,
+    statemachine_thread: thread::JoinHandle<()>,
+    /// If we daemonized, to tell the process that started us once we are ready
+    readiness: Mutex<Option<Readiness>>,
 }
 
 impl DaemonHandle {
+    /// Check we could start the Revault daemon with this configuration, without starting it.
+    pub fn check_config(config: &Config) -> Result<(), StartupError> {
+        RevaultD::check_config(config)
+    }
+
     /// This starts the Revault daemon. Call `shutdown` to shut it down.
+    /// The `log_levels` are the ones the logger was set up with (see `logger::setup_logger`), for
+    /// the commands to change them at runtime.
     ///
     /// **Note**: we internally use threads, and set a panic hook. A downstream application must
     /// not overwrite this panic hook.
-    pub fn start(config: Config) -> Result<Self, StartupError> {
+    pub fn start(config: Config, log_levels: LogLevelsHandle) -> Result<Self, StartupError> {
         setup_panic_hook();
 
         // FIXME: should probably be from_db(), would allow us to not use Option members
@@ -178,45 +285,70 @@ impl DaemonHandle {
             log::error!("Error creating global state: {}", e);
             process::exit(1);
         });
+        revaultd.log_levels = log_levels;
+
+        // NOTE: it's safe to daemonize now, as we don't carry any open DB connection
+        // https://www.sqlite.org/howtocorrupt.html#_carrying_an_open_database_connection_across_a_fork_
+        // From now on the process that started us waits until we are either ready or failed.
+        let readiness = if revaultd.daemon {
+            log::info!("Daemonizing");
+            Some(daemonize(
+                &revaultd.data_dir,
+                &revaultd.log_file(),
+                revaultd.pid_file(),
+            )?)
+        } else {
+            None
+        };
+
+        match Self::start_threads(revaultd) {
+            Ok(mut handle) => {
+                handle.readiness = Mutex::new(readiness);
+                // Without the RPC server, there is nothing left to wait for.
+                #[cfg(not(all(not(windows), feature = "jsonrpc_server")))]
+                handle.startup_done()?;
+                Ok(handle)
+            }
+            Err(e) => {
+                if let Some(readiness) = readiness {
+                    readiness.failed(&e.to_string());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn start_threads(mut revaultd: RevaultD) -> Result<Self, StartupError> {
+        log::info!("Starting {}", build_info());
         log::info!(
-            "Using Noise static public key: '{}'",
-            revaultd.noise_pubkey().0.to_hex()
+            "Using Noise static public key: '{}' (fingerprint: '{}')",
+            revaultd.noise_pubkey().0.to_hex(),
+            noise_pubkey_fingerprint(&revaultd.noise_pubkey())
         );
         log::debug!(
             "Coordinator static public key: '{}'",
-            revaultd.coordinator_noisekey.0.to_hex()
+            revaultd.coordinators.main().noise_key.0.to_hex()
         );
+        if revaultd.read_only {
+            log::info!(
+                "Running in read-only mode: only monitoring, never broadcasting nor pushing \
+                 anything to the servers"
+            );
+        }
 
         // First and foremost
         log::info!("Setting up database");
+        sdnotify::status("Setting up database");
         setup_db(&mut revaultd)?;
 
         log::info!("Setting up bitcoind connection");
+        sdnotify::status("Setting up bitcoind connection");
         let bitcoind = start_bitcoind(&mut revaultd)?;
 
-        // NOTE: it's safe to daemonize now, as we don't carry any open DB connection
-        // https://www.sqlite.org/howtocorrupt.html#_carrying_an_open_database_connection_across_a_fork_
-        if revaultd.daemon {
-            log::info!("Daemonizing");
-            let log_file = revaultd.log_file();
-            let daemon = Daemonize {
-                // TODO: Make this configurable for inits
-                pid_file: Some(revaultd.pid_file()),
-                stdout_file: Some(log_file.clone()),
-                stderr_file: Some(log_file),
-                chdir: Some(revaultd.data_dir.clone()),
-                append: true,
-                ..Daemonize::default()
-            };
-            daemon.doit().unwrap_or_else(|e| {
-                // The panic hook will log::error
-                panic!("Error daemonizing: {}", e);
-            });
-        }
-
-        // We start two threads, the bitcoind one to poll bitcoind for chain updates,
-        // and the sigfetcher one to poll the coordinator for missing signatures
-        // for pre-signed transactions.
+        // We start three threads, the bitcoind one to poll bitcoind for chain updates,
+        // the sigfetcher one to poll the coordinator for missing signatures
+        // for pre-signed transactions, and the state machine one to apply the vaults' status
+        // transitions implied by the chain updates and the commands.
         // The RPC requests are handled in the main thread, which may send requests
         // to the others.
 
@@ -226,11 +358,44 @@ impl DaemonHandle {
         // The communication from us to the signature poller
         let (sigfetcher_tx, sigfetcher_rx) = mpsc::channel();
 
+        // The communication from us and the bitcoind thread to the state machine
+        let (statemachine_tx, statemachine_rx) = mpsc::channel();
+        let statemachine: StateMachineSender = statemachine_tx.into();
+
         let revaultd = Arc::new(RwLock::new(revaultd));
         let bit_revaultd = revaultd.clone();
+        let bit_statemachine = statemachine.clone();
+        // Used by the service manager's watchdog to check the poller is not stuck
+        let heartbeat = Heartbeat::new();
+        let bit_heartbeat = heartbeat.clone();
         let bitcoind_thread = thread::spawn(move || {
-            bitcoind_main_loop(bitcoind_rx, bit_revaultd, bitcoind)
-                .expect("Error in bitcoind main loop");
+            bitcoind_main_loop(
+                bitcoind_rx,
+                bit_revaultd,
+                bitcoind,
+                bit_statemachine,
+                bit_heartbeat,
+            )
+            .expect("Error in bitcoind main loop");
+        });
+        if let Some(interval) = sdnotify::watchdog_interval() {
+            log::info!(
+                "Pinging the service manager's watchdog every {:?}",
+                interval / 2
+            );
+            // Detached, it dies with the process.
+            thread::spawn(move || sdnotify::watchdog_loop(heartbeat, interval));
+        }
+
+        let statemachine_revaultd = revaultd.clone();
+        let statemachine_bitcoind: BitcoindSender = bitcoind_tx.clone().into();
+        let statemachine_thread = thread::spawn(move || {
+            state_machine_loop(
+                statemachine_rx,
+                statemachine_revaultd,
+                statemachine_bitcoind,
+            )
+            .expect("Error in state machine thread")
         });
 
         let sigfetcher_revaultd = revaultd.clone();
@@ -246,15 +411,42 @@ impl DaemonHandle {
         let bitcoind: BitcoindSender = bitcoind_tx.into();
         let sigfetcher: SigFetcherSender = sigfetcher_tx.into();
         Ok(Self {
-            control: DaemonControl::new(revaultd, bitcoind, sigfetcher),
+            control: DaemonControl::new(revaultd, bitcoind, sigfetcher, statemachine),
             bitcoind_thread,
             sigfetcher_thread,
+            statemachine_thread,
+            readiness: Mutex::new(None),
         })
     }
 
+    // If we daemonized, write our PID file and let the process that started us exit.
+    fn startup_done(&self) -> Result<(), io::Error> {
+        match self.readiness.lock().unwrap().take() {
+            Some(readiness) => readiness.ready(),
+            None => Ok(()),
+        }
+    }
+
+    // If we daemonized, let the process that started us exit with this error.
+    #[cfg(all(not(windows), feature = "jsonrpc_server"))]
+    fn startup_failed(&self, error: &io::Error) {
+        if let Some(readiness) = self.readiness.lock().unwrap().take() {
+            readiness.failed(&format!("Setting up the JSONRPC server: '{}'", error));
+        }
+    }
+
     // NOTE: this moves out the data as it should not be reused after shutdown
     /// Shut down the Revault daemon.
     pub fn shutdown(self) {
+        sdnotify::stopping();
+        {
+            let revaultd = self.control.revaultd.read().unwrap();
+            if revaultd.daemon {
+                fs::remove_file(revaultd.pid_file()).unwrap_or_else(|e| {
+                    log::warn!("Could not remove the PID file: '{}'", e);
+                });
+            }
+        }
         self.control.send_shutdown();
 
         self.bitcoind_thread
@@ -263,6 +455,12 @@ impl DaemonHandle {
         self.sigfetcher_thread
             .join()
             .expect("Joining sigfetcher thread");
+
+        // Only once the poller is gone, as it may still be sending events to it.
+        self.control.statemachine_conn.shutdown();
+        self.statemachine_thread
+            .join()
+            .expect("Joining state machine thread");
     }
 
     /// Start the JSONRPC server and listen for commands until we are stopped
@@ -270,7 +468,21 @@ impl DaemonHandle {
     pub fn rpc_server(&self) -> Result<(), io::Error> {
         log::info!("Starting JSONRPC server");
 
-        let socket = self.control.rpc_server_setup()?;
-        jsonrpc::server::rpcserver_loop(socket, self.control.clone())
+        let (socket, tcp_listener) = match self
+            .control
+            .rpc_server_setup()
+            .and_then(|socket| Ok((socket, self.control.tcp_rpc_server_setup()?)))
+        {
+            Ok(listeners) => listeners,
+            Err(e) => {
+                self.startup_failed(&e);
+                return Err(e);
+            }
+        };
+        // The database is set up, bitcoind passed the sanity checks and we are listening for
+        // commands.
+        sdnotify::ready();
+        self.startup_done()?;
+        jsonrpc::server::rpcserver_loop(socket, tcp_listener, self.control.clone())
     }
 }
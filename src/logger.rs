@@ -0,0 +1,426 @@
+//! Logger setup, and a helper to log the important events along with structured fields.
+//!
+//! Important events (vault status transitions, broadcasts, connection failures, ..) should be
+//! logged through the `log_event` macro. The fields it is given are appended to the message
+//! in the human format, and exposed as a separate `fields` object in the JSON format.
+//!
+//! The levels at which messages are logged may be changed at runtime through a
+//! [`LogLevelsHandle`], for instance to debug a live incident without restarting.
+
+use crate::config::LogFormat;
+
+use std::{
+    cell::RefCell,
+    cmp,
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time,
+};
+
+use serde_json::{json, Map, Value};
+
+thread_local! {
+    // The fields of the event being logged, if any. They are set by `log_event` for the duration
+    // of the `log` call, which formats the record in the calling thread.
+    static EVENT_FIELDS: RefCell<Option<EventFields>> = RefCell::new(None);
+}
+
+/// The name of an event and its structured fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventFields {
+    pub event: &'static str,
+    pub fields: Vec<(&'static str, String)>,
+}
+
+/// Log an important event with structured fields. Use as:
+/// `log_event!(log::Level::Info, "spend_broadcast", txid = txid; "Broadcasted Spend '{}'", txid)`
+macro_rules! log_event {
+    ($lvl:expr, $event:expr, $($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        $crate::logger::with_event_fields(
+            $crate::logger::EventFields {
+                event: $event,
+                fields: vec![$((stringify!($key), $value.to_string())),+],
+            },
+            || log::log!($lvl, $($arg)+),
+        )
+    };
+}
+
+#[doc(hidden)]
+pub fn with_event_fields<F: FnOnce()>(fields: EventFields, log_fn: F) {
+    EVENT_FIELDS.with(|f| *f.borrow_mut() = Some(fields));
+    log_fn();
+    EVENT_FIELDS.with(|f| *f.borrow_mut() = None);
+}
+
+fn current_event_fields() -> Option<EventFields> {
+    EVENT_FIELDS.with(|f| f.borrow().clone())
+}
+
+fn unix_timestamp() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_else(|e| {
+            println!("Can't get time since epoch: '{}'. Using a dummy value.", e);
+            time::Duration::from_secs(0)
+        })
+        .as_secs()
+}
+
+fn human_line(
+    timestamp: u64,
+    record: &log::Record,
+    message: &str,
+    event: Option<&EventFields>,
+) -> String {
+    let mut line = format!(
+        "[{}][{}][{}] {}",
+        timestamp,
+        record.target(),
+        record.level(),
+        message
+    );
+    if let Some(event) = event {
+        line.push_str(&format!(" (event={}", event.event));
+        for (key, value) in event.fields.iter() {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+        line.push(')');
+    }
+
+    line
+}
+
+fn json_line(
+    timestamp: u64,
+    record: &log::Record,
+    message: &str,
+    event: Option<&EventFields>,
+) -> String {
+    let mut obj = json!({
+        "timestamp": timestamp,
+        "level": record.level().to_string(),
+        "module": record.target(),
+        "message": message,
+    });
+    if let Some(event) = event {
+        let fields: Map<String, Value> = event
+            .fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.clone())))
+            .collect();
+        obj["event"] = Value::String(event.event.to_string());
+        obj["fields"] = Value::Object(fields);
+    }
+
+    // Serializing a Value can't fail, and escapes newlines so we always output a single line.
+    obj.to_string()
+}
+
+/// The levels at which to log messages: a global one, and overrides for some modules (along
+/// with their submodules) by module path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLevels {
+    pub level: log::LevelFilter,
+    pub modules: HashMap<String, log::LevelFilter>,
+}
+
+impl LogLevels {
+    pub fn new(level: log::LevelFilter, modules: HashMap<String, log::LevelFilter>) -> Self {
+        Self { level, modules }
+    }
+
+    /// The level at which to log the messages of this module. The most specific override
+    /// applies.
+    pub fn level_for(&self, target: &str) -> log::LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target == module.as_str()
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+
+    // The most verbose of all the levels
+    fn max_level(&self) -> log::LevelFilter {
+        self.modules
+            .values()
+            .fold(self.level, |max, l| cmp::max(max, *l))
+    }
+}
+
+#[derive(Debug)]
+struct LogLevelsState {
+    configured: LogLevels,
+    current: LogLevels,
+    revert_at: Option<u64>,
+}
+
+/// The levels in use by the logger, which may be changed at runtime. Temporary changes are
+/// reverted to the configured levels by calling `expire` periodically.
+#[derive(Debug, Clone)]
+pub struct LogLevelsHandle(Arc<RwLock<LogLevelsState>>);
+
+impl LogLevelsHandle {
+    pub fn new(configured: LogLevels) -> Self {
+        Self(Arc::new(RwLock::new(LogLevelsState {
+            current: configured.clone(),
+            configured,
+            revert_at: None,
+        })))
+    }
+
+    /// Whether to log the messages with this metadata
+    pub fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.0.read().unwrap().current.level_for(metadata.target())
+    }
+
+    /// The levels in use, and the timestamp at which they'll be reverted to the configured
+    /// ones if they were changed temporarily.
+    pub fn current(&self) -> (LogLevels, Option<u64>) {
+        let state = self.0.read().unwrap();
+        (state.current.clone(), state.revert_at)
+    }
+
+    /// Use these levels, until the `revert_at` timestamp if given. Returns the previous levels
+    /// and when they were to be reverted.
+    pub fn set(&self, levels: LogLevels, revert_at: Option<u64>) -> (LogLevels, Option<u64>) {
+        let mut state = self.0.write().unwrap();
+        log::set_max_level(levels.max_level());
+        (
+            std::mem::replace(&mut state.current, levels),
+            std::mem::replace(&mut state.revert_at, revert_at),
+        )
+    }
+
+    /// Revert to the configured levels if they were temporarily changed until now or earlier.
+    /// Returns whether they were.
+    pub fn expire(&self, now: u64) -> bool {
+        let mut state = self.0.write().unwrap();
+        match state.revert_at {
+            Some(revert_at) if revert_at <= now => {
+                state.current = state.configured.clone();
+                state.revert_at = None;
+                log::set_max_level(state.current.max_level());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Change the configured levels. They are used right away, unless the current ones were
+    /// changed temporarily in which case they will be reverted to these.
+    pub fn reconfigure(&self, configured: LogLevels) {
+        let mut state = self.0.write().unwrap();
+        if state.revert_at.is_none() {
+            state.current = configured.clone();
+            log::set_max_level(state.current.max_level());
+        }
+        state.configured = configured;
+    }
+}
+
+/// Set up the global logger, writing to stdout in the given format at the levels of the given
+/// handle.
+pub fn setup_logger(log_levels: LogLevelsHandle, format: LogFormat) -> Result<(), fern::InitError> {
+    let max_level = log_levels.current().0.max_level();
+    let dispatcher = fern::Dispatch::new()
+        .filter(move |metadata| log_levels.enabled(metadata))
+        .format(move |out, message, record| {
+            let message = message.to_string();
+            let event = current_event_fields();
+            let line = match format {
+                LogFormat::Human => human_line(unix_timestamp(), record, &message, event.as_ref()),
+                LogFormat::Json => json_line(unix_timestamp(), record, &message, event.as_ref()),
+            };
+            out.finish(format_args!("{}", line))
+        })
+        // The levels are checked by the filter, as they may change
+        .level(log::LevelFilter::Trace);
+
+    dispatcher.chain(std::io::stdout()).apply()?;
+    // Don't even format the messages no level allows. It's updated whenever the levels change.
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{test_utils::MockClock, Clock};
+
+    use std::time::Duration;
+
+    fn record_line(format: LogFormat, message: &str, event: Option<EventFields>) -> String {
+        let line_fn = match format {
+            LogFormat::Human => human_line,
+            LogFormat::Json => json_line,
+        };
+        line_fn(
+            1_640_000_000,
+            &log::Record::builder()
+                .args(format_args!("{}", message))
+                .level(log::Level::Info)
+                .target("revaultd::bitcoind::poller")
+                .build(),
+            message,
+            event.as_ref(),
+        )
+    }
+
+    #[test]
+    fn json_log_lines() {
+        // A plain message has the base fields
+        let line = record_line(LogFormat::Json, "bitcoind now synced.", None);
+        let parsed: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["timestamp"], 1_640_000_000);
+        assert_eq!(parsed["level"], "INFO");
+        assert_eq!(parsed["module"], "revaultd::bitcoind::poller");
+        assert_eq!(parsed["message"], "bitcoind now synced.");
+        assert!(parsed.get("event").is_none());
+
+        // An event also has its structured fields, and a multiline message is a single line
+        let event = EventFields {
+            event: "vault_status",
+            fields: vec![
+                ("outpoint", "ab:0".to_string()),
+                ("status", "funded".to_string()),
+            ],
+        };
+        let line = record_line(LogFormat::Json, "Vault is now\nconfirmed", Some(event));
+        assert_eq!(line.lines().count(), 1);
+        let parsed: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["message"], "Vault is now\nconfirmed");
+        assert_eq!(parsed["event"], "vault_status");
+        assert_eq!(parsed["fields"]["outpoint"], "ab:0");
+        assert_eq!(parsed["fields"]["status"], "funded");
+    }
+
+    #[test]
+    fn human_log_lines() {
+        let line = record_line(LogFormat::Human, "bitcoind now synced.", None);
+        assert_eq!(
+            line,
+            "[1640000000][revaultd::bitcoind::poller][INFO] bitcoind now synced."
+        );
+
+        let event = EventFields {
+            event: "spend_broadcast",
+            fields: vec![("txid", "cafe".to_string())],
+        };
+        let line = record_line(LogFormat::Human, "Broadcasted Spend", Some(event));
+        assert_eq!(
+            line,
+            "[1640000000][revaultd::bitcoind::poller][INFO] Broadcasted Spend \
+             (event=spend_broadcast txid=cafe)"
+        );
+    }
+
+    fn metadata(level: log::Level, target: &str) -> log::Metadata {
+        log::Metadata::builder().level(level).target(target).build()
+    }
+
+    #[test]
+    fn module_log_levels() {
+        let mut modules = HashMap::new();
+        modules.insert("revaultd::bitcoind".to_string(), log::LevelFilter::Trace);
+        modules.insert(
+            "revaultd::bitcoind::interface".to_string(),
+            log::LevelFilter::Warn,
+        );
+        let levels = LogLevels::new(log::LevelFilter::Info, modules);
+
+        assert_eq!(levels.level_for("revaultd"), log::LevelFilter::Info);
+        assert_eq!(
+            levels.level_for("revaultd::sigfetcher"),
+            log::LevelFilter::Info
+        );
+        assert_eq!(
+            levels.level_for("revaultd::bitcoind"),
+            log::LevelFilter::Trace
+        );
+        assert_eq!(
+            levels.level_for("revaultd::bitcoind::poller"),
+            log::LevelFilter::Trace
+        );
+        // The most specific one applies
+        assert_eq!(
+            levels.level_for("revaultd::bitcoind::interface"),
+            log::LevelFilter::Warn
+        );
+        // A module path isn't a string prefix
+        assert_eq!(
+            levels.level_for("revaultd::bitcoindx"),
+            log::LevelFilter::Info
+        );
+        assert_eq!(levels.max_level(), log::LevelFilter::Trace);
+    }
+
+    #[test]
+    fn runtime_log_levels() {
+        let clock = MockClock::new(1_600_000_000);
+        let handle = LogLevelsHandle::new(LogLevels::new(log::LevelFilter::Info, HashMap::new()));
+        let debug = metadata(log::Level::Debug, "revaultd::statemachine");
+        assert!(!handle.enabled(&debug));
+        assert!(handle.enabled(&metadata(log::Level::Info, "revaultd::statemachine")));
+
+        // Log debug messages for the next 10 minutes
+        let revert_at = clock.unix_timestamp() + 600;
+        let (previous, previous_revert_at) = handle.set(
+            LogLevels::new(log::LevelFilter::Debug, HashMap::new()),
+            Some(revert_at),
+        );
+        assert_eq!(previous.level, log::LevelFilter::Info);
+        assert!(previous_revert_at.is_none());
+        assert!(handle.enabled(&debug));
+        assert_eq!(handle.current().1, Some(revert_at));
+
+        // A configuration reload doesn't override them
+        let mut modules = HashMap::new();
+        modules.insert("revaultd::bitcoind".to_string(), log::LevelFilter::Trace);
+        handle.reconfigure(LogLevels::new(log::LevelFilter::Warn, modules.clone()));
+        assert!(handle.enabled(&debug));
+
+        clock.advance(Duration::from_secs(599));
+        assert!(!handle.expire(clock.unix_timestamp()));
+        assert!(handle.enabled(&debug));
+
+        // Once the duration elapsed the configured ones are back
+        clock.advance(Duration::from_secs(1));
+        assert!(handle.expire(clock.unix_timestamp()));
+        assert!(!handle.expire(clock.unix_timestamp()));
+        assert_eq!(
+            handle.current(),
+            (LogLevels::new(log::LevelFilter::Warn, modules), None)
+        );
+        assert!(!handle.enabled(&debug));
+        assert!(handle.enabled(&metadata(log::Level::Trace, "revaultd::bitcoind::poller")));
+
+        // Changed without a duration, they are never reverted
+        handle.set(
+            LogLevels::new(log::LevelFilter::Debug, HashMap::new()),
+            None,
+        );
+        clock.advance(Duration::from_secs(3600 * 24 * 365));
+        assert!(!handle.expire(clock.unix_timestamp()));
+        assert!(handle.enabled(&debug));
+    }
+
+    #[test]
+    fn event_fields_scope() {
+        assert!(current_event_fields().is_none());
+        let event = EventFields {
+            event: "peer_connection_failure",
+            fields: vec![("peer", "127.0.0.1:8383".to_string())],
+        };
+        with_event_fields(event.clone(), || {
+            assert_eq!(current_event_fields(), Some(event.clone()));
+        });
+        assert!(current_event_fields().is_none());
+    }
+}
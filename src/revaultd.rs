@@ -1,16 +1,32 @@
 use crate::{
-    config::{config_folder_path, BitcoindConfig, Config},
+    bitcoind::{audit::WalletAudit, relay_floor::RelayFloorCheck},
+    clock::{Clock, SystemClock},
+    communication::{CoordinatorEndpoint, Coordinators},
+    config::{
+        config_folder_path, xpub_fingerprint_from_str, AlertTierConfig, BitcoindConfig, Config,
+        DbSynchronous, EmergencyAddresses, SigFetchOrder,
+    },
+    database::schema::ScriptKind,
+    derivation::DerivationIndex,
+    diskspace::{DiskSpace, FsStats, SystemFsStats},
+    endpoint::Endpoint,
+    indexallocator::IndexAllocator,
+    logger::{LogLevels, LogLevelsHandle},
+    schedule::SpendingSchedule,
     StartupError,
 };
 
 use std::{
+    cmp,
     collections::HashMap,
     convert::TryFrom,
     fmt, fs,
     io::{self, Read, Write},
-    net::SocketAddr,
+    iter,
+    net::{IpAddr, SocketAddr, TcpListener},
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
     time,
     vec::Vec,
 };
@@ -21,19 +37,40 @@ use revault_net::{
 };
 use revault_tx::{
     bitcoin::{
+        hashes::{sha256, Hash, HashEngine},
         secp256k1,
-        util::bip32::{self, ChildNumber, ExtendedPrivKey, ExtendedPubKey},
-        Address, BlockHash, Network, PublicKey as BitcoinPublicKey, Script,
+        util::bip32::{self, DerivationPath, ExtendedPrivKey, ExtendedPubKey},
+        Address, Amount, BlockHash, Network, PublicKey as BitcoinPublicKey, Script,
+    },
+    miniscript::{
+        descriptor::{Descriptor, DescriptorPublicKey, DescriptorTrait, WshInner},
+        MiniscriptKey,
     },
-    miniscript::descriptor::{DescriptorPublicKey, DescriptorTrait},
     scripts::{
         CpfpDescriptor, DepositDescriptor, DerivedCpfpDescriptor, DerivedDepositDescriptor,
-        DerivedUnvaultDescriptor, EmergencyAddress, UnvaultDescriptor,
+        DerivedUnvaultDescriptor, UnvaultDescriptor,
     },
 };
 
 const CPFP_SEED_FILE_SIZE: usize = 32;
 
+/// A participant to the deployment, identified by the fingerprint of their xpub and the
+/// label the user gave it in the configuration if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Participant {
+    pub fingerprint: bip32::Fingerprint,
+    pub label: Option<String>,
+}
+
+impl fmt::Display for Participant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.label {
+            Some(ref label) => write!(f, "{} ({})", label, self.fingerprint),
+            None => write!(f, "{}", self.fingerprint),
+        }
+    }
+}
+
 /// The status of a [Vault], depends both on the block chain and the set of pre-signed
 /// transactions
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -73,6 +110,10 @@ pub enum VaultStatus {
     // TODO: At what depth do we forget it ?
     /// The spend transaction is confirmed
     Spent,
+    /// The unvault transaction is confirmed and its relative timelock expired, the managers
+    /// may spend it.
+    // NOTE: it is last as the numeric value of the statuses is stored in database.
+    Spendable,
 }
 
 impl TryFrom<u32> for VaultStatus {
@@ -96,6 +137,7 @@ impl TryFrom<u32> for VaultStatus {
             13 => Ok(Self::UnvaultEmergencyVaulted),
             14 => Ok(Self::Spending),
             15 => Ok(Self::Spent),
+            16 => Ok(Self::Spendable),
             _ => Err(()),
         }
     }
@@ -122,6 +164,7 @@ impl FromStr for VaultStatus {
             "unvaultemergencyvaulted" => Ok(Self::UnvaultEmergencyVaulted),
             "spending" => Ok(Self::Spending),
             "spent" => Ok(Self::Spent),
+            "spendable" => Ok(Self::Spendable),
             _ => Err(format!("Unknown status: {}", s)),
         }
     }
@@ -149,16 +192,129 @@ impl fmt::Display for VaultStatus {
                 Self::UnvaultEmergencyVaulted => "unvaultemergencyvaulted",
                 Self::Spending => "spending",
                 Self::Spent => "spent",
+                Self::Spendable => "spendable",
             }
         )
     }
 }
 
+/// A vault status change that isn't part of the lifecycle of a vault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub from: VaultStatus,
+    pub to: VaultStatus,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid vault status transition from '{}' to '{}'",
+            self.from, self.to
+        )
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+impl VaultStatus {
+    /// Check that a vault may go from this status to `to`. Staying in the same status is always
+    /// possible.
+    ///
+    /// Note that the block chain has the last word: a transaction may be seen there before we
+    /// fetched all the signatures for it, hence the 'shortcuts' out of the signing statuses.
+    /// The backward edges are the rollbacks of a reorg, or of a transaction being evicted from
+    /// the mempool.
+    pub fn transition(self, to: VaultStatus) -> Result<(), InvalidTransition> {
+        use VaultStatus::*;
+
+        if self == to {
+            return Ok(());
+        }
+
+        let legal = match (self, to) {
+            // Signing the revocation and the Unvault transactions.
+            (Unconfirmed, Funded) => true,
+            (Funded, Securing) | (Funded, Secured) | (Funded, Active) => true,
+            (Securing, Secured) | (Securing, Active) => true,
+            (Secured, Activating) | (Secured, Active) => true,
+            (Activating, Active) => true,
+            // The Deposit output got spent.
+            (Funded, Unvaulting)
+            | (Securing, Unvaulting)
+            | (Secured, Unvaulting)
+            | (Activating, Unvaulting)
+            | (Active, Unvaulting) => true,
+            (Funded, EmergencyVaulting)
+            | (Securing, EmergencyVaulting)
+            | (Secured, EmergencyVaulting)
+            | (Activating, EmergencyVaulting)
+            | (Active, EmergencyVaulting) => true,
+            (EmergencyVaulting, EmergencyVaulted) => true,
+            // The Unvault got confirmed, and its relative timelock expired.
+            (Unvaulting, Unvaulted) | (Unvaulted, Spendable) => true,
+            // The Unvault output got spent.
+            (Unvaulting, Canceling)
+            | (Unvaulting, Spending)
+            | (Unvaulting, UnvaultEmergencyVaulting)
+            | (Unvaulted, Canceling)
+            | (Unvaulted, Spending)
+            | (Unvaulted, UnvaultEmergencyVaulting)
+            | (Spendable, Canceling)
+            | (Spendable, Spending)
+            | (Spendable, UnvaultEmergencyVaulting) => true,
+            // The Cancel is still valid once the Unvault timelock expired, and may be
+            // broadcast in place of (or replace) the Spend.
+            (Spending, Canceling) => true,
+            (Canceling, Canceled)
+            | (Spending, Spent)
+            | (UnvaultEmergencyVaulting, UnvaultEmergencyVaulted) => true,
+            // The transaction spending the Unvault output was evicted from the mempool.
+            (Canceling, Unvaulted)
+            | (Spending, Unvaulted)
+            | (UnvaultEmergencyVaulting, Unvaulted) => true,
+            // The Deposit transaction was reorged out.
+            (Funded, Unconfirmed)
+            | (Securing, Unconfirmed)
+            | (Secured, Unconfirmed)
+            | (Activating, Unconfirmed)
+            | (Active, Unconfirmed)
+            | (EmergencyVaulting, Unconfirmed)
+            | (EmergencyVaulted, Unconfirmed) => true,
+            // The Unvault transaction was reorged out.
+            (Unvaulted, Unvaulting)
+            | (Spendable, Unvaulting)
+            | (Canceling, Unvaulting)
+            | (Canceled, Unvaulting)
+            | (Spending, Unvaulting)
+            | (Spent, Unvaulting)
+            | (UnvaultEmergencyVaulting, Unvaulting)
+            | (UnvaultEmergencyVaulted, Unvaulting) => true,
+            // The Unvault was confirmed in a later block, or the last transaction was reorged
+            // out.
+            (Spendable, Unvaulted)
+            | (Canceled, Canceling)
+            | (Spent, Spending)
+            | (EmergencyVaulted, EmergencyVaulting)
+            | (UnvaultEmergencyVaulted, UnvaultEmergencyVaulting) => true,
+            _ => false,
+        };
+
+        if legal {
+            Ok(())
+        } else {
+            Err(InvalidTransition { from: self, to })
+        }
+    }
+}
+
 /// An error related to the initialization of communication keys.
 #[derive(Debug)]
 pub enum NoiseKeyError {
     ReadingKey(io::Error),
     WritingKey(io::Error),
+    Corrupted(String),
+    NullKey,
 }
 
 impl fmt::Display for NoiseKeyError {
@@ -166,6 +322,12 @@ impl fmt::Display for NoiseKeyError {
         match self {
             Self::ReadingKey(e) => write!(f, "Error reading Noise key: '{}'", e),
             Self::WritingKey(e) => write!(f, "Error writing Noise key: '{}'", e),
+            Self::Corrupted(reason) => write!(
+                f,
+                "Noise key file is corrupted ({}). Please restore it from a backup.",
+                reason
+            ),
+            Self::NullKey => write!(f, "Noise key is all zeros"),
         }
     }
 }
@@ -200,42 +362,369 @@ impl fmt::Display for CpfpKeyError {
 
 impl std::error::Error for CpfpKeyError {}
 
-// The communication keys are (for now) hot, so we just create it ourselves on first run.
-fn read_or_create_noise_key(secret_file: PathBuf) -> Result<NoisePrivKey, NoiseKeyError> {
+// Bitcoin Core's standardness limits on spending a P2WSH output, see policy/policy.h. A
+// witness exceeding them wouldn't be relayed, hence the coins would only be spendable by
+// reaching a miner directly.
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+pub const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+
+/// The size of the witness script of a derived descriptor and of the largest witness
+/// satisfying it. Derived keys are all compressed, so they don't depend on the index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptLimits {
+    /// Size of the witness script, in bytes
+    pub script_size: usize,
+    /// Maximum number of witness stack elements, not counting the witness script
+    pub witness_elements: usize,
+    /// Maximum weight of the witness satisfying the script
+    pub satisfaction_weight: usize,
+}
+
+impl ScriptLimits {
+    /// The standardness limits only apply to P2WSH descriptors, we refuse any other.
+    pub fn new(descriptor: &Descriptor<BitcoinPublicKey>) -> Result<ScriptLimits, ScriptTypeError> {
+        let witness_elements = match descriptor {
+            Descriptor::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(smv) => smv.k + 1,
+                WshInner::Ms(ms) => {
+                    ms.max_satisfaction_witness_elements()
+                        .expect("Revault scripts are satisfiable")
+                        - 1
+                }
+            },
+            _ => return Err(ScriptTypeError::Descriptor(descriptor.to_string())),
+        };
+
+        Ok(ScriptLimits {
+            script_size: descriptor.explicit_script().len(),
+            witness_elements,
+            satisfaction_weight: descriptor
+                .max_satisfaction_weight()
+                .expect("Revault scripts are satisfiable"),
+        })
+    }
+
+    /// How far we are from the script size limit
+    pub fn script_size_headroom(&self) -> i64 {
+        MAX_STANDARD_P2WSH_SCRIPT_SIZE as i64 - self.script_size as i64
+    }
+
+    /// How far we are from the witness stack elements limit
+    pub fn witness_elements_headroom(&self) -> i64 {
+        MAX_STANDARD_P2WSH_STACK_ITEMS as i64 - self.witness_elements as i64
+    }
+
+    /// Check the spending witness of this script would be standard.
+    pub fn check(&self, descriptor: &'static str) -> Result<(), ScriptLimitError> {
+        if self.script_size > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+            return Err(ScriptLimitError {
+                descriptor,
+                limit: "witness script size",
+                value: self.script_size,
+                max: MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+            });
+        }
+        if self.witness_elements > MAX_STANDARD_P2WSH_STACK_ITEMS {
+            return Err(ScriptLimitError {
+                descriptor,
+                limit: "witness stack elements",
+                value: self.witness_elements,
+                max: MAX_STANDARD_P2WSH_STACK_ITEMS,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// A descriptor whose spending witness would exceed a standardness limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptLimitError {
+    pub descriptor: &'static str,
+    pub limit: &'static str,
+    pub value: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for ScriptLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The {} descriptor exceeds the standard {} limit by {} ({} for a maximum of {}). \
+             Its coins could not be spent through the P2P network.",
+            self.descriptor,
+            self.limit,
+            self.value - self.max,
+            self.value,
+            self.max
+        )
+    }
+}
+
+impl std::error::Error for ScriptLimitError {}
+
+/// The version of the output scripts of a wallet, persisted along with it. All the descriptors
+/// of a wallet share the same one. Only P2WSH is supported for now, the version allows wallets
+/// using another script type (Taproot) to coexist with them in the future.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptVersion {
+    /// Pay-To-Witness-Script-Hash, segwit v0
+    WitnessV0 = 0,
+}
+
+impl ScriptVersion {
+    /// The version of the scripts derived from this descriptor, if it's a type we support.
+    pub fn of_descriptor<Pk: MiniscriptKey>(descriptor: &Descriptor<Pk>) -> Option<Self> {
+        match descriptor {
+            Descriptor::Wsh(_) => Some(Self::WitnessV0),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<u32> for ScriptVersion {
+    type Error = ScriptTypeError;
+
+    fn try_from(version: u32) -> Result<Self, Self::Error> {
+        match version {
+            0 => Ok(Self::WitnessV0),
+            v => Err(ScriptTypeError::Version(v)),
+        }
+    }
+}
+
+impl fmt::Display for ScriptVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::WitnessV0 => write!(f, "p2wsh"),
+        }
+    }
+}
+
+/// A script type we don't know how to handle (yet).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptTypeError {
+    /// This descriptor isn't of a supported script type
+    Descriptor(String),
+    /// A wallet was persisted with an unknown script version
+    Version(u32),
+    /// (Deposit, Unvault, Cpfp) The descriptors of a wallet don't share the same script version
+    Mismatch(ScriptVersion, ScriptVersion, ScriptVersion),
+}
+
+impl fmt::Display for ScriptTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Descriptor(desc) => write!(
+                f,
+                "Unsupported script type for descriptor '{}'. Only P2WSH descriptors are \
+                 supported.",
+                desc
+            ),
+            Self::Version(v) => write!(f, "Unknown script version '{}'", v),
+            Self::Mismatch(deposit, unvault, cpfp) => write!(
+                f,
+                "The descriptors use different script types: '{}' (deposit), '{}' (unvault), \
+                 '{}' (cpfp)",
+                deposit, unvault, cpfp
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScriptTypeError {}
+
+/// The address of the outputs paying to this (derived) descriptor, if it's of a supported
+/// script type.
+pub fn descriptor_address(
+    descriptor: &Descriptor<BitcoinPublicKey>,
+    network: Network,
+) -> Result<Address, ScriptTypeError> {
+    ScriptVersion::of_descriptor(descriptor)
+        .and_then(|_| descriptor.address(network).ok())
+        .ok_or_else(|| ScriptTypeError::Descriptor(descriptor.to_string()))
+}
+
+/// The script version of a wallet using these descriptors.
+pub fn descriptors_script_version(
+    deposit_descriptor: &DepositDescriptor,
+    unvault_descriptor: &UnvaultDescriptor,
+    cpfp_descriptor: &CpfpDescriptor,
+) -> Result<ScriptVersion, ScriptTypeError> {
+    // The script type doesn't depend on the index, any is fine.
+    let secp = secp256k1::Secp256k1::verification_only();
+    let index = DerivationIndex::ZERO;
+    let version = |desc: &Descriptor<BitcoinPublicKey>| {
+        ScriptVersion::of_descriptor(desc)
+            .ok_or_else(|| ScriptTypeError::Descriptor(desc.to_string()))
+    };
+    let deposit = version(deposit_descriptor.derive(index.into(), &secp).inner())?;
+    let unvault = version(unvault_descriptor.derive(index.into(), &secp).inner())?;
+    let cpfp = version(cpfp_descriptor.derive(index.into(), &secp).inner())?;
+
+    if deposit != unvault || deposit != cpfp {
+        return Err(ScriptTypeError::Mismatch(deposit, unvault, cpfp));
+    }
+    Ok(deposit)
+}
+
+/// Compute the limits of the scripts derived from the three descriptors, in this order.
+pub fn descriptors_script_limits(
+    deposit_descriptor: &DepositDescriptor,
+    unvault_descriptor: &UnvaultDescriptor,
+    cpfp_descriptor: &CpfpDescriptor,
+) -> Result<[ScriptLimits; 3], ScriptTypeError> {
+    // The scripts don't depend on the index, any is fine.
+    let secp = secp256k1::Secp256k1::verification_only();
+    let index = DerivationIndex::ZERO;
+    Ok([
+        ScriptLimits::new(deposit_descriptor.derive(index.into(), &secp).inner())?,
+        ScriptLimits::new(unvault_descriptor.derive(index.into(), &secp).inner())?,
+        ScriptLimits::new(cpfp_descriptor.derive(index.into(), &secp).inner())?,
+    ])
+}
+
+// Refuse descriptors of a script type we don't support, or whose coins could not be spent
+// with a standard transaction.
+fn check_script_limits(
+    deposit_descriptor: &DepositDescriptor,
+    unvault_descriptor: &UnvaultDescriptor,
+    cpfp_descriptor: &CpfpDescriptor,
+) -> Result<ScriptVersion, StartupError> {
+    let script_version =
+        descriptors_script_version(deposit_descriptor, unvault_descriptor, cpfp_descriptor)?;
+    let limits =
+        descriptors_script_limits(deposit_descriptor, unvault_descriptor, cpfp_descriptor)?;
+    for (name, limits) in ["deposit", "unvault", "cpfp"].iter().zip(limits.iter()) {
+        limits.check(*name)?;
+    }
+
+    Ok(script_version)
+}
+
+// The Noise key file is a magic, the key and the first bytes of the hash of both. Files
+// created by older versions only contain the raw key.
+const NOISE_KEY_MAGIC: [u8; NOISE_KEY_MAGIC_SIZE] = *b"RVNK";
+const NOISE_KEY_MAGIC_SIZE: usize = 4;
+const NOISE_KEY_CHECKSUM_SIZE: usize = 4;
+const NOISE_KEY_FILE_SIZE: usize = NOISE_KEY_MAGIC_SIZE + 32 + NOISE_KEY_CHECKSUM_SIZE;
+const LEGACY_NOISE_KEY_FILE_SIZE: usize = 32;
+
+fn noise_key_checksum(key: &NoisePrivKey) -> [u8; NOISE_KEY_CHECKSUM_SIZE] {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&NOISE_KEY_MAGIC);
+    engine.input(&key.0);
+    let hash = sha256::Hash::from_engine(engine);
+
+    let mut checksum = [0; NOISE_KEY_CHECKSUM_SIZE];
+    checksum.copy_from_slice(&hash[..NOISE_KEY_CHECKSUM_SIZE]);
+    checksum
+}
+
+fn serialize_noise_key(key: &NoisePrivKey) -> Vec<u8> {
+    let mut content = Vec::with_capacity(NOISE_KEY_FILE_SIZE);
+    content.extend_from_slice(&NOISE_KEY_MAGIC);
+    content.extend_from_slice(&key.0);
+    content.extend_from_slice(&noise_key_checksum(key));
+    content
+}
+
+fn deserialize_noise_key(content: &[u8]) -> Result<NoisePrivKey, NoiseKeyError> {
     let mut noise_secret = NoisePrivKey([0; 32]);
 
-    if !secret_file.as_path().exists() {
+    match content.len() {
+        LEGACY_NOISE_KEY_FILE_SIZE => noise_secret.0.copy_from_slice(content),
+        NOISE_KEY_FILE_SIZE => {
+            let (magic, rest) = content.split_at(NOISE_KEY_MAGIC_SIZE);
+            if magic != NOISE_KEY_MAGIC {
+                return Err(NoiseKeyError::Corrupted("invalid header".to_string()));
+            }
+            let (key, checksum) = rest.split_at(32);
+            noise_secret.0.copy_from_slice(key);
+            if checksum != noise_key_checksum(&noise_secret) {
+                return Err(NoiseKeyError::Corrupted("checksum mismatch".to_string()));
+            }
+        }
+        0 => return Err(NoiseKeyError::Corrupted("empty file".to_string())),
+        len => {
+            return Err(NoiseKeyError::Corrupted(format!(
+                "unexpected size of {} bytes, expected {}",
+                len, NOISE_KEY_FILE_SIZE
+            )))
+        }
+    }
+
+    if noise_secret.0 == [0; 32] {
+        return Err(NoiseKeyError::NullKey);
+    }
+
+    Ok(noise_secret)
+}
+
+// Write the key to a temporary file first and only move it in place once it's on disk, so a
+// crash never leaves a partially written key file behind.
+fn write_noise_key(secret_file: &Path, key: &NoisePrivKey) -> Result<(), io::Error> {
+    let tmp_file = secret_file.with_extension("tmp");
+    // A leftover of a previous crash. It was never moved in place, so never used.
+    if tmp_file.exists() {
+        fs::remove_file(&tmp_file)?;
+    }
+
+    // We create it in read-only but open it in write only.
+    let mut options = fs::OpenOptions::new();
+    options = options.write(true).create_new(true).clone();
+    // FIXME: handle Windows ACLs
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options = options.mode(0o400).clone();
+    }
+
+    let mut fd = options.open(&tmp_file)?;
+    fd.write_all(&serialize_noise_key(key))?;
+    fd.sync_all()?;
+    fs::rename(&tmp_file, secret_file)?;
+
+    // Make sure the rename itself hit the disk
+    #[cfg(unix)]
+    {
+        if let Some(parent) = secret_file.parent() {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            fs::File::open(parent)?.sync_all()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_noise_key(secret_file: &Path) -> Result<NoisePrivKey, NoiseKeyError> {
+    let mut content = Vec::with_capacity(NOISE_KEY_FILE_SIZE);
+    fs::File::open(secret_file)
+        .and_then(|mut fd| fd.read_to_end(&mut content))
+        .map_err(NoiseKeyError::ReadingKey)?;
+    deserialize_noise_key(&content)
+}
+
+// The communication keys are (for now) hot, so we just create it ourselves on first run.
+fn read_or_create_noise_key(secret_file: PathBuf) -> Result<NoisePrivKey, NoiseKeyError> {
+    let noise_secret = if !secret_file.as_path().exists() {
         log::info!(
             "No Noise private key at '{:?}', generating a new one",
             secret_file
         );
-        noise_secret = sodiumoxide::crypto::box_::gen_keypair().1;
-
-        // We create it in read-only but open it in write only.
-        let mut options = fs::OpenOptions::new();
-        options = options.write(true).create_new(true).clone();
-        // FIXME: handle Windows ACLs
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::OpenOptionsExt;
-            options = options.mode(0o400).clone();
-        }
-
-        let mut fd = options
-            .open(secret_file)
-            .map_err(NoiseKeyError::WritingKey)?;
-        fd.write_all(noise_secret.as_ref())
-            .map_err(NoiseKeyError::WritingKey)?;
+        let noise_secret = sodiumoxide::crypto::box_::gen_keypair().1;
+        write_noise_key(&secret_file, &noise_secret).map_err(NoiseKeyError::WritingKey)?;
+        noise_secret
     } else {
-        let mut noise_secret_fd = fs::File::open(secret_file).map_err(NoiseKeyError::ReadingKey)?;
-        noise_secret_fd
-            .read_exact(&mut noise_secret.0)
-            .map_err(NoiseKeyError::ReadingKey)?;
-    }
+        read_noise_key(&secret_file)?
+    };
 
     // TODO: have a decent memory management and mlock() the key
 
-    assert!(noise_secret.0 != [0; 32]);
     Ok(noise_secret)
 }
 
@@ -269,6 +758,48 @@ fn read_cpfp_key(
         .map(Option::Some)
 }
 
+// Read the CPFP key from the data directory, if there is one, derive it at the configured path
+// and check it is part of the CPFP descriptor.
+fn datadir_cpfp_key(
+    data_dir_str: &str,
+    cpfp_descriptor: &CpfpDescriptor,
+    key_path: Option<&DerivationPath>,
+    network: Network,
+) -> Result<Option<ExtendedPrivKey>, CpfpKeyError> {
+    let cpfp_key_file = [data_dir_str, "cpfp_secret"].iter().collect();
+    let net = if network == Network::Bitcoin {
+        Network::Bitcoin
+    } else {
+        Network::Testnet
+    };
+    let key = match (read_cpfp_key(cpfp_key_file, net)?, key_path) {
+        (Some(master_key), Some(path)) => Some(
+            master_key
+                .derive_priv(&secp256k1::Secp256k1::signing_only(), path)
+                .map_err(CpfpKeyError::InvalidSeed)?,
+        ),
+        (key, _) => key,
+    };
+    if let Some(key) = key {
+        // Checking if the key is in the cpfp descriptor
+        let secp_ctx = secp256k1::Secp256k1::signing_only();
+        let pubkey = ExtendedPubKey::from_private(&secp_ctx, &key);
+        cpfp_descriptor
+            .xpubs()
+            .iter()
+            .find(|k| {
+                if let DescriptorPublicKey::XPub(k) = k {
+                    k.xkey == pubkey
+                } else {
+                    unreachable!();
+                }
+            })
+            .ok_or_else(|| CpfpKeyError::KeyNotInDescriptor(pubkey.to_string()))?;
+    }
+
+    Ok(key)
+}
+
 /// Information about the last block in the chain.
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct BlockchainTip {
@@ -276,6 +807,16 @@ pub struct BlockchainTip {
     pub hash: BlockHash,
 }
 
+/// The feerates bitcoind estimated to be needed for a transaction to confirm, in sat/vbyte.
+/// An estimate is None if bitcoind did not have enough data to compute it.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct FeerateEstimates {
+    /// To be confirmed within 2 blocks
+    pub next_block: Option<u64>,
+    /// To be confirmed within 144 blocks
+    pub next_day: Option<u64>,
+}
+
 /// Our global state
 pub struct RevaultD {
     // Bitcoind stuff
@@ -283,8 +824,26 @@ pub struct RevaultD {
     pub bitcoind_config: BitcoindConfig,
     /// Last block we heard about
     pub tip: Option<BlockchainTip>,
+    /// The feerate estimates of bitcoind at the last block we heard about
+    pub feerate_estimates: Option<FeerateEstimates>,
     /// Minimum confirmations before considering a deposit as mature
     pub min_conf: u32,
+    /// After how many polls to give up on an unconfirmed deposit that left the mempool
+    pub deposit_abandon_polls: u32,
+    /// Longest chain of unconfirmed transactions a deposit may depend on, if limited
+    pub max_deposit_ancestry_depth: Option<u32>,
+    /// Largest total vsize of the unconfirmed transactions a deposit may depend on, if limited
+    pub max_deposit_ancestry_vsize: Option<u64>,
+    /// The derivation index up to which we may extend the deposit addresses we watch
+    pub deposit_index_ceiling: DerivationIndex,
+    /// Maximum number of vaults a Spend transaction may consume
+    pub max_spend_inputs: usize,
+    /// Maximum number of elements accepted by a single RPC command
+    pub max_batch_size: usize,
+    /// Maximum fees of a Spend transaction, in percentage of the spent vaults' value
+    pub max_spend_fee_percent: u64,
+    /// Maximum fees of a Spend transaction
+    pub max_spend_fee: Amount,
 
     // Scripts stuff
     /// Who am i, and where am i in all this mess ?
@@ -296,12 +855,16 @@ pub struct RevaultD {
     pub unvault_descriptor: UnvaultDescriptor,
     /// The miniscript descriptor of CPFP output scripts (in unvault and spend transaction)
     pub cpfp_descriptor: CpfpDescriptor,
-    /// The Emergency address, only available if we are a stakeholder
-    pub emergency_address: Option<EmergencyAddress>,
+    /// The version of the scripts derived from the three descriptors above
+    pub script_version: ScriptVersion,
+    /// The Emergency addresses and the vault amounts routed to each, only available if we are a
+    /// stakeholder
+    pub emergency_addresses: Option<EmergencyAddresses>,
     /// We don't make an enormous deal of address reuse (we cancel to the same keys),
-    /// however we at least try to generate new addresses once they're used.
+    /// however we at least try to generate new addresses once they're used. The derivation
+    /// index of the next one is shared by the RPC and the poller through this allocator.
     // FIXME: think more about desync reconciliation..
-    pub current_unused_index: ChildNumber,
+    pub deposit_indexes: IndexAllocator,
     /// The secp context required by the xpub one.. We'll eventually use it to verify keys.
     pub secp_ctx: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
     /// The locktime to use on all created transaction. Always 0 for now.
@@ -315,31 +878,112 @@ pub struct RevaultD {
     /// The static private key we use to establish connections to servers. We reuse it, but Trevor
     /// said it's fine! https://github.com/noiseprotocol/noise_spec/blob/master/noise.md#14-security-considerations
     pub noise_secret: NoisePrivKey,
-    /// The ip:port the coordinator is listening on. TODO: Tor
-    pub coordinator_host: SocketAddr,
-    /// The static public key to enact the Noise channel with the Coordinator
-    pub coordinator_noisekey: NoisePubKey,
+    /// The ip:port (TODO: Tor) and the static public key to enact the Noise channel with of the
+    /// main Coordinator and of the backup ones, along with which one is in use.
+    pub coordinators: Coordinators,
+    /// The local address we connect to all the servers from, if set
+    pub bind_address: Option<IpAddr>,
     pub coordinator_poll_interval: time::Duration,
-    /// The ip:port (TODO: Tor) and Noise public key of each cosigning server, only set if we are
+    /// In what order to fetch the missing signatures, and for how many vaults at once
+    pub sigfetch_order: SigFetchOrder,
+    pub sigfetch_batch_size: usize,
+    /// The address (TODO: Tor) and Noise public key of each cosigning server, only set if we are
     /// a manager.
-    pub cosigs: Option<Vec<(SocketAddr, NoisePubKey)>>,
-    /// The ip:port (TODO: Tor) and Noise public key of each watchtower, only set if we are
+    pub cosigs: Option<Vec<(Endpoint, NoisePubKey)>>,
+    /// The labels of the cosigning servers, in the same order as `cosigs`
+    pub cosigs_labels: Vec<Option<String>>,
+    /// For how long to wait for the cosigning servers to answer, altogether.
+    pub cosigs_timeout: time::Duration,
+    /// How many managers need to approve a spend proposal before we create a Spend transaction
+    /// for it, if proposals are required at all, and for how long they are valid.
+    pub spend_approval_threshold: Option<usize>,
+    pub spend_proposal_expiry: time::Duration,
+    /// For how long a Spend transaction we announced may go without any of its Unvault
+    /// transactions being broadcast before it expires.
+    pub spend_expiry: time::Duration,
+    /// Whether to refuse paying again to an external address we already paid to
+    pub forbid_destination_reuse: bool,
+    /// When we may initiate a spend, if restricted. Hot-reloaded on SIGHUP.
+    pub spending_schedule: Option<SpendingSchedule>,
+    /// The address (TODO: Tor) and Noise public key of each watchtower, only set if we are
     /// a stakeholder.
-    pub watchtowers: Option<Vec<(SocketAddr, NoisePubKey)>>,
+    pub watchtowers: Option<Vec<(Endpoint, NoisePubKey)>>,
+    /// The address to serve the JSONRPC interface on over TCP, if any, and the Noise public keys
+    /// of the clients allowed to connect to it.
+    pub rpc_listen: Option<SocketAddr>,
+    pub rpc_clients: Vec<NoisePubKey>,
+    /// The clients allowed to call `getwatchdata` over TCP. If empty, any of them can.
+    pub watchdata_clients: Vec<NoisePubKey>,
+    /// The labels of the participants, by fingerprint of their xpub
+    pub key_labels: HashMap<bip32::Fingerprint, String>,
+
+    // Alerting stuff
+    /// The command to run on vault status transitions, if any, the statuses to run it for and
+    /// after how long to kill it.
+    pub notify_command: Option<PathBuf>,
+    pub notify_statuses: Vec<VaultStatus>,
+    pub notify_timeout: time::Duration,
+    /// How to alert about unvaults, cancels and emergencies depending on the vault value, and
+    /// after how long to give up on an attempt to post to a webhook.
+    pub alert_tiers: Vec<AlertTierConfig>,
+    pub webhook_timeout: time::Duration,
+    /// How often to check our revocation transactions are still accepted by bitcoind's
+    /// mempool, if at all.
+    pub revocation_check_interval: Option<time::Duration>,
+    /// How often to check the watchonly wallet watches what we imported into it, if at all.
+    pub wallet_audit_interval: Option<time::Duration>,
+    /// The result of the last check of the watchonly wallet, if any since startup
+    pub wallet_audit: Option<WalletAudit>,
+    /// How often to compare the feerates of our presigned transactions with the relay floor of
+    /// bitcoind, if at all, and by how much (in percent of the floor) they must pay above it.
+    pub relay_floor_check_interval: Option<time::Duration>,
+    pub relay_floor_margin_percent: u64,
+    /// The result of the last comparison, if any since startup
+    pub relay_floor_check: Option<RelayFloorCheck>,
+    /// Below how many bytes available on the filesystem of the data directory to warn, and to
+    /// refuse storing new transactions.
+    pub disk_space_warning: u64,
+    pub disk_space_critical: u64,
+
+    /// For how long to remember the result of a command sent with an idempotency key
+    pub idempotency_retention: time::Duration,
+    /// Above how long an RPC call is logged as slow
+    pub slow_rpc_call: time::Duration,
 
     // 'Wallet' stuff
-    /// A map from a scriptPubKey to a derivation index. Used to retrieve the actual public
-    /// keys used to generate a script from bitcoind until we can pass it xpub-expressed
-    /// Miniscript descriptors.
-    pub derivation_index_map: HashMap<Script, ChildNumber>,
+    /// A map from a deposit scriptPubKey to a derivation index. Used to retrieve the actual
+    /// public keys used to generate a script from bitcoind until we can pass it xpub-expressed
+    /// Miniscript descriptors. Loaded from the database at startup.
+    pub derivation_index_map: HashMap<Script, DerivationIndex>,
+    /// Same as above, for the Unvault scriptPubKeys.
+    pub unvault_derivation_index_map: HashMap<Script, DerivationIndex>,
     /// The id of the wallet used in the db
     pub wallet_id: Option<u32>,
 
     // Misc stuff
     /// We store all our data in one place, that's here.
     pub data_dir: PathBuf,
+    /// The files that may live outside the data directory
+    db_file: PathBuf,
+    log_file: PathBuf,
+    rpc_socket_file: PathBuf,
     /// Should we run as a daemon? (Default: yes)
     pub daemon: bool,
+    /// Are we only monitoring? Set at startup, never changed afterward.
+    pub read_only: bool,
+    /// Should we check at startup that the stored scripts of our vaults still derive
+    /// identically, even if this version already ran on the database?
+    pub verify_derivations: bool,
+    /// How hard sqlite tries to make our database commits durable
+    pub db_synchronous: DbSynchronous,
+    /// Whether sqlite overwrites the content we delete from the database
+    pub db_secure_delete: bool,
+    /// Where we get the time from, for both intervals and recorded events.
+    pub clock: Arc<dyn Clock>,
+    /// Where we get the space available on disk from.
+    pub fs_stats: Arc<dyn FsStats>,
+    /// The levels at which we log, which may be changed at runtime.
+    pub log_levels: LogLevelsHandle,
     // TODO: servers connection stuff
 }
 
@@ -369,6 +1013,8 @@ fn create_datadir(datadir_path: &Path) -> Result<(), DatadirError> {
 pub enum DatadirError {
     CreateDatadir(PathBuf, String),
     DefaultNotFound,
+    MissingParentDir(PathBuf),
+    NotWritable(PathBuf, String),
 }
 
 impl fmt::Display for DatadirError {
@@ -378,14 +1024,112 @@ impl fmt::Display for DatadirError {
                 write!(f, "Could not create data directory '{:?}': {}", path, e)
             }
             Self::DefaultNotFound => write!(f, "Could not locate the default data directory "),
+            Self::MissingParentDir(path) => {
+                write!(f, "Parent directory of '{:?}' does not exist", path)
+            }
+            Self::NotWritable(path, e) => {
+                write!(f, "Directory '{:?}' is not writable: {}", path, e)
+            }
         }
     }
 }
 
+// A path given in the config is either absolute, or relative to the data directory.
+fn resolve_datadir_path(data_dir: &Path, custom: Option<PathBuf>, default: &str) -> PathBuf {
+    match custom {
+        Some(path) if path.is_absolute() => path,
+        Some(path) => data_dir.join(path),
+        None => data_dir.join(default),
+    }
+}
+
+// Make sure we'll be able to create this file, optionally creating its parent directory.
+fn check_parent_dir(file_path: &Path, create: bool) -> Result<(), DatadirError> {
+    let parent = file_path
+        .parent()
+        .ok_or_else(|| DatadirError::MissingParentDir(file_path.to_path_buf()))?;
+
+    if !parent.exists() {
+        if create {
+            create_datadir(parent)?;
+        } else {
+            return Err(DatadirError::MissingParentDir(file_path.to_path_buf()));
+        }
+    }
+
+    // Permissions bits don't tell the whole story (ACLs, read-only mounts, ..), just try.
+    let probe_path = parent.join(".revaultd_write_check");
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe_path)
+        .and_then(|_| fs::remove_file(&probe_path))
+        .map_err(|e| DatadirError::NotWritable(parent.to_path_buf(), e.to_string()))
+}
+
 impl std::error::Error for DatadirError {}
 
 impl RevaultD {
     /// Creates our global state by consuming the static configuration
+    /// Check we could start with this configuration, without creating nor modifying anything.
+    /// The configuration file parsing checked the descriptors, keys and addresses. This checks
+    /// the derived scripts are within the standardness limits, the keys in the data directory
+    /// and that we can create our files, if the data directory already exists (it is created
+    /// at first startup otherwise).
+    pub fn check_config(config: &Config) -> Result<(), StartupError> {
+        check_script_limits(
+            &config.scripts_config.deposit_descriptor,
+            &config.scripts_config.unvault_descriptor,
+            &config.scripts_config.cpfp_descriptor,
+        )?;
+
+        let mut data_dir = config
+            .data_dir
+            .clone()
+            .unwrap_or(config_folder_path().ok_or(DatadirError::DefaultNotFound)?);
+        data_dir.push(config.bitcoind_config.network.to_string());
+        if !data_dir.as_path().exists() {
+            return Ok(());
+        }
+        data_dir = fs::canonicalize(data_dir)?;
+        let data_dir_str = data_dir
+            .to_str()
+            .expect("Impossible: the datadir path is valid unicode");
+
+        let noise_secret_file = data_dir.join("noise_secret");
+        if noise_secret_file.exists() {
+            read_noise_key(&noise_secret_file)?;
+        }
+        if let Some(ref manager_config) = config.manager_config {
+            datadir_cpfp_key(
+                data_dir_str,
+                &config.scripts_config.cpfp_descriptor,
+                manager_config.cpfp_key_path.as_ref(),
+                config.bitcoind_config.network,
+            )?;
+        }
+
+        for (custom, default, create) in &[
+            (&config.db_path, "revaultd.sqlite3", false),
+            (&config.log_path, "log", false),
+            (
+                &config.rpc_socket_path,
+                "revaultd_rpc",
+                config.create_rpc_socket_dir.unwrap_or(false),
+            ),
+        ] {
+            let file = resolve_datadir_path(&data_dir, (*custom).clone(), default);
+            // We'd create it at startup
+            if *create && file.parent().map(|p| !p.exists()).unwrap_or(false) {
+                continue;
+            }
+            check_parent_dir(&file, false)?;
+        }
+
+        Ok(())
+    }
+
     pub fn from_config(config: Config) -> Result<RevaultD, StartupError> {
         let our_man_xpub = config.manager_config.as_ref().map(|x| x.xpub);
         let our_stk_xpub = config.stakeholder_config.as_ref().map(|x| x.xpub);
@@ -395,7 +1139,9 @@ impl RevaultD {
         let deposit_descriptor = config.scripts_config.deposit_descriptor;
         let unvault_descriptor = config.scripts_config.unvault_descriptor;
         let cpfp_descriptor = config.scripts_config.cpfp_descriptor;
-        let emergency_address = config
+        let script_version =
+            check_script_limits(&deposit_descriptor, &unvault_descriptor, &cpfp_descriptor)?;
+        let emergency_addresses = config
             .stakeholder_config
             .clone()
             .map(|x| x.emergency_address);
@@ -415,30 +1161,14 @@ impl RevaultD {
         let noise_secret_file = [data_dir_str, "noise_secret"].iter().collect();
         let noise_secret = read_or_create_noise_key(noise_secret_file)?;
 
-        let cpfp_key = if our_man_xpub.is_some() {
-            let cpfp_key_file = [data_dir_str, "cpfp_secret"].iter().collect();
-            let net = if config.bitcoind_config.network == Network::Bitcoin {
-                Network::Bitcoin
-            } else {
-                Network::Testnet
-            };
-            let key = read_cpfp_key(cpfp_key_file, net)?;
-            if let Some(key) = key {
-                // Checking if the key is in the cpfp descriptor
-                let secp_ctx = secp256k1::Secp256k1::signing_only();
-                let pubkey = ExtendedPubKey::from_private(&secp_ctx, &key);
-                cpfp_descriptor
-                    .xpubs()
-                    .iter()
-                    .find(|k| {
-                        if let DescriptorPublicKey::XPub(k) = k {
-                            k.xkey == pubkey
-                        } else {
-                            unreachable!();
-                        }
-                    })
-                    .ok_or_else(|| CpfpKeyError::KeyNotInDescriptor(pubkey.to_string()))?;
-            } else {
+        let cpfp_key = if let Some(ref manager_config) = config.manager_config {
+            let key = datadir_cpfp_key(
+                data_dir_str,
+                &cpfp_descriptor,
+                manager_config.cpfp_key_path.as_ref(),
+                config.bitcoind_config.network,
+            )?;
+            if key.is_none() {
                 log::warn!(
                     "CPFP key not found, consider creating a cpfp_secret file in the datadir. \
                     Automated CPFP won't be available."
@@ -449,9 +1179,79 @@ impl RevaultD {
             None
         };
 
-        let coordinator_noisekey = config.coordinator_noise_key;
+        let db_file = resolve_datadir_path(&data_dir, config.db_path, "revaultd.sqlite3");
+        check_parent_dir(&db_file, false)?;
+        let log_file = resolve_datadir_path(&data_dir, config.log_path, "log");
+        check_parent_dir(&log_file, false)?;
+        let rpc_socket_file =
+            resolve_datadir_path(&data_dir, config.rpc_socket_path, "revaultd_rpc");
+        check_parent_dir(
+            &rpc_socket_file,
+            config.create_rpc_socket_dir.unwrap_or(false),
+        )?;
+
+        // Better to find out now than when we first need to reach a server.
+        if let Some(bind_address) = config.bind_address {
+            TcpListener::bind((bind_address, 0))
+                .map_err(|e| StartupError::BindAddress(bind_address, e))?;
+        }
+
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let coordinators = Coordinators::new(
+            iter::once(CoordinatorEndpoint {
+                host: config.coordinator_host.clone(),
+                noise_key: config.coordinator_noise_key,
+            })
+            .chain(
+                config
+                    .backup_coordinators
+                    .iter()
+                    .map(|coordinator| CoordinatorEndpoint {
+                        host: coordinator.host.clone(),
+                        noise_key: coordinator.noise_key,
+                    }),
+            )
+            .collect(),
+            clock.clone(),
+        )
+        .with_push_rate(config.coordinator_push_rate, config.coordinator_push_burst)
+        .with_bind_address(config.bind_address);
         let coordinator_poll_interval = config.coordinator_poll_seconds;
 
+        let cosigs_timeout = config
+            .manager_config
+            .as_ref()
+            .map(|config| config.cosigners_timeout_secs)
+            .unwrap_or_else(|| time::Duration::from_secs(30));
+        let (spend_approval_threshold, spend_proposal_expiry) = config
+            .manager_config
+            .as_ref()
+            .map(|config| {
+                (
+                    config.spend_approval_threshold,
+                    config.spend_proposal_expiry_secs,
+                )
+            })
+            .unwrap_or_else(|| (None, time::Duration::from_secs(24 * 3600)));
+        let spend_expiry = config
+            .manager_config
+            .as_ref()
+            .map(|config| config.spend_expiry_secs)
+            .unwrap_or_else(|| time::Duration::from_secs(3 * 24 * 3600));
+        let forbid_destination_reuse = config
+            .manager_config
+            .as_ref()
+            .map(|config| config.forbid_destination_reuse)
+            .unwrap_or(false);
+        let spending_schedule = config
+            .manager_config
+            .as_ref()
+            .and_then(|config| config.spending_schedule.clone());
+        let cosigs_labels = config
+            .manager_config
+            .as_ref()
+            .map(|config| config.cosigners.iter().map(|c| c.label.clone()).collect())
+            .unwrap_or_else(Vec::new);
         let cosigs = config.manager_config.map(|config| {
             config
                 .cosigners
@@ -468,6 +1268,15 @@ impl RevaultD {
                 .collect()
         });
 
+        // Config checked they are valid fingerprints
+        let key_labels = config
+            .key_labels
+            .into_iter()
+            .filter_map(|(fingerprint, label)| {
+                xpub_fingerprint_from_str(&fingerprint).map(|fingerprint| (fingerprint, label))
+            })
+            .collect();
+
         let daemon = !matches!(config.daemon, Some(false));
 
         let secp_ctx = secp256k1::Secp256k1::verification_only();
@@ -478,27 +1287,88 @@ impl RevaultD {
             deposit_descriptor,
             unvault_descriptor,
             cpfp_descriptor,
+            script_version,
             secp_ctx,
             data_dir,
+            db_file,
+            log_file,
+            rpc_socket_file,
             daemon,
-            emergency_address,
+            read_only: config.read_only,
+            verify_derivations: config.verify_derivations,
+            db_synchronous: config.db_synchronous,
+            db_secure_delete: config.db_secure_delete,
+            emergency_addresses,
             noise_secret,
-            coordinator_host: config.coordinator_host,
-            coordinator_noisekey,
+            coordinators,
+            bind_address: config.bind_address,
             coordinator_poll_interval,
+            sigfetch_order: config.sigfetch_order,
+            sigfetch_batch_size: config.sigfetch_batch_size,
             cosigs,
+            cosigs_labels,
+            cosigs_timeout,
+            spend_approval_threshold,
+            spend_proposal_expiry,
+            spend_expiry,
+            forbid_destination_reuse,
+            spending_schedule,
             watchtowers,
             lock_time: 0,
             cpfp_key,
             min_conf: config.min_conf,
+            deposit_abandon_polls: config.deposit_abandon_polls,
+            max_deposit_ancestry_depth: config.max_deposit_ancestry_depth,
+            max_deposit_ancestry_vsize: config.max_deposit_ancestry_vsize,
+            deposit_index_ceiling: config.deposit_index_ceiling,
+            rpc_listen: config.rpc_listen,
+            rpc_clients: config.rpc_clients.iter().map(|c| c.noise_key).collect(),
+            watchdata_clients: config
+                .rpc_clients
+                .iter()
+                .filter(|c| c.watchtower)
+                .map(|c| c.noise_key)
+                .collect(),
+            key_labels,
+            max_spend_inputs: config.max_spend_inputs,
+            max_batch_size: config.max_batch_size,
+            max_spend_fee_percent: config.max_spend_fee_percent,
+            max_spend_fee: Amount::from_sat(config.max_spend_fee),
+            notify_command: config.notify_command,
+            notify_statuses: config.notify_statuses,
+            notify_timeout: config.notify_timeout_secs,
+            alert_tiers: config.alert_tiers,
+            webhook_timeout: config.webhook_timeout_secs,
+            revocation_check_interval: Some(config.revocation_check_interval_secs)
+                .filter(|interval| *interval > time::Duration::from_secs(0)),
+            wallet_audit_interval: Some(config.wallet_audit_interval_secs)
+                .filter(|interval| *interval > time::Duration::from_secs(0)),
+            wallet_audit: None,
+            relay_floor_check_interval: Some(config.relay_floor_check_interval_secs)
+                .filter(|interval| *interval > time::Duration::from_secs(0)),
+            relay_floor_margin_percent: config.relay_floor_margin_percent,
+            relay_floor_check: None,
+            disk_space_warning: config.disk_space_warning_mb.saturating_mul(1024 * 1024),
+            disk_space_critical: config.disk_space_critical_mb.saturating_mul(1024 * 1024),
+            idempotency_retention: config.idempotency_retention_secs,
+            slow_rpc_call: time::Duration::from_millis(config.slow_rpc_call_ms),
             bitcoind_config: config.bitcoind_config,
             tip: None,
+            // Will be updated by the poller
+            feerate_estimates: None,
             // Will be updated by the database
-            current_unused_index: ChildNumber::from(0),
+            deposit_indexes: IndexAllocator::new(DerivationIndex::ZERO),
             // FIXME: we don't need SipHash for those, use a faster alternative
             derivation_index_map: HashMap::new(),
+            unvault_derivation_index_map: HashMap::new(),
             // Will be updated soon (:tm:)
             wallet_id: None,
+            clock,
+            fs_stats: Arc::new(SystemFsStats),
+            log_levels: LogLevelsHandle::new(LogLevels::new(
+                config.log_level,
+                config.log_modules.clone(),
+            )),
         })
     }
 
@@ -517,34 +1387,57 @@ impl RevaultD {
         NoisePubKey(curve25519::scalarmult_base(&scalar).0)
     }
 
-    pub fn vault_address(&self, child_number: ChildNumber) -> Address {
-        self.deposit_descriptor
-            .derive(child_number, &self.secp_ctx)
-            .inner()
-            .address(self.bitcoind_config.network)
-            .expect("deposit_descriptor is a wsh")
+    pub fn vault_address(&self, index: DerivationIndex) -> Result<Address, ScriptTypeError> {
+        descriptor_address(
+            self.derived_deposit_descriptor(index).inner(),
+            self.bitcoind_config.network,
+        )
     }
 
-    pub fn unvault_address(&self, child_number: ChildNumber) -> Address {
-        self.unvault_descriptor
-            .derive(child_number, &self.secp_ctx)
-            .inner()
-            .address(self.bitcoind_config.network)
-            .expect("unvault_descriptor is a wsh")
+    pub fn unvault_address(&self, index: DerivationIndex) -> Result<Address, ScriptTypeError> {
+        descriptor_address(
+            self.derived_unvault_descriptor(index).inner(),
+            self.bitcoind_config.network,
+        )
     }
 
-    pub fn cpfp_address(&self, child_number: ChildNumber) -> Address {
-        self.cpfp_descriptor
-            .derive(child_number, &self.secp_ctx)
-            .inner()
-            .address(self.bitcoind_config.network)
-            .expect("cpfp_descriptor is a wsh")
+    pub fn cpfp_address(&self, index: DerivationIndex) -> Result<Address, ScriptTypeError> {
+        descriptor_address(
+            self.derived_cpfp_descriptor(index).inner(),
+            self.bitcoind_config.network,
+        )
     }
 
     pub fn gap_limit(&self) -> u32 {
         100
     }
 
+    /// How many new deposit addresses we may hand out without them being paid, so that a
+    /// deposit to the last one is still in the window watched by the other participants.
+    pub fn max_reserved_indexes(&self) -> usize {
+        (self.gap_limit() / 2) as usize
+    }
+
+    /// The relative timelock of the Unvault output, in blocks.
+    pub fn unvault_csv(&self) -> u32 {
+        self.unvault_descriptor.csv_value()
+    }
+
+    /// The number of blocks left to be mined before an Unvault output confirmed at this height
+    /// can be spent by the managers. A tip below the Unvault height (after a reorg, until we
+    /// rescanned) gives the whole CSV.
+    pub fn blocks_until_spendable(&self, unvault_height: u32, tip_height: u32) -> u32 {
+        let csv = self.unvault_csv();
+        if tip_height < unvault_height {
+            return csv;
+        }
+
+        // A transaction spending it is valid in the block at unvault_height + csv, hence can
+        // be broadcast once the Unvault has csv - 1 confirmations on top of its own.
+        let confirmations = tip_height - unvault_height + 1;
+        csv.saturating_sub(confirmations)
+    }
+
     pub fn watchonly_wallet_name(&self) -> Option<String> {
         self.wallet_id
             .map(|ref id| format!("revaultd-watchonly-wallet-{}", id))
@@ -556,7 +1449,7 @@ impl RevaultD {
     }
 
     pub fn log_file(&self) -> PathBuf {
-        self.file_from_datadir("log")
+        self.log_file.clone()
     }
 
     pub fn pid_file(&self) -> PathBuf {
@@ -564,7 +1457,18 @@ impl RevaultD {
     }
 
     pub fn db_file(&self) -> PathBuf {
-        self.file_from_datadir("revaultd.sqlite3")
+        self.db_file.clone()
+    }
+
+    /// The space available on the filesystem of the data directory, and how it compares to our
+    /// thresholds.
+    pub fn disk_space(&self) -> io::Result<DiskSpace> {
+        DiskSpace::check(
+            &*self.fs_stats,
+            &self.data_dir,
+            self.disk_space_warning,
+            self.disk_space_critical,
+        )
     }
 
     pub fn watchonly_wallet_file(&self) -> Option<String> {
@@ -586,7 +1490,7 @@ impl RevaultD {
     }
 
     pub fn rpc_socket_file(&self) -> PathBuf {
-        self.file_from_datadir("revaultd_rpc")
+        self.rpc_socket_file.clone()
     }
 
     pub fn is_stakeholder(&self) -> bool {
@@ -597,56 +1501,88 @@ impl RevaultD {
         self.our_man_xpub.is_some()
     }
 
-    pub fn deposit_address(&self) -> Address {
-        self.vault_address(self.current_unused_index)
+    pub fn deposit_address(&self) -> Result<Address, ScriptTypeError> {
+        self.vault_address(self.deposit_indexes.current())
     }
 
-    pub fn last_deposit_address(&self) -> Address {
-        let raw_index: u32 = self.current_unused_index.into();
-        // FIXME: this should fail instead of creating a hardened index
-        self.vault_address(ChildNumber::from(raw_index + self.gap_limit()))
+    /// The highest derivation index we derived the deposit address at
+    pub fn last_derived_index(&self) -> DerivationIndex {
+        self.derivation_index_map
+            .values()
+            .max()
+            .copied()
+            .unwrap_or(DerivationIndex::ZERO)
     }
 
-    pub fn last_unvault_address(&self) -> Address {
-        let raw_index: u32 = self.current_unused_index.into();
-        // FIXME: this should fail instead of creating a hardened index
-        self.unvault_address(ChildNumber::from(raw_index + self.gap_limit()))
+    /// The derivation index we want to watch deposits up to: the gap limit above our next unused
+    /// index, unless it's above the configured ceiling.
+    pub fn deposit_window_end(&self) -> DerivationIndex {
+        cmp::min(
+            self.deposit_indexes
+                .current()
+                .saturating_add(self.gap_limit()),
+            self.deposit_index_ceiling,
+        )
     }
 
-    /// All deposit addresses as strings up to the gap limit (100)
-    pub fn all_deposit_addresses(&mut self) -> Vec<String> {
-        self.derivation_index_map
-            .keys()
-            .map(|s| {
-                Address::from_script(s, self.bitcoind_config.network)
-                    .expect("Created from P2WSH address")
-                    .to_string()
+    /// The deposit and unvault addresses we derived above `since`, or all of them if `None`, to
+    /// be imported into the watchonly wallet. They are derived as the iterator advances, in
+    /// the order of their derivation index.
+    pub fn addresses_to_import(
+        &self,
+        since: Option<DerivationIndex>,
+    ) -> impl Iterator<Item = Result<(Address, DerivationIndex, ScriptKind), ScriptTypeError>> + '_
+    {
+        // We always derive contiguously from the first index.
+        let first_index = match since {
+            Some(index) => index.checked_add(1),
+            None => Some(DerivationIndex::ZERO),
+        };
+        let last_index = self.last_derived_index();
+
+        first_index
+            .into_iter()
+            .flat_map(move |first| first.up_to(last_index))
+            .flat_map(move |index| {
+                iter::once(
+                    self.vault_address(index)
+                        .map(|addr| (addr, index, ScriptKind::Deposit)),
+                )
+                .chain(iter::once(
+                    self.unvault_address(index)
+                        .map(|addr| (addr, index, ScriptKind::Unvault)),
+                ))
             })
-            .collect()
     }
 
-    /// All unvault addresses as strings up to the gap limit (100)
-    pub fn all_unvault_addresses(&mut self) -> Vec<String> {
-        let raw_index: u32 = self.current_unused_index.into();
-        (0..raw_index + self.gap_limit())
-            .map(|raw_index| {
-                // FIXME: this should fail instead of creating a hardened index
-                self.unvault_address(ChildNumber::from(raw_index))
-                    .to_string()
-            })
+    // All the addresses of this kind we derived, as strings
+    fn all_addresses(&self, kind: ScriptKind) -> Result<Vec<String>, ScriptTypeError> {
+        self.addresses_to_import(None)
+            .filter(|res| res.as_ref().map(|(_, _, k)| *k == kind).unwrap_or(true))
+            .map(|res| res.map(|(address, _, _)| address.to_string()))
             .collect()
     }
 
-    pub fn derived_deposit_descriptor(&self, index: ChildNumber) -> DerivedDepositDescriptor {
-        self.deposit_descriptor.derive(index, &self.secp_ctx)
+    /// All deposit addresses as strings we derived
+    pub fn all_deposit_addresses(&self) -> Result<Vec<String>, ScriptTypeError> {
+        self.all_addresses(ScriptKind::Deposit)
     }
 
-    pub fn derived_unvault_descriptor(&self, index: ChildNumber) -> DerivedUnvaultDescriptor {
-        self.unvault_descriptor.derive(index, &self.secp_ctx)
+    /// All unvault addresses as strings we derived
+    pub fn all_unvault_addresses(&self) -> Result<Vec<String>, ScriptTypeError> {
+        self.all_addresses(ScriptKind::Unvault)
     }
 
-    pub fn derived_cpfp_descriptor(&self, index: ChildNumber) -> DerivedCpfpDescriptor {
-        self.cpfp_descriptor.derive(index, &self.secp_ctx)
+    pub fn derived_deposit_descriptor(&self, index: DerivationIndex) -> DerivedDepositDescriptor {
+        self.deposit_descriptor.derive(index.into(), &self.secp_ctx)
+    }
+
+    pub fn derived_unvault_descriptor(&self, index: DerivationIndex) -> DerivedUnvaultDescriptor {
+        self.unvault_descriptor.derive(index.into(), &self.secp_ctx)
+    }
+
+    pub fn derived_cpfp_descriptor(&self, index: DerivationIndex) -> DerivedCpfpDescriptor {
+        self.cpfp_descriptor.derive(index.into(), &self.secp_ctx)
     }
 
     pub fn stakeholders_xpubs(&self) -> Vec<DescriptorPublicKey> {
@@ -672,23 +1608,23 @@ impl RevaultD {
             .collect()
     }
 
-    pub fn stakeholders_xpubs_at(&self, index: ChildNumber) -> Vec<BitcoinPublicKey> {
+    pub fn stakeholders_xpubs_at(&self, index: DerivationIndex) -> Vec<BitcoinPublicKey> {
         self.deposit_descriptor
             .xpubs()
             .into_iter()
             .map(|desc_xpub| {
                 desc_xpub
-                    .derive(index.into())
+                    .derive(index.as_u32())
                     .derive_public_key(&self.secp_ctx)
-                    .expect("Is derived, and there is never any hardened path")
+                    .expect("Is derived, and a DerivationIndex is never hardened")
             })
             .collect()
     }
 
-    pub fn our_stk_xpub_at(&self, index: ChildNumber) -> Option<BitcoinPublicKey> {
+    pub fn our_stk_xpub_at(&self, index: DerivationIndex) -> Option<BitcoinPublicKey> {
         self.our_stk_xpub.map(|xpub| {
-            xpub.derive_pub(&self.secp_ctx, &[index])
-                .expect("The derivation index stored in the database is sane (unhardened)")
+            xpub.derive_pub(&self.secp_ctx, &[index.into()])
+                .expect("A DerivationIndex is never hardened")
                 .public_key
         })
     }
@@ -698,14 +1634,69 @@ impl RevaultD {
             .managers_threshold()
             .unwrap_or(self.managers_xpubs().len())
     }
+
+    /// The participant whose xpub has this fingerprint, as we present it to the user
+    pub fn participant(&self, fingerprint: bip32::Fingerprint) -> Participant {
+        Participant {
+            fingerprint,
+            label: self.key_labels.get(&fingerprint).cloned(),
+        }
+    }
+
+    /// The stakeholders, in the same order as their keys in [RevaultD::stakeholders_xpubs_at]
+    pub fn stakeholders(&self) -> Vec<Participant> {
+        self.stakeholders_xpubs()
+            .into_iter()
+            .filter_map(|xpub| match xpub {
+                DescriptorPublicKey::XPub(xpub) => Some(self.participant(xpub.xkey.fingerprint())),
+                DescriptorPublicKey::SinglePub(_) => None,
+            })
+            .collect()
+    }
+
+    /// The stakeholder whose key at this derivation index is `key`, if any
+    pub fn stakeholder_by_key_at(
+        &self,
+        key: &BitcoinPublicKey,
+        index: DerivationIndex,
+    ) -> Option<Participant> {
+        self.stakeholders_xpubs_at(index)
+            .iter()
+            .position(|k| k == key)
+            .map(|i| self.stakeholders().swap_remove(i))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::RevaultD;
-    use crate::config::Config;
+    use super::{
+        datadir_cpfp_key, descriptor_address, descriptors_script_limits,
+        descriptors_script_version, read_or_create_noise_key, CpfpKeyError, DatadirError,
+        InvalidTransition, NoiseKeyError, RevaultD, ScriptLimits, ScriptTypeError, ScriptVersion,
+        VaultStatus, CPFP_SEED_FILE_SIZE, MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+        MAX_STANDARD_P2WSH_STACK_ITEMS,
+    };
+    use crate::{
+        config::{Config, EXAMPLE_CONFIG},
+        database::{
+            actions::{db_store_derived_scripts, setup_db},
+            schema::ScriptKind,
+        },
+        derivation::{DerivationIndex, HARDENED_INDEX},
+        utils::test_utils::{dummy_revaultd, test_datadir, UserRole},
+        StartupError,
+    };
+    use revault_tx::{
+        bitcoin::{
+            secp256k1,
+            util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey},
+            Address, Network, PublicKey as BitcoinPublicKey,
+        },
+        miniscript::{descriptor::DescriptorTrait, Descriptor},
+        scripts::CpfpDescriptor,
+    };
 
-    use std::path::PathBuf;
+    use std::{collections::HashSet, convert::TryFrom, fs, path::PathBuf, str::FromStr};
 
     #[test]
     fn test_from_config() {
@@ -732,4 +1723,543 @@ mod tests {
         RevaultD::from_config(config).expect("Creating state from config");
         // TODO: test actual fields..
     }
+
+    #[test]
+    fn test_check_config() {
+        let datadir = test_datadir();
+        fs::create_dir_all(&datadir).unwrap();
+        let config_path = datadir.join("revaultd.toml");
+        fs::write(&config_path, EXAMPLE_CONFIG).unwrap();
+        let mut config = Config::from_file(Some(config_path)).expect("Parsing example config");
+
+        // The data directory doesn't exist yet, it'd be created at startup
+        assert!(!config.data_dir.as_ref().unwrap().exists());
+        RevaultD::check_config(&config).unwrap();
+
+        // Nothing is created when checking an existing one
+        let net_datadir = datadir.join("regtest");
+        fs::create_dir_all(&net_datadir).unwrap();
+        config.data_dir = Some(datadir.clone());
+        RevaultD::check_config(&config).unwrap();
+        assert_eq!(fs::read_dir(&net_datadir).unwrap().count(), 0);
+
+        config.rpc_socket_path = Some(PathBuf::from("run/revaultd_rpc"));
+        assert!(matches!(
+            RevaultD::check_config(&config),
+            Err(StartupError::Datadir(DatadirError::MissingParentDir(_)))
+        ));
+        config.create_rpc_socket_dir = Some(true);
+        RevaultD::check_config(&config).unwrap();
+        assert!(!net_datadir.join("run").exists());
+
+        // The keys in the data directory are checked
+        fs::write(net_datadir.join("noise_secret"), &[0; 12]).unwrap();
+        assert!(matches!(
+            RevaultD::check_config(&config),
+            Err(StartupError::Noise(_))
+        ));
+        fs::remove_file(net_datadir.join("noise_secret")).unwrap();
+        fs::write(net_datadir.join("cpfp_secret"), &[0; 12]).unwrap();
+        assert!(matches!(
+            RevaultD::check_config(&config),
+            Err(StartupError::Cpfp(CpfpKeyError::InvalidSeedFile))
+        ));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_cpfp_key_path() {
+        let datadir = test_datadir();
+        fs::create_dir_all(&datadir).unwrap();
+        let datadir_str = datadir.to_str().unwrap();
+        let seed = [7; CPFP_SEED_FILE_SIZE];
+        fs::write(datadir.join("cpfp_secret"), &seed).unwrap();
+
+        // The CPFP descriptor contains a key derived from the seed
+        let secp = secp256k1::Secp256k1::new();
+        let path = DerivationPath::from_str("m/48'/1'/0'/3'").unwrap();
+        let master_key = ExtendedPrivKey::new_master(Network::Testnet, &seed).unwrap();
+        let derived_key = master_key.derive_priv(&secp, &path).unwrap();
+        let cpfp_descriptor = CpfpDescriptor::from_str(&format!(
+            "wsh(thresh(1,pk({}/*)))",
+            ExtendedPubKey::from_private(&secp, &derived_key)
+        ))
+        .unwrap();
+
+        // Without the path, the master key isn't part of it
+        assert!(matches!(
+            datadir_cpfp_key(datadir_str, &cpfp_descriptor, None, Network::Regtest),
+            Err(CpfpKeyError::KeyNotInDescriptor(_))
+        ));
+        assert!(
+            datadir_cpfp_key(datadir_str, &cpfp_descriptor, Some(&path), Network::Regtest).unwrap()
+                == Some(derived_key)
+        );
+
+        // No seed, no key
+        fs::remove_file(datadir.join("cpfp_secret")).unwrap();
+        assert!(
+            datadir_cpfp_key(datadir_str, &cpfp_descriptor, Some(&path), Network::Regtest)
+                .unwrap()
+                .is_none()
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_custom_paths() {
+        let datadir = test_datadir();
+        let mut path = PathBuf::from(file!()).parent().unwrap().to_path_buf();
+        path.push("../test_data/valid_config_man.toml");
+        let mut config = Config::from_file(Some(path)).expect("Parsing valid config file");
+        config.data_dir = Some(datadir.clone());
+
+        // By default, everything is in the datadir
+        let revaultd = RevaultD::from_config(config.clone()).unwrap();
+        assert_eq!(
+            revaultd.db_file(),
+            revaultd.data_dir.join("revaultd.sqlite3")
+        );
+        assert_eq!(revaultd.log_file(), revaultd.data_dir.join("log"));
+        assert_eq!(
+            revaultd.rpc_socket_file(),
+            revaultd.data_dir.join("revaultd_rpc")
+        );
+        let net_datadir = revaultd.data_dir.clone();
+
+        // Relative paths are resolved against the datadir, absolute ones are used as-is
+        let abs_dir = fs::canonicalize(&datadir).unwrap().join("elsewhere");
+        fs::create_dir_all(&abs_dir).unwrap();
+        config.db_path = Some(PathBuf::from("revaultd.sqlite3"));
+        config.log_path = Some(abs_dir.join("revaultd.log"));
+        config.rpc_socket_path = Some(PathBuf::from("run/revaultd_rpc"));
+        // The socket directory does not exist, and we didn't ask for its creation
+        assert!(matches!(
+            RevaultD::from_config(config.clone()),
+            Err(StartupError::Datadir(DatadirError::MissingParentDir(_)))
+        ));
+        config.create_rpc_socket_dir = Some(true);
+        let revaultd = RevaultD::from_config(config.clone()).unwrap();
+        assert_eq!(revaultd.db_file(), net_datadir.join("revaultd.sqlite3"));
+        assert_eq!(revaultd.log_file(), abs_dir.join("revaultd.log"));
+        assert_eq!(
+            revaultd.rpc_socket_file(),
+            net_datadir.join("run/revaultd_rpc")
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let perms = fs::metadata(net_datadir.join("run")).unwrap().permissions();
+            assert_eq!(perms.mode() & 0o777, 0o700);
+        }
+
+        // A read-only target directory is a startup error
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&abs_dir, fs::Permissions::from_mode(0o500)).unwrap();
+            // Permissions are not enforced for root
+            if fs::write(abs_dir.join("probe"), b"").is_err() {
+                let err = RevaultD::from_config(config).err().unwrap();
+                assert!(matches!(
+                    err,
+                    StartupError::Datadir(DatadirError::NotWritable(_, _))
+                ));
+                assert!(err.to_string().contains("is not writable"));
+            }
+            fs::set_permissions(&abs_dir, fs::Permissions::from_mode(0o700)).unwrap();
+        }
+
+        fs::remove_dir_all(&datadir).unwrap();
+    }
+
+    #[test]
+    fn test_script_limits() {
+        // Right at the limits is fine, just past them isn't
+        let mut limits = ScriptLimits {
+            script_size: MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+            witness_elements: MAX_STANDARD_P2WSH_STACK_ITEMS,
+            satisfaction_weight: 10_000,
+        };
+        limits.check("unvault").unwrap();
+        assert_eq!(limits.script_size_headroom(), 0);
+        assert_eq!(limits.witness_elements_headroom(), 0);
+
+        limits.script_size += 1;
+        assert_eq!(limits.script_size_headroom(), -1);
+        let err = limits.check("unvault").unwrap_err();
+        assert_eq!(err.limit, "witness script size");
+        assert_eq!(
+            err.to_string(),
+            "The unvault descriptor exceeds the standard witness script size limit by 1 (3601 \
+             for a maximum of 3600). Its coins could not be spent through the P2P network."
+        );
+
+        limits.script_size -= 1;
+        limits.witness_elements += 1;
+        let err = limits.check("deposit").unwrap_err();
+        assert_eq!(err.limit, "witness stack elements");
+        assert_eq!((err.value, err.max), (101, 100));
+
+        // The scripts of the dummy deployment are far from them. The deposit script is a 2-of-2
+        // multisig, satisfied by the dummy element and the two signatures.
+        let datadir = test_datadir();
+        let revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let [deposit, unvault, cpfp] = descriptors_script_limits(
+            &revaultd.deposit_descriptor,
+            &revaultd.unvault_descriptor,
+            &revaultd.cpfp_descriptor,
+        )
+        .unwrap();
+        assert_eq!(deposit.script_size, 1 + 34 * 2 + 2);
+        assert_eq!(deposit.witness_elements, 3);
+        assert!(deposit.satisfaction_weight > deposit.script_size);
+        for limits in &[deposit, unvault, cpfp] {
+            assert!(limits.script_size_headroom() > 0);
+            assert!(limits.witness_elements_headroom() > 0);
+        }
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+
+        // A chain of multisigs, each needing all its keys, up to exactly 100 witness elements
+        // (the dummy and the signatures of each multisig).
+        let secp = secp256k1::Secp256k1::signing_only();
+        let mut keys = (1..=96u8).map(|i| {
+            let seckey = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+            BitcoinPublicKey {
+                compressed: true,
+                key: secp256k1::PublicKey::from_secret_key(&secp, &seckey),
+            }
+            .to_string()
+        });
+        let mut multi = |n: usize| {
+            let keys: Vec<String> = keys.by_ref().take(n).collect();
+            format!("multi({},{})", n, keys.join(","))
+        };
+        let (m1, m2, m3, m4) = (multi(20), multi(20), multi(20), multi(20));
+        let (last_at_limit, last_past_limit) = (multi(15), multi(16));
+        let chain = |last: &str| {
+            format!(
+                "wsh(and_v(v:{},and_v(v:{},and_v(v:{},and_v(v:{},{})))))",
+                m1, m2, m3, m4, last
+            )
+        };
+
+        let at_limit = ScriptLimits::new(
+            &Descriptor::<BitcoinPublicKey>::from_str(&chain(&last_at_limit)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(at_limit.witness_elements, MAX_STANDARD_P2WSH_STACK_ITEMS);
+        at_limit.check("deposit").unwrap();
+        let past_limit = ScriptLimits::new(
+            &Descriptor::<BitcoinPublicKey>::from_str(&chain(&last_past_limit)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            past_limit.witness_elements,
+            MAX_STANDARD_P2WSH_STACK_ITEMS + 1
+        );
+        assert_eq!(past_limit.witness_elements_headroom(), -1);
+        assert!(past_limit.script_size_headroom() > 0);
+        assert_eq!(
+            past_limit.check("deposit").unwrap_err().to_string(),
+            "The deposit descriptor exceeds the standard witness stack elements limit by 1 (101 \
+             for a maximum of 100). Its coins could not be spent through the P2P network."
+        );
+    }
+
+    #[test]
+    fn test_script_version() {
+        let datadir = test_datadir();
+        let revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        assert_eq!(revaultd.script_version, ScriptVersion::WitnessV0);
+        assert_eq!(
+            descriptors_script_version(
+                &revaultd.deposit_descriptor,
+                &revaultd.unvault_descriptor,
+                &revaultd.cpfp_descriptor,
+            ),
+            Ok(ScriptVersion::WitnessV0)
+        );
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+
+        // We only know about the version we persist
+        assert_eq!(ScriptVersion::try_from(0), Ok(ScriptVersion::WitnessV0));
+        assert_eq!(ScriptVersion::try_from(1), Err(ScriptTypeError::Version(1)));
+
+        // We can't be configured with them for now, but none of the descriptor helpers may
+        // panic if handed a descriptor that isn't a P2WSH.
+        let secp = secp256k1::Secp256k1::signing_only();
+        let seckey = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let key = BitcoinPublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(&secp, &seckey),
+        };
+        let wsh = Descriptor::<BitcoinPublicKey>::from_str(&format!("wsh(pk({}))", key)).unwrap();
+        assert_eq!(
+            ScriptVersion::of_descriptor(&wsh),
+            Some(ScriptVersion::WitnessV0)
+        );
+        assert_eq!(
+            descriptor_address(&wsh, Network::Regtest).unwrap(),
+            wsh.address(Network::Regtest).unwrap()
+        );
+        ScriptLimits::new(&wsh).unwrap();
+        for desc_str in &[
+            format!("pk({})", key),
+            format!("pkh({})", key),
+            format!("wpkh({})", key),
+            format!("sh(wpkh({}))", key),
+            format!("sh(multi(1,{}))", key),
+            format!("sh(wsh(pk({})))", key),
+        ] {
+            let desc = Descriptor::<BitcoinPublicKey>::from_str(desc_str).unwrap();
+            assert_eq!(ScriptVersion::of_descriptor(&desc), None);
+            assert_eq!(
+                descriptor_address(&desc, Network::Regtest),
+                Err(ScriptTypeError::Descriptor(desc.to_string()))
+            );
+            assert_eq!(
+                ScriptLimits::new(&desc).unwrap_err().to_string(),
+                format!(
+                    "Unsupported script type for descriptor '{}'. Only P2WSH descriptors are \
+                     supported.",
+                    desc
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_blocks_until_spendable() {
+        let datadir = test_datadir();
+        let revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        // The dummy Unvault descriptor has a CSV of 6
+        assert_eq!(revaultd.unvault_csv(), 6);
+
+        // Just confirmed
+        assert_eq!(revaultd.blocks_until_spendable(100, 100), 5);
+        assert_eq!(revaultd.blocks_until_spendable(100, 104), 1);
+        // A Spend would be valid in the next block
+        assert_eq!(revaultd.blocks_until_spendable(100, 105), 0);
+        assert_eq!(revaultd.blocks_until_spendable(100, 1_000), 0);
+        // Reorg'ed below the Unvault height
+        assert_eq!(revaultd.blocks_until_spendable(100, 98), 6);
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_deposit_window() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let index = |i| DerivationIndex::new(i).unwrap();
+
+        // We derived the addresses below the gap limit, the poller extends it to the next one
+        assert_eq!(revaultd.last_derived_index(), index(99));
+        assert_eq!(revaultd.deposit_window_end(), index(100));
+
+        // It follows a deposit far above our first unused index, up to the ceiling
+        revaultd.deposit_indexes.reset(index(151), vec![]);
+        assert_eq!(revaultd.deposit_window_end(), index(251));
+        revaultd.deposit_index_ceiling = index(200);
+        assert_eq!(revaultd.deposit_window_end(), index(200));
+
+        // It never gets to hardened indexes
+        revaultd.deposit_index_ceiling = DerivationIndex::MAX;
+        revaultd
+            .deposit_indexes
+            .reset(index(HARDENED_INDEX - 10), vec![]);
+        assert_eq!(revaultd.deposit_window_end(), DerivationIndex::MAX);
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_addresses_to_import() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let index = |i| DerivationIndex::new(i).unwrap();
+
+        // All of them at first, in order of derivation index
+        let addresses: Vec<_> = revaultd
+            .addresses_to_import(None)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(addresses.len(), 200);
+        assert_eq!(
+            addresses[0],
+            (
+                revaultd.vault_address(index(0)).unwrap(),
+                index(0),
+                ScriptKind::Deposit
+            )
+        );
+        assert_eq!(
+            addresses[199],
+            (
+                revaultd.unvault_address(index(99)).unwrap(),
+                index(99),
+                ScriptKind::Unvault
+            )
+        );
+        assert_eq!(revaultd.all_deposit_addresses().unwrap().len(), 100);
+        assert_eq!(revaultd.all_unvault_addresses().unwrap().len(), 100);
+
+        // As the window advances, only the new ones are to be imported
+        let mut imported: HashSet<Address> = addresses.into_iter().map(|(a, _, _)| a).collect();
+        let mut since = Some(revaultd.last_derived_index());
+        for _ in 0..5 {
+            assert_eq!(revaultd.addresses_to_import(since).count(), 0);
+
+            let last_index = revaultd.last_derived_index();
+            let indexes: Vec<DerivationIndex> = last_index
+                .saturating_add(1)
+                .up_to(last_index.saturating_add(7))
+                .collect();
+            db_store_derived_scripts(&mut revaultd, &indexes).unwrap();
+            for res in revaultd.addresses_to_import(since) {
+                let (address, index, _) = res.unwrap();
+                assert!(indexes.contains(&index));
+                assert!(imported.insert(address), "Imported twice");
+            }
+            since = Some(revaultd.last_derived_index());
+        }
+        assert_eq!(imported.len(), 2 * (100 + 5 * 7));
+
+        // We never go past the last unhardened index
+        assert_eq!(
+            revaultd
+                .addresses_to_import(Some(DerivationIndex::MAX))
+                .count(),
+            0
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_noise_key_file() {
+        let datadir = test_datadir();
+        fs::create_dir_all(&datadir).unwrap();
+
+        // Created on first run, read back afterward
+        let secret_file = datadir.join("noise_secret");
+        // A leftover from a crash during a previous creation
+        fs::write(secret_file.with_extension("tmp"), &[1u8; 12]).unwrap();
+        let key = read_or_create_noise_key(secret_file.clone()).unwrap();
+        assert!(!secret_file.with_extension("tmp").exists());
+        assert_eq!(
+            read_or_create_noise_key(secret_file.clone()).unwrap().0,
+            key.0
+        );
+        let content = fs::read(&secret_file).unwrap();
+        assert_eq!(content.len(), 40);
+
+        // Files written by previous versions only contain the key
+        let legacy_file = datadir.join("legacy_noise_secret");
+        fs::write(&legacy_file, &key.0).unwrap();
+        assert_eq!(read_or_create_noise_key(legacy_file).unwrap().0, key.0);
+
+        // Truncated file
+        let truncated_file = datadir.join("truncated_noise_secret");
+        fs::write(&truncated_file, &content[..20]).unwrap();
+        let err = read_or_create_noise_key(truncated_file).unwrap_err();
+        assert!(matches!(err, NoiseKeyError::Corrupted(_)));
+        assert!(err.to_string().contains("restore it from a backup"));
+        let empty_file = datadir.join("empty_noise_secret");
+        fs::write(&empty_file, b"").unwrap();
+        assert!(matches!(
+            read_or_create_noise_key(empty_file),
+            Err(NoiseKeyError::Corrupted(_))
+        ));
+
+        // Zeroed file
+        let zeroed_file = datadir.join("zeroed_noise_secret");
+        fs::write(&zeroed_file, &[0u8; 40]).unwrap();
+        assert!(matches!(
+            read_or_create_noise_key(zeroed_file.clone()),
+            Err(NoiseKeyError::Corrupted(_))
+        ));
+        fs::write(&zeroed_file, &[0u8; 32]).unwrap();
+        assert!(matches!(
+            read_or_create_noise_key(zeroed_file),
+            Err(NoiseKeyError::NullKey)
+        ));
+
+        // Wrong length
+        let long_file = datadir.join("long_noise_secret");
+        let mut long_content = content.clone();
+        long_content.push(0);
+        fs::write(&long_file, &long_content).unwrap();
+        assert!(matches!(
+            read_or_create_noise_key(long_file),
+            Err(NoiseKeyError::Corrupted(_))
+        ));
+
+        // Flipped bit
+        let flipped_file = datadir.join("flipped_noise_secret");
+        let mut flipped_content = content;
+        flipped_content[10] ^= 1;
+        fs::write(&flipped_file, &flipped_content).unwrap();
+        assert!(matches!(
+            read_or_create_noise_key(flipped_file),
+            Err(NoiseKeyError::Corrupted(_))
+        ));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn vault_status_transitions() {
+        // The rows are the statuses we transition from, the columns the statuses we transition
+        // to, both in the order of their numeric value:
+        // unconfirmed, funded, securing, secured, activating, active, unvaulting, unvaulted,
+        // canceling, canceled, emergencyvaulting, emergencyvaulted, unvaultemergencyvaulting,
+        // unvaultemergencyvaulted, spending, spent, spendable.
+        #[rustfmt::skip]
+        const LEGAL: [[u8; 17]; 17] = [
+            /* unconfirmed */   [1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            /* funded */        [1, 1, 1, 1, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0],
+            /* securing */      [1, 0, 1, 1, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0],
+            /* secured */       [1, 0, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0],
+            /* activating */    [1, 0, 0, 0, 1, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0],
+            /* active */        [1, 0, 0, 0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0],
+            /* unvaulting */    [0, 0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 1, 0, 1, 0, 0],
+            /* unvaulted */     [0, 0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 1, 0, 1, 0, 1],
+            /* canceling */     [0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+            /* canceled */      [0, 0, 0, 0, 0, 0, 1, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+            /* emervaulting */  [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0],
+            /* emervaulted */   [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0],
+            /* unemervaulting */[0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 0],
+            /* unemervaulted */ [0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0],
+            /* spending */      [0, 0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 1, 1, 0],
+            /* spent */         [0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0],
+            /* spendable */     [0, 0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 1, 0, 1, 0, 1],
+        ];
+
+        // Make sure we don't forget about a status if one is ever added
+        assert!(VaultStatus::try_from(LEGAL.len() as u32).is_err());
+
+        for (from_n, row) in LEGAL.iter().enumerate() {
+            let from = VaultStatus::try_from(from_n as u32).unwrap();
+            for (to_n, legal) in row.iter().enumerate() {
+                let to = VaultStatus::try_from(to_n as u32).unwrap();
+                if *legal == 1 {
+                    assert_eq!(from.transition(to), Ok(()), "{} -> {}", from, to);
+                } else {
+                    assert_eq!(
+                        from.transition(to),
+                        Err(InvalidTransition { from, to }),
+                        "{} -> {}",
+                        from,
+                        to
+                    );
+                }
+            }
+        }
+    }
 }
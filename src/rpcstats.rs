@@ -0,0 +1,332 @@
+//! How long the RPC calls take. We record the duration of each call by method, and account
+//! for the time spent in the internal phases which may be slow (the database, bitcoind and the
+//! servers) with span timers on the thread handling the call, so that a slow call can be
+//! attributed.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+// How many of the last calls to a method to compute the percentiles over
+const LATENCY_SAMPLES: usize = 1000;
+
+/// A part of the handling of an RPC call that we account the time of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Database,
+    Bitcoind,
+    Coordinator,
+    Cosigner,
+    Watchtower,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Database => write!(f, "database"),
+            Self::Bitcoind => write!(f, "bitcoind"),
+            Self::Coordinator => write!(f, "coordinator"),
+            Self::Cosigner => write!(f, "cosigner"),
+            Self::Watchtower => write!(f, "watchtower"),
+        }
+    }
+}
+
+const N_PHASES: usize = 5;
+const PHASES: [Phase; N_PHASES] = [
+    Phase::Database,
+    Phase::Bitcoind,
+    Phase::Coordinator,
+    Phase::Cosigner,
+    Phase::Watchtower,
+];
+
+// The phases entered during the call being handled on this thread. Only the innermost one is
+// accounted for, so a phase nested in another (a database query while talking to the
+// Coordinator) isn't counted twice.
+#[derive(Debug, Default)]
+struct Spans {
+    next_id: u64,
+    // The id, phase and start (or resumption) of the ones in progress
+    stack: Vec<(u64, Phase, Instant)>,
+    totals: [Duration; N_PHASES],
+}
+
+thread_local! {
+    static SPANS: RefCell<Option<Spans>> = RefCell::new(None);
+}
+
+/// Accounts for the time until it's dropped in a phase of the RPC call being handled on this
+/// thread, if any.
+#[derive(Debug)]
+pub struct PhaseTimer(Option<u64>);
+
+/// Start accounting for the time spent in this phase, until the returned timer is dropped. It
+/// does nothing outside of an RPC call.
+pub fn phase_timer(phase: Phase) -> PhaseTimer {
+    let id = SPANS.with(|spans| {
+        spans.borrow_mut().as_mut().map(|spans| {
+            let now = Instant::now();
+            // The enclosing one is paused
+            if let Some(&(_, outer, start)) = spans.stack.last() {
+                spans.totals[outer as usize] += now.saturating_duration_since(start);
+            }
+            let id = spans.next_id;
+            spans.next_id += 1;
+            spans.stack.push((id, phase, now));
+            id
+        })
+    });
+
+    PhaseTimer(id)
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        let id = match self.0 {
+            Some(id) => id,
+            None => return,
+        };
+        SPANS.with(|spans| {
+            if let Some(spans) = spans.borrow_mut().as_mut() {
+                let now = Instant::now();
+                match spans.stack.iter().position(|(i, _, _)| *i == id) {
+                    Some(pos) if pos + 1 == spans.stack.len() => {
+                        let (_, phase, start) = spans.stack.pop().expect("Just checked");
+                        spans.totals[phase as usize] += now.saturating_duration_since(start);
+                        // The enclosing one is resumed
+                        if let Some(outer) = spans.stack.last_mut() {
+                            outer.2 = now;
+                        }
+                    }
+                    // Its time was accounted for when the following one was entered
+                    Some(pos) => {
+                        spans.stack.remove(pos);
+                    }
+                    // It was started for another call
+                    None => {}
+                }
+            }
+        })
+    }
+}
+
+/// The time spent in each phase during a call
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhaseDurations([Duration; N_PHASES]);
+
+impl PhaseDurations {
+    pub fn get(&self, phase: Phase) -> Duration {
+        self.0[phase as usize]
+    }
+
+    /// The phase the most time was spent in, if any
+    pub fn slowest(&self) -> Option<Phase> {
+        PHASES
+            .iter()
+            .copied()
+            .filter(|phase| self.get(*phase) > Duration::from_secs(0))
+            .max_by_key(|phase| self.get(*phase))
+    }
+}
+
+/// Run `handler` as an RPC call, returning its result along with how long it took and the
+/// time it spent in each phase.
+pub fn timed_call<T, F: FnOnce() -> T>(handler: F) -> (T, Duration, PhaseDurations) {
+    // Calls aren't nested, but don't mess up the accounting of the enclosing one if they ever
+    // are.
+    let enclosing = SPANS.with(|spans| spans.replace(Some(Spans::default())));
+    let start = Instant::now();
+    let res = handler();
+    let duration = start.elapsed();
+    let spans = SPANS
+        .with(|spans| spans.replace(enclosing))
+        .expect("Set above");
+
+    (res, duration, PhaseDurations(spans.totals))
+}
+
+// The calls to a method since the last reset
+#[derive(Debug, Default)]
+struct MethodStats {
+    calls: u64,
+    max: Duration,
+    // The duration of the last calls
+    recent: VecDeque<Duration>,
+}
+
+// The nearest-rank percentile of these sorted durations
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_secs(0);
+    }
+    let rank = (sorted.len() * percent + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+/// The latencies of the calls to an RPC method
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RpcMethodStats {
+    pub method: String,
+    pub calls: u64,
+    /// The median and 95th percentile durations over the last calls, in microseconds
+    pub p50_us: u64,
+    pub p95_us: u64,
+    /// The longest call, in microseconds
+    pub max_us: u64,
+}
+
+/// The latencies of the RPC calls since startup or since they were last reset
+#[derive(Debug)]
+pub struct RpcStats {
+    since: u64,
+    methods: HashMap<String, MethodStats>,
+}
+
+impl RpcStats {
+    /// Start recording at this timestamp
+    pub fn new(since: u64) -> Self {
+        Self {
+            since,
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Record a call to this method
+    pub fn record(&mut self, method: &str, duration: Duration) {
+        let stats = self.methods.entry(method.to_string()).or_default();
+        stats.calls += 1;
+        stats.max = stats.max.max(duration);
+        if stats.recent.len() == LATENCY_SAMPLES {
+            stats.recent.pop_front();
+        }
+        stats.recent.push_back(duration);
+    }
+
+    /// Forget all the calls recorded until this timestamp
+    pub fn reset(&mut self, now: u64) {
+        self.since = now;
+        self.methods.clear();
+    }
+
+    /// Since when we are recording
+    pub fn since(&self) -> u64 {
+        self.since
+    }
+
+    /// The latencies of each method called, by name
+    pub fn methods(&self) -> Vec<RpcMethodStats> {
+        let mut methods: Vec<RpcMethodStats> = self
+            .methods
+            .iter()
+            .map(|(method, stats)| {
+                let mut sorted: Vec<Duration> = stats.recent.iter().copied().collect();
+                sorted.sort();
+                RpcMethodStats {
+                    method: method.clone(),
+                    calls: stats.calls,
+                    p50_us: percentile(&sorted, 50).as_micros() as u64,
+                    p95_us: percentile(&sorted, 95).as_micros() as u64,
+                    max_us: stats.max.as_micros() as u64,
+                }
+            })
+            .collect();
+        methods.sort_by(|a, b| a.method.cmp(&b.method));
+
+        methods
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn phase_accounting() {
+        // Outside of a call, nothing is recorded
+        drop(phase_timer(Phase::Database));
+
+        let ((), duration, phases) = timed_call(|| {
+            let _db = phase_timer(Phase::Database);
+            thread::sleep(Duration::from_millis(20));
+            {
+                // Nested, only accounted for in the inner phase
+                let _coord = phase_timer(Phase::Coordinator);
+                thread::sleep(Duration::from_millis(50));
+            }
+            thread::sleep(Duration::from_millis(10));
+        });
+        assert!(duration >= Duration::from_millis(80));
+        let db = phases.get(Phase::Database);
+        assert!(db >= Duration::from_millis(30) && db < Duration::from_millis(50));
+        assert!(phases.get(Phase::Coordinator) >= Duration::from_millis(50));
+        assert_eq!(phases.get(Phase::Bitcoind), Duration::from_secs(0));
+        assert_eq!(phases.slowest(), Some(Phase::Coordinator));
+
+        // Timers dropped out of order don't mess up the accounting
+        let ((), _, phases) = timed_call(|| {
+            let cosig = phase_timer(Phase::Cosigner);
+            let bitcoind = phase_timer(Phase::Bitcoind);
+            thread::sleep(Duration::from_millis(20));
+            drop(cosig);
+            thread::sleep(Duration::from_millis(20));
+            drop(bitcoind);
+        });
+        assert!(phases.get(Phase::Bitcoind) >= Duration::from_millis(40));
+        assert!(phases.get(Phase::Cosigner) < Duration::from_millis(20));
+
+        // A call spending no time in any phase
+        let (res, _, phases) = timed_call(|| 42);
+        assert_eq!(res, 42);
+        assert_eq!(phases, PhaseDurations::default());
+        assert_eq!(phases.slowest(), None);
+    }
+
+    #[test]
+    fn method_stats() {
+        let mut stats = RpcStats::new(1);
+        for ms in 1..=100 {
+            stats.record("getinfo", Duration::from_millis(ms));
+        }
+        stats.record("listvaults", Duration::from_micros(5));
+        assert_eq!(
+            stats.methods(),
+            vec![
+                RpcMethodStats {
+                    method: "getinfo".to_string(),
+                    calls: 100,
+                    p50_us: 50_000,
+                    p95_us: 95_000,
+                    max_us: 100_000,
+                },
+                RpcMethodStats {
+                    method: "listvaults".to_string(),
+                    calls: 1,
+                    p50_us: 5,
+                    p95_us: 5,
+                    max_us: 5,
+                }
+            ]
+        );
+
+        // The percentiles are over the last calls, the max since the reset
+        for _ in 0..LATENCY_SAMPLES {
+            stats.record("getinfo", Duration::from_millis(1));
+        }
+        let getinfo = stats.methods().remove(0);
+        assert_eq!(getinfo.calls, 100 + LATENCY_SAMPLES as u64);
+        assert_eq!((getinfo.p50_us, getinfo.p95_us), (1_000, 1_000));
+        assert_eq!(getinfo.max_us, 100_000);
+
+        stats.reset(2);
+        assert_eq!(stats.since(), 2);
+        assert!(stats.methods().is_empty());
+    }
+}
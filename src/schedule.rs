@@ -0,0 +1,741 @@
+//! An optional schedule restricting when a manager may initiate a spend.
+//!
+//! Some deployments want the daemon to refuse to broadcast Unvault transactions outside business
+//! hours, on top of the policies of the cosigning servers. The schedule is a set of weekly windows
+//! evaluated in a configured timezone. The timezone is a POSIX TZ string (as in the `TZ`
+//! environment variable, for instance "CET-1CEST,M3.5.0,M10.5.0/3") so that daylight saving time
+//! is accounted for without depending on the timezone database of the system.
+
+use std::{error, fmt};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+const SECS_PER_DAY: i64 = 24 * 3600;
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// An invalid spending schedule
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleError(pub String);
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Spending schedule error: {}", self.0)
+    }
+}
+
+impl error::Error for ScheduleError {}
+
+// Days since the UNIX epoch of this date of the proleptic Gregorian calendar.
+// See http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// The year of the date this number of days after the UNIX epoch.
+fn year_from_days(days: i64) -> i64 {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    yoe + era * 400 + if mp >= 10 { 1 } else { 0 }
+}
+
+fn days_in_month(year: i64, month: u32) -> i64 {
+    let next_month = if month == 12 {
+        days_from_civil(year + 1, 1, 1)
+    } else {
+        days_from_civil(year, month + 1, 1)
+    };
+    next_month - days_from_civil(year, month, 1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ];
+
+    // The UNIX epoch was a Thursday
+    fn from_days(days: i64) -> Self {
+        Self::ALL[(days + 3).rem_euclid(7) as usize]
+    }
+
+    fn prev(self) -> Self {
+        Self::ALL[(self as usize + 6) % 7]
+    }
+}
+
+/// A time of the day, with a minute precision. "24:00" may only be used as the end of a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(u32);
+
+impl TimeOfDay {
+    pub fn from_hm(hours: u32, minutes: u32) -> Option<Self> {
+        if minutes >= 60 || hours > 24 || hours * 60 + minutes > MINUTES_PER_DAY {
+            return None;
+        }
+        Some(Self(hours * 60 + minutes))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let mut parts = s.splitn(2, ':');
+        let hours = parts.next().and_then(|h| h.parse::<u32>().ok());
+        let minutes = parts
+            .next()
+            .filter(|m| m.len() == 2)
+            .and_then(|m| m.parse::<u32>().ok());
+        match (hours, minutes) {
+            (Some(hours), Some(minutes)) => TimeOfDay::from_hm(hours, minutes),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            de::Error::custom(format!("Invalid time of the day '{}', expected HH:MM", s))
+        })
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+// The n-th (5 is the last) weekday (0 is Sunday) of a month, as in POSIX TZ's "Mm.w.d"
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TransitionDate {
+    month: u32,
+    week: u32,
+    weekday: u32,
+}
+
+impl TransitionDate {
+    // Days since the UNIX epoch of this date in the given year
+    fn days_in(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        let first_weekday = (first + 4).rem_euclid(7) as u32;
+        let mut day = (1 + (self.weekday + 7 - first_weekday) % 7 + 7 * (self.week - 1)) as i64;
+        while day > days_in_month(year, self.month) {
+            day -= 7;
+        }
+        first + day - 1
+    }
+}
+
+// When daylight saving time applies. Transitions happen at a local time: the start one in
+// standard time, the end one in daylight saving time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DstRule {
+    utc_offset: i64,
+    start: TransitionDate,
+    start_time: i64,
+    end: TransitionDate,
+    end_time: i64,
+}
+
+// A minimal parser for POSIX TZ strings, only supporting the "Mm.w.d" rules
+struct TzParser<'a> {
+    spec: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TzParser<'a> {
+    fn error(&self, what: &str) -> ScheduleError {
+        ScheduleError(format!(
+            "Invalid timezone '{}': {} at position {}",
+            String::from_utf8_lossy(self.spec),
+            what,
+            self.pos
+        ))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.spec.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos == self.spec.len()
+    }
+
+    fn number(&mut self) -> Result<i64, ScheduleError> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit()) && self.pos - start < 3 {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("expected a number"));
+        }
+        Ok(std::str::from_utf8(&self.spec[start..self.pos])
+            .expect("Only ASCII digits")
+            .parse()
+            .expect("At most 3 digits"))
+    }
+
+    // Either alphabetic or quoted between angle brackets, at least 3 characters
+    fn name(&mut self) -> Result<(), ScheduleError> {
+        let start = self.pos;
+        if self.eat(b'<') {
+            while matches!(self.peek(), Some(c) if c != b'>') {
+                self.pos += 1;
+            }
+            if !self.eat(b'>') || self.pos - start < 5 {
+                return Err(self.error("invalid quoted name"));
+            }
+        } else {
+            while matches!(self.peek(), Some(c) if c.is_ascii_alphabetic()) {
+                self.pos += 1;
+            }
+            if self.pos - start < 3 {
+                return Err(self.error("expected a name of at least 3 letters"));
+            }
+        }
+        Ok(())
+    }
+
+    // [+|-]hh[:mm[:ss]], in seconds
+    fn time(&mut self) -> Result<i64, ScheduleError> {
+        let sign = if self.eat(b'-') {
+            -1
+        } else {
+            self.eat(b'+');
+            1
+        };
+        let hours = self.number()?;
+        let minutes = if self.eat(b':') { self.number()? } else { 0 };
+        let seconds = if self.eat(b':') { self.number()? } else { 0 };
+        if hours > 167 || minutes >= 60 || seconds >= 60 {
+            return Err(self.error("time out of range"));
+        }
+        Ok(sign * (hours * 3600 + minutes * 60 + seconds))
+    }
+
+    // Mm.w.d[/time]
+    fn transition(&mut self) -> Result<(TransitionDate, i64), ScheduleError> {
+        if !self.eat(b'M') {
+            return Err(self.error("only 'Mm.w.d' rules are supported"));
+        }
+        let month = self.number()?;
+        if !self.eat(b'.') {
+            return Err(self.error("expected '.'"));
+        }
+        let week = self.number()?;
+        if !self.eat(b'.') {
+            return Err(self.error("expected '.'"));
+        }
+        let weekday = self.number()?;
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+            return Err(self.error("date out of range"));
+        }
+        let time = if self.eat(b'/') { self.time()? } else { 7200 };
+
+        Ok((
+            TransitionDate {
+                month: month as u32,
+                week: week as u32,
+                weekday: weekday as u32,
+            },
+            time,
+        ))
+    }
+}
+
+/// A timezone, as a POSIX TZ string
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeZone {
+    spec: String,
+    utc_offset: i64,
+    dst: Option<DstRule>,
+}
+
+impl TimeZone {
+    pub fn from_posix(spec: &str) -> Result<Self, ScheduleError> {
+        // For convenience, as it's the one most likely to be used without an offset
+        if spec == "UTC" {
+            return Ok(Self {
+                spec: spec.to_string(),
+                utc_offset: 0,
+                dst: None,
+            });
+        }
+
+        let mut parser = TzParser {
+            spec: spec.as_bytes(),
+            pos: 0,
+        };
+        parser.name()?;
+        // POSIX offsets are positive west of Greenwich
+        let utc_offset = -parser.time()?;
+        if parser.is_done() {
+            return Ok(Self {
+                spec: spec.to_string(),
+                utc_offset,
+                dst: None,
+            });
+        }
+
+        parser.name()?;
+        let dst_offset = if matches!(parser.peek(), Some(b',')) {
+            utc_offset + 3600
+        } else {
+            -parser.time()?
+        };
+        if !parser.eat(b',') {
+            return Err(parser.error("expected the daylight saving time rules"));
+        }
+        let (start, start_time) = parser.transition()?;
+        if !parser.eat(b',') {
+            return Err(parser.error("expected ','"));
+        }
+        let (end, end_time) = parser.transition()?;
+        if !parser.is_done() {
+            return Err(parser.error("unexpected trailing characters"));
+        }
+
+        Ok(Self {
+            spec: spec.to_string(),
+            utc_offset,
+            dst: Some(DstRule {
+                utc_offset: dst_offset,
+                start,
+                start_time,
+                end,
+                end_time,
+            }),
+        })
+    }
+
+    /// The offset to UTC, in seconds, of the local time at this UNIX timestamp
+    pub fn utc_offset_at(&self, timestamp: i64) -> i64 {
+        let dst = match self.dst {
+            Some(ref dst) => dst,
+            None => return self.utc_offset,
+        };
+
+        let year = year_from_days((timestamp + self.utc_offset).div_euclid(SECS_PER_DAY));
+        let start = dst.start.days_in(year) * SECS_PER_DAY + dst.start_time - self.utc_offset;
+        let end = dst.end.days_in(year) * SECS_PER_DAY + dst.end_time - dst.utc_offset;
+        // In the southern hemisphere, daylight saving time spans the new year.
+        let is_dst = if start < end {
+            timestamp >= start && timestamp < end
+        } else {
+            timestamp < end || timestamp >= start
+        };
+
+        if is_dst {
+            dst.utc_offset
+        } else {
+            self.utc_offset
+        }
+    }
+}
+
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeZone {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        TimeZone::from_posix(&s).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for TimeZone {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.spec)
+    }
+}
+
+/// A time range during which spending is allowed on some days of the week. If it ends before it
+/// starts, it spans midnight and ends the day after.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpendingWindow {
+    pub days: Vec<Weekday>,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl SpendingWindow {
+    fn contains(&self, weekday: Weekday, minute: u32) -> bool {
+        if self.start < self.end {
+            self.days.contains(&weekday) && minute >= self.start.0 && minute < self.end.0
+        } else {
+            (self.days.contains(&weekday) && minute >= self.start.0)
+                || (self.days.contains(&weekday.prev()) && minute < self.end.0)
+        }
+    }
+}
+
+/// When we may initiate a spend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpendingSchedule {
+    pub timezone: TimeZone,
+    pub windows: Vec<SpendingWindow>,
+}
+
+impl SpendingSchedule {
+    /// Sanity check the windows, as they can't be checked on their own when deserializing.
+    pub fn check(&self) -> Result<(), ScheduleError> {
+        if self.windows.is_empty() {
+            return Err(ScheduleError(
+                "No spending window, spending would never be allowed".to_string(),
+            ));
+        }
+        for window in self.windows.iter() {
+            if window.days.is_empty() {
+                return Err(ScheduleError(format!(
+                    "Spending window {}-{} applies to no day",
+                    window.start, window.end
+                )));
+            }
+            if window.start.0 >= MINUTES_PER_DAY {
+                return Err(ScheduleError(format!(
+                    "Spending window can't start at {}",
+                    window.start
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether spending is allowed at this UNIX timestamp
+    pub fn allows(&self, timestamp: u64) -> bool {
+        let timestamp = timestamp as i64;
+        let local = timestamp + self.timezone.utc_offset_at(timestamp);
+        let weekday = Weekday::from_days(local.div_euclid(SECS_PER_DAY));
+        let minute = (local.rem_euclid(SECS_PER_DAY) / 60) as u32;
+
+        self.windows
+            .iter()
+            .any(|window| window.contains(weekday, minute))
+    }
+
+    /// The UNIX timestamp at which the next window after this one starts. None if spending is never
+    /// allowed within a week, which may happen if the only window doesn't exist because of a
+    /// daylight saving time change.
+    pub fn next_window(&self, timestamp: u64) -> Option<u64> {
+        // Just try every minute, there are only 10080 of them in a week. This way we don't need
+        // to care about the windows that don't exist or happen twice on a DST change.
+        let first_minute = timestamp / 60 + 1;
+        (first_minute..first_minute + 8 * u64::from(MINUTES_PER_DAY))
+            .map(|minute| minute * 60)
+            .find(|timestamp| self.allows(*timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(
+        timezone: &str,
+        windows: &[(&[Weekday], (u32, u32), (u32, u32))],
+    ) -> SpendingSchedule {
+        SpendingSchedule {
+            timezone: TimeZone::from_posix(timezone).unwrap(),
+            windows: windows
+                .iter()
+                .map(|(days, start, end)| SpendingWindow {
+                    days: days.to_vec(),
+                    start: TimeOfDay::from_hm(start.0, start.1).unwrap(),
+                    end: TimeOfDay::from_hm(end.0, end.1).unwrap(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn calendar() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(2021, 3, 28), 18_714);
+        for days in &[0, 58, 59, 365, 11_016, 11_017, 18_627, 18_628, 18_992] {
+            let year = year_from_days(*days);
+            assert!(days_from_civil(year, 1, 1) <= *days);
+            assert!(days_from_civil(year + 1, 1, 1) > *days);
+        }
+        assert_eq!(days_in_month(2020, 2), 29);
+        assert_eq!(days_in_month(2021, 2), 28);
+        assert_eq!(days_in_month(2021, 12), 31);
+        // 2021-03-28 was the last Sunday of March, 2021-10-31 the last one of October.
+        let last_sunday = |month| TransitionDate {
+            month,
+            week: 5,
+            weekday: 0,
+        };
+        assert_eq!(last_sunday(3).days_in(2021), 18_714);
+        assert_eq!(last_sunday(10).days_in(2021), days_from_civil(2021, 10, 31));
+        // 2021-10-03 was the first Sunday of October
+        let first_sunday = TransitionDate {
+            month: 10,
+            week: 1,
+            weekday: 0,
+        };
+        assert_eq!(first_sunday.days_in(2021), days_from_civil(2021, 10, 3));
+        assert_eq!(Weekday::from_days(0), Weekday::Thu);
+        assert_eq!(Weekday::from_days(18_714), Weekday::Sun);
+        assert_eq!(Weekday::Mon.prev(), Weekday::Sun);
+    }
+
+    #[test]
+    fn timezone_parsing() {
+        let utc = TimeZone::from_posix("UTC").unwrap();
+        assert_eq!(utc.utc_offset_at(1_600_000_000), 0);
+        let ny = TimeZone::from_posix("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        assert_eq!(ny.utc_offset, -5 * 3600);
+        assert_eq!(ny.dst.unwrap().utc_offset, -4 * 3600);
+        let india = TimeZone::from_posix("<+0530>-5:30").unwrap();
+        assert_eq!(india.utc_offset_at(1_600_000_000), 5 * 3600 + 30 * 60);
+        assert_eq!(india.to_string(), "<+0530>-5:30");
+
+        for invalid in &[
+            "",
+            "CET",
+            "C-1",
+            "CET-1CEST",
+            "CET-1CEST,J60,J300",
+            "CET-1CEST,M3.5.0",
+            "CET-1CEST,M13.5.0,M10.5.0/3",
+            "CET-1CEST,M3.6.0,M10.5.0/3",
+            "CET-1CEST,M3.5.0,M10.5.0/3 ",
+            "CET-1:60",
+        ] {
+            TimeZone::from_posix(invalid).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn dst_transitions() {
+        let paris = TimeZone::from_posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // 2021-03-28T01:00:00Z, 02:00 in standard time becomes 03:00 in daylight saving time
+        assert_eq!(paris.utc_offset_at(1_616_893_199), 3600);
+        assert_eq!(paris.utc_offset_at(1_616_893_200), 7200);
+        // 2021-10-31T01:00:00Z, 03:00 in daylight saving time becomes 02:00 in standard time
+        assert_eq!(paris.utc_offset_at(1_635_641_999), 7200);
+        assert_eq!(paris.utc_offset_at(1_635_642_000), 3600);
+        // Winter
+        assert_eq!(paris.utc_offset_at(1_609_459_200), 3600);
+
+        // In the southern hemisphere, it's daylight saving time around the new year.
+        // 2021-04-04T03:00:00 local daylight time, 2021-10-03T02:00:00 local standard time.
+        let sydney = TimeZone::from_posix("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.utc_offset_at(1_609_459_200), 11 * 3600);
+        assert_eq!(sydney.utc_offset_at(1_617_465_599), 11 * 3600);
+        assert_eq!(sydney.utc_offset_at(1_617_465_600), 10 * 3600);
+        assert_eq!(sydney.utc_offset_at(1_633_190_399), 10 * 3600);
+        assert_eq!(sydney.utc_offset_at(1_633_190_400), 11 * 3600);
+    }
+
+    #[test]
+    fn business_hours() {
+        use Weekday::*;
+        let sched = schedule("UTC", &[(&[Mon, Tue, Wed, Thu, Fri], (9, 0), (18, 0))]);
+        sched.check().unwrap();
+
+        // Friday 2021-01-08
+        let friday = 1_610_064_000;
+        assert!(!sched.allows(friday + 9 * 3600 - 1));
+        assert!(sched.allows(friday + 9 * 3600));
+        assert!(sched.allows(friday + 18 * 3600 - 1));
+        assert!(!sched.allows(friday + 18 * 3600));
+        // Nothing on the week end, next window is on Monday morning
+        assert!(!sched.allows(friday + SECS_PER_DAY as u64 + 12 * 3600));
+        assert_eq!(
+            sched.next_window(friday + 18 * 3600),
+            Some(friday + 3 * SECS_PER_DAY as u64 + 9 * 3600)
+        );
+        assert_eq!(
+            sched.next_window(friday + 9 * 3600 - 30),
+            Some(friday + 9 * 3600)
+        );
+    }
+
+    #[test]
+    fn around_midnight() {
+        use Weekday::*;
+        // From Friday 22:00 to Saturday 02:00, and the whole Sunday in Paris
+        let sched = schedule(
+            "CET-1CEST,M3.5.0,M10.5.0/3",
+            &[(&[Fri], (22, 0), (2, 0)), (&[Sun], (0, 0), (24, 0))],
+        );
+        sched.check().unwrap();
+
+        // Friday 2021-01-08 00:00 in Paris is 2021-01-07T23:00:00Z
+        let friday = 1_610_060_400;
+        assert!(!sched.allows(friday + 22 * 3600 - 60));
+        assert!(sched.allows(friday + 22 * 3600));
+        assert!(sched.allows(friday + 24 * 3600));
+        assert!(sched.allows(friday + 26 * 3600 - 60));
+        assert!(!sched.allows(friday + 26 * 3600));
+        // Thursday night doesn't count
+        assert!(!sched.allows(friday - 3600));
+        assert!(!sched.allows(friday + 3600));
+        // From Saturday 02:00 to Sunday 00:00
+        assert_eq!(
+            sched.next_window(friday + 26 * 3600),
+            Some(friday + 48 * 3600)
+        );
+        assert!(sched.allows(friday + 72 * 3600 - 60));
+        assert!(!sched.allows(friday + 72 * 3600));
+        // Then next Friday night
+        assert_eq!(
+            sched.next_window(friday + 72 * 3600),
+            Some(friday + 7 * 24 * 3600 + 22 * 3600)
+        );
+
+        // Every window can't start at the end of a day
+        let mut invalid = sched.clone();
+        invalid.windows[0].start = TimeOfDay::from_hm(24, 0).unwrap();
+        invalid.check().unwrap_err();
+        invalid.windows[0].start = TimeOfDay::from_hm(0, 0).unwrap();
+        invalid.windows[0].days.clear();
+        invalid.check().unwrap_err();
+        invalid.windows.clear();
+        invalid.check().unwrap_err();
+    }
+
+    #[test]
+    fn around_dst_changes() {
+        use Weekday::*;
+        // Sundays from 02:30 to 03:30 in Paris
+        let sched = schedule("CET-1CEST,M3.5.0,M10.5.0/3", &[(&[Sun], (2, 30), (3, 30))]);
+
+        // Sunday 2021-03-28 in Paris, 02:00 jumps to 03:00: the window is only half an hour
+        // long, from 03:00 to 03:30 local time.
+        let dst_start = 1_616_893_200;
+        assert!(!sched.allows(dst_start - 1));
+        assert!(sched.allows(dst_start));
+        assert!(sched.allows(dst_start + 1799));
+        assert!(!sched.allows(dst_start + 1800));
+        assert_eq!(sched.next_window(dst_start - 3600), Some(dst_start));
+
+        // Sunday 2021-10-31 in Paris, 03:00 goes back to 02:00: 02:30 to 03:00 local time happens
+        // twice. The window is open from 02:30 to 03:00 in daylight saving time, then from 02:30
+        // to 03:30 in standard time.
+        let dst_end = 1_635_642_000;
+        assert!(!sched.allows(dst_end - 1801));
+        assert!(sched.allows(dst_end - 1800));
+        assert!(sched.allows(dst_end - 1));
+        assert!(!sched.allows(dst_end));
+        assert_eq!(sched.next_window(dst_end), Some(dst_end + 1800));
+        assert!(sched.allows(dst_end + 5399));
+        assert!(!sched.allows(dst_end + 5400));
+        // Next one is a week after, in standard time
+        assert_eq!(
+            sched.next_window(dst_end + 5400),
+            Some(dst_end + 7 * 24 * 3600 + 1800)
+        );
+
+        // A window that doesn't exist on the day of the change
+        let sched = schedule("CET-1CEST,M3.5.0,M10.5.0/3", &[(&[Sun], (2, 0), (2, 30))]);
+        assert_eq!(
+            sched.next_window(dst_start - 3600),
+            Some(dst_start + 7 * 24 * 3600 - 3600)
+        );
+    }
+
+    #[test]
+    fn deserialization() {
+        let sched: SpendingSchedule = toml::from_str(
+            r#"
+            timezone = "CET-1CEST,M3.5.0,M10.5.0/3"
+            [[windows]]
+            days = ["mon", "tue", "wed", "thu", "fri"]
+            start = "09:00"
+            end = "18:30"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            sched,
+            schedule(
+                "CET-1CEST,M3.5.0,M10.5.0/3",
+                &[(
+                    &[
+                        Weekday::Mon,
+                        Weekday::Tue,
+                        Weekday::Wed,
+                        Weekday::Thu,
+                        Weekday::Fri
+                    ],
+                    (9, 0),
+                    (18, 30)
+                )]
+            )
+        );
+        assert_eq!(
+            serde_json::to_value(&sched).unwrap(),
+            serde_json::json!({
+                "timezone": "CET-1CEST,M3.5.0,M10.5.0/3",
+                "windows": [{
+                    "days": ["mon", "tue", "wed", "thu", "fri"],
+                    "start": "09:00",
+                    "end": "18:30",
+                }]
+            })
+        );
+
+        for invalid_time in &["9", "09:0", "25:00", "24:01", "12:60", "ab:cd"] {
+            toml::from_str::<SpendingSchedule>(&format!(
+                "timezone = \"UTC\"\n[[windows]]\ndays = [\"mon\"]\nstart = \"{}\"\nend = \"18:00\"",
+                invalid_time
+            ))
+            .unwrap_err();
+        }
+        toml::from_str::<SpendingSchedule>(
+            "timezone = \"Europe/Paris\"\n[[windows]]\ndays = [\"mon\"]\nstart = \"09:00\"\nend = \"18:00\"",
+        )
+        .unwrap_err();
+    }
+}
@@ -0,0 +1,270 @@
+//! Tell the service manager about our state, as per the sd_notify(3) protocol, for revaultd to
+//! be run as a `Type=notify` systemd service.
+//!
+//! We notify `READY=1` once the database is set up, bitcoind passed the sanity checks and the
+//! RPC socket is listening, along with `STATUS=` updates during the phases that may take a
+//! while (waiting for bitcoind to synchronize, rescanning, reconnecting to bitcoind). If the
+//! service manager asked for it (`WATCHDOG_USEC`), a thread regularly pings its watchdog as long
+//! as the poller keeps making progress.
+//!
+//! All of this is a no-op if we were not started by a service manager (`NOTIFY_SOCKET` unset).
+//! Abstract socket addresses are not supported, only filesystem ones (systemd's default).
+
+use std::{
+    env, io, process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// The socket to send our notifications to, if any.
+#[cfg(unix)]
+fn notify_socket() -> Option<std::path::PathBuf> {
+    let path = env::var_os("NOTIFY_SOCKET")?;
+    if path.is_empty() || path.to_string_lossy().starts_with('@') {
+        return None;
+    }
+    Some(path.into())
+}
+
+/// Send this state to the service manager. Returns `false` if there is none to send it to.
+#[cfg(unix)]
+pub fn notify(state: &str) -> io::Result<bool> {
+    let path = match notify_socket() {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(true)
+}
+
+/// Send this state to the service manager. Returns `false` if there is none to send it to.
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> io::Result<bool> {
+    Ok(false)
+}
+
+// Failing to notify the service manager must never affect the daemon.
+fn notify_or_log(state: &str) {
+    if let Err(e) = notify(state) {
+        log::warn!(
+            "Could not notify the service manager of '{}': '{}'",
+            state,
+            e
+        );
+    }
+}
+
+/// We are done starting up.
+pub fn ready() {
+    notify_or_log("READY=1\nSTATUS=Running");
+}
+
+/// We are shutting down.
+pub fn stopping() {
+    notify_or_log("STOPPING=1");
+}
+
+/// A human-readable description of what we are doing.
+pub fn status(status: &str) {
+    notify_or_log(&format!("STATUS={}", status));
+}
+
+/// The interval at which the service manager expects us to ping its watchdog, if it does.
+pub fn watchdog_interval() -> Option<Duration> {
+    // It may have been set for our parent (for instance if we daemonized).
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+
+    match env::var("WATCHDOG_USEC").ok()?.parse::<u64>() {
+        Ok(0) | Err(_) => None,
+        Ok(usec) => Some(Duration::from_micros(usec)),
+    }
+}
+
+/// The liveness of the poller, as observed by the watchdog thread.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    last_beat: Arc<Mutex<Instant>>,
+    // Set during the phases that may block for a long time without being stuck
+    busy: Arc<AtomicBool>,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            last_beat: Arc::new(Mutex::new(Instant::now())),
+            busy: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Signal we are making progress.
+    pub fn beat(&self) {
+        *self.last_beat.lock().unwrap() = Instant::now();
+    }
+
+    /// Signal we are entering a phase that may legitimately take a long time (such as a
+    /// rescan), and report it as our status. It lasts until the returned guard is dropped.
+    pub fn long_phase(&self, description: &str) -> LongPhase {
+        status(description);
+        self.busy.store(true, Ordering::Relaxed);
+        LongPhase {
+            heartbeat: self.clone(),
+        }
+    }
+
+    /// Whether we made progress within this duration, or are in a long phase.
+    pub fn is_alive(&self, timeout: Duration) -> bool {
+        self.busy.load(Ordering::Relaxed) || self.last_beat.lock().unwrap().elapsed() < timeout
+    }
+}
+
+/// See [Heartbeat::long_phase].
+#[derive(Debug)]
+pub struct LongPhase {
+    heartbeat: Heartbeat,
+}
+
+impl Drop for LongPhase {
+    fn drop(&mut self) {
+        self.heartbeat.busy.store(false, Ordering::Relaxed);
+        self.heartbeat.beat();
+    }
+}
+
+/// Ping the service manager's watchdog every half `interval` as long as the heartbeat is
+/// alive. Stop pinging (and let the service manager restart us) if it is not.
+pub fn watchdog_loop(heartbeat: Heartbeat, interval: Duration) {
+    loop {
+        thread::sleep(interval / 2);
+        if heartbeat.is_alive(interval) {
+            notify_or_log("WATCHDOG=1");
+        } else {
+            log::error!(
+                "The poller did not make any progress for more than {:?}, not pinging the \
+                 service manager's watchdog anymore.",
+                interval
+            );
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::test_datadir;
+
+    use std::{fs, os::unix::net::UnixDatagram};
+
+    fn recv(socket: &UnixDatagram) -> String {
+        let mut buf = [0; 256];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    // NOTIFY_SOCKET is process-wide, so everything using it is tested here.
+    #[test]
+    fn test_notify() {
+        let datadir = test_datadir();
+        fs::create_dir_all(&datadir).unwrap();
+        let socket_path = fs::canonicalize(&datadir).unwrap().join("notify");
+        let socket = UnixDatagram::bind(&socket_path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // A no-op if we were not started by a service manager
+        env::remove_var("NOTIFY_SOCKET");
+        assert!(!notify("READY=1").unwrap());
+        env::set_var("NOTIFY_SOCKET", "@abstract");
+        assert!(!notify("READY=1").unwrap());
+
+        env::set_var("NOTIFY_SOCKET", &socket_path);
+        ready();
+        assert_eq!(recv(&socket), "READY=1\nSTATUS=Running");
+        status("Waiting for bitcoind to synchronize (42.00%)");
+        assert_eq!(
+            recv(&socket),
+            "STATUS=Waiting for bitcoind to synchronize (42.00%)"
+        );
+        let heartbeat = Heartbeat::new();
+        {
+            let _phase = heartbeat.long_phase("Rescanning");
+            assert_eq!(recv(&socket), "STATUS=Rescanning");
+        }
+        stopping();
+        assert_eq!(recv(&socket), "STOPPING=1");
+
+        // The watchdog is pinged as long as the heartbeat is alive
+        thread::spawn({
+            let heartbeat = heartbeat.clone();
+            move || watchdog_loop(heartbeat, Duration::from_millis(200))
+        });
+        assert_eq!(recv(&socket), "WATCHDOG=1");
+        assert_eq!(recv(&socket), "WATCHDOG=1");
+
+        // The service manager's socket going away is not fatal
+        env::set_var("NOTIFY_SOCKET", datadir.join("nonexistent"));
+        assert!(notify("READY=1").is_err());
+        status("Running");
+
+        env::remove_var("NOTIFY_SOCKET");
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        env::remove_var("WATCHDOG_PID");
+        env::set_var("WATCHDOG_USEC", "30000000");
+        assert_eq!(watchdog_interval(), Some(Duration::from_secs(30)));
+
+        // Not for us
+        env::set_var("WATCHDOG_PID", (process::id() + 1).to_string());
+        assert_eq!(watchdog_interval(), None);
+        env::set_var("WATCHDOG_PID", process::id().to_string());
+        assert_eq!(watchdog_interval(), Some(Duration::from_secs(30)));
+
+        env::set_var("WATCHDOG_USEC", "0");
+        assert_eq!(watchdog_interval(), None);
+        env::set_var("WATCHDOG_USEC", "invalid");
+        assert_eq!(watchdog_interval(), None);
+        env::remove_var("WATCHDOG_USEC");
+        assert_eq!(watchdog_interval(), None);
+        env::remove_var("WATCHDOG_PID");
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let heartbeat = Heartbeat::new();
+        assert!(heartbeat.is_alive(Duration::from_secs(60)));
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!heartbeat.is_alive(Duration::from_millis(10)));
+        heartbeat.beat();
+        assert!(heartbeat.is_alive(Duration::from_millis(40)));
+
+        // During a long phase, we are not considered stuck
+        thread::sleep(Duration::from_millis(50));
+        let phase = heartbeat.long_phase("Rescanning");
+        assert!(heartbeat.is_alive(Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(50));
+        assert!(heartbeat.is_alive(Duration::from_millis(10)));
+        // And its end counts as progress
+        drop(phase);
+        assert!(heartbeat.is_alive(Duration::from_millis(40)));
+    }
+}
@@ -0,0 +1,572 @@
+//! The first-run setup of a participant: out of the parameters of the deployment, write a
+//! configuration file and initialize the data directory.
+//!
+//! The descriptors are constructed from the keys of the participants, so that they are checked
+//! before anything is written. Their checksums and our Noise static public key are then to be
+//! verified out of band with the other participants and the servers' operators.
+
+use crate::{
+    communication::{ServerConnection, ServerKind},
+    config::{noise_pubkey_fingerprint, Config, ConfigError},
+    database::actions::setup_db,
+    revaultd::RevaultD,
+    StartupError,
+};
+
+use std::{error, fmt, fs, io, net::SocketAddr, path::PathBuf};
+
+use revault_net::noise::PublicKey as NoisePubkey;
+use revault_tx::{
+    bitcoin::{hashes::hex::ToHex, util::bip32::ExtendedPubKey, Address, Network},
+    miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey, Wildcard},
+    scripts::{CpfpDescriptor, DepositDescriptor, UnvaultDescriptor},
+};
+
+/// The parameters of the deployment, and of our participation to it.
+#[derive(Debug, Clone)]
+pub struct SetupParams {
+    /// Where to write the configuration file
+    pub config_file: PathBuf,
+    pub data_dir: PathBuf,
+    pub network: Network,
+    pub bitcoind_cookie_path: PathBuf,
+    pub bitcoind_addr: SocketAddr,
+    pub stakeholders_xpubs: Vec<ExtendedPubKey>,
+    pub managers_xpubs: Vec<ExtendedPubKey>,
+    pub managers_threshold: usize,
+    /// The keys of the cosigning servers in the Unvault descriptor
+    pub cosigners_keys: Vec<DescriptorPublicKey>,
+    pub unvault_csv: u32,
+    pub cpfp_xpubs: Vec<ExtendedPubKey>,
+    /// Our xpub if we are a stakeholder
+    pub our_stakeholder_xpub: Option<ExtendedPubKey>,
+    pub emergency_address: Option<Address>,
+    /// The Noise static public key and address of each of our watchtowers
+    pub watchtowers: Vec<(NoisePubkey, SocketAddr)>,
+    /// Our xpub if we are a manager
+    pub our_manager_xpub: Option<ExtendedPubKey>,
+    /// The Noise static public key and address of each cosigning server
+    pub cosigning_servers: Vec<(NoisePubkey, SocketAddr)>,
+    pub coordinator_host: SocketAddr,
+    pub coordinator_noise_key: NoisePubkey,
+    /// Overwrite an existing configuration file, and reuse an existing data directory
+    pub force: bool,
+    /// Connect to the Coordinator once set up, to check it knows our Noise key
+    pub check_coordinator: bool,
+}
+
+/// What was set up, to be checked out of band with the other participants.
+#[derive(Debug, Clone)]
+pub struct SetupSummary {
+    pub config_file: PathBuf,
+    pub data_dir: PathBuf,
+    pub noise_pubkey: NoisePubkey,
+    pub deposit_descriptor_checksum: String,
+    pub unvault_descriptor_checksum: String,
+    pub cpfp_descriptor_checksum: String,
+}
+
+impl fmt::Display for SetupSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Configuration written to '{}', data directory initialized at '{}'.",
+            self.config_file.display(),
+            self.data_dir.display()
+        )?;
+        writeln!(f, "Check these out of band with the other participants:")?;
+        writeln!(
+            f,
+            "  Noise static public key: {} (fingerprint: {})",
+            self.noise_pubkey.0.to_hex(),
+            noise_pubkey_fingerprint(&self.noise_pubkey)
+        )?;
+        writeln!(
+            f,
+            "  Deposit descriptor checksum: {}",
+            self.deposit_descriptor_checksum
+        )?;
+        writeln!(
+            f,
+            "  Unvault descriptor checksum: {}",
+            self.unvault_descriptor_checksum
+        )?;
+        write!(
+            f,
+            "  CPFP descriptor checksum: {}",
+            self.cpfp_descriptor_checksum
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum SetupError {
+    AlreadyExists(PathBuf),
+    Params(String),
+    Descriptor(String),
+    Config(ConfigError),
+    Io(io::Error),
+    Startup(StartupError),
+    Coordinator(revault_net::Error),
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AlreadyExists(path) => write!(
+                f,
+                "'{}' already exists, use --force to overwrite the configuration and reuse the \
+                 data directory",
+                path.display()
+            ),
+            Self::Params(e) => write!(f, "Invalid parameters: {}", e),
+            Self::Descriptor(e) => write!(f, "Constructing the descriptors: {}", e),
+            Self::Config(e) => write!(f, "{}", e),
+            Self::Io(e) => write!(f, "Writing the configuration: {}", e),
+            Self::Startup(e) => write!(f, "Initializing the data directory: {}", e),
+            Self::Coordinator(e) => write!(
+                f,
+                "Could not connect to the Coordinator, make sure its operator registered our \
+                 Noise key: '{}'",
+                e
+            ),
+        }
+    }
+}
+
+impl error::Error for SetupError {}
+
+impl From<ConfigError> for SetupError {
+    fn from(e: ConfigError) -> Self {
+        Self::Config(e)
+    }
+}
+
+impl From<io::Error> for SetupError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<StartupError> for SetupError {
+    fn from(e: StartupError) -> Self {
+        Self::Startup(e)
+    }
+}
+
+fn descriptor_xpub(xpub: &ExtendedPubKey) -> DescriptorPublicKey {
+    DescriptorPublicKey::XPub(DescriptorXKey {
+        origin: None,
+        xkey: *xpub,
+        derivation_path: vec![].into(),
+        wildcard: Wildcard::Unhardened,
+    })
+}
+
+/// Construct the descriptors of a deployment from the keys of the participants.
+pub fn deployment_descriptors(
+    stakeholders_xpubs: &[ExtendedPubKey],
+    managers_xpubs: &[ExtendedPubKey],
+    managers_threshold: usize,
+    cosigners_keys: Vec<DescriptorPublicKey>,
+    unvault_csv: u32,
+    cpfp_xpubs: &[ExtendedPubKey],
+) -> Result<(DepositDescriptor, UnvaultDescriptor, CpfpDescriptor), SetupError> {
+    let stk_keys: Vec<DescriptorPublicKey> =
+        stakeholders_xpubs.iter().map(descriptor_xpub).collect();
+    let deposit_descriptor = DepositDescriptor::new(stk_keys.clone())
+        .map_err(|e| SetupError::Descriptor(format!("Deposit descriptor: {}", e)))?;
+    let unvault_descriptor = UnvaultDescriptor::new(
+        stk_keys,
+        managers_xpubs.iter().map(descriptor_xpub).collect(),
+        managers_threshold,
+        cosigners_keys,
+        unvault_csv,
+    )
+    .map_err(|e| SetupError::Descriptor(format!("Unvault descriptor: {}", e)))?;
+    let cpfp_descriptor = CpfpDescriptor::new(cpfp_xpubs.iter().map(descriptor_xpub).collect())
+        .map_err(|e| SetupError::Descriptor(format!("CPFP descriptor: {}", e)))?;
+
+    Ok((deposit_descriptor, unvault_descriptor, cpfp_descriptor))
+}
+
+fn descriptor_checksum(descriptor: &str) -> String {
+    descriptor
+        .rsplit('#')
+        .next()
+        .expect("rsplit always yields")
+        .to_string()
+}
+
+fn toml_str(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+// Only the mandatory settings and the ones we were given, the others are documented in the
+// example configuration.
+fn config_toml(
+    params: &SetupParams,
+    deposit_descriptor: &DepositDescriptor,
+    unvault_descriptor: &UnvaultDescriptor,
+    cpfp_descriptor: &CpfpDescriptor,
+) -> String {
+    let mut toml = String::new();
+    toml.push_str(
+        "# Written by `revaultd --setup`. See `revaultd --dump-example-config` for all the \
+         settings.\n\n",
+    );
+    toml.push_str("daemon = false\n");
+    toml.push_str(&format!(
+        "data_dir = {}\n",
+        toml_str(&params.data_dir.to_string_lossy())
+    ));
+    toml.push_str(&format!(
+        "coordinator_host = {}\n",
+        toml_str(&params.coordinator_host.to_string())
+    ));
+    toml.push_str(&format!(
+        "coordinator_noise_key = {}\n",
+        toml_str(&params.coordinator_noise_key.0.to_hex())
+    ));
+
+    toml.push_str("\n[bitcoind_config]\n");
+    toml.push_str(&format!(
+        "network = {}\n",
+        toml_str(&params.network.to_string())
+    ));
+    toml.push_str(&format!(
+        "cookie_path = {}\n",
+        toml_str(&params.bitcoind_cookie_path.to_string_lossy())
+    ));
+    toml.push_str(&format!(
+        "addr = {}\n",
+        toml_str(&params.bitcoind_addr.to_string())
+    ));
+
+    toml.push_str("\n[scripts_config]\n");
+    toml.push_str(&format!(
+        "deposit_descriptor = {}\n",
+        toml_str(&deposit_descriptor.to_string())
+    ));
+    toml.push_str(&format!(
+        "unvault_descriptor = {}\n",
+        toml_str(&unvault_descriptor.to_string())
+    ));
+    toml.push_str(&format!(
+        "cpfp_descriptor = {}\n",
+        toml_str(&cpfp_descriptor.to_string())
+    ));
+
+    if let Some(ref xpub) = params.our_stakeholder_xpub {
+        toml.push_str("\n[stakeholder_config]\n");
+        toml.push_str(&format!("xpub = {}\n", toml_str(&xpub.to_string())));
+        if let Some(ref address) = params.emergency_address {
+            toml.push_str(&format!(
+                "emergency_address = {}\n",
+                toml_str(&address.to_string())
+            ));
+        }
+        if params.watchtowers.is_empty() {
+            toml.push_str("watchtowers = []\n");
+        }
+        for (noise_key, host) in params.watchtowers.iter() {
+            toml.push_str("\n[[stakeholder_config.watchtowers]]\n");
+            toml.push_str(&format!("host = {}\n", toml_str(&host.to_string())));
+            toml.push_str(&format!(
+                "noise_key = {}\n",
+                toml_str(&noise_key.0.to_hex())
+            ));
+        }
+    }
+
+    if let Some(ref xpub) = params.our_manager_xpub {
+        toml.push_str("\n[manager_config]\n");
+        toml.push_str(&format!("xpub = {}\n", toml_str(&xpub.to_string())));
+        for (noise_key, host) in params.cosigning_servers.iter() {
+            toml.push_str("\n[[manager_config.cosigners]]\n");
+            toml.push_str(&format!("host = {}\n", toml_str(&host.to_string())));
+            toml.push_str(&format!(
+                "noise_key = {}\n",
+                toml_str(&noise_key.0.to_hex())
+            ));
+        }
+    }
+
+    toml
+}
+
+fn check_params(params: &SetupParams) -> Result<(), SetupError> {
+    if params.our_stakeholder_xpub.is_none() && params.our_manager_xpub.is_none() {
+        return Err(SetupError::Params(
+            "we must be a stakeholder, a manager or both".to_string(),
+        ));
+    }
+    if params.our_stakeholder_xpub.is_some() != params.emergency_address.is_some() {
+        return Err(SetupError::Params(
+            "a stakeholder, and only a stakeholder, needs an Emergency address".to_string(),
+        ));
+    }
+    if params.our_stakeholder_xpub.is_none() && !params.watchtowers.is_empty() {
+        return Err(SetupError::Params(
+            "only a stakeholder has watchtowers".to_string(),
+        ));
+    }
+    if params.our_manager_xpub.is_none() && !params.cosigning_servers.is_empty() {
+        return Err(SetupError::Params(
+            "only a manager connects to the cosigning servers".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check the Coordinator accepts a connection from us. This needs its operator to have
+/// registered our Noise static public key.
+fn check_coordinator(revaultd: &RevaultD) -> Result<(), SetupError> {
+    let coordinator = revaultd.coordinators.main();
+    ServerConnection::connect(
+        ServerKind::Coordinator,
+        &coordinator.host,
+        revaultd.bind_address,
+        &revaultd.noise_secret,
+        &coordinator.noise_key,
+    )
+    .map_err(SetupError::Coordinator)?;
+
+    Ok(())
+}
+
+/// Write a configuration for this deployment and initialize the data directory (Noise key and
+/// database). Refuses to overwrite an existing configuration file or to reuse an existing data
+/// directory unless `force` is set, in which case the Noise key is kept.
+pub fn setup(params: &SetupParams) -> Result<SetupSummary, SetupError> {
+    let network_dir = params.data_dir.join(params.network.to_string());
+    if !params.force {
+        for path in &[&params.config_file, &network_dir] {
+            if path.exists() {
+                return Err(SetupError::AlreadyExists(path.to_path_buf()));
+            }
+        }
+    }
+    check_params(params)?;
+
+    let (deposit_descriptor, unvault_descriptor, cpfp_descriptor) = deployment_descriptors(
+        &params.stakeholders_xpubs,
+        &params.managers_xpubs,
+        params.managers_threshold,
+        params.cosigners_keys.clone(),
+        params.unvault_csv,
+        &params.cpfp_xpubs,
+    )?;
+
+    // Don't write anything we wouldn't start with
+    let content = config_toml(
+        params,
+        &deposit_descriptor,
+        &unvault_descriptor,
+        &cpfp_descriptor,
+    );
+    let config = Config::from_toml(content.as_bytes())?;
+    RevaultD::check_config(&config)?;
+
+    if let Some(parent) = params.config_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&params.config_file, content)?;
+
+    let mut revaultd = RevaultD::from_config(config)?;
+    setup_db(&mut revaultd).map_err(StartupError::from)?;
+    if params.check_coordinator {
+        check_coordinator(&revaultd)?;
+    }
+
+    Ok(SetupSummary {
+        config_file: params.config_file.clone(),
+        data_dir: revaultd.data_dir.clone(),
+        noise_pubkey: revaultd.noise_pubkey(),
+        deposit_descriptor_checksum: descriptor_checksum(&deposit_descriptor.to_string()),
+        unvault_descriptor_checksum: descriptor_checksum(&unvault_descriptor.to_string()),
+        cpfp_descriptor_checksum: descriptor_checksum(&cpfp_descriptor.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{setup, SetupError, SetupParams};
+    use crate::{config::Config, database::interface::db_wallet, utils::test_utils::test_datadir};
+
+    use std::{fs, net::TcpListener, str::FromStr, thread};
+
+    use revault_net::{
+        sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::gen_keypair, transport::KKTransport,
+    };
+    use revault_tx::{
+        bitcoin::{util::bip32::ExtendedPubKey, Address, Network},
+        miniscript::descriptor::DescriptorPublicKey,
+    };
+
+    fn xpub(s: &str) -> ExtendedPubKey {
+        ExtendedPubKey::from_str(s).unwrap()
+    }
+
+    fn stakeholder_manager_params(datadir: &std::path::Path) -> SetupParams {
+        let stakeholders_xpubs = vec![
+            xpub("tpubDDcdMK347DzJfWk9a8uLuskgoSKTUPn1GtMYz91Q1WdptaY14BTF4KUFiQ3cq5K6ji4bgALTEy6HUuf3TULS4f98wYpaYLcAbym33g4hsPY"),
+            xpub("tpubDFUyR1hbbP14sGZ2St39RgJ9wUk4enPVYahtA5nPwPPWsGUNVFZt2ujRLShf4JqFXbJQLrgvFTodtXCWEnYQqUnMYzLaAWjuXsZnQTYZS5C"),
+        ];
+        let managers_xpubs = vec![
+            xpub("tpubDCvsodKgyz8apUfySTojc8WU6UPDXmv32Ct9hZqWGE7a6FcWpHoJ3Efa9zUqLWHX6PHthHXQehG1mrFtFVXNMcwt6kGwCuYk6adMDreyVGu"),
+            xpub("tpubDEBMdKAsdbmZsUUohCJjRCJ8NDxH4LJr79WSEE3bzHZGEoh7doFizgHhMQJKTN1SPnW44xspcb3f3Vav8JAthd3qGzTbq7zR12CFRkLvzG2"),
+        ];
+        let cosigners_keys = vec![
+            DescriptorPublicKey::from_str(
+                "030f64b922aee2fd597f104bc6cb3b670f1ca2c6c49b1071a1a6c010575d94fe5a",
+            )
+            .unwrap(),
+            DescriptorPublicKey::from_str(
+                "02abe475b199ec3d62fa576faee16a334fdb86ffb26dce75becebaaedf328ac3fe",
+            )
+            .unwrap(),
+        ];
+        let (coordinator_noise_key, _) = gen_keypair();
+
+        SetupParams {
+            config_file: datadir.join("revaultd.toml"),
+            data_dir: datadir.join("revault"),
+            network: Network::Regtest,
+            bitcoind_cookie_path: datadir.join(".cookie"),
+            bitcoind_addr: "127.0.0.1:18443".parse().unwrap(),
+            stakeholders_xpubs: stakeholders_xpubs.clone(),
+            managers_xpubs: managers_xpubs.clone(),
+            managers_threshold: 2,
+            cosigners_keys,
+            unvault_csv: 18,
+            cpfp_xpubs: vec![xpub("tpubDEAoArgp5Xu4jD5KsLuMN88zuh8g3Tzxm3JMG9wNUNGNsAbzBn4bLzHjjQJCtKWy3ZHt5bR7vvBfFpdY59sHaYi9cqXYaJ2sqKnsStzsNLb")],
+            our_stakeholder_xpub: Some(stakeholders_xpubs[0]),
+            emergency_address: Some(
+                Address::from_str(
+                    "bcrt1qewc2348370pgw8kjz8gy09z8xyh0d9fxde6nzamd3txc9gkmjqmq8m4cdq",
+                )
+                .unwrap(),
+            ),
+            watchtowers: vec![(gen_keypair().0, "127.0.0.1:1".parse().unwrap())],
+            our_manager_xpub: Some(managers_xpubs[0]),
+            cosigning_servers: vec![
+                (gen_keypair().0, "127.0.0.1:2".parse().unwrap()),
+                (gen_keypair().0, "127.0.0.1:3".parse().unwrap()),
+            ],
+            coordinator_host: "127.0.0.1:1".parse().unwrap(),
+            coordinator_noise_key,
+            force: false,
+            check_coordinator: false,
+        }
+    }
+
+    #[test]
+    fn test_setup() {
+        let datadir = test_datadir();
+        let mut params = stakeholder_manager_params(&datadir);
+
+        // The configuration is valid, and the data directory is ready
+        let summary = setup(&params).unwrap();
+        let config = Config::from_file(Some(params.config_file.clone())).unwrap();
+        assert_eq!(config.bitcoind_config.network, Network::Regtest);
+        assert_eq!(
+            config.stakeholder_config.unwrap().watchtowers.len(),
+            params.watchtowers.len()
+        );
+        assert_eq!(
+            config.manager_config.unwrap().cosigners.len(),
+            params.cosigning_servers.len()
+        );
+        assert!(config
+            .scripts_config
+            .deposit_descriptor
+            .to_string()
+            .ends_with(&summary.deposit_descriptor_checksum));
+        assert!(summary.data_dir.join("noise_secret").exists());
+        let wallet = db_wallet(&summary.data_dir.join("revaultd.sqlite3")).unwrap();
+        assert_eq!(
+            wallet.unvault_descriptor.to_string(),
+            config.scripts_config.unvault_descriptor.to_string()
+        );
+        let printed = summary.to_string();
+        assert!(printed.contains(&summary.cpfp_descriptor_checksum));
+
+        // We don't overwrite it, unless forced to. The Noise key is then kept.
+        match setup(&params) {
+            Err(SetupError::AlreadyExists(path)) => assert_eq!(path, params.config_file),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        fs::remove_file(&params.config_file).unwrap();
+        match setup(&params) {
+            Err(SetupError::AlreadyExists(path)) => {
+                assert_eq!(path, params.data_dir.join("regtest"))
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        params.force = true;
+        let forced_summary = setup(&params).unwrap();
+        assert_eq!(forced_summary.noise_pubkey, summary.noise_pubkey);
+
+        // Descriptors we can't construct, and a config we wouldn't start with, are refused
+        // before writing anything.
+        fs::remove_file(&params.config_file).unwrap();
+        let mut invalid = params.clone();
+        invalid.managers_threshold = 3;
+        assert!(matches!(setup(&invalid), Err(SetupError::Descriptor(_))));
+        let mut invalid = params.clone();
+        invalid.our_manager_xpub = Some(invalid.cpfp_xpubs[0]);
+        assert!(matches!(setup(&invalid), Err(SetupError::Config(_))));
+        let mut invalid = params.clone();
+        invalid.emergency_address = None;
+        assert!(matches!(setup(&invalid), Err(SetupError::Params(_))));
+        assert!(!params.config_file.exists());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn test_setup_coordinator_handshake() {
+        let datadir = test_datadir();
+        let mut params = stakeholder_manager_params(&datadir);
+        let (coordinator_pubkey, coordinator_privkey) = gen_keypair();
+        params.coordinator_noise_key = coordinator_pubkey;
+
+        // A stub Coordinator that doesn't know us yet
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        params.coordinator_host = listener.local_addr().unwrap();
+        params.check_coordinator = true;
+        let (stranger_pubkey, _) = gen_keypair();
+        let privkey = coordinator_privkey.clone();
+        let coordinator_thread = thread::spawn(move || {
+            KKTransport::accept(&listener, &privkey, &[stranger_pubkey]).unwrap_err();
+            listener
+        });
+        let summary = match setup(&params) {
+            Err(SetupError::Coordinator(_)) => {
+                // Everything was written, it can be checked again once registered
+                let config = Config::from_file(Some(params.config_file.clone())).unwrap();
+                let mut data_dir = config.data_dir.unwrap();
+                data_dir.push("regtest");
+                assert!(data_dir.join("noise_secret").exists());
+                params.check_coordinator = false;
+                params.force = true;
+                setup(&params).unwrap()
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        let listener = coordinator_thread.join().unwrap();
+
+        // Once its operator registered our key, the handshake succeeds
+        let our_pubkey = summary.noise_pubkey;
+        let coordinator_thread = thread::spawn(move || {
+            KKTransport::accept(&listener, &coordinator_privkey, &[our_pubkey]).unwrap();
+        });
+        params.check_coordinator = true;
+        setup(&params).unwrap();
+        coordinator_thread.join().unwrap();
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
@@ -1,28 +1,38 @@
 ///! Background thread that will poll the coordinator for signatures
 use crate::{
+    commands::utils::invalid_signature_diagnostic,
     communication::{
-        get_presigs, send_coord_sig_msg, wts_share_rev_signatures, CommunicationError,
+        coordinator_failed, get_announced_spend, get_presigs, push_queued_signatures,
+        wts_share_rev_signatures, CommunicationError, CoordinatorEndpoint, ServerConnection,
     },
+    config::SigFetchOrder,
     database::{
-        actions::{db_update_presigned_txs, db_update_vault_status},
+        actions::{
+            db_ack_coordinator_sigs, db_dequeue_coordinator_sig, db_queue_coordinator_sigs,
+            db_raise_vault_flag, db_reconcile_spend_announcement, db_update_presigned_txs,
+            db_update_vault_status,
+        },
         bitcointx::RevaultTx,
         interface::{
-            db_cancel_transaction, db_emer_transaction, db_sig_missing, db_unvault_emer_transaction,
+            db_cancel_transaction, db_coordinator_outbox, db_coordinator_unacked_txs,
+            db_emer_transaction, db_list_spends, db_sig_missing, db_spend_announcement,
+            db_unvault_emer_transaction, db_vaults,
         },
-        schema::{DbTransaction, DbVault},
+        schema::{AnnouncementStatus, DbTransaction, DbVault, VaultFlagKind},
         DatabaseError,
     },
-    revaultd::RevaultD,
+    derivation::DerivationIndex,
+    revaultd::{RevaultD, VaultStatus},
     threadmessages::SigFetcherMessageOut,
 };
-use revault_net::transport::KKTransport;
 use revault_tx::{
-    bitcoin::{secp256k1, PublicKey as BitcoinPubKey},
+    bitcoin::{OutPoint, PublicKey as BitcoinPubKey},
     transactions::RevaultTransaction,
 };
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    cmp,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     path,
     sync::mpsc,
     sync::{Arc, RwLock},
@@ -77,14 +87,16 @@ impl From<revault_net::Error> for SignatureFetcherError {
 // Send a `get_sigs` message to the Coordinator to fetch other stakeholders' signatures for this
 // transaction (https://github.com/revault/practical-revault/blob/master/messages.md#get_sigs).
 // If the Coordinator hands us some new signatures, update the transaction we are passed.
-// If we are a stakeholder and our signature is missing, we send it to the coordinator
-fn sync_sigs<C: secp256k1::Verification>(
-    transport: &mut KKTransport,
+// If we are a stakeholder and our signature is missing, we queue it to be pushed to the
+// coordinator. Returns whether the coordinator has our signature.
+fn sync_sigs(
+    transport: &mut ServerConnection,
+    revaultd: &RevaultD,
+    derivation_index: DerivationIndex,
     stk_keys: &[BitcoinPubKey],
     our_stk_key: &Option<BitcoinPubKey>,
     tx: &mut RevaultTx,
-    secp: &secp256k1::Secp256k1<C>,
-) -> Result<(), SignatureFetcherError> {
+) -> Result<bool, SignatureFetcherError> {
     let signatures = get_presigs(transport, tx.txid())?;
     let mut contains_our_signature = false;
     let our_stk_key = our_stk_key.map(|k| k.key);
@@ -120,28 +132,123 @@ fn sync_sigs<C: secp256k1::Verification>(
             pubkey,
             tx.txid()
         );
-        if let Err(e) = tx.add_signature(pubkey.key, sig, secp) {
+        if let Err(e) = tx.add_signature(pubkey.key, sig, &revaultd.secp_ctx) {
             // FIXME: should we loudly fail instead ? If the coordinator is sending us bad
             // signatures something shady's happening.
-            log::error!("Error while adding signature for presigned tx: '{}'", e);
+            log::error!(
+                "Error while adding signature for presigned {} tx '{}': '{}', {}",
+                tx.type_str(),
+                tx.txid(),
+                e,
+                invalid_signature_diagnostic(revaultd, tx, derivation_index, &pubkey, sig)
+            );
             continue;
         }
     }
 
+    // A read-only instance only fetches signatures, the main one pushes ours.
+    if revaultd.read_only || contains_our_signature {
+        return Ok(contains_our_signature);
+    }
     if let Some(our_stk_key) = our_stk_key {
-        if !contains_our_signature {
-            // Oh, the coordinator didn't have our signature. Here it is!
-            if let Some(our_sig) = tx.signatures().remove(&our_stk_key) {
-                log::info!(
-                    "Coordinator didn't have our signature for transaction '{}', sending",
-                    tx.txid()
-                );
-                let mut map = BTreeMap::new();
-                map.insert(our_stk_key, our_sig);
-                send_coord_sig_msg(transport, tx.txid(), map)?;
+        // Oh, the coordinator didn't have our signature. Here it is!
+        if let Some(our_sig) = tx.signatures().remove(&our_stk_key) {
+            log::info!(
+                "Coordinator didn't have our signature for transaction '{}', queuing it",
+                tx.txid()
+            );
+            let mut map = BTreeMap::new();
+            map.insert(our_stk_key, our_sig);
+            db_queue_coordinator_sigs(
+                &revaultd.db_file(),
+                &tx.txid(),
+                &map,
+                revaultd.clock.unix_timestamp(),
+            )?;
+        }
+    }
+
+    Ok(false)
+}
+
+// The connection to the coordinator the signature fetcher currently talks to.
+struct CoordinatorSession {
+    index: usize,
+    endpoint: CoordinatorEndpoint,
+    transport: ServerConnection,
+    // The ones that failed during this poll
+    excluded: Vec<usize>,
+}
+
+impl CoordinatorSession {
+    fn open(revaultd: &RevaultD) -> Result<Self, SignatureFetcherError> {
+        let (index, transport) = revaultd.coordinators.connect(&revaultd.noise_secret, &[])?;
+        Ok(Self {
+            index,
+            endpoint: revaultd.coordinators.endpoints()[index].clone(),
+            transport,
+            excluded: Vec::new(),
+        })
+    }
+
+    // Run `f` with the current coordinator, failing over to the next one (and running `f` again)
+    // if it is unreachable or misbehaves.
+    fn run<T, F>(&mut self, revaultd: &RevaultD, mut f: F) -> Result<T, SignatureFetcherError>
+    where
+        F: FnMut(&mut ServerConnection, &CoordinatorEndpoint) -> Result<T, SignatureFetcherError>,
+    {
+        loop {
+            match f(&mut self.transport, &self.endpoint) {
+                Err(SignatureFetcherError::Communication(e)) if coordinator_failed(&e) => {
+                    let coordinators = &revaultd.coordinators;
+                    coordinators.report_failure(self.index, &e);
+                    self.excluded.push(self.index);
+                    if self.excluded.len() == coordinators.endpoints().len() {
+                        return Err(e.into());
+                    }
+                    let (index, transport) =
+                        coordinators.connect(&revaultd.noise_secret, &self.excluded)?;
+                    self.index = index;
+                    self.endpoint = coordinators.endpoints()[index].clone();
+                    self.transport = transport;
+                }
+                res => return res,
             }
         }
     }
+}
+
+// We may have shared our signatures with another coordinator than the one we are talking to (for
+// instance if we failed over to a backup one, and the main one is up again). Queue the ones it
+// did not acknowledge to be pushed again.
+fn queue_unacked_signatures(
+    revaultd: &RevaultD,
+    coordinator: &CoordinatorEndpoint,
+) -> Result<(), SignatureFetcherError> {
+    if revaultd.read_only || !revaultd.is_stakeholder() {
+        return Ok(());
+    }
+
+    let db_path = revaultd.db_file();
+    let coordinator_key = &coordinator.noise_key.0[..];
+    for (db_vault, db_tx) in db_coordinator_unacked_txs(&db_path, coordinator_key)? {
+        let our_stk_key = match revaultd.our_stk_xpub_at(db_vault.derivation_index) {
+            Some(key) => key.key,
+            None => continue,
+        };
+        let txid = db_tx.psbt.txid();
+        if let Some(our_sig) = db_tx.psbt.signatures().remove(&our_stk_key) {
+            log::info!(
+                "Queuing our signature for transaction '{}' for the coordinator at '{}', which \
+                 did not acknowledge it yet",
+                txid,
+                coordinator.host
+            );
+            let mut map = BTreeMap::new();
+            map.insert(our_stk_key, our_sig);
+            db_queue_coordinator_sigs(&db_path, &txid, &map, revaultd.clock.unix_timestamp())?;
+        }
+    }
 
     Ok(())
 }
@@ -154,8 +261,8 @@ fn maybe_wt_share_signatures(
     db_vault: &DbVault,
 ) -> Result<(), SignatureFetcherError> {
     let watchtowers = match revaultd.watchtowers {
-        Some(ref wt) => wt,
-        None => return Ok(()),
+        Some(ref wt) if !revaultd.read_only => wt,
+        _ => return Ok(()),
     };
 
     // They should always be there, apart from a very edgy race condition.
@@ -193,6 +300,7 @@ fn maybe_wt_share_signatures(
     );
     wts_share_rev_signatures(
         &revaultd.noise_secret,
+        revaultd.bind_address,
         watchtowers,
         db_vault.deposit_outpoint,
         db_vault.derivation_index,
@@ -211,38 +319,51 @@ fn maybe_wt_share_signatures(
 // not something we unconditionally do in the background). Wouldn't work for managers.
 fn fetch_all_signatures(
     revaultd: &RevaultD,
-    vault_txs: HashMap<DbVault, Vec<DbTransaction>>,
+    vault_txs: Vec<(DbVault, Vec<DbTransaction>)>,
 ) -> Result<(), SignatureFetcherError> {
     let db_path = &revaultd.db_file();
-    let mut transport = KKTransport::connect(
-        revaultd.coordinator_host,
-        &revaultd.noise_secret,
-        &revaultd.coordinator_noisekey,
-    )?;
+    let mut session = CoordinatorSession::open(revaultd)?;
 
-    for (db_vault, mut db_txs) in vault_txs {
+    for (db_vault, db_txs) in vault_txs {
         let stk_keys = revaultd.stakeholders_xpubs_at(db_vault.derivation_index);
         let our_stk_key = revaultd.our_stk_xpub_at(db_vault.derivation_index);
 
-        for db_tx in &mut db_txs {
-            if matches!(
-                db_tx.psbt,
-                RevaultTx::Emergency(_) | RevaultTx::UnvaultEmergency(_)
-            ) {
-                assert!(revaultd.is_stakeholder())
+        // If we fail over, start again from the transactions as they are in database.
+        let (db_txs, acked) = session.run(revaultd, |transport, _| {
+            let mut db_txs = db_txs.clone();
+            let mut acked = Vec::with_capacity(db_txs.len());
+            for db_tx in &mut db_txs {
+                if matches!(
+                    db_tx.psbt,
+                    RevaultTx::Emergency(_) | RevaultTx::UnvaultEmergency(_)
+                ) {
+                    assert!(revaultd.is_stakeholder())
+                }
+                if sync_sigs(
+                    transport,
+                    revaultd,
+                    db_vault.derivation_index,
+                    &stk_keys,
+                    &our_stk_key,
+                    &mut db_tx.psbt,
+                )? {
+                    acked.push(db_tx.psbt.txid());
+                }
             }
-            sync_sigs(
-                &mut transport,
-                &stk_keys,
-                &our_stk_key,
-                &mut db_tx.psbt,
-                &revaultd.secp_ctx,
-            )?;
-        }
+            Ok((db_txs, acked))
+        })?;
+        db_ack_coordinator_sigs(db_path, &session.endpoint.noise_key.0, &acked)?;
 
         // NOTE: In theory, the deposit could have been reorged out and the presigned
         // transactions wiped from the database. Would be a quite edgy case though.
-        if let Err(e) = db_update_presigned_txs(db_path, &db_vault, db_txs, &revaultd.secp_ctx) {
+        if let Err(e) = db_update_presigned_txs(
+            db_path,
+            &db_vault,
+            db_txs,
+            revaultd.feerate_estimates,
+            &revaultd.secp_ctx,
+            revaultd.clock.unix_timestamp(),
+        ) {
             log::error!("Error while updating presigned tx: '{}'", e);
             continue;
         }
@@ -256,19 +377,250 @@ fn fetch_all_signatures(
             // to send them to the watchtowers.
             continue;
         }
-        db_update_vault_status(db_path, &db_vault)?;
+        db_update_vault_status(db_path, &db_vault, revaultd.clock.unix_timestamp())?;
+    }
+
+    revaultd.coordinators.report_success(session.index);
+    queue_unacked_signatures(revaultd, &session.endpoint)?;
+
+    Ok(())
+}
+
+// Check what the Coordinator stores for the vaults we may have announced a Spend transaction for
+// against what we recorded. The announcements we made but did not record (for instance if we
+// stopped right after making them) are adopted, the ones the Coordinator does not have anymore are
+// marked as missing and the vaults it stores a Spend for that we never created are flagged.
+fn reconcile_spend_announcements(revaultd: &RevaultD) -> Result<(), SignatureFetcherError> {
+    if !revaultd.is_manager() {
+        return Ok(());
+    }
+
+    // The deposits spent by our Spend attempts, and the ones being unvaulted as another manager
+    // may have announced a Spend for them.
+    let db_path = revaultd.db_file();
+    let spends = db_list_spends(&db_path)?;
+    let vaults = db_vaults(&db_path)?;
+    let deposits: BTreeSet<OutPoint> = spends
+        .values()
+        .flat_map(|(_, outpoints)| outpoints.iter().copied())
+        .chain(
+            vaults
+                .iter()
+                .filter(|db_vault| {
+                    matches!(
+                        db_vault.status,
+                        VaultStatus::Unvaulting | VaultStatus::Unvaulted
+                    )
+                })
+                .map(|db_vault| db_vault.deposit_outpoint),
+        )
+        .collect();
+    if deposits.is_empty() {
+        return Ok(());
+    }
+
+    let mut session = CoordinatorSession::open(revaultd)?;
+    let announced = session.run(revaultd, |transport, _| {
+        deposits
+            .iter()
+            .map(|outpoint| Ok((*outpoint, get_announced_spend(transport, *outpoint)?)))
+            .collect::<Result<Vec<_>, SignatureFetcherError>>()
+    })?;
+    revaultd.coordinators.report_success(session.index);
+    let coordinator = session.endpoint;
+    let now = revaultd.clock.unix_timestamp();
+
+    let mut reported = HashSet::with_capacity(announced.len());
+    for (deposit_outpoint, spend_txid) in announced {
+        let spend_txid = match spend_txid {
+            Some(txid) => txid,
+            None => continue,
+        };
+        if spends.contains_key(&spend_txid) {
+            reported.insert(spend_txid);
+            continue;
+        }
+
+        let db_vault = match vaults
+            .iter()
+            .find(|db_vault| db_vault.deposit_outpoint == deposit_outpoint)
+        {
+            Some(db_vault) => db_vault,
+            None => continue,
+        };
+        if db_raise_vault_flag(
+            &db_path,
+            db_vault.id,
+            VaultFlagKind::UnknownSpendAnnouncement,
+            &format!(
+                "The Coordinator at '{}' stores Spend transaction '{}' for it, which we never \
+                 created",
+                coordinator.host, spend_txid
+            ),
+            now,
+        )? {
+            log_event!(
+                log::Level::Warn,
+                "unknown_spend_announcement",
+                outpoint = deposit_outpoint,
+                txid = spend_txid;
+                "The Coordinator at '{}' stores unknown Spend transaction '{}' for vault at '{}'",
+                coordinator.host,
+                spend_txid,
+                deposit_outpoint
+            );
+        }
+    }
+
+    for spend_txid in spends.keys() {
+        let announcement = db_spend_announcement(&db_path, spend_txid)?;
+        let status = match (reported.contains(spend_txid), announcement) {
+            (true, None) => {
+                log::info!(
+                    "Adopting the announcement of Spend transaction '{}' to the Coordinator at \
+                     '{}', which we had not recorded",
+                    spend_txid,
+                    coordinator.host
+                );
+                AnnouncementStatus::Adopted
+            }
+            (true, Some(announcement)) if announcement.status == AnnouncementStatus::Adopted => {
+                AnnouncementStatus::Adopted
+            }
+            (true, Some(_)) => AnnouncementStatus::Confirmed,
+            (false, Some(_)) => {
+                log::warn!(
+                    "The Coordinator at '{}' does not store Spend transaction '{}', which we \
+                     announced",
+                    coordinator.host,
+                    spend_txid
+                );
+                AnnouncementStatus::Missing
+            }
+            // We never announced it
+            (false, None) => continue,
+        };
+        db_reconcile_spend_announcement(
+            &db_path,
+            spend_txid,
+            &coordinator.noise_key.0,
+            status,
+            now,
+        )?;
     }
 
     Ok(())
 }
 
+// Where to fetch the signatures of a batch of vaults from. Abstracted so the order of the
+// requests can be tested without a Coordinator.
+trait SignatureSource {
+    fn fetch(
+        &mut self,
+        vault_txs: Vec<(DbVault, Vec<DbTransaction>)>,
+    ) -> Result<(), SignatureFetcherError>;
+}
+
+struct CoordinatorSource<'a> {
+    revaultd: &'a RevaultD,
+}
+
+impl<'a> SignatureSource for CoordinatorSource<'a> {
+    fn fetch(
+        &mut self,
+        vault_txs: Vec<(DbVault, Vec<DbTransaction>)>,
+    ) -> Result<(), SignatureFetcherError> {
+        fetch_all_signatures(self.revaultd, vault_txs)
+    }
+}
+
+// The vaults whose signatures we still have to fetch during the current poll, by order of
+// priority. They are processed by batches so that a large backlog (for instance after some
+// downtime) does not keep us from handling the messages from the main thread.
+#[derive(Debug, Default)]
+struct SigFetchQueue {
+    pending: VecDeque<(DbVault, Vec<DbTransaction>)>,
+}
+
+impl SigFetchQueue {
+    // The number of vaults we still have to fetch signatures for
+    fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // Replace the queue with these vaults, sorted according to the configured policy. Ties are
+    // broken by the order in which we learned about the vaults.
+    fn refill(&mut self, vault_txs: HashMap<DbVault, Vec<DbTransaction>>, order: SigFetchOrder) {
+        let mut vault_txs: Vec<(DbVault, Vec<DbTransaction>)> = vault_txs.into_iter().collect();
+        match order {
+            SigFetchOrder::Value => vault_txs.sort_unstable_by(|(a, _), (b, _)| {
+                b.amount.cmp(&a.amount).then_with(|| a.id.cmp(&b.id))
+            }),
+            SigFetchOrder::Oldest => {
+                vault_txs.sort_unstable_by_key(|(vault, _)| (vault.blockheight, vault.id))
+            }
+        }
+        self.pending = vault_txs.into();
+    }
+
+    // Fetch the signatures for the next `batch_size` vaults in the queue. Even if it's empty,
+    // the source is still queried (we may have signatures to push to it). On error the rest of
+    // the queue is dropped, it'll be retried at the next poll.
+    fn fetch_next_batch(
+        &mut self,
+        source: &mut impl SignatureSource,
+        batch_size: usize,
+    ) -> Result<(), SignatureFetcherError> {
+        let batch_size = cmp::min(batch_size, self.pending.len());
+        let batch = self.pending.drain(..batch_size).collect();
+        source.fetch(batch).map_err(|e| {
+            self.pending.clear();
+            e
+        })
+    }
+}
+
+/// Push the signatures queued in the outbox to the Coordinator, as many as the throttle allows
+/// right now, and record its acknowledgements. Returns how many it acknowledged.
+fn push_coordinator_outbox(revaultd: &RevaultD) -> Result<usize, CommunicationError> {
+    let budget = revaultd.coordinators.throttle().status().available;
+    if revaultd.read_only || budget == 0 {
+        return Ok(0);
+    }
+
+    let db_path = revaultd.db_file();
+    let queued = db_coordinator_outbox(&db_path, budget).expect("The database must be available");
+    push_queued_signatures(
+        &revaultd.coordinators,
+        &revaultd.noise_secret,
+        &queued,
+        |coordinator_key, queued_sig| {
+            db_dequeue_coordinator_sig(&db_path, queued_sig, &coordinator_key.0)
+                .expect("The database must be available")
+        },
+    )
+}
+
 // Poll the Coordinator for revocation transactions signatures indefinitely.
 pub fn signature_fetcher_loop(
     rx: mpsc::Receiver<SigFetcherMessageOut>,
     revaultd: Arc<RwLock<RevaultD>>,
 ) -> Result<(), SignatureFetcherError> {
-    let mut last_poll = time::Instant::now();
+    let clock = revaultd.read().unwrap().clock.clone();
+    let mut last_poll = clock.now();
     let poll_interval = revaultd.read().unwrap().coordinator_poll_interval;
+    let mut queue = SigFetchQueue::default();
+    // The Spend announcements are reconciled with the Coordinator at startup, and again at each
+    // poll interval until it answers.
+    let mut announcements_reconciled = false;
+    let mut last_reconciliation = None;
+    // The signatures in the outbox are pushed as fast as the Coordinator accepts them. If it
+    // fails, we try again at the next poll interval.
+    let mut last_push_failure = None;
 
     log::info!("Signature fetcher thread started.");
 
@@ -285,19 +637,409 @@ pub fn signature_fetcher_loop(
             }
         }
 
-        let elapsed = last_poll.elapsed();
-        // If enough time has elapsed, poll the sigs
-        if elapsed >= poll_interval {
+        if !announcements_reconciled
+            && last_reconciliation
+                .map(|last| clock.elapsed(last) >= poll_interval)
+                .unwrap_or(true)
+        {
+            let revaultd = revaultd.read().unwrap();
+            last_reconciliation = Some(clock.now());
+            match reconcile_spend_announcements(&revaultd) {
+                Ok(()) => announcements_reconciled = true,
+                Err(e) => log::warn!("Error while reconciling Spend announcements: '{}'", e),
+            }
+        }
+
+        // If enough time has elapsed and we are done with the previous poll, poll the sigs.
+        // Otherwise carry on with the next batch of the previous one.
+        let elapsed = clock.elapsed(last_poll);
+        let polling = queue.is_empty() && elapsed >= poll_interval;
+        if polling {
+            let revaultd = revaultd.read().unwrap();
             // This will ignore emergency transactions if we are manager-only
-            let vaults_txs = db_sig_missing(&revaultd.read().unwrap().db_file())?;
-            fetch_all_signatures(&revaultd.read().unwrap(), vaults_txs).unwrap_or_else(|e| {
-                log::warn!("Error while fetching signatures: '{}'", e);
-            });
+            let vaults_txs = db_sig_missing(&revaultd.db_file())?;
+            queue.refill(vaults_txs, revaultd.sigfetch_order);
+            if queue.len() > revaultd.sigfetch_batch_size {
+                log::info!(
+                    "Fetching signatures for '{}' vaults, by batches of '{}'",
+                    queue.len(),
+                    revaultd.sigfetch_batch_size
+                );
+            }
+            last_poll = clock.now();
+        }
+        if polling || !queue.is_empty() {
+            let revaultd = revaultd.read().unwrap();
+            let mut source = CoordinatorSource {
+                revaultd: &revaultd,
+            };
+            queue
+                .fetch_next_batch(&mut source, revaultd.sigfetch_batch_size)
+                .unwrap_or_else(|e| {
+                    log_event!(
+                        log::Level::Warn,
+                        "connection_failure",
+                        peer = revaultd.coordinators.active().host,
+                        error = e;
+                        "Error while fetching signatures: '{}'",
+                        e
+                    );
+                });
+        }
 
-            last_poll = time::Instant::now();
+        if last_push_failure
+            .map(|last| clock.elapsed(last) >= poll_interval)
+            .unwrap_or(true)
+        {
+            let revaultd = revaultd.read().unwrap();
+            last_push_failure = None;
+            if let Err(e) = push_coordinator_outbox(&revaultd) {
+                log::warn!("Error while pushing our queued signatures: '{}'", e);
+                last_push_failure = Some(clock.now());
+            }
         }
 
         // Avoid clogging the CPU by sleeping for a while
         thread::sleep(time::Duration::from_millis(500));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        push_coordinator_outbox, reconcile_spend_announcements, SigFetchQueue,
+        SignatureFetcherError, SignatureSource,
+    };
+    use crate::{
+        clock::{test_utils::MockClock, Clock},
+        communication::{CoordinatorEndpoint, Coordinators},
+        config::SigFetchOrder,
+        database::{
+            actions::{db_queue_coordinator_sigs, setup_db},
+            interface::{db_coordinator_outbox_len, db_vault_flags},
+            schema::{DbTransaction, DbVault, VaultFlagKind},
+        },
+        derivation::DerivationIndex,
+        revaultd::VaultStatus,
+        utils::test_utils::{dummy_revaultd, insert_vault_in_db, test_datadir, UserRole},
+    };
+    use revault_net::{
+        message::{
+            coordinator::{GetSpendTx, SpendTx},
+            RequestParams, ResponseResult,
+        },
+        noise::{PublicKey as NoisePubKey, SecretKey as NoisePrivKey},
+        sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::gen_keypair,
+        transport::KKTransport,
+    };
+    use revault_tx::bitcoin::{hashes::Hash, secp256k1, Amount, OutPoint, Transaction, Txid};
+
+    use std::{
+        collections::{BTreeMap, HashMap},
+        fs,
+        net::TcpListener,
+        str::FromStr,
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    // Records the vaults it was asked signatures for, by request.
+    #[derive(Default)]
+    struct CountingSource {
+        requests: Vec<Vec<u32>>,
+        fail: bool,
+    }
+
+    impl SignatureSource for CountingSource {
+        fn fetch(
+            &mut self,
+            vault_txs: Vec<(DbVault, Vec<DbTransaction>)>,
+        ) -> Result<(), SignatureFetcherError> {
+            self.requests
+                .push(vault_txs.into_iter().map(|(vault, _)| vault.id).collect());
+            if self.fail {
+                return Err(SignatureFetcherError::MissingTransaction);
+            }
+            Ok(())
+        }
+    }
+
+    fn dummy_vault(id: u32, amount: u64, blockheight: u32) -> DbVault {
+        DbVault {
+            id,
+            wallet_id: 1,
+            status: VaultStatus::Funded,
+            blockheight,
+            deposit_outpoint: OutPoint::new(Txid::from_slice(&[id as u8; 32]).unwrap(), 0),
+            amount: Amount::from_sat(amount),
+            derivation_index: DerivationIndex::ZERO,
+            funded_at: Some(blockheight),
+            secured_at: None,
+            delegated_at: None,
+            moved_at: None,
+            final_txid: None,
+        }
+    }
+
+    fn backlog() -> HashMap<DbVault, Vec<DbTransaction>> {
+        // (id, amount, blockheight)
+        [
+            (1, 50_000, 110),
+            (2, 2_000_000, 105),
+            (3, 700_000, 101),
+            (4, 10_000_000, 120),
+            (5, 700_000, 100),
+            (6, 1_000, 102),
+            (7, 300_000, 115),
+        ]
+        .iter()
+        .map(|(id, amount, height)| (dummy_vault(*id, *amount, *height), vec![]))
+        .collect()
+    }
+
+    // Fetch all the signatures of the backlog, as the signature fetcher loop would do.
+    fn fetch_backlog(order: SigFetchOrder, batch_size: usize) -> CountingSource {
+        let mut queue = SigFetchQueue::default();
+        let mut source = CountingSource::default();
+        queue.refill(backlog(), order);
+        assert_eq!(queue.len(), 7);
+        loop {
+            queue.fetch_next_batch(&mut source, batch_size).unwrap();
+            if queue.is_empty() {
+                return source;
+            }
+        }
+    }
+
+    #[test]
+    fn sigfetch_order() {
+        // Largest first, ties broken by id
+        let source = fetch_backlog(SigFetchOrder::Value, 3);
+        assert_eq!(source.requests, vec![vec![4, 2, 3], vec![5, 7, 1], vec![6]]);
+
+        // First confirmed first
+        let source = fetch_backlog(SigFetchOrder::Oldest, 3);
+        assert_eq!(source.requests, vec![vec![5, 3, 6], vec![2, 1, 7], vec![4]]);
+
+        // All at once if the batch is large enough
+        let source = fetch_backlog(SigFetchOrder::Value, 50);
+        assert_eq!(source.requests, vec![vec![4, 2, 3, 5, 7, 1, 6]]);
+        let source = fetch_backlog(SigFetchOrder::Value, 7);
+        assert_eq!(source.requests.len(), 1);
+        let source = fetch_backlog(SigFetchOrder::Value, 1);
+        assert_eq!(source.requests.len(), 7);
+        assert!(source.requests.iter().all(|req| req.len() == 1));
+
+        // Even without backlog we query the source, we may have signatures to push
+        let mut queue = SigFetchQueue::default();
+        let mut source = CountingSource::default();
+        queue.refill(HashMap::new(), SigFetchOrder::Value);
+        queue.fetch_next_batch(&mut source, 3).unwrap();
+        assert_eq!(source.requests, vec![Vec::<u32>::new()]);
+
+        // On error the rest of the poll is abandoned
+        queue.refill(backlog(), SigFetchOrder::Value);
+        source.fail = true;
+        queue.fetch_next_batch(&mut source, 3).unwrap_err();
+        assert!(queue.is_empty());
+        assert_eq!(source.requests.last().unwrap(), &vec![4, 2, 3]);
+    }
+
+    // The Coordinator stores a Spend transaction we never created for a vault being unvaulted
+    #[test]
+    fn reconcile_unknown_spend_announcement() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Manager);
+        setup_db(&mut revaultd).unwrap();
+        let db_path = revaultd.db_file();
+        let deposit_outpoint = OutPoint::new(Txid::from_slice(&[1; 32]).unwrap(), 0);
+        insert_vault_in_db(
+            &db_path,
+            1,
+            &deposit_outpoint,
+            &Amount::ONE_BTC,
+            1,
+            DerivationIndex::ZERO,
+            Some(1),
+            None,
+            VaultStatus::Unvaulted,
+            None,
+        );
+
+        let (server_pubkey, server_privkey) = gen_keypair();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        revaultd.coordinators = Coordinators::new(
+            vec![CoordinatorEndpoint {
+                host: listener.local_addr().unwrap().into(),
+                noise_key: server_pubkey,
+            }],
+            revaultd.clock.clone(),
+        );
+        let client_pubkey = revaultd.noise_pubkey();
+        let spend_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        };
+        let spend_txid = spend_tx.txid();
+        let coordinator = thread::spawn(move || {
+            let mut transport = KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+            transport
+                .read_req(|params| {
+                    assert_eq!(
+                        params,
+                        RequestParams::GetSpendTx(GetSpendTx { deposit_outpoint })
+                    );
+                    Some(ResponseResult::SpendTx(SpendTx {
+                        spend_tx: Some(spend_tx),
+                    }))
+                })
+                .unwrap();
+        });
+
+        reconcile_spend_announcements(&revaultd).unwrap();
+        coordinator.join().unwrap();
+        let flags = db_vault_flags(&db_path, 1).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].kind, VaultFlagKind::UnknownSpendAnnouncement);
+        assert!(flags[0].message.contains(&spend_txid.to_string()));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    // A Coordinator storing at most `limit` signatures per second by this clock. Past it, it tells
+    // us to retry after `retry_after` seconds. Returns when it stored each signature, and how many
+    // times it rate limited us and was sent a signature while we should have been holding off.
+    fn rate_limiting_coordinator(
+        listener: TcpListener,
+        noise_secret: NoisePrivKey,
+        client: NoisePubKey,
+        clock: Arc<MockClock>,
+        (limit, retry_after): (usize, u64),
+        expected: usize,
+    ) -> thread::JoinHandle<(Vec<(Txid, Duration)>, usize, usize)> {
+        thread::spawn(move || {
+            let start = clock.now();
+            let mut stored: Vec<(Txid, Duration)> = Vec::new();
+            let (mut rate_limited, mut violations) = (0, 0);
+            let mut held_until = None;
+
+            // A connection per push, until it got them all
+            while stored.len() < expected {
+                let mut transport = KKTransport::accept(&listener, &noise_secret, &[client])
+                    .expect("Server channel binding and accepting");
+                while let Ok(raw_req) = transport.read() {
+                    let req: serde_json::Value = serde_json::from_slice(&raw_req).unwrap();
+                    assert_eq!(req["method"], "sig");
+                    let now = clock.now().duration_since(start);
+                    let in_last_sec = stored
+                        .iter()
+                        .filter(|(_, at)| *at + Duration::from_secs(1) > now)
+                        .count();
+                    let result = if held_until.map(|until| now < until).unwrap_or(false) {
+                        violations += 1;
+                        serde_json::json!({"error": "rate_limited"})
+                    } else if in_last_sec >= limit {
+                        rate_limited += 1;
+                        held_until = Some(now + Duration::from_secs(retry_after));
+                        serde_json::json!({"error": "rate_limited", "retry_after": retry_after})
+                    } else {
+                        let txid = Txid::from_str(req["params"]["id"].as_str().unwrap()).unwrap();
+                        stored.push((txid, now));
+                        serde_json::json!({"ack": true})
+                    };
+                    let resp = serde_json::json!({"result": result, "id": req["id"]});
+                    transport
+                        .write(&serde_json::to_vec(&resp).unwrap())
+                        .unwrap();
+                }
+            }
+
+            (stored, rate_limited, violations)
+        })
+    }
+
+    // The signatures queued for the Coordinator are pushed at the pace of the throttle, holding
+    // off for as long as the Coordinator asks when it rate limits us, and each of them exactly once.
+    #[test]
+    fn outbox_push_rate_limited() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
+        setup_db(&mut revaultd).unwrap();
+        let db_path = revaultd.db_file();
+        let clock = Arc::new(MockClock::new(1_600_000_000));
+
+        // A burst of 10 then 5 per second on our side, while the Coordinator accepts 8 per
+        // second and asks us to hold off for 2 seconds past it.
+        let (server_pubkey, server_privkey) = gen_keypair();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        revaultd.coordinators = Coordinators::new(
+            vec![CoordinatorEndpoint {
+                host: listener.local_addr().unwrap().into(),
+                noise_key: server_pubkey,
+            }],
+            clock.clone(),
+        )
+        .with_push_rate(5, 10);
+
+        let secp = secp256k1::Secp256k1::new();
+        let privkey = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let mut sigs = BTreeMap::new();
+        sigs.insert(
+            secp256k1::PublicKey::from_secret_key(&secp, &privkey),
+            secp.sign(&secp256k1::Message::from_slice(&[2; 32]).unwrap(), &privkey),
+        );
+        let txids: Vec<Txid> = (1..=30)
+            .map(|i| Txid::from_slice(&[i; 32]).unwrap())
+            .collect();
+        for txid in txids.iter().chain(txids.iter().take(3)) {
+            db_queue_coordinator_sigs(&db_path, txid, &sigs, clock.unix_timestamp()).unwrap();
+        }
+        assert_eq!(db_coordinator_outbox_len(&db_path).unwrap(), 30);
+
+        let coordinator = rate_limiting_coordinator(
+            listener,
+            server_privkey,
+            revaultd.noise_pubkey(),
+            clock.clone(),
+            (8, 2),
+            txids.len(),
+        );
+        // As the signature fetcher does, every 500ms
+        let mut rounds = 0;
+        while db_coordinator_outbox_len(&db_path).unwrap() > 0 {
+            push_coordinator_outbox(&revaultd).unwrap();
+            clock.advance(Duration::from_millis(500));
+            rounds += 1;
+            assert!(rounds < 100, "The outbox is not draining");
+        }
+        let (stored, rate_limited, violations) = coordinator.join().unwrap();
+
+        // Each once, in the order they were queued
+        assert_eq!(
+            stored.iter().map(|(txid, _)| *txid).collect::<Vec<Txid>>(),
+            txids
+        );
+        // The burst went over the Coordinator's limit once, then we held off as asked
+        assert_eq!(rate_limited, 1);
+        assert_eq!(violations, 0);
+        assert_eq!(revaultd.coordinators.throttle().status().rate_limited, 1);
+        assert!(stored[..8]
+            .iter()
+            .all(|(_, at)| *at == Duration::from_secs(0)));
+        assert!(stored[8].1 >= Duration::from_secs(2));
+        // And at our own rate from there
+        for (_, at) in &stored[8..] {
+            let in_sec = stored[8..]
+                .iter()
+                .filter(|(_, other)| other >= at && *other < *at + Duration::from_secs(1))
+                .count();
+            assert!(in_sec <= 5, "{} pushes in a second", in_sec);
+        }
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
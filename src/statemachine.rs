@@ -0,0 +1,1463 @@
+//! Thread owning the vaults' status transitions. The bitcoind poller sends it the events it
+//! notices on chain and the RPC handlers the commands they need executed, which are processed
+//! one at a time and in order.
+
+use crate::{
+    bitcoind::{
+        interface::MempoolAncestry,
+        utils::{presigned_transactions, vault_emergency_address},
+    },
+    commands::{utils::broadcasted_spends_of, CommandError},
+    database::{
+        actions::{
+            db_apply_deferred_revocation_txs_dbtx, db_confirm_deposit_dbtx,
+            db_confirm_unvault_dbtx, db_insert_deposit_ancestry_dbtx,
+            db_insert_deposit_coinbase_dbtx, db_insert_mempool_conflict,
+            db_insert_new_unconfirmed_vault_dbtx, db_insert_vault_successor_dbtx,
+            db_mark_spendable_vault_dbtx, db_raise_vault_flag_dbtx, db_resurrect_vault_dbtx,
+            db_set_vault_emergency_address_dbtx, db_set_vault_origin_dbtx, db_update_tip_dbtx,
+            VaultInsertion,
+        },
+        interface::{
+            db_cancel_transaction, db_exec, db_tip_dbtx, db_unvaulted_heights_dbtx,
+            db_vault_by_cancel_txid_dbtx, db_vault_by_deposit, db_vault_by_deposit_dbtx,
+            db_vault_by_unvault_txid_dbtx, db_vault_emergency_address_dbtx, db_vault_parent_dbtx,
+        },
+        schema::{DepositOrigin, VaultFlagKind},
+        DatabaseError,
+    },
+    derivation::DerivationIndex,
+    revaultd::{BlockchainTip, RevaultD, VaultStatus},
+    threadmessages::{BitcoindThread, ChainEvent, ConfirmedTx, StateMachineMessageOut},
+};
+use revault_tx::{
+    bitcoin::{Amount, OutPoint, Txid},
+    transactions::RevaultTransaction,
+};
+
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{mpsc, Arc, RwLock},
+};
+
+use rusqlite::Transaction;
+
+// The maximum number of events applied in a single database transaction. Past this, the events
+// received since the last flush are applied in more than one.
+const MAX_BATCH_SIZE: usize = 1_000;
+
+pub struct StateMachine<B: BitcoindThread> {
+    revaultd: Arc<RwLock<RevaultD>>,
+    bitcoind_conn: B,
+    // Deposit confirmations we were told about before the deposit itself, by outpoint. They are
+    // applied as soon as the deposit is detected.
+    pending_confirmations: HashMap<OutPoint, (u32, u32)>,
+    // What to tell about the changes being applied, once they are committed. This way no one
+    // gets told about a change they can't see yet in database.
+    after_commit: Vec<Box<dyn FnOnce()>>,
+}
+
+impl<B: BitcoindThread> StateMachine<B> {
+    pub fn new(revaultd: Arc<RwLock<RevaultD>>, bitcoind_conn: B) -> Self {
+        Self {
+            revaultd,
+            bitcoind_conn,
+            pending_confirmations: HashMap::new(),
+            after_commit: Vec::new(),
+        }
+    }
+
+    /// Apply the changes implied by this event. Processing the same event twice is a no-op.
+    pub fn process_event(&mut self, event: ChainEvent) -> Result<(), DatabaseError> {
+        self.process_events(vec![event])
+    }
+
+    /// Apply the changes implied by these events, in order, in a single database transaction.
+    /// The result is the same as processing them one at a time, but readers never observe it
+    /// half-applied.
+    pub fn process_events(&mut self, events: Vec<ChainEvent>) -> Result<(), DatabaseError> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let db_path = self.revaultd.read().unwrap().db_file();
+        let pending_confirmations = self.pending_confirmations.clone();
+        let res = db_exec(&db_path, |db_tx| {
+            for event in events {
+                self.apply_event(db_tx, event)?;
+            }
+            Ok(())
+        });
+
+        let after_commit = mem::replace(&mut self.after_commit, Vec::new());
+        match res {
+            Ok(()) => {
+                for notify in after_commit {
+                    notify();
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.pending_confirmations = pending_confirmations;
+                Err(e)
+            }
+        }
+    }
+
+    fn apply_event(&mut self, db_tx: &Transaction, event: ChainEvent) -> Result<(), DatabaseError> {
+        match event {
+            ChainEvent::DepositDetected {
+                outpoint,
+                amount,
+                derivation_index,
+                funding_inputs,
+                ancestry,
+                coinbase_height,
+            } => self.deposit_detected(
+                db_tx,
+                outpoint,
+                amount,
+                derivation_index,
+                &funding_inputs,
+                ancestry,
+                coinbase_height,
+            ),
+            ChainEvent::TipChanged(tip) => self.tip_changed(db_tx, &tip),
+            ChainEvent::TxConfirmed {
+                kind:
+                    ConfirmedTx::Deposit {
+                        vout,
+                        blockheight,
+                        blocktime,
+                    },
+                txid,
+            } => self.deposit_confirmed(db_tx, OutPoint { txid, vout }, blockheight, blocktime),
+            ChainEvent::TxConfirmed {
+                kind: ConfirmedTx::Unvault { blockheight },
+                txid,
+            } => self.unvault_confirmed(db_tx, &txid, blockheight),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn deposit_detected(
+        &mut self,
+        db_tx: &Transaction,
+        outpoint: OutPoint,
+        amount: Amount,
+        derivation_index: DerivationIndex,
+        funding_inputs: &[OutPoint],
+        ancestry: Option<MempoolAncestry>,
+        coinbase_height: Option<u32>,
+    ) -> Result<(), DatabaseError> {
+        // Note that the deposit *might* have already MIN_CONF confirmations, that's fine.
+        // We'll confim it during the next poll.
+        let mut inserted = false;
+        let wallet_id = self
+            .revaultd
+            .read()
+            .unwrap()
+            .wallet_id
+            .expect("Wallet id is set at startup in setup_db()");
+        match db_insert_new_unconfirmed_vault_dbtx(
+            db_tx,
+            wallet_id,
+            &outpoint,
+            &amount,
+            derivation_index,
+        )? {
+            VaultInsertion::Inserted => {
+                inserted = true;
+                self.after_commit.push(Box::new(move || {
+                    log::debug!(
+                        "Got a new unconfirmed deposit at {} for {} (derivation index: {})",
+                        &outpoint,
+                        &amount,
+                        derivation_index
+                    )
+                }));
+            }
+            VaultInsertion::AlreadyExists(existing)
+                if existing.amount == amount && existing.derivation_index == derivation_index =>
+            {
+                log::debug!("Deposit at '{}' is already known", outpoint);
+                let now = self.revaultd.read().unwrap().clock.unix_timestamp();
+                self.resurrect_vault(db_tx, existing.id, outpoint, now)?;
+            }
+            VaultInsertion::AlreadyExists(existing) => {
+                // This can't happen unless bitcoind or our database is confused. Keep what we
+                // have, but make sure someone looks into it.
+                log_event!(
+                    log::Level::Error,
+                    "conflicting_deposit",
+                    outpoint = outpoint,
+                    amount = amount,
+                    derivation_index = derivation_index,
+                    known_amount = existing.amount,
+                    known_derivation_index = existing.derivation_index;
+                    "!!!!! Deposit at '{}' was detected again for {} (derivation index: {}) but                      we know it for {} (derivation index: {}) !!!!!",
+                    outpoint,
+                    amount,
+                    derivation_index,
+                    existing.amount,
+                    existing.derivation_index
+                );
+                let created_at = self.revaultd.read().unwrap().clock.unix_timestamp();
+                db_raise_vault_flag_dbtx(
+                    db_tx,
+                    existing.id,
+                    VaultFlagKind::ConflictingDeposit,
+                    &format!(
+                        "Detected again for {} (derivation index: {}), known for {} \
+                         (derivation index: {})",
+                        amount, derivation_index, existing.amount, existing.derivation_index
+                    ),
+                    created_at,
+                )?;
+            }
+        }
+
+        let child = db_vault_by_deposit_dbtx(db_tx, &outpoint)?
+            .expect("We just inserted it if it wasn't there");
+
+        if let (true, Some(ancestry)) = (inserted, ancestry) {
+            self.deposit_ancestry(db_tx, child.id, outpoint, ancestry)?;
+        }
+        // The poller won't confirm it before the coinbase matured.
+        if let (true, Some(height)) = (inserted, coinbase_height) {
+            db_insert_deposit_coinbase_dbtx(db_tx, child.id, height)?;
+        }
+
+        // The output of a Cancel transaction is a new deposit at the same derivation index. It
+        // needs its own presigned transactions to be signed for the funds to be secured again.
+        if let Some(parent) = db_vault_by_cancel_txid_dbtx(db_tx, &outpoint.txid)? {
+            db_insert_vault_successor_dbtx(db_tx, parent.id, child.id)?;
+            db_set_vault_origin_dbtx(db_tx, child.id, DepositOrigin::Cancel, &[])?;
+            self.after_commit.push(Box::new(move || {
+                log_event!(
+                    log::Level::Info,
+                    "vault_revaulted",
+                    outpoint = outpoint,
+                    parent = parent.deposit_outpoint;
+                    "Vault at '{}' was created by the Cancel of vault at '{}', it will need to \
+                     be secured again.",
+                    outpoint,
+                    parent.deposit_outpoint
+                )
+            }));
+        } else {
+            // Any other transaction spending one of our Unvault outputs is a Spend, and a
+            // deposit it creates is its change.
+            let mut spent_vaults = Vec::new();
+            for input in funding_inputs {
+                if let Some((vault, _)) = db_vault_by_unvault_txid_dbtx(db_tx, &input.txid)? {
+                    spent_vaults.push(vault);
+                }
+            }
+
+            if spent_vaults.is_empty() {
+                db_set_vault_origin_dbtx(db_tx, child.id, DepositOrigin::External, &[])?;
+            } else {
+                let parent_ids: Vec<u32> = spent_vaults.iter().map(|vault| vault.id).collect();
+                db_set_vault_origin_dbtx(db_tx, child.id, DepositOrigin::SpendChange, &parent_ids)?;
+                let parents: Vec<OutPoint> = spent_vaults
+                    .into_iter()
+                    .map(|vault| vault.deposit_outpoint)
+                    .collect();
+                self.after_commit.push(Box::new(move || {
+                    log::debug!(
+                        "Vault at '{}' was created by the change of the Spend of vaults at {:?}",
+                        outpoint,
+                        parents
+                    )
+                }));
+            }
+        }
+
+        if let Some((blockheight, blocktime)) = self.pending_confirmations.remove(&outpoint) {
+            self.deposit_confirmed(db_tx, outpoint, blockheight, blocktime)?;
+        }
+
+        Ok(())
+    }
+
+    // Record the unconfirmed transactions a new deposit depends on, and flag it if they exceed
+    // our limits. The poller won't confirm a flagged deposit until the flag is cleared.
+    fn deposit_ancestry(
+        &mut self,
+        db_tx: &Transaction,
+        vault_id: u32,
+        outpoint: OutPoint,
+        ancestry: MempoolAncestry,
+    ) -> Result<(), DatabaseError> {
+        db_insert_deposit_ancestry_dbtx(
+            db_tx,
+            vault_id,
+            ancestry.count,
+            ancestry.vsize,
+            ancestry.depth,
+        )?;
+
+        let revaultd = self.revaultd.read().unwrap();
+        let too_deep = revaultd
+            .max_deposit_ancestry_depth
+            .map(|max| ancestry.depth > max)
+            .unwrap_or(false);
+        let too_large = revaultd
+            .max_deposit_ancestry_vsize
+            .map(|max| ancestry.vsize > max)
+            .unwrap_or(false);
+        if !too_deep && !too_large {
+            return Ok(());
+        }
+
+        log_event!(
+            log::Level::Warn,
+            "deposit_ancestry_refused",
+            outpoint = outpoint,
+            ancestors = ancestry.count,
+            vsize = ancestry.vsize,
+            depth = ancestry.depth;
+            "Deposit at '{}' depends on {} unconfirmed transaction(s) ({} vbytes, chain of {}), \
+             it won't be confirmed until its flag is cleared.",
+            outpoint,
+            ancestry.count,
+            ancestry.vsize,
+            ancestry.depth
+        );
+        db_raise_vault_flag_dbtx(
+            db_tx,
+            vault_id,
+            VaultFlagKind::UnconfirmedAncestry,
+            &format!(
+                "Depends on {} unconfirmed transaction(s) totaling {} vbytes, in a chain of {}",
+                ancestry.count, ancestry.vsize, ancestry.depth
+            ),
+            revaultd.clock.unix_timestamp(),
+        )?;
+
+        Ok(())
+    }
+
+    // Reinstate a vault if we had given up on its deposit.
+    fn resurrect_vault(
+        &mut self,
+        db_tx: &Transaction,
+        vault_id: u32,
+        outpoint: OutPoint,
+        now: u64,
+    ) -> Result<(), DatabaseError> {
+        if db_resurrect_vault_dbtx(db_tx, vault_id, now)? {
+            self.after_commit.push(Box::new(move || {
+                log_event!(
+                    log::Level::Info,
+                    "deposit_resurrected",
+                    outpoint = outpoint;
+                    "Abandoned deposit at '{}' showed up again, resurrecting its vault",
+                    outpoint
+                )
+            }));
+        }
+
+        Ok(())
+    }
+
+    fn deposit_confirmed(
+        &mut self,
+        db_tx: &Transaction,
+        outpoint: OutPoint,
+        blockheight: u32,
+        blocktime: u32,
+    ) -> Result<(), DatabaseError> {
+        let revaultd = self.revaultd.read().unwrap();
+
+        let db_vault = match db_vault_by_deposit_dbtx(db_tx, &outpoint)? {
+            Some(db_vault) => db_vault,
+            None => {
+                log::debug!(
+                    "Deposit at '{}' confirmed before we knew about it, delaying.",
+                    outpoint
+                );
+                self.pending_confirmations
+                    .insert(outpoint, (blockheight, blocktime));
+                return Ok(());
+            }
+        };
+        // It may have been mined without us noticing it was back in the mempool.
+        self.resurrect_vault(
+            db_tx,
+            db_vault.id,
+            outpoint,
+            revaultd.clock.unix_timestamp(),
+        )?;
+        if db_vault.status != VaultStatus::Unconfirmed {
+            log::debug!(
+                "Deposit at '{}' was already confirmed (vault is '{}')",
+                outpoint,
+                db_vault.status
+            );
+            return Ok(());
+        }
+
+        // The Emergency address is chosen once, when the deposit first confirms. emer_address,
+        // emer_tx and unemer_tx are None for managers.
+        let emer_address = vault_emergency_address(
+            &revaultd,
+            db_vault_emergency_address_dbtx(db_tx, db_vault.id)?,
+            db_vault.amount,
+        );
+        let (unvault_tx, cancel_tx, emer_tx, unemer_tx) = match presigned_transactions(
+            &revaultd,
+            outpoint,
+            db_vault.amount,
+            db_vault.derivation_index,
+            emer_address.clone(),
+        ) {
+            Ok(txs) => txs,
+            Err(e) => {
+                log::error!(
+                    "Unexpected error deriving transaction for '{}', amount: '{}': '{}'",
+                    outpoint,
+                    db_vault.amount,
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        db_confirm_deposit_dbtx(
+            db_tx,
+            &outpoint,
+            blockheight,
+            blocktime,
+            &unvault_tx,
+            &cancel_tx,
+            emer_tx.as_ref(),
+            unemer_tx.as_ref(),
+            revaultd.clock.unix_timestamp(),
+        )?;
+        if let Some(ref emer_address) = emer_address {
+            db_set_vault_emergency_address_dbtx(db_tx, db_vault.id, emer_address.address())?;
+        }
+        // We may have been handed the signatures of its revocation transactions while it was
+        // unconfirmed by a reorg.
+        let n_deferred = db_apply_deferred_revocation_txs_dbtx(
+            db_tx,
+            db_vault.id,
+            revaultd.clock.unix_timestamp(),
+        )?;
+
+        let parent = db_vault_parent_dbtx(db_tx, db_vault.id)?;
+        self.after_commit.push(Box::new(move || {
+            log_event!(
+                log::Level::Debug,
+                "vault_status",
+                outpoint = outpoint,
+                from = VaultStatus::Unconfirmed,
+                to = VaultStatus::Funded;
+                "Vault at {} is now confirmed",
+                &outpoint
+            );
+            if n_deferred > 0 {
+                log_event!(
+                    log::Level::Info,
+                    "vault_status",
+                    outpoint = outpoint,
+                    from = VaultStatus::Funded,
+                    to = VaultStatus::Securing;
+                    "Stored back the signatures of the revocation transactions of vault at '{}' \
+                     handed while its deposit was unconfirmed",
+                    &outpoint
+                );
+            }
+            if let Some(parent) = parent {
+                log::warn!(
+                    "Funds canceled from vault at '{}' are not secured until the revocation \
+                     transactions of vault at '{}' are signed.",
+                    parent.deposit_outpoint,
+                    outpoint
+                );
+            }
+        }));
+
+        Ok(())
+    }
+
+    fn unvault_confirmed(
+        &mut self,
+        db_tx: &Transaction,
+        unvault_txid: &Txid,
+        blockheight: u32,
+    ) -> Result<(), DatabaseError> {
+        match db_vault_by_unvault_txid_dbtx(db_tx, unvault_txid)? {
+            Some((db_vault, _)) if db_vault.status == VaultStatus::Unvaulting => {
+                let now = self.revaultd.read().unwrap().clock.unix_timestamp();
+                db_confirm_unvault_dbtx(db_tx, unvault_txid, blockheight, now)?;
+                let unvault_txid = *unvault_txid;
+                self.after_commit.push(Box::new(move || {
+                    log::debug!(
+                        "Unvault transaction '{}' is now confirmed (height '{}')",
+                        unvault_txid,
+                        blockheight
+                    )
+                }));
+                // With a small CSV it may already be spendable
+                let tip = db_tip_dbtx(db_tx)?;
+                self.mature_unvaults(db_tx, tip.height)?;
+            }
+            Some((db_vault, _)) => log::debug!(
+                "Unvault transaction '{}' confirmed but vault is '{}'",
+                unvault_txid,
+                db_vault.status
+            ),
+            None => log::error!(
+                "Unvault transaction '{}' confirmed but we don't know about it",
+                unvault_txid
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn tip_changed(
+        &mut self,
+        db_tx: &Transaction,
+        tip: &BlockchainTip,
+    ) -> Result<(), DatabaseError> {
+        // Going backward is only ever done when rescanning after a reorg, which takes care of
+        // writing the tip itself.
+        let current_tip = db_tip_dbtx(db_tx)?;
+        if tip.height <= current_tip.height && current_tip.height != 0 {
+            log::debug!(
+                "Ignoring tip '{:?}', we are already at '{:?}'",
+                tip,
+                current_tip
+            );
+            return Ok(());
+        }
+
+        db_update_tip_dbtx(db_tx, tip)?;
+        self.mature_unvaults(db_tx, tip.height)
+    }
+
+    // Mark the 'unvaulted' vaults whose Unvault output can now be spent by the managers as
+    // 'spendable'.
+    fn mature_unvaults(
+        &mut self,
+        db_tx: &Transaction,
+        tip_height: u32,
+    ) -> Result<(), DatabaseError> {
+        let revaultd = self.revaultd.read().unwrap();
+
+        for (db_vault, unvault_height) in db_unvaulted_heights_dbtx(db_tx)? {
+            if revaultd.blocks_until_spendable(unvault_height, tip_height) > 0 {
+                continue;
+            }
+
+            db_mark_spendable_vault_dbtx(db_tx, db_vault.id, revaultd.clock.unix_timestamp())?;
+            self.after_commit.push(Box::new(move || {
+                log_event!(
+                    log::Level::Debug,
+                    "vault_status",
+                    outpoint = db_vault.deposit_outpoint,
+                    from = db_vault.status,
+                    to = VaultStatus::Spendable;
+                    "Unvault output of vault at '{}' is now spendable (confirmed at height '{}')",
+                    db_vault.deposit_outpoint,
+                    unvault_height
+                )
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Broadcast the Cancel transaction for an unvaulted vault.
+    pub fn broadcast_cancel(&mut self, deposit_outpoint: &OutPoint) -> Result<(), CommandError> {
+        let revaultd = self.revaultd.read().unwrap();
+        let db_path = revaultd.db_file();
+
+        // Checking that the vault is secured, otherwise we don't have the cancel
+        // transaction
+        let vault = db_vault_by_deposit(&db_path, deposit_outpoint)
+            .expect("Database must be accessible")
+            .ok_or_else(|| CommandError::UnknownOutpoint(*deposit_outpoint))?;
+
+        if !matches!(
+            vault.status,
+            VaultStatus::Unvaulting
+                | VaultStatus::Unvaulted
+                | VaultStatus::Spendable
+                | VaultStatus::Spending
+        ) {
+            return Err(CommandError::InvalidStatus(
+                vault.status,
+                VaultStatus::Unvaulting,
+            ));
+        }
+
+        let mut cancel_tx = db_cancel_transaction(&db_path, vault.id)
+            .expect("Database must be available")
+            .ok_or(CommandError::Race)?
+            .psbt
+            .assert_cancel();
+
+        cancel_tx.finalize(&revaultd.secp_ctx)?;
+        let transaction = cancel_tx.into_psbt().extract_tx();
+        log::debug!(
+            "Broadcasting Cancel transactions with id '{:?}'",
+            transaction.txid()
+        );
+        let cancel_txid = transaction.txid();
+        if let Err(e) = self.bitcoind_conn.broadcast(vec![transaction]) {
+            if !e.is_mempool_conflict() {
+                return Err(e.into());
+            }
+
+            // Most likely a Spend of ours made it to the mempool first. The poller will find
+            // out which one it is if we don't know about it, and which one eventually confirms.
+            let competing_txid = broadcasted_spends_of(&db_path, deposit_outpoint)
+                .expect("Database must be available")
+                .pop();
+            log::warn!(
+                "Cancel transaction '{}' conflicts with '{:?}' in mempool",
+                cancel_txid,
+                competing_txid
+            );
+            db_insert_mempool_conflict(
+                &db_path,
+                vault.id,
+                &cancel_txid,
+                competing_txid.as_ref(),
+                revaultd.clock.unix_timestamp(),
+            )
+            .expect("Database must be available");
+            return Err(CommandError::MempoolConflict(cancel_txid, competing_txid));
+        }
+
+        Ok(())
+    }
+}
+
+/// The state machine event loop.
+/// Processes the events and commands in the order they are received.
+pub fn state_machine_loop<B: BitcoindThread>(
+    rx: mpsc::Receiver<StateMachineMessageOut>,
+    revaultd: Arc<RwLock<RevaultD>>,
+    bitcoind_conn: B,
+) -> Result<(), DatabaseError> {
+    let mut state_machine = StateMachine::new(revaultd, bitcoind_conn);
+
+    log::info!("State machine thread started.");
+
+    // The events received since the last flush. They are applied all at once so readers never
+    // observe a block half-processed, and so we don't hit the disk for each of them on startup.
+    let mut events = Vec::new();
+
+    for msg in rx {
+        match msg {
+            StateMachineMessageOut::Shutdown => {
+                log::info!("State machine thread received shutdown. Exiting.");
+                return state_machine.process_events(events);
+            }
+            StateMachineMessageOut::Event(event) => {
+                events.push(event);
+                if events.len() >= MAX_BATCH_SIZE {
+                    state_machine.process_events(mem::replace(&mut events, Vec::new()))?;
+                }
+            }
+            StateMachineMessageOut::Flush(resp_tx) => {
+                state_machine.process_events(mem::replace(&mut events, Vec::new()))?;
+                // Don't crash if they gave up waiting
+                let _ = resp_tx.send(());
+            }
+            StateMachineMessageOut::BroadcastCancel(deposit_outpoint, resp_tx) => {
+                // This works on the committed state, the events being buffered are yet to be
+                // flushed by the poller.
+                log::trace!("Received 'broadcastcancel' for '{}'", deposit_outpoint);
+                let res = state_machine.broadcast_cancel(&deposit_outpoint);
+                let _ = resp_tx.send(res);
+            }
+        }
+    }
+
+    state_machine.process_events(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateMachine;
+    use crate::{
+        amount::Amount as RpcAmount,
+        bitcoind::interface::MempoolAncestry,
+        config::{EmergencyAddresses, EmergencyBand},
+        database::{
+            actions::{db_abandon_vault, db_unconfirm_deposit_dbtx, db_unvault_deposit, setup_db},
+            interface::{
+                db_abandoned_vaults, db_cancel_transaction, db_deposit_abandonments,
+                db_deposit_ancestry, db_deposit_coinbase_height, db_emer_transaction, db_exec,
+                db_tip, db_unvault_height, db_unvault_transaction, db_vault_by_deposit,
+                db_vault_change_sources, db_vault_child, db_vault_emergency_address,
+                db_vault_flags, db_vault_origin, db_vault_parent,
+            },
+            schema::{DbDepositAncestry, DepositOrigin, VaultFlagKind},
+        },
+        derivation::DerivationIndex,
+        revaultd::{BlockchainTip, VaultStatus},
+        threadmessages::{ChainEvent, ConfirmedTx},
+        utils::test_utils::{dummy_revaultd, test_datadir, MockBitcoindThread, UserRole},
+    };
+    use revault_tx::{
+        bitcoin::{hashes::Hash, Address, Amount, BlockHash, OutPoint, Txid},
+        scripts::EmergencyAddress,
+        transactions::RevaultTransaction,
+    };
+
+    use std::{
+        collections::HashMap,
+        fs,
+        path::Path,
+        str::FromStr,
+        sync::{Arc, RwLock},
+    };
+
+    use rusqlite::{params, types::Value, Connection};
+
+    fn deposit_events(outpoint: OutPoint) -> (ChainEvent, ChainEvent) {
+        (
+            ChainEvent::DepositDetected {
+                outpoint,
+                amount: Amount::from_sat(567_890),
+                derivation_index: DerivationIndex::new(4).unwrap(),
+                funding_inputs: vec![],
+                ancestry: None,
+                coinbase_height: None,
+            },
+            ChainEvent::TxConfirmed {
+                kind: ConfirmedTx::Deposit {
+                    vout: outpoint.vout,
+                    blockheight: 101,
+                    blocktime: 1_600_000_000,
+                },
+                txid: outpoint.txid,
+            },
+        )
+    }
+
+    #[test]
+    fn state_machine_deposits() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let revaultd = Arc::new(RwLock::new(revaultd));
+        let mut state_machine =
+            StateMachine::new(revaultd, MockBitcoindThread::new(HashMap::new()));
+
+        // Duplicated detection and confirmation events are only processed once
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let (detected, confirmed) = deposit_events(outpoint);
+        state_machine.process_event(detected.clone()).unwrap();
+        state_machine.process_event(detected.clone()).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Unconfirmed);
+        assert_eq!(db_vault.amount, Amount::from_sat(567_890));
+
+        state_machine.process_event(confirmed.clone()).unwrap();
+        state_machine.process_event(confirmed.clone()).unwrap();
+        state_machine.process_event(detected).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Funded);
+        assert_eq!(db_vault.blockheight, 101);
+
+        // A confirmation received before the detection is applied once the deposit is known
+        let outpoint = OutPoint::from_str(
+            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+        )
+        .unwrap();
+        let (detected, confirmed) = deposit_events(outpoint);
+        state_machine.process_event(confirmed).unwrap();
+        assert!(db_vault_by_deposit(&db_path, &outpoint).unwrap().is_none());
+        state_machine.process_event(detected).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Funded);
+
+        // Same for the Unvault confirmation
+        let unvault_txid = db_unvault_transaction(&db_path, db_vault.id)
+            .unwrap()
+            .unwrap()
+            .psbt
+            .assert_unvault()
+            .txid();
+        db_unvault_deposit(&db_path, &unvault_txid, 1_600_000_000).unwrap();
+        let unvault_confirmed = ChainEvent::TxConfirmed {
+            kind: ConfirmedTx::Unvault { blockheight: 110 },
+            txid: unvault_txid,
+        };
+        state_machine
+            .process_event(unvault_confirmed.clone())
+            .unwrap();
+        state_machine.process_event(unvault_confirmed).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Unvaulted);
+        assert_eq!(db_unvault_height(&db_path, db_vault.id).unwrap(), Some(110));
+
+        // It becomes spendable once the CSV (6 blocks) expired
+        let tip = |height: u32| BlockchainTip {
+            height,
+            hash: BlockHash::from_slice(&[height as u8; 32]).unwrap(),
+        };
+        state_machine
+            .process_event(ChainEvent::TipChanged(tip(114)))
+            .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Unvaulted);
+        state_machine
+            .process_event(ChainEvent::TipChanged(tip(115)))
+            .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Spendable);
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn state_machine_resurrected_deposits() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let revaultd = Arc::new(RwLock::new(revaultd));
+        let mut state_machine =
+            StateMachine::new(revaultd, MockBitcoindThread::new(HashMap::new()));
+
+        // An abandoned deposit detected again is resurrected
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let (detected, confirmed) = deposit_events(outpoint);
+        state_machine.process_event(detected.clone()).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert!(db_abandon_vault(&db_path, db_vault.id, 1).unwrap());
+        assert!(db_abandoned_vaults(&db_path)
+            .unwrap()
+            .contains(&db_vault.id));
+        state_machine.process_event(detected).unwrap();
+        assert!(db_abandoned_vaults(&db_path).unwrap().is_empty());
+        let abandonments = db_deposit_abandonments(&db_path, db_vault.id).unwrap();
+        assert_eq!(abandonments.len(), 1);
+        assert!(abandonments[0].resurrected_at.is_some());
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Unconfirmed);
+
+        // So is one that got mined without being detected again beforehand
+        assert!(db_abandon_vault(&db_path, db_vault.id, 2).unwrap());
+        state_machine.process_event(confirmed).unwrap();
+        assert!(db_abandoned_vaults(&db_path).unwrap().is_empty());
+        assert_eq!(
+            db_deposit_abandonments(&db_path, db_vault.id)
+                .unwrap()
+                .len(),
+            2
+        );
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Funded);
+
+        // A confirmed vault can't be abandoned
+        assert!(!db_abandon_vault(&db_path, db_vault.id, 3).unwrap());
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn state_machine_conflicting_deposits() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let revaultd = Arc::new(RwLock::new(revaultd));
+        let mut state_machine =
+            StateMachine::new(revaultd, MockBitcoindThread::new(HashMap::new()));
+
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let (detected, _) = deposit_events(outpoint);
+        state_machine.process_event(detected.clone()).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+
+        // Detecting the very same deposit again is fine
+        state_machine.process_event(detected).unwrap();
+        assert!(db_vault_flags(&db_path, db_vault.id).unwrap().is_empty());
+
+        // But not with a different amount or derivation index: the vault is left untouched and
+        // flagged.
+        state_machine
+            .process_event(ChainEvent::DepositDetected {
+                outpoint,
+                amount: Amount::from_sat(567_891),
+                derivation_index: DerivationIndex::new(4).unwrap(),
+                funding_inputs: vec![],
+                ancestry: None,
+                coinbase_height: None,
+            })
+            .unwrap();
+        let flags = db_vault_flags(&db_path, db_vault.id).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].kind, VaultFlagKind::ConflictingDeposit);
+        assert_eq!(
+            db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap(),
+            db_vault
+        );
+
+        // The flag is already raised, so this one doesn't add a new one
+        state_machine
+            .process_event(ChainEvent::DepositDetected {
+                outpoint,
+                amount: Amount::from_sat(567_890),
+                derivation_index: DerivationIndex::new(5).unwrap(),
+                funding_inputs: vec![],
+                ancestry: None,
+                coinbase_height: None,
+            })
+            .unwrap();
+        assert_eq!(db_vault_flags(&db_path, db_vault.id).unwrap().len(), 1);
+        assert_eq!(
+            db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap(),
+            db_vault
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn state_machine_deposit_ancestry() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        revaultd.max_deposit_ancestry_depth = Some(2);
+        let revaultd = Arc::new(RwLock::new(revaultd));
+        let mut state_machine =
+            StateMachine::new(revaultd, MockBitcoindThread::new(HashMap::new()));
+
+        let detected = |outpoint, depth| ChainEvent::DepositDetected {
+            outpoint,
+            amount: Amount::from_sat(567_890),
+            derivation_index: DerivationIndex::new(4).unwrap(),
+            funding_inputs: vec![],
+            ancestry: Some(MempoolAncestry {
+                count: depth + 1,
+                vsize: 300,
+                depth,
+            }),
+            coinbase_height: None,
+        };
+
+        // Within the limits, the ancestry is recorded but the vault isn't flagged
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        state_machine.process_event(detected(outpoint, 2)).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(
+            db_deposit_ancestry(&db_path, db_vault.id).unwrap(),
+            Some(DbDepositAncestry {
+                vault_id: db_vault.id,
+                ancestors: 3,
+                vsize: 300,
+                depth: 2,
+            })
+        );
+        assert!(db_vault_flags(&db_path, db_vault.id).unwrap().is_empty());
+
+        // It's only recorded once, at detection
+        state_machine.process_event(detected(outpoint, 5)).unwrap();
+        assert_eq!(
+            db_deposit_ancestry(&db_path, db_vault.id)
+                .unwrap()
+                .unwrap()
+                .depth,
+            2
+        );
+        assert!(db_vault_flags(&db_path, db_vault.id).unwrap().is_empty());
+
+        // Beyond them, the vault is flagged
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:2",
+        )
+        .unwrap();
+        state_machine.process_event(detected(outpoint, 3)).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Unconfirmed);
+        let flags = db_vault_flags(&db_path, db_vault.id).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].kind, VaultFlagKind::UnconfirmedAncestry);
+
+        // A deposit without unconfirmed parents has no ancestry
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:3",
+        )
+        .unwrap();
+        state_machine
+            .process_event(deposit_events(outpoint).0)
+            .unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert!(db_deposit_ancestry(&db_path, db_vault.id)
+            .unwrap()
+            .is_none());
+        assert!(db_deposit_coinbase_height(&db_path, db_vault.id)
+            .unwrap()
+            .is_none());
+
+        // The height of a coinbase deposit is recorded once, at detection
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:4",
+        )
+        .unwrap();
+        let coinbase_detected = |height| ChainEvent::DepositDetected {
+            outpoint,
+            amount: Amount::from_sat(567_890),
+            derivation_index: DerivationIndex::new(4).unwrap(),
+            funding_inputs: vec![],
+            ancestry: None,
+            coinbase_height: Some(height),
+        };
+        state_machine.process_event(coinbase_detected(250)).unwrap();
+        state_machine.process_event(coinbase_detected(251)).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Unconfirmed);
+        assert_eq!(
+            db_deposit_coinbase_height(&db_path, db_vault.id).unwrap(),
+            Some(250)
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn state_machine_revaulted_deposit() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let revaultd = Arc::new(RwLock::new(revaultd));
+        let mut state_machine =
+            StateMachine::new(revaultd, MockBitcoindThread::new(HashMap::new()));
+
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let (detected, confirmed) = deposit_events(outpoint);
+        state_machine.process_event(detected).unwrap();
+        state_machine.process_event(confirmed).unwrap();
+        let parent = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert!(db_vault_child(&db_path, parent.id).unwrap().is_none());
+
+        // The output of its Cancel is a new vault, linked to the canceled one
+        let cancel_outpoint = OutPoint {
+            txid: db_cancel_transaction(&db_path, parent.id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .txid(),
+            vout: 0,
+        };
+        let (detected, confirmed) = deposit_events(cancel_outpoint);
+        state_machine.process_event(detected.clone()).unwrap();
+        state_machine.process_event(detected).unwrap();
+        state_machine.process_event(confirmed).unwrap();
+        let child = db_vault_by_deposit(&db_path, &cancel_outpoint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(child.status, VaultStatus::Funded);
+        assert_eq!(db_vault_child(&db_path, parent.id).unwrap(), Some(child));
+        assert_eq!(db_vault_parent(&db_path, child.id).unwrap(), Some(parent));
+        assert!(db_vault_parent(&db_path, parent.id).unwrap().is_none());
+        assert_eq!(
+            db_vault_origin(&db_path, parent.id).unwrap(),
+            DepositOrigin::External
+        );
+        assert_eq!(
+            db_vault_origin(&db_path, child.id).unwrap(),
+            DepositOrigin::Cancel
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn state_machine_spend_change_deposit() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let revaultd = Arc::new(RwLock::new(revaultd));
+        let mut state_machine =
+            StateMachine::new(revaultd, MockBitcoindThread::new(HashMap::new()));
+
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let (detected, confirmed) = deposit_events(outpoint);
+        state_machine.process_event(detected).unwrap();
+        state_machine.process_event(confirmed).unwrap();
+        let parent = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        let unvault_txid = db_unvault_transaction(&db_path, parent.id)
+            .unwrap()
+            .unwrap()
+            .psbt
+            .assert_unvault()
+            .txid();
+
+        // A deposit created by a transaction spending its Unvault output, along with an external
+        // coin, is the change of a Spend.
+        let change_outpoint = OutPoint::from_str(
+            "a9735f42110ce529386f612194a1e137a2a2679ac0e789ad7f470cd70c3c2c24:2",
+        )
+        .unwrap();
+        let external_input = OutPoint::from_str(
+            "6e4977728e7db0fce3040d5a55b8b1e5cb29a1f6ac4d8bb4e6bcbff4ad3d9b3b:0",
+        )
+        .unwrap();
+        let detected = ChainEvent::DepositDetected {
+            outpoint: change_outpoint,
+            amount: Amount::from_sat(123_456),
+            derivation_index: DerivationIndex::new(5).unwrap(),
+            funding_inputs: vec![
+                external_input,
+                OutPoint {
+                    txid: unvault_txid,
+                    vout: 0,
+                },
+            ],
+            ancestry: None,
+            coinbase_height: None,
+        };
+        state_machine.process_event(detected.clone()).unwrap();
+        state_machine.process_event(detected).unwrap();
+        let change = db_vault_by_deposit(&db_path, &change_outpoint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            db_vault_origin(&db_path, change.id).unwrap(),
+            DepositOrigin::SpendChange
+        );
+        assert_eq!(
+            db_vault_change_sources(&db_path, change.id).unwrap(),
+            vec![parent.clone()]
+        );
+        assert!(db_vault_change_sources(&db_path, parent.id)
+            .unwrap()
+            .is_empty());
+
+        // Spending only external coins is an external deposit
+        let external_outpoint = OutPoint {
+            txid: external_input.txid,
+            vout: 1,
+        };
+        state_machine
+            .process_event(ChainEvent::DepositDetected {
+                outpoint: external_outpoint,
+                amount: Amount::from_sat(42_000),
+                derivation_index: DerivationIndex::new(6).unwrap(),
+                funding_inputs: vec![change_outpoint],
+                ancestry: None,
+                coinbase_height: None,
+            })
+            .unwrap();
+        let external = db_vault_by_deposit(&db_path, &external_outpoint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            db_vault_origin(&db_path, external.id).unwrap(),
+            DepositOrigin::External
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn state_machine_tip() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::Stakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let revaultd = Arc::new(RwLock::new(revaultd));
+        let mut state_machine =
+            StateMachine::new(revaultd, MockBitcoindThread::new(HashMap::new()));
+
+        let tip = |height: u32, byte: u8| BlockchainTip {
+            height,
+            hash: BlockHash::from_slice(&[byte; 32]).unwrap(),
+        };
+
+        state_machine
+            .process_event(ChainEvent::TipChanged(tip(102, 1)))
+            .unwrap();
+        state_machine
+            .process_event(ChainEvent::TipChanged(tip(102, 1)))
+            .unwrap();
+        assert_eq!(db_tip(&db_path).unwrap(), tip(102, 1));
+
+        // A stale tip, received late, is not taken into account
+        state_machine
+            .process_event(ChainEvent::TipChanged(tip(105, 2)))
+            .unwrap();
+        state_machine
+            .process_event(ChainEvent::TipChanged(tip(103, 3)))
+            .unwrap();
+        assert_eq!(db_tip(&db_path).unwrap(), tip(105, 2));
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+
+    // The events of 10 blocks of 100 deposits each
+    fn synthetic_sync_events() -> Vec<Vec<ChainEvent>> {
+        (0..10u32)
+            .map(|block| {
+                let blockheight = 101 + block;
+                let mut events = vec![ChainEvent::TipChanged(BlockchainTip {
+                    height: blockheight,
+                    hash: BlockHash::from_slice(&[blockheight as u8; 32]).unwrap(),
+                })];
+                for i in 0..100u32 {
+                    let index = block * 100 + i;
+                    let outpoint = OutPoint {
+                        txid: Txid::hash(&index.to_be_bytes()),
+                        vout: index % 3,
+                    };
+                    events.push(ChainEvent::DepositDetected {
+                        outpoint,
+                        amount: Amount::from_sat(100_000 + index as u64),
+                        derivation_index: DerivationIndex::new(index % 50).unwrap(),
+                        funding_inputs: vec![],
+                        ancestry: None,
+                        coinbase_height: None,
+                    });
+                    events.push(ChainEvent::TxConfirmed {
+                        kind: ConfirmedTx::Deposit {
+                            vout: outpoint.vout,
+                            blockheight,
+                            blocktime: 1_600_000_000 + blockheight,
+                        },
+                        txid: outpoint.txid,
+                    });
+                }
+                events
+            })
+            .collect()
+    }
+
+    // All the rows of all the tables, in insertion order. The wallets table is left out as it
+    // contains the creation time.
+    fn db_contents(db_path: &Path) -> Vec<(String, Vec<Vec<Value>>)> {
+        let conn = Connection::open(db_path).unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name != 'wallets' \
+                 ORDER BY name",
+            )
+            .unwrap();
+        let tables: Vec<String> = stmt
+            .query_map(params![], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+
+        tables
+            .into_iter()
+            .map(|table| {
+                let mut stmt = conn
+                    .prepare(&format!("SELECT * FROM {} ORDER BY rowid", table))
+                    .unwrap();
+                let n_columns = stmt.column_count();
+                let rows: Vec<Vec<Value>> = stmt
+                    .query_map(params![], |row| {
+                        (0..n_columns).map(|i| row.get(i)).collect()
+                    })
+                    .unwrap()
+                    .collect::<rusqlite::Result<_>>()
+                    .unwrap();
+                (table, rows)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn state_machine_batched_sync() {
+        let blocks = synthetic_sync_events();
+
+        // Applying the events one by one, each in its own database transaction
+        let datadir_single = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir_single.clone(), UserRole::ManagerStakeholder);
+        let db_path_single = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let mut state_machine = StateMachine::new(
+            Arc::new(RwLock::new(revaultd)),
+            MockBitcoindThread::new(HashMap::new()),
+        );
+        for event in blocks.iter().flatten() {
+            state_machine.process_event(event.clone()).unwrap();
+        }
+
+        // Applying them in a single database transaction per block
+        let datadir_batched = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir_batched.clone(), UserRole::ManagerStakeholder);
+        let db_path_batched = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let mut state_machine = StateMachine::new(
+            Arc::new(RwLock::new(revaultd)),
+            MockBitcoindThread::new(HashMap::new()),
+        );
+        for events in blocks {
+            state_machine.process_events(events).unwrap();
+        }
+
+        let contents = db_contents(&db_path_batched);
+        assert_eq!(contents, db_contents(&db_path_single));
+        let vaults = &contents
+            .iter()
+            .find(|(table, _)| table == "vaults")
+            .unwrap()
+            .1;
+        assert_eq!(vaults.len(), 1000);
+        assert_eq!(
+            db_tip(&db_path_batched).unwrap().height,
+            db_tip(&db_path_single).unwrap().height
+        );
+        for i in [0u32, 499, 999].iter() {
+            let outpoint = OutPoint {
+                txid: Txid::hash(&i.to_be_bytes()),
+                vout: i % 3,
+            };
+            let db_vault = db_vault_by_deposit(&db_path_batched, &outpoint)
+                .unwrap()
+                .unwrap();
+            assert_eq!(db_vault.status, VaultStatus::Funded);
+            assert_eq!(db_vault.blockheight, 101 + i / 100);
+        }
+
+        fs::remove_dir_all(&datadir_single).unwrap_or_else(|_| ());
+        fs::remove_dir_all(&datadir_batched).unwrap_or_else(|_| ());
+    }
+
+    #[test]
+    fn state_machine_emergency_address_bands() {
+        let datadir = test_datadir();
+        let mut revaultd = dummy_revaultd(datadir.clone(), UserRole::ManagerStakeholder);
+        let db_path = revaultd.db_file();
+        setup_db(&mut revaultd).unwrap();
+        let address =
+            |addr: &str| EmergencyAddress::from(Address::from_str(addr).unwrap()).unwrap();
+        let (low, high, other) = (
+            address("bcrt1qewc2348370pgw8kjz8gy09z8xyh0d9fxde6nzamd3txc9gkmjqmq8m4cdq"),
+            address("bcrt1qqesfhzgryvzcpyj303ttg5yj8z5vmtlm4s74zk2fpqrx8mhga5hqmkvaxz"),
+            address("bcrt1qvpk88fvxm86kvm4flpl04uzyaz5zk5lehzf738d47t79ghlr494q8gtzcd"),
+        );
+        revaultd.emergency_addresses = Some(
+            EmergencyAddresses::from_bands(vec![
+                EmergencyBand {
+                    address: low.clone(),
+                    min_amount: None,
+                    max_amount: Some(RpcAmount::from_sat(500_000)),
+                },
+                EmergencyBand {
+                    address: high.clone(),
+                    min_amount: Some(RpcAmount::from_sat(500_000)),
+                    max_amount: None,
+                },
+            ])
+            .unwrap(),
+        );
+        let revaultd = Arc::new(RwLock::new(revaultd));
+        let mut state_machine =
+            StateMachine::new(revaultd.clone(), MockBitcoindThread::new(HashMap::new()));
+        let emer_destination = |vault_id: u32| {
+            db_emer_transaction(&db_path, vault_id)
+                .unwrap()
+                .unwrap()
+                .psbt
+                .assert_emer()
+                .psbt()
+                .global
+                .unsigned_tx
+                .output[0]
+                .script_pubkey
+                .clone()
+        };
+
+        // The deposit of 567_890 sats is routed to the upper band when it confirms, and the
+        // address is recorded
+        let outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let (detected, confirmed) = deposit_events(outpoint);
+        state_machine.process_event(detected).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(
+            db_vault_emergency_address(&db_path, db_vault.id).unwrap(),
+            None
+        );
+        state_machine.process_event(confirmed.clone()).unwrap();
+        assert_eq!(
+            db_vault_emergency_address(&db_path, db_vault.id).unwrap(),
+            Some(high.address().clone())
+        );
+        assert_eq!(
+            emer_destination(db_vault.id),
+            high.address().script_pubkey()
+        );
+        assert_ne!(high.address(), low.address());
+
+        // The configuration changes. The vault keeps its Emergency address, even when its deposit
+        // confirms anew after a reorg.
+        revaultd.write().unwrap().emergency_addresses =
+            Some(EmergencyAddresses::single(other.clone()));
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(db_tx, db_vault.id, 1_600_000_000)
+        })
+        .unwrap();
+        state_machine.process_event(confirmed).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(db_vault.status, VaultStatus::Funded);
+        assert_eq!(
+            db_vault_emergency_address(&db_path, db_vault.id).unwrap(),
+            Some(high.address().clone())
+        );
+        assert_eq!(
+            emer_destination(db_vault.id),
+            high.address().script_pubkey()
+        );
+
+        // New vaults use the new configuration
+        let outpoint = OutPoint::from_str(
+            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+        )
+        .unwrap();
+        let (detected, confirmed) = deposit_events(outpoint);
+        state_machine.process_event(detected).unwrap();
+        state_machine.process_event(confirmed).unwrap();
+        let db_vault = db_vault_by_deposit(&db_path, &outpoint).unwrap().unwrap();
+        assert_eq!(
+            db_vault_emergency_address(&db_path, db_vault.id).unwrap(),
+            Some(other.address().clone())
+        );
+        assert_eq!(
+            emer_destination(db_vault.id),
+            other.address().script_pubkey()
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
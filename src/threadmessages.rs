@@ -1,5 +1,20 @@
-use crate::bitcoind::{interface::WalletTransaction, BitcoindError};
-use revault_tx::bitcoin::{Transaction as BitcoinTransaction, Txid};
+use crate::{
+    bitcoind::{
+        audit::WalletAudit,
+        interface::{MempoolAncestry, WalletTransaction},
+        pool::PoolStats,
+        rescan::RescanWindow,
+        BitcoindError,
+    },
+    commands::CommandError,
+    derivation::DerivationIndex,
+    revaultd::BlockchainTip,
+    rpcstats::{phase_timer, Phase},
+};
+use revault_tx::{
+    bitcoin::{Amount, OutPoint, Transaction as BitcoinTransaction, Txid},
+    transactions::UnvaultTransaction,
+};
 
 use std::sync::mpsc::{sync_channel, Sender, SyncSender};
 
@@ -36,19 +51,74 @@ impl From<Sender<SigFetcherMessageOut>> for SigFetcherSender {
 pub enum BitcoindMessageOut {
     Shutdown,
     SyncProgress(SyncSender<f64>),
+    RescanWindow(SyncSender<Option<RescanWindow>>),
+    Reachable(SyncSender<bool>),
+    ConnectionPools(SyncSender<Vec<PoolStats>>),
+    MinRelayFeerate(SyncSender<Result<u64, BitcoindError>>),
+    CpfpBalance(SyncSender<Result<Amount, BitcoindError>>),
     WalletTransaction(Txid, SyncSender<Option<WalletTransaction>>),
+    OutpointConfirmations(OutPoint, SyncSender<Result<Option<u32>, BitcoindError>>),
     BroadcastTransactions(
         Vec<BitcoinTransaction>,
         SyncSender<Result<(), BitcoindError>>,
     ),
+    /// Transactions to broadcast regardless of whether the others could be
+    BroadcastTransactionsEach(
+        Vec<BitcoinTransaction>,
+        SyncSender<Result<Vec<(Txid, Option<String>)>, BitcoindError>>,
+    ),
+    /// The finalized Unvault transactions of a Spend, and whether it has priority
+    BroadcastUnvaults(
+        Vec<UnvaultTransaction>,
+        bool,
+        SyncSender<Result<UnvaultsBroadcast, BitcoindError>>,
+    ),
+    AuditWallet(SyncSender<Result<WalletAudit, BitcoindError>>),
+}
+
+/// What became of the Unvault transactions of a Spend we tried to broadcast
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnvaultsBroadcast {
+    /// bitcoind would not accept these ones in its mempool (txid, reason). None was broadcast.
+    Rejected(Vec<(Txid, String)>),
+    /// All were broadcast, but for these ones (txid, error) that failed nonetheless
+    Broadcast(Vec<(Txid, String)>),
 }
 
 /// Interface to communicate with bitcoind client thread.
 pub trait BitcoindThread {
     fn wallet_tx(&self, txid: Txid) -> Result<Option<WalletTransaction>, BitcoindError>;
+    /// The number of confirmations of the transaction creating this output (0 if unconfirmed),
+    /// None if it doesn't exist or was spent.
+    fn outpoint_confirmations(&self, outpoint: OutPoint) -> Result<Option<u32>, BitcoindError>;
     fn broadcast(&self, transactions: Vec<BitcoinTransaction>) -> Result<(), BitcoindError>;
+    /// Broadcast each of these transactions, the failure of one not preventing the others to go
+    /// out. Returns, for each of them, the reason bitcoind rejected it if it did.
+    fn broadcast_each(
+        &self,
+        transactions: Vec<BitcoinTransaction>,
+    ) -> Result<Vec<(Txid, Option<String>)>, BitcoindError>;
+    /// Broadcast the Unvault transactions of a Spend all at once, only if bitcoind would accept
+    /// all of them. They are CPFPed right away if the Spend has priority and it's needed.
+    fn broadcast_unvaults(
+        &self,
+        unvaults: Vec<UnvaultTransaction>,
+        priority: bool,
+    ) -> Result<UnvaultsBroadcast, BitcoindError>;
     fn shutdown(&self);
     fn sync_progress(&self) -> f64;
+    /// What bitcoind is rescanning the chain for, if we are importing descriptors
+    fn rescan_window(&self) -> Option<RescanWindow>;
+    fn is_reachable(&self) -> bool;
+    /// How much our connections to bitcoind are used
+    fn connection_pools(&self) -> Vec<PoolStats>;
+    /// In sats/vbyte
+    fn min_relay_feerate(&self) -> Result<u64, BitcoindError>;
+    /// The value of the confirmed coins of the CPFP wallet. Only call it if we have a CPFP key.
+    fn cpfp_balance(&self) -> Result<Amount, BitcoindError>;
+    /// Compare our watchonly wallet with what we imported into it, importing back the missing
+    /// addresses.
+    fn audit_wallet(&self) -> Result<WalletAudit, BitcoindError>;
 }
 
 /// Interface to the bitcoind thread using synchronous MPSCs
@@ -57,6 +127,7 @@ pub struct BitcoindSender(Sender<BitcoindMessageOut>);
 
 impl<'a> BitcoindThread for BitcoindSender {
     fn wallet_tx(&self, txid: Txid) -> Result<Option<WalletTransaction>, BitcoindError> {
+        let _timer = phase_timer(Phase::Bitcoind);
         log::trace!("Sending WalletTx to bitcoind thread for {}", txid);
 
         let (bitrep_tx, bitrep_rx) = sync_channel(0);
@@ -67,6 +138,7 @@ impl<'a> BitcoindThread for BitcoindSender {
     }
 
     fn broadcast(&self, transactions: Vec<BitcoinTransaction>) -> Result<(), BitcoindError> {
+        let _timer = phase_timer(Phase::Bitcoind);
         let (bitrep_tx, bitrep_rx) = sync_channel(0);
 
         if !transactions.is_empty() {
@@ -84,6 +156,44 @@ impl<'a> BitcoindThread for BitcoindSender {
         Ok(())
     }
 
+    fn broadcast_each(
+        &self,
+        transactions: Vec<BitcoinTransaction>,
+    ) -> Result<Vec<(Txid, Option<String>)>, BitcoindError> {
+        let _timer = phase_timer(Phase::Bitcoind);
+        if transactions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::BroadcastTransactionsEach(
+                transactions,
+                bitrep_tx,
+            ))
+            .expect("Sending to bitcoind thread");
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
+
+    fn broadcast_unvaults(
+        &self,
+        unvaults: Vec<UnvaultTransaction>,
+        priority: bool,
+    ) -> Result<UnvaultsBroadcast, BitcoindError> {
+        let _timer = phase_timer(Phase::Bitcoind);
+        if unvaults.is_empty() {
+            return Ok(UnvaultsBroadcast::Broadcast(Vec::new()));
+        }
+
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::BroadcastUnvaults(
+                unvaults, priority, bitrep_tx,
+            ))
+            .expect("Sending to bitcoind thread");
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
+
     fn shutdown(&self) {
         self.0
             .send(BitcoindMessageOut::Shutdown)
@@ -91,6 +201,7 @@ impl<'a> BitcoindThread for BitcoindSender {
     }
 
     fn sync_progress(&self) -> f64 {
+        let _timer = phase_timer(Phase::Bitcoind);
         let (bitrep_tx, bitrep_rx) = sync_channel(0);
         self.0
             .send(BitcoindMessageOut::SyncProgress(bitrep_tx))
@@ -98,6 +209,78 @@ impl<'a> BitcoindThread for BitcoindSender {
 
         bitrep_rx.recv().expect("Receiving from bitcoind thread")
     }
+
+    fn rescan_window(&self) -> Option<RescanWindow> {
+        let _timer = phase_timer(Phase::Bitcoind);
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::RescanWindow(bitrep_tx))
+            .expect("Sending to bitcoind thread");
+
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
+
+    fn is_reachable(&self) -> bool {
+        let _timer = phase_timer(Phase::Bitcoind);
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::Reachable(bitrep_tx))
+            .expect("Sending to bitcoind thread");
+
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
+
+    fn connection_pools(&self) -> Vec<PoolStats> {
+        let _timer = phase_timer(Phase::Bitcoind);
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::ConnectionPools(bitrep_tx))
+            .expect("Sending to bitcoind thread");
+
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
+
+    fn min_relay_feerate(&self) -> Result<u64, BitcoindError> {
+        let _timer = phase_timer(Phase::Bitcoind);
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::MinRelayFeerate(bitrep_tx))
+            .expect("Sending to bitcoind thread");
+
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
+
+    fn outpoint_confirmations(&self, outpoint: OutPoint) -> Result<Option<u32>, BitcoindError> {
+        let _timer = phase_timer(Phase::Bitcoind);
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::OutpointConfirmations(
+                outpoint, bitrep_tx,
+            ))
+            .expect("Sending to bitcoind thread");
+
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
+
+    fn cpfp_balance(&self) -> Result<Amount, BitcoindError> {
+        let _timer = phase_timer(Phase::Bitcoind);
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::CpfpBalance(bitrep_tx))
+            .expect("Sending to bitcoind thread");
+
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
+
+    fn audit_wallet(&self) -> Result<WalletAudit, BitcoindError> {
+        let _timer = phase_timer(Phase::Bitcoind);
+        let (bitrep_tx, bitrep_rx) = sync_channel(0);
+        self.0
+            .send(BitcoindMessageOut::AuditWallet(bitrep_tx))
+            .expect("Sending to bitcoind thread");
+
+        bitrep_rx.recv().expect("Receiving from bitcoind thread")
+    }
 }
 
 impl From<Sender<BitcoindMessageOut>> for BitcoindSender {
@@ -105,3 +288,103 @@ impl From<Sender<BitcoindMessageOut>> for BitcoindSender {
         BitcoindSender(s)
     }
 }
+
+/// Which one of our transactions got confirmed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfirmedTx {
+    /// The deposit transaction, along with the index of the vault output and where it confirmed
+    Deposit {
+        vout: u32,
+        blockheight: u32,
+        blocktime: u32,
+    },
+    /// The Unvault transaction, along with where it confirmed
+    Unvault { blockheight: u32 },
+}
+
+/// Something the bitcoind poller noticed on chain
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    DepositDetected {
+        outpoint: OutPoint,
+        amount: Amount,
+        derivation_index: DerivationIndex,
+        /// The outpoints spent by the transaction that created the deposit, to tell where the
+        /// funds come from.
+        funding_inputs: Vec<OutPoint>,
+        /// The unconfirmed transactions the deposit transaction depends on, if any
+        ancestry: Option<MempoolAncestry>,
+        /// The height it was mined at, if the deposit transaction is a coinbase
+        coinbase_height: Option<u32>,
+    },
+    TipChanged(BlockchainTip),
+    TxConfirmed {
+        kind: ConfirmedTx,
+        txid: Txid,
+    },
+}
+
+/// Outgoing to the state machine thread
+#[derive(Debug)]
+pub enum StateMachineMessageOut {
+    Shutdown,
+    Event(ChainEvent),
+    /// Answered once all the previously sent messages were processed, which is when the events
+    /// received since the last flush are applied in a single database transaction
+    Flush(SyncSender<()>),
+    BroadcastCancel(OutPoint, SyncSender<Result<(), CommandError>>),
+}
+
+/// Interface to the thread owning the vaults' status transitions.
+pub trait StateMachineThread {
+    /// Does not wait for the event to be processed, use `flush` for this. The event may not be
+    /// applied until the next flush.
+    fn emit(&self, event: ChainEvent);
+    fn flush(&self);
+    fn broadcast_cancel(&self, deposit_outpoint: OutPoint) -> Result<(), CommandError>;
+    fn shutdown(&self);
+}
+
+/// Interface to the state machine thread using synchronous MPSCs
+#[derive(Debug, Clone)]
+pub struct StateMachineSender(Sender<StateMachineMessageOut>);
+
+impl StateMachineThread for StateMachineSender {
+    fn emit(&self, event: ChainEvent) {
+        log::trace!("Sending event to state machine thread: {:?}", event);
+        self.0
+            .send(StateMachineMessageOut::Event(event))
+            .expect("Sending to state machine thread")
+    }
+
+    fn flush(&self) {
+        let (rep_tx, rep_rx) = sync_channel(0);
+        self.0
+            .send(StateMachineMessageOut::Flush(rep_tx))
+            .expect("Sending to state machine thread");
+        rep_rx.recv().expect("Receiving from state machine thread")
+    }
+
+    fn broadcast_cancel(&self, deposit_outpoint: OutPoint) -> Result<(), CommandError> {
+        let (rep_tx, rep_rx) = sync_channel(0);
+        self.0
+            .send(StateMachineMessageOut::BroadcastCancel(
+                deposit_outpoint,
+                rep_tx,
+            ))
+            .expect("Sending to state machine thread");
+        rep_rx.recv().expect("Receiving from state machine thread")
+    }
+
+    fn shutdown(&self) {
+        self.0
+            .send(StateMachineMessageOut::Shutdown)
+            .expect("Sending shutdown to state machine thread")
+    }
+}
+
+impl From<Sender<StateMachineMessageOut>> for StateMachineSender {
+    fn from(s: Sender<StateMachineMessageOut>) -> Self {
+        StateMachineSender(s)
+    }
+}
@@ -2,24 +2,41 @@
 pub mod test_utils {
     use crate::config::Config;
     use crate::{
-        bitcoind::{interface::WalletTransaction, BitcoindError},
+        bitcoind::{
+            audit::WalletAudit, interface::WalletTransaction, pool::PoolStats,
+            rescan::RescanWindow, BitcoindError,
+        },
+        commands::locks::ResourceLocks,
         database::interface::db_exec,
+        derivation::DerivationIndex,
         revaultd::{RevaultD, VaultStatus},
         threadmessages::{
             BitcoindMessageOut, BitcoindSender, BitcoindThread, SigFetcherMessageOut,
+            StateMachineMessageOut, UnvaultsBroadcast,
         },
+        utils::noise_vectors::StubServer,
         DaemonControl,
     };
-    use revault_tx::bitcoin::{
-        util::bip32::ChildNumber, Amount, OutPoint, Transaction as BitcoinTransaction, Txid,
+    use revault_net::{
+        message::{cosigner::SignResult, ResponseResult},
+        noise::PublicKey as NoisePubKey,
+    };
+    use revault_tx::{
+        bitcoin::{Amount, OutPoint, Transaction as BitcoinTransaction, Txid},
+        transactions::{SpendTransaction, UnvaultTransaction},
     };
 
     use std::{
         collections::HashMap,
         fs,
+        net::SocketAddr,
         path::{Path, PathBuf},
-        sync::{mpsc, Arc, RwLock},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc, RwLock,
+        },
         thread,
+        time::Duration,
     };
 
     use rusqlite::params;
@@ -97,6 +114,7 @@ addr = "127.0.0.1:8332"
 
         let (bitcoind_tx, bitcoind_rx) = mpsc::channel();
         let (sigfetcher_tx, sigfetcher_rx) = mpsc::channel();
+        let (statemachine_tx, statemachine_rx) = mpsc::channel();
 
         let _ = Arc::from(RwLock::from(thread::spawn(move || {
             for msg in bitcoind_rx {
@@ -113,11 +131,21 @@ addr = "127.0.0.1:8332"
                 }
             }
         })));
+        let _ = Arc::from(RwLock::from(thread::spawn(move || {
+            for msg in statemachine_rx {
+                match msg {
+                    StateMachineMessageOut::Shutdown => return,
+                    _ => unreachable!(),
+                }
+            }
+        })));
 
         DaemonControl {
             revaultd,
             bitcoind_conn: BitcoindSender::from(bitcoind_tx),
             sigfetcher_conn: sigfetcher_tx.into(),
+            statemachine_conn: statemachine_tx.into(),
+            resource_locks: ResourceLocks::new(),
         }
     }
 
@@ -129,14 +157,13 @@ addr = "127.0.0.1:8332"
         deposit_outpoint: &OutPoint,
         amount: &Amount,
         blockheight: u32,
-        derivation_index: ChildNumber,
+        derivation_index: DerivationIndex,
         funded_at: Option<u32>,
         moved_at: Option<u32>,
         status: VaultStatus,
         final_txid: Option<&Txid>,
     ) {
         db_exec(db_path, |tx| {
-            let derivation_index: u32 = derivation_index.into();
             tx.execute(
             "INSERT INTO vaults ( \
                 wallet_id, status, blockheight, deposit_txid, deposit_vout, amount, derivation_index, \
@@ -162,14 +189,48 @@ addr = "127.0.0.1:8332"
         }).unwrap()
     }
 
+    /// Start a Cosigning Server answering each signature request of `client_pubkey` with
+    /// `response` after `latency`. Returns its address, its Noise key and the number of
+    /// requests it received so far.
+    pub fn stub_cosigner(
+        client_pubkey: NoisePubKey,
+        latency: Duration,
+        response: Option<SpendTransaction>,
+    ) -> (SocketAddr, NoisePubKey, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        let server = StubServer::builder("cosigner")
+            .client(client_pubkey)
+            .start(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(latency);
+                Some(ResponseResult::SignResult(SignResult {
+                    tx: response.clone(),
+                }))
+            });
+
+        (server.addr(), server.noise_key(), requests)
+    }
+
     /// MockBitcoindThread implements the BitcoindThread trait as a mock backend.
     pub struct MockBitcoindThread {
         txs: HashMap<Txid, WalletTransaction>,
+        rejected: HashMap<Txid, String>,
     }
 
     impl MockBitcoindThread {
         pub fn new(txs: HashMap<Txid, WalletTransaction>) -> Self {
-            Self { txs }
+            Self {
+                txs,
+                rejected: HashMap::new(),
+            }
+        }
+
+        /// Have `broadcast_each` reject this transaction for this reason
+        pub fn rejecting(mut self, txid: Txid, reason: &str) -> Self {
+            self.rejected.insert(txid, reason.to_string());
+            self
         }
     }
 
@@ -178,12 +239,842 @@ addr = "127.0.0.1:8332"
             let tx = self.txs.get(&txid).map(|tx| (*tx).clone());
             Ok(tx)
         }
+        fn outpoint_confirmations(
+            &self,
+            _outpoint: OutPoint,
+        ) -> Result<Option<u32>, BitcoindError> {
+            Ok(Some(u32::MAX))
+        }
         fn broadcast(&self, _transactions: Vec<BitcoinTransaction>) -> Result<(), BitcoindError> {
             Ok(())
         }
+        fn broadcast_each(
+            &self,
+            transactions: Vec<BitcoinTransaction>,
+        ) -> Result<Vec<(Txid, Option<String>)>, BitcoindError> {
+            Ok(transactions
+                .iter()
+                .map(|tx| (tx.txid(), self.rejected.get(&tx.txid()).cloned()))
+                .collect())
+        }
+        fn broadcast_unvaults(
+            &self,
+            _unvaults: Vec<UnvaultTransaction>,
+            _priority: bool,
+        ) -> Result<UnvaultsBroadcast, BitcoindError> {
+            Ok(UnvaultsBroadcast::Broadcast(Vec::new()))
+        }
         fn shutdown(&self) {}
         fn sync_progress(&self) -> f64 {
             1.0
         }
+        fn rescan_window(&self) -> Option<RescanWindow> {
+            None
+        }
+        fn is_reachable(&self) -> bool {
+            true
+        }
+        fn connection_pools(&self) -> Vec<PoolStats> {
+            Vec::new()
+        }
+        fn min_relay_feerate(&self) -> Result<u64, BitcoindError> {
+            Ok(1)
+        }
+        fn cpfp_balance(&self) -> Result<Amount, BitcoindError> {
+            Ok(Amount::from_sat(0))
+        }
+        fn audit_wallet(&self) -> Result<WalletAudit, BitcoindError> {
+            Ok(WalletAudit {
+                checked_at: 0,
+                watched: 0,
+                missing: Vec::new(),
+                unexpected: Vec::new(),
+                imports_corrected: false,
+            })
+        }
+    }
+}
+
+/// A deterministic mode for the Noise handshakes of the tests against stub servers. The keys are
+/// derived from fixed labels, and a handshake failure between one of our clients and a
+/// [StubServer](noise_vectors::StubServer) is diagnosed: which side noticed it, while
+/// processing which message, and which static key was expected versus presented. The KK
+/// handshake doesn't tell it, but both sides live in the same process here.
+#[cfg(any(test, feature = "noise_test_vectors"))]
+pub mod noise_vectors {
+    use revault_net::{
+        message::{RequestParams, ResponseResult},
+        noise::{PublicKey as NoisePubKey, SecretKey as NoisePrivKey},
+        sodiumoxide::crypto::scalarmult::curve25519,
+        transport::KKTransport,
+    };
+    use revault_tx::bitcoin::hashes::{hex::ToHex, sha256, Hash};
+
+    use std::{
+        collections::HashMap,
+        fmt,
+        net::{SocketAddr, TcpListener},
+        ptr,
+        sync::{
+            atomic::{AtomicPtr, Ordering},
+            Arc, Mutex, Once,
+        },
+        thread,
+    };
+
+    /// The Noise keypair for this label, always the same.
+    pub fn test_keypair(label: &str) -> (NoisePubKey, NoisePrivKey) {
+        let secret = sha256::Hash::hash(label.as_bytes()).into_inner();
+        (public_key(&NoisePrivKey(secret)), NoisePrivKey(secret))
+    }
+
+    fn public_key(secret: &NoisePrivKey) -> NoisePubKey {
+        NoisePubKey(curve25519::scalarmult_base(&curve25519::Scalar(secret.0)).0)
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum HandshakeSide {
+        Initiator,
+        Responder,
+    }
+
+    impl fmt::Display for HandshakeSide {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::Initiator => write!(f, "initiator"),
+                Self::Responder => write!(f, "responder"),
+            }
+        }
+    }
+
+    /// The two messages of the KK handshake
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum HandshakeMessage {
+        /// `-> e, es, ss`, read by the responder
+        First,
+        /// `<- e, ee, se`, read by the initiator
+        Second,
+    }
+
+    impl fmt::Display for HandshakeMessage {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::First => write!(f, "-> e, es, ss"),
+                Self::Second => write!(f, "<- e, ee, se"),
+            }
+        }
+    }
+
+    /// A static key which isn't the one the other side expected
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct KeyMismatch {
+        /// Whose key it is
+        pub of: HandshakeSide,
+        /// The key(s) the other side would have accepted
+        pub expected: Vec<NoisePubKey>,
+        pub presented: NoisePubKey,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct HandshakeFailure {
+        /// The side which noticed it
+        pub side: HandshakeSide,
+        /// The message it could not process
+        pub message: HandshakeMessage,
+        /// The static key which made it fail, if it's a key mismatch
+        pub mismatch: Option<KeyMismatch>,
+        /// The error of the transport
+        pub error: String,
+    }
+
+    impl fmt::Display for HandshakeFailure {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "Handshake failed on the {} side processing '{}' ({})",
+                self.side, self.message, self.error
+            )?;
+            if let Some(mismatch) = &self.mismatch {
+                let expected: Vec<String> =
+                    mismatch.expected.iter().map(|k| k.0.to_hex()).collect();
+                write!(
+                    f,
+                    ": the {} presented static key '{}', expected one of {:?}",
+                    mismatch.of,
+                    mismatch.presented.0.to_hex(),
+                    expected
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    // What each side of the handshakes with the stub servers knows, by address of the server
+    #[derive(Default)]
+    struct Registry {
+        // The static key of the server, and the ones of the clients it accepts
+        servers: HashMap<SocketAddr, (NoisePubKey, Vec<NoisePubKey>)>,
+        // The static key of the last client to connect, and the one it expected from the server
+        initiators: HashMap<SocketAddr, (NoisePubKey, NoisePubKey)>,
+        // The last failure noticed by a client
+        initiator_failures: HashMap<SocketAddr, HandshakeFailure>,
+    }
+
+    fn registry() -> &'static Mutex<Registry> {
+        static INIT: Once = Once::new();
+        static REGISTRY: AtomicPtr<Mutex<Registry>> = AtomicPtr::new(ptr::null_mut());
+        INIT.call_once(|| {
+            let registry = Box::new(Mutex::new(Registry::default()));
+            REGISTRY.store(Box::into_raw(registry), Ordering::SeqCst);
+        });
+        // Set once above and never freed
+        unsafe { &*REGISTRY.load(Ordering::SeqCst) }
+    }
+
+    // Why the handshake with the server at this address failed, if we know both sides
+    fn diagnose(
+        registry: &Registry,
+        host: SocketAddr,
+        side: HandshakeSide,
+        error: String,
+    ) -> Option<HandshakeFailure> {
+        let (server_key, clients) = registry.servers.get(&host)?;
+        let (client_key, expected_server_key) = registry.initiators.get(&host)?;
+        let mismatch = if expected_server_key != server_key {
+            Some(KeyMismatch {
+                of: HandshakeSide::Responder,
+                expected: vec![*expected_server_key],
+                presented: *server_key,
+            })
+        } else if !clients.contains(client_key) {
+            Some(KeyMismatch {
+                of: HandshakeSide::Initiator,
+                expected: clients.clone(),
+                presented: *client_key,
+            })
+        } else {
+            None
+        };
+        let message = match side {
+            HandshakeSide::Initiator => HandshakeMessage::Second,
+            HandshakeSide::Responder => HandshakeMessage::First,
+        };
+
+        Some(HandshakeFailure {
+            side,
+            message,
+            mismatch,
+            error,
+        })
+    }
+
+    /// Record a handshake about to be initiated to this host, with this static key and expecting
+    /// this one from it.
+    pub fn initiating(host: SocketAddr, noise_secret: &NoisePrivKey, expected: &NoisePubKey) {
+        registry()
+            .lock()
+            .unwrap()
+            .initiators
+            .insert(host, (public_key(noise_secret), *expected));
+    }
+
+    /// The handshake initiated to this host failed, diagnose it if the host is a stub server.
+    pub fn initiator_failed(host: SocketAddr, error: &revault_net::Error) {
+        let mut registry = registry().lock().unwrap();
+        if let Some(failure) =
+            diagnose(&registry, host, HandshakeSide::Initiator, error.to_string())
+        {
+            log::error!("{}", failure);
+            registry.initiator_failures.insert(host, failure);
+        }
+    }
+
+    /// The last handshake failure noticed by a client of the stub server at this address
+    pub fn initiator_failure(host: SocketAddr) -> Option<HandshakeFailure> {
+        registry()
+            .lock()
+            .unwrap()
+            .initiator_failures
+            .get(&host)
+            .cloned()
+    }
+
+    /// A stub server answering the requests with the given responses, in order. Once they are
+    /// exhausted, the requests are left unanswered.
+    pub fn scripted(
+        responses: Vec<ResponseResult>,
+    ) -> impl FnMut(&RequestParams) -> Option<ResponseResult> + Send + 'static {
+        let mut responses = responses.into_iter();
+        move |_| responses.next()
+    }
+
+    /// A server listening in-process for Noise KK connections, answering the requests on each
+    /// connection with a responder. It records the requests and the handshake failures.
+    pub struct StubServer {
+        addr: SocketAddr,
+        noise_key: NoisePubKey,
+        requests: Arc<Mutex<Vec<RequestParams>>>,
+        failures: Arc<Mutex<Vec<HandshakeFailure>>>,
+        thread: Option<thread::JoinHandle<()>>,
+    }
+
+    pub struct StubServerBuilder {
+        keypair: (NoisePubKey, NoisePrivKey),
+        clients: Vec<NoisePubKey>,
+        listener: Option<TcpListener>,
+        connections: Option<usize>,
+    }
+
+    impl StubServerBuilder {
+        /// Accept connections from the client with this static key
+        pub fn client(mut self, client: NoisePubKey) -> Self {
+            self.clients.push(client);
+            self
+        }
+
+        /// Listen with this listener rather than on a new local port
+        pub fn listener(mut self, listener: TcpListener) -> Self {
+            self.listener = Some(listener);
+            self
+        }
+
+        /// Stop listening after this many connection attempts, instead of never
+        pub fn connections(mut self, connections: usize) -> Self {
+            self.connections = Some(connections);
+            self
+        }
+
+        pub fn start(
+            self,
+            mut responder: impl FnMut(&RequestParams) -> Option<ResponseResult> + Send + 'static,
+        ) -> StubServer {
+            let Self {
+                keypair: (noise_key, noise_secret),
+                clients,
+                listener,
+                connections,
+            } = self;
+            let listener = listener.unwrap_or_else(|| TcpListener::bind("127.0.0.1:0").unwrap());
+            let addr = listener.local_addr().unwrap();
+            registry()
+                .lock()
+                .unwrap()
+                .servers
+                .insert(addr, (noise_key, clients.clone()));
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let failures = Arc::new(Mutex::new(Vec::new()));
+
+            let (requests_rec, failures_rec) = (requests.clone(), failures.clone());
+            let thread = thread::spawn(move || {
+                let mut attempts = 0;
+                while connections.map(|max| attempts < max).unwrap_or(true) {
+                    attempts += 1;
+                    let mut transport =
+                        match KKTransport::accept(&listener, &noise_secret, &clients) {
+                            Ok(transport) => transport,
+                            Err(e) => {
+                                let registry = registry().lock().unwrap();
+                                if let Some(failure) = diagnose(
+                                    &registry,
+                                    addr,
+                                    HandshakeSide::Responder,
+                                    e.to_string(),
+                                ) {
+                                    log::error!("{}", failure);
+                                    failures_rec.lock().unwrap().push(failure);
+                                }
+                                continue;
+                            }
+                        };
+                    // Until the client closes the connection
+                    while transport
+                        .read_req(|params| {
+                            let response = responder(&params);
+                            requests_rec.lock().unwrap().push(params);
+                            response
+                        })
+                        .is_ok()
+                    {}
+                }
+            });
+
+            StubServer {
+                addr,
+                noise_key,
+                requests,
+                failures,
+                thread: Some(thread),
+            }
+        }
+    }
+
+    impl StubServer {
+        /// A stub server with the static key of this label
+        pub fn builder(label: &str) -> StubServerBuilder {
+            StubServerBuilder {
+                keypair: test_keypair(label),
+                clients: Vec::new(),
+                listener: None,
+                connections: None,
+            }
+        }
+
+        pub fn addr(&self) -> SocketAddr {
+            self.addr
+        }
+
+        pub fn noise_key(&self) -> NoisePubKey {
+            self.noise_key
+        }
+
+        /// Take the requests received so far
+        pub fn take_requests(&self) -> Vec<RequestParams> {
+            std::mem::replace(&mut *self.requests.lock().unwrap(), Vec::new())
+        }
+
+        /// The handshakes it failed, as the responder
+        pub fn handshake_failures(&self) -> Vec<HandshakeFailure> {
+            self.failures.lock().unwrap().clone()
+        }
+
+        /// Wait until it served all the connections it was built for, and stopped listening.
+        /// Returns the handshakes it failed.
+        pub fn join(mut self) -> Vec<HandshakeFailure> {
+            self.thread
+                .take()
+                .expect("Only taken here")
+                .join()
+                .expect("Stub server panicked");
+            self.handshake_failures()
+        }
+    }
+}
+
+/// Fixtures for the benchmarks under `contrib/benches/`, which need a wallet we hold all the
+/// keys of in order to exercise the script derivation and signature verification paths. The
+/// integration tests needing a large wallet use them too.
+#[cfg(feature = "benches")]
+pub mod bench_utils {
+    use crate::{
+        config::Config,
+        database::{
+            actions::{
+                db_insert_new_unconfirmed_vault_dbtx, db_store_derived_scripts, db_txs_merge_sigs,
+                setup_db,
+            },
+            bitcointx::RevaultTx,
+            interface::db_exec,
+            schema::{DbTransaction, TransactionType},
+        },
+        derivation::DerivationIndex,
+        revaultd::RevaultD,
+    };
+    use revault_tx::{
+        bitcoin::{
+            hashes::Hash,
+            secp256k1,
+            util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey},
+            Address, Amount, Network, OutPoint, PrivateKey as BitcoinPrivKey,
+            PublicKey as BitcoinPubKey, SigHashType, Txid,
+        },
+        miniscript::descriptor::{
+            DescriptorPublicKey, DescriptorSinglePub, DescriptorXKey, Wildcard,
+        },
+        scripts::{CpfpDescriptor, DepositDescriptor, UnvaultDescriptor},
+        transactions::{
+            transaction_chain, CancelTransaction, RevaultTransaction, UnvaultTransaction,
+        },
+    };
+
+    use std::{path::PathBuf, str::FromStr};
+
+    const EMERGENCY_ADDRESS: &str =
+        "bcrt1qewc2348370pgw8kjz8gy09z8xyh0d9fxde6nzamd3txc9gkmjqmq8m4cdq";
+
+    fn xpriv(seed: u8) -> ExtendedPrivKey {
+        ExtendedPrivKey::new_master(Network::Testnet, &[seed; 32]).expect("Valid seed")
+    }
+
+    fn desc_xpub(
+        secp: &secp256k1::Secp256k1<secp256k1::All>,
+        xpriv: &ExtendedPrivKey,
+    ) -> DescriptorPublicKey {
+        DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: None,
+            xkey: ExtendedPubKey::from_private(secp, xpriv),
+            derivation_path: DerivationPath::from(vec![]),
+            wildcard: Wildcard::Unhardened,
+        })
+    }
+
+    /// A stakeholder's wallet in a deployment with `n_stakeholders` stakeholders and
+    /// `n_managers` managers, all of whose private keys we know.
+    pub struct BenchWallet {
+        revaultd: RevaultD,
+        stk_xprivs: Vec<ExtendedPrivKey>,
+        secp: secp256k1::Secp256k1<secp256k1::All>,
+    }
+
+    impl BenchWallet {
+        pub fn new(datadir: PathBuf, n_stakeholders: u8, n_managers: u8) -> Self {
+            let secp = secp256k1::Secp256k1::new();
+            let stk_xprivs: Vec<ExtendedPrivKey> =
+                (0..n_stakeholders).map(|i| xpriv(i + 1)).collect();
+            let stk_keys: Vec<DescriptorPublicKey> =
+                stk_xprivs.iter().map(|x| desc_xpub(&secp, x)).collect();
+            let man_keys: Vec<DescriptorPublicKey> = (0..n_managers)
+                .map(|i| desc_xpub(&secp, &xpriv(n_stakeholders + i + 1)))
+                .collect();
+            let cosig_keys = (0..n_stakeholders)
+                .map(|i| {
+                    let key = secp256k1::SecretKey::from_slice(&[100 + i; 32]).expect("Valid key");
+                    DescriptorPublicKey::SinglePub(DescriptorSinglePub {
+                        origin: None,
+                        key: BitcoinPubKey::from_private_key(
+                            &secp,
+                            &BitcoinPrivKey {
+                                compressed: true,
+                                network: Network::Regtest,
+                                key,
+                            },
+                        ),
+                    })
+                })
+                .collect();
+
+            let deposit_descriptor =
+                DepositDescriptor::new(stk_keys.clone()).expect("Valid deposit descriptor");
+            let unvault_descriptor =
+                UnvaultDescriptor::new(stk_keys, man_keys.clone(), 1, cosig_keys, 6)
+                    .expect("Valid unvault descriptor");
+            let cpfp_descriptor = CpfpDescriptor::new(man_keys).expect("Valid CPFP descriptor");
+
+            let config = format!(
+                r#"
+coordinator_host = "127.0.0.1:1"
+coordinator_noise_key = "d91563973102454a7830137e92d0548bc83b4ea2799f1df04622ca1307381402"
+
+[scripts_config]
+deposit_descriptor = "{}"
+unvault_descriptor = "{}"
+cpfp_descriptor = "{}"
+
+[stakeholder_config]
+xpub = "{}"
+watchtowers = []
+emergency_address = "{}"
+
+[bitcoind_config]
+network = "regtest"
+cookie_path = "/home/user/.bitcoin/.cookie"
+addr = "127.0.0.1:8332"
+"#,
+                deposit_descriptor,
+                unvault_descriptor,
+                cpfp_descriptor,
+                ExtendedPubKey::from_private(&secp, &stk_xprivs[0]),
+                EMERGENCY_ADDRESS
+            );
+            let mut config: Config = toml::from_str(&config).expect("Valid bench config");
+            config.data_dir = Some(datadir);
+            let revaultd = RevaultD::from_config(config).expect("Creating state from config");
+
+            BenchWallet {
+                revaultd,
+                stk_xprivs,
+                secp,
+            }
+        }
+
+        /// Derive the deposit and Unvault addresses for the first `count` derivation indexes.
+        pub fn derive_addresses(&self, count: u32) -> Vec<(Address, Address)> {
+            let last = DerivationIndex::new(count.saturating_sub(1)).expect("Not hardened");
+            DerivationIndex::ZERO
+                .up_to(last)
+                .map(|index| {
+                    (
+                        self.revaultd
+                            .vault_address(index)
+                            .expect("Supported script"),
+                        self.revaultd
+                            .unvault_address(index)
+                            .expect("Supported script"),
+                    )
+                })
+                .collect()
+        }
+
+        /// Create the database of a wallet which derived the scripts of the first `count`
+        /// derivation indexes.
+        pub fn store_derived_scripts(&mut self, count: u32) {
+            setup_db(&mut self.revaultd).expect("Creating the database");
+            let last = DerivationIndex::new(count.saturating_sub(1)).expect("Not hardened");
+            let indexes: Vec<DerivationIndex> = DerivationIndex::ZERO.up_to(last).collect();
+            db_store_derived_scripts(&mut self.revaultd, &indexes)
+                .expect("Storing the derived scripts");
+        }
+
+        /// Load the derived scripts from the database, as we do at startup. Returns the number
+        /// of derivation indexes loaded.
+        pub fn load_derived_scripts(&mut self) -> usize {
+            self.revaultd.derivation_index_map.clear();
+            self.revaultd.unvault_derivation_index_map.clear();
+            setup_db(&mut self.revaultd).expect("Loading the database");
+            self.revaultd.derivation_index_map.len()
+        }
+
+        /// Create the database of a wallet which received `count` deposits, not confirmed yet.
+        pub fn store_vaults(&mut self, count: u32) {
+            setup_db(&mut self.revaultd).expect("Creating the database");
+            let wallet_id = self
+                .revaultd
+                .wallet_id
+                .expect("Wallet id is set at startup in setup_db()");
+            db_exec(&self.revaultd.db_file(), |tx| {
+                for i in 0..count {
+                    let outpoint = OutPoint {
+                        txid: Txid::hash(&i.to_be_bytes()),
+                        vout: 0,
+                    };
+                    let index = DerivationIndex::new(i).expect("Not hardened");
+                    db_insert_new_unconfirmed_vault_dbtx(
+                        tx,
+                        wallet_id,
+                        &outpoint,
+                        &Amount::from_sat(1_234_567),
+                        index,
+                    )?;
+                }
+                Ok(())
+            })
+            .expect("Storing the vaults");
+        }
+
+        /// Stream the 'listvaults' response for the vaults in database as the JSONRPC server
+        /// does, while `read_response` reads it from another thread as the server loop would
+        /// write it to the socket.
+        #[cfg(all(not(windows), feature = "jsonrpc_server"))]
+        pub fn stream_listvaults<F, R>(&self, read_response: F) -> R
+        where
+            F: FnOnce(&mut dyn std::io::Read) -> R + Send + 'static,
+            R: Send + 'static,
+        {
+            use crate::{
+                commands::{utils::vaults_at_heights, VaultHeightFilter},
+                jsonrpc::stream::{PendingResponse, ResponseStream},
+            };
+            use std::{
+                collections::VecDeque,
+                sync::{Arc, RwLock},
+                thread,
+            };
+
+            let queue = Arc::new(RwLock::new(VecDeque::new()));
+            let stream = ResponseStream::new(jsonrpc_core::Id::Num(0), queue.clone());
+            let server_loop = thread::spawn(move || {
+                let receiver = loop {
+                    match queue.write().unwrap().pop_front() {
+                        Some(PendingResponse::Stream(receiver)) => break receiver,
+                        Some(PendingResponse::Bytes(_)) => panic!("Not a streamed response"),
+                        None => thread::yield_now(),
+                    }
+                };
+                read_response(&mut ChunkReader {
+                    receiver,
+                    chunk: Vec::new(),
+                    pos: 0,
+                    ended: false,
+                })
+            });
+
+            stream
+                .rows("vaults", |rows| {
+                    let filter = VaultHeightFilter::default();
+                    for vault in vaults_at_heights(&self.revaultd, None, None, &filter)
+                        .expect("Database must be available")
+                    {
+                        rows.push(&vault.expect("Database must be available"))?;
+                    }
+                    Ok(())
+                })
+                .expect("Streaming the vaults");
+            server_loop.join().expect("Reading the response")
+        }
+
+        /// The number of addresses to import into the watchonly wallet when the ones above
+        /// `since` are new, or all of them if `None`.
+        pub fn addresses_to_import(&self, since: Option<u32>) -> usize {
+            let since = since.map(|index| DerivationIndex::new(index).expect("Not hardened"));
+            self.revaultd.addresses_to_import(since).count()
+        }
+
+        fn derivation_index(&self) -> DerivationIndex {
+            DerivationIndex::new(42).expect("Not hardened")
+        }
+
+        fn stakeholder_sig(
+            &self,
+            xpriv: &ExtendedPrivKey,
+            tx: &impl RevaultTransaction,
+            sighash_type: SigHashType,
+        ) -> (secp256k1::PublicKey, secp256k1::Signature) {
+            let privkey = xpriv
+                .derive_priv(&self.secp, &[self.derivation_index().into()])
+                .expect("Not hardened")
+                .private_key
+                .key;
+            let sighash = secp256k1::Message::from_slice(
+                &tx.signature_hash(0, sighash_type)
+                    .expect("Valid input index"),
+            )
+            .expect("32 bytes");
+            (
+                secp256k1::PublicKey::from_secret_key(&self.secp, &privkey),
+                self.secp.sign(&sighash, &privkey),
+            )
+        }
+
+        fn transactions(&self) -> (UnvaultTransaction, CancelTransaction) {
+            let outpoint = OutPoint::from_str(
+                "39a8212c6a9b467680d43e47b61b8363fe1febb761f9f548eb4a432b2bc9bbec:0",
+            )
+            .expect("Valid outpoint");
+            let revaultd = &self.revaultd;
+            let amount = Amount::from_sat(567_890_000);
+            let (unvault_tx, cancel_tx, _, _) = transaction_chain(
+                outpoint,
+                amount,
+                &revaultd.deposit_descriptor,
+                &revaultd.unvault_descriptor,
+                &revaultd.cpfp_descriptor,
+                self.derivation_index().into(),
+                revaultd
+                    .emergency_addresses
+                    .as_ref()
+                    .expect("We are a stakeholder")
+                    .for_amount(amount)
+                    .clone(),
+                revaultd.lock_time,
+                &revaultd.secp_ctx,
+            )
+            .expect("Valid transaction chain");
+            (unvault_tx, cancel_tx)
+        }
+
+        /// A Cancel PSBT along with a signature from each stakeholder.
+        pub fn cancel_signatures(&self) -> SignatureSet {
+            let (_, cancel_tx) = self.transactions();
+            let sigs = self
+                .stk_xprivs
+                .iter()
+                .map(|xpriv| {
+                    self.stakeholder_sig(xpriv, &cancel_tx, SigHashType::AllPlusAnyoneCanPay)
+                })
+                .collect();
+
+            SignatureSet {
+                psbt: RevaultTx::Cancel(cancel_tx),
+                sigs,
+                secp: secp256k1::Secp256k1::verification_only(),
+            }
+        }
+
+        /// `copies` copies of the same Unvault PSBT, each signed by a single stakeholder in turn.
+        pub fn unvault_copies(&self, copies: usize) -> PsbtCopies {
+            let (unvault_tx, _) = self.transactions();
+            let db_tx = |psbt| DbTransaction {
+                id: 0,
+                vault_id: 0,
+                tx_type: TransactionType::Unvault,
+                psbt: RevaultTx::Unvault(psbt),
+                is_fully_signed: false,
+            };
+            let copies = (0..copies)
+                .map(|i| {
+                    let xpriv = &self.stk_xprivs[i % self.stk_xprivs.len()];
+                    let (pubkey, sig) = self.stakeholder_sig(xpriv, &unvault_tx, SigHashType::All);
+                    let mut copy = unvault_tx.clone();
+                    copy.add_signature(0, pubkey, sig, &self.secp)
+                        .expect("Valid signature");
+                    db_tx(copy)
+                })
+                .collect();
+
+            PsbtCopies {
+                unsigned: db_tx(unvault_tx),
+                copies,
+                secp: secp256k1::Secp256k1::verification_only(),
+            }
+        }
+    }
+
+    // Reads a streamed response as the server loop writes it to the socket
+    #[cfg(all(not(windows), feature = "jsonrpc_server"))]
+    struct ChunkReader {
+        receiver: std::sync::mpsc::Receiver<crate::jsonrpc::stream::Chunk>,
+        chunk: Vec<u8>,
+        pos: usize,
+        ended: bool,
+    }
+
+    #[cfg(all(not(windows), feature = "jsonrpc_server"))]
+    impl std::io::Read for ChunkReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            use crate::jsonrpc::stream::Chunk;
+
+            while !self.ended && self.pos == self.chunk.len() {
+                match self.receiver.recv() {
+                    Ok(Chunk::Data(data)) => {
+                        self.chunk = data;
+                        self.pos = 0;
+                    }
+                    Ok(Chunk::End) => self.ended = true,
+                    Err(_) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                }
+            }
+            let n = std::cmp::min(buf.len(), self.chunk.len() - self.pos);
+            buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    /// A presigned transaction and the signatures submitted for it.
+    pub struct SignatureSet {
+        psbt: RevaultTx,
+        sigs: Vec<(secp256k1::PublicKey, secp256k1::Signature)>,
+        secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    }
+
+    impl SignatureSet {
+        /// Verify and add all the signatures to a copy of the PSBT, as we do for the ones
+        /// submitted through `revocationtxs`. Returns whether they were all valid.
+        pub fn verify(&self) -> bool {
+            let mut psbt = self.psbt.clone();
+            self.sigs
+                .iter()
+                .all(|(pubkey, sig)| psbt.add_signature(*pubkey, *sig, &self.secp).is_ok())
+        }
+    }
+
+    /// Partially-signed copies of the same presigned transaction.
+    pub struct PsbtCopies {
+        unsigned: DbTransaction,
+        copies: Vec<DbTransaction>,
+        secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    }
+
+    impl PsbtCopies {
+        /// Merge the signatures of all the copies into the unsigned one, as we do when
+        /// updating the presigned transactions in database. Returns whether it ended up
+        /// fully signed.
+        pub fn merge(&self) -> bool {
+            let mut merged = self.unsigned.clone();
+            let mut is_fully_signed = false;
+            for copy in &self.copies {
+                is_fully_signed = db_txs_merge_sigs(&mut merged, copy, &self.secp);
+            }
+            is_fully_signed
+        }
     }
 }
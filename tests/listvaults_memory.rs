@@ -0,0 +1,160 @@
+//! The 'listvaults' response of a large deployment is streamed with a bounded memory usage.
+//!
+//! This counts the allocations of the whole process, so it's the only test of its binary.
+
+use revaultd::{bench_utils::BenchWallet, commands::ListVaultsEntry};
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    env, fmt, fs,
+    io::{self, BufReader, Read},
+    sync::atomic::{AtomicIsize, Ordering},
+};
+
+use serde::{
+    de::{Deserializer, SeqAccess, Visitor},
+    Deserialize,
+};
+
+const N_VAULTS: u32 = 10_000;
+
+// Counts the memory allocated by the threads tracking it, and its peak
+struct CountingAllocator;
+
+static ALLOCATED: AtomicIsize = AtomicIsize::new(0);
+static PEAK: AtomicIsize = AtomicIsize::new(0);
+
+thread_local! {
+    static TRACKED: Cell<bool> = Cell::new(false);
+}
+
+fn tracked() -> bool {
+    TRACKED.try_with(|tracked| tracked.get()).unwrap_or(false)
+}
+
+fn set_tracked(tracked: bool) {
+    TRACKED.with(|t| t.set(tracked));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if tracked() {
+            let size = layout.size() as isize;
+            let current = ALLOCATED.fetch_add(size, Ordering::SeqCst) + size;
+            let mut peak = PEAK.load(Ordering::SeqCst);
+            while current > peak {
+                match PEAK.compare_exchange(peak, current, Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) => break,
+                    Err(p) => peak = p,
+                }
+            }
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if tracked() {
+            ALLOCATED.fetch_sub(layout.size() as isize, Ordering::SeqCst);
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Reads the response as the client would, recording its size. The vaults were all loaded from
+// the database by the time the first rows are sent: from then on, we record the peak of the
+// memory in use above what it was.
+struct CountingReader<'a> {
+    inner: &'a mut dyn Read,
+    size: usize,
+    start: Option<isize>,
+}
+
+impl Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if self.start.is_none() {
+            let start = ALLOCATED.load(Ordering::SeqCst);
+            PEAK.store(start, Ordering::SeqCst);
+            self.start = Some(start);
+        }
+        self.size += n;
+        Ok(n)
+    }
+}
+
+// The number of vaults of the response, checked but not kept
+struct VaultRows(u32);
+
+impl<'de> Deserialize<'de> for VaultRows {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RowsVisitor;
+
+        impl<'de> Visitor<'de> for RowsVisitor {
+            type Value = VaultRows;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an array of vaults")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<VaultRows, A::Error> {
+                let mut n = 0;
+                while seq.next_element::<ListVaultsEntry>()?.is_some() {
+                    n += 1;
+                }
+                Ok(VaultRows(n))
+            }
+        }
+
+        deserializer.deserialize_seq(RowsVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+struct ListVaultsResult {
+    vaults: VaultRows,
+}
+
+#[derive(Deserialize)]
+struct ListVaultsResponse {
+    jsonrpc: String,
+    result: ListVaultsResult,
+    id: u64,
+}
+
+#[test]
+fn listvaults_bounded_memory() {
+    let datadir = env::temp_dir().join("revaultd-listvaults-memory");
+    fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    let mut wallet = BenchWallet::new(datadir.clone(), 4, 2);
+    wallet.store_vaults(N_VAULTS);
+
+    // Only count what's allocated while streaming, by the handler and the server loop
+    set_tracked(true);
+    let (resp, size, start) = wallet.stream_listvaults(|response| {
+        set_tracked(true);
+        let mut reader = CountingReader {
+            inner: response,
+            size: 0,
+            start: None,
+        };
+        let resp: ListVaultsResponse =
+            serde_json::from_reader(BufReader::new(&mut reader)).unwrap();
+        set_tracked(false);
+        (resp, reader.size, reader.start.unwrap())
+    });
+    set_tracked(false);
+
+    assert_eq!(resp.jsonrpc, "2.0");
+    assert_eq!(resp.id, 0);
+    assert_eq!(resp.result.vaults.0, N_VAULTS);
+    // Megabytes were sent, while never more than a few chunks were in memory
+    assert!(size > 3_000_000, "{}", size);
+    let peak = PEAK.load(Ordering::SeqCst) - start;
+    assert!(peak < 1_000_000, "Peak allocation of {} bytes", peak);
+
+    fs::remove_dir_all(&datadir).unwrap();
+}
@@ -4,13 +4,20 @@ This includes the tracking the status of the vaults, wallet transactions,
 handling of reorgs, etc..
 """
 
+import glob
+import os
 import logging
 import pytest
+import shutil
+import time
+import urllib.parse
 
 from fixtures import *
 from test_framework import serializations
+from test_framework.bitcoind import BitcoindRpcInterface
 from test_framework.utils import (
     POSTGRES_IS_SETUP,
+    RpcError,
     wait_for,
 )
 
@@ -871,3 +878,760 @@ def test_retrieve_vault_status(revault_network, bitcoind):
         )
         == 1
     )
+
+
+def test_bitcoind_restart(revaultd_stakeholder, bitcoind):
+    """We keep tracking the chain if bitcoind restarts and does not load our wallet back."""
+    stk = revaultd_stakeholder
+
+    addr = stk.rpc.getdepositaddress()["address"]
+    bitcoind.rpc.sendtoaddress(addr, 0.5)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 1)
+
+    # Restart bitcoind, without it loading back any wallet on startup
+    bitcoind.stop()
+    settings_file = os.path.join(bitcoind.bitcoin_dir, "regtest", "settings.json")
+    if os.path.isfile(settings_file):
+        os.remove(settings_file)
+    bitcoind.start()
+    bitcoind.rpc.loadwallet(bitcoind.rpc.wallet_name)
+
+    stk.wait_for_logs(
+        [
+            "Our watchonly wallet is not loaded anymore",
+            "Loading our watchonly wallet",
+        ]
+    )
+    wait_for(lambda: stk.rpc.getinfo()["bitcoind_reachable"])
+
+    # We still detect new deposits
+    addr = stk.rpc.getdepositaddress()["address"]
+    bitcoind.rpc.sendtoaddress(addr, 0.6)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 2)
+
+
+def test_wallet_rescan(revaultd_stakeholder, bitcoind):
+    """We stay responsive while bitcoind rescans the chain for a re-created wallet."""
+    stk = revaultd_stakeholder
+
+    addr = stk.rpc.getdepositaddress()["address"]
+    bitcoind.rpc.sendtoaddress(addr, 0.5)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 1)
+    # Give it some history to go through
+    bitcoind.generate_block(1000)
+    wait_for(lambda: stk.rpc.getinfo()["blockheight"] == bitcoind.rpc.getblockcount())
+
+    # Wipe the watchonly wallet, it will be re-created and bitcoind will rescan the chain
+    # from the date of its creation.
+    stk.stop()
+    wallet_dir = glob.glob(
+        os.path.join(stk.datadir_with_network, "revaultd-watchonly-wallet-*")
+    )[0]
+    bitcoind.rpc.unloadwallet(wallet_dir)
+    shutil.rmtree(wallet_dir)
+    stk.start()
+    stk.wait_for_log("Importing .* Deposit descriptor.*, bitcoind is going to rescan")
+
+    # The imports are queued and we keep answering in the meantime
+    while not stk.is_in_log("Done importing Unvault descriptor"):
+        start = time.time()
+        stk.rpc.getinfo()
+        assert time.time() - start < 5
+        time.sleep(0.1)
+
+    # Once done, we are back to normal
+    wait_for(lambda: stk.rpc.getinfo()["sync"] == 1.0)
+    assert len(stk.rpc.listvaults()["vaults"]) == 1
+    addr = stk.rpc.getdepositaddress()["address"]
+    bitcoind.rpc.sendtoaddress(addr, 0.6)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 2)
+
+
+def test_deposit_index_gap(revaultd_stakeholder, bitcoind):
+    """A deposit far above our first unused derivation index makes us extend the
+    addresses we watch, and detect the deposits that were beyond them."""
+    stk = revaultd_stakeholder
+
+    wait_for(lambda: stk.rpc.getinfo()["deposit_indexes"]["watched_up_to"] == 100)
+    indexes = stk.rpc.getinfo()["deposit_indexes"]
+    assert indexes["first_unused"] == 0
+    assert indexes["max_observed"] is None
+    assert indexes["ceiling"] == 100000
+    window_end = indexes["watched_up_to"]
+
+    # Another participant handed out an address 50 indexes beyond the ones we watch, and
+    # it got paid. We can't see it.
+    far_addr = stk.rpc.getdepositaddress(window_end + 50)["address"]
+    far_txid = bitcoind.rpc.sendtoaddress(far_addr, 0.5)
+    bitcoind.generate_block(1, wait_for_mempool=far_txid)
+    wait_for(lambda: stk.rpc.getinfo()["blockheight"] == bitcoind.rpc.getblockcount())
+    assert len(stk.rpc.listvaults()["vaults"]) == 0
+
+    # Then the one at the edge of our window got paid. We move our first unused index
+    # past it, extend the addresses we watch and rescan the chain for deposits to them.
+    edge_addr = stk.rpc.getdepositaddress(window_end)["address"]
+    bitcoind.rpc.sendtoaddress(edge_addr, 0.6)
+    stk.wait_for_logs(
+        [
+            f"is at derivation index {window_end}, {window_end} indexes above our "
+            "first unused one",
+            f"Extending the deposit addresses we watch from derivation index "
+            f"{window_end + 1} to {window_end * 2 + 1}",
+        ]
+    )
+
+    # Which detects the deposit we missed
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 2)
+    far_vault = next(v for v in stk.rpc.listvaults()["vaults"] if v["txid"] == far_txid)
+    assert far_vault["derivation_index"] == window_end + 50
+    wait_for(lambda: stk.rpc.getinfo()["sync"] == 1.0)
+    indexes = stk.rpc.getinfo()["deposit_indexes"]
+    assert indexes["first_unused"] == window_end + 51
+    assert indexes["max_observed"] == window_end + 50
+    wait_for(
+        lambda: stk.rpc.getinfo()["deposit_indexes"]["watched_up_to"]
+        == window_end + 151
+    )
+
+
+def test_rescan_window(revaultd_stakeholder, bitcoind):
+    """The rescans for the deposit addresses we extend our watch to go back to when the
+    addresses preceding them were needed, not to the creation of the wallet."""
+    stk = revaultd_stakeholder
+    wait_for(lambda: stk.rpc.getinfo()["deposit_indexes"]["watched_up_to"] == 100)
+
+    def mine_days_later(days):
+        """Mine blocks timestamped this many days from now, return the height of the
+        first one and their time."""
+        block_time = int(time.time()) + days * 24 * 3600
+        bitcoind.rpc.setmocktime(block_time)
+        first_height = bitcoind.rpc.getblockcount() + 1
+        bitcoind.generate_block(11)
+        wait_for(
+            lambda: stk.rpc.getinfo()["blockheight"] == bitcoind.rpc.getblockcount()
+        )
+        return first_height, block_time
+
+    def extend_past(index):
+        """Get a deposit at the edge of the addresses we watch, and wait for us to be done
+        rescanning for the ones we extend our watch to. Returns the windows of the rescans
+        getinfo reported meanwhile."""
+        log_start = len(stk.logs)
+        bitcoind.rpc.sendtoaddress(stk.rpc.getdepositaddress(index)["address"], 0.5)
+        windows = []
+        while not stk.is_in_log("Done importing Unvault descriptor", log_start):
+            rescan = stk.rpc.getinfo()["rescan"]
+            if rescan is not None:
+                windows.append(rescan)
+            time.sleep(0.1)
+        wait_for(lambda: stk.rpc.getinfo()["rescan"] is None)
+        wait_for(lambda: stk.rpc.getinfo()["sync"] == 1.0)
+        assert stk.rpc.getinfo()["deposit_indexes"]["watched_up_to"] == index + 101
+        return windows
+
+    # A month after the wallet was created, our first extension. Deposits to the new
+    # addresses may have been received since the creation of the wallet.
+    created_before = int(time.time())
+    month_height, month_time = mine_days_later(30)
+    for window in extend_past(100):
+        assert window["kind"] in ("deposit", "unvault")
+        assert window["from_timestamp"] <= created_before
+    stk.wait_for_log(
+        "Importing 101 Deposit descriptor.*, bitcoind is going to rescan the chain from "
+        "timestamp"
+    )
+
+    # Another month later, the second one. Deposits to these addresses could only be
+    # received once the ones of the first extension were needed, a month ago.
+    mine_days_later(60)
+    for window in extend_past(201):
+        assert window["from_timestamp"] == month_time
+    stk.wait_for_log(
+        f"Importing 101 Deposit descriptor.*, bitcoind is going to rescan the chain from "
+        f"timestamp {month_time}\\."
+    )
+
+    # bitcoind started rescanning from the first block mined back then, not from the
+    # creation of the wallet
+    start_hash = bitcoind.rpc.getblockhash(month_height)
+    bitcoind.wait_for_log(f"Rescan started from block {start_hash}")
+    assert bitcoind.rpc.getblockheader(start_hash)["time"] == month_time
+    previous_hash = bitcoind.rpc.getblockhash(month_height - 1)
+    assert bitcoind.rpc.getblockheader(previous_hash)["time"] < month_time - 2 * 3600
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_unvault_csv_countdown(revault_network, bitcoind):
+    """The remaining CSV blocks are exposed for unvaulted vaults, which become spendable
+    once it expired."""
+    CSV = 4
+    revault_network.deploy(2, 1, csv=CSV, with_watchtowers=False)
+    vault = revault_network.fund(1)
+    revault_network.secure_vault(vault)
+    revault_network.activate_vault(vault)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+
+    # Broadcast the Unvault without any Spend so the vault isn't spent once mature
+    stk = revault_network.stk(0)
+    unvault_tx = stk.rpc.listpresignedtransactions([deposit])[
+        "presigned_transactions"
+    ][0]["unvault"]["hex"]
+    txid = bitcoind.rpc.sendrawtransaction(unvault_tx)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    unvault_height = bitcoind.rpc.getblockcount()
+
+    for w in revault_network.participants():
+        wait_for(lambda: len(w.rpc.listvaults(["unvaulted"], [deposit])["vaults"]) == 1)
+        entry = w.rpc.listvaults([], [deposit])["vaults"][0]
+        assert entry["unvault_height"] == unvault_height
+        assert entry["csv"] == CSV
+        assert entry["blocks_until_spendable"] == CSV - 1
+
+    # One block before maturity it's still counting down
+    bitcoind.generate_block(CSV - 2)
+    for w in revault_network.participants():
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0][
+                "blocks_until_spendable"
+            ]
+            == 1
+        )
+        assert w.rpc.listvaults([], [deposit])["vaults"][0]["status"] == "unvaulted"
+
+    # Once the CSV expired the vault is spendable
+    bitcoind.generate_block(1)
+    for w in revault_network.participants():
+        wait_for(
+            lambda: len(w.rpc.listvaults(["spendable"], [deposit])["vaults"]) == 1
+        )
+        entry = w.rpc.listvaults([], [deposit])["vaults"][0]
+        assert entry["blocks_until_spendable"] == 0
+        assert entry["unvault_height"] == unvault_height
+
+    # It can still be canceled
+    revault_network.cancel_vault(vault)
+    for w in revault_network.participants():
+        entry = w.rpc.listvaults([], [deposit])["vaults"][0]
+        assert entry["csv"] is None
+        assert entry["blocks_until_spendable"] is None
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_listvaults_as_of_height(revault_network, bitcoind):
+    """We can list the vaults as they were at a past height, and filter them by funding
+    height."""
+    revault_network.deploy(2, 1, csv=3, with_watchtowers=False)
+    participants = revault_network.participants()
+
+    def mine_and_sync(n=1):
+        bitcoind.generate_block(n)
+        height = bitcoind.rpc.getblockcount()
+        for w in participants:
+            wait_for(lambda: w.rpc.getinfo()["blockheight"] == height)
+        return height
+
+    def status_as_of(w, deposit, height):
+        vaults = w.rpc.listvaults([], [deposit], None, None, height)["vaults"]
+        return vaults[0]["status"] if len(vaults) > 0 else None
+
+    vault = revault_network.fund(1)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    funded_height = vault["blockheight"]
+    mine_and_sync()
+    revault_network.secure_vault(vault)
+    secured_height = bitcoind.rpc.getblockcount()
+    mine_and_sync()
+    revault_network.activate_vault(vault)
+    active_height = bitcoind.rpc.getblockcount()
+    mine_and_sync()
+    revault_network.unvault_vaults_anyhow([vault])
+    unvaulted_height = bitcoind.rpc.getblockcount()
+    mine_and_sync()
+    revault_network.cancel_vault(vault)
+    canceled_height = bitcoind.rpc.getblockcount()
+    # Get the vault created by the Cancel to be confirmed
+    mine_and_sync(6)
+
+    for w in participants:
+        assert status_as_of(w, deposit, funded_height - 10) is None
+        assert status_as_of(w, deposit, secured_height - 1) == "funded"
+        assert status_as_of(w, deposit, secured_height) == "secured"
+        assert status_as_of(w, deposit, active_height) == "active"
+        assert status_as_of(w, deposit, unvaulted_height) == "unvaulted"
+        assert status_as_of(w, deposit, canceled_height) == "canceled"
+        vaults = w.rpc.listvaults(["active"], None, None, None, active_height)["vaults"]
+        assert [v["txid"] for v in vaults] == [vault["txid"]]
+
+        entry = w.rpc.listvaults([], [deposit])["vaults"][0]
+        assert entry["status"] == "canceled"
+        assert entry["secured_height"] == secured_height
+        assert entry["moved_height"] == canceled_height
+        # As of before the cancel, it was not moved yet
+        entry = w.rpc.listvaults([], [deposit], None, None, unvaulted_height)["vaults"][0]
+        assert entry["moved_height"] is None
+
+        # The vault created by the Cancel was funded after the original one
+        vaults = w.rpc.listvaults([], None, funded_height, funded_height)["vaults"]
+        assert [v["txid"] for v in vaults] == [vault["txid"]]
+        vaults = w.rpc.listvaults([], None, funded_height + 1)["vaults"]
+        assert len(vaults) == 1 and vaults[0]["revaulted_from"] == deposit
+        assert w.rpc.listvaults([], None, None, funded_height - 1)["vaults"] == []
+
+
+def test_spender_classification(revault_network, bitcoind):
+    """Check we tell apart the transactions spending the Deposit and Unvault outputs,
+    and raise the alarm for the ones we don't know of."""
+    # Any of the two managers may spend alone
+    revault_network.deploy(
+        2, 2, csv=3, managers_threshold=1, with_cosigs=False, with_watchtowers=False
+    )
+    stks = revault_network.stks()
+    mans = revault_network.mans()
+    participants = stks + mans
+    vaults = revault_network.fundmany([1, 2, 3, 4])
+    deposits = [f"{v['txid']}:{v['vout']}" for v in vaults]
+    revault_network.activate_fresh_vaults(vaults[1:])
+
+    def entry(w, deposit):
+        return w.rpc.listvaults([], [deposit])["vaults"][0]
+
+    def unknown_spender(w, deposit, txid):
+        flags = entry(w, deposit)["flags"]
+        return (
+            len(flags) == 1
+            and flags[0]["kind"] == "unknown_spender"
+            and txid in flags[0]["message"]
+        )
+
+    # The stakeholders may spend a Deposit to anything they like behind everyone's back
+    psbt = serializations.PSBT()
+    psbt.deserialize(stks[0].rpc.getunvaulttx(deposits[0])["unvault_tx"])
+    addr = bitcoind.rpc.getnewaddress()
+    spk = bytes.fromhex(bitcoind.rpc.getaddressinfo(addr)["scriptPubKey"])
+    value = psbt.inputs[0].witness_utxo.nValue - 10_000
+    psbt.tx.vout = [serializations.CTxOut(value, spk)]
+    psbt.outputs = [serializations.PartiallySignedOutput()]
+    psbt_str = psbt.serialize()
+    for stk in stks:
+        psbt_str = stk.stk_keychain.sign_unvault_psbt(
+            psbt_str, vaults[0]["derivation_index"]
+        )
+    bypass_tx = bitcoind.rpc.finalizepsbt(psbt_str)["hex"]
+    bypass_txid = bitcoind.rpc.sendrawtransaction(bypass_tx)
+    bitcoind.generate_block(1, wait_for_mempool=bypass_txid)
+    for w in participants:
+        wait_for(lambda: unknown_spender(w, deposits[0], bypass_txid))
+        assert entry(w, deposits[0])["status"] == "funded"
+
+    # A Spend signed by the first manager only. The second one doesn't know about it, and
+    # the stakeholders can't tell it from a regular one.
+    destinations, feerate = revault_network._any_spend_data([vaults[1]])
+    spend_tx = mans[0].rpc.getspendtx([deposits[1]], destinations, feerate)["spend_tx"]
+    spend_tx = mans[0].man_keychain.sign_spend_psbt(
+        spend_tx, [vaults[1]["derivation_index"]]
+    )
+    mans[0].rpc.updatespendtx(spend_tx)
+    spend_psbt = serializations.PSBT()
+    spend_psbt.deserialize(spend_tx)
+    spend_psbt.tx.calc_sha256()
+    spend_txid = spend_psbt.tx.hash
+    mans[0].rpc.setspendtx(spend_txid)
+    bitcoind.generate_block(1, wait_for_mempool=1)
+    bitcoind.generate_block(revault_network.csv)
+    mans[0].wait_for_log(f"Succesfully broadcasted Spend tx '{spend_txid}'")
+    bitcoind.generate_block(1, wait_for_mempool=[spend_txid])
+    for w in participants:
+        wait_for(lambda: entry(w, deposits[1])["status"] == "spent")
+        assert entry(w, deposits[1])["txid"] == vaults[1]["txid"]
+    wait_for(lambda: unknown_spender(mans[1], deposits[1], spend_txid))
+    for w in stks + [mans[0]]:
+        assert entry(w, deposits[1])["flags"] == []
+
+    # A Cancel broadcast by a stakeholder is known to everyone
+    revault_network.unvault_vaults_anyhow([vaults[2]])
+    revault_network.cancel_vault(vaults[2])
+    for w in participants:
+        assert entry(w, deposits[2])["flags"] == []
+
+    # The managers can't know the Unvault Emergency
+    revault_network.unvault_vaults_anyhow([vaults[3]])
+    stks[0].rpc.emergency()
+    unemer_tx = stks[0].rpc.listpresignedtransactions([deposits[3]])[
+        "presigned_transactions"
+    ][0]["unvault_emergency"]
+    unemer_txid = bitcoind.rpc.decoderawtransaction(unemer_tx["hex"])["txid"]
+    bitcoind.generate_block(1, wait_for_mempool=[unemer_txid])
+    for stk in stks:
+        wait_for(
+            lambda: entry(stk, deposits[3])["status"] == "unvaultemergencyvaulted"
+        )
+        assert entry(stk, deposits[3])["flags"] == []
+    for man in mans:
+        wait_for(lambda: unknown_spender(man, deposits[3], unemer_txid))
+
+
+def test_replay_missed_blocks(revault_network, bitcoind):
+    """A daemon that was stopped while a deposit was made and a vault unvaulted and spent
+    records all these transitions at the height they happened once restarted."""
+    CSV = 3
+    revault_network.deploy(2, 1, csv=CSV, with_watchtowers=False)
+    man = revault_network.man(0)
+    stk = revault_network.stk(1)
+    vault = revault_network.fund(1)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    revault_network.secure_vault(vault)
+    revault_network.activate_vault(vault)
+    stk.stop()
+
+    # A new deposit, confirmed after 6 blocks
+    addr = man.rpc.getdepositaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 2)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    deposit_height = bitcoind.rpc.getblockcount()
+    bitcoind.generate_block(5)
+    man.wait_for_log(f"Vault at {txid}.* is now confirmed")
+    new_vault = man.rpc.listvaults([], None, deposit_height, deposit_height)["vaults"][0]
+    new_deposit = f"{new_vault['txid']}:{new_vault['vout']}"
+
+    # The first vault is unvaulted and spent
+    unvault_height = bitcoind.rpc.getblockcount() + 1
+    destinations = {bitcoind.rpc.getnewaddress(): vault["amount"] // 2}
+    revault_network.spend_vaults([vault], destinations, 1)
+    spend_height = bitcoind.rpc.getblockcount()
+    bitcoind.generate_block(2)
+
+    stk.start()
+    height = bitcoind.rpc.getblockcount()
+    wait_for(lambda: stk.rpc.getinfo()["blockheight"] == height)
+    stk.wait_for_log("Done replaying the blocks up to height")
+
+    def status_as_of(deposit, height):
+        vaults = stk.rpc.listvaults([], [deposit], None, None, height)["vaults"]
+        return vaults[0]["status"] if len(vaults) > 0 else None
+
+    assert status_as_of(deposit, unvault_height - 1) == "active"
+    assert status_as_of(deposit, unvault_height) == "unvaulted"
+    assert status_as_of(deposit, unvault_height + CSV - 2) == "unvaulted"
+    assert status_as_of(deposit, unvault_height + CSV - 1) == "spendable"
+    assert status_as_of(deposit, spend_height - 1) == "spendable"
+    assert status_as_of(deposit, spend_height) == "spent"
+    assert status_as_of(new_deposit, deposit_height - 1) is None
+    assert status_as_of(new_deposit, deposit_height) == "unconfirmed"
+    assert status_as_of(new_deposit, deposit_height + 4) == "unconfirmed"
+    assert status_as_of(new_deposit, deposit_height + 5) == "funded"
+
+    # The vaults only ever moved forward
+    statuses = [status_as_of(deposit, h) for h in range(unvault_height - 1, height + 1)]
+    order = ["active", "unvaulted", "spendable", "spent"]
+    assert statuses == sorted(statuses, key=order.index)
+
+    entry = stk.rpc.listvaults([], [deposit])["vaults"][0]
+    assert entry["moved_height"] == spend_height
+    events = stk.rpc.gethistory(["deposit", "spend"], 0, int(time.time()) + 3600, 20)[
+        "events"
+    ]
+    assert sorted((e["blockheight"], e["kind"]) for e in events) == [
+        (vault["blockheight"], "deposit"),
+        (deposit_height, "deposit"),
+        (spend_height, "spend"),
+    ]
+
+
+def test_abandoned_deposit(revaultd_stakeholder, bitcoind):
+    """A replaced unconfirmed deposit is given up on, until it shows up again"""
+    stk = revaultd_stakeholder
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(
+            conf.replace("daemon = false\n", "daemon = false\ndeposit_abandon_polls = 2\n")
+        )
+    stk.start()
+
+    def vault_by_txid(txid):
+        return next(v for v in stk.rpc.listvaults()["vaults"] if v["txid"] == txid)
+
+    # A replaceable deposit, which we replace with one paying more fees
+    addr = stk.rpc.getdepositaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.5, "", "", False, True)
+    raw_tx = bitcoind.rpc.getrawtransaction(txid)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 1)
+    vault = vault_by_txid(txid)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    bump_txid = bitcoind.rpc.bumpfee(txid)["txid"]
+
+    # After two polls we give up on the replaced one, and only account for the new one
+    stk.wait_for_logs(
+        [
+            f"The transaction of unconfirmed deposit at '{deposit}' left the mempool",
+            f"Giving up on unconfirmed deposit at '{deposit}'",
+        ]
+    )
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 2)
+    assert vault_by_txid(txid)["abandoned"]
+    assert vault_by_txid(txid)["status"] == "unconfirmed"
+    bump_vault = vault_by_txid(bump_txid)
+    assert not bump_vault["abandoned"]
+    assert stk.rpc.getbalances()["unconfirmed"] == bump_vault["amount"]
+
+    # Now the original one gets mined, evicting its replacement
+    bitcoind.rpc.generateblock(bitcoind.rpc.getnewaddress(), [raw_tx])
+    stk.wait_for_log(
+        f"Abandoned deposit at '{deposit}' showed up again, resurrecting its vault"
+    )
+    bump_deposit = f"{bump_vault['txid']}:{bump_vault['vout']}"
+    stk.wait_for_log(f"Giving up on unconfirmed deposit at '{bump_deposit}'")
+    assert not vault_by_txid(txid)["abandoned"]
+    assert vault_by_txid(bump_txid)["abandoned"]
+    assert stk.rpc.getbalances()["unconfirmed"] == vault_by_txid(txid)["amount"]
+
+    # It's tracked as usual
+    bitcoind.generate_block(5)
+    stk.wait_for_deposits([deposit])
+
+
+def test_deposit_unconfirmed_ancestry(revaultd_stakeholder, bitcoind):
+    """Deposits funded by unconfirmed transactions record them, and may be refused"""
+    stk = revaultd_stakeholder
+
+    def send_through_chain(depth):
+        """Pay a new deposit address through a chain of 'depth' unconfirmed transactions"""
+        value = 1
+        addr = bitcoind.rpc.getnewaddress()
+        txid = bitcoind.rpc.sendtoaddress(addr, value)
+        for i in range(depth):
+            tx = bitcoind.rpc.decoderawtransaction(bitcoind.rpc.getrawtransaction(txid))
+            vout = next(o["n"] for o in tx["vout"] if float(o["value"]) == value)
+            value = round(value - 0.0001, 8)
+            addr = (
+                bitcoind.rpc.getnewaddress()
+                if i < depth - 1
+                else stk.rpc.getdepositaddress()["address"]
+            )
+            raw_tx = bitcoind.rpc.createrawtransaction(
+                [{"txid": txid, "vout": vout}], {addr: value}
+            )
+            signed = bitcoind.rpc.signrawtransactionwithwallet(raw_tx)
+            txid = bitcoind.rpc.sendrawtransaction(signed["hex"])
+        return txid
+
+    def vault_by_txid(txid):
+        return next(v for v in stk.rpc.listvaults()["vaults"] if v["txid"] == txid)
+
+    # The deposit depends on two unconfirmed transactions, one after the other
+    txid = send_through_chain(2)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 1)
+    vault = vault_by_txid(txid)
+    assert vault["status"] == "unconfirmed"
+    assert vault["ancestry"]["unconfirmed_ancestors"] == 2
+    assert vault["ancestry"]["depth"] == 2
+    assert vault["ancestry"]["vsize"] > 0
+    assert vault["flags"] == []
+
+    # They are all mined together, it's confirmed as usual
+    bitcoind.generate_block(6, wait_for_mempool=3)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    stk.wait_for_deposits([deposit])
+    assert vault_by_txid(txid)["ancestry"] is None
+
+    # Now refuse deposits depending on a chain of more than one unconfirmed transaction
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "daemon = false\n", "daemon = false\nmax_deposit_ancestry_depth = 1\n"
+            )
+        )
+    stk.start()
+
+    txid = send_through_chain(2)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 2)
+    vault = vault_by_txid(txid)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    stk.wait_for_log(f"Deposit at '{deposit}' depends on 2 unconfirmed transaction")
+    wait_for(lambda: len(vault_by_txid(txid)["flags"]) == 1)
+    assert vault_by_txid(txid)["flags"][0]["kind"] == "unconfirmed_ancestry"
+    assert stk.rpc.getbalances()["unconfirmed"] == 0
+
+    # It stays unconfirmed, whatever its confirmations
+    bitcoind.generate_block(6, wait_for_mempool=3)
+    wait_for(lambda: stk.rpc.getinfo()["blockheight"] == bitcoind.rpc.getblockcount())
+    time.sleep(2)
+    assert vault_by_txid(txid)["status"] == "unconfirmed"
+
+    # Until someone has a look and clears the flag
+    stk.rpc.clearvaultflag(deposit, "unconfirmed_ancestry")
+    stk.wait_for_deposits([deposit])
+
+
+def test_coinbase_deposit(revaultd_stakeholder, bitcoind):
+    """A deposit made by a coinbase is only confirmed once it matured"""
+    stk = revaultd_stakeholder
+
+    # Mine directly to a deposit address
+    addr = stk.rpc.getdepositaddress()["address"]
+    bitcoind.rpc.generatetoaddress(1, addr)
+    height = bitcoind.rpc.getblockcount()
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 1)
+    vault = stk.rpc.listvaults()["vaults"][0]
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    stk.wait_for_log(
+        f"Deposit at '{deposit}' was made by a coinbase transaction mined at height {height}"
+    )
+    assert vault["status"] == "unconfirmed"
+    assert vault["coinbase"] == {
+        "height": height,
+        "mature_height": height + 100,
+        "blocks_until_mature": 100,
+    }
+
+    # Past the usual confirmations, it's still unconfirmed and its revocation transactions
+    # can't be signed yet
+    bitcoind.generate_block(98)
+    wait_for(lambda: stk.rpc.getinfo()["blockheight"] == height + 98)
+    time.sleep(2)
+    vault = stk.rpc.listvaults()["vaults"][0]
+    assert vault["status"] == "unconfirmed"
+    assert vault["coinbase"]["blocks_until_mature"] == 2
+    with pytest.raises(RpcError, match="Invalid vault status"):
+        stk.rpc.getrevocationtxs(deposit)
+
+    # Once it matured, it's confirmed as usual
+    bitcoind.generate_block(2)
+    stk.wait_for_deposits([deposit])
+    vault = stk.rpc.listvaults()["vaults"][0]
+    assert vault["blockheight"] == height
+    assert vault["coinbase"]["blocks_until_mature"] == 0
+    stk.rpc.getrevocationtxs(deposit)
+
+
+def test_wallet_audit(revaultd_stakeholder, bitcoind):
+    """We detect the entries added to our watchonly wallet behind our back, and import back
+    the addresses it lost."""
+    stk = revaultd_stakeholder
+
+    addr = stk.rpc.getdepositaddress()["address"]
+    bitcoind.rpc.sendtoaddress(addr, 0.5)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 1)
+    wait_for(lambda: stk.rpc.getinfo()["health"]["wallet_audited_at"] is not None)
+
+    # Nothing to report at first
+    audit = stk.rpc.auditwallet()
+    watched_up_to = stk.rpc.getinfo()["deposit_indexes"]["watched_up_to"]
+    assert audit["watched"] == 2 * (watched_up_to + 1)
+    assert audit["missing"] == []
+    assert audit["unexpected"] == []
+    assert not audit["imports_corrected"]
+
+    # Someone imports an address of their own into our wallet
+    wallet_dir = glob.glob(
+        os.path.join(stk.datadir_with_network, "revaultd-watchonly-wallet-*")
+    )[0]
+    wallet_rpc = BitcoindRpcInterface(bitcoind.bitcoin_dir, "regtest", bitcoind.rpcport)
+    wallet_rpc.wallet_name = urllib.parse.quote(wallet_dir, safe="")
+    extra_addr = bitcoind.rpc.getnewaddress()
+    extra_desc = bitcoind.rpc.getdescriptorinfo(f"addr({extra_addr})")["descriptor"]
+    wallet_rpc.importdescriptors([{"desc": extra_desc, "timestamp": "now"}])
+    audit = stk.rpc.auditwallet()
+    assert audit["unexpected"] == [extra_addr]
+    assert audit["missing"] == []
+    stk.wait_for_log("Our watchonly wallet watches 1 entries we never imported")
+    health = stk.rpc.getinfo()["health"]
+    assert health["unexpected_wallet_entries"] == [extra_addr]
+
+    # Someone re-creates our wallet with only the first deposit address in it
+    stk.stop()
+    bitcoind.rpc.unloadwallet(wallet_dir)
+    shutil.rmtree(wallet_dir)
+    bitcoind.rpc.createwallet(wallet_dir, True, True, "", False, True)
+    marker_desc = bitcoind.rpc.getdescriptorinfo(f"addr({addr})")["descriptor"]
+    wallet_rpc.importdescriptors(
+        [{"desc": marker_desc, "timestamp": 0, "label": "revault-deposit"}]
+    )
+    stk.start()
+
+    # We import back the others, and keep tracking our vault
+    stk.wait_for_log(
+        f"{audit['watched'] - 1} of the addresses we imported were missing from our "
+        "watchonly wallet"
+    )
+    wait_for(lambda: stk.rpc.getinfo()["sync"] == 1.0)
+    audit = stk.rpc.auditwallet()
+    assert audit["missing"] == []
+    assert audit["unexpected"] == []
+    assert audit["watched"] == 2 * (watched_up_to + 1)
+    assert len(stk.rpc.listvaults()["vaults"]) == 1
+    addr = stk.rpc.getdepositaddress()["address"]
+    bitcoind.rpc.sendtoaddress(addr, 0.6)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 2)
+
+
+def sign_revocation_txs(stk, deposit, derivation_index):
+    psbts = stk.rpc.getrevocationtxs(deposit)
+    return [
+        stk.stk_keychain.sign_revocation_psbt(psbts[tx], derivation_index)
+        for tx in ["cancel_tx", "emergency_tx", "emergency_unvault_tx"]
+    ]
+
+
+def test_revocation_sigs_reorged_deposit(revaultd_stakeholder, bitcoind):
+    """Signatures handed for a vault whose deposit got unconfirmed are kept until it confirms
+    again"""
+    stk = revaultd_stakeholder
+
+    addr = stk.rpc.getdepositaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.5)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 1)
+    vault = stk.rpc.listvaults()["vaults"][0]
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    bitcoind.generate_block(6, wait_for_mempool=txid)
+    stk.wait_for_deposits([deposit])
+    vault = stk.rpc.listvaults()["vaults"][0]
+    rev_txs = sign_revocation_txs(stk, deposit, vault["derivation_index"])
+
+    # The deposit gets unconfirmed while they are being signed
+    bitcoind.rpc.invalidateblock(bitcoind.rpc.getblockhash(vault["blockheight"]))
+    wait_for(lambda: stk.rpc.listvaults()["vaults"][0]["status"] == "unconfirmed")
+
+    # They are accepted, but the vault is only secured once it's confirmed again
+    stk.rpc.revocationtxs(deposit, *rev_txs)
+    stk.wait_for_log(
+        f"Deposit at '{deposit}' only has 0 confirmations .*keeping the signatures"
+    )
+    assert stk.rpc.listvaults()["vaults"][0]["status"] == "unconfirmed"
+    bitcoind.generate_block(6, wait_for_mempool=txid)
+    stk.wait_for_log(
+        "Stored back the signatures of the revocation transactions of vault at "
+        f"'{deposit}'"
+    )
+    wait_for(lambda: stk.rpc.listvaults()["vaults"][0]["status"] == "securing")
+
+
+def test_revocation_sigs_vanished_deposit(revaultd_stakeholder, bitcoind):
+    """Signatures handed for a vault whose deposit was replaced after a reorg are refused"""
+    stk = revaultd_stakeholder
+
+    # A replaceable deposit
+    addr = stk.rpc.getdepositaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.5, "", "", False, True)
+    wait_for(lambda: len(stk.rpc.listvaults()["vaults"]) == 1)
+    vault = stk.rpc.listvaults()["vaults"][0]
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    bitcoind.generate_block(6, wait_for_mempool=txid)
+    stk.wait_for_deposits([deposit])
+    vault = stk.rpc.listvaults()["vaults"][0]
+    rev_txs = sign_revocation_txs(stk, deposit, vault["derivation_index"])
+
+    # It's reorged out and replaced while they are being signed
+    bitcoind.rpc.invalidateblock(bitcoind.rpc.getblockhash(vault["blockheight"]))
+    wait_for(lambda: stk.rpc.listvaults()["vaults"][0]["status"] == "unconfirmed")
+    bitcoind.rpc.bumpfee(txid)
+
+    with pytest.raises(RpcError, match="doesn't exist anymore"):
+        stk.rpc.revocationtxs(deposit, *rev_txs)
+    vault = stk.rpc.listvaults([], [deposit])["vaults"][0]
+    assert vault["status"] == "unconfirmed"
+    assert "deposit_vanished" in [flag["kind"] for flag in vault["flags"]]
@@ -596,7 +596,9 @@ class RevaultNetwork:
         self.bitcoind.generate_block(1, wait_for_mempool=len(deposits))
         for w in self.participants():
             wait_for(
-                lambda: len(w.rpc.listvaults(["unvaulted"], deposits)["vaults"])
+                lambda: len(
+                    w.rpc.listvaults(["unvaulted", "spendable"], deposits)["vaults"]
+                )
                 == len(deposits)
             )
         return spend_psbt
@@ -742,7 +744,8 @@ class RevaultNetwork:
             wait_for(
                 lambda: len(
                     w.rpc.listvaults(
-                        ["unvaulting", "unvaulted", "spending"], [deposit]
+                        ["unvaulting", "unvaulted", "spendable", "spending"],
+                        [deposit],
                     )["vaults"]
                 )
                 == 1
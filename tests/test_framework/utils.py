@@ -33,6 +33,8 @@ DEFAULT_REV_PATH = os.path.join(
     os.path.dirname(__file__), "..", "..", "target/debug/revaultd"
 )
 REVAULTD_PATH = os.getenv("REVAULTD_PATH", DEFAULT_REV_PATH)
+# The cargo features the daemon was compiled with, comma-separated, if not the default ones
+REVAULTD_FEATURES = os.getenv("REVAULTD_FEATURES", "jsonrpc_server")
 DEFAULT_MIRADORD_PATH = os.path.join(
     os.path.dirname(__file__),
     "..",
@@ -253,6 +255,33 @@ def get_descriptors(stks_xpubs, cosigs_keys, mans_xpubs, mans_thresh, cpfp_xpubs
     )
 
 
+
+def tcp_rpc_call(noise_priv, revaultd_noise_key, addr, method, params=None):
+    """Call revaultd's JSONRPC interface over TCP with contrib/tools/rpcclient.
+
+    Returns the response, or raises CalledProcessError if we could not get one.
+    """
+    # tests/test_framework/../../contrib/tools/rpcclient/target/debug/rpcclient
+    rpcclient_dir = os.path.abspath(
+        os.path.join(
+            os.path.dirname(os.path.dirname(os.path.dirname(__file__))),
+            "contrib",
+            "tools",
+            "rpcclient",
+        )
+    )
+    try:
+        subprocess.check_call(["cargo", "build"], cwd=rpcclient_dir)
+    except subprocess.CalledProcessError as e:
+        logging.error(f"Error compiling rpcclient: {str(e)}")
+        raise e
+
+    rpcclient_bin = os.path.join(rpcclient_dir, "target", "debug", "rpcclient")
+    cmd = [rpcclient_bin, noise_priv, revaultd_noise_key, addr, method]
+    if params is not None:
+        cmd.append(json.dumps(params))
+    return json.loads(subprocess.check_output(cmd, timeout=TIMEOUT))
+
 class UnixSocket(object):
     """A wrapper for socket.socket that is specialized to unix sockets.
 
@@ -336,6 +365,11 @@ class UnixDomainSocketRpc(object):
         while True:
             n_to_read = max(2048, len(buff))
             chunk = sock.recv(n_to_read)
+            if not chunk:
+                # A streamed response is cut short by closing the connection
+                raise ConnectionError(
+                    "Connection closed before the end of the response: {}".format(buff)
+                )
             buff += chunk
             if len(chunk) != n_to_read:
                 try:
@@ -1,18 +1,149 @@
+import json
 import logging
 import pytest
+import re
 import os
+import socket
+import subprocess
+import threading
+
+from http.server import BaseHTTPRequestHandler, HTTPServer
 
 from fixtures import *
 from test_framework import serializations
 from test_framework.utils import (
     TailableProc,
     POSTGRES_IS_SETUP,
+    REVAULTD_PATH,
     RpcError,
+    TIMEOUT,
     wait_for,
     COIN,
 )
 
 
+def test_version_flag():
+    """The metadata of the build can be printed without starting"""
+    res = subprocess.run([REVAULTD_PATH, "--version"], capture_output=True, check=True)
+    assert re.match(
+        r"revaultd \d+\.\d+\.\d+ \((commit [0-9a-f]{12}(-dirty)?|unknown commit), built "
+        r"\d{4}-\d{2}-\d{2} with rustc ",
+        res.stdout.decode(),
+    )
+
+
+def test_example_config(directory):
+    """The example configuration we print is valid, and can be checked without starting"""
+    example = subprocess.run(
+        [REVAULTD_PATH, "--dump-example-config"], capture_output=True, check=True
+    ).stdout.decode()
+    datadir = os.path.join(directory, "revaultd")
+    example = example.replace("/path/to/your/datadir/revault", datadir)
+    conf_file = os.path.join(directory, "revaultd.toml")
+    with open(conf_file, "w") as f:
+        f.write(example)
+
+    res = subprocess.run(
+        [REVAULTD_PATH, "--check-config", conf_file], capture_output=True
+    )
+    assert res.returncode == 0, res.stderr
+    # Nothing was created
+    assert not os.path.exists(datadir)
+
+    with open(conf_file, "w") as f:
+        f.write(example.replace('#f35b:02f1:2ff3:d64f"', '#0000:0000:0000:0000"'))
+    res = subprocess.run(
+        [REVAULTD_PATH, "--check-config", conf_file], capture_output=True
+    )
+    assert res.returncode == 1
+    assert "Fingerprint mismatch for Noise key" in res.stderr.decode()
+
+
+def test_setup(directory):
+    """A configuration and a data directory can be set up non-interactively"""
+    stk_xpubs = [
+        "tpubDDcdMK347DzJfWk9a8uLuskgoSKTUPn1GtMYz91Q1WdptaY14BTF4KUFiQ3cq5K6ji4bgALTEy6HU"
+        "uf3TULS4f98wYpaYLcAbym33g4hsPY",
+        "tpubDFUyR1hbbP14sGZ2St39RgJ9wUk4enPVYahtA5nPwPPWsGUNVFZt2ujRLShf4JqFXbJQLrgvFTodt"
+        "XCWEnYQqUnMYzLaAWjuXsZnQTYZS5C",
+    ]
+    man_xpubs = [
+        "tpubDCvsodKgyz8apUfySTojc8WU6UPDXmv32Ct9hZqWGE7a6FcWpHoJ3Efa9zUqLWHX6PHthHXQehG1m"
+        "rFtFVXNMcwt6kGwCuYk6adMDreyVGu",
+        "tpubDEBMdKAsdbmZsUUohCJjRCJ8NDxH4LJr79WSEE3bzHZGEoh7doFizgHhMQJKTN1SPnW44xspcb3f3"
+        "Vav8JAthd3qGzTbq7zR12CFRkLvzG2",
+    ]
+    cpfp_xpub = (
+        "tpubDEAoArgp5Xu4jD5KsLuMN88zuh8g3Tzxm3JMG9wNUNGNsAbzBn4bLzHjjQJCtKWy3ZHt5bR7vvBfF"
+        "pdY59sHaYi9cqXYaJ2sqKnsStzsNLb"
+    )
+    conf_file = os.path.join(directory, "revaultd.toml")
+    datadir = os.path.join(directory, "revaultd")
+    noise_key = "087629614d227ff2b9ed5f2ce2eb7cd527d2d18f866b24009647251fce58de38"
+    args = [
+        REVAULTD_PATH,
+        "--setup",
+        "--non-interactive",
+        "--conf",
+        conf_file,
+        "--network",
+        "regtest",
+        "--data-dir",
+        datadir,
+        "--bitcoind-cookie-path",
+        os.path.join(directory, ".cookie"),
+        "--bitcoind-addr",
+        "127.0.0.1:18443",
+        "--stakeholders-xpubs",
+        ",".join(stk_xpubs),
+        "--managers-xpubs",
+        ",".join(man_xpubs),
+        "--managers-threshold",
+        "2",
+        "--cosigners-keys",
+        "030f64b922aee2fd597f104bc6cb3b670f1ca2c6c49b1071a1a6c010575d94fe5a,"
+        "02abe475b199ec3d62fa576faee16a334fdb86ffb26dce75becebaaedf328ac3fe",
+        "--unvault-csv",
+        "18",
+        "--cpfp-xpubs",
+        cpfp_xpub,
+        "--manager-xpub",
+        man_xpubs[0],
+        "--cosigning-servers",
+        f"{noise_key}@127.0.0.1:1,{noise_key}@127.0.0.1:2",
+        "--coordinator-host",
+        "127.0.0.1:8383",
+        "--coordinator-noise-key",
+        "f35b02f12ff3d64f3c7982b88ffb66fec37bce5796374a7be9e8e2dd9abbb558",
+    ]
+
+    # All the mandatory parameters must be given
+    res = subprocess.run(args[:-2], capture_output=True)
+    assert res.returncode == 1
+    assert "Missing '--coordinator-noise-key'" in res.stderr.decode()
+    assert not os.path.exists(conf_file)
+
+    res = subprocess.run(args, capture_output=True)
+    assert res.returncode == 0, res.stderr
+    out = res.stdout.decode()
+    assert "Noise static public key" in out
+    assert "Deposit descriptor checksum" in out
+    assert os.path.exists(os.path.join(datadir, "regtest", "noise_secret"))
+    assert os.path.exists(os.path.join(datadir, "regtest", "revaultd.sqlite3"))
+    res = subprocess.run(
+        [REVAULTD_PATH, "--check-config", conf_file], capture_output=True
+    )
+    assert res.returncode == 0, res.stderr
+
+    # It refuses to overwrite it, unless forced to
+    res = subprocess.run(args, capture_output=True)
+    assert res.returncode == 1
+    assert "already exists" in res.stderr.decode()
+    res = subprocess.run(args + ["--force"], capture_output=True)
+    assert res.returncode == 0, res.stderr
+    assert res.stdout.decode() == out
+
+
 def test_largewallets(revaultd_stakeholder, bitcoind):
     """Test a wallet with 1000 deposits and 10 dust deposits"""
     amount = 0.01
@@ -37,6 +168,603 @@ def test_largewallets(revaultd_stakeholder, bitcoind):
     revaultd_stakeholder.rpc.listpresignedtransactions()
 
 
+def test_json_logs(revaultd_stakeholder, bitcoind):
+    """The logs can be emitted as one JSON object per line"""
+    stk = revaultd_stakeholder
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(conf.replace("daemon = false\n", 'daemon = false\nlog_format = "json"\n'))
+    logs_start = len(stk.logs)
+    stk.start()
+
+    addr = stk.rpc.getdepositaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.5)
+    bitcoind.generate_block(6, wait_for_mempool=txid)
+    stk.wait_for_log("Vault at .* is now confirmed")
+
+    lines = [json.loads(l) for l in stk.logs[logs_start:]]
+    for line in lines:
+        for field in ["timestamp", "level", "module", "message"]:
+            assert field in line
+
+    # The status transition event carries its structured fields
+    events = [l for l in lines if l.get("event") == "vault_status"]
+    assert len(events) == 1
+    assert events[0]["fields"]["outpoint"].startswith(txid)
+    assert events[0]["fields"]["to"] == "funded"
+
+
+def test_runtime_log_level(revaultd_stakeholder, bitcoind):
+    """The log level can be changed at runtime, for a limited time"""
+    stk = revaultd_stakeholder
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(re.sub(r"log_level = .*\n", "log_level = 'info'\n", conf))
+    stk.start()
+    assert stk.rpc.getloglevel() == {"level": "info", "modules": {}, "revert_at": None}
+
+    # Log the debug messages of the state machine for a few seconds
+    res = stk.rpc.setloglevel("info", {"revaultd::statemachine": "debug"}, 5)
+    assert res["previous"] == {"level": "info", "modules": {}, "revert_at": None}
+    levels = stk.rpc.getinfo()["log_levels"]
+    assert levels["modules"] == {"revaultd::statemachine": "debug"}
+    assert levels["revert_at"] is not None
+    addr = stk.rpc.getdepositaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.5)
+    stk.wait_for_log(f"Got a new unconfirmed deposit at {txid}")
+
+    # Then the configured level is restored
+    stk.wait_for_log("Restored the configured log levels")
+    assert stk.rpc.getloglevel() == {"level": "info", "modules": {}, "revert_at": None}
+
+    # Without a duration, it's kept and the module levels are untouched if not given
+    stk.rpc.setloglevel("debug", {"revaultd::bitcoind": "trace"})
+    res = stk.rpc.setloglevel("warn")
+    assert res["previous"]["level"] == "debug"
+    assert stk.rpc.getloglevel() == {
+        "level": "warn",
+        "modules": {"revaultd::bitcoind": "trace"},
+        "revert_at": None,
+    }
+
+    with pytest.raises(RpcError, match="not a valid log level"):
+        stk.rpc.setloglevel("verbose")
+
+
+def test_daemonize(revaultd_manager):
+    """In daemon mode, the process we start only exits once the daemon is ready"""
+    man = revaultd_manager
+    man.stop()
+    with open(man.conf_file, "r") as f:
+        conf = f.read()
+    with open(man.conf_file, "w") as f:
+        f.write(conf.replace("daemon = false\n", "daemon = true\n"))
+
+    res = subprocess.run(man.cmd_line, capture_output=True, timeout=TIMEOUT)
+    assert res.returncode == 0, res.stderr
+    # As soon as it exited, the RPC socket is usable
+    man.rpc.getinfo()
+    pid_file = os.path.join(man.datadir_with_network, "revaultd.pid")
+    with open(pid_file, "r") as f:
+        pid = int(f.read())
+    assert pid != os.getpid()
+    # Only the user may read what the daemon creates
+    assert os.stat(pid_file).st_mode & 0o077 == 0
+    # And it logs to the log file
+    with open(os.path.join(man.datadir_with_network, "log"), "r") as f:
+        assert "JSONRPC server started" in f.read()
+
+    def is_running(pid):
+        try:
+            os.kill(pid, 0)
+            return True
+        except ProcessLookupError:
+            return False
+
+    man.rpc.stop()
+    wait_for(lambda: not is_running(pid))
+    assert not os.path.exists(pid_file)
+
+    # With a broken configuration it exits with an error, and tells us why
+    with open(man.conf_file, "w") as f:
+        f.write(conf.replace("daemon = false\n", "daemon = true\n") + "invalid")
+    res = subprocess.run(man.cmd_line, capture_output=True, timeout=TIMEOUT)
+    assert res.returncode != 0
+    assert "Error parsing config" in res.stderr.decode()
+
+    # It also does if the startup fails once detached, here because another process is
+    # listening on the RPC socket.
+    with open(man.conf_file, "w") as f:
+        f.write(conf.replace("daemon = false\n", "daemon = true\n"))
+    socket_path = os.path.join(man.datadir_with_network, "revaultd_rpc")
+    squatter = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    squatter.bind(socket_path)
+    squatter.listen()
+    try:
+        res = subprocess.run(man.cmd_line, capture_output=True, timeout=TIMEOUT)
+    finally:
+        squatter.close()
+        os.remove(socket_path)
+    assert res.returncode != 0
+    stderr = res.stderr.decode()
+    assert "Error starting revaultd: Setting up the JSONRPC server" in stderr, stderr
+    assert not os.path.exists(pid_file)
+
+    # Back to the foreground for the teardown
+    with open(man.conf_file, "w") as f:
+        f.write(conf)
+    man.start()
+
+
+def test_notify_command(revaultd_stakeholder, bitcoind):
+    """The notify command is run once per genuine vault status transition"""
+    stk = revaultd_stakeholder
+    out_file = os.path.join(stk.datadir_with_network, "notified")
+    script = os.path.join(stk.datadir_with_network, "notify.sh")
+    with open(script, "w") as f:
+        f.write(f'#!/bin/sh\necho "$@" >> {out_file}\n')
+    os.chmod(script, 0o700)
+
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "daemon = false\n",
+                f'daemon = false\nnotify_command = "{script}"\nnotify_statuses = ["funded"]\n',
+            )
+        )
+    stk.start()
+    stk.wait_for_log("Caught up with the chain, now notifying status transitions")
+
+    def notified():
+        if not os.path.exists(out_file):
+            return []
+        with open(out_file, "r") as f:
+            return f.read().splitlines()
+
+    # A deposit getting confirmed is notified, but not it being seen unconfirmed
+    addr = stk.rpc.getdepositaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.5)
+    stk.wait_for_log(f"Got a new unconfirmed deposit at {txid}")
+    bitcoind.generate_block(6, wait_for_mempool=txid)
+    stk.wait_for_log(f"Vault at {txid}.* is now confirmed")
+    wait_for(lambda: len(notified()) == 1)
+    outpoint, old_status, new_status, notified_txid = notified()[0].split(" ")
+    assert outpoint.startswith(txid)
+    assert (old_status, new_status, notified_txid) == ("unconfirmed", "funded", txid)
+
+    # A deposit confirmed while we were down isn't notified when catching up at startup
+    addr_b = stk.rpc.getdepositaddress()["address"]
+    stk.stop()
+    txid_b = bitcoind.rpc.sendtoaddress(addr_b, 0.5)
+    bitcoind.generate_block(6, wait_for_mempool=txid_b)
+    stk.start()
+    stk.wait_for_logs(
+        [
+            f"Vault at {txid_b}.* is now confirmed",
+            "Caught up with the chain, now notifying status transitions",
+        ]
+    )
+
+    # But the ones happening afterward are
+    addr = stk.rpc.getdepositaddress()["address"]
+    txid_c = bitcoind.rpc.sendtoaddress(addr, 0.5)
+    bitcoind.generate_block(6, wait_for_mempool=txid_c)
+    stk.wait_for_log(f"Vault at {txid_c}.* is now confirmed")
+    wait_for(lambda: len(notified()) == 2)
+    assert notified()[1].split(" ")[0].startswith(txid_c)
+
+
+def test_disk_space(revaultd_manager):
+    """We warn when running low on disk space, and stop storing transactions when critically low"""
+    man = revaultd_manager
+    health = man.rpc.getinfo()["health"]
+    assert health["disk_space"] == "ok"
+    assert health["available_disk_space"] > 0
+    assert health["db_size"] > 0
+    unknown_txid = "00" * 32
+
+    def restart_with(settings):
+        man.stop()
+        with open(man.conf_file, "r") as f:
+            conf = f.read()
+        with open(man.conf_file, "w") as f:
+            f.write(conf.replace("daemon = false\n", f"daemon = false\n{settings}"))
+        man.start()
+
+    # No filesystem has an exbibyte available
+    restart_with("disk_space_warning_mb = 1099511627776\n")
+    man.wait_for_log("below the warning threshold")
+    assert man.rpc.getinfo()["health"]["disk_space"] == "low"
+    # Only a warning, the command goes through
+    with pytest.raises(RpcError, match="Unknown Spend transaction"):
+        man.rpc.setspendtx(unknown_txid)
+
+    restart_with("disk_space_critical_mb = 1099511627776\n")
+    man.wait_for_log("below the critical threshold")
+    assert man.rpc.getinfo()["health"]["disk_space"] == "critical"
+    with pytest.raises(RpcError, match="below the critical threshold"):
+        man.rpc.setspendtx(unknown_txid)
+
+
+def test_read_only_stakeholder(revaultd_stakeholder, bitcoind):
+    """A read-only stakeholder keeps monitoring the vaults but refuses to change anything"""
+    stk = revaultd_stakeholder
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(conf.replace("daemon = false\n", "daemon = false\nread_only = true\n"))
+    stk.start()
+    stk.wait_for_log("Running in read-only mode")
+    assert stk.rpc.getinfo()["read_only"]
+
+    # The chain is still monitored
+    addr = stk.rpc.getdepositaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.5)
+    bitcoind.generate_block(6, wait_for_mempool=txid)
+    stk.wait_for_log("Vault at .* is now confirmed")
+    vault = stk.rpc.listvaults()["vaults"][0]
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    assert vault["status"] == "funded"
+
+    # All the queries are available
+    psbts = stk.rpc.getrevocationtxs(deposit)
+    unvault_psbt = stk.rpc.getunvaulttx(deposit)["unvault_tx"]
+    stk.rpc.listpresignedtransactions([deposit])
+    stk.rpc.listonchaintransactions([deposit])
+    stk.rpc.getbalances()
+    stk.rpc.gethistory(["deposit"], 0, 2 ** 32 - 1, 10)
+    stk.rpc.getauditlog(0, 2 ** 32 - 1)
+
+    # But not the commands changing anything
+    for command in [
+        lambda: stk.rpc.revocationtxs(
+            deposit,
+            psbts["cancel_tx"],
+            psbts["emergency_tx"],
+            psbts["emergency_unvault_tx"],
+        ),
+        lambda: stk.rpc.unvaulttx(deposit, unvault_psbt),
+        lambda: stk.rpc.revault(deposit),
+        lambda: stk.rpc.emergency(),
+        lambda: stk.rpc.clearvaultflag(deposit, "revocation_rejected"),
+    ]:
+        with pytest.raises(RpcError, match="read-only mode"):
+            command()
+    assert stk.rpc.listvaults([], [deposit])["vaults"][0]["status"] == "funded"
+
+
+def test_read_only_manager(revaultd_manager):
+    """The read-only mode can be set from the command line, and never polls the cosigners"""
+    man = revaultd_manager
+    assert not man.rpc.getinfo()["read_only"]
+    man.stop()
+    man.cmd_line.append("--read-only")
+    man.start()
+    assert man.rpc.getinfo()["read_only"]
+
+    assert man.rpc.listspendtxs()["spend_txs"] == []
+    assert all(not c["reachable"] for c in man.rpc.getserverstatus()["cosigners"])
+    unknown_txid = "00" * 32
+    for command in [
+        lambda: man.rpc.setspendtx(unknown_txid),
+        lambda: man.rpc.delspendtx(unknown_txid),
+    ]:
+        with pytest.raises(RpcError, match="read-only mode"):
+            command()
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_revocation_check(revault_network, bitcoind):
+    """We warn when bitcoind would not accept our revocation transactions anymore"""
+    revault_network.deploy(2, 1)
+    vault = revault_network.fund(1)
+    revault_network.activate_fresh_vaults([vault])
+    deposit = f"{vault['txid']}:{vault['vout']}"
+
+    stk = revault_network.stk(0)
+    out_file = os.path.join(stk.datadir_with_network, "notified")
+    script = os.path.join(stk.datadir_with_network, "notify.sh")
+    with open(script, "w") as f:
+        f.write(f'#!/bin/sh\necho "$@" >> {out_file}\n')
+    os.chmod(script, 0o700)
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "daemon = false\n",
+                f'daemon = false\nnotify_command = "{script}"\n'
+                "revocation_check_interval_secs = 1\n",
+            )
+        )
+    stk.start()
+
+    # All good for now
+    wait_for(lambda: stk.rpc.getinfo()["health"]["revocations_checked_at"] is not None)
+    assert stk.rpc.getinfo()["health"]["rejected_revocations"] == []
+
+    # The mempool minimum feerate rises above what our presigned transactions pay
+    with open(bitcoind.conf_file, "r") as f:
+        bitcoind_conf = f.read()
+    bitcoind.stop()
+    with open(bitcoind.conf_file, "w") as f:
+        f.write(bitcoind_conf + "minrelaytxfee=0.1\n")
+    bitcoind.start()
+
+    wait_for(lambda: len(stk.rpc.getinfo()["health"]["rejected_revocations"]) == 1)
+    rejected = stk.rpc.getinfo()["health"]["rejected_revocations"][0]
+    assert rejected["deposit_outpoint"] == deposit
+    assert "min relay fee not met" in rejected["reason"]
+    emer_tx = stk.rpc.listpresignedtransactions([deposit])["presigned_transactions"][0][
+        "emergency"
+    ]
+    emer_txid = bitcoind.rpc.decoderawtransaction(emer_tx["hex"])["txid"]
+    assert rejected["txid"] == emer_txid
+    stk.wait_for_log(f"would not accept revocation transaction '{emer_txid}'")
+    wait_for(lambda: os.path.exists(out_file))
+    with open(out_file, "r") as f:
+        args = f.readline().split(" ", 4)
+    assert args[:4] == [deposit, "active", "revocation_rejected", emer_txid]
+    assert "min relay fee not met" in args[4]
+
+    # The vault is flagged until an operator acknowledges it, even across restarts
+    def flags():
+        return stk.rpc.listvaults([], [deposit])["vaults"][0]["flags"]
+
+    assert len(flags()) == 1
+    assert flags()[0]["kind"] == "revocation_rejected"
+    assert emer_txid in flags()[0]["message"]
+    stk.stop()
+    stk.start()
+    assert len(flags()) == 1
+    # Clearing it while the issue isn't fixed, it'll be raised again at the next check
+    stk.rpc.clearvaultflag(deposit, "revocation_rejected")
+    wait_for(lambda: len(flags()) == 1)
+
+    # And it's back to normal once the policy is
+    bitcoind.stop()
+    with open(bitcoind.conf_file, "w") as f:
+        f.write(bitcoind_conf)
+    bitcoind.start()
+    wait_for(lambda: stk.rpc.getinfo()["health"]["rejected_revocations"] == [])
+    assert len(flags()) == 1
+    stk.rpc.clearvaultflag(deposit, "revocation_rejected")
+    assert flags() == []
+    with pytest.raises(RpcError, match="has no active 'revocation_rejected' flag"):
+        stk.rpc.clearvaultflag(deposit, "revocation_rejected")
+    with pytest.raises(RpcError, match="Unknown vault flag kind"):
+        stk.rpc.clearvaultflag(deposit, "unknown")
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_relay_floor_check(revault_network, bitcoind):
+    """We report the presigned transactions paying less than bitcoind's relay floor"""
+    revault_network.deploy(2, 1)
+    vault = revault_network.fund(1)
+    revault_network.activate_fresh_vaults([vault])
+    deposit = f"{vault['txid']}:{vault['vout']}"
+
+    stk = revault_network.stk(0)
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "daemon = false\n",
+                "daemon = false\nrelay_floor_check_interval_secs = 1\n",
+            )
+        )
+    stk.start()
+
+    # All good for now, it's checked at startup
+    wait_for(lambda: stk.rpc.getinfo()["health"]["relay_floor"] is not None)
+    relay_floor = stk.rpc.getinfo()["health"]["relay_floor"]
+    assert relay_floor["min_relay_feerate"] == 1
+    assert relay_floor["margin_percent"] == 20
+    assert relay_floor["low_feerate_txs"] == []
+
+    # Bitcoind is restarted with a minimum relay feerate above what our presigned
+    # transactions pay
+    with open(bitcoind.conf_file, "r") as f:
+        bitcoind_conf = f.read()
+    bitcoind.stop()
+    with open(bitcoind.conf_file, "w") as f:
+        f.write(bitcoind_conf + "minrelaytxfee=0.1\n")
+    bitcoind.start()
+
+    def low_feerate_txs():
+        return stk.rpc.getinfo()["health"]["relay_floor"]["low_feerate_txs"]
+
+    wait_for(lambda: len(low_feerate_txs()) == 4)
+    relay_floor = stk.rpc.getinfo()["health"]["relay_floor"]
+    assert relay_floor["min_relay_feerate"] == 10_000
+    assert relay_floor["mempool_min_feerate"] <= 10_000
+    presigned = stk.rpc.listpresignedtransactions([deposit])["presigned_transactions"][
+        0
+    ]
+    for tx in ("unvault", "cancel", "emergency", "unvault_emergency"):
+        txid = bitcoind.rpc.decoderawtransaction(presigned[tx]["hex"])["txid"]
+        entry = next(e for e in relay_floor["low_feerate_txs"] if e["txid"] == txid)
+        assert entry["deposit_outpoint"] == deposit
+        assert entry["feerate"] == presigned[tx]["signing_context"]["feerate"]
+        assert entry["below_floor"]
+    stk.wait_for_log(
+        "4 of our presigned transactions pay less than 20% above the relay floor"
+    )
+
+    # And it's back to normal once the policy is
+    bitcoind.stop()
+    with open(bitcoind.conf_file, "w") as f:
+        f.write(bitcoind_conf)
+    bitcoind.start()
+    wait_for(lambda: low_feerate_txs() == [])
+    stk.wait_for_log("all pay more than 20% above the relay floor of bitcoind")
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_alert_tiers(revault_network, bitcoind):
+    """Vaults are alerted about according to the tier of their value"""
+    revault_network.deploy(2, 1)
+    small_vault = revault_network.fund(0.1)
+    large_vault = revault_network.fund(2)
+    revault_network.secure_vaults([small_vault, large_vault])
+    small_deposit = f"{small_vault['txid']}:{small_vault['vout']}"
+    large_deposit = f"{large_vault['txid']}:{large_vault['vout']}"
+
+    # A local stub recording what is posted to the webhook
+    posted = []
+
+    class WebhookHandler(BaseHTTPRequestHandler):
+        def do_POST(self):
+            length = int(self.headers["Content-Length"])
+            posted.append(json.loads(self.rfile.read(length)))
+            self.send_response(200)
+            self.end_headers()
+
+    server = HTTPServer(("127.0.0.1", 0), WebhookHandler)
+    threading.Thread(target=server.serve_forever, daemon=True).start()
+    webhook_url = f"http://127.0.0.1:{server.server_port}/alerts"
+
+    stk = revault_network.stk(0)
+    out_file = os.path.join(stk.datadir_with_network, "notified")
+    script = os.path.join(stk.datadir_with_network, "notify.sh")
+    with open(script, "w") as f:
+        f.write(f'#!/bin/sh\necho "$@" >> {out_file}\n')
+    os.chmod(script, 0o700)
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "daemon = false\n",
+                f'daemon = false\nnotify_command = "{script}"\n',
+            )
+            + "\n[[alert_tiers]]\nmin_amount = 0\nlog_level = \"info\"\n"
+            "run_notify_command = false\n"
+            "\n[[alert_tiers]]\nname = \"large\"\nmin_amount = 100000000\n"
+            f'log_level = "error"\nwebhook_url = "{webhook_url}"\n'
+        )
+    stk.start()
+    stk.wait_for_log("Caught up with the chain, now notifying status transitions")
+
+    stk.rpc.emergency()
+    stk.wait_for_logs(
+        [
+            f"Vault at '{small_deposit}' worth 0.10000000 BTC is now 'emergencyvaulting'",
+            f"Vault at '{large_deposit}' worth 2.00000000 BTC is now 'emergencyvaulting'",
+        ]
+    )
+
+    # Only the large vault is posted to the webhook, and the notify command only run for it
+    # (and once for the emergency run itself)
+    wait_for(lambda: len(posted) == 1)
+    assert posted[0]["tier"] == "large"
+    assert posted[0]["tier_min_amount"] == 100000000
+    assert posted[0]["deposit_outpoint"] == large_deposit
+    assert posted[0]["amount"] == 200000000
+    assert posted[0]["old_status"] == "secured"
+    assert posted[0]["new_status"] == "emergencyvaulting"
+    def notified():
+        if not os.path.exists(out_file):
+            return []
+        with open(out_file, "r") as f:
+            return f.read().splitlines()
+
+    wait_for(lambda: len(notified()) == 2)
+    lines = notified()
+    assert any(l.startswith(f"{large_deposit} secured emergencyvaulting") for l in lines)
+    assert any(l.startswith("emergency ") for l in lines)
+    assert not any(l.startswith(small_deposit) for l in lines)
+    server.shutdown()
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_bind_address(revault_network):
+    """The connections to the servers go out from the configured address"""
+    revault_network.deploy(2, 1)
+    man = revault_network.man(0)
+    coordinator_port = revault_network.coordinator_port
+
+    # A relay in front of the Coordinator, recording where the connections come from
+    relay = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    relay.bind(("127.0.0.1", 0))
+    relay.listen()
+    peers = []
+
+    def forward(src, dst):
+        try:
+            while True:
+                data = src.recv(4096)
+                if not data:
+                    break
+                dst.sendall(data)
+        except OSError:
+            pass
+        finally:
+            for sock in (src, dst):
+                try:
+                    sock.shutdown(socket.SHUT_RDWR)
+                except OSError:
+                    pass
+
+    def serve():
+        while True:
+            try:
+                client, (peer_ip, _) = relay.accept()
+            except OSError:
+                return
+            peers.append(peer_ip)
+            upstream = socket.create_connection(("127.0.0.1", coordinator_port))
+            for args in ((client, upstream), (upstream, client)):
+                threading.Thread(target=forward, args=args, daemon=True).start()
+
+    threading.Thread(target=serve, daemon=True).start()
+
+    man.stop()
+    with open(man.conf_file, "r") as f:
+        conf = f.read()
+    bound_conf = conf.replace(
+        f'coordinator_host = "127.0.0.1:{coordinator_port}"',
+        f'coordinator_host = "127.0.0.1:{relay.getsockname()[1]}"',
+    ).replace("daemon = false\n", 'daemon = false\nbind_address = "127.0.0.2"\n')
+    with open(man.conf_file, "w") as f:
+        f.write(bound_conf)
+    man.start()
+
+    # The Coordinator and the cosigning servers are reached from 127.0.0.2
+    status = man.rpc.getserverstatus()
+    assert status["coordinator"]["reachable"]
+    assert len(status["cosigners"]) > 0
+    assert all(c["reachable"] for c in status["cosigners"])
+    assert len(peers) > 0 and all(peer == "127.0.0.2" for peer in peers), peers
+    man.wait_for_log("Connected to cosigner at '.*' from '127.0.0.2:[0-9]+'")
+
+    # An address that isn't ours is refused at startup
+    man.stop()
+    with open(man.conf_file, "w") as f:
+        f.write(bound_conf.replace('"127.0.0.2"', '"192.0.2.1"'))
+    res = subprocess.run(man.cmd_line, capture_output=True, timeout=TIMEOUT)
+    assert res.returncode != 0
+    output = res.stdout.decode() + res.stderr.decode()
+    assert "Can't connect to the servers from 'bind_address' '192.0.2.1'" in output
+
+    with open(man.conf_file, "w") as f:
+        f.write(conf)
+    man.start()
+    relay.close()
+
+
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
 def test_huge_deposit(revault_network, bitcoind):
     revault_network.deploy(2, 1)
@@ -101,6 +829,26 @@ def test_revocation_sig_sharing(revault_network):
         wait_for(lambda: len(stk.rpc.listvaults(["secured"], [deposit])["vaults"]) > 0)
 
 
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_server_connections_logged(revault_network):
+    """Every connection to a server is logged along with the Noise key it proved to have."""
+    revault_network.deploy(2, 1)
+    stk = revault_network.stk(0)
+    vault = revault_network.fund(1)
+    revault_network.secure_vault(vault)
+
+    stk.wait_for_logs(
+        [
+            r"Connected to coordinator at '127.0.0.1:\d+' \(Noise key '[0-9a-f]{64}'\)"
+            r" \(event=connection_open kind=coordinator peer=127.0.0.1:\d+ ip=127.0.0.1"
+            r" noise_key=[0-9a-f]{64}\)",
+            r"Disconnected from coordinator at '127.0.0.1:\d+' after \d+ms"
+            r" \(event=connection_closed kind=coordinator peer=127.0.0.1:\d+"
+            r" duration_ms=\d+\)",
+        ]
+    )
+
+
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
 def test_raw_broadcast_cancel(revault_network, bitcoind):
     """
@@ -225,9 +973,9 @@ def test_sigfetcher_coordinator_dead(revault_network, bitcoind):
             psbts["emergency_unvault_tx"], vault["derivation_index"]
         )
 
-        # Revaultd complains because the coordinator is dead
-        with pytest.raises(RpcError, match="Connection refused"):
-            stk.rpc.revocationtxs(deposit, cancel_psbt, emer_psbt, unemer_psbt)
+        # The signatures are queued even though the coordinator is dead
+        stk.rpc.revocationtxs(deposit, cancel_psbt, emer_psbt, unemer_psbt)
+        assert stk.rpc.getserverstatus()["coordinator_queue"]["depth"] == 3
 
         # The sigfetcher tries to fetch the signatures, but fails
         stk.wait_for_log("Error while fetching signatures")
@@ -247,6 +995,7 @@ def test_sigfetcher_coordinator_dead(revault_network, bitcoind):
             ]
         )
         stk.wait_for_secured_vaults([deposit])
+        wait_for(lambda: stk.rpc.getserverstatus()["coordinator_queue"]["depth"] == 0)
 
 
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
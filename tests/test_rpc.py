@@ -4,17 +4,28 @@ These tests should ideally be focused on only sanity checking the command API. M
 complete test scenarii using these commands belong to another group.
 """
 
+import base58
+import coincurve
 import copy
+import os
 import pytest
 import random
+import re
+import signal
+import subprocess
 import time
 
+from ephemeral_port_reserve import reserve
 from fixtures import *
+from nacl.public import PrivateKey as Curve25519Private
 from test_framework import serializations
 from test_framework.utils import (
+    COIN,
     POSTGRES_IS_SETUP,
+    REVAULTD_FEATURES,
     TIMEOUT,
     RpcError,
+    tcp_rpc_call,
     wait_for,
 )
 
@@ -23,7 +34,19 @@ def test_getinfo(revaultd_manager, bitcoind):
     res = revaultd_manager.rpc.call("getinfo")
     assert res["network"] == "regtest"
     assert res["sync"] == 1.0
+    assert res["bitcoind_reachable"] is True
+    # One pool of connections per endpoint, within their bound
+    assert [p["name"] for p in res["bitcoind_connections"]] == [
+        "node",
+        "watchonly",
+        "cpfp",
+    ]
+    for pool in res["bitcoind_connections"]:
+        assert pool["max_size"] == 4
+        assert pool["in_use"] + pool["idle"] <= pool["max_size"]
+    assert res["bitcoind_connections"][0]["checkouts"] > 0
     assert res["version"] == "0.3.1"
+    assert res["db_synchronous"] == "off"
     assert res["vaults"] == 0
     # revaultd_manager always deploys with N = 2, M = 3, threshold = M
     assert res["managers_threshold"] == 3
@@ -31,13 +54,189 @@ def test_getinfo(revaultd_manager, bitcoind):
     assert res["descriptors"]["cpfp"] == revaultd_manager.cpfp_desc
     assert res["descriptors"]["deposit"] == revaultd_manager.deposit_desc
     assert res["descriptors"]["unvault"] == revaultd_manager.unvault_desc
+    assert res["limits"] == {"max_spend_inputs": 50, "max_batch_size": 1000}
 
     wait_for(lambda: revaultd_manager.rpc.call("getinfo")["blockheight"] > 0)
+    # We watch the deposit addresses up to the gap limit above the first unused index
+    wait_for(
+        lambda: revaultd_manager.rpc.call("getinfo")["deposit_indexes"]["watched_up_to"]
+        == 100
+    )
+    res = revaultd_manager.rpc.call("getinfo")
+    assert res["deposit_indexes"] == {
+        "first_unused": 0,
+        "reserved": [],
+        "max_observed": None,
+        "watched_up_to": 100,
+        "ceiling": 100000,
+    }
     height = revaultd_manager.rpc.call("getinfo")["blockheight"]
     bitcoind.generate_block(1)
     wait_for(lambda: revaultd_manager.rpc.call("getinfo")["blockheight"] == height + 1)
 
 
+def test_version(revaultd_manager):
+    """The metadata of the build is returned, and was logged at startup"""
+    res = revaultd_manager.rpc.call("version")
+    assert re.fullmatch(r"(0|[1-9]\d*)\.(0|[1-9]\d*)\.(0|[1-9]\d*)", res["version"])
+    assert res["version"] == revaultd_manager.rpc.call("getinfo")["version"]
+    assert sorted(res["features"]) == sorted(REVAULTD_FEATURES.split(","))
+    assert re.fullmatch(r"\d{4}-\d{2}-\d{2}", res["build_date"])
+    assert res["rustc_version"].startswith("rustc ")
+    # Tests are run from the repository, but not necessarily from a git checkout
+    if res["git_commit"] is not None:
+        assert re.fullmatch(r"[0-9a-f]{40}", res["git_commit"])
+        assert isinstance(res["git_dirty"], bool)
+    else:
+        assert res["git_dirty"] is None
+    # This version first ran when the daemon was started for this test
+    assert 0 < res.pop("first_run_at") <= int(time.time())
+    assert revaultd_manager.rpc.call("getinfo")["build"] == res
+    revaultd_manager.wait_for_log(f"Starting revaultd {res['version']} \\(")
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_listcommands_help(revault_network):
+    rn = revault_network
+    rn.deploy(2, 1, n_stkmanagers=1)
+    stk, man = rn.stk_wallets[0], rn.man_wallets[0]
+    stkman = rn.stkman_wallets[0]
+
+    # Each participant is only told about the commands available to them
+    stk_cmds = [c["name"] for c in stk.rpc.listcommands()["commands"]]
+    man_cmds = [c["name"] for c in man.rpc.listcommands()["commands"]]
+    stkman_cmds = [c["name"] for c in stkman.rpc.listcommands()["commands"]]
+    assert "emergency" in stk_cmds and "getspendtx" not in stk_cmds
+    assert "getspendtx" in man_cmds and "emergency" not in man_cmds
+    assert set(stkman_cmds) == set(stk_cmds) | set(man_cmds)
+    assert "getinfo" in stk_cmds and "getinfo" in man_cmds
+
+    # Without a method, the legacy summary of all the commands
+    summary = man.rpc.help()
+    assert set(summary.keys()) == set(stkman_cmds)
+    assert summary["getspendtx"] == [
+        "outpoints",
+        "outputs",
+        "feerate",
+        "[allow_high_fees]",
+        "[allow_self_send]",
+        "[proposal_id]",
+    ]
+
+    # With one, a machine-readable description of its parameters and result
+    desc = man.rpc.help("setspendtx")
+    assert desc["name"] == "setspendtx"
+    assert desc["availability"] == "manager"
+    assert [p["name"] for p in desc["params"]] == [
+        "spend_txid",
+        "priority",
+        "idempotency_key",
+        "override_schedule",
+    ]
+    assert desc["params"][0]["optional"] is False
+    assert desc["params"][1] == {
+        "name": "priority",
+        "type": "bool",
+        "optional": True,
+        "default": False,
+        "description": "Whether to CPFP the transaction if it gets stuck",
+    }
+    fields = [f["name"] for f in man.rpc.help("getinfo")["result"]]
+    assert set(fields) == set(man.rpc.getinfo().keys())
+
+    with pytest.raises(RpcError, match="Unknown command 'unknown'"):
+        man.rpc.help("unknown")
+
+
+def test_features_hello(revault_network):
+    rn = revault_network
+    rn.deploy(2, 1, n_stkmanagers=1)
+    stk, man = rn.stk_wallets[0], rn.man_wallets[0]
+    stkman = rn.stkman_wallets[0]
+
+    # The features depend on the commands available to each participant
+    stk_info, man_info = stk.rpc.getinfo(), man.rpc.getinfo()
+    assert stk_info["api_version"] == man_info["api_version"] == "1.9.0"
+    assert "batch_revocations" in stk_info["features"]
+    assert "batch_revocations" not in man_info["features"]
+    assert "spend_proposals" in man_info["features"]
+    assert "spend_proposals" not in stk_info["features"]
+    assert set(stkman.rpc.getinfo()["features"]) == set(stk_info["features"]) | set(
+        man_info["features"]
+    )
+
+    # Clients can make sure the daemon is recent enough before using it
+    res = stk.rpc.hello()
+    assert res["api_version"] == "1.9.0"
+    assert res["version"] == stk_info["version"]
+    assert res["features"] == stk_info["features"]
+    assert man.rpc.hello("1.0.0")["features"] == man_info["features"]
+    assert man.rpc.hello("0.2.0")["api_version"] == "1.9.0"
+    with pytest.raises(
+        RpcError, match="implements API version 1.9.0 but at least 1.10.0 is required"
+    ):
+        man.rpc.hello("1.10.0")
+    with pytest.raises(RpcError, match="Invalid API version 'v1'"):
+        man.rpc.hello("v1")
+
+
+def test_getnoisestaticpubkey(revaultd_manager):
+    noise_secret_file = os.path.join(revaultd_manager.datadir_with_network, "noise_secret")
+    with open(noise_secret_file, "rb") as f:
+        noise_pubkey = bytes(Curve25519Private(f.read()).public_key).hex()
+
+    res = revaultd_manager.rpc.call("getnoisestaticpubkey")
+    assert res["pubkey"] == noise_pubkey
+    assert res["fingerprint"] == ":".join(
+        noise_pubkey[i : i + 4] for i in range(0, 16, 4)
+    )
+
+
+
+def test_tcp_rpc(revaultd_manager):
+    """The JSONRPC interface over TCP is only served to the configured clients"""
+    man = revaultd_manager
+    # The regtest client of contrib/config_regtest.toml
+    regtest_conf_path = os.path.join(
+        os.path.dirname(os.path.dirname(__file__)), "contrib", "config_regtest.toml"
+    )
+    with open(regtest_conf_path, "r") as f:
+        regtest_conf = f.read()
+    client_key = re.search(r'rpc_clients = .*noise_key = "([0-9a-f]{64})"', regtest_conf)
+    client_key = client_key[1]
+    client_priv = "679a8279c5167b2b1c34dd48ef65de00ea3e7f41ac9d5d62dc83f951e2227e6c"
+    client_pub = Curve25519Private(bytes.fromhex(client_priv)).public_key
+    assert bytes(client_pub).hex() == client_key
+
+    addr = f"127.0.0.1:{reserve()}"
+    man.stop()
+    with open(man.conf_file, "r") as f:
+        conf = f.read()
+    with open(man.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "daemon = false\n",
+                f'daemon = false\nrpc_listen = "{addr}"\n'
+                f'rpc_clients = [ {{ noise_key = "{client_key}" }} ]\n',
+            )
+        )
+    man.start()
+    noise_key = man.rpc.call("getnoisestaticpubkey")["pubkey"]
+
+    # The authorized client can use the interface
+    res = tcp_rpc_call(client_priv, noise_key, addr, "getinfo")
+    assert res["result"]["network"] == "regtest"
+
+    # Another one can't even complete the handshake
+    intruder_priv = os.urandom(32).hex()
+    with pytest.raises(subprocess.CalledProcessError):
+        tcp_rpc_call(intruder_priv, noise_key, addr, "getinfo")
+    man.wait_for_log("Refused TCP JSONRPC connection")
+
+    # And it didn't prevent the authorized one from connecting again
+    res = tcp_rpc_call(client_priv, noise_key, addr, "getnoisestaticpubkey")
+    assert res["result"]["pubkey"] == noise_key
+
 def test_listvaults(revaultd_manager, bitcoind):
     res = revaultd_manager.rpc.call("listvaults")
     assert res["vaults"] == []
@@ -146,6 +345,45 @@ def test_getdepositaddress(revault_network, bitcoind):
         assert addr2 == w.rpc.call("getdepositaddress")["address"]
 
 
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_getnewdepositaddress(revault_network, bitcoind):
+    """New deposit addresses are reserved until paid, and never handed out twice"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk = rn.stk(0)
+    first_unused = stk.rpc.getinfo()["deposit_indexes"]["first_unused"]
+    shared_addr = stk.rpc.getdepositaddress()["address"]
+
+    # A new address is reserved at the next index, which is then skipped
+    new = stk.rpc.getnewdepositaddress()
+    assert new["derivation_index"] == first_unused
+    assert new["address"] == shared_addr
+    new2 = stk.rpc.getnewdepositaddress()
+    assert new2["derivation_index"] == first_unused + 1
+    assert new2["address"] != new["address"]
+    assert stk.rpc.getdepositaddress()["address"] not in (
+        new["address"],
+        new2["address"],
+    )
+    deposit_indexes = stk.rpc.getinfo()["deposit_indexes"]
+    assert deposit_indexes["first_unused"] == first_unused + 2
+    assert deposit_indexes["reserved"] == [first_unused, first_unused + 1]
+
+    # A deposit to one of them releases it
+    bitcoind.rpc.sendtoaddress(new2["address"], 0.22222)
+    stk.wait_for_log("Got a new unconfirmed deposit")
+    wait_for(
+        lambda: stk.rpc.getinfo()["deposit_indexes"]["reserved"] == [first_unused]
+    )
+
+    # The unpaid one is still reserved after a restart
+    stk.stop()
+    stk.start()
+    deposit_indexes = stk.rpc.getinfo()["deposit_indexes"]
+    assert deposit_indexes["first_unused"] == first_unused + 2
+    assert deposit_indexes["reserved"] == [first_unused]
+
+
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
 def test_getrevocationtxs(revault_network, bitcoind):
     rn = revault_network
@@ -205,7 +443,196 @@ def test_getunvaulttx(revault_network):
 
 
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
-def test_listpresignedtransactions(revault_network):
+def test_getvaultdetails(revault_network, bitcoind):
+    rn = revault_network
+    rn.deploy(2, 1, csv=5)
+    stk, man = rn.stk(0), rn.man(0)
+    rn.fund(0.5)
+    vault = rn.fund(1.2)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    assert vault["derivation_index"] == 1
+
+    # We can't query for an unknow vault
+    invalid_outpoint = f"{'0'*64}:1"
+    with pytest.raises(RpcError, match=f"No vault at '{invalid_outpoint}'"):
+        stk.rpc.getvaultdetails(invalid_outpoint)
+
+    # Everything is available before anything got signed, let alone broadcast. The
+    # managers just don't know about the Emergency.
+    stk.wait_for_deposits([deposit])
+    man.wait_for_deposits([deposit])
+    details = stk.rpc.getvaultdetails(deposit)
+    assert details["deposit_outpoint"] == deposit
+    assert details["derivation_index"] == 1
+    assert details["deposit_address"] == vault["address"]
+    assert details["emergency_address"] is not None
+    assert details["txids"]["emergency"] is not None
+    assert details["txids"]["unvault_emergency"] is not None
+    man_details = man.rpc.getvaultdetails(deposit)
+    assert man_details["emergency_address"] is None
+    assert man_details["txids"]["emergency"] is None
+    assert man_details["txids"]["unvault_emergency"] is None
+    for key in ("emergency_address", "txids"):
+        del man_details[key]
+    assert all(details[key] == man_details[key] for key in man_details)
+    assert details["txids"]["unvault"] == man.rpc.getvaultdetails(deposit)["txids"][
+        "unvault"
+    ]
+
+    deposit_tx = bitcoind.rpc.decoderawtransaction(
+        bitcoind.rpc.gettransaction(vault["txid"])["hex"]
+    )
+    deposit_txo = deposit_tx["vout"][vault["vout"]]
+    assert deposit_txo["scriptPubKey"]["hex"] == details["deposit_script_pubkey"]
+
+    # Once broadcast, the Unvault is the one we announced and pays to the scripts
+    # we were told about.
+    rn.activate_fresh_vaults([vault])
+    rn.unvault_vaults_anyhow([vault])
+    unvault_hex = stk.rpc.listonchaintransactions([deposit])["onchain_transactions"][
+        0
+    ]["unvault"]["hex"]
+    unvault_tx = bitcoind.rpc.decoderawtransaction(unvault_hex)
+    assert unvault_tx["txid"] == details["txids"]["unvault"]
+    assert (
+        unvault_tx["vout"][0]["scriptPubKey"]["hex"] == details["unvault_script_pubkey"]
+    )
+    assert unvault_tx["vout"][1]["scriptPubKey"]["hex"] == details["cpfp_script_pubkey"]
+    new_details = stk.rpc.getvaultdetails(deposit)
+    assert new_details["final_txids"]["unvault"] == details["txids"]["unvault"]
+    del details["final_txids"], new_details["final_txids"]
+    assert details == new_details
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_listwatchtxids(revault_network, bitcoind):
+    rn = revault_network
+    rn.deploy(2, 1, csv=5)
+    stk, man = rn.stk(0), rn.man(0)
+    vault = rn.fund(3)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+
+    # Nothing to watch until the presigned transactions are fully signed
+    stk.wait_for_deposits([deposit])
+    assert stk.rpc.getvaultdetails(deposit)["final_txids"] == {
+        "unvault": None,
+        "cancel": None,
+        "emergency": None,
+        "unvault_emergency": None,
+    }
+    assert stk.rpc.listwatchtxids() == {
+        "unvault": [],
+        "cancel": [],
+        "emergency": [],
+        "unvault_emergency": [],
+    }
+
+    # Once secured, the revocation transactions are known. The managers only know about
+    # the Cancel.
+    rn.secure_vault(vault)
+    watch_txids = stk.rpc.listwatchtxids()
+    assert watch_txids["unvault"] == []
+    for tx_type in ("cancel", "emergency", "unvault_emergency"):
+        assert len(watch_txids[tx_type]) == 1
+        assert watch_txids[tx_type][0]["deposit_outpoint"] == deposit
+    man_watch_txids = man.rpc.listwatchtxids()
+    assert man_watch_txids["cancel"] == watch_txids["cancel"]
+    assert man_watch_txids["emergency"] == []
+    assert man_watch_txids["unvault_emergency"] == []
+
+    # Once active, the Unvault too.
+    rn.activate_vault(vault)
+    watch_txids = stk.rpc.listwatchtxids()
+    assert len(watch_txids["unvault"]) == 1
+    final_txids = stk.rpc.getvaultdetails(deposit)["final_txids"]
+    for tx_type in ("unvault", "cancel", "emergency", "unvault_emergency"):
+        assert final_txids[tx_type] == watch_txids[tx_type][0]["txid"]
+
+    # The precomputed txids are the ones of the transactions that actually hit the chain
+    rn.unvault_vaults_anyhow([vault])
+    stk.rpc.revault(deposit)
+    stk.wait_for_log("Unvault transaction at .* is now being canceled")
+    bitcoind.generate_block(1, wait_for_mempool=1)
+    wait_for(
+        lambda: stk.rpc.listvaults([], [deposit])["vaults"][0]["status"] == "canceled"
+    )
+    onchain_txs = stk.rpc.listonchaintransactions([deposit])["onchain_transactions"][0]
+    unvault_tx = bitcoind.rpc.decoderawtransaction(onchain_txs["unvault"]["hex"])
+    cancel_tx = bitcoind.rpc.decoderawtransaction(onchain_txs["cancel"]["hex"])
+    assert unvault_tx["txid"] == final_txids["unvault"]
+    assert cancel_tx["txid"] == final_txids["cancel"]
+
+    # It's not watched anymore once canceled
+    assert all(
+        txids["deposit_outpoint"] != deposit
+        for tx_type in ("unvault", "cancel", "emergency", "unvault_emergency")
+        for txids in stk.rpc.listwatchtxids()[tx_type]
+    )
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_verifyemergencydescriptor(revault_network, bitcoind):
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk, man = rn.stk_wallets[0], rn.man_wallets[0]
+
+    # The dummy 2of2 the Emergency address is generated from, with its public keys
+    wifs = [
+        "cRE7qAArQYnFQK7S1gXFTArFT4UWvh8J2v2EUajRWXbWFvRzxoeF",
+        "cTzcgRCmHNqUqZuZgvCPLUDXXrQSoVQpZiXQZWQzsLEytcTr6iXi",
+    ]
+    desc = bitcoind.rpc.getdescriptorinfo(f"wsh(multi(2,{wifs[0]},{wifs[1]}))")[
+        "descriptor"
+    ]
+    privkey = coincurve.PrivateKey(base58.b58decode_check(wifs[0])[1:33])
+    message = "We control the Emergency address"
+    proof = {
+        "pubkey": privkey.public_key.format().hex(),
+        "message": message,
+        "signature": privkey.sign(message.encode()).hex(),
+    }
+
+    with pytest.raises(RpcError, match="This is a stakeholder command"):
+        man.rpc.verifyemergencydescriptor(desc)
+    with pytest.raises(RpcError, match="none was stored"):
+        stk.rpc.verifyemergencydescriptor()
+
+    # Another descriptor doesn't match
+    other_desc = bitcoind.rpc.getdescriptorinfo(f"wsh(multi(1,{wifs[0]},{wifs[1]}))")[
+        "descriptor"
+    ]
+    res = stk.rpc.verifyemergencydescriptor(other_desc, None, proof, True)
+    assert res["matches"] is False
+    assert res["derived_address"] != rn.emergency_address
+    assert res["stored"] is False
+    stk.wait_for_log("not our Emergency address")
+
+    # The right one does, and the signed message proves the control of one of its keys
+    res = stk.rpc.verifyemergencydescriptor(desc, None, proof)
+    assert res == {
+        "descriptor": desc,
+        "derivation_index": None,
+        "derived_address": rn.emergency_address,
+        "emergency_address": rn.emergency_address,
+        "matches": True,
+        "proof_valid": True,
+        "stored": False,
+    }
+    proof["message"] = "Another message"
+    res = stk.rpc.verifyemergencydescriptor(desc, None, proof)
+    assert res["matches"] is True and res["proof_valid"] is False
+
+    # It's only stored if asked to, and can then be re-verified
+    with pytest.raises(RpcError, match="none was stored"):
+        stk.rpc.verifyemergencydescriptor()
+    assert stk.rpc.verifyemergencydescriptor(desc, None, None, True)["stored"] is True
+    res = stk.rpc.verifyemergencydescriptor()
+    assert res["descriptor"] == desc
+    assert res["matches"] is True and res["stored"] is True
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_listpresignedtransactions(revault_network, bitcoind):
     revault_network.deploy(2, 1)
     vaultA = revault_network.fund(0.2222221)
     vaultB = revault_network.fund(122.88881)
@@ -232,6 +659,15 @@ def test_listpresignedtransactions(revault_network):
     assert man_res["emergency"] is None
     assert man_res["unvault_emergency"] is None
 
+    # Nobody signed yet, and the signers are the stakeholders
+    stk_fingerprints = [
+        p["fingerprint"] for p in stks[0].rpc.listparticipants()["stakeholders"]
+    ]
+    for tx in ("unvault", "cancel", "emergency", "unvault_emergency"):
+        signers = stk_res[tx]["signers"]
+        assert [s["fingerprint"] for s in signers] == stk_fingerprints
+        assert not any(s["signed"] for s in signers)
+
     # Sanity check they all generated the same unsigned PSBTs
     for w in stks[1:] + mans:
         w.wait_for_deposits([depositA])
@@ -253,6 +689,30 @@ def test_listpresignedtransactions(revault_network):
     assert stk_res["cancel"]["hex"] is not None
     assert stk_res["emergency"]["hex"] is not None
     assert stk_res["unvault_emergency"]["hex"] is not None
+    for tx in ("cancel", "emergency", "unvault_emergency"):
+        assert all(s["signed"] for s in stk_res[tx]["signers"])
+    assert not any(s["signed"] for s in stk_res["unvault"]["signers"])
+
+    # The fee market was recorded when the revocation transactions got fully signed
+    height = bitcoind.rpc.getblockcount()
+    for tx in ("cancel", "emergency", "unvault_emergency"):
+        context = stk_res[tx]["signing_context"]
+        assert context["feerate"] == stk_res[tx]["estimates"]["feerate"]
+        assert context["blockheight"] <= height
+    assert stk_res["unvault"]["signing_context"] is None
+
+    # Hence we can tell whether they were signed below a feerate
+    feerate = stk_res["cancel"]["signing_context"]["feerate"]
+    assert stks[0].rpc.getstalerevocations(1)["vaults"] == []
+    stale = stks[0].rpc.getstalerevocations(feerate + 1)["vaults"]
+    assert len(stale) == 1
+    assert stale[0]["deposit_outpoint"] == depositA
+    assert stale[0]["status"] == "secured"
+    assert stale[0]["transactions"][0] == {
+        "type": "cancel",
+        "txid": bitcoind.rpc.decoderawtransaction(stk_res["cancel"]["hex"])["txid"],
+        "signing_context": stk_res["cancel"]["signing_context"],
+    }
 
     # If the vault gets activated the unvault transaction will then be available
     revault_network.activate_vault(vaultA)
@@ -261,8 +721,98 @@ def test_listpresignedtransactions(revault_network):
     ][0]
     assert man_res["unvault"]["hex"] is not None
 
+    # The size estimates are upper bounds of the fully-signed transactions
+    for tx in ("unvault", "cancel", "emergency", "unvault_emergency"):
+        res = man_res if tx == "unvault" else stk_res
+        estimates = res[tx]["estimates"]
+        weight = bitcoind.rpc.decoderawtransaction(res[tx]["hex"])["weight"]
+        assert estimates["weight"] >= weight
+        assert (estimates["weight"] - weight) * 100 <= weight
+        assert estimates["vsize"] == (estimates["weight"] + 3) // 4
+        assert estimates["feerate"] == estimates["fees"] // estimates["vsize"]
+        if tx == "unvault":
+            assert estimates["cpfp_value"] == 30_000
+        else:
+            assert estimates["cpfp_value"] is None
 
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_getwatchdata(revault_network, bitcoind):
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk = rn.stk(0)
+
+    # It's a stakeholder command
+    with pytest.raises(RpcError, match="This is a stakeholder command"):
+        rn.man(0).rpc.getwatchdata()
+
+    # Nothing to watch until a vault is Active
+    vaults = rn.fundmany([1, 2, 3])
+    rn.secure_vaults(vaults)
+    assert stk.rpc.getwatchdata() == {"sequence": 0, "vaults": []}
+
+    for v in vaults[:2]:
+        rn.activate_vault(v)
+    res = stk.rpc.getwatchdata()
+    assert res["sequence"] == 2
+    deposits = [f"{v['txid']}:{v['vout']}" for v in vaults]
+    assert sorted(e["deposit_outpoint"] for e in res["vaults"]) == sorted(deposits[:2])
+    assert [e["sequence"] for e in res["vaults"]] == [1, 2]
+    for entry in res["vaults"]:
+        assert not entry["revoked"]
+        presigned = stk.rpc.listpresignedtransactions([entry["deposit_outpoint"]])[
+            "presigned_transactions"
+        ][0]
+        assert entry["cancel_tx"] == presigned["cancel"]["hex"]
+        assert entry["unvault_emergency_tx"] == presigned["unvault_emergency"]["hex"]
+        unvault_tx = bitcoind.rpc.decoderawtransaction(presigned["unvault"]["hex"])
+        assert entry["unvault_txid"] == unvault_tx["txid"]
+        unvault_spk = unvault_tx["vout"][0]["scriptPubKey"]["hex"]
+        assert entry["unvault_script_pubkey"] == unvault_spk
+        # Everything needed to revoke it, without us
+        for tx in (entry["cancel_tx"], entry["unvault_emergency_tx"]):
+            txin = bitcoind.rpc.decoderawtransaction(tx)["vin"][0]
+            assert txin["txid"] == entry["unvault_txid"]
+            assert len(txin["txinwitness"]) > 0
+
+    # Nothing changed since then
+    assert stk.rpc.getwatchdata(res["sequence"]) == {
+        "sequence": res["sequence"],
+        "vaults": [],
+    }
+
+    # A canceled vault is marked as revoked, a newly activated one is watched
+    canceled = next(
+        v
+        for v in vaults[:2]
+        if f"{v['txid']}:{v['vout']}" == res["vaults"][0]["deposit_outpoint"]
+    )
+    rn.unvault_vaults_anyhow([canceled])
+    rn.cancel_vault(canceled)
+    rn.activate_vault(vaults[2])
+    delta = stk.rpc.getwatchdata(res["sequence"])
+    assert delta["sequence"] == 4
+    assert [
+        (e["deposit_outpoint"], e["sequence"], e["revoked"]) for e in delta["vaults"]
+    ] == [
+        (res["vaults"][0]["deposit_outpoint"], 3, True),
+        (deposits[2], 4, False),
+    ]
+    revoked = delta["vaults"][0]
+    assert revoked["unvault_txid"] is None and revoked["cancel_tx"] is None
+    assert delta["vaults"][1]["cancel_tx"] is not None
+
+    # The full stream only has the latest state of each vault
+    full = stk.rpc.getwatchdata(0)
+    assert full["sequence"] == 4
+    assert [(e["sequence"], e["revoked"]) for e in full["vaults"]] == [
+        (2, False),
+        (3, True),
+        (4, False),
+    ]
+
+
 def test_listspendtxs(revault_network, bitcoind):
     rn = revault_network
     rn.deploy(n_stakeholders=2, n_managers=2, n_stkmanagers=0, csv=5)
@@ -290,6 +840,8 @@ def test_listspendtxs(revault_network, bitcoind):
         assert len(spend_txs) == 1
         assert spend_txs[0]["change_index"] is None
         assert spend_txs[0]["cpfp_index"] is not None
+        assert not any(c["signed"] for c in spend_txs[0]["cosigners"])
+        assert spend_txs[0]["announcement"] is None
 
     spend_psbt = serializations.PSBT()
     spend_psbt.deserialize(spend_tx)
@@ -306,6 +858,15 @@ def test_listspendtxs(revault_network, bitcoind):
     assert len(spend_txs) == 1
     assert spend_txs[0]["change_index"] is None
     assert spend_txs[0]["cpfp_index"] is not None
+    # We got the signatures of all the cosigning servers
+    cosigners = man.rpc.listcosigners()["cosigners"]
+    assert len(spend_txs[0]["cosigners"]) == len(cosigners)
+    assert all(c["signed"] for c in spend_txs[0]["cosigners"])
+    # We recorded announcing it to the Coordinator
+    announcement = spend_txs[0]["announcement"]
+    assert announcement["status"] == "announced"
+    assert announcement["announced_at"] is not None
+    assert announcement["reconciled_at"] is None
 
     rn.bitcoind.generate_block(rn.csv - 1, wait_for_mempool=len(deposits))
 
@@ -584,6 +1145,34 @@ def test_revocationtxs(revault_network):
         psbts["emergency_unvault_tx"], child_index
     )
 
+    # If it was signed with another stakeholder's device, we are told so
+    stk1_fingerprint = next(
+        p["fingerprint"]
+        for p in stks[0].rpc.listparticipants()["stakeholders"]
+        if p["xpub"] == stks[1].stk_keychain.get_xpub()
+    )
+    wrong_cancel = stks[1].stk_keychain.sign_revocation_psbt(
+        psbts["cancel_tx"], child_index
+    )
+    with pytest.raises(
+        RpcError,
+        match=f"No signature for ourselves.*Cancel.*only for stakeholder\\(s\\) {stk1_fingerprint}.*wrong signing device",
+    ):
+        stks[0].rpc.revocationtxs(deposit, wrong_cancel, emer_psbt, unemer_psbt)
+    # Even if the signature was attached to our key
+    psbt = serializations.PSBT()
+    psbt.deserialize(wrong_cancel)
+    our_pubkey = stks[0].stk_keychain.hd.get_pubkey_from_path([child_index])
+    their_pubkey = stks[1].stk_keychain.hd.get_pubkey_from_path([child_index])
+    psbt.inputs[0].partial_sigs[our_pubkey] = psbt.inputs[0].partial_sigs.pop(
+        their_pubkey
+    )
+    with pytest.raises(
+        RpcError,
+        match=f"Invalid signature.*Cancel PSBT.*valid for stakeholder {stk1_fingerprint}'s key.*wrong signing device",
+    ):
+        stks[0].rpc.revocationtxs(deposit, psbt.serialize(), emer_psbt, unemer_psbt)
+
     # We refuse any random garbage signature
     mal_cancel = psbt_add_invalid_sig(cancel_psbt)
     with pytest.raises(RpcError, match="Unknown key in Cancel"):
@@ -606,6 +1195,72 @@ def test_revocationtxs(revault_network):
     assert len(stks[0].rpc.listvaults(["securing"], [deposit])["vaults"]) == 1
 
 
+def test_revocationtxs_batch(revault_network):
+    """Sign the revocation transactions of many vaults at once"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk = rn.stk(0)
+    vaults = rn.fundmany([1, 2, 3])
+    deposits = [f"{v['txid']}:{v['vout']}" for v in vaults]
+    indexes = {d: v["derivation_index"] for d, v in zip(deposits, vaults)}
+    stk.wait_for_deposits(deposits)
+
+    # Managers can't, in batch either
+    with pytest.raises(RpcError, match="This is a stakeholder command"):
+        rn.man(0).rpc.getrevocationtxs(deposits)
+    # Only the funded vaults have revocation transactions to sign
+    with pytest.raises(RpcError, match="Only 'funded' vaults"):
+        stk.rpc.getrevocationtxs({"status": "securing"})
+
+    # We can get them by outpoints or by status, and they are the same as when
+    # querying a single vault
+    batch = stk.rpc.getrevocationtxs(deposits)["revocation_txs"]
+    assert sorted(batch.keys()) == sorted(deposits)
+    assert batch == stk.rpc.getrevocationtxs({"status": "funded"})["revocation_txs"]
+    for deposit in deposits:
+        assert batch[deposit] == stk.rpc.getrevocationtxs(deposit)
+
+    # Now sign them all, but corrupt the Cancel of the second one
+    signed = {}
+    for deposit, txs in batch.items():
+        signed[deposit] = {
+            name: stk.stk_keychain.sign_revocation_psbt(psbt, indexes[deposit])
+            for name, psbt in txs.items()
+        }
+    signed[deposits[1]]["cancel_tx"] = psbt_add_invalid_sig(
+        signed[deposits[1]]["cancel_tx"]
+    )
+
+    # The transactions must be part of the batch
+    with pytest.raises(RpcError, match="must be part of the batch"):
+        stk.rpc.revocationtxs(signed, batch[deposits[0]]["cancel_tx"])
+
+    # The bad one is reported, but doesn't prevent the others from being stored
+    results = stk.rpc.revocationtxs(signed)["results"]
+    assert results[deposits[0]] == {"success": True, "error": None}
+    assert results[deposits[2]] == {"success": True, "error": None}
+    assert results[deposits[1]]["success"] is False
+    assert "Unknown key in Cancel" in results[deposits[1]]["error"]["message"]
+    securing = stk.rpc.listvaults(["securing"], deposits)["vaults"]
+    assert sorted(f"{v['txid']}:{v['vout']}" for v in securing) == sorted(
+        [deposits[0], deposits[2]]
+    )
+    funded = stk.rpc.getrevocationtxs({"status": "funded"})["revocation_txs"]
+    assert list(funded.keys()) == [deposits[1]]
+
+    # The batch size limit is enforced on both calls
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(conf.replace("daemon = false\n", "daemon = false\nmax_batch_size = 2\n"))
+    stk.start()
+    with pytest.raises(RpcError, match="got '3' but the limit is '2'"):
+        stk.rpc.getrevocationtxs(deposits)
+    with pytest.raises(RpcError, match="got '3' but the limit is '2'"):
+        stk.rpc.revocationtxs(signed)
+
+
 def test_unvaulttx(revault_network):
     """Sanity checks for the unvaulttx command"""
     revault_network.deploy(3, 1)
@@ -721,7 +1376,7 @@ def test_emergency(revault_network, bitcoind):
         rn.man(1).rpc.emergency()
 
     # Calling it without any vault won't do anything
-    rn.stk(1).rpc.emergency()
+    assert rn.stk(1).rpc.emergency() == {"vaults": []}
 
     # Emergencying with a single, not unvaulted vault
     vault = rn.fund(8)
@@ -846,6 +1501,95 @@ def test_emergency(revault_network, bitcoind):
         vaults
     ) + len(unvaulted_vaults)
 
+    # The outcome is reported for each vault: a vault that isn't secured can't be
+    # emergencied, nor can one that already was.
+    emergencied = deposits[0]
+    funded = rn.fund(3)
+    funded_deposit = f"{funded['txid']}:{funded['vout']}"
+    secured = rn.fund(4)
+    secured_deposit = f"{secured['txid']}:{secured['vout']}"
+    rn.secure_vault(secured)
+    outcomes = {v["deposit_outpoint"]: v for v in rn.stk(0).rpc.emergency()["vaults"]}
+    assert outcomes[emergencied] == {
+        "deposit_outpoint": emergencied,
+        "transaction": "emergency",
+        "outcome": "skipped_status",
+        "status": "emergencyvaulted",
+    }
+    assert outcomes[funded_deposit] == {
+        "deposit_outpoint": funded_deposit,
+        "transaction": "emergency",
+        "outcome": "missing_signatures",
+    }
+    assert outcomes[secured_deposit]["outcome"] == "broadcast"
+    emer_txid = outcomes[secured_deposit]["txid"]
+    wait_for(lambda: emer_txid in bitcoind.rpc.getrawmempool())
+
+    # Calling it again only retries the ones that failed: the Emergency transaction
+    # already broadcast isn't broadcast again.
+    outcomes = {v["deposit_outpoint"]: v for v in rn.stk(0).rpc.emergency()["vaults"]}
+    assert outcomes[funded_deposit]["outcome"] == "missing_signatures"
+    assert outcomes[secured_deposit]["outcome"] in [
+        "already_broadcast",
+        "skipped_status",
+    ]
+    assert all(v["outcome"] != "broadcast" for v in outcomes.values())
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_external_unvault_emergency(revault_network, bitcoind):
+    """An Unvault Emergency we never saw broadcast (for instance by a watchtower) is told
+    apart from an Emergency in the balances, the history and the emergency outcomes."""
+    rn = revault_network
+    rn.deploy(2, 1, csv=3)
+    vault = rn.fund(7)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    rn.activate_fresh_vaults([vault])
+    rn.unvault_vaults_anyhow([vault])
+
+    # A stakeholder is down while the Unvault Emergency is broadcast by someone else
+    stk, other_stk = rn.stk(0), rn.stk(1)
+    unemer_tx = stk.rpc.listpresignedtransactions([deposit])["presigned_transactions"][
+        0
+    ]["unvault_emergency"]
+    stk.stop()
+    unemer_txid = bitcoind.rpc.sendrawtransaction(unemer_tx["hex"])
+
+    # The other one sees it in the mempool, and reports which transaction is used
+    wait_for(
+        lambda: other_stk.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+        == "unvaultemergencyvaulting"
+    )
+    balances = other_stk.rpc.getbalances()
+    assert balances["unvault_emergency_vaulting"] == vault["amount"]
+    assert balances["emergency_vaulting"] == balances["moving"] == 0
+    outcome = other_stk.rpc.emergency()["vaults"][0]
+    assert outcome == {
+        "deposit_outpoint": deposit,
+        "transaction": "unvault_emergency",
+        "outcome": "skipped_status",
+        "status": "unvaultemergencyvaulting",
+    }
+
+    # The one that was down classifies it once it's back, although it's confirmed already
+    bitcoind.generate_block(1, wait_for_mempool=[unemer_txid])
+    stk.start()
+    for w in [stk, other_stk]:
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "unvaultemergencyvaulted"
+        )
+        assert w.rpc.listvaults([], [deposit])["vaults"][0]["flags"] == []
+        end = int(time.time()) + 3600
+        assert w.rpc.gethistory(["emergency"], 0, end, 10)["events"] == []
+        events = w.rpc.gethistory(["unvault_emergency"], 0, end, 10)["events"]
+        assert len(events) == 1
+        assert events[0]["kind"] == "unvault_emergency"
+        assert events[0]["txid"] == unemer_txid
+        assert events[0]["vaults"] == [deposit]
+        assert events[0]["amount"] + events[0]["fee"] == vault["amount"]
+        assert w.rpc.getbalances()["unvault_emergency_vaulting"] == 0
+
 
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
 def test_getspendtx(revault_network, bitcoind):
@@ -902,6 +1646,55 @@ def test_getspendtx(revault_network, bitcoind):
         destinations = {addr: vault["amount"] // 10}
         man.rpc.getspendtx(spent_vaults, destinations, 100_000)
 
+    # A feerate leading to more than 5% of the value spent being paid as fees is most likely a
+    # typo, it's refused unless explicitly allowed.
+    small_vault = revault_network.fund(0.5)
+    revault_network.secure_vault(small_vault)
+    revault_network.activate_vault(small_vault)
+    small_deposit = [f"{small_vault['txid']}:{small_vault['vout']}"]
+    destinations = {addr: small_vault["amount"] // 2}
+    with pytest.raises(
+        RpcError,
+        match="Spend transaction would pay .* of fees out of .* spent, above the threshold",
+    ):
+        man.rpc.getspendtx(small_deposit, destinations, 10_000)
+    man.rpc.getspendtx(small_deposit, destinations, 10_000, True)
+
+    # We refuse to pay to an address for another network
+    mainnet_addr = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+    with pytest.raises(
+        RpcError, match=f"Destination '{mainnet_addr}' is not a 'regtest' address"
+    ):
+        man.rpc.getspendtx(small_deposit, {mainnet_addr: small_vault["amount"] // 2}, 1)
+
+    # Paying back to one of our deposit addresses must be explicitly allowed
+    deposit_addr = man.rpc.getdepositaddress()["address"]
+    destinations = {deposit_addr: small_vault["amount"] // 2}
+    with pytest.raises(
+        RpcError, match=f"Destination '{deposit_addr}' is one of our deposit addresses"
+    ):
+        man.rpc.getspendtx(small_deposit, destinations, 1)
+    man.rpc.getspendtx(small_deposit, destinations, 1, False, True)
+
+    # Amounts are either an integer number of sats or a string in BTC, never a float
+    sats = small_vault["amount"] // 2
+    with pytest.raises(RpcError, match="not a floating point number"):
+        man.rpc.getspendtx(small_deposit, {addr: sats / 100_000_000}, 1)
+    with pytest.raises(RpcError, match="negative"):
+        man.rpc.getspendtx(small_deposit, {addr: -sats}, 1)
+    with pytest.raises(RpcError, match="more than 21 million bitcoins"):
+        man.rpc.getspendtx(small_deposit, {addr: 21_000_000 * 10**8 + 1}, 1)
+    btc = f"{sats // 10**8}.{sats % 10**8:08}"
+    assert (
+        man.rpc.getspendtx(small_deposit, {addr: btc}, 1)["spend_tx"]
+        == man.rpc.getspendtx(small_deposit, {addr: sats}, 1)["spend_tx"]
+    )
+
+    # Responses never contain floats
+    balances = man.rpc.getbalances()
+    assert all(isinstance(v, int) for v in balances.values())
+    assert all(isinstance(v["amount"], int) for v in man.rpc.listvaults()["vaults"])
+
     # We can spend many vaults
     deposits = [deposit]
     amounts = [vault["amount"]]
@@ -925,7 +1718,7 @@ def test_getspendtx(revault_network, bitcoind):
         destinations = {addr: sent_amount}
         psbt = serializations.PSBT()
         psbt.deserialize(
-            man.rpc.getspendtx(deposits, destinations, feerate)["spend_tx"]
+            man.rpc.getspendtx(deposits, destinations, feerate, True)["spend_tx"]
         )
         assert (
             len(psbt.inputs) == len(deposits) and len(psbt.outputs) == 2
@@ -939,7 +1732,7 @@ def test_getspendtx(revault_network, bitcoind):
         destinations[bitcoind.rpc.getnewaddress()] = vault["amount"] // 20
         psbt = serializations.PSBT()
         psbt.deserialize(
-            man.rpc.getspendtx(deposits, destinations, feerate)["spend_tx"]
+            man.rpc.getspendtx(deposits, destinations, feerate, True)["spend_tx"]
         )
         assert (
             len(psbt.inputs) == len(deposits)
@@ -954,7 +1747,9 @@ def test_getspendtx(revault_network, bitcoind):
         deposits.append(f"{vault['txid']}:{vault['vout']}")
         destinations[bitcoind.rpc.getnewaddress()] = vault["amount"] // 2
     psbt = serializations.PSBT()
-    psbt.deserialize(man.rpc.getspendtx(deposits, destinations, feerate)["spend_tx"])
+    psbt.deserialize(
+        man.rpc.getspendtx(deposits, destinations, feerate, True)["spend_tx"]
+    )
     assert (
         len(psbt.inputs) == len(deposits)
         # destinations + CPFP + change
@@ -967,7 +1762,7 @@ def test_getspendtx(revault_network, bitcoind):
         RpcError,
         match="Transaction too large: satisfied it could be >400k weight units",
     ):
-        man.rpc.getspendtx(deposits, destinations, feerate)
+        man.rpc.getspendtx(deposits, destinations, feerate, True)
 
 
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
@@ -1097,6 +1892,122 @@ def test_revault_command(revault_network, bitcoind, executor):
         assert cancel_txid in [v["txid"] for v in new_deposits]
 
 
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_revaulted_vault(revault_network, bitcoind):
+    """The vault created by a Cancel is linked to the canceled one, and needs to be secured"""
+    revault_network.deploy(2, 1)
+    stk = revault_network.stk(0)
+    vault = revault_network.fund(5)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    revault_network.activate_fresh_vaults([vault])
+    assert stk.rpc.getbalances()["active"] == 5 * COIN
+    revault_network.unvault_vaults_anyhow([vault])
+    revault_network.cancel_vault(vault)
+
+    # The Cancel output is a new vault at the same derivation index
+    def child_entry(w):
+        return next(
+            (
+                v
+                for v in w.rpc.listvaults()["vaults"]
+                if v["revaulted_from"] == deposit
+            ),
+            None,
+        )
+
+    bitcoind.generate_block(6)
+    for w in revault_network.participants():
+        wait_for(
+            lambda: child_entry(w) is not None and child_entry(w)["status"] == "funded"
+        )
+    child = child_entry(stk)
+    child_deposit = f"{child['txid']}:{child['vout']}"
+    assert child["derivation_index"] == vault["derivation_index"]
+    assert child["awaiting_resecuring"]
+    parent = stk.rpc.listvaults([], [deposit])["vaults"][0]
+    assert parent["status"] == "canceled"
+    assert parent["revaulted_to"] == child_deposit
+    assert parent["awaiting_resecuring"]
+
+    # The canceled funds aren't counted twice, and aren't reported as secured
+    balances = stk.rpc.getbalances()
+    assert balances["awaiting_resecuring"] == child["amount"]
+    assert balances["secured"] == balances["active"] == balances["moving"] == 0
+
+    # Once its revocation transactions are signed, the funds are secured again
+    revault_network.secure_vault(
+        {
+            "txid": child["txid"],
+            "vout": child["vout"],
+            "derivation_index": child["derivation_index"],
+        }
+    )
+    for w in revault_network.participants():
+        wait_for(lambda: not child_entry(w)["awaiting_resecuring"])
+        assert not w.rpc.listvaults([], [deposit])["vaults"][0]["awaiting_resecuring"]
+    balances = stk.rpc.getbalances()
+    assert balances["awaiting_resecuring"] == 0
+    assert balances["secured"] == child["amount"]
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_deposit_origins(revault_network, bitcoind):
+    """Vaults funded by our own Cancel or Spend are told apart from external deposits"""
+    rn = revault_network
+    rn.deploy(2, 1, csv=5)
+    start = int(time.time())
+    external, canceled, spent = rn.fund(2), rn.fund(3), rn.fund(4)
+    deposits = [f"{v['txid']}:{v['vout']}" for v in [external, canceled, spent]]
+    for v in [canceled, spent]:
+        rn.secure_vault(v)
+        rn.activate_vault(v)
+
+    rn.unvault_vaults_anyhow([canceled])
+    rn.cancel_vault(canceled)
+    address = bitcoind.rpc.getnewaddress()
+    _, spend_txid = rn.spend_vaults([spent], {address: COIN}, 1)
+    bitcoind.generate_block(6)
+
+    def funded_by(w, txid):
+        return [
+            v
+            for v in w.rpc.listvaults(["funded"])["vaults"]
+            if v["txid"] == txid
+        ]
+
+    cancel_txid = rn.stk(0).rpc.listvaults([], [deposits[1]])["vaults"][0][
+        "revaulted_to"
+    ].split(":")[0]
+    for w in rn.participants():
+        wait_for(lambda: len(funded_by(w, cancel_txid)) == 1)
+        wait_for(lambda: len(funded_by(w, spend_txid)) == 1)
+
+        vaults = {f"{v['txid']}:{v['vout']}": v for v in w.rpc.listvaults()["vaults"]}
+        for deposit in deposits:
+            assert vaults[deposit]["origin"] == "external"
+            assert vaults[deposit]["change_from"] == []
+        revaulted = funded_by(w, cancel_txid)[0]
+        assert revaulted["origin"] == "cancel"
+        assert revaulted["revaulted_from"] == deposits[1]
+        assert revaulted["change_from"] == []
+        change = funded_by(w, spend_txid)[0]
+        assert change["origin"] == "spend_change"
+        assert change["change_from"] == [deposits[2]]
+
+        # Only the external funds are inflows, the rest is internal churn
+        by_origin = w.rpc.getbalances()["by_origin"]
+        assert by_origin == {
+            "external": external["amount"],
+            "cancel": revaulted["amount"],
+            "spend_change": change["amount"],
+        }
+
+    events = rn.man(0).rpc.gethistory(["deposit"], start, int(time.time()), 20)[
+        "events"
+    ]
+    assert sorted(e["vaults"][0] for e in events) == sorted(deposits)
+
+
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
 def test_getserverstatus(revault_network, bitcoind):
     rn = revault_network
@@ -1107,6 +2018,12 @@ def test_getserverstatus(revault_network, bitcoind):
         res = w.rpc.call("getserverstatus")
         assert res["coordinator"]["reachable"]
         assert res["coordinator"]["host"] == f"127.0.0.1:{rn.coordinator_port}"
+        assert len(res["coordinators"]) == 1
+        assert res["coordinators"][0]["active"]
+        assert res["coordinators"][0]["reachable"]
+        assert res["coordinators"][0]["consecutive_failures"] == 0
+        assert res["coordinator_queue"]["depth"] == 0
+        assert res["coordinator_queue"]["throttle"]["held_off_secs"] is None
 
     # The cosigners are alive, but only the managers see them
     for w in rn.mans():
@@ -1142,6 +2059,9 @@ def test_getserverstatus(revault_network, bitcoind):
         res = w.rpc.call("getserverstatus")
         assert not res["coordinator"]["reachable"]
         assert res["coordinator"]["host"] == f"127.0.0.1:{rn.coordinator_port}"
+        assert not res["coordinators"][0]["reachable"]
+        assert res["coordinators"][0]["consecutive_failures"] > 0
+        assert res["coordinators"][0]["last_error"] is not None
 
     # The cosigners are dead as well
     for w in rn.mans():
@@ -1317,3 +2237,155 @@ def test_gethistory(revault_network, bitcoind, executor):
             )
             == 5
         )
+
+
+def test_auditlog(revaultd_stakeholder):
+    rpc = revaultd_stakeholder.rpc
+    assert rpc.call("getauditlog")["entries"] == []
+    assert rpc.call("verifyauditlog") == {"valid": True, "broken_at": None}
+
+    # A failed destructive command is recorded, along with its outcome
+    invalid_outpoint = f"{'0'*64}:1"
+    with pytest.raises(RpcError, match=f"No vault at '{invalid_outpoint}'"):
+        rpc.call("revault", [invalid_outpoint])
+    entries = rpc.call("getauditlog")["entries"]
+    assert len(entries) == 2
+    assert [e["id"] for e in entries] == [1, 2]
+    assert all(e["method"] == "revault" for e in entries)
+    assert all(e["peer_uid"] == os.getuid() for e in entries)
+    assert entries[0]["result"] == "pending"
+    assert entries[1]["result"] == f"error: No vault at '{invalid_outpoint}'"
+    assert entries[0]["prev_hash"] == "00" * 32
+    assert entries[1]["prev_hash"] == entries[0]["hash"]
+
+    # We can query by date
+    assert rpc.call("getauditlog", [0, entries[0]["timestamp"] - 1])["entries"] == []
+
+    assert rpc.call("verifyauditlog") == {"valid": True, "broken_at": None}
+
+
+def test_idempotency_keys(revaultd_stakeholder):
+    """A command retried with the same idempotency key is only executed once"""
+    stk = revaultd_stakeholder
+    # Each executed command gets two audit log entries
+    executed = lambda: len(stk.rpc.call("getauditlog")["entries"]) // 2
+
+    # Without a key the command is executed each time
+    stk.rpc.call("emergency")
+    stk.rpc.call("emergency")
+    assert executed() == 2
+
+    # With a key, a retry returns the stored result without executing it again
+    assert stk.rpc.call("emergency", ["first"]) == {}
+    assert stk.rpc.call("emergency", ["first"]) == {}
+    assert executed() == 3
+    assert stk.rpc.call("emergency", ["second"]) == {}
+    assert executed() == 4
+
+    # A key can't be reused for another command
+    invalid_outpoint = f"{'0'*64}:1"
+    with pytest.raises(RpcError, match="Idempotency key 'first' was already used"):
+        stk.rpc.call("revault", [invalid_outpoint, "first"])
+    assert executed() == 4
+
+    # A failed command doesn't hold onto its key, it can be retried
+    for _ in range(2):
+        with pytest.raises(RpcError, match=f"No vault at '{invalid_outpoint}'"):
+            stk.rpc.call("revault", [invalid_outpoint, "third"])
+    assert executed() == 6
+
+    # Once the key expired, the command is executed again
+    stk.stop()
+    with open(stk.conf_file, "r") as f:
+        conf = f.read()
+    with open(stk.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "daemon = false\n", "daemon = false\nidempotency_retention_secs = 1\n"
+            )
+        )
+    stk.start()
+    time.sleep(2)
+    assert stk.rpc.call("emergency", ["first"]) == {}
+    assert executed() == 7
+    assert stk.rpc.call("emergency", ["first"]) == {}
+    assert executed() == 7
+
+
+def test_spending_schedule(revaultd_manager):
+    """setspendtx is refused outside of the spending schedule, which is reloaded on SIGHUP"""
+    man = revaultd_manager
+    spend_txid = "00" * 32
+    assert man.rpc.call("getinfo")["spending_schedule"] is None
+    with pytest.raises(RpcError, match="Unknown Spend transaction"):
+        man.rpc.call("setspendtx", [spend_txid])
+
+    # A daily window that only starts in a couple hours
+    start = f"{(time.gmtime().tm_hour + 2) % 24:02}:00"
+    end = f"{(time.gmtime().tm_hour + 3) % 24:02}:00"
+    with open(man.conf_file, "a") as f:
+        f.write(
+            "[manager_config.spending_schedule]\n"
+            'timezone = "UTC"\n'
+            "[[manager_config.spending_schedule.windows]]\n"
+            'days = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]\n'
+            f'start = "{start}"\n'
+            f'end = "{end}"\n'
+        )
+    os.kill(man.proc.pid, signal.SIGHUP)
+    man.wait_for_log("Reloaded configuration")
+    schedule = man.rpc.call("getinfo")["spending_schedule"]
+    assert schedule["timezone"] == "UTC"
+    assert schedule["windows"][0]["start"] == start
+    assert schedule["windows"][0]["end"] == end
+    with pytest.raises(RpcError, match="spending schedule doesn't allow spending now"):
+        man.rpc.call("setspendtx", [spend_txid])
+
+    # It may be overridden, but it stands out in the audit log
+    with pytest.raises(RpcError, match="Unknown Spend transaction"):
+        man.rpc.call("setspendtx", [spend_txid, False, None, True])
+    methods = [e["method"] for e in man.rpc.call("getauditlog")["entries"]]
+    assert methods[-2:] == ["setspendtx_override_schedule"] * 2
+
+    # An invalid configuration is not applied
+    with open(man.conf_file, "a") as f:
+        f.write("invalid")
+    os.kill(man.proc.pid, signal.SIGHUP)
+    man.wait_for_log("Error reloading the configuration, keeping the current one")
+    assert man.rpc.call("getinfo")["spending_schedule"] == schedule
+
+
+def test_getrpcstats(revaultd_manager):
+    """Calls are timed by method, the slow ones logged with where the time went"""
+    man = revaultd_manager
+    man.stop()
+    with open(man.conf_file, "r") as f:
+        conf = f.read()
+    with open(man.conf_file, "w") as f:
+        f.write(
+            conf.replace("daemon = false\n", "daemon = false\nslow_rpc_call_ms = 0\n")
+        )
+    man.start()
+
+    for _ in range(3):
+        man.rpc.listvaults()
+    stats = man.rpc.getrpcstats()
+    methods = {m["method"]: m for m in stats["methods"]}
+    assert methods["listvaults"]["calls"] == 3
+    for m in methods.values():
+        assert m["p50_us"] <= m["p95_us"] <= m["max_us"]
+
+    # With a threshold of 0ms, any call is slow
+    man.wait_for_log(
+        r"Slow RPC call 'listvaults': \d+ms, mostly in '\w+' \(database: \d+ms, "
+        r"bitcoind: \d+ms, coordinator: \d+ms, cosigners: \d+ms, watchtowers: \d+ms\)"
+    )
+
+    # The stats may be reset, after being returned
+    since = stats["since"]
+    stats = man.rpc.getrpcstats(True)
+    assert stats["since"] == since
+    assert "listvaults" in [m["method"] for m in stats["methods"]]
+    stats = man.rpc.getrpcstats()
+    assert stats["since"] >= since
+    assert [m["method"] for m in stats["methods"]] == ["getrpcstats"]
@@ -6,6 +6,7 @@ etc..
 
 import pytest
 import random
+import re
 
 from fixtures import *
 from test_framework import serializations
@@ -13,6 +14,7 @@ from test_framework.utils import (
     COIN,
     POSTGRES_IS_SETUP,
     RpcError,
+    User,
     wait_for,
 )
 
@@ -89,17 +91,25 @@ def test_spendtx_management(revault_network, bitcoind):
     man.rpc.updatespendtx(spend_tx_b)
     man.wait_for_log("Storing new Spend transaction")
     assert len(man.rpc.listspendtxs()["spend_txs"]) == 2
+    cosigners = [
+        {"host": c["host"], "noise_key": c["noise_key"], "signed": False}
+        for c in man.rpc.listcosigners()["cosigners"]
+    ]
     assert {
         "deposit_outpoints": [deposit],
         "psbt": spend_tx,
         "change_index": None,
         "cpfp_index": 0,
+        "conflicts": [],
+        "cosigners": cosigners,
     } in man.rpc.listspendtxs()["spend_txs"]
     assert {
         "deposit_outpoints": [deposit, deposit_b],
         "psbt": spend_tx_b,
         "change_index": 3,
         "cpfp_index": 0,
+        "conflicts": [],
+        "cosigners": cosigners,
     } in man.rpc.listspendtxs()["spend_txs"]
 
     # Now we could try to broadcast it..
@@ -568,6 +578,118 @@ def test_large_spends(revault_network, bitcoind, executor):
         assert vault["moved_at"] is not None
 
 
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_spend_limits(revault_network, bitcoind):
+    """The maximum number of vaults per Spend and per command are enforced"""
+    revault_network.deploy(2, 1)
+    man = revault_network.man(0)
+
+    vaults = revault_network.fundmany([1, 2, 3])
+    revault_network.activate_fresh_vaults(vaults)
+    deposits = [f"{v['txid']}:{v['vout']}" for v in vaults]
+    deriv_indexes = [v["derivation_index"] for v in vaults]
+    total_amount = sum(v["amount"] for v in vaults)
+
+    def signed_spend(deposits, indexes, amount):
+        fees = revault_network.compute_spendtx_fees(1, len(deposits), 1)
+        destinations = {bitcoind.rpc.getnewaddress(): amount - fees}
+        spend_tx = man.rpc.getspendtx(deposits, destinations, 1)["spend_tx"]
+        for m in revault_network.mans():
+            spend_tx = m.man_keychain.sign_spend_psbt(spend_tx, indexes)
+        spend_psbt = serializations.PSBT()
+        spend_psbt.deserialize(spend_tx)
+        spend_psbt.tx.calc_sha256()
+        return spend_tx, spend_psbt.tx.hash
+
+    # Store a Spend of the 3 vaults before lowering the limits
+    big_spend, big_spend_txid = signed_spend(deposits, deriv_indexes, total_amount)
+    man.rpc.updatespendtx(big_spend)
+
+    man.stop()
+    with open(man.conf_file, "r") as f:
+        conf = f.read()
+    with open(man.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "daemon = false\n",
+                "daemon = false\nmax_spend_inputs = 2\nmax_batch_size = 2\n",
+            )
+        )
+    man.start()
+    limits = man.rpc.getinfo()["limits"]
+    assert limits["max_spend_inputs"] == 2 and limits["max_batch_size"] == 2
+
+    # At the limit is fine, above it is not
+    for call in [
+        lambda outpoints: man.rpc.listvaults([], outpoints),
+        man.rpc.listpresignedtransactions,
+        man.rpc.listonchaintransactions,
+    ]:
+        call(deposits[:2])
+        with pytest.raises(RpcError, match="got '3' but the limit is '2'"):
+            call(deposits)
+
+    destinations = {bitcoind.rpc.getnewaddress(): 10_000}
+    with pytest.raises(RpcError, match="got '3' but the limit is '2'"):
+        man.rpc.getspendtx(deposits, destinations, 1)
+    with pytest.raises(RpcError, match="got '3' but the limit is '2'"):
+        man.rpc.updatespendtx(big_spend)
+    with pytest.raises(RpcError, match="got '3' but the limit is '2'"):
+        man.rpc.setspendtx(big_spend_txid)
+    man.rpc.delspendtx(big_spend_txid)
+
+    amount = vaults[0]["amount"] + vaults[1]["amount"]
+    spend, spend_txid = signed_spend(deposits[:2], deriv_indexes[:2], amount)
+    man.rpc.updatespendtx(spend)
+    man.rpc.setspendtx(spend_txid)
+    wait_for(
+        lambda: len(man.rpc.listvaults(["unvaulting"], deposits[:2])["vaults"]) == 2
+    )
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_spend_destination_reuse(revault_network, bitcoind):
+    """Paying again to an address a confirmed Spend paid to is flagged, or refused if configured"""
+    revault_network.deploy(2, 1, csv=3)
+    man = revault_network.man(0)
+
+    vaults = revault_network.fundmany([1, 2])
+    revault_network.activate_fresh_vaults(vaults)
+    deposits = [f"{v['txid']}:{v['vout']}" for v in vaults]
+    fees = revault_network.compute_spendtx_fees(1, 1, 1)
+    addr = bitcoind.rpc.getnewaddress()
+
+    # Nothing was ever paid to this address
+    destinations = {addr: vaults[0]["amount"] - fees}
+    res = man.rpc.getspendtx(deposits[:1], destinations, 1)
+    assert res["reused_destinations"] == []
+    revault_network.spend_vaults([vaults[0]], destinations, 1)
+
+    # Now it was, we are warned about it
+    destinations = {addr: vaults[1]["amount"] - fees}
+    res = man.rpc.getspendtx(deposits[1:], destinations, 1)
+    assert res["reused_destinations"] == [addr]
+    other_destinations = {bitcoind.rpc.getnewaddress(): vaults[1]["amount"] - fees}
+    res = man.rpc.getspendtx(deposits[1:], other_destinations, 1)
+    assert res["reused_destinations"] == []
+
+    # In strict mode, we refuse to create the transaction altogether
+    man.stop()
+    with open(man.conf_file, "r") as f:
+        conf = f.read()
+    with open(man.conf_file, "w") as f:
+        f.write(
+            conf.replace(
+                "[manager_config]\n",
+                "[manager_config]\nforbid_destination_reuse = true\n",
+            )
+        )
+    man.start()
+    with pytest.raises(RpcError, match=f"'{addr}' were already paid to"):
+        man.rpc.getspendtx(deposits[1:], destinations, 1)
+    man.rpc.getspendtx(deposits[1:], other_destinations, 1)
+
+
 # Tests that getspendtx returns an error when trying to build a spend too big
 # (it wouldn't be possible to announce it to the coordinator when fully signed)
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
@@ -696,6 +818,154 @@ def test_revaulted_spend(revault_network, bitcoind, executor):
         )
 
 
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_late_cancel(revault_network, bitcoind):
+    """
+    The Cancel is still valid once the Unvault timelock expired. A stakeholder broadcasting it
+    in place of the Spend transaction cancels the vault, and the Spend is deprecated.
+    """
+    CSV = 6
+    revault_network.deploy(2, 1, csv=CSV)
+    man = revault_network.man(0)
+    stk = revault_network.stk(0)
+
+    vault = revault_network.fund(0.5)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    revault_network.secure_vault(vault)
+    revault_network.activate_vault(vault)
+
+    # The timelock expired and the Spend is in the mempool, but the Cancel replaces it
+    _, spend_psbt = revault_network.spend_vaults_anyhow_unconfirmed([vault])
+    spend_txid = spend_psbt.tx.hash
+    cancel_txid = stk.rpc.getvaultdetails(deposit)["final_txids"]["cancel"]
+    stk.rpc.revault(deposit)
+    bitcoind.generate_block(1, wait_for_mempool=cancel_txid)
+    for w in revault_network.participants():
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"] == "canceled"
+        )
+    man.wait_for_logs(
+        [
+            f"Vault at '{deposit}' is being canceled by '{cancel_txid}' while 'spending'",
+            f"Spend tx '{spend_txid}' won't be broadcast anymore, vault at '{deposit}' was "
+            f"canceled by '{cancel_txid}'",
+        ]
+    )
+
+    # The Spend is kept, but it won't ever be broadcast again
+    assert man.rpc.listspendtxs(["pending", "broadcasted"])["spend_txs"] == []
+    spend_txs = man.rpc.listspendtxs(["deprecated"])["spend_txs"]
+    assert len(spend_txs) == 1
+    deprecation = spend_txs[0]["deprecation"]
+    assert deprecation["deposit_outpoint"] == deposit
+    assert deprecation["cancel_txid"] == cancel_txid
+    cancel_block = bitcoind.rpc.getblock(bitcoind.rpc.getbestblockhash())
+    assert deprecation["deprecated_at"] == cancel_block["time"]
+
+    # Its Unvault was broadcast, it's too late to give up on it
+    with pytest.raises(RpcError, match="use 'revault' to cancel its vaults instead"):
+        man.rpc.abortspend(spend_txid)
+    assert man.rpc.listspendtxs(["expired"])["spend_txs"] == []
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_cancel_mempool_conflict(revault_network, bitcoind):
+    """
+    The managers' wallet broadcast a Spend paying more fees than the Cancel: the Cancel a
+    stakeholder broadcasts through its daemon can't replace it. The conflict is reported and
+    recorded, and settled once the Spend confirms.
+    """
+    CSV = 6
+    revault_network.deploy(2, 1, csv=CSV)
+    stk = revault_network.stk(0)
+
+    vault = revault_network.fund(0.5)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    revault_network.secure_vault(vault)
+    revault_network.activate_vault(vault)
+
+    # A high feerate so that the Spend pays more than the Cancel, in total and per vbyte
+    feerate = 500
+    fees = revault_network.compute_spendtx_fees(feerate, 1, 1)
+    destinations = {bitcoind.rpc.getnewaddress(): vault["amount"] - fees}
+    _, spend_psbt = revault_network.spend_vaults_unconfirmed(
+        [vault], destinations, feerate
+    )
+    spend_txid = spend_psbt.tx.hash
+    wait_for(lambda: spend_txid in bitcoind.rpc.getrawmempool())
+    cancel_txid = stk.rpc.getvaultdetails(deposit)["final_txids"]["cancel"]
+
+    with pytest.raises(
+        RpcError, match=f"Transaction '{cancel_txid}' conflicts with .*in mempool"
+    ):
+        stk.rpc.revault(deposit)
+    assert cancel_txid not in bitcoind.rpc.getrawmempool()
+    conflicts = stk.rpc.listvaults([], [deposit])["vaults"][0]["conflicts"]
+    assert len(conflicts) == 1
+    assert conflicts[0]["rejected_txid"] == cancel_txid
+    assert conflicts[0]["competing_txid"] in (spend_txid, None)
+    assert conflicts[0]["confirmed_txid"] is None
+
+    # The Spend wins the race
+    bitcoind.generate_block(1, wait_for_mempool=[spend_txid])
+    wait_for(
+        lambda: stk.rpc.listvaults([], [deposit])["vaults"][0]["status"] == "spent"
+    )
+    wait_for(
+        lambda: stk.rpc.listvaults([], [deposit])["vaults"][0]["conflicts"][0][
+            "confirmed_txid"
+        ]
+        == spend_txid
+    )
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_unvaults_prevalidation(revault_network, bitcoind):
+    """
+    The Unvault transactions of a Spend are all broadcast, or none is if bitcoind would not
+    accept one of them.
+    """
+    revault_network.deploy(2, 1)
+    man = revault_network.man(0)
+    stk = revault_network.stk(0)
+
+    vaults = revault_network.fundmany([0.3, 0.4])
+    deposits = [f"{v['txid']}:{v['vout']}" for v in vaults]
+    revault_network.activate_fresh_vaults(vaults)
+    presigned = {
+        p["vault_outpoint"]: p
+        for p in stk.rpc.listpresignedtransactions(deposits)["presigned_transactions"]
+    }
+    unvault_txids = [
+        bitcoind.rpc.decoderawtransaction(presigned[d]["unvault"]["hex"])["txid"]
+        for d in deposits
+    ]
+
+    destinations, feerate = revault_network._any_spend_data(vaults)
+    spend_tx = man.rpc.getspendtx(deposits, destinations, feerate)["spend_tx"]
+    for m in revault_network.mans():
+        spend_tx = m.man_keychain.sign_spend_psbt(
+            spend_tx, [v["derivation_index"] for v in vaults]
+        )
+        m.rpc.updatespendtx(spend_tx)
+    spend_psbt = serializations.PSBT()
+    spend_psbt.deserialize(spend_tx)
+    spend_psbt.tx.calc_sha256()
+
+    # The Emergency of the second vault is in the mempool, its Unvault conflicts with it
+    emer_tx = presigned[deposits[1]]["emergency"]["hex"]
+    emer_txid = bitcoind.rpc.sendrawtransaction(emer_tx)
+    with pytest.raises(
+        RpcError, match=re.escape(f"Unvault transaction of vault(s) '{deposits[1]}'")
+    ):
+        man.rpc.setspendtx(spend_psbt.tx.hash)
+    mempool = bitcoind.rpc.getrawmempool()
+    assert emer_txid in mempool
+    assert all(txid not in mempool for txid in unvault_txids)
+    assert man.rpc.listvaults([], [deposits[0]])["vaults"][0]["status"] == "active"
+    assert man.rpc.listvaults([], [deposits[0]])["vaults"][0]["flags"] == []
+
+
 # Test that the coordinator will broadcast our spends
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
 def test_coordinator_broadcast(revault_network, bitcoind, executor):
@@ -721,3 +991,110 @@ def test_coordinator_broadcast(revault_network, bitcoind, executor):
         wait_for(
             lambda: len(w.rpc.listvaults(["spent"])["vaults"]) == 1,
         )
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_wallet_rotation(revault_network, bitcoind):
+    """Rotate the keys of a deployment and migrate the vaults to the new descriptors"""
+    CSV = 5
+    revault_network.deploy(2, 1, n_stkmanagers=1, csv=CSV, with_cosigs=False)
+    man = revault_network.man(0)
+    vaults = revault_network.fundmany([0.4, 0.5, 0.6])
+    revault_network.activate_fresh_vaults(vaults)
+    deriv_indexes = {f"{v['txid']}:{v['vout']}": v["derivation_index"] for v in vaults}
+
+    # Draw fresh keys for everyone. The CPFP keys are kept.
+    new_stk_keychains = {w: User() for w in revault_network.stks()}
+    new_man_keychains = {w: User() for w in revault_network.mans()}
+    stks_xpubs = [new_stk_keychains[w].get_xpub() for w in revault_network.stks()]
+    mans_xpubs = [new_man_keychains[w].get_xpub() for w in revault_network.mans()]
+    cpfp_xpubs = re.findall(r"tpub[1-9A-HJ-NP-Za-km-z]+", man.cpfp_desc)
+    for w in revault_network.participants():
+        our_stk_xpub, our_man_xpub = None, None
+        if w in new_stk_keychains:
+            our_stk_xpub = new_stk_keychains[w].get_xpub()
+        if w in new_man_keychains:
+            our_man_xpub = new_man_keychains[w].get_xpub()
+        res = w.rpc.initiatewalletrotation(
+            stks_xpubs,
+            mans_xpubs,
+            len(mans_xpubs),
+            [],
+            cpfp_xpubs,
+            our_stk_xpub,
+            our_man_xpub,
+        )
+    new_deposit_desc = res["deposit_descriptor"]
+    new_unvault_desc = res["unvault_descriptor"]
+    new_cpfp_desc = res["cpfp_descriptor"]
+
+    # Only one rotation at a time
+    with pytest.raises(RpcError, match="rotation"):
+        man.rpc.initiatewalletrotation(
+            stks_xpubs, mans_xpubs, len(mans_xpubs), [], cpfp_xpubs, None, mans_xpubs[0]
+        )
+
+    # All three vaults fit in a single migration
+    migrations = man.rpc.migratevaults(2)["migrations"]
+    assert len(migrations) == 1
+    assert len(migrations[0]["deposit_outpoints"]) == 3
+    assert man.rpc.migratevaults(2)["migrations"] == []
+
+    # The migration Spend is signed by the current managers and goes through the
+    # usual Unvault process.
+    spend_tx = migrations[0]["spend_tx"]
+    indexes = [deriv_indexes[d] for d in migrations[0]["deposit_outpoints"]]
+    for w in revault_network.mans():
+        spend_tx = w.man_keychain.sign_spend_psbt(spend_tx, indexes)
+        w.rpc.updatespendtx(spend_tx)
+    spend_psbt = serializations.PSBT()
+    spend_psbt.deserialize(spend_tx)
+    spend_psbt.tx.calc_sha256()
+    man.rpc.setspendtx(spend_psbt.tx.hash)
+    bitcoind.generate_block(1, wait_for_mempool=3)
+    bitcoind.generate_block(CSV)
+    man.wait_for_log(f"Succesfully broadcasted Spend tx '{spend_psbt.tx.hash}'")
+    bitcoind.generate_block(6, wait_for_mempool=[spend_psbt.tx.hash])
+    for w in revault_network.participants():
+        wait_for(lambda: len(w.rpc.listvaults(["spent"])["vaults"]) == 3)
+
+    # Restart everyone on the new descriptors
+    revault_network.stop_wallets()
+    for w in revault_network.participants():
+        with open(w.conf_file, "r") as f:
+            conf = f.read()
+        conf = (
+            conf.replace(w.deposit_desc, new_deposit_desc)
+            .replace(w.unvault_desc, new_unvault_desc)
+            .replace(w.cpfp_desc, new_cpfp_desc)
+        )
+        if w in new_stk_keychains:
+            new_xpub = new_stk_keychains[w].get_xpub()
+            conf = conf.replace(w.stk_keychain.get_xpub(), new_xpub)
+            w.stk_keychain = new_stk_keychains[w]
+        if w in new_man_keychains:
+            new_xpub = new_man_keychains[w].get_xpub()
+            conf = conf.replace(w.man_keychain.get_xpub(), new_xpub)
+            w.man_keychain = new_man_keychains[w]
+        with open(w.conf_file, "w") as f:
+            f.write(conf)
+        w.deposit_desc = new_deposit_desc
+        w.unvault_desc = new_unvault_desc
+        w.cpfp_desc = new_cpfp_desc
+    revault_network.start_wallets()
+
+    # The funds are now in a single vault of the new wallet, which can be secured
+    # with the new keys.
+    for w in revault_network.participants():
+        wait_for(lambda: len(w.rpc.listvaults(["funded"])["vaults"]) == 1)
+        assert w.rpc.listvaults(["spent"])["vaults"] == []
+    new_vault = man.rpc.listvaults(["funded"])["vaults"][0]
+    assert new_vault["address"] == migrations[0]["address"]
+    revault_network.activate_fresh_vaults([new_vault])
+    # Only the fees were paid, the rest of the funds is secured under the new keys
+    total = sum(v["amount"] for v in vaults)
+    assert total - 0.01 * COIN < new_vault["amount"] < total
+    for w in revault_network.participants():
+        w.wait_for_active_vaults([f"{new_vault['txid']}:{new_vault['vout']}"])
+        # The vaults of the previous wallet aren't listed anymore
+        assert len(w.rpc.listvaults()["vaults"]) == 1